@@ -23,11 +23,25 @@ fn main() {
     let git_dirty = get_git_dirty();
     println!("cargo:rustc-env=GIT_DIRTY={}", git_dirty);
 
+    #[cfg(feature = "grpc")]
+    compile_protos();
+
     println!("cargo:rerun-if-changed=Cargo.toml");
     println!("cargo:rerun-if-changed=.git/HEAD");
     println!("cargo:rerun-if-changed=.git/index");
 }
 
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    println!("cargo:rerun-if-changed=proto/rustpbx.proto");
+    let file_descriptors =
+        protox::compile(["proto/rustpbx.proto"], ["proto"]).expect("failed to parse protos");
+    tonic_build::configure()
+        .build_client(true)
+        .compile_fds(file_descriptors)
+        .expect("failed to compile protos");
+}
+
 fn get_git_commit_hash() -> String {
     Command::new("git")
         .args(["rev-parse", "--short", "HEAD"])
@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rustpbx::media::dtmf::{DtmfDetector, DtmfPayload};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = DtmfPayload::parse(data);
+    if let Some((payload_type, payload)) = data.split_first() {
+        let _ = DtmfDetector::new().detect_rtp(*payload_type, payload);
+    }
+});
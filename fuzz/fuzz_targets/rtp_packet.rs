@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rustpbx::media::track::rtp::parse_rtp_packet;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_rtp_packet(data);
+});
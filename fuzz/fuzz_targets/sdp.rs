@@ -0,0 +1,11 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use rustpbx::media::negotiate::{parse_sdp, prefer_audio_codec, select_peer_media};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(sdp) = parse_sdp(data) {
+        let _ = select_peer_media(&sdp, "audio");
+        let _ = prefer_audio_codec(&sdp);
+    }
+});
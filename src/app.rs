@@ -1,32 +1,55 @@
 use crate::{
-    call::ActiveCallRef,
-    callrecord::{CallRecordManagerBuilder, CallRecordSender},
-    config::Config,
-    handler::middleware::clientaddr::ClientAddr,
-    media::engine::StreamEngine,
+    call::{
+        ActiveCallRef,
+        flow::FlowStats,
+        retransmission::{RetransmissionStats, Retransmitter},
+    },
+    callrecord::{
+        CallRecordManagerBuilder, CallRecordSender, enrich::CallRecordEnricher,
+        transcript::PostCallTranscriber,
+    },
+    config::{Config, ProxyConfig},
+    features::FeatureFlags,
+    handler::{
+        introspect::dump_handler,
+        middleware::{clientaddr::ClientAddr, rbac::Principal},
+    },
+    journal::{Journal, JournalRef},
+    media::{engine::StreamEngine, nat::MediaNat},
+    pbx::RustPbx,
     proxy::{
         acl::AclModule,
+        agent,
         auth::AuthModule,
         call::CallModule,
+        campaign,
+        campon::CampOnModule,
+        clicktodial::{ClickToDial, ClickToDialModule, click_to_dial_handler},
+        message::{MessageModule, SendMessage, send_message_handler},
+        metering::usage_handler,
+        presence::PresenceModule,
+        queue::QueueModule,
         registrar::RegistrarModule,
         server::{SipServer, SipServerBuilder},
+        trunk_monitor::trunk_health_handler,
+        wallboard,
         ws::sip_ws_handler,
     },
     useragent::{UserAgent, invitation::FnCreateInvitationHandler},
 };
 use anyhow::Result;
 use axum::{
-    Router,
+    Extension, Json, Router,
     extract::WebSocketUpgrade,
+    middleware,
     response::{Html, IntoResponse, Response},
-    routing::get,
+    routing::{get, post},
 };
 use chrono::{DateTime, Utc};
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::{collections::HashMap, net::SocketAddr};
 use std::{path::Path, sync::atomic::AtomicU64};
-use tokio::select;
-use tokio::{net::TcpListener, sync::Mutex};
+use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 use tower_http::{
     cors::{AllowOrigin, CorsLayer},
@@ -41,8 +64,20 @@ pub struct AppStateInner {
     pub active_calls: Arc<Mutex<HashMap<String, ActiveCallRef>>>,
     pub stream_engine: Arc<StreamEngine>,
     pub callrecord_sender: Option<CallRecordSender>,
+    /// Where call, media and signaling events are kept when `journal` is set
+    pub journal: Option<JournalRef>,
+    /// Transcribes the recordings of ended calls when `post_transcription`
+    /// is set
+    pub transcriber: Option<Arc<PostCallTranscriber>>,
     pub total_calls: AtomicU64,
     pub total_failed_calls: AtomicU64,
+    /// SIP messages sent more than once, by the user agent and the proxy
+    pub retransmissions: Arc<RetransmissionStats>,
+    pub flow_stats: Arc<FlowStats>,
+    /// Ports and public addresses of the RTP when `media_nat` is set
+    pub media_nat: Option<Arc<MediaNat>>,
+    /// Features of the calls per tenant and extension
+    pub features: Arc<FeatureFlags>,
     pub uptime: DateTime<Utc>,
 }
 
@@ -53,6 +88,7 @@ pub struct AppStateBuilder {
     pub useragent: Option<Arc<UserAgent>>,
     pub stream_engine: Option<Arc<StreamEngine>>,
     pub callrecord_sender: Option<CallRecordSender>,
+    pub callrecord_enrichers: Vec<Arc<dyn CallRecordEnricher>>,
     pub cancel_token: Option<CancellationToken>,
     pub proxy_builder: Option<SipServerBuilder>,
     pub create_invitation_handler: Option<FnCreateInvitationHandler>,
@@ -115,6 +151,7 @@ impl AppStateBuilder {
             useragent: None,
             stream_engine: None,
             callrecord_sender: None,
+            callrecord_enrichers: Vec::new(),
             cancel_token: None,
             proxy_builder: None,
             create_invitation_handler: None,
@@ -141,6 +178,13 @@ impl AppStateBuilder {
         self
     }
 
+    /// Runs before the enrichers of `callrecord_enrich`, on the records of
+    /// the call record manager the app creates
+    pub fn with_callrecord_enricher(mut self, enricher: Arc<dyn CallRecordEnricher>) -> Self {
+        self.callrecord_enrichers.push(enricher);
+        self
+    }
+
     pub fn with_proxy_builder(mut self, builder: SipServerBuilder) -> Self {
         self.proxy_builder = Some(builder);
         self
@@ -151,6 +195,11 @@ impl AppStateBuilder {
         self
     }
 
+    pub fn with_create_invitation_handler(mut self, handler: FnCreateInvitationHandler) -> Self {
+        self.create_invitation_handler = Some(handler);
+        self
+    }
+
     pub async fn build(mut self) -> Result<(AppState, Option<SipServer>)> {
         let config: Arc<Config> = Arc::new(self.config.unwrap_or_default());
         let token = self
@@ -158,15 +207,24 @@ impl AppStateBuilder {
             .unwrap_or_else(|| CancellationToken::new());
         let _ = crate::media::cache::set_cache_dir(&config.media_cache_path);
 
+        let retransmissions = Arc::new(RetransmissionStats::default());
         let useragent = if let Some(ua) = self.useragent {
             Some(ua)
         } else {
+            let dscp = config.qos.as_ref().map(|qos| qos.signaling);
+            let retransmitter = Retransmitter::new(
+                config.sip_timers.clone().unwrap_or_default(),
+                retransmissions.clone(),
+                None,
+            );
             let config = config.ua.clone();
             if let Some(config) = config {
                 let invite_handler = self.create_invitation_handler.take();
                 let ua_builder = crate::useragent::UserAgentBuilder::new()
                     .with_cancel_token(token.child_token())
                     .with_create_invitation_handler(invite_handler)
+                    .with_dscp(dscp)
+                    .with_retransmitter(Some(retransmitter))
                     .with_config(Some(config));
                 Some(Arc::new(ua_builder.build().await?))
             } else {
@@ -179,10 +237,18 @@ impl AppStateBuilder {
             Some(sender)
         } else {
             if let Some(ref callrecord) = config.callrecord {
-                let mut callrecord_manager = CallRecordManagerBuilder::new()
+                let mut builder = CallRecordManagerBuilder::new()
                     .with_cancel_token(token.child_token())
-                    .with_config(callrecord.clone())
-                    .build();
+                    .with_config(callrecord.clone());
+                let enrichers = config
+                    .callrecord_enrich
+                    .as_ref()
+                    .map(crate::callrecord::enrich::enrichers)
+                    .unwrap_or_default();
+                for enricher in self.callrecord_enrichers.into_iter().chain(enrichers) {
+                    builder = builder.with_enricher(enricher);
+                }
+                let mut callrecord_manager = builder.build();
                 let sender = callrecord_manager.sender.clone();
                 tokio::spawn(async move {
                     callrecord_manager.serve().await;
@@ -192,6 +258,26 @@ impl AppStateBuilder {
                 None
             }
         };
+        let journal = match config.journal.clone() {
+            Some(journal_config) => Some(Journal::start(journal_config, token.child_token())?),
+            None => None,
+        };
+        let transcriber = match config.post_transcription.clone() {
+            Some(transcription) => Some(Arc::new(PostCallTranscriber::start(
+                transcription,
+                callrecord_sender.clone(),
+                token.child_token(),
+            )?)),
+            None => None,
+        };
+        let media_nat = match config.media_nat.as_ref() {
+            Some(_) => {
+                let nat = Arc::new(MediaNat::new(&config)?);
+                nat.start(token.child_token());
+                Some(nat)
+            }
+            None => None,
+        };
         let app_state = Arc::new(AppStateInner {
             config: config.clone(),
             useragent,
@@ -199,23 +285,28 @@ impl AppStateBuilder {
             active_calls: Arc::new(Mutex::new(HashMap::new())),
             stream_engine,
             callrecord_sender: callrecord_sender.clone(),
+            journal,
+            transcriber,
             total_calls: AtomicU64::new(0),
             total_failed_calls: AtomicU64::new(0),
+            retransmissions,
+            flow_stats: Arc::new(FlowStats::default()),
+            media_nat,
+            features: Arc::new(FeatureFlags::new(&config)),
             uptime: chrono::Utc::now(),
         });
 
         let sip_server = match self.proxy_builder {
-            Some(builder) => builder.build(app_state.clone()).await.ok(),
+            Some(builder) => builder
+                .with_app_defaults(token.child_token(), callrecord_sender.clone())
+                .build(app_state.clone())
+                .await
+                .ok(),
             None => {
                 if let Some(proxy_config) = config.proxy.clone() {
-                    let proxy_config = Arc::new(proxy_config);
-                    let builder = SipServerBuilder::new(proxy_config)
+                    let builder = default_proxy_builder(Arc::new(proxy_config))
                         .with_cancel_token(token.child_token())
-                        .with_callrecord_sender(callrecord_sender.clone())
-                        .register_module("acl", AclModule::create)
-                        .register_module("auth", AuthModule::create)
-                        .register_module("registrar", RegistrarModule::create)
-                        .register_module("call", CallModule::create);
+                        .with_callrecord_sender(callrecord_sender.clone());
                     builder.build(app_state.clone()).await.ok()
                 } else {
                     None
@@ -227,17 +318,48 @@ impl AppStateBuilder {
     }
 }
 
+/// A proxy builder with the modules that can be listed in `proxy.modules`
+pub fn default_proxy_builder(config: Arc<ProxyConfig>) -> SipServerBuilder {
+    SipServerBuilder::new(config)
+        .register_module("acl", AclModule::create)
+        .register_module("auth", AuthModule::create)
+        .register_module("registrar", RegistrarModule::create)
+        .register_module("message", MessageModule::create)
+        .register_module("presence", PresenceModule::create)
+        .register_module("clicktodial", ClickToDialModule::create)
+        .register_module("campon", CampOnModule::create)
+        .register_module("queue", QueueModule::create)
+        .register_module("call", CallModule::create)
+}
+
 pub async fn run(state: AppState, sip_server: Option<SipServer>) -> Result<()> {
+    RustPbx::new(state, sip_server).serve().await
+}
+
+/// Every HTTP route of the app, with those of the proxy when it runs
+pub fn router(state: AppState, sip_server: Option<&SipServer>) -> Router {
     let token = state.token.clone();
     let mut router = create_router(state.clone());
-    let addr: SocketAddr = state.config.http_addr.parse()?;
-    let listener = match TcpListener::bind(addr).await {
-        Ok(l) => l,
-        Err(e) => {
-            tracing::error!("Failed to bind to {}: {}", addr, e);
-            return Err(anyhow::anyhow!("Failed to bind to {}: {}", addr, e));
-        }
-    };
+    router = router.merge(crate::handler::health::router(
+        state.clone(),
+        sip_server.map(|sip_server| sip_server.inner.clone()),
+    ));
+
+    let dump_state = state.clone();
+    let dump_server = sip_server.map(|sip_server| sip_server.inner.clone());
+    router = router.merge(
+        Router::new()
+            .route(
+                "/ami/v1/dump",
+                get(async move || -> Response {
+                    dump_handler(dump_state.clone(), dump_server.clone()).await
+                }),
+            )
+            .layer(middleware::from_fn_with_state(
+                state.clone(),
+                crate::handler::middleware::ami_auth::ami_auth_middleware,
+            )),
+    );
 
     if let Some(sip_server) = sip_server {
         if let Some(ref ws_handler) = sip_server.inner.config.ws_handler {
@@ -245,7 +367,7 @@ pub async fn run(state: AppState, sip_server: Option<SipServer>) -> Result<()> {
                 "Registering WebSocket handler to sip server: {}",
                 ws_handler
             );
-            let endpoint_ref = sip_server.inner.endpoint.inner.clone();
+            let ws_server = sip_server.inner.clone();
             let token = token.clone();
             router = router.route(
                 ws_handler,
@@ -253,59 +375,70 @@ pub async fn run(state: AppState, sip_server: Option<SipServer>) -> Result<()> {
                     async move |client_ip: ClientAddr, ws: WebSocketUpgrade| -> Response {
                         let token = token.clone();
                         ws.protocols(["sip"]).on_upgrade(async move |socket| {
-                            sip_ws_handler(token, client_ip, socket, endpoint_ref.clone()).await
+                            sip_ws_handler(token, client_ip, socket, ws_server.clone()).await
                         })
                     },
                 ),
             );
         }
-        tokio::spawn(async move {
-            info!("Proxy server started");
-            match sip_server.serve().await {
-                Ok(_) => {}
-                Err(e) => {
-                    warn!("Proxy server error: {}", e);
-                }
-            }
-        });
-    }
-
-    let http_task = axum::serve(
-        listener,
-        router.into_make_service_with_connect_info::<SocketAddr>(),
-    );
-    select! {
-        http_result = http_task => {
-            match http_result {
-                Ok(_) => info!("Server shut down gracefully"),
-                Err(e) => {
-                    tracing::error!("Server error: {}", e);
-                    return Err(anyhow::anyhow!("Server error: {}", e));
-                }
-            }
-        }
-        ua_result = async {
-            if let Some(useragent) = &state.useragent {
-                useragent.serve().await
-            } else {
-                token.cancelled().await;
-                Ok(())
-            }
-        } => {
-            if let Err(e) = ua_result {
-                tracing::error!("User agent server error: {}", e);
-                return Err(anyhow::anyhow!("User agent server error: {}", e));
-            }
-        }
-        _ = token.cancelled() => {
-            info!("Application shutting down due to cancellation");
-        }
+        let server = sip_server.inner.clone();
+        let monitor_server = sip_server.inner.clone();
+        let dial_server = sip_server.inner.clone();
+        let usage_server = sip_server.inner.clone();
+        let campaign_server = sip_server.inner.clone();
+        let agent_server = sip_server.inner.clone();
+        let wallboard_server = sip_server.inner.clone();
+        router = router.merge(
+            Router::new()
+                .route(
+                    "/ami/v1/message",
+                    post(
+                        async move |principal: Option<Extension<Principal>>,
+                                    Json(message): Json<SendMessage>|
+                                    -> Response {
+                            let tenant = principal.and_then(|Extension(p)| p.tenant);
+                            send_message_handler(server.clone(), message, tenant).await
+                        },
+                    ),
+                )
+                .route(
+                    "/ami/v1/clicktodial",
+                    post(
+                        async move |principal: Option<Extension<Principal>>,
+                                    Json(request): Json<ClickToDial>|
+                                    -> Response {
+                            let config = dial_server.config.clone();
+                            let tenant = principal.and_then(|Extension(p)| p.tenant);
+                            click_to_dial_handler(dial_server.clone(), config, request, tenant)
+                                .await
+                        },
+                    ),
+                )
+                .route(
+                    "/ami/v1/trunks",
+                    get(async move || -> Response {
+                        trunk_health_handler(monitor_server.clone()).await
+                    }),
+                )
+                .route(
+                    "/ami/v1/usage",
+                    get(
+                        async move |principal: Option<Extension<Principal>>| -> Response {
+                            let tenant = principal.and_then(|Extension(p)| p.tenant);
+                            usage_handler(usage_server.clone(), tenant).await
+                        },
+                    ),
+                )
+                .merge(campaign::router(campaign_server))
+                .merge(agent::router(agent_server))
+                .merge(wallboard::router(wallboard_server))
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    crate::handler::middleware::ami_auth::ami_auth_middleware,
+                )),
+        );
     }
-
-    state.useragent.as_ref().map(|ua| {
-        ua.stop();
-    });
-    Ok(())
+    router
 }
 
 // Index page handler
@@ -0,0 +1,81 @@
+use anyhow::{Result, anyhow};
+use clap::Parser;
+use rustpbx::{
+    callrecord::CallRecordEventType,
+    journal::{JournalFilter, JournalReader, unfinished_sessions},
+    version,
+};
+use std::{
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+
+/// Print the entries of an event journal as JSON lines
+#[derive(Parser, Debug)]
+#[command(
+    author,
+    version = version::get_short_version(),
+    about = "Replay the events kept in a rustpbx event journal",
+    long_about = version::get_version_info()
+)]
+struct Args {
+    /// Journal directory, or a single journal file
+    #[arg(value_name = "PATH")]
+    path: PathBuf,
+
+    /// Only entries at or after this time, RFC 3339 or milliseconds since the epoch
+    #[arg(long)]
+    since: Option<String>,
+
+    /// Only entries before this time, RFC 3339 or milliseconds since the epoch
+    #[arg(long)]
+    until: Option<String>,
+
+    /// Only the entries of this session (call id)
+    #[arg(short, long)]
+    session: Option<String>,
+
+    /// Only entries of this type: event, command, sip or callRecord
+    #[arg(short = 't', long = "type")]
+    r#type: Option<String>,
+
+    /// Print the sessions that never got a call record instead of the entries
+    #[arg(long)]
+    unfinished: bool,
+}
+
+fn parse_time(value: &str) -> Result<u64> {
+    if let Ok(millis) = value.parse::<u64>() {
+        return Ok(millis);
+    }
+    chrono::DateTime::parse_from_rfc3339(value)
+        .map(|time| time.timestamp_millis() as u64)
+        .map_err(|e| anyhow!("invalid time {}: {}", value, e))
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let filter = JournalFilter {
+        since: args.since.as_deref().map(parse_time).transpose()?,
+        until: args.until.as_deref().map(parse_time).transpose()?,
+        session_id: args.session,
+        r#type: args
+            .r#type
+            .map(|t| serde_json::from_value::<CallRecordEventType>(serde_json::Value::String(t)))
+            .transpose()
+            .map_err(|e| anyhow!("invalid type: {}", e))?,
+    };
+    let reader = JournalReader::open(&args.path)?;
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    if args.unfinished {
+        for session_id in unfinished_sessions(reader.replay(filter)) {
+            writeln!(out, "{}", session_id)?;
+        }
+    } else {
+        for entry in reader.replay(filter) {
+            writeln!(out, "{}", serde_json::to_string(&entry)?)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -253,6 +253,7 @@ async fn serve_client(codec: CodecType, cli: Cli, id: u32, state: Arc<AppState>)
                                 url: cli.play_file.clone(),
                                 auto_hangup: None,
                                 wait_input_timeout: None,
+                                stream: None,
                             })?
                             .into(),
                         ))
@@ -273,6 +274,7 @@ async fn serve_client(codec: CodecType, cli: Cli, id: u32, state: Arc<AppState>)
                                     url: cli.play_file.clone(),
                                     auto_hangup: None,
                                     wait_input_timeout: None,
+                                    stream: None,
                                 })?
                                 .into(),
                             ))
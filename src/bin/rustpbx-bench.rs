@@ -0,0 +1,407 @@
+use anyhow::Result;
+use clap::Parser;
+use rsipstack::dialog::invitation::InviteOption;
+use rustpbx::{
+    AudioFrame, Samples,
+    config::UseragentConfig,
+    event::create_event_sender,
+    media::{
+        codecs::{CodecType, resample::resample_mono},
+        processor::energy_dbfs,
+        track::{Track, TrackConfig, file::read_wav_file, rtp::RtpTrackBuilder},
+    },
+    useragent::{UserAgent, UserAgentBuilder},
+    version,
+};
+use std::{
+    collections::BTreeMap,
+    net::IpAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio::{select, sync::Semaphore, time};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, level_filters::LevelFilter, warn};
+
+#[derive(Parser, Debug, Clone)]
+#[command(
+    author,
+    version = version::get_short_version(),
+    about = "A SIP load generator: originates calls with RTP audio and reports setup latency, audio quality and failures",
+    long_about = version::get_version_info()
+)]
+struct Cli {
+    /// Callee URI, e.g. sip:1000@127.0.0.1:5060
+    #[clap(long)]
+    target: String,
+
+    /// Caller user
+    #[clap(long, default_value = "bench")]
+    caller: String,
+
+    /// Local IP to bind SIP and RTP
+    #[clap(long, default_value = "127.0.0.1")]
+    addr: String,
+
+    /// Local SIP port, 0 picks a free port
+    #[clap(long, default_value = "0")]
+    port: u16,
+
+    /// New calls per second
+    #[clap(long, default_value = "1")]
+    cps: f64,
+
+    /// Total number of calls
+    #[clap(long, default_value = "10")]
+    calls: u32,
+
+    /// Max concurrent calls
+    #[clap(long, default_value = "100")]
+    concurrent: u32,
+
+    /// Call hold time in seconds
+    #[clap(long, default_value = "10")]
+    hold: u64,
+
+    /// Call setup timeout in seconds
+    #[clap(long, default_value = "30")]
+    timeout: u64,
+
+    /// WAV file streamed as RTP audio during each call
+    #[clap(long, default_value = "fixtures/sample.wav")]
+    input_file: String,
+
+    /// Codec type: pcmu, pcma, g722
+    #[clap(long, default_value = "pcmu")]
+    codec: String,
+
+    /// Verbose
+    #[clap(long)]
+    verbose: bool,
+}
+
+#[derive(Default)]
+struct CallResult {
+    setup: Option<Duration>,
+    failure: Option<String>,
+    /// received / expected audio frames
+    rx_ratio: f32,
+    rx_level: f32,
+}
+
+#[derive(Default)]
+struct BenchStats {
+    setup: Vec<Duration>,
+    failures: BTreeMap<String, u32>,
+    rx_ratios: Vec<f32>,
+    rx_levels: Vec<f32>,
+}
+
+impl BenchStats {
+    fn add(&mut self, result: CallResult) {
+        match result.failure {
+            Some(failure) => *self.failures.entry(failure).or_default() += 1,
+            None => {
+                self.setup.extend(result.setup);
+                self.rx_ratios.push(result.rx_ratio);
+                self.rx_levels.push(result.rx_level);
+            }
+        }
+    }
+
+    fn completed(&self) -> usize {
+        self.setup.len() + self.failures.values().sum::<u32>() as usize
+    }
+
+    fn report(&mut self) {
+        let total = self.completed();
+        let failed = total - self.setup.len();
+        println!(
+            "calls: {}, answered: {}, failed: {}",
+            total,
+            self.setup.len(),
+            failed
+        );
+        if total > 0 {
+            println!("failure rate: {:.2}%", failed as f64 * 100.0 / total as f64);
+        }
+        for (reason, count) in self.failures.iter() {
+            println!("  {}: {}", reason, count);
+        }
+        if !self.setup.is_empty() {
+            self.setup.sort();
+            let percentile = |p: usize| self.setup[(self.setup.len() - 1) * p / 100].as_millis();
+            println!(
+                "setup latency (ms): min {} p50 {} p95 {} p99 {} max {}",
+                self.setup[0].as_millis(),
+                percentile(50),
+                percentile(95),
+                percentile(99),
+                self.setup[self.setup.len() - 1].as_millis()
+            );
+        }
+        if !self.rx_ratios.is_empty() {
+            let count = self.rx_ratios.len() as f32;
+            let rx_ratio = self.rx_ratios.iter().sum::<f32>() / count;
+            let rx_level = self.rx_levels.iter().sum::<f32>() / count;
+            println!(
+                "audio: received {:.2}% of frames, level {:.1} dBFS, estimated MOS {:.2}",
+                rx_ratio * 100.0,
+                rx_level,
+                estimate_mos(1.0 - rx_ratio)
+            );
+        }
+    }
+}
+
+/// E-model (G.107) MOS estimate for G.711 with packet loss concealment,
+/// ignoring delay
+fn estimate_mos(loss: f32) -> f32 {
+    let ppl = loss.clamp(0.0, 1.0) * 100.0;
+    let ie_eff = 95.0 * ppl / (ppl + 25.1);
+    let r = 93.2 - ie_eff;
+    1.0 + 0.035 * r + 7e-6 * r * (r - 60.0) * (100.0 - r)
+}
+
+async fn run_call(
+    cli: Arc<Cli>,
+    ua: Arc<UserAgent>,
+    id: u32,
+    codec: CodecType,
+    audio: Arc<Vec<i16>>,
+) -> CallResult {
+    let mut result = CallResult::default();
+    let token = CancellationToken::new();
+    let track_config = TrackConfig {
+        codec,
+        samplerate: codec.samplerate(),
+        ..Default::default()
+    };
+    let track_id = format!("bench-{}", id);
+    let rtp_track = match RtpTrackBuilder::new(track_id.clone(), track_config.clone())
+        .with_local_addr(cli.addr.parse().unwrap_or(IpAddr::from([127, 0, 0, 1])))
+        .with_enabled_codecs(vec![codec])
+        .with_cancel_token(token.clone())
+        .build()
+        .await
+    {
+        Ok(track) => track,
+        Err(e) => {
+            result.failure = Some(format!("rtp: {}", e));
+            return result;
+        }
+    };
+    let offer = rtp_track.local_description().ok();
+    let caller = format!(
+        "sip:{}-{}@{}",
+        cli.caller,
+        id,
+        ua.endpoint.get_addrs()[0].addr
+    );
+    let invite_option = match (caller.clone().try_into(), cli.target.clone().try_into()) {
+        (Ok(caller_uri), Ok(callee)) => InviteOption {
+            caller: caller_uri,
+            callee,
+            content_type: Some("application/sdp".to_string()),
+            offer: offer.map(|o| o.into_bytes()),
+            contact: caller.try_into().expect("caller uri"),
+            credential: None,
+            headers: None,
+            destination: None,
+        },
+        _ => {
+            result.failure = Some("invalid uri".to_string());
+            return result;
+        }
+    };
+
+    let (state_sender, mut state_receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move { while state_receiver.recv().await.is_some() {} });
+    let start = Instant::now();
+    let invite = ua.invitation.invite(invite_option, state_sender);
+    let (dialog_id, answer) = match time::timeout(Duration::from_secs(cli.timeout), invite).await {
+        Ok(Ok(r)) => r,
+        Ok(Err(rsipstack::Error::DialogError(_, _, code))) => {
+            result.failure = Some(format!("{}", code));
+            return result;
+        }
+        Err(_) => {
+            result.failure = Some("timeout".to_string());
+            return result;
+        }
+        Ok(Err(e)) => {
+            result.failure = Some(e.to_string());
+            return result;
+        }
+    };
+    result.setup = Some(start.elapsed());
+
+    let answer = String::from_utf8_lossy(&answer.unwrap_or_default()).to_string();
+    if let Err(e) = rtp_track.set_remote_description(&answer) {
+        result.failure = Some(format!("sdp: {}", e));
+        ua.invitation.hangup(dialog_id, None, None).await.ok();
+        return result;
+    }
+    let (packet_sender, mut packet_receiver) = tokio::sync::mpsc::unbounded_channel();
+    if let Err(e) = rtp_track.start(create_event_sender(), packet_sender).await {
+        result.failure = Some(format!("rtp: {}", e));
+        ua.invitation.hangup(dialog_id, None, None).await.ok();
+        return result;
+    }
+
+    let ptime = track_config.ptime;
+    let frame_size = (track_config.samplerate as u128 * ptime.as_millis() / 1000) as usize;
+    let send_loop = async {
+        let mut ticker = time::interval(ptime);
+        for chunk in audio.chunks(frame_size).cycle() {
+            ticker.tick().await;
+            let frame = AudioFrame {
+                track_id: track_id.clone(),
+                samples: Samples::PCM {
+                    samples: chunk.to_vec(),
+                },
+                timestamp: rustpbx::get_timestamp(),
+                sample_rate: track_config.samplerate,
+            };
+            rtp_track.send_packet(&frame).await.ok();
+        }
+    };
+    let mut received = 0u32;
+    let mut level_sum = 0.0f32;
+    let recv_loop = async {
+        while let Some(frame) = packet_receiver.recv().await {
+            if let Samples::PCM { samples } = &frame.samples {
+                received += 1;
+                level_sum += energy_dbfs(samples);
+            }
+        }
+    };
+    select! {
+        _ = send_loop => {}
+        _ = recv_loop => {}
+        _ = time::sleep(Duration::from_secs(cli.hold)) => {}
+    }
+
+    let expected = (cli.hold * 1000 / ptime.as_millis().max(1) as u64).max(1) as f32;
+    result.rx_ratio = (received as f32 / expected).min(1.0);
+    result.rx_level = match received {
+        0 => -100.0,
+        n => level_sum / n as f32,
+    };
+    ua.invitation.hangup(dialog_id, None, None).await.ok();
+    rtp_track.stop().await.ok();
+    token.cancel();
+    result
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Arc::new(Cli::parse());
+    tracing_subscriber::fmt()
+        .with_max_level(if cli.verbose {
+            LevelFilter::INFO
+        } else {
+            LevelFilter::ERROR
+        })
+        .try_init()
+        .ok();
+
+    let codec = match cli.codec.as_str() {
+        "pcmu" => CodecType::PCMU,
+        "pcma" => CodecType::PCMA,
+        "g722" => CodecType::G722,
+        _ => return Err(anyhow::anyhow!("Invalid codec type")),
+    };
+    let (samples, sample_rate) = read_wav_file(&cli.input_file)?;
+    let audio = Arc::new(if sample_rate != codec.samplerate() {
+        resample_mono(&samples, sample_rate, codec.samplerate())
+    } else {
+        samples
+    });
+
+    let config = UseragentConfig {
+        addr: cli.addr.clone(),
+        udp_port: cli.port,
+        ..Default::default()
+    };
+    let ua = Arc::new(
+        UserAgentBuilder::new()
+            .with_config(Some(config))
+            .build()
+            .await?,
+    );
+
+    println!(
+        "rustpbx-bench: {} calls to {} at {} cps, hold {}s, codec {:?}",
+        cli.calls, cli.target, cli.cps, cli.hold, codec
+    );
+    let stats = Arc::new(Mutex::new(BenchStats::default()));
+    let active = Arc::new(AtomicU32::new(0));
+    let semaphore = Arc::new(Semaphore::new(cli.concurrent as usize));
+    let mut handles = Vec::new();
+    let mut ticker = time::interval(Duration::from_secs_f64(1.0 / cli.cps.max(0.001)));
+    let started = Instant::now();
+
+    let originate = async {
+        for id in 0..cli.calls {
+            ticker.tick().await;
+            let permit = match semaphore.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    warn!(id, "max concurrent calls reached");
+                    stats.lock().unwrap().add(CallResult {
+                        failure: Some("max concurrent".to_string()),
+                        ..Default::default()
+                    });
+                    continue;
+                }
+            };
+            let (cli, ua, audio) = (cli.clone(), ua.clone(), audio.clone());
+            let (stats, active) = (stats.clone(), active.clone());
+            handles.push(tokio::spawn(async move {
+                active.fetch_add(1, Ordering::Relaxed);
+                let result = run_call(cli, ua, id, codec, audio).await;
+                info!(id, setup = ?result.setup, failure = ?result.failure, "call done");
+                stats.lock().unwrap().add(result);
+                active.fetch_sub(1, Ordering::Relaxed);
+                drop(permit);
+            }));
+        }
+        for handle in handles {
+            handle.await.ok();
+        }
+    };
+    let progress = async {
+        loop {
+            time::sleep(Duration::from_secs(1)).await;
+            let completed = stats.lock().unwrap().completed();
+            println!(
+                "[{:>4}s] active: {}, completed: {}/{}",
+                started.elapsed().as_secs(),
+                active.load(Ordering::Relaxed),
+                completed,
+                cli.calls
+            );
+        }
+    };
+    select! {
+        _ = originate => {}
+        _ = progress => {}
+        r = ua.serve() => {
+            warn!("user agent stopped: {:?}", r);
+        }
+        _ = tokio::signal::ctrl_c() => {
+            println!("interrupted");
+        }
+    }
+    ua.stop();
+    println!(
+        "--- finished in {:.1}s ---",
+        started.elapsed().as_secs_f64()
+    );
+    stats.lock().unwrap().report();
+    Ok(())
+}
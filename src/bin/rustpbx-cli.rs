@@ -0,0 +1,363 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand, ValueEnum};
+use rustpbx::version;
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+#[derive(Parser, Debug)]
+#[command(
+    author,
+    version = version::get_short_version(),
+    about = "Inspects and controls a running rustpbx through its AMI interface",
+    long_about = version::get_version_info()
+)]
+struct Cli {
+    /// Base URL of the rustpbx HTTP server
+    #[clap(long, default_value = "http://127.0.0.1:8080")]
+    server: String,
+
+    /// Print the raw JSON instead of a table
+    #[clap(long)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show what the server holds right now
+    Show {
+        #[clap(value_enum)]
+        what: Show,
+    },
+    /// Read commands from the terminal until `quit`
+    Console,
+    /// Redraw the calls and the trunk health until interrupted
+    Dashboard {
+        /// Seconds between refreshes
+        #[clap(long, default_value = "2")]
+        interval: u64,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Show {
+    Calls,
+    Dialogs,
+    Transactions,
+    Registrations,
+    Trunks,
+}
+
+const CONSOLE_HELP: &str = "\
+channels                           list the active calls
+originate <user> <target> [cid]    ring the user, then connect them to the target
+hangup <id>                        hang up a call
+reload                             reload the configuration
+show <calls|dialogs|transactions|registrations|trunks>
+dashboard [seconds]                redraw calls and trunks until Ctrl-C
+help                               this help
+quit                               leave the console";
+
+struct Ami {
+    server: String,
+    client: reqwest::Client,
+    json: bool,
+}
+
+impl Ami {
+    async fn get(&self, path: &str) -> Result<Value> {
+        let url = format!("{}/ami/v1/{}", self.server, path);
+        let response = self.client.get(&url).send().await?;
+        if !response.status().is_success() {
+            anyhow::bail!("{} returned {}", url, response.status());
+        }
+        Ok(response.json().await?)
+    }
+
+    async fn post(&self, path: &str, body: Option<Value>) -> Result<Value> {
+        let url = format!("{}/ami/v1/{}", self.server, path);
+        let mut request = self.client.post(&url);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.json::<Value>().await.unwrap_or(Value::Null);
+        if !status.is_success() {
+            anyhow::bail!("{} returned {} {}", url, status, body);
+        }
+        Ok(body)
+    }
+
+    async fn show(&self, what: Show) -> Result<()> {
+        let value = match what {
+            Show::Trunks => self.get("trunks").await?,
+            _ => self.get("dump").await?,
+        };
+        let endpoints = [
+            ("useragent", &value["useragent"]),
+            ("proxy", &value["proxy"]),
+        ];
+        if self.json {
+            let section = |key: &str| {
+                let section = endpoints
+                    .iter()
+                    .map(|(name, endpoint)| (name.to_string(), endpoint[key].clone()))
+                    .collect();
+                Value::Object(section)
+            };
+            return match what {
+                Show::Calls => print_json(&value["calls"]),
+                Show::Dialogs => print_json(&section("dialogs")),
+                Show::Transactions => print_json(&section("transactions")),
+                Show::Registrations => print_json(&value["registrations"]),
+                Show::Trunks => print_json(&value),
+            };
+        }
+        match what {
+            Show::Calls => print_calls(&value),
+            Show::Dialogs => print_dialogs(&endpoints),
+            Show::Transactions => print_transactions(&endpoints),
+            Show::Registrations => print_registrations(&value)?,
+            Show::Trunks => print_trunks(&value),
+        }
+        Ok(())
+    }
+
+    async fn dashboard(&self, interval: Duration) -> Result<()> {
+        loop {
+            let dump = self.get("dump").await?;
+            // the proxy is optional, and so are its trunks
+            let trunks = self.get("trunks").await.unwrap_or(Value::Null);
+            print!("\x1b[2J\x1b[H");
+            println!(
+                "rustpbx {}  {}",
+                self.server,
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S")
+            );
+            println!();
+            print_calls(&dump);
+            if !trunks.is_null() {
+                println!();
+                print_trunks(&trunks);
+            }
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+                _ = tokio::time::sleep(interval) => {}
+            }
+        }
+    }
+
+    async fn console(&self) -> Result<()> {
+        println!("connected to {}, `help` lists the commands", self.server);
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        loop {
+            let mut stdout = tokio::io::stdout();
+            stdout.write_all(b"rustpbx> ").await?;
+            stdout.flush().await?;
+            let Some(line) = lines.next_line().await? else {
+                return Ok(());
+            };
+            let args = line.split_whitespace().collect::<Vec<_>>();
+            let r = match args.as_slice() {
+                [] => Ok(()),
+                ["quit" | "exit"] => return Ok(()),
+                ["help"] => {
+                    println!("{}", CONSOLE_HELP);
+                    Ok(())
+                }
+                ["channels"] => self.show(Show::Calls).await,
+                ["show", what] => match Show::from_str(what, true) {
+                    Ok(what) => self.show(what).await,
+                    Err(e) => Err(anyhow::anyhow!(e)),
+                },
+                ["originate", user, target, caller_id @ ..] => self
+                    .post(
+                        "clicktodial",
+                        Some(json!({
+                            "user": user,
+                            "target": target,
+                            "callerId": caller_id.first(),
+                        })),
+                    )
+                    .await
+                    .map(|r| println!("originated {}", text(&r["sessionId"]))),
+                ["hangup", id] => self
+                    .post(&format!("kill/{}", id), None)
+                    .await
+                    .map(|_| println!("hung up {}", id)),
+                ["reload"] => self
+                    .post("reload", None)
+                    .await
+                    .map(|r| println!("{}", text(&r["status"]))),
+                ["dashboard"] => self.dashboard(Duration::from_secs(2)).await,
+                ["dashboard", interval] => match interval.parse() {
+                    Ok(interval) => self.dashboard(Duration::from_secs(interval)).await,
+                    Err(e) => Err(anyhow::anyhow!("invalid interval {}: {}", interval, e)),
+                },
+                _ => Err(anyhow::anyhow!(
+                    "unknown command, `help` lists the commands"
+                )),
+            };
+            if let Err(e) = r {
+                println!("error: {}", e);
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let ami = Ami {
+        server: cli.server.trim_end_matches('/').to_string(),
+        client: reqwest::Client::new(),
+        json: cli.json,
+    };
+    match cli.command {
+        Command::Show { what } => ami.show(what).await,
+        Command::Console => ami.console().await,
+        Command::Dashboard { interval } => ami.dashboard(Duration::from_secs(interval)).await,
+    }
+}
+
+fn print_calls(dump: &Value) {
+    println!(
+        "{:<38} {:<10} {:<26} {:<6} {:<8} TRACKS",
+        "ID", "TYPE", "STARTED", "STATUS", "ANSWERED"
+    );
+    for call in dump["calls"].as_array().into_iter().flatten() {
+        let tracks = call["tracks"]
+            .as_array()
+            .map(|tracks| {
+                tracks
+                    .iter()
+                    .map(|track| format!("{}({})", text(&track["id"]), text(&track["codec"])))
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .unwrap_or_default();
+        println!(
+            "{:<38} {:<10} {:<26} {:<6} {:<8} {}",
+            text(&call["id"]),
+            text(&call["callType"]),
+            text(&call["startTime"]),
+            text(&call["lastStatusCode"]),
+            if call["answerTime"].is_null() {
+                "no"
+            } else {
+                "yes"
+            },
+            tracks
+        );
+    }
+}
+
+fn print_dialogs(endpoints: &[(&str, &Value)]) {
+    println!(
+        "{:<10} {:<60} {:<7} {:<8} {:<11} TO",
+        "ENDPOINT", "ID", "ROLE", "PENDING", "WAITING_ACK"
+    );
+    for (name, endpoint) in endpoints {
+        for dialog in endpoint["dialogs"].as_array().into_iter().flatten() {
+            println!(
+                "{:<10} {:<60} {:<7} {:<8} {:<11} {}",
+                name,
+                text(&dialog["id"]),
+                text(&dialog["role"]),
+                text(&dialog["pending"]),
+                text(&dialog["waitingAck"]),
+                text(&dialog["to"])
+            );
+        }
+        if !endpoint.is_null() {
+            let count = endpoint["dialogCount"].as_u64().unwrap_or_default() as usize;
+            let listed = endpoint["dialogs"].as_array().map_or(0, |d| d.len());
+            println!(
+                "{:<10} {} dialogs, {} not held by a call",
+                name,
+                count,
+                count.saturating_sub(listed)
+            );
+        }
+    }
+}
+
+fn print_transactions(endpoints: &[(&str, &Value)]) {
+    println!("{:<10} KEY", "ENDPOINT");
+    for (name, endpoint) in endpoints {
+        for key in endpoint["transactions"].as_array().into_iter().flatten() {
+            println!("{:<10} {}", name, text(key));
+        }
+        if !endpoint.is_null() {
+            println!(
+                "{:<10} {} finished, kept to absorb retransmissions",
+                name,
+                text(&endpoint["finishedTransactions"])
+            );
+        }
+    }
+}
+
+fn print_registrations(dump: &Value) -> Result<()> {
+    if let Some(error) = dump["registrations"]["error"].as_str() {
+        anyhow::bail!("registrations unavailable: {}", error);
+    }
+    println!(
+        "{:<30} {:<40} {:<8} INSTANCE",
+        "USER", "DESTINATION", "EXPIRES"
+    );
+    for (identifier, bindings) in dump["registrations"].as_object().into_iter().flatten() {
+        for binding in bindings.as_array().into_iter().flatten() {
+            println!(
+                "{:<30} {:<40} {:<8} {}",
+                identifier,
+                text(&binding["destination"]),
+                text(&binding["expires"]),
+                text(&binding["instanceId"])
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_trunks(trunks: &Value) {
+    println!(
+        "{:<20} {:<40} {:<5} {:<8} {:<8} LAST CHECKED",
+        "TRUNK", "DEST", "UP", "LATENCY", "FAILURES"
+    );
+    for (name, trunk) in trunks.as_object().into_iter().flatten() {
+        let health = &trunk["health"];
+        let up = match (trunk["disabled"].as_bool(), health["up"].as_bool()) {
+            (Some(true), _) => "off",
+            (_, Some(true)) => "yes",
+            (_, Some(false)) => "no",
+            _ => "-",
+        };
+        println!(
+            "{:<20} {:<40} {:<5} {:<8} {:<8} {}",
+            name,
+            text(&trunk["dest"]),
+            up,
+            text(&health["latencyMs"]),
+            text(&health["failures"]),
+            text(&health["lastChecked"])
+        );
+    }
+}
+
+fn print_json(value: &Value) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
@@ -1,7 +1,17 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use dotenv::dotenv;
-use rustpbx::{app::AppStateBuilder, config::Config, version};
+use rustpbx::{
+    config::Config,
+    config_schema::{check_config, config_schema},
+    media::{
+        codecs::transcode::{AudioFileFormat, TranscodeOption, transcode},
+        workers::WorkerLayout,
+    },
+    pbx::RustPbxBuilder,
+    version,
+};
+use std::{path::PathBuf, str::FromStr};
 use tokio::select;
 use tracing::{info, level_filters::LevelFilter};
 use tracing_subscriber::{
@@ -19,10 +29,52 @@ struct Cli {
     /// Path to the configuration file
     #[clap(long, help = "Path to the configuration file (TOML format)")]
     conf: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Convert an audio file between WAV, raw PCM and raw codec payloads
+    /// (pcmu, pcma, g722, g729, opus) with the codecs of the media path
+    Transcode {
+        /// File to read
+        input: PathBuf,
+        /// File to write
+        output: PathBuf,
+        /// Format of the input, else found from its extension
+        #[clap(long, value_parser = AudioFileFormat::from_str)]
+        from: Option<AudioFileFormat>,
+        /// Format of the output, else found from its extension
+        #[clap(long, value_parser = AudioFileFormat::from_str)]
+        to: Option<AudioFileFormat>,
+        /// Sample rate of raw PCM input
+        #[clap(long, default_value = "8000")]
+        input_rate: u32,
+        /// Sample rate of WAV and raw PCM output, else the input's
+        #[clap(long)]
+        rate: Option<u32>,
+    },
+    /// Check a configuration file, or print its JSON Schema
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Check a configuration file, exits with 1 when it cannot be loaded
+    Validate {
+        /// File to check, else the one of --conf
+        file: Option<PathBuf>,
+    },
+    /// Print the JSON Schema of the configuration
+    Schema,
+}
+
+fn main() -> Result<()> {
     rustls::crypto::ring::default_provider()
         .install_default()
         .expect("Failed to install rustls crypto provider");
@@ -31,11 +83,70 @@ async fn main() -> Result<()> {
     dotenv().ok();
     let cli = Cli::parse();
 
+    if let Some(Command::Config { command }) = cli.command {
+        match command {
+            ConfigCommand::Schema => {
+                println!("{}", serde_json::to_string_pretty(&config_schema())?);
+            }
+            ConfigCommand::Validate { file } => {
+                let Some(file) = file.or(cli.conf.map(PathBuf::from)) else {
+                    return Err(anyhow::anyhow!("no file to validate, give one or --conf"));
+                };
+                let raw = std::fs::read_to_string(&file)
+                    .map_err(|e| anyhow::anyhow!("{}: {}", e, file.display()))?;
+                let issues = check_config(&raw);
+                for issue in issues.iter() {
+                    println!("{}:{}", file.display(), issue);
+                }
+                if issues.iter().any(|issue| !issue.warning) {
+                    std::process::exit(1);
+                }
+                println!("{}: ok", file.display());
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Transcode {
+        input,
+        output,
+        from,
+        to,
+        input_rate,
+        rate,
+    }) = cli.command
+    {
+        let option = TranscodeOption {
+            input_format: from,
+            output_format: to,
+            input_sample_rate: input_rate,
+            sample_rate: rate,
+        };
+        let duration = transcode(&input, &output, &option)?;
+        println!(
+            "{} -> {}: {:.2}s of audio",
+            input.display(),
+            output.display(),
+            duration.as_secs_f64()
+        );
+        return Ok(());
+    }
+
     let config = cli
         .conf
         .map(|conf| Config::load(&conf).expect("Failed to load config"))
         .unwrap_or_default();
+    let layout = WorkerLayout::new(&config.media_workers.clone().unwrap_or_default())
+        .expect("Failed to lay out media workers");
+    layout.start().expect("Failed to start media runtime");
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to build runtime")
+        .block_on(serve(config, layout))
+}
 
+async fn serve(config: Config, layout: WorkerLayout) -> Result<()> {
     println!("{}", version::get_version_info());
     let mut env_filter = EnvFilter::from_default_env();
     if let Some(Ok(level)) = config
@@ -80,9 +191,13 @@ async fn main() -> Result<()> {
             .with(fmt_layer)
             .init();
     };
+    layout.log();
 
-    let state_builder = AppStateBuilder::new().with_config(config);
-    let (state, sip_server) = state_builder.build().await.expect("Failed to build app");
+    let pbx = RustPbxBuilder::new()
+        .with_config(config)
+        .build()
+        .await
+        .expect("Failed to build app");
 
     #[cfg(unix)]
     let sigterm = async {
@@ -96,9 +211,9 @@ async fn main() -> Result<()> {
     #[cfg(not(unix))]
     let sigterm = std::future::pending::<()>();
 
-    info!("starting rustpbx on {}", state.config.http_addr);
+    info!("starting rustpbx on {}", pbx.state.config.http_addr);
     select! {
-        _ = rustpbx::app::run(state, sip_server) => {}
+        _ = pbx.serve() => {}
         _ = tokio::signal::ctrl_c() => {
             info!("received CTRL+C, shutting down");
         }
@@ -3,43 +3,69 @@ use crate::{
     TrackId,
     app::AppState,
     call::{
-        CommandReceiver, CommandSender,
+        CallVariables, CommandReceiver, CommandSender, HangupCause,
+        bypass::{DirectMedia, MediaLeg},
+        consent,
+        flow::{FlowState, FlowStore},
+        gather::{Gather, GatherOption, GatherStep},
         sip::{DialogGuard, Invitation, client_dialog_event_loop, server_dialog_event_loop},
+        topology::{CallLeg, CallTopology},
     },
     callrecord::{CallRecord, CallRecordEvent, CallRecordEventType, CallRecordHangupReason},
+    config::DirectMediaFailureAction,
     event::{EventReceiver, EventSender, SessionEvent},
+    features,
     media::{
+        dtmf::{DTMF_DEFAULT_DURATION_MS, DtmfMode, dtmf_info_body},
         engine::StreamEngine,
-        negotiate::strip_ipv6_candidates,
+        meter::TrackLevels,
+        mixer::SuperviseMode,
+        negotiate::{SdpCapabilities, strip_ipv6_candidates},
         recorder::RecorderOption,
-        stream::{MediaStream, MediaStreamBuilder},
+        shaper::ShapingOption,
+        stream::{MediaStream, MediaStreamBuilder, TrackDirection},
         track::{
             Track, TrackConfig,
+            audiosocket::AudioSocketTrack,
+            echo::EchoTrack,
             file::FileTrack,
+            http_stream::HttpStreamTrack,
             media_pass::MediaPassTrack,
             rtp::{RtpTrack, RtpTrackBuilder},
+            rtp_fork::RtpForkTrack,
+            tone::ToneTrack,
             tts::SynthesisHandle,
             webrtc::WebrtcTrack,
             websocket::{WebsocketBytesReceiver, WebsocketTrack},
         },
+        workers,
+    },
+    synthesis::{
+        SynthesisCommand, SynthesisOption,
+        prompt::{self, Language, PromptPart, PromptSegment},
     },
-    synthesis::{SynthesisCommand, SynthesisOption},
     useragent::invitation::PendingDialog,
 };
 use anyhow::Result;
 use chrono::{DateTime, Utc};
-use rsipstack::dialog::{invitation::InviteOption, server_dialog::ServerInviteDialog};
+use rsip::prelude::HeadersExt;
+use rsipstack::dialog::{
+    dialog::Dialog, invitation::InviteOption, server_dialog::ServerInviteDialog,
+};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     path::Path,
     sync::{Arc, RwLock},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tokio::{fs::File, select, sync::Mutex, time::sleep};
 use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
+/// How long media bypass waits for the caller's ACK, 64*T1
+const DIRECT_MEDIA_WAIT: Duration = Duration::from_secs(32);
+
 #[derive(Deserialize)]
 pub struct CallParams {
     pub id: Option<String>,
@@ -64,13 +90,32 @@ pub struct ActiveCallState {
     pub ring_time: Option<DateTime<Utc>>,
     pub answer_time: Option<DateTime<Utc>>,
     pub hangup_reason: Option<CallRecordHangupReason>,
+    pub hangup_cause: Option<HangupCause>,
     pub last_status_code: u16,
     pub option: Option<CallOption>,
     pub answer: Option<String>,
     pub dialog: Option<DialogGuard>,
     pub ssrc: u32,
     pub refer_callstate: Option<ActiveCallStateRef>,
+    /// The legs dialed for supervisors, by their track id
+    pub supervisors: HashMap<String, ActiveCallStateRef>,
     pub extras: Option<HashMap<String, serde_json::Value>>,
+    /// Shared with the legs bridged or transferred from this call
+    pub variables: CallVariables,
+    /// Language of the prompts, the prompt pack's default when unset
+    pub locale: Option<String>,
+    /// The IVR flow of the call, once it entered a node
+    pub flow: Option<FlowState>,
+}
+
+/// The rest of a prompt, played when the segment `ssrc` ends
+struct PendingPrompt {
+    ssrc: u32,
+    parts: VecDeque<PromptPart>,
+    option: Option<SynthesisOption>,
+    play_id: Option<String>,
+    auto_hangup: Option<bool>,
+    wait_input_timeout: Option<u32>,
 }
 
 pub type ActiveCallRef = Arc<ActiveCall>;
@@ -86,6 +131,10 @@ pub struct ActiveCall {
     pub tts_handle: Mutex<Option<SynthesisHandle>>,
     pub auto_hangup: Arc<Mutex<Option<(u32, CallRecordHangupReason)>>>,
     pub wait_input_timeout: Arc<Mutex<Option<u32>>>,
+    /// echo test waiting for its prompt (by ssrc) to finish
+    pending_echo: Mutex<Option<(u32, Duration)>>,
+    pending_prompt: Mutex<Option<PendingPrompt>>,
+    pending_gather: Mutex<Option<Gather>>,
     pub event_sender: EventSender,
     pub app_state: AppState,
     pub invitation: Invitation,
@@ -95,6 +144,10 @@ pub struct ActiveCall {
     pub server_side_track_id: TrackId,
     can_start_send_command: CancellationToken,
     ready_to_answer: Mutex<Option<(String, Option<Box<dyn Track>>, ServerInviteDialog)>>,
+    /// Whether the media of a B2BUA call flows through rustpbx
+    direct_media: Mutex<DirectMedia>,
+    /// Which legs of the call are bridged, for billing and fraud detection
+    pub topology: CallTopology,
 }
 
 impl ActiveCall {
@@ -116,12 +169,23 @@ impl ActiveCall {
             .with_id(session_id.clone())
             .with_cancel_token(cancel_token.child_token());
         let media_stream = Arc::new(media_stream_builder.build());
+        let reinvite_timeout = app_state
+            .config
+            .proxy
+            .as_ref()
+            .and_then(|proxy| proxy.direct_media.as_ref()?.reinvite_timeout)
+            .map(Duration::from_secs);
         let call_state = Arc::new(RwLock::new(ActiveCallState {
             start_time: Utc::now(),
             ssrc: rand::random::<u32>(),
             extras,
             ..Default::default()
         }));
+        let topology = CallTopology::new(
+            session_id.clone(),
+            event_sender.clone(),
+            cancel_token.clone(),
+        );
         Self {
             cancel_token,
             call_type,
@@ -131,6 +195,9 @@ impl ActiveCall {
             track_config,
             auto_hangup: Arc::new(Mutex::new(None)),
             wait_input_timeout: Arc::new(Mutex::new(None)),
+            pending_echo: Mutex::new(None),
+            pending_prompt: Mutex::new(None),
+            pending_gather: Mutex::new(None),
             event_sender,
             tts_handle: Mutex::new(None),
             app_state,
@@ -141,6 +208,186 @@ impl ActiveCall {
             server_side_track_id: server_side_track_id.unwrap_or("server-side-track".to_string()),
             can_start_send_command: CancellationToken::new(),
             ready_to_answer: Mutex::new(None),
+            direct_media: Mutex::new(DirectMedia::default().with_timeout(reinvite_timeout)),
+            topology,
+        }
+    }
+
+    pub fn variables(&self) -> CallVariables {
+        self.call_state
+            .read()
+            .map(|cs| cs.variables.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn locale(&self) -> Option<String> {
+        self.call_state.read().ok()?.locale.clone()
+    }
+
+    /// Prompts played from now on are looked up in `locale` first
+    pub fn set_locale(&self, locale: impl Into<String>) {
+        if let Ok(mut call_state) = self.call_state.write() {
+            call_state.locale = Some(locale.into());
+        }
+    }
+
+    pub fn flow(&self) -> Option<FlowState> {
+        self.call_state.read().ok()?.flow.clone()
+    }
+
+    fn flow_store(&self) -> Option<FlowStore> {
+        self.app_state.config.flow.as_ref().and_then(FlowStore::new)
+    }
+
+    async fn do_flow_enter(
+        &self,
+        node: String,
+        input: Option<String>,
+        data: HashMap<String, serde_json::Value>,
+    ) -> Result<()> {
+        info!(session_id = self.session_id, node, input, "flow enter");
+        let flow = {
+            let mut call_state = self
+                .call_state
+                .write()
+                .map_err(|_| anyhow::anyhow!("failed to write call state"))?;
+            let flow = call_state.flow.get_or_insert_with(|| {
+                FlowState::new(self.session_id.clone(), self.session_id.clone())
+            });
+            self.app_state.flow_stats.enter(flow.node(), &node);
+            flow.enter(node, input, data, crate::get_timestamp());
+            flow.clone()
+        };
+        match self.flow_store() {
+            Some(store) => store.save(&flow).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn do_flow_resume(&self, flow_id: String) -> Result<()> {
+        let store = self
+            .flow_store()
+            .ok_or_else(|| anyhow::anyhow!("flow states are not saved"))?;
+        let mut flow = store
+            .load(&flow_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("no saved flow: {}", flow_id))?;
+        info!(
+            session_id = self.session_id,
+            flow_id,
+            from = flow.session_id,
+            node = flow.node(),
+            "flow resume"
+        );
+        flow.session_id = self.session_id.clone();
+        flow.updated_at = crate::get_timestamp();
+        store.save(&flow).await?;
+        if let Ok(mut call_state) = self.call_state.write() {
+            call_state.flow = Some(flow.clone());
+        }
+        self.event_sender
+            .send(SessionEvent::FlowResumed {
+                track_id: self.server_side_track_id.clone(),
+                timestamp: crate::get_timestamp(),
+                flow,
+            })
+            .ok();
+        Ok(())
+    }
+
+    async fn do_flow_experiment(
+        &self,
+        name: String,
+        variants: Option<HashMap<String, u32>>,
+    ) -> Result<()> {
+        let variants = match variants {
+            Some(variants) => variants,
+            None => self
+                .app_state
+                .config
+                .flow
+                .as_ref()
+                .and_then(|config| config.experiments.get(&name).cloned())
+                .ok_or_else(|| anyhow::anyhow!("no variants for experiment: {}", name))?,
+        };
+        let (variant, joined, flow) = {
+            let mut call_state = self
+                .call_state
+                .write()
+                .map_err(|_| anyhow::anyhow!("failed to write call state"))?;
+            let flow = call_state.flow.get_or_insert_with(|| {
+                FlowState::new(self.session_id.clone(), self.session_id.clone())
+            });
+            let joined = !flow.experiments.contains_key(&name);
+            let variant = flow
+                .join(&name, &variants)
+                .ok_or_else(|| anyhow::anyhow!("no variants for experiment: {}", name))?;
+            (variant, joined, flow.clone())
+        };
+        info!(
+            session_id = self.session_id,
+            name, variant, joined, "flow experiment"
+        );
+        if joined {
+            self.app_state.flow_stats.assign(&name, &variant);
+        }
+        self.variables()
+            .set(format!("experiment.{}", name), variant.clone());
+        if let Some(store) = self.flow_store() {
+            store.save(&flow).await?;
+        }
+        self.event_sender
+            .send(SessionEvent::Experiment {
+                track_id: self.server_side_track_id.clone(),
+                timestamp: crate::get_timestamp(),
+                name,
+                variant,
+            })
+            .ok();
+        Ok(())
+    }
+
+    /// A flow ends with its call, unless the call was transferred and the
+    /// flow may resume on the other side
+    async fn finish_flow(&self) {
+        let (flow, transferred, abandoned) = match self.call_state.read() {
+            Ok(call_state) => (
+                call_state.flow.clone(),
+                matches!(
+                    call_state.hangup_reason,
+                    Some(CallRecordHangupReason::ByRefer)
+                ),
+                matches!(
+                    call_state.hangup_reason,
+                    Some(CallRecordHangupReason::ByCaller)
+                ),
+            ),
+            Err(_) => return,
+        };
+        let Some(flow) = flow else {
+            return;
+        };
+        self.app_state.flow_stats.finish(&flow, abandoned);
+        let Some(store) = self.flow_store() else {
+            return;
+        };
+        // resumed by another call since
+        let saved = store.load(&flow.flow_id).await.ok().flatten();
+        if saved.is_some_and(|saved| saved.session_id != self.session_id) {
+            return;
+        }
+        let result = match transferred {
+            true => store.save(&flow).await,
+            false => store.remove(&flow.flow_id).await,
+        };
+        if let Err(e) = result {
+            warn!(session_id = self.session_id, "failed to finish flow: {}", e);
+        }
+    }
+
+    fn apply_variables(&self, option: &CallOption) {
+        if let Some(variables) = &option.variables {
+            self.variables().extend(variables.clone());
         }
     }
 
@@ -159,6 +406,11 @@ impl ActiveCall {
         let mut cmd_receiver = self.cmd_sender.subscribe();
         let dump_cmd_receiver = self.cmd_sender.subscribe();
         let dump_event_receiver = self.event_sender.subscribe();
+        let journal_receivers = self
+            .app_state
+            .journal
+            .as_ref()
+            .map(|_| (self.cmd_sender.subscribe(), self.event_sender.subscribe()));
         // can `enqueue_command` when subscribe is done
         // so we can start sending commands
         self.can_start_send_command.cancel();
@@ -188,6 +440,7 @@ impl ActiveCall {
 
         tokio::join!(
             self.dump_loop(self.dump_events, dump_cmd_receiver, dump_event_receiver),
+            self.journal_loop(journal_receivers),
             async {
                 select! {
                     _ = process_command_loop => {
@@ -203,9 +456,26 @@ impl ActiveCall {
             }
         );
         self.cleanup().await.ok();
+        self.finish_flow().await;
+        // the journal stopped listening with the call
+        let topology = self.topology.end();
+        let callrecord = self.get_callrecord().await;
+        if let Some(journal) = self.app_state.journal.as_ref() {
+            for event in topology {
+                journal.record(CallRecordEventType::Event, Some(&self.session_id), &event);
+            }
+            journal.record(
+                CallRecordEventType::CallRecord,
+                Some(&self.session_id),
+                &callrecord,
+            );
+        }
+        if let Some(transcriber) = self.app_state.transcriber.as_ref() {
+            transcriber.submit(&callrecord);
+        }
         // Send call record if available
         if let Some(sender) = self.app_state.callrecord_sender.as_ref() {
-            if let Err(e) = sender.send(self.get_callrecord().await) {
+            if let Err(e) = sender.send(callrecord) {
                 warn!(
                     session_id = self.session_id,
                     "failed to send call record: {}", e
@@ -239,15 +509,42 @@ impl ActiveCall {
                         })
                         .ok();
                 }
+                self.update_gather(|gather, now| gather.poll(now)).await;
                 sleep(Duration::from_millis(100)).await;
             }
         };
         let server_side_track_id = &self.server_side_track_id;
         let event_hook_loop = async move {
             while let Ok(event) = event_receiver.recv().await {
+                match &event {
+                    SessionEvent::Dtmf {
+                        track_id,
+                        digit,
+                        duration,
+                        ..
+                    } => {
+                        self.relay_dtmf(track_id, digit, *duration);
+                        self.update_gather(|gather, now| gather.on_dtmf(digit, now))
+                            .await;
+                    }
+                    SessionEvent::Speaking { track_id, .. }
+                    | SessionEvent::AsrDelta { track_id, .. }
+                        if track_id != server_side_track_id =>
+                    {
+                        self.update_gather(|gather, now| gather.on_speech_start(now))
+                            .await;
+                    }
+                    SessionEvent::AsrFinal { text, .. }
+                    | SessionEvent::Keyword { keyword: text, .. } => {
+                        self.update_gather(|gather, now| gather.on_speech(text, now))
+                            .await;
+                    }
+                    _ => {}
+                }
                 match event {
                     SessionEvent::Speaking { .. }
                     | SessionEvent::Dtmf { .. }
+                    | SessionEvent::Keyword { .. }
                     | SessionEvent::AsrDelta { .. }
                     | SessionEvent::AsrFinal { .. }
                     | SessionEvent::TrackStart { .. } => {
@@ -283,6 +580,35 @@ impl ActiveCall {
                             };
                             *input_timeout_expire_ref.lock().await = expire;
                         }
+                        let echo = {
+                            let mut pending_echo = self.pending_echo.lock().await;
+                            match *pending_echo {
+                                Some((echo_ssrc, _)) if echo_ssrc == ssrc => pending_echo.take(),
+                                _ => None,
+                            }
+                        };
+                        if let Some((_, delay)) = echo {
+                            self.start_echo(delay).await;
+                        }
+                        let prompt = {
+                            let mut pending_prompt = self.pending_prompt.lock().await;
+                            match *pending_prompt {
+                                Some(ref prompt) if prompt.ssrc == ssrc => pending_prompt.take(),
+                                _ => None,
+                            }
+                        };
+                        if let Some(prompt) = prompt
+                            && let Err(e) = self.play_prompt(prompt).await
+                        {
+                            warn!(session_id = self.session_id, "failed to play prompt: {}", e);
+                        }
+                        self.update_gather(|gather, now| {
+                            if gather.prompt_ssrc == Some(ssrc) {
+                                gather.prompt_done(now);
+                            }
+                            GatherStep::Wait
+                        })
+                        .await;
                     }
                     _ => {}
                 }
@@ -293,7 +619,10 @@ impl ActiveCall {
             _ = wait_input_timeout_loop=>{
                 info!(session_id = self.session_id, "wait input timeout loop done");
             }
-            _ = self.media_stream.serve() => {
+            _ = workers::run({
+                let media_stream = self.media_stream.clone();
+                async move { media_stream.serve().await }
+            }) => {
                 info!(session_id = self.session_id, "media stream loop done");
             }
             _ = event_hook_loop => {
@@ -308,8 +637,14 @@ impl ActiveCall {
 
     async fn dispatch(&self, command: Command) -> Result<()> {
         match command {
-            Command::Invite { option } => self.do_invite(option).await,
-            Command::Accept { option } => self.do_accept(option).await,
+            Command::Invite { option } => {
+                self.apply_variables(&option);
+                self.do_invite(option).await
+            }
+            Command::Accept { option } => {
+                self.apply_variables(&option);
+                self.do_accept(option).await
+            }
             Command::Reject { reason, code } => {
                 self.do_reject(code.map(|c| (c as u16).into()), Some(reason))
                     .await
@@ -345,7 +680,36 @@ impl ActiveCall {
                 url,
                 auto_hangup,
                 wait_input_timeout,
-            } => self.do_play(url, auto_hangup, wait_input_timeout).await,
+                stream,
+            } => {
+                self.do_play(
+                    url,
+                    auto_hangup,
+                    wait_input_timeout,
+                    stream.unwrap_or_default(),
+                )
+                .await
+            }
+            Command::Prompt {
+                segments,
+                locale,
+                variables,
+                play_id,
+                auto_hangup,
+                option,
+                wait_input_timeout,
+            } => {
+                self.do_prompt(
+                    segments,
+                    locale,
+                    variables,
+                    play_id,
+                    auto_hangup,
+                    option,
+                    wait_input_timeout,
+                )
+                .await
+            }
             Command::Hangup { reason, initiator } => {
                 let reason = reason.map(|r| {
                     r.parse::<CallRecordHangupReason>()
@@ -358,15 +722,296 @@ impl ActiveCall {
                 callee,
                 options,
             } => self.do_refer(caller, callee, options).await,
-            Command::Mute { track_id } => self.do_mute(track_id).await,
-            Command::Unmute { track_id } => self.do_unmute(track_id).await,
+            Command::Mute {
+                track_id,
+                direction,
+            } => self.do_mute(track_id, direction).await,
+            Command::Unmute {
+                track_id,
+                direction,
+            } => self.do_unmute(track_id, direction).await,
+            Command::Echo { delay, prompt } => self.do_echo(delay, prompt).await,
+            Command::Milliwatt {
+                frequency,
+                level,
+                duration,
+            } => self.do_milliwatt(frequency, level, duration).await,
+            Command::ProbeLatency { track_id, timeout } => {
+                self.do_probe_latency(track_id, timeout).await
+            }
+            Command::Gain {
+                track_id,
+                gain,
+                direction,
+            } => self.do_gain(track_id, gain, direction).await,
+            Command::Supervise {
+                track_id,
+                target,
+                mode,
+            } => self.do_supervise(track_id, target, mode).await,
+            Command::AttachSupervisor {
+                track_id,
+                target,
+                mode,
+                callee,
+                offer,
+            } => {
+                self.do_attach_supervisor(track_id, target, mode, callee, offer)
+                    .await
+            }
             Command::Pause {} => self.do_pause().await,
             Command::Resume {} => self.do_resume().await,
+            Command::Gather {
+                segments,
+                locale,
+                variables,
+                play_id,
+                option,
+                input,
+            } => {
+                self.do_gather(
+                    segments.unwrap_or_default(),
+                    locale,
+                    variables,
+                    play_id,
+                    option,
+                    input.unwrap_or_default(),
+                )
+                .await
+            }
             Command::Interrupt {} => self.do_interrupt().await,
             Command::History { speaker, text } => self.do_history(speaker, text).await,
+            Command::SetVariables { variables } => {
+                self.variables().update(variables);
+                Ok(())
+            }
+            Command::SetLocale { locale } => {
+                info!(session_id = self.session_id, locale, "set locale");
+                self.set_locale(locale);
+                Ok(())
+            }
+            Command::FlowEnter { node, input, data } => {
+                self.do_flow_enter(node, input, data.unwrap_or_default())
+                    .await
+            }
+            Command::FlowResume { flow_id } => self.do_flow_resume(flow_id).await,
+            Command::FlowExperiment { name, variants } => {
+                self.do_flow_experiment(name, variants).await
+            }
+            Command::Record { recorder } => self.do_record(recorder).await,
+        }
+    }
+
+    /// Takes rustpbx out of the media path once the caller is answered:
+    /// the caller and the callee are sent each other's SDP
+    pub async fn bypass_media(&self, caller_sdp: String, callee: MediaLeg) -> Result<()> {
+        let deadline = Instant::now() + DIRECT_MEDIA_WAIT;
+        let caller = loop {
+            let dialog_id = self
+                .call_state
+                .read()
+                .ok()
+                .and_then(|cs| Some(cs.dialog.as_ref()?.id().clone()));
+            let anchor = self.direct_media.lock().await.caller_anchor.clone();
+            if let (Some(dialog_id), Some(anchor)) = (dialog_id, anchor) {
+                break MediaLeg::new(dialog_id, caller_sdp, anchor);
+            }
+            if Instant::now() >= deadline || self.cancel_token.is_cancelled() {
+                return Err(anyhow::anyhow!("caller not answered"));
+            }
+            sleep(Duration::from_millis(100)).await;
+        };
+        let result = self
+            .direct_media
+            .lock()
+            .await
+            .bypass(&self.invitation, caller, callee)
+            .await;
+        if let Err(e) = &result {
+            self.on_direct_media_failure(e).await;
+        }
+        result
+    }
+
+    /// Brings the media back through rustpbx if it bypassed it
+    pub async fn anchor_media(&self) -> Result<()> {
+        let result = self
+            .direct_media
+            .lock()
+            .await
+            .anchor(&self.invitation)
+            .await;
+        if let Err(e) = &result {
+            self.on_direct_media_failure(e).await;
+        }
+        result
+    }
+
+    /// Carries on, plays the failure prompt or hangs up as `direct_media`
+    /// says, once a direct media re-INVITE failed and the media was kept as
+    /// it was
+    async fn on_direct_media_failure(&self, error: &anyhow::Error) {
+        let Some(config) = self
+            .app_state
+            .config
+            .proxy
+            .as_ref()
+            .and_then(|proxy| proxy.direct_media.as_ref())
+        else {
+            return;
+        };
+        warn!(
+            session_id = self.session_id,
+            action = ?config.on_failure,
+            "direct media re-INVITE failed: {}", error
+        );
+        match config.on_failure {
+            DirectMediaFailureAction::Keep => {}
+            DirectMediaFailureAction::Prompt => {
+                let Some(prompt) = config.failure_prompt.clone() else {
+                    return;
+                };
+                if let Err(e) = self.do_play(prompt, None, None, false).await {
+                    warn!(
+                        session_id = self.session_id,
+                        "failed to play failure prompt: {}", e
+                    );
+                }
+            }
+            DirectMediaFailureAction::Hangup => {
+                let cause = config
+                    .failure_cause
+                    .unwrap_or(HangupCause::BearerCapabilityNotAvailable);
+                if let Ok(mut cs) = self.call_state.write() {
+                    cs.hangup_cause.get_or_insert(cause);
+                }
+                self.do_hangup(Some(CallRecordHangupReason::Failed), None)
+                    .await
+                    .ok();
+            }
         }
     }
 
+    /// Relay the digits of each leg of a B2BUA call to the other, in the
+    /// mode the other takes them in
+    pub async fn set_dtmf_interworking(&self, caller: DtmfMode, callee: DtmfMode) {
+        info!(
+            session_id = self.session_id,
+            ?caller,
+            ?callee,
+            "dtmf interworking"
+        );
+        self.media_stream
+            .set_dtmf_modes(vec![
+                (self.session_id.clone(), caller),
+                (self.server_side_track_id.clone(), callee),
+            ])
+            .await;
+    }
+
+    /// Send a digit received from one leg to the other, unless the media
+    /// already carries it there
+    fn relay_dtmf(&self, from: &TrackId, digit: &str, duration: Option<u64>) {
+        let to = if from == &self.session_id {
+            self.server_side_track_id.clone()
+        } else if from == &self.server_side_track_id {
+            self.session_id.clone()
+        } else {
+            return;
+        };
+        let mode = match (
+            self.media_stream.dtmf_mode(from),
+            self.media_stream.dtmf_mode(&to),
+        ) {
+            (Some(from_mode), Some(to_mode)) => match from_mode.interwork(to_mode) {
+                Some(mode) => mode,
+                None => return,
+            },
+            _ => return,
+        };
+        let duration = duration.unwrap_or(DTMF_DEFAULT_DURATION_MS);
+        info!(
+            session_id = self.session_id,
+            from,
+            to,
+            digit,
+            duration,
+            ?mode,
+            "relay dtmf"
+        );
+        let session_id = self.session_id.clone();
+        let digit = digit.to_string();
+        if mode != DtmfMode::Info {
+            let media_stream = self.media_stream.clone();
+            tokio::spawn(async move {
+                if let Err(e) = media_stream
+                    .send_dtmf(&to, &digit, Duration::from_millis(duration))
+                    .await
+                {
+                    warn!(session_id, to, "failed to relay dtmf: {}", e);
+                }
+            });
+            return;
+        }
+        let dialog_id = self.call_state.read().ok().and_then(|cs| {
+            if to == self.session_id {
+                cs.dialog.as_ref().map(|dialog| dialog.id().clone())
+            } else {
+                let callee = cs.refer_callstate.as_ref()?.read().ok()?;
+                callee.dialog.as_ref().map(|dialog| dialog.id().clone())
+            }
+        });
+        let dialog = match dialog_id.and_then(|id| self.invitation.dialog_layer.get_dialog(&id)) {
+            Some(dialog) => dialog,
+            None => {
+                warn!(session_id, to, "no dialog to relay dtmf in");
+                return;
+            }
+        };
+        let headers = vec![rsip::Header::ContentType(
+            "application/dtmf-relay".to_string().into(),
+        )];
+        let body = dtmf_info_body(&digit, duration).into_bytes();
+        tokio::spawn(async move {
+            let result = match dialog {
+                Dialog::ServerInvite(dialog) => dialog.info(Some(headers), Some(body)).await,
+                Dialog::ClientInvite(dialog) => dialog.info(Some(headers), Some(body)).await,
+            };
+            if let Err(e) = result {
+                warn!(session_id, to, "failed to relay dtmf in INFO: {}", e);
+            }
+        });
+    }
+
+    pub async fn is_media_bypassed(&self) -> bool {
+        self.direct_media.lock().await.is_bypassed()
+    }
+
+    async fn do_record(&self, recorder: Option<RecorderOption>) -> Result<()> {
+        if let Err(e) = self.anchor_media().await {
+            warn!(
+                session_id = self.session_id,
+                "failed to anchor media: {}", e
+            );
+            // hung up on the failure
+            if self.cancel_token.is_cancelled() {
+                return Err(e);
+            }
+        }
+        let option = CallOption {
+            recorder: Some(recorder.unwrap_or_default()),
+            ..Default::default()
+        };
+        if let Some(recorder) = self.build_record_option(&option) {
+            self.media_stream.update_recorder_option(recorder).await;
+        }
+        // the CDR lists the recording
+        if let Ok(mut cs) = self.call_state.write() {
+            cs.option.get_or_insert_default().recorder = option.recorder;
+        }
+        Ok(())
+    }
+
     fn build_record_option(&self, option: &CallOption) -> Option<RecorderOption> {
         if let Some(recorder_option) = &option.recorder {
             let recorder_file = if recorder_option.recorder_file.is_empty() {
@@ -400,6 +1045,7 @@ impl ActiveCall {
                 recorder_file,
                 samplerate: recorder_samplerate,
                 ptime: recorder_ptime,
+                tap: recorder_option.tap,
             };
             Some(recorder_config)
         } else {
@@ -407,8 +1053,49 @@ impl ActiveCall {
         }
     }
 
+    /// Drops what the features of the call's caller leave out. Calls of the
+    /// proxy get theirs from the proxy
+    fn apply_features(&self, option: &mut CallOption, invite: bool) -> Result<()> {
+        if matches!(self.call_type, ActiveCallType::B2bua) {
+            return Ok(());
+        }
+        let features = &self.app_state.features;
+        let caller = option
+            .caller
+            .as_ref()
+            .and_then(|caller| rsip::Uri::try_from(caller.as_str()).ok());
+        let tenant = caller
+            .as_ref()
+            .map(|uri| features.tenant_of(&uri.host().to_string()));
+        let extension = caller
+            .as_ref()
+            .map(|uri| format!("{}@{}", uri.user().unwrap_or_default(), uri.host()).to_lowercase());
+        let enabled = |feature| features.enabled(feature, tenant.as_deref(), extension.as_deref());
+        if option.recorder.is_some() && !enabled(features::RECORDING) {
+            info!(session_id = self.session_id, "recording is disabled");
+            option.recorder = None;
+        }
+        if option.asr.is_some() && !enabled(features::ASR) {
+            info!(session_id = self.session_id, "asr is disabled");
+            option.asr = None;
+        }
+        let callee = option
+            .callee
+            .as_ref()
+            .and_then(|callee| rsip::Uri::try_from(callee.as_str()).ok());
+        if invite
+            && matches!(self.call_type, ActiveCallType::Sip)
+            && callee.is_some_and(|uri| features.is_international(uri.user().unwrap_or_default()))
+            && !enabled(features::INTERNATIONAL)
+        {
+            return Err(anyhow::anyhow!("international dialing is disabled"));
+        }
+        Ok(())
+    }
+
     async fn invite_or_accept(&self, mut option: CallOption, sender: String) -> Result<CallOption> {
         option.check_default();
+        self.apply_features(&mut option, sender == "invite")?;
         if let Some(opt) = self.build_record_option(&option) {
             self.media_stream.update_recorder_option(opt).await;
         }
@@ -423,6 +1110,40 @@ impl ActiveCall {
                 .await;
         }
 
+        if let Some(opt) = &option.rtp_fork {
+            let cancel_token = self.cancel_token.child_token();
+            let ssrc = rand::random::<u32>();
+            match RtpForkTrack::new(ssrc, cancel_token, opt.clone()) {
+                Ok(rtp_fork_track) => {
+                    self.media_stream
+                        .update_track(Box::new(rtp_fork_track), None)
+                        .await;
+                }
+                Err(e) => {
+                    warn!(session_id = self.session_id, "failed to fork rtp: {}", e);
+                }
+            }
+        }
+
+        if let Some(opt) = &option.audiosocket {
+            let cancel_token = self.cancel_token.child_token();
+            let ssrc = rand::random::<u32>();
+            match AudioSocketTrack::new(ssrc, cancel_token, opt.clone()) {
+                Ok(audiosocket_track) => {
+                    self.hangup_on_track_end(audiosocket_track.id().clone(), ssrc);
+                    self.media_stream
+                        .update_track(Box::new(audiosocket_track), None)
+                        .await;
+                }
+                Err(e) => {
+                    warn!(
+                        session_id = self.session_id,
+                        "failed to add audiosocket: {}", e
+                    );
+                }
+            }
+        }
+
         info!(
             session_id = self.session_id,
             call_type = ?self.call_type,
@@ -464,6 +1185,7 @@ impl ActiveCall {
             option = self.invite_or_accept(option, "accept".to_string()).await?;
         } else {
             option.check_default();
+            self.apply_features(&mut option, false)?;
             self.call_state
                 .write()
                 .as_mut()
@@ -481,9 +1203,26 @@ impl ActiveCall {
             let headers = vec![rsip::Header::ContentType(
                 "application/sdp".to_string().into(),
             )];
+            // sent again to anchor the media after it bypassed rustpbx
+            self.direct_media.lock().await.caller_anchor = Some(answer.clone());
+            if dialog.initial_request().body.is_empty()
+                && let Some(retransmitter) = self.invitation.retransmitter.as_ref()
+            {
+                retransmitter
+                    .late_offers()
+                    .expect_answer(dialog.id().call_id);
+            }
 
             match dialog.accept(Some(headers), Some(answer.as_bytes().to_vec())) {
                 Ok(_) => {
+                    let caller = dialog.initial_request().from_header().ok().cloned();
+                    self.topology.answered(CallLeg {
+                        id: dialog.id().call_id,
+                        track_id: self.session_id.clone(),
+                        party: caller
+                            .and_then(|from| from.uri().ok())
+                            .map(|uri| uri.to_string()),
+                    });
                     self.finish_caller_stack(&option, track).await?;
                 }
                 Err(e) => {
@@ -508,7 +1247,22 @@ impl ActiveCall {
                     ?code,
                     "rejecting call"
                 );
-                self.invitation.hangup(id, code, reason).await
+                let code = code.unwrap_or(rsip::StatusCode::Decline);
+                let cause = HangupCause::from_sip_status(code.code());
+                if let Ok(mut cs) = self.call_state.write() {
+                    cs.last_status_code = code.code();
+                    cs.hangup_cause = Some(cause);
+                }
+                // the free text reason goes into the Q.850 Reason header
+                let reason = match reason {
+                    Some(text) => format!(
+                        "Q.850;cause={};text=\"{}\"",
+                        cause.q850(),
+                        text.replace('"', "'")
+                    ),
+                    None => cause.reason(),
+                };
+                self.invitation.hangup(id, Some(code), Some(reason)).await
             }
             None => Ok(()),
         }
@@ -544,7 +1298,7 @@ impl ActiveCall {
                 ringtone, early_media, "playing ringtone"
             );
             if let Some(ringtone) = ringtone {
-                self.do_play(ringtone, None, None).await.ok();
+                self.do_play(ringtone, None, None, false).await.ok();
             } else {
                 info!(session_id = self.session_id, "no ringtone to play");
             }
@@ -552,57 +1306,325 @@ impl ActiveCall {
         Ok(())
     }
 
-    async fn do_tts(
+    async fn do_tts(
+        &self,
+        text: String,
+        speaker: Option<String>,
+        play_id: Option<String>,
+        auto_hangup: Option<bool>,
+        streaming: Option<bool>,
+        end_of_stream: Option<bool>,
+        option: Option<SynthesisOption>,
+        wait_input_timeout: Option<u32>,
+    ) -> Result<()> {
+        let tts_option = self.tts_option(option)?;
+        let speaker = match speaker {
+            Some(s) => Some(s),
+            None => tts_option.speaker.clone(),
+        };
+
+        let mut play_command = SynthesisCommand {
+            text,
+            speaker,
+            play_id: play_id.clone(),
+            streaming,
+            end_of_stream,
+            option: tts_option,
+        };
+        info!(
+            session_id = self.session_id,
+            provider = ?play_command.option.provider,
+            text = %play_command.text,
+            speaker = play_command.speaker.as_deref(),
+            auto_hangup = auto_hangup.unwrap_or_default(),
+            play_id = play_command.play_id.as_deref(),
+            streaming = play_command.streaming,
+            eos = play_command.end_of_stream.unwrap_or_default(),
+            wit = wait_input_timeout.unwrap_or_default(),
+            "new synthesis"
+        );
+
+        let ssrc = rand::random::<u32>();
+        self.pending_echo.lock().await.take();
+        self.pending_prompt.lock().await.take();
+        self.pending_gather.lock().await.take();
+        match auto_hangup {
+            Some(true) => {
+                *self.auto_hangup.lock().await = Some((ssrc, CallRecordHangupReason::BySystem))
+            }
+            _ => *self.auto_hangup.lock().await = None,
+        }
+        *self.wait_input_timeout.lock().await = wait_input_timeout;
+
+        if let Some(tts_handle) = self.tts_handle.lock().await.as_ref() {
+            match tts_handle.try_send(play_command) {
+                Ok(_) => return Ok(()),
+                Err(e) => {
+                    play_command = e.0;
+                }
+            }
+        }
+
+        let (new_handle, tts_track) = StreamEngine::create_tts_track(
+            self.app_state.stream_engine.clone(),
+            self.cancel_token.child_token(),
+            self.session_id.clone(),
+            self.server_side_track_id.clone(),
+            ssrc,
+            play_id.clone(),
+            &play_command.option,
+        )
+        .await?;
+
+        new_handle.try_send(play_command)?;
+        *self.tts_handle.lock().await = Some(new_handle);
+        self.media_stream.update_track(tts_track, play_id).await;
+        Ok(())
+    }
+
+    /// The call's tts option, overridden by the command's
+    fn tts_option(&self, option: Option<SynthesisOption>) -> Result<SynthesisOption> {
+        match self.call_state.read() {
+            Ok(ref call_state) => match call_state.option.clone().unwrap_or_default().tts {
+                Some(opt) => Ok(opt.merge_with(option)),
+                None => option.ok_or_else(|| anyhow::anyhow!("no tts option available")),
+            },
+            Err(_) => Err(anyhow::anyhow!("failed to read call state")),
+        }
+    }
+
+    /// The command's locale, else the call's, else the prompt pack's default
+    fn prompt_locale(&self, locale: Option<String>) -> String {
+        let pack = self.app_state.config.prompts.as_ref();
+        locale
+            .or_else(|| self.locale())
+            .unwrap_or_else(|| pack.map_or("en", |pack| pack.default_locale()).to_string())
+    }
+
+    /// Renders prompt segments with the call's variables
+    fn prompt_parts(
+        &self,
+        segments: &[PromptSegment],
+        locale: &str,
+        variables: Option<HashMap<String, String>>,
+        option: &Option<SynthesisOption>,
+    ) -> Result<VecDeque<PromptPart>> {
+        let takes_ssml = self
+            .tts_option(option.clone())
+            .ok()
+            .and_then(|option| option.provider)
+            .is_some_and(|provider| provider.supports_ssml());
+        let mut values = self.variables().snapshot();
+        values.extend(variables.unwrap_or_default());
+        let pack = self.app_state.config.prompts.as_ref();
+        let parts = prompt::render(segments, &values, locale, takes_ssml, pack)?;
+        info!(
+            session_id = self.session_id,
+            parts = parts.len(),
+            locale,
+            takes_ssml,
+            "render prompt"
+        );
+        Ok(parts.into())
+    }
+
+    async fn do_prompt(
+        &self,
+        segments: Vec<PromptSegment>,
+        locale: Option<String>,
+        variables: Option<HashMap<String, String>>,
+        play_id: Option<String>,
+        auto_hangup: Option<bool>,
+        option: Option<SynthesisOption>,
+        wait_input_timeout: Option<u32>,
+    ) -> Result<()> {
+        let locale = self.prompt_locale(locale);
+        let parts = self.prompt_parts(&segments, &locale, variables, &option)?;
+        info!(session_id = self.session_id, play_id, "play prompt");
+        self.pending_echo.lock().await.take();
+        self.pending_prompt.lock().await.take();
+        self.pending_gather.lock().await.take();
+        *self.auto_hangup.lock().await = None;
+        *self.wait_input_timeout.lock().await = None;
+        self.play_prompt(PendingPrompt {
+            ssrc: 0,
+            parts,
+            option,
+            play_id,
+            auto_hangup,
+            wait_input_timeout,
+        })
+        .await
+    }
+
+    async fn do_gather(
+        &self,
+        segments: Vec<PromptSegment>,
+        locale: Option<String>,
+        variables: Option<HashMap<String, String>>,
+        play_id: Option<String>,
+        option: Option<SynthesisOption>,
+        input: GatherOption,
+    ) -> Result<()> {
+        let locale = self.prompt_locale(locale);
+        let parts = self.prompt_parts(&segments, &locale, variables, &option)?;
+        info!(
+            session_id = self.session_id,
+            play_id,
+            locale,
+            dtmf = input.dtmf,
+            speech = input.speech,
+            barge_in = input.barge_in,
+            "gather input"
+        );
+        self.tts_handle.lock().await.take();
+        self.pending_echo.lock().await.take();
+        self.pending_prompt.lock().await.take();
+        *self.auto_hangup.lock().await = None;
+        *self.wait_input_timeout.lock().await = None;
+        let language = Language::from_locale(&locale).unwrap_or(Language::English);
+        let mut gather = Gather::new(input, play_id).with_language(language);
+        if parts.is_empty() {
+            gather.prompt_done(crate::get_timestamp());
+        }
+        *self.pending_gather.lock().await = Some(gather);
+        self.play_prompt(PendingPrompt {
+            ssrc: 0,
+            parts,
+            option,
+            play_id: None,
+            auto_hangup: None,
+            wait_input_timeout: None,
+        })
+        .await
+    }
+
+    /// Applies caller input or a tick to the pending gather, stopping its
+    /// prompt on barge-in and reporting the result once it completes
+    async fn update_gather(&self, update: impl FnOnce(&mut Gather, u64) -> GatherStep) {
+        let (step, prompting, play_id) = {
+            let mut pending_gather = self.pending_gather.lock().await;
+            let Some(gather) = pending_gather.as_mut() else {
+                return;
+            };
+            let prompting = gather.prompting();
+            let step = update(gather, crate::get_timestamp());
+            let play_id = match step {
+                GatherStep::Done(_) => pending_gather.take().and_then(|gather| gather.play_id),
+                _ => None,
+            };
+            (step, prompting, play_id)
+        };
+        match step {
+            GatherStep::Wait => {}
+            GatherStep::BargeIn => {
+                info!(session_id = self.session_id, "gather barge-in, stop prompt");
+                self.do_interrupt().await.ok();
+            }
+            GatherStep::Done(gathered) => {
+                if prompting {
+                    self.do_interrupt().await.ok();
+                }
+                info!(session_id = self.session_id, ?gathered, "gather done");
+                self.event_sender
+                    .send(gathered.into_event(self.server_side_track_id.clone(), play_id))
+                    .ok();
+            }
+        }
+    }
+
+    /// Plays the next part of the prompt, the call's auto hangup and input
+    /// timeout wait for the last one
+    async fn play_prompt(&self, mut prompt: PendingPrompt) -> Result<()> {
+        let Some(part) = prompt.parts.pop_front() else {
+            return Ok(());
+        };
+        let last = prompt.parts.is_empty();
+        let play_id = if last { prompt.play_id.clone() } else { None };
+        let ssrc = rand::random::<u32>();
+        self.tts_handle.lock().await.take();
+        if let Some(gather) = self.pending_gather.lock().await.as_mut() {
+            gather.prompt_ssrc = Some(ssrc);
+        }
+        match part {
+            PromptPart::File(url) => {
+                let file_track = FileTrack::new(self.server_side_track_id.clone())
+                    .with_ssrc(ssrc)
+                    .with_path(url.clone())
+                    .with_play_id(play_id)
+                    .with_cancel_token(self.cancel_token.child_token());
+                self.media_stream
+                    .update_track(Box::new(file_track), Some(url))
+                    .await;
+            }
+            PromptPart::Speak(text) | PromptPart::Ssml(text) => {
+                let option = self.tts_option(prompt.option.clone())?;
+                let (handle, tts_track) = StreamEngine::create_tts_track(
+                    self.app_state.stream_engine.clone(),
+                    self.cancel_token.child_token(),
+                    self.session_id.clone(),
+                    self.server_side_track_id.clone(),
+                    ssrc,
+                    play_id.clone(),
+                    &option,
+                )
+                .await?;
+                handle.try_send(SynthesisCommand {
+                    text,
+                    speaker: option.speaker.clone(),
+                    play_id: play_id.clone(),
+                    streaming: Some(false),
+                    end_of_stream: Some(true),
+                    option,
+                })?;
+                *self.tts_handle.lock().await = Some(handle);
+                self.media_stream.update_track(tts_track, play_id).await;
+            }
+        }
+        if last {
+            if prompt.auto_hangup == Some(true) {
+                *self.auto_hangup.lock().await = Some((ssrc, CallRecordHangupReason::BySystem));
+            }
+            *self.wait_input_timeout.lock().await = prompt.wait_input_timeout;
+        } else {
+            prompt.ssrc = ssrc;
+            *self.pending_prompt.lock().await = Some(prompt);
+        }
+        Ok(())
+    }
+
+    async fn do_play(
         &self,
-        text: String,
-        speaker: Option<String>,
-        play_id: Option<String>,
+        url: String,
         auto_hangup: Option<bool>,
-        streaming: Option<bool>,
-        end_of_stream: Option<bool>,
-        option: Option<SynthesisOption>,
         wait_input_timeout: Option<u32>,
+        stream: bool,
     ) -> Result<()> {
-        let tts_option = match self.call_state.read() {
-            Ok(ref call_state) => match call_state.option.clone().unwrap_or_default().tts {
-                Some(opt) => opt.merge_with(option),
-                None => {
-                    if let Some(opt) = option {
-                        opt
-                    } else {
-                        return Err(anyhow::anyhow!("no tts option available"));
-                    }
-                }
-            },
-            Err(_) => return Err(anyhow::anyhow!("failed to read call state")),
-        };
-        let speaker = match speaker {
-            Some(s) => Some(s),
-            None => tts_option.speaker.clone(),
-        };
-
-        let mut play_command = SynthesisCommand {
-            text,
-            speaker,
-            play_id: play_id.clone(),
-            streaming,
-            end_of_stream,
-            option: tts_option,
-        };
+        self.tts_handle.lock().await.take();
+        self.pending_echo.lock().await.take();
+        self.pending_prompt.lock().await.take();
+        self.pending_gather.lock().await.take();
+        let ssrc = rand::random::<u32>();
         info!(
             session_id = self.session_id,
-            provider = ?play_command.option.provider,
-            text = %play_command.text,
-            speaker = play_command.speaker.as_deref(),
-            auto_hangup = auto_hangup.unwrap_or_default(),
-            play_id = play_command.play_id.as_deref(),
-            streaming = play_command.streaming,
-            eos = play_command.end_of_stream.unwrap_or_default(),
-            wit = wait_input_timeout.unwrap_or_default(),
-            "new synthesis"
+            ssrc, url, auto_hangup, stream, "play file track"
         );
 
-        let ssrc = rand::random::<u32>();
+        let track: Box<dyn Track> = if stream {
+            Box::new(
+                HttpStreamTrack::new(self.server_side_track_id.clone())
+                    .with_ssrc(ssrc)
+                    .with_url(url.clone())
+                    .with_cancel_token(self.cancel_token.child_token()),
+            )
+        } else {
+            Box::new(
+                FileTrack::new(self.server_side_track_id.clone())
+                    .with_ssrc(ssrc)
+                    .with_path(url.clone())
+                    .with_cancel_token(self.cancel_token.child_token()),
+            )
+        };
         match auto_hangup {
             Some(true) => {
                 *self.auto_hangup.lock().await = Some((ssrc, CallRecordHangupReason::BySystem))
@@ -610,59 +1632,74 @@ impl ActiveCall {
             _ => *self.auto_hangup.lock().await = None,
         }
         *self.wait_input_timeout.lock().await = wait_input_timeout;
+        self.media_stream.update_track(track, Some(url)).await;
+        Ok(())
+    }
 
-        if let Some(tts_handle) = self.tts_handle.lock().await.as_ref() {
-            match tts_handle.try_send(play_command) {
-                Ok(_) => return Ok(()),
-                Err(e) => {
-                    play_command = e.0;
-                }
+    async fn do_echo(&self, delay: Option<u32>, prompt: Option<String>) -> Result<()> {
+        self.tts_handle.lock().await.take();
+        *self.auto_hangup.lock().await = None;
+        let delay = Duration::from_millis(delay.unwrap_or_default() as u64);
+        match prompt {
+            Some(url) => {
+                let ssrc = rand::random::<u32>();
+                info!(
+                    session_id = self.session_id,
+                    ssrc, url, "echo test, play prompt"
+                );
+                *self.pending_echo.lock().await = Some((ssrc, delay));
+                let file_track = FileTrack::new(self.server_side_track_id.clone())
+                    .with_ssrc(ssrc)
+                    .with_path(url.clone())
+                    .with_cancel_token(self.cancel_token.child_token());
+                self.media_stream
+                    .update_track(Box::new(file_track), Some(url))
+                    .await;
             }
+            None => self.start_echo(delay).await,
         }
-
-        let (new_handle, tts_track) = StreamEngine::create_tts_track(
-            self.app_state.stream_engine.clone(),
-            self.cancel_token.child_token(),
-            self.session_id.clone(),
-            self.server_side_track_id.clone(),
-            ssrc,
-            play_id.clone(),
-            &play_command.option,
-        )
-        .await?;
-
-        new_handle.try_send(play_command)?;
-        *self.tts_handle.lock().await = Some(new_handle);
-        self.media_stream.update_track(tts_track, play_id).await;
         Ok(())
     }
 
-    async fn do_play(
-        &self,
-        url: String,
-        auto_hangup: Option<bool>,
-        wait_input_timeout: Option<u32>,
-    ) -> Result<()> {
-        self.tts_handle.lock().await.take();
-        let ssrc = rand::random::<u32>();
+    async fn start_echo(&self, delay: Duration) {
         info!(
             session_id = self.session_id,
-            ssrc, url, auto_hangup, "play file track"
+            delay = delay.as_millis(),
+            "echo test, loop back caller audio"
         );
+        let echo_track = EchoTrack::new(self.server_side_track_id.clone())
+            .with_ssrc(rand::random::<u32>())
+            .with_config(self.track_config.clone())
+            .with_delay(delay)
+            .with_cancel_token(self.cancel_token.child_token());
+        self.media_stream
+            .update_track(Box::new(echo_track), None)
+            .await;
+    }
 
-        let file_track = FileTrack::new(self.server_side_track_id.clone())
-            .with_ssrc(ssrc)
-            .with_path(url.clone())
+    async fn do_milliwatt(
+        &self,
+        frequency: Option<f32>,
+        level: Option<f32>,
+        duration: Option<u32>,
+    ) -> Result<()> {
+        self.tts_handle.lock().await.take();
+        self.pending_echo.lock().await.take();
+        self.pending_prompt.lock().await.take();
+        self.pending_gather.lock().await.take();
+        let mut tone_track = ToneTrack::new(self.server_side_track_id.clone())
+            .with_ssrc(rand::random::<u32>())
+            .with_config(self.track_config.clone())
+            .with_duration(duration.map(|d| Duration::from_millis(d as u64)))
             .with_cancel_token(self.cancel_token.child_token());
-        match auto_hangup {
-            Some(true) => {
-                *self.auto_hangup.lock().await = Some((ssrc, CallRecordHangupReason::BySystem))
-            }
-            _ => *self.auto_hangup.lock().await = None,
+        if let Some(frequency) = frequency {
+            tone_track = tone_track.with_frequency(frequency);
+        }
+        if let Some(level) = level {
+            tone_track = tone_track.with_level(level);
         }
-        *self.wait_input_timeout.lock().await = wait_input_timeout;
         self.media_stream
-            .update_track(Box::new(file_track), Some(url))
+            .update_track(Box::new(tone_track), None)
             .await;
         Ok(())
     }
@@ -679,8 +1716,14 @@ impl ActiveCall {
             .map_err(Into::into)
     }
 
+    /// Stops playback, a gather stops prompting and starts listening
     async fn do_interrupt(&self) -> Result<()> {
         self.tts_handle.lock().await.take();
+        self.pending_echo.lock().await.take();
+        self.pending_prompt.lock().await.take();
+        if let Some(gather) = self.pending_gather.lock().await.as_mut() {
+            gather.prompt_done(crate::get_timestamp());
+        }
         self.media_stream
             .remove_track(&self.server_side_track_id)
             .await;
@@ -692,6 +1735,45 @@ impl ActiveCall {
     async fn do_resume(&self) -> Result<()> {
         Ok(())
     }
+    /// Hang up when the track ends on its own, e.g. an AudioSocket server
+    /// hanging up or disconnecting
+    fn hangup_on_track_end(&self, track_id: TrackId, ssrc: u32) {
+        let mut event_receiver = self.event_sender.subscribe();
+        let cmd_sender = self.cmd_sender.clone();
+        let cancel_token = self.cancel_token.clone();
+        tokio::spawn(async move {
+            let track_end = async {
+                while let Ok(event) = event_receiver.recv().await {
+                    if let SessionEvent::TrackEnd {
+                        track_id: id,
+                        ssrc: end_ssrc,
+                        ..
+                    } = event
+                        && id == track_id
+                        && end_ssrc == ssrc
+                    {
+                        return true;
+                    }
+                }
+                false
+            };
+            select! {
+                _ = cancel_token.cancelled() => {}
+                ended = track_end => {
+                    if ended && !cancel_token.is_cancelled() {
+                        info!(track_id, "track ended, hangup");
+                        cmd_sender
+                            .send(Command::Hangup {
+                                reason: None,
+                                initiator: Some("system".to_string()),
+                            })
+                            .ok();
+                    }
+                }
+            }
+        });
+    }
+
     async fn do_hangup(
         &self,
         reason: Option<CallRecordHangupReason>,
@@ -735,8 +1817,21 @@ impl ActiveCall {
         callee: String,
         refer_option: Option<ReferOption>,
     ) -> Result<()> {
+        if let Err(e) = self.anchor_media().await {
+            warn!(
+                session_id = self.session_id,
+                "failed to anchor media: {}", e
+            );
+            // hung up on the failure
+            if self.cancel_token.is_cancelled() {
+                return Err(e);
+            }
+        }
+        self.topology.transferred(&callee);
         if let Some(moh) = refer_option.as_ref().and_then(|o| o.moh.clone()) {
-            self.do_play(moh, None, None).await?;
+            let stream = refer_option.as_ref().and_then(|o| o.moh_stream);
+            self.do_play(moh, None, None, stream.unwrap_or_default())
+                .await?;
         }
         self.tts_handle.lock().await.take();
         let token = self.cancel_token.child_token();
@@ -771,6 +1866,7 @@ impl ActiveCall {
             start_time: Utc::now(),
             ssrc,
             option: Some(call_option),
+            variables: self.variables(),
             ..Default::default()
         }));
 
@@ -816,12 +1912,17 @@ impl ActiveCall {
                 );
                 match &e {
                     rsipstack::Error::DialogError(reason, _, code) => {
+                        let cause = refer_call_state
+                            .write()
+                            .ok()
+                            .map(|mut cs| cs.on_rejected(code, reason));
                         self.event_sender
                             .send(SessionEvent::Reject {
                                 track_id,
                                 timestamp: crate::get_timestamp(),
                                 reason: reason.clone(),
                                 code: Some(code.code() as u32),
+                                cause,
                             })
                             .ok();
                     }
@@ -833,19 +1934,225 @@ impl ActiveCall {
         Ok(())
     }
 
-    async fn do_mute(&self, track_id: Option<String>) -> Result<()> {
-        self.media_stream.mute_track(track_id).await;
+    async fn do_mute(
+        &self,
+        track_id: Option<String>,
+        direction: Option<TrackDirection>,
+    ) -> Result<()> {
+        self.media_stream
+            .mute_track(track_id, direction.unwrap_or_default())
+            .await;
+        Ok(())
+    }
+
+    async fn do_unmute(
+        &self,
+        track_id: Option<String>,
+        direction: Option<TrackDirection>,
+    ) -> Result<()> {
+        self.media_stream
+            .unmute_track(track_id, direction.unwrap_or_default())
+            .await;
+        Ok(())
+    }
+
+    async fn do_gain(
+        &self,
+        track_id: Option<String>,
+        gain: f32,
+        direction: Option<TrackDirection>,
+    ) -> Result<()> {
+        self.media_stream
+            .set_track_gain(track_id, gain, direction.unwrap_or_default())
+            .await;
+        Ok(())
+    }
+
+    async fn do_probe_latency(&self, track_id: String, timeout: Option<u32>) -> Result<()> {
+        let timeout = Duration::from_millis(timeout.unwrap_or(5000) as u64);
+        self.media_stream.probe_latency(&track_id, timeout).await;
         Ok(())
     }
 
-    async fn do_unmute(&self, track_id: Option<String>) -> Result<()> {
-        self.media_stream.unmute_track(track_id).await;
+    async fn do_supervise(
+        &self,
+        track_id: String,
+        target: String,
+        mode: SuperviseMode,
+    ) -> Result<()> {
+        let ducking = match mode {
+            SuperviseMode::Listen => None,
+            _ => self
+                .call_state
+                .read()
+                .ok()
+                .and_then(|cs| cs.option.as_ref().and_then(|o| o.ducking.clone())),
+        };
+        self.media_stream.set_ducking(&track_id, ducking).await;
+        self.media_stream.supervise(&track_id, &target, mode).await;
         Ok(())
     }
 
+    async fn do_attach_supervisor(
+        &self,
+        track_id: String,
+        target: String,
+        mode: SuperviseMode,
+        callee: Option<String>,
+        offer: Option<String>,
+    ) -> Result<()> {
+        match (callee, offer) {
+            (Some(callee), None) => self.dial_supervisor(track_id, target, mode, callee).await,
+            (None, Some(offer)) => {
+                let option = self
+                    .call_state
+                    .read()
+                    .ok()
+                    .and_then(|cs| cs.option.clone())
+                    .unwrap_or_default();
+                let offer = match option.enable_ipv6 {
+                    Some(false) | None => strip_ipv6_candidates(&offer),
+                    _ => offer,
+                };
+                let timeout = option
+                    .handshake_timeout
+                    .as_ref()
+                    .and_then(|d| d.parse::<u64>().ok())
+                    .map(Duration::from_secs);
+                let mut track = WebrtcTrack::new(
+                    self.cancel_token.child_token(),
+                    track_id.clone(),
+                    self.track_config.clone(),
+                    self.app_state.config.ice_servers.clone(),
+                )
+                .with_ssrc(rand::random::<u32>());
+                let answer = track.handshake(offer, timeout).await?;
+                let answer = match option.enable_ipv6 {
+                    Some(false) | None => strip_ipv6_candidates(&answer),
+                    _ => answer,
+                };
+                self.attach_supervisor(Box::new(track), target, mode)
+                    .await?;
+                self.event_sender
+                    .send(SessionEvent::Answer {
+                        track_id,
+                        timestamp: crate::get_timestamp(),
+                        sdp: answer,
+                    })
+                    .ok();
+                Ok(())
+            }
+            _ => Err(anyhow::anyhow!(
+                "attachSupervisor requires either callee or offer"
+            )),
+        }
+    }
+
+    /// Adds the supervisor's `track` to the call, mixed as `mode` from the start
+    pub async fn attach_supervisor(
+        &self,
+        track: Box<dyn Track>,
+        target: String,
+        mode: SuperviseMode,
+    ) -> Result<()> {
+        let track_id = track.id().clone();
+        info!(
+            session_id = self.session_id,
+            track_id,
+            target,
+            ?mode,
+            "attach supervisor"
+        );
+        self.media_stream.update_track(track, None).await;
+        self.do_supervise(track_id, target, mode).await
+    }
+
+    /// Dials the supervisor at `callee`, the leg ends with the call
+    async fn dial_supervisor(
+        &self,
+        track_id: String,
+        target: String,
+        mode: SuperviseMode,
+        callee: String,
+    ) -> Result<()> {
+        let call_option = CallOption {
+            caller: self
+                .call_state
+                .read()
+                .ok()
+                .and_then(|cs| cs.option.as_ref().and_then(|o| o.caller.clone())),
+            callee: Some(callee.clone()),
+            ..Default::default()
+        };
+        let invite_option = call_option.build_invite_option()?;
+        let supervisor_state = Arc::new(RwLock::new(ActiveCallState {
+            start_time: Utc::now(),
+            ssrc: rand::random::<u32>(),
+            option: Some(call_option),
+            variables: self.variables(),
+            ..Default::default()
+        }));
+        self.call_state
+            .write()
+            .as_mut()
+            .map(|cs| {
+                cs.supervisors
+                    .insert(track_id.clone(), supervisor_state.clone())
+            })
+            .ok();
+
+        info!(
+            session_id = self.session_id,
+            track_id,
+            callee,
+            ?mode,
+            "dial supervisor"
+        );
+        // the track joins the mix before the invite is sent, keep the
+        // supervisor out of the call while it rings
+        let mut events = self.event_sender.subscribe();
+        let invite = self.create_outgoing_sip_track(
+            self.cancel_token.child_token(),
+            supervisor_state,
+            &track_id,
+            invite_option,
+        );
+        tokio::pin!(invite);
+        let result = loop {
+            select! {
+                result = &mut invite => break result,
+                Ok(event) = events.recv() => {
+                    if let SessionEvent::TrackStart { track_id: id, .. } = event
+                        && id == track_id
+                    {
+                        self.do_supervise(track_id.clone(), target.clone(), mode)
+                            .await?;
+                    }
+                }
+            }
+        };
+        if let Err(e) = result {
+            warn!(
+                session_id = self.session_id,
+                track_id, "failed to dial supervisor: {}", e
+            );
+            self.media_stream.remove_track(&track_id).await;
+            self.call_state
+                .write()
+                .as_mut()
+                .map(|cs| cs.supervisors.remove(&track_id))
+                .ok();
+            return Err(e.into());
+        }
+        self.do_supervise(track_id, target, mode).await
+    }
+
     pub async fn cleanup(&self) -> Result<()> {
         self.call_state.write().as_mut().ok().map(|cs| {
             cs.dialog.take();
+            for supervisor in cs.supervisors.values() {
+                supervisor.write().as_mut().ok().map(|s| s.dialog.take());
+            }
             cs.refer_callstate
                 .as_mut()
                 .map(|rcs| rcs.write().as_mut().ok().map(|rcs| rcs.dialog.take()))
@@ -957,17 +2264,70 @@ impl ActiveCall {
 }
 
 impl ActiveCall {
+    /// Copies the call's commands and events to the journal
+    async fn journal_loop(&self, receivers: Option<(CommandReceiver, EventReceiver)>) {
+        let (Some(journal), Some((mut cmd_receiver, mut event_receiver))) =
+            (self.app_state.journal.as_ref(), receivers)
+        else {
+            return;
+        };
+        let session_id = Some(self.session_id.as_str());
+        loop {
+            select! {
+                _ = self.cancel_token.cancelled() => break,
+                Ok(cmd) = cmd_receiver.recv() => {
+                    journal.record(CallRecordEventType::Command, session_id, &cmd);
+                }
+                Ok(event) = event_receiver.recv() => {
+                    if !matches!(event, SessionEvent::Binary { .. }) {
+                        journal.record(CallRecordEventType::Event, session_id, &event);
+                    }
+                }
+            }
+        }
+        while let Ok(event) = event_receiver.try_recv() {
+            if !matches!(event, SessionEvent::Binary { .. }) {
+                journal.record(CallRecordEventType::Event, session_id, &event);
+            }
+        }
+    }
+
+    /// The call's track config, resampling as the option's `transrating` says
+    pub(crate) fn track_config_for(&self, option: Option<&CallOption>) -> TrackConfig {
+        match option.and_then(|o| o.transrating) {
+            Some(profile) => self.track_config.clone().with_resample_profile(profile),
+            None => self.track_config.clone(),
+        }
+    }
+
     pub async fn create_rtp_track(
         cancel_token: CancellationToken,
         app_state: AppState,
         track_id: TrackId,
         track_config: TrackConfig,
         ssrc: u32,
+        shaping: Option<ShapingOption>,
+        levels: TrackLevels,
     ) -> Result<RtpTrack> {
         let mut rtp_track = RtpTrackBuilder::new(track_id, track_config)
             .with_ssrc(ssrc)
             .with_cancel_token(cancel_token);
 
+        if let Some(shaping) = shaping.or_else(|| app_state.config.rtp_shaping.clone()) {
+            rtp_track = rtp_track.with_shaping(shaping);
+        }
+        if let Some(rewrite) = app_state.config.rtp_rewrite.clone() {
+            rtp_track = rtp_track.with_rewrite(rewrite);
+        }
+        if let Some(qos) = app_state.config.qos.as_ref() {
+            rtp_track = rtp_track.with_dscp(qos.media);
+        }
+        if app_state.config.rtp_audio_level.unwrap_or_default() {
+            rtp_track = rtp_track.with_audio_level(levels);
+        }
+        if let Some(nat) = app_state.media_nat.clone() {
+            return rtp_track.with_nat(nat).build().await;
+        }
         if let Some(rtp_start_port) = app_state.config.rtp_start_port {
             rtp_track = rtp_track.with_rtp_start_port(rtp_start_port);
         }
@@ -992,6 +2352,17 @@ impl ActiveCall {
             call_type = ?self.call_type,
             "setup caller track"
         );
+        if let Some(ducking) = option.ducking.clone() {
+            self.media_stream
+                .set_ducking(&self.server_side_track_id, Some(ducking))
+                .await;
+        }
+        if let Some(level_match) = option.level_match.clone() {
+            self.media_stream.set_level_match(Some(level_match));
+        }
+        if let Some(top_speakers) = option.top_speakers.clone() {
+            self.media_stream.set_top_speakers(Some(top_speakers));
+        }
 
         let track = match self.call_type {
             ActiveCallType::Webrtc => Some(self.create_webrtc_track().await?),
@@ -1049,12 +2420,18 @@ impl ActiveCall {
                         );
                         match &e {
                             rsipstack::Error::DialogError(reason, _, code) => {
+                                let cause = self
+                                    .call_state
+                                    .write()
+                                    .ok()
+                                    .map(|mut cs| cs.on_rejected(code, reason));
                                 self.event_sender
                                     .send(SessionEvent::Reject {
                                         track_id: self.session_id.clone(),
                                         timestamp: crate::get_timestamp(),
                                         reason: reason.clone(),
                                         code: Some(code.code() as u32),
+                                        cause,
                                     })
                                     .ok();
                             }
@@ -1277,23 +2654,32 @@ impl ActiveCall {
         Ok(Box::new(webrtc_track))
     }
 
-    async fn create_outgoing_sip_track(
+    pub(crate) async fn create_outgoing_sip_track(
         &self,
         cancel_token: CancellationToken,
         call_state_ref: ActiveCallStateRef,
         track_id: &String,
         mut invite_option: InviteOption,
     ) -> Result<String, rsipstack::Error> {
-        let ssrc = call_state_ref
+        let (ssrc, shaping, track_config) = call_state_ref
             .read()
-            .map_err(|e| rsipstack::Error::Error(e.to_string()))?
-            .ssrc;
+            .map(|cs| {
+                let option = cs.option.as_ref();
+                (
+                    cs.ssrc,
+                    option.and_then(|o| o.shaping.clone()),
+                    self.track_config_for(option),
+                )
+            })
+            .map_err(|e| rsipstack::Error::Error(e.to_string()))?;
         let rtp_track = Self::create_rtp_track(
             cancel_token.child_token(),
             self.app_state.clone(),
             track_id.clone(),
-            self.track_config.clone(),
+            track_config,
             ssrc,
+            shaping,
+            self.media_stream.levels(),
         )
         .await
         .map_err(|e| rsipstack::Error::Error(e.to_string()))?;
@@ -1314,6 +2700,13 @@ impl ActiveCall {
             .unwrap_or_default();
 
         invite_option.offer = offer.clone().map(|s| s.into());
+        if call_option.late_offer.unwrap_or_default()
+            && let Some(capabilities) = offer
+                .as_deref()
+                .and_then(|offer| SdpCapabilities::new(offer).ok())
+        {
+            self.invitation.late_offer(&mut invite_option, capabilities);
+        }
 
         Self::setup_track_with_stream(
             self.app_state.clone(),
@@ -1344,8 +2737,9 @@ impl ActiveCall {
         let call_state = call_state_ref.clone();
         let track_id_clone = track_id.clone();
         let dialog_layer = self.invitation.dialog_layer.clone();
+        let topology = self.topology.clone();
         tokio::spawn(async move {
-            client_dialog_event_loop(
+            if let Ok(dialog_id) = client_dialog_event_loop(
                 cancel_token,
                 session_id,
                 track_id_clone,
@@ -1356,13 +2750,21 @@ impl ActiveCall {
                 dialog_layer,
             )
             .await
-            .ok();
+            {
+                topology.ended(&dialog_id.call_id);
+            }
         });
 
+        let callee = invite_option.callee.to_string();
         let (dialog_id, answer) = self
             .invitation
             .invite(invite_option, dlg_state_sender)
             .await?;
+        self.topology.answered(CallLeg {
+            id: dialog_id.call_id.clone(),
+            track_id: track_id.clone(),
+            party: Some(callee),
+        });
 
         let answer = match answer {
             Some(answer) => String::from_utf8_lossy(&answer).to_string(),
@@ -1410,7 +2812,7 @@ impl ActiveCall {
             let webrtc_track = WebrtcTrack::new(
                 self.cancel_token.clone(),
                 self.session_id.clone(),
-                self.track_config.clone(),
+                self.track_config_for(Some(option)),
                 self.app_state.config.ice_servers.clone(),
             )
             .with_ssrc(ssrc);
@@ -1420,10 +2822,18 @@ impl ActiveCall {
                 self.cancel_token.clone(),
                 self.app_state.clone(),
                 self.session_id.clone(),
-                self.track_config.clone(),
+                self.track_config_for(Some(option)),
                 ssrc,
+                option.shaping.clone(),
+                self.media_stream.levels(),
             )
             .await?;
+            if offer.trim().is_empty() {
+                // a late offer (RFC 3261 13.3.1.1): ours goes in the 2xx,
+                // the answer comes in the ACK
+                let offer = rtp_track.local_description()?;
+                return Ok((offer, Box::new(rtp_track)));
+            }
             Box::new(rtp_track) as Box<dyn Track>
         };
         let answer = match media_track.handshake(offer.clone(), timeout).await {
@@ -1487,7 +2897,10 @@ impl ActiveCall {
                 return Err(anyhow::anyhow!("error creating track: {}", e));
             }
         }
+        let media_stream = self.media_stream.clone();
         let dialog_layer = self.invitation.dialog_layer.clone();
+        let retransmitter = self.invitation.retransmitter.clone();
+        let topology = self.topology.clone();
         tokio::spawn(async move {
             let forward_dlg_state_loop = async {
                 tokio::select! {
@@ -1507,7 +2920,7 @@ impl ActiveCall {
                 }
             };
 
-            tokio::join!(
+            let (_, result) = tokio::join!(
                 forward_dlg_state_loop,
                 server_dialog_event_loop(
                     cancel_token,
@@ -1516,15 +2929,28 @@ impl ActiveCall {
                     event_sender,
                     dlg_state_receiver,
                     call_state,
+                    media_stream,
                     dialog_layer,
+                    retransmitter,
                 )
-            )
+            );
+            if let Ok(dialog_id) = result {
+                topology.ended(&dialog_id.call_id);
+            }
         });
         Ok(())
     }
 }
 
 impl ActiveCallState {
+    /// Records the final response of a failed outgoing INVITE
+    pub fn on_rejected(&mut self, code: &rsip::StatusCode, reason: &str) -> HangupCause {
+        let cause = HangupCause::from_response(code.code(), Some(reason));
+        self.last_status_code = code.code();
+        self.hangup_cause = Some(cause);
+        cause
+    }
+
     pub fn build_hangup_event(
         &self,
         track_id: TrackId,
@@ -1538,6 +2964,8 @@ impl ActiveCallState {
             track_id,
             timestamp: crate::get_timestamp(),
             reason: Some(format!("{:?}", self.hangup_reason)),
+            cause: self.hangup_cause,
+            variables: self.variables.to_option(),
             initiator,
             start_time: self.start_time.to_rfc3339(),
             answer_time: self.answer_time.map(|t| t.to_rfc3339()),
@@ -1562,11 +2990,15 @@ impl ActiveCallState {
                 let file_size = std::fs::metadata(&recorder_file)
                     .map(|m| m.len())
                     .unwrap_or(0);
+                // with the consent of the caller, where it was asked for
+                let extra = self.variables.get(consent::CONSENT_VARIABLE).map(|status| {
+                    HashMap::from([("consent".to_string(), serde_json::Value::from(status))])
+                });
                 vec![crate::callrecord::CallRecordMedia {
                     track_id: session_id.clone(),
                     path: recorder_file,
                     size: file_size,
-                    extra: None,
+                    extra,
                 }]
             } else {
                 vec![]
@@ -1615,6 +3047,9 @@ impl ActiveCallState {
             caller,
             callee,
             hangup_reason: self.hangup_reason.clone(),
+            hangup_cause: self.hangup_cause,
+            variables: self.variables.to_option(),
+            flow: self.flow.clone(),
             status_code: self.last_status_code,
             answer: self.answer.clone(),
             offer,
@@ -2,19 +2,30 @@ use crate::{
     app::AppState,
     call::{
         ActiveCall, ActiveCallRef, ActiveCallState, ActiveCallType, CallOption, Command,
-        CommandSender, DialStrategy, Dialplan, Location, RouteInvite, TransactionCookie,
+        CommandSender, DialStrategy, Dialplan, HangupCause, Location, RouteInvite,
+        TransactionCookie,
+        bypass::{MediaLeg, is_trusted},
+        consent,
         sip::{Invitation, client_dialog_event_loop},
+        topology::CallLeg,
     },
-    config::RouteResult,
+    config::{ConsentJurisdiction, RouteResult},
     event::SessionEvent,
-    media::{recorder::RecorderOption, track::TrackConfig},
+    media::{
+        dtmf::DtmfMode, negotiate::SdpCapabilities, recorder::RecorderOption, track::TrackConfig,
+    },
     useragent::invitation::PendingDialog,
 };
 use anyhow::Result;
 use chrono::Utc;
 use rsip::prelude::HeadersExt;
-use rsipstack::{dialog::dialog::DialogState, transaction::transaction::Transaction};
-use std::sync::{Arc, RwLock};
+use rsipstack::{
+    dialog::dialog::DialogState, transaction::transaction::Transaction, transport::SipAddr,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
 use tokio::sync::{broadcast, mpsc};
 use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
@@ -26,6 +37,8 @@ pub struct B2bua {
     pub session_id: String,
     pub dump_events: bool,
     pub recorder: bool,
+    /// Recording waits for the consent of the caller in this jurisdiction
+    pub consent: Option<ConsentJurisdiction>,
     pub cmd_sender: CommandSender,
 }
 
@@ -37,6 +50,7 @@ pub struct B2buaBuilder {
     pub dump_events: bool,
     pub session_id: String,
     pub recorder: bool,
+    pub consent: Option<ConsentJurisdiction>,
 }
 
 impl B2buaBuilder {
@@ -49,6 +63,7 @@ impl B2buaBuilder {
             dump_events: true,
             session_id,
             recorder: true,
+            consent: None,
         }
     }
 
@@ -62,6 +77,11 @@ impl B2buaBuilder {
         self
     }
 
+    pub fn with_consent(mut self, consent: Option<ConsentJurisdiction>) -> Self {
+        self.consent = consent;
+        self
+    }
+
     pub fn with_media_capabilities(
         mut self,
         capabilities: Vec<crate::media::codecs::CodecType>,
@@ -79,6 +99,7 @@ impl B2buaBuilder {
             session_id: self.session_id,
             dump_events: self.dump_events,
             recorder: self.recorder,
+            consent: self.consent,
             cmd_sender: broadcast::Sender::<Command>::new(32),
         };
         Ok(b2bua)
@@ -94,12 +115,50 @@ impl B2bua {
         invitation: Invitation,
         dialplan: Dialplan,
     ) -> Result<()> {
+        let original = tx.original.clone();
+        let contact = caller_contact.uri.clone();
+        let extras = dialplan.extras.clone();
+        self.serve_with(tx, contact, app_state, invitation, extras, |active_call| {
+            self.dial(active_call, caller_contact, dialplan, &original)
+        })
+        .await
+    }
+
+    /// Calls the targets of the dialplan for the caller, the `connect` of
+    /// [`Self::serve`]
+    pub async fn dial(
+        &self,
+        active_call: ActiveCallRef,
+        caller_contact: rsip::typed::Contact,
+        dialplan: Dialplan,
+        original: &rsip::Request,
+    ) -> Result<()> {
+        active_call.variables().extend(dialplan.variables.clone());
+        self.process_callee_loop(active_call, caller_contact, dialplan, original)
+            .await
+    }
+
+    /// Serves the caller's dialog while `connect` sets the call up, the
+    /// caller is rejected when it fails
+    pub async fn serve_with<F, Fut>(
+        &self,
+        tx: &mut Transaction,
+        caller_contact: rsip::Uri,
+        app_state: AppState,
+        invitation: Invitation,
+        extras: Option<HashMap<String, serde_json::Value>>,
+        connect: F,
+    ) -> Result<()>
+    where
+        F: FnOnce(ActiveCallRef) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
         let (state_sender, state_receiver) = mpsc::unbounded_channel();
         let mut dialog = match invitation.dialog_layer.get_or_create_server_invite(
             &tx,
             state_sender,
             None,
-            Some(caller_contact.uri.clone()),
+            Some(caller_contact),
         ) {
             Ok(d) => d,
             Err(e) => {
@@ -138,7 +197,7 @@ impl B2bua {
             None,
             self.dump_events,
             None,
-            dialplan.extras.clone(),
+            extras,
         ));
 
         let active_calls = {
@@ -147,18 +206,24 @@ impl B2bua {
             calls.len()
         };
         info!(session_id = self.session_id, active_calls, "b2bua started");
-        let original = tx.original.clone();
         let dialog_ref = dialog.clone();
         let (_, _, _) = tokio::join!(
             dialog.handle(tx),
             async {
-                match self
-                    .process_callee_loop(active_call.clone(), caller_contact, dialplan, &original)
-                    .await
-                {
+                match connect(active_call.clone()).await {
                     Ok(_) => {}
                     Err(_) => {
-                        dialog_ref.reject(None, None).ok();
+                        let cause = active_call
+                            .call_state
+                            .read()
+                            .ok()
+                            .and_then(|cs| cs.hangup_cause);
+                        match cause {
+                            Some(cause) => dialog_ref
+                                .reject(Some(cause.sip_status()), Some(cause.reason()))
+                                .ok(),
+                            None => dialog_ref.reject(None, None).ok(),
+                        };
                     }
                 }
             },
@@ -218,8 +283,8 @@ impl B2bua {
                             )
                             .await
                         {
-                            Ok(_) => {
-                                return Ok(());
+                            Ok(callee) => {
+                                return Ok(callee);
                             }
                             Err(e) => {
                                 return Err(e);
@@ -234,16 +299,41 @@ impl B2bua {
             }
         };
         match invite_callee_loop.await {
-            Ok(_) => {
+            // the route answered the caller itself
+            Ok(None) => Ok(()),
+            Ok(Some(callee)) => {
                 info!(session_id = self.session_id, "Callee loop completed");
+                let caller_sdp = String::from_utf8_lossy(&original.body).to_string();
+                let direct_media = active_call
+                    .app_state
+                    .config
+                    .proxy
+                    .as_ref()
+                    .and_then(|proxy| proxy.direct_media.as_ref())
+                    .map(|config| config.trusted_networks())
+                    .is_some_and(|trusted| {
+                        is_trusted(&caller_sdp, &trusted) && is_trusted(&callee.sdp, &trusted)
+                    });
+                // the caller is transrated as the callee's route says
+                let transrating = active_call
+                    .call_state
+                    .read()
+                    .ok()
+                    .and_then(|cs| cs.refer_callstate.clone())
+                    .and_then(|callee| callee.read().ok()?.option.as_ref()?.transrating);
+                // the recording would stop when the media bypasses rustpbx
+                let recorder = (self.recorder && !direct_media).then(|| {
+                    let recorder_file = active_call.app_state.get_recorder_file(&self.session_id);
+                    RecorderOption::new(recorder_file)
+                });
+                // the caller is asked first where the law wants consent
+                let (recorder, consent) = match (recorder, self.consent.clone()) {
+                    (Some(recorder), Some(consent)) => (None, Some((recorder, consent))),
+                    (recorder, _) => (recorder, None),
+                };
                 let option = CallOption {
-                    recorder: if self.recorder {
-                        let recorder_file =
-                            active_call.app_state.get_recorder_file(&self.session_id);
-                        Some(RecorderOption::new(recorder_file))
-                    } else {
-                        None
-                    },
+                    transrating,
+                    recorder,
                     ..CallOption::default()
                 };
                 let answer_command = Command::Accept { option };
@@ -253,6 +343,41 @@ impl B2bua {
                         "Failed to enqueue answer command: {}", e
                     );
                 }
+                if let Some((recorder, consent)) = consent {
+                    let active_call = active_call.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = consent::obtain(&active_call, &consent, recorder).await {
+                            warn!(
+                                session_id = active_call.session_id,
+                                "failed to obtain recording consent: {}", e
+                            );
+                        }
+                    });
+                }
+                let dtmf = active_call
+                    .app_state
+                    .config
+                    .proxy
+                    .as_ref()
+                    .and_then(|proxy| proxy.dtmf.as_ref());
+                if let Some(dtmf) = dtmf
+                    && !direct_media
+                {
+                    active_call
+                        .set_dtmf_interworking(
+                            DtmfMode::negotiate(dtmf.caller, &caller_sdp),
+                            DtmfMode::negotiate(dtmf.callee, &callee.sdp),
+                        )
+                        .await;
+                }
+                if direct_media {
+                    let session_id = self.session_id.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = active_call.bypass_media(caller_sdp, callee).await {
+                            warn!(session_id, "media stays anchored: {}", e);
+                        }
+                    });
+                }
                 return Ok(());
             }
             Err(e) => {
@@ -270,27 +395,91 @@ impl B2bua {
         target: Location,
         original: &rsip::Request,
         route_invite: &Option<Box<dyn RouteInvite>>,
-    ) -> Result<()> {
+    ) -> Result<Option<MediaLeg>> {
+        let mut call_option = CallOption::default();
+        call_option.caller = caller.map(|u| u.to_string());
+        call_option.callee = Some(target.aor.to_string());
+
+        let mut invite_option = call_option.build_invite_option()?;
+        invite_option.destination = Some(target.destination.clone());
+        if let Some(first_hop) = target.route_set.first() {
+            // loose routing, the Request-URI stays the target
+            invite_option.destination = Some(SipAddr::try_from(first_hop)?);
+            invite_option.headers = target.route_header().map(|route| vec![route]);
+        }
+        invite_option.contact = caller_contact.uri.clone();
+
+        let mut invite_option = if let Some(route_invite) = &route_invite {
+            let route_result = route_invite.route_invite(invite_option, original).await?;
+            match route_result {
+                RouteResult::Forward(option) => option,
+                RouteResult::Abort(code, reason) => {
+                    warn!(session_id = self.session_id, code, reason, "route abort");
+                    if let Ok(mut cs) = active_call.call_state.write() {
+                        cs.last_status_code = code;
+                        cs.hangup_cause = Some(HangupCause::from_sip_status(code));
+                    }
+                    return Err(anyhow::anyhow!("Route abort: {} {}", code, reason));
+                }
+                RouteResult::Announce(announcement) => {
+                    info!(
+                        session_id = self.session_id,
+                        announcement, "route announcement"
+                    );
+                    active_call
+                        .enqueue_command(Command::Accept {
+                            option: CallOption::default(),
+                        })
+                        .await?;
+                    active_call
+                        .enqueue_command(Command::Play {
+                            url: announcement,
+                            auto_hangup: Some(true),
+                            wait_input_timeout: None,
+                            stream: None,
+                        })
+                        .await?;
+                    return Ok(None);
+                }
+            }
+        } else {
+            invite_option
+        };
+        // the route picks how both legs are transrated
+        call_option.transrating = route_invite
+            .as_ref()
+            .and_then(|route_invite| route_invite.take_transrating(original));
+
         let ssrc = rand::random::<u32>();
         let rtp_token = self.cancel_token.child_token();
         let rtp_track = ActiveCall::create_rtp_track(
             rtp_token.clone(),
             active_call.app_state.clone(),
             active_call.server_side_track_id.clone(),
-            active_call.track_config.clone(),
+            active_call.track_config_for(Some(&call_option)),
             ssrc,
+            active_call
+                .call_state
+                .read()
+                .ok()
+                .and_then(|cs| cs.option.as_ref()?.shaping.clone()),
+            active_call.media_stream.levels(),
         )
         .await?;
 
         let offer = rtp_track.local_description().ok().unwrap_or_default();
-        let mut call_option = CallOption::default();
-        call_option.caller = caller.map(|u| u.to_string());
-        call_option.callee = Some(target.aor.to_string());
-
-        let mut invite_option = call_option.build_invite_option()?;
-        invite_option.destination = Some(target.destination.clone());
         invite_option.offer = Some(offer.clone().into());
-        invite_option.contact = caller_contact.uri.clone();
+        // a caller that sent no SDP gets the callee's offer answered the
+        // same way, in the ACK
+        let capabilities = match original.body.is_empty() {
+            true => SdpCapabilities::new(&offer).ok(),
+            false => None,
+        };
+        if let Some(capabilities) = capabilities.clone() {
+            active_call
+                .invitation
+                .late_offer(&mut invite_option, capabilities);
+        }
 
         ActiveCall::setup_track_with_stream(
             active_call.app_state.clone(),
@@ -303,18 +492,6 @@ impl B2bua {
         )
         .await?;
 
-        let invite_option = if let Some(route_invite) = &route_invite {
-            let route_result = route_invite.route_invite(invite_option, original).await?;
-            match route_result {
-                RouteResult::Forward(option) => option,
-                RouteResult::Abort(code, reason) => {
-                    warn!(session_id = self.session_id, code, reason, "route abort");
-                    return Err(anyhow::anyhow!("Route abort: {} {}", code, reason));
-                }
-            }
-        } else {
-            invite_option
-        };
         let track_id = active_call.server_side_track_id.clone();
         info!(
             session_id = self.session_id,
@@ -330,6 +507,7 @@ impl B2bua {
             start_time: Utc::now(),
             option: Some(call_option),
             ssrc,
+            variables: active_call.variables(),
             ..Default::default()
         }));
 
@@ -352,6 +530,7 @@ impl B2bua {
         let cancel_token = self.cancel_token.clone();
         let active_call_ref = active_call.clone();
         let recorder = self.recorder;
+        let topology = active_call.topology.clone();
 
         tokio::spawn(async move {
             let (refer_dlg_state_sender, refer_dlg_state_receiver) = mpsc::unbounded_channel();
@@ -413,6 +592,9 @@ impl B2bua {
                 )
             );
             info!(session_id, "b2bua callee completed with: {:?}", r);
+            if let Ok(dialog_id) = r {
+                topology.ended(&dialog_id.call_id);
+            }
         });
 
         let caller = invite_option.caller.clone();
@@ -427,6 +609,12 @@ impl B2bua {
                 warn!(session_id = self.session_id, %caller, %callee, "callee invite failed: {}", e);
                 match &e {
                     rsipstack::Error::DialogError(reason, _, code) => {
+                        // relayed to the caller when it is rejected
+                        let cause = active_call
+                            .call_state
+                            .write()
+                            .ok()
+                            .map(|mut cs| cs.on_rejected(code, reason));
                         active_call
                             .event_sender
                             .send(SessionEvent::Reject {
@@ -434,6 +622,7 @@ impl B2bua {
                                 timestamp: crate::get_timestamp(),
                                 reason: reason.clone(),
                                 code: Some(code.code() as u32),
+                                cause,
                             })
                             .ok();
                     }
@@ -442,6 +631,11 @@ impl B2bua {
                 return Err(anyhow::anyhow!("{}", e));
             }
         };
+        active_call.topology.answered(CallLeg {
+            id: dialog_id.call_id.clone(),
+            track_id: track_id.clone(),
+            party: Some(callee.to_string()),
+        });
 
         let answer = match answer {
             Some(answer) => String::from_utf8_lossy(&answer).to_string(),
@@ -466,6 +660,11 @@ impl B2bua {
             %callee,
             answer,
             "callee answered with SDP");
-        Ok(())
+        // with a late offer the 2xx carried the callee's offer and the ACK
+        // rustpbx's answer
+        let anchor = capabilities
+            .and_then(|capabilities| capabilities.answer(&answer).ok())
+            .unwrap_or(offer);
+        Ok(Some(MediaLeg::new(dialog_id, answer, anchor)))
     }
 }
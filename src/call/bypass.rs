@@ -0,0 +1,208 @@
+//! Direct media between the two legs of a B2BUA call.
+//!
+//! Once a call between two trusted endpoints is up, each of them is sent the
+//! other's SDP by re-INVITE, so their RTP flows between them and rustpbx only
+//! keeps the signaling. Before the call is transferred or recorded, the media
+//! is anchored again: each leg is re-INVITEd with rustpbx's own SDP.
+//!
+//! A re-INVITE that fails leaves the media as it was before: the legs
+//! already re-INVITEd are sent back their previous SDP.
+use super::sip::Invitation;
+use crate::{net_tool::extract_rtp_addresses_from_sdp, proxy::acl::IpNetwork};
+use anyhow::Result;
+use rsip::StatusCodeKind;
+use rsipstack::dialog::DialogId;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// One leg of a call whose media may bypass rustpbx
+#[derive(Debug, Clone)]
+pub struct MediaLeg {
+    pub dialog_id: DialogId,
+    /// The SDP of the endpoint, the caller's offer or the callee's answer
+    pub sdp: String,
+    /// The SDP rustpbx sent the endpoint, with its media anchored
+    pub anchor: String,
+    /// The SDP of the other endpoint the leg was sent for direct media
+    direct: Option<String>,
+    /// Version of the last SDP sent on the leg, from `anchor`'s origin
+    version: u64,
+}
+
+impl MediaLeg {
+    pub fn new(dialog_id: DialogId, sdp: String, anchor: String) -> Self {
+        let version = origin(&anchor)
+            .and_then(|o| o.split_whitespace().nth(2)?.parse().ok())
+            .unwrap_or_default();
+        Self {
+            dialog_id,
+            sdp,
+            anchor,
+            direct: None,
+            version,
+        }
+    }
+
+    /// `sdp` under the origin rustpbx uses on this leg, with the next version
+    /// (RFC 3264 8)
+    fn next_sdp(&mut self, sdp: &str) -> String {
+        self.version += 1;
+        let Some(anchor_origin) = origin(&self.anchor) else {
+            return sdp.to_string();
+        };
+        let mut fields = anchor_origin.split_whitespace().collect::<Vec<_>>();
+        let version = self.version.to_string();
+        if fields.len() > 2 {
+            fields[2] = &version;
+        }
+        let origin_line = fields.join(" ");
+        sdp.lines()
+            .map(|line| match line.starts_with("o=") {
+                true => origin_line.as_str(),
+                false => line,
+            })
+            .collect::<Vec<_>>()
+            .join("\r\n")
+            + "\r\n"
+    }
+}
+
+fn origin(sdp: &str) -> Option<&str> {
+    sdp.lines().find(|line| line.starts_with("o="))
+}
+
+/// Whether an endpoint with this SDP may exchange media directly: all its
+/// addresses are trusted, and it needs no DTLS or ICE from rustpbx
+pub(crate) fn is_trusted(sdp: &str, trusted: &[IpNetwork]) -> bool {
+    if sdp.contains("SAVPF") || sdp.contains("a=fingerprint:") {
+        return false;
+    }
+    match extract_rtp_addresses_from_sdp(sdp) {
+        Ok(addrs) if !addrs.is_empty() => addrs
+            .iter()
+            .all(|addr| trusted.iter().any(|network| network.contains(addr))),
+        _ => false,
+    }
+}
+
+/// The media of a call, either anchored in rustpbx or flowing directly
+/// between its two legs
+#[derive(Default)]
+pub struct DirectMedia {
+    /// The answer rustpbx sent the caller
+    pub caller_anchor: Option<String>,
+    legs: Option<(MediaLeg, MediaLeg)>,
+    /// How long to wait for the answer to a re-INVITE, the transaction's
+    /// own timer when unset
+    timeout: Option<Duration>,
+}
+
+impl DirectMedia {
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn is_bypassed(&self) -> bool {
+        self.legs.is_some()
+    }
+
+    /// Sends the callee the caller's offer, then the caller the callee's
+    /// answer to it. The media stays anchored when the callee refuses, and
+    /// is anchored again when the caller does
+    pub async fn bypass(
+        &mut self,
+        invitation: &Invitation,
+        mut caller: MediaLeg,
+        mut callee: MediaLeg,
+    ) -> Result<()> {
+        if self.is_bypassed() {
+            return Ok(());
+        }
+        let offer = callee.next_sdp(&caller.sdp);
+        let answer = match reinvite(invitation, &callee.dialog_id, offer, self.timeout).await? {
+            Some(answer) => answer,
+            None => callee.sdp.clone(),
+        };
+        let offer = caller.next_sdp(&answer);
+        if let Err(e) = reinvite(invitation, &caller.dialog_id, offer, self.timeout).await {
+            warn!(dialog_id = %caller.dialog_id, "caller refused direct media: {}", e);
+            let offer = callee.next_sdp(&callee.anchor.clone());
+            if let Err(e) = reinvite(invitation, &callee.dialog_id, offer, self.timeout).await {
+                warn!(dialog_id = %callee.dialog_id, "failed to anchor media: {}", e);
+            }
+            return Err(e);
+        }
+        callee.direct = Some(caller.sdp.clone());
+        caller.direct = Some(answer);
+        info!(
+            caller = %caller.dialog_id,
+            callee = %callee.dialog_id,
+            "media bypasses rustpbx"
+        );
+        self.legs = Some((caller, callee));
+        Ok(())
+    }
+
+    /// Brings the media back through rustpbx, re-INVITEs each leg with the
+    /// SDP it was answered or called with. When a leg refuses, the media
+    /// keeps bypassing rustpbx: the caller, if it was anchored already, is
+    /// sent the callee's SDP again
+    pub async fn anchor(&mut self, invitation: &Invitation) -> Result<()> {
+        let Some((mut caller, mut callee)) = self.legs.take() else {
+            return Ok(());
+        };
+        let offer = caller.next_sdp(&caller.anchor.clone());
+        if let Err(e) = reinvite(invitation, &caller.dialog_id, offer, self.timeout).await {
+            warn!(dialog_id = %caller.dialog_id, "failed to anchor media: {}", e);
+            self.legs = Some((caller, callee));
+            return Err(e);
+        }
+        let offer = callee.next_sdp(&callee.anchor.clone());
+        if let Err(e) = reinvite(invitation, &callee.dialog_id, offer, self.timeout).await {
+            warn!(dialog_id = %callee.dialog_id, "failed to anchor media: {}", e);
+            if let Some(direct) = caller.direct.clone() {
+                let offer = caller.next_sdp(&direct);
+                if let Err(e) = reinvite(invitation, &caller.dialog_id, offer, self.timeout).await {
+                    warn!(dialog_id = %caller.dialog_id, "failed to restore direct media: {}", e);
+                }
+            }
+            self.legs = Some((caller, callee));
+            return Err(e);
+        }
+        info!(
+            caller = %caller.dialog_id,
+            callee = %callee.dialog_id,
+            "media anchored"
+        );
+        Ok(())
+    }
+}
+
+/// Sends `offer` in a re-INVITE, returns the SDP of the answer if any.
+/// Gives up on an answer that takes longer than `timeout`
+async fn reinvite(
+    invitation: &Invitation,
+    dialog_id: &DialogId,
+    offer: String,
+    timeout: Option<Duration>,
+) -> Result<Option<String>> {
+    let headers = vec![rsip::Header::ContentType(
+        "application/sdp".to_string().into(),
+    )];
+    // queued behind the other renegotiations of the dialog
+    let resp = invitation
+        .renegotiate(
+            dialog_id,
+            rsip::Method::Invite,
+            Some(headers),
+            Some(offer.into_bytes()),
+            timeout,
+        )
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("no response to re-INVITE"))?;
+    if resp.status_code.kind() != StatusCodeKind::Successful {
+        return Err(anyhow::anyhow!("re-INVITE rejected: {}", resp.status_code));
+    }
+    Ok((!resp.body.is_empty()).then(|| String::from_utf8_lossy(&resp.body).to_string()))
+}
@@ -0,0 +1,305 @@
+use rsipstack::dialog::dialog::TerminatedReason;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Why a call ended, as a Q.850 cause. SIP status codes and `Reason`
+/// headers are mapped onto it following RFC 3398 and RFC 3326, so busy,
+/// no answer and network congestion can be told apart whatever the
+/// signalling said
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum HangupCause {
+    UnallocatedNumber,
+    NoRouteToDestination,
+    NormalClearing,
+    UserBusy,
+    NoUserResponse,
+    NoAnswer,
+    SubscriberAbsent,
+    CallRejected,
+    NumberChanged,
+    ExchangeRoutingError,
+    DestinationOutOfOrder,
+    InvalidNumberFormat,
+    NormalUnspecified,
+    NoCircuitAvailable,
+    NetworkOutOfOrder,
+    TemporaryFailure,
+    SwitchingEquipmentCongestion,
+    ResourceUnavailable,
+    BearerCapabilityNotAvailable,
+    ServiceUnavailable,
+    ServiceNotImplemented,
+    IncompatibleDestination,
+    RecoveryOnTimerExpiry,
+    Interworking,
+    /// The caller gave up before the call was answered
+    OriginatorCancel,
+}
+
+impl HangupCause {
+    pub fn q850(&self) -> u8 {
+        match self {
+            Self::UnallocatedNumber => 1,
+            Self::NoRouteToDestination => 3,
+            Self::NormalClearing => 16,
+            Self::UserBusy => 17,
+            Self::NoUserResponse => 18,
+            Self::NoAnswer => 19,
+            Self::SubscriberAbsent => 20,
+            Self::CallRejected => 21,
+            Self::NumberChanged => 22,
+            Self::ExchangeRoutingError => 25,
+            Self::DestinationOutOfOrder => 27,
+            Self::InvalidNumberFormat => 28,
+            Self::NormalUnspecified => 31,
+            Self::NoCircuitAvailable => 34,
+            Self::NetworkOutOfOrder => 38,
+            Self::TemporaryFailure => 41,
+            Self::SwitchingEquipmentCongestion => 42,
+            Self::ResourceUnavailable => 47,
+            Self::BearerCapabilityNotAvailable => 58,
+            Self::ServiceUnavailable => 63,
+            Self::ServiceNotImplemented => 79,
+            Self::IncompatibleDestination => 88,
+            Self::RecoveryOnTimerExpiry => 102,
+            Self::Interworking => 127,
+            // Q.850 has no cancel cause, RFC 3326 sends normal clearing
+            Self::OriginatorCancel => 16,
+        }
+    }
+
+    /// Unknown causes fall back to the generic cause of their class
+    pub fn from_q850(cause: u8) -> Self {
+        match cause {
+            1 | 2 => Self::UnallocatedNumber,
+            3 => Self::NoRouteToDestination,
+            16 => Self::NormalClearing,
+            17 => Self::UserBusy,
+            18 => Self::NoUserResponse,
+            19 => Self::NoAnswer,
+            20 => Self::SubscriberAbsent,
+            21 => Self::CallRejected,
+            22 | 23 => Self::NumberChanged,
+            25 => Self::ExchangeRoutingError,
+            27 => Self::DestinationOutOfOrder,
+            28 => Self::InvalidNumberFormat,
+            0..=31 => Self::NormalUnspecified,
+            34 => Self::NoCircuitAvailable,
+            38 => Self::NetworkOutOfOrder,
+            41 => Self::TemporaryFailure,
+            42 => Self::SwitchingEquipmentCongestion,
+            32..=47 => Self::ResourceUnavailable,
+            58 => Self::BearerCapabilityNotAvailable,
+            48..=63 => Self::ServiceUnavailable,
+            64..=79 => Self::ServiceNotImplemented,
+            88 => Self::IncompatibleDestination,
+            102 => Self::RecoveryOnTimerExpiry,
+            _ => Self::Interworking,
+        }
+    }
+
+    /// SIP response to cause, RFC 3398 section 8.2.6.1
+    pub fn from_sip_status(code: u16) -> Self {
+        match code {
+            200..=299 => Self::NormalClearing,
+            401 | 402 | 403 | 407 | 603 => Self::CallRejected,
+            404 | 485 | 604 => Self::UnallocatedNumber,
+            405 => Self::ServiceUnavailable,
+            406 | 415 | 501 => Self::ServiceNotImplemented,
+            408 | 504 => Self::RecoveryOnTimerExpiry,
+            410 => Self::NumberChanged,
+            480 => Self::NoUserResponse,
+            482 | 483 => Self::ExchangeRoutingError,
+            484 => Self::InvalidNumberFormat,
+            486 | 600 => Self::UserBusy,
+            487 => Self::OriginatorCancel,
+            488 => Self::IncompatibleDestination,
+            502 => Self::DestinationOutOfOrder,
+            503 => Self::SwitchingEquipmentCongestion,
+            606 => Self::BearerCapabilityNotAvailable,
+            400 | 481 | 500 => Self::TemporaryFailure,
+            _ => Self::Interworking,
+        }
+    }
+
+    /// The response to reject a call with, RFC 3398 section 7.2.4.1
+    pub fn sip_status(&self) -> rsip::StatusCode {
+        match self {
+            Self::UnallocatedNumber | Self::NoRouteToDestination => rsip::StatusCode::NotFound,
+            Self::UserBusy => rsip::StatusCode::BusyHere,
+            Self::NoUserResponse | Self::RecoveryOnTimerExpiry => rsip::StatusCode::RequestTimeout,
+            Self::NoAnswer
+            | Self::SubscriberAbsent
+            | Self::NormalClearing
+            | Self::NormalUnspecified => rsip::StatusCode::TemporarilyUnavailable,
+            Self::CallRejected => rsip::StatusCode::Forbidden,
+            Self::NumberChanged => rsip::StatusCode::Gone,
+            Self::ExchangeRoutingError => rsip::StatusCode::TooManyHops,
+            Self::DestinationOutOfOrder => rsip::StatusCode::BadGateway,
+            Self::InvalidNumberFormat => rsip::StatusCode::AddressIncomplete,
+            Self::NoCircuitAvailable
+            | Self::NetworkOutOfOrder
+            | Self::TemporaryFailure
+            | Self::SwitchingEquipmentCongestion
+            | Self::ResourceUnavailable
+            | Self::BearerCapabilityNotAvailable
+            | Self::ServiceUnavailable => rsip::StatusCode::ServiceUnavailable,
+            Self::ServiceNotImplemented => rsip::StatusCode::NotImplemented,
+            Self::IncompatibleDestination => rsip::StatusCode::NotAcceptableHere,
+            Self::Interworking => rsip::StatusCode::ServerInternalError,
+            Self::OriginatorCancel => rsip::StatusCode::RequestTerminated,
+        }
+    }
+
+    /// Picks the cause out of a `Reason` header value, ignoring non Q.850
+    /// protocols: `Q.850;cause=17;text="USER_BUSY"`
+    pub fn from_reason(value: &str) -> Option<Self> {
+        value.split(',').find_map(|reason| {
+            let mut params = reason.split(';').map(str::trim);
+            if !params.next()?.eq_ignore_ascii_case("Q.850") {
+                return None;
+            }
+            params
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("cause"))
+                .and_then(|(_, cause)| cause.trim().parse().ok())
+                .map(Self::from_q850)
+        })
+    }
+
+    /// Prefers the Q.850 cause of the `Reason` header the far end sent
+    pub fn from_response(code: u16, reason: Option<&str>) -> Self {
+        reason
+            .and_then(Self::from_reason)
+            .unwrap_or_else(|| Self::from_sip_status(code))
+    }
+
+    pub fn from_terminated(reason: &TerminatedReason, answered: bool) -> Self {
+        match reason {
+            TerminatedReason::UacCancel => Self::OriginatorCancel,
+            TerminatedReason::UacBye | TerminatedReason::UasBye => Self::NormalClearing,
+            TerminatedReason::UacBusy | TerminatedReason::UasBusy => Self::UserBusy,
+            TerminatedReason::UasDecline => Self::CallRejected,
+            TerminatedReason::Timeout if answered => Self::RecoveryOnTimerExpiry,
+            TerminatedReason::Timeout => Self::NoAnswer,
+            TerminatedReason::ProxyAuthRequired => Self::CallRejected,
+            TerminatedReason::ProxyError(code)
+            | TerminatedReason::UacOther(code)
+            | TerminatedReason::UasOther(code) => Self::from_sip_status(code.code()),
+        }
+    }
+
+    /// `USER_BUSY` style name used in the `text` of the `Reason` header
+    pub fn text(&self) -> String {
+        serde_json::to_value(self)
+            .ok()
+            .and_then(|v| v.as_str().map(|s| s.to_uppercase()))
+            .unwrap_or_default()
+    }
+
+    /// Value of the `Reason` header to send along with a BYE, CANCEL or
+    /// failure response
+    pub fn reason(&self) -> String {
+        format!("Q.850;cause={};text=\"{}\"", self.q850(), self.text())
+    }
+
+    pub fn reason_header(&self) -> rsip::Header {
+        rsip::Header::Other("Reason".into(), self.reason())
+    }
+}
+
+impl std::fmt::Display for HangupCause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text().to_lowercase())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sip_status_mapping() {
+        assert_eq!(HangupCause::from_sip_status(486), HangupCause::UserBusy);
+        assert_eq!(
+            HangupCause::from_sip_status(480),
+            HangupCause::NoUserResponse
+        );
+        assert_eq!(
+            HangupCause::from_sip_status(503),
+            HangupCause::SwitchingEquipmentCongestion
+        );
+        assert_eq!(
+            HangupCause::from_sip_status(299),
+            HangupCause::NormalClearing
+        );
+        assert_eq!(HangupCause::from_sip_status(499), HangupCause::Interworking);
+
+        assert_eq!(
+            HangupCause::UserBusy.sip_status(),
+            rsip::StatusCode::BusyHere
+        );
+        assert_eq!(
+            HangupCause::NoCircuitAvailable.sip_status(),
+            rsip::StatusCode::ServiceUnavailable
+        );
+        for code in [403, 404, 410, 484, 486, 487, 488, 502, 503] {
+            let cause = HangupCause::from_sip_status(code);
+            assert_eq!(cause.sip_status().code(), code, "{:?}", cause);
+        }
+    }
+
+    #[test]
+    fn test_q850_mapping() {
+        assert_eq!(HangupCause::from_q850(34), HangupCause::NoCircuitAvailable);
+        assert_eq!(HangupCause::from_q850(44), HangupCause::ResourceUnavailable);
+        assert_eq!(HangupCause::from_q850(9), HangupCause::NormalUnspecified);
+        assert_eq!(HangupCause::from_q850(111), HangupCause::Interworking);
+        assert_eq!(HangupCause::NoAnswer.q850(), 19);
+    }
+
+    #[test]
+    fn test_reason_header() {
+        assert_eq!(
+            HangupCause::UserBusy.reason(),
+            "Q.850;cause=17;text=\"USER_BUSY\""
+        );
+        assert_eq!(HangupCause::NoAnswer.to_string(), "no_answer");
+        assert_eq!(
+            HangupCause::from_reason("SIP;cause=200;text=\"Call completed elsewhere\""),
+            None
+        );
+        assert_eq!(
+            HangupCause::from_reason("SIP;cause=503, q.850 ; cause = 42 ;text=\"x\""),
+            Some(HangupCause::SwitchingEquipmentCongestion)
+        );
+        assert_eq!(
+            HangupCause::from_response(503, Some("Q.850;cause=34")),
+            HangupCause::NoCircuitAvailable
+        );
+        assert_eq!(
+            HangupCause::from_response(503, Some("garbage")),
+            HangupCause::SwitchingEquipmentCongestion
+        );
+    }
+
+    #[test]
+    fn test_terminated_reason() {
+        assert_eq!(
+            HangupCause::from_terminated(&TerminatedReason::UacCancel, false),
+            HangupCause::OriginatorCancel
+        );
+        assert_eq!(
+            HangupCause::from_terminated(&TerminatedReason::Timeout, false),
+            HangupCause::NoAnswer
+        );
+        assert_eq!(
+            HangupCause::from_terminated(
+                &TerminatedReason::UasOther(rsip::StatusCode::NotFound),
+                false
+            ),
+            HangupCause::UnallocatedNumber
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use super::{ActiveCall, Command, gather::GatherOption};
+use crate::{
+    config::{ConsentJurisdiction, RecordingConsentConfig},
+    event::SessionEvent,
+    media::recorder::RecorderOption,
+};
+use anyhow::Result;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::info;
+
+const CONSENT_PLAY_ID: &str = "recording-consent";
+/// granted, denied or notified, in the call variables and the CDR
+pub const CONSENT_VARIABLE: &str = "recording.consent";
+pub const JURISDICTION_VARIABLE: &str = "recording.jurisdiction";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsentStatus {
+    /// The caller pressed the accept key
+    Granted,
+    /// The caller pressed another key or none, the call is not recorded
+    Denied,
+    /// The prompt told the caller, no key was asked for
+    Notified,
+}
+
+impl ConsentStatus {
+    /// The consent of a caller pressing `digits`, none on a timeout
+    pub fn of(jurisdiction: &ConsentJurisdiction, digits: Option<&str>) -> Self {
+        match jurisdiction.accept_key.as_deref() {
+            None => ConsentStatus::Notified,
+            Some(key) if digits == Some(key) => ConsentStatus::Granted,
+            Some(_) => ConsentStatus::Denied,
+        }
+    }
+
+    pub fn allows_recording(&self) -> bool {
+        !matches!(self, ConsentStatus::Denied)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConsentStatus::Granted => "granted",
+            ConsentStatus::Denied => "denied",
+            ConsentStatus::Notified => "notified",
+        }
+    }
+}
+
+impl RecordingConsentConfig {
+    /// The jurisdiction of the first party in one, by the numbers of the
+    /// parties and the country of the caller's address
+    pub fn jurisdiction(
+        &self,
+        numbers: &[&str],
+        country: Option<&str>,
+    ) -> Option<&ConsentJurisdiction> {
+        self.jurisdictions.iter().find(|jurisdiction| {
+            let in_country = country.is_some_and(|country| {
+                jurisdiction
+                    .countries
+                    .iter()
+                    .any(|c| c.eq_ignore_ascii_case(country))
+            });
+            let in_prefix = numbers.iter().any(|number| {
+                let number = number.trim_start_matches('+');
+                jurisdiction
+                    .prefixes
+                    .iter()
+                    .any(|prefix| number.starts_with(prefix.trim_start_matches('+')))
+            });
+            in_country || in_prefix
+        })
+    }
+}
+
+/// Plays the consent prompt of the jurisdiction to the answered caller,
+/// and records the call unless they deny it. The consent is left in the
+/// call variables
+pub async fn obtain(
+    active_call: &ActiveCall,
+    jurisdiction: &ConsentJurisdiction,
+    recorder: RecorderOption,
+) -> Result<ConsentStatus> {
+    let mut events = active_call.event_sender.subscribe();
+    let command = match jurisdiction.accept_key.as_ref() {
+        Some(_) => Command::Gather {
+            segments: Some(jurisdiction.prompt.clone()),
+            locale: None,
+            variables: None,
+            play_id: Some(CONSENT_PLAY_ID.to_string()),
+            option: None,
+            input: Some(GatherOption {
+                speech: false,
+                max_digits: Some(1),
+                finish_on_key: None,
+                timeout: jurisdiction.timeout,
+                ..Default::default()
+            }),
+        },
+        None => Command::Prompt {
+            segments: jurisdiction.prompt.clone(),
+            locale: None,
+            variables: None,
+            play_id: Some(CONSENT_PLAY_ID.to_string()),
+            auto_hangup: None,
+            option: None,
+            wait_input_timeout: None,
+        },
+    };
+    active_call.enqueue_command(command).await?;
+    let digits = match jurisdiction.accept_key.is_some() {
+        true => tokio::select! {
+            _ = active_call.cancel_token.cancelled() => None,
+            digits = wait_digits(&mut events) => digits,
+        },
+        false => None,
+    };
+    let status = ConsentStatus::of(jurisdiction, digits.as_deref());
+    info!(
+        session_id = active_call.session_id,
+        jurisdiction = jurisdiction.name,
+        consent = status.as_str(),
+        "recording consent"
+    );
+    let variables = active_call.variables();
+    variables.set(CONSENT_VARIABLE, status.as_str());
+    variables.set(JURISDICTION_VARIABLE, jurisdiction.name.clone());
+    if status.allows_recording() && !active_call.cancel_token.is_cancelled() {
+        active_call
+            .enqueue_command(Command::Record {
+                recorder: Some(recorder),
+            })
+            .await?;
+    }
+    Ok(status)
+}
+
+/// The digits of the consent gather, none when it timed out
+async fn wait_digits(
+    events: &mut tokio::sync::broadcast::Receiver<SessionEvent>,
+) -> Option<String> {
+    loop {
+        match events.recv().await {
+            Ok(SessionEvent::Gather {
+                play_id, digits, ..
+            }) if play_id.as_deref() == Some(CONSENT_PLAY_ID) => return digits,
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn jurisdiction(name: &str, prefixes: &[&str], countries: &[&str]) -> ConsentJurisdiction {
+        ConsentJurisdiction {
+            name: name.to_string(),
+            prefixes: prefixes.iter().map(|p| p.to_string()).collect(),
+            countries: countries.iter().map(|c| c.to_string()).collect(),
+            prompt: vec![],
+            accept_key: Some("1".to_string()),
+            timeout: 5000,
+        }
+    }
+
+    #[test]
+    fn test_consent_jurisdiction() {
+        let config = RecordingConsentConfig {
+            jurisdictions: vec![
+                jurisdiction("california", &["+1415", "+1650"], &[]),
+                jurisdiction("germany", &["+49"], &["DE"]),
+            ],
+        };
+        let name = |numbers: &[&str], country| {
+            config
+                .jurisdiction(numbers, country)
+                .map(|jurisdiction| jurisdiction.name.as_str())
+        };
+        // either party puts the call in the region
+        assert_eq!(name(&["1001", "14155550100"], None), Some("california"));
+        assert_eq!(name(&["+4930123456", "1001"], None), Some("germany"));
+        assert_eq!(name(&["1001", "1002"], Some("de")), Some("germany"));
+        assert_eq!(name(&["1001", "+33123456"], Some("FR")), None);
+    }
+
+    #[test]
+    fn test_consent_status() {
+        let mut jurisdiction = jurisdiction("california", &[], &[]);
+        assert_eq!(
+            ConsentStatus::of(&jurisdiction, Some("1")),
+            ConsentStatus::Granted
+        );
+        assert_eq!(
+            ConsentStatus::of(&jurisdiction, Some("2")),
+            ConsentStatus::Denied
+        );
+        assert!(!ConsentStatus::of(&jurisdiction, None).allows_recording());
+        jurisdiction.accept_key = None;
+        assert_eq!(
+            ConsentStatus::of(&jurisdiction, None),
+            ConsentStatus::Notified
+        );
+        assert!(ConsentStatus::Notified.allows_recording());
+    }
+}
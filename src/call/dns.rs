@@ -0,0 +1,445 @@
+use crate::config::DnsConfig;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use rsip::{Domain, Host, HostWithPort, Port, Transport};
+use rsip_dns::{
+    AsyncTrustDnsClient, DnsClient, SrvDomain,
+    records::{AddrRecord, NaptrFlags, NaptrRecord, SrvEntry, SrvRecord},
+    trust_dns_resolver::TokioAsyncResolver,
+};
+use rsipstack::transport::SipAddr;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info};
+
+/// Object safe view of a `DnsClient`
+#[async_trait]
+trait Lookup: Send + Sync {
+    async fn naptr(&self, domain: Domain) -> Option<NaptrRecord>;
+    async fn srv(&self, domain: SrvDomain) -> Option<SrvRecord>;
+    async fn ip(&self, domain: Domain) -> Option<AddrRecord>;
+}
+
+#[async_trait]
+impl<C: DnsClient + 'static> Lookup for C {
+    async fn naptr(&self, domain: Domain) -> Option<NaptrRecord> {
+        self.naptr_lookup(domain).await
+    }
+
+    async fn srv(&self, domain: SrvDomain) -> Option<SrvRecord> {
+        self.srv_lookup(domain).await
+    }
+
+    async fn ip(&self, domain: Domain) -> Option<AddrRecord> {
+        self.ip_lookup(domain).await.ok()
+    }
+}
+
+/// Resolves SIP URIs to the transport, address and port to send to, per
+/// RFC 3263 (NAPTR, then SRV, then A/AAAA). Targets come back in the order
+/// they should be tried, destinations that failed recently last
+pub struct SipResolver {
+    client: Box<dyn Lookup>,
+    blacklist: Mutex<HashMap<SipAddr, Instant>>,
+    blacklist_ttl: Duration,
+    prefer_ipv6: bool,
+}
+
+impl Default for SipResolver {
+    fn default() -> Self {
+        Self::new(&DnsConfig::default())
+    }
+}
+
+impl SipResolver {
+    /// Resolver using the system's name servers
+    pub fn new(config: &DnsConfig) -> Self {
+        let resolver = match TokioAsyncResolver::tokio_from_system_conf() {
+            Ok(resolver) => resolver,
+            Err(_) => TokioAsyncResolver::tokio(Default::default(), Default::default())
+                .expect("failed to create DNS resolver"),
+        };
+        Self::with_client(AsyncTrustDnsClient::new(resolver), config)
+    }
+
+    pub fn with_client(client: impl DnsClient + 'static, config: &DnsConfig) -> Self {
+        Self {
+            client: Box::new(client),
+            blacklist: Mutex::new(HashMap::new()),
+            blacklist_ttl: Duration::from_secs(config.blacklist_ttl),
+            prefer_ipv6: config.prefer_ipv6,
+        }
+    }
+
+    /// Keeps a destination out of the way for `blacklist_ttl` after a failure
+    pub fn blacklist(&self, addr: &SipAddr) {
+        if self.blacklist_ttl.is_zero() {
+            return;
+        }
+        info!(%addr, ttl = ?self.blacklist_ttl, "blacklisting destination");
+        if let Ok(mut blacklist) = self.blacklist.lock() {
+            blacklist.insert(addr.clone(), Instant::now() + self.blacklist_ttl);
+        }
+    }
+
+    pub fn is_blacklisted(&self, addr: &SipAddr) -> bool {
+        self.is_blacklisted_at(addr, Instant::now())
+    }
+
+    pub(crate) fn is_blacklisted_at(&self, addr: &SipAddr, now: Instant) -> bool {
+        let Ok(mut blacklist) = self.blacklist.lock() else {
+            return false;
+        };
+        blacklist.retain(|_, until| *until > now);
+        blacklist.contains_key(addr)
+    }
+
+    /// All targets of a destination, in the order they should be tried.
+    /// Destinations that failed recently go last rather than being left
+    /// out, so a call still has somewhere to go when every one did
+    pub async fn resolve(&self, uri: &rsip::Uri) -> Result<Vec<SipAddr>> {
+        let targets = self.lookup(uri, &mut StdRng::from_os_rng()).await?;
+        if targets.is_empty() {
+            return Err(anyhow!("no DNS records for {}", uri.host_with_port));
+        }
+        let now = Instant::now();
+        let (mut targets, blacklisted): (Vec<_>, Vec<_>) = targets
+            .into_iter()
+            .partition(|addr| !self.is_blacklisted_at(addr, now));
+        targets.extend(blacklisted);
+        debug!(%uri, ?targets, "resolved targets");
+        Ok(targets)
+    }
+
+    pub(crate) async fn lookup(&self, uri: &rsip::Uri, rng: &mut impl Rng) -> Result<Vec<SipAddr>> {
+        let transport = uri.params.iter().find_map(|p| match p {
+            rsip::Param::Transport(t) => Some(*t),
+            _ => None,
+        });
+        let secure = matches!(uri.scheme, Some(rsip::Scheme::Sips))
+            || transport.is_some_and(|t| t.is_secure());
+        let default_transport = transport.unwrap_or(if secure {
+            Transport::Tls
+        } else {
+            Transport::Udp
+        });
+        let port = uri.host_with_port.port;
+
+        let domain = match &uri.host_with_port.host {
+            Host::IpAddr(ip) => {
+                let port = port.unwrap_or_else(|| default_transport.default_port());
+                return Ok(vec![sip_addr(*ip, port, default_transport)]);
+            }
+            Host::Domain(domain) => domain.clone(),
+        };
+
+        // a port skips SRV, a transport skips NAPTR
+        let mut services = vec![];
+        if port.is_none() {
+            if transport.is_none() {
+                services = self.naptr_services(&domain, secure).await;
+            }
+            if services.is_empty() {
+                let transports = match transport {
+                    Some(transport) => vec![transport],
+                    None if secure => vec![Transport::Tls],
+                    None => vec![Transport::Udp, Transport::Tcp, Transport::Tls],
+                };
+                services = transports
+                    .into_iter()
+                    .map(|t| SrvDomain {
+                        domain: domain.clone(),
+                        protocol: t.protocol(),
+                        secure: t.is_secure(),
+                    })
+                    .collect();
+            }
+        }
+
+        let mut hosts = vec![];
+        for service in services {
+            let Some(record) = self.client.srv(service.clone()).await else {
+                continue;
+            };
+            let transport = service.transport();
+            for entry in order_srv(record.entries, rng) {
+                hosts.push((entry.target, entry.port, transport));
+            }
+        }
+        if hosts.is_empty() {
+            let port = port.unwrap_or_else(|| default_transport.default_port());
+            hosts.push((domain, port, default_transport));
+        }
+
+        let mut targets: Vec<SipAddr> = vec![];
+        for (host, port, transport) in hosts {
+            let Some(record) = self.client.ip(host).await else {
+                continue;
+            };
+            for ip in interleave(record.ip_addrs, self.prefer_ipv6) {
+                let addr = sip_addr(ip, port, transport);
+                if !targets.contains(&addr) {
+                    targets.push(addr);
+                }
+            }
+        }
+        Ok(targets)
+    }
+
+    /// SRV domains of the NAPTR records for SIP, by order then preference
+    async fn naptr_services(&self, domain: &Domain, secure: bool) -> Vec<SrvDomain> {
+        let Some(record) = self.client.naptr(domain.clone()).await else {
+            return vec![];
+        };
+        let mut entries = record
+            .entries
+            .into_iter()
+            .filter(|e| matches!(e.flags, NaptrFlags::S))
+            .filter(|e| e.services.transport().is_some() && (!secure || e.services.secure()))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|e| (e.order, e.preference));
+        entries
+            .into_iter()
+            .filter_map(|e| SrvDomain::try_from(e.replacement).ok())
+            .collect()
+    }
+}
+
+fn sip_addr(ip: IpAddr, port: Port, transport: Transport) -> SipAddr {
+    SipAddr {
+        r#type: Some(transport),
+        addr: HostWithPort {
+            host: Host::IpAddr(ip),
+            port: Some(port),
+        },
+    }
+}
+
+/// RFC 2782 ordering: lowest priority first, and within a priority a
+/// weighted random order, so weights spread the load across servers
+pub(crate) fn order_srv(mut entries: Vec<SrvEntry>, rng: &mut impl Rng) -> Vec<SrvEntry> {
+    // zero weights first, they only get picked when the draw is 0
+    entries.sort_by_key(|e| (e.priority, e.weight != 0));
+    let mut ordered = Vec::with_capacity(entries.len());
+    while !entries.is_empty() {
+        let priority = entries[0].priority;
+        let group = entries
+            .iter()
+            .take_while(|e| e.priority == priority)
+            .count();
+        let total = entries[..group]
+            .iter()
+            .map(|e| e.weight as u32)
+            .sum::<u32>();
+        let draw = rng.random_range(0..=total);
+        let mut sum = 0;
+        let index = entries[..group]
+            .iter()
+            .position(|e| {
+                sum += e.weight as u32;
+                sum >= draw
+            })
+            .unwrap_or(0);
+        ordered.push(entries.remove(index));
+    }
+    ordered
+}
+
+/// Alternates address families (RFC 8305), so a host whose first family
+/// is unreachable is still tried on the other one early on
+pub(crate) fn interleave(addrs: Vec<IpAddr>, prefer_ipv6: bool) -> Vec<IpAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(|ip| ip.is_ipv6());
+    let (first, second) = if prefer_ipv6 { (v6, v4) } else { (v4, v6) };
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    let mut ordered = vec![];
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => break,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsip_dns::records::{NaptrEntry, NaptrServices};
+    use std::sync::Arc;
+
+    #[derive(Clone, Default)]
+    struct MockDns {
+        naptr: Arc<HashMap<String, Vec<NaptrEntry>>>,
+        srv: Arc<HashMap<String, Vec<SrvEntry>>>,
+        ips: Arc<HashMap<String, Vec<IpAddr>>>,
+    }
+
+    #[async_trait]
+    impl DnsClient for MockDns {
+        async fn naptr_lookup(&self, domain: Domain) -> Option<NaptrRecord> {
+            let entries = self.naptr.get(&domain.to_string())?.clone();
+            Some(NaptrRecord { entries, domain })
+        }
+
+        async fn srv_lookup(&self, domain: SrvDomain) -> Option<SrvRecord> {
+            let entries = self.srv.get(&domain.to_string())?.clone();
+            Some(SrvRecord { entries, domain })
+        }
+
+        async fn ip_lookup(&self, domain: Domain) -> Result<AddrRecord, rsip::Error> {
+            match self.ips.get(&domain.to_string()) {
+                Some(ip_addrs) => Ok(AddrRecord {
+                    domain,
+                    ip_addrs: ip_addrs.clone(),
+                }),
+                None => Err(rsip::Error::Unexpected("NXDOMAIN".to_string())),
+            }
+        }
+    }
+
+    fn srv(priority: u16, weight: u16, port: u16, target: &str) -> SrvEntry {
+        SrvEntry {
+            priority,
+            weight,
+            port: port.into(),
+            target: target.into(),
+        }
+    }
+
+    fn naptr(order: u16, services: NaptrServices, replacement: &str) -> NaptrEntry {
+        NaptrEntry {
+            order,
+            preference: 10,
+            flags: NaptrFlags::S,
+            services,
+            regexp: vec![],
+            replacement: replacement.into(),
+        }
+    }
+
+    fn target(addr: &str, transport: Transport) -> SipAddr {
+        let addr: std::net::SocketAddr = addr.parse().unwrap();
+        sip_addr(addr.ip(), addr.port().into(), transport)
+    }
+
+    fn resolver() -> SipResolver {
+        let dns = MockDns {
+            naptr: Arc::new(HashMap::from([(
+                "example.com".to_string(),
+                vec![
+                    naptr(20, NaptrServices::SipD2u, "_sip._udp.example.com"),
+                    naptr(10, NaptrServices::SipD2t, "_sip._tcp.example.com"),
+                ],
+            )])),
+            srv: Arc::new(HashMap::from([
+                (
+                    "_sip._tcp.example.com".to_string(),
+                    vec![
+                        srv(20, 0, 5080, "b.example.com"),
+                        srv(10, 0, 5070, "a.example.com"),
+                    ],
+                ),
+                (
+                    "_sip._udp.example.com".to_string(),
+                    vec![srv(10, 0, 5060, "b.example.com")],
+                ),
+            ])),
+            ips: Arc::new(HashMap::from([
+                (
+                    "a.example.com".to_string(),
+                    vec!["2001:db8::1".parse().unwrap(), "192.0.2.1".parse().unwrap()],
+                ),
+                (
+                    "b.example.com".to_string(),
+                    vec!["192.0.2.2".parse().unwrap()],
+                ),
+            ])),
+        };
+        SipResolver::with_client(dns, &DnsConfig::default())
+    }
+
+    #[tokio::test]
+    async fn test_resolve_naptr_srv() {
+        let resolver = resolver();
+        let uri: rsip::Uri = "sip:alice@example.com".try_into().unwrap();
+        assert_eq!(
+            resolver.resolve(&uri).await.unwrap(),
+            vec![
+                target("192.0.2.1:5070", Transport::Tcp),
+                target("[2001:db8::1]:5070", Transport::Tcp),
+                target("192.0.2.2:5080", Transport::Tcp),
+                target("192.0.2.2:5060", Transport::Udp),
+            ]
+        );
+
+        // a port skips NAPTR and SRV, the transport defaults to UDP
+        let uri: rsip::Uri = "sip:alice@b.example.com:5090".try_into().unwrap();
+        assert_eq!(
+            resolver.resolve(&uri).await.unwrap(),
+            vec![target("192.0.2.2:5090", Transport::Udp)]
+        );
+        // no SRV for the transport: its default port
+        let uri: rsip::Uri = "sips:alice@a.example.com".try_into().unwrap();
+        assert_eq!(
+            resolver.resolve(&uri).await.unwrap(),
+            vec![
+                target("192.0.2.1:5061", Transport::Tls),
+                target("[2001:db8::1]:5061", Transport::Tls),
+            ]
+        );
+        let uri: rsip::Uri = "sip:alice@unknown.example.com".try_into().unwrap();
+        assert!(resolver.resolve(&uri).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_blacklist() {
+        let resolver = resolver();
+        let uri: rsip::Uri = "sip:alice@a.example.com;transport=tcp".try_into().unwrap();
+        let first = target("192.0.2.1:5060", Transport::Tcp);
+        let second = target("[2001:db8::1]:5060", Transport::Tcp);
+        resolver.blacklist(&first);
+        assert!(resolver.is_blacklisted(&first));
+        assert_eq!(
+            resolver.resolve(&uri).await.unwrap(),
+            vec![second, first.clone()]
+        );
+        assert!(!resolver.is_blacklisted_at(&first, Instant::now() + Duration::from_secs(61)));
+    }
+
+    #[test]
+    fn test_order_srv() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let entries = vec![
+            srv(20, 100, 5060, "backup.example.com"),
+            srv(10, 10, 5060, "light.example.com"),
+            srv(10, 90, 5060, "heavy.example.com"),
+        ];
+        let mut heavy_first = 0;
+        for _ in 0..1000 {
+            let ordered = order_srv(entries.clone(), &mut rng);
+            assert_eq!(ordered[2].target.to_string(), "backup.example.com");
+            if ordered[0].target.to_string() == "heavy.example.com" {
+                heavy_first += 1;
+            }
+        }
+        assert!((850..950).contains(&heavy_first), "{}", heavy_first);
+    }
+
+    #[test]
+    fn test_interleave() {
+        let addrs: Vec<IpAddr> = ["2001:db8::1", "2001:db8::2", "192.0.2.1"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+        let ordered = interleave(addrs.clone(), true);
+        assert_eq!(ordered, vec![addrs[0], addrs[2], addrs[1]]);
+        let ordered = interleave(addrs.clone(), false);
+        assert_eq!(ordered, vec![addrs[2], addrs[0], addrs[1]]);
+    }
+}
@@ -0,0 +1,454 @@
+use crate::config::FlowConfig;
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    path::PathBuf,
+    sync::Mutex,
+    time::Duration,
+};
+
+const DEFAULT_EXPIRE: Duration = Duration::from_secs(86400);
+
+/// A node of the IVR flow the caller went through
+#[skip_serializing_none]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FlowStep {
+    pub node: String,
+    /// When the caller entered it (in ms)
+    pub timestamp: u64,
+    /// The input that led here, e.g. the digit pressed at a menu
+    pub input: Option<String>,
+}
+
+/// Where a call is in its IVR flow, and the path it took to get there
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FlowState {
+    pub flow_id: String,
+    /// The call the state was last saved by
+    pub session_id: String,
+    pub path: Vec<FlowStep>,
+    /// What the flow needs to resume, e.g. an account number it collected
+    pub data: HashMap<String, serde_json::Value>,
+    /// The variant the flow got in each experiment it joined
+    #[serde(default)]
+    pub experiments: HashMap<String, String>,
+    /// Last change (in ms)
+    pub updated_at: u64,
+}
+
+impl FlowState {
+    pub fn new(flow_id: String, session_id: String) -> Self {
+        Self {
+            flow_id,
+            session_id,
+            ..Default::default()
+        }
+    }
+
+    /// The node the caller is at
+    pub fn node(&self) -> Option<&str> {
+        self.path.last().map(|step| step.node.as_str())
+    }
+
+    /// Records entering `node` and merges `data`, a null value removes the key
+    pub fn enter(
+        &mut self,
+        node: String,
+        input: Option<String>,
+        data: HashMap<String, serde_json::Value>,
+        now: u64,
+    ) {
+        self.path.push(FlowStep {
+            node,
+            timestamp: now,
+            input,
+        });
+        for (key, value) in data {
+            match value {
+                serde_json::Value::Null => self.data.remove(&key),
+                value => self.data.insert(key, value),
+            };
+        }
+        self.updated_at = now;
+    }
+
+    /// The variant of `experiment`, picked by weight the first time the flow
+    /// joins it. The pick hashes the flow id, so a resumed flow keeps it
+    pub fn join(&mut self, experiment: &str, variants: &HashMap<String, u32>) -> Option<String> {
+        if let Some(variant) = self.experiments.get(experiment) {
+            return Some(variant.clone());
+        }
+        let variant = pick_variant(&format!("{}/{}", self.flow_id, experiment), variants)?;
+        self.experiments
+            .insert(experiment.to_string(), variant.clone());
+        Some(variant)
+    }
+}
+
+/// FNV-1a, stable across releases unlike the std hasher
+fn fnv1a(key: &str) -> u64 {
+    key.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn pick_variant(key: &str, variants: &HashMap<String, u32>) -> Option<String> {
+    let variants: BTreeMap<_, _> = variants.iter().filter(|(_, w)| **w > 0).collect();
+    let total: u64 = variants.values().map(|w| **w as u64).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut point = fnv1a(key) % total;
+    for (name, weight) in variants {
+        if point < *weight as u64 {
+            return Some(name.clone());
+        }
+        point -= *weight as u64;
+    }
+    None
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct NodeCounters {
+    pub entries: u64,
+    pub exits: u64,
+    /// Calls hung up by the caller at the node
+    pub abandons: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct VariantCounters {
+    pub assignments: u64,
+    /// Flows that ended other than by the caller hanging up
+    pub completions: u64,
+    pub abandons: u64,
+}
+
+/// Name, help and value of a counter
+type Metric<T> = (&'static str, &'static str, fn(&T) -> u64);
+
+/// Flow counters of all calls, by node and by experiment variant
+#[derive(Debug, Default)]
+pub struct FlowStats {
+    nodes: Mutex<BTreeMap<String, NodeCounters>>,
+    variants: Mutex<BTreeMap<(String, String), VariantCounters>>,
+}
+
+impl FlowStats {
+    /// The flow moved from `from` to `to`
+    pub fn enter(&self, from: Option<&str>, to: &str) {
+        if let Ok(mut nodes) = self.nodes.lock() {
+            if let Some(from) = from {
+                nodes.entry(from.to_string()).or_default().exits += 1;
+            }
+            nodes.entry(to.to_string()).or_default().entries += 1;
+        }
+    }
+
+    pub fn assign(&self, experiment: &str, variant: &str) {
+        if let Ok(mut variants) = self.variants.lock() {
+            variants
+                .entry((experiment.to_string(), variant.to_string()))
+                .or_default()
+                .assignments += 1;
+        }
+    }
+
+    /// The call of `flow` ended, hung up by the caller when `abandoned`
+    pub fn finish(&self, flow: &FlowState, abandoned: bool) {
+        if let (Some(node), Ok(mut nodes)) = (flow.node(), self.nodes.lock()) {
+            let counters = nodes.entry(node.to_string()).or_default();
+            match abandoned {
+                true => counters.abandons += 1,
+                false => counters.exits += 1,
+            }
+        }
+        if let Ok(mut variants) = self.variants.lock() {
+            for (experiment, variant) in &flow.experiments {
+                let counters = variants
+                    .entry((experiment.clone(), variant.clone()))
+                    .or_default();
+                match abandoned {
+                    true => counters.abandons += 1,
+                    false => counters.completions += 1,
+                }
+            }
+        }
+    }
+
+    pub fn node(&self, node: &str) -> NodeCounters {
+        self.nodes
+            .lock()
+            .ok()
+            .and_then(|nodes| nodes.get(node).copied())
+            .unwrap_or_default()
+    }
+
+    pub fn variant(&self, experiment: &str, variant: &str) -> VariantCounters {
+        self.variants
+            .lock()
+            .ok()
+            .and_then(|variants| {
+                variants
+                    .get(&(experiment.to_string(), variant.to_string()))
+                    .copied()
+            })
+            .unwrap_or_default()
+    }
+
+    /// The counters in the Prometheus text format
+    pub fn render(&self, out: &mut String) {
+        let nodes = self.nodes.lock().map(|n| n.clone()).unwrap_or_default();
+        let node_metrics: [Metric<NodeCounters>; 3] = [
+            ("entries", "Calls that entered the flow node", |c| c.entries),
+            ("exits", "Calls that left the flow node", |c| c.exits),
+            ("abandons", "Callers that hung up at the flow node", |c| {
+                c.abandons
+            }),
+        ];
+        for (name, help, value) in node_metrics {
+            writeln!(out, "# HELP rustpbx_flow_node_{}_total {}", name, help).ok();
+            writeln!(out, "# TYPE rustpbx_flow_node_{}_total counter", name).ok();
+            for (node, counters) in &nodes {
+                writeln!(
+                    out,
+                    "rustpbx_flow_node_{}_total{{node=\"{}\"}} {}",
+                    name,
+                    escape_label(node),
+                    value(counters)
+                )
+                .ok();
+            }
+        }
+        let variants = self.variants.lock().map(|v| v.clone()).unwrap_or_default();
+        let variant_metrics: [Metric<VariantCounters>; 3] = [
+            (
+                "assignments",
+                "Flows assigned to the experiment variant",
+                |c| c.assignments,
+            ),
+            ("completions", "Flows of the variant that completed", |c| {
+                c.completions
+            }),
+            ("abandons", "Flows of the variant the caller hung up", |c| {
+                c.abandons
+            }),
+        ];
+        for (name, help, value) in variant_metrics {
+            writeln!(
+                out,
+                "# HELP rustpbx_flow_experiment_{}_total {}",
+                name, help
+            )
+            .ok();
+            writeln!(out, "# TYPE rustpbx_flow_experiment_{}_total counter", name).ok();
+            for ((experiment, variant), counters) in &variants {
+                writeln!(
+                    out,
+                    "rustpbx_flow_experiment_{}_total{{experiment=\"{}\",variant=\"{}\"}} {}",
+                    name,
+                    escape_label(experiment),
+                    escape_label(variant),
+                    value(counters)
+                )
+                .ok();
+            }
+        }
+    }
+}
+
+pub fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Flow states saved as `<path>/<flow id>.json`, so a flow survives a
+/// restart and can be resumed by a call on another node sharing the path
+#[derive(Debug, Clone)]
+pub struct FlowStore {
+    path: PathBuf,
+    expire: Duration,
+}
+
+impl FlowStore {
+    /// `None` when the config has no path
+    pub fn new(config: &FlowConfig) -> Option<Self> {
+        Some(Self {
+            path: PathBuf::from(config.path.as_ref()?),
+            expire: config
+                .expire
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_EXPIRE),
+        })
+    }
+
+    fn file(&self, flow_id: &str) -> Result<PathBuf> {
+        let valid = !flow_id.is_empty()
+            && !flow_id.starts_with('.')
+            && flow_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@'));
+        if !valid {
+            return Err(anyhow!("invalid flow id: {}", flow_id));
+        }
+        Ok(self.path.join(format!("{}.json", flow_id)))
+    }
+
+    pub async fn save(&self, state: &FlowState) -> Result<()> {
+        let file = self.file(&state.flow_id)?;
+        tokio::fs::create_dir_all(&self.path).await?;
+        // a crash mid-write leaves the previous state
+        let partial = file.with_extension("json.tmp");
+        tokio::fs::write(&partial, serde_json::to_vec(state)?).await?;
+        tokio::fs::rename(&partial, &file).await?;
+        Ok(())
+    }
+
+    /// The saved state, `None` when there is none or it expired
+    pub async fn load(&self, flow_id: &str) -> Result<Option<FlowState>> {
+        let file = self.file(flow_id)?;
+        let data = match tokio::fs::read(&file).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let state: FlowState = serde_json::from_slice(&data)?;
+        let age = crate::get_timestamp().saturating_sub(state.updated_at);
+        if age > self.expire.as_millis() as u64 {
+            tokio::fs::remove_file(&file).await.ok();
+            return Ok(None);
+        }
+        Ok(Some(state))
+    }
+
+    pub async fn remove(&self, flow_id: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.file(flow_id)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_flow_store() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let store = FlowStore::new(&FlowConfig {
+            path: Some(dir.path().to_string_lossy().to_string()),
+            expire: Some(60),
+            ..Default::default()
+        })
+        .expect("store with a path");
+        let now = crate::get_timestamp();
+        let mut state = FlowState::new("flow-1".to_string(), "call-a".to_string());
+        state.enter(
+            "main".to_string(),
+            None,
+            HashMap::from([("account".to_string(), json!("42"))]),
+            now,
+        );
+        state.enter(
+            "billing".to_string(),
+            Some("2".to_string()),
+            HashMap::from([
+                ("account".to_string(), json!(null)),
+                ("amount".to_string(), json!(12.5)),
+            ]),
+            now + 10,
+        );
+        assert_eq!(state.node(), Some("billing"));
+        assert_eq!(
+            state.data,
+            HashMap::from([("amount".to_string(), json!(12.5))])
+        );
+        store.save(&state).await?;
+
+        assert_eq!(store.load("flow-1").await?, Some(state.clone()));
+        assert_eq!(store.load("flow-2").await?, None);
+        assert!(store.load("../flow-1").await.is_err());
+
+        state.updated_at = now - 61_000;
+        store.save(&state).await?;
+        assert_eq!(store.load("flow-1").await?, None);
+        assert!(!dir.path().join("flow-1.json").exists());
+        store.remove("flow-1").await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_flow_experiment() {
+        let variants = HashMap::from([("short".to_string(), 3), ("long".to_string(), 1)]);
+        let mut picked = HashMap::<String, u32>::new();
+        for i in 0..400 {
+            let mut state = FlowState::new(format!("call-{}", i), format!("call-{}", i));
+            let variant = state.join("greeting", &variants).expect("variant");
+            // sticky, even with other weights
+            assert_eq!(
+                state.join("greeting", &HashMap::from([("long".to_string(), 1)])),
+                Some(variant.clone())
+            );
+            *picked.entry(variant).or_default() += 1;
+        }
+        let short = picked["short"];
+        assert!((250..350).contains(&short), "short picked {} of 400", short);
+        assert_eq!(short + picked["long"], 400);
+
+        let mut state = FlowState::new("call-1".to_string(), "call-1".to_string());
+        assert_eq!(
+            state.join("menu", &HashMap::from([("a".to_string(), 0)])),
+            None
+        );
+        assert!(state.experiments.is_empty());
+    }
+
+    #[test]
+    fn test_flow_stats() {
+        let stats = FlowStats::default();
+        let mut state = FlowState::new("call-1".to_string(), "call-1".to_string());
+        stats.enter(None, "main");
+        state.enter("main".to_string(), None, HashMap::new(), 0);
+        stats.enter(state.node(), "billing");
+        state.enter("billing".to_string(), None, HashMap::new(), 10);
+        state
+            .experiments
+            .insert("greeting".to_string(), "short".to_string());
+        stats.assign("greeting", "short");
+        stats.finish(&state, true);
+
+        assert_eq!(
+            stats.node("main"),
+            NodeCounters {
+                entries: 1,
+                exits: 1,
+                abandons: 0
+            }
+        );
+        assert_eq!(stats.node("billing").abandons, 1);
+        assert_eq!(
+            stats.variant("greeting", "short"),
+            VariantCounters {
+                assignments: 1,
+                completions: 0,
+                abandons: 1
+            }
+        );
+        let mut metrics = String::new();
+        stats.render(&mut metrics);
+        assert!(metrics.contains("rustpbx_flow_node_entries_total{node=\"billing\"} 1\n"));
+        assert!(metrics.contains(
+            "rustpbx_flow_experiment_abandons_total{experiment=\"greeting\",variant=\"short\"} 1\n"
+        ));
+        assert_eq!(escape_label("a\"b\\"), "a\\\"b\\\\");
+    }
+}
@@ -0,0 +1,194 @@
+use rsip::{
+    SipMessage, StatusCodeKind,
+    prelude::{HeadersExt, ToTypedHeader},
+};
+use rsipstack::{
+    transaction::{
+        endpoint::EndpointInnerRef,
+        key::{TransactionKey, TransactionRole},
+        make_via_branch,
+        transaction::Transaction,
+    },
+    transport::SipAddr,
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// Accepted INVITEs kept to recognize the 2xx responses that follow
+const MAX_TRACKED_INVITES: usize = 4096;
+
+struct Accepted {
+    endpoint: EndpointInnerRef,
+    destination: SipAddr,
+    /// To tag of the 2xx the dialog was confirmed with
+    tag: String,
+    /// The ACK of that 2xx, sent again when it is retransmitted
+    ack: rsip::Request,
+    at: Instant,
+}
+
+/// Answers the 2xx responses a forking proxy sends after the one a client
+/// INVITE was accepted with (RFC 3261 13.2.2.4). Retransmissions of the
+/// accepted 2xx get its ACK again, the 2xx of every other fork an ACK and a
+/// BYE, so that no call is left up on the far side
+#[derive(Clone)]
+pub struct ForkGuard {
+    lifetime: Duration,
+    accepted: Arc<Mutex<HashMap<TransactionKey, Accepted>>>,
+}
+
+impl ForkGuard {
+    /// Watches the 2xx of accepted INVITEs for `lifetime`, 64*T1
+    pub fn new(lifetime: Duration) -> Self {
+        Self {
+            lifetime,
+            accepted: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Takes over the 2xx responses following `resp`, the 2xx the INVITE
+    /// sent to `destination` was accepted with, from the endpoint, which
+    /// would only ACK them
+    pub fn accept(&self, endpoint: EndpointInnerRef, destination: SipAddr, resp: &rsip::Response) {
+        let Ok(key) = TransactionKey::from_response(resp, TransactionRole::Client) else {
+            return;
+        };
+        let Some(tag) = to_tag(resp) else {
+            return;
+        };
+        let ack = endpoint
+            .finished_transactions
+            .write()
+            .ok()
+            .and_then(|mut finished| finished.remove(&key))
+            .flatten();
+        let Some(SipMessage::Request(ack)) = ack else {
+            return;
+        };
+        let Ok(mut accepted) = self.accepted.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if accepted.len() >= MAX_TRACKED_INVITES {
+            accepted.retain(|_, a| now.duration_since(a.at) < self.lifetime);
+        }
+        accepted.insert(
+            key,
+            Accepted {
+                endpoint,
+                destination,
+                tag,
+                ack,
+                at: now,
+            },
+        );
+    }
+
+    /// Answers `resp` when it is a 2xx to an accepted INVITE, returns
+    /// whether it was one
+    pub fn on_response(&self, resp: &rsip::Response) -> bool {
+        if resp.status_code.kind() != StatusCodeKind::Successful
+            || !resp
+                .cseq_header()
+                .and_then(|cseq| cseq.method())
+                .is_ok_and(|method| method == rsip::Method::Invite)
+        {
+            return false;
+        }
+        let Ok(key) = TransactionKey::from_response(resp, TransactionRole::Client) else {
+            return false;
+        };
+        let (endpoint, destination, ack, fork) = {
+            let Ok(accepted) = self.accepted.lock() else {
+                return false;
+            };
+            let Some(accepted) = accepted.get(&key) else {
+                return false;
+            };
+            if accepted.at.elapsed() >= self.lifetime {
+                return false;
+            }
+            (
+                accepted.endpoint.clone(),
+                accepted.destination.clone(),
+                accepted.ack.clone(),
+                to_tag(resp).is_some_and(|tag| tag != accepted.tag),
+            )
+        };
+        let resp = resp.clone();
+        tokio::spawn(async move {
+            if !fork {
+                send_ack(&endpoint, &destination, ack).await;
+                return;
+            }
+            info!(%destination, to = ?to_tag(&resp), "releasing forked 2xx");
+            if let Err(e) = release_fork(&endpoint, &destination, &resp).await {
+                warn!(%destination, "failed to release forked 2xx: {}", e);
+            }
+        });
+        true
+    }
+}
+
+fn to_tag(resp: &rsip::Response) -> Option<String> {
+    let tag = resp.to_header().ok()?.tag().ok()??;
+    Some(tag.to_string())
+}
+
+async fn send_ack(endpoint: &EndpointInnerRef, destination: &SipAddr, ack: rsip::Request) {
+    match endpoint.transport_layer.lookup(destination, None).await {
+        Ok((connection, destination)) => {
+            if let Err(e) = connection.send(ack.into(), Some(&destination)).await {
+                warn!(%destination, "failed to send ACK: {}", e);
+            }
+        }
+        Err(e) => warn!(%destination, "failed to send ACK: {}", e),
+    }
+}
+
+/// ACKs the 2xx of a fork that lost the race, then hangs it up
+async fn release_fork(
+    endpoint: &EndpointInnerRef,
+    destination: &SipAddr,
+    resp: &rsip::Response,
+) -> rsipstack::Result<()> {
+    let ack = endpoint.make_ack(rsip::Uri::default(), resp)?;
+    send_ack(endpoint, destination, ack.clone()).await;
+
+    let mut bye = ack;
+    bye.method = rsip::Method::Bye;
+    let seq = bye.cseq_header()?.seq()?;
+    bye.cseq_header_mut()?
+        .mut_seq(seq + 1)?
+        .mut_method(rsip::Method::Bye)?;
+    // a new transaction, not the ACK's
+    if let Some(rsip::Header::Via(via)) = bye
+        .headers
+        .iter_mut()
+        .find(|h| matches!(h, rsip::Header::Via(_)))
+    {
+        let mut typed = via.typed()?;
+        typed
+            .params
+            .retain(|param| !matches!(param, rsip::Param::Branch(_)));
+        typed.params.push(make_via_branch());
+        *via = typed.into();
+    }
+
+    let key = TransactionKey::from_request(&bye, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, bye, endpoint.clone(), None);
+    tx.destination = Some(destination.clone());
+    tx.send().await?;
+    while let Some(msg) = tx.receive().await {
+        if let SipMessage::Response(resp) = msg
+            && resp.status_code.kind() != StatusCodeKind::Provisional
+        {
+            break;
+        }
+    }
+    Ok(())
+}
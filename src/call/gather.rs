@@ -0,0 +1,397 @@
+use super::grammar::{self, Grammar, Interpretation, normalize};
+use crate::event::SessionEvent;
+use crate::synthesis::prompt::Language;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct GatherOption {
+    /// Collect DTMF digits
+    pub dtmf: bool,
+    /// Collect speech recognized by the call's ASR or keyword spotter
+    pub speech: bool,
+    /// Stop the prompt as soon as the caller presses a key or starts
+    /// speaking, otherwise input during the prompt is ignored
+    pub barge_in: bool,
+    /// Complete after this many digits
+    pub max_digits: Option<usize>,
+    /// Key that completes the digits, not part of them
+    pub finish_on_key: Option<String>,
+    /// Wait for the first input after the prompt (in ms)
+    pub timeout: u32,
+    /// Wait for the next digit, or for the transcript once speech started (in ms)
+    pub inter_digit_timeout: u32,
+    /// Phrases expected in the speech, the first one found in the transcript
+    /// is reported as the hint
+    pub hints: Vec<String>,
+    /// Tried in order on the transcript, which is a no match when none
+    /// takes it. Any transcript is taken without grammars
+    pub grammars: Vec<Grammar>,
+}
+
+impl Default for GatherOption {
+    fn default() -> Self {
+        Self {
+            dtmf: true,
+            speech: true,
+            barge_in: true,
+            max_digits: None,
+            finish_on_key: Some("#".to_string()),
+            timeout: 5000,
+            inter_digit_timeout: 3000,
+            hints: Vec::new(),
+            grammars: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Gathered {
+    Dtmf {
+        digits: String,
+        terminator: Option<String>,
+    },
+    Speech {
+        text: String,
+        hint: Option<String>,
+        interpretation: Option<Interpretation>,
+    },
+    /// Speech none of the grammars took
+    NoMatch {
+        text: String,
+    },
+    Timeout,
+}
+
+impl Gathered {
+    pub fn into_event(self, track_id: String, play_id: Option<String>) -> SessionEvent {
+        let (input, digits, terminator, text, hint, interpretation) = match self {
+            Gathered::Dtmf { digits, terminator } => {
+                ("dtmf", Some(digits), terminator, None, None, None)
+            }
+            Gathered::Speech {
+                text,
+                hint,
+                interpretation,
+            } => ("speech", None, None, Some(text), hint, interpretation),
+            Gathered::NoMatch { text } => ("nomatch", None, None, Some(text), None, None),
+            Gathered::Timeout => ("timeout", None, None, None, None, None),
+        };
+        let (grammar, value, confidence) = match interpretation {
+            Some(interpretation) => (
+                Some(interpretation.grammar),
+                Some(interpretation.value),
+                Some(interpretation.confidence),
+            ),
+            None => (None, None, None),
+        };
+        SessionEvent::Gather {
+            track_id,
+            timestamp: crate::get_timestamp(),
+            play_id,
+            input: input.to_string(),
+            digits,
+            terminator,
+            text,
+            hint,
+            grammar,
+            value,
+            confidence,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GatherStep {
+    Wait,
+    /// Input started during the prompt, stop it
+    BargeIn,
+    Done(Gathered),
+}
+
+/// Collects DTMF or speech for one gather, whichever the caller starts with.
+/// Timestamps are in ms, as returned by `crate::get_timestamp`
+#[derive(Debug)]
+pub struct Gather {
+    pub option: GatherOption,
+    pub play_id: Option<String>,
+    /// ssrc of the last prompt segment, listening starts when it ends
+    pub prompt_ssrc: Option<u32>,
+    /// Number and date rules of the grammars
+    language: Language,
+    prompting: bool,
+    digits: String,
+    deadline: Option<u64>,
+}
+
+impl Gather {
+    pub fn new(option: GatherOption, play_id: Option<String>) -> Self {
+        Self {
+            option,
+            play_id,
+            prompt_ssrc: None,
+            language: Language::English,
+            prompting: true,
+            digits: String::new(),
+            deadline: None,
+        }
+    }
+
+    pub fn with_language(mut self, language: Language) -> Self {
+        self.language = language;
+        self
+    }
+
+    pub fn prompting(&self) -> bool {
+        self.prompting
+    }
+
+    /// The prompt ended or was stopped, the input timeout starts now
+    pub fn prompt_done(&mut self, now: u64) {
+        self.prompting = false;
+        if self.deadline.is_none() {
+            self.deadline = Some(now + self.option.timeout as u64);
+        }
+    }
+
+    fn accepts_input(&mut self) -> Option<GatherStep> {
+        if !self.prompting {
+            return None;
+        }
+        if !self.option.barge_in {
+            return Some(GatherStep::Wait);
+        }
+        self.prompting = false;
+        Some(GatherStep::BargeIn)
+    }
+
+    pub fn on_dtmf(&mut self, digit: &str, now: u64) -> GatherStep {
+        if !self.option.dtmf {
+            return GatherStep::Wait;
+        }
+        let barge_in = match self.accepts_input() {
+            Some(GatherStep::Wait) => return GatherStep::Wait,
+            barge_in => barge_in,
+        };
+        if self.option.finish_on_key.as_deref() == Some(digit) {
+            return GatherStep::Done(Gathered::Dtmf {
+                digits: std::mem::take(&mut self.digits),
+                terminator: Some(digit.to_string()),
+            });
+        }
+        self.digits.push_str(digit);
+        if self
+            .option
+            .max_digits
+            .is_some_and(|max| self.digits.len() >= max)
+        {
+            return GatherStep::Done(Gathered::Dtmf {
+                digits: std::mem::take(&mut self.digits),
+                terminator: None,
+            });
+        }
+        self.deadline = Some(now + self.option.inter_digit_timeout as u64);
+        barge_in.unwrap_or(GatherStep::Wait)
+    }
+
+    /// Voice activity or a partial transcript, the final one is awaited
+    pub fn on_speech_start(&mut self, now: u64) -> GatherStep {
+        if !self.option.speech || !self.digits.is_empty() {
+            return GatherStep::Wait;
+        }
+        let barge_in = match self.accepts_input() {
+            Some(GatherStep::Wait) => return GatherStep::Wait,
+            barge_in => barge_in,
+        };
+        self.deadline = Some(now + self.option.inter_digit_timeout as u64);
+        barge_in.unwrap_or(GatherStep::Wait)
+    }
+
+    pub fn on_speech(&mut self, text: &str, now: u64) -> GatherStep {
+        if !self.option.speech || !self.digits.is_empty() || text.trim().is_empty() {
+            return GatherStep::Wait;
+        }
+        if self.prompting {
+            // a transcript without a partial before it
+            match self.on_speech_start(now) {
+                GatherStep::Wait if self.prompting => return GatherStep::Wait,
+                _ => {}
+            }
+        }
+        let text = text.trim().to_string();
+        let interpretation = grammar::interpret(&self.option.grammars, &text, self.language);
+        if interpretation.is_none() && !self.option.grammars.is_empty() {
+            return GatherStep::Done(Gathered::NoMatch { text });
+        }
+        GatherStep::Done(Gathered::Speech {
+            hint: match_hint(&self.option.hints, &text),
+            text,
+            interpretation,
+        })
+    }
+
+    pub fn poll(&mut self, now: u64) -> GatherStep {
+        match self.deadline {
+            Some(deadline) if now >= deadline => {}
+            _ => return GatherStep::Wait,
+        }
+        if self.digits.is_empty() {
+            return GatherStep::Done(Gathered::Timeout);
+        }
+        GatherStep::Done(Gathered::Dtmf {
+            digits: std::mem::take(&mut self.digits),
+            terminator: None,
+        })
+    }
+}
+
+/// The first hint found in the transcript, ignoring case and punctuation
+pub fn match_hint(hints: &[String], text: &str) -> Option<String> {
+    let text = format!(" {} ", normalize(text));
+    hints
+        .iter()
+        .find(|hint| {
+            let hint = normalize(hint);
+            !hint.is_empty()
+                && (text.contains(&format!(" {} ", hint))
+                    || (!hint.is_ascii() && text.contains(&hint)))
+        })
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gather_dtmf() {
+        let mut gather = Gather::new(
+            GatherOption {
+                max_digits: Some(4),
+                ..Default::default()
+            },
+            None,
+        );
+        assert_eq!(gather.on_dtmf("1", 0), GatherStep::BargeIn);
+        assert!(!gather.prompting());
+        // speech after the first digit is ignored
+        assert_eq!(gather.on_speech("sales", 10), GatherStep::Wait);
+        assert_eq!(gather.on_dtmf("2", 20), GatherStep::Wait);
+        assert_eq!(
+            gather.on_dtmf("#", 30),
+            GatherStep::Done(Gathered::Dtmf {
+                digits: "12".to_string(),
+                terminator: Some("#".to_string()),
+            })
+        );
+
+        let mut gather = Gather::new(
+            GatherOption {
+                max_digits: Some(2),
+                ..Default::default()
+            },
+            None,
+        );
+        gather.prompt_done(0);
+        assert_eq!(gather.on_dtmf("4", 100), GatherStep::Wait);
+        assert_eq!(gather.poll(3099), GatherStep::Wait);
+        assert_eq!(
+            gather.on_dtmf("2", 3000),
+            GatherStep::Done(Gathered::Dtmf {
+                digits: "42".to_string(),
+                terminator: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_gather_timeouts() {
+        let mut gather = Gather::new(GatherOption::default(), None);
+        // the input timeout starts with the end of the prompt
+        assert_eq!(gather.poll(10_000), GatherStep::Wait);
+        gather.prompt_done(10_000);
+        assert_eq!(gather.poll(14_999), GatherStep::Wait);
+        assert_eq!(gather.poll(15_000), GatherStep::Done(Gathered::Timeout));
+
+        let mut gather = Gather::new(GatherOption::default(), None);
+        gather.prompt_done(0);
+        gather.on_dtmf("7", 1000);
+        assert_eq!(
+            gather.poll(4000),
+            GatherStep::Done(Gathered::Dtmf {
+                digits: "7".to_string(),
+                terminator: None,
+            })
+        );
+    }
+
+    #[test]
+    fn test_gather_speech() {
+        let mut gather = Gather::new(
+            GatherOption {
+                hints: vec!["sales".to_string(), "tech support".to_string()],
+                ..Default::default()
+            },
+            None,
+        );
+        assert_eq!(gather.on_speech_start(0), GatherStep::BargeIn);
+        assert_eq!(
+            gather.on_speech(" I need Tech-Support, please. ", 500),
+            GatherStep::Done(Gathered::Speech {
+                text: "I need Tech-Support, please.".to_string(),
+                hint: Some("tech support".to_string()),
+                interpretation: None,
+            })
+        );
+
+        // without barge-in, input during the prompt is ignored
+        let mut gather = Gather::new(
+            GatherOption {
+                barge_in: false,
+                ..Default::default()
+            },
+            None,
+        );
+        assert_eq!(gather.on_dtmf("1", 0), GatherStep::Wait);
+        assert_eq!(gather.on_speech("sales", 0), GatherStep::Wait);
+        gather.prompt_done(100);
+        assert!(matches!(
+            gather.on_speech("sales", 200),
+            GatherStep::Done(Gathered::Speech { .. })
+        ));
+
+        assert_eq!(
+            match_hint(&["销售".to_string()], "我要找销售部"),
+            Some("销售".to_string())
+        );
+        assert_eq!(match_hint(&["sale".to_string()], "sales"), None);
+    }
+
+    #[test]
+    fn test_gather_grammars() {
+        let option = GatherOption {
+            grammars: vec![Grammar::YesNo],
+            ..Default::default()
+        };
+        let mut gather = Gather::new(option.clone(), None).with_language(Language::Chinese);
+        gather.prompt_done(0);
+        match gather.on_speech("对的", 100) {
+            GatherStep::Done(Gathered::Speech {
+                interpretation: Some(interpretation),
+                ..
+            }) => assert_eq!(interpretation.value, "yes"),
+            step => panic!("unexpected {:?}", step),
+        }
+        let mut gather = Gather::new(option, None);
+        gather.prompt_done(0);
+        assert_eq!(
+            gather.on_speech("maybe later", 100),
+            GatherStep::Done(Gathered::NoMatch {
+                text: "maybe later".to_string()
+            })
+        );
+    }
+}
@@ -0,0 +1,834 @@
+use crate::synthesis::prompt::{EN_MONTHS, EN_ONES, EN_TENS, Language, ZH_DIGITS, en_ordinal};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+
+const DEFAULT_THRESHOLD: f32 = 0.8;
+const EN_YES: [&str; 12] = [
+    "yes",
+    "yeah",
+    "yep",
+    "yup",
+    "sure",
+    "correct",
+    "right",
+    "ok",
+    "okay",
+    "affirmative",
+    "true",
+    "absolutely",
+];
+const EN_NO: [&str; 8] = [
+    "no",
+    "nope",
+    "nah",
+    "not",
+    "incorrect",
+    "wrong",
+    "negative",
+    "false",
+];
+const ZH_YES: [&str; 8] = ["是", "对", "好", "可以", "行", "嗯", "没错", "确认"];
+const ZH_NO: [&str; 8] = ["不是", "不对", "不要", "不用", "不行", "没有", "不", "否"];
+const EN_MAJOR_UNITS: [&str; 10] = [
+    "dollar", "dollars", "buck", "bucks", "euro", "euros", "pound", "pounds", "yuan", "rmb",
+];
+const EN_MINOR_UNITS: [&str; 4] = ["cent", "cents", "penny", "pence"];
+
+/// What a transcript has to say for a gather to take it
+#[skip_serializing_none]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum Grammar {
+    /// A number said digit by digit or as a whole, e.g. an account number
+    #[serde(rename_all = "camelCase")]
+    Digits {
+        min_length: Option<usize>,
+        max_length: Option<usize>,
+    },
+    /// yes or no
+    YesNo,
+    /// An amount, as `12.50`
+    Currency,
+    /// A date, as `YYYY-MM-DD`, with `????` for a year not said
+    Date,
+    /// One of the choices, by the closest of its phrases
+    #[serde(rename_all = "camelCase")]
+    Phrases {
+        name: Option<String>,
+        choices: Vec<GrammarChoice>,
+        /// Minimum confidence (0.0 - 1.0), 0.8 by default
+        threshold: Option<f32>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrammarChoice {
+    /// Reported when the choice matches
+    pub value: String,
+    /// Ways of saying it, the value itself when empty
+    #[serde(default)]
+    pub phrases: Vec<String>,
+}
+
+/// What the transcript means to the grammar that took it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interpretation {
+    pub grammar: String,
+    pub value: String,
+    pub confidence: f32,
+}
+
+impl Grammar {
+    pub fn name(&self) -> &str {
+        match self {
+            Grammar::Digits { .. } => "digits",
+            Grammar::YesNo => "yesNo",
+            Grammar::Currency => "currency",
+            Grammar::Date => "date",
+            Grammar::Phrases { name, .. } => name.as_deref().unwrap_or("phrases"),
+        }
+    }
+
+    pub fn interpret(&self, text: &str, language: Language) -> Option<Interpretation> {
+        let (value, confidence) = match self {
+            Grammar::Digits {
+                min_length,
+                max_length,
+            } => {
+                let digits = digits(text, language)?;
+                if min_length.is_some_and(|min| digits.len() < min)
+                    || max_length.is_some_and(|max| digits.len() > max)
+                {
+                    return None;
+                }
+                (digits, 1.0)
+            }
+            Grammar::YesNo => (yes_no(text, language)?.to_string(), 1.0),
+            Grammar::Currency => (currency(text, language)?, 1.0),
+            Grammar::Date => (date(text, language)?, 1.0),
+            Grammar::Phrases {
+                choices, threshold, ..
+            } => {
+                let (value, confidence) = choose(choices, text)?;
+                if confidence < threshold.unwrap_or(DEFAULT_THRESHOLD) {
+                    return None;
+                }
+                (value, confidence)
+            }
+        };
+        Some(Interpretation {
+            grammar: self.name().to_string(),
+            value,
+            confidence,
+        })
+    }
+}
+
+/// The first grammar, in order, that takes the transcript
+pub fn interpret(grammars: &[Grammar], text: &str, language: Language) -> Option<Interpretation> {
+    grammars
+        .iter()
+        .find_map(|grammar| grammar.interpret(text, language))
+}
+
+/// Lowercase words, with punctuation as spaces
+pub fn normalize(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .flat_map(char::to_lowercase)
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Lowercase words, keeping decimal points and dropping thousands
+/// separators between digits
+fn words(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut cleaned = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        let between_digits = i > 0
+            && chars[i - 1].is_ascii_digit()
+            && chars.get(i + 1).is_some_and(|c| c.is_ascii_digit());
+        match c {
+            ',' if between_digits => {}
+            '.' if between_digits => cleaned.push('.'),
+            c if c.is_alphanumeric() => cleaned.extend(c.to_lowercase()),
+            _ => cleaned.push(' '),
+        }
+    }
+    cleaned.split_whitespace().map(str::to_string).collect()
+}
+
+fn is_numeral(word: &str) -> bool {
+    let mut parts = word.split('.');
+    let integer = parts.next().unwrap_or_default();
+    !integer.is_empty()
+        && integer.chars().all(|c| c.is_ascii_digit())
+        && parts.all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+        && word.matches('.').count() <= 1
+}
+
+/// A number in a transcript, at words (or chars for Chinese) `start..end`
+#[derive(Debug, Clone, PartialEq)]
+struct Number {
+    text: String,
+    start: usize,
+    end: usize,
+}
+
+impl Number {
+    fn value(&self) -> Option<u64> {
+        self.text.parse().ok()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Ones,
+    Teen,
+    Tens,
+    Hundred,
+    Scale,
+}
+
+fn en_word(word: &str) -> Option<(u64, Kind)> {
+    if word == "oh" {
+        return Some((0, Kind::Ones));
+    }
+    if let Some(n) = EN_ONES.iter().position(|ones| *ones == word) {
+        let kind = if n < 10 { Kind::Ones } else { Kind::Teen };
+        return Some((n as u64, kind));
+    }
+    if let Some(n) = EN_TENS
+        .iter()
+        .position(|tens| !tens.is_empty() && *tens == word)
+    {
+        return Some((n as u64 * 10, Kind::Tens));
+    }
+    match word {
+        "hundred" => Some((100, Kind::Hundred)),
+        "thousand" => Some((1_000, Kind::Scale)),
+        "million" => Some((1_000_000, Kind::Scale)),
+        _ => None,
+    }
+}
+
+/// Numbers in words or digits. "one two" are two numbers, "twenty one" is
+/// one, and so is "one hundred and five"
+fn en_numbers(words: &[String]) -> Vec<Number> {
+    // total, current, start and kind of the last word of the number being read
+    let mut pending: Option<(u64, u64, usize, Kind)> = None;
+    let mut numbers = Vec::new();
+    let flush = |pending: &mut Option<(u64, u64, usize, Kind)>, numbers: &mut Vec<Number>, end| {
+        if let Some((total, current, start, _)) = pending.take() {
+            numbers.push(Number {
+                text: (total + current).to_string(),
+                start,
+                end,
+            });
+        }
+    };
+    let mut i = 0;
+    while i < words.len() {
+        let word = words[i].as_str();
+        if let Some((value, kind)) = en_word(word) {
+            let joins = match (pending, kind) {
+                (None, _) => false,
+                (Some((.., last)), Kind::Ones) => {
+                    matches!(last, Kind::Tens | Kind::Hundred | Kind::Scale)
+                }
+                (Some((.., last)), Kind::Teen | Kind::Tens) => {
+                    matches!(last, Kind::Hundred | Kind::Scale)
+                }
+                (Some(_), Kind::Hundred | Kind::Scale) => true,
+            };
+            if !joins {
+                flush(&mut pending, &mut numbers, i);
+            }
+            let (total, current, start, _) = pending.unwrap_or((0, 0, i, kind));
+            pending = Some(match kind {
+                Kind::Hundred => (total, current.max(1) * 100, start, kind),
+                Kind::Scale => (total + current.max(1) * value, 0, start, kind),
+                _ => (total, current + value, start, kind),
+            });
+            i += 1;
+            continue;
+        }
+        let next_is_number = words.get(i + 1).is_some_and(|next| en_word(next).is_some());
+        if word == "and" && pending.is_some() && next_is_number {
+            i += 1;
+            continue;
+        }
+        flush(&mut pending, &mut numbers, i);
+        if word == "point" && numbers.last().is_some_and(|last| last.end == i) {
+            let decimals: String = words[i + 1..]
+                .iter()
+                .map_while(|word| match en_word(word) {
+                    Some((digit, Kind::Ones)) => Some(digit.to_string()),
+                    _ => None,
+                })
+                .collect();
+            if let Some(last) = numbers.last_mut()
+                && !decimals.is_empty()
+                && !last.text.contains('.')
+            {
+                last.text = format!("{}.{}", last.text, decimals);
+                last.end = i + 1 + decimals.len();
+                i = last.end;
+                continue;
+            }
+        }
+        if is_numeral(word) {
+            numbers.push(Number {
+                text: word.to_string(),
+                start: i,
+                end: i + 1,
+            });
+        }
+        i += 1;
+    }
+    flush(&mut pending, &mut numbers, words.len());
+    numbers
+}
+
+/// first, twenty (first), 21st
+fn en_ordinal_value(word: &str) -> Option<u64> {
+    if let Some(n) = ["st", "nd", "rd", "th"]
+        .iter()
+        .find_map(|suffix| word.strip_suffix(suffix))
+        .and_then(|n| n.parse().ok())
+    {
+        return Some(n);
+    }
+    (1..20)
+        .find(|&n| en_ordinal(EN_ONES[n]) == word)
+        .map(|n| n as u64)
+        .or_else(|| {
+            (2..10)
+                .find(|&n| en_ordinal(EN_TENS[n]) == word)
+                .map(|n| n as u64 * 10)
+        })
+}
+
+fn zh_digit(c: char) -> Option<u64> {
+    match c {
+        '〇' => Some(0),
+        '幺' => Some(1),
+        '两' => Some(2),
+        _ => ZH_DIGITS
+            .iter()
+            .position(|digit| *digit == c)
+            .map(|digit| digit as u64)
+            .or_else(|| c.to_digit(10).map(u64::from)),
+    }
+}
+
+fn zh_unit(c: char) -> Option<u64> {
+    match c {
+        '十' => Some(10),
+        '百' => Some(100),
+        '千' => Some(1_000),
+        '万' => Some(10_000),
+        '亿' => Some(100_000_000),
+        _ => None,
+    }
+}
+
+/// 二零二四 digit by digit, 一千零五十 by its units
+fn zh_integer(run: &str) -> String {
+    if !run.chars().any(|c| zh_unit(c).is_some()) {
+        return run
+            .chars()
+            .filter_map(zh_digit)
+            .map(|d| d.to_string())
+            .collect();
+    }
+    let (mut total, mut section, mut number) = (0u64, 0u64, 0u64);
+    for c in run.chars() {
+        if let Some(digit) = zh_digit(c) {
+            number = digit;
+            continue;
+        }
+        match zh_unit(c) {
+            Some(100_000_000) => {
+                total = (total + section + number).saturating_mul(100_000_000);
+                (section, number) = (0, 0);
+            }
+            Some(10_000) => {
+                total = total.saturating_add((section + number).saturating_mul(10_000));
+                (section, number) = (0, 0);
+            }
+            Some(unit) => {
+                section = section.saturating_add(number.max(1) * unit);
+                number = 0;
+            }
+            None => {}
+        }
+    }
+    (total + section + number).to_string()
+}
+
+/// Numbers in Chinese or Arabic digits, at chars `start..end`
+fn zh_numbers(text: &str) -> Vec<Number> {
+    let chars: Vec<char> = text.chars().collect();
+    let is_number = |c: char| zh_digit(c).is_some() || zh_unit(c).is_some();
+    let mut numbers = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if !is_number(chars[i]) {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len()
+            && (is_number(chars[i])
+                || (matches!(chars[i], '点' | '.')
+                    && chars.get(i + 1).is_some_and(|c| zh_digit(*c).is_some())))
+        {
+            i += 1;
+        }
+        let run: String = chars[start..i].iter().collect();
+        let text = match run.split_once(['点', '.']) {
+            Some((integer, decimals)) => {
+                let decimals: String = decimals
+                    .chars()
+                    .filter_map(zh_digit)
+                    .map(|d| d.to_string())
+                    .collect();
+                format!("{}.{}", zh_integer(integer), decimals)
+            }
+            None => zh_integer(&run),
+        };
+        numbers.push(Number {
+            text,
+            start,
+            end: i,
+        });
+    }
+    numbers
+}
+
+fn numbers(text: &str, language: Language) -> Vec<Number> {
+    match language {
+        Language::English => en_numbers(&words(text)),
+        Language::Chinese => zh_numbers(text),
+    }
+}
+
+fn digits(text: &str, language: Language) -> Option<String> {
+    let numbers = numbers(text, language);
+    if numbers.is_empty() || numbers.iter().any(|number| number.text.contains('.')) {
+        return None;
+    }
+    Some(numbers.iter().map(|number| number.text.as_str()).collect())
+}
+
+/// "not right" is a no, both a yes and a no is neither
+fn yes_no(text: &str, language: Language) -> Option<&'static str> {
+    let (yes, no) = match language {
+        Language::English => {
+            let words = words(text);
+            let (mut yes, mut no) = (false, false);
+            let mut i = 0;
+            while i < words.len() {
+                if EN_NO.contains(&words[i].as_str()) {
+                    no = true;
+                    if words
+                        .get(i + 1)
+                        .is_some_and(|next| EN_YES.contains(&next.as_str()))
+                    {
+                        i += 1;
+                    }
+                } else if EN_YES.contains(&words[i].as_str()) {
+                    yes = true;
+                }
+                i += 1;
+            }
+            (yes, no)
+        }
+        Language::Chinese => {
+            let text = text.replace("没错", "对").replace("不错", "好");
+            let no = ZH_NO.iter().any(|no| text.contains(no));
+            let rest = ZH_NO.iter().fold(text, |text, no| text.replace(no, ""));
+            (ZH_YES.iter().any(|yes| rest.contains(yes)), no)
+        }
+    };
+    match (yes, no) {
+        (true, false) => Some("yes"),
+        (false, true) => Some("no"),
+        _ => None,
+    }
+}
+
+/// `major.minor` with two decimals, from "12.5" or "12" and "5"
+fn amount(major: Option<&str>, minor: Option<u64>) -> Option<String> {
+    let (whole, decimals) = match major {
+        Some(major) => major.split_once('.').unwrap_or((major, "")),
+        None => ("0", ""),
+    };
+    let whole: u64 = whole.parse().ok()?;
+    let cents = match (decimals, minor) {
+        ("", Some(minor)) if minor < 100 => minor,
+        ("", None) => 0,
+        (decimals, None) if decimals.len() <= 2 => format!("{:0<2}", decimals).parse().ok()?,
+        _ => return None,
+    };
+    Some(format!("{}.{:02}", whole, cents))
+}
+
+fn currency(text: &str, language: Language) -> Option<String> {
+    match language {
+        Language::English => {
+            let words = words(text);
+            let numbers = en_numbers(&words);
+            let unit = |number: &Number| words.get(number.end).map(String::as_str);
+            let major = numbers
+                .iter()
+                .find(|n| unit(n).is_some_and(|unit| EN_MAJOR_UNITS.contains(&unit)));
+            let minor = numbers
+                .iter()
+                .find(|n| unit(n).is_some_and(|unit| EN_MINOR_UNITS.contains(&unit)));
+            let (major, minor) = match (major, minor) {
+                (None, None) => match numbers.as_slice() {
+                    [amount] => (Some(amount), None),
+                    // twelve fifty
+                    [major, minor] if minor.start == major.end => (Some(major), Some(minor)),
+                    _ => return None,
+                },
+                // twelve dollars fifty
+                (Some(major), None) => (
+                    Some(major),
+                    numbers.iter().find(|n| n.start == major.end + 1),
+                ),
+                pair => pair,
+            };
+            let minor = match minor {
+                Some(minor) => Some(minor.value()?),
+                None => None,
+            };
+            amount(major.map(|n| n.text.as_str()), minor)
+        }
+        Language::Chinese => {
+            let chars: Vec<char> = text.chars().collect();
+            let numbers = zh_numbers(text);
+            let unit = |number: &Number| chars.get(number.end).copied();
+            let (mut major, mut jiao, mut fen) = (None, None, None);
+            for number in &numbers {
+                match unit(number) {
+                    Some('元' | '块') => major = Some(number),
+                    Some('角' | '毛') => jiao = number.value(),
+                    Some('分') => fen = number.value(),
+                    // 十二块五
+                    _ if major.is_some_and(|major| number.start == major.end + 1) => {
+                        jiao = number.value()
+                    }
+                    _ => {}
+                }
+            }
+            if major.is_none() && jiao.is_none() && fen.is_none() {
+                match numbers.as_slice() {
+                    [amount] => major = Some(amount),
+                    _ => return None,
+                }
+            }
+            let minor = match (jiao, fen) {
+                (None, None) => None,
+                (jiao, fen) if jiao.unwrap_or(0) < 10 && fen.unwrap_or(0) < 10 => {
+                    Some(jiao.unwrap_or(0) * 10 + fen.unwrap_or(0))
+                }
+                _ => return None,
+            };
+            amount(major.map(|n| n.text.as_str()), minor)
+        }
+    }
+}
+
+/// 2024 as is, "twenty twenty four" and "nineteen ninety" as two numbers
+fn year(numbers: &[&Number]) -> Option<u64> {
+    match numbers {
+        [year, ..] if year.value()? >= 1000 => year.value(),
+        [century, rest, ..] if rest.start == century.end => {
+            let (century, rest) = (century.value()?, rest.value()?);
+            ((10..100).contains(&century) && rest < 100).then_some(century * 100 + rest)
+        }
+        _ => None,
+    }
+}
+
+fn en_date(text: &str) -> Option<(Option<u64>, u64, u64)> {
+    let words = words(text);
+    let numbers = en_numbers(&words);
+    let is_month = |word: &str, month: &str| {
+        let month = month.to_lowercase();
+        word == month || (word.len() >= 3 && month.starts_with(word))
+    };
+    let Some((at, month)) = words.iter().enumerate().find_map(|(i, word)| {
+        EN_MONTHS
+            .iter()
+            .position(|month| is_month(word, month))
+            .map(|month| (i, month as u64 + 1))
+    }) else {
+        // 2024-03-05 or 3/5/2024
+        let numerals: Vec<u64> = words.iter().filter_map(|word| word.parse().ok()).collect();
+        return match numerals.as_slice() {
+            [year, month, day] if *year >= 1000 => Some((Some(*year), *month, *day)),
+            [month, day, year] if *year >= 1000 => Some((Some(*year), *month, *day)),
+            _ => None,
+        };
+    };
+    // the day right after the month, or before it as in "the fifth of March"
+    let day_at = |i: usize| -> Option<(u64, usize)> {
+        let word = words.get(i)?;
+        if let Some(day) = en_ordinal_value(word) {
+            return Some((day, i + 1));
+        }
+        if let (Some((tens, Kind::Tens)), Some(ones)) = (
+            en_word(word),
+            words.get(i + 1).and_then(|next| en_ordinal_value(next)),
+        ) && ones < 10
+        {
+            return Some((tens + ones, i + 2));
+        }
+        let number = numbers.iter().find(|number| number.start == i)?;
+        Some((number.value()?, number.end))
+    };
+    let after = at + 1 + usize::from(words.get(at + 1).is_some_and(|w| w == "the"));
+    let (day, year_from) = match day_at(after) {
+        Some((day, end)) => (day, end),
+        None => {
+            let of = at.checked_sub(1).filter(|&i| words[i] == "of")?;
+            let day = (of.saturating_sub(2)..of)
+                .filter_map(day_at)
+                .find(|(_, end)| *end == of)?;
+            (day.0, at + 1)
+        }
+    };
+    let rest: Vec<&Number> = numbers.iter().filter(|n| n.start >= year_from).collect();
+    Some((year(&rest), month, day))
+}
+
+fn zh_date(text: &str) -> Option<(Option<u64>, u64, u64)> {
+    let chars: Vec<char> = text.chars().collect();
+    let numbers = zh_numbers(text);
+    let before = |marker: &[char]| {
+        numbers
+            .iter()
+            .find(|n| chars.get(n.end).is_some_and(|c| marker.contains(c)))
+    };
+    let Some(month) = before(&['月']) else {
+        return en_date(text);
+    };
+    let day = numbers
+        .iter()
+        .find(|n| n.start == month.end + 1)
+        .and_then(Number::value)?;
+    let year = before(&['年'])
+        .and_then(Number::value)
+        .filter(|year| *year >= 1000);
+    Some((year, month.value()?, day))
+}
+
+fn date(text: &str, language: Language) -> Option<String> {
+    let (year, month, day) = match language {
+        Language::English => en_date(text)?,
+        Language::Chinese => zh_date(text)?,
+    };
+    // a leap year checks February 29th without a year
+    NaiveDate::from_ymd_opt(year.unwrap_or(2024) as i32, month as u32, day as u32)?;
+    Some(match year {
+        Some(year) => format!("{:04}-{:02}-{:02}", year, month, day),
+        None => format!("????-{:02}-{:02}", month, day),
+    })
+}
+
+fn similarity(a: &[char], b: &[char]) -> f32 {
+    let len = a.len().max(b.len());
+    if len == 0 {
+        return 1.0;
+    }
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    1.0 - row[b.len()] as f32 / len as f32
+}
+
+/// How closely some part of the transcript says the phrase, 1.0 when it is
+/// said word for word
+fn phrase_confidence(text: &str, phrase: &str) -> f32 {
+    let phrase = normalize(phrase);
+    if phrase.is_empty() {
+        return 0.0;
+    }
+    let windows: Vec<Vec<char>> = if phrase.is_ascii() {
+        let words: Vec<&str> = text.split(' ').collect();
+        let len = phrase.split(' ').count().min(words.len());
+        if len == 0 {
+            return 0.0;
+        }
+        words
+            .windows(len)
+            .map(|window| window.join(" ").chars().collect())
+            .collect()
+    } else {
+        let chars: Vec<char> = text.chars().filter(|c| *c != ' ').collect();
+        let len = phrase
+            .chars()
+            .filter(|c| *c != ' ')
+            .count()
+            .min(chars.len());
+        if len == 0 {
+            return 0.0;
+        }
+        chars.windows(len).map(<[char]>::to_vec).collect()
+    };
+    let phrase: Vec<char> = phrase
+        .chars()
+        .filter(|c| phrase.is_ascii() || *c != ' ')
+        .collect();
+    windows
+        .iter()
+        .map(|window| similarity(window, &phrase))
+        .fold(0.0, f32::max)
+}
+
+/// The choice with the closest phrase, with its confidence
+fn choose(choices: &[GrammarChoice], text: &str) -> Option<(String, f32)> {
+    let text = normalize(text);
+    let mut best: Option<(&GrammarChoice, f32)> = None;
+    for choice in choices {
+        let confidence = match choice.phrases.is_empty() {
+            true => phrase_confidence(&text, &choice.value),
+            false => choice
+                .phrases
+                .iter()
+                .map(|phrase| phrase_confidence(&text, phrase))
+                .fold(0.0, f32::max),
+        };
+        if best.is_none_or(|(_, best)| confidence > best) {
+            best = Some((choice, confidence));
+        }
+    }
+    best.map(|(choice, confidence)| (choice.value.clone(), confidence))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(grammar: &Grammar, text: &str, language: Language) -> Option<String> {
+        grammar.interpret(text, language).map(|i| i.value)
+    }
+
+    #[test]
+    fn test_builtin_grammars() {
+        use Language::{Chinese, English};
+        let digits = Grammar::Digits {
+            min_length: Some(3),
+            max_length: Some(6),
+        };
+        let cases = [
+            (&digits, "one two three four", English, Some("1234")),
+            (&digits, "my pin is 12 oh 5", English, Some("1205")),
+            (&digits, "twenty one hundred", English, Some("2100")),
+            (&digits, "one two", English, None),
+            (&digits, "幺二三四", Chinese, Some("1234")),
+            (&digits, "一百零五", Chinese, Some("105")),
+            (&Grammar::YesNo, "Yes, that's right.", English, Some("yes")),
+            (
+                &Grammar::YesNo,
+                "no that's not correct",
+                English,
+                Some("no"),
+            ),
+            (&Grammar::YesNo, "yes no", English, None),
+            (&Grammar::YesNo, "没错", Chinese, Some("yes")),
+            (&Grammar::YesNo, "不是的", Chinese, Some("no")),
+            (
+                &Grammar::Currency,
+                "twelve dollars and fifty cents",
+                English,
+                Some("12.50"),
+            ),
+            (&Grammar::Currency, "$1,250.5", English, Some("1250.50")),
+            (&Grammar::Currency, "twelve fifty", English, Some("12.50")),
+            (
+                &Grammar::Currency,
+                "twelve point five",
+                English,
+                Some("12.50"),
+            ),
+            (&Grammar::Currency, "十二块五", Chinese, Some("12.50")),
+            (&Grammar::Currency, "一百元零五分", Chinese, Some("100.05")),
+            (
+                &Grammar::Date,
+                "March fifth twenty twenty four",
+                English,
+                Some("2024-03-05"),
+            ),
+            (
+                &Grammar::Date,
+                "the twenty first of June",
+                English,
+                Some("????-06-21"),
+            ),
+            (&Grammar::Date, "on Feb 29 2023", English, None),
+            (&Grammar::Date, "2024-03-05", English, Some("2024-03-05")),
+            (
+                &Grammar::Date,
+                "二零二四年三月五号",
+                Chinese,
+                Some("2024-03-05"),
+            ),
+            (&Grammar::Date, "12月31日", Chinese, Some("????-12-31")),
+        ];
+        for (grammar, text, language, expected) in cases {
+            assert_eq!(
+                value(grammar, text, language).as_deref(),
+                expected,
+                "{:?} {}",
+                grammar,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn test_phrases_grammar() {
+        let grammar: Grammar = serde_json::from_str(
+            r#"{"type": "phrases", "name": "department", "threshold": 0.75, "choices": [
+                {"value": "sales", "phrases": ["sales", "buy something"]},
+                {"value": "support", "phrases": ["tech support", "help"]},
+                {"value": "billing", "phrases": ["账单"]}
+            ]}"#,
+        )
+        .expect("grammar");
+        let interpretation = grammar
+            .interpret("I need tech sport please", Language::English)
+            .expect("close enough");
+        assert_eq!(interpretation.grammar, "department");
+        assert_eq!(interpretation.value, "support");
+        assert!((0.75..1.0).contains(&interpretation.confidence));
+
+        let interpretation = grammar
+            .interpret("我想查一下账单", Language::Chinese)
+            .expect("contained");
+        assert_eq!(interpretation.value, "billing");
+        assert_eq!(interpretation.confidence, 1.0);
+        assert!(grammar.interpret("weather", Language::English).is_none());
+
+        let grammars = [Grammar::YesNo, grammar];
+        let interpretation = interpret(&grammars, "yes, sales", Language::English).expect("yes");
+        assert_eq!(interpretation.grammar, "yesNo");
+    }
+}
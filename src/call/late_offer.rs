@@ -0,0 +1,251 @@
+//! Late offers (RFC 3261 13.2.1, RFC 3725): an INVITE without SDP gets the
+//! offer in its 2xx and the answer in the ACK.
+//!
+//! The transaction layer sends the ACK of a 2xx as soon as the 2xx arrives,
+//! so an INVITE rustpbx sends without SDP has the offer of its 2xx answered
+//! from the cached [`SdpCapabilities`] of its track, as the ACK goes out.
+//! The answer to an offer rustpbx puts in the 2xx of an INVITE it received
+//! without SDP is taken from the ACK before the dialog drops its body.
+use crate::media::negotiate::SdpCapabilities;
+use rsip::{
+    Header, SipMessage, StatusCodeKind,
+    prelude::{HeadersExt, UntypedHeader},
+};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// Calls waiting for an offer or an answer, kept to bound memory
+const MAX_TRACKED_CALLS: usize = 4096;
+
+enum Pending {
+    /// The INVITE went out without SDP, the offer of its 2xx is answered
+    /// in the ACK
+    Offer {
+        capabilities: Box<SdpCapabilities>,
+        answer: Option<String>,
+    },
+    /// The 2xx to an INVITE received without SDP carries the offer, the
+    /// answer comes in the ACK
+    Answer(Option<String>),
+}
+
+struct Entry {
+    pending: Pending,
+    at: Instant,
+}
+
+/// The calls with a late offer in progress, by Call-ID
+#[derive(Clone)]
+pub struct LateOffers {
+    lifetime: Duration,
+    calls: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl LateOffers {
+    /// Keeps each call for `lifetime`, 64*T1, the longest an ACK can take
+    pub fn new(lifetime: Duration) -> Self {
+        Self {
+            lifetime,
+            calls: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn insert(&self, call_id: String, pending: Pending) {
+        let Ok(mut calls) = self.calls.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if calls.len() >= MAX_TRACKED_CALLS {
+            calls.retain(|_, e| now.duration_since(e.at) < self.lifetime);
+        }
+        calls.insert(call_id, Entry { pending, at: now });
+    }
+
+    /// The INVITE of `call_id` goes out without SDP, the offer of its 2xx
+    /// is to be answered from `capabilities`
+    pub fn expect_offer(&self, call_id: String, capabilities: SdpCapabilities) {
+        self.insert(
+            call_id,
+            Pending::Offer {
+                capabilities: Box::new(capabilities),
+                answer: None,
+            },
+        );
+    }
+
+    /// The 2xx of `call_id` carries the offer, its ACK the answer
+    pub fn expect_answer(&self, call_id: String) {
+        self.insert(call_id, Pending::Answer(None));
+    }
+
+    /// The answer the ACK of `call_id` carried, once it arrived
+    pub fn take_answer(&self, call_id: &str) -> Option<String> {
+        let mut calls = self.calls.lock().ok()?;
+        match calls.remove(call_id)?.pending {
+            Pending::Answer(answer) => answer,
+            Pending::Offer { .. } => None,
+        }
+    }
+
+    pub fn forget(&self, call_id: &str) {
+        if let Ok(mut calls) = self.calls.lock() {
+            calls.remove(call_id);
+        }
+    }
+
+    /// Answers the offer of a 2xx to an INVITE sent without SDP, keeps the
+    /// answer of an ACK to a 2xx that carried the offer
+    pub fn on_received(&self, msg: &SipMessage) {
+        let (call_id, body) = match msg {
+            SipMessage::Response(resp)
+                if resp.status_code.kind() == StatusCodeKind::Successful
+                    && resp
+                        .cseq_header()
+                        .and_then(|cseq| cseq.method())
+                        .is_ok_and(|method| method == rsip::Method::Invite) =>
+            {
+                (resp.call_id_header(), &resp.body)
+            }
+            SipMessage::Request(req) if req.method == rsip::Method::Ack => {
+                (req.call_id_header(), &req.body)
+            }
+            _ => return,
+        };
+        let Ok(call_id) = call_id.map(|h| h.value().to_string()) else {
+            return;
+        };
+        if body.is_empty() {
+            return;
+        }
+        let Ok(mut calls) = self.calls.lock() else {
+            return;
+        };
+        let Some(entry) = calls.get_mut(&call_id) else {
+            return;
+        };
+        let sdp = String::from_utf8_lossy(body);
+        match (&mut entry.pending, msg) {
+            (
+                Pending::Offer {
+                    capabilities,
+                    answer,
+                },
+                SipMessage::Response(_),
+            ) if answer.is_none() => match capabilities.answer(&sdp) {
+                Ok(sdp) => {
+                    info!(call_id, "answering the late offer in the ACK");
+                    answer.replace(sdp);
+                }
+                Err(e) => warn!(call_id, "failed to answer the late offer: {}", e),
+            },
+            (Pending::Answer(answer), SipMessage::Request(_)) => {
+                answer.replace(sdp.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    /// Puts the answer in the ACK to a 2xx that carried a late offer
+    pub fn before_send(&self, msg: SipMessage) -> SipMessage {
+        let mut ack = match msg {
+            SipMessage::Request(req) if req.method == rsip::Method::Ack && req.body.is_empty() => {
+                req
+            }
+            msg => return msg,
+        };
+        let answer = ack.call_id_header().ok().and_then(|call_id| {
+            let calls = self.calls.lock().ok()?;
+            match &calls.get(call_id.value())?.pending {
+                Pending::Offer { answer, .. } => answer.clone(),
+                Pending::Answer(_) => None,
+            }
+        });
+        if let Some(answer) = answer {
+            ack.headers
+                .unique_push(Header::ContentType("application/sdp".to_string().into()));
+            ack.headers
+                .unique_push(Header::ContentLength((answer.len() as u32).into()));
+            ack.body = answer.into_bytes();
+        }
+        ack.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL: &str = "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\n\
+        t=0 0\r\nm=audio 12000 RTP/AVP 0 8\r\na=rtpmap:0 PCMU/8000\r\na=rtpmap:8 PCMA/8000\r\n";
+    const REMOTE: &str = "v=0\r\no=- 1 1 IN IP4 10.0.0.1\r\ns=-\r\nc=IN IP4 10.0.0.1\r\n\
+        t=0 0\r\nm=audio 30000 RTP/AVP 8\r\na=rtpmap:8 PCMA/8000\r\n";
+
+    fn message(start: &str, method: &str, body: &str) -> String {
+        format!(
+            "{start}\r\n\
+            Via: SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK776asdhds\r\n\
+            From: <sip:alice@example.com>;tag=1928301774\r\n\
+            To: <sip:bob@example.com>;tag=a6c85cf\r\n\
+            Call-ID: a84b4c76e66710\r\n\
+            CSeq: 314159 {method}\r\n\
+            Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[test]
+    fn test_answer_in_ack() {
+        let late_offers = LateOffers::new(Duration::from_secs(32));
+        late_offers.expect_offer(
+            "a84b4c76e66710".to_string(),
+            SdpCapabilities::new(LOCAL).unwrap(),
+        );
+        let ok: rsip::Response = message("SIP/2.0 200 OK", "INVITE", REMOTE)
+            .try_into()
+            .unwrap();
+        late_offers.on_received(&ok.into());
+
+        let ack: rsip::Request = message("ACK sip:bob@192.0.2.4 SIP/2.0", "ACK", "")
+            .try_into()
+            .unwrap();
+        let SipMessage::Request(ack) = late_offers.before_send(ack.into()) else {
+            panic!("not a request");
+        };
+        let answer = String::from_utf8(ack.body.clone()).unwrap();
+        assert!(answer.contains("m=audio 12000 RTP/AVP 8"));
+        assert!(!answer.contains("PCMU"));
+        assert!(ack.headers.iter().any(|h| matches!(
+            h,
+            Header::ContentLength(len) if len.value() == answer.len().to_string()
+        )));
+
+        // the ACK of any other call is left alone
+        late_offers.forget("a84b4c76e66710");
+        let ack: rsip::Request = message("ACK sip:bob@192.0.2.4 SIP/2.0", "ACK", "")
+            .try_into()
+            .unwrap();
+        let SipMessage::Request(ack) = late_offers.before_send(ack.into()) else {
+            panic!("not a request");
+        };
+        assert!(ack.body.is_empty());
+    }
+
+    #[test]
+    fn test_answer_from_ack() {
+        let late_offers = LateOffers::new(Duration::from_secs(32));
+        late_offers.expect_answer("a84b4c76e66710".to_string());
+        let ack: rsip::Request = message("ACK sip:bob@192.0.2.4 SIP/2.0", "ACK", REMOTE)
+            .try_into()
+            .unwrap();
+        late_offers.on_received(&ack.into());
+        assert_eq!(
+            late_offers.take_answer("a84b4c76e66710").as_deref(),
+            Some(REMOTE)
+        );
+        assert_eq!(late_offers.take_answer("a84b4c76e66710"), None);
+    }
+}
@@ -1,7 +1,25 @@
 use crate::{
+    call::gather::GatherOption,
     config::RouteResult,
-    media::{recorder::RecorderOption, track::media_pass::MediaPassOption, vad::VADOption},
-    synthesis::SynthesisOption,
+    media::{
+        codecs::resample::ResampleProfile,
+        distortion::DistortionOption,
+        dtmf::InbandDtmfOption,
+        keyword::KeywordOption,
+        language::LanguageOption,
+        leveler::LevelMatchOption,
+        meter::MeterOption,
+        mixer::{DuckingOption, SuperviseMode, TopSpeakersOption},
+        prosody::ProsodyOption,
+        recorder::RecorderOption,
+        shaper::ShapingOption,
+        stream::TrackDirection,
+        track::{
+            audiosocket::AudioSocketOption, media_pass::MediaPassOption, rtp_fork::RtpForkOption,
+        },
+        vad::VADOption,
+    },
+    synthesis::{SynthesisOption, prompt::PromptSegment},
     transcription::TranscriptionOption,
 };
 use anyhow::Result;
@@ -15,15 +33,29 @@ use serde_with::skip_serializing_none;
 use std::{collections::HashMap, time::Instant};
 pub mod active_call;
 pub mod b2bua;
+pub mod bypass;
+pub mod cause;
+pub mod consent;
 pub mod cookie;
+pub mod dns;
+pub mod flow;
+pub mod forking;
+pub mod gather;
+pub mod grammar;
+pub mod late_offer;
+pub mod retransmission;
 pub mod sip;
+pub mod topology;
 pub mod user;
+pub mod variables;
 pub use active_call::ActiveCall;
 pub use active_call::ActiveCallRef;
 pub use active_call::ActiveCallState;
 pub use active_call::ActiveCallType;
+pub use cause::HangupCause;
 pub use cookie::TransactionCookie;
 pub use user::SipUser;
+pub use variables::CallVariables;
 
 pub type CommandSender = tokio::sync::broadcast::Sender<Command>;
 pub type CommandReceiver = tokio::sync::broadcast::Receiver<Command>;
@@ -50,12 +82,43 @@ pub struct CallOption {
     pub asr: Option<TranscriptionOption>,
     pub tts: Option<SynthesisOption>,
     pub media_pass: Option<MediaPassOption>,
+    /// Fork the call's audio to an external address as plain RTP
+    pub rtp_fork: Option<RtpForkOption>,
+    /// Stream the call's audio to an Asterisk AudioSocket server
+    pub audiosocket: Option<AudioSocketOption>,
     pub handshake_timeout: Option<String>,
     pub enable_ipv6: Option<bool>,
     pub sip: Option<SipOption>,
     pub extra: Option<HashMap<String, String>>,
     pub codec: Option<String>, // pcmu, pcma, g722, pcm, only for websocket call
     pub eou: Option<EouOption>,
+    pub keyword: Option<KeywordOption>,
+    pub language: Option<LanguageOption>,
+    pub prosody: Option<ProsodyOption>,
+    /// Report the RMS and peak level of the tracks as `level` events
+    pub meter: Option<MeterOption>,
+    /// Report sustained clipping or DC offset as `distortion` events
+    pub distortion: Option<DistortionOption>,
+    /// Detect DTMF dialed in band, report it as `dtmf` events and mute the
+    /// tones in the forwarded audio
+    pub inband_dtmf: Option<InbandDtmfOption>,
+    /// Duck the live audio while prompts play or a supervisor whispers
+    pub ducking: Option<DuckingOption>,
+    /// Gain the legs towards a common speech level, so a quiet carrier
+    /// sounds as loud as the other side
+    pub level_match: Option<LevelMatchOption>,
+    /// Mix only the loudest of the call's tracks, for large rooms
+    pub top_speakers: Option<TopSpeakersOption>,
+    /// Tags the call with variables, carried into CDRs and hangup events
+    pub variables: Option<HashMap<String, String>>,
+    /// Caps the bandwidth of the call's outgoing RTP, overrides `rtp_shaping`
+    pub shaping: Option<ShapingOption>,
+    /// Send the INVITE without SDP (late offer), the callee offers in its
+    /// 2xx and is answered in the ACK
+    pub late_offer: Option<bool>,
+    /// How the call's audio is resampled between codecs of different rates,
+    /// `latency` or `quality`
+    pub transrating: Option<ResampleProfile>,
 }
 
 impl Default for CallOption {
@@ -70,12 +133,27 @@ impl Default for CallOption {
             vad: None,
             tts: None,
             media_pass: None,
+            rtp_fork: None,
+            audiosocket: None,
             handshake_timeout: None,
             enable_ipv6: None,
             sip: None,
             extra: None,
             codec: None,
             eou: None,
+            keyword: None,
+            language: None,
+            prosody: None,
+            meter: None,
+            distortion: None,
+            inband_dtmf: None,
+            ducking: None,
+            level_match: None,
+            top_speakers: None,
+            variables: None,
+            shaping: None,
+            late_offer: None,
+            transrating: None,
         }
     }
 }
@@ -127,6 +205,8 @@ pub struct ReferOption {
     pub denoise: Option<bool>,
     pub timeout: Option<u32>,
     pub moh: Option<String>,
+    /// The moh url is a live stream, such as an Icecast radio
+    pub moh_stream: Option<bool>,
     pub asr: Option<TranscriptionOption>,
     /// hangup after the call is ended
     pub auto_hangup: Option<bool>,
@@ -190,6 +270,32 @@ pub enum Command {
         url: String,
         auto_hangup: Option<bool>,
         wait_input_timeout: Option<u32>,
+        /// Play an HTTP(S) url while it downloads, for live streams
+        stream: Option<bool>,
+    },
+    /// Play recordings and TTS in turn, with the call variables and
+    /// `variables` filled into their placeholders
+    Prompt {
+        segments: Vec<PromptSegment>,
+        /// Number and date rules, en (default) or zh
+        locale: Option<String>,
+        variables: Option<HashMap<String, String>>,
+        /// Reported by the trackEnd of the last segment
+        play_id: Option<String>,
+        auto_hangup: Option<bool>,
+        option: Option<SynthesisOption>,
+        wait_input_timeout: Option<u32>,
+    },
+    /// Play an optional prompt and collect DTMF or speech, whichever the
+    /// caller starts with, reported by a `gather` event
+    Gather {
+        segments: Option<Vec<PromptSegment>>,
+        locale: Option<String>,
+        variables: Option<HashMap<String, String>>,
+        /// Reported by the gather event
+        play_id: Option<String>,
+        option: Option<SynthesisOption>,
+        input: Option<GatherOption>,
     },
     Interrupt {},
     Pause {},
@@ -206,14 +312,92 @@ pub enum Command {
     },
     Mute {
         track_id: Option<String>,
+        /// rx (default), tx or both
+        direction: Option<TrackDirection>,
     },
     Unmute {
         track_id: Option<String>,
+        direction: Option<TrackDirection>,
+    },
+    /// Echo test: play `prompt`, then loop the caller's audio back after `delay` ms
+    Echo {
+        delay: Option<u32>,
+        prompt: Option<String>,
+    },
+    /// Play a test tone, 1004 Hz at 0 dBm0 (digital milliwatt) by default
+    Milliwatt {
+        frequency: Option<f32>,
+        /// in dBm0
+        level: Option<f32>,
+        /// in ms, plays until interrupted when not set
+        duration: Option<u32>,
+    },
+    /// Measure the latency of a track, its far end must loop the audio back
+    ProbeLatency {
+        track_id: String,
+        /// in ms, default 5000
+        timeout: Option<u32>,
+    },
+    /// Set the gain of a track in dB, 0 restores the original level
+    Gain {
+        track_id: Option<String>,
+        gain: f32,
+        direction: Option<TrackDirection>,
+    },
+    /// Switch how the supervisor's track is mixed: listen, whisper or barge
+    Supervise {
+        track_id: String,
+        /// the agent's track, the only one to hear the supervisor in whisper mode
+        target: String,
+        mode: SuperviseMode,
+    },
+    /// Bring a supervisor into the live call as the track `track_id`, dialed
+    /// as `callee` over SIP or answered from a WebRTC `offer`
+    AttachSupervisor {
+        track_id: String,
+        /// the agent's track, the only one to hear the supervisor in whisper mode
+        target: String,
+        mode: SuperviseMode,
+        callee: Option<String>,
+        offer: Option<String>,
     },
     History {
         speaker: String,
         text: String,
     },
+    /// Set call variables, a null value removes the variable
+    SetVariables {
+        variables: HashMap<String, Option<String>>,
+    },
+    /// Switch the language prompts are played in, e.g. zh-CN
+    SetLocale {
+        locale: String,
+    },
+    /// Record that the IVR flow entered `node`, saved to resume the flow
+    /// after a restart. A null value of `data` removes the key
+    FlowEnter {
+        node: String,
+        /// The input that led to the node, e.g. the digit pressed
+        input: Option<String>,
+        data: Option<HashMap<String, serde_json::Value>>,
+    },
+    /// Continue a saved flow in this call, reported by a `flowResumed` event
+    FlowResume {
+        flow_id: String,
+    },
+    /// Join the flow to an experiment, reported by an `experiment` event.
+    /// The variant is set as the `experiment.<name>` variable, so prompts
+    /// can be named after it
+    FlowExperiment {
+        name: String,
+        /// Variant weights, those of the `[flow.experiments]` config when unset
+        variants: Option<HashMap<String, u32>>,
+    },
+    /// Start recording the call, its media is anchored again first if it
+    /// bypassed rustpbx
+    Record {
+        recorder: Option<RecorderOption>,
+    },
 }
 
 #[async_trait]
@@ -234,6 +418,51 @@ pub struct Location {
     pub supports_webrtc: bool,
     pub credential: Option<Credential>,
     pub headers: Option<Vec<rsip::Header>>,
+    /// `+sip.instance` of the registered device, e.g. `urn:uuid:...` (RFC 5626)
+    pub instance_id: Option<String>,
+    /// `reg-id` of the outbound flow within the instance
+    pub reg_id: Option<u32>,
+    /// Route set to reach the target, the registered Path (RFC 3327) or the
+    /// preloaded Route entries left after this proxy
+    pub route_set: Vec<rsip::Uri>,
+}
+
+impl Location {
+    /// Two bindings with the same flow replace each other, bindings without
+    /// an instance share the legacy single-contact slot
+    pub fn is_same_flow(&self, other: &Location) -> bool {
+        self.instance_id == other.instance_id && self.reg_id == other.reg_id
+    }
+
+    /// Single Route header carrying the whole route set, since outgoing
+    /// requests keep one header per name
+    pub fn route_header(&self) -> Option<rsip::Header> {
+        if self.route_set.is_empty() {
+            return None;
+        }
+        let routes = self
+            .route_set
+            .iter()
+            .map(|uri| format!("<{}>", uri))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Some(rsip::Header::Route(routes.into()))
+    }
+}
+
+/// Parses the URIs of a Path or Route header value, e.g. `<sip:edge;lr>, <sip:core;lr>`
+pub fn parse_route_set(value: &str) -> Vec<rsip::Uri> {
+    value
+        .split(',')
+        .filter_map(|entry| {
+            let entry = entry.trim();
+            let uri = match (entry.find('<'), entry.find('>')) {
+                (Some(start), Some(end)) if start < end => &entry[start + 1..end],
+                _ => entry,
+            };
+            rsip::Uri::try_from(uri).ok()
+        })
+        .collect()
 }
 
 impl std::fmt::Display for Location {
@@ -255,6 +484,8 @@ pub struct Dialplan {
     pub max_ring_time: u32,
     pub route_invite: Option<Box<dyn RouteInvite>>,
     pub extras: Option<HashMap<String, serde_json::Value>>,
+    /// Initial call variables, e.g. set by a `CallRouter` or `DialplanInspector`
+    pub variables: HashMap<String, String>,
 }
 
 impl Dialplan {
@@ -275,6 +506,7 @@ impl Default for Dialplan {
             max_ring_time: 60,
             route_invite: None,
             extras: None,
+            variables: HashMap::new(),
         }
     }
 }
@@ -286,4 +518,9 @@ pub trait RouteInvite: Sync + Send {
         option: InviteOption,
         origin: &rsip::Request,
     ) -> Result<RouteResult>;
+
+    /// The transrating profile of the route `origin` was last forwarded on
+    fn take_transrating(&self, _origin: &rsip::Request) -> Option<ResampleProfile> {
+        None
+    }
 }
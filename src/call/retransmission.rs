@@ -0,0 +1,379 @@
+use super::{forking::ForkGuard, late_offer::LateOffers};
+use crate::config::SipTimersConfig;
+use rsip::{
+    SipMessage, Transport,
+    prelude::{HeadersExt, ToTypedHeader, UntypedHeader},
+};
+use rsipstack::{
+    transaction::endpoint::{EndpointInnerRef, MessageInspector},
+    transport::{SipAddr, SipConnection},
+};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+/// Messages sent kept to recognize their retransmissions
+const MAX_TRACKED_MESSAGES: usize = 4096;
+
+#[derive(Debug, Default)]
+pub struct RetransmissionStats {
+    requests: AtomicU64,
+    responses: AtomicU64,
+    ok_responses: AtomicU64,
+    unacknowledged: AtomicU64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetransmissionSnapshot {
+    /// Requests sent again for want of a response
+    pub requests: u64,
+    /// Responses sent again, to retransmitted requests or for want of an ACK
+    pub responses: u64,
+    /// 2xx responses to INVITE sent again for want of an ACK
+    pub ok_responses: u64,
+    /// 2xx responses to INVITE that were never acknowledged
+    pub unacknowledged: u64,
+}
+
+impl RetransmissionStats {
+    pub fn snapshot(&self) -> RetransmissionSnapshot {
+        RetransmissionSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            responses: self.responses.load(Ordering::Relaxed),
+            ok_responses: self.ok_responses.load(Ordering::Relaxed),
+            unacknowledged: self.unacknowledged.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct RetransmitterInner {
+    timers: SipTimersConfig,
+    stats: Arc<RetransmissionStats>,
+    inspector: Option<Box<dyn MessageInspector>>,
+    forks: ForkGuard,
+    late_offers: LateOffers,
+    /// When each message was last sent, by transaction and status
+    sent: Mutex<HashMap<String, Instant>>,
+}
+
+/// Counts the SIP messages an endpoint sends more than once, and keeps
+/// retransmitting 2xx responses to INVITE over UDP until they are
+/// acknowledged. Installed as the endpoint's inspector, chaining to the
+/// inspector it wraps, it also hands the 2xx following an accepted one to
+/// its [`ForkGuard`], and the offers and answers of ACKed 2xx to its
+/// [`LateOffers`]
+#[derive(Clone)]
+pub struct Retransmitter {
+    inner: Arc<RetransmitterInner>,
+}
+
+impl Retransmitter {
+    pub fn new(
+        timers: SipTimersConfig,
+        stats: Arc<RetransmissionStats>,
+        inspector: Option<Box<dyn MessageInspector>>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(RetransmitterInner {
+                forks: ForkGuard::new(timers.t1x64()),
+                late_offers: LateOffers::new(timers.t1x64()),
+                timers,
+                stats,
+                inspector,
+                sent: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    pub fn timers(&self) -> &SipTimersConfig {
+        &self.inner.timers
+    }
+
+    pub fn stats(&self) -> Arc<RetransmissionStats> {
+        self.inner.stats.clone()
+    }
+
+    pub fn forks(&self) -> &ForkGuard {
+        &self.inner.forks
+    }
+
+    pub fn late_offers(&self) -> &LateOffers {
+        &self.inner.late_offers
+    }
+
+    /// Whether the message was sent before, within the lifetime of its
+    /// transaction
+    fn is_retransmission(&self, msg: &SipMessage) -> bool {
+        let key = match msg {
+            SipMessage::Request(req) => message_key(&req.headers, req.method.to_string()),
+            SipMessage::Response(resp) => {
+                message_key(&resp.headers, resp.status_code.code().to_string())
+            }
+        };
+        let Some(key) = key else {
+            return false;
+        };
+        let Ok(mut sent) = self.inner.sent.lock() else {
+            return false;
+        };
+        let now = Instant::now();
+        if sent.len() >= MAX_TRACKED_MESSAGES {
+            let lifetime = self.inner.timers.t1x64();
+            sent.retain(|_, at| now.duration_since(*at) < lifetime);
+        }
+        sent.insert(key, now).is_some()
+    }
+
+    /// Along with the transaction, sends the 2xx response to an INVITE over
+    /// UDP at intervals from T1 doubling up to T2 until `acked` is cancelled
+    /// (RFC 3261 13.3.1.4), giving up after 64*T1
+    pub fn retransmit_2xx(
+        &self,
+        endpoint: EndpointInnerRef,
+        resp: rsip::Response,
+        acked: CancellationToken,
+    ) {
+        let target = match resp
+            .via_header()
+            .map_err(|e| e.to_string())
+            .and_then(|via| SipConnection::parse_target_from_via(via).map_err(|e| e.to_string()))
+        {
+            Ok((Transport::Udp, addr)) => SipAddr {
+                r#type: Some(Transport::Udp),
+                addr,
+            },
+            Ok(_) => return,
+            Err(e) => {
+                warn!("no destination to retransmit 2xx to: {}", e);
+                return;
+            }
+        };
+        let retransmitter = self.clone();
+        tokio::spawn(async move {
+            let mut last = Duration::ZERO;
+            for at in retransmit_schedule(&retransmitter.inner.timers) {
+                tokio::select! {
+                    _ = acked.cancelled() => return,
+                    _ = tokio::time::sleep(at - last) => {}
+                }
+                last = at;
+                let (connection, destination) =
+                    match endpoint.transport_layer.lookup(&target, None).await {
+                        Ok(r) => r,
+                        Err(e) => {
+                            warn!(%target, "failed to retransmit 2xx: {}", e);
+                            return;
+                        }
+                    };
+                debug!(%destination, "retransmitting 2xx");
+                retransmitter
+                    .inner
+                    .stats
+                    .ok_responses
+                    .fetch_add(1, Ordering::Relaxed);
+                let msg = retransmitter.before_send(resp.clone().into());
+                if let Err(e) = connection.send(msg, Some(&destination)).await {
+                    warn!(%destination, "failed to retransmit 2xx: {}", e);
+                }
+            }
+            tokio::select! {
+                _ = acked.cancelled() => {}
+                _ = tokio::time::sleep(retransmitter.inner.timers.t1x64().saturating_sub(last)) => {
+                    warn!(%target, "2xx was never acknowledged");
+                    retransmitter
+                        .inner
+                        .stats
+                        .unacknowledged
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+    }
+}
+
+impl MessageInspector for Retransmitter {
+    fn before_send(&self, msg: SipMessage) -> SipMessage {
+        let msg = match self.inner.inspector.as_ref() {
+            Some(inspector) => inspector.before_send(msg),
+            None => msg,
+        };
+        let msg = self.inner.late_offers.before_send(msg);
+        if self.is_retransmission(&msg) {
+            let counter = match msg {
+                SipMessage::Request(_) => &self.inner.stats.requests,
+                SipMessage::Response(_) => &self.inner.stats.responses,
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        msg
+    }
+
+    fn after_received(&self, msg: SipMessage) -> SipMessage {
+        let msg = match self.inner.inspector.as_ref() {
+            Some(inspector) => inspector.after_received(msg),
+            None => msg,
+        };
+        if let SipMessage::Response(resp) = &msg {
+            self.inner.forks.on_response(resp);
+        }
+        self.inner.late_offers.on_received(&msg);
+        msg
+    }
+}
+
+/// A message is identified by its transaction (top Via branch and CSeq)
+/// and its method or status code
+fn message_key(headers: &rsip::Headers, kind: String) -> Option<String> {
+    let via = headers.iter().find_map(|h| match h {
+        rsip::Header::Via(via) => via.typed().ok(),
+        _ => None,
+    })?;
+    let branch = via.branch()?.to_string();
+    let cseq = headers.iter().find_map(|h| match h {
+        rsip::Header::CSeq(cseq) => Some(cseq.value().to_string()),
+        _ => None,
+    })?;
+    Some(format!("{} {} {}", branch, cseq, kind))
+}
+
+/// Times after the first send at which a 2xx is sent again, from T1
+/// doubling up to T2 until 64*T1 (RFC 3261 13.3.1.4)
+fn rfc_schedule(timers: &SipTimersConfig) -> Vec<Duration> {
+    doubling_schedule(timers, timers.t2())
+}
+
+/// Times at which the server INVITE transaction of rsipstack sends the 2xx
+/// again itself: its Timer G doubles up to 64*T1 rather than T2
+fn transaction_schedule(timers: &SipTimersConfig) -> Vec<Duration> {
+    doubling_schedule(timers, timers.t1x64())
+}
+
+fn doubling_schedule(timers: &SipTimersConfig, cap: Duration) -> Vec<Duration> {
+    let mut times = vec![];
+    let mut interval = timers.t1();
+    let mut at = interval;
+    while at < timers.t1x64() {
+        times.push(at);
+        interval = (interval * 2).min(cap);
+        at += interval;
+    }
+    times
+}
+
+/// Times the dialog sends a 2xx again, on top of the transaction, so that
+/// together they send it as often as RFC 3261 says and never more: an RFC
+/// time is skipped when the transaction sends before the next one anyway.
+/// rsipstack is pinned in Cargo.toml for this, once its Timer G is capped
+/// at T2 this is empty and `retransmit_2xx` can go.
+pub(crate) fn retransmit_schedule(timers: &SipTimersConfig) -> Vec<Duration> {
+    let rfc = rfc_schedule(timers);
+    let transaction = transaction_schedule(timers);
+    let mut schedule = vec![];
+    for (i, at) in rfc.iter().enumerate() {
+        let next = rfc.get(i + 1).copied().unwrap_or(timers.t1x64());
+        let sent = transaction.iter().filter(|t| **t < next).count() + schedule.len();
+        if sent <= i {
+            schedule.push(*at);
+        }
+    }
+    schedule
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retransmit_schedule() {
+        let schedule = retransmit_schedule(&SipTimersConfig::default());
+        // the transaction sends at 0.5s, 1.5s, 3.5s, 7.5s, 15.5s and 31.5s
+        let expected = [11500, 19500, 23500, 27500]
+            .into_iter()
+            .map(Duration::from_millis)
+            .collect::<Vec<_>>();
+        assert_eq!(schedule, expected);
+    }
+
+    #[test]
+    fn test_combined_schedule_within_rfc() {
+        for timers in [
+            SipTimersConfig::default(),
+            SipTimersConfig {
+                t1: 200,
+                t2: 1000,
+                ..Default::default()
+            },
+            SipTimersConfig {
+                t1: 300,
+                t2: 700,
+                ..Default::default()
+            },
+            SipTimersConfig {
+                t1: 1000,
+                t2: 16000,
+                ..Default::default()
+            },
+        ] {
+            let rfc = rfc_schedule(&timers);
+            let mut combined = transaction_schedule(&timers);
+            combined.extend(retransmit_schedule(&timers));
+            combined.sort();
+            // as many sends as RFC 3261 says, and at no time more
+            assert_eq!(combined.len(), rfc.len());
+            for at in combined.iter().chain(rfc.iter()) {
+                let sent = combined.iter().filter(|t| *t <= at).count();
+                let allowed = rfc.iter().filter(|t| *t <= at).count();
+                assert!(sent <= allowed, "{:?}: {} sent by {:?}", timers, sent, at);
+            }
+        }
+    }
+
+    #[test]
+    fn test_count_retransmissions() {
+        let stats = Arc::new(RetransmissionStats::default());
+        let retransmitter = Retransmitter::new(SipTimersConfig::default(), stats.clone(), None);
+        let invite: rsip::Request = "INVITE sip:bob@example.com SIP/2.0\r\n\
+            Via: SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK776asdhds\r\n\
+            From: <sip:alice@example.com>;tag=1928301774\r\n\
+            To: <sip:bob@example.com>\r\n\
+            Call-ID: a84b4c76e66710\r\n\
+            CSeq: 314159 INVITE\r\n\
+            Content-Length: 0\r\n\r\n"
+            .try_into()
+            .unwrap();
+        let trying: rsip::Response = "SIP/2.0 100 Trying\r\n\
+            Via: SIP/2.0/UDP 192.0.2.1:5060;branch=z9hG4bK776asdhds\r\n\
+            From: <sip:alice@example.com>;tag=1928301774\r\n\
+            To: <sip:bob@example.com>\r\n\
+            Call-ID: a84b4c76e66710\r\n\
+            CSeq: 314159 INVITE\r\n\
+            Content-Length: 0\r\n\r\n"
+            .try_into()
+            .unwrap();
+
+        retransmitter.before_send(invite.clone().into());
+        retransmitter.before_send(trying.clone().into());
+        assert_eq!(stats.snapshot(), RetransmissionSnapshot::default());
+
+        retransmitter.before_send(invite.clone().into());
+        retransmitter.before_send(invite.into());
+        retransmitter.before_send(trying.into());
+        assert_eq!(
+            stats.snapshot(),
+            RetransmissionSnapshot {
+                requests: 2,
+                responses: 1,
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -1,23 +1,33 @@
 use crate::TrackId;
+use crate::call::HangupCause;
 use crate::call::active_call::ActiveCallStateRef;
+use crate::call::dns::SipResolver;
+use crate::call::retransmission::Retransmitter;
 use crate::callrecord::CallRecordHangupReason;
 use crate::event::EventSender;
+use crate::media::dtmf::parse_dtmf_info;
+use crate::media::negotiate::SdpCapabilities;
 use crate::media::stream::MediaStream;
 use crate::useragent::invitation::PendingDialog;
 use anyhow::Result;
 use chrono::Utc;
+use rsip::prelude::UntypedHeader;
 use rsipstack::dialog::DialogId;
+use rsipstack::dialog::client_dialog::ClientInviteDialog;
 use rsipstack::dialog::dialog::{
-    DialogState, DialogStateReceiver, DialogStateSender, TerminatedReason,
+    Dialog, DialogState, DialogStateReceiver, DialogStateSender, TerminatedReason,
 };
 use rsipstack::dialog::dialog_layer::DialogLayer;
 use rsipstack::dialog::invitation::InviteOption;
 use rsipstack::rsip_ext::RsipResponseExt;
-use std::collections::HashMap;
+use rsipstack::transaction::make_call_id;
+use rsipstack::transport::SipAddr;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
-use tracing::info;
+use tracing::{info, warn};
 
 #[derive(Clone)]
 pub struct DialogGuard {
@@ -54,6 +64,60 @@ impl Drop for DialogGuard {
 pub struct Invitation {
     pub dialog_layer: Arc<DialogLayer>,
     pub pending_dialogs: Arc<Mutex<HashMap<String, PendingDialog>>>,
+    pub resolver: Arc<SipResolver>,
+    pub retransmitter: Option<Retransmitter>,
+    /// INVITEs hung up before their final response, by the key of
+    /// [`Self::invite`], which outlives the Call-ID of each attempt
+    cancelled: Arc<std::sync::Mutex<HashSet<String>>>,
+    /// Key of the INVITE each attempt of [`Self::invite`] belongs to, by the
+    /// Call-ID of the attempt
+    attempts: Arc<std::sync::Mutex<HashMap<String, String>>>,
+    /// Dialogs with a re-INVITE or UPDATE of ours in progress
+    renegotiating: Arc<std::sync::Mutex<HashSet<DialogId>>>,
+    /// Renegotiations of each dialog wait on its queue for their turn
+    renegotiations: Arc<std::sync::Mutex<HashMap<DialogId, Arc<Mutex<()>>>>>,
+}
+
+/// Times a re-INVITE or UPDATE refused with 491 Request Pending is sent again
+const MAX_GLARE_RETRIES: usize = 3;
+
+/// How long to wait before sending again a renegotiation refused with 491
+/// (RFC 3261 14.1): 2.1s to 4s for the owner of the Call-ID, up to 2s for
+/// the other side
+pub(crate) fn glare_delay(owner: bool) -> Duration {
+    let ms = if owner {
+        rand::random_range(2100..=4000)
+    } else {
+        rand::random_range(0..=2000)
+    };
+    Duration::from_millis(ms)
+}
+
+/// Marks a dialog renegotiating until dropped, also when the renegotiation
+/// is given up on before its answer
+struct Renegotiating {
+    dialogs: Arc<std::sync::Mutex<HashSet<DialogId>>>,
+    dialog_id: DialogId,
+}
+
+impl Renegotiating {
+    fn new(dialogs: Arc<std::sync::Mutex<HashSet<DialogId>>>, dialog_id: &DialogId) -> Self {
+        if let Ok(mut renegotiating) = dialogs.lock() {
+            renegotiating.insert(dialog_id.clone());
+        }
+        Self {
+            dialogs,
+            dialog_id: dialog_id.clone(),
+        }
+    }
+}
+
+impl Drop for Renegotiating {
+    fn drop(&mut self) {
+        if let Ok(mut renegotiating) = self.dialogs.lock() {
+            renegotiating.remove(&self.dialog_id);
+        }
+    }
 }
 
 impl Invitation {
@@ -61,8 +125,26 @@ impl Invitation {
         Self {
             dialog_layer,
             pending_dialogs: Arc::new(Mutex::new(HashMap::new())),
+            resolver: Arc::new(SipResolver::default()),
+            retransmitter: None,
+            cancelled: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            attempts: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            renegotiating: Arc::new(std::sync::Mutex::new(HashSet::new())),
+            renegotiations: Arc::new(std::sync::Mutex::new(HashMap::new())),
         }
     }
+
+    pub fn with_resolver(mut self, resolver: Arc<SipResolver>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Retransmits the 2xx responses to incoming INVITEs until their ACK, and
+    /// releases the forks answering outgoing ones after the first
+    pub fn with_retransmitter(mut self, retransmitter: Retransmitter) -> Self {
+        self.retransmitter = Some(retransmitter);
+        self
+    }
     pub async fn add_pending(&self, session_id: String, pending: PendingDialog) {
         let mut pending_dialogs = self.pending_dialogs.lock().await;
         pending_dialogs.insert(session_id, pending);
@@ -89,6 +171,7 @@ impl Invitation {
             call.dialog.reject(code, reason).ok();
             call.token.cancel();
         }
+        self.mark_cancelled(&dialog_id);
         match self.dialog_layer.get_dialog(&dialog_id) {
             Some(dialog) => {
                 dialog.hangup().await.ok();
@@ -104,6 +187,7 @@ impl Invitation {
             call.dialog.reject(None, None).ok();
             call.token.cancel();
         }
+        self.mark_cancelled(&dialog_id);
         match self.dialog_layer.get_dialog(&dialog_id) {
             Some(dialog) => {
                 dialog.hangup().await.ok();
@@ -114,27 +198,288 @@ impl Invitation {
         Ok(())
     }
 
+    /// Hanging up an INVITE before its final response sends a CANCEL, which
+    /// the 2xx may cross. Such a 2xx is hung up once the INVITE returns, and
+    /// no other target is tried. The attempt hung up may be over already,
+    /// between two targets
+    fn mark_cancelled(&self, dialog_id: &DialogId) {
+        if !dialog_id.to_tag.is_empty() {
+            return;
+        }
+        let key = self
+            .attempts
+            .lock()
+            .ok()
+            .and_then(|attempts| attempts.get(&dialog_id.call_id).cloned());
+        if let Some(key) = key
+            && let Ok(mut cancelled) = self.cancelled.lock()
+        {
+            cancelled.insert(key);
+        }
+    }
+
+    fn is_cancelled(&self, key: &str) -> bool {
+        self.cancelled
+            .lock()
+            .is_ok_and(|cancelled| cancelled.contains(key))
+    }
+
+    /// Forwards the states of an attempt of the INVITE `key`, noting its
+    /// Call-ID before the caller can hang it up. The termination of an
+    /// attempt that may be failed over by a 503 is left out, it only means
+    /// the next target gets its turn
+    fn relay_attempt_states(
+        &self,
+        key: &str,
+        state_sender: DialogStateSender,
+        last: bool,
+    ) -> DialogStateSender {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let attempts = self.attempts.clone();
+        let key = key.to_string();
+        tokio::spawn(async move {
+            while let Some(state) = receiver.recv().await {
+                match &state {
+                    DialogState::Calling(id) => {
+                        if let Ok(mut attempts) = attempts.lock() {
+                            attempts.insert(id.call_id.clone(), key.clone());
+                        }
+                    }
+                    DialogState::Terminated(_, TerminatedReason::UasOther(code))
+                        if !last && *code == rsip::StatusCode::ServiceUnavailable =>
+                    {
+                        continue;
+                    }
+                    _ => {}
+                }
+                if state_sender.send(state).is_err() {
+                    break;
+                }
+            }
+        });
+        sender
+    }
+
+    /// Forgets the attempts of the INVITE `key`, and whether it was hung
+    /// up. Whether it was is returned
+    fn finish_invite(&self, key: &str) -> bool {
+        if let Ok(mut attempts) = self.attempts.lock() {
+            attempts.retain(|_, k| k != key);
+        }
+        self.cancelled
+            .lock()
+            .is_ok_and(|mut cancelled| cancelled.remove(key))
+    }
+
+    /// Whether a re-INVITE or UPDATE of ours is in progress on the dialog,
+    /// another one received meanwhile is refused with 491
+    pub fn is_renegotiating(&self, dialog_id: &DialogId) -> bool {
+        self.renegotiating
+            .lock()
+            .is_ok_and(|renegotiating| renegotiating.contains(dialog_id))
+    }
+
+    /// Sends a re-INVITE on a confirmed dialog, see [`Self::renegotiate`]
+    pub async fn reinvite(
+        &self,
+        dialog_id: &DialogId,
+        headers: Option<Vec<rsip::Header>>,
+        body: Option<Vec<u8>>,
+    ) -> Result<Option<rsip::Response>> {
+        self.renegotiate(dialog_id, rsip::Method::Invite, headers, body, None)
+            .await
+    }
+
+    /// Sends an UPDATE on a confirmed dialog, see [`Self::renegotiate`]
+    pub async fn update(
+        &self,
+        dialog_id: &DialogId,
+        headers: Option<Vec<rsip::Header>>,
+        body: Option<Vec<u8>>,
+    ) -> Result<Option<rsip::Response>> {
+        self.renegotiate(dialog_id, rsip::Method::Update, headers, body, None)
+            .await
+    }
+
+    /// Sends a re-INVITE or an UPDATE on a confirmed dialog once the ones
+    /// issued on it before are done, in order. One the other side refuses
+    /// with 491, because its own crossed ours, is sent again after a random
+    /// delay. `timeout` bounds the wait for the answer once its turn came,
+    /// not the wait in the queue.
+    ///
+    /// Every re-INVITE and UPDATE of ours goes through here, rather than
+    /// the dialog, for the queue and the 491s to work
+    pub async fn renegotiate(
+        &self,
+        dialog_id: &DialogId,
+        method: rsip::Method,
+        headers: Option<Vec<rsip::Header>>,
+        body: Option<Vec<u8>>,
+        timeout: Option<Duration>,
+    ) -> Result<Option<rsip::Response>> {
+        let queue = self
+            .renegotiations
+            .lock()
+            .map_err(|e| anyhow::anyhow!("{}", e))?
+            .entry(dialog_id.clone())
+            .or_default()
+            .clone();
+        let turn = queue.lock().await;
+        let name = method.to_string();
+        let request = self.send_renegotiation(dialog_id, method, headers, body);
+        let result = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, request)
+                .await
+                .unwrap_or_else(|_| Err(anyhow::anyhow!("{} timed out after {:?}", name, timeout))),
+            None => request.await,
+        };
+        drop(turn);
+        // the queue goes once nobody else waits on it
+        if let Ok(mut renegotiations) = self.renegotiations.lock()
+            && Arc::strong_count(&queue) == 2
+        {
+            renegotiations.remove(dialog_id);
+        }
+        result
+    }
+
+    async fn send_renegotiation(
+        &self,
+        dialog_id: &DialogId,
+        method: rsip::Method,
+        headers: Option<Vec<rsip::Header>>,
+        body: Option<Vec<u8>>,
+    ) -> Result<Option<rsip::Response>> {
+        let dialog = self
+            .dialog_layer
+            .get_dialog(dialog_id)
+            .ok_or_else(|| anyhow::anyhow!("dialog not found: {}", dialog_id))?;
+        // the side that sent the initial INVITE owns the Call-ID
+        let owner = matches!(dialog, Dialog::ClientInvite(_));
+        let _renegotiating = Renegotiating::new(self.renegotiating.clone(), dialog_id);
+        let mut attempts = 0;
+        let result = loop {
+            let (headers, body) = (headers.clone(), body.clone());
+            let result = match (&dialog, &method) {
+                (Dialog::ClientInvite(d), rsip::Method::Update) => d.update(headers, body).await,
+                (Dialog::ClientInvite(d), _) => d.reinvite(headers, body).await,
+                (Dialog::ServerInvite(d), rsip::Method::Update) => d.update(headers, body).await,
+                (Dialog::ServerInvite(d), _) => d.reinvite(headers, body).await,
+            };
+            match result {
+                Ok(Some(resp))
+                    if resp.status_code == rsip::StatusCode::RequestPending
+                        && attempts < MAX_GLARE_RETRIES =>
+                {
+                    attempts += 1;
+                    let delay = glare_delay(owner);
+                    info!(%dialog_id, %method, ?delay, "glare, sending again");
+                    tokio::time::sleep(delay).await;
+                }
+                result => break result,
+            }
+        };
+        result.map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Makes the INVITE of `invite_option` go out without SDP (RFC 3261
+    /// 13.2.1): the offer comes in the 2xx and is answered in the ACK from
+    /// `capabilities`. The offer is kept, and `false` returned, without a
+    /// retransmitter to see the 2xx and the ACK
+    pub fn late_offer(
+        &self,
+        invite_option: &mut InviteOption,
+        capabilities: SdpCapabilities,
+    ) -> bool {
+        let Some(retransmitter) = self.retransmitter.as_ref() else {
+            return false;
+        };
+        // the INVITE is matched with its 2xx and ACK by a Call-ID of ours
+        let call_id = make_call_id(self.dialog_layer.endpoint.option.callid_suffix.as_deref());
+        retransmitter
+            .late_offers()
+            .expect_offer(call_id.value().to_string(), capabilities);
+        invite_option
+            .headers
+            .get_or_insert_with(Vec::new)
+            .push(rsip::Header::CallId(call_id));
+        invite_option.offer = None;
+        true
+    }
+
+    /// Sends the INVITE to each target of the destination in turn (RFC 3263),
+    /// moving on when one can't be reached, times out or answers 503. The
+    /// first 2xx wins, a 2xx that crossed the CANCEL of a hangup is hung up.
+    /// Once hung up, whichever attempt it was, no other target is tried
     pub async fn invite(
         &self,
         invite_option: InviteOption,
         state_sender: DialogStateSender,
     ) -> Result<(DialogId, Option<Vec<u8>>), rsipstack::Error> {
-        let (dialog, resp) = self
-            .dialog_layer
-            .do_invite(invite_option, state_sender)
-            .await?;
+        // each attempt has a Call-ID of its own
+        let key = make_call_id(None).value().to_string();
+        let result = self
+            .invite_attempts(&key, &invite_option, state_sender)
+            .await;
+        let cancelled = self.finish_invite(&key);
+        let (result, destination) = result?;
+        let call_id = match &result {
+            Ok((dialog, _)) => Some(dialog.id().call_id),
+            Err(rsipstack::Error::DialogError(_, id, _)) => Some(id.call_id.clone()),
+            Err(_) => None,
+        };
+        if let (Some(retransmitter), Some(call_id)) = (self.retransmitter.as_ref(), &call_id) {
+            // the ACK went out with the answer to a late offer, if any
+            retransmitter.late_offers().forget(call_id);
+        }
+        let (dialog, resp) = result?;
 
         let offer = match resp {
             Some(resp) => match resp.status_code.kind() {
                 rsip::StatusCodeKind::Successful => {
+                    if cancelled {
+                        // the 2xx crossed our CANCEL (RFC 3261 9.1)
+                        info!(id = %dialog.id(), callee = %invite_option.callee, "2xx crossed the CANCEL, hanging up");
+                        dialog.bye().await.ok();
+                        self.dialog_layer.remove_dialog(&dialog.id());
+                        return Err(rsipstack::Error::DialogError(
+                            "cancelled".to_string(),
+                            dialog.id(),
+                            rsip::StatusCode::RequestTerminated,
+                        ));
+                    }
+                    let destination =
+                        destination.or_else(|| SipAddr::try_from(&invite_option.callee).ok());
+                    if let (Some(retransmitter), Some(destination)) =
+                        (self.retransmitter.as_ref(), destination)
+                    {
+                        retransmitter.forks().accept(
+                            self.dialog_layer.endpoint.clone(),
+                            destination,
+                            &resp,
+                        );
+                    }
                     let offer = resp.body.clone();
                     Some(offer)
                 }
                 _ => {
+                    // the Reason header carries the Q.850 cause when the far end sent one
                     let reason = resp
-                        .reason_phrase()
-                        .unwrap_or(&resp.status_code.to_string())
-                        .to_string();
+                        .headers
+                        .iter()
+                        .find_map(|h| match h {
+                            rsip::Header::Other(name, value)
+                                if name.eq_ignore_ascii_case("Reason") =>
+                            {
+                                Some(value.clone())
+                            }
+                            _ => None,
+                        })
+                        .unwrap_or_else(|| {
+                            resp.reason_phrase()
+                                .unwrap_or(&resp.status_code.to_string())
+                                .to_string()
+                        });
                     return Err(rsipstack::Error::DialogError(
                         reason,
                         dialog.id(),
@@ -152,6 +497,111 @@ impl Invitation {
         };
         Ok((dialog.id(), offer))
     }
+
+    /// The outcome of the first attempt that isn't failed over, and its
+    /// target. The INVITE hung up, the attempt that failed last is the
+    /// outcome, cancelled
+    async fn invite_attempts(
+        &self,
+        key: &str,
+        invite_option: &InviteOption,
+        state_sender: DialogStateSender,
+    ) -> Result<(InviteResult, Option<SipAddr>), rsipstack::Error> {
+        let destinations = self.destinations(invite_option).await;
+        let mut attempts = destinations.into_iter().peekable();
+        let mut failed = None;
+        while let Some(destination) = attempts.next() {
+            // also right after each attempt that failed
+            if self.is_cancelled(key)
+                && let Some((result, destination)) = failed.take()
+            {
+                info!(callee = %invite_option.callee, "INVITE hung up, not failing over");
+                return Ok((cancelled(result), destination));
+            }
+            let last = attempts.peek().is_none();
+            let mut option = invite_option.clone();
+            if destination.is_some() {
+                option.destination = destination.clone();
+            }
+            let sender = self.relay_attempt_states(key, state_sender.clone(), last);
+            let result = self.dialog_layer.do_invite(option, sender).await;
+            let unreachable = match &result {
+                Ok((_, resp)) => resp.is_none(),
+                Err(rsipstack::Error::DialogError(_, _, code)) => {
+                    *code == rsip::StatusCode::ServiceUnavailable
+                }
+                Err(e) => matches!(
+                    e,
+                    rsipstack::Error::TransportLayerError(..)
+                        | rsipstack::Error::DnsResolutionError(_)
+                        | rsipstack::Error::TransactionError(..)
+                        | rsipstack::Error::IoError(_)
+                ),
+            };
+            if unreachable && let Some(destination) = destination.as_ref() {
+                self.resolver.blacklist(destination);
+                if !last {
+                    warn!(%destination, callee = %invite_option.callee, "destination failed, trying next");
+                    failed = Some((result, Some(destination.clone())));
+                    continue;
+                }
+            }
+            return Ok((result, destination));
+        }
+        Err(rsipstack::Error::DnsResolutionError(format!(
+            "no destination for {}",
+            invite_option.callee
+        )))
+    }
+
+    /// Targets to try, `None` leaves the choice to the transport layer
+    async fn destinations(&self, invite_option: &InviteOption) -> Vec<Option<SipAddr>> {
+        let uri = match invite_option.destination.as_ref() {
+            Some(destination) => {
+                if !matches!(destination.addr.host, rsip::Host::Domain(_)) {
+                    return vec![Some(destination.clone())];
+                }
+                let transport = destination.r#type.unwrap_or_default();
+                rsip::Uri {
+                    scheme: Some(if transport.is_secure() {
+                        rsip::Scheme::Sips
+                    } else {
+                        rsip::Scheme::Sip
+                    }),
+                    host_with_port: destination.addr.clone(),
+                    params: destination
+                        .r#type
+                        .map(|t| vec![rsip::Param::Transport(t)])
+                        .unwrap_or_default(),
+                    ..Default::default()
+                }
+            }
+            None => invite_option.callee.clone(),
+        };
+        match self.resolver.resolve(&uri).await {
+            Ok(targets) => targets.into_iter().map(Some).collect(),
+            Err(e) => {
+                warn!(%uri, "failed to resolve destination: {}", e);
+                vec![invite_option.destination.clone()]
+            }
+        }
+    }
+}
+
+type InviteResult = Result<(ClientInviteDialog, Option<rsip::Response>), rsipstack::Error>;
+
+/// The outcome of an attempt of an INVITE hung up meanwhile
+fn cancelled(result: InviteResult) -> InviteResult {
+    let id = match &result {
+        Ok((dialog, _)) => dialog.id(),
+        Err(rsipstack::Error::DialogError(_, id, _)) => id.clone(),
+        Err(_) => return result,
+    };
+    Err(rsipstack::Error::DialogError(
+        "cancelled".to_string(),
+        id,
+        rsip::StatusCode::RequestTerminated,
+    ))
 }
 
 fn on_dialog_terminated(
@@ -177,6 +627,12 @@ fn on_dialog_terminated(
         TerminatedReason::UasOther(code) => code.code(),
         _ => 500, // Default to internal server error
     };
+    if call_state_ref.hangup_cause.is_none() {
+        let answered = call_state_ref.answer_time.is_some();
+        call_state_ref
+            .hangup_cause
+            .replace(HangupCause::from_terminated(&reason, answered));
+    }
 
     if call_state_ref.hangup_reason.is_none() {
         call_state_ref.hangup_reason.replace(match reason {
@@ -217,6 +673,27 @@ fn on_dialog_terminated(
     event_sender.send(hangup_event).ok();
 }
 
+/// Reports the digit of an INFO carrying DTMF as a `Dtmf` event of the leg
+fn on_dialog_info(track_id: &TrackId, request: &rsip::Request, event_sender: &EventSender) {
+    let content_type = request.headers.iter().find_map(|h| match h {
+        rsip::Header::ContentType(content_type) => Some(content_type.value().to_string()),
+        _ => None,
+    });
+    let body = String::from_utf8_lossy(&request.body);
+    if let Some((digit, duration)) =
+        content_type.and_then(|content_type| parse_dtmf_info(&content_type, &body))
+    {
+        event_sender
+            .send(crate::event::SessionEvent::Dtmf {
+                track_id: track_id.clone(),
+                timestamp: crate::get_timestamp(),
+                digit,
+                duration,
+            })
+            .ok();
+    }
+}
+
 pub async fn client_dialog_event_loop(
     cancel_token: CancellationToken,
     session_id: String,
@@ -259,6 +736,10 @@ pub async fn client_dialog_event_loop(
             DialogState::Calling(dialog_id) => {
                 info!(session_id, track_id, %dialog_id, "client dialog calling");
             }
+            DialogState::Info(dialog_id, request) => {
+                info!(session_id, track_id, %dialog_id, "client dialog info");
+                on_dialog_info(&track_id, &request, &event_sender);
+            }
             DialogState::Confirmed(dialog_id) => {
                 info!(session_id, track_id, %dialog_id, "client dialog confirmed");
                 call_state
@@ -306,8 +787,11 @@ pub async fn server_dialog_event_loop(
     event_sender: EventSender,
     mut dlg_state_receiver: DialogStateReceiver,
     call_state: ActiveCallStateRef,
+    media_stream: Arc<MediaStream>,
     dialog_layer: Arc<DialogLayer>,
+    retransmitter: Option<Retransmitter>,
 ) -> Result<DialogId> {
+    let acked = cancel_token.child_token();
     while let Some(event) = dlg_state_receiver.recv().await {
         match event {
             DialogState::Trying(dialog_id) => {
@@ -329,8 +813,33 @@ pub async fn server_dialog_event_loop(
             DialogState::Calling(dialog_id) => {
                 info!(session_id, track_id, %dialog_id, "server dialog calling");
             }
+            DialogState::Info(dialog_id, request) => {
+                info!(session_id, track_id, %dialog_id, "server dialog info");
+                on_dialog_info(&track_id, &request, &event_sender);
+            }
+            DialogState::WaitAck(dialog_id, resp) => {
+                info!(session_id, track_id, %dialog_id, "server dialog waiting for ACK");
+                if let Some(retransmitter) = retransmitter.as_ref() {
+                    retransmitter.retransmit_2xx(
+                        dialog_layer.endpoint.clone(),
+                        resp,
+                        acked.clone(),
+                    );
+                }
+            }
             DialogState::Confirmed(dialog_id) => {
                 info!(session_id, track_id, %dialog_id, "server dialog confirmed");
+                acked.cancel();
+                // the INVITE had no SDP, the answer to ours came in the ACK
+                if let Some(answer) = retransmitter
+                    .as_ref()
+                    .and_then(|r| r.late_offers().take_answer(&dialog_id.call_id))
+                {
+                    info!(session_id, track_id, %dialog_id, "late offer answered in the ACK: \n{}", answer);
+                    media_stream
+                        .update_remote_description(&track_id, &answer)
+                        .await?;
+                }
                 call_state
                     .write()
                     .as_mut()
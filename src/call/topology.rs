@@ -0,0 +1,290 @@
+//! Who talks to whom in a call.
+//!
+//! Billing and fraud detection need to follow a call through its transfers,
+//! which the hangup of each leg alone doesn't tell. Each answered SIP leg is
+//! known by the Call-ID of its dialog, and every change of which legs are
+//! bridged is a `topology` event: two legs bridged, the bridge split with
+//! how long it lasted, the call transferred, or a leg replaced by another
+//! on its track.
+use crate::{
+    TrackId,
+    event::{EventSender, SessionEvent},
+};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TopologyChange {
+    /// The legs hear each other
+    Bridged,
+    /// The legs stopped hearing each other, after `duration`
+    Split,
+    /// The call is sent to `target`, away from the other leg
+    Transferred,
+    /// Another leg took the place of the leg, e.g. the target of a transfer
+    Replaced,
+}
+
+/// An answered SIP leg of a call
+#[skip_serializing_none]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallLeg {
+    /// The Call-ID of the leg's dialog, the same for the life of the leg
+    pub id: String,
+    pub track_id: TrackId,
+    /// The URI of the far end
+    pub party: Option<String>,
+}
+
+/// The answered legs of a call and whether they are bridged
+#[derive(Debug)]
+pub struct Topology {
+    session_id: String,
+    /// The caller's leg first
+    legs: Vec<CallLeg>,
+    /// When the legs were bridged (in ms since the epoch)
+    bridged_at: Option<u64>,
+}
+
+impl Topology {
+    pub fn new(session_id: String) -> Self {
+        Self {
+            session_id,
+            legs: Vec::new(),
+            bridged_at: None,
+        }
+    }
+
+    pub fn legs(&self) -> &[CallLeg] {
+        &self.legs
+    }
+
+    /// `leg` was answered, in place of the leg on its track if any. Two
+    /// legs are bridged
+    pub fn answered(&mut self, leg: CallLeg, now: u64) -> Vec<SessionEvent> {
+        let mut events = Vec::new();
+        if let Some(index) = self.legs.iter().position(|l| l.track_id == leg.track_id) {
+            if self.legs[index].id == leg.id {
+                return events;
+            }
+            events.extend(self.split(now));
+            let replaced = self.legs.remove(index);
+            events.push(self.event(
+                TopologyChange::Replaced,
+                vec![replaced, leg.clone()],
+                None,
+                None,
+                now,
+            ));
+        }
+        // the caller's leg is on the session's track
+        match leg.track_id == self.session_id {
+            true => self.legs.insert(0, leg),
+            false => self.legs.push(leg),
+        }
+        if self.legs.len() >= 2 && self.bridged_at.is_none() {
+            self.bridged_at = Some(now);
+            events.push(self.event(TopologyChange::Bridged, self.legs.clone(), None, None, now));
+        }
+        events
+    }
+
+    /// The leg with the Call-ID `id` hung up, a leg replaced already is
+    /// forgotten
+    pub fn ended(&mut self, id: &str, now: u64) -> Vec<SessionEvent> {
+        let Some(index) = self.legs.iter().position(|l| l.id == id) else {
+            return Vec::new();
+        };
+        let events = self.split(now).into_iter().collect();
+        self.legs.remove(index);
+        events
+    }
+
+    /// The call is sent to `target`, the bridge it was in is split. The
+    /// leg answering there replaces the other leg
+    pub fn transferred(&mut self, target: &str, now: u64) -> Vec<SessionEvent> {
+        if self.legs.is_empty() {
+            return Vec::new();
+        }
+        let mut events = vec![self.event(
+            TopologyChange::Transferred,
+            self.legs.clone(),
+            Some(target.to_string()),
+            None,
+            now,
+        )];
+        events.extend(self.split(now));
+        events
+    }
+
+    /// The call is over, the bridge it was in is split
+    pub fn end(&mut self, now: u64) -> Vec<SessionEvent> {
+        let events = self.split(now).into_iter().collect();
+        self.legs.clear();
+        events
+    }
+
+    fn split(&mut self, now: u64) -> Option<SessionEvent> {
+        let bridged_at = self.bridged_at.take()?;
+        Some(self.event(
+            TopologyChange::Split,
+            self.legs.clone(),
+            None,
+            Some(now.saturating_sub(bridged_at)),
+            now,
+        ))
+    }
+
+    fn event(
+        &self,
+        change: TopologyChange,
+        legs: Vec<CallLeg>,
+        target: Option<String>,
+        duration: Option<u64>,
+        timestamp: u64,
+    ) -> SessionEvent {
+        SessionEvent::Topology {
+            track_id: self.session_id.clone(),
+            timestamp,
+            change,
+            legs,
+            target,
+            duration,
+        }
+    }
+}
+
+/// The topology of a call, shared with the tasks of its legs. The changes
+/// are sent as events of the call
+#[derive(Clone)]
+pub struct CallTopology {
+    topology: Arc<Mutex<Topology>>,
+    event_sender: EventSender,
+    cancel_token: CancellationToken,
+}
+
+impl CallTopology {
+    pub fn new(
+        session_id: String,
+        event_sender: EventSender,
+        cancel_token: CancellationToken,
+    ) -> Self {
+        Self {
+            topology: Arc::new(Mutex::new(Topology::new(session_id))),
+            event_sender,
+            cancel_token,
+        }
+    }
+
+    pub fn answered(&self, leg: CallLeg) {
+        self.update(|topology, now| topology.answered(leg, now));
+    }
+
+    /// The leg hung up. Once the call is cancelled, its end splits the
+    /// legs instead
+    pub fn ended(&self, id: &str) {
+        if self.cancel_token.is_cancelled() {
+            return;
+        }
+        self.update(|topology, now| topology.ended(id, now));
+    }
+
+    pub fn transferred(&self, target: &str) {
+        self.update(|topology, now| topology.transferred(target, now));
+    }
+
+    /// The call is over. The events are also returned, for the call to keep
+    /// them once nobody listens to its events anymore
+    pub fn end(&self) -> Vec<SessionEvent> {
+        self.update(|topology, now| topology.end(now))
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Topology, u64) -> Vec<SessionEvent>) -> Vec<SessionEvent> {
+        let events = match self.topology.lock() {
+            Ok(mut topology) => f(&mut topology, crate::get_timestamp()),
+            Err(_) => return Vec::new(),
+        };
+        for event in &events {
+            self.event_sender.send(event.clone()).ok();
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leg(id: &str, track_id: &str) -> CallLeg {
+        CallLeg {
+            id: id.to_string(),
+            track_id: track_id.to_string(),
+            party: Some(format!("sip:{}@example.com", id)),
+        }
+    }
+
+    fn changes(events: &[SessionEvent]) -> Vec<(TopologyChange, Vec<String>, Option<u64>)> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                SessionEvent::Topology {
+                    change,
+                    legs,
+                    duration,
+                    ..
+                } => Some((
+                    *change,
+                    legs.iter().map(|l| l.id.clone()).collect(),
+                    *duration,
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_topology_transfer() {
+        let mut topology = Topology::new("session".to_string());
+        let ids = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect::<Vec<_>>();
+        // the callee of a B2BUA call answers before the caller is
+        assert!(topology.answered(leg("bob", "callee"), 1000).is_empty());
+        assert_eq!(
+            changes(&topology.answered(leg("alice", "session"), 1100)),
+            vec![(TopologyChange::Bridged, ids(&["alice", "bob"]), None)]
+        );
+        assert!(topology.answered(leg("alice", "session"), 1200).is_empty());
+
+        let events = topology.transferred("sip:carol@example.com", 61100);
+        assert_eq!(
+            changes(&events),
+            vec![
+                (TopologyChange::Transferred, ids(&["alice", "bob"]), None),
+                (TopologyChange::Split, ids(&["alice", "bob"]), Some(60000)),
+            ]
+        );
+        assert!(matches!(
+            &events[0],
+            SessionEvent::Topology { target: Some(target), .. } if target == "sip:carol@example.com"
+        ));
+        assert_eq!(
+            changes(&topology.answered(leg("carol", "callee"), 70000)),
+            vec![
+                (TopologyChange::Replaced, ids(&["bob", "carol"]), None),
+                (TopologyChange::Bridged, ids(&["alice", "carol"]), None),
+            ]
+        );
+        // the hangup of the replaced leg comes late, and changes nothing
+        assert!(topology.ended("bob", 70100).is_empty());
+        assert_eq!(
+            changes(&topology.ended("alice", 100000)),
+            vec![(TopologyChange::Split, ids(&["alice", "carol"]), Some(30000))]
+        );
+        assert_eq!(topology.legs(), &[leg("carol", "callee")]);
+        assert!(topology.end(100100).is_empty());
+        assert!(topology.legs().is_empty());
+    }
+}
@@ -9,9 +9,10 @@ use rsipstack::{
     transaction::transaction::Transaction,
     transport::{SipAddr, SipConnection},
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct SipUser {
     #[serde(default)]
     pub id: u64,
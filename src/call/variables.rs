@@ -0,0 +1,88 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+/// Key-value tags of a call, e.g. a campaign id or an account number.
+/// Clones share the same store, so every leg bridged or transferred from
+/// the call sees and updates the same variables
+#[derive(Debug, Clone, Default)]
+pub struct CallVariables(Arc<RwLock<HashMap<String, String>>>);
+
+impl CallVariables {
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.0.read().ok()?.get(name).cloned()
+    }
+
+    pub fn set(&self, name: impl Into<String>, value: impl Into<String>) {
+        if let Ok(mut variables) = self.0.write() {
+            variables.insert(name.into(), value.into());
+        }
+    }
+
+    pub fn remove(&self, name: &str) -> Option<String> {
+        self.0.write().ok()?.remove(name)
+    }
+
+    /// Applies a batch of changes, a `None` value removes the variable
+    pub fn update(&self, changes: HashMap<String, Option<String>>) {
+        if let Ok(mut variables) = self.0.write() {
+            for (name, value) in changes {
+                match value {
+                    Some(value) => variables.insert(name, value),
+                    None => variables.remove(&name),
+                };
+            }
+        }
+    }
+
+    pub fn extend(&self, values: HashMap<String, String>) {
+        if let Ok(mut variables) = self.0.write() {
+            variables.extend(values);
+        }
+    }
+
+    pub fn snapshot(&self) -> HashMap<String, String> {
+        self.0
+            .read()
+            .map(|variables| variables.clone())
+            .unwrap_or_default()
+    }
+
+    /// The variables for CDRs and events, `None` when there are none
+    pub fn to_option(&self) -> Option<HashMap<String, String>> {
+        Some(self.snapshot()).filter(|variables| !variables.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::call::ActiveCallState;
+    use crate::event::SessionEvent;
+
+    #[test]
+    fn test_variables_shared_across_legs() {
+        let caller = ActiveCallState::default();
+        let callee = ActiveCallState {
+            variables: caller.variables.clone(),
+            ..Default::default()
+        };
+        assert!(caller.variables.to_option().is_none());
+
+        caller.variables.set("campaign", "spring");
+        callee.variables.update(HashMap::from([
+            ("account".to_string(), Some("42".to_string())),
+            ("campaign".to_string(), None),
+        ]));
+        assert_eq!(caller.variables.get("campaign"), None);
+        assert_eq!(caller.variables.get("account").as_deref(), Some("42"));
+
+        match callee.build_hangup_event("track".to_string(), None) {
+            SessionEvent::Hangup { variables, .. } => {
+                assert_eq!(variables, Some(caller.variables.snapshot()));
+            }
+            _ => panic!("expected hangup event"),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use super::CallRecord;
+use crate::config::CallRecordEnrichConfig;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::{process::Stdio, sync::Arc, time::Duration};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+/// Changes a call record before it is written, e.g. to add the caller's
+/// account or drop personal data. Enrichers run in the order they were
+/// added, and one that fails leaves the record as it was
+#[async_trait]
+pub trait CallRecordEnricher: Send + Sync {
+    async fn enrich(&self, record: &mut CallRecord) -> Result<()>;
+}
+
+/// Runs a program with the record as JSON on its stdin, and takes the
+/// record it prints. Printing nothing keeps the record
+pub struct ScriptEnricher {
+    pub program: String,
+    pub args: Vec<String>,
+    pub timeout: Duration,
+}
+
+#[async_trait]
+impl CallRecordEnricher for ScriptEnricher {
+    async fn enrich(&self, record: &mut CallRecord) -> Result<()> {
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow!("{}: {}", self.program, e))?;
+        let input = serde_json::to_vec(record)?;
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("{}: no stdin", self.program))?;
+        let output = tokio::time::timeout(self.timeout, async move {
+            stdin.write_all(&input).await?;
+            drop(stdin);
+            child.wait_with_output().await
+        })
+        .await
+        .map_err(|_| anyhow!("{}: timed out", self.program))??;
+        if !output.status.success() {
+            return Err(anyhow!("{}: {}", self.program, output.status));
+        }
+        if output.stdout.iter().all(|c| c.is_ascii_whitespace()) {
+            return Ok(());
+        }
+        *record = serde_json::from_slice(&output.stdout)
+            .map_err(|e| anyhow!("{}: invalid record: {}", self.program, e))?;
+        Ok(())
+    }
+}
+
+/// Masks fields given by their dotted paths in the record's JSON, such as
+/// `caller` or `variables.card_number`. Text is replaced by the mask,
+/// anything else removed
+pub struct RedactEnricher {
+    pub fields: Vec<String>,
+    pub mask: String,
+}
+
+impl RedactEnricher {
+    fn redact(value: &mut Value, path: &[&str], mask: &str) {
+        let Some((last, parents)) = path.split_last() else {
+            return;
+        };
+        let mut value = value;
+        for name in parents {
+            match value.get_mut(*name) {
+                Some(child) => value = child,
+                None => return,
+            }
+        }
+        let Some(object) = value.as_object_mut() else {
+            return;
+        };
+        match object.get_mut(*last) {
+            Some(Value::String(text)) => *text = mask.to_string(),
+            Some(_) => {
+                object.remove(*last);
+            }
+            None => {}
+        }
+    }
+}
+
+#[async_trait]
+impl CallRecordEnricher for RedactEnricher {
+    async fn enrich(&self, record: &mut CallRecord) -> Result<()> {
+        let mut value = serde_json::to_value(&*record)?;
+        for field in self.fields.iter() {
+            let path = field.split('.').collect::<Vec<_>>();
+            Self::redact(&mut value, &path, &self.mask);
+        }
+        *record = serde_json::from_value(value)
+            .map_err(|e| anyhow!("can't redact {:?}: {}", self.fields, e))?;
+        Ok(())
+    }
+}
+
+/// The script, then the redaction, so that nothing the script adds escapes
+/// it
+pub fn enrichers(config: &CallRecordEnrichConfig) -> Vec<Arc<dyn CallRecordEnricher>> {
+    let mut enrichers: Vec<Arc<dyn CallRecordEnricher>> = Vec::new();
+    if let Some(program) = &config.script {
+        enrichers.push(Arc::new(ScriptEnricher {
+            program: program.clone(),
+            args: config.args.clone(),
+            timeout: Duration::from_secs(config.timeout),
+        }));
+    }
+    if !config.redact.is_empty() {
+        enrichers.push(Arc::new(RedactEnricher {
+            fields: config.redact.clone(),
+            mask: config.mask.clone(),
+        }));
+    }
+    enrichers
+}
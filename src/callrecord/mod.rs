@@ -1,9 +1,10 @@
 use crate::{
-    call::{ActiveCallType, CallOption},
+    call::{ActiveCallType, CallOption, HangupCause, flow::FlowState},
     config::{CallRecordConfig, S3Vendor},
 };
 use anyhow::Result;
 use chrono::{DateTime, Utc};
+use enrich::CallRecordEnricher;
 use object_store::{
     ObjectStore, aws::AmazonS3Builder, azure::MicrosoftAzureBuilder,
     gcp::GoogleCloudStorageBuilder, path::Path as ObjectPath,
@@ -19,8 +20,11 @@ use tokio::{fs::File, io::AsyncWriteExt, select};
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 
+pub mod enrich;
+pub mod summary;
 #[cfg(test)]
 mod tests;
+pub mod transcript;
 
 pub type CallRecordSender = tokio::sync::mpsc::UnboundedSender<CallRecord>;
 pub type CallRecordReceiver = tokio::sync::mpsc::UnboundedReceiver<CallRecord>;
@@ -38,12 +42,16 @@ pub type FnSaveCallRecord = Arc<
     >,
 >;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum CallRecordEventType {
     Event,
     Command,
     Sip,
+    /// The record of a call that ended
+    CallRecord,
+    /// A control API request that changed something
+    Audit,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -87,7 +95,11 @@ pub struct CallRecord {
     pub offer: Option<String>,
     pub answer: Option<String>,
     pub hangup_reason: Option<CallRecordHangupReason>,
-    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hangup_cause: Option<HangupCause>,
+    pub variables: Option<HashMap<String, String>>,
+    /// The IVR flow's path and data when the call ended
+    pub flow: Option<FlowState>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub recorder: Vec<CallRecordMedia>,
     pub extras: Option<HashMap<String, serde_json::Value>>,
     pub dump_event_file: Option<String>,
@@ -215,6 +227,7 @@ pub struct CallRecordManager {
     receiver: CallRecordReceiver,
     saver_fn: FnSaveCallRecord,
     formatter: Arc<dyn CallRecordFormatter>,
+    enrichers: Arc<Vec<Arc<dyn CallRecordEnricher>>>,
 }
 
 pub struct CallRecordManagerBuilder {
@@ -222,6 +235,7 @@ pub struct CallRecordManagerBuilder {
     pub config: Option<CallRecordConfig>,
     saver_fn: Option<FnSaveCallRecord>,
     formatter: Option<Arc<dyn CallRecordFormatter>>,
+    enrichers: Vec<Arc<dyn CallRecordEnricher>>,
 }
 
 impl CallRecordManagerBuilder {
@@ -231,6 +245,7 @@ impl CallRecordManagerBuilder {
             config: None,
             saver_fn: None,
             formatter: None,
+            enrichers: Vec::new(),
         }
     }
 
@@ -254,6 +269,12 @@ impl CallRecordManagerBuilder {
         self
     }
 
+    /// Runs after the enrichers added before it
+    pub fn with_enricher(mut self, enricher: Arc<dyn CallRecordEnricher>) -> Self {
+        self.enrichers.push(enricher);
+        self
+    }
+
     pub fn build(self) -> CallRecordManager {
         let cancel_token = self.cancel_token.unwrap_or_default();
         let config = Arc::new(self.config.unwrap_or_default());
@@ -288,11 +309,32 @@ impl CallRecordManagerBuilder {
             config,
             saver_fn,
             formatter,
+            enrichers: Arc::new(self.enrichers),
         }
     }
 }
 
 impl CallRecordManager {
+    /// Passes the record through the enrichers, skipping the ones that fail
+    pub async fn enrich(
+        enrichers: &[Arc<dyn CallRecordEnricher>],
+        mut record: CallRecord,
+    ) -> CallRecord {
+        for enricher in enrichers.iter() {
+            let mut enriched = record.clone();
+            match enricher.enrich(&mut enriched).await {
+                Ok(_) => record = enriched,
+                Err(e) => {
+                    warn!(
+                        call_id = record.call_id,
+                        "Failed to enrich call record: {}", e
+                    )
+                }
+            }
+        }
+        record
+    }
+
     fn default_saver(
         _cancel_token: CancellationToken,
         formatter: Arc<dyn CallRecordFormatter>,
@@ -563,6 +605,7 @@ impl CallRecordManager {
                 self.formatter.clone(),
                 self.config.clone(),
                 self.saver_fn.clone(),
+                self.enrichers.clone(),
                 &mut self.receiver,
             ) => {
                 info!("CallRecordManager received done");
@@ -576,6 +619,7 @@ impl CallRecordManager {
         formatter: Arc<dyn CallRecordFormatter>,
         config: Arc<CallRecordConfig>,
         saver_fn: FnSaveCallRecord,
+        enrichers: Arc<Vec<Arc<dyn CallRecordEnricher>>>,
         receiver: &mut CallRecordReceiver,
     ) -> Result<()> {
         while let Some(record) = receiver.recv().await {
@@ -583,7 +627,9 @@ impl CallRecordManager {
             let save_fn_ref = saver_fn.clone();
             let config_ref = config.clone();
             let formatter_ref = formatter.clone();
+            let enrichers_ref = enrichers.clone();
             tokio::spawn(async move {
+                let record = Self::enrich(&enrichers_ref, record).await;
                 select! {
                     _ = cancel_token_ref.cancelled() => {
                         info!("CallRecordManager cancelled");
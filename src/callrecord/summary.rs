@@ -0,0 +1,128 @@
+use super::transcript::TranscriptSegment;
+use crate::config::CallSummaryConfig;
+use anyhow::{Result, anyhow};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+
+/// What the model made of a call, kept in the transcript and the call
+/// record's extras
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallSummary {
+    pub summary: String,
+    /// The call's outcome, e.g. `resolved` or `callback`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disposition: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Deserialize)]
+struct ChatChoice {
+    message: ChatMessage,
+}
+
+#[derive(Deserialize)]
+struct ChatMessage {
+    #[serde(default)]
+    content: String,
+}
+
+/// Sends transcripts to an OpenAI compatible `chat/completions` endpoint,
+/// masked first, and takes the summary it replies
+pub struct CallSummarizer {
+    config: CallSummaryConfig,
+    redact: Vec<Regex>,
+}
+
+impl CallSummarizer {
+    pub fn new(config: CallSummaryConfig) -> Result<Self> {
+        let redact = config
+            .redact
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .map_err(|e| anyhow!("invalid redact pattern {}: {}", pattern, e))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { config, redact })
+    }
+
+    /// A line per segment, `speaker: text`, with the redacted patterns
+    /// masked
+    pub fn conversation(&self, segments: &[TranscriptSegment]) -> String {
+        segments
+            .iter()
+            .map(|segment| {
+                let text = self.redact.iter().fold(segment.text.clone(), |text, re| {
+                    re.replace_all(&text, self.config.mask.as_str()).to_string()
+                });
+                format!("{}: {}", segment.speaker, text)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub async fn summarize(
+        &self,
+        client: &reqwest::Client,
+        segments: &[TranscriptSegment],
+    ) -> Result<CallSummary> {
+        let body = json!({
+            "model": self.config.model,
+            "messages": [
+                {"role": "system", "content": self.config.prompt},
+                {"role": "user", "content": self.conversation(segments)},
+            ],
+        });
+        let mut request = client
+            .post(&self.config.url)
+            .json(&body)
+            .timeout(Duration::from_secs(self.config.timeout));
+        if let Some(api_key) = self.config.api_key.as_ref() {
+            request = request.bearer_auth(api_key);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "{} returned {}",
+                self.config.url,
+                response.status()
+            ));
+        }
+        let response = response.json::<ChatResponse>().await?;
+        let content = response
+            .choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content)
+            .ok_or_else(|| anyhow!("{} returned no choices", self.config.url))?;
+        parse_summary(&content)
+    }
+}
+
+/// The JSON the prompt asks for, fenced or not. A model that replied in
+/// prose gets its reply as the summary
+pub fn parse_summary(content: &str) -> Result<CallSummary> {
+    let content = content.trim();
+    let json = content
+        .strip_prefix("```json")
+        .or_else(|| content.strip_prefix("```"))
+        .and_then(|inner| inner.strip_suffix("```"))
+        .unwrap_or(content)
+        .trim();
+    if let Ok(summary) = serde_json::from_str::<CallSummary>(json) {
+        return Ok(summary);
+    }
+    if content.is_empty() {
+        return Err(anyhow!("empty summary"));
+    }
+    Ok(CallSummary {
+        summary: content.to_string(),
+        disposition: None,
+    })
+}
@@ -26,6 +26,9 @@ async fn test_save_with_http_without_media() {
         answer: None,
         offer: None,
         hangup_reason: None,
+        hangup_cause: None,
+        variables: None,
+        flow: None,
         recorder: vec![],
         extras: Some(extras),
         dump_event_file: None,
@@ -94,6 +97,9 @@ async fn test_save_with_http_with_media() {
         answer: None,
         offer: None,
         hangup_reason: Some(CallRecordHangupReason::ByCaller),
+        hangup_cause: Some(crate::call::HangupCause::NormalClearing),
+        variables: None,
+        flow: None,
         recorder: vec![media],
         extras: Some(extras),
         dump_event_file: None,
@@ -150,6 +156,9 @@ async fn test_save_with_http_with_custom_headers() {
         answer: None,
         offer: None,
         hangup_reason: Some(CallRecordHangupReason::ByCaller),
+        hangup_cause: Some(crate::call::HangupCause::NormalClearing),
+        variables: None,
+        flow: None,
         recorder: vec![],
         extras: Some(extras),
         dump_event_file: None,
@@ -204,6 +213,9 @@ async fn test_save_with_s3_like_with_custom_headers() {
         answer: None,
         offer: None,
         hangup_reason: Some(CallRecordHangupReason::ByCaller),
+        hangup_cause: Some(crate::call::HangupCause::NormalClearing),
+        variables: None,
+        flow: None,
         recorder: vec![],
         extras: Some(extras),
         dump_event_file: None,
@@ -264,6 +276,9 @@ async fn test_save_with_s3_like_memory_store() {
         answer: None,
         offer: None,
         hangup_reason: Some(CallRecordHangupReason::ByCaller),
+        hangup_cause: Some(crate::call::HangupCause::NormalClearing),
+        variables: None,
+        flow: None,
         recorder: vec![],
         extras: Some(extras),
         dump_event_file: None,
@@ -331,6 +346,9 @@ async fn test_save_with_s3_like_with_media() {
         offer: None,
         answer: None,
         hangup_reason: Some(CallRecordHangupReason::ByCaller),
+        hangup_cause: Some(crate::call::HangupCause::NormalClearing),
+        variables: None,
+        flow: None,
         recorder: vec![media],
         extras: Some(extras),
         dump_event_file: None,
@@ -379,3 +397,263 @@ async fn test_save_with_s3_like_with_media() {
         }
     }
 }
+
+fn enrich_record() -> CallRecord {
+    CallRecord {
+        call_type: crate::call::ActiveCallType::B2bua,
+        option: None,
+        call_id: "enrich_call".to_string(),
+        start_time: Utc::now(),
+        ring_time: None,
+        answer_time: None,
+        end_time: Utc::now(),
+        caller: "+1234567890".to_string(),
+        callee: "+0987654321".to_string(),
+        status_code: 200,
+        answer: None,
+        offer: Some("v=0".to_string()),
+        hangup_reason: None,
+        hangup_cause: None,
+        variables: Some(HashMap::from([
+            ("pin".to_string(), "1234".to_string()),
+            ("lang".to_string(), "en".to_string()),
+        ])),
+        flow: None,
+        recorder: vec![],
+        extras: Some(HashMap::from([(
+            "email".to_string(),
+            serde_json::json!({"home": "a@example.com"}),
+        )])),
+        dump_event_file: None,
+        refer_callrecord: None,
+    }
+}
+
+#[tokio::test]
+async fn test_redact_call_record() {
+    let redact = enrich::RedactEnricher {
+        fields: vec![
+            "caller".to_string(),
+            "offer".to_string(),
+            "variables.pin".to_string(),
+            "extras.email".to_string(),
+            "variables.missing".to_string(),
+        ],
+        mask: "***".to_string(),
+    };
+    let record = CallRecordManager::enrich(&[Arc::new(redact)], enrich_record()).await;
+    assert_eq!(record.caller, "***");
+    assert_eq!(record.callee, "+0987654321");
+    assert_eq!(record.offer.as_deref(), Some("***"));
+    let variables = record.variables.unwrap();
+    assert_eq!(variables.get("pin").map(String::as_str), Some("***"));
+    assert_eq!(variables.get("lang").map(String::as_str), Some("en"));
+    assert!(record.extras.unwrap().get("email").is_none());
+
+    // a required number can't be dropped, the record is kept as it was
+    let redact = enrich::RedactEnricher {
+        fields: vec!["caller".to_string(), "status_code".to_string()],
+        mask: "***".to_string(),
+    };
+    let record = CallRecordManager::enrich(&[Arc::new(redact)], enrich_record()).await;
+    assert_eq!(record.caller, "+1234567890");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_script_enriches_call_record() {
+    let script = |command: &str| -> Arc<dyn enrich::CallRecordEnricher> {
+        Arc::new(enrich::ScriptEnricher {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), command.to_string()],
+            timeout: std::time::Duration::from_secs(5),
+        })
+    };
+    let enrichers = vec![
+        script(r#"sed 's/"callee":"[^"]*"/"callee":"account-42"/'"#),
+        // prints nothing, keeps the record
+        script("cat > /dev/null"),
+        // fails, skipped
+        script("exit 1"),
+        script("echo not json"),
+    ];
+    let record = CallRecordManager::enrich(&enrichers, enrich_record()).await;
+    assert_eq!(record.callee, "account-42");
+    assert_eq!(record.caller, "+1234567890");
+
+    let config = crate::config::CallRecordEnrichConfig {
+        script: Some("sh".to_string()),
+        args: vec!["-c".to_string(), "sleep 5".to_string()],
+        timeout: 0,
+        redact: vec!["callee".to_string()],
+        mask: "x".to_string(),
+    };
+    let enrichers = enrich::enrichers(&config);
+    assert_eq!(enrichers.len(), 2);
+    let record = CallRecordManager::enrich(&enrichers, enrich_record()).await;
+    assert_eq!(record.callee, "x");
+}
+
+#[tokio::test]
+async fn test_post_call_transcription() {
+    use axum::{Json, Router, routing::post};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use transcript::{PostCallTranscriber, Transcript, speaker_channels, transcript_path};
+
+    assert_eq!(speaker_channels(1), Vec::<usize>::new());
+    assert_eq!(speaker_channels(2), vec![0, 1]);
+    // raw and processed taps, each track's raw audio is transcribed
+    assert_eq!(speaker_channels(4), vec![0, 2]);
+    assert_eq!(
+        transcript_path("/tmp/call-1.wav"),
+        "/tmp/call-1.transcript.json"
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let recording = dir.path().join("call-1.wav");
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 8000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(&recording, spec).unwrap();
+    for i in 0..8000 {
+        writer.write_sample((i % 100) as i16).unwrap();
+        writer.write_sample(-((i % 50) as i16)).unwrap();
+    }
+    writer.finalize().unwrap();
+
+    // the caller's channel has segments, the callee's only text
+    let requests = Arc::new(AtomicUsize::new(0));
+    let (webhook_sender, mut webhook) = tokio::sync::mpsc::unbounded_channel();
+    let (chat_sender, mut chat) = tokio::sync::mpsc::unbounded_channel();
+    let (callrecord_sender, mut callrecords) = tokio::sync::mpsc::unbounded_channel();
+    let app = Router::new()
+        .route(
+            "/transcriptions",
+            post({
+                let requests = requests.clone();
+                move || async move {
+                    Json(match requests.fetch_add(1, Ordering::SeqCst) {
+                        0 => serde_json::json!({"text": "hello 4111111111111111 bye", "segments": [
+                            {"start": 0.5, "end": 1.0, "text": " hello 4111111111111111"},
+                            {"start": 2.0, "end": 2.5, "text": " bye"},
+                        ]}),
+                        _ => serde_json::json!({"text": " hi there"}),
+                    })
+                }
+            }),
+        )
+        .route(
+            "/chat",
+            post(move |Json(body): Json<serde_json::Value>| async move {
+                chat_sender.send(body).ok();
+                Json(serde_json::json!({"choices": [{"message": {"content":
+                    "```json\n{\"summary\": \"Said hello\", \"disposition\": \"resolved\"}\n```"
+                }}]}))
+            }),
+        )
+        .route(
+            "/webhook",
+            post(move |Json(body): Json<serde_json::Value>| async move {
+                webhook_sender.send(body).ok();
+            }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let token = CancellationToken::new();
+    let transcriber = PostCallTranscriber::start(
+        crate::config::PostTranscriptionConfig {
+            url: format!("http://{}/transcriptions", addr),
+            api_key: None,
+            model: "whisper-1".to_string(),
+            language: None,
+            speakers: vec!["caller".to_string(), "callee".to_string()],
+            concurrency: 1,
+            queue: 4,
+            timeout: 10,
+            webhook: Some(format!("http://{}/webhook", addr)),
+            summary: Some(crate::config::CallSummaryConfig {
+                url: format!("http://{}/chat", addr),
+                api_key: None,
+                model: "gpt-4o-mini".to_string(),
+                prompt: "Summarize".to_string(),
+                redact: vec![r"\d{13,19}".to_string()],
+                mask: "***".to_string(),
+                timeout: 10,
+            }),
+        },
+        Some(callrecord_sender),
+        token.clone(),
+    )
+    .unwrap();
+    let mut record = enrich_record();
+    record.recorder = vec![CallRecordMedia {
+        track_id: "call-1".to_string(),
+        path: recording.to_string_lossy().to_string(),
+        size: 0,
+        extra: None,
+    }];
+    transcriber.submit(&record);
+
+    let body = tokio::time::timeout(std::time::Duration::from_secs(10), webhook.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    let path = transcript_path(&recording.to_string_lossy());
+    assert_eq!(body["path"], path.as_str());
+    let transcript: Transcript = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(transcript.call_id, "enrich_call");
+    let segments = transcript
+        .segments
+        .iter()
+        .map(|s| (s.speaker.as_str(), s.channel, s.start, s.text.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        segments,
+        vec![
+            ("callee", 1, 0.0, "hi there"),
+            ("caller", 0, 0.5, "hello 4111111111111111"),
+            ("caller", 0, 2.0, "bye"),
+        ]
+    );
+    assert_eq!(body["segments"][0]["end"], 1.0);
+    assert_eq!(body["summary"]["disposition"], "resolved");
+
+    // the card number never reaches the model
+    let chat = chat.recv().await.unwrap();
+    assert_eq!(
+        chat["messages"][1]["content"],
+        "callee: hi there\ncaller: hello ***\ncaller: bye"
+    );
+    let record = callrecords.recv().await.unwrap();
+    let extras = record.extras.unwrap();
+    assert_eq!(extras["summary"], "Said hello");
+    assert_eq!(extras["disposition"], "resolved");
+    token.cancel();
+}
+
+#[test]
+fn test_parse_call_summary() {
+    use summary::{CallSummary, parse_summary};
+
+    assert_eq!(
+        parse_summary(r#"{"summary": "Asked for a refund", "disposition": "refund"}"#).unwrap(),
+        CallSummary {
+            summary: "Asked for a refund".to_string(),
+            disposition: Some("refund".to_string()),
+        }
+    );
+    // prose is kept as the summary
+    assert_eq!(
+        parse_summary(" The caller hung up. ").unwrap(),
+        CallSummary {
+            summary: "The caller hung up.".to_string(),
+            disposition: None,
+        }
+    );
+    assert!(parse_summary("").is_err());
+}
@@ -0,0 +1,373 @@
+use super::{
+    CallRecord, CallRecordSender,
+    summary::{CallSummarizer, CallSummary},
+};
+use crate::config::PostTranscriptionConfig;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use serde::{Deserialize, Serialize};
+use std::{io::Cursor, path::Path, sync::Arc, time::Duration};
+use tokio::sync::{Semaphore, mpsc};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// What a speaker said, seconds from the start of the recording
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TranscriptSegment {
+    pub speaker: String,
+    pub channel: usize,
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/// The diarized transcript of a recording, written next to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Transcript {
+    pub call_id: String,
+    pub recording: String,
+    /// The speakers' segments, by start
+    pub segments: Vec<TranscriptSegment>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary: Option<CallSummary>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The webhook's body
+#[derive(Serialize)]
+struct TranscriptReady<'a> {
+    /// Where the transcript was written
+    path: &'a str,
+    #[serde(flatten)]
+    transcript: &'a Transcript,
+}
+
+#[derive(Deserialize)]
+struct TranscriptionResponse {
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    segments: Vec<ResponseSegment>,
+}
+
+#[derive(Deserialize)]
+struct ResponseSegment {
+    start: f64,
+    end: f64,
+    text: String,
+}
+
+struct TranscriptJob {
+    call_id: String,
+    recording: String,
+    /// Sent again with the summary
+    record: Arc<CallRecord>,
+}
+
+/// What the jobs share
+struct Transcription {
+    config: PostTranscriptionConfig,
+    summarizer: Option<CallSummarizer>,
+    callrecord_sender: Option<CallRecordSender>,
+    client: reqwest::Client,
+}
+
+/// Transcribes the recordings of ended calls in the background, a few at
+/// a time, so the calls in progress don't wait on it
+pub struct PostCallTranscriber {
+    sender: mpsc::Sender<TranscriptJob>,
+}
+
+impl PostCallTranscriber {
+    /// Records summarized are sent again to `callrecord_sender`
+    pub fn start(
+        config: PostTranscriptionConfig,
+        callrecord_sender: Option<CallRecordSender>,
+        token: CancellationToken,
+    ) -> Result<Self> {
+        let summarizer = config
+            .summary
+            .clone()
+            .map(CallSummarizer::new)
+            .transpose()?;
+        let (sender, receiver) = mpsc::channel(config.queue.max(1));
+        let transcription = Transcription {
+            config,
+            summarizer,
+            callrecord_sender,
+            client: reqwest::Client::new(),
+        };
+        tokio::spawn(run_jobs(Arc::new(transcription), receiver, token));
+        Ok(Self { sender })
+    }
+
+    /// Queues the recordings of the call, skipped when the queue is full
+    pub fn submit(&self, record: &CallRecord) {
+        if record.recorder.is_empty() {
+            return;
+        }
+        let shared = Arc::new(record.clone());
+        for media in record.recorder.iter() {
+            let job = TranscriptJob {
+                call_id: record.call_id.clone(),
+                recording: media.path.clone(),
+                record: shared.clone(),
+            };
+            if let Err(e) = self.sender.try_send(job) {
+                warn!(
+                    call_id = record.call_id,
+                    recording = media.path,
+                    "recording not transcribed: {}",
+                    e
+                );
+            }
+        }
+    }
+}
+
+async fn run_jobs(
+    transcription: Arc<Transcription>,
+    mut receiver: mpsc::Receiver<TranscriptJob>,
+    token: CancellationToken,
+) {
+    let permits = Arc::new(Semaphore::new(transcription.config.concurrency.max(1)));
+    loop {
+        let job = tokio::select! {
+            _ = token.cancelled() => break,
+            job = receiver.recv() => match job {
+                Some(job) => job,
+                None => break,
+            },
+        };
+        let Ok(permit) = permits.clone().acquire_owned().await else {
+            break;
+        };
+        let transcription = transcription.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            let _permit = permit;
+            tokio::select! {
+                _ = token.cancelled() => {}
+                r = transcribe(&transcription, &job) => {
+                    if let Err(e) = r {
+                        warn!(
+                            call_id = job.call_id,
+                            recording = job.recording,
+                            "failed to transcribe recording: {}",
+                            e
+                        );
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Transcribes each speaker's channel of the recording, writes the
+/// transcript next to it and posts it to the webhook
+async fn transcribe(transcription: &Transcription, job: &TranscriptJob) -> Result<()> {
+    let config = &transcription.config;
+    let client = &transcription.client;
+    let recording = job.recording.clone();
+    let (samplerate, channels) =
+        tokio::task::spawn_blocking(move || read_channels(&recording)).await??;
+    let speakers = speaker_channels(channels.len());
+    if speakers.is_empty() {
+        debug!(
+            call_id = job.call_id,
+            recording = job.recording,
+            "recording is not stereo, not transcribed"
+        );
+        return Ok(());
+    }
+    let mut segments = Vec::new();
+    for (index, channel) in speakers.into_iter().enumerate() {
+        let samples = &channels[channel];
+        // a silent party costs nothing to transcribe
+        if samples.iter().all(|sample| *sample == 0) {
+            continue;
+        }
+        let speaker = config
+            .speakers
+            .get(index)
+            .cloned()
+            .unwrap_or_else(|| format!("speaker-{}", index));
+        let duration = samples.len() as f64 / samplerate as f64;
+        let wav = mono_wav(samples, samplerate)?;
+        for (start, end, text) in request(config, client, wav, duration).await? {
+            segments.push(TranscriptSegment {
+                speaker: speaker.clone(),
+                channel,
+                start,
+                end,
+                text,
+            });
+        }
+    }
+    let segments = merge(segments);
+    // a failed summary still leaves the transcript
+    let summary = match transcription.summarizer.as_ref() {
+        Some(summarizer) if !segments.is_empty() => {
+            match summarizer.summarize(client, &segments).await {
+                Ok(summary) => Some(summary),
+                Err(e) => {
+                    warn!(
+                        call_id = job.call_id,
+                        recording = job.recording,
+                        "failed to summarize transcript: {}",
+                        e
+                    );
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    let transcript = Transcript {
+        call_id: job.call_id.clone(),
+        recording: job.recording.clone(),
+        segments,
+        summary,
+        created_at: Utc::now(),
+    };
+    let path = transcript_path(&job.recording);
+    tokio::fs::write(&path, serde_json::to_vec_pretty(&transcript)?).await?;
+    info!(
+        call_id = job.call_id,
+        path,
+        segments = transcript.segments.len(),
+        "recording transcribed"
+    );
+    if let (Some(summary), Some(sender)) = (
+        transcript.summary.as_ref(),
+        transcription.callrecord_sender.as_ref(),
+    ) {
+        let mut record = job.record.as_ref().clone();
+        with_summary(&mut record, summary);
+        if let Err(e) = sender.send(record) {
+            warn!(
+                call_id = job.call_id,
+                "failed to send summarized record: {}", e
+            );
+        }
+    }
+    if let Some(url) = config.webhook.as_ref() {
+        let body = TranscriptReady {
+            path: &path,
+            transcript: &transcript,
+        };
+        let response = client.post(url).json(&body).send().await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("{} returned {}", url, response.status()));
+        }
+    }
+    Ok(())
+}
+
+/// Adds the summary and disposition to the record's extras
+pub fn with_summary(record: &mut CallRecord, summary: &CallSummary) {
+    let extras = record.extras.get_or_insert_default();
+    extras.insert("summary".to_string(), summary.summary.clone().into());
+    if let Some(disposition) = summary.disposition.as_ref() {
+        extras.insert("disposition".to_string(), disposition.clone().into());
+    }
+}
+
+/// Sends a channel to the endpoint, the segments it heard
+async fn request(
+    config: &PostTranscriptionConfig,
+    client: &reqwest::Client,
+    wav: Vec<u8>,
+    duration: f64,
+) -> Result<Vec<(f64, f64, String)>> {
+    let file = reqwest::multipart::Part::bytes(wav)
+        .file_name("channel.wav")
+        .mime_str("audio/wav")?;
+    let mut form = reqwest::multipart::Form::new()
+        .text("model", config.model.clone())
+        .text("response_format", "verbose_json")
+        .part("file", file);
+    if let Some(language) = config.language.clone() {
+        form = form.text("language", language);
+    }
+    let mut request = client
+        .post(&config.url)
+        .multipart(form)
+        .timeout(Duration::from_secs(config.timeout));
+    if let Some(api_key) = config.api_key.as_ref() {
+        request = request.bearer_auth(api_key);
+    }
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(anyhow!("{} returned {}", config.url, response.status()));
+    }
+    let response = response.json::<TranscriptionResponse>().await?;
+    // endpoints without segments only have the text of the whole channel
+    if response.segments.is_empty() {
+        let text = response.text.trim();
+        return Ok(match text.is_empty() {
+            true => vec![],
+            false => vec![(0.0, duration, text.to_string())],
+        });
+    }
+    Ok(response
+        .segments
+        .into_iter()
+        .map(|segment| (segment.start, segment.end, segment.text.trim().to_string()))
+        .filter(|(_, _, text)| !text.is_empty())
+        .collect())
+}
+
+/// The samples of each channel of a 16-bit WAV file, and their rate
+fn read_channels(path: &str) -> Result<(u32, Vec<Vec<i16>>)> {
+    let mut reader = WavReader::open(path)?;
+    let spec = reader.spec();
+    let mut channels = vec![Vec::new(); spec.channels as usize];
+    for (index, sample) in reader.samples::<i16>().enumerate() {
+        channels[index % spec.channels as usize].push(sample?);
+    }
+    Ok((spec.sample_rate, channels))
+}
+
+/// The first channel of each track's half, where the recorder put its
+/// audio as decoded. None for a mono recording
+pub fn speaker_channels(channels: usize) -> Vec<usize> {
+    match channels {
+        0 | 1 => vec![],
+        _ => vec![0, channels / 2],
+    }
+}
+
+fn mono_wav(samples: &[i16], samplerate: u32) -> Result<Vec<u8>> {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: samplerate,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut cursor = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut cursor, spec)?;
+    for sample in samples {
+        writer.write_sample(*sample)?;
+    }
+    writer.finalize()?;
+    Ok(cursor.into_inner())
+}
+
+/// Interleaves the speakers' segments by start, a speaker's own keep their
+/// order
+pub fn merge(mut segments: Vec<TranscriptSegment>) -> Vec<TranscriptSegment> {
+    segments.sort_by(|a, b| a.start.total_cmp(&b.start));
+    segments
+}
+
+/// `call.wav` is transcribed into `call.transcript.json`
+pub fn transcript_path(recording: &str) -> String {
+    Path::new(recording)
+        .with_extension("transcript.json")
+        .to_string_lossy()
+        .to_string()
+}
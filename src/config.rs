@@ -1,13 +1,23 @@
 use crate::{
-    call::user::SipUser,
-    proxy::routing::{DefaultRoute, RouteRule, TrunkConfig},
+    call::{HangupCause, user::SipUser},
+    media::{dtmf::DtmfMode, rewriter::RtpRewriteOption, shaper::ShapingOption},
+    proxy::{
+        acl::{IpNetwork, parse_network},
+        campaign::CallingHours,
+        routing::{CircuitsBusyConfig, DefaultRoute, RouteRule, TrunkConfig},
+    },
+    synthesis::prompt::PromptSegment,
     useragent::RegisterOption,
 };
 use anyhow::{Error, Result};
 use clap::Parser;
 use rsipstack::dialog::invitation::InviteOption;
+use rsipstack::transaction::endpoint::EndpointOption;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::time::Duration;
+use tracing::warn;
 
 #[derive(Parser, Debug)]
 #[command(version)]
@@ -48,10 +58,12 @@ fn default_callid_suffix() -> Option<String> {
     Some("rustpbx.com".to_string())
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
 pub struct Config {
     #[serde(default = "default_config_http_addr")]
     pub http_addr: String,
+    /// gRPC call control and media streaming API, disabled when unset
+    pub grpc_addr: Option<String>,
     pub log_level: Option<String>,
     pub log_file: Option<String>,
     pub ua: Option<UseragentConfig>,
@@ -62,19 +74,50 @@ pub struct Config {
     pub rtp_start_port: Option<u16>,
     #[serde(default = "default_config_rtp_end_port")]
     pub rtp_end_port: Option<u16>,
+    /// Default egress bandwidth cap of each call's RTP
+    pub rtp_shaping: Option<ShapingOption>,
+    /// SSRC and header extension rewriting of RTP relayed between legs
+    pub rtp_rewrite: Option<RtpRewriteOption>,
+    /// Audio level header extension (RFC 6464) on the RTP of each call, so
+    /// the mixer knows the speakers' levels without decoding them
+    pub rtp_audio_level: Option<bool>,
+    /// Public addresses of the RTP ports, for media behind a NAT such as a
+    /// container without host networking
+    pub media_nat: Option<MediaNatConfig>,
+    /// Worker threads of the runtime carrying the media, and the CPUs they
+    /// are pinned to, one thread per CPU and unpinned when unset
+    pub media_workers: Option<MediaWorkersConfig>,
 
     #[serde(default = "default_config_recorder_path")]
     pub recorder_path: String,
     pub callrecord: Option<CallRecordConfig>,
+    /// Changes made to call records before they are written
+    pub callrecord_enrich: Option<CallRecordEnrichConfig>,
+    /// Transcribes the stereo recordings once their calls end, a speaker
+    /// per channel
+    pub post_transcription: Option<PostTranscriptionConfig>,
+    /// Keeps every call event, command and SIP request in rotating files
+    pub journal: Option<JournalConfig>,
     #[serde(default = "default_config_media_cache_path")]
     pub media_cache_path: String,
     pub llmproxy: Option<String>,
     pub restsend_token: Option<String>,
     pub ice_servers: Option<Vec<IceServer>>,
     pub ami: Option<AmiConfig>,
+    /// DSCP marking of media and signaling packets, best-effort when unset
+    pub qos: Option<QosConfig>,
+    /// SIP transaction timers of the proxy and the user agent, RFC 3261
+    /// defaults when unset
+    pub sip_timers: Option<SipTimersConfig>,
+    /// Named prompts in several languages, picked by the call's locale
+    pub prompts: Option<PromptPackConfig>,
+    /// Where IVR flow states are saved, kept with the call only when unset
+    pub flow: Option<FlowConfig>,
+    /// Capabilities toggled per tenant and extension, all enabled when unset
+    pub features: Option<FeatureFlagsConfig>,
 }
 
-#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+#[derive(Default, Debug, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct IceServer {
     pub urls: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -83,7 +126,7 @@ pub struct IceServer {
     pub credential: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
 pub struct UseragentConfig {
     pub addr: String,
     pub udp_port: u16,
@@ -97,7 +140,7 @@ pub struct UseragentConfig {
     pub accept_timeout: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type")]
 pub enum InviteHandlerConfig {
@@ -108,7 +151,7 @@ pub enum InviteHandlerConfig {
     },
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum UserBackendConfig {
@@ -136,7 +179,48 @@ pub enum UserBackendConfig {
     },
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+/// Where the `auth` module checks credentials before the user backend
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum AuthBackendConfig {
+    /// Credentials listed in the config
+    Static { users: Vec<SipUser> },
+    /// A webhook sent the digest of each request, which answers with the
+    /// user when it checks out
+    Http {
+        url: String,
+        headers: Option<HashMap<String, String>>,
+        /// Seconds to wait for the answer, 5 by default
+        timeout: Option<u64>,
+    },
+    /// A directory keeping the SIP passwords of its users
+    Ldap(LdapAuthConfig),
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct LdapAuthConfig {
+    /// `ldap://host:port`, or `ldaps://host:port` for TLS, the default
+    /// port when left out
+    pub url: String,
+    /// Upgrade an `ldap://` connection with StartTLS
+    pub starttls: Option<bool>,
+    /// Simple bind before searching, anonymous when unset
+    pub bind_dn: Option<String>,
+    pub bind_password: Option<String>,
+    /// Where users are searched, with the whole subtree
+    pub base_dn: String,
+    /// Attribute matching the username, `uid` when unset
+    pub username_attribute: Option<String>,
+    /// Attribute holding the plain SIP password, `sipPassword` when unset
+    pub password_attribute: Option<String>,
+    /// Attribute of the display name, `cn` when unset
+    pub display_name_attribute: Option<String>,
+    /// Seconds connecting and each operation may take, 5 by default
+    pub timeout: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum LocatorConfig {
@@ -154,7 +238,7 @@ pub enum LocatorConfig {
     },
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
 #[serde(rename_all = "snake_case")]
 pub enum S3Vendor {
     Aliyun,
@@ -166,7 +250,7 @@ pub enum S3Vendor {
     DigitalOcean,
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum CallRecordConfig {
@@ -190,7 +274,731 @@ pub enum CallRecordConfig {
     },
 }
 
-#[derive(Debug, Deserialize, Clone, Copy, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct CallRecordEnrichConfig {
+    /// Run with the record as JSON on stdin, prints the record to write
+    pub script: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Seconds the script may take
+    #[serde(default = "default_callrecord_enrich_timeout")]
+    pub timeout: u64,
+    /// Dotted paths of fields to mask, e.g. `caller` or `variables.pin`
+    #[serde(default)]
+    pub redact: Vec<String>,
+    #[serde(default = "default_callrecord_enrich_mask")]
+    pub mask: String,
+}
+
+fn default_callrecord_enrich_timeout() -> u64 {
+    5
+}
+
+fn default_callrecord_enrich_mask() -> String {
+    "***".to_string()
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct PostTranscriptionConfig {
+    /// An OpenAI compatible `audio/transcriptions` endpoint, such as a
+    /// whisper server
+    pub url: String,
+    /// Sent as the bearer token
+    pub api_key: Option<String>,
+    #[serde(default = "default_post_transcription_model")]
+    pub model: String,
+    pub language: Option<String>,
+    /// Speaker of each track's half of the channels, in the order the
+    /// recorder met the tracks
+    #[serde(default = "default_post_transcription_speakers")]
+    pub speakers: Vec<String>,
+    /// Recordings transcribed at once
+    #[serde(default = "default_post_transcription_concurrency")]
+    pub concurrency: usize,
+    /// Recordings waiting their turn, the ones past it are not transcribed
+    #[serde(default = "default_post_transcription_queue")]
+    pub queue: usize,
+    /// Seconds the endpoint may take per channel
+    #[serde(default = "default_post_transcription_timeout")]
+    pub timeout: u64,
+    /// Posted the transcript once it is written
+    pub webhook: Option<String>,
+    /// Has the transcript summarized, and the call record sent again with
+    /// the summary
+    pub summary: Option<CallSummaryConfig>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct CallSummaryConfig {
+    /// An OpenAI compatible `chat/completions` endpoint
+    pub url: String,
+    /// Sent as the bearer token
+    pub api_key: Option<String>,
+    #[serde(default = "default_call_summary_model")]
+    pub model: String,
+    /// Sent ahead of the transcript, asks for the JSON of the summary
+    #[serde(default = "default_call_summary_prompt")]
+    pub prompt: String,
+    /// Patterns masked in the transcript before it is sent, e.g.
+    /// `\d{13,19}` for card numbers
+    #[serde(default)]
+    pub redact: Vec<String>,
+    #[serde(default = "default_callrecord_enrich_mask")]
+    pub mask: String,
+    /// Seconds the endpoint may take
+    #[serde(default = "default_call_summary_timeout")]
+    pub timeout: u64,
+}
+
+fn default_call_summary_model() -> String {
+    "gpt-4o-mini".to_string()
+}
+
+fn default_call_summary_prompt() -> String {
+    "Summarize this phone call in a few sentences and name its outcome in a word or two. \
+     Reply with JSON only: {\"summary\": \"...\", \"disposition\": \"...\"}"
+        .to_string()
+}
+
+fn default_call_summary_timeout() -> u64 {
+    30
+}
+
+fn default_post_transcription_model() -> String {
+    "whisper-1".to_string()
+}
+
+fn default_post_transcription_speakers() -> Vec<String> {
+    vec!["caller".to_string(), "callee".to_string()]
+}
+
+fn default_post_transcription_concurrency() -> usize {
+    2
+}
+
+fn default_post_transcription_queue() -> usize {
+    100
+}
+
+fn default_post_transcription_timeout() -> u64 {
+    300
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct JournalConfig {
+    /// Directory of the journal files
+    pub path: String,
+    /// Bytes after which a file is rotated
+    #[serde(default = "default_journal_max_size")]
+    pub max_size: u64,
+    /// Seconds after which a file is rotated
+    #[serde(default = "default_journal_max_age")]
+    pub max_age: u64,
+    /// Gzip the rotated files
+    #[serde(default = "default_journal_compress")]
+    pub compress: bool,
+    /// Rotated files kept, the oldest are deleted. All when unset
+    pub keep: Option<usize>,
+}
+
+fn default_journal_max_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_journal_max_age() -> u64 {
+    3600
+}
+
+fn default_journal_compress() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize, Default)]
+pub struct RadiusConfig {
+    /// Accounting server, `host:port`, port 1813 when left out
+    pub accounting: Option<String>,
+    /// Authentication server asked with an Access-Request before the call
+    /// is placed, port 1812 when left out
+    pub auth: Option<String>,
+    pub secret: String,
+    /// Sent as NAS-Identifier, `rustpbx` when unset
+    pub nas_identifier: Option<String>,
+    /// User-Password of Access-Requests, the caller's number when unset
+    pub password: Option<String>,
+    /// Seconds to wait for each answer
+    #[serde(default = "default_radius_timeout")]
+    pub timeout: u64,
+    /// Requests sent again when unanswered
+    #[serde(default = "default_radius_retries")]
+    pub retries: u32,
+}
+
+fn default_radius_timeout() -> u64 {
+    3
+}
+
+fn default_radius_retries() -> u32 {
+    2
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Copy, Serialize, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SipParseMode {
+    /// Reject anything the SIP parser does not accept as is
+    Strict,
+    /// Repair what RFC 4475 torture messages throw at us, and drop single
+    /// unparseable headers instead of the whole message
+    #[default]
+    Lenient,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Copy, Serialize, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NatKeepaliveMethod {
+    /// Double CRLF ping (RFC 5626), no response expected
+    #[default]
+    Crlf,
+    /// Out-of-dialog OPTIONS request
+    Options,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct NatKeepaliveConfig {
+    /// Seconds between keepalives
+    #[serde(default = "default_nat_keepalive_interval")]
+    pub interval: u64,
+    #[serde(default)]
+    pub method: NatKeepaliveMethod,
+    /// Only ping UAs whose Contact differs from the address the REGISTER came from
+    #[serde(default = "default_nat_keepalive_nat_only")]
+    pub nat_only: bool,
+}
+
+fn default_nat_keepalive_interval() -> u64 {
+    25
+}
+
+fn default_nat_keepalive_nat_only() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct SipHardeningConfig {
+    /// Server header sent on responses in place of the User-Agent
+    #[serde(default)]
+    pub server: Option<String>,
+    /// Strip User-Agent and Server from everything we send
+    #[serde(default)]
+    pub hide_identity: bool,
+    /// Answer requests with broken mandatory headers with a bare 400
+    #[serde(default = "default_hardening_reject_malformed")]
+    pub reject_malformed: bool,
+    /// User-Agent substrings of scanners whose requests are dropped unanswered
+    #[serde(default = "default_hardening_block_user_agents")]
+    pub block_user_agents: Vec<String>,
+}
+
+fn default_hardening_reject_malformed() -> bool {
+    true
+}
+
+fn default_hardening_block_user_agents() -> Vec<String> {
+    [
+        "friendly-scanner",
+        "sipvicious",
+        "sipcli",
+        "sip-scan",
+        "VaxSIPUserAgent",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
+}
+
+impl Default for SipHardeningConfig {
+    fn default() -> Self {
+        Self {
+            server: None,
+            hide_identity: false,
+            reject_malformed: default_hardening_reject_malformed(),
+            block_user_agents: default_hardening_block_user_agents(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct CampOnConfig {
+    /// Dialled as `<code><extension>`, or alone for the last busy extension
+    #[serde(default = "default_camp_on_feature_code")]
+    pub feature_code: String,
+    /// Seconds a callback request stays queued
+    #[serde(default = "default_camp_on_expires")]
+    pub expires: u64,
+}
+
+fn default_camp_on_feature_code() -> String {
+    "*6".to_string()
+}
+
+fn default_camp_on_expires() -> u64 {
+    1800
+}
+
+/// Caps on concurrent calls and calls per second, unset means unlimited
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize, Default)]
+pub struct CallLimitConfig {
+    pub max_calls: Option<u32>,
+    pub max_cps: Option<u32>,
+}
+
+/// B2BUA calls whose two endpoints are in the trusted networks exchange
+/// their RTP directly once answered
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize, Default)]
+pub struct DirectMediaConfig {
+    /// Addresses or networks, e.g. `10.0.0.0/8`
+    #[serde(default)]
+    pub trusted: Vec<String>,
+    /// Seconds to wait for the answer to a direct media re-INVITE, the 32s
+    /// of the transaction by default
+    pub reinvite_timeout: Option<u64>,
+    /// What the call does once a direct media re-INVITE failed, the media
+    /// is kept as it was before either way
+    #[serde(default)]
+    pub on_failure: DirectMediaFailureAction,
+    /// Played to the caller with `on_failure = "prompt"`
+    pub failure_prompt: Option<String>,
+    /// Cause of the calls hung up with `on_failure = "hangup"`,
+    /// `bearer_capability_not_available` by default
+    pub failure_cause: Option<HangupCause>,
+}
+
+/// What a call does when taking its media out of rustpbx, or back in,
+/// failed. Other re-INVITEs and UPDATEs leave it to their callers
+#[derive(Debug, Deserialize, JsonSchema, Clone, Copy, Serialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DirectMediaFailureAction {
+    /// Carry on with the media as it was
+    #[default]
+    Keep,
+    /// Carry on and play `failure_prompt` to the caller
+    Prompt,
+    /// Hang up the call with `failure_cause`
+    Hangup,
+}
+
+/// How B2BUA calls relay DTMF between their legs, a digit received from
+/// one leg goes to the other in the other's mode
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize, Default)]
+pub struct DtmfInterworkingConfig {
+    /// Mode of the caller leg, negotiated from its SDP when unset
+    pub caller: Option<DtmfMode>,
+    /// Mode of the callee leg, negotiated from its SDP when unset
+    pub callee: Option<DtmfMode>,
+}
+
+/// Recording consent, by the jurisdiction of the parties of the calls the
+/// proxy records
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize, Default)]
+pub struct RecordingConsentConfig {
+    /// Tried in order, the first one the caller or the callee is in applies.
+    /// Calls in none are recorded without consent
+    #[serde(default)]
+    pub jurisdictions: Vec<ConsentJurisdiction>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct ConsentJurisdiction {
+    pub name: String,
+    /// Numbers of the region by prefix, e.g. `+1415`
+    #[serde(default)]
+    pub prefixes: Vec<String>,
+    /// Countries of the region, of the caller's address by `proxy.geoip`
+    #[serde(default)]
+    pub countries: Vec<String>,
+    /// Played to the caller once the call is answered
+    #[serde(default = "default_consent_prompt")]
+    pub prompt: Vec<PromptSegment>,
+    /// Key the caller presses to consent, recording is blocked unless they
+    /// do. The prompt only notifies them when unset
+    pub accept_key: Option<String>,
+    /// Wait for the key after the prompt, in ms
+    #[serde(default = "default_consent_timeout")]
+    pub timeout: u32,
+}
+
+fn default_consent_prompt() -> Vec<PromptSegment> {
+    vec![PromptSegment::Text {
+        text: "This call may be recorded.".to_string(),
+    }]
+}
+
+fn default_consent_timeout() -> u32 {
+    5000
+}
+
+impl DirectMediaConfig {
+    pub(crate) fn trusted_networks(&self) -> Vec<IpNetwork> {
+        self.trusted
+            .iter()
+            .filter_map(|network| match parse_network(network) {
+                Ok((addr, prefix_len)) => Some(IpNetwork::new(addr, prefix_len)),
+                Err(_) => {
+                    warn!(network, "invalid direct media network");
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct CallLimitsConfig {
+    /// Across all calls through the proxy
+    #[serde(flatten)]
+    pub global: CallLimitConfig,
+    /// Applied to every local extension, as caller and as callee
+    #[serde(default)]
+    pub extension: CallLimitConfig,
+    /// Per extension overrides, keyed by username
+    #[serde(default)]
+    pub extensions: HashMap<String, CallLimitConfig>,
+    /// Seconds sent in `Retry-After` when a call is refused
+    #[serde(default = "default_limit_retry_after")]
+    pub retry_after: u32,
+}
+
+fn default_limit_retry_after() -> u32 {
+    5
+}
+
+impl Default for CallLimitsConfig {
+    fn default() -> Self {
+        Self {
+            global: CallLimitConfig::default(),
+            extension: CallLimitConfig::default(),
+            extensions: HashMap::new(),
+            retry_after: default_limit_retry_after(),
+        }
+    }
+}
+
+/// Nodes sharing the database locator as one registrar. Each node
+/// heartbeats into the database, and the bindings of a node that stopped
+/// are taken over by the first live node
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct ClusterConfig {
+    /// Unique in the cluster, e.g. the host name
+    pub node_id: String,
+    /// Seconds between heartbeats, 5 by default
+    #[serde(default = "default_cluster_heartbeat_interval")]
+    pub heartbeat_interval: u64,
+    /// Seconds without a heartbeat before a node is down, 15 by default
+    #[serde(default = "default_cluster_node_timeout")]
+    pub node_timeout: u64,
+}
+
+fn default_cluster_heartbeat_interval() -> u64 {
+    5
+}
+
+fn default_cluster_node_timeout() -> u64 {
+    15
+}
+
+/// Countries of networks, for routing on the country calls come from
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize, Default)]
+pub struct GeoIpConfig {
+    /// Lines of `network,country`, e.g. `1.0.1.0/24,CN`, exported from a
+    /// GeoIP database. `#` starts a comment
+    pub file: Option<String>,
+    /// Networks by ISO country code, e.g. `{ CN = ["1.0.1.0/24"] }`
+    #[serde(default)]
+    pub networks: HashMap<String, Vec<String>>,
+}
+
+/// Scores the outbound calls of local extensions by rules, the scores of
+/// the rules a call breaks add up
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct FraudConfig {
+    /// Calls scoring this much or more are reported as `fraudAlert`
+    #[serde(default = "default_fraud_alert_score")]
+    pub alert_score: u32,
+    /// Calls scoring this much or more are refused with 403, none when unset
+    pub block_score: Option<u32>,
+    /// Risky destinations, e.g. premium rate or satellite ranges
+    #[serde(default)]
+    pub destinations: Vec<DestinationRisk>,
+    /// Too many calls of an extension in a short time
+    pub velocity: Option<VelocityRule>,
+    /// A spike of calls of an extension outside business hours
+    pub after_hours: Option<AfterHoursRule>,
+    /// Too many international calls of an extension at once
+    pub international: Option<InternationalRule>,
+}
+
+fn default_fraud_alert_score() -> u32 {
+    50
+}
+
+/// Scores calls to numbers starting with `prefix`, the longest prefix
+/// matching a number applies
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct DestinationRisk {
+    /// e.g. `+882` or `00882`
+    pub prefix: String,
+    pub score: u32,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct VelocityRule {
+    /// Calls allowed in `window`, the calls over it score
+    pub max_calls: u32,
+    /// Seconds, 60 by default
+    #[serde(default = "default_velocity_window")]
+    pub window: u64,
+    pub score: u32,
+}
+
+fn default_velocity_window() -> u64 {
+    60
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct AfterHoursRule {
+    pub business_hours: CallingHours,
+    /// Time zone of the business hours, e.g. `+08:00`, UTC when unset
+    pub utc_offset: Option<String>,
+    /// Calls allowed after hours in `window`, the calls over it score.
+    /// Every call after hours scores by default
+    #[serde(default)]
+    pub max_calls: u32,
+    /// Seconds, an hour by default
+    #[serde(default = "default_after_hours_window")]
+    pub window: u64,
+    pub score: u32,
+}
+
+fn default_after_hours_window() -> u64 {
+    3600
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct InternationalRule {
+    /// Numbers starting with one of these are international, `00` and `+`
+    /// by default
+    #[serde(default = "default_international_prefixes")]
+    pub prefixes: Vec<String>,
+    /// International calls an extension may have at once, the calls over
+    /// it score
+    pub max_calls: u32,
+    pub score: u32,
+}
+
+fn default_international_prefixes() -> Vec<String> {
+    vec!["00".to_string(), "+".to_string()]
+}
+
+/// Periodic export of channel usage per tenant and trunk
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct MeteringConfig {
+    /// Seconds between usage snapshots
+    #[serde(default = "default_metering_interval")]
+    pub interval: u64,
+    /// Directory the snapshots are written to as JSON files
+    pub path: Option<String>,
+    /// URL the snapshots are POSTed to as JSON
+    pub url: Option<String>,
+    /// Tenant name per realm, other realms are their own tenant
+    #[serde(default)]
+    pub tenants: HashMap<String, String>,
+}
+
+fn default_metering_interval() -> u64 {
+    300
+}
+
+/// Resolution of SIP destinations (RFC 3263) and failover between them
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct DnsConfig {
+    /// Seconds a destination is tried last after it failed, 0 to disable
+    #[serde(default = "default_dns_blacklist_ttl")]
+    pub blacklist_ttl: u64,
+    /// Try IPv6 addresses of a host before its IPv4 ones
+    #[serde(default)]
+    pub prefer_ipv6: bool,
+}
+
+fn default_dns_blacklist_ttl() -> u64 {
+    60
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        Self {
+            blacklist_ttl: default_dns_blacklist_ttl(),
+            prefer_ipv6: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct CampaignConfig {
+    /// Numbers never dialled, one per line, `#` starts a comment. Read
+    /// again for each campaign
+    pub dnc_file: Option<String>,
+    #[serde(default)]
+    pub dnc: Vec<String>,
+    /// Share of answered calls left without an agent, above which a
+    /// campaign stops dialling ahead of its agents
+    #[serde(default = "default_campaign_max_abandon_rate")]
+    pub max_abandon_rate: f64,
+}
+
+fn default_campaign_max_abandon_rate() -> f64 {
+    0.03
+}
+
+impl Default for CampaignConfig {
+    fn default() -> Self {
+        Self {
+            dnc_file: None,
+            dnc: Vec::new(),
+            max_abandon_rate: default_campaign_max_abandon_rate(),
+        }
+    }
+}
+
+/// Callers dialling `extension` are answered and wait for the first idle
+/// agent
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct QueueConfig {
+    pub extension: String,
+    /// Rung in turn, as SIP URIs or users of the realm the queue was
+    /// dialled in
+    #[serde(default)]
+    pub agents: Vec<String>,
+    /// Played in a loop while the caller waits
+    pub music: Option<String>,
+    /// Offer to call back instead of waiting
+    pub callback: Option<QueueCallbackConfig>,
+    /// Skills an agent needs to take the queue's calls, e.g.
+    /// `lang:es & (billing | sales)`
+    pub requires: Option<String>,
+    /// Looser requirements once callers have waited long enough
+    #[serde(default)]
+    pub overflow: Vec<QueueOverflowConfig>,
+    /// Only agents logged in take calls, otherwise agents without a
+    /// session do too
+    #[serde(default)]
+    pub login: bool,
+    /// Seconds after a call before the agent takes the next one
+    #[serde(default)]
+    pub wrap_up: u64,
+    /// Seconds within which calls count as answered in time, for the
+    /// service level of the wallboard
+    #[serde(default = "default_queue_sla")]
+    pub sla: u64,
+}
+
+fn default_queue_sla() -> u64 {
+    20
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct QueueOverflowConfig {
+    /// Seconds of waiting
+    pub after: u64,
+    /// Replaces the skills the call requires, blank for any agent
+    #[serde(default)]
+    pub requires: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize, Default)]
+pub struct AgentConfig {
+    #[serde(default)]
+    pub skills: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct QueueCallbackConfig {
+    /// Seconds of waiting before the offer is made
+    #[serde(default = "default_queue_callback_after")]
+    pub after: u64,
+    /// Takes the offer, and confirms the number
+    #[serde(default = "default_queue_callback_key")]
+    pub key: String,
+    /// Asks whether to be called back, `{queue.key}` is the key
+    #[serde(default = "default_queue_callback_offer")]
+    pub offer: Vec<PromptSegment>,
+    /// Reads `{callback.number}` back. The key confirms it, other digits
+    /// ending with `#` replace it
+    #[serde(default = "default_queue_callback_confirm")]
+    pub confirm: Vec<PromptSegment>,
+    /// Played before hanging up once the callback is booked
+    #[serde(default = "default_queue_callback_booked")]
+    pub booked: Vec<PromptSegment>,
+}
+
+fn default_queue_callback_after() -> u64 {
+    60
+}
+
+fn default_queue_callback_key() -> String {
+    "1".to_string()
+}
+
+fn default_queue_callback_offer() -> Vec<PromptSegment> {
+    vec![PromptSegment::Text {
+        text: "To be called back when an agent is free, press {queue.key}.".to_string(),
+    }]
+}
+
+fn default_queue_callback_confirm() -> Vec<PromptSegment> {
+    vec![PromptSegment::Text {
+        text: "We will call you back at {callback.number:digits}. Press {queue.key} to confirm, \
+               or enter another number followed by the pound key."
+            .to_string(),
+    }]
+}
+
+fn default_queue_callback_booked() -> Vec<PromptSegment> {
+    vec![PromptSegment::Text {
+        text: "Thank you, an agent will call you back. Goodbye.".to_string(),
+    }]
+}
+
+impl Default for QueueCallbackConfig {
+    fn default() -> Self {
+        Self {
+            after: default_queue_callback_after(),
+            key: default_queue_callback_key(),
+            offer: default_queue_callback_offer(),
+            confirm: default_queue_callback_confirm(),
+            booked: default_queue_callback_booked(),
+        }
+    }
+}
+
+impl Default for CampOnConfig {
+    fn default() -> Self {
+        Self {
+            feature_code: default_camp_on_feature_code(),
+            expires: default_camp_on_expires(),
+        }
+    }
+}
+
+impl Default for NatKeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            interval: default_nat_keepalive_interval(),
+            method: NatKeepaliveMethod::default(),
+            nat_only: default_nat_keepalive_nat_only(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Copy, Serialize)]
 #[serde(rename_all = "snake_case")]
 #[derive(PartialEq)]
 pub enum MediaProxyMode {
@@ -210,7 +1018,7 @@ impl Default for MediaProxyMode {
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
 pub struct ProxyConfig {
     pub modules: Option<Vec<String>>,
     pub addr: String,
@@ -232,8 +1040,13 @@ pub struct ProxyConfig {
     pub registrar_expires: Option<u32>,
     #[serde(default)]
     pub user_backend: UserBackendConfig,
+    /// Checks credentials before the user backend, e.g. in a directory
+    pub auth_backend: Option<AuthBackendConfig>,
     #[serde(default)]
     pub locator: LocatorConfig,
+    /// Runs the registrar as one of several nodes, needs the `database`
+    /// locator
+    pub cluster: Option<ClusterConfig>,
     #[serde(default)]
     pub media_proxy: MediaProxyMode,
     #[serde(default)]
@@ -245,16 +1058,347 @@ pub struct ProxyConfig {
     pub trunks: HashMap<String, TrunkConfig>,
     #[serde(default)]
     pub default: Option<DefaultRoute>,
+    /// Countries of source addresses, for the `source.country` condition
+    /// of routes
+    pub geoip: Option<GeoIpConfig>,
+    /// Days off by calendar name, `YYYY-MM-DD` or `MM-DD` for every year,
+    /// for the `holidays` of the time conditions of routes
+    #[serde(default)]
+    pub holidays: HashMap<String, Vec<String>>,
+    /// Answers or rejects the calls the trunks of their route are too busy
+    /// for, 503 with the trunk's reason when unset
+    pub circuits_busy: Option<CircuitsBusyConfig>,
+    /// URL that receives MESSAGE delivery events as JSON
+    pub message_webhook: Option<String>,
+    /// Keep NAT bindings of registered UAs open
+    pub nat_keepalive: Option<NatKeepaliveConfig>,
+    /// Identity header control and early rejection of malformed requests
+    pub hardening: Option<SipHardeningConfig>,
+    /// How forgiving the in-process SIP parser is
+    #[serde(default)]
+    pub sip_parse_mode: SipParseMode,
+    /// Call-back on busy, used by the `campon` module
+    pub camp_on: Option<CampOnConfig>,
+    /// Caps on concurrent calls and call rate, per trunk caps are in `trunks`
+    pub call_limits: Option<CallLimitsConfig>,
+    /// Scoring of outbound calls, to report or refuse likely fraud
+    pub fraud: Option<FraudConfig>,
+    /// Channel usage accounting export
+    pub metering: Option<MeteringConfig>,
+    /// DNS resolution of destinations, on by default
+    pub dns: Option<DnsConfig>,
+    /// Do-not-call list and defaults of outbound campaigns
+    pub campaign: Option<CampaignConfig>,
+    /// Call queues by name, used by the `queue` module
+    #[serde(default)]
+    pub queues: HashMap<String, QueueConfig>,
+    /// Queue agents by user, `user@realm` or SIP URI
+    #[serde(default)]
+    pub agents: HashMap<String, AgentConfig>,
+    /// Reasons agents may give for a pause, any when empty
+    #[serde(default)]
+    pub pause_reasons: Vec<String>,
+    /// Seconds between wallboard updates, 5 by default
+    pub wallboard_interval: Option<u64>,
+    /// Lets the media of calls between trusted endpoints bypass rustpbx
+    pub direct_media: Option<DirectMediaConfig>,
+    /// Relays DTMF between the legs of B2BUA calls, in-band, RFC 4733 or
+    /// SIP INFO as each leg takes it
+    pub dtmf: Option<DtmfInterworkingConfig>,
+    /// Calls the proxy originates, click-to-dial, queues and campaigns,
+    /// send their INVITEs without SDP and answer the offer in the ACK
+    pub late_offer: Option<bool>,
+    /// Asks the parties of the calls it records for consent, where the law
+    /// requires it
+    pub recording_consent: Option<RecordingConsentConfig>,
 }
 
 pub enum RouteResult {
     Forward(InviteOption),
     Abort(u16, String),
+    /// Answers the caller, plays the audio and hangs up
+    Announce(String),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+fn default_media_nat_stun_interval() -> u64 {
+    300
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize, Default)]
+pub struct MediaNatConfig {
+    /// Public addresses of local port ranges, for a NAT forwarding ports one
+    /// to one like a Kubernetes NodePort or load balancer
+    #[serde(default)]
+    pub mappings: Vec<PortMapping>,
+    /// Splits `rtp_start_port..rtp_end_port` into slices of this many ports,
+    /// one per pod, so pods behind one public IP never pick the same port
+    pub pod_ports: Option<u16>,
+    /// The pod's slice, the ordinal ending the hostname when unset, like
+    /// `rustpbx-2` of a StatefulSet
+    pub pod_index: Option<u16>,
+    /// Finds the public IP of the ports with no mapping, `host:port`
+    pub stun_server: Option<String>,
+    /// Seconds between two STUN lookups
+    #[serde(default = "default_media_nat_stun_interval")]
+    pub stun_interval: u64,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize, Default)]
+pub struct MediaWorkersConfig {
+    /// Worker threads, one per CPU the workers may use when unset
+    pub threads: Option<usize>,
+    /// CPUs the workers are pinned to in turn, a Linux CPU list like
+    /// `0-3,8-11`
+    pub cpus: Option<String>,
+    /// NUMA node whose CPUs the workers are pinned to, e.g. the node of the
+    /// network card, when `cpus` is unset (Linux only)
+    pub numa_node: Option<usize>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct PortMapping {
+    /// First local port of the range
+    pub start: u16,
+    /// Last local port of the range
+    pub end: u16,
+    pub external_ip: String,
+    /// Public port of `start`, the local one when unset
+    pub external_port: Option<u16>,
+}
+
+/// DSCP code points, by name like `ef` and `af31` or as numbers
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct QosConfig {
+    /// RTP sent by call tracks
+    #[serde(default = "default_qos_media", deserialize_with = "deserialize_dscp")]
+    #[schemars(with = "Dscp")]
+    pub media: u8,
+    /// SIP over UDP from the proxy and the user agent
+    #[serde(
+        default = "default_qos_signaling",
+        deserialize_with = "deserialize_dscp"
+    )]
+    #[schemars(with = "Dscp")]
+    pub signaling: u8,
+}
+
+fn default_qos_media() -> u8 {
+    46 // EF
+}
+
+fn default_qos_signaling() -> u8 {
+    26 // AF31
+}
+
+impl Default for QosConfig {
+    fn default() -> Self {
+        Self {
+            media: default_qos_media(),
+            signaling: default_qos_signaling(),
+        }
+    }
+}
+
+/// RFC 3261 transaction timers, in milliseconds
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize, PartialEq)]
+pub struct SipTimersConfig {
+    /// Round-trip time estimate, the first retransmission interval
+    #[serde(default = "default_timer_t1")]
+    pub t1: u64,
+    /// Longest retransmission interval of 2xx responses to INVITE
+    #[serde(default = "default_timer_t2")]
+    pub t2: u64,
+    /// Timeout of client transactions (Timer B and F), 64*T1 when unset
+    pub timer_b: Option<u64>,
+}
+
+fn default_timer_t1() -> u64 {
+    500
+}
+
+fn default_timer_t2() -> u64 {
+    4000
+}
+
+impl Default for SipTimersConfig {
+    fn default() -> Self {
+        Self {
+            t1: default_timer_t1(),
+            t2: default_timer_t2(),
+            timer_b: None,
+        }
+    }
+}
+
+impl SipTimersConfig {
+    pub fn t1(&self) -> Duration {
+        Duration::from_millis(self.t1.max(1))
+    }
+
+    pub fn t2(&self) -> Duration {
+        Duration::from_millis(self.t2).max(self.t1())
+    }
+
+    /// Timer H and J: 64*T1
+    pub fn t1x64(&self) -> Duration {
+        self.t1() * 64
+    }
+
+    pub fn timer_b(&self) -> Duration {
+        self.timer_b
+            .map(Duration::from_millis)
+            .unwrap_or_else(|| self.t1x64())
+    }
+
+    /// Timers of an rsipstack endpoint. Its server INVITE transaction waits
+    /// `t4` for the ACK of a final response, which is Timer H
+    pub fn endpoint_option(&self) -> EndpointOption {
+        EndpointOption {
+            t1: self.t1(),
+            t4: self.t1x64(),
+            t1x64: self.t1x64(),
+            timerb: self.timer_b(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Flow states saved under `path`, shared by the nodes a flow may resume on
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize, Default)]
+pub struct FlowConfig {
+    /// Flow states are kept with their call only when unset
+    pub path: Option<String>,
+    /// States not updated for this long (in seconds) are not resumed,
+    /// a day when unset
+    pub expire: Option<u64>,
+    /// Variant weights by experiment, e.g. `greeting = { short = 1, long = 1 }`
+    #[serde(default)]
+    pub experiments: HashMap<String, HashMap<String, u32>>,
+}
+
+/// Features of the calls by name, like `recording`, `asr` and
+/// `international`. A feature no scope lists is enabled
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
+pub struct FeatureFlagsConfig {
+    /// Flags of every call
+    #[serde(default)]
+    pub defaults: HashMap<String, bool>,
+    /// Flags of the calls of a tenant, over the defaults
+    #[serde(default)]
+    pub tenants: HashMap<String, HashMap<String, bool>>,
+    /// Flags of the calls of an extension, `user@realm`, over its tenant's
+    #[serde(default)]
+    pub extensions: HashMap<String, HashMap<String, bool>>,
+    /// Numbers dialed with these prefixes need the `international` feature
+    #[serde(default = "default_international_prefixes")]
+    pub international_prefixes: Vec<String>,
+}
+
+impl Default for FeatureFlagsConfig {
+    fn default() -> Self {
+        Self {
+            defaults: HashMap::new(),
+            tenants: HashMap::new(),
+            extensions: HashMap::new(),
+            international_prefixes: default_international_prefixes(),
+        }
+    }
+}
+
+/// Recordings under `path/<locale>/<name>.wav` (or `.mp3`) and text
+/// prompts spoken by the TTS where a locale has no recording
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize, Default)]
+pub struct PromptPackConfig {
+    pub path: Option<String>,
+    /// Last locale tried, `en` when unset
+    pub default_locale: Option<String>,
+    /// Locales tried after a locale and before its language, e.g.
+    /// `"pt-BR" = ["pt-PT"]`
+    #[serde(default)]
+    pub fallback: HashMap<String, Vec<String>>,
+    /// Text prompts by locale, then by name
+    #[serde(default)]
+    pub texts: HashMap<String, HashMap<String, String>>,
+}
+
+impl PromptPackConfig {
+    pub fn default_locale(&self) -> &str {
+        self.default_locale.as_deref().unwrap_or("en")
+    }
+
+    /// The locales a prompt is looked up in: the locale, its fallbacks, its
+    /// language, then the default locale
+    pub fn locale_chain(&self, locale: &str) -> Vec<String> {
+        let mut chain: Vec<String> = Vec::new();
+        let mut push = |locale: &str| {
+            if !chain.iter().any(|l| l.eq_ignore_ascii_case(locale)) {
+                chain.push(locale.to_string());
+            }
+        };
+        push(locale);
+        for fallback in self.fallback.get(locale).into_iter().flatten() {
+            push(fallback);
+        }
+        if let Some((language, _)) = locale.split_once(['-', '_']) {
+            push(language);
+        }
+        push(self.default_locale());
+        chain
+    }
+}
+
+/// A DSCP value, or its name like `ef` or `af31`
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum Dscp {
+    Number(u8),
+    Name(String),
+}
+
+fn deserialize_dscp<'de, D>(deserializer: D) -> Result<u8, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = match Dscp::deserialize(deserializer)? {
+        Dscp::Number(dscp) => dscp.to_string(),
+        Dscp::Name(name) => name,
+    };
+    crate::net_tool::parse_dscp(&value).map_err(serde::de::Error::custom)
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
 pub struct AmiConfig {
     pub allows: Option<Vec<String>>,
+    /// Bearer tokens for clients not in `allows`, also needed by the call
+    /// WebSockets once set
+    pub jwt: Option<JwtConfig>,
+}
+
+/// HMAC signed JWTs
+#[derive(Debug, Deserialize, JsonSchema, Serialize, Clone)]
+pub struct JwtConfig {
+    pub secret: String,
+    /// The only algorithm tokens may be signed with, HS256, HS384 or HS512
+    #[serde(default = "default_jwt_algorithm")]
+    pub algorithm: String,
+    /// Required `iss` claim, any when unset
+    pub issuer: Option<String>,
+    /// Required in the `aud` claim, any when unset
+    pub audience: Option<String>,
+    /// Seconds of clock skew allowed on `exp` and `nbf`
+    #[serde(default = "default_jwt_leeway")]
+    pub leeway: u64,
+    /// Accept tokens without an `exp` claim, which never expire
+    #[serde(default)]
+    pub allow_no_expiry: bool,
+}
+
+fn default_jwt_algorithm() -> String {
+    "HS256".to_string()
+}
+
+fn default_jwt_leeway() -> u64 {
+    60
 }
 
 impl AmiConfig {
@@ -271,6 +1415,7 @@ impl Default for AmiConfig {
     fn default() -> Self {
         Self {
             allows: Some(vec!["127.0.0.1".to_string(), "::1".to_string()]), // Default to allow localhost
+            jwt: None,
         }
     }
 }
@@ -296,6 +1441,7 @@ impl Default for ProxyConfig {
                 "acl".to_string(),
                 "auth".to_string(),
                 "registrar".to_string(),
+                "message".to_string(),
                 "call".to_string(),
             ]),
             external_ip: None,
@@ -310,6 +1456,7 @@ impl Default for ProxyConfig {
             max_concurrency: None,
             registrar_expires: Some(60),
             user_backend: UserBackendConfig::default(),
+            auth_backend: None,
             locator: LocatorConfig::default(),
             media_proxy: MediaProxyMode::default(),
             realms: Some(vec![]),
@@ -317,6 +1464,28 @@ impl Default for ProxyConfig {
             routes: None,
             trunks: HashMap::new(),
             default: None,
+            message_webhook: None,
+            nat_keepalive: None,
+            hardening: None,
+            sip_parse_mode: SipParseMode::default(),
+            camp_on: None,
+            call_limits: None,
+            fraud: None,
+            geoip: None,
+            holidays: HashMap::new(),
+            circuits_busy: None,
+            cluster: None,
+            metering: None,
+            dns: None,
+            campaign: None,
+            queues: HashMap::new(),
+            agents: HashMap::new(),
+            pause_reasons: Vec::new(),
+            wallboard_interval: None,
+            direct_media: None,
+            dtmf: None,
+            late_offer: None,
+            recording_consent: None,
         }
     }
 }
@@ -363,6 +1532,7 @@ impl Default for Config {
     fn default() -> Self {
         Self {
             http_addr: default_config_http_addr(),
+            grpc_addr: None,
             log_level: None,
             log_file: None,
             ua: Some(UseragentConfig::default()),
@@ -370,13 +1540,26 @@ impl Default for Config {
             recorder_path: default_config_recorder_path(),
             media_cache_path: default_config_media_cache_path(),
             callrecord: None,
+            callrecord_enrich: None,
+            post_transcription: None,
+            journal: None,
             llmproxy: None,
             restsend_token: None,
             ice_servers: None,
             ami: Some(AmiConfig::default()),
+            qos: None,
+            sip_timers: None,
+            prompts: None,
+            flow: None,
+            features: None,
             external_ip: None,
             rtp_start_port: default_config_rtp_start_port(),
             rtp_end_port: default_config_rtp_end_port(),
+            rtp_shaping: None,
+            rtp_rewrite: None,
+            rtp_audio_level: None,
+            media_nat: None,
+            media_workers: None,
         }
     }
 }
@@ -428,6 +1611,7 @@ mod tests {
                 ..Default::default()
             }),
             action: crate::proxy::routing::RouteAction::default(),
+            transrating: None,
             disabled: None,
         });
         routes.push(crate::proxy::routing::RouteRule {
@@ -443,6 +1627,7 @@ mod tests {
                 ..Default::default()
             }),
             action: crate::proxy::routing::RouteAction::default(),
+            transrating: None,
             disabled: None,
         });
         prxconfig.routes = Some(routes);
@@ -459,4 +1644,34 @@ mod tests {
         let config_str = toml::to_string(&config).unwrap();
         println!("{}", config_str);
     }
+
+    #[test]
+    fn test_qos_config() {
+        let qos: QosConfig = toml::from_str("media = \"cs5\"").unwrap();
+        assert_eq!(qos.media, 40);
+        assert_eq!(qos.signaling, 26);
+        let qos: QosConfig = toml::from_str("media = 34\nsignaling = \"AF21\"").unwrap();
+        assert_eq!((qos.media, qos.signaling), (34, 18));
+        assert!(toml::from_str::<QosConfig>("media = \"af5\"").is_err());
+    }
+
+    #[test]
+    fn test_prompt_locale_chain() {
+        let prompts: PromptPackConfig = toml::from_str(
+            r#"
+            default_locale = "en-US"
+            [fallback]
+            "pt-BR" = ["pt-PT"]
+            [texts.en-US]
+            welcome = "Welcome"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            prompts.locale_chain("pt-BR"),
+            ["pt-BR", "pt-PT", "pt", "en-US"]
+        );
+        assert_eq!(prompts.locale_chain("en-US"), ["en-US", "en"]);
+        assert_eq!(prompts.texts["en-US"]["welcome"], "Welcome");
+    }
 }
@@ -0,0 +1,347 @@
+//! JSON Schema of the configuration and checks of config files.
+//!
+//! The schema is derived from the config types with `schemars`: doc comments
+//! become descriptions, serde defaults the `default` of each key and
+//! internally tagged enums a `oneOf` of their variants.
+use crate::config::Config;
+use schemars::generate::SchemaSettings;
+use serde_json::{Value, json};
+use std::fmt;
+use toml::de::{DeTable, DeValue};
+
+/// JSON Schema of the configuration file
+pub fn config_schema() -> Value {
+    let generator = SchemaSettings::draft2020_12()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator();
+    let mut schema = generator.into_root_schema_for::<Config>().to_value();
+    if let Value::Object(schema) = &mut schema {
+        schema.insert("title".to_string(), json!("rustpbx configuration"));
+    }
+    schema
+}
+
+/// An error or a warning about a config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigIssue {
+    /// Dotted path of the key, empty for the whole file
+    pub path: String,
+    /// Line and column, from 1
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    /// Warnings do not stop the config from loading
+    pub warning: bool,
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = if self.warning { "warning" } else { "error" };
+        match self.path.is_empty() {
+            true => write!(
+                f,
+                "{}:{}: {}: {}",
+                self.line, self.column, level, self.message
+            ),
+            false => write!(
+                f,
+                "{}:{}: {}: {}: {}",
+                self.line, self.column, level, self.path, self.message
+            ),
+        }
+    }
+}
+
+/// Line and column, from 1, of a byte offset of `raw`
+fn position(raw: &str, offset: usize) -> (usize, usize) {
+    let before = &raw[..offset.min(raw.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
+
+/// Checks a config file: syntax errors and values the config cannot load
+/// are errors, keys the config does not know are warnings
+pub fn check_config(raw: &str) -> Vec<ConfigIssue> {
+    let table = match DeTable::parse(raw) {
+        Ok(table) => table,
+        Err(e) => {
+            let (line, column) = position(raw, e.span().map(|s| s.start).unwrap_or(0));
+            return vec![ConfigIssue {
+                path: String::new(),
+                line,
+                column,
+                message: e.message().to_string(),
+                warning: false,
+            }];
+        }
+    };
+    let mut issues = Vec::new();
+    let loaded = toml::de::Deserializer::parse(raw)
+        .map_err(|e| (String::new(), e))
+        .and_then(|deserializer| {
+            serde_path_to_error::deserialize::<_, Config>(deserializer)
+                .map_err(|e| (e.path().to_string(), e.into_inner()))
+        });
+    if let Err((path, e)) = loaded {
+        let (line, column) = position(raw, e.span().map(|s| s.start).unwrap_or(0));
+        issues.push(ConfigIssue {
+            path: if path == "." { String::new() } else { path },
+            line,
+            column,
+            message: e.message().to_string(),
+            warning: false,
+        });
+    }
+    let schema = config_schema();
+    unknown_keys(raw, table.get_ref(), &schema, &schema, "", &mut issues);
+    issues
+}
+
+fn child(path: &str, key: &str) -> String {
+    match path.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", path, key),
+    }
+}
+
+fn unknown_keys(
+    raw: &str,
+    table: &DeTable<'_>,
+    root: &Value,
+    schema: &Value,
+    path: &str,
+    issues: &mut Vec<ConfigIssue>,
+) {
+    for (key, value) in table.iter() {
+        let name = key.get_ref().as_ref();
+        let Some(schema) = property(root, schema, name) else {
+            let (line, column) = position(raw, key.span().start);
+            issues.push(ConfigIssue {
+                path: child(path, name),
+                line,
+                column,
+                message: "unknown key, ignored".to_string(),
+                warning: true,
+            });
+            continue;
+        };
+        unknown_values(
+            raw,
+            value.get_ref(),
+            root,
+            schema,
+            &child(path, name),
+            issues,
+        );
+    }
+}
+
+fn unknown_values(
+    raw: &str,
+    value: &DeValue<'_>,
+    root: &Value,
+    schema: &Value,
+    path: &str,
+    issues: &mut Vec<ConfigIssue>,
+) {
+    match value {
+        DeValue::Table(table) => unknown_keys(raw, table, root, schema, path, issues),
+        DeValue::Array(items) => {
+            let Some(schema) = items_of(root, schema) else {
+                return;
+            };
+            for (i, item) in items.iter().enumerate() {
+                unknown_values(
+                    raw,
+                    item.get_ref(),
+                    root,
+                    schema,
+                    &format!("{}[{}]", path, i),
+                    issues,
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The schema `schema` refers to, recursive types are left as a `$ref`
+/// into the root's `$defs`
+fn resolve<'a>(root: &'a Value, schema: &'a Value) -> &'a Value {
+    schema
+        .get("$ref")
+        .and_then(Value::as_str)
+        .and_then(|pointer| pointer.strip_prefix('#'))
+        .and_then(|pointer| root.pointer(pointer))
+        .unwrap_or(schema)
+}
+
+/// The alternatives of `schema`: the variants of an enum, or the value and
+/// null of an option. Null takes no keys and is left out
+fn alternatives<'a>(root: &'a Value, schema: &'a Value) -> impl Iterator<Item = &'a Value> {
+    ["oneOf", "anyOf", "allOf"]
+        .into_iter()
+        .filter_map(|key| schema.get(key).and_then(Value::as_array))
+        .flatten()
+        .map(|schema| resolve(root, schema))
+        .filter(|schema| schema.get("type") != Some(&json!("null")))
+}
+
+/// The schema of key `name` in an object described by `schema`, none when
+/// the object is described but has no such key. The key of any variant of
+/// an enum is known
+fn property<'a>(root: &'a Value, schema: &'a Value, name: &str) -> Option<&'a Value> {
+    static ANY: Value = Value::Null;
+    let schema = resolve(root, schema);
+    if let Some(property) = schema.get("properties").and_then(|p| p.get(name)) {
+        return Some(property);
+    }
+    if let Some(additional) = schema.get("additionalProperties") {
+        return match additional {
+            Value::Bool(false) => None,
+            Value::Object(_) => Some(additional),
+            _ => Some(&ANY),
+        };
+    }
+    let mut alternatives = alternatives(root, schema).peekable();
+    if alternatives.peek().is_some() {
+        return alternatives.find_map(|schema| property(root, schema, name));
+    }
+    match schema.get("properties") {
+        Some(_) => None,
+        None => Some(&ANY),
+    }
+}
+
+/// The schema of the items of an array described by `schema`
+fn items_of<'a>(root: &'a Value, schema: &'a Value) -> Option<&'a Value> {
+    let schema = resolve(root, schema);
+    schema
+        .get("items")
+        .or_else(|| alternatives(root, schema).find_map(|schema| items_of(root, schema)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The schema of the key at `path`, through options and enum variants
+    fn schema_at<'a>(schema: &'a Value, path: &[&str]) -> &'a Value {
+        path.iter().fold(schema, |current, name| {
+            property(schema, current, name).expect(name)
+        })
+    }
+
+    fn has_type(schema: &Value, kind: &str) -> bool {
+        match &schema["type"] {
+            Value::String(t) => t == kind,
+            Value::Array(types) => types.contains(&json!(kind)),
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_config_schema() {
+        let schema = config_schema();
+        assert_eq!(schema["title"], json!("rustpbx configuration"));
+        assert!(has_type(schema_at(&schema, &["http_addr"]), "string"));
+        let start = items_of(&schema, schema_at(&schema, &["media_nat", "mappings"]))
+            .map(|items| property(&schema, items, "start").expect("start"))
+            .expect("items");
+        assert!(has_type(start, "integer"));
+        assert!(has_type(
+            schema_at(&schema, &["proxy", "fraud", "alert_score"]),
+            "integer"
+        ));
+        // doc comments and serde defaults
+        let keepalive = schema_at(&schema, &["proxy", "nat_keepalive", "interval"]);
+        assert!(keepalive["description"].is_string(), "{}", keepalive);
+        assert!(keepalive.get("default").is_some(), "{}", keepalive);
+        let ldap = schema_at(&schema, &["proxy", "auth_backend", "base_dn"]);
+        assert_eq!(
+            ldap["description"],
+            json!("Where users are searched, with the whole subtree")
+        );
+        // required keys
+        let proxy = resolve(&schema, &schema["properties"]["proxy"]);
+        let proxy = alternatives(&schema, proxy).next().unwrap_or(proxy);
+        assert!(
+            proxy["required"]
+                .as_array()
+                .is_some_and(|required| required.contains(&json!("addr"))),
+            "{}",
+            proxy["required"]
+        );
+    }
+
+    #[test]
+    fn test_tagged_enums() {
+        let schema = config_schema();
+        for (path, tags) in [
+            (
+                vec!["proxy", "user_backend"],
+                vec!["memory", "http", "plain", "database"],
+            ),
+            (
+                vec!["proxy", "auth_backend"],
+                vec!["static", "http", "ldap"],
+            ),
+            (vec!["proxy", "locator"], vec!["memory", "http", "database"]),
+            (vec!["callrecord"], vec!["local", "s3", "http"]),
+            (vec!["ua", "handler"], vec!["webhook"]),
+        ] {
+            let types = alternatives(&schema, resolve(&schema, schema_at(&schema, &path)))
+                .flat_map(|variant| {
+                    let alternatives = alternatives(&schema, variant).collect::<Vec<_>>();
+                    match alternatives.is_empty() {
+                        true => vec![variant],
+                        false => alternatives,
+                    }
+                })
+                .filter_map(|variant| variant["properties"]["type"]["const"].as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(types, tags, "{:?}", path);
+        }
+        // the keys of each variant are known
+        assert!(
+            property(
+                &schema,
+                schema_at(&schema, &["proxy", "user_backend"]),
+                "table_name"
+            )
+            .is_some()
+        );
+        assert!(
+            property(
+                &schema,
+                schema_at(&schema, &["proxy", "user_backend"]),
+                "no_such_key"
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_check_config() {
+        assert!(check_config("http_addr = \"0.0.0.0:8080\"\n").is_empty());
+
+        let issues = check_config("http_addr = \"0.0.0.0:8080\"\n[media_nat]\npod_ports = \"x\"\n");
+        assert_eq!(issues.len(), 1, "{:?}", issues);
+        assert_eq!(issues[0].path, "media_nat.pod_ports");
+        assert_eq!((issues[0].line, issues[0].column), (3, 13));
+        assert!(!issues[0].warning);
+
+        let issues = check_config("http_adr = \"0.0.0.0:8080\"\n\n[media_nat]\n  pod_size = 2\n");
+        assert_eq!(issues.len(), 2, "{:?}", issues);
+        assert_eq!(issues[0].path, "http_adr");
+        assert_eq!((issues[0].line, issues[0].column), (1, 1));
+        assert_eq!(issues[1].path, "media_nat.pod_size");
+        assert_eq!((issues[1].line, issues[1].column), (4, 3));
+        assert!(issues.iter().all(|issue| issue.warning));
+
+        let issues = check_config("http_addr = \n");
+        assert_eq!(issues.len(), 1, "{:?}", issues);
+        assert_eq!(issues[0].line, 1);
+    }
+}
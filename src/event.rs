@@ -1,4 +1,11 @@
 use crate::PcmBuf;
+use crate::call::HangupCause;
+use crate::call::flow::FlowState;
+use crate::call::topology::{CallLeg, TopologyChange};
+use crate::media::distortion::{DistortionKind, DistortionStats};
+use crate::media::latency::LatencyReport;
+use crate::media::meter::AudioLevel;
+use crate::media::prosody::ProsodyFeatures;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
 use std::collections::HashMap;
@@ -48,6 +55,7 @@ pub enum SessionEvent {
         timestamp: u64,
         reason: String,
         code: Option<u32>,
+        cause: Option<HangupCause>,
     },
     Ringing {
         track_id: String,
@@ -58,6 +66,8 @@ pub enum SessionEvent {
         track_id: String,
         timestamp: u64,
         reason: Option<String>,
+        cause: Option<HangupCause>,
+        variables: Option<HashMap<String, String>>,
         initiator: Option<String>,
         start_time: String,
         hangup_time: String,
@@ -94,10 +104,65 @@ pub enum SessionEvent {
         timestamp: u64,
         completed: bool,
     },
+    /// The legs of the call were bridged, split, transferred or replaced
+    Topology {
+        track_id: String,
+        timestamp: u64,
+        change: TopologyChange,
+        /// The legs bridged or split, the caller's first. On replaced, the
+        /// leg replaced then the one that took its place
+        legs: Vec<CallLeg>,
+        /// Where the call is transferred to
+        target: Option<String>,
+        /// How long the legs were bridged (in ms), on split
+        duration: Option<u64>,
+    },
     Dtmf {
         track_id: String,
         timestamp: u64,
         digit: String,
+        /// How long the digit was held (in ms), when the source tells
+        duration: Option<u64>,
+    },
+    Keyword {
+        track_id: String,
+        timestamp: u64,
+        keyword: String,
+        score: f32,
+        start_time: u64,
+        end_time: u64,
+    },
+    LanguageDetected {
+        track_id: String,
+        timestamp: u64,
+        language: String,
+        confidence: f32,
+        /// speech duration used for identification (in ms)
+        duration: u64,
+        /// ASR model mapped from the language
+        model: Option<String>,
+    },
+    Prosody {
+        track_id: String,
+        timestamp: u64,
+        start_time: u64,
+        end_time: u64,
+        features: ProsodyFeatures,
+        sentiment: Option<f32>,
+        emotion: Option<String>,
+    },
+    /// Audio level of the track over the last metering interval
+    Level {
+        track_id: String,
+        timestamp: u64,
+        level: AudioLevel,
+    },
+    /// Sustained clipping or DC offset on the track
+    Distortion {
+        track_id: String,
+        timestamp: u64,
+        kind: DistortionKind,
+        stats: DistortionStats,
     },
     TrackStart {
         track_id: String,
@@ -111,6 +176,21 @@ pub enum SessionEvent {
         ssrc: u32,
         play_id: Option<String>,
     },
+    /// Result of a latency probe on the track
+    Latency {
+        track_id: String,
+        timestamp: u64,
+        report: LatencyReport,
+    },
+    /// The mute/gain state of a track changed, gains are in dB
+    TrackControl {
+        track_id: String,
+        timestamp: u64,
+        rx_muted: bool,
+        tx_muted: bool,
+        rx_gain: f32,
+        tx_gain: f32,
+    },
     Interruption {
         track_id: String,
         timestamp: u64,
@@ -136,6 +216,36 @@ pub enum SessionEvent {
         end_time: Option<u64>,
         text: String,
     },
+    /// A saved flow continues in this call
+    FlowResumed {
+        track_id: String,
+        timestamp: u64,
+        flow: FlowState,
+    },
+    /// The variant the flow got in an experiment
+    Experiment {
+        track_id: String,
+        timestamp: u64,
+        name: String,
+        variant: String,
+    },
+    /// Result of a gather command
+    Gather {
+        track_id: String,
+        timestamp: u64,
+        play_id: Option<String>,
+        /// dtmf, speech, nomatch or timeout
+        input: String,
+        digits: Option<String>,
+        /// The finish key that completed the digits
+        terminator: Option<String>,
+        text: Option<String>,
+        hint: Option<String>,
+        /// The grammar that took the transcript, with its interpretation
+        grammar: Option<String>,
+        value: Option<String>,
+        confidence: Option<f32>,
+    },
     Metrics {
         timestamp: u64,
         key: String,
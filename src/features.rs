@@ -0,0 +1,205 @@
+use crate::config::{Config, FeatureFlagsConfig};
+use serde::Serialize;
+use std::{collections::HashMap, sync::RwLock};
+use tracing::info;
+
+/// Records calls when their option asks for it
+pub const RECORDING: &str = "recording";
+/// Transcribes calls when their option asks for it
+pub const ASR: &str = "asr";
+/// Dials numbers starting with one of `international_prefixes`
+pub const INTERNATIONAL: &str = "international";
+
+/// Where flags apply, the narrowest one setting a feature wins
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeatureScope {
+    Defaults,
+    Tenant(String),
+    /// `user@realm`
+    Extension(String),
+}
+
+/// The flags set over the API, by scope like the config's
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FeatureOverrides {
+    pub defaults: HashMap<String, bool>,
+    pub tenants: HashMap<String, HashMap<String, bool>>,
+    pub extensions: HashMap<String, HashMap<String, bool>>,
+}
+
+impl FeatureOverrides {
+    fn scope_mut(&mut self, scope: &FeatureScope) -> &mut HashMap<String, bool> {
+        match scope {
+            FeatureScope::Defaults => &mut self.defaults,
+            FeatureScope::Tenant(tenant) => self.tenants.entry(tenant.clone()).or_default(),
+            FeatureScope::Extension(aor) => self.extensions.entry(aor.clone()).or_default(),
+        }
+    }
+}
+
+/// The features of the calls, from `features` and the flags set over the
+/// API since the start. An API flag takes precedence over the config's in
+/// the same scope
+#[derive(Debug)]
+pub struct FeatureFlags {
+    config: FeatureFlagsConfig,
+    /// Tenant name per realm, from `proxy.metering.tenants`
+    tenants: HashMap<String, String>,
+    overrides: RwLock<FeatureOverrides>,
+}
+
+impl FeatureFlags {
+    pub fn new(config: &Config) -> Self {
+        let mut features = config.features.clone().unwrap_or_default();
+        features.extensions = features
+            .extensions
+            .into_iter()
+            .map(|(aor, flags)| (aor.to_lowercase(), flags))
+            .collect();
+        Self {
+            config: features,
+            tenants: config
+                .proxy
+                .as_ref()
+                .and_then(|proxy| proxy.metering.as_ref())
+                .map(|metering| metering.tenants.clone())
+                .unwrap_or_default(),
+            overrides: RwLock::new(FeatureOverrides::default()),
+        }
+    }
+
+    /// The tenant of the extensions of `realm`, as usage metering names it
+    pub fn tenant_of(&self, realm: &str) -> String {
+        self.tenants
+            .get(realm)
+            .cloned()
+            .unwrap_or_else(|| realm.to_string())
+    }
+
+    /// Whether the calls of `extension` in `tenant` have `feature`
+    pub fn enabled(&self, feature: &str, tenant: Option<&str>, extension: Option<&str>) -> bool {
+        let overrides = self.overrides.read().ok();
+        let overrides = overrides.as_deref();
+        let lookup = |scoped: Option<&HashMap<String, bool>>| scoped?.get(feature).copied();
+        let mut scopes = Vec::new();
+        if let Some(extension) = extension {
+            scopes.push(overrides.and_then(|o| o.extensions.get(extension)));
+            scopes.push(self.config.extensions.get(extension));
+        }
+        if let Some(tenant) = tenant {
+            scopes.push(overrides.and_then(|o| o.tenants.get(tenant)));
+            scopes.push(self.config.tenants.get(tenant));
+        }
+        scopes.push(overrides.map(|o| &o.defaults));
+        scopes.push(Some(&self.config.defaults));
+        scopes.into_iter().find_map(lookup).unwrap_or(true)
+    }
+
+    /// Whether dialing `number` needs the `international` feature
+    pub fn is_international(&self, number: &str) -> bool {
+        self.config
+            .international_prefixes
+            .iter()
+            .any(|prefix| number.starts_with(prefix))
+    }
+
+    /// Sets the flags of a scope over the config's, or clears the ones set
+    /// to `None`. Returns the flags of the scope set over the API
+    pub fn set(
+        &self,
+        scope: &FeatureScope,
+        flags: HashMap<String, Option<bool>>,
+    ) -> HashMap<String, bool> {
+        let Ok(mut overrides) = self.overrides.write() else {
+            return HashMap::new();
+        };
+        info!(?scope, ?flags, "feature flags changed");
+        let scoped = overrides.scope_mut(scope);
+        for (feature, enabled) in flags {
+            match enabled {
+                Some(enabled) => scoped.insert(feature, enabled),
+                None => scoped.remove(&feature),
+            };
+        }
+        let scoped = scoped.clone();
+        overrides.tenants.retain(|_, flags| !flags.is_empty());
+        overrides.extensions.retain(|_, flags| !flags.is_empty());
+        scoped
+    }
+
+    pub fn config(&self) -> &FeatureFlagsConfig {
+        &self.config
+    }
+
+    pub fn overrides(&self) -> FeatureOverrides {
+        self.overrides
+            .read()
+            .map(|overrides| overrides.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_flags() {
+        let config = Config {
+            features: Some(FeatureFlagsConfig {
+                defaults: HashMap::from([(INTERNATIONAL.to_string(), false)]),
+                tenants: HashMap::from([(
+                    "acme".to_string(),
+                    HashMap::from([
+                        (INTERNATIONAL.to_string(), true),
+                        (RECORDING.to_string(), false),
+                    ]),
+                )]),
+                extensions: HashMap::from([(
+                    "1001@acme.com".to_string(),
+                    HashMap::from([(RECORDING.to_string(), true)]),
+                )]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let flags = FeatureFlags::new(&config);
+        // unlisted features are enabled
+        assert!(flags.enabled(ASR, None, None));
+        assert!(!flags.enabled(INTERNATIONAL, None, None));
+        assert!(!flags.enabled(INTERNATIONAL, Some("other"), Some("1002@other.com")));
+        assert!(flags.enabled(INTERNATIONAL, Some("acme"), Some("1002@acme.com")));
+        assert!(!flags.enabled(RECORDING, Some("acme"), Some("1002@acme.com")));
+        assert!(flags.enabled(RECORDING, Some("acme"), Some("1001@acme.com")));
+
+        assert!(flags.is_international("0044123"));
+        assert!(flags.is_international("+44123"));
+        assert!(!flags.is_international("1002"));
+
+        // API flags win over the config's of the same scope only
+        let scoped = flags.set(
+            &FeatureScope::Tenant("acme".to_string()),
+            HashMap::from([(RECORDING.to_string(), Some(true))]),
+        );
+        assert_eq!(scoped, HashMap::from([(RECORDING.to_string(), true)]));
+        assert!(flags.enabled(RECORDING, Some("acme"), Some("1002@acme.com")));
+        flags.set(
+            &FeatureScope::Extension("1001@acme.com".to_string()),
+            HashMap::from([(RECORDING.to_string(), Some(false))]),
+        );
+        assert!(!flags.enabled(RECORDING, Some("acme"), Some("1001@acme.com")));
+        flags.set(
+            &FeatureScope::Defaults,
+            HashMap::from([(ASR.to_string(), Some(false))]),
+        );
+        assert!(!flags.enabled(ASR, Some("acme"), Some("1001@acme.com")));
+
+        // clearing a flag falls back to the config
+        flags.set(
+            &FeatureScope::Tenant("acme".to_string()),
+            HashMap::from([(RECORDING.to_string(), None)]),
+        );
+        assert!(!flags.enabled(RECORDING, Some("acme"), Some("1002@acme.com")));
+        assert!(flags.overrides().tenants.is_empty());
+    }
+}
@@ -1,19 +1,30 @@
-use crate::{app::AppState, handler::middleware::clientaddr::ClientAddr};
+use crate::{
+    app::AppState, call::flow::FlowStore, features::FeatureScope,
+    handler::middleware::clientaddr::ClientAddr, media::codecs::resample::RESAMPLE_STATS,
+};
 use axum::{
     Json, Router,
     extract::{Path, State},
+    http::StatusCode,
     middleware,
     response::{IntoResponse, Response},
     routing::{get, post},
 };
 use chrono::Utc;
-use std::sync::atomic::Ordering;
+use std::{collections::HashMap, sync::atomic::Ordering};
 use tracing::{info, warn};
 
 pub fn router(app_state: AppState) -> Router<AppState> {
     Router::new()
         .route("/lists", get(list_calls))
         .route("/kill/{id}", post(kill_call))
+        .route("/variables/{id}", get(get_variables).post(set_variables))
+        .route("/flows/{id}", get(get_flow))
+        .route("/features", get(get_features))
+        .route("/features/defaults", post(set_default_features))
+        .route("/features/tenants/{tenant}", post(set_tenant_features))
+        .route("/features/extensions/{aor}", post(set_extension_features))
+        .route("/metrics", get(metrics_handler))
         .route("/shutdown", post(shutdown_handler))
         .route("/reload", post(reload_handler))
         .layer(middleware::from_fn_with_state(
@@ -30,6 +41,7 @@ pub(super) async fn health_handler(State(state): State<AppState>) -> Response {
         "total": state.total_calls.load(Ordering::Relaxed),
         "failed": state.total_failed_calls.load(Ordering::Relaxed),
         "runnings": state.active_calls.lock().await.len(),
+        "retransmissions": state.retransmissions.snapshot(),
     });
     Json(health).into_response()
 }
@@ -57,6 +69,7 @@ async fn list_calls(State(state): State<AppState>) -> Response {
                 "answerTime": call_state.answer_time.map(|t| t.to_rfc3339()),
                 "duration": call_state.answer_time
                     .map(|t| (Utc::now() - t).num_seconds()),
+                "variables": call_state.variables.snapshot(),
             })
         }).collect::<Vec<_>>(),
     });
@@ -75,6 +88,141 @@ async fn kill_call(
     Json(true).into_response()
 }
 
+async fn get_variables(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    match state.active_calls.lock().await.get(&id) {
+        Some(call) => Json(call.variables().snapshot()).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "call not found"})),
+        )
+            .into_response(),
+    }
+}
+
+/// Merges the variables into the call's, a null value removes the variable
+async fn set_variables(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(changes): Json<HashMap<String, Option<String>>>,
+) -> Response {
+    match state.active_calls.lock().await.get(&id) {
+        Some(call) => {
+            let variables = call.variables();
+            variables.update(changes);
+            Json(variables.snapshot()).into_response()
+        }
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "call not found"})),
+        )
+            .into_response(),
+    }
+}
+
+/// The feature flags of the config, and the ones set over the API
+async fn get_features(State(state): State<AppState>) -> Response {
+    Json(serde_json::json!({
+        "config": state.features.config(),
+        "overrides": state.features.overrides(),
+    }))
+    .into_response()
+}
+
+/// Sets the flags over the config's, a null value clears the flag
+async fn set_default_features(
+    State(state): State<AppState>,
+    Json(flags): Json<HashMap<String, Option<bool>>>,
+) -> Response {
+    Json(state.features.set(&FeatureScope::Defaults, flags)).into_response()
+}
+
+async fn set_tenant_features(
+    State(state): State<AppState>,
+    Path(tenant): Path<String>,
+    Json(flags): Json<HashMap<String, Option<bool>>>,
+) -> Response {
+    Json(state.features.set(&FeatureScope::Tenant(tenant), flags)).into_response()
+}
+
+async fn set_extension_features(
+    State(state): State<AppState>,
+    Path(aor): Path<String>,
+    Json(flags): Json<HashMap<String, Option<bool>>>,
+) -> Response {
+    let scope = FeatureScope::Extension(aor.to_lowercase());
+    Json(state.features.set(&scope, flags)).into_response()
+}
+
+/// Call and flow counters in the Prometheus text format
+async fn metrics_handler(State(state): State<AppState>) -> Response {
+    let mut metrics = String::new();
+    let calls = [
+        (
+            "rustpbx_calls_total",
+            "counter",
+            "Calls handled",
+            state.total_calls.load(Ordering::Relaxed),
+        ),
+        (
+            "rustpbx_calls_failed_total",
+            "counter",
+            "Calls that failed",
+            state.total_failed_calls.load(Ordering::Relaxed),
+        ),
+        (
+            "rustpbx_calls_active",
+            "gauge",
+            "Calls in progress",
+            state.active_calls.lock().await.len() as u64,
+        ),
+    ];
+    for (name, kind, help, value) in calls {
+        metrics.push_str(&format!(
+            "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+        ));
+    }
+    state.flow_stats.render(&mut metrics);
+    RESAMPLE_STATS.render(&mut metrics);
+    (
+        [(
+            axum::http::header::CONTENT_TYPE,
+            "text/plain; version=0.0.4",
+        )],
+        metrics,
+    )
+        .into_response()
+}
+
+/// The flow of an active call by its session id, else a saved flow by its id
+async fn get_flow(State(state): State<AppState>, Path(id): Path<String>) -> Response {
+    if let Some(flow) = state
+        .active_calls
+        .lock()
+        .await
+        .get(&id)
+        .and_then(|call| call.flow())
+    {
+        return Json(flow).into_response();
+    }
+    let saved = match state.config.flow.as_ref().and_then(FlowStore::new) {
+        Some(store) => store.load(&id).await,
+        None => Ok(None),
+    };
+    match saved {
+        Ok(Some(flow)) => Json(flow).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "flow not found"})),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
 async fn reload_handler(State(_state): State<AppState>, client_ip: ClientAddr) -> Response {
     info!(%client_ip, "Reload configuration initiated via /reload endpoint");
     Json(serde_json::json!({"status": "configuration reloaded"})).into_response()
@@ -0,0 +1,426 @@
+//! gRPC API (see proto/rustpbx.proto): call control mirroring the AMI and
+//! `/call` WebSocket endpoints, and a bidirectional stream for external media
+//! processing of a track's audio.
+use crate::{
+    AudioFrame, PcmBuf, Samples,
+    app::AppState,
+    call::{ActiveCall, ActiveCallType, Command},
+    event::SessionEvent,
+    media::{
+        codecs::{bytes_to_samples, samples_to_bytes},
+        error::MediaResult,
+        processor::Processor,
+        track::TrackConfig,
+    },
+};
+use anyhow::Result;
+use bytes::Bytes;
+use chrono::Utc;
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::{join, select, sync::mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::CancellationToken;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+pub mod proto {
+    tonic::include_proto!("rustpbx.v1");
+}
+
+use proto::{
+    CallInfo, CallRequest, CallResponse, ExternalMediaRequest, KillCallRequest, KillCallResponse,
+    ListCallsRequest, ListCallsResponse, MediaFrame,
+    call_control_server::{CallControl, CallControlServer},
+    call_request, call_response, external_media_request,
+};
+
+/// Frames buffered towards a slow external media client before dropping
+const EXTERNAL_MEDIA_QUEUE: usize = 50;
+
+pub struct CallControlService {
+    app_state: AppState,
+}
+
+pub fn service(app_state: AppState) -> CallControlServer<CallControlService> {
+    CallControlServer::new(CallControlService { app_state })
+}
+
+pub async fn serve(app_state: AppState, addr: SocketAddr) -> Result<()> {
+    let token = app_state.token.clone();
+    info!("gRPC server listening on {}", addr);
+    tonic::transport::Server::builder()
+        .add_service(service(app_state))
+        .serve_with_shutdown(addr, token.cancelled())
+        .await?;
+    Ok(())
+}
+
+impl CallControlService {
+    /// Same allow list as the AMI endpoints
+    #[allow(clippy::result_large_err)]
+    fn check_ami_access<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let client_ip = request
+            .remote_addr()
+            .map(|addr| addr.ip().to_string())
+            .unwrap_or_default();
+        let is_allowed = self
+            .app_state
+            .config
+            .ami
+            .as_ref()
+            .is_some_and(|ami| ami.is_allowed(&client_ip));
+        if !is_allowed {
+            warn!(client_ip, "gRPC AMI access denied for client");
+            return Err(Status::permission_denied(
+                "You don't have permission to access AMI interfaces",
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl CallControl for CallControlService {
+    type CallStream = ReceiverStream<Result<CallResponse, Status>>;
+    type ExternalMediaStream = ReceiverStream<Result<MediaFrame, Status>>;
+
+    async fn list_calls(
+        &self,
+        request: Request<ListCallsRequest>,
+    ) -> Result<Response<ListCallsResponse>, Status> {
+        self.check_ami_access(&request)?;
+        let active_calls = self.app_state.active_calls.lock().await;
+        let calls = active_calls
+            .iter()
+            .filter_map(|(id, call)| {
+                let call_state = call.call_state.read().ok()?;
+                Some(CallInfo {
+                    id: id.clone(),
+                    call_type: serde_json::to_value(&call.call_type)
+                        .ok()
+                        .and_then(|v| v.as_str().map(|s| s.to_string()))
+                        .unwrap_or_default(),
+                    start_time: call_state.start_time.to_rfc3339(),
+                    ring_time: call_state.ring_time.map(|t| t.to_rfc3339()),
+                    answer_time: call_state.answer_time.map(|t| t.to_rfc3339()),
+                    duration: call_state
+                        .answer_time
+                        .map(|t| (Utc::now() - t).num_seconds()),
+                })
+            })
+            .collect();
+        Ok(Response::new(ListCallsResponse { calls }))
+    }
+
+    async fn kill_call(
+        &self,
+        request: Request<KillCallRequest>,
+    ) -> Result<Response<KillCallResponse>, Status> {
+        self.check_ami_access(&request)?;
+        let id = request.into_inner().id;
+        let killed = match self.app_state.active_calls.lock().await.remove(&id) {
+            Some(call) => {
+                call.cancel_token.cancel();
+                info!(id, "call killed via gRPC");
+                true
+            }
+            None => false,
+        };
+        Ok(Response::new(KillCallResponse { killed }))
+    }
+
+    async fn call(
+        &self,
+        request: Request<Streaming<CallRequest>>,
+    ) -> Result<Response<Self::CallStream>, Status> {
+        let client_ip = request.remote_addr();
+        let mut inbound = request.into_inner();
+        let setup = match inbound.message().await? {
+            Some(CallRequest {
+                message: Some(call_request::Message::Setup(setup)),
+            }) => setup,
+            _ => return Err(Status::invalid_argument("first message must be setup")),
+        };
+        let call_type = match setup.call_type.as_str() {
+            "" | "websocket" | "web_socket" => ActiveCallType::WebSocket,
+            "webrtc" => ActiveCallType::Webrtc,
+            "sip" => ActiveCallType::Sip,
+            other => {
+                return Err(Status::invalid_argument(format!(
+                    "invalid call type: {}",
+                    other
+                )));
+            }
+        };
+        let useragent = self
+            .app_state
+            .useragent
+            .clone()
+            .ok_or_else(|| Status::unavailable("User agent not initialized"))?;
+        let session_id = if setup.id.is_empty() {
+            Uuid::new_v4().to_string()
+        } else {
+            setup.id
+        };
+
+        let (audio_sender, audio_receiver) = mpsc::unbounded_channel::<Bytes>();
+        let cancel_token = CancellationToken::new();
+        let active_call = Arc::new(ActiveCall::new(
+            call_type.clone(),
+            cancel_token.clone(),
+            session_id.clone(),
+            useragent.invitation.clone(),
+            self.app_state.clone(),
+            TrackConfig::default(),
+            Some(audio_receiver),
+            setup.dump.unwrap_or(true),
+            setup.server_side_track,
+            None,
+        ));
+
+        let (sender, receiver) = mpsc::channel(128);
+        let app_state = self.app_state.clone();
+        tokio::spawn(async move {
+            let recv_loop = async {
+                while let Ok(Some(request)) = inbound.message().await {
+                    match request.message {
+                        Some(call_request::Message::Command(text)) => {
+                            let command = match serde_json::from_str::<Command>(&text) {
+                                Ok(command) => command,
+                                Err(e) => {
+                                    warn!(session_id, ?client_ip, %text, "Failed to parse command {}", e);
+                                    continue;
+                                }
+                            };
+                            if active_call.enqueue_command(command).await.is_err() {
+                                break;
+                            }
+                        }
+                        Some(call_request::Message::Audio(audio)) => {
+                            audio_sender.send(audio.into()).ok();
+                        }
+                        _ => {}
+                    }
+                }
+            };
+
+            let mut event_receiver = active_call.event_sender.subscribe();
+            let send_loop = async {
+                while let Ok(event) = event_receiver.recv().await {
+                    let Some(response) = event_to_response(event) else {
+                        continue;
+                    };
+                    if sender.send(Ok(response)).await.is_err() {
+                        break;
+                    }
+                }
+            };
+
+            app_state
+                .active_calls
+                .lock()
+                .await
+                .insert(session_id.clone(), active_call.clone());
+            info!(session_id, ?client_ip, ?call_type, "new gRPC call started");
+
+            let (r, _) = join! {
+                active_call.serve(),
+                async {
+                    select! {
+                        _ = cancel_token.cancelled() => {},
+                        _ = send_loop => { cancel_token.cancel() },
+                        _ = recv_loop => {
+                            info!(session_id, ?client_ip, "gRPC stream closed by client");
+                            cancel_token.cancel()
+                        },
+                    }
+                },
+            };
+            match r {
+                Ok(_) => info!(session_id, ?client_ip, "call ended successfully"),
+                Err(e) => warn!(session_id, ?client_ip, "call ended with error: {}", e),
+            }
+            app_state.active_calls.lock().await.remove(&session_id);
+
+            // Drain remaining events
+            while let Ok(event) = event_receiver.try_recv() {
+                if matches!(event, SessionEvent::Binary { .. }) {
+                    continue;
+                }
+                let Some(response) = event_to_response(event) else {
+                    continue;
+                };
+                if sender.send(Ok(response)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+
+    async fn external_media(
+        &self,
+        request: Request<Streaming<ExternalMediaRequest>>,
+    ) -> Result<Response<Self::ExternalMediaStream>, Status> {
+        let mut inbound = request.into_inner();
+        let attach = match inbound.message().await? {
+            Some(ExternalMediaRequest {
+                message: Some(external_media_request::Message::Attach(attach)),
+            }) => attach,
+            _ => return Err(Status::invalid_argument("first message must be attach")),
+        };
+        let active_call = self
+            .app_state
+            .active_calls
+            .lock()
+            .await
+            .get(&attach.session_id)
+            .cloned()
+            .ok_or_else(|| Status::not_found("call not found"))?;
+        let track_id = attach
+            .track_id
+            .unwrap_or_else(|| active_call.server_side_track_id.clone());
+
+        let (sender, receiver) = mpsc::channel(EXTERNAL_MEDIA_QUEUE);
+        let processor = ExternalMediaProcessor::new(sender);
+        let processed = processor.processed.clone();
+        if !active_call
+            .media_stream
+            .append_processor(&track_id, Box::new(processor))
+            .await
+        {
+            return Err(Status::not_found("track not found"));
+        }
+        info!(
+            session_id = attach.session_id,
+            track_id, "external media attached"
+        );
+
+        tokio::spawn(async move {
+            let recv_loop = async {
+                while let Ok(Some(request)) = inbound.message().await {
+                    if let Some(external_media_request::Message::Frame(frame)) = request.message {
+                        let mut processed = processed.lock().unwrap();
+                        if processed.len() >= EXTERNAL_MEDIA_QUEUE {
+                            processed.pop_front();
+                        }
+                        processed.push_back(bytes_to_samples(&frame.pcm));
+                    }
+                }
+            };
+            select! {
+                _ = recv_loop => {}
+                _ = active_call.cancel_token.cancelled() => {}
+            }
+            active_call
+                .media_stream
+                .remove_processor::<ExternalMediaProcessor>(&track_id)
+                .await;
+            info!(
+                session_id = attach.session_id,
+                track_id, "external media detached"
+            );
+        });
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+fn event_to_response(event: SessionEvent) -> Option<CallResponse> {
+    let message = match event {
+        SessionEvent::Binary { data, .. } => call_response::Message::Audio(data),
+        // HTTP/2 has its own keepalive
+        SessionEvent::Ping { .. } => return None,
+        _ => call_response::Message::Event(serde_json::to_string(&event).ok()?),
+    };
+    Some(CallResponse {
+        message: Some(message),
+    })
+}
+
+/// Sends a copy of every PCM frame to the external media client and replaces
+/// the frame with the oldest processed frame received back, if any
+pub struct ExternalMediaProcessor {
+    sender: mpsc::Sender<Result<MediaFrame, Status>>,
+    processed: Arc<Mutex<VecDeque<PcmBuf>>>,
+}
+
+impl ExternalMediaProcessor {
+    fn new(sender: mpsc::Sender<Result<MediaFrame, Status>>) -> Self {
+        Self {
+            sender,
+            processed: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+}
+
+impl Processor for ExternalMediaProcessor {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
+        let samples = match &mut frame.samples {
+            Samples::PCM { samples } => samples,
+            _ => return Ok(()),
+        };
+        // a slow client loses frames instead of stalling the media path
+        self.sender
+            .try_send(Ok(MediaFrame {
+                track_id: frame.track_id.clone(),
+                timestamp: frame.timestamp,
+                sample_rate: frame.sample_rate,
+                pcm: samples_to_bytes(samples),
+            }))
+            .ok();
+        if let Some(processed) = self.processed.lock().unwrap().pop_front() {
+            *samples = processed;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_external_media_processor() {
+        let (sender, mut receiver) = mpsc::channel(2);
+        let processor = ExternalMediaProcessor::new(sender);
+        let frame = |samples: Vec<i16>| AudioFrame {
+            track_id: "callee".to_string(),
+            samples: Samples::PCM { samples },
+            timestamp: 20,
+            sample_rate: 8000,
+        };
+
+        // nothing processed yet, the frame passes through
+        let mut audio = frame(vec![1, 2, 3]);
+        processor.process_frame(&mut audio).unwrap();
+        assert!(matches!(audio.samples, Samples::PCM { ref samples } if samples == &[1, 2, 3]));
+        let sent = receiver.try_recv().unwrap().unwrap();
+        assert_eq!(sent.track_id, "callee");
+        assert_eq!(sent.sample_rate, 8000);
+        assert_eq!(bytes_to_samples(&sent.pcm), vec![1, 2, 3]);
+
+        processor.processed.lock().unwrap().push_back(vec![7, 8]);
+        let mut audio = frame(vec![4, 5]);
+        processor.process_frame(&mut audio).unwrap();
+        assert!(matches!(audio.samples, Samples::PCM { ref samples } if samples == &[7, 8]));
+
+        // a full queue drops frames instead of blocking
+        let mut audio = frame(vec![6]);
+        processor.process_frame(&mut audio).unwrap();
+        processor.process_frame(&mut audio).unwrap();
+        assert_eq!(
+            bytes_to_samples(&receiver.try_recv().unwrap().unwrap().pcm),
+            vec![4, 5]
+        );
+        assert_eq!(
+            bytes_to_samples(&receiver.try_recv().unwrap().unwrap().pcm),
+            vec![6]
+        );
+        assert!(receiver.try_recv().is_err());
+    }
+}
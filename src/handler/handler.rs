@@ -5,13 +5,14 @@ use crate::{
     call::{
         active_call::{ CallParams}, ActiveCall, ActiveCallType, Command
     },
-    event::SessionEvent, media::track::TrackConfig,
+    event::{create_event_sender, SessionEvent}, media::{mixer::SuperviseMode, track::{websocket::WebsocketTrack, TrackConfig}},
 };
-use axum::{extract::{ ws::Message, Query, State, WebSocketUpgrade}, response::{IntoResponse, Response}, routing::get, Json, Router
+use axum::{extract::{ ws::Message, Query, State, WebSocketUpgrade}, http::StatusCode, middleware, response::{IntoResponse, Response}, routing::get, Json, Router
 };
 use bytes::Bytes;
 use chrono::Utc;
 use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
 use tokio::{join, select};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
@@ -22,6 +23,11 @@ pub fn router(app_state: AppState) -> Router<AppState> {
         .route("/call", get(ws_handler))
         .route("/call/webrtc", get(webrtc_handler))
         .route("/call/sip", get(sip_handler))
+        .route("/call/supervise", get(supervise_handler))
+        .layer(middleware::from_fn_with_state(
+            app_state.clone(),
+            super::middleware::ami_auth::call_auth_middleware,
+        ))
         .nest("/llm/v1", super::llmproxy::router())
         .route("/iceservers", get(super::webrtc::get_iceservers))
         .route("/health", get(super::ami::health_handler))
@@ -224,3 +230,100 @@ pub async fn call_handler(
     });
     resp
 }
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SuperviseParams {
+    /// Session id of the live call to supervise
+    pub id: String,
+    pub track_id: Option<String>,
+    /// the agent's track, the only one to hear the supervisor in whisper mode
+    pub target: String,
+    pub mode: Option<SuperviseMode>,
+    pub codec: Option<String>,
+}
+
+/// Attaches the WebSocket as a supervisor of a live call, binary messages
+/// carry the audio both ways like the websocket call
+pub async fn supervise_handler(
+    client_ip: ClientAddr,
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Query(params): Query<SuperviseParams>,
+) -> Response {
+    let session_id = params.id.clone();
+    let active_call = match state.active_calls.lock().await.get(&session_id).cloned() {
+        Some(call) => call,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({
+                    "error": "Call not found",
+                    "message": format!("No active call with id {}", session_id)
+                })),
+            )
+                .into_response();
+        }
+    };
+
+    ws.on_upgrade(move |socket| async move {
+        let (mut ws_sender, mut ws_receiver) = socket.split();
+        let (audio_sender, audio_receiver) = tokio::sync::mpsc::unbounded_channel::<Bytes>();
+        // the mix for the supervisor must not reach the call's own client
+        let event_sender = create_event_sender();
+        let mut event_receiver = event_sender.subscribe();
+        let track_id = params
+            .track_id
+            .unwrap_or_else(|| format!("supervisor-{}", Uuid::new_v4()));
+        let cancel_token = active_call.cancel_token.child_token();
+        let track = WebsocketTrack::new(
+            cancel_token.clone(),
+            track_id.clone(),
+            active_call.track_config.clone(),
+            event_sender,
+            audio_receiver,
+            params.codec,
+            rand::random::<u32>(),
+        );
+        let mode = params.mode.unwrap_or(SuperviseMode::Listen);
+        if let Err(e) = active_call
+            .attach_supervisor(Box::new(track), params.target, mode)
+            .await
+        {
+            warn!(session_id, %client_ip, track_id, "failed to attach supervisor: {}", e);
+            ws_sender.close().await.ok();
+            return;
+        }
+
+        let recv_from_ws_loop = async {
+            while let Some(Ok(message)) = ws_receiver.next().await {
+                match message {
+                    Message::Binary(bin) => {
+                        audio_sender.send(bin.into()).ok();
+                    }
+                    Message::Close(_) => break,
+                    _ => {}
+                }
+            }
+        };
+        let send_to_ws_loop = async {
+            while let Ok(event) = event_receiver.recv().await {
+                if let SessionEvent::Binary { data, .. } = event {
+                    if let Err(e) = ws_sender.send(Message::Binary(data.into())).await {
+                        warn!(session_id, %client_ip, "Failed to send WebSocket message: {}", e);
+                        break;
+                    }
+                }
+            }
+        };
+        select! {
+            _ = cancel_token.cancelled() => {},
+            _ = recv_from_ws_loop => {},
+            _ = send_to_ws_loop => {},
+        }
+        info!(session_id, %client_ip, track_id, "supervisor left");
+        active_call.media_stream.remove_track(&track_id).await;
+        cancel_token.cancel();
+        ws_sender.close().await.ok();
+    })
+}
@@ -0,0 +1,164 @@
+use crate::{app::AppState, proxy::server::SipServerRef};
+use anyhow::{Result, anyhow};
+use axum::{
+    Json, Router,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use serde::Serialize;
+use std::{future::Future, net::UdpSocket, time::Duration};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+const RTP_PROBE_ATTEMPTS: usize = 8;
+
+/// The outcome of one subsystem's check
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Probe {
+    pub name: String,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl Probe {
+    fn new(name: &str, result: Result<String>) -> Self {
+        match result {
+            Ok(detail) => Self {
+                name: name.to_string(),
+                ok: true,
+                detail: (!detail.is_empty()).then_some(detail),
+            },
+            Err(e) => Self {
+                name: name.to_string(),
+                ok: false,
+                detail: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Vec<Probe>,
+}
+
+async fn with_timeout(check: impl Future<Output = Result<()>>) -> Result<String> {
+    tokio::time::timeout(PROBE_TIMEOUT, check)
+        .await
+        .map_err(|_| anyhow!("timed out after {:?}", PROBE_TIMEOUT))?
+        .map(|_| String::new())
+}
+
+fn check_transport(addrs: Vec<rsipstack::transport::SipAddr>) -> Result<String> {
+    if addrs.is_empty() {
+        return Err(anyhow!("no transport bound"));
+    }
+    Ok(addrs
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect::<Vec<_>>()
+        .join(", "))
+}
+
+/// The RTP range has room for another call when it has more even ports
+/// than calls, and one of them can be bound
+pub fn check_rtp_ports(start: u16, end: u16, calls: usize) -> Result<String> {
+    if start > end {
+        return Err(anyhow!("empty port range {}-{}", start, end));
+    }
+    let capacity = (end as usize - start as usize) / 2 + 1;
+    if calls >= capacity {
+        return Err(anyhow!("{} calls use all {} ports", calls, capacity));
+    }
+    for _ in 0..RTP_PROBE_ATTEMPTS {
+        let port = rand::random_range::<u16, _>(start..=end) & !1;
+        if port >= start && UdpSocket::bind(("0.0.0.0", port)).is_ok() {
+            return Ok(format!("{} of {} ports free", capacity - calls, capacity));
+        }
+    }
+    Err(anyhow!(
+        "no free port in {}-{} after {} attempts",
+        start,
+        end,
+        RTP_PROBE_ATTEMPTS
+    ))
+}
+
+/// Runs every check, a deployment is ready when all of them pass
+pub async fn readiness(state: &AppState, server: Option<&SipServerRef>) -> Readiness {
+    let mut checks = Vec::new();
+    checks.push(Probe::new(
+        "shutdown",
+        match state.token.is_cancelled() {
+            true => Err(anyhow!("shutting down")),
+            false => Ok(String::new()),
+        },
+    ));
+    if let Some(useragent) = state.useragent.as_ref() {
+        checks.push(Probe::new(
+            "uaTransport",
+            check_transport(useragent.endpoint.get_addrs()),
+        ));
+    }
+    let calls = state.active_calls.lock().await.len();
+    let (start, end) = match state.media_nat.as_ref() {
+        Some(nat) => nat.port_range(),
+        None => (
+            state.config.rtp_start_port.unwrap_or(12000),
+            state.config.rtp_end_port.unwrap_or(u16::MAX - 1),
+        ),
+    };
+    checks.push(Probe::new("rtpPorts", check_rtp_ports(start, end, calls)));
+    if let Some(sender) = state.callrecord_sender.as_ref() {
+        checks.push(Probe::new(
+            "callRecords",
+            match sender.is_closed() {
+                true => Err(anyhow!("call record writer stopped")),
+                false => Ok(String::new()),
+            },
+        ));
+    }
+    if let Some(server) = server {
+        checks.push(Probe::new(
+            "proxyTransport",
+            check_transport(server.endpoint.get_addrs()),
+        ));
+        checks.push(Probe::new(
+            "userBackend",
+            with_timeout(server.user_backend.check()).await,
+        ));
+        checks.push(Probe::new(
+            "locator",
+            with_timeout(server.locator.check()).await,
+        ));
+    }
+    Readiness {
+        ready: checks.iter().all(|check| check.ok),
+        checks,
+    }
+}
+
+/// `/healthz` answers as long as the process serves requests, `/readyz`
+/// only when every subsystem can take calls
+pub fn router(state: AppState, server: Option<SipServerRef>) -> Router {
+    Router::new()
+        .route(
+            "/healthz",
+            get(async || -> Response { Json(serde_json::json!({"status": "ok"})).into_response() }),
+        )
+        .route(
+            "/readyz",
+            get(async move || -> Response {
+                let readiness = readiness(&state, server.as_ref()).await;
+                let status = match readiness.ready {
+                    true => StatusCode::OK,
+                    false => StatusCode::SERVICE_UNAVAILABLE,
+                };
+                (status, Json(readiness)).into_response()
+            }),
+        )
+}
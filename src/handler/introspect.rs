@@ -0,0 +1,165 @@
+use crate::{app::AppState, call::sip::Invitation, proxy::server::SipServerRef};
+use axum::{
+    Json,
+    response::{IntoResponse, Response},
+};
+use rsipstack::dialog::{DialogId, dialog::Dialog, dialog_layer::DialogLayer};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::warn;
+
+/// `GET /ami/v1/dump` handler: the calls, dialogs, transactions,
+/// registrations and tracks the server holds right now
+pub async fn dump_handler(state: AppState, server: Option<SipServerRef>) -> Response {
+    Json(dump(&state, server.as_ref()).await).into_response()
+}
+
+pub async fn dump(state: &AppState, server: Option<&SipServerRef>) -> Value {
+    let calls = state
+        .active_calls
+        .lock()
+        .await
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut call_dialogs = BTreeMap::new();
+    let mut dumped_calls = Vec::with_capacity(calls.len());
+    for call in calls {
+        let mut dumped = match call.call_state.read() {
+            Ok(call_state) => {
+                let dialog_id = call_state.dialog.as_ref().map(|dialog| dialog.id().clone());
+                if let Some(ref dialog_id) = dialog_id {
+                    call_dialogs.insert(dialog_id.to_string(), dialog_id.clone());
+                }
+                json!({
+                    "id": call.session_id,
+                    "callType": call.call_type,
+                    "dialogId": dialog_id.map(|id| id.to_string()),
+                    "startTime": call_state.start_time.to_rfc3339(),
+                    "ringTime": call_state.ring_time.map(|t| t.to_rfc3339()),
+                    "answerTime": call_state.answer_time.map(|t| t.to_rfc3339()),
+                    "lastStatusCode": call_state.last_status_code,
+                    "hangupReason": call_state.hangup_reason,
+                })
+            }
+            Err(_) => json!({"id": call.session_id, "error": "Failed to read call state"}),
+        };
+        dumped["tracks"] = json!(call.media_stream.track_states().await);
+        dumped_calls.push(dumped);
+    }
+
+    let registrations = match server {
+        Some(server) => match server.locator.bindings().await {
+            Ok(bindings) => bindings
+                .into_iter()
+                .map(|(identifier, locations)| {
+                    let locations = locations
+                        .iter()
+                        .map(|location| {
+                            json!({
+                                "aor": location.aor.to_string(),
+                                "destination": location.destination.to_string(),
+                                "expires": location.expires,
+                                "instanceId": location.instance_id,
+                                "regId": location.reg_id,
+                                "supportsWebrtc": location.supports_webrtc,
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    (identifier, json!(locations))
+                })
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+            Err(e) => {
+                warn!("failed to list registrations: {}", e);
+                json!({"error": e.to_string()})
+            }
+        },
+        None => Value::Null,
+    };
+
+    let useragent = match state.useragent.as_ref() {
+        Some(ua) => dump_endpoint(&ua.dialog_layer, Some(&ua.invitation), &call_dialogs).await,
+        None => Value::Null,
+    };
+    let proxy = match server {
+        Some(server) => dump_endpoint(&server.dialog_layer, None, &call_dialogs).await,
+        None => Value::Null,
+    };
+
+    json!({
+        "calls": dumped_calls,
+        "useragent": useragent,
+        "proxy": proxy,
+        "registrations": registrations,
+    })
+}
+
+/// Dialogs of the layer that a call, a pending invitation or a transaction
+/// waiting for an ACK refers to, and the transactions of its endpoint.
+/// rsipstack can't list the dialogs themselves, so `dialogCount` above the
+/// number listed points at dialogs nothing holds anymore
+async fn dump_endpoint(
+    dialog_layer: &DialogLayer,
+    invitation: Option<&Invitation>,
+    call_dialogs: &BTreeMap<String, DialogId>,
+) -> Value {
+    let endpoint = &dialog_layer.endpoint;
+    let mut dialog_ids = call_dialogs.clone();
+    let mut pending = BTreeSet::new();
+    if let Some(invitation) = invitation {
+        for pending_dialog in invitation.pending_dialogs.lock().await.values() {
+            let dialog_id = pending_dialog.dialog.id();
+            pending.insert(dialog_id.to_string());
+            dialog_ids.insert(dialog_id.to_string(), dialog_id);
+        }
+    }
+    let mut waiting_ack = BTreeSet::new();
+    if let Ok(waiting) = endpoint.waiting_ack.read() {
+        for dialog_id in waiting.keys() {
+            waiting_ack.insert(dialog_id.to_string());
+            dialog_ids.insert(dialog_id.to_string(), dialog_id.clone());
+        }
+    }
+    let transactions = endpoint
+        .transactions
+        .read()
+        .map(|transactions| {
+            transactions
+                .keys()
+                .map(|key| key.to_string())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let finished_transactions = endpoint
+        .finished_transactions
+        .read()
+        .map(|finished| finished.len())
+        .unwrap_or_default();
+
+    let dialogs = dialog_ids
+        .iter()
+        .filter_map(|(id, dialog_id)| {
+            let dialog = dialog_layer.get_dialog(dialog_id)?;
+            Some(json!({
+                "id": id,
+                "role": match dialog {
+                    Dialog::ServerInvite(_) => "server",
+                    Dialog::ClientInvite(_) => "client",
+                },
+                "from": dialog.from(),
+                "to": dialog.to(),
+                "remoteContact": dialog.remote_contact().map(|uri| uri.to_string()),
+                "pending": pending.contains(id),
+                "waitingAck": waiting_ack.contains(id),
+            }))
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "dialogCount": dialog_layer.len(),
+        "dialogs": dialogs,
+        "transactions": transactions,
+        "finishedTransactions": finished_transactions,
+    })
+}
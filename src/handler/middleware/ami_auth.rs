@@ -1,8 +1,15 @@
-use crate::{app::AppState, handler::middleware::clientaddr::ClientAddr};
+use crate::{
+    app::AppState,
+    handler::middleware::{
+        clientaddr::ClientAddr,
+        jwt::{self, Scope},
+        rbac::{self, AuditEntry, Principal},
+    },
+};
 use axum::{
     Json,
-    extract::{Request, State},
-    http::StatusCode,
+    extract::{OriginalUri, Request, State},
+    http::{HeaderValue, StatusCode, header::WWW_AUTHENTICATE},
     middleware::Next,
     response::{IntoResponse, Response},
 };
@@ -18,21 +25,168 @@ pub async fn ami_auth_middleware(
         ami.is_allowed(client_ip.ip().to_string().as_str())
     });
 
+    let scope = Scope::required(request.method(), request.uri().path());
     if !is_allowed {
+        return bearer_auth(&state, &client_ip, scope, request, next).await;
+    }
+
+    let principal = Principal::local(&client_ip);
+    run_as(&state, &client_ip, principal, scope, request, next).await
+}
+
+/// Guards the call WebSockets once `ami.jwt` is set, open otherwise
+pub async fn call_auth_middleware(
+    State(state): State<AppState>,
+    client_ip: ClientAddr,
+    request: Request,
+    next: Next,
+) -> Response {
+    let jwt_enabled = state
+        .config
+        .ami
+        .as_ref()
+        .is_some_and(|ami| ami.jwt.is_some());
+    if !jwt_enabled {
+        return next.run(request).await;
+    }
+    bearer_auth(&state, &client_ip, Scope::Originate, request, next).await
+}
+
+/// Runs the request as the token's principal, with its claims in the
+/// request's extensions, when the token is valid and has `scope`
+async fn bearer_auth(
+    state: &AppState,
+    client_ip: &ClientAddr,
+    scope: Scope,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(config) = state.config.ami.as_ref().and_then(|ami| ami.jwt.as_ref()) else {
         warn!(
             %client_ip,
             "AMI access denied for client"
         );
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "You don't have permission to access AMI interfaces",
+        );
+    };
+    let Some(token) = jwt::bearer_token(request.headers(), request.uri()) else {
+        return error_response(StatusCode::UNAUTHORIZED, "A bearer token is required");
+    };
+    let claims = match jwt::verify(&token, config) {
+        Ok(claims) => claims,
+        Err(e) => {
+            warn!(%client_ip, "invalid bearer token: {}", e);
+            return error_response(StatusCode::UNAUTHORIZED, "The bearer token is invalid");
+        }
+    };
+    let principal = Principal::from_claims(&claims);
+    if !claims.has_scope(scope) {
+        warn!(
+            %client_ip,
+            sub = ?claims.sub,
+            path = request.uri().path(),
+            "bearer token lacks the {} scope",
+            scope.as_str()
+        );
+        let response = error_response(
+            StatusCode::FORBIDDEN,
+            &format!("The {} scope is required", scope.as_str()),
+        );
+        if scope != Scope::Listen {
+            audit(state, client_ip, principal, &request, &response);
+        }
+        return response;
+    }
+    request.extensions_mut().insert(claims);
+    run_as(state, client_ip, principal, scope, request, next).await
+}
 
-        return (
+/// Keeps principals bound to a tenant to the endpoints that check tenants,
+/// and audits the requests needing more than the listen scope
+async fn run_as(
+    state: &AppState,
+    client_ip: &ClientAddr,
+    principal: Principal,
+    scope: Scope,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let mut entry = AuditEntry {
+        principal: principal.clone(),
+        client: client_ip.to_string(),
+        method: request.method().to_string(),
+        path: request_path(&request),
+        status: 0,
+    };
+    let response = if principal.tenant.is_some() && !rbac::is_tenant_aware(&entry.path) {
+        warn!(
+            %client_ip,
+            subject = principal.subject,
+            tenant = ?principal.tenant,
+            path = entry.path,
+            "endpoint not available to tenant principals"
+        );
+        error_response(
             StatusCode::FORBIDDEN,
-            Json(serde_json::json!({
-                "error": "Access denied",
-                "message": "You don't have permission to access AMI interfaces"
-            })),
+            "The endpoint is not available to tenant principals",
         )
-            .into_response();
+    } else {
+        request.extensions_mut().insert(principal);
+        next.run(request).await
+    };
+    if scope != Scope::Listen {
+        entry.status = response.status().as_u16();
+        rbac::audit(state, &entry);
     }
+    response
+}
 
-    next.run(request).await
+/// The path the client asked for, before nested routers stripped prefixes
+fn request_path(request: &Request) -> String {
+    match request.extensions().get::<OriginalUri>() {
+        Some(OriginalUri(uri)) => uri.path().to_string(),
+        None => request.uri().path().to_string(),
+    }
+}
+
+fn audit(
+    state: &AppState,
+    client_ip: &ClientAddr,
+    principal: Principal,
+    request: &Request,
+    response: &Response,
+) {
+    rbac::audit(
+        state,
+        &AuditEntry {
+            principal,
+            client: client_ip.to_string(),
+            method: request.method().to_string(),
+            path: request_path(request),
+            status: response.status().as_u16(),
+        },
+    );
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    let error = match status {
+        StatusCode::UNAUTHORIZED => "Unauthorized",
+        _ => "Access denied",
+    };
+    let mut response = (
+        status,
+        Json(serde_json::json!({
+            "error": error,
+            "message": message
+        })),
+    )
+        .into_response();
+    if status == StatusCode::UNAUTHORIZED {
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+    }
+    response
 }
@@ -0,0 +1,142 @@
+use super::rbac::Role;
+use crate::config::JwtConfig;
+use anyhow::{Result, anyhow};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// What a token lets its holder do on the control APIs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Place calls, send messages, run campaigns and agents
+    Originate,
+    /// Read calls, stats and wallboards
+    Listen,
+    /// Everything, including killing calls, reloads and shutdown
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Originate => "originate",
+            Scope::Listen => "listen",
+            Scope::Admin => "admin",
+        }
+    }
+
+    /// The scope a control API request needs
+    pub fn required(method: &http::Method, path: &str) -> Self {
+        let path = path.trim_end_matches('/');
+        if path.ends_with("/shutdown")
+            || path.ends_with("/reload")
+            || path.ends_with("/dump")
+            || path.contains("/kill/")
+        {
+            Scope::Admin
+        } else if method == http::Method::GET || method == http::Method::HEAD {
+            Scope::Listen
+        } else if path.contains("/features") {
+            Scope::Admin
+        } else {
+            Scope::Originate
+        }
+    }
+}
+
+/// The claims of a verified token, put in the request's extensions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Claims {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// A string or an array of strings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+    /// Space separated scopes, like OAuth's
+    #[serde(default)]
+    pub scope: String,
+    /// Grants the role's scopes on top of `scope`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    /// Binds the holder to the resources of one tenant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+impl Claims {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.role.is_some_and(|role| role.allows(scope))
+            || self
+                .scope
+                .split_whitespace()
+                .any(|s| s == scope.as_str() || s == Scope::Admin.as_str())
+    }
+}
+
+fn algorithm(alg: &str) -> Result<Algorithm> {
+    match alg {
+        "HS256" => Ok(Algorithm::HS256),
+        "HS384" => Ok(Algorithm::HS384),
+        "HS512" => Ok(Algorithm::HS512),
+        _ => Err(anyhow!("unsupported JWT algorithm: {}", alg)),
+    }
+}
+
+/// A HS256 token of `claims`, for clients and tests
+pub fn encode(claims: &Claims, secret: &str) -> Result<String> {
+    Ok(jsonwebtoken::encode(
+        &Header::new(Algorithm::HS256),
+        claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )?)
+}
+
+/// The claims of `token` when its algorithm, signature, times, issuer and
+/// audience check out
+pub fn verify(token: &str, config: &JwtConfig) -> Result<Claims> {
+    let mut validation = Validation::new(algorithm(&config.algorithm)?);
+    validation.leeway = config.leeway;
+    validation.validate_nbf = true;
+    if config.allow_no_expiry {
+        // `exp` is checked when there is one
+        validation.required_spec_claims.clear();
+    }
+    if let Some(issuer) = &config.issuer {
+        validation.set_issuer(&[issuer]);
+    }
+    match &config.audience {
+        Some(audience) => validation.set_audience(&[audience]),
+        None => validation.validate_aud = false,
+    }
+    let data = jsonwebtoken::decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(config.secret.as_bytes()),
+        &validation,
+    )
+    .map_err(|e| anyhow!("invalid JWT: {}", e))?;
+    Ok(data.claims)
+}
+
+/// The bearer token of a request, from `Authorization` or, for WebSocket
+/// clients that can't set headers, the `token` query parameter
+pub fn bearer_token(headers: &http::HeaderMap, uri: &http::Uri) -> Option<String> {
+    if let Some(value) = headers.get(http::header::AUTHORIZATION) {
+        let value = value.to_str().ok()?;
+        let (scheme, token) = value.split_once(' ')?;
+        return scheme
+            .eq_ignore_ascii_case("bearer")
+            .then(|| token.trim().to_string());
+    }
+    uri.query()?.split('&').find_map(|param| {
+        let token = param.strip_prefix("token=")?;
+        urlencoding::decode(token)
+            .ok()
+            .map(|token| token.into_owned())
+    })
+}
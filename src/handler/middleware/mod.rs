@@ -1,2 +1,4 @@
 pub mod ami_auth;
 pub mod clientaddr;
+pub mod jwt;
+pub mod rbac;
@@ -0,0 +1,115 @@
+use super::{
+    clientaddr::ClientAddr,
+    jwt::{Claims, Scope},
+};
+use crate::{app::AppState, callrecord::CallRecordEventType};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+/// Roles of control API principals, each with the scopes of the ones below
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Reads calls, stats and wallboards
+    Viewer,
+    /// Also places calls, sends messages, runs campaigns and agents
+    Operator,
+    /// Also kills calls, toggles features, reloads and shuts down
+    Admin,
+}
+
+impl Role {
+    pub fn allows(&self, scope: Scope) -> bool {
+        match scope {
+            Scope::Listen => true,
+            Scope::Originate => *self >= Role::Operator,
+            Scope::Admin => *self == Role::Admin,
+        }
+    }
+}
+
+/// Who a control API request is made by, in the request's extensions
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Principal {
+    pub subject: String,
+    pub role: Role,
+    /// The tenant whose resources alone the principal may act on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+}
+
+impl Principal {
+    /// A client in `ami.allows`, trusted with everything
+    pub fn local(client_ip: &ClientAddr) -> Self {
+        Self {
+            subject: format!("ip:{}", client_ip.ip()),
+            role: Role::Admin,
+            tenant: None,
+        }
+    }
+
+    /// The `role` claim, or the role of the widest scope when there's none
+    pub fn from_claims(claims: &Claims) -> Self {
+        let role = claims.role.unwrap_or_else(|| {
+            let scopes = claims.scope.split_whitespace().collect::<Vec<_>>();
+            if scopes.contains(&Scope::Admin.as_str()) {
+                Role::Admin
+            } else if scopes.contains(&Scope::Originate.as_str()) {
+                Role::Operator
+            } else {
+                Role::Viewer
+            }
+        });
+        Self {
+            subject: claims.sub.clone().unwrap_or_default(),
+            role,
+            tenant: claims.tenant.clone(),
+        }
+    }
+
+    /// Whether the principal may act on what belongs to `tenant`, resources
+    /// of no tenant are only for principals bound to none
+    pub fn can_access(&self, tenant: Option<&str>) -> bool {
+        match &self.tenant {
+            None => true,
+            Some(own) => tenant == Some(own.as_str()),
+        }
+    }
+}
+
+/// Endpoints that check the tenant of what they act on, the only ones open
+/// to principals bound to a tenant
+pub fn is_tenant_aware(path: &str) -> bool {
+    let path = path.trim_end_matches('/');
+    ["/ami/v1/clicktodial", "/ami/v1/message", "/ami/v1/usage"].contains(&path)
+}
+
+/// A control API request that changed something
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEntry {
+    pub principal: Principal,
+    pub client: String,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+}
+
+/// Logs the entry to the `audit` target, and keeps it in the journal when
+/// there is one
+pub fn audit(state: &AppState, entry: &AuditEntry) {
+    info!(
+        target: "audit",
+        subject = entry.principal.subject,
+        role = ?entry.principal.role,
+        tenant = ?entry.principal.tenant,
+        client = entry.client,
+        status = entry.status,
+        "{} {}",
+        entry.method,
+        entry.path
+    );
+    if let Some(journal) = state.journal.as_ref() {
+        journal.record(CallRecordEventType::Audit, None, entry);
+    }
+}
@@ -1,4 +1,6 @@
 pub mod handler;
+pub mod health;
+pub mod introspect;
 pub mod llmproxy;
 pub mod middleware;
 #[cfg(test)]
@@ -6,3 +8,5 @@ mod tests;
 pub mod webrtc;
 pub use handler::router;
 pub mod ami;
+#[cfg(feature = "grpc")]
+pub mod grpc;
@@ -0,0 +1,170 @@
+#[cfg(test)]
+mod gather_tests {
+    use crate::{
+        app::AppStateBuilder,
+        call::{ActiveCall, ActiveCallType, Command, gather::GatherOption},
+        config::{Config, UseragentConfig},
+        event::SessionEvent,
+        media::track::TrackConfig,
+        synthesis::prompt::PromptSegment,
+    };
+    use anyhow::Result;
+    use std::{sync::Arc, time::Duration};
+    use tokio::{sync::broadcast, time::timeout};
+    use tokio_util::sync::CancellationToken;
+
+    async fn create_test_call(port_offset: u16, session_id: &str) -> Result<Arc<ActiveCall>> {
+        let mut config = Config::default();
+        config.ua = Some(UseragentConfig {
+            addr: "127.0.0.1".to_string(),
+            udp_port: 25020 + port_offset,
+            useragent: Some("rustpbx-test".to_string()),
+            ..Default::default()
+        });
+        let (app_state, _) = AppStateBuilder::new().with_config(config).build().await?;
+        let invitation = app_state
+            .useragent
+            .clone()
+            .ok_or(anyhow::anyhow!("user agent must be initialized"))?
+            .invitation
+            .clone();
+        let active_call = Arc::new(ActiveCall::new(
+            ActiveCallType::WebSocket,
+            CancellationToken::new(),
+            session_id.to_string(),
+            invitation,
+            app_state,
+            TrackConfig::default(),
+            None,
+            false,
+            None,
+            None,
+        ));
+        let serving = active_call.clone();
+        tokio::spawn(async move { serving.serve().await });
+        Ok(active_call)
+    }
+
+    fn gather(segments: Option<Vec<PromptSegment>>, input: GatherOption) -> Command {
+        Command::Gather {
+            segments,
+            locale: None,
+            variables: None,
+            play_id: Some("menu".to_string()),
+            option: None,
+            input: Some(input),
+        }
+    }
+
+    fn send_dtmf(active_call: &ActiveCall, digit: &str) -> Result<()> {
+        active_call.event_sender.send(SessionEvent::Dtmf {
+            track_id: "caller".to_string(),
+            timestamp: crate::get_timestamp(),
+            digit: digit.to_string(),
+            duration: None,
+        })?;
+        Ok(())
+    }
+
+    /// (input, digits, text) of the next gather event
+    async fn next_gather(
+        receiver: &mut broadcast::Receiver<SessionEvent>,
+    ) -> Result<(String, Option<String>, Option<String>)> {
+        timeout(Duration::from_secs(2), async {
+            loop {
+                if let SessionEvent::Gather {
+                    play_id,
+                    input,
+                    digits,
+                    text,
+                    ..
+                } = receiver.recv().await?
+                {
+                    assert_eq!(play_id.as_deref(), Some("menu"));
+                    return Ok((input, digits, text));
+                }
+            }
+        })
+        .await?
+    }
+
+    #[tokio::test]
+    async fn test_gather_dtmf_barge_in() -> Result<()> {
+        let active_call = create_test_call(1, "test_gather_dtmf").await?;
+        let mut receiver = active_call.event_sender.subscribe();
+        let prompt = vec![PromptSegment::File {
+            url: "fixtures/sample.wav".to_string(),
+        }];
+        active_call
+            .enqueue_command(gather(
+                Some(prompt),
+                GatherOption {
+                    max_digits: Some(3),
+                    ..Default::default()
+                },
+            ))
+            .await?;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        for digit in ["4", "2", "#"] {
+            send_dtmf(&active_call, digit)?;
+        }
+        // speech after the digits is not reported
+        active_call.event_sender.send(SessionEvent::AsrFinal {
+            track_id: "caller".to_string(),
+            timestamp: crate::get_timestamp(),
+            index: 0,
+            start_time: None,
+            end_time: None,
+            text: "sales".to_string(),
+        })?;
+        let gathered = next_gather(&mut receiver).await?;
+        assert_eq!(gathered, ("dtmf".to_string(), Some("42".to_string()), None));
+        assert!(
+            timeout(Duration::from_millis(300), next_gather(&mut receiver))
+                .await
+                .is_err()
+        );
+        active_call.cancel_token.cancel();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_gather_speech_and_timeout() -> Result<()> {
+        let active_call = create_test_call(2, "test_gather_speech").await?;
+        let mut receiver = active_call.event_sender.subscribe();
+        let input = GatherOption {
+            timeout: 300,
+            hints: vec!["sales".to_string()],
+            ..Default::default()
+        };
+        active_call
+            .enqueue_command(gather(None, input.clone()))
+            .await?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        active_call.event_sender.send(SessionEvent::AsrFinal {
+            track_id: "caller".to_string(),
+            timestamp: crate::get_timestamp(),
+            index: 0,
+            start_time: None,
+            end_time: None,
+            text: "Sales, please".to_string(),
+        })?;
+        send_dtmf(&active_call, "1")?;
+        let gathered = next_gather(&mut receiver).await?;
+        assert_eq!(
+            gathered,
+            (
+                "speech".to_string(),
+                None,
+                Some("Sales, please".to_string())
+            )
+        );
+
+        active_call.enqueue_command(gather(None, input)).await?;
+        let gathered = next_gather(&mut receiver).await?;
+        assert_eq!(gathered, ("timeout".to_string(), None, None));
+        active_call.cancel_token.cancel();
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+use crate::app::AppStateBuilder;
+use crate::config::Config;
+use crate::handler::grpc::{
+    self,
+    proto::{
+        CallRequest, CallSetup, ExternalMediaAttach, ExternalMediaRequest, KillCallRequest,
+        ListCallsRequest, call_control_client::CallControlClient, call_request,
+        external_media_request,
+    },
+};
+use anyhow::Result;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::Code;
+
+#[tokio::test]
+async fn test_grpc_call_control() -> Result<()> {
+    let mut config = Config::default();
+    config.ua = None;
+    let (state, _) = AppStateBuilder::new().with_config(config).build().await?;
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let token = state.token.clone();
+    tokio::spawn(
+        tonic::transport::Server::builder()
+            .add_service(grpc::service(state.clone()))
+            .serve_with_incoming_shutdown(
+                TcpListenerStream::new(listener),
+                token.cancelled_owned(),
+            ),
+    );
+
+    let mut client = CallControlClient::connect(format!("http://{}", addr)).await?;
+    let calls = client.list_calls(ListCallsRequest {}).await?.into_inner();
+    assert!(calls.calls.is_empty());
+    let killed = client
+        .kill_call(KillCallRequest {
+            id: "unknown".to_string(),
+        })
+        .await?
+        .into_inner();
+    assert!(!killed.killed);
+
+    // the first message must set up the call, which needs a user agent
+    let setup = CallRequest {
+        message: Some(call_request::Message::Setup(CallSetup {
+            id: "grpc-test".to_string(),
+            ..Default::default()
+        })),
+    };
+    let err = client
+        .call(tokio_stream::iter(vec![setup]))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::Unavailable);
+    let command = CallRequest {
+        message: Some(call_request::Message::Command("{}".to_string())),
+    };
+    let err = client
+        .call(tokio_stream::iter(vec![command]))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::InvalidArgument);
+
+    let attach = ExternalMediaRequest {
+        message: Some(external_media_request::Message::Attach(
+            ExternalMediaAttach {
+                session_id: "unknown".to_string(),
+                track_id: None,
+            },
+        )),
+    };
+    let err = client
+        .external_media(tokio_stream::iter(vec![attach]))
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), Code::NotFound);
+
+    state.token.cancel();
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use crate::{
+    app::AppStateBuilder,
+    config::Config,
+    handler::health::{check_rtp_ports, readiness},
+    media::engine::StreamEngine,
+    proxy::{locator::Locator, locator_db::DbLocator},
+};
+use std::sync::Arc;
+
+#[test]
+fn test_check_rtp_ports() {
+    assert!(check_rtp_ports(30000, 30100, 0).is_ok());
+    assert!(check_rtp_ports(30000, 30100, 51).is_err());
+    assert!(check_rtp_ports(30100, 30000, 0).is_err());
+
+    let socket = std::net::UdpSocket::bind("0.0.0.0:30200").expect("bind");
+    let r = check_rtp_ports(30200, 30201, 0);
+    assert!(r.is_err(), "the only port is taken: {:?}", r);
+    drop(socket);
+}
+
+#[tokio::test]
+async fn test_readiness() {
+    let config = Config {
+        rtp_start_port: Some(31000),
+        rtp_end_port: Some(31100),
+        ..Default::default()
+    };
+    let (state, _) = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(StreamEngine::default()))
+        .build()
+        .await
+        .expect("build app state");
+
+    let ready = readiness(&state, None).await;
+    assert!(ready.ready, "{:?}", ready);
+    assert!(ready.checks.iter().any(|check| check.name == "rtpPorts"));
+
+    state.token.cancel();
+    let ready = readiness(&state, None).await;
+    assert!(!ready.ready);
+    let shutdown = ready
+        .checks
+        .iter()
+        .find(|check| check.name == "shutdown")
+        .expect("shutdown check");
+    assert!(!shutdown.ok);
+}
+
+#[tokio::test]
+async fn test_db_locator_check() {
+    let locator = DbLocator::new("sqlite::memory:".to_string())
+        .await
+        .expect("create locator");
+    assert!(locator.check().await.is_ok());
+}
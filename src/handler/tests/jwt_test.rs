@@ -0,0 +1,221 @@
+use crate::{
+    app::AppStateBuilder,
+    config::{AmiConfig, Config, JwtConfig},
+    handler::middleware::jwt::{Claims, Scope, bearer_token, encode, verify},
+    media::engine::StreamEngine,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+use std::{net::SocketAddr, sync::Arc};
+
+const SECRET: &str = "jwt-test-secret";
+
+fn jwt_config() -> JwtConfig {
+    JwtConfig {
+        secret: SECRET.to_string(),
+        algorithm: "HS256".to_string(),
+        issuer: Some("https://auth.example.com".to_string()),
+        audience: Some("rustpbx".to_string()),
+        leeway: 10,
+        allow_no_expiry: false,
+    }
+}
+
+fn claims(scope: &str) -> Claims {
+    Claims {
+        sub: Some("ops".to_string()),
+        iss: Some("https://auth.example.com".to_string()),
+        aud: Some(json!(["other", "rustpbx"])),
+        exp: Some(chrono::Utc::now().timestamp() as u64 + 300),
+        nbf: None,
+        scope: scope.to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_verify() {
+    let config = jwt_config();
+    let now = chrono::Utc::now().timestamp() as u64;
+    let token = encode(&claims("listen originate"), SECRET).unwrap();
+    let verified = verify(&token, &config).unwrap();
+    assert_eq!(verified.sub.as_deref(), Some("ops"));
+    assert!(verified.has_scope(Scope::Listen));
+    assert!(verified.has_scope(Scope::Originate));
+    assert!(!verified.has_scope(Scope::Admin));
+    assert!(claims("admin").has_scope(Scope::Originate));
+
+    // expired, but within the leeway of 10 seconds, then past it
+    let mut expired = claims("listen");
+    expired.exp = Some(now - 5);
+    assert!(verify(&encode(&expired, SECRET).unwrap(), &config).is_ok());
+    expired.exp = Some(now - 15);
+    assert!(verify(&encode(&expired, SECRET).unwrap(), &config).is_err());
+    assert!(verify(&encode(&claims(""), "other").unwrap(), &config).is_err());
+
+    let mut wrong = claims("listen");
+    wrong.iss = Some("https://evil.example.com".to_string());
+    assert!(verify(&encode(&wrong, SECRET).unwrap(), &config).is_err());
+    let mut wrong = claims("listen");
+    wrong.aud = Some(json!("other"));
+    assert!(verify(&encode(&wrong, SECRET).unwrap(), &config).is_err());
+    let mut early = claims("listen");
+    early.nbf = Some(now + 60);
+    assert!(verify(&encode(&early, SECRET).unwrap(), &config).is_err());
+
+    // unsigned tokens are never accepted
+    let (message, _) = token.rsplit_once('.').unwrap();
+    let payload = message.split_once('.').unwrap().1;
+    let none = format!("eyJhbGciOiJub25lIn0.{}.", payload);
+    assert!(verify(&none, &config).is_err());
+    assert!(verify("a.b", &config).is_err());
+
+    // nor ones signed with another algorithm than the configured one
+    let hs384 = jsonwebtoken::encode(
+        &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::HS384),
+        &claims("listen"),
+        &jsonwebtoken::EncodingKey::from_secret(SECRET.as_bytes()),
+    )
+    .unwrap();
+    assert!(verify(&hs384, &config).is_err());
+    let mut config_hs384 = jwt_config();
+    config_hs384.algorithm = "HS384".to_string();
+    assert!(verify(&hs384, &config_hs384).is_ok());
+    assert!(verify(&token, &config_hs384).is_err());
+}
+
+#[test]
+fn test_verify_far_expiry() {
+    let mut far = claims("listen");
+    far.exp = Some(u64::MAX);
+    far.nbf = Some(0);
+    let token = encode(&far, SECRET).unwrap();
+    assert!(
+        verify(&token, &jwt_config())
+            .unwrap()
+            .has_scope(Scope::Listen)
+    );
+}
+
+#[test]
+fn test_verify_no_expiry() {
+    let mut forever = claims("listen");
+    forever.exp = None;
+    let token = encode(&forever, SECRET).unwrap();
+    assert!(verify(&token, &jwt_config()).is_err());
+
+    let mut config = jwt_config();
+    config.allow_no_expiry = true;
+    assert!(verify(&token, &config).unwrap().has_scope(Scope::Listen));
+}
+
+#[test]
+fn test_required_scope() {
+    let get = http::Method::GET;
+    let post = http::Method::POST;
+    assert_eq!(Scope::required(&get, "/ami/v1/lists"), Scope::Listen);
+    assert_eq!(Scope::required(&get, "/ami/v1/wallboard/ws"), Scope::Listen);
+    assert_eq!(
+        Scope::required(&post, "/ami/v1/clicktodial"),
+        Scope::Originate
+    );
+    assert_eq!(Scope::required(&post, "/kill/abc"), Scope::Admin);
+    assert_eq!(Scope::required(&post, "/shutdown"), Scope::Admin);
+    assert_eq!(Scope::required(&get, "/ami/v1/dump"), Scope::Admin);
+}
+
+#[test]
+fn test_bearer_token() {
+    let mut headers = http::HeaderMap::new();
+    let uri: http::Uri = "/call?id=1&token=a%2Eb".parse().unwrap();
+    assert_eq!(bearer_token(&headers, &uri).as_deref(), Some("a.b"));
+    headers.insert(http::header::AUTHORIZATION, "Bearer x.y.z".parse().unwrap());
+    assert_eq!(bearer_token(&headers, &uri).as_deref(), Some("x.y.z"));
+    headers.insert(http::header::AUTHORIZATION, "Basic eDp5".parse().unwrap());
+    assert_eq!(bearer_token(&headers, &uri), None);
+}
+
+#[tokio::test]
+async fn test_jwt_protected_routes() {
+    let config = Config {
+        ami: Some(AmiConfig {
+            allows: Some(vec![]),
+            jwt: Some(jwt_config()),
+        }),
+        rtp_start_port: Some(31200),
+        rtp_end_port: Some(31300),
+        ..Default::default()
+    };
+    let (state, _) = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(StreamEngine::default()))
+        .build()
+        .await
+        .expect("build app state");
+    let router = crate::app::router(state.clone(), None);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+
+    let client = reqwest::Client::new();
+    let listen = encode(&claims("listen"), SECRET).unwrap();
+    let admin = encode(&claims("admin"), SECRET).unwrap();
+
+    let response = client
+        .get(format!("{}/ami/v1/lists", base))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers()["www-authenticate"], "Bearer");
+
+    let response = client
+        .get(format!("{}/ami/v1/lists", base))
+        .bearer_auth(&listen)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .get(format!("{}/ami/v1/lists?token={}", base, listen))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = client
+        .post(format!("{}/ami/v1/shutdown", base))
+        .bearer_auth(&listen)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!state.token.is_cancelled());
+
+    // the call WebSockets need the originate scope once JWTs are on
+    let response = client.get(format!("{}/call", base)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client
+        .get(format!("{}/call", base))
+        .bearer_auth(&listen)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = client
+        .post(format!("{}/ami/v1/shutdown", base))
+        .bearer_auth(&admin)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(state.token.is_cancelled());
+}
@@ -1,5 +1,12 @@
 pub mod callrecord_test;
+#[cfg(feature = "grpc")]
+mod grpc_test;
+pub mod gather_test;
+mod health_test;
+mod jwt_test;
+mod rbac_test;
 mod sip_test;
+mod supervise_test;
 pub mod wait_input_timeout_test;
 pub mod webrtc_test;
 pub mod ws_test;
@@ -0,0 +1,253 @@
+use crate::{
+    app::AppStateBuilder,
+    callrecord::CallRecordEventType,
+    config::{AmiConfig, Config, JournalConfig, JwtConfig},
+    features::RECORDING,
+    handler::middleware::{
+        jwt::{Claims, Scope, encode},
+        rbac::{Principal, Role, is_tenant_aware},
+    },
+    journal::JournalReader,
+    media::engine::StreamEngine,
+};
+use reqwest::StatusCode;
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+const SECRET: &str = "rbac-test-secret";
+
+fn claims(role: Option<Role>, tenant: Option<&str>) -> Claims {
+    Claims {
+        sub: Some("alice".to_string()),
+        exp: Some(chrono::Utc::now().timestamp() as u64 + 300),
+        role,
+        tenant: tenant.map(|tenant| tenant.to_string()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_roles() {
+    assert!(Role::Viewer.allows(Scope::Listen));
+    assert!(!Role::Viewer.allows(Scope::Originate));
+    assert!(Role::Operator.allows(Scope::Originate));
+    assert!(!Role::Operator.allows(Scope::Admin));
+    assert!(Role::Admin.allows(Scope::Admin));
+
+    let operator = claims(Some(Role::Operator), None);
+    assert!(operator.has_scope(Scope::Originate));
+    assert!(!operator.has_scope(Scope::Admin));
+
+    // without a role claim, the widest scope decides
+    let mut scoped = claims(None, None);
+    scoped.scope = "listen originate".to_string();
+    assert_eq!(Principal::from_claims(&scoped).role, Role::Operator);
+    scoped.scope = String::new();
+    assert_eq!(Principal::from_claims(&scoped).role, Role::Viewer);
+
+    let principal = Principal::from_claims(&claims(Some(Role::Admin), Some("acme")));
+    assert_eq!(principal.subject, "alice");
+    assert!(principal.can_access(Some("acme")));
+    assert!(!principal.can_access(Some("globex")));
+    assert!(!principal.can_access(None));
+    assert!(Principal::from_claims(&claims(None, None)).can_access(Some("acme")));
+
+    assert!(is_tenant_aware("/ami/v1/usage"));
+    assert!(is_tenant_aware("/ami/v1/clicktodial/"));
+    assert!(!is_tenant_aware("/ami/v1/lists"));
+    assert!(!is_tenant_aware("/ami/v1/kill/abc"));
+}
+
+#[tokio::test]
+async fn test_rbac_routes() {
+    let journal_dir = tempfile::tempdir().unwrap();
+    let config = Config {
+        ami: Some(AmiConfig {
+            allows: Some(vec![]),
+            jwt: Some(JwtConfig {
+                secret: SECRET.to_string(),
+                algorithm: "HS256".to_string(),
+                issuer: None,
+                audience: None,
+                leeway: 10,
+                allow_no_expiry: false,
+            }),
+        }),
+        journal: Some(JournalConfig {
+            path: journal_dir.path().to_string_lossy().to_string(),
+            max_size: 1024 * 1024,
+            max_age: 3600,
+            compress: false,
+            keep: None,
+        }),
+        rtp_start_port: Some(31300),
+        rtp_end_port: Some(31400),
+        ..Default::default()
+    };
+    let (state, _) = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(StreamEngine::default()))
+        .build()
+        .await
+        .expect("build app state");
+    let router = crate::app::router(state.clone(), None);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+
+    let client = reqwest::Client::new();
+    let viewer = encode(&claims(Some(Role::Viewer), None), SECRET).unwrap();
+    let tenant_admin = encode(&claims(Some(Role::Admin), Some("acme")), SECRET).unwrap();
+    let operator = encode(&claims(Some(Role::Operator), None), SECRET).unwrap();
+    let admin = encode(&claims(Some(Role::Admin), None), SECRET).unwrap();
+
+    let response = client
+        .get(format!("{}/ami/v1/lists", base))
+        .bearer_auth(&viewer)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // tenant principals only reach the endpoints that check tenants
+    let response = client
+        .get(format!("{}/ami/v1/lists", base))
+        .bearer_auth(&tenant_admin)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .post(format!("{}/ami/v1/shutdown", base))
+        .bearer_auth(&tenant_admin)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = client
+        .post(format!("{}/ami/v1/shutdown", base))
+        .bearer_auth(&operator)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!state.token.is_cancelled());
+
+    let response = client
+        .post(format!("{}/ami/v1/shutdown", base))
+        .bearer_auth(&admin)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(state.token.is_cancelled());
+
+    // shutting down closes the journal
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let audits = JournalReader::open(journal_dir.path())
+        .unwrap()
+        .entries()
+        .filter(|entry| entry.r#type == CallRecordEventType::Audit)
+        .collect::<Vec<_>>();
+    assert_eq!(audits.len(), 3);
+    assert_eq!(audits[0].content["principal"]["tenant"], "acme");
+    assert_eq!(audits[0].content["status"], 403);
+    assert_eq!(audits[1].content["principal"]["role"], "operator");
+    assert_eq!(audits[1].content["status"], 403);
+    assert_eq!(audits[2].content["principal"]["role"], "admin");
+    assert_eq!(audits[2].content["method"], "POST");
+    assert_eq!(audits[2].content["path"], "/ami/v1/shutdown");
+    assert_eq!(audits[2].content["status"], 200);
+}
+
+#[tokio::test]
+async fn test_feature_routes() {
+    let config = Config {
+        ami: Some(AmiConfig {
+            allows: Some(vec![]),
+            jwt: Some(JwtConfig {
+                secret: SECRET.to_string(),
+                algorithm: "HS256".to_string(),
+                issuer: None,
+                audience: None,
+                leeway: 10,
+                allow_no_expiry: false,
+            }),
+        }),
+        rtp_start_port: Some(31400),
+        rtp_end_port: Some(31500),
+        ..Default::default()
+    };
+    let (state, _) = AppStateBuilder::new()
+        .with_config(config)
+        .with_stream_engine(Arc::new(StreamEngine::default()))
+        .build()
+        .await
+        .expect("build app state");
+    let router = crate::app::router(state.clone(), None);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+
+    let client = reqwest::Client::new();
+    let viewer = encode(&claims(Some(Role::Viewer), None), SECRET).unwrap();
+    let operator = encode(&claims(Some(Role::Operator), None), SECRET).unwrap();
+    let admin = encode(&claims(Some(Role::Admin), None), SECRET).unwrap();
+    let url = format!("{}/ami/v1/features/extensions/1001@acme.com", base);
+    let flags = serde_json::json!({ "recording": false });
+
+    // toggling features takes the admin scope
+    let response = client
+        .post(&url)
+        .bearer_auth(&operator)
+        .json(&flags)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .post(&url)
+        .bearer_auth(&admin)
+        .json(&flags)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(
+        !state
+            .features
+            .enabled(RECORDING, Some("acme.com"), Some("1001@acme.com"))
+    );
+    assert!(
+        state
+            .features
+            .enabled(RECORDING, Some("acme.com"), Some("1002@acme.com"))
+    );
+
+    let features = client
+        .get(format!("{}/ami/v1/features", base))
+        .bearer_auth(&viewer)
+        .send()
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(
+        features["overrides"]["extensions"]["1001@acme.com"]["recording"],
+        false
+    );
+    state.token.cancel();
+}
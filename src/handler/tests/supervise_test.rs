@@ -0,0 +1,127 @@
+use crate::app::AppStateBuilder;
+use crate::call::{CallOption, Command};
+use crate::config::{Config, UseragentConfig};
+use crate::event::SessionEvent;
+use crate::media::codecs::samples_to_bytes;
+use anyhow::Result;
+use axum::Router;
+use futures::{SinkExt, StreamExt};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::time;
+use tokio_tungstenite::tungstenite::Message;
+use uuid::Uuid;
+
+/// 20ms of a 16kHz square wave, loud enough to be mixed
+fn tone_frame() -> Vec<u8> {
+    let samples = (0..320)
+        .map(|i| if (i / 20) % 2 == 0 { 8000 } else { -8000 })
+        .collect::<Vec<i16>>();
+    samples_to_bytes(&samples)
+}
+
+#[tokio::test]
+async fn test_attach_websocket_supervisor() -> Result<()> {
+    let mut config = Config::default();
+    config.ua = Some(UseragentConfig {
+        addr: "127.0.0.1".to_string(),
+        udp_port: 25064, // Different from other tests
+        useragent: Some("rustpbx-test".to_string()),
+        ..Default::default()
+    });
+    let app = Router::new()
+        .route(
+            "/call",
+            axum::routing::get(crate::handler::handler::ws_handler),
+        )
+        .route(
+            "/call/supervise",
+            axum::routing::get(crate::handler::handler::supervise_handler),
+        )
+        .with_state(AppStateBuilder::new().with_config(config).build().await?.0);
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+
+    // the websocket call, its track is named after the session
+    let session_id = Uuid::new_v4().to_string();
+    let (call_ws, _) =
+        tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}/call?id={}", port, session_id))
+            .await?;
+    let (mut call_sender, mut call_receiver) = call_ws.split();
+    let command = Command::Invite {
+        option: CallOption {
+            codec: Some("pcm".to_string()),
+            ..Default::default()
+        },
+    };
+    call_sender
+        .send(Message::Text(serde_json::to_string(&command)?.into()))
+        .await?;
+    time::timeout(Duration::from_secs(5), async {
+        while let Some(Ok(message)) = call_receiver.next().await {
+            if let Message::Text(text) = message
+                && let Ok(SessionEvent::Answer { .. }) = serde_json::from_str(&text)
+            {
+                return Ok(());
+            }
+        }
+        Err(anyhow::anyhow!("call closed before the answer"))
+    })
+    .await??;
+
+    let (supervisor_ws, _) = tokio_tungstenite::connect_async(format!(
+        "ws://127.0.0.1:{}/call/supervise?id={}&trackId=supervisor&target={}&mode=barge",
+        port, session_id, session_id
+    ))
+    .await?;
+    let (mut supervisor_sender, mut supervisor_receiver) = supervisor_ws.split();
+
+    let talk = async {
+        let mut ticker = time::interval(Duration::from_millis(20));
+        loop {
+            ticker.tick().await;
+            if let Err(e) = call_sender.send(Message::Binary(tone_frame().into())).await {
+                return e;
+            }
+            if let Err(e) = supervisor_sender
+                .send(Message::Binary(tone_frame().into()))
+                .await
+            {
+                return e;
+            }
+        }
+    };
+    // barging in, the supervisor hears the call and the call hears the supervisor
+    let heard = async {
+        let call_heard = async {
+            while let Some(Ok(message)) = call_receiver.next().await {
+                if let Message::Binary(data) = message
+                    && !data.is_empty()
+                {
+                    return true;
+                }
+            }
+            false
+        };
+        let supervisor_heard = async {
+            while let Some(Ok(message)) = supervisor_receiver.next().await {
+                if let Message::Binary(data) = message
+                    && !data.is_empty()
+                {
+                    return true;
+                }
+            }
+            false
+        };
+        tokio::join!(call_heard, supervisor_heard)
+    };
+    let heard = tokio::select! {
+        e = talk => return Err(e.into()),
+        heard = time::timeout(Duration::from_secs(5), heard) => heard?,
+    };
+    assert_eq!(heard, (true, true));
+    Ok(())
+}
@@ -241,6 +241,7 @@ mod wait_input_timeout_tests {
             track_id: session_id.clone(),
             timestamp: crate::get_timestamp(),
             digit: "1".to_string(),
+            duration: None,
         })?;
 
         // Check that no Silence event is received
@@ -0,0 +1,41 @@
+use anyhow::Result;
+use flate2::{Compression, read::MultiGzDecoder, write::GzEncoder};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Compresses `src` into the gzip file `dest`
+pub fn compress_file(src: &Path, dest: &Path) -> Result<()> {
+    let mut input = File::open(src)?;
+    let mut encoder = GzEncoder::new(File::create(dest)?, Compression::new(6));
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    Ok(())
+}
+
+/// Reads a gzip file, or a plain one as is
+pub struct GzReader {
+    inner: Box<dyn Read + Send>,
+}
+
+impl GzReader {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = BufReader::new(File::open(path)?);
+        let inner: Box<dyn Read + Send> = if file.fill_buf()?.starts_with(&GZIP_MAGIC) {
+            Box::new(MultiGzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        Ok(Self { inner })
+    }
+}
+
+impl Read for GzReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
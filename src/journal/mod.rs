@@ -0,0 +1,342 @@
+use crate::{callrecord::CallRecordEventType, config::JournalConfig, get_timestamp};
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashSet, VecDeque},
+    io::{BufRead, BufReader, Lines},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{fs::File, io::AsyncWriteExt, select, sync::mpsc};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+pub mod gzip;
+#[cfg(test)]
+mod tests;
+
+const PREFIX: &str = "events-";
+const PLAIN: &str = ".jsonl";
+const COMPRESSED: &str = ".jsonl.gz";
+
+/// One line of the journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub timestamp: u64,
+    pub r#type: CallRecordEventType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    pub content: serde_json::Value,
+}
+
+/// Appends events to the journal files, written in the background
+pub struct Journal {
+    sender: mpsc::UnboundedSender<JournalEntry>,
+}
+
+pub type JournalRef = Arc<Journal>;
+
+impl Journal {
+    /// Starts the writer, which runs until `token` is cancelled
+    pub fn start(config: JournalConfig, token: CancellationToken) -> Result<JournalRef> {
+        std::fs::create_dir_all(&config.path)?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(JournalWriter::new(config).run(receiver, token));
+        Ok(Arc::new(Self { sender }))
+    }
+
+    pub fn record(
+        &self,
+        r#type: CallRecordEventType,
+        session_id: Option<&str>,
+        content: &impl Serialize,
+    ) {
+        let Ok(content) = serde_json::to_value(content) else {
+            return;
+        };
+        self.sender
+            .send(JournalEntry {
+                timestamp: get_timestamp(),
+                r#type,
+                session_id: session_id.map(|id| id.to_string()),
+                content,
+            })
+            .ok();
+    }
+}
+
+struct CurrentFile {
+    file: File,
+    path: PathBuf,
+    size: u64,
+    opened_at: Instant,
+}
+
+/// Writes the entries to `events-<time>.jsonl`, and rotates the file
+/// once it is too big or too old
+pub struct JournalWriter {
+    config: JournalConfig,
+    current: Option<CurrentFile>,
+}
+
+impl JournalWriter {
+    pub fn new(config: JournalConfig) -> Self {
+        Self {
+            config,
+            current: None,
+        }
+    }
+
+    async fn run(
+        mut self,
+        mut receiver: mpsc::UnboundedReceiver<JournalEntry>,
+        token: CancellationToken,
+    ) {
+        // files left open by a crash are rotated like any other
+        for path in journal_files(Path::new(&self.config.path)) {
+            if path.to_string_lossy().ends_with(PLAIN) {
+                self.finish(path).await;
+            }
+        }
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            select! {
+                _ = token.cancelled() => break,
+                entry = receiver.recv() => match entry {
+                    Some(entry) => {
+                        if let Err(e) = self.write(&entry).await {
+                            warn!(path = self.config.path, "failed to write journal: {}", e);
+                        }
+                    }
+                    None => break,
+                },
+                _ = ticker.tick() => {
+                    if self.expired() {
+                        self.rotate().await;
+                    }
+                }
+            }
+        }
+        while let Ok(entry) = receiver.try_recv() {
+            self.write(&entry).await.ok();
+        }
+        self.rotate().await;
+        info!(path = self.config.path, "journal closed");
+    }
+
+    fn expired(&self) -> bool {
+        self.current.as_ref().is_some_and(|current| {
+            current.opened_at.elapsed() >= Duration::from_secs(self.config.max_age)
+        })
+    }
+
+    pub async fn write(&mut self, entry: &JournalEntry) -> Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let full = self.current.as_ref().is_some_and(|current| {
+            current.size > 0 && current.size + line.len() as u64 > self.config.max_size
+        });
+        if full || self.expired() {
+            self.rotate().await;
+        }
+        let current = match self.current.as_mut() {
+            Some(current) => current,
+            None => self.current.insert(self.open().await?),
+        };
+        current.file.write_all(&line).await?;
+        current.size += line.len() as u64;
+        Ok(())
+    }
+
+    async fn open(&self) -> Result<CurrentFile> {
+        let name = format!(
+            "{}{}{}",
+            PREFIX,
+            Utc::now().format("%Y%m%d-%H%M%S%.3f"),
+            PLAIN
+        );
+        let path = Path::new(&self.config.path).join(name);
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        let size = file.metadata().await?.len();
+        Ok(CurrentFile {
+            file,
+            path,
+            size,
+            opened_at: Instant::now(),
+        })
+    }
+
+    /// Closes the current file, compresses it and deletes the files past
+    /// `keep`
+    pub async fn rotate(&mut self) {
+        let Some(current) = self.current.take() else {
+            return;
+        };
+        current.file.sync_all().await.ok();
+        drop(current.file);
+        self.finish(current.path).await;
+        if let Some(keep) = self.config.keep {
+            let files = journal_files(Path::new(&self.config.path));
+            for path in files.iter().take(files.len().saturating_sub(keep)) {
+                std::fs::remove_file(path).ok();
+            }
+        }
+    }
+
+    async fn finish(&self, path: PathBuf) {
+        if !self.config.compress {
+            return;
+        }
+        let dest = PathBuf::from(format!("{}.gz", path.to_string_lossy()));
+        let r = {
+            let (path, dest) = (path.clone(), dest.clone());
+            tokio::task::spawn_blocking(move || gzip::compress_file(&path, &dest)).await
+        };
+        match r {
+            Ok(Ok(_)) => {
+                std::fs::remove_file(&path).ok();
+            }
+            Ok(Err(e)) => {
+                warn!(path = %path.display(), "failed to compress journal: {}", e);
+                std::fs::remove_file(&dest).ok();
+            }
+            Err(e) => warn!(path = %path.display(), "failed to compress journal: {}", e),
+        }
+    }
+}
+
+/// The journal files in a directory, oldest first
+pub fn journal_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            name.starts_with(PREFIX) && (name.ends_with(PLAIN) || name.ends_with(COMPRESSED))
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Which entries to replay
+#[derive(Debug, Clone, Default)]
+pub struct JournalFilter {
+    /// Milliseconds since the epoch
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub session_id: Option<String>,
+    pub r#type: Option<CallRecordEventType>,
+}
+
+impl JournalFilter {
+    pub fn matches(&self, entry: &JournalEntry) -> bool {
+        self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+            && self
+                .session_id
+                .as_ref()
+                .is_none_or(|id| entry.session_id.as_ref() == Some(id))
+            && self.r#type.as_ref().is_none_or(|t| entry.r#type == *t)
+    }
+}
+
+/// Reads back the entries of a journal directory or of a single file,
+/// compressed or not. Lines that don't parse, such as the last one of a
+/// file cut short by a crash, are skipped
+pub struct JournalReader {
+    files: Vec<PathBuf>,
+}
+
+impl JournalReader {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let files = match path.is_dir() {
+            true => journal_files(path),
+            false if path.exists() => vec![path.to_path_buf()],
+            false => return Err(anyhow::anyhow!("{} not found", path.display())),
+        };
+        Ok(Self { files })
+    }
+
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// In the order they were written
+    pub fn entries(&self) -> JournalEntries {
+        JournalEntries {
+            files: self.files.iter().cloned().collect(),
+            lines: None,
+        }
+    }
+
+    pub fn replay(&self, filter: JournalFilter) -> impl Iterator<Item = JournalEntry> {
+        self.entries().filter(move |entry| filter.matches(entry))
+    }
+}
+
+pub struct JournalEntries {
+    files: VecDeque<PathBuf>,
+    lines: Option<Lines<BufReader<gzip::GzReader>>>,
+}
+
+impl Iterator for JournalEntries {
+    type Item = JournalEntry;
+
+    fn next(&mut self) -> Option<JournalEntry> {
+        loop {
+            let Some(lines) = self.lines.as_mut() else {
+                let path = self.files.pop_front()?;
+                match gzip::GzReader::open(&path) {
+                    Ok(reader) => self.lines = Some(BufReader::new(reader).lines()),
+                    Err(e) => warn!("failed to read journal: {}", e),
+                }
+                continue;
+            };
+            match lines.next() {
+                Some(Ok(line)) => {
+                    if let Ok(entry) = serde_json::from_str(&line) {
+                        return Some(entry);
+                    }
+                }
+                Some(Err(e)) => {
+                    warn!("failed to read journal: {}", e);
+                    self.lines = None;
+                }
+                None => self.lines = None,
+            }
+        }
+    }
+}
+
+/// The calls with entries but no call record, in progress when the
+/// journal stopped, such as on a crash
+pub fn unfinished_sessions(entries: impl Iterator<Item = JournalEntry>) -> Vec<String> {
+    let mut sessions = Vec::new();
+    let mut ended = HashSet::new();
+    for entry in entries {
+        let Some(session_id) = entry.session_id else {
+            continue;
+        };
+        if entry.r#type == CallRecordEventType::CallRecord {
+            ended.insert(session_id);
+        } else if !sessions.contains(&session_id) {
+            sessions.push(session_id);
+        }
+    }
+    sessions.retain(|id| !ended.contains(id));
+    sessions
+}
@@ -0,0 +1,180 @@
+use super::*;
+use std::io::Write;
+
+fn config(dir: &Path) -> JournalConfig {
+    JournalConfig {
+        path: dir.to_string_lossy().to_string(),
+        max_size: 64 * 1024 * 1024,
+        max_age: 3600,
+        compress: true,
+        keep: None,
+    }
+}
+
+fn entry(timestamp: u64, r#type: CallRecordEventType, session_id: &str) -> JournalEntry {
+    JournalEntry {
+        timestamp,
+        r#type,
+        session_id: Some(session_id.to_string()),
+        content: serde_json::json!({ "n": timestamp }),
+    }
+}
+
+#[tokio::test]
+async fn test_journal_rotates_and_compresses() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut writer = JournalWriter::new(JournalConfig {
+        max_size: 200,
+        ..config(dir.path())
+    });
+    for n in 0..10 {
+        writer
+            .write(&entry(n, CallRecordEventType::Event, "call-1"))
+            .await?;
+        // keeps the file names apart
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    writer.rotate().await;
+
+    let files = journal_files(dir.path());
+    assert!(files.len() > 1, "expected rotation, got {:?}", files);
+    for path in files.iter() {
+        assert!(path.to_string_lossy().ends_with(COMPRESSED));
+        assert!(std::fs::metadata(path)?.len() > 0);
+    }
+
+    let reader = JournalReader::open(dir.path())?;
+    let timestamps = reader.entries().map(|e| e.timestamp).collect::<Vec<_>>();
+    assert_eq!(timestamps, (0..10).collect::<Vec<_>>());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_journal_keep() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let mut writer = JournalWriter::new(JournalConfig {
+        max_size: 1,
+        compress: false,
+        keep: Some(2),
+        ..config(dir.path())
+    });
+    for n in 0..5 {
+        writer
+            .write(&entry(n, CallRecordEventType::Event, "call-1"))
+            .await?;
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+    writer.rotate().await;
+
+    let files = journal_files(dir.path());
+    assert_eq!(files.len(), 2);
+    let reader = JournalReader::open(dir.path())?;
+    let timestamps = reader.entries().map(|e| e.timestamp).collect::<Vec<_>>();
+    assert_eq!(timestamps, vec![3, 4]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_journal_start_and_stop() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let token = CancellationToken::new();
+    let journal = Journal::start(config(dir.path()), token.clone())?;
+    journal.record(
+        CallRecordEventType::Command,
+        Some("call-1"),
+        &serde_json::json!({ "command": "hangup" }),
+    );
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    token.cancel();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let files = journal_files(dir.path());
+    assert_eq!(files.len(), 1);
+    assert!(files[0].to_string_lossy().ends_with(COMPRESSED));
+    let entries = JournalReader::open(dir.path())?
+        .entries()
+        .collect::<Vec<_>>();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].r#type, CallRecordEventType::Command);
+    assert_eq!(entries[0].content["command"], "hangup");
+    Ok(())
+}
+
+#[test]
+fn test_journal_reader_skips_bad_lines() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let plain = dir.path().join("events-20250101-000000.000.jsonl");
+    let mut file = std::fs::File::create(&plain)?;
+    for n in 0..2 {
+        let line = serde_json::to_string(&entry(n, CallRecordEventType::Sip, "call-1"))?;
+        writeln!(file, "{}", line)?;
+    }
+    writeln!(file, "not json")?;
+    // cut short by a crash
+    write!(file, "{{\"timestamp\":2,\"type\":")?;
+    drop(file);
+
+    let compressed = dir.path().join("events-20250101-000001.000.jsonl.gz");
+    let source = dir.path().join("source.jsonl");
+    let line = serde_json::to_string(&entry(3, CallRecordEventType::Sip, "call-2"))?;
+    std::fs::write(&source, format!("{}\n", line))?;
+    gzip::compress_file(&source, &compressed)?;
+
+    let reader = JournalReader::open(dir.path())?;
+    assert_eq!(reader.files(), &[plain, compressed]);
+    let timestamps = reader.entries().map(|e| e.timestamp).collect::<Vec<_>>();
+    assert_eq!(timestamps, vec![0, 1, 3]);
+    Ok(())
+}
+
+#[test]
+fn test_journal_filter() {
+    let entries = vec![
+        entry(100, CallRecordEventType::Sip, "call-1"),
+        entry(200, CallRecordEventType::Event, "call-1"),
+        entry(300, CallRecordEventType::Event, "call-2"),
+        entry(400, CallRecordEventType::CallRecord, "call-1"),
+    ];
+    let select = |filter: JournalFilter| {
+        entries
+            .iter()
+            .filter(|e| filter.matches(e))
+            .map(|e| e.timestamp)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(select(JournalFilter::default()).len(), 4);
+    assert_eq!(
+        select(JournalFilter {
+            since: Some(200),
+            until: Some(400),
+            ..Default::default()
+        }),
+        vec![200, 300]
+    );
+    assert_eq!(
+        select(JournalFilter {
+            session_id: Some("call-1".to_string()),
+            r#type: Some(CallRecordEventType::Event),
+            ..Default::default()
+        }),
+        vec![200]
+    );
+}
+
+#[test]
+fn test_unfinished_sessions() {
+    let entries = vec![
+        entry(1, CallRecordEventType::Sip, "call-1"),
+        entry(2, CallRecordEventType::Event, "call-2"),
+        entry(3, CallRecordEventType::Event, "call-3"),
+        entry(4, CallRecordEventType::CallRecord, "call-1"),
+        JournalEntry {
+            session_id: None,
+            ..entry(5, CallRecordEventType::Sip, "")
+        },
+    ];
+    assert_eq!(
+        unfinished_sessions(entries.into_iter()),
+        vec!["call-2".to_string(), "call-3".to_string()]
+    );
+}
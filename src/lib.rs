@@ -4,11 +4,16 @@ pub mod app;
 pub mod call;
 pub mod callrecord;
 pub mod config;
+pub mod config_schema;
 pub mod event;
+pub mod features;
 pub mod handler;
+pub mod journal;
 pub mod llm;
 pub mod media;
+pub mod mrcp;
 pub mod net_tool;
+pub mod pbx;
 pub mod proxy;
 pub mod synthesis;
 pub mod transcription;
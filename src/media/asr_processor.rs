@@ -1,6 +1,6 @@
+use super::error::MediaResult;
 use super::processor::Processor;
 use crate::{AudioFrame, Samples, transcription::TranscriptionClient};
-use anyhow::Result;
 
 pub struct AsrProcessor {
     pub asr_client: Box<dyn TranscriptionClient>,
@@ -9,7 +9,7 @@ pub struct AsrProcessor {
 impl AsrProcessor {}
 
 impl Processor for AsrProcessor {
-    fn process_frame(&self, frame: &mut AudioFrame) -> Result<()> {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
         match &frame.samples {
             Samples::PCM { samples } => {
                 self.asr_client.send_audio(&samples)?;
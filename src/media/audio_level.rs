@@ -0,0 +1,64 @@
+//! Client-to-mixer audio level header extension (RFC 6464).
+//!
+//! Each packet sent carries the level of the audio it encodes, and the level
+//! of each packet received is kept in [`TrackLevels`], so that a mixer can
+//! pick the active speakers from the RTP headers without decoding every
+//! participant.
+use super::meter::{AudioLevel, TrackLevels};
+use crate::Sample;
+use webrtc::{
+    rtp::{extension::audio_level_extension::AudioLevelExtension, header::Header},
+    util::{Marshal, Unmarshal},
+};
+
+pub const AUDIO_LEVEL_URI: &str = "urn:ietf:params:rtp-hdrext:ssrc-audio-level";
+
+/// Level of silence, and of anything quieter (in -dBov)
+pub const SILENCE_LEVEL: u8 = 127;
+
+/// Level of `samples` (in -dBov), 0 for a full scale square wave
+pub fn audio_level(samples: &[Sample]) -> u8 {
+    if samples.is_empty() {
+        return SILENCE_LEVEL;
+    }
+    let squares: f64 = samples.iter().map(|&s| s as f64 * s as f64).sum();
+    let rms = (squares / samples.len() as f64).sqrt();
+    if rms < 1.0 {
+        return SILENCE_LEVEL;
+    }
+    (-20.0 * (rms / 32768.0).log10())
+        .round()
+        .clamp(0.0, SILENCE_LEVEL as f64) as u8
+}
+
+/// The level a packet carries in the extension with `id`
+pub fn read_audio_level(header: &Header, id: u8) -> Option<AudioLevelExtension> {
+    let mut payload = header.get_extension(id)?;
+    AudioLevelExtension::unmarshal(&mut payload).ok()
+}
+
+/// Adds the level of the audio to a packet, in the extension with `id`
+pub fn write_audio_level(header: &mut Header, id: u8, level: AudioLevelExtension) {
+    if let Ok(payload) = level.marshal() {
+        header.set_extension(id, payload).ok();
+    }
+}
+
+/// Keeps the level a packet of `track_id` carries, received at `timestamp`
+pub fn update_levels(
+    levels: &TrackLevels,
+    track_id: &str,
+    level: AudioLevelExtension,
+    timestamp: u64,
+) {
+    let dbfs = -(level.level.min(SILENCE_LEVEL) as f32);
+    levels.update(
+        track_id,
+        AudioLevel {
+            rms: dbfs,
+            peak: dbfs,
+            start_time: timestamp,
+            end_time: timestamp,
+        },
+    );
+}
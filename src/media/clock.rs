@@ -0,0 +1,112 @@
+//! Time source for pacing and jitter buffering.
+//!
+//! The [`Pacer`](crate::media::pacer::Pacer), the jitter buffer and the
+//! tone, file, TTS and RTP tracks built on them ask a [`Clock`] for the time
+//! instead of the system, so tests can swap in a [`ManualClock`] and step
+//! through seconds of media without waiting for them. The call timers are
+//! left on tokio's time, which `start_paused` tests control.
+use futures::future::BoxFuture;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Instant;
+    /// Wall clock time (in ms since the epoch), as `crate::get_timestamp`
+    fn timestamp(&self) -> u64;
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()>;
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        self.sleep_until(self.now() + duration)
+    }
+}
+
+pub type ClockRef = Arc<dyn Clock>;
+
+/// The tokio clock, paused along with tokio's in `start_paused` tests
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn timestamp(&self) -> u64 {
+        crate::get_timestamp()
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep_until(deadline))
+    }
+}
+
+pub fn system_clock() -> ClockRef {
+    Arc::new(SystemClock)
+}
+
+/// A clock that only moves when told to. Sleepers wake once `advance`
+/// passed their deadline
+#[derive(Debug)]
+pub struct ManualClock {
+    start: Instant,
+    epoch: u64,
+    now: watch::Sender<Instant>,
+    elapsed: Mutex<Duration>,
+}
+
+impl ManualClock {
+    /// Starts at `epoch` (in ms since the epoch) on the wall clock
+    pub fn new(epoch: u64) -> Arc<Self> {
+        let start = Instant::now();
+        Arc::new(Self {
+            start,
+            epoch,
+            now: watch::Sender::new(start),
+            elapsed: Mutex::new(Duration::ZERO),
+        })
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut elapsed = self.elapsed.lock().unwrap();
+        *elapsed += duration;
+        self.now.send_replace(self.start + *elapsed);
+    }
+
+    /// Advances `step` at a time until `duration` passed, yielding in
+    /// between so the tasks woken on each step get to run
+    pub async fn run_for(&self, duration: Duration, step: Duration) {
+        let step = step.max(Duration::from_millis(1));
+        let mut remaining = duration;
+        while !remaining.is_zero() {
+            let step = step.min(remaining);
+            self.advance(step);
+            remaining -= step;
+            for _ in 0..8 {
+                tokio::task::yield_now().await;
+            }
+        }
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.borrow()
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.epoch + self.elapsed().as_millis() as u64
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+        let mut now = self.now.subscribe();
+        Box::pin(async move {
+            now.wait_for(|now| *now >= deadline).await.ok();
+        })
+    }
+}
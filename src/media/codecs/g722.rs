@@ -1,11 +1,53 @@
 use super::{Decoder, Encoder};
 use crate::{PcmBuf, Sample};
+use serde::{Deserialize, Serialize};
+
+/// The 64, 56 and 48 kbit/s modes, the lower ones drop the least
+/// significant bits of the low band
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "u32", into = "u32")]
 pub enum Bitrate {
+    #[default]
     Mode1_64000,
     Mode2_56000,
     Mode3_48000,
 }
 
+impl TryFrom<u32> for Bitrate {
+    type Error = String;
+
+    fn try_from(bps: u32) -> Result<Self, Self::Error> {
+        match bps {
+            64000 => Ok(Bitrate::Mode1_64000),
+            56000 => Ok(Bitrate::Mode2_56000),
+            48000 => Ok(Bitrate::Mode3_48000),
+            _ => Err(format!("unsupported G.722 bitrate {}", bps)),
+        }
+    }
+}
+
+impl From<Bitrate> for u32 {
+    fn from(rate: Bitrate) -> Self {
+        match rate {
+            Bitrate::Mode1_64000 => 64000,
+            Bitrate::Mode2_56000 => 56000,
+            Bitrate::Mode3_48000 => 48000,
+        }
+    }
+}
+
+/// How the codes of a G.722 stream are put in the RTP payload. Unpacked,
+/// each code takes an octet, left aligned so a 64 kbit/s decoder plays the
+/// lower modes too (RFC 3551). Packed, the codes are bit packed and the
+/// lower modes actually take less bandwidth, both ends must agree on it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct G722Mode {
+    pub bitrate: Bitrate,
+    pub packed: bool,
+}
+
 // Quantization decision thresholds used in the encoder
 const QUANT_DECISION_LEVEL: [i32; 32] = [
     0, 35, 72, 110, 150, 190, 233, 276, 323, 370, 422, 473, 530, 587, 650, 714, 786, 858, 940,
@@ -77,7 +119,7 @@ const QUANT_MULT_64K: [i32; 64] = [
 ];
 
 impl Bitrate {
-    fn bits_per_sample(&self) -> i32 {
+    pub fn bits_per_sample(&self) -> i32 {
         match self {
             Bitrate::Mode1_64000 => 8,
             Bitrate::Mode2_56000 => 7,
@@ -280,6 +322,10 @@ impl G722Encoder {
         Self::with_options(Bitrate::Mode1_64000, false, false)
     }
 
+    pub fn with_mode(mode: G722Mode) -> Self {
+        Self::with_options(mode.bitrate, false, mode.packed)
+    }
+
     /// Creates an encoder with specified bitrate and options
     pub fn with_options(rate: Bitrate, eight_k: bool, packed: bool) -> Self {
         let mut encoder = Self {
@@ -291,11 +337,7 @@ impl G722Encoder {
             out_buffer: 0,
             out_bits: 0,
         };
-
-        // Initialize band states with correct starting values
-        encoder.band[0].log_scale_factor = 32 << 2; // Initial det value for lower band
-        encoder.band[1].log_scale_factor = 8 << 2; // Initial det value for upper band
-
+        Encoder::reset(&mut encoder);
         encoder
     }
 
@@ -362,9 +404,12 @@ impl G722Encoder {
             self.output_code(code, &mut output);
         }
 
-        // Handle any remaining bits in the output buffer
+        // Handle any remaining bits in the output buffer, each call is a
+        // packet of its own
         if self.packed && self.out_bits > 0 {
             output.push((self.out_buffer & 0xFF) as u8);
+            self.out_buffer = 0;
+            self.out_bits = 0;
         }
 
         output
@@ -423,7 +468,7 @@ impl G722Encoder {
 
         // Return appropriate value based on mode
         if is_eight_k {
-            ((0xc0 | ilow) >> 8) - self.bits_per_sample
+            (0xc0 | ilow) >> (8 - self.bits_per_sample)
         } else {
             ilow
         }
@@ -487,8 +532,8 @@ impl G722Encoder {
                 self.out_buffer >>= 8;
             }
         } else {
-            // Direct byte-aligned output
-            output.push(code as u8);
+            // Direct byte-aligned output, unused low bits left zero
+            output.push((code << (8 - self.bits_per_sample)) as u8);
         }
     }
 }
@@ -498,6 +543,10 @@ impl G722Decoder {
         Self::with_options(Bitrate::Mode1_64000, false, false)
     }
 
+    pub fn with_mode(mode: G722Mode) -> Self {
+        Self::with_options(mode.bitrate, mode.packed, false)
+    }
+
     pub fn with_options(rate: Bitrate, packed: bool, eight_k: bool) -> Self {
         Self {
             packed,
@@ -525,7 +574,7 @@ impl G722Decoder {
             code
         } else {
             // Direct byte-based access when not packed
-            let code = data[*idx] as i32;
+            let code = data[*idx] as i32 >> (8 - self.bits_per_sample);
             *idx += 1;
             code
         }
@@ -659,8 +708,11 @@ impl G722Decoder {
     pub fn decode_frame(&mut self, data: &[u8]) -> PcmBuf {
         let mut output = Vec::with_capacity(data.len() * 2);
         let mut idx = 0;
+        // each packed payload starts on an octet boundary
+        self.in_buffer = 0;
+        self.in_bits = 0;
 
-        while idx < data.len() {
+        while idx < data.len() || (self.packed && self.in_bits >= self.bits_per_sample) {
             // Extract the next code from input data
             let code = self.extract_code(data, &mut idx);
 
@@ -692,6 +744,16 @@ impl Encoder for G722Encoder {
         self.g722_encode(samples)
     }
 
+    fn reset(&mut self) {
+        self.x = [0; 24];
+        self.band = Default::default();
+        // Initialize band states with correct starting values
+        self.band[0].log_scale_factor = 32 << 2; // Initial det value for lower band
+        self.band[1].log_scale_factor = 8 << 2; // Initial det value for upper band
+        self.out_buffer = 0;
+        self.out_bits = 0;
+    }
+
     fn sample_rate(&self) -> u32 {
         16000 // G.722 encoding sample rate is 16kHz
     }
@@ -706,6 +768,13 @@ impl Decoder for G722Decoder {
         self.decode_frame(data)
     }
 
+    fn reset(&mut self) {
+        self.x = Default::default();
+        self.band = Default::default();
+        self.in_buffer = 0;
+        self.in_bits = 0;
+    }
+
     fn sample_rate(&self) -> u32 {
         16000
     }
@@ -50,6 +50,10 @@ impl Decoder for G729Decoder {
     fn channels(&self) -> u16 {
         1 // G.729 is always mono
     }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
 }
 
 /// G.729 audio encoder using g729-sys
@@ -100,4 +104,8 @@ impl Encoder for G729Encoder {
     fn channels(&self) -> u16 {
         1 // G.729 is always mono
     }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
 }
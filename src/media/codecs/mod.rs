@@ -1,3 +1,4 @@
+use super::error::MediaError;
 use crate::{PcmBuf, Sample};
 pub mod g722;
 #[cfg(feature = "g729")]
@@ -8,8 +9,11 @@ pub mod pcma;
 pub mod pcmu;
 pub mod resample;
 pub mod telephone_event;
+pub mod testing;
 #[cfg(test)]
 mod tests;
+pub mod transcode;
+pub mod verify;
 #[derive(Debug, Clone, Copy, Eq, Ord, PartialEq, PartialOrd)]
 pub enum CodecType {
     PCMU,
@@ -31,6 +35,10 @@ pub trait Decoder: Send + Sync {
 
     /// Get the number of channels
     fn channels(&self) -> u16;
+
+    /// Forget the stream decoded so far, on a discontinuity (new SSRC, a
+    /// long gap, resume from hold)
+    fn reset(&mut self) {}
 }
 
 pub trait Encoder: Send + Sync {
@@ -42,6 +50,9 @@ pub trait Encoder: Send + Sync {
 
     /// Get the number of channels expected for input
     fn channels(&self) -> u16;
+
+    /// Start a new stream, on a discontinuity
+    fn reset(&mut self) {}
 }
 
 pub fn create_decoder(codec: CodecType) -> Box<dyn Decoder> {
@@ -87,7 +98,8 @@ impl CodecType {
         match self {
             CodecType::PCMU => "PCMU/8000",
             CodecType::PCMA => "PCMA/8000",
-            CodecType::G722 => "G722/16000",
+            // sampled at 16 kHz, but the RTP clock is 8 kHz (RFC 3551)
+            CodecType::G722 => "G722/8000",
             #[cfg(feature = "g729")]
             CodecType::G729 => "G729/8000",
             #[cfg(feature = "opus")]
@@ -142,25 +154,51 @@ impl CodecType {
             _ => false,
         }
     }
-}
 
-impl TryFrom<&String> for CodecType {
-    type Error = anyhow::Error;
+    /// Codec of a payload type when the SDP doesn't say, the static ones of
+    /// RFC 3551 and the dynamic ones offered by default
+    pub fn from_payload_type(payload_type: u8) -> Option<Self> {
+        match payload_type {
+            0 => Some(CodecType::PCMU),
+            8 => Some(CodecType::PCMA),
+            9 => Some(CodecType::G722),
+            #[cfg(feature = "g729")]
+            18 => Some(CodecType::G729), // Static payload type
+            #[cfg(feature = "opus")]
+            111 => Some(CodecType::Opus), // Dynamic payload type
+            101 => Some(CodecType::TelephoneEvent),
+            _ => None,
+        }
+    }
 
-    fn try_from(value: &String) -> Result<Self, Self::Error> {
-        match value.as_str() {
-            "0" => Ok(CodecType::PCMU),
-            "8" => Ok(CodecType::PCMA),
-            "9" => Ok(CodecType::G722),
+    /// Codec of an `a=rtpmap` encoding name, e.g. `opus` of `opus/48000/2`
+    pub fn from_encoding_name(name: &str) -> Option<Self> {
+        let name = name.split('/').next().unwrap_or_default();
+        match name.to_ascii_lowercase().as_str() {
+            "pcmu" => Some(CodecType::PCMU),
+            "pcma" => Some(CodecType::PCMA),
+            "g722" => Some(CodecType::G722),
             #[cfg(feature = "g729")]
-            "18" => Ok(CodecType::G729), // Static payload type
+            "g729" => Some(CodecType::G729),
             #[cfg(feature = "opus")]
-            "111" => Ok(CodecType::Opus), // Dynamic payload type
-            "101" => Ok(CodecType::TelephoneEvent),
-            _ => Err(anyhow::anyhow!("Invalid codec type: {}", value)),
+            "opus" => Some(CodecType::Opus),
+            "telephone-event" => Some(CodecType::TelephoneEvent),
+            _ => None,
         }
     }
 }
+
+impl TryFrom<&String> for CodecType {
+    type Error = MediaError;
+
+    fn try_from(value: &String) -> Result<Self, Self::Error> {
+        value
+            .parse::<u8>()
+            .ok()
+            .and_then(CodecType::from_payload_type)
+            .ok_or_else(|| MediaError::UnsupportedCodec(value.clone()))
+    }
+}
 #[cfg(target_endian = "little")]
 pub fn samples_to_bytes(samples: &[Sample]) -> Vec<u8> {
     unsafe {
@@ -71,6 +71,10 @@ impl Decoder for OpusDecoder {
     fn channels(&self) -> u16 {
         self.channels
     }
+
+    fn reset(&mut self) {
+        self.decoder.reset_state().ok();
+    }
 }
 
 /// Opus audio encoder
@@ -136,4 +140,8 @@ impl Encoder for OpusEncoder {
     fn channels(&self) -> u16 {
         self.channels
     }
+
+    fn reset(&mut self) {
+        self.encoder.reset_state().ok();
+    }
 }
@@ -79,9 +79,10 @@ impl PcmaEncoder {
 
     /// Converts a linear 16-bit PCM sample to an A-law encoded byte
     fn linear2alaw(&self, pcm_val: i16) -> u8 {
-        // Special case handling for small negative values [-8, -1]
+        // Special case handling for small negative values [-8, -1], they
+        // share the lowest negative code with -8
         if pcm_val < 0 && pcm_val >= -8 {
-            return 0xD5;
+            return 0x55;
         }
 
         // Determine sign mask and prepare the positive sample value
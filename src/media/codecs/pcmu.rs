@@ -75,9 +75,10 @@ impl PcmuEncoder {
             sample
         };
 
-        // Get the sample's sign and make it positive
-        let sign = if sample < 0 { 0x80 } else { 0x00 };
-        if sign == 0x80 {
+        // Get the sample's sign and make it positive, the encoded byte is
+        // the one's complement with the sign bit set for positive samples
+        let mask = if sample < 0 { 0x7F } else { 0xFF };
+        if sample < 0 {
             sample = -sample;
         }
 
@@ -92,13 +93,11 @@ impl PcmuEncoder {
             value >>= 1;
         }
 
-        // Combine sign, segment, and quantization
+        // Combine segment and quantization
         let uval = if segment >= 8 {
-            0x7F ^ sign
+            0x7F ^ mask
         } else {
-            let shift = if segment == 0 { 7 } else { segment + 3 };
-            let mask = 0xFF ^ (0xFF >> shift);
-            (sign | (segment << 4) | ((sample >> (segment + 3)) & 0x0F)) ^ mask
+            ((segment << 4) | ((sample >> (segment + 3)) & 0x0F)) ^ mask
         };
 
         uval as u8
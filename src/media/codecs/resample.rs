@@ -1,6 +1,14 @@
 use crate::{PcmBuf, Sample};
 use anyhow::Result;
-use rubato::{FftFixedOut, Resampler};
+use rubato::{FftFixedIn, FftFixedOut, Resampler};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
 
 pub struct LinearResampler {
     resampler: FftFixedOut<f64>,
@@ -35,6 +43,11 @@ impl LinearResampler {
         })
     }
 
+    /// Samples `resample` takes at a time, a shorter input is not resampled
+    pub fn input_chunk_size(&self) -> usize {
+        self.input_chunk_size
+    }
+
     pub fn resample(&mut self, input: &[Sample]) -> PcmBuf {
         if self.input_sample_rate == self.output_sample_rate {
             return input.to_vec();
@@ -83,26 +96,285 @@ pub fn resample_mono(input: &[Sample], input_sample_rate: u32, output_sample_rat
     result
 }
 
+/// How the audio of a call is resampled when it is transrated, e.g. from
+/// Opus at 48 kHz to G.711 at 8 kHz
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResampleProfile {
+    /// Linear interpolation of each frame as it comes, adds no delay and
+    /// little CPU but lets some aliasing through
+    Latency,
+    /// FFT resampling of 20ms chunks, frames of other sizes are buffered
+    /// until a chunk is complete, which delays the audio by up to a chunk
+    #[default]
+    Quality,
+}
+
+impl ResampleProfile {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResampleProfile::Latency => "latency",
+            ResampleProfile::Quality => "quality",
+        }
+    }
+}
+
+/// CPU spent resampling, by profile
+struct ProfileCounters {
+    frames: AtomicU64,
+    samples: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl ProfileCounters {
+    const fn new() -> Self {
+        Self {
+            frames: AtomicU64::new(0),
+            samples: AtomicU64::new(0),
+            nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+/// What the transrating of calls cost, to compare the profiles
+pub struct ResampleStats {
+    latency: ProfileCounters,
+    quality: ProfileCounters,
+}
+
+/// The stats of every [`StreamResampler`] of the process
+pub static RESAMPLE_STATS: ResampleStats = ResampleStats::new();
+
+impl ResampleStats {
+    const fn new() -> Self {
+        Self {
+            latency: ProfileCounters::new(),
+            quality: ProfileCounters::new(),
+        }
+    }
+
+    fn counters(&self, profile: ResampleProfile) -> &ProfileCounters {
+        match profile {
+            ResampleProfile::Latency => &self.latency,
+            ResampleProfile::Quality => &self.quality,
+        }
+    }
+
+    fn record(&self, profile: ResampleProfile, samples: usize, nanos: u64) {
+        let counters = self.counters(profile);
+        counters.frames.fetch_add(1, Ordering::Relaxed);
+        counters
+            .samples
+            .fetch_add(samples as u64, Ordering::Relaxed);
+        counters.nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Appends the counters in the Prometheus text format
+    pub fn render(&self, out: &mut String) {
+        type Metric = (&'static str, &'static str, fn(&ProfileCounters) -> String);
+        let metrics: [Metric; 3] = [
+            ("frames_total", "Frames resampled", |c| {
+                c.frames.load(Ordering::Relaxed).to_string()
+            }),
+            (
+                "samples_total",
+                "Samples resampled, counted at the input",
+                |c| c.samples.load(Ordering::Relaxed).to_string(),
+            ),
+            ("cpu_seconds_total", "CPU time spent resampling", |c| {
+                (c.nanos.load(Ordering::Relaxed) as f64 / 1e9).to_string()
+            }),
+        ];
+        for (name, help, value) in metrics {
+            writeln!(out, "# HELP rustpbx_resample_{} {}", name, help).ok();
+            writeln!(out, "# TYPE rustpbx_resample_{} counter", name).ok();
+            for profile in [ResampleProfile::Latency, ResampleProfile::Quality] {
+                writeln!(
+                    out,
+                    "rustpbx_resample_{}{{profile=\"{}\"}} {}",
+                    name,
+                    profile.as_str(),
+                    value(self.counters(profile))
+                )
+                .ok();
+            }
+        }
+    }
+}
+
+/// Interpolates between the samples of consecutive frames, the position is
+/// kept in steps of 1/`output_sample_rate` input samples to stay exact
+struct Interpolator {
+    input_sample_rate: i64,
+    output_sample_rate: i64,
+    /// Position of the next output sample, -1 is the last sample of the
+    /// previous frame
+    position: i64,
+    last: Sample,
+}
+
+impl Interpolator {
+    fn resample(&mut self, input: &[Sample]) -> PcmBuf {
+        let (step, scale) = (self.input_sample_rate, self.output_sample_rate);
+        let end = (input.len() as i64 - 1) * scale;
+        let sample = |i: i64| match i {
+            -1 => self.last,
+            i => input[i as usize],
+        };
+        let mut result = Vec::with_capacity((input.len() as i64 * scale / step + 1) as usize);
+        while self.position < end {
+            let i = self.position.div_euclid(scale);
+            let frac = self.position.rem_euclid(scale);
+            let (a, b) = (sample(i) as i64, sample(i + 1) as i64);
+            result.push((a + (b - a) * frac / scale) as Sample);
+            self.position += step;
+        }
+        if let Some(last) = input.last() {
+            self.position -= input.len() as i64 * scale;
+            self.last = *last;
+        }
+        result
+    }
+}
+
+/// Resamples whole chunks, with frames of any size buffered in and out so
+/// that each frame gives its length of output
+struct ChunkResampler {
+    resampler: FftFixedIn<f64>,
+    input: Vec<f64>,
+    output: VecDeque<Sample>,
+    /// Silence put out before the first chunk
+    padded: usize,
+    input_sample_rate: usize,
+    output_sample_rate: usize,
+}
+
+impl ChunkResampler {
+    fn resample(&mut self, input: &[Sample]) -> PcmBuf {
+        self.input
+            .extend(input.iter().map(|s| *s as f64 / i16::MAX as f64));
+        while self.input.len() >= self.resampler.input_frames_next() {
+            let chunk = self
+                .input
+                .drain(..self.resampler.input_frames_next())
+                .collect::<Vec<_>>();
+            match self.resampler.process(&[chunk], None) {
+                Ok(resampled) => self
+                    .output
+                    .extend(resampled[0].iter().map(|s| (s * i16::MAX as f64) as Sample)),
+                Err(_) => break,
+            }
+        }
+        // silence until the first chunk is out
+        let wanted = input.len() * self.output_sample_rate / self.input_sample_rate;
+        let available = self.output.len().min(wanted);
+        self.padded += wanted - available;
+        let mut result = vec![0; wanted - available];
+        result.extend(self.output.drain(..available));
+        result
+    }
+}
+
+enum StreamResamplerInner {
+    Interpolator(Interpolator),
+    Chunks(Box<ChunkResampler>),
+}
+
+/// Resamples the consecutive frames of a stream with a [`ResampleProfile`],
+/// the time spent is counted in [`RESAMPLE_STATS`]
+pub struct StreamResampler {
+    profile: ResampleProfile,
+    input_sample_rate: u32,
+    output_sample_rate: u32,
+    inner: StreamResamplerInner,
+}
+
+impl StreamResampler {
+    pub fn new(
+        profile: ResampleProfile,
+        input_sample_rate: u32,
+        output_sample_rate: u32,
+    ) -> Result<Self> {
+        let inner = match profile {
+            ResampleProfile::Latency => StreamResamplerInner::Interpolator(Interpolator {
+                input_sample_rate: input_sample_rate as i64,
+                output_sample_rate: output_sample_rate as i64,
+                position: 0,
+                last: 0,
+            }),
+            ResampleProfile::Quality => {
+                let chunk_size = (input_sample_rate as usize).div_ceil(50);
+                let resampler = FftFixedIn::<f64>::new(
+                    input_sample_rate as usize,
+                    output_sample_rate as usize,
+                    chunk_size,
+                    1,
+                    1,
+                )?;
+                StreamResamplerInner::Chunks(Box::new(ChunkResampler {
+                    resampler,
+                    input: Vec::with_capacity(chunk_size * 2),
+                    output: VecDeque::new(),
+                    padded: 0,
+                    input_sample_rate: input_sample_rate as usize,
+                    output_sample_rate: output_sample_rate as usize,
+                }))
+            }
+        };
+        Ok(Self {
+            profile,
+            input_sample_rate,
+            output_sample_rate,
+            inner,
+        })
+    }
+
+    pub fn profile(&self) -> ResampleProfile {
+        self.profile
+    }
+
+    /// Whether it resamples from `input_sample_rate` to `output_sample_rate`
+    pub fn converts(&self, input_sample_rate: u32, output_sample_rate: u32) -> bool {
+        self.input_sample_rate == input_sample_rate && self.output_sample_rate == output_sample_rate
+    }
+
+    /// Samples of output that come before the first sample of input
+    pub fn delay(&self) -> usize {
+        match &self.inner {
+            _ if self.input_sample_rate == self.output_sample_rate => 0,
+            StreamResamplerInner::Interpolator(_) => 0,
+            StreamResamplerInner::Chunks(chunks) => chunks.resampler.output_delay() + chunks.padded,
+        }
+    }
+
+    pub fn resample(&mut self, input: &[Sample]) -> PcmBuf {
+        if self.input_sample_rate == self.output_sample_rate {
+            return input.to_vec();
+        }
+        let start = Instant::now();
+        let result = match &mut self.inner {
+            StreamResamplerInner::Interpolator(interpolator) => interpolator.resample(input),
+            StreamResamplerInner::Chunks(chunks) => chunks.resample(input),
+        };
+        RESAMPLE_STATS.record(self.profile, input.len(), start.elapsed().as_nanos() as u64);
+        result
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::media::codecs::samples_to_bytes;
-    use crate::media::track::file::read_wav_file;
-    use std::fs::File;
-    use std::io::Write;
+    use crate::media::codecs::testing::speech_noise;
+    use std::time::Duration;
 
     #[test]
     fn test_resampler() {
-        let (all_samples, samplerate) = read_wav_file("fixtures/sample.wav").unwrap();
+        let all_samples = speech_noise(1, 16000, Duration::from_secs(3));
         let frame_samples = all_samples[0..640].to_vec();
-        let frame_resampled = resample_mono(&frame_samples, samplerate, 8000);
+        let frame_resampled = resample_mono(&frame_samples, 16000, 8000);
         assert_eq!(frame_resampled.len(), 320);
 
-        let resampled = resample_mono(&all_samples, samplerate, 8000);
-        let mut file = File::create("fixtures/sample.8k.decoded").expect("Failed to create file");
-        let decoded_bytes = samples_to_bytes(&resampled);
-        file.write_all(&decoded_bytes)
-            .expect("Failed to write file");
+        let resampled = resample_mono(&all_samples, 16000, 8000);
         let rate = resampled.len() as f64 / all_samples.len() as f64;
         println!(
             "resampled {}->{} samples, rate: {}",
@@ -110,6 +382,39 @@ mod tests {
             resampled.len(),
             rate
         );
-        println!("ffplay -f s16le -ar 8000 -i fixtures/sample.8k.decoded");
+        assert!((rate - 0.5).abs() < 0.01, "rate {}", rate);
+    }
+
+    #[test]
+    fn test_stream_resampler_profiles() {
+        let all_samples = speech_noise(1, 16000, Duration::from_secs(1));
+        for profile in [ResampleProfile::Latency, ResampleProfile::Quality] {
+            // 20ms and 10ms frames come out with their length at the new rate
+            let mut resampler = StreamResampler::new(profile, 16000, 8000).unwrap();
+            for frame in all_samples.chunks_exact(320).take(10) {
+                assert_eq!(resampler.resample(frame).len(), 160);
+            }
+            for frame in all_samples.chunks_exact(160).take(10) {
+                assert_eq!(resampler.resample(frame).len(), 80);
+            }
+            let mut resampler = StreamResampler::new(profile, 8000, 48000).unwrap();
+            let lengths = all_samples
+                .chunks_exact(160)
+                .take(10)
+                .map(|frame| resampler.resample(frame).len())
+                .collect::<Vec<_>>();
+            assert!(lengths[1..].iter().all(|len| *len == 960), "{:?}", lengths);
+        }
+
+        // interpolation continues across frames
+        let mut resampler = StreamResampler::new(ResampleProfile::Latency, 8000, 16000).unwrap();
+        assert_eq!(resampler.resample(&[0, 100]), vec![0, 50]);
+        assert_eq!(resampler.resample(&[200, 300]), vec![100, 150, 200, 250]);
+
+        let mut metrics = String::new();
+        RESAMPLE_STATS.render(&mut metrics);
+        assert!(metrics.contains("# TYPE rustpbx_resample_cpu_seconds_total counter\n"));
+        assert!(metrics.contains("rustpbx_resample_frames_total{profile=\"latency\"} "));
+        assert!(!metrics.contains("rustpbx_resample_frames_total{profile=\"quality\"} 0\n"));
     }
 }
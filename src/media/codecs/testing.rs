@@ -0,0 +1,219 @@
+//! Deterministic test signals, synthesized at any sample rate so tests don't
+//! depend on recordings in `fixtures/`.
+//!
+//! The same arguments always give the same samples, on every platform: the
+//! noise comes from a seeded xorshift generator rather than `rand`.
+use crate::{PcmBuf, Sample};
+use anyhow::{Result, anyhow};
+use std::{f64::consts::PI, path::Path, time::Duration};
+
+/// Peak amplitude of the signals, -6 dBFS, leaving codecs headroom
+pub const AMPLITUDE: f64 = 16384.0;
+
+fn samples_of(duration: Duration, sample_rate: u32) -> usize {
+    (duration.as_secs_f64() * sample_rate as f64).round() as usize
+}
+
+fn to_sample(value: f64) -> Sample {
+    value.round().clamp(Sample::MIN as f64, Sample::MAX as f64) as Sample
+}
+
+/// A tone of `frequency` Hz
+pub fn sine(frequency: f64, sample_rate: u32, duration: Duration) -> PcmBuf {
+    let step = 2.0 * PI * frequency / sample_rate as f64;
+    (0..samples_of(duration, sample_rate))
+        .map(|i| to_sample((step * i as f64).sin() * AMPLITUDE))
+        .collect()
+}
+
+/// A tone gliding exponentially from `from` to `to` Hz, every octave getting
+/// the same time
+pub fn sine_sweep(from: f64, to: f64, sample_rate: u32, duration: Duration) -> PcmBuf {
+    let len = samples_of(duration, sample_rate);
+    let mut phase: f64 = 0.0;
+    (0..len)
+        .map(|i| {
+            let frequency = from * (to / from).powf(i as f64 / len.max(1) as f64);
+            let sample = to_sample(phase.sin() * AMPLITUDE);
+            phase = (phase + 2.0 * PI * frequency / sample_rate as f64) % (2.0 * PI);
+            sample
+        })
+        .collect()
+}
+
+/// The row and column frequencies of a DTMF digit (ITU-T Q.23)
+pub fn dtmf_frequencies(digit: char) -> Option<(f64, f64)> {
+    let (row, column) = match digit.to_ascii_uppercase() {
+        '1' => (0, 0),
+        '2' => (0, 1),
+        '3' => (0, 2),
+        'A' => (0, 3),
+        '4' => (1, 0),
+        '5' => (1, 1),
+        '6' => (1, 2),
+        'B' => (1, 3),
+        '7' => (2, 0),
+        '8' => (2, 1),
+        '9' => (2, 2),
+        'C' => (2, 3),
+        '*' => (3, 0),
+        '0' => (3, 1),
+        '#' => (3, 2),
+        'D' => (3, 3),
+        _ => return None,
+    };
+    const ROWS: [f64; 4] = [697.0, 770.0, 852.0, 941.0];
+    const COLUMNS: [f64; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+    Some((ROWS[row], COLUMNS[column]))
+}
+
+/// `digits` dialed in band, each a `tone` long and followed by `gap` of
+/// silence. Unknown digits are an error
+pub fn dtmf(digits: &str, sample_rate: u32, tone: Duration, gap: Duration) -> Result<PcmBuf> {
+    let tone_len = samples_of(tone, sample_rate);
+    let gap_len = samples_of(gap, sample_rate);
+    let mut samples = PcmBuf::with_capacity(digits.len() * (tone_len + gap_len));
+    for digit in digits.chars() {
+        let (low, high) =
+            dtmf_frequencies(digit).ok_or_else(|| anyhow!("not a DTMF digit: {}", digit))?;
+        let (low, high) = (
+            2.0 * PI * low / sample_rate as f64,
+            2.0 * PI * high / sample_rate as f64,
+        );
+        samples.extend((0..tone_len).map(|i| {
+            let t = i as f64;
+            to_sample(((low * t).sin() + (high * t).sin()) * AMPLITUDE / 2.0)
+        }));
+        samples.resize(samples.len() + gap_len, 0);
+    }
+    Ok(samples)
+}
+
+/// xorshift64*, small and the same everywhere
+struct Noise(u64);
+
+impl Noise {
+    fn new(seed: u64) -> Self {
+        // the state must not be zero
+        Self((seed ^ 0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    /// Uniform in [-1, 1)
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        let value = self.0.wrapping_mul(0x2545_f491_4f6c_dd1d);
+        (value >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+}
+
+/// One-pole filter coefficient for a cutoff of `frequency` Hz
+fn pole(frequency: f64, sample_rate: u32) -> f64 {
+    (-2.0 * PI * frequency / sample_rate as f64).exp()
+}
+
+/// Noise with the long-term spectrum of speech, band-limited to about
+/// 100 Hz - 3.4 kHz and falling above 500 Hz, cut into syllables of 150 to
+/// 350ms with short pauses between words. Each `seed` gives other noise
+pub fn speech_noise(seed: u64, sample_rate: u32, duration: Duration) -> PcmBuf {
+    let len = samples_of(duration, sample_rate);
+    let mut noise = Noise::new(seed);
+    let high_pass = pole(100.0, sample_rate);
+    let low_pass = pole(500.0, sample_rate);
+    let anti_alias = pole(3400.0f64.min(sample_rate as f64 * 0.4), sample_rate);
+    let (mut previous, mut high, mut low) = (0.0, 0.0, 0.0);
+    let mut smooth = [0.0; 2];
+
+    let mut samples = PcmBuf::with_capacity(len);
+    while samples.len() < len {
+        let syllable =
+            samples_of(Duration::from_millis(250), sample_rate) as f64 * (1.0 + 0.4 * noise.next());
+        let syllable = syllable as usize;
+        // one syllable in four ends a word
+        let pause = match noise.next() > 0.5 {
+            true => samples_of(Duration::from_millis(120), sample_rate),
+            false => samples_of(Duration::from_millis(20), sample_rate),
+        };
+        let level = 0.6 + 0.4 * noise.next().abs();
+        for i in 0..syllable + pause {
+            let white = noise.next();
+            high = high_pass * (high + white - previous);
+            previous = white;
+            low = low_pass * low + (1.0 - low_pass) * high;
+            // mostly the low band, some of the rest for the consonants
+            let mut band = low * 3.0 + high * 0.15;
+            for stage in smooth.iter_mut() {
+                *stage = anti_alias * *stage + (1.0 - anti_alias) * band;
+                band = *stage;
+            }
+            let envelope = match i < syllable {
+                true => (PI * i as f64 / syllable as f64).sin() * level,
+                false => 0.0,
+            };
+            samples.push(to_sample(band * envelope * AMPLITUDE * 2.0));
+        }
+    }
+    samples.truncate(len);
+    samples
+}
+
+/// Writes `samples` as a 16-bit mono WAV file, to feed file-based tracks
+pub fn write_wav(path: &Path, samples: &[Sample], sample_rate: u32) -> Result<()> {
+    super::transcode::write_audio_file(
+        path,
+        super::transcode::AudioFileFormat::Wav,
+        samples,
+        sample_rate,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn energy_at(samples: &[Sample], frequency: f64, sample_rate: u32) -> f64 {
+        // Goertzel
+        let coefficient = 2.0 * (2.0 * PI * frequency / sample_rate as f64).cos();
+        let (mut s1, mut s2) = (0.0, 0.0);
+        for sample in samples {
+            let s0 = *sample as f64 + coefficient * s1 - s2;
+            s2 = s1;
+            s1 = s0;
+        }
+        (s1 * s1 + s2 * s2 - coefficient * s1 * s2) / (samples.len() as f64).powi(2)
+    }
+
+    #[test]
+    fn test_signals_are_deterministic() {
+        for sample_rate in [8000, 16000, 44100, 48000] {
+            let duration = Duration::from_millis(500);
+            let len = sample_rate as usize / 2;
+            assert_eq!(sine(1000.0, sample_rate, duration).len(), len);
+            assert_eq!(sine_sweep(100.0, 3400.0, sample_rate, duration).len(), len);
+            let speech = speech_noise(7, sample_rate, duration);
+            assert_eq!(speech.len(), len);
+            assert_eq!(speech, speech_noise(7, sample_rate, duration));
+            assert_ne!(speech, speech_noise(8, sample_rate, duration));
+            assert!(speech.iter().any(|s| s.unsigned_abs() > 1000));
+        }
+    }
+
+    #[test]
+    fn test_dtmf() {
+        let tone = Duration::from_millis(80);
+        let gap = Duration::from_millis(40);
+        let samples = dtmf("1#", 8000, tone, gap).unwrap();
+        assert_eq!(samples.len(), 2 * (640 + 320));
+        assert!(samples[640..960].iter().all(|s| *s == 0));
+
+        let one = &samples[..640];
+        assert!(energy_at(one, 697.0, 8000) > 100.0 * energy_at(one, 941.0, 8000));
+        assert!(energy_at(one, 1209.0, 8000) > 100.0 * energy_at(one, 1477.0, 8000));
+        let pound = &samples[960..1600];
+        assert!(energy_at(pound, 941.0, 8000) > 100.0 * energy_at(pound, 697.0, 8000));
+        assert!(energy_at(pound, 1477.0, 8000) > 100.0 * energy_at(pound, 1209.0, 8000));
+
+        assert!(dtmf("12x", 8000, tone, gap).is_err());
+    }
+}
@@ -238,6 +238,122 @@ fn test_g722_encode() {
     println!("ffplay -f s16le -ar 16000 -i fixtures/sample.g722.chunk.encoded.decoded");
 }
 
+#[test]
+fn test_g722_bitrate_modes() {
+    use crate::media::track::track_codec::TrackCodec;
+    use g722::{Bitrate, G722Decoder, G722Encoder, G722Mode};
+
+    // a 1 kHz tone, 20ms at 16kHz per packet
+    let samples: PcmBuf = (0..3200)
+        .map(|i| ((i as f32 * 2.0 * std::f32::consts::PI / 16.0).sin() * 10000.0) as Sample)
+        .collect();
+    let snr = |decoded: &[Sample]| {
+        // the codec delays the signal by 22 samples
+        let (signal, noise) = samples[640..3000]
+            .iter()
+            .zip(&decoded[662..])
+            .fold((0.0f64, 0.0f64), |(s, n), (&a, &b)| {
+                (s + (a as f64).powi(2), n + (a as f64 - b as f64).powi(2))
+            });
+        10.0 * (signal / noise.max(1.0)).log10()
+    };
+
+    for (bitrate, packed, size) in [
+        (Bitrate::Mode1_64000, false, 160),
+        (Bitrate::Mode2_56000, false, 160),
+        (Bitrate::Mode3_48000, false, 160),
+        (Bitrate::Mode2_56000, true, 140),
+        (Bitrate::Mode3_48000, true, 120),
+    ] {
+        let mode = G722Mode { bitrate, packed };
+        let mut encoder = G722Encoder::with_mode(mode);
+        let mut decoder = G722Decoder::with_mode(mode);
+        let mut decoded = Vec::new();
+        for chunk in samples.chunks(320) {
+            let payload = encoder.encode(chunk);
+            assert_eq!(payload.len(), size, "{:?}", mode);
+            decoded.extend(decoder.decode(&payload));
+        }
+        assert_eq!(decoded.len(), samples.len());
+        assert!(snr(&decoded) > 20.0, "{:?}: {}", mode, snr(&decoded));
+    }
+
+    // unpacked, a 64 kbit/s decoder plays the lower modes
+    let mut encoder = G722Encoder::with_mode(G722Mode {
+        bitrate: Bitrate::Mode3_48000,
+        packed: false,
+    });
+    let mut decoder = G722Decoder::new();
+    let decoded: PcmBuf = samples
+        .chunks(320)
+        .flat_map(|chunk| decoder.decode(&encoder.encode(chunk)))
+        .collect();
+    assert!(snr(&decoded) > 20.0, "{}", snr(&decoded));
+
+    assert_eq!(Bitrate::try_from(56000), Ok(Bitrate::Mode2_56000));
+    assert!(Bitrate::try_from(32000).is_err());
+    // 16 kHz audio on an 8 kHz RTP clock
+    assert_eq!(CodecType::G722.rtpmap(), "G722/8000");
+    let codec = TrackCodec::new();
+    assert_eq!(codec.sample_rate(9), 16000);
+    assert_eq!(codec.clock_samples(9, 320, 16000), 160);
+    assert_eq!(codec.clock_samples(0, 320, 16000), 160);
+}
+
+#[test]
+fn test_track_codec_resets_on_discontinuity() {
+    use crate::media::track::track_codec::TrackCodec;
+    use crate::{AudioFrame, Samples};
+
+    let tone = |step: f32| -> PcmBuf {
+        (0..320)
+            .map(|i| ((i as f32 * step).sin() * 8000.0) as Sample)
+            .collect()
+    };
+    let mut far_end = g722::G722Encoder::new();
+    let first: Vec<Vec<u8>> = (0..10).map(|_| far_end.encode(&tone(0.3))).collect();
+    // the far end resumes from hold with a fresh encoder
+    let mut far_end = g722::G722Encoder::new();
+    let resumed = far_end.encode(&tone(0.7));
+    let expected = g722::G722Decoder::new().decode(&resumed);
+
+    let codec = TrackCodec::new();
+    for (i, payload) in first.iter().enumerate() {
+        codec.decode_packet(9, 100 + i as u16, 1000 + i as u64 * 20, payload, 16000);
+    }
+    // the next packet in sequence continues the stream
+    let continued = TrackCodec::new();
+    for (i, payload) in first.iter().enumerate() {
+        continued.decode_packet(9, 100 + i as u16, 1000 + i as u64 * 20, payload, 16000);
+    }
+    let garbled = continued.decode_packet(9, 110, 1200, &resumed, 16000);
+    assert_ne!(garbled[160..], expected[160..]);
+
+    // 30s later, the decoder starts over and fades in
+    let decoded = codec.decode_packet(9, 110, 31200, &resumed, 16000);
+    assert_eq!(decoded[160..], expected[160..]);
+    assert_eq!(decoded[0], 0);
+    // as it does for a far off sequence number (a new SSRC)
+    let codec = TrackCodec::new();
+    codec.decode_packet(9, 100, 1000, &first[0], 16000);
+    let decoded = codec.decode_packet(9, 40000, 1020, &resumed, 16000);
+    assert_eq!(decoded[160..], expected[160..]);
+
+    // the encoder starts over after a long gap
+    let frame = |timestamp, samples| AudioFrame {
+        track_id: "test".to_string(),
+        samples: Samples::PCM { samples },
+        timestamp,
+        sample_rate: 16000,
+    };
+    let codec = TrackCodec::new();
+    for i in 0..10 {
+        codec.encode(9, frame(1000 + i * 20, tone(0.3)));
+    }
+    let (_, payload) = codec.encode(9, frame(5000, tone(0.7)));
+    assert_eq!(payload, resumed);
+}
+
 #[test]
 fn test_codec_encode_decode() {
     let reader = BufReader::new(
@@ -363,3 +479,238 @@ fn test_g729_encode_decode() {
         println!("ffplay -f s16le -ar 8000  -i fixtures/sample.g729.decoded");
     }
 }
+
+mod verify_props {
+    use super::super::verify::{
+        audio_codecs, round_trip, round_trip_with_frame_size, segmental_snr, snr,
+    };
+    use super::super::*;
+    use proptest::prelude::*;
+
+    /// Minimum SNR of a clean tone, G.729 and Opus model the signal instead
+    /// of preserving the waveform
+    fn min_snr(codec: CodecType) -> f32 {
+        match codec {
+            CodecType::PCMU | CodecType::PCMA => 30.0,
+            CodecType::G722 => 15.0,
+            _ => 5.0,
+        }
+    }
+
+    /// G.729 and Opus high-pass filter their input
+    fn preserves_dc(codec: CodecType) -> bool {
+        matches!(codec, CodecType::PCMU | CodecType::PCMA | CodecType::G722)
+    }
+
+    fn tone(codec: CodecType, frequency: f32, amplitude: f32, dc: f32, len: usize) -> PcmBuf {
+        let sample_rate = codec.samplerate() as f32;
+        (0..len)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate;
+                (phase.sin() * amplitude + dc).clamp(-32768.0, 32767.0) as Sample
+            })
+            .collect()
+    }
+
+    fn edge_sample() -> impl Strategy<Value = Sample> {
+        prop_oneof![
+            Just(Sample::MIN),
+            Just(Sample::MAX),
+            Just(-1 as Sample),
+            Just(0 as Sample),
+            Just(1 as Sample),
+            any::<Sample>(),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn prop_g711_decode_is_stable(byte in any::<u8>()) {
+            for codec in [CodecType::PCMU, CodecType::PCMA] {
+                let mut encoder = create_encoder(codec);
+                let mut decoder = create_decoder(codec);
+                let decoded = decoder.decode(&[byte]);
+                let reencoded = encoder.encode(&decoded);
+                prop_assert_eq!(decoder.decode(&reencoded), decoded);
+            }
+        }
+
+        #[test]
+        fn prop_round_trip_edge_amplitudes(samples in prop::collection::vec(edge_sample(), 0..800)) {
+            for codec in audio_codecs() {
+                let result = round_trip(codec, &samples);
+                prop_assert!(result.encoded_len <= samples.len() * 2);
+                prop_assert!(result.decoded.len() <= samples.len() + samples.len() / 2 + 1);
+                if matches!(codec, CodecType::PCMU | CodecType::PCMA) {
+                    prop_assert_eq!(result.decoded.len(), samples.len());
+                    let full_scale = samples.iter().all(|s| s.unsigned_abs() >= 16384);
+                    prop_assert!(!full_scale || result.snr >= min_snr(codec), "{:?} {}", codec, result.snr);
+                }
+            }
+        }
+
+        #[test]
+        fn prop_round_trip_tone_snr(
+            frequency in 200.0f32..3000.0,
+            amplitude in 2000.0f32..30000.0,
+            dc in -2000.0f32..2000.0,
+        ) {
+            for codec in audio_codecs() {
+                let dc = if preserves_dc(codec) { dc } else { 0.0 };
+                let samples = tone(codec, frequency, amplitude, dc, frame_size_of(codec) * 10);
+                let result = round_trip(codec, &samples);
+                prop_assert!(
+                    result.snr >= min_snr(codec),
+                    "{:?} {}Hz amplitude {} dc {}: snr {}", codec, frequency, amplitude, dc, result.snr
+                );
+            }
+        }
+
+        #[test]
+        fn prop_round_trip_odd_frame_sizes(frame_size in 1usize..400, frames in 1usize..6) {
+            for codec in audio_codecs() {
+                let samples = tone(codec, 440.0, 8000.0, 0.0, frame_size * frames);
+                let result = round_trip_with_frame_size(codec, &samples, frame_size);
+                match codec {
+                    CodecType::PCMU | CodecType::PCMA => {
+                        // stateless codecs don't care about framing
+                        prop_assert_eq!(&result.decoded, &round_trip(codec, &samples).decoded);
+                    }
+                    CodecType::G722 => {
+                        // an odd frame is padded to a whole byte
+                        prop_assert_eq!(result.decoded.len(), frames * (frame_size + frame_size % 2));
+                    }
+                    #[cfg(feature = "g729")]
+                    CodecType::G729 => {
+                        // partial 10ms frames are dropped
+                        prop_assert_eq!(result.decoded.len(), frames * (frame_size / 80 * 80));
+                    }
+                    _ => {
+                        // Opus only accepts a few frame durations
+                        prop_assert!(result.decoded.len() <= samples.len());
+                    }
+                }
+            }
+        }
+    }
+
+    fn frame_size_of(codec: CodecType) -> usize {
+        super::super::verify::frame_size(codec)
+    }
+
+    /// Minimum perceptual score of speech, the waveform codecs are near
+    /// transparent
+    fn min_score(codec: CodecType) -> f32 {
+        match codec {
+            CodecType::PCMU | CodecType::PCMA => 4.3,
+            CodecType::G722 => 4.0,
+            _ => 2.5,
+        }
+    }
+
+    #[test]
+    fn test_speech_quality() {
+        use super::super::testing::speech_noise;
+        use super::super::verify::perceptual_score;
+        use std::time::Duration;
+
+        for codec in audio_codecs() {
+            let sample_rate = codec.samplerate();
+            let speech = speech_noise(3, sample_rate, Duration::from_secs(2));
+            let result = round_trip(codec, &speech);
+            let decoded = &result.decoded[result.delay..];
+            let segmental = segmental_snr(&speech, decoded, frame_size_of(codec));
+            assert!(segmental > min_snr(codec), "{:?}: {}", codec, segmental);
+            let score = perceptual_score(&speech, decoded, sample_rate);
+            assert!(score > min_score(codec), "{:?}: {}", codec, score);
+        }
+
+        let speech = speech_noise(3, 8000, Duration::from_secs(2));
+        assert_eq!(perceptual_score(&speech, &speech, 8000), 4.5);
+        // the level doesn't matter, noise does
+        let quieter: PcmBuf = speech.iter().map(|s| s / 2).collect();
+        assert!(perceptual_score(&speech, &quieter, 8000) > 4.4);
+        let noise = speech_noise(4, 8000, Duration::from_secs(2));
+        let scores = [0.01, 0.1, 1.0].map(|level| {
+            let noisy: PcmBuf = speech
+                .iter()
+                .zip(noise.iter())
+                .map(|(s, n)| s.saturating_add((*n as f32 * level) as Sample))
+                .collect();
+            perceptual_score(&speech, &noisy, 8000)
+        });
+        assert!(scores.windows(2).all(|w| w[0] > w[1]), "{:?}", scores);
+        assert!(scores[2] < 2.0, "{:?}", scores);
+        assert!(perceptual_score(&speech, &vec![0; speech.len()], 8000) < 1.5);
+    }
+
+    #[test]
+    fn test_segmental_snr() {
+        let loud: PcmBuf = (0..1600).map(|i| ((i % 40) * 800) as Sample).collect();
+        assert_eq!(segmental_snr(&loud, &loud, 160), 35.0);
+        assert_eq!(segmental_snr(&vec![0; 160], &loud[..160], 160), 35.0);
+
+        // a quiet passage lost in noise is heard, whatever the loud one
+        let quiet: PcmBuf = loud.iter().map(|s| s / 100).collect();
+        let reference = [loud.clone(), quiet.clone()].concat();
+        let degraded = [loud, vec![0; quiet.len()]].concat();
+        assert!(snr(&reference, &degraded) > 35.0);
+        let segmental = segmental_snr(&reference, &degraded, 160);
+        assert!((17.0..18.0).contains(&segmental), "{}", segmental);
+    }
+
+    #[test]
+    fn test_snr() {
+        let reference: PcmBuf = (0..160).map(|i| (i * 100) as Sample).collect();
+        assert_eq!(snr(&reference, &reference), super::super::verify::MAX_SNR);
+        let noisy: PcmBuf = reference.iter().map(|s| s + 10).collect();
+        assert!(snr(&reference, &noisy) > 50.0);
+        assert!(snr(&reference, &vec![0; 160]) < 0.1);
+    }
+}
+
+#[test]
+fn test_transcode() {
+    use std::time::Duration;
+    use transcode::{AudioFileFormat, TranscodeOption, read_audio_file, transcode};
+    let dir = tempfile::tempdir().unwrap();
+    let original = testing::speech_noise(1, 16000, Duration::from_millis(7510));
+    let sample = dir.path().join("sample.wav");
+    testing::write_wav(&sample, &original, 16000).unwrap();
+
+    let pcmu = dir.path().join("sample.ulaw");
+    let duration = transcode(&sample, &pcmu, &TranscodeOption::default()).unwrap();
+    assert_eq!(duration.as_millis(), original.len() as u128 / 16);
+    let size = std::fs::metadata(&pcmu).unwrap().len() as usize;
+    assert_eq!(size, (original.len() / 2).div_ceil(160) * 160);
+
+    // back to 16 kHz through G.722, then to WAV
+    let g722 = dir.path().join("sample.g722");
+    transcode(&pcmu, &g722, &TranscodeOption::default()).unwrap();
+    let wav = dir.path().join("sample.out");
+    let option = TranscodeOption {
+        output_format: Some(AudioFileFormat::Wav),
+        ..Default::default()
+    };
+    transcode(&g722, &wav, &option).unwrap();
+    let (decoded, sample_rate) = read_audio_file(&wav, AudioFileFormat::Wav, 0).unwrap();
+    assert_eq!(sample_rate, 16000);
+    assert_eq!(decoded.len(), size * 2);
+    let (_, snr) = verify::aligned_snr(&original, &decoded, 320);
+    assert!(snr > 10.0, "snr {}", snr);
+
+    // raw PCM says nothing of its rate
+    let pcm = dir.path().join("sample.pcm");
+    let option = TranscodeOption {
+        input_format: Some(AudioFileFormat::Wav),
+        sample_rate: Some(8000),
+        ..Default::default()
+    };
+    transcode(&wav, &pcm, &option).unwrap();
+    let (samples, sample_rate) = read_audio_file(&pcm, AudioFileFormat::Pcm, 8000).unwrap();
+    assert_eq!((samples.len(), sample_rate), (size, 8000));
+    assert!("sample.mp3".parse::<AudioFileFormat>().is_err());
+    assert!(AudioFileFormat::from_path(&dir.path().join("sample")).is_err());
+}
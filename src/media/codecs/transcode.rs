@@ -0,0 +1,228 @@
+//! Offline conversion of audio files between the codecs of the media path,
+//! behind `rustpbx transcode`.
+//!
+//! Besides WAV, files hold raw audio: 16-bit little-endian PCM, or the
+//! payloads of a codec back to back, as they go in RTP. Opus packets are not
+//! self-delimiting, so in a raw Opus file each one is preceded by its length
+//! on two bytes, big-endian.
+use super::{
+    CodecType, bytes_to_samples, create_decoder, create_encoder,
+    resample::{ResampleProfile, StreamResampler},
+    samples_to_bytes,
+};
+use crate::{PcmBuf, Sample, media::track::file::read_wav_file};
+use anyhow::{Result, anyhow};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use std::{path::Path, str::FromStr, time::Duration};
+
+/// The formats `rustpbx transcode` reads and writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioFileFormat {
+    /// WAV, written as 16-bit PCM mono. Stereo is mixed down when read
+    Wav,
+    /// Raw 16-bit little-endian PCM
+    Pcm,
+    /// Raw payloads of a codec
+    Codec(CodecType),
+}
+
+impl FromStr for AudioFileFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "wav" => Ok(Self::Wav),
+            "pcm" | "raw" | "sln" | "s16le" => Ok(Self::Pcm),
+            "pcmu" | "ulaw" | "mulaw" | "g711u" => Ok(Self::Codec(CodecType::PCMU)),
+            "pcma" | "alaw" | "g711a" => Ok(Self::Codec(CodecType::PCMA)),
+            "g722" => Ok(Self::Codec(CodecType::G722)),
+            #[cfg(feature = "g729")]
+            "g729" => Ok(Self::Codec(CodecType::G729)),
+            #[cfg(feature = "opus")]
+            "opus" => Ok(Self::Codec(CodecType::Opus)),
+            _ => Err(anyhow!("unsupported audio format: {}", s)),
+        }
+    }
+}
+
+impl AudioFileFormat {
+    /// The format of a file by its extension
+    pub fn from_path(path: &Path) -> Result<Self> {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .ok_or_else(|| anyhow!("no format for {}, give one", path.display()))?
+            .parse()
+    }
+}
+
+/// Bytes of 20ms of audio of a codec with fixed size frames
+fn frame_bytes(codec: CodecType) -> usize {
+    match codec {
+        #[cfg(feature = "g729")]
+        CodecType::G729 => 20,
+        _ => 160,
+    }
+}
+
+/// Reads the audio of a file, with its sample rate. `pcm_sample_rate` is the
+/// rate of raw PCM, which the file doesn't say
+pub fn read_audio_file(
+    path: &Path,
+    format: AudioFileFormat,
+    pcm_sample_rate: u32,
+) -> Result<(PcmBuf, u32)> {
+    let codec = match format {
+        AudioFileFormat::Wav => return read_wav_file(&path.to_string_lossy()),
+        AudioFileFormat::Pcm => {
+            return Ok((bytes_to_samples(&std::fs::read(path)?), pcm_sample_rate));
+        }
+        AudioFileFormat::Codec(codec) => codec,
+    };
+    let data = std::fs::read(path)?;
+    let mut decoder = create_decoder(codec);
+    let mut samples = PcmBuf::new();
+    match codec {
+        #[cfg(feature = "opus")]
+        CodecType::Opus => {
+            let mut rest = &data[..];
+            while let [high, low, packets @ ..] = rest {
+                let len = u16::from_be_bytes([*high, *low]) as usize;
+                let packet = packets
+                    .get(..len)
+                    .ok_or_else(|| anyhow!("truncated opus packet in {}", path.display()))?;
+                samples.extend(decoder.decode(packet));
+                rest = &packets[len..];
+            }
+        }
+        _ => {
+            for payload in data.chunks(frame_bytes(codec)) {
+                samples.extend(decoder.decode(payload));
+            }
+        }
+    }
+    Ok((samples, codec.samplerate()))
+}
+
+/// Resamples a whole recording, the resampler's delay trimmed from the start
+fn resample(samples: &[Sample], input_sample_rate: u32, output_sample_rate: u32) -> PcmBuf {
+    if input_sample_rate == output_sample_rate {
+        return samples.to_vec();
+    }
+    let Ok(mut resampler) = StreamResampler::new(
+        ResampleProfile::Quality,
+        input_sample_rate,
+        output_sample_rate,
+    ) else {
+        return samples.to_vec();
+    };
+    let frame_size = input_sample_rate as usize / 50;
+    let len = samples.len() * output_sample_rate as usize / input_sample_rate as usize;
+    let mut resampled = PcmBuf::with_capacity(len);
+    // whole frames only, a short one would get silence in the middle of the
+    // output. Frames of silence then flush what is still buffered
+    let flush = vec![0; frame_size];
+    for frame in samples.chunks(frame_size) {
+        let mut frame = frame.to_vec();
+        frame.resize(frame_size, 0);
+        resampled.extend(resampler.resample(&frame));
+    }
+    while resampled.len() < resampler.delay() + len {
+        resampled.extend(resampler.resample(&flush));
+    }
+    let delay = resampler.delay();
+    resampled.drain(..delay);
+    resampled.truncate(len);
+    resampled
+}
+
+/// Writes `samples` to a file. WAV and raw PCM keep `sample_rate`, codecs
+/// are fed at their own rate, in 20ms frames
+pub fn write_audio_file(
+    path: &Path,
+    format: AudioFileFormat,
+    samples: &[Sample],
+    sample_rate: u32,
+) -> Result<()> {
+    let codec = match format {
+        AudioFileFormat::Wav => {
+            let spec = WavSpec {
+                channels: 1,
+                sample_rate,
+                bits_per_sample: 16,
+                sample_format: SampleFormat::Int,
+            };
+            let mut writer = WavWriter::create(path, spec)?;
+            for sample in samples {
+                writer.write_sample(*sample)?;
+            }
+            writer.finalize()?;
+            return Ok(());
+        }
+        AudioFileFormat::Pcm => {
+            std::fs::write(path, samples_to_bytes(samples))?;
+            return Ok(());
+        }
+        AudioFileFormat::Codec(codec) => codec,
+    };
+    let samples = resample(samples, sample_rate, codec.samplerate());
+    let mut encoder = create_encoder(codec);
+    let frame_size = codec.samplerate() as usize / 50;
+    let mut data = Vec::new();
+    for frame in samples.chunks(frame_size) {
+        // codecs with fixed frames get the last one padded with silence
+        let mut frame = frame.to_vec();
+        frame.resize(frame_size, 0);
+        let payload = encoder.encode(&frame);
+        #[cfg(feature = "opus")]
+        if codec == CodecType::Opus {
+            data.extend((payload.len() as u16).to_be_bytes());
+        }
+        data.extend(payload);
+    }
+    std::fs::write(path, data)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscodeOption {
+    /// Format of the input, else found from its extension
+    pub input_format: Option<AudioFileFormat>,
+    /// Format of the output, else found from its extension
+    pub output_format: Option<AudioFileFormat>,
+    /// Sample rate of raw PCM input
+    pub input_sample_rate: u32,
+    /// Sample rate of WAV and raw PCM output, else the input's
+    pub sample_rate: Option<u32>,
+}
+
+impl Default for TranscodeOption {
+    fn default() -> Self {
+        Self {
+            input_format: None,
+            output_format: None,
+            input_sample_rate: 8000,
+            sample_rate: None,
+        }
+    }
+}
+
+/// Converts `input` to `output`, returns the duration of the audio
+pub fn transcode(input: &Path, output: &Path, option: &TranscodeOption) -> Result<Duration> {
+    let input_format = match option.input_format {
+        Some(format) => format,
+        None => AudioFileFormat::from_path(input)?,
+    };
+    let output_format = match option.output_format {
+        Some(format) => format,
+        None => AudioFileFormat::from_path(output)?,
+    };
+    let (samples, input_sample_rate) =
+        read_audio_file(input, input_format, option.input_sample_rate)?;
+    let duration = Duration::from_secs_f64(samples.len() as f64 / input_sample_rate as f64);
+    let (samples, sample_rate) = match (output_format, option.sample_rate) {
+        (AudioFileFormat::Codec(_), _) | (_, None) => (samples, input_sample_rate),
+        (_, Some(rate)) => (resample(&samples, input_sample_rate, rate), rate),
+    };
+    write_audio_file(output, output_format, &samples, sample_rate)?;
+    Ok(duration)
+}
@@ -0,0 +1,224 @@
+//! Codec round trip helpers: encode a signal, decode it again and measure
+//! how much of it survived, to check codec implementations against each
+//! other and against expected quality.
+//!
+//! Besides the plain SNR, [`segmental_snr`] and [`perceptual_score`] rate a
+//! degraded signal the way a listener would, and work as well for checking
+//! jitter buffers or processors: compare what went in with what came out.
+use super::{CodecType, create_decoder, create_encoder};
+use crate::{PcmBuf, Sample};
+use std::f64::consts::PI;
+
+/// Upper bound of the reported SNR, returned for a lossless round trip (in dB)
+pub const MAX_SNR: f32 = 100.0;
+
+/// Bounds of the SNR of one segment in [`segmental_snr`] (in dB): silence
+/// in the reference doesn't weigh in as noise, and segments clean enough
+/// don't hide the bad ones
+pub const SEGMENT_SNR_RANGE: (f32, f32) = (-10.0, 35.0);
+
+/// Range of [`perceptual_score`], on the scale of a MOS
+pub const SCORE_RANGE: (f32, f32) = (1.0, 4.5);
+
+#[derive(Debug, Clone)]
+pub struct RoundTrip {
+    pub codec: CodecType,
+    /// Size of the encoded payload (in bytes)
+    pub encoded_len: usize,
+    pub decoded: PcmBuf,
+    /// Codec delay found by aligning the decoded signal with the input (in samples)
+    pub delay: usize,
+    /// Signal to noise ratio of the decoded signal (in dB)
+    pub snr: f32,
+}
+
+/// Audio codecs enabled in this build
+pub fn audio_codecs() -> Vec<CodecType> {
+    vec![
+        CodecType::PCMU,
+        CodecType::PCMA,
+        CodecType::G722,
+        #[cfg(feature = "g729")]
+        CodecType::G729,
+        #[cfg(feature = "opus")]
+        CodecType::Opus,
+    ]
+}
+
+/// Samples in a 20ms frame at the codec sample rate
+pub fn frame_size(codec: CodecType) -> usize {
+    codec.samplerate() as usize / 50
+}
+
+/// Encode and decode `samples` frame by frame, the last frame may be
+/// shorter, then measure the SNR against the input allowing up to one
+/// frame of codec delay
+pub fn round_trip(codec: CodecType, samples: &[Sample]) -> RoundTrip {
+    round_trip_with_frame_size(codec, samples, frame_size(codec))
+}
+
+pub fn round_trip_with_frame_size(
+    codec: CodecType,
+    samples: &[Sample],
+    frame_size: usize,
+) -> RoundTrip {
+    let mut encoder = create_encoder(codec);
+    let mut decoder = create_decoder(codec);
+    let mut encoded_len = 0;
+    let mut decoded = Vec::with_capacity(samples.len());
+    for frame in samples.chunks(frame_size.max(1)) {
+        let payload = encoder.encode(frame);
+        encoded_len += payload.len();
+        decoded.extend(decoder.decode(&payload));
+    }
+    let (delay, snr) = aligned_snr(samples, &decoded, frame_size);
+    RoundTrip {
+        codec,
+        encoded_len,
+        decoded,
+        delay,
+        snr,
+    }
+}
+
+/// Signal to noise ratio of `decoded` against `reference`, compared sample
+/// by sample over the shorter of the two (in dB)
+pub fn snr(reference: &[Sample], decoded: &[Sample]) -> f32 {
+    let mut signal = 0.0f64;
+    let mut noise = 0.0f64;
+    for (&r, &d) in reference.iter().zip(decoded.iter()) {
+        signal += (r as f64) * (r as f64);
+        let diff = r as f64 - d as f64;
+        noise += diff * diff;
+    }
+    if noise == 0.0 {
+        return MAX_SNR;
+    }
+    if signal == 0.0 {
+        return -MAX_SNR;
+    }
+    ((10.0 * (signal / noise).log10()) as f32).clamp(-MAX_SNR, MAX_SNR)
+}
+
+/// Best SNR of `decoded` delayed by 0 to `max_delay` samples against
+/// `reference`, returns the delay and the SNR
+pub fn aligned_snr(reference: &[Sample], decoded: &[Sample], max_delay: usize) -> (usize, f32) {
+    let mut best = (0, snr(reference, decoded));
+    for delay in 1..=max_delay.min(decoded.len()) {
+        let value = snr(reference, &decoded[delay..]);
+        if value > best.1 {
+            best = (delay, value);
+        }
+    }
+    best
+}
+
+/// Energy of a segment under which the reference is taken as silence,
+/// -60 dBFS
+const SILENCE: f64 = 32768.0 * 32768.0 * 1e-6;
+
+/// The score of [`perceptual_score`] falls exponentially with the
+/// distortion, by a factor e over this much
+const DISTORTION_SCALE: f64 = 0.2;
+
+/// Average of the SNR of the `segment_len` long segments of `decoded`
+/// against `reference`, each bounded to [`SEGMENT_SNR_RANGE`], skipping the
+/// segments where the reference is silent (in dB). Unlike [`snr`], a loud
+/// passage doesn't hide a damaged quiet one
+pub fn segmental_snr(reference: &[Sample], decoded: &[Sample], segment_len: usize) -> f32 {
+    let len = reference.len().min(decoded.len());
+    let (low, high) = SEGMENT_SNR_RANGE;
+    let mut total = 0.0;
+    let mut segments = 0;
+    for (r, d) in reference[..len]
+        .chunks(segment_len.max(1))
+        .zip(decoded[..len].chunks(segment_len.max(1)))
+    {
+        let energy = r.iter().map(|s| (*s as f64).powi(2)).sum::<f64>() / r.len() as f64;
+        if energy < SILENCE {
+            continue;
+        }
+        total += snr(r, d).clamp(low, high);
+        segments += 1;
+    }
+    match segments {
+        0 => high,
+        _ => total / segments as f32,
+    }
+}
+
+/// Bark (critical band) of a frequency, after Traunmüller
+fn bark(frequency: f64) -> f64 {
+    (26.81 * frequency / (1960.0 + frequency) - 0.53).max(0.0)
+}
+
+/// Energy of `frame` in each critical band from 100 Hz to 7 kHz, under a
+/// Hann window
+fn band_energies(frame: &[Sample], sample_rate: u32) -> Vec<f64> {
+    let len = frame.len();
+    let windowed = frame
+        .iter()
+        .enumerate()
+        .map(|(i, s)| *s as f64 * (0.5 - 0.5 * (2.0 * PI * i as f64 / len as f64).cos()))
+        .collect::<Vec<_>>();
+    let resolution = sample_rate as f64 / len as f64;
+    let first = (100.0 / resolution).ceil() as usize;
+    let last = ((7000.0f64.min(sample_rate as f64 / 2.0)) / resolution) as usize;
+    let mut bands = vec![0.0; bark(last as f64 * resolution) as usize + 1];
+    for bin in first..last {
+        let step = 2.0 * PI * bin as f64 / len as f64;
+        let (mut re, mut im) = (0.0, 0.0);
+        for (i, s) in windowed.iter().enumerate() {
+            let (sin, cos) = (step * i as f64).sin_cos();
+            re += s * cos;
+            im -= s * sin;
+        }
+        bands[bark(bin as f64 * resolution) as usize] += re * re + im * im;
+    }
+    bands
+}
+
+/// A perceptual rating of `decoded` against `reference`, from 1 (bad) to
+/// 4.5 (no audible difference), in the spirit of PESQ (ITU-T P.862) but far
+/// simpler: with the levels of the two aligned, the loudness of the
+/// critical bands of each 32ms frame where the reference isn't silent is
+/// compared, and the average distortion mapped to the scale of a MOS. The two signals must be aligned, see [`aligned_snr`].
+/// Scores are only comparable between signals of the same sample rate
+pub fn perceptual_score(reference: &[Sample], decoded: &[Sample], sample_rate: u32) -> f32 {
+    let frame_len = (sample_rate as usize * 32 / 1000).max(2);
+    let len = reference.len().min(decoded.len());
+    // levels are aligned first, a louder or quieter copy sounds the same
+    let power = |samples: &[Sample]| samples.iter().map(|s| (*s as f64).powi(2)).sum::<f64>();
+    let gain = power(&reference[..len]) / power(&decoded[..len]).max(1.0);
+    let mut distortion = 0.0;
+    let mut frames = 0;
+    for start in (0..(len + 1).saturating_sub(frame_len)).step_by(frame_len / 2) {
+        let r = &reference[start..start + frame_len];
+        if power(r) / (frame_len as f64) < SILENCE {
+            continue;
+        }
+        let d = &decoded[start..start + frame_len];
+        let reference_bands = band_energies(r, sample_rate);
+        let decoded_bands = band_energies(d, sample_rate)
+            .into_iter()
+            .map(|e| e * gain)
+            .collect::<Vec<_>>();
+        // bands 40 dB under the loudest one of the frame are inaudible
+        let floor = reference_bands.iter().cloned().fold(0.0, f64::max) * 1e-4;
+        // loudness grows with the energy to the power 0.23 (Zwicker)
+        let loudness = |e: f64| (e + floor).powf(0.23);
+        let (mut difference, mut total) = (0.0, 0.0);
+        for (r, d) in reference_bands.iter().zip(decoded_bands.iter()) {
+            difference += (loudness(*r) - loudness(*d)).abs();
+            total += loudness(*r);
+        }
+        distortion += difference / total.max(f64::MIN_POSITIVE);
+        frames += 1;
+    }
+    let (low, high) = SCORE_RANGE;
+    if frames == 0 {
+        return high;
+    }
+    let distortion = distortion / frames as f64;
+    low + (high - low) * (-(distortion / DISTORTION_SCALE)).exp() as f32
+}
@@ -1,4 +1,5 @@
 use super::codecs::resample::LinearResampler;
+use super::error::MediaResult;
 use crate::{media::processor::Processor, AudioFrame, Sample, PcmBuf, Samples};
 use anyhow::Result;
 use nnnoiseless::DenoiseState;
@@ -8,6 +9,7 @@ pub struct NoiseReducer {
     resampler_target: RefCell<LinearResampler>,
     resampler_source: RefCell<LinearResampler>,
     denoiser: RefCell<Box<DenoiseState<'static>>>,
+    sample_rate: u32,
 }
 
 impl NoiseReducer {
@@ -19,6 +21,7 @@ impl NoiseReducer {
             resampler_target: RefCell::new(resampler48k),
             resampler_source: RefCell::new(resampler16k),
             denoiser: RefCell::new(denoiser),
+            sample_rate: input_sample_rate as u32,
         })
     }
 }
@@ -26,7 +29,11 @@ unsafe impl Send for NoiseReducer {}
 unsafe impl Sync for NoiseReducer {}
 
 impl Processor for NoiseReducer {
-    fn process_frame(&self, frame: &mut AudioFrame) -> Result<()> {
+    fn sample_rate(&self) -> Option<u32> {
+        Some(self.sample_rate)
+    }
+
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
         // If empty frame, nothing to do
         if frame.samples.is_empty() {
             return Ok(());
@@ -0,0 +1,182 @@
+use crate::event::{EventSender, SessionEvent};
+use crate::media::error::MediaResult;
+use crate::media::processor::Processor;
+use crate::{AudioFrame, Samples};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Samples at or above this share of full scale count as clipped
+const CLIP_LEVEL: f64 = 0.99;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct DistortionOption {
+    /// Analysis interval (in ms)
+    pub interval: u64,
+    /// Share of clipped samples that makes an interval clipped, 0.0 - 1.0
+    pub clip_ratio: f64,
+    /// DC offset that makes an interval offset (in dBFS)
+    pub dc_threshold: f64,
+    /// How long the distortion must last before it's reported (in ms)
+    pub min_duration: u64,
+}
+
+impl Default for DistortionOption {
+    fn default() -> Self {
+        Self {
+            interval: 500,
+            clip_ratio: 0.005,
+            dc_threshold: -30.0,
+            min_duration: 2000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DistortionKind {
+    Clipping,
+    DcOffset,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DistortionStats {
+    /// Since when the track is distorted (in ms)
+    pub start_time: u64,
+    pub duration: u64,
+    /// Share of clipped samples, 0.0 - 1.0
+    pub clipped_ratio: f64,
+    /// Mean of the samples (in dBFS)
+    pub dc_offset: f64,
+}
+
+#[derive(Default)]
+struct Episode {
+    start_time: u64,
+    duration: u64,
+    samples: u64,
+    clipped: u64,
+    sum: f64,
+    reported: bool,
+}
+
+#[derive(Default)]
+struct TrackWindow {
+    start_time: Option<u64>,
+    samples: u64,
+    clipped: u64,
+    sum: f64,
+    episodes: HashMap<DistortionKind, Episode>,
+}
+
+fn dbfs(value: f64) -> f64 {
+    if value <= 0.0 {
+        return -100.0;
+    }
+    (20.0 * (value / 32768.0).log10()).max(-100.0)
+}
+
+/// Flags sustained clipping and DC offset on a track, usually a gateway
+/// with a misconfigured gain or a broken codec, as `distortion` events.
+/// Each episode is reported once, when it lasted `min_duration`.
+pub struct DistortionDetector {
+    event_sender: EventSender,
+    option: DistortionOption,
+    windows: Mutex<HashMap<String, TrackWindow>>,
+}
+
+impl DistortionDetector {
+    pub fn new(event_sender: EventSender, option: DistortionOption) -> Self {
+        Self {
+            event_sender,
+            option,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Processor for DistortionDetector {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
+        let samples = match &frame.samples {
+            Samples::PCM { samples } if !samples.is_empty() => samples,
+            _ => return Ok(()),
+        };
+        let clip_level = (i16::MAX as f64 * CLIP_LEVEL) as i32;
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows.entry(frame.track_id.clone()).or_default();
+        let start_time = *window.start_time.get_or_insert(frame.timestamp);
+        for &sample in samples.iter() {
+            if (sample as i32).abs() >= clip_level {
+                window.clipped += 1;
+            }
+            window.sum += sample as f64;
+        }
+        window.samples += samples.len() as u64;
+        let duration = window.samples * 1000 / frame.sample_rate.max(1) as u64;
+        if duration < self.option.interval.max(1) {
+            return Ok(());
+        }
+
+        let clipped_ratio = window.clipped as f64 / window.samples as f64;
+        let dc_offset = dbfs((window.sum / window.samples as f64).abs());
+        let (samples, clipped, sum) = (window.samples, window.clipped, window.sum);
+        window.start_time = None;
+        window.samples = 0;
+        window.clipped = 0;
+        window.sum = 0.0;
+
+        for (kind, distorted) in [
+            (
+                DistortionKind::Clipping,
+                clipped_ratio >= self.option.clip_ratio,
+            ),
+            (
+                DistortionKind::DcOffset,
+                dc_offset >= self.option.dc_threshold,
+            ),
+        ] {
+            if !distorted {
+                window.episodes.remove(&kind);
+                continue;
+            }
+            let episode = window.episodes.entry(kind).or_insert_with(|| Episode {
+                start_time,
+                ..Default::default()
+            });
+            episode.duration += duration;
+            episode.samples += samples;
+            episode.clipped += clipped;
+            episode.sum += sum;
+            if episode.reported || episode.duration < self.option.min_duration {
+                continue;
+            }
+            episode.reported = true;
+            let stats = DistortionStats {
+                start_time: episode.start_time,
+                duration: episode.duration,
+                clipped_ratio: episode.clipped as f64 / episode.samples as f64,
+                dc_offset: dbfs((episode.sum / episode.samples as f64).abs()),
+            };
+            warn!(
+                track_id = frame.track_id,
+                ?kind,
+                clipped_ratio = stats.clipped_ratio,
+                dc_offset = stats.dc_offset,
+                "distorted audio"
+            );
+            self.event_sender
+                .send(SessionEvent::Distortion {
+                    track_id: frame.track_id.clone(),
+                    timestamp: crate::get_timestamp(),
+                    kind,
+                    stats,
+                })
+                .ok();
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,17 @@
-use std::sync::atomic::{AtomicU8, AtomicU16};
+use crate::event::{EventSender, SessionEvent};
+use crate::media::error::MediaResult;
+use crate::media::processor::{Processor, energy_dbfs};
+use crate::{AudioFrame, Sample, Samples};
+use anyhow::{Result, anyhow};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicU8, AtomicU16},
+    },
+    time::Duration,
+};
 // DTMF events as per RFC 4733
 const DTMF_EVENT_0: u8 = 0;
 const DTMF_EVENT_1: u8 = 1;
@@ -17,31 +30,176 @@ const DTMF_EVENT_B: u8 = 13;
 const DTMF_EVENT_C: u8 = 14;
 const DTMF_EVENT_D: u8 = 15;
 
+// In-band DTMF frequencies as per ITU-T Q.23
+const DTMF_ROWS: [f64; 4] = [697.0, 770.0, 852.0, 941.0];
+const DTMF_COLUMNS: [f64; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const DTMF_KEYS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+// Share of the frame's energy the row and column tones must hold together
+const DTMF_PURITY: f64 = 0.7;
+// Share each of them must hold, about 9 dB of twist
+const DTMF_MIN_SHARE: f64 = 0.1;
+// Clock of the duration of telephone-events
+const DTMF_EVENT_CLOCK_RATE: u64 = 8000;
+// Amplitude of each of the two tones of a digit dialed in band, -12 dBFS
+const DTMF_TONE_AMPLITUDE: f64 = 8192.0;
+// Shortest digit dialed in band, and the silence after it (in ms)
+const DTMF_TONE_MIN_MS: u64 = 40;
+const DTMF_TONE_GAP_MS: u64 = 60;
+
+/// Duration of a digit that came without one (in ms)
+pub const DTMF_DEFAULT_DURATION_MS: u64 = 160;
+
+/// The RFC 4733 event of a digit
+pub fn dtmf_event_code(digit: &str) -> Option<u8> {
+    let event = match digit {
+        "0" => DTMF_EVENT_0,
+        "1" => DTMF_EVENT_1,
+        "2" => DTMF_EVENT_2,
+        "3" => DTMF_EVENT_3,
+        "4" => DTMF_EVENT_4,
+        "5" => DTMF_EVENT_5,
+        "6" => DTMF_EVENT_6,
+        "7" => DTMF_EVENT_7,
+        "8" => DTMF_EVENT_8,
+        "9" => DTMF_EVENT_9,
+        "*" => DTMF_EVENT_STAR,
+        "#" => DTMF_EVENT_POUND,
+        "A" => DTMF_EVENT_A,
+        "B" => DTMF_EVENT_B,
+        "C" => DTMF_EVENT_C,
+        "D" => DTMF_EVENT_D,
+        _ => return None,
+    };
+    Some(event)
+}
+
+/// The digit of an RFC 4733 event
+pub fn dtmf_digit(event: u8) -> Option<&'static str> {
+    let digit = match event {
+        DTMF_EVENT_0 => "0",
+        DTMF_EVENT_1 => "1",
+        DTMF_EVENT_2 => "2",
+        DTMF_EVENT_3 => "3",
+        DTMF_EVENT_4 => "4",
+        DTMF_EVENT_5 => "5",
+        DTMF_EVENT_6 => "6",
+        DTMF_EVENT_7 => "7",
+        DTMF_EVENT_8 => "8",
+        DTMF_EVENT_9 => "9",
+        DTMF_EVENT_STAR => "*",
+        DTMF_EVENT_POUND => "#",
+        DTMF_EVENT_A => "A",
+        DTMF_EVENT_B => "B",
+        DTMF_EVENT_C => "C",
+        DTMF_EVENT_D => "D",
+        _ => return None,
+    };
+    Some(digit)
+}
+
+/// How a leg of a call sends and takes DTMF
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DtmfMode {
+    /// telephone-event RTP packets
+    #[default]
+    Rfc4733,
+    /// Tones in the audio
+    Inband,
+    /// SIP INFO requests with an `application/dtmf-relay` body
+    Info,
+}
+
+impl DtmfMode {
+    /// The mode of a leg whose SDP is `sdp`. RFC 4733 needs telephone-event
+    /// in the SDP, a leg without it gets the digits in band. Without a
+    /// preference, RFC 4733 when it was negotiated
+    pub fn negotiate(preferred: Option<DtmfMode>, sdp: &str) -> DtmfMode {
+        let telephone_event = sdp.to_ascii_lowercase().contains("telephone-event");
+        match preferred {
+            Some(DtmfMode::Rfc4733) | None if !telephone_event => DtmfMode::Inband,
+            Some(mode) => mode,
+            None => DtmfMode::Rfc4733,
+        }
+    }
+
+    /// The mode a digit received in this mode is sent to a leg taking `to`
+    /// in, `None` when the media already carries it there: RFC 4733 packets
+    /// and tones are forwarded as they came
+    pub fn interwork(self, to: DtmfMode) -> Option<DtmfMode> {
+        match (self, to) {
+            (DtmfMode::Rfc4733, DtmfMode::Rfc4733) | (DtmfMode::Inband, DtmfMode::Inband) => None,
+            (_, to) => Some(to),
+        }
+    }
+}
+
+/// The digit and duration (in ms) of a SIP INFO carrying DTMF, as
+/// `application/dtmf-relay` ("Signal=5\r\nDuration=160") or `application/dtmf`
+pub fn parse_dtmf_info(content_type: &str, body: &str) -> Option<(String, Option<u64>)> {
+    let content_type = content_type.split(';').next()?.trim().to_ascii_lowercase();
+    let (signal, duration) = match content_type.as_str() {
+        "application/dtmf-relay" => {
+            let (mut signal, mut duration) = (None, None);
+            for line in body.lines() {
+                let Some((key, value)) = line.split_once('=') else {
+                    continue;
+                };
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "signal" => signal = Some(value.trim()),
+                    "duration" => duration = value.trim().parse::<u64>().ok(),
+                    _ => {}
+                }
+            }
+            (signal?, duration)
+        }
+        "application/dtmf" => (body.trim(), None),
+        _ => return None,
+    };
+    // some send the event, "10" for "*"
+    let digit = match signal.parse::<u8>() {
+        Ok(event) => dtmf_digit(event)?.to_string(),
+        Err(_) => signal.to_ascii_uppercase(),
+    };
+    dtmf_event_code(&digit)?;
+    Some((digit, duration))
+}
+
+/// The `application/dtmf-relay` body of a SIP INFO
+pub fn dtmf_info_body(digit: &str, duration: u64) -> String {
+    format!("Signal={}\r\nDuration={}\r\n", digit, duration)
+}
+
 pub struct DtmfDetector {
     // Track the last seen event to avoid repeated events
     last_event: AtomicU8,
     last_duration: AtomicU16,
 }
 
-#[derive(Debug)]
-struct DtmfPayload {
-    event: u8, // 8bits
-    #[allow(dead_code)]
-    is_end: bool, // 1bit
-    _reserved: u8, // 1bits
-    _volume: u8, // 6bits
-    duration: u16, // 16bits
+#[derive(Debug, Clone, PartialEq)]
+pub struct DtmfPayload {
+    pub event: u8,     // 8bits
+    pub is_end: bool,  // 1bit
+    pub reserved: u8,  // 1bits
+    pub volume: u8,    // 6bits
+    pub duration: u16, // 16bits
 }
 
 impl DtmfPayload {
-    fn parse(payload: &[u8]) -> Option<Self> {
+    /// Parse a RFC 4733 telephone-event payload
+    pub fn parse(payload: &[u8]) -> Result<Self> {
         if payload.len() < 4 {
-            return None;
+            return Err(anyhow!("dtmf payload too short: {} bytes", payload.len()));
         }
 
         let event = payload[0];
         if event > DTMF_EVENT_D {
-            return None;
+            return Err(anyhow!("unsupported dtmf event: {}", event));
         }
 
         //     0                   1                   2                   3
@@ -63,14 +221,19 @@ impl DtmfPayload {
         let duration_low = payload[3] as u16;
         let duration = (duration_high << 8) | duration_low;
 
-        Some(Self {
+        Ok(Self {
             event,
             is_end,
-            _reserved: reserved,
-            _volume: volume,
+            reserved,
+            volume,
             duration,
         })
     }
+
+    /// The duration in ms, telephone-events have a clock of 8000 Hz
+    pub fn duration_ms(&self) -> u64 {
+        self.duration as u64 * 1000 / DTMF_EVENT_CLOCK_RATE
+    }
 }
 
 impl DtmfDetector {
@@ -95,7 +258,7 @@ impl DtmfDetector {
         }
 
         // Parse the DTMF payload
-        let dtmf_payload = DtmfPayload::parse(payload)?;
+        let dtmf_payload = DtmfPayload::parse(payload).ok()?;
 
         // Get current duration
         let current_event = dtmf_payload.event;
@@ -111,34 +274,234 @@ impl DtmfDetector {
             return None;
         }
 
-        Some(
-            match dtmf_payload.event {
-                DTMF_EVENT_0 => "0",
-                DTMF_EVENT_1 => "1",
-                DTMF_EVENT_2 => "2",
-                DTMF_EVENT_3 => "3",
-                DTMF_EVENT_4 => "4",
-                DTMF_EVENT_5 => "5",
-                DTMF_EVENT_6 => "6",
-                DTMF_EVENT_7 => "7",
-                DTMF_EVENT_8 => "8",
-                DTMF_EVENT_9 => "9",
-                DTMF_EVENT_STAR => "*",
-                DTMF_EVENT_POUND => "#",
-                DTMF_EVENT_A => "A",
-                DTMF_EVENT_B => "B",
-                DTMF_EVENT_C => "C",
-                DTMF_EVENT_D => "D",
-                _ => return None, // Invalid event
+        dtmf_digit(dtmf_payload.event).map(|digit| digit.to_string())
+    }
+
+    /// Duration of the last event seen (in ms)
+    pub fn duration(&self) -> u64 {
+        self.last_duration
+            .load(std::sync::atomic::Ordering::Relaxed) as u64
+            * 1000
+            / DTMF_EVENT_CLOCK_RATE
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct InbandDtmfOption {
+    /// Mute the tones in the forwarded audio, so the far end doesn't detect
+    /// the digits again after they were relayed as RFC 4733 events
+    pub suppress: bool,
+    /// Shortest tone reported as a digit (in ms)
+    pub min_duration: u64,
+    /// Audio muted after a tone ended, covers its decay (in ms)
+    pub hangover: u64,
+    /// Quietest tone detected (in dBFS)
+    pub energy_threshold: f32,
+}
+
+impl Default for InbandDtmfOption {
+    fn default() -> Self {
+        Self {
+            suppress: true,
+            min_duration: 40,
+            hangover: 20,
+            energy_threshold: -36.0,
+        }
+    }
+}
+
+/// Goertzel power at `frequency`, as a share of the energy of `samples`:
+/// 1.0 for a pure tone of that frequency
+fn tone_share(samples: &[Sample], frequency: f64, sample_rate: u32, energy: f64) -> f64 {
+    let coefficient = 2.0 * (2.0 * std::f64::consts::PI * frequency / sample_rate as f64).cos();
+    let (mut s1, mut s2) = (0.0, 0.0);
+    for sample in samples {
+        let s0 = *sample as f64 + coefficient * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+    }
+    let power = s1 * s1 + s2 * s2 - coefficient * s1 * s2;
+    2.0 * power / (samples.len() as f64 * energy)
+}
+
+/// The digit dialed in band in `samples`, if one row and one column tone
+/// carry most of their energy
+pub fn detect_inband(samples: &[Sample], sample_rate: u32, energy_threshold: f32) -> Option<char> {
+    if samples.is_empty() || energy_dbfs(samples) < energy_threshold {
+        return None;
+    }
+    let energy = samples.iter().map(|&s| s as f64 * s as f64).sum::<f64>();
+    let strongest = |frequencies: &[f64; 4]| {
+        frequencies
+            .iter()
+            .map(|&f| tone_share(samples, f, sample_rate, energy))
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or_default()
+    };
+    let (row, row_share) = strongest(&DTMF_ROWS);
+    let (column, column_share) = strongest(&DTMF_COLUMNS);
+    if row_share + column_share < DTMF_PURITY
+        || row_share < DTMF_MIN_SHARE
+        || column_share < DTMF_MIN_SHARE
+    {
+        return None;
+    }
+    Some(DTMF_KEYS[row][column])
+}
+
+/// A digit dialed in band, written over the frames sent to a leg
+#[derive(Debug, Clone, PartialEq)]
+pub struct DtmfTone {
+    low: f64,
+    high: f64,
+    /// Time into the tone (in s)
+    position: f64,
+    tone: f64,
+    /// The tone and the silence after it (in s)
+    length: f64,
+}
+
+impl DtmfTone {
+    pub fn new(digit: &str, duration: Duration) -> Option<Self> {
+        let key = match digit.as_bytes() {
+            [key] => key.to_ascii_uppercase() as char,
+            _ => return None,
+        };
+        let (row, column) = DTMF_KEYS.iter().enumerate().find_map(|(row, keys)| {
+            keys.iter()
+                .position(|&k| k == key)
+                .map(|column| (row, column))
+        })?;
+        let tone = duration
+            .max(Duration::from_millis(DTMF_TONE_MIN_MS))
+            .as_secs_f64();
+        Some(Self {
+            low: DTMF_ROWS[row],
+            high: DTMF_COLUMNS[column],
+            position: 0.0,
+            tone,
+            length: tone + DTMF_TONE_GAP_MS as f64 / 1000.0,
+        })
+    }
+
+    /// Writes the next samples of the tone over `samples`, then silence.
+    /// False once the silence after the tone is over
+    pub fn render(&mut self, samples: &mut [Sample], sample_rate: u32) -> bool {
+        let step = 1.0 / sample_rate.max(1) as f64;
+        let phase = 2.0 * std::f64::consts::PI;
+        // half a sample short of the end is the end, the steps add up
+        // rounding errors
+        let half = step / 2.0;
+        for sample in samples.iter_mut() {
+            *sample = if self.position + half < self.tone {
+                let t = self.position;
+                (((phase * self.low * t).sin() + (phase * self.high * t).sin())
+                    * DTMF_TONE_AMPLITUDE) as Sample
+            } else {
+                0
+            };
+            self.position += step;
+        }
+        self.position + half < self.length
+    }
+}
+
+#[derive(Default)]
+struct SuppressorState {
+    digit: Option<char>,
+    /// How long `digit` has been sounding (in ms)
+    duration: u64,
+    hangover: u64,
+}
+
+/// Detects DTMF dialed in band and reports each digit as a `Dtmf` event
+/// with its duration once the tone ended, the way RFC 4733 digits are, and
+/// mutes the tones in the frames it passes on. A frame is muted as soon as
+/// it holds a tone, before the tone lasted long enough to be a digit
+pub struct DtmfSuppressor {
+    option: InbandDtmfOption,
+    event_sender: EventSender,
+    state: Mutex<SuppressorState>,
+}
+
+impl DtmfSuppressor {
+    pub fn new(event_sender: EventSender, option: InbandDtmfOption) -> Self {
+        Self {
+            option,
+            event_sender,
+            state: Mutex::new(SuppressorState::default()),
+        }
+    }
+
+    pub fn create(
+        event_sender: EventSender,
+        option: InbandDtmfOption,
+    ) -> Result<Box<dyn Processor>> {
+        Ok(Box::new(Self::new(event_sender, option)))
+    }
+
+    fn finish(&self, state: &mut SuppressorState, track_id: &str, timestamp: u64) {
+        if let Some(digit) = state.digit.take()
+            && state.duration >= self.option.min_duration
+        {
+            self.event_sender
+                .send(SessionEvent::Dtmf {
+                    track_id: track_id.to_string(),
+                    timestamp,
+                    digit: digit.to_string(),
+                    duration: Some(state.duration),
+                })
+                .ok();
+        }
+        state.duration = 0;
+    }
+}
+
+impl Processor for DtmfSuppressor {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
+        let samples = match &mut frame.samples {
+            Samples::PCM { samples } if !samples.is_empty() => samples,
+            _ => return Ok(()),
+        };
+        let duration = samples.len() as u64 * 1000 / frame.sample_rate.max(1) as u64;
+        let digit = detect_inband(samples, frame.sample_rate, self.option.energy_threshold);
+        let mut state = self.state.lock().unwrap();
+        let muted = match digit {
+            Some(digit) => {
+                if state.digit != Some(digit) {
+                    self.finish(&mut state, &frame.track_id, frame.timestamp);
+                    state.digit = Some(digit);
+                }
+                state.duration += duration;
+                state.hangover = self.option.hangover;
+                true
+            }
+            // a gap shorter than the hangover doesn't end the digit
+            None if state.hangover > 0 => {
+                state.hangover = state.hangover.saturating_sub(duration);
+                true
             }
-            .to_string(),
-        )
+            None => {
+                self.finish(&mut state, &frame.track_id, frame.timestamp);
+                false
+            }
+        };
+        if muted && self.option.suppress {
+            samples.fill(0);
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::event::create_event_sender;
+    use crate::media::codecs::testing;
+    use std::time::Duration;
 
     #[test]
     fn test_dtmf_payload_parse() {
@@ -149,8 +512,8 @@ mod tests {
         let dtmf = DtmfPayload::parse(&payload).unwrap();
         assert_eq!(dtmf.event, 1);
         assert_eq!(dtmf.is_end, true);
-        assert_eq!(dtmf._reserved, 0);
-        assert_eq!(dtmf._volume, 10); // 10 = 001010 binary
+        assert_eq!(dtmf.reserved, 0);
+        assert_eq!(dtmf.volume, 10); // 10 = 001010 binary
         assert_eq!(dtmf.duration, 160);
 
         // Test payload with end bit not set
@@ -159,15 +522,15 @@ mod tests {
         let dtmf = DtmfPayload::parse(&payload).unwrap();
         assert_eq!(dtmf.event, 2);
         assert_eq!(dtmf.is_end, false);
-        assert_eq!(dtmf._volume, 0);
+        assert_eq!(dtmf.volume, 0);
 
         // Invalid event code
         let payload = [20, 0x80, 10, 100]; // 20 > DTMF_EVENT_D
-        assert!(DtmfPayload::parse(&payload).is_none());
+        assert!(DtmfPayload::parse(&payload).is_err());
 
         // Too short payload
         let payload = [1, 0x80, 10]; // Missing duration byte
-        assert!(DtmfPayload::parse(&payload).is_none());
+        assert!(DtmfPayload::parse(&payload).is_err());
 
         // Test the specific case [2, 138, 3, 32]
         let payload = [2, 138, 3, 32];
@@ -179,8 +542,8 @@ mod tests {
         let dtmf = DtmfPayload::parse(&payload).unwrap();
         assert_eq!(dtmf.event, 2); // DTMF digit "2"
         assert_eq!(dtmf.is_end, true); // End bit is set
-        assert_eq!(dtmf._reserved, 0); // Reserved bit is 0
-        assert_eq!(dtmf._volume, 10); // Volume is 10
+        assert_eq!(dtmf.reserved, 0); // Reserved bit is 0
+        assert_eq!(dtmf.volume, 10); // Volume is 10
         assert_eq!(dtmf.duration, 800); // Duration is 3 * 256 + 32 = 800
     }
 
@@ -227,4 +590,167 @@ mod tests {
             assert_eq!(digit4, Some("6".to_string()));
         }
     }
+
+    #[test]
+    fn test_detect_inband() {
+        let ms = Duration::from_millis;
+        for sample_rate in [8000, 16000] {
+            let frame = sample_rate as usize / 50;
+            let tones = testing::dtmf("0123456789*#ABCD", sample_rate, ms(20), ms(0)).unwrap();
+            let digits: String = tones
+                .chunks(frame)
+                .filter_map(|tone| detect_inband(tone, sample_rate, -36.0))
+                .collect();
+            assert_eq!(digits, "0123456789*#ABCD");
+
+            let speech = testing::speech_noise(3, sample_rate, Duration::from_secs(5));
+            assert!(
+                speech
+                    .chunks(frame)
+                    .all(|speech| detect_inband(speech, sample_rate, -36.0).is_none())
+            );
+            let silence = vec![0; frame];
+            assert_eq!(detect_inband(&silence, sample_rate, -36.0), None);
+        }
+    }
+
+    #[test]
+    fn test_dtmf_suppressor() {
+        let event_sender = create_event_sender();
+        let mut events = event_sender.subscribe();
+        let suppressor = DtmfSuppressor::new(event_sender, InbandDtmfOption::default());
+        let ms = Duration::from_millis;
+        let mut samples = testing::dtmf("155", 8000, ms(100), ms(60)).unwrap();
+        // a tone too short to be a digit is muted all the same
+        samples.extend(testing::dtmf("9", 8000, ms(20), ms(60)).unwrap());
+        let speech = testing::speech_noise(5, 8000, ms(500));
+        samples.extend(&speech);
+
+        let mut output = Vec::new();
+        for (i, chunk) in samples.chunks(160).enumerate() {
+            let mut frame = AudioFrame {
+                track_id: "caller".to_string(),
+                timestamp: i as u64 * 20,
+                samples: Samples::PCM {
+                    samples: chunk.to_vec(),
+                },
+                sample_rate: 8000,
+            };
+            suppressor.process_frame(&mut frame).unwrap();
+            if let Samples::PCM { samples } = frame.samples {
+                output.extend(samples);
+            }
+        }
+
+        let mut digits = Vec::new();
+        while let Ok(SessionEvent::Dtmf {
+            digit,
+            timestamp,
+            duration,
+            ..
+        }) = events.try_recv()
+        {
+            digits.push((digit, timestamp, duration));
+        }
+        // reported once the tone and the hangover after it ended
+        assert_eq!(
+            digits,
+            vec![
+                ("1".to_string(), 120, Some(100)),
+                ("5".to_string(), 280, Some(100)),
+                ("5".to_string(), 440, Some(100)),
+            ]
+        );
+        let tones = samples.len() - speech.len();
+        assert!(output[..tones].iter().all(|&s| s == 0));
+        // the speech after the tones gets through untouched
+        assert_eq!(&output[tones..], &speech[..]);
+    }
+
+    #[test]
+    fn test_dtmf_mode_interworking() {
+        let with_events = "m=audio 4000 RTP/AVP 0 101\r\na=rtpmap:101 telephone-event/8000\r\n";
+        let without_events = "m=audio 4000 RTP/AVP 0\r\n";
+        assert_eq!(DtmfMode::negotiate(None, with_events), DtmfMode::Rfc4733);
+        assert_eq!(DtmfMode::negotiate(None, without_events), DtmfMode::Inband);
+        assert_eq!(
+            DtmfMode::negotiate(Some(DtmfMode::Rfc4733), without_events),
+            DtmfMode::Inband
+        );
+        assert_eq!(
+            DtmfMode::negotiate(Some(DtmfMode::Info), with_events),
+            DtmfMode::Info
+        );
+        assert_eq!(
+            DtmfMode::negotiate(Some(DtmfMode::Inband), with_events),
+            DtmfMode::Inband
+        );
+
+        assert_eq!(DtmfMode::Rfc4733.interwork(DtmfMode::Rfc4733), None);
+        assert_eq!(DtmfMode::Inband.interwork(DtmfMode::Inband), None);
+        assert_eq!(
+            DtmfMode::Info.interwork(DtmfMode::Info),
+            Some(DtmfMode::Info)
+        );
+        assert_eq!(
+            DtmfMode::Rfc4733.interwork(DtmfMode::Inband),
+            Some(DtmfMode::Inband)
+        );
+        assert_eq!(
+            DtmfMode::Inband.interwork(DtmfMode::Info),
+            Some(DtmfMode::Info)
+        );
+        assert_eq!(
+            DtmfMode::Info.interwork(DtmfMode::Rfc4733),
+            Some(DtmfMode::Rfc4733)
+        );
+    }
+
+    #[test]
+    fn test_parse_dtmf_info() {
+        assert_eq!(
+            parse_dtmf_info("application/dtmf-relay", &dtmf_info_body("5", 160)),
+            Some(("5".to_string(), Some(160)))
+        );
+        assert_eq!(
+            parse_dtmf_info("Application/DTMF-Relay", "Signal= 11\nDuration= 250\n"),
+            Some(("#".to_string(), Some(250)))
+        );
+        assert_eq!(
+            parse_dtmf_info("application/dtmf", "a"),
+            Some(("A".to_string(), None))
+        );
+        assert_eq!(
+            parse_dtmf_info("application/dtmf-relay", "Duration=160"),
+            None
+        );
+        assert_eq!(parse_dtmf_info("application/dtmf-relay", "Signal=x"), None);
+        assert_eq!(parse_dtmf_info("text/plain", "Signal=5"), None);
+    }
+
+    #[test]
+    fn test_dtmf_tone_render() {
+        assert_eq!(DtmfTone::new("x", Duration::from_millis(100)), None);
+        for sample_rate in [8000, 16000] {
+            let frame = sample_rate as usize / 50;
+            let mut tone = DtmfTone::new("7", Duration::from_millis(100)).unwrap();
+            let mut frames = Vec::new();
+            loop {
+                let mut samples = vec![1000; frame];
+                let more = tone.render(&mut samples, sample_rate);
+                frames.push(samples);
+                if !more {
+                    break;
+                }
+            }
+            // 100ms of tone then 60ms of silence
+            assert_eq!(frames.len(), 8);
+            assert!(
+                frames[..5]
+                    .iter()
+                    .all(|samples| detect_inband(samples, sample_rate, -36.0) == Some('7'))
+            );
+            assert!(frames[5..].iter().flatten().all(|&s| s == 0));
+        }
+    }
 }
@@ -1,7 +1,13 @@
 use super::{
     asr_processor::AsrProcessor,
     denoiser::NoiseReducer,
+    distortion::DistortionDetector,
+    dtmf::DtmfSuppressor,
+    keyword::{KeywordOption, KeywordSpotter},
+    language::{LanguageDetector, LanguageOption},
+    meter::LevelMeter,
     processor::Processor,
+    prosody::ProsodyAnalyzer,
     track::{
         Track,
         tts::{SynthesisHandle, TtsTrack},
@@ -13,12 +19,12 @@ use crate::{
     call::{CallOption, EouOption},
     event::EventSender,
     synthesis::{
-        AliyunTtsClient, SynthesisClient, SynthesisOption, SynthesisType, TencentCloudTtsClient,
-        VoiceApiTtsClient,
+        AliyunTtsClient, MrcpTtsClient, SynthesisClient, SynthesisOption, SynthesisType,
+        TencentCloudTtsClient, VoiceApiTtsClient,
     },
     transcription::{
-        AliyunAsrClientBuilder, TencentCloudAsrClientBuilder, TranscriptionClient,
-        TranscriptionOption, TranscriptionType, VoiceApiAsrClientBuilder,
+        AliyunAsrClientBuilder, MrcpAsrClientBuilder, TencentCloudAsrClientBuilder,
+        TranscriptionClient, TranscriptionOption, TranscriptionType, VoiceApiAsrClientBuilder,
     },
 };
 use anyhow::Result;
@@ -38,6 +44,18 @@ pub type FnCreateEouProcessor = fn(
     option: EouOption,
 ) -> Result<Box<dyn Processor>>;
 
+pub type FnCreateKeywordProcessor = fn(
+    token: CancellationToken,
+    event_sender: EventSender,
+    option: KeywordOption,
+) -> Result<Box<dyn Processor>>;
+
+pub type FnCreateLanguageProcessor = fn(
+    token: CancellationToken,
+    event_sender: EventSender,
+    option: LanguageOption,
+) -> Result<Box<dyn Processor>>;
+
 pub type FnCreateAsrClient = Box<
     dyn Fn(
             TrackId,
@@ -66,6 +84,8 @@ pub type CreateProcessorsHook = Box<
 pub struct StreamEngine {
     vad_creators: HashMap<VadType, FnCreateVadProcessor>,
     eou_creators: HashMap<String, FnCreateEouProcessor>,
+    keyword_creators: HashMap<String, FnCreateKeywordProcessor>,
+    language_creators: HashMap<String, FnCreateLanguageProcessor>,
     asr_creators: HashMap<TranscriptionType, FnCreateAsrClient>,
     tts_creators: HashMap<SynthesisType, FnCreateTtsClient>,
     create_processors_hook: Arc<CreateProcessorsHook>,
@@ -80,6 +100,8 @@ impl Default for StreamEngine {
         engine.register_vad(VadType::WebRTC, VadProcessor::create_webrtc);
         #[cfg(feature = "vad_ten")]
         engine.register_vad(VadType::Ten, VadProcessor::create_ten);
+        engine.register_keyword("template".to_string(), KeywordSpotter::create_template);
+        engine.register_language("http".to_string(), LanguageDetector::create_http);
 
         engine.register_asr(
             TranscriptionType::TencentCloud,
@@ -93,9 +115,14 @@ impl Default for StreamEngine {
             TranscriptionType::Aliyun,
             Box::new(AliyunAsrClientBuilder::create),
         );
+        engine.register_asr(
+            TranscriptionType::Mrcp,
+            Box::new(MrcpAsrClientBuilder::create),
+        );
         engine.register_tts(SynthesisType::Aliyun, AliyunTtsClient::create);
         engine.register_tts(SynthesisType::TencentCloud, TencentCloudTtsClient::create);
         engine.register_tts(SynthesisType::VoiceApi, VoiceApiTtsClient::create);
+        engine.register_tts(SynthesisType::Mrcp, MrcpTtsClient::create);
         engine
     }
 }
@@ -107,6 +134,8 @@ impl StreamEngine {
             asr_creators: HashMap::new(),
             tts_creators: HashMap::new(),
             eou_creators: HashMap::new(),
+            keyword_creators: HashMap::new(),
+            language_creators: HashMap::new(),
             create_processors_hook: Arc::new(Box::new(Self::default_create_procesors_hook)),
         }
     }
@@ -121,6 +150,24 @@ impl StreamEngine {
         self
     }
 
+    pub fn register_keyword(
+        &mut self,
+        name: String,
+        creator: FnCreateKeywordProcessor,
+    ) -> &mut Self {
+        self.keyword_creators.insert(name, creator);
+        self
+    }
+
+    pub fn register_language(
+        &mut self,
+        name: String,
+        creator: FnCreateLanguageProcessor,
+    ) -> &mut Self {
+        self.language_creators.insert(name, creator);
+        self
+    }
+
     pub fn register_asr(
         &mut self,
         asr_type: TranscriptionType,
@@ -168,6 +215,37 @@ impl StreamEngine {
         }
     }
 
+    pub fn create_keyword_processor(
+        &self,
+        token: CancellationToken,
+        event_sender: EventSender,
+        option: KeywordOption,
+    ) -> Result<Box<dyn Processor>> {
+        let name = option
+            .r#type
+            .clone()
+            .unwrap_or_else(|| "template".to_string());
+        if let Some(creator) = self.keyword_creators.get(&name) {
+            creator(token, event_sender, option)
+        } else {
+            Err(anyhow::anyhow!("Keyword type not found: {}", name))
+        }
+    }
+
+    pub fn create_language_processor(
+        &self,
+        token: CancellationToken,
+        event_sender: EventSender,
+        option: LanguageOption,
+    ) -> Result<Box<dyn Processor>> {
+        let name = option.r#type.clone().unwrap_or_else(|| "http".to_string());
+        if let Some(creator) = self.language_creators.get(&name) {
+            creator(token, event_sender, option)
+        } else {
+            Err(anyhow::anyhow!("Language type not found: {}", name))
+        }
+    }
+
     pub async fn create_asr_processor(
         &self,
         track_id: TrackId,
@@ -260,6 +338,15 @@ impl StreamEngine {
         let samplerate = track.config().samplerate as usize;
         Box::pin(async move {
             let mut processors = vec![];
+            // ahead of the others, they must not hear the tones either
+            match option.inband_dtmf {
+                Some(ref option) => {
+                    let suppressor =
+                        DtmfSuppressor::create(event_sender.clone(), option.to_owned())?;
+                    processors.push(suppressor);
+                }
+                None => {}
+            }
             match option.denoise {
                 Some(true) => {
                     let noise_reducer = NoiseReducer::new(samplerate)?;
@@ -278,6 +365,53 @@ impl StreamEngine {
                 }
                 None => {}
             }
+            match option.keyword {
+                Some(ref option) => {
+                    let keyword_processor = engine.create_keyword_processor(
+                        cancel_token.child_token(),
+                        event_sender.clone(),
+                        option.to_owned(),
+                    )?;
+                    processors.push(keyword_processor);
+                }
+                None => {}
+            }
+            match option.language {
+                Some(ref option) => {
+                    let language_processor = engine.create_language_processor(
+                        cancel_token.child_token(),
+                        event_sender.clone(),
+                        option.to_owned(),
+                    )?;
+                    processors.push(language_processor);
+                }
+                None => {}
+            }
+            match option.prosody {
+                Some(ref option) => {
+                    let prosody_analyzer = ProsodyAnalyzer::create(
+                        cancel_token.child_token(),
+                        event_sender.clone(),
+                        option.to_owned(),
+                    )?;
+                    processors.push(prosody_analyzer);
+                }
+                None => {}
+            }
+            match option.meter {
+                Some(ref option) => {
+                    let meter = LevelMeter::new(event_sender.clone(), option.to_owned());
+                    processors.push(Box::new(meter) as Box<dyn Processor>);
+                }
+                None => {}
+            }
+            match option.distortion {
+                Some(ref option) => {
+                    let detector = DistortionDetector::new(event_sender.clone(), option.to_owned());
+                    processors.push(Box::new(detector) as Box<dyn Processor>);
+                }
+                None => {}
+            }
             match option.asr {
                 Some(ref option) => {
                     let asr_processor = engine
@@ -0,0 +1,146 @@
+//! Errors of the media path: processors, codecs, pipelines and tracks.
+//!
+//! [`MediaError`] tells what went wrong so callers can react, e.g. skip a
+//! payload type they don't handle or slow down on a buffer overrun, rather
+//! than match strings. Errors of the transports underneath are carried as
+//! they come, in [`MediaError::Io`] and [`MediaError::Other`].
+use std::fmt;
+
+pub type MediaResult<T> = std::result::Result<T, MediaError>;
+
+#[derive(Debug)]
+pub enum MediaError {
+    /// A codec failed to decode a payload
+    DecodeError {
+        payload_type: u8,
+        reason: String,
+    },
+    /// No codec of this build handles the payload type
+    UnsupportedPayload(u8),
+    /// A codec name or payload type no codec of this build handles
+    UnsupportedCodec(String),
+    /// Only PCM frames can be encoded or sent
+    UnsupportedFrame,
+    /// A datagram that isn't RTP, RTCP or STUN
+    InvalidPacket(String),
+    /// A buffer was full and dropped its oldest frames
+    BufferOverrun {
+        capacity: usize,
+        dropped: u64,
+    },
+    /// The frame couldn't be brought to the rate the processor at
+    /// `processor` in the chain needs
+    SampleRate {
+        processor: usize,
+        expected: u32,
+        actual: u32,
+    },
+    /// The processor at `processor` handles other channels than the chain
+    /// carries
+    Channels {
+        processor: usize,
+        expected: u16,
+        actual: u16,
+    },
+    /// The samples of the frame don't make whole sets of channels
+    PartialFrame {
+        processor: usize,
+        samples: usize,
+        channels: u16,
+    },
+    Io(std::io::Error),
+    Other(anyhow::Error),
+}
+
+impl fmt::Display for MediaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DecodeError {
+                payload_type,
+                reason,
+            } => write!(
+                f,
+                "failed to decode payload type {}: {}",
+                payload_type, reason
+            ),
+            Self::UnsupportedPayload(payload_type) => {
+                write!(f, "unsupported payload type: {}", payload_type)
+            }
+            Self::UnsupportedCodec(codec) => write!(f, "unsupported codec: {}", codec),
+            Self::UnsupportedFrame => write!(f, "only PCM frames can be sent"),
+            Self::InvalidPacket(reason) => write!(f, "invalid packet: {}", reason),
+            Self::BufferOverrun { capacity, dropped } => write!(
+                f,
+                "buffer of {} frames overrun, {} dropped",
+                capacity, dropped
+            ),
+            Self::SampleRate {
+                processor,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "processor {} needs frames at {} Hz, got {} Hz",
+                processor, expected, actual
+            ),
+            Self::Channels {
+                processor,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "processor {} handles {} channels, the chain carries {}",
+                processor, expected, actual
+            ),
+            Self::PartialFrame {
+                processor,
+                samples,
+                channels,
+            } => write!(
+                f,
+                "frame of {} samples for processor {} is not a multiple of {} channels",
+                samples, processor, channels
+            ),
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for MediaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Other(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for MediaError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<anyhow::Error> for MediaError {
+    fn from(e: anyhow::Error) -> Self {
+        // a media error that went through anyhow keeps its kind
+        match e.downcast::<MediaError>() {
+            Ok(e) => e,
+            Err(e) => Self::Other(e),
+        }
+    }
+}
+
+impl From<webrtc::Error> for MediaError {
+    fn from(e: webrtc::Error) -> Self {
+        Self::Other(e.into())
+    }
+}
+
+impl From<webrtc::util::Error> for MediaError {
+    fn from(e: webrtc::util::Error) -> Self {
+        Self::Other(e.into())
+    }
+}
@@ -1,4 +1,5 @@
 use crate::AudioFrame;
+use crate::media::clock::{ClockRef, system_clock};
 use std::collections::VecDeque;
 
 #[derive(Debug, Clone)]
@@ -24,6 +25,9 @@ pub struct JitterBuffer {
     // Buffer configuration
     target_delay_ms: u32, // Target buffering delay
     max_delay_ms: u32,    // Maximum acceptable delay
+
+    // Time source for the buffer delay
+    clock: ClockRef,
 }
 
 impl JitterBuffer {
@@ -45,9 +49,16 @@ impl JitterBuffer {
             total_late: 0,
             target_delay_ms,
             max_delay_ms,
+            clock: system_clock(),
         }
     }
 
+    /// Measure the buffer delay against `clock` instead of the system time
+    pub fn with_clock(mut self, clock: ClockRef) -> Self {
+        self.clock = clock;
+        self
+    }
+
     pub fn push(&mut self, frame: AudioFrame) -> bool {
         self.total_received += 1;
 
@@ -151,7 +162,7 @@ impl JitterBuffer {
             return false;
         }
 
-        let now = crate::get_timestamp();
+        let now = self.clock.timestamp();
         let oldest_ts = self.frames.front().unwrap().timestamp;
         let buffer_delay = now.saturating_sub(oldest_ts);
 
@@ -164,7 +175,7 @@ impl JitterBuffer {
             return false;
         }
 
-        let now = crate::get_timestamp();
+        let now = self.clock.timestamp();
         let oldest_ts = self.frames.front().unwrap().timestamp;
         let buffer_delay = now.saturating_sub(oldest_ts);
 
@@ -185,7 +196,7 @@ impl JitterBuffer {
     // New: Get current buffer delay
     pub fn current_delay(&self) -> u32 {
         if let Some(oldest) = self.frames.front() {
-            let now = crate::get_timestamp();
+            let now = self.clock.timestamp();
             now.saturating_sub(oldest.timestamp) as u32
         } else {
             0
@@ -197,7 +208,7 @@ impl JitterBuffer {
         let mut removed = 0;
 
         if self.has_excessive_delay() {
-            let now = crate::get_timestamp();
+            let now = self.clock.timestamp();
             let max_age = now.saturating_sub(self.max_delay_ms as u64);
 
             while let Some(oldest) = self.frames.front() {
@@ -0,0 +1,386 @@
+use crate::event::{EventSender, SessionEvent};
+use crate::media::codecs::resample::resample_mono;
+use crate::media::error::MediaResult;
+use crate::media::processor::{Processor, energy_dbfs};
+use crate::media::track::file::read_wav_file;
+use crate::{AudioFrame, PcmBuf, Sample, Samples};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+const FEATURE_SAMPLE_RATE: u32 = 16000;
+const FEATURE_FRAME_SIZE: usize = 400; // 25ms
+const FEATURE_HOP_SIZE: usize = 160; // 10ms
+const FEATURE_BANDS: usize = 16;
+const FEATURE_MIN_FREQ: f32 = 250.0;
+const FEATURE_MAX_FREQ: f32 = 3400.0;
+
+#[skip_serializing_none]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct KeywordOption {
+    /// Spotting engine, `template` by default
+    pub r#type: Option<String>,
+    /// Phrases to spot, e.g. ["operator", "agent"]
+    pub keywords: Vec<String>,
+    /// Reference recordings (wav files) for each phrase, used by the template engine
+    pub templates: HashMap<String, Vec<String>>,
+    /// Minimum score (0.0 - 1.0) to report a keyword
+    pub threshold: f32,
+    /// Energy gate for speech segmentation (in dBFS)
+    pub energy_threshold: f32,
+    /// Segments shorter than this are ignored (in ms)
+    pub min_duration: u64,
+    /// Segments longer than this are never matched, keywords are short phrases (in ms)
+    pub max_duration: u64,
+    /// Silence that closes a segment (in ms)
+    pub silence_duration: u64,
+}
+
+impl Default for KeywordOption {
+    fn default() -> Self {
+        Self {
+            r#type: None,
+            keywords: Vec::new(),
+            templates: HashMap::new(),
+            threshold: 0.6,
+            energy_threshold: -40.0,
+            min_duration: 200,
+            max_duration: 1500,
+            silence_duration: 300,
+        }
+    }
+}
+
+pub trait KeywordEngine: Send + Sync + Any {
+    /// Match a short speech segment, returns the best keyword and its score
+    fn spot(&mut self, samples: &[Sample], sample_rate: u32) -> Option<(String, f32)>;
+}
+
+struct KeywordSpotterInner {
+    segment: PcmBuf,
+    speech_end: usize,
+    segment_rate: u32,
+    segment_start: Option<u64>,
+    segment_duration: u64,
+    silence_duration: u64,
+    overflow: bool,
+}
+
+/// Keyword spotting processor, only short speech bursts are handed to the
+/// engine so phrases like "operator" can barge in without continuous ASR.
+/// The engine runs on a blocking thread, off the media path.
+pub struct KeywordSpotter {
+    engine: Arc<Mutex<Box<dyn KeywordEngine>>>,
+    event_sender: EventSender,
+    option: KeywordOption,
+    token: CancellationToken,
+    inner: Mutex<KeywordSpotterInner>,
+}
+
+/// Speech segment closed by silence, to be matched by the engine
+struct Segment {
+    samples: PcmBuf,
+    sample_rate: u32,
+    start_time: u64,
+    duration: u64,
+}
+
+impl KeywordSpotter {
+    pub fn new(
+        engine: Box<dyn KeywordEngine>,
+        token: CancellationToken,
+        event_sender: EventSender,
+        option: KeywordOption,
+    ) -> Self {
+        Self {
+            engine: Arc::new(Mutex::new(engine)),
+            event_sender,
+            option,
+            token,
+            inner: Mutex::new(KeywordSpotterInner {
+                segment: Vec::new(),
+                speech_end: 0,
+                segment_rate: FEATURE_SAMPLE_RATE,
+                segment_start: None,
+                segment_duration: 0,
+                silence_duration: 0,
+                overflow: false,
+            }),
+        }
+    }
+
+    pub fn create_template(
+        token: CancellationToken,
+        event_sender: EventSender,
+        option: KeywordOption,
+    ) -> Result<Box<dyn Processor>> {
+        let engine = TemplateKeywordEngine::from_option(&option)?;
+        Ok(Box::new(Self::new(
+            Box::new(engine),
+            token,
+            event_sender,
+            option,
+        )))
+    }
+
+    fn start_spot(&self, track_id: String, segment: Segment) {
+        let engine = self.engine.clone();
+        let event_sender = self.event_sender.clone();
+        let token = self.token.clone();
+        let threshold = self.option.threshold;
+        tokio::task::spawn_blocking(move || {
+            if token.is_cancelled() {
+                return;
+            }
+            let result = match engine.lock() {
+                Ok(mut engine) => engine.spot(&segment.samples, segment.sample_rate),
+                Err(_) => return,
+            };
+            let (keyword, score) = match result {
+                Some(result) => result,
+                None => return,
+            };
+            debug!(track_id, keyword, score, "keyword spotter result");
+            if score < threshold || token.is_cancelled() {
+                return;
+            }
+            event_sender
+                .send(SessionEvent::Keyword {
+                    track_id,
+                    timestamp: crate::get_timestamp(),
+                    keyword,
+                    score,
+                    start_time: segment.start_time,
+                    end_time: segment.start_time + segment.duration,
+                })
+                .ok();
+        });
+    }
+}
+
+impl KeywordSpotterInner {
+    /// Adds a frame to the current segment, returns the segment it closes
+    fn push_frame(&mut self, frame: &AudioFrame, option: &KeywordOption) -> Option<Segment> {
+        let samples = match &frame.samples {
+            Samples::PCM { samples } if !samples.is_empty() => samples,
+            _ => return None,
+        };
+        let sample_rate = frame.sample_rate.max(1);
+        let duration = samples.len() as u64 * 1000 / sample_rate as u64;
+        let is_speech = energy_dbfs(samples) >= option.energy_threshold;
+
+        if self.segment_start.is_none() {
+            if !is_speech {
+                return None;
+            }
+            self.segment_start = Some(frame.timestamp);
+            self.segment_rate = sample_rate;
+        }
+
+        self.segment_duration += duration;
+        if is_speech {
+            self.silence_duration = 0;
+        } else {
+            self.silence_duration += duration;
+        }
+
+        if self.segment_duration > option.max_duration + option.silence_duration {
+            // too long to be a keyword, drop the buffer and wait for silence
+            self.overflow = true;
+            self.segment.clear();
+            self.speech_end = 0;
+        }
+        if !self.overflow {
+            self.segment.extend_from_slice(samples);
+            if is_speech {
+                self.speech_end = self.segment.len();
+            }
+        }
+        if self.silence_duration < option.silence_duration {
+            return None;
+        }
+        self.finish_segment(frame.timestamp, option)
+    }
+
+    fn finish_segment(&mut self, timestamp: u64, option: &KeywordOption) -> Option<Segment> {
+        let start_time = self.segment_start.take().unwrap_or(timestamp);
+        let speech_duration = self.segment_duration.saturating_sub(self.silence_duration);
+        let mut segment = std::mem::take(&mut self.segment);
+        segment.truncate(self.speech_end);
+        self.speech_end = 0;
+        let overflow = self.overflow;
+        self.segment_duration = 0;
+        self.silence_duration = 0;
+        self.overflow = false;
+
+        if overflow
+            || speech_duration < option.min_duration
+            || speech_duration > option.max_duration
+        {
+            return None;
+        }
+        Some(Segment {
+            samples: segment,
+            sample_rate: self.segment_rate,
+            start_time,
+            duration: speech_duration,
+        })
+    }
+}
+
+impl Processor for KeywordSpotter {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
+        let segment = self.inner.lock().unwrap().push_frame(frame, &self.option);
+        if let Some(segment) = segment {
+            self.start_spot(frame.track_id.clone(), segment);
+        }
+        Ok(())
+    }
+}
+
+type Features = Vec<[f32; FEATURE_BANDS]>;
+
+/// Compute log band energies with Goertzel filters, mean normalized per band
+fn extract_features(samples: &[Sample], sample_rate: u32) -> Features {
+    let samples = if sample_rate != FEATURE_SAMPLE_RATE {
+        resample_mono(samples, sample_rate, FEATURE_SAMPLE_RATE)
+    } else {
+        samples.to_vec()
+    };
+    if samples.len() < FEATURE_FRAME_SIZE {
+        return Vec::new();
+    }
+
+    let ratio = (FEATURE_MAX_FREQ / FEATURE_MIN_FREQ).powf(1.0 / (FEATURE_BANDS - 1) as f32);
+    let coeffs: Vec<f32> = (0..FEATURE_BANDS)
+        .map(|i| {
+            let freq = FEATURE_MIN_FREQ * ratio.powi(i as i32);
+            2.0 * (2.0 * std::f32::consts::PI * freq / FEATURE_SAMPLE_RATE as f32).cos()
+        })
+        .collect();
+
+    let mut features: Features = samples
+        .windows(FEATURE_FRAME_SIZE)
+        .step_by(FEATURE_HOP_SIZE)
+        .map(|window| {
+            let mut bands = [0.0f32; FEATURE_BANDS];
+            for (band, coeff) in bands.iter_mut().zip(coeffs.iter()) {
+                let (mut s1, mut s2) = (0.0f32, 0.0f32);
+                for &sample in window {
+                    let s0 = sample as f32 / 32768.0 + coeff * s1 - s2;
+                    s2 = s1;
+                    s1 = s0;
+                }
+                let power = s1 * s1 + s2 * s2 - coeff * s1 * s2;
+                *band = (power.max(0.0) + 1e-6).ln();
+            }
+            bands
+        })
+        .collect();
+
+    let count = features.len() as f32;
+    let mut mean = [0.0f32; FEATURE_BANDS];
+    for frame in features.iter() {
+        for (m, v) in mean.iter_mut().zip(frame.iter()) {
+            *m += v / count;
+        }
+    }
+    for frame in features.iter_mut() {
+        for (v, m) in frame.iter_mut().zip(mean.iter()) {
+            *v -= m;
+        }
+    }
+    features
+}
+
+/// Dynamic time warping distance, normalized by the path length
+fn dtw_distance(a: &Features, b: &Features) -> f32 {
+    if a.is_empty() || b.is_empty() {
+        return f32::MAX;
+    }
+    let (n, m) = (a.len(), b.len());
+    let mut prev = vec![f32::MAX; m + 1];
+    let mut curr = vec![f32::MAX; m + 1];
+    prev[0] = 0.0;
+    for i in 1..=n {
+        curr[0] = f32::MAX;
+        for j in 1..=m {
+            let cost = a[i - 1]
+                .iter()
+                .zip(b[j - 1].iter())
+                .map(|(x, y)| (x - y) * (x - y))
+                .sum::<f32>()
+                .sqrt();
+            let best = prev[j].min(curr[j - 1]).min(prev[j - 1]);
+            curr[j] = cost + best;
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    prev[m] / (n + m) as f32
+}
+
+/// Matches speech segments against reference recordings of each keyword
+#[derive(Default)]
+pub struct TemplateKeywordEngine {
+    templates: Vec<(String, Features)>,
+}
+
+impl TemplateKeywordEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_option(option: &KeywordOption) -> Result<Self> {
+        let mut engine = Self::new();
+        for keyword in option.keywords.iter() {
+            let paths = match option.templates.get(keyword) {
+                Some(paths) if !paths.is_empty() => paths,
+                _ => {
+                    warn!(keyword, "keyword has no template, skipping");
+                    continue;
+                }
+            };
+            for path in paths {
+                let (samples, sample_rate) = read_wav_file(path)
+                    .map_err(|e| anyhow!("failed to load keyword template {}: {}", path, e))?;
+                engine.add_template(keyword.clone(), &samples, sample_rate);
+            }
+        }
+        if engine.templates.is_empty() {
+            return Err(anyhow!("no keyword templates configured"));
+        }
+        Ok(engine)
+    }
+
+    pub fn add_template(&mut self, keyword: String, samples: &[Sample], sample_rate: u32) {
+        let features = extract_features(samples, sample_rate);
+        if features.is_empty() {
+            warn!(keyword, "keyword template is too short, skipping");
+            return;
+        }
+        self.templates.push((keyword, features));
+    }
+}
+
+impl KeywordEngine for TemplateKeywordEngine {
+    fn spot(&mut self, samples: &[Sample], sample_rate: u32) -> Option<(String, f32)> {
+        let features = extract_features(samples, sample_rate);
+        if features.is_empty() {
+            return None;
+        }
+        self.templates
+            .iter()
+            .map(|(keyword, template)| {
+                let distance = dtw_distance(&features, template);
+                (keyword.clone(), 1.0 / (1.0 + distance))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+    }
+}
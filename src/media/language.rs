@@ -0,0 +1,239 @@
+use crate::event::{EventSender, SessionEvent};
+use crate::media::error::MediaResult;
+use crate::media::processor::{Processor, energy_dbfs};
+use crate::media::track::file::encode_wav;
+use crate::{AudioFrame, PcmBuf, Samples};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+#[skip_serializing_none]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct LanguageOption {
+    /// Identification provider, `http` by default
+    pub r#type: Option<String>,
+    /// Candidate languages, e.g. ["zh-CN", "en-US"], empty means any
+    pub languages: Vec<String>,
+    /// Speech collected before identification (in ms)
+    pub duration: u64,
+    /// Energy gate for speech frames (in dBFS)
+    pub energy_threshold: f32,
+    /// Map detected languages to ASR models, e.g. {"en-US": "16k_en"}
+    pub models: HashMap<String, String>,
+    pub endpoint: Option<String>,
+    pub secret_key: Option<String>,
+    pub secret_id: Option<String>,
+}
+
+impl Default for LanguageOption {
+    fn default() -> Self {
+        Self {
+            r#type: None,
+            languages: Vec::new(),
+            duration: 3000,
+            energy_threshold: -40.0,
+            models: HashMap::new(),
+            endpoint: None,
+            secret_key: None,
+            secret_id: None,
+        }
+    }
+}
+
+#[async_trait]
+pub trait LanguageIdentifier: Send + Sync {
+    /// Identify the spoken language, returns the language code and its confidence
+    async fn identify(
+        &self,
+        samples: PcmBuf,
+        sample_rate: u32,
+        candidates: &[String],
+    ) -> Result<(String, f32)>;
+}
+
+struct LanguageDetectorInner {
+    samples: PcmBuf,
+    sample_rate: u32,
+    speech_duration: u64,
+    finished: bool,
+}
+
+/// Collects the first seconds of speech on a track and identifies its
+/// language once, the result is emitted as a `languageDetected` event.
+pub struct LanguageDetector {
+    identifier: Arc<dyn LanguageIdentifier>,
+    event_sender: EventSender,
+    option: LanguageOption,
+    token: CancellationToken,
+    inner: Mutex<LanguageDetectorInner>,
+}
+
+impl LanguageDetector {
+    pub fn new(
+        identifier: Arc<dyn LanguageIdentifier>,
+        token: CancellationToken,
+        event_sender: EventSender,
+        option: LanguageOption,
+    ) -> Self {
+        Self {
+            identifier,
+            event_sender,
+            option,
+            token,
+            inner: Mutex::new(LanguageDetectorInner {
+                samples: Vec::new(),
+                sample_rate: 16000,
+                speech_duration: 0,
+                finished: false,
+            }),
+        }
+    }
+
+    pub fn create_http(
+        token: CancellationToken,
+        event_sender: EventSender,
+        option: LanguageOption,
+    ) -> Result<Box<dyn Processor>> {
+        let identifier = HttpLanguageIdentifier::new(&option)?;
+        Ok(Box::new(Self::new(
+            Arc::new(identifier),
+            token,
+            event_sender,
+            option,
+        )))
+    }
+
+    fn start_identify(&self, track_id: String, samples: PcmBuf, sample_rate: u32, duration: u64) {
+        let identifier = self.identifier.clone();
+        let event_sender = self.event_sender.clone();
+        let token = self.token.clone();
+        let languages = self.option.languages.clone();
+        let models = self.option.models.clone();
+        tokio::spawn(async move {
+            let result = tokio::select! {
+                _ = token.cancelled() => return,
+                result = identifier.identify(samples, sample_rate, &languages) => result,
+            };
+            match result {
+                Ok((language, confidence)) => {
+                    info!(track_id, language, confidence, "language detected");
+                    let model = models.get(&language).cloned();
+                    event_sender
+                        .send(SessionEvent::LanguageDetected {
+                            track_id,
+                            timestamp: crate::get_timestamp(),
+                            language,
+                            confidence,
+                            duration,
+                            model,
+                        })
+                        .ok();
+                }
+                Err(e) => {
+                    warn!(track_id, "language identification failed: {}", e);
+                    event_sender
+                        .send(SessionEvent::Error {
+                            track_id,
+                            timestamp: crate::get_timestamp(),
+                            sender: "language".to_string(),
+                            error: e.to_string(),
+                            code: None,
+                        })
+                        .ok();
+                }
+            }
+        });
+    }
+}
+
+impl Processor for LanguageDetector {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
+        let samples = match &frame.samples {
+            Samples::PCM { samples } if !samples.is_empty() => samples,
+            _ => return Ok(()),
+        };
+        let mut inner = self.inner.lock().unwrap();
+        if inner.finished || energy_dbfs(samples) < self.option.energy_threshold {
+            return Ok(());
+        }
+        inner.sample_rate = frame.sample_rate;
+        inner.samples.extend_from_slice(samples);
+        inner.speech_duration += samples.len() as u64 * 1000 / frame.sample_rate.max(1) as u64;
+        if inner.speech_duration < self.option.duration {
+            return Ok(());
+        }
+        inner.finished = true;
+        let samples = std::mem::take(&mut inner.samples);
+        let (sample_rate, duration) = (inner.sample_rate, inner.speech_duration);
+        drop(inner);
+        self.start_identify(frame.track_id.clone(), samples, sample_rate, duration);
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct HttpLanguageResponse {
+    language: String,
+    #[serde(default)]
+    confidence: f32,
+}
+
+/// Posts the collected speech as a wav file to `endpoint`, expecting
+/// `{"language": "en-US", "confidence": 0.9}` in response.
+pub struct HttpLanguageIdentifier {
+    client: reqwest::Client,
+    endpoint: String,
+    secret_id: Option<String>,
+    secret_key: Option<String>,
+}
+
+impl HttpLanguageIdentifier {
+    pub fn new(option: &LanguageOption) -> Result<Self> {
+        let endpoint = option
+            .endpoint
+            .clone()
+            .ok_or_else(|| anyhow!("language identification endpoint is required"))?;
+        Ok(Self {
+            client: reqwest::Client::new(),
+            endpoint,
+            secret_id: option.secret_id.clone(),
+            secret_key: option.secret_key.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl LanguageIdentifier for HttpLanguageIdentifier {
+    async fn identify(
+        &self,
+        samples: PcmBuf,
+        sample_rate: u32,
+        candidates: &[String],
+    ) -> Result<(String, f32)> {
+        let wav = encode_wav(&samples, sample_rate)?;
+        let mut request = self
+            .client
+            .post(&self.endpoint)
+            .header("Content-Type", "audio/wav")
+            .body(wav);
+        if !candidates.is_empty() {
+            request = request.query(&[("languages", candidates.join(","))]);
+        }
+        if let Some(secret_id) = &self.secret_id {
+            request = request.header("X-Secret-Id", secret_id);
+        }
+        if let Some(secret_key) = &self.secret_key {
+            request = request.header("Authorization", format!("Bearer {}", secret_key));
+        }
+        let response = request.send().await?.error_for_status()?;
+        let result: HttpLanguageResponse = response.json().await?;
+        Ok((result.language, result.confidence))
+    }
+}
@@ -0,0 +1,220 @@
+use crate::media::processor::energy_dbfs;
+use crate::{AudioFrame, Sample, Samples, TrackId};
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+use std::time::{Duration, Instant};
+
+const MARKER_FREQUENCY: f32 = 1800.0;
+const MARKER_DURATION_MS: u64 = 60;
+const MARKER_AMPLITUDE: f32 = 16000.0;
+/// Share of the frame energy at the marker frequency for a detection
+const DETECT_RATIO: f32 = 0.6;
+const DETECT_MIN_DBFS: f32 = -40.0;
+
+/// Latency contributions measured by a [`LatencyProbe`] (in ms)
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyReport {
+    /// Estimated one-way latency: half the network round trip plus local contributions
+    pub mouth_to_ear: f32,
+    /// From sending the marker until it was received back
+    pub round_trip: f32,
+    /// Round trip spent outside this process (network and far end)
+    pub network: f32,
+    /// Time the marker waited in the jitter buffer
+    pub jitter_buffer: f32,
+    /// Decoding and processor chain time
+    pub processing: f32,
+    /// Encoding and sending time
+    pub encode: f32,
+    /// Where the marker went, in order
+    pub hops: Vec<LatencyHop>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum LatencyHopKind {
+    /// Injected into the audio sent to the measured leg
+    Sent,
+    /// Received from a leg, the measured one looping it back or a peer leg
+    Received,
+    /// Passed on from the receiving leg to a leg bridged with it
+    Forwarded,
+}
+
+/// One hop of the marker through the call
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyHop {
+    pub track_id: TrackId,
+    pub kind: LatencyHopKind,
+    /// Since the marker was sent (in ms)
+    pub at: f32,
+    /// Since the previous hop (in ms)
+    pub delta: f32,
+}
+
+/// Measures the latency of a leg: a marker tone is injected into the audio
+/// sent to the track, and detected when it arrives from any leg, the track
+/// itself when its far end loops it back or a peer leg bridged behind it.
+/// The arrival is followed to the legs it is forwarded to, each hop is
+/// reported. Resolution is one packet time.
+pub struct LatencyProbe {
+    track_id: TrackId,
+    created_at: u64,
+    timeout: Duration,
+    sent_at: Option<u64>,
+    /// Monotonic twin of `sent_at`, times the hops below a millisecond
+    sent: Option<Instant>,
+    injected: usize,
+    phase: f32,
+    encode: Duration,
+    encoded_frames: u32,
+    arrival: Option<LatencyReport>,
+}
+
+impl LatencyProbe {
+    pub fn new(track_id: TrackId, timeout: Duration) -> Self {
+        Self {
+            track_id,
+            created_at: crate::get_timestamp(),
+            timeout,
+            sent_at: None,
+            sent: None,
+            injected: 0,
+            phase: 0.0,
+            encode: Duration::ZERO,
+            encoded_frames: 0,
+            arrival: None,
+        }
+    }
+
+    pub fn track_id(&self) -> &TrackId {
+        &self.track_id
+    }
+
+    pub fn is_expired(&self) -> bool {
+        crate::get_timestamp() >= self.created_at + self.timeout.as_millis() as u64
+    }
+
+    /// Replace an outgoing PCM frame with the marker, returns true when it did
+    pub fn inject(&mut self, frame: &mut AudioFrame) -> bool {
+        let samples = match &mut frame.samples {
+            Samples::PCM { samples } => samples,
+            _ => return false,
+        };
+        let sample_rate = frame.sample_rate.max(1);
+        let total = (sample_rate as u64 * MARKER_DURATION_MS / 1000) as usize;
+        if self.injected >= total {
+            return false;
+        }
+        let step = 2.0 * PI * MARKER_FREQUENCY / sample_rate as f32;
+        for sample in samples.iter_mut() {
+            *sample = (self.phase.sin() * MARKER_AMPLITUDE) as Sample;
+            self.phase = (self.phase + step) % (2.0 * PI);
+        }
+        self.injected += samples.len();
+        self.sent_at.get_or_insert_with(crate::get_timestamp);
+        self.sent.get_or_insert_with(Instant::now);
+        true
+    }
+
+    /// Record the time spent encoding and sending an injected frame
+    pub fn on_encoded(&mut self, elapsed: Duration) {
+        self.encode += elapsed;
+        self.encoded_frames += 1;
+    }
+
+    /// Look for the marker in a frame received from `frame.track_id`,
+    /// `processing` is the time the track's processor chain spent on it.
+    /// Returns true on its arrival, the forwarding hops are recorded next.
+    pub fn detect(&mut self, frame: &AudioFrame, processing: Duration) -> bool {
+        let (Some(sent_at), Some(sent)) = (self.sent_at, self.sent) else {
+            return false;
+        };
+        if self.arrival.is_some() {
+            return false;
+        }
+        let samples = match &frame.samples {
+            Samples::PCM { samples } if !samples.is_empty() => samples,
+            _ => return false,
+        };
+        if energy_dbfs(samples) < DETECT_MIN_DBFS
+            || tone_ratio(samples, frame.sample_rate, MARKER_FREQUENCY) < DETECT_RATIO
+        {
+            return false;
+        }
+        let now = crate::get_timestamp();
+        let encode = match self.encoded_frames {
+            0 => 0.0,
+            n => self.encode.as_secs_f32() * 1000.0 / n as f32,
+        };
+        let processing = processing.as_secs_f32() * 1000.0;
+        let round_trip = frame.timestamp.saturating_sub(sent_at) as f32;
+        let network = (round_trip - encode).max(0.0);
+        let jitter_buffer = (now.saturating_sub(frame.timestamp) as f32 - processing).max(0.0);
+        let received = sent.elapsed().as_secs_f32() * 1000.0;
+        self.arrival = Some(LatencyReport {
+            mouth_to_ear: network / 2.0 + jitter_buffer + processing + encode,
+            round_trip,
+            network,
+            jitter_buffer,
+            processing,
+            encode,
+            hops: vec![
+                LatencyHop {
+                    track_id: self.track_id.clone(),
+                    kind: LatencyHopKind::Sent,
+                    at: 0.0,
+                    delta: 0.0,
+                },
+                LatencyHop {
+                    track_id: frame.track_id.clone(),
+                    kind: LatencyHopKind::Received,
+                    at: received,
+                    delta: received,
+                },
+            ],
+        });
+        true
+    }
+
+    /// Record the arrived marker being sent on to a bridged leg
+    pub fn on_forwarded(&mut self, track_id: &TrackId) {
+        let (Some(sent), Some(arrival)) = (self.sent, self.arrival.as_mut()) else {
+            return;
+        };
+        let at = sent.elapsed().as_secs_f32() * 1000.0;
+        let previous = arrival.hops.last().map(|hop| hop.at).unwrap_or_default();
+        arrival.hops.push(LatencyHop {
+            track_id: track_id.clone(),
+            kind: LatencyHopKind::Forwarded,
+            at,
+            delta: at - previous,
+        });
+    }
+
+    /// The report once the marker arrived and was forwarded
+    pub fn finish(&mut self) -> Option<LatencyReport> {
+        self.arrival.take()
+    }
+}
+
+/// Share of the energy of `samples` at `frequency`, 1.0 for a pure tone
+fn tone_ratio(samples: &[Sample], sample_rate: u32, frequency: f32) -> f32 {
+    let coeff = 2.0 * (2.0 * PI * frequency / sample_rate as f32).cos();
+    let (mut s1, mut s2) = (0.0f32, 0.0f32);
+    let mut energy = 0.0f32;
+    for &sample in samples {
+        let x = sample as f32;
+        let s0 = x + coeff * s1 - s2;
+        s2 = s1;
+        s1 = s0;
+        energy += x * x;
+    }
+    if energy <= 0.0 {
+        return 0.0;
+    }
+    let power = s1 * s1 + s2 * s2 - coeff * s1 * s2;
+    power / (energy * samples.len() as f32 / 2.0)
+}
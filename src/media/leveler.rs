@@ -0,0 +1,119 @@
+//! Matches the loudness of the legs of a bridge.
+//!
+//! Carriers deliver audio at very different levels, and the agent on a
+//! quiet one keeps turning their headset up and down. The [`LevelMatcher`]
+//! tracks the long-term speech level of each leg and gains every leg
+//! towards a common target, slowly enough not to pump with the speech.
+use crate::media::processor::{Processor, energy_dbfs};
+use crate::media::stream::GainProcessor;
+use crate::{AudioFrame, Samples, TrackId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How fast the applied gain follows the levels (in dB/s)
+const SLEW_RATE: f32 = 6.0;
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct LevelMatchOption {
+    /// Speech level all legs are gained towards (in dBFS), the mean level
+    /// of the legs when None
+    pub target: Option<f32>,
+    /// Largest gain or attenuation applied to a leg (in dB)
+    pub max_gain: f32,
+    /// Time over which the speech level is averaged (in ms)
+    pub window: u32,
+    /// Frames below this level don't count as speech (in dBFS)
+    pub speech_threshold: f32,
+}
+
+impl Default for LevelMatchOption {
+    fn default() -> Self {
+        Self {
+            target: None,
+            max_gain: 12.0,
+            window: 10000,
+            speech_threshold: -50.0,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LegLevel {
+    /// Long-term speech level (in dBFS), None before the first speech
+    level: Option<f32>,
+    /// Gain currently applied (in dB)
+    gain: f32,
+}
+
+pub struct LevelMatcher {
+    option: LevelMatchOption,
+    legs: HashMap<TrackId, LegLevel>,
+}
+
+impl LevelMatcher {
+    pub fn new(option: LevelMatchOption) -> Self {
+        Self {
+            option,
+            legs: HashMap::new(),
+        }
+    }
+
+    /// The long-term speech level of a leg (in dBFS)
+    pub fn level(&self, track_id: &TrackId) -> Option<f32> {
+        self.legs.get(track_id).and_then(|leg| leg.level)
+    }
+
+    /// The gain applied to a leg (in dB)
+    pub fn gain(&self, track_id: &TrackId) -> f32 {
+        self.legs.get(track_id).map(|leg| leg.gain).unwrap_or(0.0)
+    }
+
+    pub fn remove(&mut self, track_id: &TrackId) {
+        self.legs.remove(track_id);
+    }
+
+    /// Measures a frame received from a leg and applies the leg's gain
+    pub fn process(&mut self, frame: &mut AudioFrame) {
+        let samples = match &frame.samples {
+            Samples::PCM { samples } if !samples.is_empty() => samples,
+            _ => return,
+        };
+        let duration = samples.len() as f32 * 1000.0 / frame.sample_rate.max(1) as f32;
+        let energy = energy_dbfs(samples);
+        let leg = self.legs.entry(frame.track_id.clone()).or_default();
+        if energy >= self.option.speech_threshold {
+            let alpha = (duration / self.option.window.max(1) as f32).min(1.0);
+            leg.level = Some(match leg.level {
+                Some(level) => level + (energy - level) * alpha,
+                None => energy,
+            });
+        }
+
+        let desired = match (self.target(), self.level(&frame.track_id)) {
+            (Some(target), Some(level)) => {
+                (target - level).clamp(-self.option.max_gain, self.option.max_gain)
+            }
+            _ => 0.0,
+        };
+        let leg = self.legs.get_mut(&frame.track_id).unwrap();
+        let step = SLEW_RATE * duration / 1000.0;
+        leg.gain += (desired - leg.gain).clamp(-step, step);
+        if leg.gain != 0.0 {
+            GainProcessor::new(leg.gain).process_frame(frame).ok();
+        }
+    }
+
+    /// Nothing to match before two legs were heard, unless the target is fixed
+    fn target(&self) -> Option<f32> {
+        if self.option.target.is_some() {
+            return self.option.target;
+        }
+        let levels: Vec<f32> = self.legs.values().filter_map(|leg| leg.level).collect();
+        if levels.len() < 2 {
+            return None;
+        }
+        Some(levels.iter().sum::<f32>() / levels.len() as f32)
+    }
+}
@@ -0,0 +1,163 @@
+use crate::event::{EventSender, SessionEvent};
+use crate::media::error::MediaResult;
+use crate::media::processor::Processor;
+use crate::{AudioFrame, Samples};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Level reported for an interval without samples (in dBFS)
+pub const SILENCE_DBFS: f32 = -100.0;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct MeterOption {
+    /// Report interval (in ms)
+    pub interval: u64,
+}
+
+impl Default for MeterOption {
+    fn default() -> Self {
+        Self { interval: 100 }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioLevel {
+    /// RMS level of the interval (in dBFS)
+    pub rms: f32,
+    /// Highest sample of the interval (in dBFS)
+    pub peak: f32,
+    pub start_time: u64,
+    pub end_time: u64,
+}
+
+fn to_dbfs(amplitude: f64) -> f32 {
+    if amplitude <= 0.0 {
+        return SILENCE_DBFS;
+    }
+    ((20.0 * (amplitude / 32768.0).log10()) as f32).max(SILENCE_DBFS)
+}
+
+/// The latest level of each metered track, shared with the code that acts
+/// on it (silence detection, active speaker)
+#[derive(Clone, Debug, Default)]
+pub struct TrackLevels {
+    levels: Arc<Mutex<HashMap<String, AudioLevel>>>,
+}
+
+impl TrackLevels {
+    pub fn get(&self, track_id: &str) -> Option<AudioLevel> {
+        self.levels.lock().unwrap().get(track_id).cloned()
+    }
+
+    /// The latest level of every track
+    pub fn all(&self) -> Vec<(String, AudioLevel)> {
+        self.levels
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(track_id, level)| (track_id.clone(), level.clone()))
+            .collect()
+    }
+
+    /// The track with the highest RMS level above `threshold` (in dBFS)
+    pub fn loudest(&self, threshold: f32) -> Option<String> {
+        self.levels
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, level)| level.rms >= threshold)
+            .max_by(|a, b| a.1.rms.total_cmp(&b.1.rms))
+            .map(|(track_id, _)| track_id.clone())
+    }
+
+    pub fn update(&self, track_id: &str, level: AudioLevel) {
+        self.levels
+            .lock()
+            .unwrap()
+            .insert(track_id.to_string(), level);
+    }
+}
+
+#[derive(Default)]
+struct MeterWindow {
+    start_time: Option<u64>,
+    /// Samples so far, in ms worth of the track's rate
+    duration: u64,
+    squares: f64,
+    samples: u64,
+    peak: i32,
+}
+
+/// Measures the RMS and peak level of each track every `interval` and
+/// emits them as `level` events, e.g. for VU meters.
+pub struct LevelMeter {
+    event_sender: EventSender,
+    option: MeterOption,
+    levels: TrackLevels,
+    windows: Mutex<HashMap<String, MeterWindow>>,
+}
+
+impl LevelMeter {
+    pub fn new(event_sender: EventSender, option: MeterOption) -> Self {
+        Self {
+            event_sender,
+            option,
+            levels: TrackLevels::default(),
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_levels(mut self, levels: TrackLevels) -> Self {
+        self.levels = levels;
+        self
+    }
+
+    pub fn levels(&self) -> TrackLevels {
+        self.levels.clone()
+    }
+}
+
+impl Processor for LevelMeter {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
+        let samples = match &frame.samples {
+            Samples::PCM { samples } if !samples.is_empty() => samples,
+            _ => return Ok(()),
+        };
+        let sample_rate = frame.sample_rate.max(1) as u64;
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows.entry(frame.track_id.clone()).or_default();
+        window.start_time.get_or_insert(frame.timestamp);
+        for &sample in samples.iter() {
+            window.squares += sample as f64 * sample as f64;
+            window.peak = window.peak.max((sample as i32).abs());
+        }
+        window.samples += samples.len() as u64;
+        window.duration = window.samples * 1000 / sample_rate;
+        if window.duration < self.option.interval.max(1) {
+            return Ok(());
+        }
+
+        let window = std::mem::take(window);
+        drop(windows);
+        let start_time = window.start_time.unwrap_or(frame.timestamp);
+        let level = AudioLevel {
+            rms: to_dbfs((window.squares / window.samples as f64).sqrt()),
+            peak: to_dbfs(window.peak as f64),
+            start_time,
+            end_time: start_time + window.duration,
+        };
+        self.levels.update(&frame.track_id, level.clone());
+        self.event_sender
+            .send(SessionEvent::Level {
+                track_id: frame.track_id.clone(),
+                timestamp: crate::get_timestamp(),
+                level,
+            })
+            .ok();
+        Ok(())
+    }
+}
@@ -0,0 +1,459 @@
+use crate::media::codecs::resample::resample_mono;
+use crate::media::meter::{SILENCE_DBFS, TrackLevels};
+use crate::{AudioFrame, PcmBuf, Sample, Samples, TrackId};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Sources silent for longer than this no longer take part in the mix (in ms)
+const SOURCE_TIMEOUT_MS: u64 = 200;
+/// Max audio buffered per source and destination (in ms)
+const MAX_PENDING_MS: usize = 200;
+/// How often the loudest sources are picked again (in ms)
+const RESELECT_MS: u64 = 20;
+/// Every how many samples the level of a source is estimated from
+const ESTIMATE_STRIDE: usize = 4;
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SuperviseMode {
+    /// supervisor hears both parties, nobody hears the supervisor
+    Listen,
+    /// supervisor is mixed only into the agent's ear
+    Whisper,
+    /// supervisor is mixed into both parties
+    Barge,
+}
+
+/// Attenuation of the primary stream while a ducking source is active
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct DuckingOption {
+    /// Gain applied to the primary stream while ducked (in dB)
+    pub level: f32,
+    /// Time to reach `level` (in ms)
+    pub attack: u32,
+    /// Time to recover after the ducking source stops (in ms)
+    pub release: u32,
+}
+
+impl Default for DuckingOption {
+    fn default() -> Self {
+        Self {
+            level: -12.0,
+            attack: 50,
+            release: 300,
+        }
+    }
+}
+
+/// Mixes only the loudest sources, for rooms with many participants
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct TopSpeakersOption {
+    /// Sources mixed at once
+    pub max_speakers: usize,
+    /// Time a source stays mixed after it dropped out of the loudest, so
+    /// that speakers don't flap in and out (in ms)
+    pub hold: u64,
+}
+
+impl Default for TopSpeakersOption {
+    fn default() -> Self {
+        Self {
+            max_speakers: 3,
+            hold: 400,
+        }
+    }
+}
+
+#[derive(Default)]
+struct SelectionState {
+    option: Option<TopSpeakersOption>,
+    /// Level (in dBov) and time of the sources reporting none, from their
+    /// decoded audio
+    estimates: HashMap<TrackId, (f32, u64)>,
+    /// When each source was last among the loudest
+    selected: HashMap<TrackId, u64>,
+    updated_at: Option<u64>,
+}
+
+/// Picks the sources the mixer takes, by the levels they report in their
+/// audio level extensions or else an estimate from their decoded audio.
+/// Shared with the processor chains of the tracks, which discard the audio
+/// of a source not picked instead of decoding it when its level is reported.
+#[derive(Clone, Default)]
+pub struct SpeakerSelection {
+    levels: TrackLevels,
+    state: Arc<Mutex<SelectionState>>,
+}
+
+impl SpeakerSelection {
+    pub fn new(levels: TrackLevels) -> Self {
+        Self {
+            levels,
+            state: Arc::new(Mutex::new(SelectionState::default())),
+        }
+    }
+
+    /// Mix the loudest sources only, None mixes them all
+    pub fn set_option(&self, option: Option<TopSpeakersOption>) {
+        *self.state.lock().unwrap() = SelectionState {
+            option,
+            ..Default::default()
+        };
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.lock().unwrap().option.is_some()
+    }
+
+    /// The level `track_id` reported recently, e.g. in its audio level
+    /// extension
+    fn reported(&self, track_id: &str, now: u64) -> Option<f32> {
+        self.levels
+            .get(track_id)
+            .filter(|level| now.saturating_sub(level.end_time) <= SOURCE_TIMEOUT_MS)
+            .map(|level| level.rms)
+    }
+
+    /// Whether the audio of `track_id` has to be decoded: it is mixed, or
+    /// its level can only be told from the audio
+    pub fn needs_decode(&self, track_id: &str, now: u64) -> bool {
+        !self.is_enabled() || self.reported(track_id, now).is_none() || self.is_mixed(track_id, now)
+    }
+
+    /// Estimates the level of a source that reports none, from every few
+    /// of its samples
+    pub fn estimate(&self, track_id: &TrackId, samples: &[Sample], now: u64) {
+        if !self.is_enabled() || self.reported(track_id, now).is_some() {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        if state
+            .estimates
+            .get(track_id)
+            .is_some_and(|(_, at)| *at == now)
+        {
+            return;
+        }
+        let (squares, count) = samples
+            .iter()
+            .step_by(ESTIMATE_STRIDE)
+            .fold((0f64, 0usize), |(squares, count), &s| {
+                (squares + s as f64 * s as f64, count + 1)
+            });
+        let rms = (squares / count.max(1) as f64).sqrt();
+        let level = match rms < 1.0 {
+            true => SILENCE_DBFS,
+            false => (20.0 * (rms / 32768.0).log10()) as f32,
+        };
+        state.estimates.insert(track_id.clone(), (level, now));
+    }
+
+    /// Whether `track_id` is among the loudest sources, or was within the
+    /// hold time. All are while the selection is disabled.
+    pub fn is_mixed(&self, track_id: &str, now: u64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let Some(option) = state.option.clone() else {
+            return true;
+        };
+        if state
+            .updated_at
+            .is_none_or(|at| now.saturating_sub(at) >= RESELECT_MS)
+        {
+            self.reselect(&mut state, &option, now);
+        }
+        state
+            .selected
+            .get(track_id)
+            .is_some_and(|at| now.saturating_sub(*at) <= option.hold)
+    }
+
+    fn reselect(&self, state: &mut SelectionState, option: &TopSpeakersOption, now: u64) {
+        state
+            .estimates
+            .retain(|_, (_, at)| now.saturating_sub(*at) <= SOURCE_TIMEOUT_MS);
+        state
+            .selected
+            .retain(|_, at| now.saturating_sub(*at) <= option.hold);
+        let mut candidates: HashMap<TrackId, f32> = state
+            .estimates
+            .iter()
+            .map(|(id, (level, _))| (id.clone(), *level))
+            .collect();
+        for (id, level) in self.levels.all() {
+            if now.saturating_sub(level.end_time) <= SOURCE_TIMEOUT_MS {
+                candidates.insert(id, level.rms);
+            }
+        }
+        let mut candidates = candidates.into_iter().collect::<Vec<_>>();
+        candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+        for (id, _) in candidates.into_iter().take(option.max_speakers) {
+            state.selected.insert(id, now);
+        }
+        state.updated_at = Some(now);
+    }
+}
+
+struct PendingAudio {
+    samples: VecDeque<Sample>,
+    sample_rate: u32,
+    last_seen: u64,
+}
+
+#[derive(Default)]
+struct Destination {
+    /// the source that paces frames towards this destination, and when it was last seen
+    clock: Option<(TrackId, u64)>,
+    pending: HashMap<TrackId, PendingAudio>,
+    /// current ducking gain (in dB) and the option it ramps with
+    duck_level: f32,
+    ducking: Option<DuckingOption>,
+}
+
+/// Per-destination routing and mixing of track audio.
+///
+/// By default every track hears every other track. When more than one source
+/// is active towards a destination, the first one paces the output and the
+/// others are buffered and summed into its frames, so an RTP peer never gets
+/// interleaved streams.
+///
+/// Sources configured with [`DuckingOption`] (prompts, a whispering
+/// supervisor) attenuate everything else they are mixed with while active.
+#[derive(Default)]
+pub struct MediaMixer {
+    routes: HashMap<TrackId, HashSet<TrackId>>,
+    destinations: HashMap<TrackId, Destination>,
+    ducking: HashMap<TrackId, DuckingOption>,
+    /// The sources mixed, by their latest levels
+    speakers: SpeakerSelection,
+}
+
+impl MediaMixer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_levels(mut self, levels: TrackLevels) -> Self {
+        self.speakers = SpeakerSelection::new(levels);
+        self
+    }
+
+    /// Mix only the loudest sources towards each destination, None mixes
+    /// them all
+    pub fn set_top_speakers(&mut self, option: Option<TopSpeakersOption>) {
+        self.speakers.set_option(option);
+    }
+
+    /// The selection of the sources mixed, for the tracks' processor chains
+    pub fn speakers(&self) -> SpeakerSelection {
+        self.speakers.clone()
+    }
+
+    /// Latest level of each source, e.g. from their audio level extensions
+    pub fn levels(&self) -> TrackLevels {
+        self.speakers.levels.clone()
+    }
+
+    /// The loudest source above `threshold` (in dBov), by the levels the
+    /// sources reported
+    pub fn active_speaker(&self, threshold: f32) -> Option<TrackId> {
+        self.speakers.levels.loudest(threshold)
+    }
+
+    /// Restrict the destinations of `source`, None restores the default
+    pub fn set_route(&mut self, source: &TrackId, destinations: Option<HashSet<TrackId>>) {
+        match destinations {
+            Some(destinations) => {
+                for (id, destination) in self.destinations.iter_mut() {
+                    if !destinations.contains(id) {
+                        destination.remove_source(source);
+                    }
+                }
+                self.routes.insert(source.clone(), destinations);
+            }
+            None => {
+                self.routes.remove(source);
+            }
+        }
+    }
+
+    pub fn is_routed(&self, source: &TrackId, destination: &TrackId) -> bool {
+        if source == destination {
+            return false;
+        }
+        match self.routes.get(source) {
+            Some(destinations) => destinations.contains(destination),
+            None => true,
+        }
+    }
+
+    pub fn supervise(&mut self, supervisor: &TrackId, agent: &TrackId, mode: SuperviseMode) {
+        let destinations = match mode {
+            SuperviseMode::Listen => Some(HashSet::new()),
+            SuperviseMode::Whisper => Some(HashSet::from([agent.clone()])),
+            SuperviseMode::Barge => None,
+        };
+        self.set_route(supervisor, destinations);
+    }
+
+    /// Make `source` duck the other sources while it is active. This is kept
+    /// when the track is replaced, so each prompt played on it ducks too.
+    pub fn set_ducking(&mut self, source: &TrackId, option: Option<DuckingOption>) {
+        match option {
+            Some(option) => {
+                self.ducking.insert(source.clone(), option);
+            }
+            None => {
+                self.ducking.remove(source);
+            }
+        }
+    }
+
+    pub fn remove_track(&mut self, id: &TrackId) {
+        self.routes.remove(id);
+        self.destinations.remove(id);
+        for destination in self.destinations.values_mut() {
+            destination.remove_source(id);
+        }
+    }
+
+    /// Returns the frame to send to `destination`, None when the packet was
+    /// buffered to be mixed into the next frame of the pacing source.
+    pub fn mix(&mut self, packet: &AudioFrame, destination: &TrackId) -> Option<AudioFrame> {
+        let now = crate::get_timestamp();
+        let samples = match &packet.samples {
+            Samples::PCM { samples } => samples,
+            // discarded, e.g. the audio of a source not among the loudest
+            Samples::Empty => return None,
+            // encoded payloads (e.g. dtmf) are forwarded untouched, whoever
+            // the loudest sources are
+            Samples::RTP { .. } => return Some(packet.clone()),
+        };
+        self.speakers.estimate(&packet.track_id, samples, now);
+        if !self.speakers.is_mixed(&packet.track_id, now) {
+            // not among the loudest, a source that is paces the destination
+            if let Some(dest) = self.destinations.get_mut(destination) {
+                dest.remove_source(&packet.track_id);
+            }
+            return None;
+        }
+        let dest = self.destinations.entry(destination.clone()).or_default();
+        dest.pending.retain(|id, p| {
+            id == &packet.track_id || now.saturating_sub(p.last_seen) <= SOURCE_TIMEOUT_MS
+        });
+
+        let is_clock = match &dest.clock {
+            Some((id, _)) if id == &packet.track_id => true,
+            Some((_, last_seen)) if now.saturating_sub(*last_seen) <= SOURCE_TIMEOUT_MS => false,
+            _ => {
+                dest.pending.remove(&packet.track_id);
+                true
+            }
+        };
+
+        if !is_clock {
+            let max_pending = MAX_PENDING_MS * packet.sample_rate as usize / 1000;
+            let pending = dest
+                .pending
+                .entry(packet.track_id.clone())
+                .or_insert_with(|| PendingAudio {
+                    samples: VecDeque::new(),
+                    sample_rate: packet.sample_rate,
+                    last_seen: now,
+                });
+            if pending.sample_rate != packet.sample_rate {
+                pending.samples.clear();
+                pending.sample_rate = packet.sample_rate;
+            }
+            pending.last_seen = now;
+            pending.samples.extend(samples.iter());
+            while pending.samples.len() > max_pending {
+                pending.samples.pop_front();
+            }
+            return None;
+        }
+
+        dest.clock = Some((packet.track_id.clone(), now));
+        let active = dest
+            .pending
+            .keys()
+            .chain(std::iter::once(&packet.track_id))
+            .find_map(|id| self.ducking.get(id));
+        if let Some(option) = active {
+            dest.ducking = Some(option.clone());
+        }
+        if dest.pending.is_empty() && dest.duck_level == 0.0 {
+            return Some(packet.clone());
+        }
+
+        // primary audio gets ducked, audio from ducking sources is added on top
+        let mut primary = vec![0i32; samples.len()];
+        let mut secondary = vec![0i32; samples.len()];
+        let target = match self.ducking.contains_key(&packet.track_id) {
+            true => &mut secondary,
+            false => &mut primary,
+        };
+        for (m, s) in target.iter_mut().zip(samples.iter()) {
+            *m += *s as i32;
+        }
+        for (id, pending) in dest.pending.iter_mut() {
+            let needed = samples.len() * pending.sample_rate as usize / packet.sample_rate as usize;
+            let take = needed.min(pending.samples.len());
+            let chunk: PcmBuf = pending.samples.drain(..take).collect();
+            let chunk = if pending.sample_rate != packet.sample_rate {
+                resample_mono(&chunk, pending.sample_rate, packet.sample_rate)
+            } else {
+                chunk
+            };
+            let target = match self.ducking.contains_key(id) {
+                true => &mut secondary,
+                false => &mut primary,
+            };
+            for (m, s) in target.iter_mut().zip(chunk.iter()) {
+                *m += *s as i32;
+            }
+        }
+
+        let option = dest.ducking.clone().unwrap_or_default();
+        let target_level = match active {
+            Some(_) => option.level.min(0.0),
+            None => 0.0,
+        };
+        let samples_per_ms = packet.sample_rate.max(1000) as f32 / 1000.0;
+        let attack_step = -option.level / (option.attack.max(1) as f32 * samples_per_ms);
+        let release_step = -option.level / (option.release.max(1) as f32 * samples_per_ms);
+        let mut gain = 10f32.powf(dest.duck_level / 20.0);
+        let mixed = primary
+            .iter()
+            .zip(secondary.iter())
+            .map(|(&p, &s)| {
+                if dest.duck_level != target_level {
+                    dest.duck_level = if dest.duck_level > target_level {
+                        (dest.duck_level - attack_step).max(target_level)
+                    } else {
+                        (dest.duck_level + release_step).min(target_level)
+                    };
+                    gain = 10f32.powf(dest.duck_level / 20.0);
+                }
+                let v = p as f32 * gain + s as f32;
+                v.clamp(Sample::MIN as f32, Sample::MAX as f32) as Sample
+            })
+            .collect();
+        let mut frame = packet.clone();
+        frame.samples = Samples::PCM { samples: mixed };
+        Some(frame)
+    }
+}
+
+impl Destination {
+    fn remove_source(&mut self, source: &TrackId) {
+        self.pending.remove(source);
+        if self.clock.as_ref().map(|(id, _)| id) == Some(source) {
+            self.clock = None;
+        }
+    }
+}
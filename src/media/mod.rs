@@ -1,15 +1,33 @@
 pub mod asr_processor;
+pub mod audio_level;
 pub mod cache;
+pub mod clock;
 pub mod codecs;
 pub mod denoiser;
+pub mod distortion;
 pub mod dtmf;
 pub mod engine;
+pub mod error;
 pub mod jitter;
+pub mod keyword;
+pub mod language;
+pub mod latency;
+pub mod leveler;
+pub mod meter;
+pub mod mixer;
+pub mod nat;
 pub mod negotiate;
+pub mod pacer;
+pub mod pipeline;
 pub mod processor;
+pub mod prosody;
 pub mod recorder;
+pub mod replay;
+pub mod rewriter;
+pub mod shaper;
 pub mod stream;
 #[cfg(test)]
 mod tests;
 pub mod track;
 pub mod vad;
+pub mod workers;
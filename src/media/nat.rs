@@ -0,0 +1,187 @@
+use crate::{config::Config, net_tool};
+use anyhow::{Result, anyhow};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, PartialEq)]
+struct Mapping {
+    start: u16,
+    end: u16,
+    external_ip: IpAddr,
+    external_port: u16,
+}
+
+/// Where the RTP ports of this instance are reached from outside, by the
+/// `media_nat` config: a mapped range first, then the public IP found over
+/// STUN, then `external_ip`
+#[derive(Debug)]
+pub struct MediaNat {
+    ports: (u16, u16),
+    mappings: Vec<Mapping>,
+    external_ip: Option<IpAddr>,
+    stun_server: Option<String>,
+    stun_interval: Duration,
+    discovered: RwLock<Option<IpAddr>>,
+}
+
+impl MediaNat {
+    pub fn new(config: &Config) -> Result<Self> {
+        let nat = config.media_nat.clone().unwrap_or_default();
+        let start = config.rtp_start_port.unwrap_or(12000);
+        let end = config.rtp_end_port.unwrap_or(u16::MAX - 1);
+        let ports = match nat.pod_ports {
+            Some(size) => {
+                let index = match nat.pod_index {
+                    Some(index) => index,
+                    None => std::env::var("HOSTNAME")
+                        .ok()
+                        .as_deref()
+                        .and_then(pod_ordinal)
+                        .ok_or_else(|| anyhow!("no pod_index and no ordinal in the hostname"))?,
+                };
+                pod_range(start, end, size, index)?
+            }
+            None => (start, end),
+        };
+        let mut mappings = Vec::new();
+        for mapping in nat.mappings.iter() {
+            if mapping.start > mapping.end {
+                return Err(anyhow!(
+                    "invalid port mapping {}-{}",
+                    mapping.start,
+                    mapping.end
+                ));
+            }
+            let external_port = mapping.external_port.unwrap_or(mapping.start);
+            if external_port as u32 + (mapping.end - mapping.start) as u32 > u16::MAX as u32 {
+                return Err(anyhow!(
+                    "port mapping {}-{} overflows from {}",
+                    mapping.start,
+                    mapping.end,
+                    external_port
+                ));
+            }
+            mappings.push(Mapping {
+                start: mapping.start,
+                end: mapping.end,
+                external_ip: mapping.external_ip.parse()?,
+                external_port,
+            });
+        }
+        let external_ip = match config.external_ip.as_ref() {
+            Some(ip) => Some(ip.parse()?),
+            None => None,
+        };
+        Ok(Self {
+            ports,
+            mappings,
+            external_ip,
+            stun_server: nat.stun_server,
+            stun_interval: Duration::from_secs(nat.stun_interval.max(1)),
+            discovered: RwLock::new(None),
+        })
+    }
+
+    /// The RTP ports this instance allocates from
+    pub fn port_range(&self) -> (u16, u16) {
+        self.ports
+    }
+
+    /// The public address of the local RTP or RTCP socket, none when it is
+    /// reached as is
+    pub fn external(&self, local: SocketAddr) -> Option<SocketAddr> {
+        let port = local.port();
+        if let Some(mapping) = self
+            .mappings
+            .iter()
+            .find(|mapping| (mapping.start..=mapping.end).contains(&port))
+        {
+            return Some(SocketAddr::new(
+                mapping.external_ip,
+                mapping.external_port + (port - mapping.start),
+            ));
+        }
+        self.discovered()
+            .or(self.external_ip)
+            .map(|ip| SocketAddr::new(ip, port))
+    }
+
+    /// The public IP of the last STUN lookup
+    pub fn discovered(&self) -> Option<IpAddr> {
+        self.discovered.read().ok().and_then(|ip| *ip)
+    }
+
+    pub fn set_discovered(&self, ip: IpAddr) {
+        if let Ok(mut discovered) = self.discovered.write()
+            && *discovered != Some(ip)
+        {
+            info!(%ip, "public media address changed");
+            *discovered = Some(ip);
+        }
+    }
+
+    /// Asks the STUN server for the public IP, from a socket on the media
+    /// interface
+    pub async fn refresh(&self) -> Result<IpAddr> {
+        let Some(stun_server) = self.stun_server.as_ref() else {
+            return Err(anyhow!("no stun_server"));
+        };
+        let addr = net_tool::get_first_non_loopback_interface()?;
+        let mut conn =
+            net_tool::create_udp_connection(SocketAddr::new(addr, 0), None, None, None).await?;
+        let external =
+            net_tool::external_by_stun(&mut conn, stun_server, Duration::from_secs(5)).await?;
+        self.set_discovered(external.ip());
+        Ok(external.ip())
+    }
+
+    /// Refreshes the public IP every `stun_interval`, when a STUN server is
+    /// set
+    pub fn start(self: &Arc<Self>, token: CancellationToken) {
+        if self.stun_server.is_none() {
+            return;
+        }
+        let nat = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(nat.stun_interval);
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = ticker.tick() => {
+                        if let Err(e) = nat.refresh().await {
+                            warn!("failed to refresh the public media address: {}", e);
+                        }
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// The `index`th slice of `size` ports of `start..=end`
+pub fn pod_range(start: u16, end: u16, size: u16, index: u16) -> Result<(u16, u16)> {
+    if size < 2 {
+        return Err(anyhow!("pod_ports must be at least 2"));
+    }
+    let first = start as u32 + index as u32 * size as u32;
+    let last = first + size as u32 - 1;
+    if last > end as u32 {
+        return Err(anyhow!(
+            "pod {} has no ports left in {}-{}",
+            index,
+            start,
+            end
+        ));
+    }
+    Ok((first as u16, last as u16))
+}
+
+/// The ordinal a StatefulSet ends its pod names with
+pub fn pod_ordinal(hostname: &str) -> Option<u16> {
+    hostname.rsplit_once('-')?.1.parse().ok()
+}
@@ -1,4 +1,6 @@
 use super::codecs::{self, CodecType};
+use anyhow::Result;
+use std::io::Cursor;
 use webrtc::sdp::SessionDescription;
 
 #[derive(Clone)]
@@ -9,6 +11,15 @@ pub struct PeerMedia {
     pub rtcp_port: u16,
     pub rtcp_mux: bool,
     pub codecs: Vec<CodecType>,
+    /// Codec of each payload type, in the order of the formats, dynamic
+    /// ones by their `a=rtpmap`
+    pub payload_types: Vec<(u8, CodecType)>,
+}
+
+/// Parse an SDP body received from the network
+pub fn parse_sdp(data: &[u8]) -> Result<SessionDescription> {
+    let mut reader = Cursor::new(data);
+    Ok(SessionDescription::unmarshal(&mut reader)?)
 }
 
 pub fn strip_ipv6_candidates(sdp: &str) -> String {
@@ -37,6 +48,7 @@ pub fn select_peer_media(sdp: &SessionDescription, media_type: &str) -> Option<P
         rtcp_port: 0,
         rtcp_mux: false,
         codecs: Vec::new(),
+        payload_types: Vec::new(),
     };
 
     match sdp.connection_information {
@@ -54,14 +66,35 @@ pub fn select_peer_media(sdp: &SessionDescription, media_type: &str) -> Option<P
     }
     for media in sdp.media_descriptions.iter() {
         if media.media_name.media == media_type {
-            media.media_name.formats.iter().for_each(|format| {
-                match CodecType::try_from(format) {
-                    Ok(codec) => peer_media.codecs.push(codec),
-                    Err(_) => return,
+            let rtpmaps = media
+                .attributes
+                .iter()
+                .filter(|attribute| attribute.key == "rtpmap")
+                .filter_map(|attribute| {
+                    let (payload_type, encoding) = attribute.value.as_ref()?.split_once(' ')?;
+                    Some((payload_type.parse::<u8>().ok()?, encoding.trim()))
+                })
+                .collect::<Vec<_>>();
+            for format in media.media_name.formats.iter() {
+                let Ok(payload_type) = format.parse::<u8>() else {
+                    continue;
                 };
-            });
+                // an rtpmap names the codec, only formats without one are
+                // taken as the static assignment
+                let codec = match rtpmaps.iter().find(|(pt, _)| *pt == payload_type) {
+                    Some((_, encoding)) => CodecType::from_encoding_name(encoding),
+                    None => CodecType::from_payload_type(payload_type),
+                };
+                let Some(codec) = codec else {
+                    continue;
+                };
+                peer_media.payload_types.push((payload_type, codec));
+                if !peer_media.codecs.contains(&codec) {
+                    peer_media.codecs.push(codec);
+                }
+            }
             peer_media.rtp_port = media.media_name.port.value as u16;
-            peer_media.rtcp_port = peer_media.rtp_port + 1;
+            peer_media.rtcp_port = peer_media.rtp_port.saturating_add(1);
 
             match media.connection_information {
                 Some(ref connection_information) => {
@@ -103,11 +136,75 @@ pub fn select_peer_media(sdp: &SessionDescription, media_type: &str) -> Option<P
     Some(peer_media)
 }
 
+/// The media a local track supports, cached from the SDP it would offer so
+/// that an offer can be answered without the track, e.g. in the ACK the
+/// transaction layer sends as soon as a 2xx with a late offer arrives
+#[derive(Debug, Clone)]
+pub struct SdpCapabilities {
+    sdp: SessionDescription,
+    codecs: Vec<CodecType>,
+}
+
+impl SdpCapabilities {
+    pub fn new(local_description: &str) -> Result<Self> {
+        let sdp = parse_sdp(local_description.as_bytes())?;
+        let codecs = select_peer_media(&sdp, "audio")
+            .map(|media| media.codecs)
+            .unwrap_or_default();
+        if codecs.is_empty() {
+            return Err(anyhow::anyhow!("no audio codecs in local SDP"));
+        }
+        Ok(Self { sdp, codecs })
+    }
+
+    /// Answers `offer` (RFC 3264 6) with the first audio codec offered that
+    /// is supported, the one the track sends once given the offer
+    pub fn answer(&self, offer: &str) -> Result<String> {
+        let offer = parse_sdp(offer.as_bytes())?;
+        let (offered_type, codec) = select_peer_media(&offer, "audio")
+            .and_then(|media| {
+                media
+                    .payload_types
+                    .into_iter()
+                    .find(|(_, codec)| codec.is_audio() && self.codecs.contains(codec))
+            })
+            .ok_or_else(|| anyhow::anyhow!("no supported audio codec in offer"))?;
+        // answered with the payload type of the offer (RFC 3264 6.1)
+        let payload_type = codec.payload_type().to_string();
+        let offered_type = offered_type.to_string();
+
+        let mut answer = self.sdp.clone();
+        for media in answer.media_descriptions.iter_mut() {
+            if media.media_name.media != "audio" {
+                continue;
+            }
+            media.media_name.formats = vec![offered_type.clone()];
+            media.attributes.retain(|attribute| {
+                !matches!(attribute.key.as_str(), "rtpmap" | "fmtp")
+                    || attribute
+                        .value
+                        .as_ref()
+                        .and_then(|v| v.split_whitespace().next())
+                        .is_some_and(|pt| pt == payload_type)
+            });
+            for attribute in media.attributes.iter_mut() {
+                if matches!(attribute.key.as_str(), "rtpmap" | "fmtp")
+                    && let Some(value) = attribute.value.as_mut()
+                    && let Some((_, rest)) = value.split_once(' ')
+                {
+                    *value = format!("{} {}", offered_type, rest);
+                }
+            }
+        }
+        Ok(answer.marshal())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::media::{
         codecs::CodecType,
-        negotiate::{prefer_audio_codec, select_peer_media},
+        negotiate::{SdpCapabilities, parse_sdp, prefer_audio_codec, select_peer_media},
     };
     use std::io::Cursor;
     use webrtc::sdp::SessionDescription;
@@ -139,4 +236,63 @@ a=ptime:20"#;
         let codec = prefer_audio_codec(&offer_sdp);
         assert_eq!(codec, Some(CodecType::PCMU));
     }
+
+    #[test]
+    fn test_parse_sdp() {
+        let offer = "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\nm=audio 65535 RTP/AVP 0\r\n";
+        let sdp = parse_sdp(offer.as_bytes()).expect("parse sdp");
+        let peer_media = select_peer_media(&sdp, "audio").unwrap();
+        assert_eq!(peer_media.rtp_port, 65535);
+        assert_eq!(peer_media.rtcp_port, 65535);
+
+        assert!(parse_sdp(b"").is_err());
+        assert!(parse_sdp(b"v=0\r\nm=audio").is_err());
+        assert!(parse_sdp(&offer.as_bytes()[..20]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "opus")]
+    fn test_dynamic_payload_types() {
+        let offer = "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+            m=audio 4000 RTP/AVP 96 0 97 98\r\na=rtpmap:96 opus/48000/2\r\n\
+            a=rtpmap:97 telephone-event/8000\r\na=rtpmap:98 AMR/8000\r\n";
+        let sdp = parse_sdp(offer.as_bytes()).expect("parse sdp");
+        let peer_media = select_peer_media(&sdp, "audio").unwrap();
+        assert_eq!(
+            peer_media.payload_types,
+            vec![
+                (96, CodecType::Opus),
+                (0, CodecType::PCMU),
+                (97, CodecType::TelephoneEvent)
+            ]
+        );
+        assert_eq!(
+            peer_media.codecs,
+            vec![CodecType::Opus, CodecType::PCMU, CodecType::TelephoneEvent]
+        );
+    }
+
+    #[test]
+    fn test_answer_from_capabilities() {
+        let local = "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+            m=audio 12000 RTP/AVP 9 0 8 101\r\na=rtpmap:9 G722/8000\r\na=rtpmap:0 PCMU/8000\r\n\
+            a=rtpmap:8 PCMA/8000\r\na=rtpmap:101 telephone-event/8000\r\na=sendrecv\r\n";
+        let capabilities = SdpCapabilities::new(local).expect("capabilities");
+        let offer = "v=0\r\no=- 1 1 IN IP4 10.0.0.1\r\ns=-\r\nc=IN IP4 10.0.0.1\r\nt=0 0\r\n\
+            m=audio 30000 RTP/AVP 101 8 0\r\na=rtpmap:101 telephone-event/8000\r\n\
+            a=rtpmap:8 PCMA/8000\r\na=rtpmap:0 PCMU/8000\r\n";
+
+        let answer = capabilities.answer(offer).expect("answer");
+        let sdp = parse_sdp(answer.as_bytes()).unwrap();
+        let media = select_peer_media(&sdp, "audio").unwrap();
+        assert_eq!(media.codecs, vec![CodecType::PCMA]);
+        assert_eq!(media.rtp_port, 12000);
+        assert!(answer.contains("a=rtpmap:8 PCMA/8000"));
+        assert!(!answer.contains("PCMU"));
+        assert!(answer.contains("a=sendrecv"));
+
+        let unsupported = "v=0\r\no=- 1 1 IN IP4 10.0.0.1\r\ns=-\r\nc=IN IP4 10.0.0.1\r\nt=0 0\r\n\
+            m=audio 30000 RTP/AVP 18\r\n";
+        assert!(capabilities.answer(unsupported).is_err());
+    }
 }
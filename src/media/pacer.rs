@@ -0,0 +1,108 @@
+//! Paces outbound frames on the codec's frame interval.
+//!
+//! Tracks that produce audio faster than real time (files, TTS, HTTP
+//! streams) must not hand it on as fast as they get it. `tokio::time::interval`
+//! bursts the ticks it missed after a stall, and a sleep per frame drifts by
+//! the timer's lateness every frame. [`PlayoutClock`] puts the frames on a
+//! fixed grid from the first one instead, so lateness doesn't add up, and
+//! starts a new grid when it fell behind by a whole frame rather than
+//! catching up in a burst.
+use crate::media::clock::{ClockRef, system_clock};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PacingStats {
+    pub frames: u64,
+    /// Frames that went out after their deadline
+    pub late_frames: u64,
+    /// Times the clock fell behind by a frame or more and started over
+    pub resyncs: u64,
+    /// The latest a frame went out (in ms)
+    pub max_lateness: u64,
+}
+
+/// The deadlines of the frames of one track, `interval` apart
+#[derive(Debug)]
+pub struct PlayoutClock {
+    interval: Duration,
+    start: Instant,
+    /// Frames since `start`
+    frames: u32,
+    stats: PacingStats,
+}
+
+impl PlayoutClock {
+    pub fn new(interval: Duration, now: Instant) -> Self {
+        Self {
+            interval: interval.max(Duration::from_millis(1)),
+            start: now,
+            frames: 0,
+            stats: PacingStats::default(),
+        }
+    }
+
+    /// When the next frame is due
+    pub fn deadline(&self) -> Instant {
+        self.start + self.interval * self.frames
+    }
+
+    /// Marks the next frame sent at `now`. Late by less than a frame, the
+    /// frame after keeps its deadline; later, the grid starts over at `now`
+    pub fn advance(&mut self, now: Instant) {
+        let lateness = now.saturating_duration_since(self.deadline());
+        self.stats.frames += 1;
+        if !lateness.is_zero() {
+            self.stats.late_frames += 1;
+            self.stats.max_lateness = self.stats.max_lateness.max(lateness.as_millis() as u64);
+        }
+        if lateness >= self.interval {
+            self.stats.resyncs += 1;
+            self.start = now;
+            self.frames = 1;
+        } else if self.frames == u32::MAX {
+            self.start = self.deadline();
+            self.frames = 1;
+        } else {
+            self.frames += 1;
+        }
+    }
+
+    pub fn stats(&self) -> &PacingStats {
+        &self.stats
+    }
+}
+
+/// Waits for the deadline of each frame of a track
+#[derive(Debug)]
+pub struct Pacer {
+    clock: PlayoutClock,
+    time: ClockRef,
+}
+
+impl Pacer {
+    /// The first frame is due right away
+    pub fn new(interval: Duration) -> Self {
+        Self::with_clock(interval, system_clock())
+    }
+
+    pub fn with_clock(interval: Duration, time: ClockRef) -> Self {
+        Self {
+            clock: PlayoutClock::new(interval, time.now()),
+            time,
+        }
+    }
+
+    /// Waits until the next frame is due. Cancel safe: a tick dropped before
+    /// it completed leaves the deadline as it was
+    pub async fn tick(&mut self) {
+        self.time.sleep_until(self.clock.deadline()).await;
+        self.clock.advance(self.time.now());
+    }
+
+    pub fn stats(&self) -> &PacingStats {
+        self.clock.stats()
+    }
+}
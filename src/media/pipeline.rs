@@ -0,0 +1,217 @@
+//! Sans-IO media pipeline: RTP datagrams in, decoded and processed frames
+//! out, and PCM frames in, RTP datagrams out.
+//!
+//! [`MediaPipeline`] owns the jitter buffer, the codecs and the processor
+//! chain of one media leg but no sockets, timers or async runtime. The caller
+//! moves the bytes and drives playout with its own clock: `push_packet` for
+//! every datagram received, `pull_frame` once per ptime, `push_frame` for
+//! every frame to send and `pull_packet` until it returns `None`. Frames of
+//! several pipelines can be combined with [`super::mixer::MediaMixer`].
+use super::{
+    codecs::CodecType,
+    dtmf::DtmfDetector,
+    error::{MediaError, MediaResult},
+    jitter::JitterBuffer,
+    processor::ProcessorChain,
+    track::{
+        TrackConfig,
+        rtp::{RtpPacketKind, parse_rtp_packet},
+        track_codec::TrackCodec,
+    },
+};
+use crate::{AudioFrame, Samples, TrackId};
+use std::collections::VecDeque;
+use webrtc::{
+    rtp::{header::Header, packet::Packet},
+    util::Marshal,
+};
+
+/// Convert a received RTP packet to a frame, stamped with the arrival time.
+/// `sample_rate` is the rate of its payload type as negotiated, see
+/// [`TrackCodec::sample_rate`]
+pub fn packet_to_frame(
+    track_id: &TrackId,
+    packet: Packet,
+    sample_rate: u32,
+    timestamp: u64,
+) -> AudioFrame {
+    let payload_type = packet.header.payload_type;
+    AudioFrame {
+        track_id: track_id.clone(),
+        samples: Samples::RTP {
+            payload_type,
+            payload: packet.payload.to_vec(),
+            sequence_number: packet.header.sequence_number,
+        },
+        timestamp,
+        sample_rate,
+    }
+}
+
+pub struct MediaPipeline {
+    track_id: TrackId,
+    config: TrackConfig,
+    ssrc: u32,
+    sequence_number: u16,
+    rtp_timestamp: u32,
+    jitter: JitterBuffer,
+    processor_chain: ProcessorChain,
+    encoder: TrackCodec,
+    dtmf_detector: DtmfDetector,
+    digits: VecDeque<String>,
+    outgoing: VecDeque<Vec<u8>>,
+}
+
+impl MediaPipeline {
+    pub fn new(track_id: TrackId, config: TrackConfig) -> Self {
+        let processor_chain =
+            ProcessorChain::new(config.samplerate).with_resample_profile(config.resample_profile);
+        let encoder = TrackCodec::with_profile(config.resample_profile).with_g722_mode(config.g722);
+        Self {
+            track_id,
+            config,
+            ssrc: rand::random::<u32>(),
+            sequence_number: rand::random::<u16>(),
+            rtp_timestamp: rand::random::<u32>(),
+            jitter: JitterBuffer::new(),
+            processor_chain,
+            encoder,
+            dtmf_detector: DtmfDetector::new(),
+            digits: VecDeque::new(),
+            outgoing: VecDeque::new(),
+        }
+    }
+
+    pub fn with_ssrc(mut self, ssrc: u32) -> Self {
+        self.ssrc = ssrc;
+        self
+    }
+
+    /// Use fixed initial sequence number and RTP timestamp, e.g. for
+    /// reproducible output in tests
+    pub fn with_initial_sequence(mut self, sequence_number: u16, rtp_timestamp: u32) -> Self {
+        self.sequence_number = sequence_number;
+        self.rtp_timestamp = rtp_timestamp;
+        self
+    }
+
+    pub fn with_jitter_buffer(mut self, jitter: JitterBuffer) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn track_id(&self) -> &TrackId {
+        &self.track_id
+    }
+
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    pub fn config(&self) -> &TrackConfig {
+        &self.config
+    }
+
+    /// Codec of the outgoing packets
+    pub fn set_codec(&mut self, codec: CodecType) {
+        self.config.codec = codec;
+    }
+
+    /// Payload types negotiated in the SDP, dynamic ones included, for the
+    /// packets received and sent
+    pub fn set_payload_types(&mut self, payload_types: &[(u8, CodecType)]) {
+        self.encoder.set_payload_types(payload_types);
+        self.processor_chain.set_payload_types(payload_types);
+    }
+
+    pub fn processor_chain(&mut self) -> &mut ProcessorChain {
+        &mut self.processor_chain
+    }
+
+    /// Feed a datagram received from the network at `now` (in ms). STUN and
+    /// RTCP datagrams are accepted and ignored, telephone events are
+    /// available from `pull_dtmf`. A static payload type no codec handles
+    /// is [`MediaError::UnsupportedPayload`], a full jitter buffer that had
+    /// to drop frames [`MediaError::BufferOverrun`].
+    pub fn push_packet(&mut self, data: &[u8], now: u64) -> MediaResult<()> {
+        let packet = match parse_rtp_packet(data)? {
+            RtpPacketKind::Rtp(packet) => packet,
+            RtpPacketKind::Rtcp(_) | RtpPacketKind::Stun => return Ok(()),
+        };
+        let payload_type = packet.header.payload_type;
+        if !self.encoder.is_audio(payload_type) {
+            if !(96..=127).contains(&payload_type) {
+                return Err(MediaError::UnsupportedPayload(payload_type));
+            }
+            if let Some(digit) = self.dtmf_detector.detect_rtp(payload_type, &packet.payload) {
+                self.digits.push_back(digit);
+            }
+            return Ok(());
+        }
+        let dropped = self.jitter.stats().total_dropped;
+        let sample_rate = self.encoder.sample_rate(payload_type);
+        self.jitter
+            .push(packet_to_frame(&self.track_id, packet, sample_rate, now));
+        let stats = self.jitter.stats();
+        if stats.total_dropped > dropped {
+            return Err(MediaError::BufferOverrun {
+                capacity: stats.buffer_size,
+                dropped: stats.total_dropped - dropped,
+            });
+        }
+        Ok(())
+    }
+
+    /// Next frame to play out, decoded and run through the processor chain.
+    /// Call once per ptime.
+    pub fn pull_frame(&mut self) -> MediaResult<Option<AudioFrame>> {
+        let mut frame = match self.jitter.pop() {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        self.processor_chain.process_frame(&mut frame)?;
+        Ok(Some(frame))
+    }
+
+    pub fn pull_dtmf(&mut self) -> Option<String> {
+        self.digits.pop_front()
+    }
+
+    /// Encode a PCM frame to send, the RTP datagram is available from
+    /// `pull_packet`
+    pub fn push_frame(&mut self, frame: &AudioFrame) -> MediaResult<()> {
+        let samples = match &frame.samples {
+            Samples::PCM { samples } => samples.len() as u64,
+            _ => return Err(MediaError::UnsupportedFrame),
+        };
+        let codec = self.config.codec;
+        let (payload_type, payload) = self
+            .encoder
+            .encode(self.encoder.payload_type(codec), frame.clone());
+        if payload.is_empty() {
+            return Ok(());
+        }
+        let clock_samples =
+            self.encoder
+                .clock_samples(payload_type, samples as usize, frame.sample_rate);
+        let packet = Packet {
+            header: Header {
+                version: 2,
+                payload_type,
+                sequence_number: self.sequence_number,
+                timestamp: self.rtp_timestamp,
+                ssrc: self.ssrc,
+                ..Default::default()
+            },
+            payload: payload.into(),
+        };
+        self.sequence_number = self.sequence_number.wrapping_add(1);
+        self.rtp_timestamp = self.rtp_timestamp.wrapping_add(clock_samples);
+        self.outgoing.push_back(packet.marshal()?.to_vec());
+        Ok(())
+    }
+
+    pub fn pull_packet(&mut self) -> Option<Vec<u8>> {
+        self.outgoing.pop_front()
+    }
+}
@@ -1,11 +1,39 @@
+use super::codecs::{
+    CodecType,
+    resample::{ResampleProfile, StreamResampler},
+};
+use super::error::{MediaError, MediaResult};
+use super::mixer::SpeakerSelection;
 use super::track::track_codec::TrackCodec;
-use crate::{AudioFrame, Samples};
+use crate::{AudioFrame, Sample, Samples};
 use anyhow::Result;
 use std::any::Any;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 pub trait Processor: Send + Sync + Any {
-    fn process_frame(&self, frame: &mut AudioFrame) -> Result<()>;
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()>;
+
+    /// The rate the processor needs its frames at, the chain resamples them
+    /// when it differs. `None` takes frames at any rate
+    fn sample_rate(&self) -> Option<u32> {
+        None
+    }
+
+    /// Interleaved channels in the frames the processor handles
+    fn channels(&self) -> u16 {
+        1
+    }
+}
+
+/// Where a tap of a [`ProcessorChain`] sees the frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapPoint {
+    /// Decoded, before the first processor
+    Input,
+    /// After the last processor
+    Output,
 }
 
 impl Default for AudioFrame {
@@ -29,68 +57,288 @@ impl Samples {
     }
 }
 
+/// RMS level of the samples in dBFS, -100 for digital silence
+pub fn energy_dbfs(samples: &[Sample]) -> f32 {
+    let sum = samples
+        .iter()
+        .map(|&s| (s as f64) * (s as f64))
+        .sum::<f64>();
+    let rms = (sum / samples.len().max(1) as f64).sqrt();
+    if rms <= 0.0 {
+        return -100.0;
+    }
+    (20.0 * (rms / 32768.0).log10()) as f32
+}
+
+/// Resamples the frames of a processor that needs another rate, and what
+/// it changed back
+struct ResampleAdapter {
+    input_sample_rate: u32,
+    output_sample_rate: u32,
+    to_processor: StreamResampler,
+    from_processor: StreamResampler,
+}
+
+impl ResampleAdapter {
+    fn new(
+        profile: ResampleProfile,
+        input_sample_rate: u32,
+        output_sample_rate: u32,
+    ) -> Result<Self> {
+        Ok(Self {
+            input_sample_rate,
+            output_sample_rate,
+            to_processor: StreamResampler::new(profile, input_sample_rate, output_sample_rate)?,
+            from_processor: StreamResampler::new(profile, output_sample_rate, input_sample_rate)?,
+        })
+    }
+}
+
+struct Stage {
+    processor: Box<dyn Processor>,
+    adapter: Option<ResampleAdapter>,
+}
+
+impl Stage {
+    fn new(processor: Box<dyn Processor>) -> Self {
+        Self {
+            processor,
+            adapter: None,
+        }
+    }
+
+    /// Checks the frame fits the processor at `index`, resampled to its rate
+    fn process_frame(
+        &mut self,
+        index: usize,
+        frame: &mut AudioFrame,
+        channels: u16,
+        profile: ResampleProfile,
+    ) -> MediaResult<()> {
+        if self.processor.channels() != channels {
+            return Err(MediaError::Channels {
+                processor: index,
+                expected: self.processor.channels(),
+                actual: channels,
+            });
+        }
+        let Samples::PCM { samples } = &mut frame.samples else {
+            return self.processor.process_frame(frame);
+        };
+        if samples.len() % channels.max(1) as usize != 0 {
+            return Err(MediaError::PartialFrame {
+                processor: index,
+                samples: samples.len(),
+                channels,
+            });
+        }
+        let input_sample_rate = frame.sample_rate;
+        let sample_rate = match self.processor.sample_rate() {
+            Some(rate) if rate != input_sample_rate => rate,
+            _ => return self.processor.process_frame(frame),
+        };
+        let adapter = match &mut self.adapter {
+            Some(adapter)
+                if adapter.input_sample_rate == input_sample_rate
+                    && adapter.output_sample_rate == sample_rate =>
+            {
+                adapter
+            }
+            adapter => match ResampleAdapter::new(profile, input_sample_rate, sample_rate) {
+                Ok(new_adapter) => adapter.insert(new_adapter),
+                Err(_) => {
+                    return Err(MediaError::SampleRate {
+                        processor: index,
+                        expected: sample_rate,
+                        actual: input_sample_rate,
+                    });
+                }
+            },
+        };
+        let original = std::mem::take(samples);
+        let resampled = adapter.to_processor.resample(&original);
+        frame.samples = Samples::PCM {
+            samples: resampled.clone(),
+        };
+        frame.sample_rate = sample_rate;
+        let result = self.processor.process_frame(frame);
+        // audio the processor only looked at goes on as it came
+        if let Samples::PCM { samples } = &mut frame.samples {
+            *samples = match *samples == resampled {
+                true => original,
+                false => adapter.from_processor.resample(samples),
+            };
+        }
+        frame.sample_rate = input_sample_rate;
+        result
+    }
+}
+
+type Tap = (TapPoint, Box<dyn Processor>);
+
 #[derive(Clone)]
 pub struct ProcessorChain {
-    processors: Arc<Mutex<Vec<Box<dyn Processor>>>>,
+    processors: Arc<Mutex<Vec<Stage>>>,
+    taps: Arc<Mutex<Vec<Tap>>>,
     codec: Arc<Mutex<TrackCodec>>,
+    speakers: Arc<Mutex<Option<SpeakerSelection>>>,
     sample_rate: u32,
+    channels: u16,
+    resample_profile: ResampleProfile,
     pub force_decode: bool,
+    /// time spent on the last frame (in us)
+    elapsed: Arc<AtomicU64>,
 }
 
 impl ProcessorChain {
     pub fn new(sample_rate: u32) -> Self {
         Self {
             processors: Arc::new(Mutex::new(Vec::new())),
+            taps: Arc::new(Mutex::new(Vec::new())),
             codec: Arc::new(Mutex::new(TrackCodec::new())),
+            speakers: Arc::new(Mutex::new(None)),
             sample_rate,
+            channels: 1,
+            resample_profile: ResampleProfile::default(),
             force_decode: true,
+            elapsed: Arc::new(AtomicU64::new(0)),
         }
     }
+    /// Decodes with `profile` when the codec's rate is not the chain's, and
+    /// resamples with it for processors that need another rate
+    pub fn with_resample_profile(mut self, profile: ResampleProfile) -> Self {
+        self.codec = Arc::new(Mutex::new(TrackCodec::with_profile(profile)));
+        self.resample_profile = profile;
+        self
+    }
+
+    /// Interleaved channels of the frames, the processors must handle as many
+    pub fn with_channels(mut self, channels: u16) -> Self {
+        self.channels = channels;
+        self
+    }
+
     pub fn insert_processor(&mut self, processor: Box<dyn Processor>) {
-        self.processors.lock().unwrap().insert(0, processor);
+        self.processors
+            .lock()
+            .unwrap()
+            .insert(0, Stage::new(processor));
     }
     pub fn append_processor(&mut self, processor: Box<dyn Processor>) {
-        self.processors.lock().unwrap().push(processor);
+        self.processors.lock().unwrap().push(Stage::new(processor));
+    }
+
+    /// Shows `tap` the frames at `point`, whatever processors are added
+    /// before or after it. A tap looks at the frames, what it changes of
+    /// them is not kept
+    pub fn add_tap(&mut self, point: TapPoint, tap: Box<dyn Processor>) {
+        self.taps.lock().unwrap().push((point, tap));
     }
 
     pub fn has_processor<T: 'static>(&self) -> bool {
         let processors = self.processors.lock().unwrap();
         processors
             .iter()
-            .any(|processor| (processor.as_ref() as &dyn Any).is::<T>())
+            .any(|stage| (stage.processor.as_ref() as &dyn Any).is::<T>())
     }
 
     pub fn remove_processor<T: 'static>(&self) {
         let mut processors = self.processors.lock().unwrap();
-        processors.retain(|processor| !(processor.as_ref() as &dyn Any).is::<T>());
+        processors.retain(|stage| !(stage.processor.as_ref() as &dyn Any).is::<T>());
     }
 
-    pub fn process_frame(&self, frame: &mut AudioFrame) -> Result<()> {
-        let processors = self.processors.lock().unwrap();
-        if !self.force_decode && processors.is_empty() {
+    /// Payload types negotiated for the frames decoded, see
+    /// [`TrackCodec::set_payload_types`]
+    pub fn set_payload_types(&self, payload_types: &[(u8, CodecType)]) {
+        self.codec.lock().unwrap().set_payload_types(payload_types);
+    }
+
+    /// Whether a payload type is audio the chain decodes
+    pub fn is_audio(&self, payload_type: u8) -> bool {
+        self.codec.lock().unwrap().is_audio(payload_type)
+    }
+
+    /// Rate of the audio a payload type carries, see
+    /// [`TrackCodec::sample_rate`]
+    pub fn sample_rate(&self, payload_type: u8) -> u32 {
+        self.codec.lock().unwrap().sample_rate(payload_type)
+    }
+
+    /// Discards the audio instead of decoding it while the mixer doesn't
+    /// take it, see [`SpeakerSelection::needs_decode`]. The processors don't
+    /// see it either then.
+    pub fn set_speaker_selection(&self, speakers: Option<SpeakerSelection>) {
+        *self.speakers.lock().unwrap() = speakers;
+    }
+
+    /// Start decoding a new stream, see [`TrackCodec::reset`]
+    pub fn reset_codec(&self) {
+        self.codec.lock().unwrap().reset();
+    }
+
+    /// Time spent decoding and processing the last frame
+    pub fn processing_time(&self) -> Duration {
+        Duration::from_micros(self.elapsed.load(Ordering::Relaxed))
+    }
+
+    pub fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
+        let start = Instant::now();
+        let result = self.process_frame_inner(frame);
+        self.elapsed
+            .store(start.elapsed().as_micros() as u64, Ordering::Relaxed);
+        result
+    }
+
+    fn process_frame_inner(&self, frame: &mut AudioFrame) -> MediaResult<()> {
+        let mut processors = self.processors.lock().unwrap();
+        let taps = self.taps.lock().unwrap();
+        if !self.force_decode && processors.is_empty() && taps.is_empty() {
             return Ok(());
         }
 
         if let Samples::RTP {
             payload_type,
             payload,
-            ..
+            sequence_number,
         } = &frame.samples
         {
-            if TrackCodec::is_audio(*payload_type) {
-                let samples =
-                    self.codec
-                        .lock()
-                        .unwrap()
-                        .decode(*payload_type, &payload, self.sample_rate);
+            let codec = self.codec.lock().unwrap();
+            if codec.is_audio(*payload_type) {
+                if !self.needs_decode(&frame.track_id) {
+                    frame.samples = Samples::Empty;
+                    return Ok(());
+                }
+                let samples = codec.decode_packet(
+                    *payload_type,
+                    *sequence_number,
+                    frame.timestamp,
+                    payload,
+                    self.sample_rate,
+                );
                 frame.samples = Samples::PCM { samples };
                 frame.sample_rate = self.sample_rate;
             }
         }
+        Self::tap(&taps, TapPoint::Input, frame);
         // Process the frame with all processors
-        for processor in processors.iter() {
-            processor.process_frame(frame)?;
+        for (index, stage) in processors.iter_mut().enumerate() {
+            stage.process_frame(index, frame, self.channels, self.resample_profile)?;
         }
+        Self::tap(&taps, TapPoint::Output, frame);
         Ok(())
     }
+
+    fn needs_decode(&self, track_id: &str) -> bool {
+        self.speakers
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_none_or(|speakers| speakers.needs_decode(track_id, crate::get_timestamp()))
+    }
+
+    fn tap(taps: &[Tap], point: TapPoint, frame: &AudioFrame) {
+        for (_, tap) in taps.iter().filter(|(at, _)| *at == point) {
+            tap.process_frame(&mut frame.clone()).ok();
+        }
+    }
 }
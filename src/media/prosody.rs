@@ -0,0 +1,349 @@
+use crate::event::{EventSender, SessionEvent};
+use crate::media::error::MediaResult;
+use crate::media::processor::{Processor, energy_dbfs};
+use crate::media::track::file::encode_wav;
+use crate::{AudioFrame, PcmBuf, Sample, Samples};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+const MIN_PITCH: f32 = 70.0;
+const MAX_PITCH: f32 = 400.0;
+const VOICING_THRESHOLD: f32 = 0.45;
+const SYLLABLE_DELTA_DB: f32 = 6.0;
+
+#[skip_serializing_none]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct ProsodyOption {
+    /// Report interval (in ms)
+    pub interval: u64,
+    /// Energy gate for speech frames (in dBFS)
+    pub energy_threshold: f32,
+    /// Sentiment provider endpoint, receives each window as `audio/wav` and
+    /// returns `{"sentiment": -0.4, "emotion": "angry"}`
+    pub endpoint: Option<String>,
+    pub secret_key: Option<String>,
+    pub secret_id: Option<String>,
+}
+
+impl Default for ProsodyOption {
+    fn default() -> Self {
+        Self {
+            interval: 5000,
+            energy_threshold: -40.0,
+            endpoint: None,
+            secret_key: None,
+            secret_id: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProsodyFeatures {
+    /// Mean energy of speech frames (in dBFS)
+    pub energy: f32,
+    /// Mean pitch of voiced frames (in Hz)
+    pub pitch: f32,
+    /// Standard deviation of the pitch (in Hz)
+    pub pitch_stddev: f32,
+    /// Syllable nuclei per second of speech
+    pub speaking_rate: f32,
+    /// Share of the window containing speech, 0.0 - 1.0
+    pub speech_ratio: f32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct SentimentScore {
+    /// -1.0 (negative) to 1.0 (positive)
+    pub sentiment: f32,
+    pub emotion: Option<String>,
+}
+
+#[async_trait]
+pub trait SentimentScorer: Send + Sync {
+    async fn score(
+        &self,
+        samples: PcmBuf,
+        sample_rate: u32,
+        features: &ProsodyFeatures,
+    ) -> Result<SentimentScore>;
+}
+
+#[derive(Default)]
+struct ProsodyWindow {
+    start_time: Option<u64>,
+    duration: u64,
+    speech_duration: u64,
+    energy_sum: f32,
+    speech_frames: u32,
+    pitches: Vec<f32>,
+    syllables: u32,
+    in_nucleus: bool,
+    peak: f32,
+    valley: f32,
+    samples: PcmBuf,
+    sample_rate: u32,
+}
+
+impl ProsodyWindow {
+    fn features(&self) -> ProsodyFeatures {
+        let mut features = ProsodyFeatures::default();
+        if self.speech_frames > 0 {
+            features.energy = self.energy_sum / self.speech_frames as f32;
+        }
+        if !self.pitches.is_empty() {
+            let count = self.pitches.len() as f32;
+            let mean = self.pitches.iter().sum::<f32>() / count;
+            let variance = self
+                .pitches
+                .iter()
+                .map(|p| (p - mean) * (p - mean))
+                .sum::<f32>()
+                / count;
+            features.pitch = mean;
+            features.pitch_stddev = variance.sqrt();
+        }
+        if self.speech_duration > 0 {
+            features.speaking_rate = self.syllables as f32 * 1000.0 / self.speech_duration as f32;
+        }
+        if self.duration > 0 {
+            features.speech_ratio = self.speech_duration as f32 / self.duration as f32;
+        }
+        features
+    }
+}
+
+/// Computes prosodic features per interval, optionally scored by a sentiment
+/// provider, and emits them as `prosody` events for supervisor dashboards.
+pub struct ProsodyAnalyzer {
+    scorer: Option<Arc<dyn SentimentScorer>>,
+    event_sender: EventSender,
+    option: ProsodyOption,
+    token: CancellationToken,
+    window: Mutex<ProsodyWindow>,
+}
+
+impl ProsodyAnalyzer {
+    pub fn new(
+        scorer: Option<Arc<dyn SentimentScorer>>,
+        token: CancellationToken,
+        event_sender: EventSender,
+        option: ProsodyOption,
+    ) -> Self {
+        Self {
+            scorer,
+            event_sender,
+            option,
+            token,
+            window: Mutex::new(ProsodyWindow::default()),
+        }
+    }
+
+    pub fn create(
+        token: CancellationToken,
+        event_sender: EventSender,
+        option: ProsodyOption,
+    ) -> Result<Box<dyn Processor>> {
+        let scorer = option
+            .endpoint
+            .as_ref()
+            .map(|_| Arc::new(HttpSentimentScorer::new(&option)) as Arc<dyn SentimentScorer>);
+        Ok(Box::new(Self::new(scorer, token, event_sender, option)))
+    }
+
+    fn emit(&self, track_id: String, window: ProsodyWindow) {
+        let features = window.features();
+        let start_time = window.start_time.unwrap_or_default();
+        let end_time = start_time + window.duration;
+        let scorer = match (&self.scorer, window.speech_frames) {
+            (Some(scorer), n) if n > 0 => scorer.clone(),
+            _ => {
+                self.event_sender
+                    .send(SessionEvent::Prosody {
+                        track_id,
+                        timestamp: crate::get_timestamp(),
+                        start_time,
+                        end_time,
+                        features,
+                        sentiment: None,
+                        emotion: None,
+                    })
+                    .ok();
+                return;
+            }
+        };
+        let event_sender = self.event_sender.clone();
+        let token = self.token.clone();
+        tokio::spawn(async move {
+            let result = tokio::select! {
+                _ = token.cancelled() => return,
+                result = scorer.score(window.samples, window.sample_rate, &features) => result,
+            };
+            let score = result
+                .map_err(|e| warn!(track_id, "sentiment scoring failed: {}", e))
+                .ok();
+            event_sender
+                .send(SessionEvent::Prosody {
+                    track_id,
+                    timestamp: crate::get_timestamp(),
+                    start_time,
+                    end_time,
+                    features,
+                    sentiment: score.as_ref().map(|s| s.sentiment),
+                    emotion: score.and_then(|s| s.emotion),
+                })
+                .ok();
+        });
+    }
+}
+
+impl Processor for ProsodyAnalyzer {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
+        let samples = match &frame.samples {
+            Samples::PCM { samples } if !samples.is_empty() => samples,
+            _ => return Ok(()),
+        };
+        let sample_rate = frame.sample_rate.max(1);
+        let duration = samples.len() as u64 * 1000 / sample_rate as u64;
+        let energy = energy_dbfs(samples);
+        let is_speech = energy >= self.option.energy_threshold;
+
+        let mut window = self.window.lock().unwrap();
+        if window.start_time.is_none() {
+            window.start_time = Some(frame.timestamp);
+            window.valley = energy;
+        }
+        window.duration += duration;
+        window.sample_rate = sample_rate;
+
+        if is_speech {
+            window.speech_duration += duration;
+            window.speech_frames += 1;
+            window.energy_sum += energy;
+            if let Some(pitch) = estimate_pitch(samples, sample_rate) {
+                window.pitches.push(pitch);
+            }
+            if self.scorer.is_some() {
+                window.samples.extend_from_slice(samples);
+            }
+        }
+
+        // syllable nuclei are energy peaks rising above the last valley
+        if window.in_nucleus {
+            window.peak = window.peak.max(energy);
+            if energy < window.peak - SYLLABLE_DELTA_DB {
+                window.in_nucleus = false;
+                window.valley = energy;
+            }
+        } else {
+            window.valley = window.valley.min(energy);
+            if is_speech && energy > window.valley + SYLLABLE_DELTA_DB {
+                window.in_nucleus = true;
+                window.peak = energy;
+                window.syllables += 1;
+            }
+        }
+
+        if window.duration >= self.option.interval {
+            let mut next = ProsodyWindow {
+                in_nucleus: window.in_nucleus,
+                peak: window.peak,
+                valley: window.valley,
+                ..Default::default()
+            };
+            next.start_time = window.start_time.map(|t| t + window.duration);
+            let finished = std::mem::replace(&mut *window, next);
+            drop(window);
+            self.emit(frame.track_id.clone(), finished);
+        }
+        Ok(())
+    }
+}
+
+/// Autocorrelation pitch estimate, None for unvoiced frames
+fn estimate_pitch(samples: &[Sample], sample_rate: u32) -> Option<f32> {
+    let min_lag = (sample_rate as f32 / MAX_PITCH) as usize;
+    let max_lag = ((sample_rate as f32 / MIN_PITCH) as usize).min(samples.len() / 2);
+    if min_lag == 0 || min_lag >= max_lag {
+        return None;
+    }
+    let signal: Vec<f32> = samples.iter().map(|&s| s as f32).collect();
+    let energy = signal.iter().map(|s| s * s).sum::<f32>();
+    if energy <= 0.0 {
+        return None;
+    }
+    let mut best = (0, 0.0f32);
+    for lag in min_lag..=max_lag {
+        let (mut corr, mut norm) = (0.0f32, 0.0f32);
+        for i in 0..signal.len() - lag {
+            corr += signal[i] * signal[i + lag];
+            norm += signal[i + lag] * signal[i + lag];
+        }
+        let score = corr / (energy * norm).sqrt().max(1.0);
+        if score > best.1 {
+            best = (lag, score);
+        }
+    }
+    if best.1 < VOICING_THRESHOLD {
+        return None;
+    }
+    Some(sample_rate as f32 / best.0 as f32)
+}
+
+/// Posts each speech window as a wav file to `endpoint`, the prosodic
+/// features are passed as query parameters.
+pub struct HttpSentimentScorer {
+    client: reqwest::Client,
+    endpoint: String,
+    secret_id: Option<String>,
+    secret_key: Option<String>,
+}
+
+impl HttpSentimentScorer {
+    pub fn new(option: &ProsodyOption) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            endpoint: option.endpoint.clone().unwrap_or_default(),
+            secret_id: option.secret_id.clone(),
+            secret_key: option.secret_key.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl SentimentScorer for HttpSentimentScorer {
+    async fn score(
+        &self,
+        samples: PcmBuf,
+        sample_rate: u32,
+        features: &ProsodyFeatures,
+    ) -> Result<SentimentScore> {
+        let wav = encode_wav(&samples, sample_rate)?;
+        let mut request = self
+            .client
+            .post(&self.endpoint)
+            .header("Content-Type", "audio/wav")
+            .query(&[
+                ("energy", features.energy),
+                ("pitch", features.pitch),
+                ("pitchStddev", features.pitch_stddev),
+                ("speakingRate", features.speaking_rate),
+            ])
+            .body(wav);
+        if let Some(secret_id) = &self.secret_id {
+            request = request.header("X-Secret-Id", secret_id);
+        }
+        if let Some(secret_key) = &self.secret_key {
+            request = request.header("Authorization", format!("Bearer {}", secret_key));
+        }
+        let response = request.send().await?.error_for_status()?;
+        Ok(response.json().await?)
+    }
+}
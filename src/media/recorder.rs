@@ -1,4 +1,7 @@
-use crate::{AudioFrame, PcmBuf, Samples, media::codecs::samples_to_bytes};
+use crate::{
+    AudioFrame, PcmBuf, Samples,
+    media::{codecs::samples_to_bytes, processor::TapPoint},
+};
 use anyhow::Result;
 use futures::StreamExt;
 use hound::{SampleFormat, WavSpec};
@@ -33,6 +36,42 @@ pub struct RecorderOption {
     pub samplerate: u32,
     #[serde(default)]
     pub ptime: Duration,
+    #[serde(default)]
+    pub tap: RecordTap,
+}
+
+/// Which audio of the tracks is recorded. Each of the two tracks of a call
+/// gets half of the channels
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordTap {
+    /// As decoded, before the processors (noise reduction, AGC, ...)
+    #[default]
+    Raw,
+    /// As the processors left it
+    Processed,
+    /// Both, each track's raw audio on a channel and its processed audio on
+    /// the next
+    Both,
+}
+
+impl RecordTap {
+    /// Channels of each track
+    pub fn taps(&self) -> usize {
+        match self {
+            RecordTap::Both => 2,
+            _ => 1,
+        }
+    }
+
+    /// The points of the processor chain recorded
+    pub fn points(&self) -> &'static [TapPoint] {
+        match self {
+            RecordTap::Raw => &[TapPoint::Input],
+            RecordTap::Processed => &[TapPoint::Output],
+            RecordTap::Both => &[TapPoint::Input, TapPoint::Output],
+        }
+    }
 }
 
 impl RecorderOption {
@@ -50,6 +89,7 @@ impl Default for RecorderOption {
             recorder_file: "".to_string(),
             samplerate: 16000,
             ptime: Duration::from_millis(200),
+            tap: RecordTap::default(),
         }
     }
 }
@@ -61,8 +101,8 @@ pub struct Recorder {
     cancel_token: CancellationToken,
     channel_idx: AtomicUsize,
     channels: Mutex<HashMap<String, usize>>,
-    stereo_buf: Mutex<PcmBuf>,
-    mono_buf: Mutex<PcmBuf>,
+    /// Samples waiting to be written, by channel
+    buffers: Mutex<Vec<PcmBuf>>,
 }
 
 impl Recorder {
@@ -71,6 +111,7 @@ impl Recorder {
         session_id: String,
         option: RecorderOption,
     ) -> Self {
+        let buffers = vec![Vec::new(); option.tap.taps() * 2];
         Self {
             session_id,
             option,
@@ -78,19 +119,19 @@ impl Recorder {
             cancel_token,
             channel_idx: AtomicUsize::new(0),
             channels: Mutex::new(HashMap::new()),
-            stereo_buf: Mutex::new(Vec::new()),
-            mono_buf: Mutex::new(Vec::new()),
+            buffers: Mutex::new(buffers),
         }
     }
 
     async fn update_wav_header(&self, file: &mut File) -> Result<()> {
         // Get total data size (in bytes)
         let total_samples = self.samples_written.load(Ordering::SeqCst);
-        let data_size = total_samples * 4; // Stereo, 16-bit = 4 bytes per sample
+        let channels = self.option.tap.taps() as u16 * 2;
+        let data_size = total_samples * channels as usize * 2; // 16-bit = 2 bytes per sample
 
         // Create a WavSpec for the WAV header
         let spec = WavSpec {
-            channels: 2,
+            channels,
             sample_rate: self.option.samplerate,
             bits_per_sample: 16,
             sample_format: SampleFormat::Int,
@@ -139,7 +180,7 @@ impl Recorder {
     pub async fn process_recording(
         &self,
         file_path: &Path,
-        mut receiver: UnboundedReceiver<AudioFrame>,
+        mut receiver: UnboundedReceiver<(TapPoint, AudioFrame)>,
     ) -> Result<()> {
         let mut file = match File::create(file_path).await {
             Ok(file) => file,
@@ -172,12 +213,12 @@ impl Recorder {
         let mut interval = IntervalStream::new(tokio::time::interval(self.option.ptime));
         loop {
             select! {
-                Some(frame) = receiver.recv() => {
-                    self.append_frame(frame).await.ok();
+                Some((point, frame)) = receiver.recv() => {
+                    self.append_frame(point, frame).await.ok();
                 }
                 _ = interval.next() => {
-                    let (mono_buf, stereo_buf) = self.pop(chunk_size).await;
-                    self.process_buffers(&mut file, mono_buf, stereo_buf).await?;
+                    let channels = self.pop(chunk_size).await;
+                    self.process_buffers(&mut file, channels).await?;
                     self.update_wav_header(&mut file).await?;
                 }
                 _ = self.cancel_token.cancelled() => {
@@ -192,25 +233,32 @@ impl Recorder {
         }
     }
 
-    /// Get or assign channel index for a track
-    fn get_channel_index(&self, track_id: &str) -> usize {
+    /// Channel of the audio of a track at a tap: tracks take turns for
+    /// the two halves, each half has a channel per tap
+    fn get_channel_index(&self, track_id: &str, point: TapPoint) -> usize {
         let mut channels = self.channels.lock().unwrap();
-        if let Some(&channel_idx) = channels.get(track_id) {
-            channel_idx % 2
-        } else {
-            let new_idx = self.channel_idx.fetch_add(1, Ordering::SeqCst);
-            channels.insert(track_id.to_string(), new_idx);
-            info!(
-                session_id = self.session_id,
-                "Assigned channel {} to track: {}",
-                new_idx % 2,
-                track_id
-            );
-            new_idx % 2
-        }
+        let track_idx = match channels.get(track_id) {
+            Some(&channel_idx) => channel_idx % 2,
+            None => {
+                let new_idx = self.channel_idx.fetch_add(1, Ordering::SeqCst);
+                channels.insert(track_id.to_string(), new_idx);
+                info!(
+                    session_id = self.session_id,
+                    "Assigned channel {} to track: {}",
+                    new_idx % 2,
+                    track_id
+                );
+                new_idx % 2
+            }
+        };
+        let offset = match (self.option.tap, point) {
+            (RecordTap::Both, TapPoint::Output) => 1,
+            _ => 0,
+        };
+        track_idx * self.option.tap.taps() + offset
     }
 
-    async fn append_frame(&self, frame: AudioFrame) -> Result<()> {
+    async fn append_frame(&self, point: TapPoint, frame: AudioFrame) -> Result<()> {
         let buffer = match frame.samples {
             Samples::PCM { samples } => samples,
             _ => return Ok(()), // ignore non-PCM frames
@@ -221,22 +269,10 @@ impl Recorder {
             return Ok(());
         }
 
-        // Get channel assignment
-        let channel_idx = self.get_channel_index(&frame.track_id);
-
-        // Add to appropriate buffer
-        match channel_idx {
-            0 => {
-                let mut mono_buf = self.mono_buf.lock().unwrap();
-                mono_buf.extend(buffer.iter());
-            }
-            1 => {
-                let mut stereo_buf = self.stereo_buf.lock().unwrap();
-                stereo_buf.extend(buffer.iter());
-            }
-            _ => {}
+        let channel_idx = self.get_channel_index(&frame.track_id, point);
+        if let Some(channel) = self.buffers.lock().unwrap().get_mut(channel_idx) {
+            channel.extend(buffer.iter());
         }
-
         Ok(())
     }
 
@@ -250,66 +286,28 @@ impl Recorder {
         }
     }
 
-    async fn pop(&self, chunk_size: usize) -> (PcmBuf, PcmBuf) {
-        let mut mono_buf = self.mono_buf.lock().unwrap();
-        let mut stereo_buf = self.stereo_buf.lock().unwrap();
+    /// A chunk of every channel. Channels short of data are padded with
+    /// silence, when flushing (`chunk_size` of `usize::MAX`) only to the
+    /// longest of them
+    async fn pop(&self, chunk_size: usize) -> Vec<PcmBuf> {
+        let mut buffers = self.buffers.lock().unwrap();
+        let flushing = chunk_size == usize::MAX;
 
         // Limit chunk_size to prevent capacity overflow
         let safe_chunk_size = chunk_size.min(16000 * 10); // Max 10 seconds at 16kHz
 
-        let mono_result = if mono_buf.len() >= safe_chunk_size {
-            // Sufficient data, extract complete chunk
-            Self::extract_samples(&mut mono_buf, safe_chunk_size)
-        } else if !mono_buf.is_empty() {
-            // Partial data, extract all and pad with silence
-            let available_len = mono_buf.len(); // Store length before mutable borrow
-            let mut result = Self::extract_samples(&mut mono_buf, available_len);
-            if chunk_size != usize::MAX {
-                // Don't pad when flushing
-                result.resize(safe_chunk_size, 0); // Pad with silence to chunk_size
-            }
-            result
-        } else {
-            // No data, output silence (only when not flushing)
-            if chunk_size != usize::MAX {
-                vec![0; safe_chunk_size]
-            } else {
-                Vec::new()
-            }
+        let mut chunks = buffers
+            .iter_mut()
+            .map(|buffer| Self::extract_samples(buffer, safe_chunk_size))
+            .collect::<Vec<_>>();
+        let len = match flushing {
+            true => chunks.iter().map(|c| c.len()).max().unwrap_or(0),
+            false => safe_chunk_size,
         };
-
-        let stereo_result = if stereo_buf.len() >= safe_chunk_size {
-            // Sufficient data, extract complete chunk
-            Self::extract_samples(&mut stereo_buf, safe_chunk_size)
-        } else if !stereo_buf.is_empty() {
-            // Partial data, extract all and pad with silence
-            let available_len = stereo_buf.len(); // Store length before mutable borrow
-            let mut result = Self::extract_samples(&mut stereo_buf, available_len);
-            if chunk_size != usize::MAX {
-                // Don't pad when flushing
-                result.resize(safe_chunk_size, 0); // Pad with silence to chunk_size
-            }
-            result
-        } else {
-            // No data, output silence (only when not flushing)
-            if chunk_size != usize::MAX {
-                vec![0; safe_chunk_size]
-            } else {
-                Vec::new()
-            }
-        };
-
-        // Ensure buffers have equal length when flushing
-        if chunk_size == usize::MAX {
-            let max_len = mono_result.len().max(stereo_result.len());
-            let mut mono_final = mono_result;
-            let mut stereo_final = stereo_result;
-            mono_final.resize(max_len, 0);
-            stereo_final.resize(max_len, 0);
-            (mono_final, stereo_final)
-        } else {
-            (mono_result, stereo_result)
+        for chunk in chunks.iter_mut() {
+            chunk.resize(len, 0);
         }
+        chunks
     }
 
     pub fn stop_recording(&self) -> Result<()> {
@@ -317,59 +315,39 @@ impl Recorder {
         Ok(())
     }
 
-    /// Mix mono and stereo buffers into interleaved stereo output
-    pub(crate) fn mix_buffers(mono_buf: &PcmBuf, stereo_buf: &PcmBuf) -> Vec<i16> {
-        // Ensure both buffers have equal length (guaranteed by pop() method)
-        assert_eq!(
-            mono_buf.len(),
-            stereo_buf.len(),
+    /// Interleave the channels, of equal length, into frames
+    pub(crate) fn interleave(channels: &[PcmBuf]) -> PcmBuf {
+        let len = channels.first().map(|c| c.len()).unwrap_or(0);
+        assert!(
+            channels.iter().all(|c| c.len() == len),
             "Buffer lengths must be equal after pop()"
         );
-
-        let len = mono_buf.len();
-        let mut mix_buff = Vec::with_capacity(len * 2);
-
+        let mut frames = Vec::with_capacity(len * channels.len());
         for i in 0..len {
-            mix_buff.push(mono_buf[i]); // Left channel
-            mix_buff.push(stereo_buf[i]); // Right channel
+            frames.extend(channels.iter().map(|c| c[i]));
         }
-
-        mix_buff
+        frames
     }
 
-    /// Write mixed audio data to file
-    async fn write_audio_data(
-        &self,
-        file: &mut File,
-        mono_buf: &PcmBuf,
-        stereo_buf: &PcmBuf,
-    ) -> Result<usize> {
-        let max_len = mono_buf.len().max(stereo_buf.len());
-        if max_len == 0 {
+    /// Write interleaved audio data to file, returns the frames written
+    async fn write_audio_data(&self, file: &mut File, channels: &[PcmBuf]) -> Result<usize> {
+        let len = channels.first().map(|c| c.len()).unwrap_or(0);
+        if len == 0 {
             return Ok(0);
         }
 
-        let mix_buff = Self::mix_buffers(mono_buf, stereo_buf);
+        let frames = Self::interleave(channels);
 
         file.seek(std::io::SeekFrom::End(0)).await?;
-        file.write_all(&samples_to_bytes(&mix_buff)).await?;
+        file.write_all(&samples_to_bytes(&frames)).await?;
 
-        Ok(max_len)
+        Ok(len)
     }
 
     /// Process buffers with quality checks and write to file
-    async fn process_buffers(
-        &self,
-        file: &mut File,
-        mono_buf: PcmBuf,
-        stereo_buf: PcmBuf,
-    ) -> Result<()> {
-        // Skip if no data
-        if mono_buf.is_empty() && stereo_buf.is_empty() {
-            return Ok(());
-        }
+    async fn process_buffers(&self, file: &mut File, channels: Vec<PcmBuf>) -> Result<()> {
         // Write audio data
-        let samples_written = self.write_audio_data(file, &mono_buf, &stereo_buf).await?;
+        let samples_written = self.write_audio_data(file, &channels).await?;
         if samples_written > 0 {
             self.samples_written
                 .fetch_add(samples_written, Ordering::SeqCst);
@@ -380,17 +358,11 @@ impl Recorder {
     /// Flush all remaining buffer content
     async fn flush_buffers(&self, file: &mut File) -> Result<()> {
         loop {
-            let (mono_buf, stereo_buf) = self.pop(usize::MAX).await;
-
-            if mono_buf.is_empty() && stereo_buf.is_empty() {
+            let channels = self.pop(usize::MAX).await;
+            if channels.iter().all(|c| c.is_empty()) {
                 break;
             }
-
-            let samples_written = self.write_audio_data(file, &mono_buf, &stereo_buf).await?;
-            if samples_written > 0 {
-                self.samples_written
-                    .fetch_add(samples_written, Ordering::SeqCst);
-            }
+            self.process_buffers(file, channels).await?;
         }
 
         Ok(())
@@ -0,0 +1,289 @@
+//! Offline replay of captured media through a processor chain.
+//!
+//! A [`Timeline`] is loaded from a pcap capture (RTP over UDP) or from a
+//! JSON lines file of [`AudioFrame`]s, and [`Replayer`] feeds it through a
+//! jitter buffer and a [`ProcessorChain`] on a virtual clock, the same way
+//! the RTP track does, without sockets or timers. The output only depends on
+//! the capture, so DSP issues seen in production can be reproduced in tests.
+use super::{
+    codecs::CodecType,
+    jitter::JitterBuffer,
+    pipeline::packet_to_frame,
+    processor::ProcessorChain,
+    track::{
+        rtp::{RtpPacketKind, parse_rtp_packet},
+        track_codec::TrackCodec,
+    },
+};
+use crate::{AudioFrame, TrackId};
+use anyhow::{Result, anyhow};
+use std::{path::Path, time::Duration};
+
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+#[derive(Debug, Clone, Default)]
+pub struct Timeline {
+    /// Captured frames, ordered by arrival time
+    pub frames: Vec<AudioFrame>,
+}
+
+impl Timeline {
+    pub fn new(mut frames: Vec<AudioFrame>) -> Self {
+        frames.sort_by_key(|f| f.timestamp);
+        Self { frames }
+    }
+
+    /// Load a JSON lines file, one serialized `AudioFrame` per line
+    pub fn from_jsonl(text: &str) -> Result<Self> {
+        let mut frames = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let frame = serde_json::from_str::<AudioFrame>(line)
+                .map_err(|e| anyhow!("invalid frame at line {}: {}", n + 1, e))?;
+            frames.push(frame);
+        }
+        Ok(Self::new(frames))
+    }
+
+    pub fn from_jsonl_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_jsonl(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn to_jsonl(&self) -> Result<String> {
+        let mut text = String::new();
+        for frame in self.frames.iter() {
+            text.push_str(&serde_json::to_string(frame)?);
+            text.push('\n');
+        }
+        Ok(text)
+    }
+
+    /// Load the RTP audio packets of a pcap capture. Each RTP stream becomes
+    /// a track named after its SSRC (`{:08x}`), non RTP packets are skipped.
+    pub fn from_pcap(data: &[u8]) -> Result<Self> {
+        Self::from_pcap_with_payload_types(data, &[])
+    }
+
+    /// Like [`Timeline::from_pcap`], with the payload types negotiated in the
+    /// SDP of the captured call, so that dynamic ones are kept at their rate
+    pub fn from_pcap_with_payload_types(
+        data: &[u8],
+        payload_types: &[(u8, CodecType)],
+    ) -> Result<Self> {
+        if data.len() < 24 {
+            return Err(anyhow!("pcap: file too short"));
+        }
+        let magic = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+        let (little_endian, nanos) = match magic {
+            0xa1b2c3d4 => (true, false),
+            0xa1b23c4d => (true, true),
+            0xd4c3b2a1 => (false, false),
+            0x4d3cb2a1 => (false, true),
+            _ => return Err(anyhow!("pcap: unknown magic {:08x}", magic)),
+        };
+        let read_u32 = |buf: &[u8]| {
+            let bytes = [buf[0], buf[1], buf[2], buf[3]];
+            if little_endian {
+                u32::from_le_bytes(bytes)
+            } else {
+                u32::from_be_bytes(bytes)
+            }
+        };
+        let linktype = read_u32(&data[20..]);
+        let codec = TrackCodec::new();
+        codec.set_payload_types(payload_types);
+
+        let mut frames = Vec::new();
+        let mut offset = 24;
+        while offset + 16 <= data.len() {
+            let ts_sec = read_u32(&data[offset..]) as u64;
+            let ts_frac = read_u32(&data[offset + 4..]) as u64;
+            let incl_len = read_u32(&data[offset + 8..]) as usize;
+            offset += 16;
+            if offset + incl_len > data.len() {
+                return Err(anyhow!("pcap: truncated record at offset {}", offset));
+            }
+            let record = &data[offset..offset + incl_len];
+            offset += incl_len;
+
+            let timestamp = ts_sec * 1000
+                + if nanos {
+                    ts_frac / 1_000_000
+                } else {
+                    ts_frac / 1000
+                };
+            let payload = match udp_payload(linktype, record) {
+                Some(payload) => payload,
+                None => continue,
+            };
+            if let Some(frame) = rtp_frame(&codec, payload, timestamp) {
+                frames.push(frame);
+            }
+        }
+        Ok(Self::new(frames))
+    }
+
+    pub fn from_pcap_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_pcap(&std::fs::read(path)?)
+    }
+
+    pub fn track_ids(&self) -> Vec<TrackId> {
+        let mut track_ids = Vec::new();
+        for frame in self.frames.iter() {
+            if !track_ids.contains(&frame.track_id) {
+                track_ids.push(frame.track_id.clone());
+            }
+        }
+        track_ids
+    }
+
+    /// Time between the first and the last frame (in ms)
+    pub fn duration(&self) -> u64 {
+        match (self.frames.first(), self.frames.last()) {
+            (Some(first), Some(last)) => last.timestamp - first.timestamp,
+            _ => 0,
+        }
+    }
+}
+
+fn udp_payload(linktype: u32, record: &[u8]) -> Option<&[u8]> {
+    let (mut ethertype, mut ip) = match linktype {
+        LINKTYPE_ETHERNET if record.len() >= 14 => {
+            (u16::from_be_bytes([record[12], record[13]]), &record[14..])
+        }
+        LINKTYPE_LINUX_SLL if record.len() >= 16 => {
+            (u16::from_be_bytes([record[14], record[15]]), &record[16..])
+        }
+        LINKTYPE_LINUX_SLL2 if record.len() >= 20 => {
+            (u16::from_be_bytes([record[0], record[1]]), &record[20..])
+        }
+        LINKTYPE_RAW if !record.is_empty() => match record[0] >> 4 {
+            4 => (0x0800, record),
+            6 => (0x86dd, record),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    // 802.1Q VLAN tag
+    if ethertype == 0x8100 && ip.len() >= 4 {
+        ethertype = u16::from_be_bytes([ip[2], ip[3]]);
+        ip = &ip[4..];
+    }
+    let udp = match ethertype {
+        0x0800 if ip.len() >= 20 => {
+            let header_len = ((ip[0] & 0x0f) as usize) * 4;
+            let fragment = u16::from_be_bytes([ip[6], ip[7]]) & 0x3fff;
+            if ip[9] != 17 || fragment != 0 || ip.len() < header_len {
+                return None;
+            }
+            &ip[header_len..]
+        }
+        0x86dd if ip.len() >= 40 => {
+            if ip[6] != 17 {
+                return None;
+            }
+            &ip[40..]
+        }
+        _ => return None,
+    };
+    if udp.len() < 8 {
+        return None;
+    }
+    let udp_len = (u16::from_be_bytes([udp[4], udp[5]]) as usize).clamp(8, udp.len());
+    Some(&udp[8..udp_len])
+}
+
+fn rtp_frame(codec: &TrackCodec, payload: &[u8], timestamp: u64) -> Option<AudioFrame> {
+    let packet = match parse_rtp_packet(payload).ok()? {
+        RtpPacketKind::Rtp(packet) => packet,
+        _ => return None,
+    };
+    let payload_type = packet.header.payload_type;
+    if !codec.is_audio(payload_type) {
+        return None;
+    }
+    let track_id = format!("{:08x}", packet.header.ssrc);
+    let sample_rate = codec.sample_rate(payload_type);
+    Some(packet_to_frame(&track_id, packet, sample_rate, timestamp))
+}
+
+pub struct Replayer {
+    timeline: Timeline,
+    ptime: Duration,
+    jitter_buffer: bool,
+}
+
+impl Replayer {
+    pub fn new(timeline: Timeline) -> Self {
+        Self {
+            timeline,
+            ptime: Duration::from_millis(20),
+            jitter_buffer: true,
+        }
+    }
+
+    pub fn with_ptime(mut self, ptime: Duration) -> Self {
+        self.ptime = ptime;
+        self
+    }
+
+    /// Feed frames straight to the processor chain in capture order
+    /// instead of pacing them through a jitter buffer
+    pub fn with_jitter_buffer(mut self, jitter_buffer: bool) -> Self {
+        self.jitter_buffer = jitter_buffer;
+        self
+    }
+
+    pub fn timeline(&self) -> &Timeline {
+        &self.timeline
+    }
+
+    /// Replay one track through the processor chain and return the frames
+    /// that came out of it, in playout order
+    pub fn replay(
+        &self,
+        track_id: &TrackId,
+        processor_chain: &ProcessorChain,
+    ) -> Result<Vec<AudioFrame>> {
+        let mut frames = self
+            .timeline
+            .frames
+            .iter()
+            .filter(|f| &f.track_id == track_id)
+            .cloned()
+            .peekable();
+        let mut output = Vec::new();
+        if !self.jitter_buffer {
+            for mut frame in frames {
+                processor_chain.process_frame(&mut frame)?;
+                output.push(frame);
+            }
+            return Ok(output);
+        }
+
+        let ptime = self.ptime.as_millis().max(1) as u64;
+        let mut now = match frames.peek() {
+            Some(frame) => frame.timestamp,
+            None => return Ok(output),
+        };
+        let mut jitter = JitterBuffer::new();
+        loop {
+            while let Some(frame) = frames.next_if(|f| f.timestamp <= now) {
+                jitter.push(frame);
+            }
+            if let Some(mut frame) = jitter.pop() {
+                processor_chain.process_frame(&mut frame)?;
+                output.push(frame);
+            } else if frames.peek().is_none() {
+                break;
+            }
+            now += ptime;
+        }
+        Ok(output)
+    }
+}
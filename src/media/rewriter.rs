@@ -0,0 +1,236 @@
+//! Rewrites the RTP relayed from one leg to another without transcoding.
+//!
+//! The packets keep their payload and timing but go out under the SSRC of
+//! the sending track, with sequence numbers and timestamps offset so that a
+//! change of source (a transfer, a new fork) looks like one continuous
+//! stream to the receiver. Header extensions negotiated on both legs are
+//! renumbered to the ids of the outgoing leg, any other is stripped.
+use bytes::Bytes;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use webrtc::rtp::{
+    header::{EXTENSION_PROFILE_ONE_BYTE, EXTENSION_PROFILE_TWO_BYTE, Extension},
+    packet::Packet,
+};
+
+pub const ABS_SEND_TIME_URI: &str = "http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time";
+pub const SDES_MID_URI: &str = "urn:ietf:params:rtp-hdrext:sdes:mid";
+
+/// Samples per packet assumed until two packets of a source were seen
+const DEFAULT_TIMESTAMP_STEP: u32 = 160;
+
+/// How RTP relayed from another leg is sent
+#[derive(Debug, Clone, Deserialize, JsonSchema, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct RtpRewriteOption {
+    /// Send with the track's own SSRC, sequence numbers and timestamps
+    /// continuing across changes of source
+    pub ssrc: bool,
+    /// Header extensions relayed, by name (`abs-send-time`, `mid`) or URI.
+    /// All others are stripped
+    pub header_extensions: Vec<String>,
+}
+
+impl Default for RtpRewriteOption {
+    fn default() -> Self {
+        Self {
+            ssrc: true,
+            header_extensions: Vec::new(),
+        }
+    }
+}
+
+impl RtpRewriteOption {
+    /// URIs of the relayed header extensions
+    pub fn extension_uris(&self) -> Vec<String> {
+        self.header_extensions
+            .iter()
+            .map(|name| match name.as_str() {
+                "abs-send-time" => ABS_SEND_TIME_URI.to_string(),
+                "mid" => SDES_MID_URI.to_string(),
+                uri => uri.to_string(),
+            })
+            .collect()
+    }
+}
+
+/// Header extension ids by URI from the `a=extmap` lines of an SDP
+pub fn parse_extmaps(sdp: &str) -> HashMap<String, u8> {
+    sdp.lines()
+        .filter_map(|line| line.trim().strip_prefix("a=extmap:"))
+        .filter_map(|extmap| {
+            // id[/direction] uri [attributes]
+            let mut parts = extmap.split_whitespace();
+            let id = parts.next()?.split('/').next()?.parse::<u8>().ok()?;
+            let uri = parts.next()?;
+            (id != 0).then(|| (uri.to_string(), id))
+        })
+        .collect()
+}
+
+/// The `a=mid` of the first media of an SDP
+pub fn parse_mid(sdp: &str) -> Option<String> {
+    sdp.lines()
+        .find_map(|line| line.trim().strip_prefix("a=mid:"))
+        .map(|mid| mid.to_string())
+}
+
+struct Source {
+    ssrc: u32,
+    sequence_offset: u16,
+    timestamp_offset: u32,
+}
+
+pub struct RtpRewriter {
+    ssrc: u32,
+    rewrite_ssrc: bool,
+    /// URIs of the relayed extensions
+    relayed: Vec<String>,
+    /// URIs by id, as negotiated on the leg the packets come from
+    ingress: HashMap<u8, String>,
+    /// Ids by URI, as negotiated on the leg the packets go to
+    egress: Option<HashMap<String, u8>>,
+    /// Value of the `mid` extension on the outgoing leg
+    mid: Option<String>,
+    source: Option<Source>,
+    /// Sequence number and timestamp of the last packet sent
+    last: Option<(u16, u32)>,
+    timestamp_step: u32,
+}
+
+impl RtpRewriter {
+    pub fn new(ssrc: u32, option: &RtpRewriteOption) -> Self {
+        Self {
+            ssrc,
+            rewrite_ssrc: option.ssrc,
+            relayed: option.extension_uris(),
+            ingress: HashMap::new(),
+            egress: None,
+            mid: None,
+            source: None,
+            last: None,
+            timestamp_step: DEFAULT_TIMESTAMP_STEP,
+        }
+    }
+
+    /// The extensions of the leg the packets come from
+    pub fn set_ingress(&mut self, extmaps: HashMap<String, u8>) {
+        self.ingress = extmaps.into_iter().map(|(uri, id)| (id, uri)).collect();
+    }
+
+    /// The extensions of the leg the packets go to
+    pub fn set_egress(&mut self, extmaps: HashMap<String, u8>) {
+        self.egress = Some(extmaps);
+    }
+
+    /// The `a=extmap` entries to put in the outgoing leg's SDP: those the
+    /// peer offered when answering, else all relayed extensions
+    pub fn extmaps(&self) -> Vec<(u8, String)> {
+        match self.egress.as_ref() {
+            Some(egress) => self
+                .relayed
+                .iter()
+                .filter_map(|uri| Some((*egress.get(uri)?, uri.clone())))
+                .collect(),
+            None => self
+                .relayed
+                .iter()
+                .enumerate()
+                .map(|(i, uri)| (i as u8 + 1, uri.clone()))
+                .collect(),
+        }
+    }
+
+    /// Sets the `mid` the relayed packets carry, the outgoing leg's own
+    pub fn set_mid(&mut self, mid: Option<String>) {
+        self.mid = mid;
+    }
+
+    pub fn rewrite(&mut self, packet: &mut Packet) {
+        if self.rewrite_ssrc {
+            self.rewrite_ssrc(packet);
+        }
+        self.rewrite_extensions(packet);
+    }
+
+    fn rewrite_ssrc(&mut self, packet: &mut Packet) {
+        let header = &mut packet.header;
+        if self.source.as_ref().map(|s| s.ssrc) != Some(header.ssrc) {
+            // the first packet of a source goes right after the last one sent
+            let (sequence_offset, timestamp_offset) = match self.last {
+                Some((seq, ts)) => (
+                    seq.wrapping_add(1).wrapping_sub(header.sequence_number),
+                    ts.wrapping_add(self.timestamp_step)
+                        .wrapping_sub(header.timestamp),
+                ),
+                None => (0, 0),
+            };
+            self.source = Some(Source {
+                ssrc: header.ssrc,
+                sequence_offset,
+                timestamp_offset,
+            });
+            // a new talkspurt, receivers resync their jitter buffers
+            header.marker |= self.last.is_some();
+        }
+        let Some(source) = self.source.as_ref() else {
+            return;
+        };
+        header.ssrc = self.ssrc;
+        header.sequence_number = header.sequence_number.wrapping_add(source.sequence_offset);
+        header.timestamp = header.timestamp.wrapping_add(source.timestamp_offset);
+
+        if let Some((seq, ts)) = self.last
+            && header.sequence_number == seq.wrapping_add(1)
+        {
+            let step = header.timestamp.wrapping_sub(ts);
+            if step > 0 && step < u32::MAX / 2 {
+                self.timestamp_step = step;
+            }
+        }
+        match self.last {
+            // reordered packets don't move the stream back
+            Some((seq, _)) if (header.sequence_number.wrapping_sub(seq) as i16) <= 0 => {}
+            _ => self.last = Some((header.sequence_number, header.timestamp)),
+        }
+    }
+
+    fn rewrite_extensions(&mut self, packet: &mut Packet) {
+        let header = &mut packet.header;
+        let extensions = std::mem::take(&mut header.extensions)
+            .into_iter()
+            .filter_map(|extension| {
+                let uri = self.ingress.get(&extension.id)?;
+                if !self.relayed.contains(uri) {
+                    return None;
+                }
+                let id = *self.egress.as_ref()?.get(uri)?;
+                let payload = match (uri.as_str(), self.mid.as_ref()) {
+                    (SDES_MID_URI, Some(mid)) => Bytes::from(mid.clone().into_bytes()),
+                    _ => extension.payload,
+                };
+                Some(Extension { id, payload })
+            })
+            .collect::<Vec<_>>();
+
+        header.extensions_padding = 0;
+        if extensions.is_empty() {
+            header.extension = false;
+            header.extension_profile = 0;
+            return;
+        }
+        // RFC 8285: one-byte headers fit ids 1-14 and up to 16 bytes
+        let one_byte = extensions
+            .iter()
+            .all(|e| e.id < 15 && !e.payload.is_empty() && e.payload.len() <= 16);
+        header.extension = true;
+        header.extension_profile = if one_byte {
+            EXTENSION_PROFILE_ONE_BYTE
+        } else {
+            EXTENSION_PROFILE_TWO_BYTE
+        };
+        header.extensions = extensions;
+    }
+}
@@ -0,0 +1,145 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::{
+    Mutex,
+    atomic::{AtomicU64, Ordering},
+};
+use std::time::{Duration, Instant};
+
+/// Egress bandwidth cap of a call's RTP
+#[derive(Debug, Clone, Deserialize, JsonSchema, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+#[serde(default)]
+pub struct ShapingOption {
+    /// Maximum rate (in kbit/s), RTP headers included
+    pub bitrate: u32,
+    /// Bytes that may go out back to back above the rate, 0 for 100ms worth
+    pub burst: u32,
+    /// Longest a packet may be held back to fit the rate before it is
+    /// dropped instead (in ms)
+    pub latency: u32,
+}
+
+impl Default for ShapingOption {
+    fn default() -> Self {
+        Self {
+            bitrate: 128,
+            burst: 0,
+            latency: 20,
+        }
+    }
+}
+
+/// Classic token bucket: tokens are bytes, refilled at `rate` per second up
+/// to `capacity`. A packet that has to wait borrows its tokens, so the
+/// packets after it queue up behind it
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate: f64, capacity: f64, now: Instant) -> Self {
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            last: now,
+        }
+    }
+
+    /// Takes `size` tokens and returns how long to wait before sending, or
+    /// `None` when the wait would exceed `max_wait` and nothing was taken
+    pub fn reserve(&mut self, size: usize, now: Instant, max_wait: Duration) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = self.last.max(now);
+
+        let size = size as f64;
+        if self.tokens >= size {
+            self.tokens -= size;
+            return Some(Duration::ZERO);
+        }
+        let wait = Duration::from_secs_f64((size - self.tokens) / self.rate);
+        if wait > max_wait {
+            return None;
+        }
+        self.tokens -= size;
+        Some(wait)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShapingStats {
+    pub sent_packets: u64,
+    /// Packets held back to fit the rate
+    pub delayed_packets: u64,
+    pub dropped_packets: u64,
+    pub dropped_bytes: u64,
+}
+
+/// Holds back or drops outgoing packets so a track stays under its bitrate
+#[derive(Debug)]
+pub struct Shaper {
+    bucket: Mutex<TokenBucket>,
+    max_delay: Duration,
+    sent_packets: AtomicU64,
+    delayed_packets: AtomicU64,
+    dropped_packets: AtomicU64,
+    dropped_bytes: AtomicU64,
+}
+
+impl Shaper {
+    pub fn new(option: &ShapingOption) -> Self {
+        let rate = option.bitrate.max(1) as f64 * 1000.0 / 8.0;
+        let burst = match option.burst {
+            0 => (rate / 10.0).max(1500.0),
+            burst => burst as f64,
+        };
+        Self {
+            bucket: Mutex::new(TokenBucket::new(rate, burst, Instant::now())),
+            max_delay: Duration::from_millis(option.latency as u64),
+            sent_packets: AtomicU64::new(0),
+            delayed_packets: AtomicU64::new(0),
+            dropped_packets: AtomicU64::new(0),
+            dropped_bytes: AtomicU64::new(0),
+        }
+    }
+
+    /// Waits until a packet of `size` bytes fits the rate, returns false
+    /// when it has to be dropped instead
+    pub async fn admit(&self, size: usize) -> bool {
+        let wait = match self.bucket.lock() {
+            Ok(mut bucket) => bucket.reserve(size, Instant::now(), self.max_delay),
+            Err(_) => Some(Duration::ZERO),
+        };
+        match wait {
+            Some(wait) => {
+                if !wait.is_zero() {
+                    self.delayed_packets.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(wait).await;
+                }
+                self.sent_packets.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            None => {
+                self.dropped_packets.fetch_add(1, Ordering::Relaxed);
+                self.dropped_bytes.fetch_add(size as u64, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    pub fn stats(&self) -> ShapingStats {
+        ShapingStats {
+            sent_packets: self.sent_packets.load(Ordering::Relaxed),
+            delayed_packets: self.delayed_packets.load(Ordering::Relaxed),
+            dropped_packets: self.dropped_packets.load(Ordering::Relaxed),
+            dropped_bytes: self.dropped_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -1,13 +1,20 @@
 use crate::event::{EventSender, SessionEvent};
-use crate::media::dtmf::DtmfDetector;
+use crate::media::dtmf::{DtmfDetector, DtmfMode, DtmfSuppressor, DtmfTone, InbandDtmfOption};
 use crate::media::{
-    processor::Processor,
+    error::{MediaError, MediaResult},
+    latency::LatencyProbe,
+    leveler::{LevelMatchOption, LevelMatcher},
+    meter::TrackLevels,
+    mixer::{DuckingOption, MediaMixer, SuperviseMode, TopSpeakersOption},
+    processor::{Processor, TapPoint},
     recorder::{Recorder, RecorderOption},
-    track::{Track, TrackPacketReceiver, TrackPacketSender},
+    track::{Track, TrackPacketReceiver, TrackPacketSender, send_queue::SendStats},
 };
 use crate::{AudioFrame, Samples, TrackId};
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::path::Path;
 use std::time::Duration;
 use tokio::task::JoinHandle;
@@ -19,16 +26,76 @@ use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
 use uuid;
 
+/// Which side of a track a control applies to: `rx` is the audio received
+/// from the track (what the others hear), `tx` the audio sent to it.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackDirection {
+    #[default]
+    Rx,
+    Tx,
+    Both,
+}
+
+impl TrackDirection {
+    fn has_rx(&self) -> bool {
+        matches!(self, TrackDirection::Rx | TrackDirection::Both)
+    }
+    fn has_tx(&self) -> bool {
+        matches!(self, TrackDirection::Tx | TrackDirection::Both)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackControl {
+    pub rx_muted: bool,
+    pub tx_muted: bool,
+    /// gain in dB
+    pub rx_gain: f32,
+    /// gain in dB
+    pub tx_gain: f32,
+}
+
+/// How DTMF reaches a leg, and the digits still to dial to it in band
+#[derive(Debug, Default)]
+struct DtmfLeg {
+    mode: DtmfMode,
+    tones: VecDeque<DtmfTone>,
+}
+
+/// A track of a stream as seen from the outside
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackState {
+    pub id: TrackId,
+    pub ssrc: u32,
+    pub codec: String,
+    pub samplerate: u32,
+    /// packet time in milliseconds
+    pub ptime: u64,
+    pub rx_muted: bool,
+    pub tx_muted: bool,
+    pub rx_gain: f32,
+    pub tx_gain: f32,
+    /// The send queue of a track that sends from one
+    pub send: Option<SendStats>,
+}
+
 pub struct MediaStream {
     id: String,
     cancel_token: CancellationToken,
     recorder_option: Mutex<Option<RecorderOption>>,
     tracks: Mutex<HashMap<TrackId, (Box<dyn Track>, DtmfDetector)>>,
+    mixer: std::sync::Mutex<MediaMixer>,
+    controls: std::sync::Mutex<HashMap<TrackId, TrackControl>>,
+    dtmf_legs: std::sync::Mutex<HashMap<TrackId, DtmfLeg>>,
+    latency_probe: std::sync::Mutex<Option<LatencyProbe>>,
+    level_matcher: std::sync::Mutex<Option<LevelMatcher>>,
     event_sender: EventSender,
     pub packet_sender: TrackPacketSender,
     packet_receiver: Mutex<Option<TrackPacketReceiver>>,
-    recorder_sender: mpsc::UnboundedSender<AudioFrame>,
-    recorder_receiver: Mutex<Option<mpsc::UnboundedReceiver<AudioFrame>>>,
+    recorder_sender: mpsc::UnboundedSender<(TapPoint, AudioFrame)>,
+    recorder_receiver: Mutex<Option<mpsc::UnboundedReceiver<(TapPoint, AudioFrame)>>>,
     recorder_handle: Mutex<Option<JoinHandle<()>>>,
 }
 
@@ -75,6 +142,11 @@ impl MediaStreamBuilder {
             cancel_token,
             recorder_option: Mutex::new(self.recorder_config),
             tracks,
+            mixer: std::sync::Mutex::new(MediaMixer::new()),
+            controls: std::sync::Mutex::new(HashMap::new()),
+            dtmf_legs: std::sync::Mutex::new(HashMap::new()),
+            latency_probe: std::sync::Mutex::new(None),
+            level_matcher: std::sync::Mutex::new(None),
             event_sender: self.event_sender,
             packet_sender: track_packet_sender,
             packet_receiver: Mutex::new(Some(track_packet_receiver)),
@@ -131,6 +203,11 @@ impl MediaStream {
     }
 
     pub async fn remove_track(&self, id: &TrackId) {
+        self.mixer.lock().unwrap().remove_track(id);
+        self.controls.lock().unwrap().remove(id);
+        if let Some(matcher) = self.level_matcher.lock().unwrap().as_mut() {
+            matcher.remove(id);
+        }
         if let Some((track, _)) = self.tracks.lock().await.remove(id) {
             match track.stop().await {
                 Ok(_) => {}
@@ -152,11 +229,19 @@ impl MediaStream {
     }
     pub async fn update_track(&self, mut track: Box<dyn Track>, play_id: Option<String>) {
         self.remove_track(track.id()).await;
-        if self.recorder_option.lock().await.is_some() {
-            track.insert_processor(Box::new(RecorderProcessor::new(
-                self.recorder_sender.clone(),
-            )));
+        if let Some(recorder_option) = self.recorder_option.lock().await.as_ref() {
+            for point in recorder_option.tap.points() {
+                track.processor_chain().add_tap(
+                    *point,
+                    Box::new(RecorderProcessor::new(self.recorder_sender.clone(), *point)),
+                );
+            }
         }
+        self.apply_dtmf_mode(track.as_mut());
+        let speakers = self.mixer.lock().unwrap().speakers();
+        track
+            .processor_chain()
+            .set_speaker_selection(Some(speakers));
         match track
             .start(self.event_sender.clone(), self.packet_sender.clone())
             .await
@@ -188,46 +273,240 @@ impl MediaStream {
         }
     }
 
-    pub async fn mute_track(&self, id: Option<TrackId>) {
-        if let Some(id) = id {
-            if let Some((track, _)) = self.tracks.lock().await.get_mut(&id) {
-                MuteProcessor::mute_track(track.as_mut());
+    /// Append a processor to a track's chain, false if the track is not found
+    pub async fn append_processor(&self, id: &TrackId, processor: Box<dyn Processor>) -> bool {
+        match self.tracks.lock().await.get_mut(id) {
+            Some((track, _)) => {
+                track.append_processor(processor);
+                true
             }
-        } else {
-            for (track, _) in self.tracks.lock().await.values_mut() {
-                MuteProcessor::mute_track(track.as_mut());
+            None => false,
+        }
+    }
+
+    pub async fn remove_processor<T: 'static>(&self, id: &TrackId) {
+        if let Some((track, _)) = self.tracks.lock().await.get_mut(id) {
+            track.processor_chain().remove_processor::<T>();
+        }
+    }
+
+    /// Set how DTMF is sent to and taken from each leg, the tracks may be
+    /// added later. Digits a leg dials in band are detected and muted in
+    /// the audio of the others, unless they take them in band too
+    pub async fn set_dtmf_modes(&self, modes: Vec<(TrackId, DtmfMode)>) {
+        {
+            let mut legs = self.dtmf_legs.lock().unwrap();
+            for (id, mode) in modes {
+                legs.entry(id).or_default().mode = mode;
             }
         }
+        for (track, _) in self.tracks.lock().await.values_mut() {
+            self.apply_dtmf_mode(track.as_mut());
+        }
+    }
+
+    pub fn dtmf_mode(&self, id: &TrackId) -> Option<DtmfMode> {
+        self.dtmf_legs.lock().unwrap().get(id).map(|leg| leg.mode)
+    }
+
+    /// Send a digit to a leg as its DTMF mode says: telephone-events, or
+    /// tones written over the audio it gets. SIP INFO is up to the dialog
+    pub async fn send_dtmf(
+        &self,
+        id: &TrackId,
+        digit: &str,
+        duration: Duration,
+    ) -> MediaResult<()> {
+        match self.dtmf_mode(id) {
+            Some(DtmfMode::Inband) => {
+                let tone = DtmfTone::new(digit, duration)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid DTMF digit"))?;
+                if let Some(leg) = self.dtmf_legs.lock().unwrap().get_mut(id) {
+                    leg.tones.push_back(tone);
+                }
+                Ok(())
+            }
+            Some(DtmfMode::Info) => Err(anyhow::anyhow!("DTMF of {} goes in SIP INFO", id).into()),
+            _ => match self.tracks.lock().await.get(id) {
+                Some((track, _)) => track.send_dtmf(digit, duration).await,
+                None => Err(anyhow::anyhow!("track not found: {}", id).into()),
+            },
+        }
+    }
+
+    fn apply_dtmf_mode(&self, track: &mut dyn Track) {
+        let suppress = {
+            let legs = self.dtmf_legs.lock().unwrap();
+            // tracks of no leg keep the processors the call gave them
+            let Some(leg) = legs.get(track.id()) else {
+                return;
+            };
+            leg.mode == DtmfMode::Inband
+                && legs
+                    .iter()
+                    .any(|(id, leg)| id != track.id() && leg.mode != DtmfMode::Inband)
+        };
+        let chain = track.processor_chain();
+        chain.remove_processor::<DtmfSuppressor>();
+        if suppress {
+            chain.insert_processor(Box::new(DtmfSuppressor::new(
+                self.event_sender.clone(),
+                InbandDtmfOption::default(),
+            )));
+        }
+    }
+
+    /// Measure the latency of a track, its far end must loop the audio back
+    /// or bridge it into another leg of the stream
+    pub async fn probe_latency(&self, id: &TrackId, timeout: Duration) {
+        info!(session_id = self.id, track_id = id, "start latency probe");
+        *self.latency_probe.lock().unwrap() = Some(LatencyProbe::new(id.clone(), timeout));
+    }
+
+    /// Gain the legs towards a common speech level, None disables it
+    pub fn set_level_match(&self, option: Option<LevelMatchOption>) {
+        *self.level_matcher.lock().unwrap() = option.map(LevelMatcher::new);
+    }
+
+    /// Levels of the tracks the mixer picks speakers by, for the tracks to
+    /// report to, e.g. from their audio level extensions
+    pub fn levels(&self) -> TrackLevels {
+        self.mixer.lock().unwrap().levels()
+    }
+
+    /// Mix only the loudest tracks, for rooms with many participants. None
+    /// mixes them all
+    pub fn set_top_speakers(&self, option: Option<TopSpeakersOption>) {
+        self.mixer.lock().unwrap().set_top_speakers(option);
+    }
+
+    /// Duck the other audio while `id` is active, None disables it
+    pub async fn set_ducking(&self, id: &TrackId, option: Option<DuckingOption>) {
+        self.mixer.lock().unwrap().set_ducking(id, option);
+    }
+
+    pub async fn mute_track(&self, id: Option<TrackId>, direction: TrackDirection) {
+        self.update_controls(id, |track, control| {
+            if direction.has_rx() {
+                MuteProcessor::mute_track(track);
+                control.rx_muted = true;
+            }
+            if direction.has_tx() {
+                control.tx_muted = true;
+            }
+        })
+        .await;
+    }
+
+    pub async fn unmute_track(&self, id: Option<TrackId>, direction: TrackDirection) {
+        self.update_controls(id, |track, control| {
+            if direction.has_rx() {
+                MuteProcessor::unmute_track(track);
+                control.rx_muted = false;
+            }
+            if direction.has_tx() {
+                control.tx_muted = false;
+            }
+        })
+        .await;
     }
 
-    pub async fn unmute_track(&self, id: Option<TrackId>) {
-        if let Some(id) = id {
-            if let Some((track, _)) = self.tracks.lock().await.get_mut(&id) {
-                MuteProcessor::unmute_track(track.as_mut());
+    /// Set the gain (in dB) of a track, or of all tracks when `id` is None
+    pub async fn set_track_gain(&self, id: Option<TrackId>, gain: f32, direction: TrackDirection) {
+        self.update_controls(id, |track, control| {
+            if direction.has_rx() {
+                GainProcessor::set_track_gain(track, gain);
+                control.rx_gain = gain;
             }
-        } else {
-            for (track, _) in self.tracks.lock().await.values_mut() {
-                MuteProcessor::unmute_track(track.as_mut());
+            if direction.has_tx() {
+                control.tx_gain = gain;
             }
+        })
+        .await;
+    }
+
+    pub async fn track_states(&self) -> Vec<TrackState> {
+        let tracks = self.tracks.lock().await;
+        let controls = self.controls.lock().unwrap();
+        tracks
+            .values()
+            .map(|(track, _)| {
+                let control = controls.get(track.id()).cloned().unwrap_or_default();
+                let config = track.config();
+                TrackState {
+                    id: track.id().clone(),
+                    ssrc: track.ssrc(),
+                    codec: config.codec.mime_type().to_string(),
+                    samplerate: config.samplerate,
+                    ptime: config.ptime.as_millis() as u64,
+                    rx_muted: control.rx_muted,
+                    tx_muted: control.tx_muted,
+                    rx_gain: control.rx_gain,
+                    tx_gain: control.tx_gain,
+                    send: track.send_stats(),
+                }
+            })
+            .collect()
+    }
+
+    async fn update_controls<F>(&self, id: Option<TrackId>, mut update: F)
+    where
+        F: FnMut(&mut dyn Track, &mut TrackControl),
+    {
+        let mut tracks = self.tracks.lock().await;
+        let mut controls = self.controls.lock().unwrap();
+        for (track, _) in tracks.values_mut() {
+            if id.as_ref().is_some_and(|id| id != track.id()) {
+                continue;
+            }
+            let control = controls.entry(track.id().clone()).or_default();
+            update(track.as_mut(), control);
+            self.event_sender
+                .send(SessionEvent::TrackControl {
+                    track_id: track.id().clone(),
+                    timestamp: crate::get_timestamp(),
+                    rx_muted: control.rx_muted,
+                    tx_muted: control.tx_muted,
+                    rx_gain: control.rx_gain,
+                    tx_gain: control.tx_gain,
+                })
+                .ok();
         }
     }
+
+    /// Route the supervisor's audio: `listen` to nobody, `whisper` only to
+    /// the agent, `barge` to everyone. The supervisor always hears the call.
+    pub async fn supervise(&self, supervisor: &TrackId, agent: &TrackId, mode: SuperviseMode) {
+        info!(
+            session_id = self.id,
+            supervisor,
+            agent,
+            ?mode,
+            "update supervise mode"
+        );
+        self.mixer
+            .lock()
+            .unwrap()
+            .supervise(supervisor, agent, mode);
+    }
 }
 
 #[derive(Clone)]
 pub struct RecorderProcessor {
-    sender: mpsc::UnboundedSender<AudioFrame>,
+    sender: mpsc::UnboundedSender<(TapPoint, AudioFrame)>,
+    point: TapPoint,
 }
 
 impl RecorderProcessor {
-    pub fn new(sender: mpsc::UnboundedSender<AudioFrame>) -> Self {
-        Self { sender }
+    pub fn new(sender: mpsc::UnboundedSender<(TapPoint, AudioFrame)>, point: TapPoint) -> Self {
+        Self { sender, point }
     }
 }
 
 impl Processor for RecorderProcessor {
-    fn process_frame(&self, frame: &mut AudioFrame) -> Result<()> {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
         let frame_clone = frame.clone();
-        let _ = self.sender.send(frame_clone);
+        let _ = self.sender.send((self.point, frame_clone));
         Ok(())
     }
 }
@@ -283,9 +562,26 @@ impl MediaStream {
 
     async fn handle_forward_track(&self, mut packet_receiver: TrackPacketReceiver) {
         let event_sender = self.event_sender.clone();
-        while let Some(packet) = packet_receiver.recv().await {
+        while let Some(mut packet) = packet_receiver.recv().await {
+            self.expire_latency_probe();
+            if let Some(matcher) = self.level_matcher.lock().unwrap().as_mut() {
+                matcher.process(&mut packet);
+            }
+            let mut tracks = self.tracks.lock().await;
+            // payload types are the ones negotiated on the leg it came from
+            let source_chain = tracks
+                .get_mut(&packet.track_id)
+                .map(|(track, _)| track.processor_chain().clone());
+            let marker_arrived = tracks.get_mut(&packet.track_id).is_some_and(|(track, _)| {
+                let processing = track.processor_chain().processing_time();
+                self.latency_probe
+                    .lock()
+                    .unwrap()
+                    .as_mut()
+                    .is_some_and(|probe| probe.detect(&packet, processing))
+            });
             // Process the packet with each track
-            for (track, dtmf_detector) in self.tracks.lock().await.values() {
+            for (track, dtmf_detector) in tracks.values_mut() {
                 if &packet.track_id == track.id() {
                     match &packet.samples {
                         Samples::RTP {
@@ -300,6 +596,7 @@ impl MediaStream {
                                         track_id: packet.track_id.to_string(),
                                         timestamp: packet.timestamp,
                                         digit,
+                                        duration: Some(dtmf_detector.duration()),
                                     })
                                     .ok();
                             }
@@ -308,17 +605,131 @@ impl MediaStream {
                     }
                     continue;
                 }
-                if let Err(e) = track.send_packet(&packet).await {
-                    warn!(
-                        id = track.id(),
-                        "media_stream: Failed to send packet to track: {}", e
-                    );
+                let mut frame = {
+                    let mut mixer = self.mixer.lock().unwrap();
+                    if !mixer.is_routed(&packet.track_id, track.id()) {
+                        continue;
+                    }
+                    match mixer.mix(&packet, track.id()) {
+                        Some(frame) => frame,
+                        None => continue,
+                    }
+                };
+                if let Some(leg) = self.dtmf_legs.lock().unwrap().get_mut(track.id()) {
+                    match &mut frame.samples {
+                        // a leg that doesn't take telephone-events gets the
+                        // digits in its own mode instead
+                        Samples::RTP { payload_type, .. }
+                            if leg.mode != DtmfMode::Rfc4733
+                                && !source_chain
+                                    .as_ref()
+                                    .is_some_and(|chain| chain.is_audio(*payload_type)) =>
+                        {
+                            continue;
+                        }
+                        Samples::PCM { samples } => {
+                            if let Some(tone) = leg.tones.front_mut()
+                                && !tone.render(samples, frame.sample_rate)
+                            {
+                                leg.tones.pop_front();
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                if let Some(control) = self.controls.lock().unwrap().get(track.id()) {
+                    if control.tx_muted {
+                        match frame.samples {
+                            Samples::PCM { .. } => MuteProcessor.process_frame(&mut frame).ok(),
+                            // encoded audio can't be silenced, don't send it at all
+                            _ => continue,
+                        };
+                    } else if control.tx_gain != 0.0 {
+                        GainProcessor::new(control.tx_gain)
+                            .process_frame(&mut frame)
+                            .ok();
+                    }
+                }
+                let injected = match self.latency_probe.lock().unwrap().as_mut() {
+                    Some(probe) if probe.track_id() == track.id() => probe.inject(&mut frame),
+                    _ => false,
+                };
+                let send_start = std::time::Instant::now();
+                match track.send_frame(&frame).await {
+                    Ok(_) => {}
+                    // counted in the track's send stats
+                    Err(MediaError::BufferOverrun { .. }) => {
+                        debug!(id = track.id(), "media_stream: send queue of track full");
+                    }
+                    Err(e) => {
+                        warn!(
+                            id = track.id(),
+                            "media_stream: Failed to send packet to track: {}", e
+                        );
+                    }
                 }
+                if let Some(probe) = self.latency_probe.lock().unwrap().as_mut() {
+                    if injected {
+                        probe.on_encoded(send_start.elapsed());
+                    }
+                    if marker_arrived {
+                        probe.on_forwarded(track.id());
+                    }
+                }
+            }
+            if marker_arrived {
+                self.finish_latency_probe();
             }
         }
     }
 }
 
+impl MediaStream {
+    /// Emits the report of the latency probe once the marker arrived and
+    /// was forwarded to the bridged legs
+    fn finish_latency_probe(&self) {
+        let Some(mut probe) = self.latency_probe.lock().unwrap().take() else {
+            return;
+        };
+        let Some(report) = probe.finish() else {
+            return;
+        };
+        let track_id = probe.track_id().clone();
+        info!(
+            session_id = self.id,
+            track_id,
+            ?report,
+            "latency probe done"
+        );
+        self.event_sender
+            .send(SessionEvent::Latency {
+                track_id,
+                timestamp: crate::get_timestamp(),
+                report,
+            })
+            .ok();
+    }
+
+    /// Drops the latency probe with an error once the marker is overdue
+    fn expire_latency_probe(&self) {
+        let mut latency_probe = self.latency_probe.lock().unwrap();
+        let Some(probe) = latency_probe.take_if(|probe| probe.is_expired()) else {
+            return;
+        };
+        let track_id = probe.track_id();
+        warn!(session_id = self.id, track_id, "latency probe timeout");
+        self.event_sender
+            .send(SessionEvent::Error {
+                track_id: track_id.clone(),
+                timestamp: crate::get_timestamp(),
+                sender: "latency".to_string(),
+                error: "marker not received back before timeout".to_string(),
+                code: None,
+            })
+            .ok();
+    }
+}
+
 pub struct MuteProcessor;
 
 impl MuteProcessor {
@@ -336,7 +747,7 @@ impl MuteProcessor {
 }
 
 impl Processor for MuteProcessor {
-    fn process_frame(&self, frame: &mut AudioFrame) -> Result<()> {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
         match &mut frame.samples {
             Samples::PCM { samples } => {
                 samples.fill(0);
@@ -350,3 +761,37 @@ impl Processor for MuteProcessor {
         Ok(())
     }
 }
+
+/// Scales PCM samples by a fixed gain, clamping instead of wrapping
+pub struct GainProcessor {
+    factor: f32,
+}
+
+impl GainProcessor {
+    pub fn new(gain_db: f32) -> Self {
+        Self {
+            factor: 10f32.powf(gain_db / 20.0),
+        }
+    }
+
+    /// Replace the gain of the track, 0 dB removes the processor
+    pub fn set_track_gain(track: &mut dyn Track, gain_db: f32) {
+        let chain = track.processor_chain();
+        chain.remove_processor::<GainProcessor>();
+        if gain_db != 0.0 {
+            chain.insert_processor(Box::new(GainProcessor::new(gain_db)));
+        }
+    }
+}
+
+impl Processor for GainProcessor {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
+        if let Samples::PCM { samples } = &mut frame.samples {
+            for sample in samples.iter_mut() {
+                *sample =
+                    (*sample as f32 * self.factor).clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            }
+        }
+        Ok(())
+    }
+}
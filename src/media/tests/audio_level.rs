@@ -0,0 +1,118 @@
+use crate::{
+    AudioFrame, Samples,
+    event::create_event_sender,
+    media::{
+        audio_level::{
+            AUDIO_LEVEL_URI, SILENCE_LEVEL, audio_level, read_audio_level, write_audio_level,
+        },
+        meter::TrackLevels,
+        negotiate::{parse_sdp, select_peer_media},
+        track::{Track, TrackConfig, rtp::RtpTrackBuilder},
+    },
+};
+use anyhow::Result;
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::{net::UdpSocket, sync::mpsc, time::timeout};
+use webrtc::{
+    rtp::{extension::audio_level_extension::AudioLevelExtension, header::Header, packet::Packet},
+    util::{Marshal, Unmarshal},
+};
+
+#[test]
+fn test_audio_level() {
+    assert_eq!(audio_level(&[]), SILENCE_LEVEL);
+    assert_eq!(audio_level(&[0; 160]), SILENCE_LEVEL);
+    assert_eq!(audio_level(&[i16::MIN; 160]), 0);
+    // a tenth of full scale is -20 dBov
+    let samples = (0..160)
+        .map(|i| if i % 2 == 0 { 3277 } else { -3277 })
+        .collect::<Vec<_>>();
+    assert_eq!(audio_level(&samples), 20);
+}
+
+#[test]
+fn test_read_write_audio_level() {
+    let mut header = Header::default();
+    assert_eq!(read_audio_level(&header, 1), None);
+    let level = AudioLevelExtension {
+        level: 42,
+        voice: true,
+    };
+    write_audio_level(&mut header, 3, level);
+    assert_eq!(read_audio_level(&header, 3), Some(level));
+    assert_eq!(read_audio_level(&header, 1), None);
+    assert_eq!(
+        header.get_extension(3),
+        Some(Bytes::from_static(&[0x80 | 42]))
+    );
+}
+
+#[tokio::test]
+async fn test_rtp_track_audio_level() -> Result<()> {
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let levels = TrackLevels::default();
+    let track = RtpTrackBuilder::new("speaker".to_string(), TrackConfig::default())
+        .with_local_addr("127.0.0.1".parse()?)
+        .with_ice_connectivity_check(false)
+        .with_audio_level(levels.clone())
+        .build()
+        .await?;
+
+    let offer = track.local_description()?;
+    assert!(offer.contains(&format!("a=extmap:1 {} vad=off", AUDIO_LEVEL_URI)));
+    let port = select_peer_media(&parse_sdp(offer.as_bytes())?, "audio")
+        .unwrap()
+        .rtp_port;
+    let answer = format!(
+        "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+         m=audio {} RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\na=extmap:5 {}\r\n",
+        peer.local_addr()?.port(),
+        AUDIO_LEVEL_URI
+    );
+    track.set_remote_description(&answer)?;
+
+    // the level of the audio sent goes along with it
+    let frame = AudioFrame {
+        track_id: "speaker".to_string(),
+        samples: Samples::PCM {
+            samples: (0..160)
+                .map(|i| if i % 2 == 0 { 3277 } else { -3277 })
+                .collect(),
+        },
+        timestamp: 0,
+        sample_rate: 8000,
+    };
+    track.send_packet(&frame).await?;
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = timeout(Duration::from_secs(1), peer.recv_from(&mut buf)).await??;
+    let sent = Packet::unmarshal(&mut &buf[..n])?;
+    assert_eq!(
+        read_audio_level(&sent.header, 5).map(|level| level.level),
+        Some(20)
+    );
+
+    // the level of the audio received is kept without decoding it
+    let (packet_sender, _packet_receiver) = mpsc::unbounded_channel();
+    track.start(create_event_sender(), packet_sender).await?;
+    let mut received = sent.clone();
+    write_audio_level(
+        &mut received.header,
+        5,
+        AudioLevelExtension {
+            level: 35,
+            voice: false,
+        },
+    );
+    peer.send_to(&received.marshal()?, ("127.0.0.1", port))
+        .await?;
+    for _ in 0..50 {
+        if levels.get("speaker").is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(levels.get("speaker").map(|level| level.rms), Some(-35.0));
+    track.stop().await?;
+    Ok(())
+}
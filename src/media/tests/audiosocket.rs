@@ -0,0 +1,204 @@
+use crate::event::{SessionEvent, create_event_sender};
+use crate::media::track::Track;
+use crate::media::track::audiosocket::{AudioSocketFrame, AudioSocketOption, AudioSocketTrack};
+use crate::{AudioFrame, Samples};
+use bytes::BytesMut;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+async fn read_frame(stream: &mut TcpStream, buf: &mut BytesMut) -> AudioSocketFrame {
+    loop {
+        if let Some((frame, len)) = AudioSocketFrame::parse(buf).unwrap() {
+            let _ = buf.split_to(len);
+            return frame;
+        }
+        let n = timeout(Duration::from_secs(1), stream.read_buf(buf))
+            .await
+            .expect("audiosocket frame")
+            .unwrap();
+        assert!(n > 0, "audiosocket closed");
+    }
+}
+
+#[test]
+fn test_audiosocket_frame() {
+    let frame = AudioSocketFrame::Audio {
+        sample_rate: 16000,
+        samples: vec![1, -2, 300],
+    };
+    let data = frame.encode().unwrap();
+    assert_eq!(&data[..3], &[0x12, 0x00, 0x06]);
+    assert_eq!(&data[3..5], &[0x01, 0x00]);
+    assert_eq!(AudioSocketFrame::parse(&data).unwrap(), Some((frame, 9)));
+    assert_eq!(AudioSocketFrame::parse(&data[..8]).unwrap(), None);
+
+    let uuid = Uuid::new_v4();
+    let data = AudioSocketFrame::Uuid(uuid).encode().unwrap();
+    assert_eq!(data.len(), 19);
+    assert_eq!(
+        AudioSocketFrame::parse(&data).unwrap(),
+        Some((AudioSocketFrame::Uuid(uuid), 19))
+    );
+    assert_eq!(
+        AudioSocketFrame::parse(&[0x00, 0x00, 0x00, 0x03]).unwrap(),
+        Some((AudioSocketFrame::Hangup, 3))
+    );
+    assert_eq!(
+        AudioSocketFrame::parse(&[0x03, 0x00, 0x01, b'#']).unwrap(),
+        Some((AudioSocketFrame::Dtmf('#'), 4))
+    );
+    assert!(AudioSocketFrame::parse(&[0x42, 0x00, 0x00]).is_err());
+    assert!(
+        AudioSocketFrame::Audio {
+            sample_rate: 11025,
+            samples: vec![0; 10],
+        }
+        .encode()
+        .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_audiosocket_track() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let uuid = Uuid::new_v4();
+    let option = AudioSocketOption {
+        addr: listener.local_addr().unwrap().to_string(),
+        uuid: Some(uuid.to_string()),
+        ..Default::default()
+    };
+    let track = AudioSocketTrack::new(1234, CancellationToken::new(), option).unwrap();
+    assert_eq!(track.id(), "audiosocket");
+    assert_eq!(track.config().samplerate, 8000);
+    let event_sender = create_event_sender();
+    let mut events = event_sender.subscribe();
+    let (packet_sender, mut packet_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (started, accepted) = tokio::join!(
+        track.start(event_sender.clone(), packet_sender),
+        listener.accept()
+    );
+    started.unwrap();
+    let (mut server, _) = accepted.unwrap();
+    let mut buf = BytesMut::new();
+    assert_eq!(
+        read_frame(&mut server, &mut buf).await,
+        AudioSocketFrame::Uuid(uuid)
+    );
+
+    // 30ms frames at 16kHz are sent as 20ms of 8kHz slin
+    for _ in 0..2 {
+        let frame = AudioFrame {
+            track_id: "caller".to_string(),
+            samples: Samples::PCM {
+                samples: vec![1000; 480],
+            },
+            timestamp: 0,
+            sample_rate: 16000,
+        };
+        track.send_packet(&frame).await.unwrap();
+    }
+    // caller dtmf is forwarded after the audio
+    event_sender
+        .send(SessionEvent::Dtmf {
+            track_id: "caller".to_string(),
+            timestamp: 0,
+            digit: "5".to_string(),
+            duration: None,
+        })
+        .unwrap();
+    let mut received = 0;
+    loop {
+        match read_frame(&mut server, &mut buf).await {
+            AudioSocketFrame::Audio {
+                sample_rate,
+                samples,
+            } => {
+                assert_eq!(sample_rate, 8000);
+                assert_eq!(samples.len(), 160);
+                received += samples.len();
+            }
+            frame => {
+                assert_eq!(frame, AudioSocketFrame::Dtmf('5'));
+                break;
+            }
+        }
+    }
+    assert!(received >= 320, "received {} samples", received);
+
+    // audio from the server is played into the call at the track's rate
+    let reply = AudioSocketFrame::Audio {
+        sample_rate: 16000,
+        samples: vec![500; 320],
+    };
+    server.write_all(&reply.encode().unwrap()).await.unwrap();
+    let frame = timeout(Duration::from_secs(1), packet_receiver.recv())
+        .await
+        .expect("frame from audiosocket")
+        .unwrap();
+    assert_eq!(frame.track_id, "audiosocket");
+    assert_eq!(frame.sample_rate, 8000);
+    assert!(matches!(frame.samples, Samples::PCM { ref samples } if samples.len() == 160));
+
+    // the track ends when the server hangs up
+    server
+        .write_all(&AudioSocketFrame::Hangup.encode().unwrap())
+        .await
+        .unwrap();
+    let track_end = timeout(Duration::from_secs(1), async {
+        loop {
+            if let SessionEvent::TrackEnd { track_id, ssrc, .. } = events.recv().await.unwrap() {
+                break (track_id, ssrc);
+            }
+        }
+    })
+    .await
+    .expect("track end");
+    assert_eq!(track_end, ("audiosocket".to_string(), 1234));
+}
+
+#[tokio::test]
+async fn test_audiosocket_stop() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let option = AudioSocketOption {
+        addr: listener.local_addr().unwrap().to_string(),
+        samplerate: Some(16000),
+        ..Default::default()
+    };
+    let track = AudioSocketTrack::new(1, CancellationToken::new(), option).unwrap();
+    let (packet_sender, _packet_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (started, accepted) = tokio::join!(
+        track.start(create_event_sender(), packet_sender),
+        listener.accept()
+    );
+    started.unwrap();
+    let (mut server, _) = accepted.unwrap();
+    let mut buf = BytesMut::new();
+    assert_eq!(
+        read_frame(&mut server, &mut buf).await,
+        AudioSocketFrame::Uuid(*track.uuid())
+    );
+
+    // the server is told when the call side stops
+    track.stop().await.unwrap();
+    assert_eq!(
+        read_frame(&mut server, &mut buf).await,
+        AudioSocketFrame::Hangup
+    );
+
+    for option in [
+        AudioSocketOption {
+            uuid: Some("not-a-uuid".to_string()),
+            ..Default::default()
+        },
+        AudioSocketOption {
+            samplerate: Some(22050),
+            ..Default::default()
+        },
+    ] {
+        assert!(AudioSocketTrack::new(1, CancellationToken::new(), option).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::event::{SessionEvent, create_event_sender};
+use crate::media::clock::{Clock, ManualClock};
+use crate::media::jitter::JitterBuffer;
+use crate::media::pacer::Pacer;
+use crate::media::track::Track;
+use crate::media::track::file::FileTrack;
+use crate::media::track::tone::ToneTrack;
+use crate::{AudioFrame, Samples};
+use anyhow::Result;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[tokio::test]
+async fn test_manual_clock_sleep() {
+    let clock = ManualClock::new(1_000_000);
+    let start = clock.now();
+    let sleeping = tokio::spawn(clock.sleep(ms(500)));
+    clock.advance(ms(499));
+    tokio::task::yield_now().await;
+    assert!(!sleeping.is_finished());
+    clock.advance(ms(1));
+    sleeping.await.unwrap();
+    assert_eq!(clock.now() - start, ms(500));
+    assert_eq!(clock.timestamp(), 1_000_500);
+
+    // a minute long sleep ends as soon as the clock gets there
+    let waiting = tokio::spawn(clock.sleep(ms(60_000)));
+    tokio::task::yield_now().await;
+    clock.run_for(ms(60_000), ms(10_000)).await;
+    assert!(waiting.is_finished());
+}
+
+#[tokio::test]
+async fn test_pacer_on_manual_clock() {
+    let clock = ManualClock::new(0);
+    let ticks = Arc::new(AtomicU32::new(0));
+    let pacing = {
+        let mut pacer = Pacer::with_clock(ms(20), clock.clone());
+        let ticks = ticks.clone();
+        tokio::spawn(async move {
+            loop {
+                pacer.tick().await;
+                ticks.fetch_add(1, Ordering::SeqCst);
+            }
+        })
+    };
+    clock.run_for(ms(1000), ms(5)).await;
+    // the first frame is due right away, then one every 20ms
+    assert_eq!(ticks.load(Ordering::SeqCst), 51);
+
+    // a stall gets one frame, not a burst
+    clock.advance(ms(100));
+    clock.run_for(ms(5), ms(5)).await;
+    assert_eq!(ticks.load(Ordering::SeqCst), 52);
+    pacing.abort();
+}
+
+#[test]
+fn test_jitter_buffer_delay_on_manual_clock() {
+    let clock = ManualClock::new(10_000);
+    let mut buffer = JitterBuffer::with_config(100, 60, 200).with_clock(clock.clone());
+    for ts in [10_000, 10_020, 10_040] {
+        buffer.push(AudioFrame {
+            track_id: "test".to_string(),
+            samples: Samples::Empty,
+            timestamp: ts,
+            sample_rate: 8000,
+        });
+    }
+    assert!(!buffer.is_ready());
+    clock.advance(ms(60));
+    assert!(buffer.is_ready());
+    assert_eq!(buffer.current_delay(), 60);
+
+    clock.advance(ms(170));
+    assert!(buffer.has_excessive_delay());
+    // frames older than 200ms go
+    assert_eq!(buffer.adaptive_cleanup(), 2);
+    assert_eq!(buffer.len(), 1);
+}
+
+#[tokio::test]
+async fn test_tone_track_on_manual_clock() -> Result<()> {
+    let clock = ManualClock::new(0);
+    let event_sender = create_event_sender();
+    let mut events = event_sender.subscribe();
+    let (packet_sender, mut packet_receiver) = mpsc::unbounded_channel();
+    let track = ToneTrack::new("tone".to_string())
+        .with_duration(Some(ms(3000)))
+        .with_clock(clock.clone());
+    track.start(event_sender, packet_sender).await?;
+
+    tokio::task::yield_now().await;
+    let mut frames = 0;
+    while packet_receiver.try_recv().is_ok() {
+        frames += 1;
+    }
+    assert_eq!(frames, 1);
+
+    clock.run_for(ms(3000), ms(20)).await;
+    let mut last = 0;
+    while let Some(frame) = packet_receiver.recv().await {
+        if let Samples::PCM { samples } = frame.samples {
+            assert_eq!(samples.len(), 320);
+        }
+        last = frame.timestamp;
+        frames += 1;
+    }
+    assert_eq!(frames, 150);
+    assert_eq!(last, 2980);
+    match events.recv().await? {
+        SessionEvent::TrackEnd { duration, .. } => assert_eq!(duration, 2980),
+        event => panic!("unexpected event {:?}", event),
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_file_track_on_manual_clock() -> Result<()> {
+    let temp_dir = tempfile::tempdir()?;
+    let path = temp_dir.path().join("second.wav");
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 16000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(&path, spec)?;
+    for i in 0..16000 {
+        writer.write_sample(((i as f32 * 0.05).sin() * 10000.0) as i16)?;
+    }
+    writer.finalize()?;
+
+    let clock = ManualClock::new(0);
+    let event_sender = create_event_sender();
+    let mut events = event_sender.subscribe();
+    let (packet_sender, mut packet_receiver) = mpsc::unbounded_channel();
+    let track = FileTrack::new("file".to_string())
+        .with_path(path.to_str().unwrap().to_string())
+        .with_sample_rate(16000)
+        .with_ptime(ms(20))
+        .with_clock(clock.clone());
+    track.start(event_sender, packet_sender).await?;
+
+    // the file loads in real time, then the first frame is due right away
+    let first = packet_receiver.recv().await.unwrap();
+    assert_eq!(first.timestamp, 0);
+    tokio::task::yield_now().await;
+    assert!(packet_receiver.try_recv().is_err());
+
+    clock.run_for(ms(1000), ms(20)).await;
+    let mut frames = 1;
+    let mut last = 0;
+    while let Some(frame) = packet_receiver.recv().await {
+        last = frame.timestamp;
+        frames += 1;
+    }
+    assert_eq!(frames, 50);
+    assert_eq!(last, 980);
+    match events.recv().await? {
+        SessionEvent::TrackEnd { duration, .. } => assert_eq!(duration, 980),
+        event => panic!("unexpected event {:?}", event),
+    }
+    Ok(())
+}
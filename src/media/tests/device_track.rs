@@ -0,0 +1,35 @@
+use crate::media::track::device::{Playout, downmix};
+
+#[test]
+fn test_downmix() {
+    let stereo = [0.5f32, -0.5, 1.0, 0.0];
+    assert_eq!(downmix(&stereo, 2), vec![0, 16384]);
+
+    let mono = [i16::MIN, 0, i16::MAX];
+    assert_eq!(downmix(&mono, 1), vec![i16::MIN, 0, i16::MAX]);
+}
+
+#[test]
+fn test_playout() {
+    let playout = Playout::default();
+    // dropped until the output device is open
+    playout.push(&[1000; 160], 8000);
+    let mut data = [1.0f32; 4];
+    playout.fill(&mut data, 2);
+    assert_eq!(data, [0.0; 4]);
+
+    playout.open(8000);
+    playout.push(&[16384; 160], 8000);
+    let mut data = [0i16; 6];
+    playout.fill(&mut data, 2);
+    assert_eq!(data, [16384; 6]);
+
+    // keeps the latest 500ms, then plays silence once drained
+    for _ in 0..30 {
+        playout.push(&[16384; 160], 8000);
+    }
+    let mut data = vec![1i16; 4001];
+    playout.fill(&mut data, 1);
+    assert_eq!(data[3999], 16384);
+    assert_eq!(data[4000], 0);
+}
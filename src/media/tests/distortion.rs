@@ -0,0 +1,74 @@
+use crate::event::{SessionEvent, create_event_sender};
+use crate::media::distortion::{DistortionDetector, DistortionKind, DistortionOption};
+use crate::media::processor::Processor;
+use crate::{AudioFrame, Samples};
+
+// 1 kHz sine at 8 kHz, scaled by `gain` and hard limited like a hot gateway
+fn feed(detector: &DistortionDetector, gain: f32, offset: f32, from: u64, frames: u64) {
+    for i in 0..frames {
+        let samples = (0..160)
+            .map(|n| {
+                let t = n as f32 / 8000.0;
+                let v = (2.0 * std::f32::consts::PI * 1000.0 * t).sin() * 16000.0 * gain + offset;
+                v.clamp(i16::MIN as f32, i16::MAX as f32) as i16
+            })
+            .collect();
+        let mut frame = AudioFrame {
+            track_id: "gateway".to_string(),
+            samples: Samples::PCM { samples },
+            timestamp: from + i * 20,
+            sample_rate: 8000,
+        };
+        detector.process_frame(&mut frame).unwrap();
+    }
+}
+
+#[test]
+fn test_detect_sustained_clipping() {
+    let event_sender = create_event_sender();
+    let mut event_receiver = event_sender.subscribe();
+    let detector = DistortionDetector::new(event_sender, DistortionOption::default());
+
+    // clean audio and a short burst of clipping are not reported
+    feed(&detector, 0.5, 0.0, 0, 100);
+    feed(&detector, 4.0, 0.0, 2000, 50);
+    feed(&detector, 0.5, 0.0, 3000, 25);
+    assert!(event_receiver.try_recv().is_err());
+
+    // clipping for 2s is, once
+    feed(&detector, 4.0, 0.0, 3500, 200);
+    match event_receiver.try_recv() {
+        Ok(SessionEvent::Distortion {
+            track_id,
+            kind,
+            stats,
+            ..
+        }) => {
+            assert_eq!(track_id, "gateway");
+            assert_eq!(kind, DistortionKind::Clipping);
+            assert_eq!(stats.start_time, 3500);
+            assert_eq!(stats.duration, 2000);
+            assert!(stats.clipped_ratio > 0.3, "{:?}", stats);
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+    assert!(event_receiver.try_recv().is_err());
+}
+
+#[test]
+fn test_detect_dc_offset() {
+    let event_sender = create_event_sender();
+    let mut event_receiver = event_sender.subscribe();
+    let detector = DistortionDetector::new(event_sender, DistortionOption::default());
+
+    feed(&detector, 0.5, 2000.0, 0, 150);
+    match event_receiver.try_recv() {
+        Ok(SessionEvent::Distortion { kind, stats, .. }) => {
+            assert_eq!(kind, DistortionKind::DcOffset);
+            // 2000 of 32768 is about -24 dBFS
+            assert!((stats.dc_offset + 24.3).abs() < 0.5, "{:?}", stats);
+            assert_eq!(stats.clipped_ratio, 0.0);
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+}
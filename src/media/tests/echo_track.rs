@@ -0,0 +1,78 @@
+use crate::event::{SessionEvent, create_event_sender};
+use crate::media::track::Track;
+use crate::media::track::echo::EchoTrack;
+use crate::media::track::tone::ToneTrack;
+use crate::{AudioFrame, Samples};
+use anyhow::Result;
+use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant, timeout};
+
+#[tokio::test]
+async fn test_echo_track_delay() -> Result<()> {
+    let event_sender = create_event_sender();
+    let (packet_sender, mut packet_receiver) = mpsc::unbounded_channel();
+    let track = EchoTrack::new("echo".to_string()).with_delay(Duration::from_millis(100));
+    track.start(event_sender, packet_sender).await?;
+
+    let started = Instant::now();
+    track
+        .send_packet(&AudioFrame {
+            track_id: "caller".to_string(),
+            timestamp: 0,
+            samples: Samples::PCM {
+                samples: vec![1000; 320],
+            },
+            sample_rate: 16000,
+        })
+        .await?;
+
+    let frame = timeout(Duration::from_secs(1), packet_receiver.recv())
+        .await?
+        .unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(100));
+    assert_eq!(frame.track_id, "echo");
+    match frame.samples {
+        Samples::PCM { samples } => assert_eq!(samples, vec![1000; 320]),
+        _ => panic!("expected pcm samples"),
+    }
+    track.stop().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tone_track_milliwatt() -> Result<()> {
+    let event_sender = create_event_sender();
+    let mut event_receiver = event_sender.subscribe();
+    let (packet_sender, mut packet_receiver) = mpsc::unbounded_channel();
+    let track = ToneTrack::new("tone".to_string())
+        .with_ssrc(42)
+        .with_duration(Some(Duration::from_millis(100)));
+    track.start(event_sender, packet_sender).await?;
+
+    let mut samples = Vec::new();
+    while let Some(frame) = packet_receiver.recv().await {
+        if let Samples::PCM { samples: pcm } = frame.samples {
+            samples.extend(pcm);
+        }
+    }
+    assert_eq!(samples.len(), 1600);
+
+    // 0 dBm0 peaks 3.17 dB below full scale
+    let peak = samples.iter().map(|s| s.unsigned_abs()).max().unwrap();
+    assert!((22500..23500).contains(&peak), "peak {}", peak);
+    // 1004 Hz crosses zero ~200 times in 100ms
+    let crossings = samples
+        .windows(2)
+        .filter(|w| (w[0] < 0) != (w[1] < 0))
+        .count();
+    assert!((195..=205).contains(&crossings), "crossings {}", crossings);
+
+    match timeout(Duration::from_secs(1), event_receiver.recv()).await?? {
+        SessionEvent::TrackEnd { track_id, ssrc, .. } => {
+            assert_eq!(track_id, "tone");
+            assert_eq!(ssrc, 42);
+        }
+        other => panic!("expected track end, got {:?}", other),
+    }
+    Ok(())
+}
@@ -1,4 +1,5 @@
 use crate::event::SessionEvent;
+use crate::media::error::MediaResult;
 use crate::media::processor::Processor;
 use crate::media::stream::MuteProcessor;
 use crate::media::track::Track;
@@ -29,7 +30,7 @@ impl CountingProcessor {
 }
 
 impl Processor for CountingProcessor {
-    fn process_frame(&self, _frame: &mut AudioFrame) -> Result<()> {
+    fn process_frame(&self, _frame: &mut AudioFrame) -> MediaResult<()> {
         self.count.fetch_add(1, Ordering::Relaxed);
         Ok(())
     }
@@ -236,3 +237,118 @@ async fn test_mute_and_unmute() -> Result<()> {
     }
     Ok(())
 }
+
+#[test]
+fn test_detect_audio_format() {
+    use crate::media::track::file::AudioFormat;
+    assert_eq!(
+        AudioFormat::detect(b"RIFF\x24\x00\x00\x00WAVEfmt ", "bin"),
+        Some(AudioFormat::Wav)
+    );
+    assert_eq!(
+        AudioFormat::detect(b"ID3\x04", "wav"),
+        Some(AudioFormat::Mp3)
+    );
+    assert_eq!(
+        AudioFormat::detect(&[0xff, 0xfb, 0x90], ""),
+        Some(AudioFormat::Mp3)
+    );
+    assert_eq!(
+        AudioFormat::detect(b"OggS\x00\x02", "mp3"),
+        Some(AudioFormat::Ogg)
+    );
+    assert_eq!(
+        AudioFormat::detect(b"fLaC\x00", ""),
+        Some(AudioFormat::Flac)
+    );
+    assert_eq!(AudioFormat::detect(b"", "MP3"), Some(AudioFormat::Mp3));
+    assert_eq!(AudioFormat::detect(b"text", "txt"), None);
+}
+
+/// A mono 16 bit FLAC file of verbatim subframes, frames of 256 samples
+fn encode_flac(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+    fn crc8(data: &[u8]) -> u8 {
+        data.iter().fold(0u8, |mut crc, &byte| {
+            crc ^= byte;
+            for _ in 0..8 {
+                crc = if crc & 0x80 != 0 {
+                    (crc << 1) ^ 0x07
+                } else {
+                    crc << 1
+                };
+            }
+            crc
+        })
+    }
+    fn crc16(data: &[u8]) -> u16 {
+        data.iter().fold(0u16, |mut crc, &byte| {
+            crc ^= (byte as u16) << 8;
+            for _ in 0..8 {
+                crc = if crc & 0x8000 != 0 {
+                    (crc << 1) ^ 0x8005
+                } else {
+                    crc << 1
+                };
+            }
+            crc
+        })
+    }
+    let block_size = 256;
+    let mut flac = b"fLaC".to_vec();
+    // last metadata block, STREAMINFO of 34 bytes
+    flac.extend_from_slice(&[0x80, 0, 0, 34]);
+    flac.extend_from_slice(&(block_size as u16).to_be_bytes());
+    flac.extend_from_slice(&(block_size as u16).to_be_bytes());
+    flac.extend_from_slice(&[0; 6]); // frame sizes unknown
+    let info = (sample_rate as u64) << 44 | 15 << 36 | samples.len() as u64;
+    flac.extend_from_slice(&info.to_be_bytes());
+    flac.extend_from_slice(&[0; 16]); // no MD5
+    for (index, block) in samples.chunks(block_size).enumerate() {
+        // fixed blocks of 8 bit size - 1, 16 kHz, mono 16 bit
+        let mut frame = vec![0xff, 0xf8, 0x75, 0x08, index as u8, (block.len() - 1) as u8];
+        frame.push(crc8(&frame));
+        frame.push(0x02); // verbatim
+        for sample in block {
+            frame.extend_from_slice(&sample.to_be_bytes());
+        }
+        frame.extend_from_slice(&crc16(&frame).to_be_bytes());
+        flac.extend(frame);
+    }
+    flac
+}
+
+#[tokio::test]
+async fn test_file_track_flac() -> Result<()> {
+    let samples: Vec<i16> = (0..2560)
+        .map(|i| ((i as f32 * 0.05).sin() * 10000.0) as i16)
+        .collect();
+    let temp_dir = tempfile::tempdir()?;
+    let path = temp_dir.path().join("test.flac");
+    std::fs::write(&path, encode_flac(&samples, 16000))?;
+
+    let track_id = "test_flac_track".to_string();
+    let file_track = FileTrack::new(track_id.clone())
+        .with_path(path.to_str().unwrap().to_string())
+        .with_config(
+            crate::media::track::TrackConfig::default()
+                .with_sample_rate(16000)
+                .with_ptime(Duration::from_millis(20)),
+        );
+    let (event_sender, _) = broadcast::channel(16);
+    let (packet_sender, mut packet_receiver) = mpsc::unbounded_channel();
+    file_track.start(event_sender, packet_sender).await?;
+
+    let mut decoded = Vec::new();
+    while decoded.len() < samples.len() {
+        let packet = tokio::time::timeout(Duration::from_secs(5), packet_receiver.recv())
+            .await?
+            .expect("packet channel closed");
+        match packet.samples {
+            Samples::PCM { samples } => decoded.extend(samples),
+            _ => unreachable!("Expected PCM samples"),
+        }
+    }
+    assert_eq!(&decoded[..samples.len()], &samples[..]);
+    file_track.stop().await?;
+    Ok(())
+}
@@ -0,0 +1,180 @@
+use crate::event::SessionEvent;
+use crate::media::track::file::encode_wav;
+use crate::media::track::http_stream::{HttpStreamTrack, StreamDecoder};
+use crate::media::track::{Track, TrackConfig};
+use crate::{Sample, Samples};
+use anyhow::Result;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+fn sine(len: usize) -> Vec<Sample> {
+    (0..len)
+        .map(|i| ((i as f32 * 0.05).sin() * 10000.0) as Sample)
+        .collect()
+}
+
+/// Serves `body` on every connection in small chunks, with a
+/// Content-Length unless `live`, and counts the connections
+async fn serve(body: Vec<u8>, live: bool) -> Result<(String, Arc<AtomicUsize>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/prompt", listener.local_addr()?);
+    let connections = Arc::new(AtomicUsize::new(0));
+    let counter = connections.clone();
+    tokio::spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            counter.fetch_add(1, Ordering::Relaxed);
+            let body = body.clone();
+            tokio::spawn(async move {
+                let mut request = [0u8; 1024];
+                socket.read(&mut request).await.ok();
+                let header = match live {
+                    true => "HTTP/1.0 200 OK\r\nContent-Type: audio/wav\r\n\r\n".to_string(),
+                    false => format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: audio/wav\r\nContent-Length: {}\r\n\r\n",
+                        body.len()
+                    ),
+                };
+                socket.write_all(header.as_bytes()).await.ok();
+                for chunk in body.chunks(1001) {
+                    if socket.write_all(chunk).await.is_err() {
+                        return;
+                    }
+                    tokio::time::sleep(Duration::from_millis(5)).await;
+                }
+                socket.shutdown().await.ok();
+            });
+        }
+    });
+    Ok((url, connections))
+}
+
+#[tokio::test]
+async fn test_http_stream_wav() -> Result<()> {
+    let body = encode_wav(&sine(4000), 8000)?;
+    let (url, connections) = serve(body, false).await?;
+
+    let track = HttpStreamTrack::new("http_stream".to_string())
+        .with_url(url.clone())
+        .with_config(TrackConfig::default().with_sample_rate(16000))
+        .with_prebuffer(Duration::from_millis(100));
+    let (event_sender, mut event_receiver) = broadcast::channel(16);
+    let (packet_sender, mut packet_receiver) = mpsc::unbounded_channel();
+    track.start(event_sender, packet_sender).await?;
+
+    let mut received = 0;
+    let ended = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match event_receiver.recv().await? {
+                SessionEvent::TrackEnd { play_id, .. } => return Ok(play_id),
+                SessionEvent::Error { error, .. } => anyhow::bail!("stream failed: {}", error),
+                _ => {}
+            }
+        }
+    })
+    .await??;
+    assert_eq!(ended, Some(url));
+    while let Ok(packet) = packet_receiver.try_recv() {
+        assert_eq!(packet.sample_rate, 16000);
+        if let Samples::PCM { samples } = packet.samples {
+            received += samples.len();
+        }
+    }
+    // half a second of 8 kHz audio, played at 16 kHz
+    assert!((7900..=8100).contains(&received), "received {}", received);
+    assert_eq!(connections.load(Ordering::Relaxed), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_http_stream_reconnects_live_stream() -> Result<()> {
+    let body = encode_wav(&sine(1600), 8000)?;
+    let (url, connections) = serve(body, true).await?;
+
+    let token = CancellationToken::new();
+    let track = HttpStreamTrack::new("http_stream".to_string())
+        .with_url(url)
+        .with_prebuffer(Duration::from_millis(100))
+        .with_cancel_token(token.clone());
+    let (event_sender, mut event_receiver) = broadcast::channel(16);
+    let (packet_sender, _packet_receiver) = mpsc::unbounded_channel();
+    track.start(event_sender, packet_sender).await?;
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while connections.load(Ordering::Relaxed) < 2 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await?;
+    token.cancel();
+    let ended = tokio::time::timeout(Duration::from_secs(1), async {
+        loop {
+            if let SessionEvent::TrackEnd { .. } = event_receiver.recv().await? {
+                return Ok::<_, anyhow::Error>(());
+            }
+        }
+    })
+    .await;
+    assert!(ended.is_ok(), "no trackEnd after cancel");
+    Ok(())
+}
+
+#[test]
+fn test_stream_decoder_mp3_chunks() -> Result<()> {
+    let data = std::fs::read("fixtures/sample.mp3")?;
+    let mut expected = 0;
+    let mut decoder = rmp3::Decoder::new(&data);
+    while let Some(frame) = decoder.next() {
+        if let rmp3::Frame::Audio(audio) = frame {
+            expected += audio.sample_count();
+        }
+    }
+
+    let mut decoder = StreamDecoder::new("".to_string());
+    let mut decoded = 0;
+    for chunk in data.chunks(777) {
+        for (samples, _) in decoder.decode(chunk, false)? {
+            decoded += samples.len();
+        }
+    }
+    for (samples, _) in decoder.decode(&[], true)? {
+        decoded += samples.len();
+    }
+    assert!(expected > 0);
+    assert_eq!(decoded, expected);
+    Ok(())
+}
+
+#[test]
+fn test_stream_decoder_stereo_wav() -> Result<()> {
+    let spec = hound::WavSpec {
+        channels: 2,
+        sample_rate: 8000,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut data = std::io::Cursor::new(Vec::new());
+    let mut writer = hound::WavWriter::new(&mut data, spec)?;
+    for _ in 0..100 {
+        writer.write_sample(1000i16)?;
+        writer.write_sample(3000i16)?;
+    }
+    writer.finalize()?;
+
+    let mut decoder = StreamDecoder::new("".to_string());
+    let mut samples = Vec::new();
+    // split mid header and mid frame
+    for chunk in data.into_inner().chunks(7) {
+        for (chunk, sample_rate) in decoder.decode(chunk, false)? {
+            assert_eq!(sample_rate, 8000);
+            samples.extend(chunk);
+        }
+    }
+    assert_eq!(samples.len(), 100);
+    assert!(samples.iter().all(|s| (1999..=2001).contains(s)));
+    Ok(())
+}
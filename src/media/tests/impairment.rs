@@ -0,0 +1,318 @@
+//! Simulated network impairments: loss, jitter, reordering and duplication
+//! with deterministic seeds, for jitter buffer tests and a UDP relay that
+//! sits between two RTP endpoints.
+
+use crate::media::codecs::{testing::speech_noise, verify::perceptual_score};
+use crate::media::jitter::JitterBuffer;
+use crate::{AudioFrame, PcmBuf, Samples};
+use anyhow::Result;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+use tokio::time::{Duration, sleep, timeout};
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone)]
+pub struct ImpairmentProfile {
+    /// Fixed one-way delay (in ms)
+    pub delay: u64,
+    /// Uniform random extra delay, 0 - jitter (in ms)
+    pub jitter: u64,
+    /// Probability to start losing packets
+    pub loss: f64,
+    /// Probability that the next packet is lost too (Gilbert model)
+    pub burst: f64,
+    /// Probability that a packet is held back behind the next ones
+    pub reorder: f64,
+    /// Extra delay of a reordered packet (in ms)
+    pub reorder_delay: u64,
+    /// Probability that a packet is delivered twice
+    pub duplicate: f64,
+    pub seed: u64,
+}
+
+impl ImpairmentProfile {
+    pub fn lan(seed: u64) -> Self {
+        Self {
+            delay: 1,
+            jitter: 2,
+            loss: 0.0,
+            burst: 0.0,
+            reorder: 0.0,
+            reorder_delay: 0,
+            duplicate: 0.0,
+            seed,
+        }
+    }
+
+    pub fn mobile(seed: u64) -> Self {
+        Self {
+            delay: 60,
+            jitter: 40,
+            loss: 0.02,
+            burst: 0.3,
+            reorder: 0.02,
+            reorder_delay: 50,
+            duplicate: 0.01,
+            seed,
+        }
+    }
+
+    pub fn congested(seed: u64) -> Self {
+        Self {
+            delay: 120,
+            jitter: 120,
+            loss: 0.08,
+            burst: 0.5,
+            reorder: 0.05,
+            reorder_delay: 80,
+            duplicate: 0.02,
+            seed,
+        }
+    }
+}
+
+pub struct Impairment {
+    profile: ImpairmentProfile,
+    rng: StdRng,
+    losing: bool,
+}
+
+impl Impairment {
+    pub fn new(profile: ImpairmentProfile) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(profile.seed),
+            profile,
+            losing: false,
+        }
+    }
+
+    /// Arrival times of a packet sent at `sent_at`: empty when lost, two
+    /// entries when duplicated
+    pub fn apply(&mut self, sent_at: u64) -> Vec<u64> {
+        let p = &self.profile;
+        self.losing = match self.losing {
+            true => self.rng.random_bool(p.burst),
+            false => self.rng.random_bool(p.loss),
+        };
+        if self.losing {
+            return vec![];
+        }
+        let mut arrival = sent_at + p.delay + self.rng.random_range(0..=p.jitter);
+        if self.rng.random_bool(p.reorder) {
+            arrival += p.reorder_delay;
+        }
+        let mut arrivals = vec![arrival];
+        if self.rng.random_bool(p.duplicate) {
+            arrivals.push(arrival + self.rng.random_range(0..=p.jitter));
+        }
+        arrivals
+    }
+
+    /// Packets as (sent_at, packet), returned as (arrival, packet) in arrival order
+    pub fn simulate<T: Clone>(
+        &mut self,
+        packets: impl IntoIterator<Item = (u64, T)>,
+    ) -> Vec<(u64, T)> {
+        let mut arrived = Vec::new();
+        for (sent_at, packet) in packets {
+            for arrival in self.apply(sent_at) {
+                arrived.push((arrival, packet.clone()));
+            }
+        }
+        arrived.sort_by_key(|(arrival, _)| *arrival);
+        arrived
+    }
+}
+
+/// Forwards UDP datagrams received on `addr` to `target` through an
+/// [`Impairment`], stops when dropped.
+pub struct ImpairedRelay {
+    pub addr: SocketAddr,
+    token: CancellationToken,
+}
+
+impl ImpairedRelay {
+    pub async fn start(target: SocketAddr, profile: ImpairmentProfile) -> Result<Self> {
+        let socket = Arc::new(UdpSocket::bind("127.0.0.1:0").await?);
+        let addr = socket.local_addr()?;
+        let token = CancellationToken::new();
+        let relay_token = token.clone();
+        tokio::spawn(async move {
+            let mut impairment = Impairment::new(profile);
+            let mut buf = vec![0u8; 1500];
+            loop {
+                let n = tokio::select! {
+                    _ = relay_token.cancelled() => break,
+                    r = socket.recv(&mut buf) => match r {
+                        Ok(n) => n,
+                        Err(_) => break,
+                    },
+                };
+                for delay in impairment.apply(0) {
+                    let socket = socket.clone();
+                    let datagram = buf[..n].to_vec();
+                    tokio::spawn(async move {
+                        sleep(Duration::from_millis(delay)).await;
+                        socket.send_to(&datagram, target).await.ok();
+                    });
+                }
+            }
+        });
+        Ok(Self { addr, token })
+    }
+}
+
+impl Drop for ImpairedRelay {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+fn frame(timestamp: u64) -> AudioFrame {
+    AudioFrame {
+        track_id: "test".to_string(),
+        samples: Samples::Empty,
+        timestamp,
+        sample_rate: 8000,
+    }
+}
+
+#[test]
+fn test_impairment_is_deterministic() {
+    let packets: Vec<(u64, u32)> = (0..500).map(|seq| (seq as u64 * 20, seq)).collect();
+    let first = Impairment::new(ImpairmentProfile::congested(7)).simulate(packets.clone());
+    let second = Impairment::new(ImpairmentProfile::congested(7)).simulate(packets.clone());
+    let other = Impairment::new(ImpairmentProfile::congested(8)).simulate(packets);
+    assert_eq!(first, second);
+    assert_ne!(first, other);
+}
+
+#[test]
+fn test_impairment_rates() {
+    let packets = (0..10000u64).map(|seq| (seq * 20, seq));
+    let mut profile = ImpairmentProfile::lan(1);
+    profile.loss = 0.05;
+    profile.duplicate = 0.02;
+    let arrived = Impairment::new(profile).simulate(packets);
+
+    let mut unique: Vec<u64> = arrived.iter().map(|(_, seq)| *seq).collect();
+    unique.sort();
+    unique.dedup();
+    let lost = 10000 - unique.len();
+    let duplicated = arrived.len() - unique.len();
+    assert!((400..600).contains(&lost), "lost {}", lost);
+    assert!(
+        (120..280).contains(&duplicated),
+        "duplicated {}",
+        duplicated
+    );
+}
+
+#[test]
+fn test_jitter_buffer_under_impairment() {
+    for profile in [
+        ImpairmentProfile::lan(42),
+        ImpairmentProfile::mobile(42),
+        ImpairmentProfile::congested(42),
+    ] {
+        let arrived =
+            Impairment::new(profile.clone()).simulate((0..1000u64).map(|seq| (seq * 20, seq)));
+
+        // play out every 20ms, starting once the first packet is 60ms old
+        let mut jitter = JitterBuffer::new();
+        let mut played = Vec::new();
+        let mut pending = arrived.into_iter().peekable();
+        let start = profile.delay + 60;
+        for tick in 0..1100u64 {
+            let now = start + tick * 20;
+            while let Some((_, seq)) = pending.next_if(|(arrival, _)| *arrival <= now) {
+                jitter.push(frame(seq * 20));
+            }
+            if let Some(frame) = jitter.pop() {
+                played.push(frame.timestamp);
+            }
+        }
+        assert!(
+            played.windows(2).all(|w| w[0] < w[1]),
+            "{:?}: out of order or duplicated playout",
+            profile
+        );
+        let stats = jitter.stats();
+        assert!(
+            played.len() as u64 + stats.total_late <= stats.total_received,
+            "{:?}",
+            profile
+        );
+    }
+}
+
+#[test]
+fn test_playout_quality_under_impairment() {
+    let speech = speech_noise(5, 8000, Duration::from_secs(10));
+    let mut scores = Vec::new();
+    for profile in [
+        ImpairmentProfile::lan(42),
+        ImpairmentProfile::mobile(42),
+        ImpairmentProfile::congested(42),
+    ] {
+        let arrived = Impairment::new(profile.clone()).simulate(
+            speech
+                .chunks(160)
+                .enumerate()
+                .map(|(seq, samples)| (seq as u64 * 20, (seq as u64, samples.to_vec()))),
+        );
+
+        // what is missing at playout time is played as silence
+        let mut jitter = JitterBuffer::new();
+        let mut played = PcmBuf::from(vec![0; speech.len()]);
+        let mut pending = arrived.into_iter().peekable();
+        let start = profile.delay + 60;
+        for tick in 0..600u64 {
+            let now = start + tick * 20;
+            while let Some((_, (seq, samples))) = pending.next_if(|(arrival, _)| *arrival <= now) {
+                let mut frame = frame(seq * 20);
+                frame.samples = Samples::PCM { samples };
+                jitter.push(frame);
+            }
+            if let Some(frame) = jitter.pop()
+                && let Samples::PCM { samples } = frame.samples
+            {
+                let at = frame.timestamp as usize * 8;
+                played[at..at + samples.len()].copy_from_slice(&samples);
+            }
+        }
+        scores.push(perceptual_score(&speech, &played, 8000));
+    }
+    assert!(scores[0] > 4.4, "{:?}", scores);
+    assert!(scores.windows(2).all(|w| w[0] > w[1]), "{:?}", scores);
+}
+
+#[tokio::test]
+async fn test_impaired_relay() -> Result<()> {
+    let receiver = UdpSocket::bind("127.0.0.1:0").await?;
+    let mut profile = ImpairmentProfile::lan(3);
+    profile.loss = 0.3;
+    let relay = ImpairedRelay::start(receiver.local_addr()?, profile.clone()).await?;
+    let expected = Impairment::new(profile)
+        .simulate((0..50u64).map(|seq| (0, seq)))
+        .len();
+
+    let sender = UdpSocket::bind("127.0.0.1:0").await?;
+    for seq in 0..50u8 {
+        sender.send_to(&[seq], relay.addr).await?;
+    }
+    let mut received = 0;
+    let mut buf = [0u8; 16];
+    while timeout(Duration::from_millis(200), receiver.recv(&mut buf))
+        .await
+        .is_ok()
+    {
+        received += 1;
+    }
+    assert_eq!(received, expected);
+    assert!(received < 50);
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use crate::event::{SessionEvent, create_event_sender};
+use crate::media::keyword::{KeywordEngine, KeywordOption, KeywordSpotter, TemplateKeywordEngine};
+use crate::media::processor::Processor;
+use crate::{AudioFrame, PcmBuf, Samples};
+use std::sync::{Mutex, mpsc};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+const SAMPLE_RATE: u32 = 16000;
+
+// Synthesize a "phrase" as a sequence of tones
+fn phrase(freqs: &[f32], segment_ms: u32) -> PcmBuf {
+    let per_segment = (SAMPLE_RATE * segment_ms / 1000) as usize;
+    let mut samples = Vec::new();
+    for &freq in freqs {
+        for i in 0..per_segment {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let v = (2.0 * std::f32::consts::PI * freq * t).sin() * 8000.0;
+            samples.push(v as i16);
+        }
+    }
+    samples
+}
+
+fn feed(spotter: &KeywordSpotter, samples: &[i16], timestamp: &mut u64) {
+    for chunk in samples.chunks(320) {
+        let mut frame = AudioFrame {
+            track_id: "test".to_string(),
+            samples: Samples::PCM {
+                samples: chunk.to_vec(),
+            },
+            timestamp: *timestamp,
+            sample_rate: SAMPLE_RATE,
+        };
+        spotter.process_frame(&mut frame).unwrap();
+        *timestamp += 20;
+    }
+}
+
+#[test]
+fn test_template_engine_scores() {
+    let operator = phrase(&[500.0, 1200.0, 800.0], 200);
+    let agent = phrase(&[2500.0, 300.0, 2000.0], 200);
+    let mut engine = TemplateKeywordEngine::new();
+    engine.add_template("operator".to_string(), &operator, SAMPLE_RATE);
+    engine.add_template("agent".to_string(), &agent, SAMPLE_RATE);
+
+    let (keyword, score) = engine.spot(&operator, SAMPLE_RATE).unwrap();
+    assert_eq!(keyword, "operator");
+    assert!(score > 0.9, "unexpected score {}", score);
+
+    // slower utterance of the same phrase still matches
+    let slow = phrase(&[500.0, 1200.0, 800.0], 300);
+    let (keyword, _) = engine.spot(&slow, SAMPLE_RATE).unwrap();
+    assert_eq!(keyword, "operator");
+
+    // 8k input is resampled before matching
+    let narrowband = crate::media::codecs::resample::resample_mono(&agent, SAMPLE_RATE, 8000);
+    let (keyword, _) = engine.spot(&narrowband, 8000).unwrap();
+    assert_eq!(keyword, "agent");
+}
+
+#[tokio::test]
+async fn test_keyword_spotter_events() {
+    let operator = phrase(&[500.0, 1200.0, 800.0], 200);
+    let mut engine = TemplateKeywordEngine::new();
+    engine.add_template("operator".to_string(), &operator, SAMPLE_RATE);
+
+    let event_sender = create_event_sender();
+    let mut event_receiver = event_sender.subscribe();
+    let spotter = KeywordSpotter::new(
+        Box::new(engine),
+        CancellationToken::new(),
+        event_sender.clone(),
+        KeywordOption::default(),
+    );
+
+    let silence = vec![0i16; SAMPLE_RATE as usize / 2];
+    let mut timestamp = 1000;
+    feed(&spotter, &silence, &mut timestamp);
+    feed(&spotter, &operator, &mut timestamp);
+    feed(&spotter, &silence, &mut timestamp);
+
+    // the engine runs on a blocking thread
+    let event = tokio::time::timeout(Duration::from_secs(5), event_receiver.recv()).await;
+    match event {
+        Ok(Ok(SessionEvent::Keyword {
+            keyword,
+            score,
+            start_time,
+            end_time,
+            ..
+        })) => {
+            assert_eq!(keyword, "operator");
+            assert!(score >= KeywordOption::default().threshold);
+            assert_eq!(start_time, 1500);
+            assert_eq!(end_time, 2100);
+        }
+        other => panic!("expected keyword event, got {:?}", other),
+    }
+
+    // long speech is never handed to the engine
+    let long_speech = phrase(&[500.0, 1200.0, 800.0], 1000);
+    feed(&spotter, &long_speech, &mut timestamp);
+    feed(&spotter, &silence, &mut timestamp);
+    assert!(event_receiver.try_recv().is_err());
+}
+
+// Holds every match until released
+struct GatedEngine(Mutex<mpsc::Receiver<()>>);
+
+impl KeywordEngine for GatedEngine {
+    fn spot(&mut self, _samples: &[i16], _sample_rate: u32) -> Option<(String, f32)> {
+        self.0.lock().unwrap().recv().ok()?;
+        Some(("operator".to_string(), 1.0))
+    }
+}
+
+#[tokio::test]
+async fn test_keyword_spotter_slow_engine() {
+    let (release, gate) = mpsc::channel();
+    let event_sender = create_event_sender();
+    let mut event_receiver = event_sender.subscribe();
+    let spotter = KeywordSpotter::new(
+        Box::new(GatedEngine(Mutex::new(gate))),
+        CancellationToken::new(),
+        event_sender.clone(),
+        KeywordOption::default(),
+    );
+
+    // frames keep flowing while the engine is busy with the first segment
+    let operator = phrase(&[500.0, 1200.0, 800.0], 200);
+    let silence = vec![0i16; SAMPLE_RATE as usize / 2];
+    let mut timestamp = 0;
+    for _ in 0..3 {
+        feed(&spotter, &operator, &mut timestamp);
+        feed(&spotter, &silence, &mut timestamp);
+    }
+    assert!(event_receiver.try_recv().is_err());
+
+    release.send(()).unwrap();
+    let event = tokio::time::timeout(Duration::from_secs(5), event_receiver.recv()).await;
+    assert!(matches!(event, Ok(Ok(SessionEvent::Keyword { .. }))));
+}
+
+#[test]
+fn test_keyword_option_deserialize() {
+    let json =
+        r#"{"keywords":["operator"],"templates":{"operator":["operator.wav"]},"threshold":0.7}"#;
+    let option: KeywordOption = serde_json::from_str(json).unwrap();
+    assert_eq!(option.keywords, vec!["operator".to_string()]);
+    assert_eq!(option.threshold, 0.7);
+    assert_eq!(option.max_duration, 1500);
+    assert!(TemplateKeywordEngine::from_option(&option).is_err());
+}
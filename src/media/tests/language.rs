@@ -0,0 +1,139 @@
+use crate::event::{SessionEvent, create_event_sender};
+use crate::media::language::{
+    HttpLanguageIdentifier, LanguageDetector, LanguageIdentifier, LanguageOption,
+};
+use crate::media::processor::Processor;
+use crate::{AudioFrame, PcmBuf, Samples};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::time::{Duration, timeout};
+use tokio_util::sync::CancellationToken;
+
+struct MockIdentifier {
+    calls: AtomicUsize,
+}
+
+#[async_trait]
+impl LanguageIdentifier for MockIdentifier {
+    async fn identify(
+        &self,
+        samples: PcmBuf,
+        _sample_rate: u32,
+        candidates: &[String],
+    ) -> Result<(String, f32)> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        assert_eq!(samples.len(), 16000);
+        assert_eq!(candidates, &["zh-CN".to_string(), "en-US".to_string()]);
+        Ok(("en-US".to_string(), 0.9))
+    }
+}
+
+fn speech_frame(timestamp: u64) -> AudioFrame {
+    let samples = (0..320)
+        .map(|i| ((i as f32 * 0.2).sin() * 6000.0) as i16)
+        .collect();
+    AudioFrame {
+        track_id: "test".to_string(),
+        samples: Samples::PCM { samples },
+        timestamp,
+        sample_rate: 16000,
+    }
+}
+
+#[tokio::test]
+async fn test_language_detector_event() {
+    let identifier = Arc::new(MockIdentifier {
+        calls: AtomicUsize::new(0),
+    });
+    let event_sender = create_event_sender();
+    let mut event_receiver = event_sender.subscribe();
+    let mut option = LanguageOption::default();
+    option.duration = 1000;
+    option.languages = vec!["zh-CN".to_string(), "en-US".to_string()];
+    option
+        .models
+        .insert("en-US".to_string(), "16k_en".to_string());
+
+    let detector = LanguageDetector::new(
+        identifier.clone(),
+        CancellationToken::new(),
+        event_sender.clone(),
+        option,
+    );
+
+    // silence is not counted as speech
+    let mut silence = AudioFrame {
+        samples: Samples::PCM {
+            samples: vec![0; 320],
+        },
+        ..speech_frame(0)
+    };
+    detector.process_frame(&mut silence).unwrap();
+
+    for i in 0..100 {
+        detector.process_frame(&mut speech_frame(i * 20)).unwrap();
+    }
+
+    let event = timeout(Duration::from_secs(1), event_receiver.recv())
+        .await
+        .expect("timeout waiting for language event")
+        .unwrap();
+    match event {
+        SessionEvent::LanguageDetected {
+            language,
+            confidence,
+            duration,
+            model,
+            ..
+        } => {
+            assert_eq!(language, "en-US");
+            assert_eq!(confidence, 0.9);
+            assert_eq!(duration, 1000);
+            assert_eq!(model, Some("16k_en".to_string()));
+        }
+        other => panic!("expected language event, got {:?}", other),
+    }
+    assert_eq!(identifier.calls.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn test_http_language_identifier() -> Result<()> {
+    use axum::{Router, body::Bytes, extract::Query, routing::post};
+    use std::collections::HashMap;
+
+    let app = Router::new().route(
+        "/lid",
+        post(
+            |Query(params): Query<HashMap<String, String>>, body: Bytes| async move {
+                assert_eq!(params.get("languages").unwrap(), "zh-CN,en-US");
+                assert_eq!(&body[0..4], b"RIFF");
+                axum::Json(serde_json::json!({"language": "zh-CN", "confidence": 0.75}))
+            },
+        ),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.ok();
+    });
+
+    let option = LanguageOption {
+        endpoint: Some(format!("http://{}/lid", addr)),
+        ..Default::default()
+    };
+    let identifier = HttpLanguageIdentifier::new(&option)?;
+    let (language, confidence) = identifier
+        .identify(
+            vec![100; 1600],
+            16000,
+            &["zh-CN".to_string(), "en-US".to_string()],
+        )
+        .await?;
+    assert_eq!(language, "zh-CN");
+    assert_eq!(confidence, 0.75);
+
+    assert!(HttpLanguageIdentifier::new(&LanguageOption::default()).is_err());
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use crate::media::latency::{LatencyHopKind, LatencyProbe};
+use crate::{AudioFrame, Samples};
+use std::time::{Duration, Instant};
+
+fn frame(value: i16, timestamp: u64) -> AudioFrame {
+    AudioFrame {
+        track_id: "callee".to_string(),
+        samples: Samples::PCM {
+            samples: vec![value; 160],
+        },
+        timestamp,
+        sample_rate: 8000,
+    }
+}
+
+#[test]
+fn test_latency_probe_marker() {
+    let mut probe = LatencyProbe::new("callee".to_string(), Duration::from_secs(5));
+    assert!(!probe.is_expired());
+
+    // nothing is detected before the marker is sent
+    assert!(!probe.detect(&frame(1000, crate::get_timestamp()), Duration::ZERO));
+
+    // the marker spans 60ms: three 20ms frames
+    let mut sent = Vec::new();
+    loop {
+        let mut outgoing = frame(1000, 0);
+        let encode_start = Instant::now();
+        if !probe.inject(&mut outgoing) {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(1));
+        probe.on_encoded(encode_start.elapsed());
+        sent.push(outgoing);
+    }
+    assert_eq!(sent.len(), 3);
+
+    // speech or silence coming back is not the marker
+    assert!(!probe.detect(&frame(1000, crate::get_timestamp()), Duration::ZERO));
+    assert!(!probe.detect(&frame(0, crate::get_timestamp()), Duration::ZERO));
+
+    // looped back after 80ms on the wire, then 20ms in the jitter buffer
+    std::thread::sleep(Duration::from_millis(80));
+    let mut looped = sent[1].clone();
+    looped.timestamp = crate::get_timestamp();
+    std::thread::sleep(Duration::from_millis(20));
+    assert!(probe.detect(&looped, Duration::from_micros(500)));
+    // detected once only
+    assert!(!probe.detect(&looped, Duration::ZERO));
+    std::thread::sleep(Duration::from_millis(5));
+    probe.on_forwarded(&"caller".to_string());
+
+    let report = probe.finish().expect("marker detected");
+    assert!(probe.finish().is_none());
+    assert!(report.round_trip >= 80.0 && report.round_trip < 150.0);
+    assert!(report.encode >= 1.0 && report.encode < 10.0);
+    assert_eq!(report.processing, 0.5);
+    assert!(report.jitter_buffer >= 15.0 && report.jitter_buffer < 60.0);
+    assert!((report.network - report.round_trip + report.encode).abs() < 0.01);
+    assert!(report.mouth_to_ear >= report.network / 2.0 + report.jitter_buffer);
+
+    let hops = report
+        .hops
+        .iter()
+        .map(|hop| (hop.track_id.as_str(), hop.kind))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        hops,
+        vec![
+            ("callee", LatencyHopKind::Sent),
+            ("callee", LatencyHopKind::Received),
+            ("caller", LatencyHopKind::Forwarded),
+        ]
+    );
+    // sent 3ms in, so the arrival is over 100ms later
+    assert!(report.hops[1].at >= 100.0);
+    assert!(report.hops[2].delta >= 5.0 && report.hops[2].delta < 50.0);
+    assert!((report.hops[2].at - report.hops[1].at - report.hops[2].delta).abs() < 0.01);
+}
+
+#[test]
+fn test_latency_probe_skips_encoded_frames() {
+    let mut probe = LatencyProbe::new("callee".to_string(), Duration::ZERO);
+    let mut rtp = AudioFrame {
+        samples: Samples::RTP {
+            payload_type: 0,
+            payload: vec![0xff; 160],
+            sequence_number: 1,
+        },
+        ..frame(0, 0)
+    };
+    assert!(!probe.inject(&mut rtp));
+    assert!(probe.is_expired());
+}
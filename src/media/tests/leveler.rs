@@ -0,0 +1,78 @@
+use crate::media::leveler::{LevelMatchOption, LevelMatcher};
+use crate::media::processor::energy_dbfs;
+use crate::{AudioFrame, Samples};
+
+// a 20ms frame of a 400 Hz sine with an RMS of `level` dBFS
+fn frame(track_id: &str, level: f32) -> AudioFrame {
+    let amplitude = 32768.0 * 10f32.powf(level / 20.0) * std::f32::consts::SQRT_2;
+    let samples = (0..160)
+        .map(|n| {
+            let t = n as f32 / 8000.0;
+            ((2.0 * std::f32::consts::PI * 400.0 * t).sin() * amplitude) as i16
+        })
+        .collect();
+    AudioFrame {
+        track_id: track_id.to_string(),
+        samples: Samples::PCM { samples },
+        timestamp: 0,
+        sample_rate: 8000,
+    }
+}
+
+fn level_of(frame: &AudioFrame) -> f32 {
+    match &frame.samples {
+        Samples::PCM { samples } => energy_dbfs(samples),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_level_match_between_legs() {
+    let mut matcher = LevelMatcher::new(LevelMatchOption::default());
+    let (carrier, agent) = ("carrier".to_string(), "agent".to_string());
+
+    // one leg alone has nothing to be matched against
+    for _ in 0..50 {
+        matcher.process(&mut frame(&carrier, -38.0));
+    }
+    assert_eq!(matcher.gain(&carrier), 0.0);
+
+    // 30s of both talking, silence doesn't move the levels
+    for _ in 0..1500 {
+        matcher.process(&mut frame(&carrier, -38.0));
+        matcher.process(&mut frame(&agent, -18.0));
+        matcher.process(&mut frame(&carrier, -70.0));
+    }
+    assert!((matcher.level(&carrier).unwrap() + 38.0).abs() < 0.5);
+    assert!((matcher.level(&agent).unwrap() + 18.0).abs() < 0.5);
+    // both meet in the middle
+    assert!((matcher.gain(&carrier) - 10.0).abs() < 0.5);
+    assert!((matcher.gain(&agent) + 10.0).abs() < 0.5);
+
+    let mut quiet = frame(&carrier, -38.0);
+    matcher.process(&mut quiet);
+    let mut loud = frame(&agent, -18.0);
+    matcher.process(&mut loud);
+    assert!((level_of(&quiet) - level_of(&loud)).abs() < 1.0);
+}
+
+#[test]
+fn test_level_match_fixed_target() {
+    let mut matcher = LevelMatcher::new(LevelMatchOption {
+        target: Some(-20.0),
+        max_gain: 6.0,
+        ..Default::default()
+    });
+    let carrier = "carrier".to_string();
+    let mut gains = Vec::new();
+    for i in 0..500 {
+        matcher.process(&mut frame(&carrier, -40.0));
+        if i % 50 == 49 {
+            gains.push(matcher.gain(&carrier));
+        }
+    }
+    // ramps up without jumps, capped at max_gain
+    assert!(gains.windows(2).all(|g| g[1] >= g[0]));
+    assert!(gains[0] < 6.0);
+    assert_eq!(matcher.gain(&carrier), 6.0);
+}
@@ -0,0 +1,70 @@
+use crate::event::{SessionEvent, create_event_sender};
+use crate::media::meter::{LevelMeter, MeterOption, SILENCE_DBFS};
+use crate::media::processor::Processor;
+use crate::{AudioFrame, Samples};
+
+fn feed(meter: &LevelMeter, track_id: &str, amplitude: f32, frames: u64) {
+    for i in 0..frames {
+        let samples = (0..160)
+            .map(|n| {
+                let t = n as f32 / 8000.0;
+                ((2.0 * std::f32::consts::PI * 1000.0 * t).sin() * amplitude) as i16
+            })
+            .collect();
+        let mut frame = AudioFrame {
+            track_id: track_id.to_string(),
+            samples: Samples::PCM { samples },
+            timestamp: 1000 + i * 20,
+            sample_rate: 8000,
+        };
+        meter.process_frame(&mut frame).unwrap();
+    }
+}
+
+#[test]
+fn test_level_meter_reports_rms_and_peak() {
+    let event_sender = create_event_sender();
+    let mut event_receiver = event_sender.subscribe();
+    let meter = LevelMeter::new(event_sender, MeterOption { interval: 100 });
+
+    // 12 frames of 20ms: two full intervals, the rest waits for the next
+    feed(&meter, "caller", 16384.0, 12);
+    let mut levels = Vec::new();
+    while let Ok(event) = event_receiver.try_recv() {
+        match event {
+            SessionEvent::Level {
+                track_id, level, ..
+            } => {
+                assert_eq!(track_id, "caller");
+                levels.push(level);
+            }
+            event => panic!("unexpected event {:?}", event),
+        }
+    }
+    assert_eq!(levels.len(), 2);
+    assert_eq!(levels[0].start_time, 1000);
+    assert_eq!(levels[0].end_time, 1100);
+    assert_eq!(levels[1].start_time, 1100);
+    // a half scale sine peaks at -6 dBFS, its RMS is 3 dB lower
+    assert!((levels[0].peak + 6.0).abs() < 0.1, "{:?}", levels[0]);
+    assert!((levels[0].rms + 9.0).abs() < 0.2, "{:?}", levels[0]);
+
+    feed(&meter, "callee", 0.0, 5);
+    assert_eq!(meter.levels().get("callee").unwrap().rms, SILENCE_DBFS);
+}
+
+#[test]
+fn test_level_meter_loudest_track() {
+    let meter = LevelMeter::new(create_event_sender(), MeterOption::default());
+    let levels = meter.levels();
+    assert_eq!(levels.loudest(-50.0), None);
+
+    feed(&meter, "caller", 1000.0, 5);
+    feed(&meter, "callee", 8000.0, 5);
+    assert_eq!(levels.loudest(-50.0).as_deref(), Some("callee"));
+
+    // nobody is talking above the threshold
+    feed(&meter, "callee", 10.0, 5);
+    feed(&meter, "caller", 10.0, 5);
+    assert_eq!(levels.loudest(-50.0), None);
+}
@@ -0,0 +1,250 @@
+use crate::media::meter::{AudioLevel, TrackLevels};
+use crate::media::mixer::{
+    DuckingOption, MediaMixer, SpeakerSelection, SuperviseMode, TopSpeakersOption,
+};
+use crate::{AudioFrame, Samples};
+
+fn pcm_frame(track_id: &str, value: i16) -> AudioFrame {
+    AudioFrame {
+        track_id: track_id.to_string(),
+        samples: Samples::PCM {
+            samples: vec![value; 320],
+        },
+        timestamp: 0,
+        sample_rate: 16000,
+    }
+}
+
+fn pcm_of(frame: &AudioFrame) -> &[i16] {
+    match &frame.samples {
+        Samples::PCM { samples } => samples,
+        _ => panic!("expected pcm frame"),
+    }
+}
+
+#[test]
+fn test_supervise_routes() {
+    let mut mixer = MediaMixer::new();
+    let (caller, agent, supervisor) = (
+        "caller".to_string(),
+        "agent".to_string(),
+        "supervisor".to_string(),
+    );
+    assert!(mixer.is_routed(&supervisor, &caller));
+    assert!(!mixer.is_routed(&agent, &agent));
+
+    mixer.supervise(&supervisor, &agent, SuperviseMode::Listen);
+    assert!(!mixer.is_routed(&supervisor, &caller));
+    assert!(!mixer.is_routed(&supervisor, &agent));
+    assert!(mixer.is_routed(&caller, &supervisor));
+    assert!(mixer.is_routed(&agent, &supervisor));
+
+    mixer.supervise(&supervisor, &agent, SuperviseMode::Whisper);
+    assert!(!mixer.is_routed(&supervisor, &caller));
+    assert!(mixer.is_routed(&supervisor, &agent));
+
+    mixer.supervise(&supervisor, &agent, SuperviseMode::Barge);
+    assert!(mixer.is_routed(&supervisor, &caller));
+    assert!(mixer.is_routed(&supervisor, &agent));
+
+    mixer.supervise(&supervisor, &agent, SuperviseMode::Listen);
+    mixer.remove_track(&supervisor);
+    assert!(mixer.is_routed(&supervisor, &caller));
+}
+
+#[test]
+fn test_mix_into_pacing_source() {
+    let mut mixer = MediaMixer::new();
+    let agent = "agent".to_string();
+
+    // a single source is passed through
+    let out = mixer.mix(&pcm_frame("caller", 1000), &agent).unwrap();
+    assert_eq!(pcm_of(&out), &[1000; 320]);
+
+    // the whisper is buffered and summed into the caller's next frame
+    assert!(mixer.mix(&pcm_frame("supervisor", 500), &agent).is_none());
+    let out = mixer.mix(&pcm_frame("caller", 1000), &agent).unwrap();
+    assert_eq!(out.track_id, "caller");
+    assert_eq!(pcm_of(&out), &[1500; 320]);
+
+    // nothing pending, the caller is passed through again
+    let out = mixer.mix(&pcm_frame("caller", 1000), &agent).unwrap();
+    assert_eq!(pcm_of(&out), &[1000; 320]);
+
+    // mixing clamps instead of wrapping
+    assert!(mixer.mix(&pcm_frame("supervisor", 30000), &agent).is_none());
+    let out = mixer.mix(&pcm_frame("caller", 30000), &agent).unwrap();
+    assert_eq!(pcm_of(&out), &[i16::MAX; 320]);
+
+    // removing the pacing source hands the clock over
+    mixer.remove_track(&"caller".to_string());
+    let out = mixer.mix(&pcm_frame("supervisor", 500), &agent).unwrap();
+    assert_eq!(pcm_of(&out), &[500; 320]);
+}
+
+#[test]
+fn test_supervise_mode_serde() {
+    let mode: SuperviseMode = serde_json::from_str("\"whisper\"").unwrap();
+    assert_eq!(mode, SuperviseMode::Whisper);
+    assert_eq!(
+        serde_json::to_string(&SuperviseMode::Barge).unwrap(),
+        "\"barge\""
+    );
+}
+
+#[test]
+fn test_ducking_attack_and_release() {
+    let mut mixer = MediaMixer::new();
+    let (caller, prompt) = ("caller".to_string(), "prompt".to_string());
+    mixer.set_ducking(
+        &prompt,
+        Some(DuckingOption {
+            level: -20.0,
+            attack: 20,
+            release: 40,
+        }),
+    );
+
+    let out = mixer.mix(&pcm_frame(&caller, 10000), &"agent".to_string());
+    assert_eq!(pcm_of(&out.unwrap()), &[10000; 320]);
+
+    // the prompt is active: the caller ramps down to -20dB within 20ms
+    assert!(
+        mixer
+            .mix(&pcm_frame(&prompt, 100), &"agent".to_string())
+            .is_none()
+    );
+    let out = mixer
+        .mix(&pcm_frame(&caller, 10000), &"agent".to_string())
+        .unwrap();
+    let samples = pcm_of(&out);
+    assert!(samples[0] > 9000, "{}", samples[0]);
+    assert!(samples.windows(2).all(|w| w[0] >= w[1]));
+    assert_eq!(samples[319], 1000 + 100);
+
+    // the prompt stopped: released back to unity over 40ms
+    mixer.remove_track(&prompt);
+    let out = mixer
+        .mix(&pcm_frame(&caller, 10000), &"agent".to_string())
+        .unwrap();
+    let samples = pcm_of(&out);
+    assert!(samples[0] < 2000, "{}", samples[0]);
+    assert!(samples[319] < 10000);
+    let out = mixer
+        .mix(&pcm_frame(&caller, 10000), &"agent".to_string())
+        .unwrap();
+    assert_eq!(pcm_of(&out)[319], 10000);
+}
+
+#[test]
+fn test_top_speakers_selection() {
+    let levels = TrackLevels::default();
+    let speakers = SpeakerSelection::new(levels.clone());
+    assert!(speakers.is_mixed("a", 0));
+    speakers.set_option(Some(TopSpeakersOption {
+        max_speakers: 2,
+        hold: 0,
+    }));
+
+    let (a, b, c) = ("a".to_string(), "b".to_string(), "c".to_string());
+    speakers.estimate(&a, &[10000; 160], 1000);
+    speakers.estimate(&b, &[1000; 160], 1000);
+    speakers.estimate(&c, &[100; 160], 1000);
+    assert!(speakers.is_mixed(&a, 1000));
+    assert!(speakers.is_mixed(&b, 1000));
+    assert!(!speakers.is_mixed(&c, 1000));
+
+    // c got louder than b
+    speakers.estimate(&a, &[10000; 160], 1040);
+    speakers.estimate(&b, &[1000; 160], 1040);
+    speakers.estimate(&c, &[20000; 160], 1040);
+    assert!(speakers.is_mixed(&c, 1040));
+    assert!(!speakers.is_mixed(&b, 1040));
+
+    // a reported level needs no decoding, unless it is among the loudest
+    let level = |rms: f32| AudioLevel {
+        rms,
+        peak: rms,
+        start_time: 1060,
+        end_time: 1060,
+    };
+    levels.update("d", level(-1.0));
+    levels.update("e", level(-60.0));
+    assert!(speakers.needs_decode("d", 1060));
+    assert!(!speakers.needs_decode("e", 1060));
+    assert!(speakers.needs_decode("f", 1060));
+}
+
+#[test]
+fn test_top_speakers_hold() {
+    let speakers = SpeakerSelection::new(TrackLevels::default());
+    speakers.set_option(Some(TopSpeakersOption {
+        max_speakers: 1,
+        hold: 100,
+    }));
+    let (a, b) = ("a".to_string(), "b".to_string());
+    speakers.estimate(&a, &[10000; 160], 0);
+    assert!(speakers.is_mixed(&a, 0));
+    speakers.estimate(&b, &[20000; 160], 40);
+    assert!(speakers.is_mixed(&b, 40));
+    assert!(speakers.is_mixed(&a, 80));
+    speakers.estimate(&b, &[20000; 160], 120);
+    assert!(!speakers.is_mixed(&a, 120));
+}
+
+#[test]
+fn test_mix_top_speakers() {
+    let mut mixer = MediaMixer::new();
+    mixer.set_top_speakers(Some(TopSpeakersOption {
+        max_speakers: 1,
+        ..Default::default()
+    }));
+    let agent = "agent".to_string();
+    assert!(mixer.mix(&pcm_frame("caller", 10000), &agent).is_some());
+    assert!(mixer.mix(&pcm_frame("supervisor", 100), &agent).is_none());
+    let out = mixer.mix(&pcm_frame("caller", 10000), &agent).unwrap();
+    assert_eq!(pcm_of(&out), &[10000; 320]);
+}
+
+#[test]
+fn test_mix_forwards_dtmf_of_quiet_speakers() {
+    let mut mixer = MediaMixer::new();
+    mixer.set_top_speakers(Some(TopSpeakersOption {
+        max_speakers: 1,
+        ..Default::default()
+    }));
+    let (caller, agent) = ("caller".to_string(), "agent".to_string());
+    assert!(mixer.mix(&pcm_frame("caller", 10000), &agent).is_some());
+    assert!(mixer.mix(&pcm_frame("supervisor", 100), &agent).is_none());
+
+    // the supervisor isn't among the loudest, its digits get through anyway
+    let dtmf = AudioFrame {
+        track_id: "supervisor".to_string(),
+        samples: Samples::RTP {
+            sequence_number: 1,
+            payload_type: 101,
+            payload: vec![1, 0x0a, 0x00, 0xa0],
+        },
+        timestamp: 0,
+        sample_rate: 8000,
+    };
+    for destination in [&agent, &caller] {
+        let out = mixer.mix(&dtmf, destination).unwrap();
+        assert!(matches!(
+            out.samples,
+            Samples::RTP {
+                payload_type: 101,
+                ..
+            }
+        ));
+    }
+
+    // its audio, discarded undecoded, doesn't
+    let discarded = AudioFrame {
+        samples: Samples::Empty,
+        ..dtmf
+    };
+    assert!(mixer.mix(&discarded, &agent).is_none());
+    let out = mixer.mix(&pcm_frame("caller", 10000), &agent).unwrap();
+    assert_eq!(pcm_of(&out), &[10000; 320]);
+}
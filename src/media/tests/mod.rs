@@ -1,9 +1,34 @@
+mod audio_level;
+mod audiosocket;
+mod clock;
 mod denoiser;
+mod distortion;
+mod echo_track;
 mod file_track;
+mod impairment;
 mod jitter;
+mod keyword;
+mod language;
+mod latency;
+mod leveler;
+mod meter;
+mod mixer;
+mod nat;
+mod pacer;
+mod pipeline;
+mod processor;
+mod prosody;
 mod recorder;
+mod replay;
+mod rewriter;
+mod rtp_fork;
 mod rtp_track;
+mod send_queue;
+mod shaper;
 mod stream;
 mod tts_track;
 mod webrtc_track;
-mod media_pass;
\ No newline at end of file
+mod media_pass;
+mod device_track;
+mod http_stream_track;
+mod workers;
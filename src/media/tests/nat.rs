@@ -0,0 +1,106 @@
+use crate::{
+    config::{Config, MediaNatConfig, PortMapping},
+    media::{
+        nat::{MediaNat, pod_ordinal, pod_range},
+        track::{TrackConfig, rtp::RtpTrackBuilder},
+    },
+};
+use anyhow::Result;
+use std::{net::SocketAddr, sync::Arc};
+
+fn config(media_nat: MediaNatConfig) -> Config {
+    Config {
+        external_ip: Some("198.51.100.1".to_string()),
+        rtp_start_port: Some(32000),
+        rtp_end_port: Some(32999),
+        media_nat: Some(media_nat),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_pod_range() {
+    assert_eq!(pod_range(20000, 29999, 1000, 0).unwrap(), (20000, 20999));
+    assert_eq!(pod_range(20000, 29999, 1000, 9).unwrap(), (29000, 29999));
+    assert!(pod_range(20000, 29999, 1000, 10).is_err());
+    assert!(pod_range(20000, 29999, 1, 0).is_err());
+
+    assert_eq!(pod_ordinal("rustpbx-2"), Some(2));
+    assert_eq!(pod_ordinal("rustpbx-media-12"), Some(12));
+    assert_eq!(pod_ordinal("rustpbx-7d9f8b6c4-x2k8p"), None);
+    assert_eq!(pod_ordinal("rustpbx"), None);
+}
+
+#[test]
+fn test_media_nat_external() -> Result<()> {
+    let nat = MediaNat::new(&config(MediaNatConfig {
+        mappings: vec![PortMapping {
+            start: 32000,
+            end: 32099,
+            external_ip: "203.0.113.10".to_string(),
+            external_port: Some(40000),
+        }],
+        pod_ports: Some(200),
+        pod_index: Some(0),
+        ..Default::default()
+    }))?;
+    assert_eq!(nat.port_range(), (32000, 32199));
+
+    let local = |port| SocketAddr::new("10.0.0.5".parse().unwrap(), port);
+    // mapped ports shift with their range
+    assert_eq!(
+        nat.external(local(32042)),
+        Some("203.0.113.10:40042".parse()?)
+    );
+    // the others keep their port, on external_ip until STUN finds one
+    assert_eq!(
+        nat.external(local(32100)),
+        Some("198.51.100.1:32100".parse()?)
+    );
+    nat.set_discovered("192.0.2.7".parse()?);
+    assert_eq!(nat.external(local(32100)), Some("192.0.2.7:32100".parse()?));
+    assert_eq!(
+        nat.external(local(32042)),
+        Some("203.0.113.10:40042".parse()?)
+    );
+
+    // the slice must fit the RTP ports
+    assert!(
+        MediaNat::new(&config(MediaNatConfig {
+            pod_ports: Some(200),
+            pod_index: Some(5),
+            ..Default::default()
+        }))
+        .is_err()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_rtp_track_with_nat() -> Result<()> {
+    let mut config = config(MediaNatConfig {
+        mappings: vec![PortMapping {
+            start: 32100,
+            end: 32199,
+            external_ip: "203.0.113.10".to_string(),
+            external_port: None,
+        }],
+        pod_ports: Some(100),
+        pod_index: Some(1),
+        ..Default::default()
+    });
+    config.external_ip = None;
+    let nat = Arc::new(MediaNat::new(&config)?);
+    let builder = RtpTrackBuilder::new("test-rtp-nat".to_string(), TrackConfig::default())
+        .with_local_addr("127.0.0.1".parse()?)
+        .with_nat(nat);
+    let (rtp, _) = builder.build_rtp_rtcp_conn().await?;
+    let local = rtp.get_addr().get_socketaddr()?;
+    assert!((32100..=32199).contains(&local.port()), "{}", local);
+    let external = rtp.external.as_ref().expect("public address");
+    assert_eq!(
+        external.get_socketaddr()?,
+        SocketAddr::new("203.0.113.10".parse()?, local.port())
+    );
+    Ok(())
+}
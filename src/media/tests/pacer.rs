@@ -0,0 +1,53 @@
+use crate::media::pacer::{Pacer, PacingStats, PlayoutClock};
+use tokio::time::{Duration, Instant};
+
+#[test]
+fn test_playout_clock_compensates_drift() {
+    let start = Instant::now();
+    let ms = Duration::from_millis;
+    let mut clock = PlayoutClock::new(ms(20), start);
+    assert_eq!(clock.deadline(), start);
+    clock.advance(start);
+    // a timer that fires late doesn't push the frames after it
+    for frame in 1..50u32 {
+        assert_eq!(clock.deadline(), start + ms(20) * frame);
+        clock.advance(clock.deadline() + ms(3));
+    }
+    assert_eq!(clock.deadline(), start + ms(20) * 50);
+    assert_eq!(clock.stats().late_frames, 49);
+    assert_eq!(clock.stats().resyncs, 0);
+
+    // a stall of several frames starts a new grid instead of a burst
+    let stalled = clock.deadline() + ms(70);
+    clock.advance(stalled);
+    assert_eq!(clock.deadline(), stalled + ms(20));
+    assert_eq!(
+        clock.stats(),
+        &PacingStats {
+            frames: 51,
+            late_frames: 50,
+            resyncs: 1,
+            max_lateness: 70,
+        }
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_pacer_ticks_on_interval() {
+    let mut pacer = Pacer::new(Duration::from_millis(20));
+    let start = Instant::now();
+    let mut ticks = Vec::new();
+    for _ in 0..5 {
+        pacer.tick().await;
+        ticks.push(start.elapsed().as_millis());
+    }
+    assert_eq!(ticks, vec![0, 20, 40, 60, 80]);
+
+    // a producer that was held up gets no burst of frames
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    pacer.tick().await;
+    let resumed = start.elapsed();
+    pacer.tick().await;
+    assert_eq!(start.elapsed() - resumed, Duration::from_millis(20));
+    assert_eq!(pacer.stats().resyncs, 1);
+}
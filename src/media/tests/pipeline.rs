@@ -0,0 +1,157 @@
+use crate::media::codecs::{CodecType, verify::snr};
+use crate::media::error::MediaError;
+use crate::media::jitter::JitterBuffer;
+use crate::media::pipeline::MediaPipeline;
+use crate::media::stream::GainProcessor;
+use crate::media::track::TrackConfig;
+use crate::{AudioFrame, Samples};
+use webrtc::rtcp::sender_report::SenderReport;
+use webrtc::rtp::{header::Header, packet::Packet};
+use webrtc::util::{Marshal, Unmarshal};
+
+fn tone_frame(index: usize) -> AudioFrame {
+    let samples = (0..160)
+        .map(|i| {
+            let t = (index * 160 + i) as f32 / 8000.0;
+            ((t * 2.0 * std::f32::consts::PI * 440.0).sin() * 8000.0) as i16
+        })
+        .collect();
+    AudioFrame {
+        track_id: "caller".to_string(),
+        samples: Samples::PCM { samples },
+        timestamp: 0,
+        sample_rate: 8000,
+    }
+}
+
+fn rtp(payload_type: u8, sequence_number: u16, payload: Vec<u8>) -> Vec<u8> {
+    Packet {
+        header: Header {
+            version: 2,
+            payload_type,
+            sequence_number,
+            ssrc: 1,
+            ..Default::default()
+        },
+        payload: payload.into(),
+    }
+    .marshal()
+    .unwrap()
+    .to_vec()
+}
+
+fn config(codec: CodecType) -> TrackConfig {
+    TrackConfig {
+        codec,
+        samplerate: 8000,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_pipeline_loopback() {
+    let mut sender = MediaPipeline::new("caller".to_string(), config(CodecType::PCMA))
+        .with_ssrc(0x1234)
+        .with_initial_sequence(100, 1000);
+    let mut receiver = MediaPipeline::new("callee".to_string(), config(CodecType::PCMA));
+
+    let mut sent = Vec::new();
+    let mut received = Vec::new();
+    for index in 0..10 {
+        let frame = tone_frame(index);
+        sender.push_frame(&frame).expect("encode");
+        if let Samples::PCM { samples } = frame.samples {
+            sent.extend(samples);
+        }
+        let now = index as u64 * 20;
+        while let Some(datagram) = sender.pull_packet() {
+            let packet = Packet::unmarshal(&mut &datagram[..]).unwrap();
+            assert_eq!(packet.header.ssrc, 0x1234);
+            assert_eq!(packet.header.sequence_number, 100 + index as u16);
+            assert_eq!(packet.header.timestamp, 1000 + index as u32 * 160);
+            assert_eq!(packet.header.payload_type, 8);
+            receiver.push_packet(&datagram, now).expect("receive");
+        }
+        let frame = receiver.pull_frame().expect("pull").expect("frame");
+        assert_eq!(frame.track_id, "callee");
+        if let Samples::PCM { samples } = frame.samples {
+            received.extend(samples);
+        }
+    }
+    assert!(receiver.pull_frame().unwrap().is_none());
+    assert_eq!(sent.len(), received.len());
+    assert!(snr(&sent, &received) > 30.0);
+}
+
+#[test]
+fn test_pipeline_processors_and_dtmf() {
+    let mut pipeline = MediaPipeline::new("callee".to_string(), config(CodecType::PCMU));
+    pipeline
+        .processor_chain()
+        .append_processor(Box::new(GainProcessor::new(-120.0)));
+
+    pipeline
+        .push_packet(&rtp(0, 1, vec![0x80; 160]), 0)
+        .unwrap();
+    // telephone event "5" with end bit, and a duplicate of it
+    pipeline
+        .push_packet(&rtp(101, 2, vec![5, 0x80, 0, 160]), 20)
+        .unwrap();
+    pipeline
+        .push_packet(&rtp(101, 3, vec![5, 0x80, 0, 160]), 40)
+        .unwrap();
+
+    let frame = pipeline.pull_frame().unwrap().expect("audio frame");
+    match frame.samples {
+        Samples::PCM { samples } => assert!(samples.iter().all(|&s| s == 0)),
+        _ => panic!("expected decoded frame"),
+    }
+    assert!(pipeline.pull_frame().unwrap().is_none());
+    assert_eq!(pipeline.pull_dtmf(), Some("5".to_string()));
+    assert_eq!(pipeline.pull_dtmf(), None);
+
+    // garbage is rejected, RTCP is ignored
+    assert!(matches!(
+        pipeline.push_packet(&[0x00, 0x01], 60),
+        Err(MediaError::InvalidPacket(_))
+    ));
+    let sr = SenderReport::default().marshal().unwrap();
+    assert!(pipeline.push_packet(&sr, 60).is_ok());
+    assert!(pipeline.pull_frame().unwrap().is_none());
+
+    assert!(matches!(
+        pipeline.push_frame(&AudioFrame {
+            samples: Samples::Empty,
+            ..Default::default()
+        }),
+        Err(MediaError::UnsupportedFrame)
+    ));
+}
+
+#[test]
+fn test_pipeline_errors() {
+    let mut pipeline = MediaPipeline::new("callee".to_string(), config(CodecType::PCMU))
+        .with_jitter_buffer(JitterBuffer::with_max_size(2));
+
+    // comfort noise has no codec, a dynamic payload type may be an event
+    assert!(matches!(
+        pipeline.push_packet(&rtp(13, 1, vec![0x40]), 0),
+        Err(MediaError::UnsupportedPayload(13))
+    ));
+    assert!(pipeline.push_packet(&rtp(96, 2, vec![0; 4]), 0).is_ok());
+
+    pipeline
+        .push_packet(&rtp(0, 3, vec![0xff; 160]), 20)
+        .unwrap();
+    pipeline
+        .push_packet(&rtp(0, 4, vec![0xff; 160]), 40)
+        .unwrap();
+    match pipeline.push_packet(&rtp(0, 5, vec![0xff; 160]), 60) {
+        Err(MediaError::BufferOverrun { capacity, dropped }) => {
+            assert_eq!((capacity, dropped), (2, 1));
+        }
+        other => panic!("expected an overrun, got {:?}", other),
+    }
+    // the newest frames are kept
+    assert_eq!(pipeline.pull_frame().unwrap().unwrap().timestamp, 40);
+}
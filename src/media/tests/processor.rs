@@ -0,0 +1,182 @@
+use crate::media::codecs::{
+    resample::ResampleProfile,
+    testing::sine,
+    verify::{aligned_snr, snr},
+};
+use crate::media::error::{MediaError, MediaResult};
+use crate::media::processor::{Processor, ProcessorChain, TapPoint};
+use crate::{AudioFrame, PcmBuf, Samples};
+use anyhow::Result;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Records the rate and length of the frames it sees, halves them when
+/// `attenuate` is set
+struct Wideband {
+    seen: Arc<Mutex<Vec<(u32, usize)>>>,
+    attenuate: bool,
+    channels: u16,
+}
+
+impl Processor for Wideband {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
+        if let Samples::PCM { samples } = &mut frame.samples {
+            self.seen
+                .lock()
+                .unwrap()
+                .push((frame.sample_rate, samples.len()));
+            if self.attenuate {
+                samples.iter_mut().for_each(|s| *s /= 2);
+            }
+        }
+        Ok(())
+    }
+
+    fn sample_rate(&self) -> Option<u32> {
+        Some(16000)
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+}
+
+fn wideband(attenuate: bool) -> (Box<dyn Processor>, Arc<Mutex<Vec<(u32, usize)>>>) {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let processor = Wideband {
+        seen: seen.clone(),
+        attenuate,
+        channels: 1,
+    };
+    (Box::new(processor), seen)
+}
+
+fn frame(samples: &[i16]) -> AudioFrame {
+    AudioFrame {
+        track_id: "test".to_string(),
+        samples: Samples::PCM {
+            samples: samples.to_vec(),
+        },
+        timestamp: 0,
+        sample_rate: 8000,
+    }
+}
+
+fn pcm(frame: AudioFrame) -> PcmBuf {
+    match frame.samples {
+        Samples::PCM { samples } => samples,
+        _ => panic!("not PCM"),
+    }
+}
+
+#[test]
+fn test_processor_at_its_own_rate() -> Result<()> {
+    let tone = sine(440.0, 8000, Duration::from_secs(1));
+    let (observer, seen) = wideband(false);
+    let mut chain = ProcessorChain::new(8000);
+    chain.append_processor(observer);
+
+    let mut played = PcmBuf::new();
+    for samples in tone.chunks(160) {
+        let mut frame = frame(samples);
+        chain.process_frame(&mut frame)?;
+        assert_eq!(frame.sample_rate, 8000);
+        played.extend(pcm(frame));
+    }
+    assert!(
+        seen.lock()
+            .unwrap()
+            .iter()
+            .all(|seen| *seen == (16000, 320))
+    );
+    // what the processor only looked at goes on untouched
+    assert_eq!(played, tone);
+
+    let (attenuator, seen) = wideband(true);
+    let mut chain = ProcessorChain::new(8000).with_resample_profile(ResampleProfile::Quality);
+    chain.append_processor(attenuator);
+    let mut played = PcmBuf::new();
+    for samples in tone.chunks(160) {
+        let mut frame = frame(samples);
+        chain.process_frame(&mut frame)?;
+        assert_eq!(frame.sample_rate, 8000);
+        played.extend(pcm(frame));
+    }
+    assert_eq!(seen.lock().unwrap().len(), 50);
+    assert_eq!(played.len(), tone.len());
+    let quieter: PcmBuf = tone.iter().map(|s| s / 2).collect();
+    let (_, quality) = aligned_snr(&quieter, &played, 320);
+    assert!(quality > 20.0, "snr {}", quality);
+    assert!(snr(&tone, &played) < 10.0);
+    Ok(())
+}
+
+#[test]
+fn test_processor_frame_checks() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let stereo = || Wideband {
+        seen: seen.clone(),
+        attenuate: false,
+        channels: 2,
+    };
+    let mut chain = ProcessorChain::new(8000);
+    chain.append_processor(Box::new(stereo()));
+    assert!(matches!(
+        chain.process_frame(&mut frame(&[0; 160])),
+        Err(MediaError::Channels {
+            processor: 0,
+            expected: 2,
+            actual: 1,
+        })
+    ));
+
+    let mut chain = ProcessorChain::new(8000).with_channels(2);
+    chain.append_processor(Box::new(stereo()));
+    assert!(chain.process_frame(&mut frame(&[0; 320])).is_ok());
+    assert!(matches!(
+        chain.process_frame(&mut frame(&[0; 161])),
+        Err(MediaError::PartialFrame {
+            processor: 0,
+            samples: 161,
+            channels: 2,
+        })
+    ));
+    assert_eq!(seen.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn test_processor_chain_taps() {
+    let mut chain = ProcessorChain::new(16000);
+    let (processor, _) = wideband(true);
+    chain.append_processor(processor);
+    let (input, input_seen) = tap();
+    let (output, output_seen) = tap();
+    chain.add_tap(TapPoint::Output, output);
+    chain.add_tap(TapPoint::Input, input);
+
+    let mut processed = frame(&[1000; 160]);
+    processed.sample_rate = 16000;
+    chain.process_frame(&mut processed).unwrap();
+    assert_eq!(pcm(processed), vec![500; 160]);
+    assert_eq!(*input_seen.lock().unwrap(), vec![vec![1000; 160]]);
+    assert_eq!(*output_seen.lock().unwrap(), vec![vec![500; 160]]);
+}
+
+/// Keeps the samples of the frames it is shown, and changes them to see
+/// the chain doesn't keep it
+struct Tap(Arc<Mutex<Vec<PcmBuf>>>);
+
+impl Processor for Tap {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
+        if let Samples::PCM { samples } = &mut frame.samples {
+            self.0.lock().unwrap().push(samples.clone());
+            samples.clear();
+        }
+        Ok(())
+    }
+}
+
+fn tap() -> (Box<dyn Processor>, Arc<Mutex<Vec<PcmBuf>>>) {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    (Box::new(Tap(seen.clone())), seen)
+}
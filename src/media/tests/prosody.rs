@@ -0,0 +1,130 @@
+use crate::event::{SessionEvent, create_event_sender};
+use crate::media::processor::Processor;
+use crate::media::prosody::{
+    ProsodyAnalyzer, ProsodyFeatures, ProsodyOption, SentimentScore, SentimentScorer,
+};
+use crate::{AudioFrame, PcmBuf, Samples};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::time::{Duration, timeout};
+use tokio_util::sync::CancellationToken;
+
+const SAMPLE_RATE: u32 = 16000;
+
+// 200Hz voice with a 4Hz syllable envelope
+fn modulated_voice(duration_ms: u32) -> PcmBuf {
+    let total = (SAMPLE_RATE * duration_ms / 1000) as usize;
+    (0..total)
+        .map(|i| {
+            let t = i as f32 / SAMPLE_RATE as f32;
+            let envelope = 0.5 * (1.0 - (2.0 * std::f32::consts::PI * 4.0 * t).cos());
+            let v = (2.0 * std::f32::consts::PI * 200.0 * t).sin() * envelope * 10000.0;
+            v as i16
+        })
+        .collect()
+}
+
+fn feed(analyzer: &ProsodyAnalyzer, samples: &[i16]) {
+    for (i, chunk) in samples.chunks(320).enumerate() {
+        let mut frame = AudioFrame {
+            track_id: "test".to_string(),
+            samples: Samples::PCM {
+                samples: chunk.to_vec(),
+            },
+            timestamp: 1000 + i as u64 * 20,
+            sample_rate: SAMPLE_RATE,
+        };
+        analyzer.process_frame(&mut frame).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_prosody_features() {
+    let event_sender = create_event_sender();
+    let mut event_receiver = event_sender.subscribe();
+    let option = ProsodyOption {
+        interval: 2000,
+        ..Default::default()
+    };
+    let analyzer = ProsodyAnalyzer::new(None, CancellationToken::new(), event_sender, option);
+    feed(&analyzer, &modulated_voice(2000));
+
+    match event_receiver.try_recv() {
+        Ok(SessionEvent::Prosody {
+            start_time,
+            end_time,
+            features,
+            sentiment,
+            ..
+        }) => {
+            assert_eq!(start_time, 1000);
+            assert_eq!(end_time, 3000);
+            assert!(
+                (features.pitch - 200.0).abs() < 10.0,
+                "pitch {}",
+                features.pitch
+            );
+            assert!(features.pitch_stddev < 10.0);
+            assert!(
+                features.speaking_rate > 3.0 && features.speaking_rate < 8.0,
+                "rate {}",
+                features.speaking_rate
+            );
+            assert!(features.speech_ratio > 0.5 && features.speech_ratio < 1.0);
+            assert!(sentiment.is_none());
+        }
+        other => panic!("expected prosody event, got {:?}", other),
+    }
+}
+
+struct MockScorer;
+
+#[async_trait]
+impl SentimentScorer for MockScorer {
+    async fn score(
+        &self,
+        samples: PcmBuf,
+        sample_rate: u32,
+        features: &ProsodyFeatures,
+    ) -> Result<SentimentScore> {
+        assert!(!samples.is_empty());
+        assert_eq!(sample_rate, SAMPLE_RATE);
+        assert!(features.energy < 0.0);
+        Ok(SentimentScore {
+            sentiment: -0.5,
+            emotion: Some("angry".to_string()),
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_prosody_sentiment_score() {
+    let event_sender = create_event_sender();
+    let mut event_receiver = event_sender.subscribe();
+    let option = ProsodyOption {
+        interval: 1000,
+        ..Default::default()
+    };
+    let analyzer = ProsodyAnalyzer::new(
+        Some(Arc::new(MockScorer)),
+        CancellationToken::new(),
+        event_sender,
+        option,
+    );
+    feed(&analyzer, &modulated_voice(1000));
+
+    let event = timeout(Duration::from_secs(1), event_receiver.recv())
+        .await
+        .expect("timeout waiting for prosody event")
+        .unwrap();
+    match event {
+        SessionEvent::Prosody {
+            sentiment, emotion, ..
+        } => {
+            assert_eq!(sentiment, Some(-0.5));
+            assert_eq!(emotion, Some("angry".to_string()));
+        }
+        other => panic!("expected prosody event, got {:?}", other),
+    }
+}
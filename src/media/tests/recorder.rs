@@ -1,6 +1,9 @@
 use crate::{
     AudioFrame, PcmBuf, Sample, Samples,
-    media::recorder::{Recorder, RecorderOption},
+    media::{
+        processor::TapPoint,
+        recorder::{RecordTap, Recorder, RecorderOption},
+    },
 };
 use anyhow::Result;
 use std::{path::Path, sync::Arc};
@@ -77,8 +80,8 @@ async fn test_recorder() -> Result<()> {
         };
 
         // Send frames
-        tx.send(left_frame)?;
-        tx.send(right_frame)?;
+        tx.send((TapPoint::Input, left_frame))?;
+        tx.send((TapPoint::Input, right_frame))?;
 
         // Wait a bit to simulate real-time recording
         tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
@@ -153,7 +156,7 @@ async fn test_recorder_intermittent_data() -> Result<()> {
             sample_rate: 16000,
         };
 
-        tx.send(frame)?;
+        tx.send((TapPoint::Input, frame))?;
 
         // Simulate intermittent arrival with gaps
         if i % 3 == 0 {
@@ -171,7 +174,7 @@ async fn test_recorder_intermittent_data() -> Result<()> {
         timestamp: 1000,
         sample_rate: 16000,
     };
-    tx.send(clipped_frame)?;
+    tx.send((TapPoint::Input, clipped_frame))?;
 
     // Test 3: Send frames with constant values (freeze detection)
     let constant_samples: PcmBuf = vec![1000; 20]; // 20 identical values
@@ -183,7 +186,7 @@ async fn test_recorder_intermittent_data() -> Result<()> {
         timestamp: 1100,
         sample_rate: 16000,
     };
-    tx.send(constant_frame)?;
+    tx.send((TapPoint::Input, constant_frame))?;
 
     // Wait for processing
     tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
@@ -233,7 +236,7 @@ async fn test_constant_value_detection() -> Result<()> {
         timestamp: 0,
         sample_rate: 16000,
     };
-    tx.send(frame1)?;
+    tx.send((TapPoint::Input, frame1))?;
 
     // Test 2: Medium silence (should NOT trigger warning as it's normal)
     let medium_silence: PcmBuf = vec![0; 40]; // Less than 50 samples
@@ -245,7 +248,7 @@ async fn test_constant_value_detection() -> Result<()> {
         timestamp: 100,
         sample_rate: 16000,
     };
-    tx.send(frame2)?;
+    tx.send((TapPoint::Input, frame2))?;
 
     // Test 3: Large silence buffer (should trigger warning)
     let large_silence: PcmBuf = vec![0; 100]; // More than 50 samples, all zeros
@@ -257,7 +260,7 @@ async fn test_constant_value_detection() -> Result<()> {
         timestamp: 200,
         sample_rate: 16000,
     };
-    tx.send(frame3)?;
+    tx.send((TapPoint::Input, frame3))?;
 
     // Test 4: Non-zero constant values (should trigger warning)
     let constant_non_zero: PcmBuf = vec![1000; 50]; // 50 identical non-zero values
@@ -269,7 +272,7 @@ async fn test_constant_value_detection() -> Result<()> {
         timestamp: 300,
         sample_rate: 16000,
     };
-    tx.send(frame4)?;
+    tx.send((TapPoint::Input, frame4))?;
 
     // Test 5: Normal audio (should NOT trigger warning)
     let normal_audio: PcmBuf = (0..50)
@@ -283,7 +286,7 @@ async fn test_constant_value_detection() -> Result<()> {
         timestamp: 400,
         sample_rate: 16000,
     };
-    tx.send(frame5)?;
+    tx.send((TapPoint::Input, frame5))?;
 
     // Wait for processing
     tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
@@ -359,10 +362,10 @@ async fn test_recorder_200ms_timing() -> Result<()> {
             sample_rate: 16000,
         };
 
-        tx.send(frame_1)?;
+        tx.send((TapPoint::Input, frame_1))?;
         // Send second frame with slight delay to test buffer handling
         tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
-        tx.send(frame_2)?;
+        tx.send((TapPoint::Input, frame_2))?;
 
         // Wait for the 200ms interval
         tokio::time::sleep(tokio::time::Duration::from_millis(150)).await;
@@ -382,3 +385,52 @@ async fn test_recorder_200ms_timing() -> Result<()> {
     println!("200ms timing test completed successfully");
     Ok(())
 }
+
+#[tokio::test]
+async fn test_recorder_both_taps() -> Result<()> {
+    let temp_dir = tempdir()?;
+    let file_path = temp_dir.path().join("test_both_taps.wav");
+    let file_path_clone = file_path.clone();
+    let cancel_token = CancellationToken::new();
+    let config = RecorderOption {
+        tap: RecordTap::Both,
+        ..Default::default()
+    };
+    let recorder = Arc::new(Recorder::new(
+        cancel_token.clone(),
+        "test".to_string(),
+        config,
+    ));
+    let (tx, rx) = mpsc::unbounded_channel();
+    let recorder_clone = recorder.clone();
+    let recorder_handle =
+        tokio::spawn(async move { recorder_clone.process_recording(&file_path_clone, rx).await });
+
+    let frame = |track_id: &str, value: Sample| AudioFrame {
+        track_id: track_id.to_string(),
+        samples: Samples::PCM {
+            samples: vec![value; 3200],
+        },
+        timestamp: 0,
+        sample_rate: 16000,
+    };
+    tx.send((TapPoint::Input, frame("caller", 1000)))?;
+    tx.send((TapPoint::Output, frame("caller", 500)))?;
+    tx.send((TapPoint::Input, frame("callee", 2000)))?;
+    tx.send((TapPoint::Output, frame("callee", 1500)))?;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    recorder.stop_recording()?;
+    recorder_handle.await??;
+
+    let mut reader = hound::WavReader::open(&file_path)?;
+    assert_eq!(reader.spec().channels, 4);
+    let samples = reader.samples::<i16>().collect::<Result<Vec<_>, _>>()?;
+    // caller raw, caller processed, callee raw, callee processed
+    let frames = samples
+        .chunks(4)
+        .filter(|frame| frame.iter().any(|s| *s != 0))
+        .collect::<Vec<_>>();
+    assert_eq!(frames.len(), 3200);
+    assert!(frames.iter().all(|frame| *frame == [1000, 500, 2000, 1500]));
+    Ok(())
+}
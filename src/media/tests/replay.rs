@@ -0,0 +1,168 @@
+use crate::media::codecs::CodecType;
+use crate::media::error::MediaResult;
+use crate::media::processor::{Processor, ProcessorChain};
+use crate::media::replay::{Replayer, Timeline};
+use crate::media::stream::GainProcessor;
+use crate::{AudioFrame, Samples};
+use std::sync::{Arc, Mutex};
+
+/// Build a pcap (ethernet, microsecond timestamps) of RTP packets sent at
+/// `(arrival ms, sequence number)`
+fn build_pcap(packets: &[(u64, u16)], ssrc: u32) -> Vec<u8> {
+    let mut pcap = Vec::new();
+    pcap.extend_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+    pcap.extend_from_slice(&2u16.to_le_bytes());
+    pcap.extend_from_slice(&4u16.to_le_bytes());
+    pcap.extend_from_slice(&[0; 8]);
+    pcap.extend_from_slice(&65535u32.to_le_bytes());
+    pcap.extend_from_slice(&1u32.to_le_bytes());
+
+    for &(arrival, seq) in packets {
+        let mut rtp = vec![0x80, 0x00];
+        rtp.extend_from_slice(&seq.to_be_bytes());
+        rtp.extend_from_slice(&(seq as u32 * 160).to_be_bytes());
+        rtp.extend_from_slice(&ssrc.to_be_bytes());
+        // PCMU 0x8f is a small positive sample
+        rtp.extend_from_slice(&[0x8f; 160]);
+
+        let mut udp = Vec::new();
+        udp.extend_from_slice(&4000u16.to_be_bytes());
+        udp.extend_from_slice(&5000u16.to_be_bytes());
+        udp.extend_from_slice(&((rtp.len() + 8) as u16).to_be_bytes());
+        udp.extend_from_slice(&[0, 0]);
+        udp.extend_from_slice(&rtp);
+
+        let mut ip = vec![0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, 17, 0, 0];
+        ip[2..4].copy_from_slice(&((udp.len() + 20) as u16).to_be_bytes());
+        ip.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        ip.extend_from_slice(&udp);
+
+        let mut ether = vec![0; 12];
+        ether.extend_from_slice(&0x0800u16.to_be_bytes());
+        ether.extend_from_slice(&ip);
+
+        let ts = 1_700_000_000_000 + arrival;
+        pcap.extend_from_slice(&((ts / 1000) as u32).to_le_bytes());
+        pcap.extend_from_slice(&(((ts % 1000) * 1000) as u32).to_le_bytes());
+        pcap.extend_from_slice(&(ether.len() as u32).to_le_bytes());
+        pcap.extend_from_slice(&(ether.len() as u32).to_le_bytes());
+        pcap.extend_from_slice(&ether);
+    }
+    pcap
+}
+
+struct Collector {
+    levels: Arc<Mutex<Vec<i16>>>,
+}
+
+impl Processor for Collector {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
+        if let Samples::PCM { samples } = &frame.samples {
+            let peak = samples.iter().map(|s| s.abs()).max().unwrap_or(0);
+            self.levels.lock().unwrap().push(peak);
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_timeline_from_pcap() {
+    let pcap = build_pcap(&[(0, 1), (20, 2), (45, 4), (47, 3), (80, 5)], 0x1234);
+    let timeline = Timeline::from_pcap(&pcap).expect("parse pcap");
+    assert_eq!(timeline.frames.len(), 5);
+    assert_eq!(timeline.track_ids(), vec!["00001234".to_string()]);
+    assert_eq!(timeline.duration(), 80);
+    assert!(matches!(
+        timeline.frames[2].samples,
+        Samples::RTP {
+            sequence_number: 4,
+            payload_type: 0,
+            ..
+        }
+    ));
+
+    assert!(Timeline::from_pcap(&[0u8; 24]).is_err());
+    assert!(Timeline::from_pcap(&pcap[..pcap.len() - 10]).is_err());
+}
+
+#[test]
+fn test_timeline_dynamic_payload_type() {
+    let mut pcap = build_pcap(&[(0, 1)], 1);
+    // the RTP payload type of the only packet
+    pcap[24 + 16 + 14 + 20 + 8 + 1] = 102;
+    // unknown without the SDP
+    assert!(Timeline::from_pcap(&pcap).unwrap().frames.is_empty());
+    let timeline =
+        Timeline::from_pcap_with_payload_types(&pcap, &[(102, CodecType::G722)]).unwrap();
+    assert_eq!(timeline.frames.len(), 1);
+    assert_eq!(timeline.frames[0].sample_rate, 16000);
+}
+
+#[test]
+fn test_timeline_jsonl_roundtrip() {
+    let pcap = build_pcap(&[(0, 1), (20, 2), (40, 3)], 1);
+    let timeline = Timeline::from_pcap(&pcap).unwrap();
+    let text = timeline.to_jsonl().unwrap();
+    assert_eq!(text.lines().count(), 3);
+    let loaded = Timeline::from_jsonl(&text).unwrap();
+    assert_eq!(loaded.frames.len(), 3);
+    assert_eq!(loaded.frames[1].timestamp, timeline.frames[1].timestamp);
+    assert!(Timeline::from_jsonl("{\"trackId\": 1}").is_err());
+}
+
+#[test]
+fn test_replay_is_deterministic() {
+    let packets: Vec<(u64, u16)> = (0..50u16)
+        .map(|seq| {
+            // every 7th packet arrives late, behind the next one
+            let jitter = if seq % 7 == 3 { 25 } else { (seq % 3) as u64 };
+            (seq as u64 * 20 + jitter, seq)
+        })
+        .collect();
+    let timeline = Timeline::from_pcap(&build_pcap(&packets, 7)).unwrap();
+    let replayer = Replayer::new(timeline);
+
+    let run = || {
+        let levels = Arc::new(Mutex::new(Vec::new()));
+        let mut chain = ProcessorChain::new(8000);
+        chain.append_processor(Box::new(GainProcessor::new(6.0)));
+        chain.append_processor(Box::new(Collector {
+            levels: levels.clone(),
+        }));
+        let output = replayer
+            .replay(&"00000007".to_string(), &chain)
+            .expect("replay");
+        let levels = levels.lock().unwrap().clone();
+        (output, levels)
+    };
+    let (output, levels) = run();
+    let (output2, levels2) = run();
+
+    assert!(!output.is_empty() && output.len() <= 50);
+    assert_eq!(output.len(), levels.len());
+    assert_eq!(levels, levels2);
+    assert_eq!(
+        output.iter().map(|f| f.timestamp).collect::<Vec<_>>(),
+        output2.iter().map(|f| f.timestamp).collect::<Vec<_>>()
+    );
+    // decoded to PCM and amplified by the chain
+    assert!(
+        output
+            .iter()
+            .all(|f| matches!(f.samples, Samples::PCM { .. }))
+    );
+    assert!(levels.iter().all(|&l| l > 0));
+
+    // without a jitter buffer every captured packet is processed in order
+    let replayer = Replayer::new(replayer.timeline().clone()).with_jitter_buffer(false);
+    let output = replayer
+        .replay(&"00000007".to_string(), &ProcessorChain::new(8000))
+        .unwrap();
+    assert_eq!(output.len(), 50);
+    assert!(
+        replayer
+            .replay(&"unknown".to_string(), &ProcessorChain::new(8000))
+            .unwrap()
+            .is_empty()
+    );
+}
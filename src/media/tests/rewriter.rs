@@ -0,0 +1,209 @@
+use crate::media::{
+    rewriter::{ABS_SEND_TIME_URI, RtpRewriteOption, RtpRewriter, SDES_MID_URI, parse_extmaps},
+    track::{TrackConfig, rtp::RtpTrackBuilder},
+};
+use anyhow::Result;
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::{net::UdpSocket, time::timeout};
+use webrtc::{
+    rtp::{
+        header::{EXTENSION_PROFILE_ONE_BYTE, Extension, Header},
+        packet::Packet,
+    },
+    util::{Marshal, Unmarshal},
+};
+
+const WEBRTC_OFFER: &str = "v=0\r\n\
+o=- 0 0 IN IP4 127.0.0.1\r\n\
+s=-\r\n\
+t=0 0\r\n\
+m=audio 9 UDP/TLS/RTP/SAVPF 0\r\n\
+a=mid:0\r\n\
+a=extmap:1 urn:ietf:params:rtp-hdrext:ssrc-audio-level\r\n\
+a=extmap:3 http://www.webrtc.org/experiments/rtp-hdrext/abs-send-time\r\n\
+a=extmap:4/sendrecv urn:ietf:params:rtp-hdrext:sdes:mid\r\n";
+
+fn packet(ssrc: u32, sequence_number: u16, timestamp: u32) -> Packet {
+    Packet {
+        header: Header {
+            version: 2,
+            ssrc,
+            sequence_number,
+            timestamp,
+            ..Default::default()
+        },
+        payload: Bytes::from_static(&[0xff; 160]),
+    }
+}
+
+#[test]
+fn test_parse_extmaps() {
+    let extmaps = parse_extmaps(WEBRTC_OFFER);
+    assert_eq!(extmaps.len(), 3);
+    assert_eq!(extmaps.get(ABS_SEND_TIME_URI), Some(&3));
+    assert_eq!(extmaps.get(SDES_MID_URI), Some(&4));
+}
+
+#[test]
+fn test_rewrite_ssrc() {
+    let mut rewriter = RtpRewriter::new(1234, &RtpRewriteOption::default());
+
+    let mut first = packet(1, 100, 8000);
+    rewriter.rewrite(&mut first);
+    assert_eq!(first.header.ssrc, 1234);
+    assert_eq!(first.header.sequence_number, 100);
+    assert_eq!(first.header.timestamp, 8000);
+    let mut second = packet(1, 101, 8160);
+    rewriter.rewrite(&mut second);
+    assert!(!second.header.marker);
+
+    // another source, e.g. after a transfer, continues the stream
+    let mut switched = packet(2, 65535, 4_294_967_200);
+    rewriter.rewrite(&mut switched);
+    assert_eq!(switched.header.ssrc, 1234);
+    assert_eq!(switched.header.sequence_number, 102);
+    assert_eq!(switched.header.timestamp, 8320);
+    assert!(switched.header.marker);
+    let mut next = packet(2, 0, 64);
+    rewriter.rewrite(&mut next);
+    assert_eq!(next.header.sequence_number, 103);
+    assert_eq!(next.header.timestamp, 8480);
+
+    // a late packet of the new source doesn't move the stream back
+    let mut late = packet(2, 65534, 4_294_967_040);
+    rewriter.rewrite(&mut late);
+    assert_eq!(late.header.sequence_number, 101);
+    let mut next = packet(2, 1, 224);
+    rewriter.rewrite(&mut next);
+    assert_eq!(next.header.sequence_number, 104);
+
+    let mut kept = RtpRewriter::new(
+        1234,
+        &RtpRewriteOption {
+            ssrc: false,
+            ..Default::default()
+        },
+    );
+    let mut packet = packet(1, 100, 8000);
+    kept.rewrite(&mut packet);
+    assert_eq!(packet.header.ssrc, 1);
+}
+
+#[test]
+fn test_rewrite_header_extensions() {
+    let mut packet = packet(1, 100, 8000);
+    packet.header.extension = true;
+    packet.header.extension_profile = EXTENSION_PROFILE_ONE_BYTE;
+    packet.header.extensions = vec![
+        Extension {
+            id: 1,
+            payload: Bytes::from_static(&[0x80]),
+        },
+        Extension {
+            id: 3,
+            payload: Bytes::from_static(&[1, 2, 3]),
+        },
+        Extension {
+            id: 4,
+            payload: Bytes::from_static(b"0"),
+        },
+    ];
+
+    // stripped by default, a plain SIP phone gets plain RTP
+    let mut stripped = packet.clone();
+    let mut rewriter = RtpRewriter::new(1234, &RtpRewriteOption::default());
+    rewriter.set_ingress(parse_extmaps(WEBRTC_OFFER));
+    rewriter.rewrite(&mut stripped);
+    assert!(!stripped.header.extension);
+    assert!(stripped.header.extensions.is_empty());
+    let parsed = Packet::unmarshal(&mut &stripped.marshal().unwrap()[..]).unwrap();
+    assert_eq!(parsed.payload, packet.payload);
+
+    // relayed with the ids of the outgoing leg, which has its own mid
+    let mut rewriter = RtpRewriter::new(
+        1234,
+        &RtpRewriteOption {
+            header_extensions: vec!["abs-send-time".to_string(), "mid".to_string()],
+            ..Default::default()
+        },
+    );
+    rewriter.set_ingress(parse_extmaps(WEBRTC_OFFER));
+    assert_eq!(
+        rewriter.extmaps(),
+        vec![
+            (1, ABS_SEND_TIME_URI.to_string()),
+            (2, SDES_MID_URI.to_string())
+        ]
+    );
+    rewriter.set_egress(parse_extmaps(&format!(
+        "a=extmap:2 {}\r\na=extmap:5 {}\r\n",
+        ABS_SEND_TIME_URI, SDES_MID_URI
+    )));
+    rewriter.set_mid(Some("audio".to_string()));
+    rewriter.rewrite(&mut packet);
+    let parsed = Packet::unmarshal(&mut &packet.marshal().unwrap()[..]).unwrap();
+    assert_eq!(parsed.header.extension_profile, EXTENSION_PROFILE_ONE_BYTE);
+    assert_eq!(parsed.header.get_extension_ids(), vec![2, 5]);
+    assert_eq!(
+        parsed.header.get_extension(2),
+        Some(Bytes::from_static(&[1, 2, 3]))
+    );
+    assert_eq!(
+        parsed.header.get_extension(5),
+        Some(Bytes::from_static(b"audio"))
+    );
+
+    // only what the peer accepted is relayed
+    rewriter.set_egress(parse_extmaps(&format!("a=extmap:7 {}\r\n", SDES_MID_URI)));
+    assert_eq!(rewriter.extmaps(), vec![(7, SDES_MID_URI.to_string())]);
+}
+
+#[tokio::test]
+async fn test_rtp_track_send_rtp() -> Result<()> {
+    let peer = UdpSocket::bind("127.0.0.1:0").await?;
+    let track = RtpTrackBuilder::new("relay".to_string(), TrackConfig::default())
+        .with_local_addr("127.0.0.1".parse()?)
+        .with_ssrc(1234)
+        .with_rewrite(RtpRewriteOption {
+            header_extensions: vec!["abs-send-time".to_string()],
+            ..Default::default()
+        })
+        .build()
+        .await?;
+
+    let offer = track.local_description()?;
+    assert!(offer.contains(&format!("a=extmap:1 {}", ABS_SEND_TIME_URI)));
+    let answer = format!(
+        "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+         m=audio {} RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\na=extmap:1 {}\r\n",
+        peer.local_addr()?.port(),
+        ABS_SEND_TIME_URI
+    );
+    track.set_remote_description(&answer)?;
+    track.set_ingress_description(WEBRTC_OFFER);
+
+    let mut relayed = packet(42, 7, 160);
+    relayed.header.extension = true;
+    relayed.header.extension_profile = EXTENSION_PROFILE_ONE_BYTE;
+    relayed.header.extensions = vec![
+        Extension {
+            id: 3,
+            payload: Bytes::from_static(&[1, 2, 3]),
+        },
+        Extension {
+            id: 4,
+            payload: Bytes::from_static(b"0"),
+        },
+    ];
+    track.send_rtp(relayed).await?;
+
+    let mut buf = vec![0u8; 1500];
+    let (n, _) = timeout(Duration::from_secs(1), peer.recv_from(&mut buf)).await??;
+    let received = Packet::unmarshal(&mut &buf[..n])?;
+    assert_eq!(received.header.ssrc, 1234);
+    assert_eq!(received.header.sequence_number, 7);
+    assert_eq!(received.header.get_extension_ids(), vec![1]);
+    assert_eq!(received.payload.len(), 160);
+    Ok(())
+}
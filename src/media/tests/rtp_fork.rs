@@ -0,0 +1,101 @@
+use crate::event::create_event_sender;
+use crate::media::track::Track;
+use crate::media::track::rtp_fork::{RtpForkOption, RtpForkTrack};
+use crate::{AudioFrame, Samples};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use webrtc::rtp::{header::Header, packet::Packet};
+use webrtc::util::{Marshal, Unmarshal};
+
+#[tokio::test]
+async fn test_rtp_fork() {
+    let remote = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let option = RtpForkOption {
+        addr: remote.local_addr().unwrap().to_string(),
+        codec: Some("PCMA".to_string()),
+        ptime: Some(20),
+        receive: Some(true),
+        local_addr: Some("127.0.0.1:0".to_string()),
+        ..Default::default()
+    };
+    let cancel_token = CancellationToken::new();
+    let track = RtpForkTrack::new(1234, cancel_token.clone(), option).unwrap();
+    assert_eq!(track.id(), "rtp-fork");
+    let (packet_sender, mut packet_receiver) = tokio::sync::mpsc::unbounded_channel();
+    track
+        .start(create_event_sender(), packet_sender)
+        .await
+        .unwrap();
+
+    // 30ms frames at 16kHz are repacketized to 20ms of PCMA
+    for _ in 0..2 {
+        let frame = AudioFrame {
+            track_id: "caller".to_string(),
+            samples: Samples::PCM {
+                samples: vec![1000; 480],
+            },
+            timestamp: 0,
+            sample_rate: 16000,
+        };
+        track.send_packet(&frame).await.unwrap();
+    }
+    let mut buf = vec![0u8; 2048];
+    let mut sequence = None;
+    for _ in 0..3 {
+        let (n, from) = timeout(Duration::from_secs(1), remote.recv_from(&mut buf))
+            .await
+            .expect("forked packet")
+            .unwrap();
+        assert_eq!(Some(from), track.local_addr());
+        let packet = Packet::unmarshal(&mut &buf[..n]).unwrap();
+        assert_eq!(packet.header.payload_type, 8);
+        assert_eq!(packet.header.ssrc, 1234);
+        assert_eq!(packet.payload.len(), 160);
+        if let Some(sequence) = sequence {
+            assert_eq!(
+                packet.header.sequence_number,
+                u16::wrapping_add(sequence, 1)
+            );
+        }
+        sequence = Some(packet.header.sequence_number);
+    }
+
+    // audio sent back to the fork is decoded into the call
+    let reply = Packet {
+        header: Header {
+            version: 2,
+            payload_type: 0,
+            sequence_number: 1,
+            ssrc: 42,
+            ..Default::default()
+        },
+        payload: vec![0x8f; 160].into(),
+    }
+    .marshal()
+    .unwrap();
+    remote
+        .send_to(&reply, track.local_addr().unwrap())
+        .await
+        .unwrap();
+    let frame = timeout(Duration::from_secs(1), packet_receiver.recv())
+        .await
+        .expect("frame from fork")
+        .unwrap();
+    assert_eq!(frame.track_id, "rtp-fork");
+    assert!(matches!(frame.samples, Samples::PCM { ref samples } if !samples.is_empty()));
+
+    track.stop().await.unwrap();
+    assert!(
+        RtpForkTrack::new(
+            1,
+            CancellationToken::new(),
+            RtpForkOption {
+                codec: Some("speex".to_string()),
+                ..Default::default()
+            }
+        )
+        .is_err()
+    );
+}
@@ -2,6 +2,7 @@ use crate::{
     AudioFrame, PcmBuf, Sample, Samples,
     event::create_event_sender,
     media::{
+        error::MediaResult,
         jitter::JitterBuffer,
         processor::Processor,
         track::{Track, TrackConfig, rtp::*, track_codec::TrackCodec},
@@ -26,7 +27,7 @@ const RTP_MTU: usize = 1500;
 struct TestProcessor;
 
 impl Processor for TestProcessor {
-    fn process_frame(&self, _frame: &mut AudioFrame) -> Result<()> {
+    fn process_frame(&self, _frame: &mut AudioFrame) -> MediaResult<()> {
         // Simple pass-through processor
         Ok(())
     }
@@ -221,7 +222,7 @@ async fn test_rtp_track_send_dtmf() -> Result<()> {
     // Try sending a DTMF digit
     // This will likely fail to send since we're not actually connecting to a real endpoint,
     // but it will exercise the DTMF packet creation code
-    let result = track.send_dtmf("5", Some(100)).await;
+    let result = track.send_dtmf("5", Duration::from_millis(100)).await;
 
     // Clean up resources
     cancel_token.cancel();
@@ -0,0 +1,113 @@
+use crate::event::{SessionEvent, create_event_sender};
+use crate::media::error::MediaError;
+use crate::media::track::echo::EchoTrack;
+use crate::media::track::send_queue::{DropPolicy, SendQueue, SendStats};
+use crate::media::track::{Track, TrackConfig};
+use crate::{AudioFrame, Samples};
+use anyhow::Result;
+use tokio::sync::mpsc;
+use tokio::time::{Duration, timeout};
+
+#[tokio::test]
+async fn test_send_queue_drop_policies() {
+    let queue = SendQueue::new(2, DropPolicy::DropOldest);
+    assert_eq!(
+        queue.send(1).await.unwrap(),
+        SendStats {
+            queued: 1,
+            capacity: 2,
+            sent: 0,
+            dropped: 0,
+        }
+    );
+    queue.send(2).await.unwrap();
+    assert!(matches!(
+        queue.send(3).await,
+        Err(MediaError::BufferOverrun {
+            capacity: 2,
+            dropped: 1,
+        })
+    ));
+    assert_eq!(queue.recv().await, Some(2));
+    assert_eq!(queue.recv().await, Some(3));
+    assert_eq!(queue.stats().sent, 2);
+    assert_eq!(queue.stats().dropped, 1);
+
+    let queue = SendQueue::new(2, DropPolicy::DropNewest);
+    for item in 1..=3 {
+        queue.send(item).await.ok();
+    }
+    queue.close();
+    assert!(queue.send(4).await.is_ok());
+    assert_eq!(queue.recv().await, Some(1));
+    assert_eq!(queue.recv().await, Some(2));
+    assert_eq!(queue.recv().await, None);
+    assert_eq!(queue.stats().dropped, 1);
+}
+
+#[tokio::test]
+async fn test_send_queue_wait() {
+    let queue = SendQueue::new(1, DropPolicy::Wait(Duration::from_millis(500)));
+    queue.send(1).await.unwrap();
+    let consumer = queue.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        consumer.recv().await
+    });
+    // held until the consumer made room
+    queue.send(2).await.unwrap();
+    assert_eq!(queue.stats().dropped, 0);
+
+    // a consumer that doesn't come back costs the sender the timeout
+    let queue = SendQueue::new(1, DropPolicy::Wait(Duration::from_millis(20)));
+    queue.send(1).await.unwrap();
+    assert!(queue.send(2).await.is_err());
+    assert_eq!(queue.recv().await, Some(2));
+}
+
+#[tokio::test]
+async fn test_echo_track_send_queue() -> Result<()> {
+    let event_sender = create_event_sender();
+    let mut events = event_sender.subscribe();
+    let (packet_sender, _packet_receiver) = mpsc::unbounded_channel();
+    let track = EchoTrack::new("echo".to_string())
+        .with_config(TrackConfig::default().with_send_queue(2, DropPolicy::DropOldest))
+        .with_delay(Duration::from_millis(100));
+    assert_eq!(track.send_stats(), None);
+    track.start(event_sender, packet_sender).await?;
+
+    let frame = AudioFrame {
+        track_id: "caller".to_string(),
+        timestamp: 0,
+        samples: Samples::PCM {
+            samples: vec![1000; 320],
+        },
+        sample_rate: 16000,
+    };
+    // 2 frames of queue and 5 held back by the delay
+    let mut dropped = 0;
+    for _ in 0..10 {
+        match track.send_frame(&frame).await {
+            Ok(stats) => assert_eq!(stats.map(|s| s.capacity), Some(7)),
+            Err(MediaError::BufferOverrun { .. }) => dropped += 1,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    assert_eq!(dropped, 3);
+    assert_eq!(track.send_stats().map(|s| s.dropped), Some(3));
+
+    track.stop().await?;
+    let metrics = timeout(Duration::from_secs(1), async {
+        loop {
+            if let Ok(SessionEvent::Metrics { key, data, .. }) = events.recv().await
+                && key == "send.echo"
+            {
+                return data;
+            }
+        }
+    })
+    .await?;
+    assert_eq!(metrics["dropped"], 3);
+    assert_eq!(metrics["capacity"], 7);
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use crate::media::shaper::{Shaper, ShapingOption, ShapingStats, TokenBucket};
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_token_bucket() {
+    let start = Instant::now();
+    // 1000 bytes per second, 300 bytes of burst
+    let mut bucket = TokenBucket::new(1000.0, 300.0, start);
+    let max_wait = Duration::from_millis(100);
+
+    assert_eq!(bucket.reserve(200, start, max_wait), Some(Duration::ZERO));
+    assert_eq!(bucket.reserve(100, start, max_wait), Some(Duration::ZERO));
+    // empty: the next packet waits for its tokens
+    assert_eq!(
+        bucket.reserve(50, start, max_wait),
+        Some(Duration::from_millis(50))
+    );
+    // and the one after it queues behind, too long to wait
+    assert_eq!(bucket.reserve(100, start, max_wait), None);
+
+    // a dropped packet takes no tokens
+    let later = start + Duration::from_millis(150);
+    assert_eq!(bucket.reserve(100, later, max_wait), Some(Duration::ZERO));
+    // the bucket never holds more than the burst
+    let idle = start + Duration::from_secs(10);
+    assert_eq!(bucket.reserve(300, idle, max_wait), Some(Duration::ZERO));
+    assert_eq!(bucket.reserve(300, idle, max_wait), None);
+}
+
+#[tokio::test]
+async fn test_shaper_caps_bitrate() {
+    // 64 kbit/s is 8000 bytes per second
+    let shaper = Shaper::new(&ShapingOption {
+        bitrate: 64,
+        burst: 1000,
+        latency: 30,
+    });
+    let start = Instant::now();
+    let mut sent = 0;
+    for _ in 0..20 {
+        if shaper.admit(200).await {
+            sent += 200;
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    assert!(
+        sent as f64 <= 1000.0 + 8000.0 * elapsed + 200.0,
+        "{} bytes in {}s",
+        sent,
+        elapsed
+    );
+
+    let stats = shaper.stats();
+    assert_eq!(stats.sent_packets + stats.dropped_packets, 20);
+    assert!(stats.delayed_packets > 0);
+    assert_eq!(stats.dropped_bytes, stats.dropped_packets * 200);
+}
+
+#[tokio::test]
+async fn test_shaper_under_rate() {
+    let shaper = Shaper::new(&ShapingOption::default());
+    // G.711 at 20ms: 172 bytes every 20ms, about 69 kbit/s
+    for _ in 0..5 {
+        assert!(shaper.admit(172).await);
+    }
+    assert_eq!(
+        shaper.stats(),
+        ShapingStats {
+            sent_packets: 5,
+            ..Default::default()
+        }
+    );
+}
@@ -1,3 +1,4 @@
+use crate::media::error::MediaResult;
 use crate::media::processor::ProcessorChain;
 use crate::media::recorder::RecorderOption;
 use crate::media::track::TrackConfig;
@@ -23,6 +24,8 @@ pub struct TestTrack {
     sender: Option<TrackPacketSender>,
     processor_chain: ProcessorChain,
     received_packets: Arc<Mutex<Vec<AudioFrame>>>,
+    /// How long the far end takes to send a packet back, None never does
+    loopback: Option<Duration>,
 }
 
 impl TestTrack {
@@ -33,8 +36,14 @@ impl TestTrack {
             sender: None,
             processor_chain: ProcessorChain::new(16000),
             received_packets: Arc::new(Mutex::new(Vec::new())),
+            loopback: Some(Duration::ZERO),
         }
     }
+
+    pub fn with_loopback(mut self, loopback: Option<Duration>) -> Self {
+        self.loopback = loopback;
+        self
+    }
 }
 
 #[async_trait]
@@ -51,14 +60,18 @@ impl Track for TestTrack {
     fn processor_chain(&mut self) -> &mut ProcessorChain {
         &mut self.processor_chain
     }
-    async fn handshake(&mut self, _offer: String, _timeout: Option<Duration>) -> Result<String> {
+    async fn handshake(
+        &mut self,
+        _offer: String,
+        _timeout: Option<Duration>,
+    ) -> MediaResult<String> {
         Ok("".to_string())
     }
     async fn start(
         &self,
         _event_sender: EventSender,
         packet_sender: TrackPacketSender,
-    ) -> Result<()> {
+    ) -> MediaResult<()> {
         // Store the packet sender for later use
         if let Some(sender) = unsafe { (self as *const _ as *mut TestTrack).as_mut() } {
             sender.sender = Some(packet_sender);
@@ -66,11 +79,11 @@ impl Track for TestTrack {
         Ok(())
     }
 
-    async fn stop(&self) -> Result<()> {
+    async fn stop(&self) -> MediaResult<()> {
         Ok(())
     }
 
-    async fn send_packet(&self, packet: &AudioFrame) -> Result<()> {
+    async fn send_packet(&self, packet: &AudioFrame) -> MediaResult<()> {
         {
             let mut received = self.received_packets.lock().await;
             received.push(packet.clone());
@@ -84,12 +97,22 @@ impl Track for TestTrack {
             warn!("Error processing packet: {}", e);
         }
 
-        if let Some(sender) = &self.sender {
-            match sender.send(packet_clone) {
-                Ok(_) => {}
-                Err(e) => {
-                    tracing::error!("Failed to send packet: {}", e);
-                }
+        let (Some(sender), Some(loopback)) = (&self.sender, self.loopback) else {
+            return Ok(());
+        };
+        if !loopback.is_zero() {
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(loopback).await;
+                packet_clone.timestamp = crate::get_timestamp();
+                sender.send(packet_clone).ok();
+            });
+            return Ok(());
+        }
+        match sender.send(packet_clone) {
+            Ok(_) => {}
+            Err(e) => {
+                tracing::error!("Failed to send packet: {}", e);
             }
         }
 
@@ -358,3 +381,220 @@ async fn test_stream_forward_payload_conversion() -> Result<()> {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_stream_latency_probe_hops() -> Result<()> {
+    use crate::event::SessionEvent;
+    use crate::media::latency::LatencyHopKind;
+
+    let event_sender = crate::event::create_event_sender();
+    let stream = Arc::new(MediaStreamBuilder::new(event_sender.clone()).build());
+    let caller = TestTrack::new("caller".to_string()).with_loopback(None);
+    let callee =
+        TestTrack::new("callee".to_string()).with_loopback(Some(Duration::from_millis(40)));
+    stream.update_track(Box::new(caller), None).await;
+    stream.update_track(Box::new(callee), None).await;
+    let mut event_receiver = event_sender.subscribe();
+    let serving = stream.clone();
+    let handle = tokio::spawn(async move { serving.serve().await });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    stream
+        .probe_latency(&"callee".to_string(), Duration::from_secs(2))
+        .await;
+    let packet_sender = stream.packet_sender.clone();
+    let speaking = tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_millis(20));
+        loop {
+            ticker.tick().await;
+            let packet = AudioFrame {
+                track_id: "caller".to_string(),
+                timestamp: crate::get_timestamp(),
+                samples: Samples::PCM {
+                    samples: vec![200; 160],
+                },
+                sample_rate: 8000,
+            };
+            if packet_sender.send(packet).is_err() {
+                break;
+            }
+        }
+    });
+
+    let report = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            match event_receiver.recv().await {
+                Ok(SessionEvent::Latency {
+                    track_id, report, ..
+                }) => {
+                    assert_eq!(track_id, "callee");
+                    break report;
+                }
+                Ok(SessionEvent::Error { error, .. }) => panic!("probe failed: {}", error),
+                _ => {}
+            }
+        }
+    })
+    .await?;
+    speaking.abort();
+    handle.abort();
+
+    let hops = report
+        .hops
+        .iter()
+        .map(|hop| (hop.track_id.as_str(), hop.kind))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        hops,
+        vec![
+            ("callee", LatencyHopKind::Sent),
+            ("callee", LatencyHopKind::Received),
+            ("caller", LatencyHopKind::Forwarded),
+        ]
+    );
+    // the far end held the marker for 40ms
+    assert!(report.hops[1].at >= 40.0, "{:?}", report);
+    assert!(report.round_trip >= 35.0, "{:?}", report);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_track_controls() -> Result<()> {
+    use crate::event::SessionEvent;
+    use crate::media::stream::TrackDirection;
+
+    let event_sender = crate::event::create_event_sender();
+    let stream = MediaStreamBuilder::new(event_sender.clone()).build();
+    stream
+        .update_track(Box::new(TestTrack::new("test1".to_string())), None)
+        .await;
+    stream
+        .update_track(Box::new(TestTrack::new("test2".to_string())), None)
+        .await;
+    let mut event_receiver = event_sender.subscribe();
+
+    stream
+        .mute_track(Some("test1".to_string()), TrackDirection::Both)
+        .await;
+    stream
+        .unmute_track(Some("test1".to_string()), TrackDirection::Rx)
+        .await;
+    stream.set_track_gain(None, -6.0, TrackDirection::Tx).await;
+
+    let mut controls = Vec::new();
+    while let Ok(event) = event_receiver.try_recv() {
+        if let SessionEvent::TrackControl {
+            track_id,
+            rx_muted,
+            tx_muted,
+            tx_gain,
+            ..
+        } = event
+        {
+            controls.push((track_id, rx_muted, tx_muted, tx_gain));
+        }
+    }
+    assert_eq!(controls.len(), 4);
+    assert_eq!(controls[0], ("test1".to_string(), true, true, 0.0));
+    assert_eq!(controls[1], ("test1".to_string(), false, true, 0.0));
+    assert!(controls[2..].contains(&("test1".to_string(), false, true, -6.0)));
+    assert!(controls[2..].contains(&("test2".to_string(), false, false, -6.0)));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_dtmf_interworking() -> Result<()> {
+    use crate::media::dtmf::{DtmfMode, detect_inband};
+
+    let event_sender = crate::event::create_event_sender();
+    let stream = Arc::new(MediaStreamBuilder::new(event_sender).build());
+    let caller = TestTrack::new("caller".to_string());
+    let caller_received = caller.received_packets.clone();
+    stream.update_track(Box::new(caller), None).await;
+    stream
+        .update_track(Box::new(TestTrack::new("callee".to_string())), None)
+        .await;
+    stream
+        .set_dtmf_modes(vec![
+            ("caller".to_string(), DtmfMode::Inband),
+            ("callee".to_string(), DtmfMode::Info),
+        ])
+        .await;
+    assert_eq!(
+        stream.dtmf_mode(&"caller".to_string()),
+        Some(DtmfMode::Inband)
+    );
+    assert!(
+        stream
+            .send_dtmf(&"callee".to_string(), "5", Duration::from_millis(100))
+            .await
+            .is_err()
+    );
+    stream
+        .send_dtmf(&"caller".to_string(), "5", Duration::from_millis(100))
+        .await?;
+
+    let serving = stream.clone();
+    let handle = tokio::spawn(async move { serving.serve().await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    // telephone-events don't reach the leg that takes the digits in band
+    stream.packet_sender.send(AudioFrame {
+        track_id: "callee".to_string(),
+        timestamp: 0,
+        samples: Samples::RTP {
+            payload_type: 101,
+            payload: vec![5, 0x80, 0, 160],
+            sequence_number: 1,
+        },
+        sample_rate: 8000,
+    })?;
+    for i in 0..8 {
+        stream.packet_sender.send(AudioFrame {
+            track_id: "callee".to_string(),
+            timestamp: i * 20,
+            samples: Samples::PCM {
+                samples: vec![0; 320],
+            },
+            sample_rate: 16000,
+        })?;
+    }
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    handle.abort();
+
+    let received = caller_received.lock().await;
+    let digits: Vec<_> = received
+        .iter()
+        .map(|frame| match &frame.samples {
+            Samples::PCM { samples } => detect_inband(samples, frame.sample_rate, -36.0),
+            _ => panic!("telephone-event forwarded"),
+        })
+        .collect();
+    // the tone is written over the first 100ms the caller gets, the test
+    // tracks loop the frames back so there is more audio after it
+    assert!(digits[..5].iter().all(|digit| digit == &Some('5')));
+    assert_eq!(digits.iter().flatten().count(), 5);
+    Ok(())
+}
+
+#[test]
+fn test_gain_processor() {
+    use crate::media::{processor::Processor, stream::GainProcessor};
+
+    let mut frame = AudioFrame {
+        track_id: "test".to_string(),
+        timestamp: 0,
+        samples: Samples::PCM {
+            samples: vec![1000, -1000, 20000],
+        },
+        sample_rate: 16000,
+    };
+    GainProcessor::new(6.0).process_frame(&mut frame).unwrap();
+    match frame.samples {
+        Samples::PCM { samples } => {
+            assert!((1990..2000).contains(&samples[0]), "{}", samples[0]);
+            assert!((-2000..-1990).contains(&samples[1]), "{}", samples[1]);
+            assert_eq!(samples[2], i16::MAX);
+        }
+        _ => panic!("expected pcm samples"),
+    }
+}
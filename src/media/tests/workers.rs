@@ -0,0 +1,113 @@
+use crate::{
+    config::MediaWorkersConfig,
+    media::workers::{WorkerLayout, parse_cpu_list},
+};
+
+#[test]
+fn test_parse_cpu_list() {
+    assert_eq!(parse_cpu_list("0-3").unwrap(), vec![0, 1, 2, 3]);
+    assert_eq!(parse_cpu_list("0-1,8-9\n").unwrap(), vec![0, 1, 8, 9]);
+    assert_eq!(parse_cpu_list("5, 2,2").unwrap(), vec![2, 5]);
+    assert!(parse_cpu_list("").is_err());
+    assert!(parse_cpu_list("3-1").is_err());
+    assert!(parse_cpu_list("a-b").is_err());
+}
+
+#[test]
+fn test_worker_layout() {
+    let layout = WorkerLayout::new(&MediaWorkersConfig {
+        cpus: Some("2-3".to_string()),
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(layout.threads(), 2);
+    assert_eq!(layout.cpus(), &[2, 3]);
+
+    let layout = WorkerLayout::new(&MediaWorkersConfig {
+        threads: Some(5),
+        cpus: Some("2-3".to_string()),
+        ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(layout.threads(), 5);
+    assert_eq!(layout.cpu_of(0), Some(2));
+    assert_eq!(layout.cpu_of(1), Some(3));
+    assert_eq!(layout.cpu_of(4), Some(2));
+
+    let layout = WorkerLayout::new(&MediaWorkersConfig::default()).unwrap();
+    assert!(layout.threads() >= 1);
+    assert!(layout.cpus().is_empty());
+    assert_eq!(layout.cpu_of(0), None);
+
+    assert!(
+        WorkerLayout::new(&MediaWorkersConfig {
+            threads: Some(0),
+            ..Default::default()
+        })
+        .is_err()
+    );
+}
+
+#[cfg(target_os = "linux")]
+fn affinity() -> libc::cpu_set_t {
+    let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+    unsafe {
+        libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set);
+    }
+    set
+}
+
+#[cfg(target_os = "linux")]
+fn allowed_cpus() -> usize {
+    unsafe { libc::CPU_COUNT(&affinity()) as usize }
+}
+
+/// A CPU the tests may run on, e.g. not CPU 0 in a restricted container
+fn allowed_cpu() -> String {
+    #[cfg(target_os = "linux")]
+    {
+        let set = affinity();
+        if let Some(cpu) =
+            (0..libc::CPU_SETSIZE as usize).find(|cpu| unsafe { libc::CPU_ISSET(*cpu, &set) })
+        {
+            return cpu.to_string();
+        }
+    }
+    "0".to_string()
+}
+
+#[test]
+fn test_worker_runtime() {
+    let layout = WorkerLayout::new(&MediaWorkersConfig {
+        threads: Some(2),
+        cpus: Some(allowed_cpu()),
+        ..Default::default()
+    })
+    .unwrap();
+    let runtime = layout.build_runtime().unwrap();
+    assert_eq!(runtime.block_on(async { 1 + 1 }), 2);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_worker_runtime_pins_workers_only() {
+    let layout = WorkerLayout::new(&MediaWorkersConfig {
+        threads: Some(1),
+        cpus: Some(allowed_cpu()),
+        ..Default::default()
+    })
+    .unwrap();
+    let runtime = layout.build_runtime().unwrap();
+    let (worker, blocking) = runtime.block_on(async {
+        tokio::spawn(async {
+            // the worker went idle meanwhile
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            let blocking = tokio::task::spawn_blocking(allowed_cpus).await.unwrap();
+            (allowed_cpus(), blocking)
+        })
+        .await
+        .unwrap()
+    });
+    assert_eq!(worker, 1);
+    assert_eq!(blocking, allowed_cpus());
+}
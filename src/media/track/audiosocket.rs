@@ -0,0 +1,430 @@
+use super::{
+    Track, TrackConfig, TrackPacketSender,
+    send_queue::{SendQueue, SendStats},
+};
+use crate::{
+    AudioFrame, PcmBuf, Samples, TrackId,
+    event::{EventSender, SessionEvent},
+    media::{
+        codecs::{resample::resample_mono, samples_to_bytes},
+        error::MediaResult,
+        processor::ProcessorChain,
+    },
+};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use bytes::BytesMut;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::{sync::Mutex, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    select,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Audio is sent in 20ms frames, like Asterisk does
+const FRAME_MS: usize = 20;
+
+/// Sample rates of the signed linear audio kinds, 0x10 (8kHz) to 0x18
+const AUDIO_KINDS: [(u8, u32); 9] = [
+    (0x10, 8000),
+    (0x11, 12000),
+    (0x12, 16000),
+    (0x13, 24000),
+    (0x14, 32000),
+    (0x15, 44100),
+    (0x16, 48000),
+    (0x17, 96000),
+    (0x18, 192000),
+];
+
+/// A frame of the Asterisk AudioSocket protocol: kind (1 byte), payload
+/// length (2 bytes, big endian), payload
+#[derive(Debug, Clone, PartialEq)]
+pub enum AudioSocketFrame {
+    Hangup,
+    Uuid(Uuid),
+    Dtmf(char),
+    /// Signed linear 16 bit mono audio, little endian on the wire
+    Audio {
+        sample_rate: u32,
+        samples: PcmBuf,
+    },
+    Error(u8),
+}
+
+impl AudioSocketFrame {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let (kind, payload) = match self {
+            AudioSocketFrame::Hangup => (0x00, Vec::new()),
+            AudioSocketFrame::Uuid(uuid) => (0x01, uuid.as_bytes().to_vec()),
+            AudioSocketFrame::Dtmf(digit) => (0x03, vec![*digit as u8]),
+            AudioSocketFrame::Audio {
+                sample_rate,
+                samples,
+            } => {
+                let kind = AUDIO_KINDS
+                    .iter()
+                    .find(|(_, rate)| rate == sample_rate)
+                    .map(|(kind, _)| *kind)
+                    .ok_or_else(|| {
+                        anyhow!("unsupported AudioSocket sample rate: {}", sample_rate)
+                    })?;
+                (kind, samples_to_bytes(samples))
+            }
+            AudioSocketFrame::Error(code) => (0xff, vec![*code]),
+        };
+        let len = u16::try_from(payload.len())
+            .map_err(|_| anyhow!("AudioSocket payload too large: {}", payload.len()))?;
+        let mut data = Vec::with_capacity(3 + payload.len());
+        data.push(kind);
+        data.extend_from_slice(&len.to_be_bytes());
+        data.extend_from_slice(&payload);
+        Ok(data)
+    }
+
+    /// Parse the first frame of `buf`, returns the frame and its length, or
+    /// None if `buf` doesn't hold a complete frame yet
+    pub fn parse(buf: &[u8]) -> Result<Option<(Self, usize)>> {
+        if buf.len() < 3 {
+            return Ok(None);
+        }
+        let len = 3 + u16::from_be_bytes([buf[1], buf[2]]) as usize;
+        if buf.len() < len {
+            return Ok(None);
+        }
+        let payload = &buf[3..len];
+        let frame = match buf[0] {
+            0x00 => AudioSocketFrame::Hangup,
+            0x01 => AudioSocketFrame::Uuid(Uuid::from_slice(payload)?),
+            0x03 => match payload.first() {
+                Some(digit) => AudioSocketFrame::Dtmf(*digit as char),
+                None => return Err(anyhow!("empty AudioSocket dtmf frame")),
+            },
+            0xff => AudioSocketFrame::Error(payload.first().copied().unwrap_or_default()),
+            kind => {
+                let sample_rate = AUDIO_KINDS
+                    .iter()
+                    .find(|(k, _)| *k == kind)
+                    .map(|(_, rate)| *rate)
+                    .ok_or_else(|| anyhow!("unknown AudioSocket frame kind: {:#04x}", kind))?;
+                AudioSocketFrame::Audio {
+                    sample_rate,
+                    // the payload is not aligned for a plain cast to samples
+                    samples: payload
+                        .chunks_exact(2)
+                        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                        .collect(),
+                }
+            }
+        };
+        Ok(Some((frame, len)))
+    }
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioSocketOption {
+    /// Address of the AudioSocket server, e.g. 127.0.0.1:9092
+    pub addr: String,
+    /// Uuid sent to the server when connected, random by default
+    pub uuid: Option<String>,
+    /// Sample rate of the audio sent to the server, default 8000
+    pub samplerate: Option<u32>,
+    /// Id of the track, default `audiosocket`
+    pub track_id: Option<String>,
+}
+
+/// Streams the audio it is sent to an AudioSocket server and plays the audio
+/// the server sends back into the call. Caller DTMF is forwarded to the
+/// server; the track ends when the server hangs up or disconnects.
+pub struct AudioSocketTrack {
+    track_id: TrackId,
+    config: TrackConfig,
+    cancel_token: CancellationToken,
+    processor_chain: ProcessorChain,
+    option: AudioSocketOption,
+    uuid: Uuid,
+    ssrc: u32,
+    sender: Mutex<Option<SendQueue<AudioSocketFrame>>>,
+    /// PCM waiting for a full frame
+    buffer: Mutex<PcmBuf>,
+}
+
+impl AudioSocketTrack {
+    pub fn new(
+        ssrc: u32,
+        cancel_token: CancellationToken,
+        option: AudioSocketOption,
+    ) -> Result<Self> {
+        let uuid = match &option.uuid {
+            Some(uuid) => Uuid::parse_str(uuid)?,
+            None => Uuid::new_v4(),
+        };
+        let samplerate = option.samplerate.unwrap_or(8000);
+        if !AUDIO_KINDS.iter().any(|(_, rate)| *rate == samplerate) {
+            return Err(anyhow!(
+                "unsupported AudioSocket sample rate: {}",
+                samplerate
+            ));
+        }
+        let track_id = option
+            .track_id
+            .clone()
+            .unwrap_or_else(|| "audiosocket".to_string());
+        let config = TrackConfig::default().with_sample_rate(samplerate);
+        Ok(Self {
+            processor_chain: ProcessorChain::new(samplerate),
+            track_id,
+            config,
+            cancel_token,
+            option,
+            uuid,
+            ssrc,
+            sender: Mutex::new(None),
+            buffer: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn uuid(&self) -> &Uuid {
+        &self.uuid
+    }
+}
+
+#[async_trait]
+impl Track for AudioSocketTrack {
+    fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+    fn id(&self) -> &TrackId {
+        &self.track_id
+    }
+    fn config(&self) -> &TrackConfig {
+        &self.config
+    }
+    fn processor_chain(&mut self) -> &mut ProcessorChain {
+        &mut self.processor_chain
+    }
+
+    async fn handshake(&mut self, _: String, _: Option<Duration>) -> MediaResult<String> {
+        Ok("".to_string())
+    }
+
+    async fn start(
+        &self,
+        event_sender: EventSender,
+        packet_sender: TrackPacketSender,
+    ) -> MediaResult<()> {
+        let stream = tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&self.option.addr))
+            .await
+            .map_err(|_| anyhow!("AudioSocket connect timeout: {}", self.option.addr))??;
+        stream.set_nodelay(true).ok();
+        info!(
+            track_id = self.track_id,
+            addr = self.option.addr,
+            uuid = %self.uuid,
+            "audiosocket connected"
+        );
+        let (mut reader, mut writer) = stream.into_split();
+        writer
+            .write_all(&AudioSocketFrame::Uuid(self.uuid).encode()?)
+            .await?;
+        let receiver = SendQueue::new(self.config.send_queue_capacity, self.config.drop_policy);
+        *self.sender.lock().unwrap() = Some(receiver.clone());
+
+        let track_id = self.track_id.clone();
+        let cancel_token = self.cancel_token.clone();
+        let processor_chain = self.processor_chain.clone();
+        let sample_rate = self.config.samplerate;
+        let start_time = crate::get_timestamp();
+        let ssrc = self.ssrc;
+        let mut events = event_sender.subscribe();
+        crate::media::workers::spawn(async move {
+            let write_loop = async {
+                loop {
+                    let frame = select! {
+                        _ = cancel_token.cancelled() => {
+                            if let Ok(data) = AudioSocketFrame::Hangup.encode() {
+                                writer.write_all(&data).await.ok();
+                            }
+                            break;
+                        }
+                        frame = receiver.recv() => match frame {
+                            Some(frame) => frame,
+                            None => break,
+                        },
+                        event = events.recv() => match event {
+                            Ok(SessionEvent::Dtmf { track_id: from, digit, .. }) if from != track_id => {
+                                match digit.chars().next() {
+                                    Some(digit) => AudioSocketFrame::Dtmf(digit),
+                                    None => continue,
+                                }
+                            }
+                            Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(_) => break,
+                        },
+                    };
+                    let data = match frame.encode() {
+                        Ok(data) => data,
+                        Err(e) => {
+                            warn!(track_id, "failed to encode AudioSocket frame: {}", e);
+                            continue;
+                        }
+                    };
+                    if let Err(e) = writer.write_all(&data).await {
+                        warn!(track_id, "failed to write AudioSocket frame: {}", e);
+                        break;
+                    }
+                }
+            };
+            let read_loop = async {
+                let mut buf = BytesMut::with_capacity(4096);
+                loop {
+                    match AudioSocketFrame::parse(&buf) {
+                        Ok(Some((frame, len))) => {
+                            let _ = buf.split_to(len);
+                            match frame {
+                                AudioSocketFrame::Hangup => {
+                                    info!(track_id, "audiosocket hangup by server");
+                                    break;
+                                }
+                                AudioSocketFrame::Audio {
+                                    sample_rate: rate,
+                                    samples,
+                                } => {
+                                    let samples = if rate == sample_rate {
+                                        samples
+                                    } else {
+                                        resample_mono(&samples, rate, sample_rate)
+                                    };
+                                    let mut frame = AudioFrame {
+                                        track_id: track_id.clone(),
+                                        samples: Samples::PCM { samples },
+                                        timestamp: crate::get_timestamp(),
+                                        sample_rate,
+                                    };
+                                    if let Err(e) = processor_chain.process_frame(&mut frame) {
+                                        warn!(
+                                            track_id,
+                                            "failed to process AudioSocket frame: {}", e
+                                        );
+                                        continue;
+                                    }
+                                    if packet_sender.send(frame).is_err() {
+                                        break;
+                                    }
+                                }
+                                AudioSocketFrame::Error(code) => {
+                                    warn!(track_id, code, "audiosocket error from server");
+                                    event_sender
+                                        .send(SessionEvent::Error {
+                                            timestamp: crate::get_timestamp(),
+                                            track_id: track_id.clone(),
+                                            sender: "audiosocket".to_string(),
+                                            error: format!("AudioSocket error: {:#04x}", code),
+                                            code: Some(code as u32),
+                                        })
+                                        .ok();
+                                }
+                                frame => {
+                                    debug!(track_id, ?frame, "ignored AudioSocket frame");
+                                }
+                            }
+                        }
+                        Ok(None) => match reader.read_buf(&mut buf).await {
+                            Ok(0) => {
+                                info!(track_id, "audiosocket closed by server");
+                                break;
+                            }
+                            Ok(_) => {}
+                            Err(e) => {
+                                warn!(track_id, "failed to read AudioSocket: {}", e);
+                                break;
+                            }
+                        },
+                        Err(e) => {
+                            warn!(track_id, "invalid AudioSocket frame: {}", e);
+                            break;
+                        }
+                    }
+                }
+            };
+            select! {
+                _ = write_loop => {}
+                _ = read_loop => {}
+            }
+            info!(track_id, "audiosocket stopped");
+            receiver.close();
+            event_sender
+                .send(SessionEvent::Metrics {
+                    timestamp: crate::get_timestamp(),
+                    key: format!("send.{}", track_id),
+                    duration: 0,
+                    data: serde_json::json!(receiver.stats()),
+                })
+                .ok();
+            event_sender
+                .send(SessionEvent::TrackEnd {
+                    track_id,
+                    timestamp: crate::get_timestamp(),
+                    duration: crate::get_timestamp() - start_time,
+                    ssrc,
+                    play_id: None,
+                })
+                .ok();
+        });
+        Ok(())
+    }
+
+    async fn stop(&self) -> MediaResult<()> {
+        self.cancel_token.cancel();
+        Ok(())
+    }
+
+    async fn send_packet(&self, packet: &AudioFrame) -> MediaResult<()> {
+        let samples = match &packet.samples {
+            Samples::PCM { samples } => samples,
+            _ => return Ok(()),
+        };
+        let sample_rate = self.config.samplerate;
+        let samples = if packet.sample_rate == sample_rate {
+            samples.clone()
+        } else {
+            resample_mono(samples, packet.sample_rate, sample_rate)
+        };
+        let frames = {
+            let mut buffer = self.buffer.lock().unwrap();
+            buffer.extend_from_slice(&samples);
+            let size = sample_rate as usize * FRAME_MS / 1000;
+            let mut frames = Vec::new();
+            while buffer.len() >= size {
+                frames.push(AudioSocketFrame::Audio {
+                    sample_rate,
+                    samples: buffer.drain(..size).collect(),
+                });
+            }
+            frames
+        };
+        let Some(sender) = self.sender.lock().unwrap().clone() else {
+            return Ok(());
+        };
+        // queue them all, the overrun is reported once
+        let mut overrun = None;
+        for frame in frames {
+            if let Err(e) = sender.send(frame).await {
+                overrun = Some(e);
+            }
+        }
+        overrun.map_or(Ok(()), Err)
+    }
+
+    fn send_stats(&self) -> Option<SendStats> {
+        self.sender.lock().unwrap().as_ref().map(|s| s.stats())
+    }
+}
@@ -0,0 +1,444 @@
+use crate::event::{EventSender, SessionEvent};
+use crate::media::codecs::resample::resample_mono;
+use crate::media::error::MediaResult;
+use crate::media::processor::ProcessorChain;
+use crate::media::track::{Track, TrackConfig, TrackPacketSender};
+use crate::{AudioFrame, PcmBuf, Sample, Samples, TrackId};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use cpal::{
+    FromSample, Sample as _, SampleFormat, SizedSample,
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicU32, Ordering},
+    mpsc::{self, RecvTimeoutError},
+};
+use tokio::select;
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Audio queued for the speaker, older audio is dropped beyond it
+const MAX_PLAYOUT: Duration = Duration::from_millis(500);
+/// Wait before reopening a device that failed or went away
+const REOPEN_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AudioDevice {
+    pub name: String,
+    pub input: bool,
+    pub output: bool,
+}
+
+/// Audio devices of the default host
+pub fn list_devices() -> Result<Vec<AudioDevice>> {
+    let host = cpal::default_host();
+    let mut devices = Vec::new();
+    for device in host.devices()? {
+        devices.push(AudioDevice {
+            name: device.name()?,
+            input: device.supports_input(),
+            output: device.supports_output(),
+        });
+    }
+    Ok(devices)
+}
+
+/// Mono audio waiting for the speaker, at the device's sample rate
+#[derive(Default)]
+pub struct Playout {
+    samples: Mutex<VecDeque<Sample>>,
+    /// 0 until the output device is open
+    sample_rate: AtomicU32,
+}
+
+impl Playout {
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate.load(Ordering::Relaxed)
+    }
+
+    /// Queues a frame, dropped while no output device is open
+    pub fn push(&self, samples: &[Sample], sample_rate: u32) {
+        let device_rate = self.sample_rate();
+        if device_rate == 0 {
+            return;
+        }
+        let samples = resample_mono(samples, sample_rate, device_rate);
+        let max = (device_rate as u128 * MAX_PLAYOUT.as_millis() / 1000) as usize;
+        let mut queue = self.samples.lock().unwrap();
+        queue.extend(samples);
+        if queue.len() > max {
+            let excess = queue.len() - max;
+            queue.drain(..excess);
+        }
+    }
+
+    /// Fills an interleaved buffer of `channels` channels, with silence
+    /// when the queue runs dry
+    pub fn fill<T: SizedSample + FromSample<f32>>(&self, data: &mut [T], channels: usize) {
+        let mut queue = self.samples.lock().unwrap();
+        for frame in data.chunks_mut(channels.max(1)) {
+            let sample = queue.pop_front().map_or(0.0, f32::from_sample);
+            frame.fill(T::from_sample(sample));
+        }
+    }
+
+    pub(crate) fn open(&self, sample_rate: u32) {
+        self.samples.lock().unwrap().clear();
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    fn close(&self) {
+        self.sample_rate.store(0, Ordering::Relaxed);
+        self.samples.lock().unwrap().clear();
+    }
+}
+
+/// Averages the channels of interleaved device samples
+pub fn downmix<T>(data: &[T], channels: usize) -> PcmBuf
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = channels.max(1);
+    data.chunks(channels)
+        .map(|frame| {
+            let sum = frame.iter().map(|s| f32::from_sample(*s)).sum::<f32>();
+            Sample::from_sample(sum / frame.len() as f32)
+        })
+        .collect()
+}
+
+/// Streams a local microphone into the media stream and plays what the
+/// stream sends to the track on a local speaker, so rustpbx can act as a
+/// softphone or a test head. Devices are picked by name, the default ones
+/// when None, and reopened when they fail or are unplugged.
+pub struct DeviceTrack {
+    track_id: TrackId,
+    config: TrackConfig,
+    cancel_token: CancellationToken,
+    processor_chain: ProcessorChain,
+    ssrc: u32,
+    input_device: Option<String>,
+    output_device: Option<String>,
+    capture: bool,
+    playback: bool,
+    playout: Arc<Playout>,
+}
+
+impl DeviceTrack {
+    pub fn new(id: TrackId) -> Self {
+        let config = TrackConfig::default();
+        Self {
+            track_id: id,
+            processor_chain: ProcessorChain::new(config.samplerate),
+            config,
+            cancel_token: CancellationToken::new(),
+            ssrc: 0,
+            input_device: None,
+            output_device: None,
+            capture: true,
+            playback: true,
+            playout: Arc::new(Playout::default()),
+        }
+    }
+
+    pub fn with_ssrc(mut self, ssrc: u32) -> Self {
+        self.ssrc = ssrc;
+        self
+    }
+
+    pub fn with_config(mut self, config: TrackConfig) -> Self {
+        self.processor_chain = ProcessorChain::new(config.samplerate);
+        self.config = config;
+        self
+    }
+
+    pub fn with_cancel_token(mut self, cancel_token: CancellationToken) -> Self {
+        self.cancel_token = cancel_token;
+        self
+    }
+
+    pub fn with_input_device(mut self, name: Option<String>) -> Self {
+        self.input_device = name;
+        self
+    }
+
+    pub fn with_output_device(mut self, name: Option<String>) -> Self {
+        self.output_device = name;
+        self
+    }
+
+    /// Whether to record the microphone
+    pub fn with_capture(mut self, capture: bool) -> Self {
+        self.capture = capture;
+        self
+    }
+
+    /// Whether to play on the speaker
+    pub fn with_playback(mut self, playback: bool) -> Self {
+        self.playback = playback;
+        self
+    }
+}
+
+#[async_trait]
+impl Track for DeviceTrack {
+    fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+    fn id(&self) -> &TrackId {
+        &self.track_id
+    }
+    fn config(&self) -> &TrackConfig {
+        &self.config
+    }
+    fn processor_chain(&mut self) -> &mut ProcessorChain {
+        &mut self.processor_chain
+    }
+
+    async fn handshake(
+        &mut self,
+        _offer: String,
+        _timeout: Option<Duration>,
+    ) -> MediaResult<String> {
+        Ok("".to_string())
+    }
+
+    async fn start(
+        &self,
+        event_sender: EventSender,
+        packet_sender: TrackPacketSender,
+    ) -> MediaResult<()> {
+        let id = self.track_id.clone();
+        let ssrc = self.ssrc;
+        let sample_rate = self.config.samplerate;
+        let ptime = self.config.ptime;
+        let processor_chain = self.processor_chain.clone();
+        let token = self.cancel_token.clone();
+        let start_time = crate::get_timestamp();
+        let (captured_sender, mut captured_receiver) = unbounded_channel();
+
+        // cpal streams can't leave the thread that opened them
+        let devices = Devices {
+            input: self.capture.then(|| self.input_device.clone()),
+            output: self.playback.then(|| self.output_device.clone()),
+        };
+        let playout = self.playout.clone();
+        let device_token = token.clone();
+        std::thread::Builder::new()
+            .name(format!("device-{}", id))
+            .spawn(move || run_devices(devices, captured_sender, playout, device_token))?;
+
+        info!(track_id = id, "devicetrack: started");
+        crate::media::workers::spawn(async move {
+            let capture_loop = async {
+                let mut pending = PcmBuf::new();
+                let mut pending_rate = 0;
+                while let Some((device_rate, samples)) = captured_receiver.recv().await {
+                    if device_rate != pending_rate {
+                        pending.clear();
+                        pending_rate = device_rate;
+                    }
+                    pending.extend(samples);
+                    let chunk = (device_rate as u128 * ptime.as_millis() / 1000) as usize;
+                    while chunk > 0 && pending.len() >= chunk {
+                        let samples = resample_mono(&pending[..chunk], device_rate, sample_rate);
+                        pending.drain(..chunk);
+                        let mut frame = AudioFrame {
+                            track_id: id.clone(),
+                            timestamp: crate::get_timestamp(),
+                            samples: Samples::PCM { samples },
+                            sample_rate,
+                        };
+                        processor_chain.process_frame(&mut frame).ok();
+                        if packet_sender.send(frame).is_err() {
+                            return;
+                        }
+                    }
+                }
+            };
+            select! {
+                _ = token.cancelled() => {}
+                _ = capture_loop => {}
+            }
+            token.cancel();
+            event_sender
+                .send(SessionEvent::TrackEnd {
+                    track_id: id,
+                    timestamp: crate::get_timestamp(),
+                    duration: crate::get_timestamp() - start_time,
+                    ssrc,
+                    play_id: None,
+                })
+                .ok();
+        });
+        Ok(())
+    }
+
+    async fn stop(&self) -> MediaResult<()> {
+        self.cancel_token.cancel();
+        Ok(())
+    }
+
+    async fn send_packet(&self, packet: &AudioFrame) -> MediaResult<()> {
+        if let Samples::PCM { samples } = &packet.samples {
+            self.playout.push(samples, packet.sample_rate);
+        }
+        Ok(())
+    }
+}
+
+/// Devices to open, None when not wanted, Some(None) for the default one
+struct Devices {
+    input: Option<Option<String>>,
+    output: Option<Option<String>>,
+}
+
+fn run_devices(
+    devices: Devices,
+    captured: UnboundedSender<(u32, PcmBuf)>,
+    playout: Arc<Playout>,
+    token: CancellationToken,
+) {
+    while !token.is_cancelled() {
+        let (error_sender, error_receiver) = mpsc::channel();
+        match open_streams(&devices, &captured, &playout, error_sender) {
+            Ok(_streams) => loop {
+                match error_receiver.recv_timeout(Duration::from_millis(100)) {
+                    Ok(e) => {
+                        warn!("devicetrack: device failed: {}", e);
+                        break;
+                    }
+                    Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {}
+                }
+                if token.is_cancelled() || captured.is_closed() {
+                    playout.close();
+                    return;
+                }
+            },
+            Err(e) => warn!("devicetrack: failed to open devices: {}", e),
+        }
+        playout.close();
+        std::thread::sleep(REOPEN_INTERVAL);
+    }
+}
+
+fn find_device(host: &cpal::Host, name: &Option<String>, input: bool) -> Result<cpal::Device> {
+    let device = match name {
+        None if input => host.default_input_device(),
+        None => host.default_output_device(),
+        Some(name) => {
+            let mut devices = match input {
+                true => host.input_devices()?,
+                false => host.output_devices()?,
+            };
+            devices.find(|device| device.name().is_ok_and(|n| &n == name))
+        }
+    };
+    device.ok_or_else(|| anyhow!("no audio device {}", name.as_deref().unwrap_or("default")))
+}
+
+fn open_streams(
+    devices: &Devices,
+    captured: &UnboundedSender<(u32, PcmBuf)>,
+    playout: &Arc<Playout>,
+    errors: mpsc::Sender<cpal::StreamError>,
+) -> Result<Vec<cpal::Stream>> {
+    let host = cpal::default_host();
+    let mut streams = Vec::new();
+    if let Some(name) = &devices.input {
+        let device = find_device(&host, name, true)?;
+        let config = device.default_input_config()?;
+        info!(
+            device = device.name()?,
+            ?config,
+            "devicetrack: opening input"
+        );
+        let stream = match config.sample_format() {
+            SampleFormat::I16 => input_stream::<i16>(&device, &config, captured, &errors),
+            SampleFormat::I32 => input_stream::<i32>(&device, &config, captured, &errors),
+            SampleFormat::U16 => input_stream::<u16>(&device, &config, captured, &errors),
+            SampleFormat::F32 => input_stream::<f32>(&device, &config, captured, &errors),
+            format => Err(anyhow!("unsupported sample format {}", format)),
+        }?;
+        stream.play()?;
+        streams.push(stream);
+    }
+    if let Some(name) = &devices.output {
+        let device = find_device(&host, name, false)?;
+        let config = device.default_output_config()?;
+        info!(
+            device = device.name()?,
+            ?config,
+            "devicetrack: opening output"
+        );
+        let stream = match config.sample_format() {
+            SampleFormat::I16 => output_stream::<i16>(&device, &config, playout, &errors),
+            SampleFormat::I32 => output_stream::<i32>(&device, &config, playout, &errors),
+            SampleFormat::U16 => output_stream::<u16>(&device, &config, playout, &errors),
+            SampleFormat::F32 => output_stream::<f32>(&device, &config, playout, &errors),
+            format => Err(anyhow!("unsupported sample format {}", format)),
+        }?;
+        playout.open(config.sample_rate().0);
+        stream.play()?;
+        streams.push(stream);
+    }
+    Ok(streams)
+}
+
+fn input_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    captured: &UnboundedSender<(u32, PcmBuf)>,
+    errors: &mpsc::Sender<cpal::StreamError>,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = config.channels() as usize;
+    let sample_rate = config.sample_rate().0;
+    let captured = captured.clone();
+    let errors = errors.clone();
+    let stream = device.build_input_stream(
+        &config.config(),
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            captured.send((sample_rate, downmix(data, channels))).ok();
+        },
+        move |e| {
+            errors.send(e).ok();
+        },
+        None,
+    )?;
+    Ok(stream)
+}
+
+fn output_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::SupportedStreamConfig,
+    playout: &Arc<Playout>,
+    errors: &mpsc::Sender<cpal::StreamError>,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels() as usize;
+    let playout = playout.clone();
+    let errors = errors.clone();
+    let stream = device.build_output_stream(
+        &config.config(),
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| playout.fill(data, channels),
+        move |e| {
+            errors.send(e).ok();
+        },
+        None,
+    )?;
+    Ok(stream)
+}
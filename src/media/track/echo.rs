@@ -0,0 +1,165 @@
+use crate::event::{EventSender, SessionEvent};
+use crate::media::error::MediaResult;
+use crate::media::processor::ProcessorChain;
+use crate::media::track::{
+    Track, TrackConfig, TrackPacketSender,
+    send_queue::{SendQueue, SendStats},
+};
+use crate::{AudioFrame, TrackId};
+use async_trait::async_trait;
+use std::sync::Mutex;
+use tokio::select;
+use tokio::time::{Duration, Instant, sleep_until};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Loops the audio it receives back to the stream after `delay`, used by the
+/// echo test to diagnose one-way audio and latency.
+pub struct EchoTrack {
+    track_id: TrackId,
+    config: TrackConfig,
+    cancel_token: CancellationToken,
+    processor_chain: ProcessorChain,
+    delay: Duration,
+    ssrc: u32,
+    sender: Mutex<Option<SendQueue<(Instant, AudioFrame)>>>,
+}
+
+impl EchoTrack {
+    pub fn new(id: TrackId) -> Self {
+        let config = TrackConfig::default();
+        Self {
+            track_id: id,
+            processor_chain: ProcessorChain::new(config.samplerate),
+            config,
+            cancel_token: CancellationToken::new(),
+            delay: Duration::ZERO,
+            ssrc: 0,
+            sender: Mutex::new(None),
+        }
+    }
+
+    pub fn with_ssrc(mut self, ssrc: u32) -> Self {
+        self.ssrc = ssrc;
+        self
+    }
+
+    pub fn with_config(mut self, config: TrackConfig) -> Self {
+        self.processor_chain = ProcessorChain::new(config.samplerate);
+        self.config = config;
+        self
+    }
+
+    pub fn with_cancel_token(mut self, cancel_token: CancellationToken) -> Self {
+        self.cancel_token = cancel_token;
+        self
+    }
+
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+#[async_trait]
+impl Track for EchoTrack {
+    fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+    fn id(&self) -> &TrackId {
+        &self.track_id
+    }
+    fn config(&self) -> &TrackConfig {
+        &self.config
+    }
+    fn processor_chain(&mut self) -> &mut ProcessorChain {
+        &mut self.processor_chain
+    }
+
+    async fn handshake(
+        &mut self,
+        _offer: String,
+        _timeout: Option<Duration>,
+    ) -> MediaResult<String> {
+        Ok("".to_string())
+    }
+
+    async fn start(
+        &self,
+        event_sender: EventSender,
+        packet_sender: TrackPacketSender,
+    ) -> MediaResult<()> {
+        // room for the frames held back by the delay on top of the queue
+        let delayed = self.delay.as_millis() / self.config.ptime.as_millis().max(1);
+        let receiver = SendQueue::new(
+            self.config.send_queue_capacity + delayed as usize,
+            self.config.drop_policy,
+        );
+        *self.sender.lock().unwrap() = Some(receiver.clone());
+
+        let id = self.track_id.clone();
+        let delay = self.delay;
+        let ssrc = self.ssrc;
+        let processor_chain = self.processor_chain.clone();
+        let token = self.cancel_token.clone();
+        let start_time = crate::get_timestamp();
+        info!(
+            track_id = id,
+            delay = delay.as_millis(),
+            "echotrack: started"
+        );
+        crate::media::workers::spawn(async move {
+            let echo_loop = async {
+                while let Some((received_at, mut frame)) = receiver.recv().await {
+                    sleep_until(received_at + delay).await;
+                    frame.track_id = id.clone();
+                    frame.timestamp = crate::get_timestamp();
+                    processor_chain.process_frame(&mut frame).ok();
+                    if packet_sender.send(frame).is_err() {
+                        break;
+                    }
+                }
+            };
+            select! {
+                _ = token.cancelled() => {}
+                _ = echo_loop => {}
+            }
+            receiver.close();
+            event_sender
+                .send(SessionEvent::Metrics {
+                    timestamp: crate::get_timestamp(),
+                    key: format!("send.{}", id),
+                    duration: 0,
+                    data: serde_json::json!(receiver.stats()),
+                })
+                .ok();
+            event_sender
+                .send(SessionEvent::TrackEnd {
+                    track_id: id,
+                    timestamp: crate::get_timestamp(),
+                    duration: crate::get_timestamp() - start_time,
+                    ssrc,
+                    play_id: None,
+                })
+                .ok();
+        });
+        Ok(())
+    }
+
+    async fn stop(&self) -> MediaResult<()> {
+        self.cancel_token.cancel();
+        Ok(())
+    }
+
+    async fn send_packet(&self, packet: &AudioFrame) -> MediaResult<()> {
+        let sender = self.sender.lock().unwrap().clone();
+        if let Some(sender) = sender {
+            sender.send((Instant::now(), packet.clone())).await?;
+        }
+        Ok(())
+    }
+
+    fn send_stats(&self) -> Option<SendStats> {
+        self.sender.lock().unwrap().as_ref().map(|s| s.stats())
+    }
+}
@@ -1,27 +1,39 @@
 use crate::event::{EventSender, SessionEvent};
+use crate::media::clock::{ClockRef, system_clock};
 use crate::media::codecs::resample::LinearResampler;
+use crate::media::error::MediaResult;
+use crate::media::pacer::Pacer;
 use crate::media::processor::ProcessorChain;
 use crate::media::{
     cache,
     track::{Track, TrackConfig, TrackPacketSender},
 };
-use crate::{AudioFrame, PcmBuf, Samples, TrackId};
+use crate::{AudioFrame, PcmBuf, Sample, Samples, TrackId};
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
-use hound::WavReader;
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
 use reqwest::Client;
 use rmp3;
 use std::cmp::min;
 use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom, Write};
 use std::time::Instant;
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{CODEC_TYPE_NULL, CODEC_TYPE_OPUS, DecoderOptions},
+    errors::Error as SymphoniaError,
+    formats::{FormatOptions, Packet},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+};
 use tokio::select;
 use tokio::time::Duration;
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 use url::Url;
 
-// AudioReader trait to unify WAV and MP3 handling
+// AudioReader trait to unify WAV, MP3, OGG and FLAC handling
 trait AudioReader: Send {
     fn fill_buffer(&mut self) -> Result<usize>;
 
@@ -347,6 +359,170 @@ impl AudioReader for Mp3AudioReader {
     }
 }
 
+/// Decodes a packet of the demuxer to interleaved samples
+type PacketDecoder = Box<dyn FnMut(&Packet) -> Result<Vec<i16>>>;
+
+#[cfg(feature = "opus")]
+fn opus_packet_decoder(channels: usize) -> Result<PacketDecoder> {
+    use crate::media::codecs::{Decoder, opus::OpusDecoder};
+    let mut decoder = OpusDecoder::new(48000, channels as u16);
+    Ok(Box::new(move |packet| Ok(decoder.decode(&packet.data))))
+}
+
+#[cfg(not(feature = "opus"))]
+fn opus_packet_decoder(_channels: usize) -> Result<PacketDecoder> {
+    Err(anyhow!("Opus files need the opus feature"))
+}
+
+/// OGG (Vorbis, Opus) and FLAC files, decoded with symphonia. Opus has no
+/// symphonia decoder, its packets go to the codec of the media path.
+struct SymphoniaAudioReader {
+    buffer: Vec<i16>,
+    sample_rate: u32,
+    position: usize,
+    target_sample_rate: u32,
+    resampler: Option<LinearResampler>,
+}
+
+impl SymphoniaAudioReader {
+    fn from_file(file: File, format: AudioFormat, target_sample_rate: u32) -> Result<Self> {
+        let source = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        hint.with_extension(match format {
+            AudioFormat::Flac => "flac",
+            _ => "ogg",
+        });
+        let mut reader = symphonia::default::get_probe()
+            .format(
+                &hint,
+                source,
+                &FormatOptions::default(),
+                &MetadataOptions::default(),
+            )?
+            .format;
+        let track = reader
+            .tracks()
+            .iter()
+            .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| anyhow!("No audio track in {:?} file", format))?;
+        let track_id = track.id;
+        let params = track.codec_params.clone();
+        let channels = params.channels.map(|c| c.count()).unwrap_or(1).max(1);
+        let (mut decode, sample_rate, channels) = if params.codec == CODEC_TYPE_OPUS {
+            // decoded at 48 kHz whatever the input rate was
+            let channels = channels.min(2);
+            (opus_packet_decoder(channels)?, 48000, channels)
+        } else {
+            let mut decoder =
+                symphonia::default::get_codecs().make(&params, &DecoderOptions::default())?;
+            let decode: PacketDecoder = Box::new(move |packet| match decoder.decode(packet) {
+                Ok(decoded) => {
+                    let mut samples =
+                        SampleBuffer::<i16>::new(decoded.capacity() as u64, *decoded.spec());
+                    samples.copy_interleaved_ref(decoded);
+                    Ok(samples.samples().to_vec())
+                }
+                Err(SymphoniaError::DecodeError(e)) => {
+                    warn!("filetrack: Skipping undecodable packet: {}", e);
+                    Ok(Vec::new())
+                }
+                Err(e) => Err(e.into()),
+            });
+            (decode, params.sample_rate.unwrap_or(0), channels)
+        };
+
+        let mut interleaved = Vec::new();
+        loop {
+            let packet = match reader.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e))
+                    if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                {
+                    break;
+                }
+                Err(SymphoniaError::ResetRequired) => break,
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != track_id {
+                continue;
+            }
+            interleaved.extend(decode(&packet)?);
+        }
+        // encoder delay, e.g. the pre-skip of Opus
+        let delay = params.delay.unwrap_or(0) as usize * channels;
+        interleaved.drain(..delay.min(interleaved.len()));
+
+        let all_samples: Vec<i16> = interleaved
+            .chunks(channels)
+            .map(|frame| (frame.iter().map(|&s| s as i32).sum::<i32>() / frame.len() as i32) as i16)
+            .collect();
+        info!(
+            "{:?} file detected with sample rate: {} Hz, channels: {}, decoded {} samples",
+            format,
+            sample_rate,
+            channels,
+            all_samples.len()
+        );
+
+        Ok(Self {
+            buffer: all_samples,
+            sample_rate,
+            position: 0,
+            target_sample_rate,
+            resampler: None,
+        })
+    }
+}
+
+impl AudioReader for SymphoniaAudioReader {
+    fn fill_buffer(&mut self) -> Result<usize> {
+        // All data is already decoded and stored in buffer
+        if self.position >= self.buffer.len() {
+            return Ok(0);
+        }
+        Ok(self.buffer.len() - self.position)
+    }
+
+    fn buffer_size(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn set_position(&mut self, pos: usize) {
+        self.position = pos;
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn target_sample_rate(&self) -> u32 {
+        self.target_sample_rate
+    }
+
+    fn extract_chunk(&self, start: usize, end: usize) -> Vec<i16> {
+        self.buffer[start..end].to_vec()
+    }
+
+    fn resample_chunk(&mut self, chunk: &[i16]) -> Vec<i16> {
+        if self.sample_rate == 0 || self.sample_rate == self.target_sample_rate {
+            return chunk.to_vec();
+        }
+        if self.resampler.is_none() {
+            self.resampler =
+                LinearResampler::new(self.sample_rate as usize, self.target_sample_rate as usize)
+                    .ok();
+        }
+        match &mut self.resampler {
+            Some(resampler) => resampler.resample(chunk),
+            None => chunk.to_vec(),
+        }
+    }
+}
+
 // Unified function to process any audio reader and stream audio
 async fn process_audio_reader(
     processor_chain: ProcessorChain,
@@ -356,6 +532,7 @@ async fn process_audio_reader(
     target_sample_rate: u32,
     token: CancellationToken,
     packet_sender: TrackPacketSender,
+    clock: ClockRef,
 ) -> Result<()> {
     info!(
         "streaming audio with target_sample_rate: {}, packet_duration: {}ms",
@@ -363,11 +540,15 @@ async fn process_audio_reader(
     );
     let stream_loop = async move {
         let start_time = Instant::now();
-        let mut ticker = tokio::time::interval(Duration::from_millis(packet_duration_ms as u64));
+        let mut pacer = Pacer::with_clock(
+            Duration::from_millis(packet_duration_ms as u64),
+            clock.clone(),
+        );
         while let Some((chunk, chunk_sample_rate)) = audio_reader.read_chunk(packet_duration_ms)? {
+            pacer.tick().await;
             let mut packet = AudioFrame {
                 track_id: track_id.to_string(),
-                timestamp: crate::get_timestamp(),
+                timestamp: clock.timestamp(),
                 samples: Samples::PCM { samples: chunk },
                 sample_rate: chunk_sample_rate,
             };
@@ -383,11 +564,13 @@ async fn process_audio_reader(
                 warn!("failed to send audio packet: {}", e);
                 break;
             }
-
-            ticker.tick().await;
         }
 
-        info!("stream loop finished in {:?}", start_time.elapsed());
+        info!(
+            pacing = ?pacer.stats(),
+            "stream loop finished in {:?}",
+            start_time.elapsed()
+        );
         Ok(()) as Result<()>
     };
 
@@ -409,8 +592,10 @@ pub struct FileTrack {
     cancel_token: CancellationToken,
     processor_chain: ProcessorChain,
     path: Option<String>,
+    play_id: Option<String>,
     use_cache: bool,
     ssrc: u32,
+    clock: ClockRef,
 }
 
 impl FileTrack {
@@ -422,8 +607,10 @@ impl FileTrack {
             config,
             cancel_token: CancellationToken::new(),
             path: None,
+            play_id: None,
             use_cache: true,
             ssrc: 0,
+            clock: system_clock(),
         }
     }
     pub fn with_ssrc(mut self, ssrc: u32) -> Self {
@@ -445,6 +632,12 @@ impl FileTrack {
         self
     }
 
+    /// Reported in the trackEnd event instead of the path
+    pub fn with_play_id(mut self, play_id: Option<String>) -> Self {
+        self.play_id = play_id;
+        self
+    }
+
     pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
         self.config = self.config.with_sample_rate(sample_rate);
         self
@@ -459,6 +652,12 @@ impl FileTrack {
         self.use_cache = use_cache;
         self
     }
+
+    /// Paces the frames and stamps them and the events on `clock`
+    pub fn with_clock(mut self, clock: ClockRef) -> Self {
+        self.clock = clock;
+        self
+    }
 }
 
 #[async_trait]
@@ -476,7 +675,11 @@ impl Track for FileTrack {
         &mut self.processor_chain
     }
 
-    async fn handshake(&mut self, _offer: String, _timeout: Option<Duration>) -> Result<String> {
+    async fn handshake(
+        &mut self,
+        _offer: String,
+        _timeout: Option<Duration>,
+    ) -> MediaResult<String> {
         Ok("".to_string())
     }
 
@@ -484,21 +687,23 @@ impl Track for FileTrack {
         &self,
         event_sender: EventSender,
         packet_sender: TrackPacketSender,
-    ) -> Result<()> {
+    ) -> MediaResult<()> {
         if self.path.is_none() {
-            return Err(anyhow::anyhow!("filetrack: No path provided for FileTrack"));
+            return Err(anyhow::anyhow!("filetrack: No path provided for FileTrack").into());
         }
         let path = self.path.clone().unwrap();
+        let play_id = self.play_id.clone().unwrap_or_else(|| path.clone());
         let id = self.track_id.clone();
         let sample_rate = self.config.samplerate;
         let use_cache = self.use_cache;
         let packet_duration_ms = self.config.ptime.as_millis() as u32;
         let processor_chain = self.processor_chain.clone();
         let token = self.cancel_token.clone();
-        let start_time = crate::get_timestamp();
+        let clock = self.clock.clone();
+        let start_time = clock.timestamp();
         let ssrc = self.ssrc;
         // Spawn async task to handle file streaming
-        tokio::spawn(async move {
+        crate::media::workers::spawn(async move {
             // Determine file extension
             let extension = if path.starts_with("http://") || path.starts_with("https://") {
                 path.parse::<Url>()?
@@ -525,7 +730,7 @@ impl Track for FileTrack {
                     event_sender
                         .send(SessionEvent::Error {
                             track_id: id.clone(),
-                            timestamp: crate::get_timestamp(),
+                            timestamp: clock.timestamp(),
                             sender: format!("filetrack: {}", path),
                             error: e.to_string(),
                             code: None,
@@ -534,10 +739,10 @@ impl Track for FileTrack {
                     event_sender
                         .send(SessionEvent::TrackEnd {
                             track_id: id,
-                            timestamp: crate::get_timestamp(),
-                            duration: crate::get_timestamp() - start_time,
+                            timestamp: clock.timestamp(),
+                            duration: clock.timestamp() - start_time,
                             ssrc,
-                            play_id: Some(path),
+                            play_id: Some(play_id),
                         })
                         .ok();
                     return Err(e);
@@ -554,6 +759,7 @@ impl Track for FileTrack {
                 packet_duration_ms,
                 token,
                 packet_sender,
+                clock.clone(),
             )
             .await;
 
@@ -563,7 +769,7 @@ impl Track for FileTrack {
                 event_sender
                     .send(SessionEvent::Error {
                         track_id: id.clone(),
-                        timestamp: crate::get_timestamp(),
+                        timestamp: clock.timestamp(),
                         sender: format!("filetrack: {}", path),
                         error: e.to_string(),
                         code: None,
@@ -575,10 +781,10 @@ impl Track for FileTrack {
             event_sender
                 .send(SessionEvent::TrackEnd {
                     track_id: id,
-                    timestamp: crate::get_timestamp(),
-                    duration: crate::get_timestamp() - start_time,
+                    timestamp: clock.timestamp(),
+                    duration: clock.timestamp() - start_time,
                     ssrc,
-                    play_id: Some(path),
+                    play_id: Some(play_id),
                 })
                 .ok();
             Ok::<(), anyhow::Error>(())
@@ -586,14 +792,14 @@ impl Track for FileTrack {
         Ok(())
     }
 
-    async fn stop(&self) -> Result<()> {
+    async fn stop(&self) -> MediaResult<()> {
         // Cancel the file streaming task
         self.cancel_token.cancel();
         Ok(())
     }
 
     // Do nothing as we are not sending packets
-    async fn send_packet(&self, _packet: &AudioFrame) -> Result<()> {
+    async fn send_packet(&self, _packet: &AudioFrame) -> MediaResult<()> {
         Ok(())
     }
 }
@@ -646,20 +852,68 @@ async fn download_from_url(url: &str, use_cache: bool) -> Result<File> {
     Ok(temp_file)
 }
 
-// Helper function to stream a WAV or MP3 file
+/// Container of an audio file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AudioFormat {
+    Wav,
+    Mp3,
+    Ogg,
+    Flac,
+}
+
+impl AudioFormat {
+    /// Recognizes the format by the leading bytes of the file, by the
+    /// extension when they are not conclusive
+    pub fn detect(header: &[u8], extension: &str) -> Option<Self> {
+        match header {
+            [
+                b'R',
+                b'I',
+                b'F',
+                b'F',
+                _,
+                _,
+                _,
+                _,
+                b'W',
+                b'A',
+                b'V',
+                b'E',
+                ..,
+            ] => Some(Self::Wav),
+            [b'I', b'D', b'3', ..] => Some(Self::Mp3),
+            [0xff, sync, ..] if sync & 0xe0 == 0xe0 => Some(Self::Mp3),
+            [b'O', b'g', b'g', b'S', ..] => Some(Self::Ogg),
+            [b'f', b'L', b'a', b'C', ..] => Some(Self::Flac),
+            _ => match extension.to_lowercase().as_str() {
+                "wav" => Some(Self::Wav),
+                "mp3" => Some(Self::Mp3),
+                "ogg" | "oga" | "opus" => Some(Self::Ogg),
+                "flac" => Some(Self::Flac),
+                _ => None,
+            },
+        }
+    }
+}
+
+// Helper function to stream a WAV, MP3, OGG or FLAC file
 async fn stream_audio_file(
     processor_chain: ProcessorChain,
     extension: &str,
-    file: File,
+    mut file: File,
     track_id: &str,
     target_sample_rate: u32,
     packet_duration_ms: u32,
     token: CancellationToken,
     packet_sender: TrackPacketSender,
+    clock: ClockRef,
 ) -> Result<()> {
     let start_time = Instant::now();
-    let audio_reader = match extension {
-        "wav" => {
+    let mut header = [0u8; 12];
+    let read = file.read(&mut header)?;
+    file.seek(SeekFrom::Start(0))?;
+    let audio_reader = match AudioFormat::detect(&header[..read], extension) {
+        Some(AudioFormat::Wav) => {
             // Use spawn_blocking for CPU-intensive WAV decoding
             let reader = tokio::task::spawn_blocking(move || {
                 WavAudioReader::from_file(file, target_sample_rate)
@@ -667,7 +921,7 @@ async fn stream_audio_file(
             .await??;
             Box::new(reader) as Box<dyn AudioReader>
         }
-        "mp3" => {
+        Some(AudioFormat::Mp3) => {
             // Use spawn_blocking for CPU-intensive MP3 decoding
             let reader = tokio::task::spawn_blocking(move || {
                 Mp3AudioReader::from_file(file, target_sample_rate)
@@ -675,7 +929,14 @@ async fn stream_audio_file(
             .await??;
             Box::new(reader) as Box<dyn AudioReader>
         }
-        _ => return Err(anyhow!("Unsupported audio format: {}", extension)),
+        Some(format @ (AudioFormat::Ogg | AudioFormat::Flac)) => {
+            let reader = tokio::task::spawn_blocking(move || {
+                SymphoniaAudioReader::from_file(file, format, target_sample_rate)
+            })
+            .await??;
+            Box::new(reader) as Box<dyn AudioReader>
+        }
+        None => return Err(anyhow!("Unsupported audio format: {}", extension)),
     };
     info!(
         "filetrack: Load file duration: {:.2} seconds, sample rate: {} Hz, extension: {}",
@@ -691,6 +952,7 @@ async fn stream_audio_file(
         target_sample_rate,
         token,
         packet_sender,
+        clock,
     )
     .await
 }
@@ -744,15 +1006,40 @@ pub fn read_wav_file(path: &str) -> Result<(PcmBuf, u32)> {
     Ok((all_samples, spec.sample_rate))
 }
 
+/// Encode mono 16bit PCM samples as an in-memory WAV file
+pub fn encode_wav(samples: &[Sample], sample_rate: u32) -> Result<Vec<u8>> {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut buf = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut buf, spec)?;
+    for &sample in samples {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+    Ok(buf.into_inner())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::media::cache::ensure_cache_dir;
+    use crate::media::{cache::ensure_cache_dir, codecs::testing};
     use tokio::sync::{broadcast, mpsc};
 
+    /// Speech-like noise at 16 kHz, in a WAV file of `dir`
+    fn sample_wav(dir: &tempfile::TempDir, duration: Duration) -> Result<String> {
+        let path = dir.path().join("sample.wav");
+        testing::write_wav(&path, &testing::speech_noise(1, 16000, duration), 16000)?;
+        Ok(path.to_string_lossy().to_string())
+    }
+
     #[tokio::test]
     async fn test_wav_reader() -> Result<()> {
-        let file_path = "fixtures/sample.wav";
+        let dir = tempfile::tempdir()?;
+        let file_path = sample_wav(&dir, Duration::from_millis(7510))?;
         let file = File::open(file_path)?;
         let mut reader = WavAudioReader::from_file(file, 16000)?;
         let mut total_samples = 0;
@@ -776,11 +1063,12 @@ mod tests {
     async fn test_wav_file_track() -> Result<()> {
         println!("Starting WAV file track test");
 
-        let file_path = "fixtures/sample.wav";
-        let file = File::open(file_path)?;
+        let dir = tempfile::tempdir()?;
+        let file_path = sample_wav(&dir, Duration::from_millis(7510))?;
+        let file = File::open(&file_path)?;
 
         // First get the expected duration and samples using hound directly
-        let mut reader = hound::WavReader::new(File::open(file_path)?)?;
+        let mut reader = hound::WavReader::new(File::open(&file_path)?)?;
         let spec = reader.spec();
         let total_expected_samples = reader.duration() as usize;
         let expected_duration = total_expected_samples as f64 / spec.sample_rate as f64;
@@ -847,7 +1135,8 @@ mod tests {
     #[tokio::test]
     async fn test_file_track_with_cache() -> Result<()> {
         ensure_cache_dir().await?;
-        let file_path = "fixtures/sample.wav".to_string();
+        let dir = tempfile::tempdir()?;
+        let file_path = sample_wav(&dir, Duration::from_secs(1))?;
 
         // Create a FileTrack instance
         let track_id = "test_track".to_string();
@@ -0,0 +1,630 @@
+use crate::event::{EventSender, SessionEvent};
+use crate::media::codecs::resample::LinearResampler;
+use crate::media::error::MediaResult;
+use crate::media::pacer::Pacer;
+use crate::media::processor::ProcessorChain;
+use crate::media::track::device::downmix;
+use crate::media::track::file::AudioFormat;
+use crate::media::track::{Track, TrackConfig, TrackPacketSender};
+use crate::{AudioFrame, PcmBuf, Sample, Samples, TrackId};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use reqwest::{Client, Response, header};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::select;
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use url::Url;
+
+/// Audio fetched before playback starts, and again after an underrun
+const DEFAULT_PREBUFFER: Duration = Duration::from_secs(1);
+/// Audio fetched ahead of playback, the download waits beyond it
+const MAX_BUFFER: Duration = Duration::from_secs(10);
+/// Bytes kept ahead of the MP3 decoder so it never sees half a frame
+const MP3_LOOKAHEAD: usize = 8 * 1024;
+/// Bytes searched for an MP3 frame before the stream is given up
+const MAX_MP3_GARBAGE: usize = 256 * 1024;
+const DEFAULT_MAX_RETRIES: u32 = 5;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// A stream that sends nothing for this long is reconnected
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Mono audio at the track's sample rate, between the download and the
+/// playback
+#[derive(Default)]
+struct StreamBuffer {
+    samples: Mutex<VecDeque<Sample>>,
+    finished: AtomicBool,
+}
+
+impl StreamBuffer {
+    fn len(&self) -> usize {
+        self.samples.lock().unwrap().len()
+    }
+
+    fn push(&self, samples: PcmBuf) {
+        self.samples.lock().unwrap().extend(samples);
+    }
+
+    fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+}
+
+/// Turns the bytes of one connection into mono samples, whatever the
+/// chunks they arrive in
+pub(crate) struct StreamDecoder {
+    format: Option<AudioFormat>,
+    extension: String,
+    pending: Vec<u8>,
+    mp3: rmp3::RawDecoder,
+    /// Channels and sample rate of a WAV stream once its header is read
+    wav: Option<(usize, u32)>,
+}
+
+impl StreamDecoder {
+    pub(crate) fn new(extension: String) -> Self {
+        Self {
+            format: None,
+            extension,
+            pending: Vec::new(),
+            mp3: rmp3::RawDecoder::new(),
+            wav: None,
+        }
+    }
+
+    /// Decodes what `data` completes, `eof` flushes the rest
+    pub(crate) fn decode(&mut self, data: &[u8], eof: bool) -> Result<Vec<(PcmBuf, u32)>> {
+        self.pending.extend_from_slice(data);
+        let format = match self.format {
+            Some(format) => format,
+            None if self.pending.len() < 12 && !eof => return Ok(vec![]),
+            None => {
+                let format = AudioFormat::detect(&self.pending, &self.extension)
+                    .ok_or_else(|| anyhow!("Unsupported audio format: {}", self.extension))?;
+                info!("httpstream: detected {:?} stream", format);
+                *self.format.insert(format)
+            }
+        };
+        match format {
+            AudioFormat::Mp3 => self.decode_mp3(eof),
+            AudioFormat::Wav => self.decode_wav(),
+            AudioFormat::Ogg | AudioFormat::Flac => Err(anyhow!(
+                "Unsupported audio format: {:?}, stream WAV or MP3",
+                format
+            )),
+        }
+    }
+
+    fn decode_mp3(&mut self, eof: bool) -> Result<Vec<(PcmBuf, u32)>> {
+        let mut frames = Vec::new();
+        let mut pcm = [0; rmp3::MAX_SAMPLES_PER_FRAME];
+        let mut consumed = 0;
+        while eof || self.pending.len() - consumed >= MP3_LOOKAHEAD {
+            match self.mp3.next(&self.pending[consumed..], &mut pcm) {
+                Some((rmp3::Frame::Audio(audio), skip)) => {
+                    let samples = downmix(audio.samples(), audio.channels() as usize);
+                    frames.push((samples, audio.sample_rate()));
+                    consumed += skip;
+                }
+                Some((rmp3::Frame::Other(_), skip)) => consumed += skip,
+                None => break,
+            }
+        }
+        self.pending.drain(..consumed);
+        if self.pending.len() > MAX_MP3_GARBAGE {
+            return Err(anyhow!("No MP3 frame in {} bytes", self.pending.len()));
+        }
+        Ok(frames)
+    }
+
+    fn decode_wav(&mut self) -> Result<Vec<(PcmBuf, u32)>> {
+        let (channels, sample_rate) = match self.wav {
+            Some(wav) => wav,
+            None => match parse_wav_header(&self.pending)? {
+                Some((offset, channels, sample_rate)) => {
+                    self.pending.drain(..offset);
+                    *self.wav.insert((channels, sample_rate))
+                }
+                None => return Ok(vec![]),
+            },
+        };
+        let frame_bytes = channels * 2;
+        let len = self.pending.len() / frame_bytes * frame_bytes;
+        if len == 0 {
+            return Ok(vec![]);
+        }
+        let samples = self
+            .pending
+            .drain(..len)
+            .collect::<Vec<_>>()
+            .chunks_exact(2)
+            .map(|b| Sample::from_le_bytes([b[0], b[1]]))
+            .collect::<Vec<_>>();
+        Ok(vec![(downmix(&samples, channels), sample_rate)])
+    }
+}
+
+/// Offset of the samples, channels and sample rate of a 16 bit PCM WAV
+/// stream, None until enough of the header has arrived. The data chunk
+/// size is ignored, streamed WAVs rarely know it
+fn parse_wav_header(data: &[u8]) -> Result<Option<(usize, usize, u32)>> {
+    let mut format = None;
+    let mut pos = 12;
+    while data.len() >= pos + 8 {
+        let id = &data[pos..pos + 4];
+        let size = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]])
+            as usize;
+        let body = pos + 8;
+        if id == b"data" {
+            let Some((channels, sample_rate)) = format else {
+                return Err(anyhow!("WAV stream without a fmt chunk"));
+            };
+            return Ok(Some((body, channels, sample_rate)));
+        }
+        if data.len() < body + size {
+            return Ok(None);
+        }
+        if id == b"fmt " {
+            if size < 16 {
+                return Err(anyhow!("Invalid WAV fmt chunk"));
+            }
+            let fmt = &data[body..body + size];
+            let channels = u16::from_le_bytes([fmt[2], fmt[3]]) as usize;
+            let sample_rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+            let bits = u16::from_le_bytes([fmt[14], fmt[15]]);
+            if bits != 16 || channels == 0 {
+                return Err(anyhow!(
+                    "Unsupported WAV stream: {} bits, {} channels",
+                    bits,
+                    channels
+                ));
+            }
+            format = Some((channels, sample_rate));
+        }
+        // chunks are padded to an even size
+        pos = body + size + (size & 1);
+    }
+    Ok(None)
+}
+
+/// Resamples decoded audio to the track rate in the chunks the resampler
+/// takes, keeping its state from one chunk to the next
+struct StreamResampler {
+    sample_rate: u32,
+    resampler: Option<(u32, LinearResampler)>,
+    pending: PcmBuf,
+}
+
+impl StreamResampler {
+    fn new(sample_rate: u32) -> Self {
+        Self {
+            sample_rate,
+            resampler: None,
+            pending: Vec::new(),
+        }
+    }
+
+    fn resample(&mut self, samples: &[Sample], rate: u32) -> Result<PcmBuf> {
+        if rate == self.sample_rate {
+            return Ok(samples.to_vec());
+        }
+        let mut resampled = Vec::new();
+        if self
+            .resampler
+            .as_ref()
+            .is_none_or(|(from, _)| *from != rate)
+        {
+            resampled = self.flush();
+            let resampler = LinearResampler::new(rate as usize, self.sample_rate as usize)?;
+            self.resampler = Some((rate, resampler));
+        }
+        let Some((_, resampler)) = self.resampler.as_mut() else {
+            return Ok(resampled);
+        };
+        self.pending.extend_from_slice(samples);
+        let chunk_size = resampler.input_chunk_size();
+        let len = self.pending.len() / chunk_size * chunk_size;
+        for chunk in self
+            .pending
+            .drain(..len)
+            .collect::<Vec<_>>()
+            .chunks(chunk_size)
+        {
+            resampled.extend(resampler.resample(chunk));
+        }
+        Ok(resampled)
+    }
+
+    /// Resamples what is left, padded with silence to a whole chunk
+    fn flush(&mut self) -> PcmBuf {
+        let Some((rate, resampler)) = self.resampler.as_mut() else {
+            return Vec::new();
+        };
+        if self.pending.is_empty() {
+            return Vec::new();
+        }
+        let len = self.pending.len() * self.sample_rate as usize / *rate as usize;
+        self.pending.resize(resampler.input_chunk_size(), 0);
+        let mut resampled = resampler.resample(&self.pending);
+        self.pending.clear();
+        resampled.truncate(len);
+        resampled
+    }
+}
+
+enum FetchEnd {
+    Complete,
+    /// The connection went away, the stream may come back
+    Dropped(anyhow::Error),
+}
+
+/// Plays audio fetched from an HTTP(S) URL while it downloads, so a prompt
+/// generated on the fly starts before it is complete and an Icecast or
+/// other live stream can serve as music on hold. WAV and MP3 are decoded.
+/// A live stream, one with neither a length nor a chunked body, is
+/// reconnected when it drops, as is any stream that fails before it sends
+/// audio, with `max_retries` attempts in a row
+pub struct HttpStreamTrack {
+    track_id: TrackId,
+    config: TrackConfig,
+    cancel_token: CancellationToken,
+    processor_chain: ProcessorChain,
+    url: Option<String>,
+    prebuffer: Duration,
+    max_retries: u32,
+    ssrc: u32,
+}
+
+impl HttpStreamTrack {
+    pub fn new(id: TrackId) -> Self {
+        let config = TrackConfig::default();
+        Self {
+            track_id: id,
+            processor_chain: ProcessorChain::new(config.samplerate),
+            config,
+            cancel_token: CancellationToken::new(),
+            url: None,
+            prebuffer: DEFAULT_PREBUFFER,
+            max_retries: DEFAULT_MAX_RETRIES,
+            ssrc: 0,
+        }
+    }
+
+    pub fn with_ssrc(mut self, ssrc: u32) -> Self {
+        self.ssrc = ssrc;
+        self
+    }
+
+    pub fn with_config(mut self, config: TrackConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_cancel_token(mut self, cancel_token: CancellationToken) -> Self {
+        self.cancel_token = cancel_token;
+        self
+    }
+
+    pub fn with_url(mut self, url: String) -> Self {
+        self.url = Some(url);
+        self
+    }
+
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.config = self.config.with_sample_rate(sample_rate);
+        self
+    }
+
+    pub fn with_ptime(mut self, ptime: Duration) -> Self {
+        self.config = self.config.with_ptime(ptime);
+        self
+    }
+
+    /// Audio to buffer before playing, at the start and after an underrun
+    pub fn with_prebuffer(mut self, prebuffer: Duration) -> Self {
+        self.prebuffer = prebuffer;
+        self
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+}
+
+#[async_trait]
+impl Track for HttpStreamTrack {
+    fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+    fn id(&self) -> &TrackId {
+        &self.track_id
+    }
+    fn config(&self) -> &TrackConfig {
+        &self.config
+    }
+    fn processor_chain(&mut self) -> &mut ProcessorChain {
+        &mut self.processor_chain
+    }
+
+    async fn handshake(
+        &mut self,
+        _offer: String,
+        _timeout: Option<Duration>,
+    ) -> MediaResult<String> {
+        Ok("".to_string())
+    }
+
+    async fn start(
+        &self,
+        event_sender: EventSender,
+        packet_sender: TrackPacketSender,
+    ) -> MediaResult<()> {
+        let Some(url) = self.url.clone() else {
+            return Err(anyhow!("httpstream: No url provided for HttpStreamTrack").into());
+        };
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()
+            .map_err(anyhow::Error::from)?;
+        let id = self.track_id.clone();
+        let sample_rate = self.config.samplerate;
+        let ptime = self.config.ptime;
+        let prebuffer = self.prebuffer;
+        let max_retries = self.max_retries;
+        let processor_chain = self.processor_chain.clone();
+        let token = self.cancel_token.clone();
+        let start_time = crate::get_timestamp();
+        let ssrc = self.ssrc;
+        crate::media::workers::spawn(async move {
+            let buffer = StreamBuffer::default();
+            let fetched = {
+                let fetch = async {
+                    let r = fetch_stream(&client, &url, &buffer, sample_rate, max_retries).await;
+                    buffer.finish();
+                    r
+                };
+                let play = play_stream(
+                    &buffer,
+                    processor_chain,
+                    &id,
+                    sample_rate,
+                    ptime,
+                    prebuffer,
+                    packet_sender,
+                );
+                tokio::pin!(fetch, play);
+                // playback drains the buffer after the download ends, and the
+                // download stops with playback
+                select! {
+                    biased;
+                    _ = token.cancelled() => {
+                        info!("httpstream: stream cancelled {}", url);
+                        Ok(())
+                    }
+                    fetched = &mut fetch => {
+                        select! {
+                            _ = token.cancelled() => {}
+                            _ = &mut play => {}
+                        }
+                        fetched
+                    }
+                    _ = &mut play => Ok(()),
+                }
+            };
+            if let Err(e) = fetched {
+                error!("httpstream: Error streaming {}: {}", url, e);
+                event_sender
+                    .send(SessionEvent::Error {
+                        track_id: id.clone(),
+                        timestamp: crate::get_timestamp(),
+                        sender: format!("httpstream: {}", url),
+                        error: e.to_string(),
+                        code: None,
+                    })
+                    .ok();
+            }
+            event_sender
+                .send(SessionEvent::TrackEnd {
+                    track_id: id,
+                    timestamp: crate::get_timestamp(),
+                    duration: crate::get_timestamp() - start_time,
+                    ssrc,
+                    play_id: Some(url),
+                })
+                .ok();
+        });
+        Ok(())
+    }
+
+    async fn stop(&self) -> MediaResult<()> {
+        self.cancel_token.cancel();
+        Ok(())
+    }
+
+    // Do nothing as we are not sending packets
+    async fn send_packet(&self, _packet: &AudioFrame) -> MediaResult<()> {
+        Ok(())
+    }
+}
+
+async fn fetch_stream(
+    client: &Client,
+    url: &str,
+    buffer: &StreamBuffer,
+    sample_rate: u32,
+    max_retries: u32,
+) -> Result<()> {
+    let extension = url
+        .parse::<Url>()?
+        .path()
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_string())
+        .unwrap_or_default();
+    let mut failures = 0;
+    loop {
+        let mut received = false;
+        let e =
+            match fetch_once(client, url, &extension, buffer, sample_rate, &mut received).await? {
+                FetchEnd::Complete => return Ok(()),
+                FetchEnd::Dropped(e) => e,
+            };
+        if received {
+            failures = 0;
+        }
+        failures += 1;
+        if failures > max_retries {
+            return Err(e);
+        }
+        let backoff = RECONNECT_INTERVAL * failures;
+        warn!(
+            "httpstream: {} dropped: {}, reconnecting in {:?}",
+            url, e, backoff
+        );
+        tokio::time::sleep(backoff).await;
+    }
+}
+
+async fn fetch_once(
+    client: &Client,
+    url: &str,
+    extension: &str,
+    buffer: &StreamBuffer,
+    sample_rate: u32,
+    received: &mut bool,
+) -> Result<FetchEnd> {
+    let mut response = match client.get(url).send().await {
+        Ok(response) => response,
+        Err(e) => return Ok(FetchEnd::Dropped(e.into())),
+    };
+    let status = response.status();
+    if status.is_client_error() {
+        return Err(anyhow!("Failed to fetch {}: {}", url, status));
+    }
+    if !status.is_success() {
+        return Ok(FetchEnd::Dropped(anyhow!("status {}", status)));
+    }
+    let live = is_live(&response);
+    let mut decoder = StreamDecoder::new(
+        content_extension(&response)
+            .unwrap_or(extension)
+            .to_string(),
+    );
+    let mut resampler = StreamResampler::new(sample_rate);
+    let max_buffered = (sample_rate as u128 * MAX_BUFFER.as_millis() / 1000) as usize;
+    loop {
+        let chunk = match tokio::time::timeout(READ_TIMEOUT, response.chunk()).await {
+            Ok(Ok(chunk)) => chunk,
+            Ok(Err(e)) if live || !*received => return Ok(FetchEnd::Dropped(e.into())),
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) if live || !*received => {
+                return Ok(FetchEnd::Dropped(anyhow!("no data for {:?}", READ_TIMEOUT)));
+            }
+            Err(_) => return Err(anyhow!("No data from {} for {:?}", url, READ_TIMEOUT)),
+        };
+        let eof = chunk.is_none();
+        for (samples, rate) in decoder.decode(chunk.as_deref().unwrap_or_default(), eof)? {
+            buffer.push(resampler.resample(&samples, rate)?);
+            *received = true;
+        }
+        if eof {
+            buffer.push(resampler.flush());
+            return Ok(match live {
+                true => FetchEnd::Dropped(anyhow!("stream ended")),
+                false => FetchEnd::Complete,
+            });
+        }
+        while buffer.len() > max_buffered {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+/// Icecast and Shoutcast streams announce themselves with icy headers,
+/// other live streams by ending only when the connection closes
+fn is_live(response: &Response) -> bool {
+    let headers = response.headers();
+    let chunked = headers
+        .get(header::TRANSFER_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains("chunked"));
+    headers.keys().any(|name| name.as_str().starts_with("icy-"))
+        || (response.content_length().is_none() && !chunked)
+}
+
+/// Format hint from the Content-Type, streams seldom have an extension
+fn content_extension(response: &Response) -> Option<&'static str> {
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)?
+        .to_str()
+        .ok()?;
+    match content_type.split(';').next()?.trim() {
+        "audio/mpeg" | "audio/mp3" => Some("mp3"),
+        "audio/wav" | "audio/x-wav" | "audio/wave" | "audio/vnd.wave" => Some("wav"),
+        "audio/ogg" | "application/ogg" => Some("ogg"),
+        "audio/flac" => Some("flac"),
+        _ => None,
+    }
+}
+
+async fn play_stream(
+    buffer: &StreamBuffer,
+    processor_chain: ProcessorChain,
+    track_id: &str,
+    sample_rate: u32,
+    ptime: Duration,
+    prebuffer: Duration,
+    packet_sender: TrackPacketSender,
+) {
+    let frame_size = (sample_rate as u128 * ptime.as_millis() / 1000) as usize;
+    let prebuffer = (sample_rate as u128 * prebuffer.as_millis() / 1000) as usize;
+    let mut pacer = Pacer::new(ptime);
+    let mut buffering = true;
+    loop {
+        pacer.tick().await;
+        let samples = {
+            let mut queue = buffer.samples.lock().unwrap();
+            let finished = buffer.is_finished();
+            if finished && queue.is_empty() {
+                break;
+            }
+            let wanted = match buffering {
+                true => prebuffer.max(frame_size),
+                false => frame_size,
+            };
+            if !finished && queue.len() < wanted {
+                if !buffering {
+                    info!("httpstream: underrun, buffering {}", track_id);
+                    buffering = true;
+                }
+                continue;
+            }
+            buffering = false;
+            let len = frame_size.min(queue.len());
+            queue.drain(..len).collect::<PcmBuf>()
+        };
+        let mut packet = AudioFrame {
+            track_id: track_id.to_string(),
+            timestamp: crate::get_timestamp(),
+            samples: Samples::PCM { samples },
+            sample_rate,
+        };
+        if let Err(e) = processor_chain.process_frame(&mut packet) {
+            warn!("httpstream: failed to process audio packet: {}", e);
+        }
+        if let Err(e) = packet_sender.send(packet) {
+            warn!("httpstream: failed to send audio packet: {}", e);
+            break;
+        }
+    }
+}
@@ -3,6 +3,7 @@ use crate::{
     event::{EventSender, SessionEvent},
     media::{
         codecs::{bytes_to_samples, resample::resample_mono, samples_to_bytes},
+        error::MediaResult,
         processor::{Processor, ProcessorChain},
         track::{Track, TrackConfig, TrackPacketSender},
     },
@@ -115,7 +116,7 @@ impl Track for MediaPassTrack {
     fn append_processor(&mut self, processor: Box<dyn Processor>) {
         self.processor_chain().append_processor(processor);
     }
-    async fn handshake(&mut self, _: String, _: Option<Duration>) -> Result<String> {
+    async fn handshake(&mut self, _: String, _: Option<Duration>) -> MediaResult<String> {
         Ok("".to_string())
     }
 
@@ -123,7 +124,7 @@ impl Track for MediaPassTrack {
         &self,
         event_sender: EventSender,
         packet_sender: TrackPacketSender,
-    ) -> Result<()> {
+    ) -> MediaResult<()> {
         let target = format!(
             "{}?sample_rate={}&packet_size={}",
             self.url.clone(),
@@ -132,7 +133,9 @@ impl Track for MediaPassTrack {
         );
         debug!("Media pass connecting url: {target}");
         let sample_rate = self.config.samplerate;
-        let (ws_stream, _) = tokio_tungstenite::connect_async(target).await?;
+        let (ws_stream, _) = tokio_tungstenite::connect_async(target)
+            .await
+            .map_err(anyhow::Error::from)?;
         let (ws_sink, mut ws_source) = ws_stream.split();
         *self.ws_sink.lock().await = Some(ws_sink);
 
@@ -143,7 +146,7 @@ impl Track for MediaPassTrack {
         let cancel_token = self.cancel_token.clone();
         let ptime = self.config.ptime;
         let packet_bytes_size = 2 * sample_rate * ptime.as_millis() as u32 / 1000;
-        tokio::spawn(async move {
+        crate::media::workers::spawn(async move {
             let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
             let track_id_clone = track_id.clone();
             let event_sender_clone = event_sender.clone();
@@ -235,7 +238,7 @@ impl Track for MediaPassTrack {
         Ok(())
     }
 
-    async fn stop(&self) -> Result<()> {
+    async fn stop(&self) -> MediaResult<()> {
         if let Some(ws_sink) = self.ws_sink.lock().await.as_mut() {
             ws_sink.close().await.ok();
         }
@@ -243,7 +246,7 @@ impl Track for MediaPassTrack {
         Ok(())
     }
 
-    async fn send_packet(&self, packet: &AudioFrame) -> Result<()> {
+    async fn send_packet(&self, packet: &AudioFrame) -> MediaResult<()> {
         if let Some(ws_sink) = self.ws_sink.lock().await.as_mut() {
             if let Samples::PCM { samples } = &packet.samples {
                 let mut buffer = self.buffer.lock().await;
@@ -253,12 +256,18 @@ impl Track for MediaPassTrack {
                 while buffer.len() >= max_buffer_size as usize {
                     let bytes = buffer.split_to(max_buffer_size as usize).freeze();
                     if packet.sample_rate == self.sample_rate {
-                        ws_sink.send(Message::Binary(bytes)).await?;
+                        ws_sink
+                            .send(Message::Binary(bytes))
+                            .await
+                            .map_err(anyhow::Error::from)?;
                     } else {
                         let sample = bytes_to_samples(&bytes);
                         let resample = resample_mono(&sample, packet.sample_rate, self.sample_rate);
                         let bytes = samples_to_bytes(resample.as_slice());
-                        ws_sink.send(Message::Binary(bytes.into())).await?;
+                        ws_sink
+                            .send(Message::Binary(bytes.into()))
+                            .await
+                            .map_err(anyhow::Error::from)?;
                     }
                 }
             }
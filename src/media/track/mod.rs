@@ -1,8 +1,9 @@
-use super::codecs::CodecType;
+use super::codecs::{CodecType, g722::G722Mode, resample::ResampleProfile};
 use crate::event::EventSender;
+use crate::media::track::send_queue::{DEFAULT_SEND_QUEUE_CAPACITY, DropPolicy, SendStats};
+use crate::media::error::MediaResult;
 use crate::media::processor::{Processor, ProcessorChain};
 use crate::{AudioFrame, TrackId};
-use anyhow::Result;
 use async_trait::async_trait;
 use tokio::sync::mpsc;
 use tokio::time::Duration;
@@ -20,6 +21,14 @@ pub struct TrackConfig {
     pub samplerate: u32,
     // Number of audio channels (1 for mono, 2 for stereo)
     pub channels: u16,
+    // How audio is resampled between the track and its codec
+    pub resample_profile: ResampleProfile,
+    // Frames queued for a track that sends from a queue, and what gives
+    // when the queue is full
+    pub send_queue_capacity: usize,
+    pub drop_policy: DropPolicy,
+    // Bitrate and packing of G.722
+    pub g722: G722Mode,
 }
 
 impl Default for TrackConfig {
@@ -29,6 +38,10 @@ impl Default for TrackConfig {
             ptime: Duration::from_millis(20),
             samplerate: 16000,
             channels: 1,
+            resample_profile: ResampleProfile::default(),
+            send_queue_capacity: DEFAULT_SEND_QUEUE_CAPACITY,
+            drop_policy: DropPolicy::default(),
+            g722: G722Mode::default(),
         }
     }
 }
@@ -48,11 +61,34 @@ impl TrackConfig {
         self.channels = channels;
         self
     }
+
+    pub fn with_resample_profile(mut self, resample_profile: ResampleProfile) -> Self {
+        self.resample_profile = resample_profile;
+        self
+    }
+
+    pub fn with_g722_mode(mut self, mode: G722Mode) -> Self {
+        self.g722 = mode;
+        self
+    }
+
+    pub fn with_send_queue(mut self, capacity: usize, drop_policy: DropPolicy) -> Self {
+        self.send_queue_capacity = capacity;
+        self.drop_policy = drop_policy;
+        self
+    }
 }
 
+pub mod audiosocket;
+pub mod device;
+pub mod echo;
 pub mod file;
+pub mod http_stream;
 pub mod media_pass;
 pub mod rtp;
+pub mod rtp_fork;
+pub mod send_queue;
+pub mod tone;
 pub mod track_codec;
 pub mod tts;
 pub mod webrtc;
@@ -69,16 +105,33 @@ pub trait Track: Send + Sync {
     fn append_processor(&mut self, processor: Box<dyn Processor>) {
         self.processor_chain().append_processor(processor);
     }
-    async fn handshake(&mut self, offer: String, timeout: Option<Duration>) -> Result<String>;
+    async fn handshake(&mut self, offer: String, timeout: Option<Duration>) -> MediaResult<String>;
     #[allow(unused_variables)]
-    async fn update_remote_description(&mut self, answer: &String) -> Result<()> {
+    async fn update_remote_description(&mut self, answer: &String) -> MediaResult<()> {
         Ok(())
     }
     async fn start(
         &self,
         event_sender: EventSender,
         packet_sender: TrackPacketSender,
-    ) -> Result<()>;
-    async fn stop(&self) -> Result<()>;
-    async fn send_packet(&self, packet: &AudioFrame) -> Result<()>;
+    ) -> MediaResult<()>;
+    async fn stop(&self) -> MediaResult<()>;
+    async fn send_packet(&self, packet: &AudioFrame) -> MediaResult<()>;
+    /// Sends a frame and returns the state of the track's send queue, if it
+    /// has one. A full queue applies the track's drop policy rather than
+    /// growing, a frame dropped is [`crate::media::error::MediaError::BufferOverrun`]
+    async fn send_frame(&self, frame: &AudioFrame) -> MediaResult<Option<SendStats>> {
+        self.send_packet(frame).await?;
+        Ok(self.send_stats())
+    }
+    /// The state of the send queue of a track that sends from one
+    fn send_stats(&self) -> Option<SendStats> {
+        None
+    }
+    /// Sends a digit as RFC 4733 telephone-events lasting `duration`, for
+    /// the tracks that carry RTP
+    #[allow(unused_variables)]
+    async fn send_dtmf(&self, digit: &str, duration: Duration) -> MediaResult<()> {
+        Err(anyhow::anyhow!("track can't send telephone-events").into())
+    }
 }
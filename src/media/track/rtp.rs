@@ -3,12 +3,25 @@ use crate::{
     AudioFrame, Samples, TrackId,
     event::{EventSender, SessionEvent},
     media::{
+        audio_level::{
+            AUDIO_LEVEL_URI, audio_level, read_audio_level, update_levels, write_audio_level,
+        },
+        clock::{ClockRef, system_clock},
         codecs::CodecType,
+        dtmf::dtmf_event_code,
+        error::{MediaError, MediaResult},
         jitter::JitterBuffer,
-        negotiate::select_peer_media,
+        meter::TrackLevels,
+        nat::MediaNat,
+        negotiate::{parse_sdp, select_peer_media},
+        pacer::Pacer,
+        pipeline::packet_to_frame,
         processor::ProcessorChain,
+        rewriter::{RtpRewriteOption, RtpRewriter, parse_extmaps, parse_mid},
+        shaper::{Shaper, ShapingOption},
         track::{Track, TrackConfig, TrackPacketSender},
     },
+    net_tool::create_udp_connection,
 };
 use anyhow::Result;
 use async_trait::async_trait;
@@ -24,12 +37,13 @@ use std::{
     },
     time::Duration,
 };
-use tokio::{select, time::Instant, time::interval_at};
+use tokio::{select, sync::mpsc, time::Instant, time::interval_at};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 use webrtc::{
     rtcp::{
         goodbye::Goodbye,
+        packet::{Packet as RtcpPacket, unmarshal as rtcp_unmarshal},
         receiver_report::ReceiverReport,
         reception_report::ReceptionReport,
         sender_report::SenderReport,
@@ -39,6 +53,7 @@ use webrtc::{
     },
     rtp::{
         codecs::g7xx::G7xxPayloader,
+        extension::audio_level_extension::AudioLevelExtension,
         packet::Packet,
         packetizer::{Packetizer, new_packetizer},
         sequence::{Sequencer, new_random_sequencer},
@@ -54,12 +69,11 @@ use webrtc::{
             },
         },
     },
-    util::{Marshal, Unmarshal},
+    util::{Marshal, MarshalSize, Unmarshal},
 };
 const RTP_MTU: usize = 1500; // UDP MTU size
 const RTP_OUTBOUND_MTU: usize = 1200; // Standard MTU size
 const RTCP_SR_INTERVAL_MS: u64 = 5000; // 5 seconds RTCP sender report interval
-const DTMF_EVENT_DURATION_MS: u64 = 160; // Default DTMF event duration (in ms)
 const DTMF_EVENT_VOLUME: u8 = 10; // Default volume for DTMF events (0-63)
 
 // STUN constants for ICE connectivity check
@@ -67,6 +81,54 @@ const STUN_BINDING_REQUEST: u16 = 0x0001;
 const STUN_MAGIC_COOKIE: u32 = 0x2112A442;
 const STUN_TRANSACTION_ID_SIZE: usize = 12;
 
+/// A datagram received on the RTP socket, which is shared with STUN for ICE
+/// connectivity checks and with RTCP when rtcp-mux is used
+pub enum RtpPacketKind {
+    Stun,
+    Rtcp(Vec<Box<dyn RtcpPacket + Send + Sync>>),
+    Rtp(Packet),
+}
+
+/// Classify and parse a datagram received on the RTP socket
+pub fn parse_rtp_packet(buf: &[u8]) -> MediaResult<RtpPacketKind> {
+    if buf.len() < 2 {
+        return Err(MediaError::InvalidPacket(format!(
+            "packet too short: {} bytes",
+            buf.len()
+        )));
+    }
+    // STUN packets have the magic cookie, or message types with the two
+    // most significant bits unset
+    if buf.len() >= 8 {
+        let msg_type = u16::from_be_bytes([buf[0], buf[1]]);
+        let msg_length = u16::from_be_bytes([buf[2], buf[3]]) as usize;
+        let magic_cookie = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+        if magic_cookie == STUN_MAGIC_COOKIE
+            || (msg_type & 0xC000) == 0x0000 && msg_length + 20 <= buf.len()
+        {
+            return Ok(RtpPacketKind::Stun);
+        }
+    }
+
+    // RTCP packet structure: V(2) + P(1) + RC(5) + PT(8) + Length(16) + ...
+    // For RTCP: PT is the full second byte (200-207)
+    let version = (buf[0] >> 6) & 0x03;
+    if version != 2 {
+        return Err(MediaError::InvalidPacket(format!(
+            "invalid RTP version: {}",
+            version
+        )));
+    }
+    if (200..=207).contains(&buf[1]) {
+        let packets =
+            rtcp_unmarshal(&mut &buf[..]).map_err(|e| MediaError::InvalidPacket(e.to_string()))?;
+        return Ok(RtpPacketKind::Rtcp(packets));
+    }
+    Ok(RtpPacketKind::Rtp(
+        Packet::unmarshal(&mut &buf[..]).map_err(|e| MediaError::InvalidPacket(e.to_string()))?,
+    ))
+}
+
 struct RtpTrackStats {
     timestamp: Arc<AtomicU32>,
     packet_count: Arc<AtomicU32>,
@@ -158,6 +220,7 @@ pub struct RtpTrackBuilder {
     config: TrackConfig,
     local_addr: Option<IpAddr>,
     external_addr: Option<IpAddr>,
+    nat: Option<Arc<MediaNat>>,
     rtp_socket: Option<UdpConnection>,
     rtcp_socket: Option<UdpConnection>,
     rtcp_mux: bool,
@@ -168,6 +231,11 @@ pub struct RtpTrackBuilder {
     ssrc_cname: String,
     ssrc: u32,
     ice_connectivity_check: bool,
+    shaping: Option<ShapingOption>,
+    rewrite: Option<RtpRewriteOption>,
+    dscp: Option<u8>,
+    levels: Option<TrackLevels>,
+    clock: ClockRef,
 }
 pub struct RtpTrackInner {
     dtmf_payload_type: u8,
@@ -179,6 +247,10 @@ pub struct RtpTrackInner {
     remote_addr: Option<SipAddr>,
     remote_rtcp_addr: Option<SipAddr>,
     enabled_codecs: Vec<CodecType>,
+    /// Levels of the packets received, by the audio level extension
+    levels: Option<TrackLevels>,
+    /// Id of the audio level extension, as negotiated
+    audio_level_id: Option<u8>,
 }
 
 pub struct RtpTrack {
@@ -194,7 +266,13 @@ pub struct RtpTrack {
     sequencer: Box<dyn Sequencer + Send + Sync>,
     sendrecv: AtomicBool,
     ice_connectivity_check: bool,
+    shaper: Option<Arc<Shaper>>,
+    rewriter: Option<Mutex<RtpRewriter>>,
     inner: Arc<Mutex<RtpTrackInner>>,
+    // digits to send as telephone-events, one after the other
+    dtmf_sender: mpsc::UnboundedSender<(u8, Duration)>,
+    dtmf_receiver: Mutex<Option<mpsc::UnboundedReceiver<(u8, Duration)>>>,
+    clock: ClockRef,
 }
 impl RtpTrackBuilder {
     pub fn new(track_id: TrackId, config: TrackConfig) -> Self {
@@ -204,6 +282,7 @@ impl RtpTrackBuilder {
             config,
             local_addr: None,
             external_addr: None,
+            nat: None,
             cancel_token: None,
             rtp_socket: None,
             rtcp_socket: None,
@@ -224,6 +303,11 @@ impl RtpTrackBuilder {
             ssrc_cname: format!("rustpbx-{}", ssrc),
             ssrc,
             ice_connectivity_check: true, // Default enabled
+            shaping: None,
+            rewrite: None,
+            dscp: None,
+            levels: None,
+            clock: system_clock(),
         }
     }
 
@@ -255,6 +339,15 @@ impl RtpTrackBuilder {
         self
     }
 
+    /// Takes the ports and their public addresses from the media NAT
+    pub fn with_nat(mut self, nat: Arc<MediaNat>) -> Self {
+        let (start, end) = nat.port_range();
+        self.rtp_start_port = start;
+        self.rtp_end_port = end;
+        self.nat = Some(nat);
+        self
+    }
+
     pub fn with_cancel_token(mut self, cancel_token: CancellationToken) -> Self {
         self.cancel_token = Some(cancel_token);
         self
@@ -286,6 +379,38 @@ impl RtpTrackBuilder {
         self.ice_connectivity_check = enabled;
         self
     }
+
+    /// Caps the outgoing RTP bitrate
+    pub fn with_shaping(mut self, shaping: ShapingOption) -> Self {
+        self.shaping = Some(shaping);
+        self
+    }
+
+    /// Rewrites the RTP relayed from another leg with `send_rtp`
+    pub fn with_rewrite(mut self, rewrite: RtpRewriteOption) -> Self {
+        self.rewrite = Some(rewrite);
+        self
+    }
+
+    /// Marks the RTP and RTCP packets with this DSCP
+    pub fn with_dscp(mut self, dscp: u8) -> Self {
+        self.dscp = Some(dscp);
+        self
+    }
+
+    /// Negotiates the audio level header extension (RFC 6464): the packets
+    /// sent carry the level of their audio, the levels of the packets
+    /// received are kept in `levels`
+    pub fn with_audio_level(mut self, levels: TrackLevels) -> Self {
+        self.levels = Some(levels);
+        self
+    }
+
+    /// Plays the received frames out of the jitter buffer on `clock`
+    pub fn with_clock(mut self, clock: ClockRef) -> Self {
+        self.clock = clock;
+        self
+    }
     pub async fn build_rtp_rtcp_conn(&self) -> Result<(UdpConnection, UdpConnection)> {
         let addr = match self.local_addr {
             Some(addr) => addr,
@@ -299,18 +424,20 @@ impl RtpTrackBuilder {
             if port % 2 != 0 {
                 continue;
             }
-            if let Ok(c) = UdpConnection::create_connection(
+            if let Ok(c) = create_udp_connection(
                 format!("{:?}:{}", addr, port).parse()?,
                 None,
+                self.dscp,
                 self.cancel_token.clone(),
             )
             .await
             {
                 if !self.rtcp_mux {
                     // if rtcp mux is not enabled, we need to create a separate RTCP socket
-                    rtcp_conn = match UdpConnection::create_connection(
+                    rtcp_conn = match create_udp_connection(
                         format!("{:?}:{}", addr, port + 1).parse()?,
                         None,
+                        self.dscp,
                         self.cancel_token.clone(),
                     )
                     .await
@@ -365,6 +492,13 @@ impl RtpTrackBuilder {
                 .into(),
             );
         }
+        if let Some(nat) = self.nat.as_ref() {
+            for conn in [&mut rtp_conn, &mut rtcp_conn] {
+                if let Some(external) = nat.external(conn.get_addr().get_socketaddr()?) {
+                    conn.external = Some(external.into());
+                }
+            }
+        }
         Ok((rtp_conn, rtcp_conn))
     }
 
@@ -380,7 +514,10 @@ impl RtpTrackBuilder {
         let cancel_token = self
             .cancel_token
             .unwrap_or_else(|| CancellationToken::new());
-        let processor_chain = ProcessorChain::new(self.config.samplerate);
+        let resample_profile = self.config.resample_profile;
+        let g722_mode = self.config.g722;
+        let processor_chain =
+            ProcessorChain::new(self.config.samplerate).with_resample_profile(resample_profile);
         let ssrc = if self.ssrc != 0 {
             self.ssrc
         } else {
@@ -401,7 +538,10 @@ impl RtpTrackBuilder {
             remote_addr: None,
             remote_rtcp_addr: None,
             enabled_codecs: self.enabled_codecs.clone(),
+            levels: self.levels.clone(),
+            audio_level_id: None,
         };
+        let (dtmf_sender, dtmf_receiver) = mpsc::unbounded_channel();
         let track = RtpTrack {
             ssrc,
             ssrc_cname: self.ssrc_cname.clone(),
@@ -411,11 +551,22 @@ impl RtpTrackBuilder {
             processor_chain,
             rtp_socket: rtp_socket.unwrap(),
             rtcp_socket: rtcp_socket.unwrap(),
-            encoder: TrackCodec::new(),
+            encoder: TrackCodec::with_profile(resample_profile).with_g722_mode(g722_mode),
             sequencer: Box::new(new_random_sequencer()),
             sendrecv: AtomicBool::new(true),
             ice_connectivity_check: self.ice_connectivity_check,
+            shaper: self
+                .shaping
+                .as_ref()
+                .map(|shaping| Arc::new(Shaper::new(shaping))),
+            rewriter: self
+                .rewrite
+                .as_ref()
+                .map(|rewrite| Mutex::new(RtpRewriter::new(ssrc, rewrite))),
             inner: Arc::new(Mutex::new(inner)),
+            dtmf_sender,
+            dtmf_receiver: Mutex::new(Some(dtmf_receiver)),
+            clock: self.clock,
         };
         Ok(track)
     }
@@ -440,8 +591,7 @@ impl RtpTrack {
             // if remote description is already set, don't set it again
             return Ok(());
         }
-        let mut reader = Cursor::new(answer);
-        let sdp = SessionDescription::unmarshal(&mut reader)?;
+        let sdp = parse_sdp(answer.as_bytes())?;
         let peer_media = match select_peer_media(&sdp, "audio") {
             Some(peer_media) => peer_media,
             None => return Err(anyhow::anyhow!("no audio media in answer SDP")),
@@ -472,6 +622,9 @@ impl RtpTrack {
             r#type: Some(rsip::transport::Transport::Udp),
         };
         let codec_type = peer_media.codecs[0];
+        self.encoder.set_payload_types(&peer_media.payload_types);
+        self.processor_chain
+            .set_payload_types(&peer_media.payload_types);
         info!(
             track_id = self.track_id,
             rtcp_mux = peer_media.rtcp_mux,
@@ -481,8 +634,17 @@ impl RtpTrack {
             "set remote description"
         );
 
-        inner.payload_type = codec_type.payload_type();
+        inner.payload_type = self.encoder.payload_type(codec_type);
+        inner.dtmf_payload_type = self.encoder.payload_type(CodecType::TelephoneEvent);
         inner.enabled_codecs = vec![codec_type];
+        if inner.levels.is_some() {
+            inner.audio_level_id = parse_extmaps(answer).get(AUDIO_LEVEL_URI).copied();
+        }
+        if let Some(rewriter) = self.rewriter.as_ref() {
+            let mut rewriter = rewriter.lock().unwrap();
+            rewriter.set_egress(parse_extmaps(answer));
+            rewriter.set_mid(parse_mid(answer));
+        }
 
         inner.remote_addr.replace(remote_addr);
         inner.remote_rtcp_addr.replace(remote_rtcp_addr);
@@ -549,13 +711,11 @@ impl RtpTrack {
         };
         let inner = self.inner.lock().unwrap();
         for codec in inner.enabled_codecs.iter() {
-            media
-                .media_name
-                .formats
-                .push(codec.payload_type().to_string());
+            let payload_type = self.encoder.payload_type(*codec);
+            media.media_name.formats.push(payload_type.to_string());
             media.attributes.push(Attribute {
                 key: "rtpmap".to_string(),
-                value: Some(format!("{} {}", codec.payload_type(), codec.rtpmap())),
+                value: Some(format!("{} {}", payload_type, codec.rtpmap())),
             });
         }
 
@@ -566,6 +726,26 @@ impl RtpTrack {
                 value: None,
             });
         }
+        let mut extmaps = match self.rewriter.as_ref() {
+            Some(rewriter) => rewriter.lock().unwrap().extmaps(),
+            None => Vec::new(),
+        };
+        if inner.levels.is_some() {
+            // offered with the next free id, answered only when offered
+            let id = match inner.remote_description {
+                Some(_) => inner.audio_level_id,
+                None => Some(extmaps.iter().map(|(id, _)| *id).max().unwrap_or(0) + 1),
+            };
+            if let Some(id) = id {
+                extmaps.push((id, format!("{} vad=off", AUDIO_LEVEL_URI)));
+            }
+        }
+        for (id, uri) in extmaps {
+            media.attributes.push(Attribute {
+                key: "extmap".to_string(),
+                value: Some(format!("{} {}", id, uri)),
+            });
+        }
         media.attributes.push(Attribute {
             key: ATTR_KEY_SSRC.to_string(),
             value: Some(if self.ssrc_cname.is_empty() {
@@ -589,56 +769,93 @@ impl RtpTrack {
         Ok(sdp.marshal())
     }
 
-    // Send DTMF tone using RFC 4733
-    pub async fn send_dtmf(&self, digit: &str, duration_ms: Option<u64>) -> Result<()> {
-        // Map DTMF digit to event code first (validate before checking remote address)
-        let event_code = match digit {
-            "0" => 0,
-            "1" => 1,
-            "2" => 2,
-            "3" => 3,
-            "4" => 4,
-            "5" => 5,
-            "6" => 6,
-            "7" => 7,
-            "8" => 8,
-            "9" => 9,
-            "*" => 10,
-            "#" => 11,
-            "A" => 12,
-            "B" => 13,
-            "C" => 14,
-            "D" => 15,
-            _ => return Err(anyhow::anyhow!("Invalid DTMF digit")),
-        };
-        let inner = self.inner.lock().unwrap();
-        let socket = &self.rtp_socket;
-        let remote_addr = match inner.remote_addr.as_ref() {
-            Some(addr) => addr.clone(),
-            None => return Err(anyhow::anyhow!("Remote address not set")),
+    /// The header extensions negotiated on the leg `send_rtp` packets come
+    /// from, by its SDP
+    pub fn set_ingress_description(&self, sdp: &str) {
+        if let Some(rewriter) = self.rewriter.as_ref() {
+            rewriter.lock().unwrap().set_ingress(parse_extmaps(sdp));
+        }
+    }
+
+    /// Relays a packet received on another leg as is, without transcoding,
+    /// rewritten when the track was built `with_rewrite`
+    pub async fn send_rtp(&self, mut packet: Packet) -> Result<()> {
+        let (remote_addr, stats) = {
+            let inner = self.inner.lock().unwrap();
+            match inner.remote_addr.clone() {
+                Some(addr) => (addr, inner.stats.clone()),
+                None => return Ok(()),
+            }
         };
+        match self.rewriter.as_ref() {
+            Some(rewriter) => rewriter.lock().unwrap().rewrite(&mut packet),
+            None => packet.header.ssrc = self.ssrc,
+        }
+        if let Some(shaper) = self.shaper.as_ref()
+            && !shaper.admit(packet.marshal_size()).await
+        {
+            return Ok(());
+        }
+        let rtp_data = packet.marshal()?;
+        self.rtp_socket.send_raw(&rtp_data, &remote_addr).await?;
+        stats.update_send_stats(rtp_data.len() as u32, 0);
+        stats
+            .timestamp
+            .store(packet.header.timestamp, Ordering::Relaxed);
+        Ok(())
+    }
 
-        // Use default duration if not specified
-        let duration = duration_ms.unwrap_or(DTMF_EVENT_DURATION_MS);
+    /// Sends the digits queued by `send_dtmf`, each after the one before
+    async fn send_dtmf_loop(
+        inner: Arc<Mutex<RtpTrackInner>>,
+        track_id: TrackId,
+        socket: UdpConnection,
+        config: TrackConfig,
+        mut dtmf_receiver: mpsc::UnboundedReceiver<(u8, Duration)>,
+    ) {
+        while let Some((event_code, duration)) = dtmf_receiver.recv().await {
+            if let Err(e) =
+                Self::send_dtmf_events(inner.clone(), &socket, &config, event_code, duration).await
+            {
+                warn!(track_id, "Failed to send DTMF: {}", e);
+            }
+        }
+    }
+
+    // Send DTMF tone using RFC 4733, one event every ptime
+    async fn send_dtmf_events(
+        inner: Arc<Mutex<RtpTrackInner>>,
+        socket: &UdpConnection,
+        config: &TrackConfig,
+        event_code: u8,
+        duration: Duration,
+    ) -> Result<()> {
+        let (remote_addr, dtmf_payload_type, stats) = {
+            let inner = inner.lock().unwrap();
+            match inner.remote_addr.clone() {
+                Some(addr) => (addr, inner.dtmf_payload_type, inner.stats.clone()),
+                None => return Err(anyhow::anyhow!("Remote address not set")),
+            }
+        };
+        let ptime_ms = (config.ptime.as_millis() as u64).max(1);
+        let duration_ms = (duration.as_millis() as u64).max(1);
 
         // Calculate number of packets to send
-        // We send one packet every 20ms (default packet time)
-        let num_packets = (duration as f64 / self.config.ptime.as_millis() as f64).ceil() as u32;
+        let num_packets = duration_ms.div_ceil(ptime_ms) as u32;
 
         // Calculate samples per packet for timestamp increments
-        let samples_per_packet =
-            (self.config.samplerate as f64 * self.config.ptime.as_secs_f64()) as u32;
+        let samples_per_packet = (config.samplerate as f64 * config.ptime.as_secs_f64()) as u32;
 
         let now = crate::get_timestamp();
-        inner
-            .stats
-            .last_timestamp_update
-            .store(now, Ordering::Relaxed);
+        stats.last_timestamp_update.store(now, Ordering::Relaxed);
 
         // Generate RFC 4733 DTMF events
         for i in 0..num_packets {
             let is_end = i == num_packets - 1;
-            let event_duration = i * (self.config.ptime.as_millis() as u32 * 8); // Duration in timestamp units
+            // Duration so far in timestamp units (8000 Hz), the last event
+            // carries the whole digit
+            let event_duration =
+                (((i as u64 + 1) * ptime_ms).min(duration_ms) * 8).min(u16::MAX as u64) as u16;
 
             // Create DTMF event payload
             // Format: |event(8)|E|R|Volume(6)|Duration(16)|
@@ -650,37 +867,27 @@ impl RtpTrack {
             }
 
             // Duration (16 bits, network byte order)
-            payload[2] = ((event_duration >> 8) & 0xFF) as u8;
-            payload[3] = (event_duration & 0xFF) as u8;
+            payload[2..4].copy_from_slice(&event_duration.to_be_bytes());
 
-            let packets = match inner.packetizer.lock().unwrap().as_mut() {
+            let packets = match inner.lock().unwrap().packetizer.lock().unwrap().as_mut() {
                 Some(p) => p.packetize(&Bytes::from_owner(payload), samples_per_packet)?,
                 None => return Err(anyhow::anyhow!("Packetizer not set")),
             };
             for mut packet in packets {
-                packet.header.payload_type = inner.dtmf_payload_type;
+                packet.header.payload_type = dtmf_payload_type;
                 packet.header.marker = false;
 
                 match packet.marshal() {
                     Ok(ref rtp_data) => {
-                        match socket.send_raw(rtp_data, &remote_addr).await {
-                            Ok(_) => {}
-                            Err(e) => {
-                                error!("Failed to send DTMF RTP packet: {}", e);
-                            }
+                        if let Err(e) = socket.send_raw(rtp_data, &remote_addr).await {
+                            error!("Failed to send DTMF RTP packet: {}", e);
                         }
 
                         // Update counters for RTCP
-                        inner.stats.packet_count.fetch_add(1, Ordering::Relaxed);
-                        inner
-                            .stats
+                        stats.packet_count.fetch_add(1, Ordering::Relaxed);
+                        stats
                             .octet_count
                             .fetch_add(rtp_data.len() as u32, Ordering::Relaxed);
-
-                        // Sleep for packet time if not the last packet
-                        if !is_end {
-                            tokio::time::sleep(self.config.ptime).await;
-                        }
                     }
                     Err(e) => {
                         error!("Failed to create DTMF RTP packet: {:?}", e);
@@ -688,11 +895,14 @@ impl RtpTrack {
                     }
                 }
             }
+            // Sleep for packet time if not the last packet
+            if !is_end {
+                tokio::time::sleep(config.ptime).await;
+            }
         }
 
         // After sending DTMF, update the timestamp to account for the DTMF duration
-        inner
-            .stats
+        stats
             .timestamp
             .fetch_add(samples_per_packet * num_packets, Ordering::Relaxed);
 
@@ -715,24 +925,12 @@ impl RtpTrack {
         Ok(())
     }
 
-    async fn handle_rtcp_packet(
+    fn handle_rtcp_packets(
         track_id: &TrackId,
-        buf: &[u8],
-        n: usize,
+        packets: Vec<Box<dyn RtcpPacket + Send + Sync>>,
         stats: &Arc<RtpTrackStats>,
         ssrc: u32,
-    ) -> Result<()> {
-        use webrtc::rtcp::packet::unmarshal;
-
-        let mut buf_slice = &buf[0..n];
-        let packets = match unmarshal(&mut buf_slice) {
-            Ok(packets) => packets,
-            Err(e) => {
-                warn!(track_id, "Failed to parse RTCP packet: {:?}", e);
-                return Ok(());
-            }
-        };
-
+    ) {
         for packet in packets {
             if let Some(sr) = packet.as_any().downcast_ref::<SenderReport>() {
                 stats.store_sr_info(sr.rtp_time as u64, sr.ntp_time);
@@ -775,76 +973,6 @@ impl RtpTrack {
                 );
             }
         }
-
-        Ok(())
-    }
-
-    async fn is_rtcp_or_stun(
-        track_id: &TrackId,
-        buf: &[u8],
-        n: usize,
-        stats: &Arc<RtpTrackStats>,
-        ssrc: u32,
-    ) -> bool {
-        // RTCP packet detection and filtering for rtcp-mux scenarios
-        let version = (buf[0] >> 6) & 0x03;
-
-        // Check if this is a STUN packet first
-        // STUN packets have specific message types and magic cookie
-        if n >= 8 {
-            let msg_type = u16::from_be_bytes([buf[0], buf[1]]);
-            let msg_length = u16::from_be_bytes([buf[2], buf[3]]);
-            let magic_cookie = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
-
-            // STUN magic cookie is 0x2112A442
-            // STUN message types are in specific ranges (0x0001, 0x0101, etc.)
-            if magic_cookie == STUN_MAGIC_COOKIE
-                || (msg_type & 0xC000) == 0x0000 && msg_length <= (n - 20) as u16
-            {
-                debug!(
-                    track_id,
-                    "Received STUN packet with message type: 0x{:04X}, length: {}, skipping RTP processing",
-                    msg_type,
-                    n
-                );
-                return true;
-            }
-        }
-
-        // Check if this is an RTCP packet
-        // RTCP packet structure: V(2) + P(1) + RC(5) + PT(8) + Length(16) + ...
-        // For RTCP: PT is the full second byte (200-207)
-        let rtcp_pt = buf[1]; // Full second byte for RTCP
-        if version == 2 && rtcp_pt >= 200 && rtcp_pt <= 207 {
-            if let Err(e) = Self::handle_rtcp_packet(&track_id, &buf, n, &stats, ssrc).await {
-                warn!(track_id, "Failed to handle RTCP packet: {:?}", e);
-            }
-            return true;
-        }
-
-        // For RTP packets: V(2) + P(1) + X(1) + CC(4) + M(1) + PT(7) + ...
-        // PT is only 7 bits for RTP
-        let rtp_pt = buf[1] & 0x7F; // Extract payload type (7 bits) for RTP
-
-        // Additional validation for RTP packets
-        if version != 2 {
-            info!(
-                track_id,
-                "Received packet with invalid RTP version: {}, skipping", version
-            );
-            return true;
-        }
-
-        // RTP payload types should be < 128 (7 bits)
-        if rtp_pt >= 128 {
-            debug!(
-                track_id,
-                "Received packet with invalid RTP payload type: {}, might be unrecognized protocol",
-                rtp_pt
-            );
-            return true;
-        }
-        false
     }
 
     async fn recv_rtp_packets(
@@ -856,11 +984,16 @@ impl RtpTrack {
         packet_sender: TrackPacketSender,
         _rtcp_socket: UdpConnection,
         ssrc: u32,
+        clock: ClockRef,
     ) -> Result<()> {
         let mut buf = vec![0u8; RTP_MTU];
-        let mut send_ticker = tokio::time::interval(ptime);
-        let mut jitter = JitterBuffer::new();
-        let stats = inner.lock().unwrap().stats.clone();
+        let mut send_ticker = Pacer::with_clock(ptime, clock.clone());
+        let mut jitter = JitterBuffer::new().with_clock(clock.clone());
+        let (stats, levels) = {
+            let inner = inner.lock().unwrap();
+            (inner.stats.clone(), inner.levels.clone())
+        };
+        let mut remote_ssrc = None;
 
         loop {
             select! {
@@ -868,40 +1001,43 @@ impl RtpTrack {
                     if n <= 0 {
                         continue;
                     }
-                    if Self::is_rtcp_or_stun(&track_id, &buf, n, &stats, ssrc).await {
-                        continue;
-                    }
-                    let packet = match Packet::unmarshal(&mut &buf[0..n]) {
-                        Ok(packet) => packet,
+                    let packet = match parse_rtp_packet(&buf[0..n]) {
+                        Ok(RtpPacketKind::Rtp(packet)) => packet,
+                        Ok(RtpPacketKind::Rtcp(packets)) => {
+                            Self::handle_rtcp_packets(&track_id, packets, &stats, ssrc);
+                            continue;
+                        }
+                        Ok(RtpPacketKind::Stun) => {
+                            debug!(track_id, n, "Received STUN packet, skipping RTP processing");
+                            continue;
+                        }
                         Err(e) => {
-                            info!(track_id, "Error creating RTP reader: {:?}", e);
+                            info!(track_id, "Invalid RTP packet: {}", e);
                             continue;
                         }
                     };
 
+                    // a new SSRC is a new stream, nothing of the old one applies
+                    if remote_ssrc
+                        .replace(packet.header.ssrc)
+                        .is_some_and(|ssrc| ssrc != packet.header.ssrc)
+                    {
+                        info!(track_id, ssrc = packet.header.ssrc, "remote SSRC changed");
+                        jitter.clear();
+                        processor_chain.reset_codec();
+                    }
                     let seq_num = packet.header.sequence_number as u32;
                     let payload_len = packet.payload.len() as u32;
                     stats.update_receive_stats(seq_num, payload_len);
+                    let audio_level_id = inner.lock().unwrap().audio_level_id;
+                    if let (Some(levels), Some(id)) = (levels.as_ref(), audio_level_id)
+                        && let Some(level) = read_audio_level(&packet.header, id)
+                    {
+                        update_levels(levels, &track_id, level, clock.timestamp());
+                    }
 
-                    let payload_type = packet.header.payload_type;
-                    let payload = packet.payload.to_vec();
-                    let sample_rate = match payload_type {
-                        9 => 16000,   // G.722
-                        111 => 48000, // Opus
-                        _ => 8000,
-                    };
-
-                    let frame = AudioFrame {
-                        track_id: track_id.clone(),
-                        samples: Samples::RTP {
-                            payload_type,
-                            payload,
-                            sequence_number: packet.header.sequence_number.into(),
-                        },
-                        timestamp: crate::get_timestamp(),
-                        sample_rate,
-                    };
-
+                    let sample_rate = processor_chain.sample_rate(packet.header.payload_type);
+                    let frame = packet_to_frame(&track_id, packet, sample_rate, clock.timestamp());
                     jitter.push(frame);
                 }
                 _ = send_ticker.tick() => {
@@ -1055,13 +1191,20 @@ impl Track for RtpTrack {
         &mut self.processor_chain
     }
 
-    async fn handshake(&mut self, offer: String, _timeout: Option<Duration>) -> Result<String> {
+    async fn handshake(
+        &mut self,
+        offer: String,
+        _timeout: Option<Duration>,
+    ) -> MediaResult<String> {
         self.set_remote_description(&offer)?;
-        self.local_description()
+        Ok(self.local_description()?)
     }
 
-    async fn update_remote_description(&mut self, answer: &String) -> Result<()> {
+    async fn update_remote_description(&mut self, answer: &String) -> MediaResult<()> {
         self.set_remote_description(&answer).ok();
+        // e.g. resumed from hold, the stream starts over
+        self.encoder.reset();
+        self.processor_chain.reset_codec();
 
         if self.ice_connectivity_check {
             self.try_ice_connectivity_check().await;
@@ -1073,7 +1216,7 @@ impl Track for RtpTrack {
         &self,
         event_sender: EventSender,
         packet_sender: TrackPacketSender,
-    ) -> Result<()> {
+    ) -> MediaResult<()> {
         let track_id = self.track_id.clone();
         let rtcp_socket = self.rtcp_socket.clone();
         let ssrc = self.ssrc;
@@ -1083,6 +1226,23 @@ impl Track for RtpTrack {
         let ssrc_cname = self.ssrc_cname.clone();
         let start_time = crate::get_timestamp();
         let ptime = self.config.ptime;
+        let clock = self.clock.clone();
+        let shaper = self.shaper.clone();
+        let dtmf_receiver = self.dtmf_receiver.lock().unwrap().take();
+        let dtmf_loop = {
+            let inner = self.inner.clone();
+            let track_id = self.track_id.clone();
+            let rtp_socket = self.rtp_socket.clone();
+            let config = self.config.clone();
+            async move {
+                match dtmf_receiver {
+                    Some(receiver) => {
+                        Self::send_dtmf_loop(inner, track_id, rtp_socket, config, receiver).await
+                    }
+                    None => std::future::pending().await,
+                }
+            }
+        };
 
         if self.ice_connectivity_check {
             self.try_ice_connectivity_check().await;
@@ -1090,7 +1250,7 @@ impl Track for RtpTrack {
 
         let inner = self.inner.clone();
 
-        tokio::spawn(async move {
+        crate::media::workers::spawn(async move {
             // Send ICE connectivity check if enabled and remote address is available
             select! {
                 _ = token.cancelled() => {
@@ -1106,8 +1266,10 @@ impl Track for RtpTrack {
                     packet_sender,
                     rtcp_socket.clone(),
                     ssrc,
+                    clock,
                 ) => {
                 }
+                _ = dtmf_loop => {}
             };
             let remote_rtcp_addr = inner.lock().unwrap().remote_rtcp_addr.clone();
             // send rtcp bye packet
@@ -1127,6 +1289,16 @@ impl Track for RtpTrack {
                 None => {}
             }
             info!(track_id, "RTP processor completed");
+            if let Some(shaper) = shaper {
+                event_sender
+                    .send(SessionEvent::Metrics {
+                        timestamp: crate::get_timestamp(),
+                        key: format!("shaping.rtp.{}", track_id),
+                        duration: 0,
+                        data: serde_json::json!(shaper.stats()),
+                    })
+                    .ok();
+            }
             event_sender
                 .send(SessionEvent::TrackEnd {
                     track_id,
@@ -1141,17 +1313,42 @@ impl Track for RtpTrack {
         Ok(())
     }
 
-    async fn stop(&self) -> Result<()> {
+    async fn stop(&self) -> MediaResult<()> {
         self.cancel_token.cancel();
         Ok(())
     }
 
-    async fn send_packet(&self, packet: &AudioFrame) -> Result<()> {
+    /// Queues the digit, the events go out from the track's task
+    async fn send_dtmf(&self, digit: &str, duration: Duration) -> MediaResult<()> {
+        // Map DTMF digit to event code first (validate before checking remote address)
+        let event_code =
+            dtmf_event_code(digit).ok_or_else(|| anyhow::anyhow!("Invalid DTMF digit"))?;
+        if self.inner.lock().unwrap().remote_addr.is_none() {
+            return Err(anyhow::anyhow!("Remote address not set").into());
+        }
+        self.dtmf_sender
+            .send((event_code, duration))
+            .map_err(|_| anyhow::anyhow!("track stopped"))?;
+        Ok(())
+    }
+
+    async fn send_packet(&self, packet: &AudioFrame) -> MediaResult<()> {
         let remote_addr = match self.inner.lock().unwrap().remote_addr.clone() {
             Some(addr) => addr,
             None => return Ok(()),
         };
         let stats = self.inner.lock().unwrap().stats.clone();
+        let audio_level_id = self.inner.lock().unwrap().audio_level_id;
+        let level = match (&packet.samples, audio_level_id) {
+            (Samples::PCM { samples }, Some(id)) => Some((
+                id,
+                AudioLevelExtension {
+                    level: audio_level(samples),
+                    voice: false,
+                },
+            )),
+            _ => None,
+        };
 
         let (payload_type, payload) = self
             .encoder
@@ -1160,11 +1357,7 @@ impl Track for RtpTrack {
             return Ok(());
         }
 
-        let clock_rate = match payload_type {
-            9 => 8000,    // G.722 (RTP clock rate is 8000 even though sample rate is 16000)
-            111 => 48000, // Opus
-            _ => 8000,
-        };
+        let clock_rate = self.encoder.clock_rate(payload_type);
 
         let now = crate::get_timestamp();
         let last_update = stats.last_timestamp_update.load(Ordering::Relaxed);
@@ -1201,13 +1394,22 @@ impl Track for RtpTrack {
                 if skipped_packets > 0 {
                     p.skip_samples((skipped_packets * samples_per_packet as u64) as u32);
                 }
-                p.packetize(&Bytes::from_owner(payload), samples_per_packet)?
+                p.packetize(&Bytes::from_owner(payload), samples_per_packet)
+                    .map_err(anyhow::Error::from)?
             }
-            None => return Err(anyhow::anyhow!("Packetizer not set")),
+            None => return Err(anyhow::anyhow!("Packetizer not set").into()),
         };
         for mut packet in packets {
             packet.header.marker = false;
             packet.header.payload_type = payload_type;
+            if let Some((id, level)) = level {
+                write_audio_level(&mut packet.header, id, level);
+            }
+            if let Some(shaper) = self.shaper.as_ref()
+                && !shaper.admit(packet.marshal_size()).await
+            {
+                continue;
+            }
             match packet.marshal() {
                 Ok(ref rtp_data) => match self.rtp_socket.send_raw(rtp_data, &remote_addr).await {
                     Ok(_) => {
@@ -1222,7 +1424,7 @@ impl Track for RtpTrack {
                         track_id = self.track_id,
                         "Failed to build RTP packet: {:?}", e
                     );
-                    return Err(anyhow::anyhow!("Failed to build RTP packet"));
+                    return Err(anyhow::anyhow!("Failed to build RTP packet").into());
                 }
             }
         }
@@ -1337,6 +1539,37 @@ a=fmtp:101 0-16"#;
         assert!(!inner.rtcp_mux); // RTCP is on separate port
     }
 
+    #[tokio::test]
+    #[cfg(feature = "opus")]
+    async fn test_dynamic_payload_types() {
+        let sdp = r#"v=0
+o=- 1 1 IN IP4 192.168.1.202
+s=-
+c=IN IP4 192.168.1.202
+t=0 0
+m=audio 4002 RTP/AVP 96 97
+a=rtpmap:96 opus/48000/2
+a=rtpmap:97 telephone-event/48000
+a=sendrecv"#;
+        let rtp_track = RtpTrackBuilder::new("test".to_string(), TrackConfig::default())
+            .build()
+            .await
+            .expect("Failed to build rtp track");
+        rtp_track
+            .set_remote_description(sdp)
+            .expect("Failed to set remote description");
+        {
+            let inner = rtp_track.inner.lock().unwrap();
+            assert_eq!(inner.payload_type, 96);
+            assert_eq!(inner.dtmf_payload_type, 97);
+        }
+        assert!(rtp_track.encoder.is_audio(96));
+        assert_eq!(rtp_track.encoder.clock_rate(96), 48000);
+        assert!(rtp_track.processor_chain.is_audio(96));
+        let answer = rtp_track.local_description().unwrap();
+        assert!(answer.contains("a=rtpmap:96 opus/48000"));
+    }
+
     #[tokio::test]
     async fn test_parse_rtcp_mux() {
         let answer = r#"v=0
@@ -1479,14 +1712,14 @@ t=0 0"#;
         for digit in &valid_digits {
             // Since we don't have remote address set, this will fail with "Remote address not set"
             // but it shouldn't fail on digit mapping
-            let result = track.send_dtmf(digit, Some(100)).await;
+            let result = track.send_dtmf(digit, Duration::from_millis(100)).await;
             assert!(result.is_err());
             let error_msg = result.unwrap_err().to_string();
             assert!(error_msg.contains("Remote address not set"));
         }
 
         // Test invalid digit
-        let result = track.send_dtmf("X", Some(100)).await;
+        let result = track.send_dtmf("X", Duration::from_millis(100)).await;
         assert!(result.is_err());
         let error_msg = result.unwrap_err().to_string();
         assert!(error_msg.contains("Invalid DTMF digit"));
@@ -1537,6 +1770,43 @@ t=0 0"#;
         assert_eq!(track.id(), &track_id);
     }
 
+    #[test]
+    fn test_parse_rtp_packet() {
+        let mut stun = vec![0u8; 20];
+        stun[0..2].copy_from_slice(&STUN_BINDING_REQUEST.to_be_bytes());
+        stun[4..8].copy_from_slice(&STUN_MAGIC_COOKIE.to_be_bytes());
+        assert!(matches!(parse_rtp_packet(&stun), Ok(RtpPacketKind::Stun)));
+
+        let sr = SenderReport {
+            ssrc: 1234,
+            ..Default::default()
+        }
+        .marshal()
+        .unwrap();
+        match parse_rtp_packet(&sr) {
+            Ok(RtpPacketKind::Rtcp(packets)) => assert_eq!(packets.len(), 1),
+            _ => panic!("expected RTCP"),
+        }
+
+        let mut rtp = vec![0x80, 0x00, 0x00, 0x01, 0, 0, 0, 160, 0, 0, 0x12, 0x34];
+        rtp.extend_from_slice(&[0xff; 160]);
+        match parse_rtp_packet(&rtp) {
+            Ok(RtpPacketKind::Rtp(packet)) => {
+                assert_eq!(packet.header.sequence_number, 1);
+                assert_eq!(packet.header.ssrc, 0x1234);
+                assert_eq!(packet.payload.len(), 160);
+            }
+            _ => panic!("expected RTP"),
+        }
+
+        // truncated and malformed input is an error, not a panic
+        for len in 0..12 {
+            assert!(parse_rtp_packet(&rtp[..len]).is_err());
+        }
+        assert!(parse_rtp_packet(&[0xc0; 32]).is_err());
+        assert!(parse_rtp_packet(&[0x81, 200, 0xff, 0xff]).is_err());
+    }
+
     #[test]
     fn test_codec_type_payload_mapping() {
         // Test common codec payload types
@@ -0,0 +1,249 @@
+use super::{
+    Track, TrackConfig, TrackPacketSender,
+    rtp::{RtpPacketKind, parse_rtp_packet},
+};
+use crate::{
+    AudioFrame, PcmBuf, Samples, TrackId,
+    event::{EventSender, SessionEvent},
+    media::{
+        codecs::CodecType,
+        error::MediaResult,
+        pipeline::{MediaPipeline, packet_to_frame},
+        processor::ProcessorChain,
+    },
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{net::UdpSocket, select};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+#[skip_serializing_none]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RtpForkOption {
+    /// Destination of the forked audio, e.g. 10.0.0.5:4000
+    pub addr: String,
+    /// pcmu (default), pcma, g722, g729 or opus
+    pub codec: Option<String>,
+    /// Packet time in ms, default 20
+    pub ptime: Option<u32>,
+    /// Mix the RTP received back on the fork's port into the call
+    pub receive: Option<bool>,
+    /// Id of the fork track, default `rtp-fork`
+    pub track_id: Option<String>,
+    /// Local address to bind, default 0.0.0.0:0
+    pub local_addr: Option<String>,
+}
+
+impl RtpForkOption {
+    pub fn codec(&self) -> Result<CodecType> {
+        let codec = self.codec.as_deref().unwrap_or("pcmu").to_lowercase();
+        match codec.as_str() {
+            "pcmu" => Ok(CodecType::PCMU),
+            "pcma" => Ok(CodecType::PCMA),
+            "g722" => Ok(CodecType::G722),
+            #[cfg(feature = "g729")]
+            "g729" => Ok(CodecType::G729),
+            #[cfg(feature = "opus")]
+            "opus" => Ok(CodecType::Opus),
+            _ => Err(anyhow::anyhow!("unsupported fork codec: {}", codec)),
+        }
+    }
+}
+
+/// Forks the audio it is sent to a remote address as plain RTP, and plays
+/// the RTP received back into the call when `receive` is set
+pub struct RtpForkTrack {
+    track_id: TrackId,
+    config: TrackConfig,
+    cancel_token: CancellationToken,
+    processor_chain: ProcessorChain,
+    option: RtpForkOption,
+    ssrc: u32,
+    socket: Mutex<Option<Arc<UdpSocket>>>,
+    remote_addr: Mutex<Option<SocketAddr>>,
+    /// packetizer and the PCM waiting for a full ptime
+    pipeline: Mutex<(MediaPipeline, PcmBuf)>,
+}
+
+impl RtpForkTrack {
+    pub fn new(ssrc: u32, cancel_token: CancellationToken, option: RtpForkOption) -> Result<Self> {
+        let codec = option.codec()?;
+        let track_id = option
+            .track_id
+            .clone()
+            .unwrap_or_else(|| "rtp-fork".to_string());
+        let config = TrackConfig {
+            codec,
+            ptime: Duration::from_millis(option.ptime.unwrap_or(20).max(10) as u64),
+            samplerate: codec.samplerate(),
+            ..Default::default()
+        };
+        let pipeline = MediaPipeline::new(track_id.clone(), config.clone()).with_ssrc(ssrc);
+        Ok(Self {
+            processor_chain: ProcessorChain::new(config.samplerate),
+            track_id,
+            config,
+            cancel_token,
+            option,
+            ssrc,
+            socket: Mutex::new(None),
+            remote_addr: Mutex::new(None),
+            pipeline: Mutex::new((pipeline, Vec::new())),
+        })
+    }
+
+    /// Address the fork sends from and receives on, once started
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|socket| socket.local_addr().ok())
+    }
+}
+
+#[async_trait]
+impl Track for RtpForkTrack {
+    fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+    fn id(&self) -> &TrackId {
+        &self.track_id
+    }
+    fn config(&self) -> &TrackConfig {
+        &self.config
+    }
+    fn processor_chain(&mut self) -> &mut ProcessorChain {
+        &mut self.processor_chain
+    }
+
+    async fn handshake(&mut self, _: String, _: Option<Duration>) -> MediaResult<String> {
+        Ok("".to_string())
+    }
+
+    async fn start(
+        &self,
+        event_sender: EventSender,
+        packet_sender: TrackPacketSender,
+    ) -> MediaResult<()> {
+        let remote_addr = tokio::net::lookup_host(&self.option.addr)
+            .await?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("invalid fork address: {}", self.option.addr))?;
+        let local_addr = self.option.local_addr.as_deref().unwrap_or("0.0.0.0:0");
+        let socket = Arc::new(UdpSocket::bind(local_addr).await?);
+        info!(
+            track_id = self.track_id,
+            local_addr = %socket.local_addr()?,
+            %remote_addr,
+            codec = ?self.config.codec,
+            "rtp fork started"
+        );
+        *self.socket.lock().unwrap() = Some(socket.clone());
+        *self.remote_addr.lock().unwrap() = Some(remote_addr);
+
+        let track_id = self.track_id.clone();
+        let cancel_token = self.cancel_token.clone();
+        let processor_chain = self.processor_chain.clone();
+        let receive = self.option.receive.unwrap_or_default();
+        let start_time = crate::get_timestamp();
+        let ssrc = self.ssrc;
+        crate::media::workers::spawn(async move {
+            let recv_loop = async {
+                if !receive {
+                    return cancel_token.cancelled().await;
+                }
+                let mut buf = vec![0u8; 2048];
+                while let Ok((n, _)) = socket.recv_from(&mut buf).await {
+                    let packet = match parse_rtp_packet(&buf[..n]) {
+                        Ok(RtpPacketKind::Rtp(packet)) => packet,
+                        Ok(_) => continue,
+                        Err(e) => {
+                            debug!(track_id, "invalid rtp from fork: {}", e);
+                            continue;
+                        }
+                    };
+                    if !processor_chain.is_audio(packet.header.payload_type) {
+                        continue;
+                    }
+                    let sample_rate = processor_chain.sample_rate(packet.header.payload_type);
+                    let mut frame =
+                        packet_to_frame(&track_id, packet, sample_rate, crate::get_timestamp());
+                    if let Err(e) = processor_chain.process_frame(&mut frame) {
+                        warn!(track_id, "failed to process fork frame: {}", e);
+                        continue;
+                    }
+                    if packet_sender.send(frame).is_err() {
+                        break;
+                    }
+                }
+            };
+            select! {
+                _ = cancel_token.cancelled() => {}
+                _ = recv_loop => {}
+            }
+            info!(track_id, "rtp fork stopped");
+            event_sender
+                .send(SessionEvent::TrackEnd {
+                    track_id,
+                    timestamp: crate::get_timestamp(),
+                    duration: crate::get_timestamp() - start_time,
+                    ssrc,
+                    play_id: None,
+                })
+                .ok();
+        });
+        Ok(())
+    }
+
+    async fn stop(&self) -> MediaResult<()> {
+        self.cancel_token.cancel();
+        Ok(())
+    }
+
+    async fn send_packet(&self, packet: &AudioFrame) -> MediaResult<()> {
+        let samples = match &packet.samples {
+            Samples::PCM { samples } => samples,
+            _ => return Ok(()),
+        };
+        let socket = self.socket.lock().unwrap().clone();
+        let remote_addr = *self.remote_addr.lock().unwrap();
+        let (Some(socket), Some(remote_addr)) = (socket, remote_addr) else {
+            return Ok(());
+        };
+        let datagrams = {
+            let (pipeline, buffer) = &mut *self.pipeline.lock().unwrap();
+            buffer.extend_from_slice(samples);
+            let size = (packet.sample_rate as u128 * self.config.ptime.as_millis() / 1000) as usize;
+            let mut datagrams = Vec::new();
+            while size > 0 && buffer.len() >= size {
+                let frame = AudioFrame {
+                    track_id: self.track_id.clone(),
+                    samples: Samples::PCM {
+                        samples: buffer.drain(..size).collect(),
+                    },
+                    timestamp: packet.timestamp,
+                    sample_rate: packet.sample_rate,
+                };
+                pipeline.push_frame(&frame)?;
+                while let Some(datagram) = pipeline.pull_packet() {
+                    datagrams.push(datagram);
+                }
+            }
+            datagrams
+        };
+        for datagram in datagrams {
+            socket.send_to(&datagram, remote_addr).await?;
+        }
+        Ok(())
+    }
+}
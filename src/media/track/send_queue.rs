@@ -0,0 +1,179 @@
+//! Bounded queue between the stream and the task a track sends its frames
+//! from.
+//!
+//! A consumer that stalls, e.g. a socket that stopped draining, must not
+//! make frames pile up without end or hold up the other tracks of the
+//! stream: once the queue is full, its [`DropPolicy`] decides what gives.
+use crate::media::error::{MediaError, MediaResult};
+use serde::Serialize;
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::Notify;
+
+/// One second of 20ms frames
+pub const DEFAULT_SEND_QUEUE_CAPACITY: usize = 50;
+
+/// What a full queue does with a new frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// Drop the oldest frame, the consumer gets the most recent audio
+    #[default]
+    DropOldest,
+    /// Drop the new frame
+    DropNewest,
+    /// Hold the sender up to this long for room, then drop the oldest
+    Wait(Duration),
+}
+
+/// The state of a track's send queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendStats {
+    /// Frames waiting for the consumer
+    pub queued: usize,
+    pub capacity: usize,
+    /// Frames taken by the consumer
+    pub sent: u64,
+    /// Frames dropped because the queue was full
+    pub dropped: u64,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    closed: bool,
+}
+
+struct Inner<T> {
+    state: Mutex<State<T>>,
+    capacity: usize,
+    policy: DropPolicy,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    readable: Notify,
+    writable: Notify,
+}
+
+/// A bounded queue of frames with one consumer. Clones share the queue
+pub struct SendQueue<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Clone for SendQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> SendQueue<T> {
+    pub fn new(capacity: usize, policy: DropPolicy) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    items: VecDeque::with_capacity(capacity.max(1)),
+                    closed: false,
+                }),
+                capacity: capacity.max(1),
+                policy,
+                sent: AtomicU64::new(0),
+                dropped: AtomicU64::new(0),
+                readable: Notify::new(),
+                writable: Notify::new(),
+            }),
+        }
+    }
+
+    pub fn stats(&self) -> SendStats {
+        let queued = self.inner.state.lock().unwrap().items.len();
+        self.stats_of(queued)
+    }
+
+    /// Queues `item` and returns the state of the queue. A frame dropped to
+    /// make room, or this one, is [`MediaError::BufferOverrun`]. Items sent
+    /// after [`SendQueue::close`] are discarded
+    pub async fn send(&self, item: T) -> MediaResult<SendStats> {
+        if let DropPolicy::Wait(timeout) = self.inner.policy {
+            let deadline = tokio::time::Instant::now() + timeout;
+            loop {
+                let writable = self.inner.writable.notified();
+                if self.has_room() {
+                    break;
+                }
+                if tokio::time::timeout_at(deadline, writable).await.is_err() {
+                    break;
+                }
+            }
+        }
+        let dropped = {
+            let mut state = self.inner.state.lock().unwrap();
+            if state.closed {
+                return Ok(self.stats_of(state.items.len()));
+            }
+            let full = state.items.len() >= self.inner.capacity;
+            match (full, self.inner.policy) {
+                (true, DropPolicy::DropNewest) => {}
+                (true, _) => {
+                    state.items.pop_front();
+                    state.items.push_back(item);
+                }
+                (false, _) => state.items.push_back(item),
+            }
+            full
+        };
+        self.inner.readable.notify_one();
+        if dropped {
+            self.inner.dropped.fetch_add(1, Ordering::Relaxed);
+            return Err(MediaError::BufferOverrun {
+                capacity: self.inner.capacity,
+                dropped: 1,
+            });
+        }
+        Ok(self.stats())
+    }
+
+    /// The next item, `None` once the queue is closed and drained
+    pub async fn recv(&self) -> Option<T> {
+        loop {
+            let readable = self.inner.readable.notified();
+            {
+                let mut state = self.inner.state.lock().unwrap();
+                if let Some(item) = state.items.pop_front() {
+                    self.inner.sent.fetch_add(1, Ordering::Relaxed);
+                    self.inner.writable.notify_one();
+                    return Some(item);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            readable.await;
+        }
+    }
+
+    /// Stops taking items, the consumer still gets those queued
+    pub fn close(&self) {
+        self.inner.state.lock().unwrap().closed = true;
+        self.inner.readable.notify_one();
+    }
+
+    fn has_room(&self) -> bool {
+        let state = self.inner.state.lock().unwrap();
+        state.closed || state.items.len() < self.inner.capacity
+    }
+
+    fn stats_of(&self, queued: usize) -> SendStats {
+        SendStats {
+            queued,
+            capacity: self.inner.capacity,
+            sent: self.inner.sent.load(Ordering::Relaxed),
+            dropped: self.inner.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
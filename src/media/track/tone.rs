@@ -0,0 +1,188 @@
+use crate::event::{EventSender, SessionEvent};
+use crate::media::clock::{ClockRef, system_clock};
+use crate::media::error::MediaResult;
+use crate::media::pacer::Pacer;
+use crate::media::processor::ProcessorChain;
+use crate::media::track::{Track, TrackConfig, TrackPacketSender};
+use crate::{AudioFrame, Sample, Samples, TrackId};
+use async_trait::async_trait;
+use std::f32::consts::PI;
+use tokio::select;
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Level of a full scale sine wave in G.711 (in dBm0)
+const FULL_SCALE_DBM0: f32 = 3.17;
+
+/// Generates a sine tone, by default the 1004 Hz, 0 dBm0 digital milliwatt
+/// used to check levels and one-way audio.
+pub struct ToneTrack {
+    track_id: TrackId,
+    config: TrackConfig,
+    cancel_token: CancellationToken,
+    processor_chain: ProcessorChain,
+    frequency: f32,
+    level: f32,
+    duration: Option<Duration>,
+    ssrc: u32,
+    clock: ClockRef,
+}
+
+impl ToneTrack {
+    pub fn new(id: TrackId) -> Self {
+        let config = TrackConfig::default();
+        Self {
+            track_id: id,
+            processor_chain: ProcessorChain::new(config.samplerate),
+            config,
+            cancel_token: CancellationToken::new(),
+            frequency: 1004.0,
+            level: 0.0,
+            duration: None,
+            ssrc: 0,
+            clock: system_clock(),
+        }
+    }
+
+    pub fn with_ssrc(mut self, ssrc: u32) -> Self {
+        self.ssrc = ssrc;
+        self
+    }
+
+    pub fn with_config(mut self, config: TrackConfig) -> Self {
+        self.processor_chain = ProcessorChain::new(config.samplerate);
+        self.config = config;
+        self
+    }
+
+    pub fn with_cancel_token(mut self, cancel_token: CancellationToken) -> Self {
+        self.cancel_token = cancel_token;
+        self
+    }
+
+    pub fn with_frequency(mut self, frequency: f32) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    /// Tone level (in dBm0)
+    pub fn with_level(mut self, level: f32) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Stop after `duration`, plays until stopped when None
+    pub fn with_duration(mut self, duration: Option<Duration>) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn with_clock(mut self, clock: ClockRef) -> Self {
+        self.clock = clock;
+        self
+    }
+}
+
+#[async_trait]
+impl Track for ToneTrack {
+    fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+    fn id(&self) -> &TrackId {
+        &self.track_id
+    }
+    fn config(&self) -> &TrackConfig {
+        &self.config
+    }
+    fn processor_chain(&mut self) -> &mut ProcessorChain {
+        &mut self.processor_chain
+    }
+
+    async fn handshake(
+        &mut self,
+        _offer: String,
+        _timeout: Option<Duration>,
+    ) -> MediaResult<String> {
+        Ok("".to_string())
+    }
+
+    async fn start(
+        &self,
+        event_sender: EventSender,
+        packet_sender: TrackPacketSender,
+    ) -> MediaResult<()> {
+        let id = self.track_id.clone();
+        let ssrc = self.ssrc;
+        let sample_rate = self.config.samplerate;
+        let ptime = self.config.ptime;
+        let frame_size = (sample_rate as u128 * ptime.as_millis() / 1000) as usize;
+        let total_frames = self
+            .duration
+            .map(|d| (d.as_millis() / ptime.as_millis().max(1)) as u64);
+        let amplitude = Sample::MAX as f32 * 10f32.powf((self.level - FULL_SCALE_DBM0) / 20.0);
+        let step = 2.0 * PI * self.frequency / sample_rate as f32;
+        let processor_chain = self.processor_chain.clone();
+        let token = self.cancel_token.clone();
+        let clock = self.clock.clone();
+        let start_time = clock.timestamp();
+        info!(
+            track_id = id,
+            frequency = self.frequency,
+            level = self.level,
+            "tonetrack: started"
+        );
+        crate::media::workers::spawn(async move {
+            let tone_loop = async {
+                let mut pacer = Pacer::with_clock(ptime, clock.clone());
+                let mut phase = 0.0f32;
+                let mut sent = 0u64;
+                while total_frames.is_none_or(|total| sent < total) {
+                    pacer.tick().await;
+                    let samples = (0..frame_size)
+                        .map(|_| {
+                            let v = phase.sin() * amplitude;
+                            phase = (phase + step) % (2.0 * PI);
+                            v.clamp(Sample::MIN as f32, Sample::MAX as f32) as Sample
+                        })
+                        .collect();
+                    let mut frame = AudioFrame {
+                        track_id: id.clone(),
+                        timestamp: clock.timestamp(),
+                        samples: Samples::PCM { samples },
+                        sample_rate,
+                    };
+                    processor_chain.process_frame(&mut frame).ok();
+                    if packet_sender.send(frame).is_err() {
+                        break;
+                    }
+                    sent += 1;
+                }
+            };
+            select! {
+                _ = token.cancelled() => {}
+                _ = tone_loop => {}
+            }
+            event_sender
+                .send(SessionEvent::TrackEnd {
+                    track_id: id,
+                    timestamp: clock.timestamp(),
+                    duration: clock.timestamp() - start_time,
+                    ssrc,
+                    play_id: None,
+                })
+                .ok();
+        });
+        Ok(())
+    }
+
+    async fn stop(&self) -> MediaResult<()> {
+        self.cancel_token.cancel();
+        Ok(())
+    }
+
+    // Do nothing as we are not sending packets
+    async fn send_packet(&self, _packet: &AudioFrame) -> MediaResult<()> {
+        Ok(())
+    }
+}
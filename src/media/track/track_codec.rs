@@ -1,21 +1,29 @@
 use crate::{
-    AudioFrame, PcmBuf, Samples,
+    AudioFrame, PcmBuf, Sample, Samples,
     media::codecs::{
-        Decoder, Encoder, bytes_to_samples,
-        g722::{G722Decoder, G722Encoder},
+        CodecType, Decoder, Encoder, bytes_to_samples,
+        g722::{G722Decoder, G722Encoder, G722Mode},
         pcma::{PcmaDecoder, PcmaEncoder},
         pcmu::{PcmuDecoder, PcmuEncoder},
-        resample::LinearResampler,
+        resample::{ResampleProfile, StreamResampler},
         samples_to_bytes,
     },
 };
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 
 #[cfg(feature = "g729")]
 use crate::media::codecs::g729::{G729Decoder, G729Encoder};
 #[cfg(feature = "opus")]
 use crate::media::codecs::opus::{OpusDecoder, OpusEncoder};
 
+/// A gap this long (in ms) between frames starts a new stream
+const DISCONTINUITY_GAP: u64 = 1000;
+/// Sequence numbers further ahead than this are from another stream (RFC 3550)
+const MAX_DROPOUT: u16 = 3000;
+const MAX_MISORDER: u16 = 100;
+/// Fade in after a reset (in ms), the decoder's output takes a moment to settle
+const FADE_IN: u32 = 10;
+
 pub struct TrackCodec {
     pub pcmu_encoder: RefCell<PcmuEncoder>,
     pub pcmu_decoder: RefCell<PcmuDecoder>,
@@ -24,6 +32,7 @@ pub struct TrackCodec {
 
     pub g722_encoder: RefCell<G722Encoder>,
     pub g722_decoder: RefCell<G722Decoder>,
+    pub g722_mode: G722Mode,
 
     #[cfg(feature = "g729")]
     pub g729_encoder: RefCell<G729Encoder>,
@@ -35,19 +44,64 @@ pub struct TrackCodec {
     #[cfg(feature = "opus")]
     pub opus_decoder: RefCell<Option<OpusDecoder>>,
 
-    pub resampler: RefCell<Option<LinearResampler>>,
+    pub resample_profile: ResampleProfile,
+    /// Codec of each payload type negotiated, see [`TrackCodec::codec_type`]
+    payload_types: RefCell<Vec<(u8, CodecType)>>,
+    /// Resamples the decoded audio to the track's rate
+    pub decode_resampler: RefCell<Option<StreamResampler>>,
+    /// Resamples the track's audio to the rate of the codec it is sent with
+    pub encode_resampler: RefCell<Option<StreamResampler>>,
+
+    /// Payload type, sequence number and time (in ms) of the last packet decoded
+    last_decoded: Cell<Option<(u8, u16, u64)>>,
+    /// Time (in ms) of the last frame encoded
+    last_encoded: Cell<Option<u64>>,
+    /// The decoders were reset, fade in their next frame
+    fade_in: Cell<bool>,
 }
 unsafe impl Send for TrackCodec {}
 unsafe impl Sync for TrackCodec {}
 
 impl Clone for TrackCodec {
     fn clone(&self) -> Self {
-        Self::new() // Since each codec has its own state, create a fresh instance
+        // Since each codec has its own state, create a fresh instance
+        let codec = Self::with_profile(self.resample_profile).with_g722_mode(self.g722_mode);
+        codec.set_payload_types(&self.payload_types.borrow());
+        codec
+    }
+}
+
+/// Resamples `pcm` with the resampler in `slot`, made again when the rates
+/// change
+fn resample(
+    slot: &RefCell<Option<StreamResampler>>,
+    profile: ResampleProfile,
+    pcm: &[Sample],
+    input_sample_rate: u32,
+    output_sample_rate: u32,
+) -> PcmBuf {
+    let mut slot = slot.borrow_mut();
+    if !slot
+        .as_ref()
+        .is_some_and(|r| r.converts(input_sample_rate, output_sample_rate))
+    {
+        match StreamResampler::new(profile, input_sample_rate, output_sample_rate) {
+            Ok(resampler) => *slot = Some(resampler),
+            Err(_) => return pcm.to_vec(),
+        }
+    }
+    match slot.as_mut() {
+        Some(resampler) => resampler.resample(pcm),
+        None => pcm.to_vec(),
     }
 }
 
 impl TrackCodec {
     pub fn new() -> Self {
+        Self::with_profile(ResampleProfile::default())
+    }
+
+    pub fn with_profile(resample_profile: ResampleProfile) -> Self {
         Self {
             pcmu_encoder: RefCell::new(PcmuEncoder::new()),
             pcmu_decoder: RefCell::new(PcmuDecoder::new()),
@@ -55,6 +109,7 @@ impl TrackCodec {
             pcma_decoder: RefCell::new(PcmaDecoder::new()),
             g722_encoder: RefCell::new(G722Encoder::new()),
             g722_decoder: RefCell::new(G722Decoder::new()),
+            g722_mode: G722Mode::default(),
             #[cfg(feature = "g729")]
             g729_encoder: RefCell::new(G729Encoder::new()),
             #[cfg(feature = "g729")]
@@ -63,30 +118,150 @@ impl TrackCodec {
             opus_encoder: RefCell::new(None),
             #[cfg(feature = "opus")]
             opus_decoder: RefCell::new(None),
-            resampler: RefCell::new(None),
+            resample_profile,
+            payload_types: RefCell::new(Vec::new()),
+            decode_resampler: RefCell::new(None),
+            encode_resampler: RefCell::new(None),
+            last_decoded: Cell::new(None),
+            last_encoded: Cell::new(None),
+            fade_in: Cell::new(false),
         }
     }
 
-    pub fn is_audio(payload_type: u8) -> bool {
-        match payload_type {
-            0 | 8 | 9 => true,
-            #[cfg(feature = "g729")]
-            18 => true,
-            #[cfg(feature = "opus")]
-            111 => true,
-            _ => false,
+    pub fn with_g722_mode(mut self, mode: G722Mode) -> Self {
+        self.g722_encoder = RefCell::new(G722Encoder::with_mode(mode));
+        self.g722_decoder = RefCell::new(G722Decoder::with_mode(mode));
+        self.g722_mode = mode;
+        self
+    }
+
+    /// Start over with fresh codec state, e.g. when the stream resumes from
+    /// hold or the far end changed its SSRC
+    pub fn reset(&self) {
+        self.reset_decoders();
+        self.reset_encoders();
+    }
+
+    fn reset_decoders(&self) {
+        self.pcmu_decoder.borrow_mut().reset();
+        self.pcma_decoder.borrow_mut().reset();
+        self.g722_decoder.borrow_mut().reset();
+        #[cfg(feature = "g729")]
+        self.g729_decoder.borrow_mut().reset();
+        #[cfg(feature = "opus")]
+        if let Some(decoder) = self.opus_decoder.borrow_mut().as_mut() {
+            decoder.reset();
         }
+        self.decode_resampler.borrow_mut().take();
+        self.last_decoded.set(None);
+        self.fade_in.set(true);
+    }
+
+    fn reset_encoders(&self) {
+        self.pcmu_encoder.borrow_mut().reset();
+        self.pcma_encoder.borrow_mut().reset();
+        self.g722_encoder.borrow_mut().reset();
+        #[cfg(feature = "g729")]
+        self.g729_encoder.borrow_mut().reset();
+        #[cfg(feature = "opus")]
+        if let Some(encoder) = self.opus_encoder.borrow_mut().as_mut() {
+            encoder.reset();
+        }
+        self.encode_resampler.borrow_mut().take();
+        self.last_encoded.set(None);
+    }
+
+    /// Decode a packet received at `timestamp` (in ms). A packet that
+    /// doesn't continue the stream before it (another payload type, a
+    /// sequence number far off or a long gap) resets the decoders first
+    pub fn decode_packet(
+        &self,
+        payload_type: u8,
+        sequence_number: u16,
+        timestamp: u64,
+        payload: &[u8],
+        target_sample_rate: u32,
+    ) -> PcmBuf {
+        if let Some((last_type, last_sequence, last_timestamp)) = self.last_decoded.get() {
+            let ahead = sequence_number.wrapping_sub(last_sequence);
+            if last_type != payload_type
+                || (ahead > MAX_DROPOUT && ahead < u16::MAX - MAX_MISORDER)
+                || timestamp.saturating_sub(last_timestamp) >= DISCONTINUITY_GAP
+            {
+                self.reset_decoders();
+            }
+        }
+        self.last_decoded
+            .set(Some((payload_type, sequence_number, timestamp)));
+        let mut samples = self.decode(payload_type, payload, target_sample_rate);
+        if self.fade_in.replace(false) {
+            let ramp = (target_sample_rate * FADE_IN / 1000).max(1) as usize;
+            for (i, sample) in samples.iter_mut().take(ramp).enumerate() {
+                *sample = (*sample as i32 * i as i32 / ramp as i32) as Sample;
+            }
+        }
+        samples
+    }
+
+    /// Payload types as negotiated in the SDP, so that dynamic ones (e.g.
+    /// Opus at 96) are decoded with their codec
+    pub fn set_payload_types(&self, payload_types: &[(u8, CodecType)]) {
+        *self.payload_types.borrow_mut() = payload_types.to_vec();
+    }
+
+    /// Codec of a payload type, as negotiated or else its default
+    /// assignment
+    pub fn codec_type(&self, payload_type: u8) -> Option<CodecType> {
+        self.payload_types
+            .borrow()
+            .iter()
+            .find(|(pt, _)| *pt == payload_type)
+            .map(|(_, codec)| *codec)
+            .or_else(|| CodecType::from_payload_type(payload_type))
+    }
+
+    /// Payload type to send `codec` with, the first negotiated for it
+    pub fn payload_type(&self, codec: CodecType) -> u8 {
+        self.payload_types
+            .borrow()
+            .iter()
+            .find(|(_, c)| *c == codec)
+            .map(|(pt, _)| *pt)
+            .unwrap_or_else(|| codec.payload_type())
+    }
+
+    /// Rate of the audio a payload type carries
+    pub fn sample_rate(&self, payload_type: u8) -> u32 {
+        self.codec_type(payload_type)
+            .map_or(8000, |codec| codec.samplerate())
+    }
+
+    /// Rate of the RTP timestamps of a payload type. G.722 is sampled at
+    /// 16 kHz but keeps the 8 kHz clock of RFC 1890 for compatibility
+    pub fn clock_rate(&self, payload_type: u8) -> u32 {
+        self.codec_type(payload_type)
+            .map_or(8000, |codec| codec.clock_rate())
+    }
+
+    /// RTP timestamp ticks of `samples` at `sample_rate`
+    pub fn clock_samples(&self, payload_type: u8, samples: usize, sample_rate: u32) -> u32 {
+        (samples as u64 * self.clock_rate(payload_type) as u64 / sample_rate.max(1) as u64) as u32
+    }
+
+    pub fn is_audio(&self, payload_type: u8) -> bool {
+        self.codec_type(payload_type)
+            .is_some_and(|codec| codec.is_audio())
     }
 
     pub fn decode(&self, payload_type: u8, payload: &[u8], target_sample_rate: u32) -> PcmBuf {
-        let payload = match payload_type {
-            0 => self.pcmu_decoder.borrow_mut().decode(payload),
-            8 => self.pcma_decoder.borrow_mut().decode(payload),
-            9 => self.g722_decoder.borrow_mut().decode(payload),
+        let payload = match self.codec_type(payload_type) {
+            Some(CodecType::PCMU) => self.pcmu_decoder.borrow_mut().decode(payload),
+            Some(CodecType::PCMA) => self.pcma_decoder.borrow_mut().decode(payload),
+            Some(CodecType::G722) => self.g722_decoder.borrow_mut().decode(payload),
             #[cfg(feature = "g729")]
-            18 => self.g729_decoder.borrow_mut().decode(payload),
+            Some(CodecType::G729) => self.g729_decoder.borrow_mut().decode(payload),
             #[cfg(feature = "opus")]
-            111 => {
+            Some(CodecType::Opus) => {
                 let mut opus_decoder = self.opus_decoder.borrow_mut();
                 if opus_decoder.is_none() {
                     *opus_decoder = Some(OpusDecoder::new_default());
@@ -99,26 +274,15 @@ impl TrackCodec {
             }
             _ => bytes_to_samples(payload),
         };
-        let sample_rate = match payload_type {
-            0 => 8000,
-            8 => 8000,
-            9 => 16000,
-            18 => 8000,
-            111 => 48000, // Opus sample rate
-            _ => 8000,
-        };
+        let sample_rate = self.sample_rate(payload_type);
         if sample_rate != target_sample_rate {
-            if self.resampler.borrow().is_none() {
-                self.resampler.borrow_mut().replace(
-                    LinearResampler::new(sample_rate as usize, target_sample_rate as usize)
-                        .unwrap(),
-                );
-            }
-            self.resampler
-                .borrow_mut()
-                .as_mut()
-                .unwrap()
-                .resample(&payload)
+            resample(
+                &self.decode_resampler,
+                self.resample_profile,
+                &payload,
+                sample_rate,
+                target_sample_rate,
+            )
         } else {
             payload
         }
@@ -127,36 +291,32 @@ impl TrackCodec {
     pub fn encode(&self, payload_type: u8, frame: AudioFrame) -> (u8, Vec<u8>) {
         match frame.samples {
             Samples::PCM { samples: mut pcm } => {
-                let target_samplerate = match payload_type {
-                    0 => 8000,
-                    8 => 8000,
-                    9 => 16000,
-                    18 => 8000,
-                    111 => 48000, // Opus sample rate
-                    _ => 8000,
-                };
+                if let Some(last) = self.last_encoded.get()
+                    && frame.timestamp.saturating_sub(last) >= DISCONTINUITY_GAP
+                {
+                    self.reset_encoders();
+                }
+                self.last_encoded.set(Some(frame.timestamp));
+                let target_samplerate = self.sample_rate(payload_type);
 
                 if frame.sample_rate != target_samplerate {
-                    if self.resampler.borrow().is_none() {
-                        self.resampler.borrow_mut().replace(
-                            LinearResampler::new(
-                                frame.sample_rate as usize,
-                                target_samplerate as usize,
-                            )
-                            .unwrap(),
-                        );
-                    }
-                    pcm = self.resampler.borrow_mut().as_mut().unwrap().resample(&pcm);
+                    pcm = resample(
+                        &self.encode_resampler,
+                        self.resample_profile,
+                        &pcm,
+                        frame.sample_rate,
+                        target_samplerate,
+                    );
                 }
 
-                let payload = match payload_type {
-                    0 => self.pcmu_encoder.borrow_mut().encode(&pcm),
-                    8 => self.pcma_encoder.borrow_mut().encode(&pcm),
-                    9 => self.g722_encoder.borrow_mut().encode(&pcm),
+                let payload = match self.codec_type(payload_type) {
+                    Some(CodecType::PCMU) => self.pcmu_encoder.borrow_mut().encode(&pcm),
+                    Some(CodecType::PCMA) => self.pcma_encoder.borrow_mut().encode(&pcm),
+                    Some(CodecType::G722) => self.g722_encoder.borrow_mut().encode(&pcm),
                     #[cfg(feature = "g729")]
-                    18 => self.g729_encoder.borrow_mut().encode(&pcm),
+                    Some(CodecType::G729) => self.g729_encoder.borrow_mut().encode(&pcm),
                     #[cfg(feature = "opus")]
-                    111 => {
+                    Some(CodecType::Opus) => {
                         let mut opus_encoder = self.opus_encoder.borrow_mut();
                         if opus_encoder.is_none() {
                             *opus_encoder = Some(OpusEncoder::new_default());
@@ -3,7 +3,10 @@ use crate::{
     event::{EventSender, SessionEvent},
     media::{
         cache,
+        clock::{ClockRef, system_clock},
         codecs::bytes_to_samples,
+        error::MediaResult,
+        pacer::Pacer,
         processor::ProcessorChain,
         track::{Track, TrackConfig, TrackId, TrackPacketSender},
     },
@@ -51,6 +54,7 @@ pub struct TtsTrack {
     command_rx: Mutex<Option<SynthesisCommandReceiver>>,
     client: Mutex<Option<Box<dyn SynthesisClient>>>,
     ssrc: u32,
+    clock: ClockRef,
 }
 
 impl SynthesisHandle {
@@ -90,6 +94,7 @@ impl TtsTrack {
             use_cache: true,
             client: Mutex::new(Some(client)),
             ssrc: 0,
+            clock: system_clock(),
         }
     }
     pub fn with_ssrc(mut self, ssrc: u32) -> Self {
@@ -121,6 +126,12 @@ impl TtsTrack {
         self.use_cache = use_cache;
         self
     }
+
+    /// Paces the frames and stamps them and the events on `clock`
+    pub fn with_clock(mut self, clock: ClockRef) -> Self {
+        self.clock = clock;
+        self
+    }
 }
 
 #[async_trait]
@@ -138,7 +149,11 @@ impl Track for TtsTrack {
         &mut self.processor_chain
     }
 
-    async fn handshake(&mut self, _offer: String, _timeout: Option<Duration>) -> Result<String> {
+    async fn handshake(
+        &mut self,
+        _offer: String,
+        _timeout: Option<Duration>,
+    ) -> MediaResult<String> {
         Ok("".to_string())
     }
 
@@ -146,7 +161,7 @@ impl Track for TtsTrack {
         &self,
         event_sender: EventSender,
         packet_sender: TrackPacketSender,
-    ) -> Result<()> {
+    ) -> MediaResult<()> {
         let mut command_rx = self
             .command_rx
             .lock()
@@ -182,6 +197,7 @@ impl Track for TtsTrack {
         let client_ref = client.clone();
         let (buffer_tx, mut buffer_rx) = mpsc::unbounded_channel();
         let buffer_tx_ref = buffer_tx.clone();
+        let clock = self.clock.clone();
 
         let command_loop = async move {
             while let Some(mut command) = command_rx.recv().await {
@@ -237,7 +253,7 @@ impl Track for TtsTrack {
 
                                 event_sender_clone
                                     .send(SessionEvent::Metrics {
-                                        timestamp: crate::get_timestamp(),
+                                        timestamp: clock.timestamp(),
                                         key: format!("completed.tts.{}", provider),
                                         data: serde_json::json!({
                                                 "speaker": command.option.speaker,
@@ -291,7 +307,7 @@ impl Track for TtsTrack {
                         warn!(session_id, "error synthesizing text: {}", e);
                         event_sender_clone
                             .send(SessionEvent::Error {
-                                timestamp: crate::get_timestamp(),
+                                timestamp: clock.timestamp(),
                                 track_id: track_id.clone(),
                                 sender: format!("tts.{}", provider),
                                 error: e.to_string(),
@@ -312,6 +328,7 @@ impl Track for TtsTrack {
         let event_sender_clone = event_sender.clone();
         let track_id = self.track_id.clone();
         let buffer_tx_ref = buffer_tx.clone();
+        let clock = self.clock.clone();
 
         let receive_result_loop = async move {
             let mut audio_chunks = Vec::new();
@@ -334,7 +351,7 @@ impl Track for TtsTrack {
                                 // Send metrics event after the first chunk
                                 event_sender_clone
                                     .send(SessionEvent::Metrics {
-                                        timestamp: crate::get_timestamp(),
+                                        timestamp: clock.timestamp(),
                                         key: format!("ttfb.tts.{}", provider),
                                         data: serde_json::json!({
                                                 "speaker": status.speaker,
@@ -382,7 +399,7 @@ impl Track for TtsTrack {
                         // Send metrics event after all chunks are received
                         event_sender_clone
                             .send(SessionEvent::Metrics {
-                                timestamp: crate::get_timestamp(),
+                                timestamp: clock.timestamp(),
                                 key: format!("completed.tts.{}", provider),
                                 data: serde_json::json!({
                                         "speaker": status.speaker,
@@ -425,7 +442,7 @@ impl Track for TtsTrack {
                         warn!(session_id, "Error in audio stream chunk: {}", e);
                         event_sender_clone
                             .send(SessionEvent::Error {
-                                timestamp: crate::get_timestamp(),
+                                timestamp: clock.timestamp(),
                                 track_id: track_id.clone(),
                                 sender: format!("tts.{}", provider),
                                 error: e.to_string(),
@@ -450,20 +467,23 @@ impl Track for TtsTrack {
         let session_id = self.session_id.clone();
         let remaining_size = Arc::new(Mutex::new(0usize));
         let remaining_size_ref = Arc::new(Mutex::new(0usize));
+        let clock = self.clock.clone();
         let emit_loop = async move {
-            let mut ptimer =
-                tokio::time::interval(Duration::from_millis(packet_duration_ms as u64));
+            let mut pacer = Pacer::with_clock(
+                Duration::from_millis(packet_duration_ms as u64),
+                clock.clone(),
+            );
             let mut buffer = Vec::new();
             let mut is_recv_finished = false;
             loop {
                 select! {
-                        _ = ptimer.tick() => {
+                        _ = pacer.tick() => {
                                 let mut packet = if buffer.len() >= max_pcm_chunk_size {
                                     let packet_samples = buffer.drain(..max_pcm_chunk_size).collect::<Vec<_>>();
                                     AudioFrame {
                                         track_id: track_id.clone(),
                                         samples: Samples::PCM { samples: packet_samples },
-                                        timestamp: crate::get_timestamp(),
+                                        timestamp: clock.timestamp(),
                                         sample_rate,
                                     }
                                 } else {
@@ -473,7 +493,7 @@ impl Track for TtsTrack {
                                     AudioFrame {
                                         track_id: track_id.clone(),
                                         samples: Samples::PCM { samples: Vec::new() },
-                                        timestamp: crate::get_timestamp(),
+                                        timestamp: clock.timestamp(),
                                         sample_rate,
                                     }
                                 };
@@ -513,8 +533,9 @@ impl Track for TtsTrack {
         let session_id = self.session_id.clone();
         let ssrc = self.ssrc;
         let event_sender_clone = event_sender.clone();
-        tokio::spawn(async move {
-            let start_time = crate::get_timestamp();
+        let clock = self.clock.clone();
+        crate::media::workers::spawn(async move {
+            let start_time = clock.timestamp();
             select! {
                 _ = command_loop => {
                     info!(session_id, "command loop completed");
@@ -548,7 +569,7 @@ impl Track for TtsTrack {
                     let event = SessionEvent::Interruption {
                         track_id: track_id.clone(),
                         play_id: status.play_id.clone(),
-                        timestamp: crate::get_timestamp(),
+                        timestamp: clock.timestamp(),
                         subtitle: Some(status.full_text),
                         position,
                         total_duration,
@@ -558,7 +579,7 @@ impl Track for TtsTrack {
                 }
             }
 
-            let duration = crate::get_timestamp() - start_time;
+            let duration = clock.timestamp() - start_time;
             info!(session_id, track_id, duration, "tts track ended");
             let play_id = match status.read() {
                 Ok(status) => status.play_id.clone(),
@@ -567,7 +588,7 @@ impl Track for TtsTrack {
             event_sender_clone
                 .send(SessionEvent::TrackEnd {
                     track_id,
-                    timestamp: crate::get_timestamp(),
+                    timestamp: clock.timestamp(),
                     duration,
                     ssrc,
                     play_id,
@@ -577,12 +598,12 @@ impl Track for TtsTrack {
         Ok(())
     }
 
-    async fn stop(&self) -> Result<()> {
+    async fn stop(&self) -> MediaResult<()> {
         self.cancel_token.cancel();
         Ok(())
     }
 
-    async fn send_packet(&self, _packet: &AudioFrame) -> Result<()> {
+    async fn send_packet(&self, _packet: &AudioFrame) -> MediaResult<()> {
         Ok(())
     }
 }
@@ -5,6 +5,7 @@ use crate::{
     event::{EventSender, SessionEvent},
     media::{
         codecs::CodecType,
+        error::MediaResult,
         negotiate::prefer_audio_codec,
         processor::ProcessorChain,
         track::{Track, TrackConfig, TrackId, TrackPacketSender},
@@ -169,7 +170,9 @@ impl WebrtcTrack {
         track_config: TrackConfig,
         ice_servers: Option<Vec<IceServer>>,
     ) -> Self {
-        let processor_chain = ProcessorChain::new(track_config.samplerate);
+        let resample_profile = track_config.resample_profile;
+        let processor_chain =
+            ProcessorChain::new(track_config.samplerate).with_resample_profile(resample_profile);
         Self {
             track_id: id,
             track_config,
@@ -177,7 +180,7 @@ impl WebrtcTrack {
             packet_sender: Arc::new(Mutex::new(None)),
             cancel_token,
             local_track: None,
-            encoder: TrackCodec::new(),
+            encoder: TrackCodec::with_profile(resample_profile),
             prefered_codec: None,
             ssrc: 0,
             peer_connection: None,
@@ -261,11 +264,13 @@ impl WebrtcTrack {
                 let track_id_clone = track_id_clone.clone();
                 let packet_sender_clone = packet_sender.clone();
                 let processor_chain = processor_chain.clone();
-                let track_samplerate = match track.codec().payload_type {
-                    9 => 16000,   // G722
-                    111 => 48000, // Opus
-                    _ => 8000,    // PCMU, PCMA, TELEPHONE_EVENT
-                };
+                let track_samplerate = track
+                    .codec()
+                    .capability
+                    .mime_type
+                    .strip_prefix("audio/")
+                    .and_then(CodecType::from_encoding_name)
+                    .map_or(8000, |codec| codec.samplerate());
                 info!(
                     track_id=track_id_clone,
                     "on_track received: {} samplerate: {}",
@@ -382,17 +387,18 @@ impl Track for WebrtcTrack {
         &mut self.processor_chain
     }
 
-    async fn handshake(&mut self, offer: String, timeout: Option<Duration>) -> Result<String> {
-        self.setup_webrtc_track(offer, timeout)
+    async fn handshake(&mut self, offer: String, timeout: Option<Duration>) -> MediaResult<String> {
+        Ok(self
+            .setup_webrtc_track(offer, timeout)
             .await
-            .map(|answer| answer.sdp)
+            .map(|answer| answer.sdp)?)
     }
 
     async fn start(
         &self,
         event_sender: EventSender,
         packet_sender: TrackPacketSender,
-    ) -> Result<()> {
+    ) -> MediaResult<()> {
         // Store the packet sender
         *self.packet_sender.lock().await = Some(packet_sender.clone());
         let token_clone = self.cancel_token.clone();
@@ -400,7 +406,7 @@ impl Track for WebrtcTrack {
         let track_id = self.track_id.clone();
         let start_time = crate::get_timestamp();
         let ssrc = self.ssrc;
-        tokio::spawn(async move {
+        crate::media::workers::spawn(async move {
             token_clone.cancelled().await;
             let _ = event_sender_clone.send(SessionEvent::TrackEnd {
                 track_id,
@@ -414,13 +420,13 @@ impl Track for WebrtcTrack {
         Ok(())
     }
 
-    async fn stop(&self) -> Result<()> {
+    async fn stop(&self) -> MediaResult<()> {
         // Cancel all processing
         self.cancel_token.cancel();
         Ok(())
     }
 
-    async fn send_packet(&self, packet: &AudioFrame) -> Result<()> {
+    async fn send_packet(&self, packet: &AudioFrame) -> MediaResult<()> {
         if self.local_track.is_none() {
             return Ok(());
         }
@@ -448,7 +454,7 @@ impl Track for WebrtcTrack {
             Ok(_) => {}
             Err(e) => {
                 error!("failed to send sample: {}", e);
-                return Err(anyhow::anyhow!("Failed to send sample: {}", e));
+                return Err(anyhow::anyhow!("Failed to send sample: {}", e).into());
             }
         }
         Ok(())
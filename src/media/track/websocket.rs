@@ -2,9 +2,8 @@ use super::{Track, TrackConfig, TrackPacketSender, track_codec::TrackCodec};
 use crate::{
     AudioFrame, Samples, TrackId,
     event::{EventSender, SessionEvent},
-    media::{codecs::bytes_to_samples, processor::ProcessorChain},
+    media::{codecs::bytes_to_samples, error::MediaResult, processor::ProcessorChain},
 };
-use anyhow::Result;
 use async_trait::async_trait;
 use bytes::Bytes;
 use std::{sync::Mutex, time::Duration};
@@ -73,7 +72,11 @@ impl Track for WebsocketTrack {
         &mut self.processor_chain
     }
 
-    async fn handshake(&mut self, _offer: String, _timeout: Option<Duration>) -> Result<String> {
+    async fn handshake(
+        &mut self,
+        _offer: String,
+        _timeout: Option<Duration>,
+    ) -> MediaResult<String> {
         Ok("".to_string())
     }
 
@@ -81,7 +84,7 @@ impl Track for WebsocketTrack {
         &self,
         event_sender: EventSender,
         packet_sender: TrackPacketSender,
-    ) -> Result<()> {
+    ) -> MediaResult<()> {
         let track_id = self.track_id.clone();
         let token = self.cancel_token.clone();
         let mut audio_from_ws = match self.rx.lock().unwrap().take() {
@@ -95,7 +98,7 @@ impl Track for WebsocketTrack {
         let payload_type = self.payload_type;
         let start_time = crate::get_timestamp();
         let ssrc = self.ssrc;
-        tokio::spawn(async move {
+        crate::media::workers::spawn(async move {
             let track_id_clone = track_id.clone();
             let audio_from_ws_loop = async move {
                 let mut sequence_number = 0;
@@ -151,12 +154,12 @@ impl Track for WebsocketTrack {
         Ok(())
     }
 
-    async fn stop(&self) -> Result<()> {
+    async fn stop(&self) -> MediaResult<()> {
         self.cancel_token.cancel();
         Ok(())
     }
 
-    async fn send_packet(&self, packet: &AudioFrame) -> Result<()> {
+    async fn send_packet(&self, packet: &AudioFrame) -> MediaResult<()> {
         let (_, payload) = self.encoder.encode(self.payload_type, packet.clone());
         if payload.is_empty() {
             return Ok(());
@@ -168,6 +171,6 @@ impl Track for WebsocketTrack {
                 data: payload,
             })
             .map(|_| ())
-            .map_err(|_| anyhow::anyhow!("error sending binary event"))
+            .map_err(|_| anyhow::anyhow!("error sending binary event").into())
     }
 }
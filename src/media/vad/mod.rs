@@ -1,4 +1,5 @@
 use crate::event::{EventSender, SessionEvent};
+use crate::media::error::MediaResult;
 use crate::media::processor::Processor;
 use crate::{AudioFrame, PcmBuf, Samples};
 use anyhow::Result;
@@ -128,7 +129,7 @@ pub trait VadEngine: Send + Sync + Any {
 }
 
 impl VadProcessorInner {
-    pub fn process_frame(&mut self, frame: &mut AudioFrame) -> Result<()> {
+    pub fn process_frame(&mut self, frame: &mut AudioFrame) -> MediaResult<()> {
         let samples = match &frame.samples {
             Samples::PCM { samples } => samples,
             _ => return Ok(()),
@@ -310,9 +311,13 @@ impl VadProcessor {
 }
 
 impl Processor for VadProcessor {
-    fn process_frame(&self, frame: &mut AudioFrame) -> Result<()> {
+    fn process_frame(&self, frame: &mut AudioFrame) -> MediaResult<()> {
         self.inner.borrow_mut().process_frame(frame)
     }
+
+    fn sample_rate(&self) -> Option<u32> {
+        Some(self.inner.borrow().option.samplerate)
+    }
 }
 
 struct NopVad {}
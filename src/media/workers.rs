@@ -0,0 +1,245 @@
+use crate::config::MediaWorkersConfig;
+use anyhow::{Result, anyhow};
+use core_affinity::CoreId;
+use std::cell::Cell;
+use std::sync::{
+    Arc, OnceLock,
+    atomic::{AtomicUsize, Ordering},
+};
+use tokio::runtime::Runtime;
+use tokio::task::{JoinError, JoinHandle};
+use tracing::{info, warn};
+
+/// The runtime carrying the media, once started
+static MEDIA_RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+thread_local! {
+    static PINNED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Spawns `future` on the media runtime once one is started, on the
+/// current runtime otherwise
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match MEDIA_RUNTIME.get() {
+        Some(runtime) => runtime.spawn(future),
+        None => tokio::spawn(future),
+    }
+}
+
+/// Runs `future` on the media runtime, see [`spawn`]. It is aborted when
+/// the returned future is dropped.
+pub async fn run<F>(future: F) -> Result<F::Output, JoinError>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    struct AbortOnDrop<T>(JoinHandle<T>);
+    impl<T> Drop for AbortOnDrop<T> {
+        fn drop(&mut self) {
+            self.0.abort();
+        }
+    }
+    let mut task = AbortOnDrop(spawn(future));
+    (&mut task.0).await
+}
+
+/// Worker threads of the runtime carrying the media, by the `media_workers`
+/// config: how many, and the CPUs they are pinned to in turn
+#[derive(Debug, Clone)]
+pub struct WorkerLayout {
+    threads: usize,
+    cpus: Vec<usize>,
+    pinned: Arc<AtomicUsize>,
+}
+
+impl WorkerLayout {
+    pub fn new(config: &MediaWorkersConfig) -> Result<Self> {
+        let cpus = match (&config.cpus, config.numa_node) {
+            (Some(cpus), _) => parse_cpu_list(cpus)?,
+            (None, Some(node)) => numa_node_cpus(node)?,
+            (None, None) => Vec::new(),
+        };
+        let threads = match config.threads {
+            Some(0) => return Err(anyhow!("media_workers.threads must be at least 1")),
+            Some(threads) => threads,
+            None if !cpus.is_empty() => cpus.len(),
+            None => available_cpus(),
+        };
+        Ok(Self {
+            threads,
+            cpus,
+            pinned: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// CPUs the workers are pinned to, empty when they are not
+    pub fn cpus(&self) -> &[usize] {
+        &self.cpus
+    }
+
+    /// CPU of the `index`th thread started, the CPUs taken in turn
+    pub fn cpu_of(&self, index: usize) -> Option<usize> {
+        match self.cpus.len() {
+            0 => None,
+            len => Some(self.cpus[index % len]),
+        }
+    }
+
+    /// Multi-threaded runtime of the layout, for the media only. Its worker
+    /// threads are pinned as they first go idle, its blocking threads are
+    /// not: they may run on any CPU the process may.
+    pub fn build_runtime(&self) -> Result<Runtime> {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        builder
+            .enable_all()
+            .worker_threads(self.threads.max(1))
+            .thread_name("media-worker");
+        if !self.cpus.is_empty() {
+            // threads started by a pinned worker inherit its CPU
+            let unpinned = affinity::Unpinned::current();
+            builder.on_thread_start(move || {
+                if let Some(unpinned) = unpinned.as_ref() {
+                    unpinned.restore();
+                }
+            });
+            // only the workers park
+            let layout = self.clone();
+            let parked = AtomicUsize::new(0);
+            builder.on_thread_park(move || {
+                if PINNED.replace(true) {
+                    return;
+                }
+                let index = parked.fetch_add(1, Ordering::Relaxed);
+                if let Some(id) = layout.cpu_of(index)
+                    && core_affinity::set_for_current(CoreId { id })
+                {
+                    layout.pinned.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Starts the media runtime, which [`spawn`] and [`run`] use from then on
+    pub fn start(&self) -> Result<()> {
+        let runtime = self.build_runtime()?;
+        MEDIA_RUNTIME
+            .set(runtime)
+            .map_err(|_| anyhow!("media runtime already started"))
+    }
+
+    /// Logs the layout, once tracing is set up, for the threads pinned
+    /// before it was
+    pub fn log(&self) {
+        let available = available_cpus();
+        if self.cpus.is_empty() {
+            info!(
+                threads = self.threads,
+                available, "media workers not pinned"
+            );
+            return;
+        }
+        let pinned = self.pinned.load(Ordering::Relaxed);
+        info!(
+            threads = self.threads,
+            cpus = ?self.cpus,
+            pinned,
+            available,
+            "media workers pinned"
+        );
+        if pinned < self.threads.min(self.cpus.len()) {
+            warn!(
+                cpus = ?self.cpus,
+                "some media workers could not be pinned, are the CPUs online and allowed?"
+            );
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod affinity {
+    /// The CPUs the process may run on, as the kernel had them for the
+    /// thread that took them
+    pub struct Unpinned(libc::cpu_set_t);
+
+    impl Unpinned {
+        pub fn current() -> Option<Self> {
+            let mut set = unsafe { std::mem::zeroed::<libc::cpu_set_t>() };
+            match unsafe { libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut set) } {
+                0 => Some(Self(set)),
+                _ => None,
+            }
+        }
+
+        /// Lets the current thread run on any of them again
+        pub fn restore(&self) {
+            unsafe {
+                libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &self.0);
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod affinity {
+    /// Elsewhere threads don't inherit the CPUs of the thread starting them
+    pub struct Unpinned;
+
+    impl Unpinned {
+        pub fn current() -> Option<Self> {
+            None
+        }
+
+        pub fn restore(&self) {}
+    }
+}
+
+fn available_cpus() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+}
+
+/// CPUs of a Linux CPU list like `0-3,8-11`, as in `/sys` and `taskset -c`
+pub fn parse_cpu_list(list: &str) -> Result<Vec<usize>> {
+    let mut cpus = Vec::new();
+    for part in list.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let parse = |cpu: &str| {
+            cpu.trim()
+                .parse::<usize>()
+                .map_err(|_| anyhow!("invalid CPU `{}` in `{}`", cpu, list))
+        };
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (parse(first)?, parse(last)?);
+                if first > last {
+                    return Err(anyhow!("invalid CPU range `{}` in `{}`", part, list));
+                }
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(parse(part)?),
+        }
+    }
+    if cpus.is_empty() {
+        return Err(anyhow!("no CPU in `{}`", list));
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+    Ok(cpus)
+}
+
+/// CPUs of a NUMA node, as the kernel lists them
+pub fn numa_node_cpus(node: usize) -> Result<Vec<usize>> {
+    let path = format!("/sys/devices/system/node/node{}/cpulist", node);
+    let list = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow!("NUMA node {}: {}: {}", node, e, path))?;
+    parse_cpu_list(&list)
+}
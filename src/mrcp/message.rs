@@ -0,0 +1,235 @@
+//! MRCPv2 message framing (RFC 6787 section 5)
+use anyhow::{Result, anyhow};
+
+pub const MRCP_VERSION: &str = "MRCP/2.0";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestState {
+    Complete,
+    InProgress,
+    Pending,
+}
+
+impl RequestState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RequestState::Complete => "COMPLETE",
+            RequestState::InProgress => "IN-PROGRESS",
+            RequestState::Pending => "PENDING",
+        }
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "COMPLETE" => Ok(RequestState::Complete),
+            "IN-PROGRESS" => Ok(RequestState::InProgress),
+            "PENDING" => Ok(RequestState::Pending),
+            _ => Err(anyhow!("invalid request state: {}", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StartLine {
+    Request { method: String },
+    Response { status: u16, state: RequestState },
+    Event { name: String, state: RequestState },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MrcpMessage {
+    pub start_line: StartLine,
+    pub request_id: u32,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl MrcpMessage {
+    pub fn request(method: &str, request_id: u32) -> Self {
+        Self {
+            start_line: StartLine::Request {
+                method: method.to_string(),
+            },
+            request_id,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn response(request_id: u32, status: u16, state: RequestState) -> Self {
+        Self {
+            start_line: StartLine::Response { status, state },
+            request_id,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn event(name: &str, request_id: u32, state: RequestState) -> Self {
+        Self {
+            start_line: StartLine::Event {
+                name: name.to_string(),
+                state,
+            },
+            request_id,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn with_body(mut self, content_type: &str, body: Vec<u8>) -> Self {
+        self.headers.retain(|(name, _)| {
+            !name.eq_ignore_ascii_case("content-type")
+                && !name.eq_ignore_ascii_case("content-length")
+        });
+        self.headers
+            .push(("Content-Type".to_string(), content_type.to_string()));
+        self.body = body;
+        self
+    }
+
+    /// Value of the first header named `name`, case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn method(&self) -> Option<&str> {
+        match &self.start_line {
+            StartLine::Request { method } => Some(method),
+            _ => None,
+        }
+    }
+
+    pub fn event_name(&self) -> Option<&str> {
+        match &self.start_line {
+            StartLine::Event { name, .. } => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        match &self.start_line {
+            StartLine::Response { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut rest = match &self.start_line {
+            StartLine::Request { method } => format!(" {} {}\r\n", method, self.request_id),
+            StartLine::Response { status, state } => {
+                format!(" {} {} {}\r\n", self.request_id, status, state.as_str())
+            }
+            StartLine::Event { name, state } => {
+                format!(" {} {} {}\r\n", name, self.request_id, state.as_str())
+            }
+        };
+        for (name, value) in &self.headers {
+            if !name.eq_ignore_ascii_case("content-length") {
+                rest.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        if !self.body.is_empty() {
+            rest.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        rest.push_str("\r\n");
+
+        // message-length counts the whole message, including its own digits
+        let fixed = MRCP_VERSION.len() + 1 + rest.len() + self.body.len();
+        let mut length = fixed + 1;
+        while fixed + length.to_string().len() != length {
+            length = fixed + length.to_string().len();
+        }
+        let mut data = format!("{} {}{}", MRCP_VERSION, length, rest).into_bytes();
+        data.extend_from_slice(&self.body);
+        data
+    }
+
+    /// Parse the first message of `buf`, returns the message and its length,
+    /// or None if `buf` doesn't hold a complete message yet
+    pub fn parse(buf: &[u8]) -> Result<Option<(Self, usize)>> {
+        let line_end = match buf.windows(2).position(|w| w == b"\r\n") {
+            Some(pos) => pos,
+            None if buf.len() > 1024 => return Err(anyhow!("MRCP start line too long")),
+            None => return Ok(None),
+        };
+        let start_line = std::str::from_utf8(&buf[..line_end])?;
+        let tokens: Vec<&str> = start_line.split_ascii_whitespace().collect();
+        if tokens.len() < 4 || tokens[0] != MRCP_VERSION {
+            return Err(anyhow!("invalid MRCP start line: {}", start_line));
+        }
+        let length: usize = tokens[1]
+            .parse()
+            .map_err(|_| anyhow!("invalid MRCP message length: {}", tokens[1]))?;
+        if length <= line_end {
+            return Err(anyhow!("invalid MRCP message length: {}", length));
+        }
+        if buf.len() < length {
+            return Ok(None);
+        }
+
+        let (start_line, request_id) = match tokens.len() {
+            4 => (
+                StartLine::Request {
+                    method: tokens[2].to_string(),
+                },
+                tokens[3],
+            ),
+            5 if tokens[2].bytes().all(|b| b.is_ascii_digit()) => (
+                StartLine::Response {
+                    status: tokens[3]
+                        .parse()
+                        .map_err(|_| anyhow!("invalid MRCP status: {}", tokens[3]))?,
+                    state: RequestState::parse(tokens[4])?,
+                },
+                tokens[2],
+            ),
+            5 => (
+                StartLine::Event {
+                    name: tokens[2].to_string(),
+                    state: RequestState::parse(tokens[4])?,
+                },
+                tokens[3],
+            ),
+            _ => return Err(anyhow!("invalid MRCP start line: {}", start_line)),
+        };
+        let request_id = request_id
+            .parse()
+            .map_err(|_| anyhow!("invalid MRCP request id: {}", request_id))?;
+
+        let message = &buf[line_end + 2..length];
+        let header_end = message
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .map(|pos| pos + 4)
+            .or_else(|| message.starts_with(b"\r\n").then_some(2))
+            .ok_or_else(|| anyhow!("MRCP headers are not terminated"))?;
+        let mut headers = Vec::new();
+        for line in std::str::from_utf8(&message[..header_end])?.split("\r\n") {
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| anyhow!("invalid MRCP header: {}", line))?;
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+        Ok(Some((
+            Self {
+                start_line,
+                request_id,
+                headers,
+                body: message[header_end..].to_vec(),
+            },
+            length,
+        )))
+    }
+}
@@ -0,0 +1,554 @@
+//! MRCPv2 client (RFC 6787) for speech servers.
+//!
+//! A session is set up with a SIP INVITE whose SDP offers an MRCPv2 control
+//! channel on TCP for one resource and an RTP audio stream. The answer gives
+//! the channel identifier and the addresses of both, requests then flow on
+//! the control connection and audio on RTP.
+use crate::media::codecs::CodecType;
+use anyhow::{Result, anyhow};
+use bytes::BytesMut;
+use rsipstack::{
+    dialog::{
+        authenticate::Credential, client_dialog::ClientInviteDialog, dialog_layer::DialogLayer,
+        invitation::InviteOption,
+    },
+    transaction::EndpointBuilder,
+    transport::{SipAddr, TransportLayer, udp::UdpConnection},
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{
+        TcpStream, UdpSocket,
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+    },
+    select,
+    time::timeout,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+pub mod message;
+#[cfg(test)]
+mod tests;
+
+pub use message::{MrcpMessage, RequestState, StartLine};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MrcpResource {
+    SpeechRecog,
+    SpeechSynth,
+}
+
+impl MrcpResource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MrcpResource::SpeechRecog => "speechrecog",
+            MrcpResource::SpeechSynth => "speechsynth",
+        }
+    }
+
+    /// Direction of the audio from our side
+    fn direction(&self) -> &'static str {
+        match self {
+            MrcpResource::SpeechRecog => "sendonly",
+            MrcpResource::SpeechSynth => "recvonly",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MrcpSessionOption {
+    /// SIP uri of the speech server, e.g. sip:mresources@10.0.0.5:8060
+    pub server: String,
+    /// Local address to advertise, detected from the route to the server
+    /// when not set
+    pub local_ip: Option<IpAddr>,
+    /// Answers the server's digest challenges
+    pub credential: Option<Credential>,
+    pub timeout: Duration,
+}
+
+impl MrcpSessionOption {
+    pub fn new(server: String) -> Self {
+        Self {
+            server,
+            local_ip: None,
+            credential: None,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    pub fn with_local_ip(mut self, local_ip: IpAddr) -> Self {
+        self.local_ip = Some(local_ip);
+        self
+    }
+
+    pub fn with_credential(mut self, credential: Credential) -> Self {
+        self.credential = Some(credential);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// host:port of the SIP uri, 5060 by default
+    fn server_addr(&self) -> String {
+        let server = self.server.trim_start_matches("sips:");
+        let server = server.trim_start_matches("sip:");
+        let host = server.rsplit('@').next().unwrap_or(server);
+        let host = host.split([';', '?']).next().unwrap_or(host);
+        if host.starts_with('[') {
+            match host.rfind("]:") {
+                Some(_) => host.to_string(),
+                None => format!("{}:5060", host),
+            }
+        } else if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{}:5060", host)
+        }
+    }
+}
+
+/// Media and control addresses from the server's SDP answer
+#[derive(Debug, Clone, PartialEq)]
+pub struct MrcpAnswer {
+    pub channel: String,
+    pub control_addr: SocketAddr,
+    pub rtp_addr: SocketAddr,
+    pub codec: CodecType,
+}
+
+impl MrcpAnswer {
+    /// The SDP is scanned line by line, generic SDP parsers refuse the
+    /// `TCP/MRCPv2` transport of the control channel
+    pub fn parse(sdp: &[u8]) -> Result<Self> {
+        struct Media {
+            kind: String,
+            port: u16,
+            formats: Vec<String>,
+            ip: Option<String>,
+            channel: Option<String>,
+        }
+        let mut session_ip = None;
+        let mut medias: Vec<Media> = Vec::new();
+        for line in std::str::from_utf8(sdp)?.lines() {
+            let Some((kind, value)) = line.trim().split_once('=') else {
+                continue;
+            };
+            match kind {
+                "m" => {
+                    let mut fields = value.split_ascii_whitespace();
+                    let kind = fields.next().unwrap_or_default().to_string();
+                    let port = fields.next().unwrap_or_default();
+                    let port = port
+                        .split('/')
+                        .next()
+                        .and_then(|p| p.parse().ok())
+                        .ok_or_else(|| anyhow!("invalid media port: {}", port))?;
+                    medias.push(Media {
+                        kind,
+                        port,
+                        formats: fields.skip(1).map(|f| f.to_string()).collect(),
+                        ip: None,
+                        channel: None,
+                    });
+                }
+                "c" => {
+                    let ip = value.split_ascii_whitespace().nth(2).map(|a| a.to_string());
+                    match medias.last_mut() {
+                        Some(media) => media.ip = ip,
+                        None => session_ip = ip,
+                    }
+                }
+                "a" => {
+                    if let Some(channel) = value.strip_prefix("channel:")
+                        && let Some(media) = medias.last_mut()
+                    {
+                        media.channel = Some(channel.trim().to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut control = None;
+        let mut audio = None;
+        for media in &medias {
+            let ip = media
+                .ip
+                .clone()
+                .or_else(|| session_ip.clone())
+                .ok_or_else(|| anyhow!("no connection address in MRCP answer"))?;
+            let ip: IpAddr = ip
+                .parse()
+                .map_err(|_| anyhow!("invalid connection address: {}", ip))?;
+            let addr = SocketAddr::new(ip, media.port);
+            match media.kind.as_str() {
+                "application" if control.is_none() => {
+                    let channel = media
+                        .channel
+                        .clone()
+                        .ok_or_else(|| anyhow!("no channel in MRCP answer"))?;
+                    control = Some((channel, addr));
+                }
+                "audio" if audio.is_none() => {
+                    let codec = media
+                        .formats
+                        .iter()
+                        .find_map(|pt| CodecType::try_from(pt).ok().filter(|c| c.is_audio()))
+                        .ok_or_else(|| anyhow!("no supported codec in MRCP answer"))?;
+                    audio = Some((addr, codec));
+                }
+                _ => {}
+            }
+        }
+        let (channel, control_addr) = control.ok_or_else(|| anyhow!("MRCP channel rejected"))?;
+        let (rtp_addr, codec) = audio.ok_or_else(|| anyhow!("MRCP audio rejected"))?;
+        if control_addr.port() == 0 {
+            return Err(anyhow!("MRCP channel rejected"));
+        }
+        Ok(Self {
+            channel,
+            control_addr,
+            rtp_addr,
+            codec,
+        })
+    }
+}
+
+/// SIP leg of the session, on an endpoint of its own. A leg dropped
+/// without `bye`, e.g. on a failed setup, hangs up in the background.
+struct SipLeg {
+    dialog_layer: Arc<DialogLayer>,
+    dialog: ClientInviteDialog,
+    token: CancellationToken,
+    closed: bool,
+}
+
+impl SipLeg {
+    async fn bye(&mut self) -> Result<()> {
+        self.closed = true;
+        let result = self.dialog.bye().await;
+        self.dialog_layer.remove_dialog(&self.dialog.id());
+        self.token.cancel();
+        Ok(result?)
+    }
+}
+
+impl Drop for SipLeg {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            self.token.cancel();
+            return;
+        };
+        let dialog = self.dialog.clone();
+        let dialog_layer = self.dialog_layer.clone();
+        let token = self.token.clone();
+        handle.spawn(async move {
+            if let Err(e) = dialog.bye().await {
+                warn!(id = %dialog.id(), "failed to send BYE: {}", e);
+            }
+            dialog_layer.remove_dialog(&dialog.id());
+            token.cancel();
+        });
+    }
+}
+
+/// UDP endpoint of one session, its requests are handed to the dialog
+async fn start_endpoint(
+    local_ip: IpAddr,
+    token: CancellationToken,
+) -> Result<(Arc<DialogLayer>, SocketAddr)> {
+    let transport_layer = TransportLayer::new(token.clone());
+    let connection =
+        UdpConnection::create_connection(SocketAddr::new(local_ip, 0), None, Some(token.clone()))
+            .await?;
+    let local_addr = connection.get_addr().get_socketaddr()?;
+    transport_layer.add_transport(connection.into());
+    let endpoint = EndpointBuilder::new()
+        .with_user_agent(&crate::version::get_useragent())
+        .with_cancel_token(token.clone())
+        .with_transport_layer(transport_layer)
+        .build();
+    let mut incoming = endpoint.incoming_transactions()?;
+    let dialog_layer = Arc::new(DialogLayer::new(endpoint.inner.clone()));
+    let dialogs = dialog_layer.clone();
+    tokio::spawn(async move {
+        let serve_requests = async {
+            while let Some(mut tx) = incoming.recv().await {
+                match dialogs.match_dialog(&tx.original) {
+                    Some(mut dialog) => {
+                        tokio::spawn(async move {
+                            if let Err(e) = dialog.handle(&mut tx).await {
+                                warn!("MRCP session failed to handle request: {}", e);
+                            }
+                        });
+                    }
+                    None => {
+                        tx.reply(rsip::StatusCode::CallTransactionDoesNotExist)
+                            .await
+                            .ok();
+                    }
+                }
+            }
+        };
+        select! {
+            _ = endpoint.serve() => {}
+            _ = serve_requests => {}
+            _ = token.cancelled() => {}
+        }
+    });
+    Ok((dialog_layer, local_addr))
+}
+
+pub struct MrcpSession {
+    resource: MrcpResource,
+    answer: MrcpAnswer,
+    rtp_socket: Arc<UdpSocket>,
+    reader: OwnedReadHalf,
+    writer: OwnedWriteHalf,
+    buffer: BytesMut,
+    next_request_id: u32,
+    sip: SipLeg,
+}
+
+impl MrcpSession {
+    pub async fn connect(option: &MrcpSessionOption, resource: MrcpResource) -> Result<Self> {
+        let server_addr = tokio::net::lookup_host(option.server_addr())
+            .await?
+            .next()
+            .ok_or_else(|| anyhow!("invalid MRCP server: {}", option.server))?;
+        let local_ip = match option.local_ip {
+            Some(ip) => ip,
+            None => {
+                let probe = UdpSocket::bind(SocketAddr::new(
+                    if server_addr.is_ipv4() {
+                        IpAddr::from([0, 0, 0, 0])
+                    } else {
+                        IpAddr::from([0u16; 8])
+                    },
+                    0,
+                ))
+                .await?;
+                probe.connect(server_addr).await?;
+                probe.local_addr()?.ip()
+            }
+        };
+        let rtp_socket = Arc::new(UdpSocket::bind(SocketAddr::new(local_ip, 0)).await?);
+        let token = CancellationToken::new();
+        let (dialog_layer, local_addr) = start_endpoint(local_ip, token.clone()).await?;
+        let ip_version = if local_ip.is_ipv4() { "IP4" } else { "IP6" };
+        let session_version = rand::random::<u32>();
+        let sdp = format!(
+            "v=0\r\n\
+             o=rustpbx {session_version} {session_version} IN {ip_version} {local_ip}\r\n\
+             s=-\r\n\
+             c=IN {ip_version} {local_ip}\r\n\
+             t=0 0\r\n\
+             m=application 9 TCP/MRCPv2 1\r\n\
+             a=setup:active\r\n\
+             a=connection:new\r\n\
+             a=resource:{resource}\r\n\
+             a=cmid:1\r\n\
+             m=audio {rtp_port} RTP/AVP 0 8 101\r\n\
+             a=rtpmap:0 PCMU/8000\r\n\
+             a=rtpmap:8 PCMA/8000\r\n\
+             a=rtpmap:101 telephone-event/8000\r\n\
+             a=fmtp:101 0-15\r\n\
+             a={direction}\r\n\
+             a=mid:1\r\n",
+            resource = resource.as_str(),
+            rtp_port = rtp_socket.local_addr()?.port(),
+            direction = resource.direction(),
+        );
+        let local_uri: rsip::Uri = format!("sip:rustpbx@{}", local_addr).try_into()?;
+        let invite = InviteOption {
+            caller: local_uri.clone(),
+            callee: option
+                .server
+                .as_str()
+                .try_into()
+                .map_err(|e| anyhow!("invalid MRCP server {}: {}", option.server, e))?,
+            destination: Some(SipAddr {
+                r#type: Some(rsip::Transport::Udp),
+                addr: server_addr.into(),
+            }),
+            content_type: Some("application/sdp".to_string()),
+            offer: Some(sdp.into_bytes()),
+            contact: local_uri,
+            credential: option.credential.clone(),
+            headers: None,
+        };
+
+        info!(server = %server_addr, resource = resource.as_str(), "MRCP session connecting");
+        // provisional responses, retransmissions and auth challenges are
+        // left to the dialog layer
+        let (state_sender, _states) = tokio::sync::mpsc::unbounded_channel();
+        let invited = timeout(option.timeout, dialog_layer.do_invite(invite, state_sender)).await;
+        let (dialog, response) = match invited {
+            Ok(Ok(invited)) => invited,
+            Ok(Err(rsipstack::Error::DialogError(_, _, code))) => {
+                token.cancel();
+                return Err(anyhow!("MRCP session rejected: {}", code));
+            }
+            Ok(Err(e)) => {
+                token.cancel();
+                return Err(e.into());
+            }
+            Err(_) => {
+                token.cancel();
+                return Err(anyhow!("MRCP session setup timed out"));
+            }
+        };
+        // from here on the leg hangs up when dropped
+        let sip = SipLeg {
+            dialog_layer,
+            dialog,
+            token,
+            closed: false,
+        };
+        let response = response.ok_or_else(|| anyhow!("no answer to the MRCP INVITE"))?;
+        if response.status_code.code() >= 300 {
+            return Err(anyhow!("MRCP session rejected: {}", response.status_code));
+        }
+        let answer = MrcpAnswer::parse(&response.body)?;
+        let stream = timeout(option.timeout, TcpStream::connect(answer.control_addr))
+            .await
+            .map_err(|_| anyhow!("MRCP control connection timed out"))??;
+        stream.set_nodelay(true).ok();
+        let (reader, writer) = stream.into_split();
+        info!(
+            channel = answer.channel,
+            control = %answer.control_addr,
+            rtp = %answer.rtp_addr,
+            codec = ?answer.codec,
+            "MRCP session established"
+        );
+        Ok(Self {
+            resource,
+            answer,
+            rtp_socket,
+            reader,
+            writer,
+            buffer: BytesMut::with_capacity(4096),
+            next_request_id: 1,
+            sip,
+        })
+    }
+
+    pub fn resource(&self) -> MrcpResource {
+        self.resource
+    }
+
+    pub fn channel(&self) -> &str {
+        &self.answer.channel
+    }
+
+    pub fn codec(&self) -> CodecType {
+        self.answer.codec
+    }
+
+    /// Socket of the RTP stream, connected to nothing: send to `rtp_addr`
+    pub fn rtp_socket(&self) -> Arc<UdpSocket> {
+        self.rtp_socket.clone()
+    }
+
+    pub fn rtp_addr(&self) -> SocketAddr {
+        self.answer.rtp_addr
+    }
+
+    /// Send a request on the channel, returns its request id
+    pub async fn send(&mut self, request: MrcpMessage) -> Result<u32> {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        let mut request = request.with_header("Channel-Identifier", &self.answer.channel);
+        request.request_id = request_id;
+        debug!(
+            channel = self.answer.channel,
+            method = request.method(),
+            request_id,
+            "MRCP request"
+        );
+        self.writer.write_all(&request.encode()).await?;
+        Ok(request_id)
+    }
+
+    /// Next response or event from the server, cancel safe
+    pub async fn recv(&mut self) -> Result<MrcpMessage> {
+        loop {
+            if let Some((message, length)) = MrcpMessage::parse(&self.buffer)? {
+                let _ = self.buffer.split_to(length);
+                return Ok(message);
+            }
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
+                return Err(anyhow!("MRCP control connection closed"));
+            }
+        }
+    }
+
+    /// Send a request and wait for its response, events received meanwhile
+    /// are dropped
+    pub async fn request(&mut self, request: MrcpMessage) -> Result<MrcpMessage> {
+        let request_id = self.send(request).await?;
+        loop {
+            let message = self.recv().await?;
+            if message.request_id == request_id && message.status().is_some() {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Tear the session down with a BYE
+    pub async fn close(mut self) {
+        if let Err(e) = self.sip.bye().await {
+            warn!(channel = self.answer.channel, "failed to send BYE: {}", e);
+        }
+    }
+}
+
+/// Text of the first interpretation of an NLSML recognition result
+/// (RFC 6787 section 9.6.3.1), the input is preferred over the instance
+pub fn parse_nlsml(body: &str) -> Option<String> {
+    let tag_content = |text: &str, tag: &str| -> Option<String> {
+        let start = text.find(&format!("<{}", tag))?;
+        let start = start + text[start..].find('>')? + 1;
+        if text[..start].ends_with("/>") {
+            return None;
+        }
+        let end = start + text[start..].find(&format!("</{}", tag))?;
+        Some(text[start..end].to_string())
+    };
+    let interpretation = tag_content(body, "interpretation").unwrap_or_else(|| body.to_string());
+    let text = tag_content(&interpretation, "input")
+        .or_else(|| tag_content(&interpretation, "instance"))?;
+    // strip nested markup, e.g. <input mode="speech"><noinput/></input>
+    let mut plain = String::new();
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => plain.push(c),
+            _ => {}
+        }
+    }
+    let plain = plain
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    let plain = plain.trim();
+    (!plain.is_empty()).then(|| plain.to_string())
+}
@@ -0,0 +1,294 @@
+use super::*;
+use crate::event::{SessionEvent, create_event_sender};
+use crate::transcription::{
+    MrcpAsrClientBuilder, TranscriptionClient, TranscriptionOption, TranscriptionType,
+};
+use tokio::net::TcpListener;
+
+#[test]
+fn test_message_roundtrip() {
+    let request = MrcpMessage::request("SPEAK", 543257)
+        .with_header("Channel-Identifier", "32AECB23433802@speechsynth")
+        .with_header("Voice-gender", "neutral")
+        .with_body("text/plain", b"Hello world".to_vec());
+    let data = request.encode();
+    let text = String::from_utf8(data.clone()).unwrap();
+    let length: usize = text.split(' ').nth(1).unwrap().parse().unwrap();
+    assert_eq!(length, data.len());
+    assert!(text.contains("Content-Length: 11\r\n"));
+
+    let (parsed, consumed) = MrcpMessage::parse(&data).unwrap().unwrap();
+    assert_eq!(consumed, data.len());
+    assert_eq!(parsed.method(), Some("SPEAK"));
+    assert_eq!(parsed.request_id, 543257);
+    assert_eq!(parsed.header("voice-gender"), Some("neutral"));
+    assert_eq!(parsed.body, b"Hello world");
+
+    // incomplete and pipelined messages
+    assert!(
+        MrcpMessage::parse(&data[..data.len() - 1])
+            .unwrap()
+            .is_none()
+    );
+    let response = MrcpMessage::response(543257, 200, RequestState::InProgress).encode();
+    let event = MrcpMessage::event("SPEAK-COMPLETE", 543257, RequestState::Complete)
+        .with_header("Completion-Cause", "000 normal")
+        .encode();
+    let mut stream = response.clone();
+    stream.extend_from_slice(&event);
+    let (first, consumed) = MrcpMessage::parse(&stream).unwrap().unwrap();
+    assert_eq!(consumed, response.len());
+    assert_eq!(first.status(), Some(200));
+    assert_eq!(
+        first.start_line,
+        StartLine::Response {
+            status: 200,
+            state: RequestState::InProgress
+        }
+    );
+    let (second, _) = MrcpMessage::parse(&stream[consumed..]).unwrap().unwrap();
+    assert_eq!(second.event_name(), Some("SPEAK-COMPLETE"));
+    assert_eq!(second.header("Completion-Cause"), Some("000 normal"));
+
+    assert!(MrcpMessage::parse(b"RTSP/1.0 200 OK\r\n\r\n").is_err());
+    assert!(MrcpMessage::parse(b"MRCP/2.0 10 SPEAK 1\r\n\r\n").is_err());
+}
+
+#[test]
+fn test_parse_answer() {
+    let sdp = "v=0\r\n\
+        o=- 1 1 IN IP4 10.0.0.5\r\n\
+        s=-\r\n\
+        c=IN IP4 10.0.0.5\r\n\
+        t=0 0\r\n\
+        m=application 1544 TCP/MRCPv2 1\r\n\
+        a=setup:passive\r\n\
+        a=connection:new\r\n\
+        a=channel:32AECB234338@speechrecog\r\n\
+        a=cmid:1\r\n\
+        m=audio 5004 RTP/AVP 8 101\r\n\
+        a=rtpmap:8 PCMA/8000\r\n\
+        a=rtpmap:101 telephone-event/8000\r\n\
+        a=recvonly\r\n\
+        a=mid:1\r\n";
+    let answer = MrcpAnswer::parse(sdp.as_bytes()).unwrap();
+    assert_eq!(answer.channel, "32AECB234338@speechrecog");
+    assert_eq!(answer.control_addr, "10.0.0.5:1544".parse().unwrap());
+    assert_eq!(answer.rtp_addr, "10.0.0.5:5004".parse().unwrap());
+    assert_eq!(answer.codec, CodecType::PCMA);
+
+    let rejected = sdp.replace("m=application 1544", "m=application 0");
+    assert!(MrcpAnswer::parse(rejected.as_bytes()).is_err());
+}
+
+#[test]
+fn test_parse_nlsml() {
+    let body = r#"<?xml version="1.0"?>
+<result>
+  <interpretation grammar="session:request1@form-level.store" confidence="0.6">
+    <instance><ticket><to>SFO</to></ticket></instance>
+    <input mode="speech">fly to san francisco &amp; back</input>
+  </interpretation>
+</result>"#;
+    assert_eq!(
+        parse_nlsml(body),
+        Some("fly to san francisco & back".to_string())
+    );
+    let body = r#"<result><interpretation><instance>yes</instance></interpretation></result>"#;
+    assert_eq!(parse_nlsml(body), Some("yes".to_string()));
+    let body = r#"<result><interpretation><input><noinput/></input></interpretation></result>"#;
+    assert_eq!(parse_nlsml(body), None);
+}
+
+#[test]
+fn test_server_addr() {
+    let addr = |server: &str| MrcpSessionOption::new(server.to_string()).server_addr();
+    assert_eq!(addr("sip:mresources@10.0.0.5:8060"), "10.0.0.5:8060");
+    assert_eq!(addr("sip:10.0.0.5;transport=udp"), "10.0.0.5:5060");
+    assert_eq!(addr("sip:mrcp@[::1]:8060"), "[::1]:8060");
+}
+
+/// Answers the INVITE received on `sip` with `sdp` and waits for its ACK,
+/// returns the client's address and the INVITE
+async fn answer_invite(sip: &UdpSocket, sdp: &str) -> (SocketAddr, String) {
+    let sip_addr = sip.local_addr().unwrap();
+    let mut buf = vec![0u8; 4096];
+    let (n, client) = sip.recv_from(&mut buf).await.unwrap();
+    let invite = String::from_utf8_lossy(&buf[..n]).to_string();
+    assert!(invite.starts_with("INVITE "));
+    let header = |name: &str| {
+        invite
+            .lines()
+            .find(|l| l.starts_with(name))
+            .unwrap()
+            .to_string()
+    };
+    let ok = format!(
+        "SIP/2.0 200 OK\r\n{}\r\n{}\r\n{};tag=server\r\n{}\r\n{}\r\n\
+         Contact: <sip:mrcp@{}>\r\nContent-Type: application/sdp\r\n\
+         Content-Length: {}\r\n\r\n{}",
+        header("Via:"),
+        header("From:"),
+        header("To:"),
+        header("Call-ID:"),
+        header("CSeq:"),
+        sip_addr,
+        sdp.len(),
+        sdp
+    );
+    sip.send_to(ok.as_bytes(), client).await.unwrap();
+    loop {
+        let (n, _) = sip.recv_from(&mut buf).await.unwrap();
+        // INVITE retransmissions until the 200 arrived
+        if buf[..n].starts_with(b"ACK sip:mrcp@") {
+            break;
+        }
+        assert!(buf[..n].starts_with(b"INVITE "));
+    }
+    (client, invite)
+}
+
+/// Answers one INVITE for a speechrecog channel, reports the RECOGNIZE it
+/// receives once audio arrives, and recognizes `text`
+async fn mock_speech_server(text: &'static str) -> SocketAddr {
+    let sip = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let sip_addr = sip.local_addr().unwrap();
+    let control = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let rtp = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let control_port = control.local_addr().unwrap().port();
+    let rtp_port = rtp.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 4096];
+        let sdp = format!(
+            "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+             m=application {control_port} TCP/MRCPv2 1\r\na=setup:passive\r\n\
+             a=connection:new\r\na=channel:1@speechrecog\r\na=cmid:1\r\n\
+             m=audio {rtp_port} RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\na=recvonly\r\na=mid:1\r\n"
+        );
+        let (_, invite) = answer_invite(&sip, &sdp).await;
+        assert!(invite.contains("a=resource:speechrecog"));
+
+        let (stream, _) = control.accept().await.unwrap();
+        let (mut reader, mut writer) = stream.into_split();
+        let mut pending = BytesMut::new();
+        let request = loop {
+            if let Some((message, _)) = MrcpMessage::parse(&pending).unwrap() {
+                break message;
+            }
+            reader.read_buf(&mut pending).await.unwrap();
+        };
+        assert_eq!(request.method(), Some("RECOGNIZE"));
+        assert_eq!(request.header("Channel-Identifier"), Some("1@speechrecog"));
+        assert_eq!(request.header("Speech-Language"), Some("en-US"));
+        assert_eq!(request.body, b"builtin:grammar/digits");
+        let response = MrcpMessage::response(request.request_id, 200, RequestState::InProgress)
+            .with_header("Channel-Identifier", "1@speechrecog");
+        writer.write_all(&response.encode()).await.unwrap();
+
+        // PCMU audio from the client
+        let (n, _) = rtp.recv_from(&mut buf).await.unwrap();
+        assert!(n > 12 && buf[1] & 0x7f == 0);
+
+        let body = format!(
+            "<?xml version=\"1.0\"?><result><interpretation><input>{}</input>\
+             </interpretation></result>",
+            text
+        );
+        let complete = MrcpMessage::event(
+            "RECOGNITION-COMPLETE",
+            request.request_id,
+            RequestState::Complete,
+        )
+        .with_header("Channel-Identifier", "1@speechrecog")
+        .with_header("Completion-Cause", "000 success")
+        .with_body("application/nlsml+xml", body.into_bytes());
+        writer.write_all(&complete.encode()).await.unwrap();
+        // keep the connection for the next RECOGNIZE
+        let _ = reader.read_buf(&mut pending).await;
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    });
+    sip_addr
+}
+
+#[tokio::test]
+async fn test_mrcp_asr() {
+    let server = mock_speech_server("one two three").await;
+    let event_sender = create_event_sender();
+    let mut events = event_sender.subscribe();
+    let option = TranscriptionOption {
+        provider: Some(TranscriptionType::Mrcp),
+        endpoint: Some(format!("sip:mrcp@{}", server)),
+        language: Some("en-US".to_string()),
+        samplerate: Some(8000),
+        extra: Some(
+            [("grammar".to_string(), "builtin:grammar/digits".to_string())]
+                .into_iter()
+                .collect(),
+        ),
+        ..Default::default()
+    };
+    let token = tokio_util::sync::CancellationToken::new();
+    let client = MrcpAsrClientBuilder::new(option, event_sender)
+        .with_track_id("caller".to_string())
+        .with_cancel_token(token.clone())
+        .build()
+        .await
+        .unwrap();
+
+    let text = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            client.send_audio(&[100; 160]).unwrap();
+            tokio::select! {
+                event = events.recv() => match event.unwrap() {
+                    SessionEvent::AsrFinal { text, track_id, index, .. } => {
+                        assert_eq!(track_id, "caller");
+                        assert_eq!(index, 0);
+                        break text;
+                    }
+                    SessionEvent::Error { error, .. } => panic!("{}", error),
+                    _ => {}
+                },
+                _ = tokio::time::sleep(Duration::from_millis(20)) => {}
+            }
+        }
+    })
+    .await
+    .expect("recognition result");
+    assert_eq!(text, "one two three");
+    token.cancel();
+}
+
+#[tokio::test]
+async fn test_mrcp_bye_on_failed_control_connection() {
+    let sip = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let server = sip.local_addr().unwrap();
+    // a port nothing listens on
+    let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let control_port = closed.local_addr().unwrap().port();
+    drop(closed);
+
+    let server_task = tokio::spawn(async move {
+        let sdp = format!(
+            "v=0\r\no=- 1 1 IN IP4 127.0.0.1\r\ns=-\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+             m=application {control_port} TCP/MRCPv2 1\r\na=channel:1@speechsynth\r\n\
+             m=audio 5004 RTP/AVP 0\r\na=sendonly\r\n"
+        );
+        answer_invite(&sip, &sdp).await;
+        let mut buf = vec![0u8; 4096];
+        let (n, _) = sip.recv_from(&mut buf).await.unwrap();
+        String::from_utf8_lossy(&buf[..n]).to_string()
+    });
+
+    let option = MrcpSessionOption::new(format!("sip:mrcp@{}", server));
+    assert!(
+        MrcpSession::connect(&option, MrcpResource::SpeechSynth)
+            .await
+            .is_err()
+    );
+    let bye = tokio::time::timeout(Duration::from_secs(5), server_task)
+        .await
+        .expect("BYE after the failed setup")
+        .unwrap();
+    assert!(bye.starts_with("BYE sip:mrcp@"), "{}", bye);
+}
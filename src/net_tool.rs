@@ -1,12 +1,17 @@
 use anyhow::Result;
 use get_if_addrs::get_if_addrs;
-use rsipstack::transport::{udp::UdpConnection, SipAddr};
+use rsipstack::transport::{
+    SipAddr, SipConnection,
+    udp::{UdpConnection, UdpInner},
+};
 use std::{
     io::BufReader,
     net::{IpAddr, SocketAddr},
     time::Duration,
 };
-use tracing::info;
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 use webrtc::stun::{
     agent::TransactionId,
     message::{Getter, Message, BINDING_REQUEST},
@@ -150,6 +155,87 @@ pub fn sdp_contains_private_ip(sdp: &str) -> Result<bool> {
     Ok(addresses.iter().any(is_private_ip))
 }
 
+/// DSCP code point by name, `ef`, `afXY`, `csN` or `be`, or as a number
+/// from 0 to 63
+pub fn parse_dscp(value: &str) -> Result<u8> {
+    let value = value.trim().to_lowercase();
+    let dscp = match value.as_str() {
+        "ef" => 46,
+        "be" | "default" => 0,
+        "va" | "voice-admit" => 44,
+        _ => {
+            if let Some(af) = value.strip_prefix("af")
+                && let [class @ b'1'..=b'4', drop @ b'1'..=b'3'] = af.as_bytes()
+            {
+                (class - b'0') * 8 + (drop - b'0') * 2
+            } else if let Some(cs) = value.strip_prefix("cs")
+                && let [class @ b'0'..=b'7'] = cs.as_bytes()
+            {
+                (class - b'0') * 8
+            } else {
+                value
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|dscp| *dscp < 64)
+                    .ok_or_else(|| anyhow::anyhow!("invalid DSCP: {}", value))?
+            }
+        }
+    };
+    Ok(dscp)
+}
+
+/// Marks the packets sent on the socket with `dscp`, the upper 6 bits of the
+/// IPv4 ToS or IPv6 traffic class
+pub fn set_dscp(socket: &UdpSocket, dscp: u8) -> Result<()> {
+    let tos = (dscp as u32) << 2;
+    match socket.local_addr()? {
+        #[cfg(not(any(
+            target_os = "fuchsia",
+            target_os = "redox",
+            target_os = "solaris",
+            target_os = "illumos",
+            target_os = "haiku",
+            target_os = "wasi",
+        )))]
+        SocketAddr::V4(_) => Ok(socket.set_tos_v4(tos)?),
+        #[cfg(any(
+            target_os = "android",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "fuchsia",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "netbsd",
+            target_os = "openbsd",
+        ))]
+        SocketAddr::V6(_) => Ok(socket.set_tclass_v6(tos)?),
+        #[allow(unreachable_patterns)]
+        _ => Err(anyhow::anyhow!("DSCP is not supported on this platform")),
+    }
+}
+
+/// Binds a UDP connection, marking its packets with `dscp` when set. A
+/// platform that cannot mark packets only gets a warning
+pub async fn create_udp_connection(
+    local: SocketAddr,
+    external: Option<SocketAddr>,
+    dscp: Option<u8>,
+    cancel_token: Option<CancellationToken>,
+) -> Result<UdpConnection> {
+    let Some(dscp) = dscp else {
+        return Ok(UdpConnection::create_connection(local, external, cancel_token).await?);
+    };
+    let conn = UdpSocket::bind(local).await?;
+    if let Err(e) = set_dscp(&conn, dscp) {
+        warn!(%local, dscp, "failed to set DSCP: {}", e);
+    }
+    let addr = SipAddr {
+        r#type: Some(rsip::transport::Transport::Udp),
+        addr: SipConnection::resolve_bind_address(conn.local_addr()?).into(),
+    };
+    Ok(UdpConnection::attach(UdpInner { conn, addr }, external, cancel_token).await)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -174,6 +260,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_dscp() {
+        assert_eq!(parse_dscp("EF").unwrap(), 46);
+        assert_eq!(parse_dscp("af31").unwrap(), 26);
+        assert_eq!(parse_dscp("af41").unwrap(), 34);
+        assert_eq!(parse_dscp("cs3").unwrap(), 24);
+        assert_eq!(parse_dscp("be").unwrap(), 0);
+        assert_eq!(parse_dscp("18").unwrap(), 18);
+        assert!(parse_dscp("af51").is_err());
+        assert!(parse_dscp("64").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_create_udp_connection_with_dscp() -> Result<()> {
+        let conn = create_udp_connection("127.0.0.1:0".parse()?, None, Some(46), None).await?;
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        set_dscp(&socket, 26)?;
+        #[cfg(target_os = "linux")]
+        assert_eq!(socket.tos_v4()?, 26 << 2);
+        assert!(conn.get_addr().get_socketaddr()?.port() > 0);
+        Ok(())
+    }
+
     #[test]
     fn test_is_private_ip() {
         // Private IPv4 addresses
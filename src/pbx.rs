@@ -0,0 +1,543 @@
+use crate::{
+    app::{self, AppState, AppStateBuilder},
+    callrecord::{CallRecordSender, enrich::CallRecordEnricher},
+    config::{Config, ProxyConfig, UseragentConfig},
+    media::engine::StreamEngine,
+    proxy::{
+        FnCreateProxyModule,
+        auth::AuthBackend,
+        call::{CallRouter, DialplanInspector},
+        locator::Locator,
+        server::SipServer,
+        user::UserBackend,
+    },
+    useragent::invitation::FnCreateInvitationHandler,
+};
+use anyhow::{Result, anyhow};
+use axum::Router;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{net::TcpListener, select};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// Builds a server to embed in another application: what the `rustpbx`
+/// binary runs from its config file, with the config, transports and
+/// handlers given in code
+///
+/// ```no_run
+/// # async fn example() -> anyhow::Result<()> {
+/// let pbx = rustpbx::pbx::RustPbxBuilder::new()
+///     .with_http_addr("127.0.0.1:8080")
+///     .with_sip_udp_port(5060)
+///     .with_rtp_ports(20000, 30000)
+///     .build()
+///     .await?;
+/// let token = pbx.cancel_token();
+/// tokio::spawn(pbx.serve());
+/// // ...
+/// token.cancel();
+/// # Ok(())
+/// # }
+/// ```
+pub struct RustPbxBuilder {
+    config: Config,
+    http: bool,
+    useragent: bool,
+    proxy: bool,
+    routes: Vec<Router>,
+    stream_engine: Option<Arc<StreamEngine>>,
+    callrecord_sender: Option<CallRecordSender>,
+    callrecord_enrichers: Vec<Arc<dyn CallRecordEnricher>>,
+    cancel_token: Option<CancellationToken>,
+    create_invitation_handler: Option<FnCreateInvitationHandler>,
+    proxy_modules: Vec<(String, FnCreateProxyModule)>,
+    user_backend: Option<Box<dyn UserBackend>>,
+    auth_backend: Option<Box<dyn AuthBackend>>,
+    call_router: Option<Box<dyn CallRouter>>,
+    dialplan_inspector: Option<Box<dyn DialplanInspector>>,
+    locator: Option<Box<dyn Locator>>,
+}
+
+impl RustPbxBuilder {
+    /// Starts from the default config: the user agent on, and the proxy
+    /// off until it gets a transport or `with_proxy(true)`
+    pub fn new() -> Self {
+        Self {
+            config: Config::default(),
+            http: true,
+            useragent: true,
+            proxy: true,
+            routes: Vec::new(),
+            stream_engine: None,
+            callrecord_sender: None,
+            callrecord_enrichers: Vec::new(),
+            cancel_token: None,
+            create_invitation_handler: None,
+            proxy_modules: Vec::new(),
+            user_backend: None,
+            auth_backend: None,
+            call_router: None,
+            dialplan_inspector: None,
+            locator: None,
+        }
+    }
+
+    /// Replaces the whole config, the other settings apply on top of it
+    pub fn with_config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_http_addr(mut self, addr: &str) -> Self {
+        self.config.http_addr = addr.to_string();
+        self
+    }
+
+    /// Without the HTTP server the routes are still available from
+    /// `RustPbx::router`, to be served by the application
+    pub fn with_http(mut self, enabled: bool) -> Self {
+        self.http = enabled;
+        self
+    }
+
+    pub fn with_grpc_addr(mut self, addr: Option<&str>) -> Self {
+        self.config.grpc_addr = addr.map(|addr| addr.to_string());
+        self
+    }
+
+    /// Turns the user agent off, or on with the default config when none
+    /// is set
+    pub fn with_useragent(mut self, enabled: bool) -> Self {
+        self.useragent = enabled;
+        if enabled && self.config.ua.is_none() {
+            self.config.ua = Some(UseragentConfig::default());
+        }
+        self
+    }
+
+    pub fn with_ua_addr(mut self, addr: &str, udp_port: u16) -> Self {
+        let ua = self.config.ua.get_or_insert_with(Default::default);
+        ua.addr = addr.to_string();
+        ua.udp_port = udp_port;
+        self
+    }
+
+    /// Turns the proxy off, or on with the default config when none is set
+    pub fn with_proxy(mut self, enabled: bool) -> Self {
+        self.proxy = enabled;
+        if enabled && self.config.proxy.is_none() {
+            self.config.proxy = Some(ProxyConfig::default());
+        }
+        self
+    }
+
+    fn proxy_config(&mut self) -> &mut ProxyConfig {
+        self.config.proxy.get_or_insert_with(Default::default)
+    }
+
+    /// The address the proxy's transports bind to
+    pub fn with_sip_addr(mut self, addr: &str) -> Self {
+        self.proxy_config().addr = addr.to_string();
+        self
+    }
+
+    pub fn with_sip_udp_port(mut self, port: u16) -> Self {
+        self.proxy_config().udp_port = Some(port);
+        self
+    }
+
+    pub fn with_sip_tcp_port(mut self, port: u16) -> Self {
+        self.proxy_config().tcp_port = Some(port);
+        self
+    }
+
+    pub fn with_sip_tls_port(mut self, port: u16) -> Self {
+        self.proxy_config().tls_port = Some(port);
+        self
+    }
+
+    pub fn with_sip_ws_port(mut self, port: u16) -> Self {
+        self.proxy_config().ws_port = Some(port);
+        self
+    }
+
+    pub fn with_rtp_ports(mut self, start: u16, end: u16) -> Self {
+        self.config.rtp_start_port = Some(start);
+        self.config.rtp_end_port = Some(end);
+        self
+    }
+
+    pub fn with_external_ip(mut self, ip: &str) -> Self {
+        self.config.external_ip = Some(ip.to_string());
+        self
+    }
+
+    /// Adds a proxy module, and loads it before the `call` module unless
+    /// `proxy.modules` lists it already
+    pub fn with_proxy_module(mut self, name: &str, create: FnCreateProxyModule) -> Self {
+        self.proxy_modules.push((name.to_string(), create));
+        let modules = self.proxy_config().modules.get_or_insert_with(Vec::new);
+        if !modules.iter().any(|module| module == name) {
+            match modules.iter().position(|module| module == "call") {
+                Some(index) => modules.insert(index, name.to_string()),
+                None => modules.push(name.to_string()),
+            }
+        }
+        self
+    }
+
+    pub fn with_user_backend(mut self, user_backend: Box<dyn UserBackend>) -> Self {
+        self.user_backend = Some(user_backend);
+        self
+    }
+
+    pub fn with_auth_backend(mut self, auth_backend: Box<dyn AuthBackend>) -> Self {
+        self.auth_backend = Some(auth_backend);
+        self
+    }
+
+    pub fn with_call_router(mut self, call_router: Box<dyn CallRouter>) -> Self {
+        self.call_router = Some(call_router);
+        self
+    }
+
+    pub fn with_dialplan_inspector(mut self, inspector: Box<dyn DialplanInspector>) -> Self {
+        self.dialplan_inspector = Some(inspector);
+        self
+    }
+
+    pub fn with_locator(mut self, locator: Box<dyn Locator>) -> Self {
+        self.locator = Some(locator);
+        self
+    }
+
+    /// Handles the calls the user agent receives
+    pub fn with_invitation_handler(mut self, create: FnCreateInvitationHandler) -> Self {
+        self.create_invitation_handler = Some(create);
+        self
+    }
+
+    pub fn with_stream_engine(mut self, stream_engine: Arc<StreamEngine>) -> Self {
+        self.stream_engine = Some(stream_engine);
+        self
+    }
+
+    pub fn with_callrecord_sender(mut self, sender: CallRecordSender) -> Self {
+        self.callrecord_sender = Some(sender);
+        self
+    }
+
+    pub fn with_callrecord_enricher(mut self, enricher: Arc<dyn CallRecordEnricher>) -> Self {
+        self.callrecord_enrichers.push(enricher);
+        self
+    }
+
+    /// Stops the server when cancelled, e.g. with the application
+    pub fn with_cancel_token(mut self, token: CancellationToken) -> Self {
+        self.cancel_token = Some(token);
+        self
+    }
+
+    /// Routes of the application served alongside the server's
+    pub fn with_routes(mut self, routes: Router) -> Self {
+        self.routes.push(routes);
+        self
+    }
+
+    /// Creates the transports and subsystems, nothing is served until
+    /// `RustPbx::serve`
+    pub async fn build(self) -> Result<RustPbx> {
+        let mut config = self.config;
+        if !self.useragent {
+            config.ua = None;
+        }
+        if !self.proxy {
+            config.proxy = None;
+        }
+        let token = self.cancel_token.unwrap_or_default();
+        let mut builder = AppStateBuilder::new().with_cancel_token(token.clone());
+        if let Some(proxy_config) = config.proxy.clone() {
+            let mut proxy_builder = app::default_proxy_builder(Arc::new(proxy_config));
+            for (name, create) in self.proxy_modules {
+                proxy_builder = proxy_builder.register_module(&name, create);
+            }
+            if let Some(user_backend) = self.user_backend {
+                proxy_builder = proxy_builder.with_user_backend(user_backend);
+            }
+            if let Some(auth_backend) = self.auth_backend {
+                proxy_builder = proxy_builder.with_auth_backend(auth_backend);
+            }
+            if let Some(call_router) = self.call_router {
+                proxy_builder = proxy_builder.with_call_router(call_router);
+            }
+            if let Some(inspector) = self.dialplan_inspector {
+                proxy_builder = proxy_builder.with_dialplan_inspector(inspector);
+            }
+            if let Some(locator) = self.locator {
+                proxy_builder = proxy_builder.with_locator(locator);
+            }
+            builder = builder.with_proxy_builder(proxy_builder);
+        }
+        if let Some(create) = self.create_invitation_handler {
+            builder = builder.with_create_invitation_handler(create);
+        }
+        if let Some(stream_engine) = self.stream_engine {
+            builder = builder.with_stream_engine(stream_engine);
+        }
+        if let Some(sender) = self.callrecord_sender {
+            builder = builder.with_callrecord_sender(sender);
+        }
+        for enricher in self.callrecord_enrichers {
+            builder = builder.with_callrecord_enricher(enricher);
+        }
+        let proxy = config.proxy.is_some();
+        let (state, sip_server) = builder.with_config(config).build().await?;
+        if proxy && sip_server.is_none() {
+            token.cancel();
+            return Err(anyhow!("failed to start the proxy"));
+        }
+        let mut pbx = RustPbx::new(state, sip_server).with_http(self.http);
+        pbx.routes = self.routes;
+        Ok(pbx)
+    }
+}
+
+impl Default for RustPbxBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A built server, running once `serve` is awaited
+pub struct RustPbx {
+    pub state: AppState,
+    pub sip_server: Option<SipServer>,
+    http: bool,
+    routes: Vec<Router>,
+}
+
+impl RustPbx {
+    pub fn new(state: AppState, sip_server: Option<SipServer>) -> Self {
+        Self {
+            state,
+            sip_server,
+            http: true,
+            routes: Vec::new(),
+        }
+    }
+
+    fn with_http(mut self, http: bool) -> Self {
+        self.http = http;
+        self
+    }
+
+    pub fn cancel_token(&self) -> CancellationToken {
+        self.state.token.clone()
+    }
+
+    /// Makes `serve` return
+    pub fn stop(&self) {
+        self.state.token.cancel();
+    }
+
+    /// The HTTP routes of the server and those added to the builder
+    pub fn router(&self) -> Router {
+        self.routes.iter().cloned().fold(
+            app::router(self.state.clone(), self.sip_server.as_ref()),
+            |router, routes| router.merge(routes),
+        )
+    }
+
+    /// Serves SIP, HTTP and gRPC until the cancel token is cancelled
+    pub async fn serve(self) -> Result<()> {
+        let state = self.state.clone();
+        let token = state.token.clone();
+        let listener = match self.http {
+            true => {
+                let addr: SocketAddr = state.config.http_addr.parse()?;
+                match TcpListener::bind(addr).await {
+                    Ok(l) => Some(l),
+                    Err(e) => {
+                        tracing::error!("Failed to bind to {}: {}", addr, e);
+                        return Err(anyhow!("Failed to bind to {}: {}", addr, e));
+                    }
+                }
+            }
+            false => None,
+        };
+        let router = self.router();
+
+        if let Some(sip_server) = self.sip_server {
+            tokio::spawn(async move {
+                info!("Proxy server started");
+                match sip_server.serve().await {
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("Proxy server error: {}", e);
+                    }
+                }
+            });
+        }
+
+        if let Some(grpc_addr) = &state.config.grpc_addr {
+            #[cfg(feature = "grpc")]
+            {
+                let grpc_addr: SocketAddr = grpc_addr.parse()?;
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = crate::handler::grpc::serve(state, grpc_addr).await {
+                        warn!("gRPC server error: {}", e);
+                    }
+                });
+            }
+            #[cfg(not(feature = "grpc"))]
+            warn!(
+                "grpc_addr {} is set but the grpc feature is not enabled",
+                grpc_addr
+            );
+        }
+
+        let http_task = async {
+            match listener {
+                Some(listener) => {
+                    axum::serve(
+                        listener,
+                        router.into_make_service_with_connect_info::<SocketAddr>(),
+                    )
+                    .await
+                }
+                None => {
+                    token.cancelled().await;
+                    Ok(())
+                }
+            }
+        };
+        select! {
+            http_result = http_task => {
+                match http_result {
+                    Ok(_) => info!("Server shut down gracefully"),
+                    Err(e) => {
+                        tracing::error!("Server error: {}", e);
+                        return Err(anyhow!("Server error: {}", e));
+                    }
+                }
+            }
+            ua_result = async {
+                if let Some(useragent) = &state.useragent {
+                    useragent.serve().await
+                } else {
+                    token.cancelled().await;
+                    Ok(())
+                }
+            } => {
+                if let Err(e) = ua_result {
+                    tracing::error!("User agent server error: {}", e);
+                    return Err(anyhow!("User agent server error: {}", e));
+                }
+            }
+            _ = token.cancelled() => {
+                info!("Application shutting down due to cancellation");
+            }
+        }
+
+        if let Some(ua) = state.useragent.as_ref() {
+            ua.stop();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proxy::acl::AclModule;
+    use axum::routing::get;
+    use std::time::Duration;
+
+    fn free_port() -> u16 {
+        std::net::UdpSocket::bind("127.0.0.1:0")
+            .and_then(|socket| socket.local_addr())
+            .map(|addr| addr.port())
+            .expect("free port")
+    }
+
+    #[test]
+    fn test_proxy_module_order() {
+        let mut config = Config::default();
+        config.proxy = Some(ProxyConfig {
+            modules: Some(vec!["auth".to_string(), "call".to_string()]),
+            ..Default::default()
+        });
+        let builder = RustPbxBuilder::new()
+            .with_config(config)
+            .with_proxy_module("acl2", AclModule::create)
+            .with_proxy_module("auth", AclModule::create);
+        let modules = builder
+            .config
+            .proxy
+            .as_ref()
+            .and_then(|p| p.modules.clone());
+        assert_eq!(
+            modules,
+            Some(vec![
+                "auth".to_string(),
+                "acl2".to_string(),
+                "call".to_string()
+            ])
+        );
+        assert_eq!(builder.proxy_modules.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_build_and_serve() -> Result<()> {
+        let http_port = free_port();
+        let sip_port = free_port();
+        let pbx = RustPbxBuilder::new()
+            .with_useragent(false)
+            .with_http_addr(&format!("127.0.0.1:{}", http_port))
+            .with_sip_addr("127.0.0.1")
+            .with_sip_udp_port(sip_port)
+            .with_rtp_ports(32000, 32100)
+            .with_routes(Router::new().route("/embedded", get(async || "embedded")))
+            .build()
+            .await?;
+        assert!(pbx.state.useragent.is_none());
+        let sip_server = pbx.sip_server.as_ref().expect("proxy");
+        let addrs = sip_server.inner.endpoint.get_addrs();
+        assert_eq!(addrs.len(), 1);
+        assert_eq!(addrs[0].addr.port, Some(sip_port.into()));
+
+        let token = pbx.cancel_token();
+        let serve = tokio::spawn(pbx.serve());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let base = format!("http://127.0.0.1:{}", http_port);
+        let body = reqwest::get(format!("{}/embedded", base))
+            .await?
+            .text()
+            .await?;
+        assert_eq!(body, "embedded");
+        let ready = reqwest::get(format!("{}/readyz", base)).await?;
+        assert_eq!(ready.status(), reqwest::StatusCode::OK);
+
+        token.cancel();
+        tokio::time::timeout(Duration::from_secs(2), serve).await???;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_build_without_http() -> Result<()> {
+        let pbx = RustPbxBuilder::new()
+            .with_useragent(false)
+            .with_proxy(false)
+            .with_sip_udp_port(free_port())
+            .with_http(false)
+            .build()
+            .await?;
+        assert!(pbx.sip_server.is_none());
+        let _router = pbx.router();
+        pbx.stop();
+        tokio::time::timeout(Duration::from_secs(1), pbx.serve()).await??;
+        Ok(())
+    }
+}
@@ -10,20 +10,20 @@ use tokio_util::sync::CancellationToken;
 use tracing::{debug, info};
 
 #[derive(Debug, Clone)]
-struct IpNetwork {
+pub(crate) struct IpNetwork {
     network: IpAddr,
     prefix_len: u8,
 }
 
 impl IpNetwork {
-    fn new(network: IpAddr, prefix_len: u8) -> Self {
+    pub(crate) fn new(network: IpAddr, prefix_len: u8) -> Self {
         Self {
             network,
             prefix_len,
         }
     }
 
-    fn contains(&self, ip: &IpAddr) -> bool {
+    pub(crate) fn contains(&self, ip: &IpAddr) -> bool {
         match (self.network, ip) {
             (IpAddr::V4(network), IpAddr::V4(ip)) => {
                 let mask = if self.prefix_len == 0 {
@@ -172,7 +172,7 @@ impl AclModule {
     }
 }
 
-fn parse_network(addr: &str) -> Result<(IpAddr, u8)> {
+pub(crate) fn parse_network(addr: &str) -> Result<(IpAddr, u8)> {
     // If no mask is specified, treat as single IP
     if !addr.contains('/') {
         let ip = IpAddr::from_str(addr)?;
@@ -0,0 +1,467 @@
+use super::server::SipServerRef;
+use anyhow::{Result, anyhow};
+use axum::{
+    Json, Router,
+    extract::{
+        Path, State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use chrono::{DateTime, Utc};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::{collections::HashMap, sync::Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, info, warn};
+
+/// Where a queue agent is in their shift
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AgentState {
+    LoggedOut,
+    Available,
+    Paused,
+    /// On a queue call
+    OnCall,
+    /// Finishing the last queue call, available when the time is up
+    WrapUp,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentSession {
+    /// `user@realm`, or the user alone for any realm
+    pub agent: String,
+    pub state: AgentState,
+    /// Why the agent paused
+    pub reason: Option<String>,
+    /// When the agent entered the state
+    pub since: DateTime<Utc>,
+    pub wrap_up_until: Option<DateTime<Utc>>,
+    /// Queue calls taken since the server started
+    pub calls: u64,
+    /// Seconds on queue calls
+    pub talk_time: u64,
+    #[serde(skip)]
+    call_started: Option<DateTime<Utc>>,
+}
+
+impl AgentSession {
+    fn new(agent: &str, state: AgentState, now: DateTime<Utc>) -> Self {
+        Self {
+            agent: agent.to_string(),
+            state,
+            reason: None,
+            since: now,
+            wrap_up_until: None,
+            calls: 0,
+            talk_time: 0,
+            call_started: None,
+        }
+    }
+
+    fn enter(&mut self, state: AgentState, now: DateTime<Utc>) {
+        self.state = state;
+        self.since = now;
+        self.reason = None;
+        self.wrap_up_until = None;
+    }
+}
+
+/// A change of an agent's state, as sent on the agent WebSocket
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AgentEvent {
+    pub agent: String,
+    pub state: AgentState,
+    pub reason: Option<String>,
+    pub timestamp: u64,
+}
+
+/// The states of the agents that logged in or took a queue call. Agents
+/// without a session are available unless the queue requires a login
+pub struct AgentSessions {
+    sessions: Mutex<HashMap<String, AgentSession>>,
+    /// Reasons accepted for a pause, any when empty
+    pause_reasons: Vec<String>,
+    events: broadcast::Sender<AgentEvent>,
+}
+
+impl Default for AgentSessions {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl AgentSessions {
+    pub fn new(pause_reasons: Vec<String>) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            pause_reasons,
+            events: broadcast::channel(256).0,
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AgentEvent> {
+        self.events.subscribe()
+    }
+
+    fn notify(&self, session: &AgentSession) {
+        debug!(agent = session.agent, state = ?session.state, "agent state");
+        self.events
+            .send(AgentEvent {
+                agent: session.agent.clone(),
+                state: session.state,
+                reason: session.reason.clone(),
+                timestamp: session.since.timestamp_millis() as u64,
+            })
+            .ok();
+    }
+
+    /// Runs `update` on the agent's session, `None` when there is none
+    /// and `create` is not given
+    fn update<T>(
+        &self,
+        agent: &str,
+        create: Option<DateTime<Utc>>,
+        update: impl FnOnce(&mut AgentSession) -> Result<T>,
+    ) -> Result<T> {
+        let mut sessions = self.sessions.lock().map_err(|e| anyhow!("{}", e))?;
+        let key = agent.to_lowercase();
+        if let Some(now) = create {
+            sessions
+                .entry(key.clone())
+                .or_insert_with(|| AgentSession::new(&key, AgentState::LoggedOut, now));
+        }
+        let Some(session) = sessions.get_mut(&key) else {
+            return Err(anyhow!("{} is not logged in", agent));
+        };
+        let state = session.state;
+        let reason = session.reason.clone();
+        let r = update(session)?;
+        if session.state != state || session.reason != reason {
+            let session = session.clone();
+            drop(sessions);
+            self.notify(&session);
+        }
+        Ok(r)
+    }
+
+    pub fn get(&self, agent: &str) -> Option<AgentSession> {
+        self.sessions
+            .lock()
+            .ok()?
+            .get(&agent.to_lowercase())
+            .cloned()
+    }
+
+    /// By agent
+    pub fn list(&self) -> Vec<AgentSession> {
+        let mut sessions: Vec<_> = self
+            .sessions
+            .lock()
+            .map(|sessions| sessions.values().cloned().collect())
+            .unwrap_or_default();
+        sessions.sort_by(|a, b| a.agent.cmp(&b.agent));
+        sessions
+    }
+
+    pub fn login(&self, agent: &str, now: DateTime<Utc>) -> Result<AgentSession> {
+        self.update(agent, Some(now), |session| {
+            if session.state == AgentState::LoggedOut {
+                session.enter(AgentState::Available, now);
+                info!(agent = session.agent, "agent logged in");
+            }
+            Ok(session.clone())
+        })
+    }
+
+    pub fn logout(&self, agent: &str, now: DateTime<Utc>) -> Result<AgentSession> {
+        self.update(agent, None, |session| {
+            session.enter(AgentState::LoggedOut, now);
+            info!(agent = session.agent, "agent logged out");
+            Ok(session.clone())
+        })
+    }
+
+    /// Stops queue calls to the agent. A pause during a call skips the
+    /// wrap-up
+    pub fn pause(
+        &self,
+        agent: &str,
+        reason: Option<String>,
+        now: DateTime<Utc>,
+    ) -> Result<AgentSession> {
+        if let Some(reason) = reason.as_ref()
+            && !self.pause_reasons.is_empty()
+            && !self.pause_reasons.contains(reason)
+        {
+            return Err(anyhow!("unknown pause reason: {}", reason));
+        }
+        self.update(agent, None, |session| {
+            if session.state == AgentState::LoggedOut {
+                return Err(anyhow!("{} is not logged in", session.agent));
+            }
+            session.enter(AgentState::Paused, now);
+            session.reason = reason;
+            Ok(session.clone())
+        })
+    }
+
+    pub fn resume(&self, agent: &str, now: DateTime<Utc>) -> Result<AgentSession> {
+        self.update(agent, None, |session| {
+            if session.state != AgentState::Paused {
+                return Err(anyhow!("{} is not paused", session.agent));
+            }
+            session.enter(AgentState::Available, now);
+            Ok(session.clone())
+        })
+    }
+
+    /// The key of the session of `user@realm`, or of the user alone. A
+    /// bare user finds their session in any realm
+    fn key(&self, aor: &str) -> String {
+        let user = aor.split('@').next().unwrap_or_default();
+        let Ok(sessions) = self.sessions.lock() else {
+            return aor.to_string();
+        };
+        if sessions.contains_key(aor) {
+            return aor.to_string();
+        }
+        if user != aor {
+            return match sessions.contains_key(user) {
+                true => user.to_string(),
+                false => aor.to_string(),
+            };
+        }
+        let prefix = format!("{}@", user);
+        match sessions.keys().find(|key| key.starts_with(&prefix)) {
+            Some(key) => key.clone(),
+            None => aor.to_string(),
+        }
+    }
+
+    /// The agent answered a queue call
+    pub fn call_started(&self, aor: &str, now: DateTime<Utc>) {
+        self.update(&self.key(aor), Some(now), |session| {
+            if session.state != AgentState::Paused {
+                session.enter(AgentState::OnCall, now);
+            }
+            session.calls += 1;
+            session.call_started = Some(now);
+            Ok(())
+        })
+        .ok();
+    }
+
+    /// The agent's queue call ended, wrap-up follows for `wrap_up` seconds
+    pub fn call_ended(&self, aor: &str, wrap_up: u64, now: DateTime<Utc>) {
+        self.update(&self.key(aor), None, |session| {
+            if let Some(started) = session.call_started.take() {
+                session.talk_time += (now - started).num_seconds().max(0) as u64;
+            }
+            if session.state != AgentState::OnCall {
+                return Ok(());
+            }
+            match wrap_up {
+                0 => session.enter(AgentState::Available, now),
+                _ => {
+                    session.enter(AgentState::WrapUp, now);
+                    session.wrap_up_until = Some(now + chrono::Duration::seconds(wrap_up as i64));
+                }
+            }
+            Ok(())
+        })
+        .ok();
+    }
+
+    /// Ends the wrap-ups that are over
+    pub fn expire(&self, now: DateTime<Utc>) {
+        let done: Vec<String> = match self.sessions.lock() {
+            Ok(sessions) => sessions
+                .values()
+                .filter(|s| s.state == AgentState::WrapUp)
+                .filter(|s| s.wrap_up_until.is_none_or(|until| until <= now))
+                .map(|s| s.agent.clone())
+                .collect(),
+            Err(_) => return,
+        };
+        for agent in done {
+            self.update(&agent, None, |session| {
+                if session.state == AgentState::WrapUp {
+                    session.enter(AgentState::Available, now);
+                }
+                Ok(())
+            })
+            .ok();
+        }
+    }
+
+    /// The state of the agent, `None` without a session
+    pub fn state(&self, aor: &str, now: DateTime<Utc>) -> Option<AgentState> {
+        let session = self.get(&self.key(aor))?;
+        match session.state {
+            AgentState::WrapUp if session.wrap_up_until.is_some_and(|until| until <= now) => {
+                Some(AgentState::Available)
+            }
+            state => Some(state),
+        }
+    }
+
+    /// Whether queue calls may be offered to the agent
+    pub fn available(&self, aor: &str, login: bool, now: DateTime<Utc>) -> bool {
+        match self.state(aor, now) {
+            Some(state) => state == AgentState::Available,
+            None => !login,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PauseRequest {
+    pub reason: Option<String>,
+}
+
+/// A request on the agent WebSocket, answered with the agent's session
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "camelCase")]
+pub enum AgentAction {
+    Login {
+        agent: String,
+    },
+    Logout {
+        agent: String,
+    },
+    Pause {
+        agent: String,
+        reason: Option<String>,
+    },
+    Resume {
+        agent: String,
+    },
+}
+
+impl AgentSessions {
+    pub fn apply(&self, action: AgentAction, now: DateTime<Utc>) -> Result<AgentSession> {
+        match action {
+            AgentAction::Login { agent } => self.login(&agent, now),
+            AgentAction::Logout { agent } => self.logout(&agent, now),
+            AgentAction::Pause { agent, reason } => self.pause(&agent, reason, now),
+            AgentAction::Resume { agent } => self.resume(&agent, now),
+        }
+    }
+}
+
+fn conflict(e: anyhow::Error) -> Response {
+    (
+        StatusCode::CONFLICT,
+        Json(serde_json::json!({"error": e.to_string()})),
+    )
+        .into_response()
+}
+
+/// The agent API, mounted under the AMI
+pub fn router(server: SipServerRef) -> Router {
+    Router::new()
+        .route("/ami/v1/agents", get(list_agents_handler))
+        .route("/ami/v1/agents/ws", get(agents_ws_handler))
+        .route("/ami/v1/agents/{agent}", get(get_agent_handler))
+        .route("/ami/v1/agents/{agent}/login", post(login_handler))
+        .route("/ami/v1/agents/{agent}/logout", post(logout_handler))
+        .route("/ami/v1/agents/{agent}/pause", post(pause_handler))
+        .route("/ami/v1/agents/{agent}/resume", post(resume_handler))
+        .with_state(server)
+}
+
+fn respond(r: Result<AgentSession>) -> Response {
+    match r {
+        Ok(session) => Json(session).into_response(),
+        Err(e) => conflict(e),
+    }
+}
+
+async fn list_agents_handler(State(server): State<SipServerRef>) -> Response {
+    Json(server.agent_sessions.list()).into_response()
+}
+
+async fn get_agent_handler(
+    State(server): State<SipServerRef>,
+    Path(agent): Path<String>,
+) -> Response {
+    match server.agent_sessions.get(&agent) {
+        Some(session) => Json(session).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "agent not found"})),
+        )
+            .into_response(),
+    }
+}
+
+async fn login_handler(State(server): State<SipServerRef>, Path(agent): Path<String>) -> Response {
+    respond(server.agent_sessions.login(&agent, Utc::now()))
+}
+
+async fn logout_handler(State(server): State<SipServerRef>, Path(agent): Path<String>) -> Response {
+    respond(server.agent_sessions.logout(&agent, Utc::now()))
+}
+
+async fn pause_handler(
+    State(server): State<SipServerRef>,
+    Path(agent): Path<String>,
+    request: Option<Json<PauseRequest>>,
+) -> Response {
+    let reason = request.and_then(|Json(request)| request.reason);
+    respond(server.agent_sessions.pause(&agent, reason, Utc::now()))
+}
+
+async fn resume_handler(State(server): State<SipServerRef>, Path(agent): Path<String>) -> Response {
+    respond(server.agent_sessions.resume(&agent, Utc::now()))
+}
+
+async fn agents_ws_handler(State(server): State<SipServerRef>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(async move |socket| serve_agents_ws(server, socket).await)
+}
+
+/// Sends every agent state change, and applies the actions received
+async fn serve_agents_ws(server: SipServerRef, socket: WebSocket) {
+    let (mut sink, mut stream) = socket.split();
+    let mut events = server.agent_sessions.subscribe();
+    loop {
+        let text = tokio::select! {
+            _ = server.cancel_token.cancelled() => break,
+            event = events.recv() => match event {
+                Ok(event) => serde_json::to_string(&event).unwrap_or_default(),
+                Err(RecvError::Lagged(n)) => {
+                    warn!(n, "agent websocket lagged");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = stream.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    let reply = serde_json::from_str::<AgentAction>(&text)
+                        .map_err(|e| anyhow!(e))
+                        .and_then(|action| server.agent_sessions.apply(action, Utc::now()));
+                    match reply {
+                        // the change itself is sent as an event
+                        Ok(_) => continue,
+                        Err(e) => serde_json::json!({"error": e.to_string()}).to_string(),
+                    }
+                }
+                Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                Some(Ok(_)) => continue,
+            },
+        };
+        if sink.send(Message::Text(text.into())).await.is_err() {
+            break;
+        }
+    }
+}
@@ -1,8 +1,11 @@
-use super::{ProxyAction, ProxyModule, server::SipServerRef};
+use super::{
+    ProxyAction, ProxyModule, auth_http::HttpAuthBackend, auth_ldap::LdapAuthBackend,
+    auth_static::StaticAuthBackend, server::SipServerRef,
+};
 use crate::call::TransactionCookie;
 use crate::call::user::SipUser;
 use crate::call::user::check_authorization_headers;
-use crate::config::ProxyConfig;
+use crate::config::{AuthBackendConfig, ProxyConfig};
 use anyhow::Result;
 use async_trait::async_trait;
 use rsip::Header;
@@ -18,11 +21,53 @@ use std::sync::Arc;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info};
 
+/// Checks the credentials of requests before the user backend does, for
+/// stores that keep them apart from the users, like a directory
 #[async_trait]
 pub trait AuthBackend: Send + Sync {
+    /// The user the request authenticates as, `None` when it doesn't and
+    /// the user backend gets to check it
     async fn authenticate(&self, original: &rsip::Request) -> Result<Option<SipUser>>;
 }
 
+pub async fn create_auth_backend(config: &AuthBackendConfig) -> Result<Box<dyn AuthBackend>> {
+    match config {
+        AuthBackendConfig::Static { users } => Ok(Box::new(StaticAuthBackend::new(users.clone()))),
+        AuthBackendConfig::Http {
+            url,
+            headers,
+            timeout,
+        } => Ok(Box::new(HttpAuthBackend::new(url, headers, *timeout))),
+        AuthBackendConfig::Ldap(config) => Ok(Box::new(LdapAuthBackend::new(config.clone())?)),
+    }
+}
+
+/// Whether the digest of `auth` was computed with the user's password
+pub fn verify_digest(
+    user: &SipUser,
+    uri: &Uri,
+    method: &rsip::Method,
+    auth: &Authorization,
+) -> bool {
+    let empty_string = "".to_string();
+    let password = user.password.as_ref().unwrap_or(&empty_string);
+
+    // Create a digest generator to compute the expected response
+    let expected_response = DigestGenerator {
+        username: &user.username,
+        password,
+        algorithm: auth.algorithm.unwrap_or(Algorithm::Md5),
+        nonce: &auth.nonce,
+        method,
+        qop: auth.qop.as_ref(),
+        uri,
+        realm: &auth.realm,
+    }
+    .compute();
+
+    expected_response == auth.response
+}
+
 #[derive(Clone)]
 pub struct AuthModule {
     server: SipServerRef,
@@ -66,12 +111,7 @@ impl AuthModule {
                     }
                 }
 
-                match self.verify_credentials(
-                    &stored_user,
-                    &original.uri,
-                    &original.method,
-                    &auth_inner,
-                ) {
+                match verify_digest(&stored_user, &original.uri, &original.method, &auth_inner) {
                     true => Ok(Some(stored_user)),
                     false => Ok(None),
                 }
@@ -83,33 +123,6 @@ impl AuthModule {
         }
     }
 
-    fn verify_credentials(
-        &self,
-        user: &SipUser,
-        uri: &Uri,
-        method: &rsip::Method,
-        auth: &Authorization,
-    ) -> bool {
-        // Use the same approach as common.rs
-        let empty_string = "".to_string();
-        let password = user.password.as_ref().unwrap_or(&empty_string);
-
-        // Create a digest generator to compute the expected response
-        let expected_response = DigestGenerator {
-            username: &user.username,
-            password,
-            algorithm: auth.algorithm.unwrap_or(Algorithm::Md5),
-            nonce: &auth.nonce,
-            method,
-            qop: auth.qop.as_ref(),
-            uri,
-            realm: &auth.realm,
-        }
-        .compute();
-
-        expected_response == auth.response
-    }
-
     pub fn create_proxy_auth_challenge(&self, realm: &str) -> Result<ProxyAuthenticate> {
         let nonce = rsipstack::transaction::random_text(16);
         let proxy_auth = ProxyAuthenticate::new(format!(
@@ -160,9 +173,15 @@ impl ProxyModule for AuthModule {
         tx: &mut Transaction,
         cookie: TransactionCookie,
     ) -> Result<ProxyAction> {
-        // Only authenticate INVITE and REGISTER requests
-        if tx.original.method != rsip::Method::Invite
-            && tx.original.method != rsip::Method::Register
+        // Only authenticate INVITE, REGISTER and MESSAGE requests, and REFER
+        // outside a dialog since it places calls
+        let out_of_dialog_refer =
+            tx.original.method == rsip::Method::Refer && tx.original.to_header()?.tag()?.is_none();
+        if !out_of_dialog_refer
+            && !matches!(
+                tx.original.method,
+                rsip::Method::Invite | rsip::Method::Register | rsip::Method::Message
+            )
         {
             return Ok(ProxyAction::Continue);
         }
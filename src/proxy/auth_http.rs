@@ -0,0 +1,108 @@
+use super::auth::AuthBackend;
+use crate::call::user::{SipUser, check_authorization_headers};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+use rsip::headers::auth::AuthQop;
+use serde::Serialize;
+use std::{collections::HashMap, time::Duration, time::Instant};
+use tracing::info;
+
+/// What the webhook is sent to check a request's digest
+#[derive(Debug, Serialize)]
+pub struct DigestCredentials {
+    pub username: String,
+    pub realm: String,
+    pub method: String,
+    pub uri: String,
+    pub nonce: String,
+    pub response: String,
+    pub algorithm: Option<String>,
+    pub qop: Option<String>,
+    pub nc: Option<String>,
+    pub cnonce: Option<String>,
+}
+
+/// Posts the digest of each request to a webhook, which answers with the
+/// user as JSON when the digest checks out, and 401, 403 or 404 when not
+pub struct HttpAuthBackend {
+    url: String,
+    headers: HashMap<String, String>,
+    client: Client,
+}
+
+impl HttpAuthBackend {
+    pub fn new(url: &str, headers: &Option<HashMap<String, String>>, timeout: Option<u64>) -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(timeout.unwrap_or(5)))
+            .build()
+            .unwrap_or_default();
+        Self {
+            url: url.to_string(),
+            headers: headers.clone().unwrap_or_default(),
+            client,
+        }
+    }
+}
+
+#[async_trait]
+impl AuthBackend for HttpAuthBackend {
+    async fn authenticate(&self, original: &rsip::Request) -> Result<Option<SipUser>> {
+        let auth = match check_authorization_headers(original)? {
+            Some((_, auth)) => auth,
+            None => return Ok(None),
+        };
+        let (qop, nc, cnonce) = match &auth.qop {
+            Some(AuthQop::Auth { cnonce, nc }) => ("auth", *nc, cnonce),
+            Some(AuthQop::AuthInt { cnonce, nc }) => ("auth-int", *nc, cnonce),
+            None => ("", 0, &String::new()),
+        };
+        let credentials = DigestCredentials {
+            username: auth.username.clone(),
+            realm: auth.realm.clone(),
+            method: original.method.to_string(),
+            uri: original.uri.to_string(),
+            nonce: auth.nonce.clone(),
+            response: auth.response.clone(),
+            algorithm: auth.algorithm.map(|algorithm| algorithm.to_string()),
+            qop: auth.qop.as_ref().map(|_| qop.to_string()),
+            nc: auth.qop.as_ref().map(|_| format!("{:08}", nc)),
+            cnonce: auth.qop.as_ref().map(|_| cnonce.clone()),
+        };
+
+        let start_time = Instant::now();
+        let mut request_builder = self.client.post(&self.url).json(&credentials);
+        for (key, value) in &self.headers {
+            request_builder = request_builder.header(key, value);
+        }
+        let response = request_builder
+            .send()
+            .await
+            .map_err(|e| anyhow!("HTTP request error: {}", e))?;
+        info!(
+            username = credentials.username,
+            realm = credentials.realm,
+            "auth webhook took {:?} status {}",
+            start_time.elapsed(),
+            response.status()
+        );
+
+        match response.status() {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN | StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                let mut user = response
+                    .json::<SipUser>()
+                    .await
+                    .map_err(|e| anyhow!("HTTP response error: {}", e))?;
+                if !user.enabled {
+                    return Ok(None);
+                }
+                if user.realm.is_none() {
+                    user.realm = Some(credentials.realm);
+                }
+                Ok(Some(user))
+            }
+            status => Err(anyhow!("HTTP response error: {}", status)),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use super::auth::{AuthBackend, verify_digest};
+use crate::{
+    call::user::{SipUser, check_authorization_headers},
+    config::LdapAuthConfig,
+};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use ldap3::{
+    Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry, SearchOptions, ldap_escape,
+};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// The first value of `attribute` in `entry`, whatever the case of its name
+fn first_value<'a>(entry: &'a SearchEntry, attribute: &str) -> Option<&'a str> {
+    entry
+        .attrs
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(attribute))
+        .and_then(|(_, values)| values.first())
+        .map(|value| value.as_str())
+}
+
+/// Looks users up in a directory and checks the digest of requests against
+/// the SIP password it keeps, since digests can't be checked with a bind
+pub struct LdapAuthBackend {
+    config: LdapAuthConfig,
+    timeout: Duration,
+    /// Bound connection the lookups share, made again once it fails
+    conn: Mutex<Option<Ldap>>,
+}
+
+impl LdapAuthBackend {
+    pub fn new(config: LdapAuthConfig) -> Result<Self> {
+        if !config.url.starts_with("ldap://") && !config.url.starts_with("ldaps://") {
+            return Err(anyhow!("unsupported LDAP url: {}", config.url));
+        }
+        Ok(Self {
+            timeout: Duration::from_secs(config.timeout.unwrap_or(5)),
+            config,
+            conn: Mutex::new(None),
+        })
+    }
+
+    async fn connect(&self) -> Result<Ldap> {
+        let settings = LdapConnSettings::new()
+            .set_conn_timeout(self.timeout)
+            .set_starttls(self.config.starttls.unwrap_or(false));
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.config.url).await?;
+        tokio::spawn(async move {
+            if let Err(e) = conn.drive().await {
+                warn!("LDAP connection failed: {}", e);
+            }
+        });
+        if let Some(bind_dn) = &self.config.bind_dn {
+            ldap.with_timeout(self.timeout)
+                .simple_bind(bind_dn, self.config.bind_password.as_deref().unwrap_or(""))
+                .await?
+                .success()?;
+        }
+        Ok(ldap)
+    }
+
+    /// The shared connection, and whether it was made before
+    async fn connection(&self) -> Result<(Ldap, bool)> {
+        let mut conn = self.conn.lock().await;
+        if let Some(ldap) = conn.as_ref() {
+            return Ok((ldap.clone(), true));
+        }
+        let ldap = self.connect().await?;
+        *conn = Some(ldap.clone());
+        Ok((ldap, false))
+    }
+
+    /// The user with the password the directory keeps, `None` when there's
+    /// no such user
+    pub async fn lookup(&self, username: &str) -> Result<Option<SipUser>> {
+        let password_attribute = self
+            .config
+            .password_attribute
+            .as_deref()
+            .unwrap_or("sipPassword");
+        let display_name_attribute = self
+            .config
+            .display_name_attribute
+            .as_deref()
+            .unwrap_or("cn");
+        let attributes = [password_attribute, display_name_attribute];
+
+        let (mut ldap, reused) = self.connection().await?;
+        let entries = match self.search(&mut ldap, username, &attributes).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                self.conn.lock().await.take();
+                if !reused {
+                    return Err(e);
+                }
+                // the directory may have closed the idle connection
+                info!(username, "LDAP lookup failed, connecting again: {}", e);
+                let (mut ldap, _) = self.connection().await?;
+                self.search(&mut ldap, username, &attributes).await?
+            }
+        };
+
+        let entry = match entries.as_slice() {
+            [] => return Ok(None),
+            [entry] => entry,
+            _ => return Err(anyhow!("LDAP has more than one entry for {}", username)),
+        };
+        let Some(password) = first_value(entry, password_attribute) else {
+            info!(username, dn = entry.dn, "LDAP entry has no SIP password");
+            return Ok(None);
+        };
+        Ok(Some(SipUser {
+            username: username.to_string(),
+            password: Some(password.to_string()),
+            display_name: first_value(entry, display_name_attribute).map(|n| n.to_string()),
+            ..Default::default()
+        }))
+    }
+
+    /// Entries under the base DN whose username attribute is `username`
+    async fn search(
+        &self,
+        ldap: &mut Ldap,
+        username: &str,
+        attributes: &[&str],
+    ) -> Result<Vec<SearchEntry>> {
+        let filter = format!(
+            "({}={})",
+            self.config.username_attribute.as_deref().unwrap_or("uid"),
+            ldap_escape(username)
+        );
+        let (entries, _) = ldap
+            .with_search_options(SearchOptions::new().sizelimit(2))
+            .with_timeout(self.timeout)
+            .search(
+                &self.config.base_dn,
+                Scope::Subtree,
+                &filter,
+                attributes.to_vec(),
+            )
+            .await?
+            .success()?;
+        Ok(entries.into_iter().map(SearchEntry::construct).collect())
+    }
+}
+
+#[async_trait]
+impl AuthBackend for LdapAuthBackend {
+    async fn authenticate(&self, original: &rsip::Request) -> Result<Option<SipUser>> {
+        let (user, auth) = match check_authorization_headers(original)? {
+            Some((user, auth)) => (user, auth),
+            None => return Ok(None),
+        };
+        let Some(mut stored_user) = self.lookup(&user.username).await? else {
+            return Ok(None);
+        };
+        if !verify_digest(&stored_user, &original.uri, &original.method, &auth) {
+            return Ok(None);
+        }
+        stored_user.realm = user.realm;
+        Ok(Some(stored_user))
+    }
+}
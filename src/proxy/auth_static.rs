@@ -0,0 +1,66 @@
+use super::auth::{AuthBackend, verify_digest};
+use crate::{
+    call::user::{SipUser, check_authorization_headers},
+    config::ProxyConfig,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use tracing::info;
+
+/// Credentials listed in the config, users without a realm match any realm
+pub struct StaticAuthBackend {
+    users: HashMap<String, SipUser>,
+}
+
+impl StaticAuthBackend {
+    pub fn new(users: Vec<SipUser>) -> Self {
+        info!("Creating StaticAuthBackend, users: {}", users.len());
+        let users = users
+            .into_iter()
+            .map(|user| {
+                (
+                    Self::get_identifier(&user.username, user.realm.as_deref()),
+                    user,
+                )
+            })
+            .collect();
+        Self { users }
+    }
+
+    fn get_identifier(user: &str, realm: Option<&str>) -> String {
+        match realm {
+            Some(realm) if !realm.is_empty() => {
+                format!("{}@{}", user, ProxyConfig::normalize_realm(realm))
+            }
+            _ => user.to_string(),
+        }
+    }
+
+    fn get_user(&self, username: &str, realm: Option<&str>) -> Option<&SipUser> {
+        self.users
+            .get(&Self::get_identifier(username, realm))
+            .or_else(|| self.users.get(username))
+    }
+}
+
+#[async_trait]
+impl AuthBackend for StaticAuthBackend {
+    async fn authenticate(&self, original: &rsip::Request) -> Result<Option<SipUser>> {
+        let (user, auth) = match check_authorization_headers(original)? {
+            Some((user, auth)) => (user, auth),
+            None => return Ok(None),
+        };
+        let Some(stored_user) = self.get_user(&user.username, user.realm.as_deref()) else {
+            return Ok(None);
+        };
+        if !stored_user.enabled
+            || !verify_digest(stored_user, &original.uri, &original.method, &auth)
+        {
+            return Ok(None);
+        }
+        let mut stored_user = stored_user.clone();
+        stored_user.realm = user.realm;
+        Ok(Some(stored_user))
+    }
+}
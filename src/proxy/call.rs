@@ -1,19 +1,29 @@
 use super::{ProxyAction, ProxyModule, server::SipServerRef};
+use crate::call::ActiveCallRef;
 use crate::call::DialStrategy;
 use crate::call::Dialplan;
+use crate::call::HangupCause;
 use crate::call::Location;
 use crate::call::RouteInvite;
 use crate::call::SipUser;
 use crate::call::TransactionCookie;
 use crate::call::b2bua::B2buaBuilder;
+use crate::call::parse_route_set;
 use crate::call::sip::Invitation;
-use crate::config::ProxyConfig;
 use crate::config::RouteResult;
-use crate::proxy::routing::matcher::match_invite;
+use crate::config::{CallLimitsConfig, ProxyConfig};
+use crate::features;
+use crate::media::codecs::resample::ResampleProfile;
+use crate::proxy::fraud::FraudVerdict;
+use crate::proxy::limits::{CallLimit, LimitScope};
+use crate::proxy::metering::call_tenant;
+use crate::proxy::presence::PresenceState;
+use crate::proxy::radius::{RadiusCall, RadiusClient};
+use crate::proxy::routing::matcher::{match_invite, source_country};
 use anyhow::Error;
 use anyhow::{Result, anyhow};
 use async_trait::async_trait;
-use rsip::prelude::HeadersExt;
+use rsip::prelude::{HeadersExt, UntypedHeader};
 use rsipstack::dialog::DialogId;
 use rsipstack::dialog::dialog_layer::DialogLayer;
 use rsipstack::dialog::invitation::InviteOption;
@@ -61,6 +71,11 @@ impl RouteInvite for DefaultRouteInvite {
         )
         .await
     }
+
+    fn take_transrating(&self, origin: &rsip::Request) -> Option<ResampleProfile> {
+        let call_id = origin.call_id_header().ok()?;
+        self.routing_state.take_transrating(call_id.value())
+    }
 }
 
 #[derive(Clone)]
@@ -84,14 +99,16 @@ impl CallModule {
     }
 
     pub fn new(config: Arc<ProxyConfig>, server: SipServerRef) -> Self {
-        let dialog_layer = Arc::new(DialogLayer::new(server.endpoint.inner.clone()));
-        let invitation = Invitation::new(dialog_layer.clone());
+        let dialog_layer = server.dialog_layer.clone();
+        let invitation = Invitation::new(dialog_layer.clone())
+            .with_resolver(server.resolver.clone())
+            .with_retransmitter(server.retransmitter.clone());
         let inner = Arc::new(CallModuleInner {
             config,
+            routing_state: server.routing_state.clone(),
             server,
             invitation,
             dialog_layer,
-            routing_state: Arc::new(crate::proxy::routing::RoutingState::new()),
         });
         Self { inner }
     }
@@ -115,6 +132,7 @@ impl CallModule {
             let mut location = Location {
                 aor: callee_uri.clone(),
                 destination: SipAddr::try_from(&callee_uri).map_err(|e| (anyhow!(e), None))?,
+                route_set: self.preloaded_routes(original),
                 ..Default::default()
             };
 
@@ -137,7 +155,7 @@ impl CallModule {
             });
         }
 
-        let mut locations = self
+        let locations = self
             .inner
             .server
             .locator
@@ -150,6 +168,16 @@ impl CallModule {
             return Err((anyhow!("User offline"), Some(rsip::StatusCode::NotFound)));
         }
 
+        let gruu = gruu_instance(&original.uri).or_else(|| gruu_instance(&callee_uri));
+        let mut locations = select_flows(locations, gruu.as_deref());
+        if locations.is_empty() {
+            warn!(callee = %callee_uri, gruu, "GRUU instance not registered");
+            return Err((
+                anyhow!("GRUU instance offline"),
+                Some(rsip::StatusCode::TemporarilyUnavailable),
+            ));
+        }
+
         if let Some(location_inspector) = self.inner.server.location_inspector.as_ref() {
             for loc in locations.iter_mut() {
                 match location_inspector
@@ -174,6 +202,56 @@ impl CallModule {
         })
     }
 
+    /// Preloaded Route entries left after the ones addressed to this proxy
+    pub(crate) fn preloaded_routes(&self, original: &rsip::Request) -> Vec<rsip::Uri> {
+        let addrs = self.inner.server.endpoint.get_addrs();
+        let realms = self.inner.config.realms.clone().unwrap_or_default();
+        let is_local = |uri: &rsip::Uri| {
+            let host = &uri.host_with_port;
+            realms.iter().any(|realm| *realm == host.host.to_string())
+                || addrs.iter().any(|addr| {
+                    addr.addr.host == host.host
+                        && (host.port.is_none() || addr.addr.port == host.port)
+                })
+        };
+        original
+            .headers
+            .iter()
+            .filter_map(|h| match h {
+                rsip::Header::Route(route) => Some(parse_route_set(route.value())),
+                _ => None,
+            })
+            .flatten()
+            .skip_while(|uri| is_local(uri))
+            .collect()
+    }
+
+    /// The global limit and those of the local extensions on either end
+    async fn call_limits(
+        &self,
+        config: &CallLimitsConfig,
+        original: &rsip::Request,
+    ) -> Result<Vec<CallLimit>> {
+        let mut limits = vec![CallLimit::new(LimitScope::Global, "*", &config.global)];
+        for uri in [original.from_header()?.uri()?, original.to_header()?.uri()?] {
+            let realm = uri.host().to_string();
+            if !self.inner.server.is_same_realm(&realm).await {
+                continue;
+            }
+            let username = uri.user().unwrap_or_default();
+            let extension = config.extensions.get(username).unwrap_or(&config.extension);
+            let limit = CallLimit::new(
+                LimitScope::Extension,
+                PresenceState::aor(username, &realm),
+                extension,
+            );
+            if !limits.iter().any(|l| l.name == limit.name) {
+                limits.push(limit);
+            }
+        }
+        Ok(limits)
+    }
+
     pub(crate) async fn handle_invite(
         &self,
         tx: &mut Transaction,
@@ -190,6 +268,28 @@ impl CallModule {
             }
         };
 
+        // the call holds its slots, trunk ones included, until it ends
+        let call_id = tx.original.call_id_header()?.value().to_string();
+        let limiter = self.inner.routing_state.limiter.clone();
+        let _slot = limiter.enter(call_id.clone());
+        if let Some(config) = self.inner.config.call_limits.as_ref() {
+            let limits = self.call_limits(config, &tx.original).await?;
+            if let Err(e) = limiter.admit(&call_id, &limits) {
+                let retry_after = rsip::headers::RetryAfter::new(config.retry_after.to_string());
+                tx.reply_with(
+                    rsip::StatusCode::ServiceUnavailable,
+                    vec![
+                        retry_after.into(),
+                        HangupCause::from_sip_status(503).reason_header(),
+                    ],
+                    None,
+                )
+                .await
+                .map_err(|e| anyhow!("Failed to send reply: {}", e))?;
+                return Err(anyhow!(e));
+            }
+        }
+
         let route_invite = match self.inner.server.create_route_invite.as_ref() {
             Some(f) => f(self.inner.server.clone(), self.inner.config.clone())?,
             None => Box::new(DefaultRouteInvite {
@@ -223,6 +323,125 @@ impl CallModule {
             dialplan
         };
 
+        let tenant = call_tenant(
+            &self.inner.server,
+            &[
+                &tx.original.from_header()?.uri()?,
+                &tx.original.to_header()?.uri()?,
+            ],
+        )
+        .await;
+        let meter = self.inner.server.meter.clone();
+        let _channel = meter.enter(call_id.clone(), tenant.clone());
+        let trunk = limiter.trunk(&call_id);
+        if let Some(trunk) = trunk.as_ref() {
+            meter.set_trunk(&call_id, trunk);
+        }
+
+        // outbound calls of local extensions are scored for fraud
+        let from = tx.original.from_header()?.uri()?;
+        let to = tx.original.to_header()?.uri()?;
+        let server = &self.inner.server;
+        let _fraud = if let Some(fraud) = server.fraud.as_ref()
+            && server.is_same_realm(&from.host().to_string()).await
+            && (trunk.is_some() || !server.is_same_realm(&to.host().to_string()).await)
+        {
+            let slot = fraud.enter(call_id.clone());
+            let extension =
+                PresenceState::aor(from.user().unwrap_or_default(), &from.host().to_string());
+            let number = to.user().unwrap_or_default();
+            if fraud.check(&call_id, &extension, number) == FraudVerdict::Block {
+                let cause = HangupCause::from_sip_status(403);
+                tx.reply_with(
+                    rsip::StatusCode::Forbidden,
+                    vec![cause.reason_header()],
+                    None,
+                )
+                .await
+                .map_err(|e| anyhow!("Failed to send reply: {}", e))?;
+                return Err(anyhow!("call blocked as likely fraud"));
+            }
+            Some(slot)
+        } else {
+            None
+        };
+
+        // the call has the features of its first local party
+        let features = server.app_state.features.clone();
+        let extension = if server.is_same_realm(&from.host().to_string()).await {
+            Some(PresenceState::aor(
+                from.user().unwrap_or_default(),
+                &from.host().to_string(),
+            ))
+        } else if server.is_same_realm(&to.host().to_string()).await {
+            Some(PresenceState::aor(
+                to.user().unwrap_or_default(),
+                &to.host().to_string(),
+            ))
+        } else {
+            None
+        };
+        let enabled = |feature| features.enabled(feature, tenant.as_deref(), extension.as_deref());
+        if features.is_international(to.user().unwrap_or_default())
+            && server.is_same_realm(&from.host().to_string()).await
+            && (trunk.is_some() || !server.is_same_realm(&to.host().to_string()).await)
+            && !enabled(features::INTERNATIONAL)
+        {
+            let cause = HangupCause::from_sip_status(403);
+            tx.reply_with(
+                rsip::StatusCode::Forbidden,
+                vec![cause.reason_header()],
+                None,
+            )
+            .await
+            .map_err(|e| anyhow!("Failed to send reply: {}", e))?;
+            return Err(anyhow!("international dialing is disabled"));
+        }
+        let recording = enabled(features::RECORDING);
+        let consent = self
+            .inner
+            .config
+            .recording_consent
+            .as_ref()
+            .and_then(|config| {
+                let country = source_country(&tx.original, &self.inner.routing_state);
+                let numbers = [
+                    from.user().unwrap_or_default(),
+                    to.user().unwrap_or_default(),
+                ];
+                config
+                    .jurisdiction(&numbers, Some(country.as_str()).filter(|c| !c.is_empty()))
+                    .cloned()
+            });
+
+        // trunks billed over RADIUS may refuse the call before it is placed
+        let radius = trunk
+            .and_then(|trunk| self.inner.config.trunks.get(&trunk))
+            .and_then(|trunk| trunk.radius.clone())
+            .map(|config| Arc::new(RadiusClient::new(config)));
+        let radius_call = RadiusCall {
+            session_id: call_id.clone(),
+            caller: from.user().unwrap_or_default().to_string(),
+            callee: to.user().unwrap_or_default().to_string(),
+        };
+        if let Some(radius) = radius.as_ref() {
+            let code = match radius.authorize(&radius_call).await {
+                Ok(true) => None,
+                Ok(false) => Some(rsip::StatusCode::Forbidden),
+                Err(e) => {
+                    warn!(call_id, "radius authorization failed: {}", e);
+                    Some(rsip::StatusCode::ServiceUnavailable)
+                }
+            };
+            if let Some(code) = code {
+                let cause = HangupCause::from_sip_status(code.code());
+                tx.reply_with(code, vec![cause.reason_header()], None)
+                    .await
+                    .map_err(|e| anyhow!("Failed to send reply: {}", e))?;
+                return Err(anyhow!("call not authorized by radius"));
+            }
+        }
+
         let cancel_token = CancellationToken::new();
         let media_capabilities = vec![];
 
@@ -233,21 +452,44 @@ impl CallModule {
             .clone()
             .unwrap_or_else(|| format!("b2bua-{}-{}", rand::random::<u32>(), dialog_id));
 
+        // both parties show as busy for as long as the call lasts
+        let _presence = self.inner.server.presence.enter_call(
+            [
+                tx.original.from_header()?.uri()?,
+                tx.original.to_header()?.uri()?,
+            ]
+            .iter()
+            .map(|uri| PresenceState::aor(uri.user().unwrap_or_default(), &uri.host().to_string()))
+            .collect(),
+        );
+
         let app_state = self.inner.server.app_state.clone();
         let b2bua = B2buaBuilder::new(app_state.clone(), cookie, session_id)
-            .with_recorder(true)
+            .with_recorder(recording)
+            .with_consent(consent)
             .with_cancel_token(cancel_token)
             .with_media_capabilities(media_capabilities)
             .build(&tx)
             .await?;
 
+        let original = tx.original.clone();
+        let contact = caller_contact.uri.clone();
+        let extras = dialplan.extras.clone();
+        let connect = |active_call: ActiveCallRef| {
+            if let Some(radius) = radius {
+                let active_call = active_call.clone();
+                tokio::spawn(async move { radius.account_call(active_call, radius_call).await });
+            }
+            b2bua.dial(active_call, caller_contact, dialplan, &original)
+        };
         match b2bua
-            .serve(
+            .serve_with(
                 tx,
-                caller_contact,
+                contact,
                 app_state.clone(),
                 self.inner.invitation.clone(),
-                dialplan,
+                extras,
+                connect,
             )
             .await
         {
@@ -368,3 +610,30 @@ impl ProxyModule for CallModule {
         Ok(())
     }
 }
+
+/// Instance a GRUU (`;gr=urn:uuid:...`) points at
+fn gruu_instance(uri: &rsip::Uri) -> Option<String> {
+    uri.params.iter().find_map(|param| match param {
+        rsip::Param::Other(key, Some(value)) if key.value().eq_ignore_ascii_case("gr") => {
+            Some(value.value().to_string())
+        }
+        _ => None,
+    })
+}
+
+/// Keeps the most recently registered flow of each device instance (RFC 5626),
+/// and only the device a GRUU targets when `gruu` is set (RFC 5627)
+pub(crate) fn select_flows(locations: Vec<Location>, gruu: Option<&str>) -> Vec<Location> {
+    let mut seen = std::collections::HashSet::new();
+    let mut selected = locations
+        .into_iter()
+        .rev()
+        .filter(|loc| gruu.is_none_or(|gruu| loc.instance_id.as_deref() == Some(gruu)))
+        .filter(|loc| match loc.instance_id.as_ref() {
+            Some(instance_id) => seen.insert(instance_id.clone()),
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    selected.reverse();
+    selected
+}
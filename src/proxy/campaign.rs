@@ -0,0 +1,1053 @@
+use super::{
+    call::select_flows,
+    clicktodial::{dial_routed, local_contact, parse_target, ring_locations, serve_call},
+    message::resolve_targets,
+    presence::{CallPresence, PresenceState},
+    server::SipServerRef,
+};
+use crate::call::{ActiveCall, ActiveCallType, HangupCause};
+use crate::config::CampaignConfig;
+use crate::media::track::TrackConfig;
+use anyhow::{Result, anyhow};
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveTime, Utc, Weekday};
+use rsipstack::transaction::make_tag;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_with::skip_serializing_none;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+/// What became of a campaign target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Disposition {
+    /// Answered and put through to an agent
+    Connected,
+    /// Answered, but no agent took the call
+    Abandoned,
+    Busy,
+    NoAnswer,
+    Failed,
+    /// On the do-not-call list, never dialled
+    DoNotCall,
+    /// Not a phone number
+    Invalid,
+    /// The campaign was stopped before it was dialled
+    Cancelled,
+}
+
+impl Disposition {
+    /// The disposition of a call that was not answered
+    pub fn unanswered(cause: Option<HangupCause>) -> Self {
+        match cause {
+            Some(HangupCause::UserBusy) => Disposition::Busy,
+            Some(
+                HangupCause::NoAnswer
+                | HangupCause::NoUserResponse
+                | HangupCause::SubscriberAbsent
+                | HangupCause::RecoveryOnTimerExpiry,
+            ) => Disposition::NoAnswer,
+            _ => Disposition::Failed,
+        }
+    }
+
+    fn retry(&self) -> bool {
+        matches!(self, Disposition::Busy | Disposition::NoAnswer)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CampaignStatus {
+    Running,
+    /// Every target has its disposition
+    Completed,
+    Stopped,
+}
+
+/// How many targets the dialer calls ahead of its agents
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Pacing {
+    /// `dialRatio` calls per idle agent
+    #[default]
+    Ratio,
+    /// Enough calls, at the answer rate seen so far, to keep busy the agents
+    /// that are idle or about to finish their calls. `dialRatio` caps the
+    /// calls per agent
+    Predictive,
+}
+
+/// Dialled and answered calls of the campaigns since the server started,
+/// the answer rate a new campaign starts from
+#[derive(Debug, Default)]
+pub struct AnswerHistory {
+    attempts: AtomicU64,
+    answered: AtomicU64,
+}
+
+impl AnswerHistory {
+    pub fn record(&self, answered: bool) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+        if answered {
+            self.answered.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn rate(&self) -> Option<f64> {
+        match self.attempts.load(Ordering::Relaxed) {
+            0 => None,
+            attempts => Some(self.answered.load(Ordering::Relaxed) as f64 / attempts as f64),
+        }
+    }
+}
+
+/// Answer rate assumed without any history
+const DEFAULT_ANSWER_RATE: f64 = 0.5;
+/// Weight of the history against the campaign's own calls, in calls
+const HISTORY_WEIGHT: f64 = 10.0;
+/// Finished calls needed before agents are expected to free up on time
+const MIN_TALK_SAMPLES: u64 = 3;
+
+/// When targets may be called, in their local time
+#[derive(Debug, Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallingHours {
+    /// e.g. `09:00`
+    pub start: String,
+    /// e.g. `21:00`, before `start` for a window past midnight
+    pub end: String,
+    /// e.g. `["mon", "tue"]`, every day when empty
+    #[serde(default)]
+    pub days: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CallingWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+    days: Vec<Weekday>,
+}
+
+impl CallingWindow {
+    pub(crate) fn parse(hours: &CallingHours) -> Result<Self> {
+        let time = |value: &str| {
+            NaiveTime::parse_from_str(value.trim(), "%H:%M")
+                .map_err(|_| anyhow!("invalid calling hour: {}", value))
+        };
+        let days = hours
+            .days
+            .iter()
+            .map(|day| {
+                day.parse::<Weekday>()
+                    .map_err(|_| anyhow!("invalid calling day: {}", day))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            start: time(&hours.start)?,
+            end: time(&hours.end)?,
+            days,
+        })
+    }
+
+    pub(crate) fn allows(&self, local: DateTime<FixedOffset>) -> bool {
+        let time = local.time();
+        // a window past midnight belongs to the day it started on
+        let (day, inside) = if self.start <= self.end {
+            (local.weekday(), self.start <= time && time < self.end)
+        } else if time >= self.start {
+            (local.weekday(), true)
+        } else {
+            (local.weekday().pred(), time < self.end)
+        };
+        inside && (self.days.is_empty() || self.days.contains(&day))
+    }
+}
+
+/// Starts a campaign with the targets of a CSV text
+#[skip_serializing_none]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CampaignRequest {
+    pub name: Option<String>,
+    /// A header row naming the columns, then a row per target. The
+    /// `number` column is dialled, an optional `utc_offset` column gives the
+    /// target's time zone, the others become call variables
+    pub csv: String,
+    /// Local users the answered calls are put through to
+    pub agents: Vec<String>,
+    /// Shown to the targets, the first agent when unset
+    pub caller_id: Option<String>,
+    /// Host the numbers are dialled at, routing rules and trunks apply as
+    /// to a call to it. The agents' realm when unset
+    pub domain: Option<String>,
+    pub calling_hours: Option<CallingHours>,
+    /// Time zone of targets without their own, e.g. `+08:00`, UTC when unset
+    pub utc_offset: Option<String>,
+    /// Calls in progress at most, 10 by default
+    pub max_concurrent: Option<usize>,
+    /// `ratio` by default
+    pub pacing: Option<Pacing>,
+    /// Calls dialled per idle agent while the abandon rate is under its
+    /// cap, 1 by default. The most calls per agent with predictive
+    /// pacing, 3 by default
+    pub dial_ratio: Option<f64>,
+    /// The `max_abandon_rate` of the campaign config when unset
+    pub max_abandon_rate: Option<f64>,
+    /// Busy and unanswered targets are dialled again up to this many
+    /// times in all, 1 by default
+    pub max_attempts: Option<u32>,
+    /// Seconds before dialling a target again, 300 by default
+    pub retry_interval: Option<u64>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CampaignTarget {
+    pub number: String,
+    /// The other columns of its row
+    pub variables: HashMap<String, String>,
+    pub attempts: u32,
+    /// Of the last attempt, final once the target is no longer retried
+    pub disposition: Option<Disposition>,
+    /// Session id of the last attempt
+    pub session_id: Option<String>,
+    /// The agent the call was put through to
+    pub agent: Option<String>,
+    #[serde(skip)]
+    utc_offset: Option<FixedOffset>,
+    #[serde(skip)]
+    in_call: bool,
+    /// Dialled and not yet put through to an agent
+    #[serde(skip)]
+    dialing: bool,
+    #[serde(skip)]
+    retry_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    dialed_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    connected_at: Option<DateTime<Utc>>,
+}
+
+/// A target to dial now
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dial {
+    pub index: usize,
+    pub number: String,
+    pub session_id: String,
+    pub variables: HashMap<String, String>,
+}
+
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CampaignReport {
+    pub id: String,
+    pub name: Option<String>,
+    pub status: CampaignStatus,
+    pub created_at: DateTime<Utc>,
+    /// Calls in progress, and those of them not yet put through
+    pub in_call: usize,
+    pub dialing: usize,
+    /// Abandoned share of the answered calls
+    pub abandon_rate: f64,
+    /// Answered share of the dialled calls, with the history of earlier
+    /// campaigns while there are few
+    pub answer_rate: f64,
+    /// Targets by their last disposition
+    pub dispositions: HashMap<Disposition, usize>,
+    pub targets: Option<Vec<CampaignTarget>>,
+}
+
+struct CampaignState {
+    status: CampaignStatus,
+    targets: Vec<CampaignTarget>,
+    /// Agents being rung for an answered call
+    ringing: HashSet<String>,
+    stats: CallStats,
+}
+
+/// Durations are in ms
+#[derive(Default)]
+struct CallStats {
+    attempts: u64,
+    answered: u64,
+    /// From dialling to an agent taking the call
+    setup: i64,
+    setups: u64,
+    /// From an agent taking the call to its end
+    talk: i64,
+    talks: u64,
+}
+
+/// Outbound calls to a list of targets, each answered call is put through
+/// to an idle agent
+pub struct Campaign {
+    pub id: String,
+    pub name: Option<String>,
+    pub created_at: DateTime<Utc>,
+    agents: Vec<rsip::Uri>,
+    caller_id: rsip::Uri,
+    domain: rsip::Uri,
+    calling_hours: Option<CallingWindow>,
+    utc_offset: FixedOffset,
+    max_concurrent: usize,
+    pacing: Pacing,
+    dial_ratio: f64,
+    max_abandon_rate: f64,
+    max_attempts: u32,
+    retry_interval: Duration,
+    state: Mutex<CampaignState>,
+    history: Arc<AnswerHistory>,
+    token: CancellationToken,
+}
+
+/// Rows of a CSV text, a quoted field may hold commas, line breaks and
+/// doubled quotes. Blank lines are skipped
+pub fn parse_csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    field.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                if row.iter().any(|f| !f.trim().is_empty()) {
+                    rows.push(std::mem::take(&mut row));
+                }
+                row.clear();
+            }
+            c => field.push(c),
+        }
+    }
+    row.push(field);
+    if row.iter().any(|f| !f.trim().is_empty()) {
+        rows.push(row);
+    }
+    rows
+}
+
+/// The digits of a phone number, with its leading `+`. `None` when it is
+/// not a number
+pub fn normalize_number(number: &str) -> Option<String> {
+    let number = number.trim();
+    let (plus, rest) = match number.strip_prefix('+') {
+        Some(rest) => ("+", rest),
+        None => ("", number),
+    };
+    if !rest
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, ' ' | '-' | '.' | '(' | ')'))
+    {
+        return None;
+    }
+    let digits: String = rest.chars().filter(|c| c.is_ascii_digit()).collect();
+    match digits.len() {
+        3..=15 => Some(format!("{}{}", plus, digits)),
+        _ => None,
+    }
+}
+
+/// Whether the do-not-call list has the number. Numbers are compared by
+/// their digits, and a national number matches its international form
+pub fn on_dnc_list(dnc: &HashSet<String>, number: &str) -> bool {
+    let digits = number.trim_start_matches('+');
+    dnc.iter().any(|entry| {
+        let entry = entry.trim_start_matches('+');
+        let (short, long) = match entry.len() <= digits.len() {
+            true => (entry, digits),
+            false => (digits, entry),
+        };
+        short == long || (short.len() >= 7 && long.ends_with(short))
+    })
+}
+
+/// The do-not-call numbers of the config and its file
+pub async fn load_dnc(config: &CampaignConfig) -> Result<HashSet<String>> {
+    let mut entries = config.dnc.clone();
+    if let Some(file) = config.dnc_file.as_ref() {
+        let text = tokio::fs::read_to_string(file)
+            .await
+            .map_err(|e| anyhow!("failed to read dnc file {}: {}", file, e))?;
+        entries.extend(
+            text.lines()
+                .map(|line| line.split('#').next().unwrap_or_default().to_string()),
+        );
+    }
+    Ok(entries
+        .iter()
+        .filter_map(|entry| normalize_number(entry))
+        .collect())
+}
+
+pub(crate) fn parse_offset(value: &str) -> Result<FixedOffset> {
+    value
+        .trim()
+        .parse::<FixedOffset>()
+        .map_err(|_| anyhow!("invalid utc offset: {}", value))
+}
+
+fn parse_uri(uri: &str) -> Result<rsip::Uri> {
+    let uri = match uri.contains(':') {
+        true => uri.trim().to_string(),
+        false => format!("sip:{}", uri.trim()),
+    };
+    rsip::Uri::try_from(uri.as_str()).map_err(|e| anyhow!("invalid uri {}: {}", uri, e))
+}
+
+impl Campaign {
+    pub fn new(
+        id: String,
+        request: CampaignRequest,
+        config: &CampaignConfig,
+        dnc: &HashSet<String>,
+    ) -> Result<Self> {
+        let agents = request
+            .agents
+            .iter()
+            .map(|agent| parse_uri(agent))
+            .collect::<Result<Vec<_>>>()?;
+        let first_agent = agents
+            .first()
+            .ok_or_else(|| anyhow!("a campaign needs agents"))?;
+        let caller_id = match request.caller_id.as_deref() {
+            Some(caller_id) => parse_target(caller_id, first_agent)?,
+            None => first_agent.clone(),
+        };
+        let mut domain = first_agent.clone();
+        if let Some(host) = request.domain.as_deref() {
+            domain.host_with_port = rsip::HostWithPort::try_from(host.trim())
+                .map_err(|e| anyhow!("invalid domain {}: {}", host, e))?;
+        }
+        let calling_hours = request
+            .calling_hours
+            .as_ref()
+            .map(CallingWindow::parse)
+            .transpose()?;
+        let utc_offset = match request.utc_offset.as_deref() {
+            Some(offset) => parse_offset(offset)?,
+            None => FixedOffset::east_opt(0).expect("zero offset"),
+        };
+
+        let mut rows = parse_csv(&request.csv).into_iter();
+        let header: Vec<String> = rows
+            .next()
+            .ok_or_else(|| anyhow!("the csv has no header"))?
+            .iter()
+            .map(|name| name.trim().to_lowercase())
+            .collect();
+        let number_column = header
+            .iter()
+            .position(|name| name == "number")
+            .ok_or_else(|| anyhow!("the csv has no number column"))?;
+        let mut targets = Vec::new();
+        for row in rows {
+            let raw = row.get(number_column).cloned().unwrap_or_default();
+            let mut variables = HashMap::new();
+            let mut target_offset = None;
+            for (name, value) in header.iter().zip(row.iter()) {
+                match name.as_str() {
+                    "number" => {}
+                    "utc_offset" if !value.trim().is_empty() => {
+                        target_offset = Some(parse_offset(value)?)
+                    }
+                    _ => {
+                        variables.insert(name.clone(), value.trim().to_string());
+                    }
+                }
+            }
+            let (number, disposition) = match normalize_number(&raw) {
+                Some(number) if on_dnc_list(dnc, &number) => (number, Some(Disposition::DoNotCall)),
+                Some(number) => (number, None),
+                None => (raw.trim().to_string(), Some(Disposition::Invalid)),
+            };
+            targets.push(CampaignTarget {
+                number,
+                variables,
+                attempts: 0,
+                disposition,
+                session_id: None,
+                agent: None,
+                utc_offset: target_offset,
+                in_call: false,
+                dialing: false,
+                retry_at: None,
+                dialed_at: None,
+                connected_at: None,
+            });
+        }
+        let pacing = request.pacing.unwrap_or_default();
+        Ok(Self {
+            id,
+            name: request.name,
+            created_at: Utc::now(),
+            agents,
+            caller_id,
+            domain,
+            calling_hours,
+            utc_offset,
+            max_concurrent: request.max_concurrent.unwrap_or(10).max(1),
+            pacing,
+            dial_ratio: request
+                .dial_ratio
+                .unwrap_or(match pacing {
+                    Pacing::Ratio => 1.0,
+                    Pacing::Predictive => 3.0,
+                })
+                .max(1.0),
+            max_abandon_rate: request.max_abandon_rate.unwrap_or(config.max_abandon_rate),
+            max_attempts: request.max_attempts.unwrap_or(1).max(1),
+            retry_interval: Duration::from_secs(request.retry_interval.unwrap_or(300)),
+            state: Mutex::new(CampaignState {
+                status: CampaignStatus::Running,
+                targets,
+                ringing: HashSet::new(),
+                stats: CallStats::default(),
+            }),
+            history: Arc::new(AnswerHistory::default()),
+            token: CancellationToken::new(),
+        })
+    }
+
+    /// Shares the answer history with other campaigns
+    pub fn with_history(mut self, history: Arc<AnswerHistory>) -> Self {
+        self.history = history;
+        self
+    }
+
+    fn answer_rate(&self, stats: &CallStats) -> f64 {
+        let prior = self.history.rate().unwrap_or(DEFAULT_ANSWER_RATE);
+        let rate = (stats.answered as f64 + prior * HISTORY_WEIGHT)
+            / (stats.attempts as f64 + HISTORY_WEIGHT);
+        // never dial more than twenty calls for an agent
+        rate.clamp(0.05, 1.0)
+    }
+
+    /// Calls to have in progress before agents take them
+    fn lines(&self, state: &CampaignState, idle: usize, now: DateTime<Utc>) -> usize {
+        let abandon_rate = Self::abandon_rate(&state.targets);
+        if abandon_rate > self.max_abandon_rate {
+            return idle;
+        }
+        if self.pacing == Pacing::Ratio {
+            return (idle as f64 * self.dial_ratio).floor() as usize;
+        }
+        let stats = &state.stats;
+        // agents whose calls run longer than it takes to get the next
+        // target on the line
+        let finishing = match (stats.talks, stats.setups) {
+            (talks, setups) if talks >= MIN_TALK_SAMPLES && setups > 0 => {
+                let talk = stats.talk / talks as i64;
+                let setup = stats.setup / setups as i64;
+                state
+                    .targets
+                    .iter()
+                    .filter(|t| t.in_call && !t.dialing)
+                    .filter_map(|t| t.connected_at)
+                    .filter(|at| (now - *at).num_milliseconds() >= talk - setup)
+                    .count()
+            }
+            _ => 0,
+        };
+        let agents = (idle + finishing) as f64;
+        let lines = (agents / self.answer_rate(stats)).min(agents * self.dial_ratio);
+        // dial ahead less as abandons near their cap
+        let headroom = match self.max_abandon_rate > 0.0 {
+            true => 1.0 - abandon_rate / self.max_abandon_rate,
+            false => 0.0,
+        };
+        let lines = agents + (lines - agents).max(0.0) * headroom;
+        (lines.floor() as usize).max(idle)
+    }
+
+    fn agent_aor(agent: &rsip::Uri) -> String {
+        PresenceState::aor(agent.user().unwrap_or_default(), &agent.host().to_string())
+    }
+
+    fn abandon_rate(targets: &[CampaignTarget]) -> f64 {
+        let count = |disposition| {
+            targets
+                .iter()
+                .filter(|t| t.disposition == Some(disposition))
+                .count()
+        };
+        let abandoned = count(Disposition::Abandoned);
+        match count(Disposition::Connected) + abandoned {
+            0 => 0.0,
+            answered => abandoned as f64 / answered as f64,
+        }
+    }
+
+    fn pending(&self, target: &CampaignTarget) -> bool {
+        !target.in_call
+            && match target.disposition {
+                None => true,
+                Some(disposition) => disposition.retry() && target.attempts < self.max_attempts,
+            }
+    }
+
+    /// The targets to dial now: as many as the idle agents times the dial
+    /// ratio, or one per idle agent while the abandon rate is over its cap.
+    /// `None` once the campaign is over
+    pub fn next_targets(&self, presence: &PresenceState, now: DateTime<Utc>) -> Option<Vec<Dial>> {
+        let mut state = self.state.lock().ok()?;
+        if state.status != CampaignStatus::Running {
+            return None;
+        }
+        let state = &mut *state;
+        if !state.targets.iter().any(|t| t.in_call || self.pending(t)) {
+            info!(campaign = self.id, "campaign completed");
+            state.status = CampaignStatus::Completed;
+            return None;
+        }
+        let idle = self
+            .agents
+            .iter()
+            .map(Self::agent_aor)
+            .filter(|aor| !presence.is_busy(aor) && !state.ringing.contains(aor))
+            .count();
+        let in_call = state.targets.iter().filter(|t| t.in_call).count();
+        let dialing = state.targets.iter().filter(|t| t.dialing).count();
+        let slots = self
+            .lines(state, idle, now)
+            .saturating_sub(dialing)
+            .min(self.max_concurrent.saturating_sub(in_call));
+
+        let mut dials = Vec::new();
+        for (index, target) in state.targets.iter_mut().enumerate() {
+            if dials.len() >= slots {
+                break;
+            }
+            let due = target.retry_at.is_none_or(|at| at <= now);
+            let offset = target.utc_offset.unwrap_or(self.utc_offset);
+            let open = self
+                .calling_hours
+                .as_ref()
+                .is_none_or(|hours| hours.allows(now.with_timezone(&offset)));
+            if !due || !open || !self.pending(target) {
+                continue;
+            }
+            let session_id = format!("campaign-{}-{}", self.id, make_tag());
+            target.attempts += 1;
+            target.in_call = true;
+            target.dialing = true;
+            target.session_id = Some(session_id.clone());
+            target.agent = None;
+            target.dialed_at = Some(now);
+            target.connected_at = None;
+            dials.push(Dial {
+                index,
+                number: target.number.clone(),
+                session_id,
+                variables: target.variables.clone(),
+            });
+        }
+        Some(dials)
+    }
+
+    /// An idle agent not tried yet for the call, rung until released
+    fn reserve_agent(&self, presence: &PresenceState, tried: &[rsip::Uri]) -> Option<rsip::Uri> {
+        let mut state = self.state.lock().ok()?;
+        let agent = self
+            .agents
+            .iter()
+            .filter(|agent| !tried.contains(agent))
+            .find(|agent| {
+                let aor = Self::agent_aor(agent);
+                !presence.is_busy(&aor) && !state.ringing.contains(&aor)
+            })?
+            .clone();
+        state.ringing.insert(Self::agent_aor(&agent));
+        Some(agent)
+    }
+
+    fn release_agent(&self, agent: &rsip::Uri) {
+        if let Ok(mut state) = self.state.lock() {
+            state.ringing.remove(&Self::agent_aor(agent));
+        }
+    }
+
+    /// The answered call of a target was put through to `agent`
+    pub fn connected(&self, index: usize, agent: &rsip::Uri, now: DateTime<Utc>) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let state = &mut *state;
+        let Some(target) = state.targets.get_mut(index) else {
+            return;
+        };
+        target.dialing = false;
+        target.agent = Some(agent.to_string());
+        // counts as answered while the call goes on
+        target.disposition = Some(Disposition::Connected);
+        target.connected_at = Some(now);
+        if let Some(dialed_at) = target.dialed_at {
+            state.stats.setup += (now - dialed_at).num_milliseconds();
+            state.stats.setups += 1;
+        }
+    }
+
+    /// The call to a target ended
+    pub fn finish(&self, index: usize, disposition: Disposition, now: DateTime<Utc>) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let state = &mut *state;
+        let stopped = state.status == CampaignStatus::Stopped;
+        let Some(target) = state.targets.get_mut(index) else {
+            return;
+        };
+        let answered = matches!(disposition, Disposition::Connected | Disposition::Abandoned);
+        if target.dialed_at.is_some() {
+            state.stats.attempts += 1;
+            state.stats.answered += answered as u64;
+            self.history.record(answered);
+        }
+        if let Some(connected_at) = target.connected_at.take() {
+            state.stats.talk += (now - connected_at).num_milliseconds();
+            state.stats.talks += 1;
+        }
+        target.in_call = false;
+        target.dialing = false;
+        target.disposition = Some(disposition);
+        if disposition.retry() && target.attempts < self.max_attempts {
+            target.retry_at =
+                Some(now + chrono::Duration::from_std(self.retry_interval).unwrap_or_default());
+            if stopped {
+                target.disposition = Some(Disposition::Cancelled);
+            }
+        }
+        info!(
+            campaign = self.id,
+            number = target.number,
+            attempts = target.attempts,
+            ?disposition,
+            "campaign target done"
+        );
+    }
+
+    /// Stops dialling, calls in progress go on. Targets not dialled yet
+    /// are cancelled
+    pub fn stop(&self) {
+        self.token.cancel();
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if state.status != CampaignStatus::Running {
+            return;
+        }
+        state.status = CampaignStatus::Stopped;
+        let pending: Vec<usize> = (0..state.targets.len())
+            .filter(|i| self.pending(&state.targets[*i]))
+            .collect();
+        for index in pending {
+            state.targets[index].disposition = Some(Disposition::Cancelled);
+        }
+    }
+
+    pub fn report(&self, with_targets: bool) -> CampaignReport {
+        let (status, targets, answer_rate) = match self.state.lock() {
+            Ok(state) => (
+                state.status,
+                state.targets.clone(),
+                self.answer_rate(&state.stats),
+            ),
+            Err(_) => (CampaignStatus::Stopped, Vec::new(), 0.0),
+        };
+        let mut dispositions = HashMap::new();
+        for disposition in targets.iter().filter_map(|t| t.disposition) {
+            *dispositions.entry(disposition).or_default() += 1;
+        }
+        CampaignReport {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            status,
+            created_at: self.created_at,
+            in_call: targets.iter().filter(|t| t.in_call).count(),
+            dialing: targets.iter().filter(|t| t.dialing).count(),
+            abandon_rate: Self::abandon_rate(&targets),
+            answer_rate,
+            dispositions,
+            targets: with_targets.then_some(targets),
+        }
+    }
+
+    /// Dials the campaign until it is over, on each presence change and
+    /// every second for calling hours and retries
+    async fn run(self: Arc<Self>, server: SipServerRef) {
+        let token = self.token.clone();
+        let server_token = server.cancel_token.clone();
+        let mut changes = server.presence.subscribe();
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = server_token.cancelled() => break,
+                change = changes.recv() => {
+                    if let Err(tokio::sync::broadcast::error::RecvError::Closed) = change {
+                        break;
+                    }
+                }
+                _ = ticker.tick() => {}
+            }
+            let Some(dials) = self.next_targets(&server.presence, Utc::now()) else {
+                break;
+            };
+            for dial in dials {
+                let campaign = self.clone();
+                let server = server.clone();
+                tokio::spawn(async move {
+                    let index = dial.index;
+                    let disposition = campaign.dial(&server, dial).await;
+                    campaign.finish(index, disposition, Utc::now());
+                });
+            }
+        }
+    }
+
+    /// Calls a target, and puts the answered call through to an idle agent
+    async fn dial(&self, server: &SipServerRef, dial: Dial) -> Disposition {
+        let target = match parse_target(&dial.number, &self.domain) {
+            Ok(target) => target,
+            Err(_) => return Disposition::Invalid,
+        };
+        info!(campaign = self.id, session_id = dial.session_id, %target, "campaign dial");
+        let active_call = Arc::new(ActiveCall::new(
+            ActiveCallType::B2bua,
+            server.cancel_token.child_token(),
+            dial.session_id.clone(),
+            crate::call::sip::Invitation::new(server.dialog_layer.clone())
+                .with_resolver(server.resolver.clone())
+                .with_retransmitter(server.retransmitter.clone()),
+            server.app_state.clone(),
+            TrackConfig::default(),
+            None,
+            false,
+            None,
+            None,
+        ));
+        let variables = active_call.variables();
+        variables.extend(dial.variables);
+        variables.set("campaign.id", self.id.clone());
+        variables.set("campaign.number", dial.number.clone());
+
+        let disposition = Mutex::new(Disposition::Failed);
+        let agent_presence: Mutex<Option<CallPresence>> = Mutex::new(None);
+        let set = |value| {
+            if let Ok(mut disposition) = disposition.lock() {
+                *disposition = value;
+            }
+        };
+        let connect = async {
+            let contact = local_contact(server, "campaign")?;
+            let config = server.config.clone();
+            let session_id = active_call.session_id.clone();
+            if let Err(e) = dial_routed(
+                server,
+                &config,
+                &active_call,
+                &session_id,
+                &self.caller_id,
+                &target,
+                &contact,
+            )
+            .await
+            {
+                let cause = active_call
+                    .call_state
+                    .read()
+                    .ok()
+                    .and_then(|cs| cs.hangup_cause);
+                set(Disposition::unanswered(cause));
+                return Err(e);
+            }
+            set(Disposition::Abandoned);
+            let track_id = active_call.server_side_track_id.clone();
+            let mut tried = Vec::new();
+            while let Some(agent) = self.reserve_agent(&server.presence, &tried) {
+                let answered = match resolve_targets(server, &agent).await {
+                    Ok(locations) => {
+                        ring_locations(
+                            &active_call,
+                            &track_id,
+                            select_flows(locations, None),
+                            &target,
+                            &contact,
+                        )
+                        .await
+                    }
+                    Err((e, _)) => Err(e),
+                };
+                if matches!(answered, Ok(true)) {
+                    if let Ok(mut presence) = agent_presence.lock() {
+                        *presence = Some(server.presence.enter_call(vec![Self::agent_aor(&agent)]));
+                    }
+                    self.release_agent(&agent);
+                    self.connected(dial.index, &agent, Utc::now());
+                    set(Disposition::Connected);
+                    info!(campaign = self.id, session_id, %agent, "campaign call connected");
+                    return Ok(());
+                }
+                self.release_agent(&agent);
+                tried.push(agent);
+            }
+            Err(anyhow!("no agent took the call"))
+        };
+        if let Err(e) = serve_call(server, &active_call, &[&target], connect).await {
+            info!(
+                campaign = self.id,
+                session_id = dial.session_id,
+                "campaign call ended: {}",
+                e
+            );
+        }
+        disposition.into_inner().unwrap_or(Disposition::Failed)
+    }
+}
+
+/// The campaigns started since the server started
+#[derive(Default)]
+pub struct Campaigns {
+    campaigns: Mutex<HashMap<String, Arc<Campaign>>>,
+    history: Arc<AnswerHistory>,
+}
+
+impl Campaigns {
+    pub async fn start(
+        &self,
+        server: &SipServerRef,
+        request: CampaignRequest,
+    ) -> Result<CampaignReport> {
+        let config = server.config.campaign.clone().unwrap_or_default();
+        let dnc = load_dnc(&config).await?;
+        let id = format!("{}", rand::random::<u32>());
+        let campaign = Arc::new(
+            Campaign::new(id.clone(), request, &config, &dnc)?.with_history(self.history.clone()),
+        );
+        info!(
+            campaign = id,
+            name = campaign.name,
+            agents = campaign.agents.len(),
+            "campaign started"
+        );
+        if let Ok(mut campaigns) = self.campaigns.lock() {
+            campaigns.insert(id, campaign.clone());
+        }
+        tokio::spawn(campaign.clone().run(server.clone()));
+        Ok(campaign.report(false))
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<Campaign>> {
+        self.campaigns.lock().ok()?.get(id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<Arc<Campaign>> {
+        self.campaigns
+            .lock()
+            .map(|campaigns| campaigns.values().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+fn not_found() -> Response {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({"error": "campaign not found"})),
+    )
+        .into_response()
+}
+
+/// The campaign API, mounted under the AMI
+pub fn router(server: SipServerRef) -> Router {
+    Router::new()
+        .route(
+            "/ami/v1/campaigns",
+            get(list_campaigns_handler).post(create_campaign_handler),
+        )
+        .route("/ami/v1/campaigns/{id}", get(get_campaign_handler))
+        .route("/ami/v1/campaigns/{id}/stop", post(stop_campaign_handler))
+        .with_state(server)
+}
+
+async fn create_campaign_handler(
+    State(server): State<SipServerRef>,
+    Json(request): Json<CampaignRequest>,
+) -> Response {
+    match server.campaigns.start(&server, request).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => {
+            warn!("failed to start campaign: {}", e);
+            (
+                StatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn list_campaigns_handler(State(server): State<SipServerRef>) -> Response {
+    let reports: Vec<CampaignReport> = server
+        .campaigns
+        .list()
+        .iter()
+        .map(|campaign| campaign.report(false))
+        .collect();
+    Json(reports).into_response()
+}
+
+async fn get_campaign_handler(
+    State(server): State<SipServerRef>,
+    Path(id): Path<String>,
+) -> Response {
+    match server.campaigns.get(&id) {
+        Some(campaign) => Json(campaign.report(true)).into_response(),
+        None => not_found(),
+    }
+}
+
+async fn stop_campaign_handler(
+    State(server): State<SipServerRef>,
+    Path(id): Path<String>,
+) -> Response {
+    match server.campaigns.get(&id) {
+        Some(campaign) => {
+            info!(campaign = id, "campaign stopped");
+            campaign.stop();
+            Json(campaign.report(false)).into_response()
+        }
+        None => not_found(),
+    }
+}
@@ -0,0 +1,229 @@
+use super::{
+    ProxyAction, ProxyModule,
+    clicktodial::{ClickToDial, click_to_dial},
+    presence::PresenceState,
+    server::SipServerRef,
+};
+use crate::call::{SipUser, TransactionCookie};
+use crate::config::{CampOnConfig, ProxyConfig};
+use anyhow::Result;
+use async_trait::async_trait;
+use rsip::prelude::HeadersExt;
+use rsipstack::transaction::transaction::Transaction;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// A caller waiting for a busy callee, both as `user@realm`
+#[derive(Debug, Clone)]
+pub struct CampOn {
+    pub caller: String,
+    pub callee: String,
+    pub expires_at: Instant,
+}
+
+/// Call-back on busy: a caller dials the feature code to queue a callback,
+/// and once presence shows both parties free the proxy rings the caller and
+/// connects them to the callee
+#[derive(Clone)]
+pub struct CampOnModule {
+    server: SipServerRef,
+    config: Arc<ProxyConfig>,
+    camp_on: CampOnConfig,
+    pending: Arc<Mutex<Vec<CampOn>>>,
+    /// The last busy callee each caller tried, for the bare feature code
+    last_busy: Arc<Mutex<HashMap<String, String>>>,
+}
+
+impl CampOnModule {
+    pub fn create(server: SipServerRef, config: Arc<ProxyConfig>) -> Result<Box<dyn ProxyModule>> {
+        let module = CampOnModule::new(server, config);
+        Ok(Box::new(module))
+    }
+
+    pub fn new(server: SipServerRef, config: Arc<ProxyConfig>) -> Self {
+        Self {
+            server,
+            camp_on: config.camp_on.clone().unwrap_or_default(),
+            config,
+            pending: Arc::new(Mutex::new(Vec::new())),
+            last_busy: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn pending(&self) -> Vec<CampOn> {
+        self.pending
+            .lock()
+            .map(|pending| pending.clone())
+            .unwrap_or_default()
+    }
+
+    fn queue(&self, caller: String, callee: String) {
+        let expires_at = Instant::now() + Duration::from_secs(self.camp_on.expires);
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.retain(|c| !(c.caller == caller && c.callee == callee));
+            pending.push(CampOn {
+                caller,
+                callee,
+                expires_at,
+            });
+        }
+    }
+
+    /// Places the callbacks whose parties are both free
+    async fn run_due(&self) {
+        let due = match self.pending.lock() {
+            Ok(mut pending) => take_due(&mut pending, &self.server.presence, Instant::now()),
+            Err(_) => return,
+        };
+        for camp_on in due {
+            let request = ClickToDial {
+                user: camp_on.caller.clone(),
+                target: camp_on.callee.clone(),
+                caller_id: None,
+                variables: None,
+            };
+            match click_to_dial(self.server.clone(), self.config.clone(), request).await {
+                Ok(session_id) => {
+                    info!(
+                        session_id,
+                        caller = camp_on.caller,
+                        callee = camp_on.callee,
+                        "camp-on callback"
+                    );
+                }
+                Err((e, _)) => {
+                    // the caller may be unregistered for a moment, retry until it expires
+                    debug!(
+                        caller = camp_on.caller,
+                        callee = camp_on.callee,
+                        "camp-on callback deferred: {}",
+                        e
+                    );
+                    if let Ok(mut pending) = self.pending.lock() {
+                        pending.push(camp_on);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Removes expired requests, and returns the ones ready to call back
+pub fn take_due(pending: &mut Vec<CampOn>, presence: &PresenceState, now: Instant) -> Vec<CampOn> {
+    pending.retain(|c| c.expires_at > now);
+    let (due, waiting) = pending
+        .drain(..)
+        .partition(|c| !presence.is_busy(&c.callee) && !presence.is_busy(&c.caller));
+    *pending = waiting;
+    due
+}
+
+#[async_trait]
+impl ProxyModule for CampOnModule {
+    fn name(&self) -> &str {
+        "campon"
+    }
+
+    async fn on_start(&mut self) -> Result<()> {
+        let module = self.clone();
+        let token = self.server.cancel_token.child_token();
+        let mut changes = self.server.presence.subscribe();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(30));
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    change = changes.recv() => match change {
+                        Ok(change) if change.busy => continue,
+                        Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                        _ => {}
+                    },
+                    _ = ticker.tick() => {}
+                }
+                module.run_due().await;
+            }
+        });
+        debug!("Camp-on module started");
+        Ok(())
+    }
+
+    async fn on_stop(&self) -> Result<()> {
+        debug!("Camp-on module stopped");
+        Ok(())
+    }
+
+    async fn on_transaction_begin(
+        &self,
+        _token: CancellationToken,
+        tx: &mut Transaction,
+        cookie: TransactionCookie,
+    ) -> Result<ProxyAction> {
+        if tx.original.method != rsip::Method::Invite {
+            return Ok(ProxyAction::Continue);
+        }
+        let caller = match cookie.get_user() {
+            Some(user) => user,
+            None => SipUser::try_from(&*tx)?,
+        };
+        let caller_realm = caller
+            .realm
+            .clone()
+            .unwrap_or_else(|| tx.original.uri.host().to_string());
+        let caller = PresenceState::aor(&caller.username, &caller_realm);
+        let to = tx.original.to_header()?.uri()?;
+        let dialled = to.user().unwrap_or_default().to_string();
+        let realm = to.host().to_string();
+
+        let Some(extension) = dialled.strip_prefix(&self.camp_on.feature_code) else {
+            let callee = PresenceState::aor(&dialled, &realm);
+            if self.server.presence.is_busy(&callee)
+                && let Ok(mut last_busy) = self.last_busy.lock()
+            {
+                last_busy.insert(caller, callee);
+            }
+            return Ok(ProxyAction::Continue);
+        };
+
+        let callee = if extension.is_empty() {
+            self.last_busy
+                .lock()
+                .ok()
+                .and_then(|last_busy| last_busy.get(&caller).cloned())
+        } else {
+            Some(PresenceState::aor(extension, &realm))
+        };
+        let Some(callee) = callee else {
+            info!(caller, "no busy extension to camp on");
+            tx.reply(rsip::StatusCode::NotFound).await.ok();
+            return Ok(ProxyAction::Abort);
+        };
+        if callee == caller {
+            tx.reply(rsip::StatusCode::BadRequest).await.ok();
+            return Ok(ProxyAction::Abort);
+        }
+
+        info!(caller, callee, "camp-on queued");
+        self.queue(caller, callee);
+        // the call itself is not completed, the callback comes later
+        tx.reply_with(
+            rsip::StatusCode::TemporarilyUnavailable,
+            vec![rsip::Header::Other(
+                "Reason".into(),
+                "SIP;cause=480;text=\"Callback queued\"".into(),
+            )],
+            None,
+        )
+        .await
+        .map_err(|e| warn!(key = %tx.key, "failed to reply: {}", e))
+        .ok();
+
+        let module = self.clone();
+        tokio::spawn(async move { module.run_due().await });
+        Ok(ProxyAction::Abort)
+    }
+}
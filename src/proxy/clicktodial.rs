@@ -0,0 +1,569 @@
+use super::{
+    ProxyAction, ProxyModule,
+    call::{DefaultRouteInvite, select_flows},
+    message::resolve_targets,
+    metering::call_tenant,
+    presence::PresenceState,
+    server::{SipServerInner, SipServerRef},
+};
+use crate::call::{
+    ActiveCall, ActiveCallRef, ActiveCallState, ActiveCallType, CallOption, Location, RouteInvite,
+    TransactionCookie, active_call::ActiveCallStateRef,
+};
+use crate::config::{ProxyConfig, RouteResult};
+use crate::media::track::TrackConfig;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use axum::{
+    Json,
+    http::StatusCode as HttpStatusCode,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use rsip::prelude::HeadersExt;
+use rsipstack::{
+    dialog::invitation::InviteOption,
+    transaction::{make_tag, transaction::Transaction},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// A click-to-dial request: ring `user` first, then connect them to `target`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClickToDial {
+    /// The user's extension, e.g. `sip:1001@example.com`
+    pub user: String,
+    /// A SIP URI, or a bare number dialled in the user's realm
+    pub target: String,
+    /// Shown on the user's phone while it rings, the target by default
+    pub caller_id: Option<String>,
+    /// Call variables to tag the call with
+    pub variables: Option<HashMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClickToDialResult {
+    pub session_id: String,
+}
+
+/// Turns out-of-dialog REFER (RFC 3515) into click-to-dial calls: the
+/// Request-URI is the user to ring, Refer-To the party to connect them to
+#[derive(Clone)]
+pub struct ClickToDialModule {
+    server: SipServerRef,
+    config: Arc<ProxyConfig>,
+}
+
+impl ClickToDialModule {
+    pub fn create(server: SipServerRef, config: Arc<ProxyConfig>) -> Result<Box<dyn ProxyModule>> {
+        let module = ClickToDialModule::new(server, config);
+        Ok(Box::new(module))
+    }
+
+    pub fn new(server: SipServerRef, config: Arc<ProxyConfig>) -> Self {
+        Self { server, config }
+    }
+}
+
+#[async_trait]
+impl ProxyModule for ClickToDialModule {
+    fn name(&self) -> &str {
+        "clicktodial"
+    }
+
+    fn allow_methods(&self) -> Vec<rsip::Method> {
+        vec![rsip::Method::Refer]
+    }
+
+    async fn on_start(&mut self) -> Result<()> {
+        debug!("Click-to-dial module started");
+        Ok(())
+    }
+
+    async fn on_stop(&self) -> Result<()> {
+        debug!("Click-to-dial module stopped");
+        Ok(())
+    }
+
+    async fn on_transaction_begin(
+        &self,
+        _token: CancellationToken,
+        tx: &mut Transaction,
+        _cookie: TransactionCookie,
+    ) -> Result<ProxyAction> {
+        if tx.original.method != rsip::Method::Refer || tx.original.to_header()?.tag()?.is_some() {
+            return Ok(ProxyAction::Continue);
+        }
+        let Some(refer_to) = refer_to(&tx.original) else {
+            tx.reply(rsip::StatusCode::BadRequest).await.ok();
+            return Ok(ProxyAction::Abort);
+        };
+        let request = ClickToDial {
+            user: tx.original.uri.to_string(),
+            target: refer_to,
+            caller_id: None,
+            variables: None,
+        };
+        match click_to_dial(self.server.clone(), self.config.clone(), request).await {
+            Ok(session_id) => {
+                info!(key = %tx.key, session_id, "click-to-dial accepted");
+                tx.reply(rsip::StatusCode::Accepted).await.ok();
+            }
+            Err((e, status)) => {
+                warn!(key = %tx.key, "click-to-dial failed: {}", e);
+                tx.reply(status).await.ok();
+            }
+        }
+        Ok(ProxyAction::Abort)
+    }
+}
+
+/// The URI of the Refer-To header, without its angle brackets
+pub(crate) fn refer_to(req: &rsip::Request) -> Option<String> {
+    req.headers.iter().find_map(|h| match h {
+        rsip::Header::Other(name, value)
+            if name.eq_ignore_ascii_case("Refer-To") || name.eq_ignore_ascii_case("r") =>
+        {
+            let value = value.trim();
+            let uri = match (value.find('<'), value.find('>')) {
+                (Some(start), Some(end)) if start < end => &value[start + 1..end],
+                _ => value.split(';').next().unwrap_or_default(),
+            };
+            Some(uri.trim().to_string())
+        }
+        _ => None,
+    })
+}
+
+/// Parses a SIP URI, the `sip:` scheme may be left out
+fn parse_uri(uri: &str) -> Result<rsip::Uri> {
+    let uri = if uri.contains(':') {
+        uri.to_string()
+    } else {
+        format!("sip:{}", uri)
+    };
+    rsip::Uri::try_from(uri.as_str()).map_err(|e| anyhow!("invalid uri {}: {}", uri, e))
+}
+
+/// Parses a target, dialling bare numbers in the user's realm
+pub(crate) fn parse_target(target: &str, user: &rsip::Uri) -> Result<rsip::Uri> {
+    let target = target.trim();
+    if target.contains(':') || target.contains('@') {
+        return parse_uri(target);
+    }
+    let mut uri = user.clone();
+    uri.auth = Some(rsip::Auth {
+        user: target.to_string(),
+        password: None,
+    });
+    uri.params.clear();
+    Ok(uri)
+}
+
+/// Starts a click-to-dial call and returns its session id once the user is
+/// known to be registered, the dialling itself runs in the background
+pub async fn click_to_dial(
+    server: SipServerRef,
+    config: Arc<ProxyConfig>,
+    request: ClickToDial,
+) -> Result<String, (anyhow::Error, rsip::StatusCode)> {
+    let user = parse_uri(request.user.trim()).map_err(|e| (e, rsip::StatusCode::BadRequest))?;
+    if !server.is_same_realm(&user.host().to_string()).await {
+        return Err((
+            anyhow!("{} is not a local user", user),
+            rsip::StatusCode::Forbidden,
+        ));
+    }
+    let target =
+        parse_target(&request.target, &user).map_err(|e| (e, rsip::StatusCode::BadRequest))?;
+    let caller_id = match request.caller_id.as_deref() {
+        Some(caller_id) => {
+            parse_target(caller_id, &user).map_err(|e| (e, rsip::StatusCode::BadRequest))?
+        }
+        None => target.clone(),
+    };
+    let locations = select_flows(resolve_targets(&server, &user).await?, None);
+
+    let session_id = format!("c2d-{}-{}", rand::random::<u32>(), make_tag());
+    info!(session_id, %user, %target, "click-to-dial");
+    let active_call = Arc::new(ActiveCall::new(
+        ActiveCallType::B2bua,
+        server.cancel_token.child_token(),
+        session_id.clone(),
+        crate::call::sip::Invitation::new(server.dialog_layer.clone())
+            .with_resolver(server.resolver.clone())
+            .with_retransmitter(server.retransmitter.clone()),
+        server.app_state.clone(),
+        TrackConfig::default(),
+        None,
+        false,
+        None,
+        None,
+    ));
+    active_call
+        .variables()
+        .extend(request.variables.unwrap_or_default());
+    tokio::spawn(async move {
+        let session_id = active_call.session_id.clone();
+        if let Err(e) = run_call(
+            server,
+            config,
+            active_call,
+            locations,
+            user,
+            target,
+            caller_id,
+        )
+        .await
+        {
+            warn!(session_id, "click-to-dial ended: {}", e);
+        }
+    });
+    Ok(session_id)
+}
+
+async fn run_call(
+    server: SipServerRef,
+    config: Arc<ProxyConfig>,
+    active_call: ActiveCallRef,
+    locations: Vec<Location>,
+    user: rsip::Uri,
+    target: rsip::Uri,
+    caller_id: rsip::Uri,
+) -> Result<()> {
+    let dial = connect(
+        &server,
+        &config,
+        &active_call,
+        locations,
+        &user,
+        &target,
+        &caller_id,
+    );
+    serve_call(&server, &active_call, &[&user, &target], dial).await
+}
+
+/// Serves a call the proxy originates while `dial` sets up its legs, as a
+/// call of `parties` for limits, metering and presence. The call ends if
+/// `dial` fails
+pub(crate) async fn serve_call(
+    server: &SipServerRef,
+    active_call: &ActiveCallRef,
+    parties: &[&rsip::Uri],
+    dial: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    let app_state = server.app_state.clone();
+    let cancel_token = active_call.cancel_token.clone();
+    let session_id = active_call.session_id.clone();
+    let _slot = server.routing_state.limiter.enter(session_id.clone());
+    let tenant = call_tenant(server, parties).await;
+    let _channel = server.meter.enter(session_id.clone(), tenant);
+    app_state
+        .active_calls
+        .lock()
+        .await
+        .insert(session_id.clone(), active_call.clone());
+    let _presence = server.presence.enter_call(
+        parties
+            .iter()
+            .map(|uri| PresenceState::aor(uri.user().unwrap_or_default(), &uri.host().to_string()))
+            .collect(),
+    );
+
+    let dial = async {
+        let r = dial.await;
+        if r.is_err() {
+            cancel_token.cancel();
+        }
+        r
+    };
+    let (r, _) = tokio::join!(dial, active_call.serve());
+    app_state.active_calls.lock().await.remove(&session_id);
+    r
+}
+
+/// Rings the user's devices in turn, then calls the target over the server
+/// side track so the media stream bridges the two legs
+async fn connect(
+    server: &SipServerRef,
+    config: &Arc<ProxyConfig>,
+    active_call: &ActiveCall,
+    locations: Vec<Location>,
+    user: &rsip::Uri,
+    target: &rsip::Uri,
+    caller_id: &rsip::Uri,
+) -> Result<()> {
+    let contact = local_contact(server, "clicktodial")?;
+    let session_id = active_call.session_id.clone();
+    if !ring_locations(active_call, &session_id, locations, caller_id, &contact).await? {
+        return Err(anyhow!("{} did not answer", user));
+    }
+    let track_id = active_call.server_side_track_id.clone();
+    dial_routed(
+        server,
+        config,
+        active_call,
+        &track_id,
+        user,
+        target,
+        &contact,
+    )
+    .await?;
+    info!(session_id = active_call.session_id, %user, %target, "click-to-dial connected");
+    Ok(())
+}
+
+/// The call state of a leg: the call's own for the session track, a new
+/// one referred to by it for the server side track
+fn leg_call_state(
+    active_call: &ActiveCall,
+    track_id: &str,
+    option: CallOption,
+) -> Result<ActiveCallStateRef> {
+    if track_id == active_call.session_id {
+        active_call
+            .call_state
+            .write()
+            .map_err(|e| anyhow!("{}", e))?
+            .option = Some(option);
+        return Ok(active_call.call_state.clone());
+    }
+    let call_state = Arc::new(RwLock::new(ActiveCallState {
+        start_time: Utc::now(),
+        ssrc: rand::random::<u32>(),
+        option: Some(option),
+        variables: active_call.variables(),
+        ..Default::default()
+    }));
+    if let Ok(mut cs) = active_call.call_state.write() {
+        cs.refer_callstate = Some(call_state.clone());
+    }
+    Ok(call_state)
+}
+
+/// Rings `locations` in turn on `track_id` with `caller` shown, and tells
+/// whether one answered. The call ends when the answered leg hangs up
+pub(crate) async fn ring_locations(
+    active_call: &ActiveCall,
+    track_id: &str,
+    locations: Vec<Location>,
+    caller: &rsip::Uri,
+    contact: &rsip::Uri,
+) -> Result<bool> {
+    let late_offer = active_call
+        .app_state
+        .config
+        .proxy
+        .as_ref()
+        .and_then(|proxy| proxy.late_offer);
+    for location in locations {
+        let option = CallOption {
+            caller: Some(caller.to_string()),
+            callee: Some(location.aor.to_string()),
+            late_offer,
+            ..Default::default()
+        };
+        let invite_option = leg_invite_option(&option, &location, contact)?;
+        let call_state = leg_call_state(active_call, track_id, option)?;
+        // a device that is busy must not end the whole call
+        let leg_token = active_call.cancel_token.child_token();
+        match active_call
+            .create_outgoing_sip_track(
+                leg_token.clone(),
+                call_state,
+                &track_id.to_string(),
+                invite_option,
+            )
+            .await
+        {
+            Ok(_) => {
+                hangup_with(leg_token, active_call.cancel_token.clone());
+                return Ok(true);
+            }
+            Err(e) => {
+                info!(session_id = active_call.session_id, aor = %location.aor, "leg failed: {}", e);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Calls `target` on `track_id` as if `user` dialled it, so routing rules
+/// and trunks apply
+pub(crate) async fn dial_routed(
+    server: &SipServerRef,
+    config: &Arc<ProxyConfig>,
+    active_call: &ActiveCall,
+    track_id: &str,
+    user: &rsip::Uri,
+    target: &rsip::Uri,
+    contact: &rsip::Uri,
+) -> Result<()> {
+    let origin = make_origin(server, &active_call.session_id, user, target)?;
+    let targets = resolve_targets(server, target).await.map_err(|(e, _)| e)?;
+    let route_invite = match server.create_route_invite.as_ref() {
+        Some(f) => f(server.clone(), config.clone())?,
+        None => Box::new(DefaultRouteInvite {
+            routing_state: server.routing_state.clone(),
+            config: config.clone(),
+        }) as Box<dyn RouteInvite>,
+    };
+    for location in select_flows(targets, None) {
+        let mut option = CallOption {
+            caller: Some(user.to_string()),
+            callee: Some(location.aor.to_string()),
+            late_offer: config.late_offer,
+            ..Default::default()
+        };
+        let invite_option = leg_invite_option(&option, &location, contact)?;
+        let invite_option = match route_invite.route_invite(invite_option, &origin).await? {
+            RouteResult::Forward(option) => option,
+            RouteResult::Abort(code, reason) => {
+                return Err(anyhow!("route abort: {} {}", code, reason));
+            }
+            RouteResult::Announce(announcement) => {
+                return Err(anyhow!("route announces {}", announcement));
+            }
+        };
+        option.transrating = route_invite.take_transrating(&origin);
+        if let Some(trunk) = server.routing_state.limiter.trunk(&active_call.session_id) {
+            server.meter.set_trunk(&active_call.session_id, &trunk);
+        }
+        let call_state = leg_call_state(active_call, track_id, option)?;
+        let leg_token = active_call.cancel_token.child_token();
+        match active_call
+            .create_outgoing_sip_track(
+                leg_token.clone(),
+                call_state,
+                &track_id.to_string(),
+                invite_option,
+            )
+            .await
+        {
+            Ok(_) => {
+                hangup_with(leg_token, active_call.cancel_token.clone());
+                return Ok(());
+            }
+            Err(e) => {
+                info!(session_id = active_call.session_id, aor = %location.aor, "target leg failed: {}", e);
+            }
+        }
+    }
+    Err(anyhow!("{} did not answer", target))
+}
+
+/// Ends the call when an answered leg hangs up
+fn hangup_with(leg_token: CancellationToken, call_token: CancellationToken) {
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = leg_token.cancelled() => call_token.cancel(),
+            _ = call_token.cancelled() => {}
+        }
+    });
+}
+
+fn leg_invite_option(
+    option: &CallOption,
+    location: &Location,
+    contact: &rsip::Uri,
+) -> Result<InviteOption> {
+    let mut invite_option = option.build_invite_option()?;
+    invite_option.destination = Some(location.destination.clone());
+    if let Some(first_hop) = location.route_set.first() {
+        invite_option.destination = Some(first_hop.try_into()?);
+        invite_option.headers = location.route_header().map(|route| vec![route]);
+    }
+    invite_option.contact = contact.clone();
+    Ok(invite_option)
+}
+
+/// Contact of the proxy itself, both legs send their BYE back to us
+pub(crate) fn local_contact(server: &SipServerInner, user: &str) -> Result<rsip::Uri> {
+    let addr = server
+        .endpoint
+        .get_addrs()
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow!("no listening address"))?;
+    Ok(rsip::Uri {
+        scheme: Some(rsip::Scheme::Sip),
+        auth: Some(rsip::Auth {
+            user: user.to_string(),
+            password: None,
+        }),
+        host_with_port: addr.addr,
+        ..Default::default()
+    })
+}
+
+/// The INVITE the user would have sent to reach the target, so routing rules
+/// and trunks apply to click-to-dial like to a dialled call
+/// The INVITE routing sees for the target leg, its Call-ID is the session
+/// id so trunk call limits are released with the session
+fn make_origin(
+    server: &SipServerInner,
+    session_id: &str,
+    user: &rsip::Uri,
+    target: &rsip::Uri,
+) -> Result<rsip::Request> {
+    let via = server.endpoint.inner.get_via(None, None)?;
+    let from = rsip::typed::From {
+        display_name: None,
+        uri: user.clone(),
+        params: vec![],
+    }
+    .with_tag(make_tag());
+    let to = rsip::typed::To {
+        display_name: None,
+        uri: target.clone(),
+        params: vec![],
+    };
+    let mut origin =
+        server
+            .endpoint
+            .inner
+            .make_request(rsip::Method::Invite, target.clone(), via, from, to, 1);
+    origin
+        .headers
+        .retain(|h| !matches!(h, rsip::Header::CallId(_)));
+    origin.headers.push(rsip::Header::CallId(session_id.into()));
+    Ok(origin)
+}
+
+/// Places the call, for a principal bound to `tenant` only from that
+/// tenant's users
+pub async fn click_to_dial_handler(
+    server: SipServerRef,
+    config: Arc<ProxyConfig>,
+    request: ClickToDial,
+    tenant: Option<String>,
+) -> Response {
+    if let Some(tenant) = tenant {
+        let owner = match parse_uri(request.user.trim()) {
+            Ok(user) => call_tenant(&server, &[&user]).await,
+            Err(_) => None,
+        };
+        if owner.as_ref() != Some(&tenant) {
+            return (
+                HttpStatusCode::FORBIDDEN,
+                Json(serde_json::json!({"error": format!("{} is not a user of {}", request.user, tenant)})),
+            )
+                .into_response();
+        }
+    }
+    match click_to_dial(server, config, request).await {
+        Ok(session_id) => Json(ClickToDialResult { session_id }).into_response(),
+        Err((e, status)) => (
+            HttpStatusCode::from_u16(status.code()).unwrap_or(HttpStatusCode::BAD_REQUEST),
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
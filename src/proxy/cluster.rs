@@ -0,0 +1,397 @@
+//! Several rustpbx nodes acting as one registrar.
+//!
+//! The nodes share the database locator, so a binding registered through
+//! one node is found by all of them and a refresh through any node updates
+//! it for all. Each binding records the node it was registered through,
+//! the one holding the UA's NAT binding. Nodes heartbeat into the database,
+//! and when one goes silent for `node_timeout` the first live node by id
+//! takes over its bindings and subscriptions. It keeps notifying the
+//! subscribers, and with NAT keepalives pings the bindings until the UAs
+//! register again. The pings leave from the surviving node's address, so
+//! they only reach UAs whose NAT or firewall accepts packets from it.
+use super::{
+    locator_db::{self, is_live, model_location, unix_timestamp},
+    presence::Subscription,
+    server::SipServerRef,
+    status::{ProxyStatus, ProxyStatusSender},
+};
+use crate::{call::Location, config::ClusterConfig};
+use anyhow::Result;
+use sea_orm::{Database, Set, entity::prelude::*, sea_query::OnConflict};
+use sea_orm_migration::{
+    prelude::*,
+    schema::{big_integer, string, string_null, text},
+};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+pub mod node {
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "rustpbx_cluster_nodes")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub node_id: String,
+        /// Unix time of the last heartbeat, in seconds
+        pub heartbeat: i64,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+pub mod subscription {
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "rustpbx_subscriptions")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub call_id: String,
+        pub aor: String,
+        /// Unix time the subscription ends at, in seconds
+        pub expires: i64,
+        /// The node holding the subscription
+        pub node: String,
+        /// The subscription dialog, as JSON
+        pub dialog: String,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+/// Adds the nodes to the locator's database
+pub struct ClusterMigration;
+
+impl MigrationName for ClusterMigration {
+    fn name(&self) -> &str {
+        "m20250101_000002_rustpbx_cluster"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for ClusterMigration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(node::Entity)
+                    .if_not_exists()
+                    .col(string(node::Column::NodeId).char_len(64).primary_key())
+                    .col(big_integer(node::Column::Heartbeat).not_null())
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(locator_db::Entity)
+                    .add_column(string_null(locator_db::Column::Node).char_len(64))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(locator_db::Entity)
+                    .drop_column(locator_db::Column::Node)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .drop_table(Table::drop().table(node::Entity).to_owned())
+            .await
+    }
+}
+
+/// Adds the subscriptions to the locator's database
+pub struct SubscriptionMigration;
+
+impl MigrationName for SubscriptionMigration {
+    fn name(&self) -> &str {
+        "m20250101_000003_rustpbx_subscriptions"
+    }
+}
+
+#[async_trait::async_trait]
+impl MigrationTrait for SubscriptionMigration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(subscription::Entity)
+                    .if_not_exists()
+                    .col(
+                        string(subscription::Column::CallId)
+                            .char_len(255)
+                            .primary_key(),
+                    )
+                    .col(string(subscription::Column::Aor).char_len(255).not_null())
+                    .col(big_integer(subscription::Column::Expires).not_null())
+                    .col(string(subscription::Column::Node).char_len(64).not_null())
+                    .col(text(subscription::Column::Dialog).not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(subscription::Entity).to_owned())
+            .await
+    }
+}
+
+/// The bindings and subscriptions of a node that went down, now owned by
+/// this node
+#[derive(Clone)]
+pub struct Takeover {
+    pub node: String,
+    /// By `user@realm`
+    pub bindings: Vec<(String, Location)>,
+    pub subscriptions: Vec<Subscription>,
+}
+
+pub struct Cluster {
+    config: ClusterConfig,
+    db: DatabaseConnection,
+    takeovers: broadcast::Sender<Takeover>,
+    status: Option<ProxyStatusSender>,
+}
+
+impl Cluster {
+    /// Joins the nodes of the locator database at `url`, once the locator
+    /// created its tables
+    pub async fn new(config: ClusterConfig, url: &str) -> Result<Self> {
+        let db = Database::connect(url)
+            .await
+            .map_err(|e| anyhow::anyhow!("Database connection error: {}", e))?;
+        Ok(Self {
+            config,
+            db,
+            takeovers: broadcast::channel(16).0,
+            status: None,
+        })
+    }
+
+    /// Publishes `clusterTakeover` events
+    pub fn with_status(mut self, status: ProxyStatusSender) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn node_id(&self) -> &str {
+        &self.config.node_id
+    }
+
+    /// The bindings and subscriptions this node takes over, e.g. to keep
+    /// their NAT bindings open
+    pub fn subscribe(&self) -> broadcast::Receiver<Takeover> {
+        self.takeovers.subscribe()
+    }
+
+    pub async fn heartbeat(&self, now: i64) -> Result<()> {
+        node::Entity::insert(node::ActiveModel {
+            node_id: Set(self.config.node_id.clone()),
+            heartbeat: Set(now),
+        })
+        .on_conflict(
+            OnConflict::column(node::Column::NodeId)
+                .update_column(node::Column::Heartbeat)
+                .to_owned(),
+        )
+        .exec(&self.db)
+        .await
+        .map_err(|e| anyhow::anyhow!("Database error on heartbeat: {}", e))?;
+        Ok(())
+    }
+
+    /// Shares a subscription held by this node, so another node can take
+    /// it over
+    pub async fn save_subscription(&self, subscription: &Subscription) -> Result<()> {
+        subscription::Entity::insert(subscription::ActiveModel {
+            call_id: Set(subscription.call_id.clone()),
+            aor: Set(subscription.aor.clone()),
+            expires: Set(subscription.expires),
+            node: Set(self.config.node_id.clone()),
+            dialog: Set(serde_json::to_string(subscription)?),
+        })
+        .on_conflict(
+            OnConflict::column(subscription::Column::CallId)
+                .update_columns([
+                    subscription::Column::Aor,
+                    subscription::Column::Expires,
+                    subscription::Column::Node,
+                    subscription::Column::Dialog,
+                ])
+                .to_owned(),
+        )
+        .exec(&self.db)
+        .await
+        .map_err(|e| anyhow::anyhow!("Database error on subscription: {}", e))?;
+        Ok(())
+    }
+
+    pub async fn remove_subscription(&self, call_id: &str) -> Result<()> {
+        subscription::Entity::delete_many()
+            .filter(subscription::Column::CallId.eq(call_id))
+            .exec(&self.db)
+            .await
+            .map_err(|e| anyhow::anyhow!("Database error on subscription: {}", e))?;
+        Ok(())
+    }
+
+    /// The ids of the nodes that sent a heartbeat within `node_timeout`
+    pub async fn live_nodes(&self, now: i64) -> Result<Vec<String>> {
+        let mut nodes = node::Entity::find()
+            .filter(node::Column::Heartbeat.gte(self.cutoff(now)))
+            .all(&self.db)
+            .await
+            .map_err(|e| anyhow::anyhow!("Database error on nodes: {}", e))?
+            .into_iter()
+            .map(|node| node.node_id)
+            .collect::<Vec<_>>();
+        nodes.sort();
+        Ok(nodes)
+    }
+
+    /// Takes over the bindings of the nodes that went down, when this node
+    /// is the first live one. A node claims a dead one by removing it, so
+    /// only one node takes over its bindings
+    pub async fn check(&self, now: i64) -> Result<Vec<Takeover>> {
+        let live = self.live_nodes(now).await?;
+        if live.first() != Some(&self.config.node_id) {
+            return Ok(Vec::new());
+        }
+        let dead = node::Entity::find()
+            .filter(node::Column::Heartbeat.lt(self.cutoff(now)))
+            .all(&self.db)
+            .await
+            .map_err(|e| anyhow::anyhow!("Database error on nodes: {}", e))?;
+        let mut takeovers = Vec::new();
+        for node in dead {
+            let claimed = node::Entity::delete_many()
+                .filter(node::Column::NodeId.eq(&node.node_id))
+                .filter(node::Column::Heartbeat.eq(node.heartbeat))
+                .exec(&self.db)
+                .await
+                .map_err(|e| anyhow::anyhow!("Database error on takeover: {}", e))?;
+            if claimed.rows_affected == 0 {
+                continue;
+            }
+            let takeover = self.take_over(&node.node_id).await?;
+            info!(
+                node = node.node_id,
+                bindings = takeover.bindings.len(),
+                subscriptions = takeover.subscriptions.len(),
+                "took over a node that went down"
+            );
+            if let Some(status) = &self.status {
+                status
+                    .send(ProxyStatus::ClusterTakeover {
+                        timestamp: crate::get_timestamp(),
+                        node: takeover.node.clone(),
+                        by: self.config.node_id.clone(),
+                        bindings: takeover.bindings.len(),
+                    })
+                    .ok();
+            }
+            self.takeovers.send(takeover.clone()).ok();
+            takeovers.push(takeover);
+        }
+        Ok(takeovers)
+    }
+
+    async fn take_over(&self, node: &str) -> Result<Takeover> {
+        let models = locator_db::Entity::find()
+            .filter(locator_db::Column::Node.eq(node))
+            .all(&self.db)
+            .await
+            .map_err(|e| anyhow::anyhow!("Database error on takeover: {}", e))?;
+        locator_db::Entity::update_many()
+            .col_expr(
+                locator_db::Column::Node,
+                Expr::value(self.config.node_id.clone()),
+            )
+            .filter(locator_db::Column::Node.eq(node))
+            .exec(&self.db)
+            .await
+            .map_err(|e| anyhow::anyhow!("Database error on takeover: {}", e))?;
+        let mut bindings = Vec::new();
+        for model in models.into_iter().filter(is_live) {
+            let key = format!("{}@{}", model.username, model.realm);
+            match model_location(model) {
+                Ok(location) => bindings.push((key, location)),
+                Err(e) => warn!(key, "invalid binding: {}", e),
+            }
+        }
+
+        let models = subscription::Entity::find()
+            .filter(subscription::Column::Node.eq(node))
+            .all(&self.db)
+            .await
+            .map_err(|e| anyhow::anyhow!("Database error on takeover: {}", e))?;
+        subscription::Entity::update_many()
+            .col_expr(
+                subscription::Column::Node,
+                Expr::value(self.config.node_id.clone()),
+            )
+            .filter(subscription::Column::Node.eq(node))
+            .exec(&self.db)
+            .await
+            .map_err(|e| anyhow::anyhow!("Database error on takeover: {}", e))?;
+        let now = unix_timestamp();
+        let mut subscriptions = Vec::new();
+        for model in models.into_iter().filter(|model| model.expires > now) {
+            match serde_json::from_str::<Subscription>(&model.dialog) {
+                Ok(subscription) => subscriptions.push(subscription),
+                Err(e) => warn!(call_id = model.call_id, "invalid subscription: {}", e),
+            }
+        }
+        Ok(Takeover {
+            node: node.to_string(),
+            bindings,
+            subscriptions,
+        })
+    }
+
+    fn cutoff(&self, now: i64) -> i64 {
+        now - self.config.node_timeout as i64
+    }
+}
+
+/// Heartbeats and watches the other nodes, when the proxy is in a cluster
+pub fn start_cluster(server: &SipServerRef) {
+    let Some(cluster) = server.cluster.clone() else {
+        return;
+    };
+    info!(node = cluster.node_id(), "joining the registrar cluster");
+    let token = server.cancel_token.child_token();
+    let interval = Duration::from_secs(cluster.config.heartbeat_interval.max(1));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = ticker.tick() => {
+                    let now = unix_timestamp();
+                    if let Err(e) = cluster.heartbeat(now).await {
+                        warn!("cluster heartbeat failed: {}", e);
+                        continue;
+                    }
+                    if let Err(e) = cluster.check(now).await {
+                        warn!("cluster check failed: {}", e);
+                    }
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,258 @@
+use super::{
+    campaign::{CallingWindow, parse_offset},
+    status::{ProxyStatus, ProxyStatusSender},
+};
+use crate::config::{FraudConfig, InternationalRule};
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// The rules of the fraud config a call can break
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum FraudRule {
+    Destination,
+    Velocity,
+    AfterHours,
+    International,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FraudScore {
+    pub score: u32,
+    /// The rules the call broke
+    pub rules: Vec<FraudRule>,
+}
+
+impl FraudScore {
+    fn add(&mut self, rule: FraudRule, score: u32) {
+        self.score = self.score.saturating_add(score);
+        self.rules.push(rule);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FraudVerdict {
+    Allow,
+    Alert,
+    Block,
+}
+
+#[derive(Debug, Default)]
+struct FraudState {
+    /// When each extension placed its calls during the velocity window
+    attempts: HashMap<String, VecDeque<Instant>>,
+    /// When each extension placed its calls after hours during the window
+    after_hours: HashMap<String, VecDeque<Instant>>,
+    /// Call-IDs of the international calls in progress, per extension
+    international: HashMap<String, HashSet<String>>,
+}
+
+/// Scores outbound calls by the rules of the fraud config, globally for the
+/// proxy so that the rules count the calls of an extension
+#[derive(Debug)]
+pub struct FraudDetector {
+    config: FraudConfig,
+    business_hours: Option<(CallingWindow, FixedOffset)>,
+    state: Mutex<FraudState>,
+    status: Option<ProxyStatusSender>,
+}
+
+impl FraudDetector {
+    pub fn new(config: FraudConfig) -> Result<Self> {
+        let business_hours = match config.after_hours.as_ref() {
+            Some(rule) => Some((
+                CallingWindow::parse(&rule.business_hours)?,
+                match rule.utc_offset.as_deref() {
+                    Some(offset) => parse_offset(offset)?,
+                    None => FixedOffset::east_opt(0).expect("zero offset"),
+                },
+            )),
+            None => None,
+        };
+        Ok(Self {
+            config,
+            business_hours,
+            state: Mutex::new(FraudState::default()),
+            status: None,
+        })
+    }
+
+    /// Publishes `fraudAlert` events for the calls scoring `alert_score`
+    pub fn with_status(mut self, status: ProxyStatusSender) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    /// Scores the call of `extension` to `number` and counts it towards the
+    /// rules. An international call is in progress until it is released
+    pub fn check(&self, call_id: &str, extension: &str, number: &str) -> FraudVerdict {
+        let score = self.score(call_id, extension, number, Instant::now(), Utc::now());
+        let verdict = if self
+            .config
+            .block_score
+            .is_some_and(|block| score.score >= block)
+        {
+            FraudVerdict::Block
+        } else if score.score >= self.config.alert_score {
+            FraudVerdict::Alert
+        } else {
+            return FraudVerdict::Allow;
+        };
+        let blocked = verdict == FraudVerdict::Block;
+        if blocked {
+            self.release(call_id);
+        }
+        warn!(
+            call_id,
+            extension,
+            number,
+            score = score.score,
+            blocked,
+            "suspicious call: {:?}",
+            score.rules
+        );
+        if let Some(status) = &self.status {
+            status
+                .send(ProxyStatus::FraudAlert {
+                    timestamp: crate::get_timestamp(),
+                    call_id: call_id.to_string(),
+                    extension: extension.to_string(),
+                    number: number.to_string(),
+                    score: score.score,
+                    rules: score.rules,
+                    blocked,
+                })
+                .ok();
+        }
+        verdict
+    }
+
+    /// Scores the call placed at `now`, `time` by the wall clock, and counts
+    /// it towards the rules
+    pub fn score(
+        &self,
+        call_id: &str,
+        extension: &str,
+        number: &str,
+        now: Instant,
+        time: DateTime<Utc>,
+    ) -> FraudScore {
+        let mut score = FraudScore::default();
+        if let Some(risk) = self
+            .config
+            .destinations
+            .iter()
+            .filter(|risk| number.starts_with(&risk.prefix))
+            .max_by_key(|risk| risk.prefix.len())
+        {
+            score.add(FraudRule::Destination, risk.score);
+        }
+        let Ok(mut state) = self.state.lock() else {
+            return score;
+        };
+        if let Some(rule) = self.config.velocity.as_ref() {
+            let calls = count(
+                &mut state.attempts,
+                extension,
+                now,
+                Duration::from_secs(rule.window),
+            );
+            if calls > rule.max_calls as usize {
+                score.add(FraudRule::Velocity, rule.score);
+            }
+        }
+        if let (Some(rule), Some((hours, offset))) = (
+            self.config.after_hours.as_ref(),
+            self.business_hours.as_ref(),
+        ) && !hours.allows(time.with_timezone(offset))
+        {
+            let calls = count(
+                &mut state.after_hours,
+                extension,
+                now,
+                Duration::from_secs(rule.window),
+            );
+            if calls > rule.max_calls as usize {
+                score.add(FraudRule::AfterHours, rule.score);
+            }
+        }
+        if let Some(rule) = self.config.international.as_ref()
+            && is_international(rule, number)
+        {
+            let calls = state
+                .international
+                .entry(extension.to_string())
+                .or_default();
+            calls.insert(call_id.to_string());
+            if calls.len() > rule.max_calls as usize {
+                score.add(FraudRule::International, rule.score);
+            }
+        }
+        if !score.rules.is_empty() {
+            info!(call_id, extension, number, "fraud score {}", score.score);
+        }
+        score
+    }
+
+    /// The call ended, its international call no longer counts
+    pub fn release(&self, call_id: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            state.international.retain(|_, calls| {
+                calls.remove(call_id);
+                !calls.is_empty()
+            });
+        }
+    }
+
+    /// Releases the call when the returned guard is dropped
+    pub fn enter(self: &Arc<Self>, call_id: impl Into<String>) -> FraudSlot {
+        FraudSlot {
+            detector: self.clone(),
+            call_id: call_id.into(),
+        }
+    }
+}
+
+fn is_international(rule: &InternationalRule, number: &str) -> bool {
+    rule.prefixes
+        .iter()
+        .any(|prefix| number.starts_with(prefix))
+}
+
+/// Records a call of `extension` at `now`, and returns the calls it placed
+/// during the last `window`, this one included
+fn count(
+    calls: &mut HashMap<String, VecDeque<Instant>>,
+    extension: &str,
+    now: Instant,
+    window: Duration,
+) -> usize {
+    let calls = calls.entry(extension.to_string()).or_default();
+    while calls
+        .front()
+        .is_some_and(|at| now.duration_since(*at) >= window)
+    {
+        calls.pop_front();
+    }
+    calls.push_back(now);
+    calls.len()
+}
+
+/// Holds a call's international slot for as long as the call lasts
+pub struct FraudSlot {
+    detector: Arc<FraudDetector>,
+    call_id: String,
+}
+
+impl Drop for FraudSlot {
+    fn drop(&mut self) {
+        self.detector.release(&self.call_id);
+    }
+}
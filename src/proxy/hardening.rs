@@ -0,0 +1,121 @@
+use crate::config::SipHardeningConfig;
+use rsip::{
+    Header, SipMessage,
+    prelude::{HeadersExt, ToTypedHeader, UntypedHeader},
+};
+use rsipstack::transaction::endpoint::MessageInspector;
+
+/// What to do with an incoming request before any module sees it
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Accept,
+    /// Drop without a response, scanners learn nothing
+    Drop(String),
+    /// Answer with a bare status and stop processing
+    Reject(rsip::StatusCode, String),
+}
+
+pub fn inspect_request(config: &SipHardeningConfig, req: &rsip::Request) -> Verdict {
+    if let Some(user_agent) = rsip::header_opt!(req.headers.iter(), Header::UserAgent) {
+        let user_agent = user_agent.value().to_lowercase();
+        if let Some(blocked) = config
+            .block_user_agents
+            .iter()
+            .find(|blocked| user_agent.contains(&blocked.to_lowercase()))
+        {
+            return Verdict::Drop(format!("blocked user agent: {}", blocked));
+        }
+    }
+    if !config.reject_malformed {
+        return Verdict::Accept;
+    }
+    if let Err(e) = check_mandatory_headers(req) {
+        return Verdict::Reject(rsip::StatusCode::BadRequest, e);
+    }
+    if let Some(max_forwards) = rsip::header_opt!(req.headers.iter(), Header::MaxForwards) {
+        match max_forwards.value().trim().parse::<u32>() {
+            Ok(0) => {
+                return Verdict::Reject(rsip::StatusCode::TooManyHops, "max-forwards is 0".into());
+            }
+            Ok(_) => {}
+            Err(_) => {
+                return Verdict::Reject(
+                    rsip::StatusCode::BadRequest,
+                    "invalid max-forwards".into(),
+                );
+            }
+        }
+    }
+    Verdict::Accept
+}
+
+fn check_mandatory_headers(req: &rsip::Request) -> Result<(), String> {
+    req.from_header()
+        .and_then(|h| h.typed())
+        .map_err(|e| format!("invalid from: {}", e))?;
+    req.to_header()
+        .and_then(|h| h.typed())
+        .map_err(|e| format!("invalid to: {}", e))?;
+    let call_id = req
+        .call_id_header()
+        .map_err(|e| format!("invalid call-id: {}", e))?;
+    if call_id.value().trim().is_empty() {
+        return Err("empty call-id".into());
+    }
+    let cseq = req
+        .cseq_header()
+        .and_then(|h| h.typed())
+        .map_err(|e| format!("invalid cseq: {}", e))?;
+    if cseq.method != req.method {
+        return Err(format!("cseq method {} != {}", cseq.method, req.method));
+    }
+    Ok(())
+}
+
+/// Rewrites the identifying headers of everything the endpoint sends,
+/// chaining to the inspector configured on the server builder
+pub struct HardeningInspector {
+    config: SipHardeningConfig,
+    inner: Option<Box<dyn MessageInspector>>,
+}
+
+impl HardeningInspector {
+    pub fn new(config: SipHardeningConfig, inner: Option<Box<dyn MessageInspector>>) -> Self {
+        Self { config, inner }
+    }
+}
+
+impl MessageInspector for HardeningInspector {
+    fn before_send(&self, msg: SipMessage) -> SipMessage {
+        let mut msg = match self.inner.as_ref() {
+            Some(inner) => inner.before_send(msg),
+            None => msg,
+        };
+        match &mut msg {
+            SipMessage::Request(req) if self.config.hide_identity => {
+                req.headers
+                    .retain(|h| !matches!(h, Header::UserAgent(_) | Header::Server(_)));
+            }
+            SipMessage::Response(resp) if self.config.hide_identity => {
+                resp.headers
+                    .retain(|h| !matches!(h, Header::UserAgent(_) | Header::Server(_)));
+            }
+            SipMessage::Response(resp) => {
+                if let Some(server) = self.config.server.as_ref() {
+                    resp.headers.retain(|h| !matches!(h, Header::UserAgent(_)));
+                    resp.headers
+                        .unique_push(Header::Server(server.clone().into()));
+                }
+            }
+            _ => {}
+        }
+        msg
+    }
+
+    fn after_received(&self, msg: SipMessage) -> SipMessage {
+        match self.inner.as_ref() {
+            Some(inner) => inner.after_received(msg),
+            None => msg,
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use super::status::{ProxyStatus, ProxyStatusSender};
+use crate::config::CallLimitConfig;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LimitScope {
+    Global,
+    Extension,
+    Trunk,
+}
+
+/// A cap that applies to a call, `name` is the extension or trunk
+#[derive(Debug, Clone)]
+pub struct CallLimit {
+    pub scope: LimitScope,
+    pub name: String,
+    pub max_calls: Option<u32>,
+    pub max_cps: Option<u32>,
+}
+
+impl CallLimit {
+    pub fn new(scope: LimitScope, name: impl Into<String>, config: &CallLimitConfig) -> Self {
+        Self {
+            scope,
+            name: name.into(),
+            max_calls: config.max_calls,
+            max_cps: config.max_cps,
+        }
+    }
+
+    fn key(&self) -> (LimitScope, String) {
+        (self.scope, self.name.clone())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum LimitKind {
+    Calls,
+    Cps,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub scope: LimitScope,
+    pub name: String,
+    pub kind: LimitKind,
+    pub max: u32,
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} {} limit of {} reached for {}",
+            self.scope,
+            self.kind_name(),
+            self.max,
+            self.name
+        )
+    }
+}
+
+impl LimitExceeded {
+    fn kind_name(&self) -> &'static str {
+        match self.kind {
+            LimitKind::Calls => "concurrent call",
+            LimitKind::Cps => "calls per second",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct LimiterState {
+    /// Call-IDs of the calls holding a slot, per scope and name
+    calls: HashMap<(LimitScope, String), HashSet<String>>,
+    /// When calls were admitted during the last second
+    attempts: HashMap<(LimitScope, String), VecDeque<Instant>>,
+}
+
+/// Counts concurrent calls and call attempts per second globally, per
+/// extension and per trunk
+#[derive(Debug, Default)]
+pub struct CallLimiter {
+    state: Mutex<LimiterState>,
+    status: Option<ProxyStatusSender>,
+}
+
+impl CallLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Publishes `callLimitExceeded` events when calls are refused
+    pub fn with_status(mut self, status: ProxyStatusSender) -> Self {
+        self.status = Some(status);
+        self
+    }
+
+    pub fn active_calls(&self, scope: LimitScope, name: &str) -> usize {
+        self.state
+            .lock()
+            .ok()
+            .and_then(|state| {
+                state
+                    .calls
+                    .get(&(scope, name.to_string()))
+                    .map(|calls| calls.len())
+            })
+            .unwrap_or_default()
+    }
+
+    /// The trunk the call holds a slot in, that is the one it was routed to
+    pub fn trunk(&self, call_id: &str) -> Option<String> {
+        let state = self.state.lock().ok()?;
+        state
+            .calls
+            .iter()
+            .find(|((scope, _), calls)| *scope == LimitScope::Trunk && calls.contains(call_id))
+            .map(|((_, name), _)| name.clone())
+    }
+
+    /// Whether the call fits in the limit, without taking a slot
+    pub fn has_capacity(&self, call_id: &str, limit: &CallLimit) -> bool {
+        match self.state.lock() {
+            Ok(mut state) => check(&mut state, call_id, limit, Instant::now()).is_ok(),
+            Err(_) => true,
+        }
+    }
+
+    /// Takes a slot in every limit for the call, or none when one of them
+    /// is reached. Limits the call already holds a slot in always pass
+    pub fn admit(&self, call_id: &str, limits: &[CallLimit]) -> Result<(), LimitExceeded> {
+        let now = Instant::now();
+        let Ok(mut state) = self.state.lock() else {
+            return Ok(());
+        };
+        for limit in limits {
+            if let Err(exceeded) = check(&mut state, call_id, limit, now) {
+                drop(state);
+                info!(call_id, "call refused: {}", exceeded);
+                if let Some(status) = &self.status {
+                    status
+                        .send(ProxyStatus::CallLimitExceeded {
+                            timestamp: crate::get_timestamp(),
+                            call_id: call_id.to_string(),
+                            scope: exceeded.scope,
+                            name: exceeded.name.clone(),
+                            limit: exceeded.kind,
+                            max: exceeded.max,
+                        })
+                        .ok();
+                }
+                return Err(exceeded);
+            }
+        }
+        for limit in limits {
+            let calls = state.calls.entry(limit.key()).or_default();
+            if calls.insert(call_id.to_string()) && limit.max_cps.is_some() {
+                state
+                    .attempts
+                    .entry(limit.key())
+                    .or_default()
+                    .push_back(now);
+            }
+        }
+        Ok(())
+    }
+
+    /// Frees every slot the call holds
+    pub fn release(&self, call_id: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            state.calls.retain(|_, calls| {
+                calls.remove(call_id);
+                !calls.is_empty()
+            });
+        }
+    }
+
+    /// Releases the call's slots when the returned guard is dropped
+    pub fn enter(self: &Arc<Self>, call_id: impl Into<String>) -> CallSlot {
+        CallSlot {
+            limiter: self.clone(),
+            call_id: call_id.into(),
+        }
+    }
+}
+
+fn check(
+    state: &mut LimiterState,
+    call_id: &str,
+    limit: &CallLimit,
+    now: Instant,
+) -> Result<(), LimitExceeded> {
+    let key = limit.key();
+    let exceeded = |kind, max| LimitExceeded {
+        scope: limit.scope,
+        name: limit.name.clone(),
+        kind,
+        max,
+    };
+    let calls = state.calls.get(&key);
+    if calls.is_some_and(|calls| calls.contains(call_id)) {
+        return Ok(());
+    }
+    if let Some(max) = limit.max_calls
+        && calls.map(|calls| calls.len()).unwrap_or_default() >= max as usize
+    {
+        return Err(exceeded(LimitKind::Calls, max));
+    }
+    if let Some(max) = limit.max_cps
+        && let Some(attempts) = state.attempts.get_mut(&key)
+    {
+        while attempts
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= Duration::from_secs(1))
+        {
+            attempts.pop_front();
+        }
+        if attempts.len() >= max as usize {
+            return Err(exceeded(LimitKind::Cps, max));
+        }
+    }
+    Ok(())
+}
+
+/// Holds a call's slots for as long as the call lasts
+pub struct CallSlot {
+    limiter: Arc<CallLimiter>,
+    call_id: String,
+}
+
+impl Drop for CallSlot {
+    fn drop(&mut self) {
+        self.limiter.release(&self.call_id);
+    }
+}
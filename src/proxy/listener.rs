@@ -0,0 +1,380 @@
+use super::{parser::parse_and_report, status::ProxyStatusSender};
+use crate::config::SipParseMode;
+use anyhow::{Result, anyhow};
+use rsip::{
+    prelude::{HeadersExt, UntypedHeader},
+    transport::Transport,
+};
+use rsipstack::{
+    transaction::endpoint::EndpointInnerRef,
+    transport::{
+        SipAddr, SipConnection, TransportEvent,
+        channel::ChannelConnection,
+        connection::{KEEPALIVE_REQUEST, KEEPALIVE_RESPONSE},
+        udp::UdpConnection,
+    },
+};
+use rustls::{
+    ServerConfig,
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, WriteHalf},
+    net::TcpListener,
+    select,
+    sync::{Mutex, mpsc},
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+const MAX_SIP_MESSAGE_SIZE: usize = 65535;
+
+/// A bound UDP socket or stream listener, served once the proxy starts
+pub enum SipListener {
+    Udp(UdpConnection),
+    Tcp(TcpListener),
+    Tls(TcpListener, TlsAcceptor),
+}
+
+/// Hands messages received on UDP, TCP and TLS to the endpoint after
+/// `parse_and_report`, the same path WebSocket messages take
+#[derive(Clone)]
+pub struct SipReceiver {
+    pub endpoint: EndpointInnerRef,
+    pub status: ProxyStatusSender,
+    pub parse_mode: SipParseMode,
+    pub token: CancellationToken,
+}
+
+impl SipReceiver {
+    pub async fn serve(self, listener: SipListener) {
+        match listener {
+            SipListener::Udp(conn) => self.serve_udp(conn).await,
+            SipListener::Tcp(listener) => self.serve_listener(listener, None).await,
+            SipListener::Tls(listener, acceptor) => {
+                self.serve_listener(listener, Some(acceptor)).await
+            }
+        }
+    }
+
+    fn parse(
+        &self,
+        raw: &[u8],
+        source: SocketAddr,
+        transport: Transport,
+    ) -> Option<rsip::SipMessage> {
+        let message = parse_and_report(
+            &self.status,
+            self.parse_mode,
+            raw,
+            &source.to_string(),
+            &transport.to_string(),
+        )?;
+        debug!(
+            %source,
+            cseq = message.cseq_header().ok().map(|c| c.value()).unwrap_or_default(),
+            "{} received: \n{}",
+            transport,
+            message
+        );
+        match SipConnection::update_msg_received(message, source, transport) {
+            Ok(message) => Some(message),
+            Err(e) => {
+                warn!(%source, "error updating SIP via: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Reads datagrams off a UDP connection whose own receive loop is
+    /// stopped, the connection still sends through the transport layer
+    async fn serve_udp(self, conn: UdpConnection) {
+        let (from_udp_tx, from_udp_rx) = mpsc::unbounded_channel();
+        let (unused_tx, _) = mpsc::unbounded_channel();
+        // no transport type, so lookups for a destination never pick it
+        let injector_addr = SipAddr {
+            r#type: None,
+            addr: conn.get_addr().addr.clone(),
+        };
+        let injector = match ChannelConnection::create_connection(
+            from_udp_rx,
+            unused_tx,
+            injector_addr,
+            Some(self.token.child_token()),
+        )
+        .await
+        {
+            Ok(injector) => injector,
+            Err(e) => {
+                warn!(addr = %conn.get_addr(), "failed to create UDP receiver: {}", e);
+                return;
+            }
+        };
+        self.endpoint
+            .transport_layer
+            .add_connection(SipConnection::Channel(injector));
+
+        let mut buf = vec![0u8; MAX_SIP_MESSAGE_SIZE];
+        loop {
+            let (len, source) = select! {
+                _ = self.token.cancelled() => break,
+                result = conn.recv_raw(&mut buf) => match result {
+                    Ok(received) => received,
+                    Err(e) => {
+                        warn!(addr = %conn.get_addr(), "error receiving UDP packet: {}", e);
+                        continue;
+                    }
+                },
+            };
+            let raw = &buf[..len];
+            if raw == KEEPALIVE_REQUEST {
+                conn.send_raw(KEEPALIVE_RESPONSE, &source).await.ok();
+                continue;
+            }
+            if raw.iter().all(|b| b.is_ascii_whitespace()) {
+                continue;
+            }
+            let Ok(source_addr) = source.get_socketaddr() else {
+                continue;
+            };
+            let Some(message) = self.parse(raw, source_addr, Transport::Udp) else {
+                continue;
+            };
+            if from_udp_tx
+                .send(TransportEvent::Incoming(
+                    message,
+                    SipConnection::Udp(conn.clone()),
+                    source,
+                ))
+                .is_err()
+            {
+                break;
+            }
+        }
+        info!(addr = %conn.get_addr(), "UDP receiver exiting");
+    }
+
+    /// Accepts TCP connections, or TLS ones when an acceptor is given
+    async fn serve_listener(self, listener: TcpListener, acceptor: Option<TlsAcceptor>) {
+        loop {
+            let (stream, remote) = select! {
+                _ = self.token.cancelled() => break,
+                result = listener.accept() => match result {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!("failed to accept connection: {}", e);
+                        continue;
+                    }
+                },
+            };
+            let receiver = self.clone();
+            match acceptor.clone() {
+                Some(acceptor) => {
+                    tokio::spawn(async move {
+                        match acceptor.accept(stream).await {
+                            Ok(stream) => {
+                                receiver.serve_stream(stream, remote, Transport::Tls).await
+                            }
+                            Err(e) => warn!(%remote, "TLS handshake failed: {}", e),
+                        }
+                    });
+                }
+                None => {
+                    tokio::spawn(receiver.serve_stream(stream, remote, Transport::Tcp));
+                }
+            }
+        }
+    }
+
+    async fn serve_stream<S>(self, stream: S, remote: SocketAddr, transport: Transport)
+    where
+        S: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (mut read_half, write_half) = tokio::io::split(stream);
+        let write_half = Arc::new(Mutex::new(write_half));
+        let (from_stream_tx, from_stream_rx) = mpsc::unbounded_channel();
+        let (to_stream_tx, mut to_stream_rx) = mpsc::unbounded_channel();
+        let remote_addr = SipAddr {
+            r#type: Some(transport),
+            addr: remote.into(),
+        };
+        let stream_token = self.token.child_token();
+        let connection = match ChannelConnection::create_connection(
+            from_stream_rx,
+            to_stream_tx,
+            remote_addr.clone(),
+            Some(stream_token.clone()),
+        )
+        .await
+        {
+            Ok(conn) => SipConnection::Channel(conn),
+            Err(e) => {
+                warn!(addr = %remote_addr, "failed to create channel connection: {}", e);
+                return;
+            }
+        };
+        let transport_layer = &self.endpoint.transport_layer;
+        transport_layer.add_connection(connection.clone());
+        info!(addr = %remote_addr, "new {} connection", transport);
+
+        let read_loop = async {
+            let mut buffer = Vec::with_capacity(MAX_SIP_MESSAGE_SIZE);
+            let mut read_buf = vec![0u8; MAX_SIP_MESSAGE_SIZE];
+            loop {
+                match read_half.read(&mut read_buf).await {
+                    Ok(0) => break,
+                    Ok(n) => buffer.extend_from_slice(&read_buf[..n]),
+                    Err(e) => {
+                        warn!(addr = %remote_addr, "error reading from stream: {}", e);
+                        break;
+                    }
+                }
+                loop {
+                    let frame = match next_frame(&mut buffer) {
+                        Ok(Some(frame)) => frame,
+                        Ok(None) => break,
+                        Err(e) => {
+                            warn!(addr = %remote_addr, "closing stream: {}", e);
+                            return;
+                        }
+                    };
+                    let raw = match frame {
+                        StreamFrame::KeepaliveRequest => {
+                            send_raw(&write_half, KEEPALIVE_RESPONSE).await.ok();
+                            continue;
+                        }
+                        StreamFrame::KeepaliveResponse => continue,
+                        StreamFrame::Message(raw) => raw,
+                    };
+                    // a malformed message is reported and skipped, the stream stays up
+                    let Some(message) = self.parse(&raw, remote, transport) else {
+                        continue;
+                    };
+                    if from_stream_tx
+                        .send(TransportEvent::Incoming(
+                            message,
+                            connection.clone(),
+                            remote_addr.clone(),
+                        ))
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+        };
+        let write_loop = async {
+            while let Some(event) = to_stream_rx.recv().await {
+                match event {
+                    TransportEvent::Incoming(message, _, _) => {
+                        if let Err(e) = send_raw(&write_half, message.to_string().as_bytes()).await
+                        {
+                            warn!(addr = %remote_addr, "error writing to stream: {}", e);
+                            break;
+                        }
+                    }
+                    TransportEvent::New(_) => {}
+                    TransportEvent::Closed(_) => break,
+                }
+            }
+        };
+
+        select! {
+            _ = self.token.cancelled() => {}
+            _ = read_loop => {}
+            _ = write_loop => {}
+        }
+        stream_token.cancel();
+        write_half.lock().await.shutdown().await.ok();
+        transport_layer.del_connection(&remote_addr);
+        info!(addr = %remote_addr, "{} connection closed", transport);
+    }
+}
+
+async fn send_raw<W: AsyncWrite>(write_half: &Mutex<WriteHalf<W>>, data: &[u8]) -> Result<()> {
+    let mut write_half = write_half.lock().await;
+    write_half.write_all(data).await?;
+    write_half.flush().await?;
+    Ok(())
+}
+
+/// Lists a stream listener among the endpoint's addresses, its messages
+/// arrive on the per-connection channels of `serve_stream` instead
+pub async fn listen_placeholder(addr: SipAddr) -> Result<SipConnection> {
+    let (_, incoming) = mpsc::unbounded_channel();
+    let (outgoing, _) = mpsc::unbounded_channel();
+    let conn = ChannelConnection::create_connection(incoming, outgoing, addr, None).await?;
+    Ok(SipConnection::Channel(conn))
+}
+
+/// Builds the acceptor for the TLS listener from PEM files
+pub fn create_tls_acceptor(certificate: &str, private_key: &str) -> Result<TlsAcceptor> {
+    let certs = CertificateDer::pem_file_iter(certificate)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| anyhow!("failed to load certificate {}: {}", certificate, e))?;
+    let key = PrivateKeyDer::from_pem_file(private_key)
+        .map_err(|e| anyhow!("failed to load private key {}: {}", private_key, e))?;
+    let config =
+        ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StreamFrame {
+    KeepaliveRequest,
+    KeepaliveResponse,
+    Message(Vec<u8>),
+}
+
+/// Takes the next frame off a stream buffer, a message ends after the
+/// blank line plus its Content-Length, `None` means more bytes are needed
+pub fn next_frame(buffer: &mut Vec<u8>) -> Result<Option<StreamFrame>> {
+    if buffer.starts_with(KEEPALIVE_REQUEST) {
+        buffer.drain(..KEEPALIVE_REQUEST.len());
+        return Ok(Some(StreamFrame::KeepaliveRequest));
+    }
+    if buffer.starts_with(KEEPALIVE_RESPONSE) {
+        buffer.drain(..KEEPALIVE_RESPONSE.len());
+        return Ok(Some(StreamFrame::KeepaliveResponse));
+    }
+    // bare LF line endings are framed too, lenient parsing repairs them
+    let head_end = buffer
+        .windows(3)
+        .enumerate()
+        .find_map(|(pos, window)| match window {
+            [b'\n', b'\n', _] => Some(pos + 2),
+            [b'\n', b'\r', b'\n'] => Some(pos + 3),
+            _ => None,
+        });
+    let head_end = match head_end {
+        Some(head_end) => head_end,
+        None if buffer.ends_with(b"\n\n") => buffer.len(),
+        None if buffer.len() > MAX_SIP_MESSAGE_SIZE => {
+            return Err(anyhow!("SIP message too large"));
+        }
+        None => return Ok(None),
+    };
+    let content_length = String::from_utf8_lossy(&buffer[..head_end])
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| {
+            let name = name.trim();
+            name.eq_ignore_ascii_case("content-length") || name.eq_ignore_ascii_case("l")
+        })
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    let end = head_end + content_length;
+    if end > MAX_SIP_MESSAGE_SIZE {
+        return Err(anyhow!("SIP message too large"));
+    }
+    if buffer.len() < end {
+        return Ok(None);
+    }
+    Ok(Some(StreamFrame::Message(buffer.drain(..end).collect())))
+}
@@ -21,11 +21,29 @@ pub trait Locator: Send + Sync {
     async fn register(&self, username: &str, realm: Option<&str>, location: Location)
     -> Result<()>;
     async fn unregister(&self, username: &str, realm: Option<&str>) -> Result<()>;
+    /// Removes a single outbound flow, backends without flows drop the whole AOR
+    async fn unregister_flow(
+        &self,
+        username: &str,
+        realm: Option<&str>,
+        _instance_id: &str,
+        _reg_id: Option<u32>,
+    ) -> Result<()> {
+        self.unregister(username, realm).await
+    }
     async fn lookup(&self, username: &str, realm: Option<&str>) -> Result<Vec<Location>>;
+    /// Whether the locator's store can be reached, for the readiness probe
+    async fn check(&self) -> Result<()> {
+        Ok(())
+    }
+    /// Every registered binding, by identifier
+    async fn bindings(&self) -> Result<HashMap<String, Vec<Location>>> {
+        Err(anyhow::anyhow!("listing bindings is not supported"))
+    }
 }
 
 pub struct MemoryLocator {
-    locations: Mutex<HashMap<String, Location>>,
+    locations: Mutex<HashMap<String, Vec<Location>>>,
 }
 
 impl MemoryLocator {
@@ -52,7 +70,9 @@ impl Locator for MemoryLocator {
         let identifier = self.get_identifier(username, realm);
         debug!(identifier, %location, "Registering");
         let mut locations = self.locations.lock().await;
-        locations.insert(identifier, location);
+        let bindings = locations.entry(identifier).or_default();
+        bindings.retain(|l| !l.is_same_flow(&location));
+        bindings.push(location);
         Ok(())
     }
 
@@ -63,25 +83,55 @@ impl Locator for MemoryLocator {
         Ok(())
     }
 
+    async fn unregister_flow(
+        &self,
+        username: &str,
+        realm: Option<&str>,
+        instance_id: &str,
+        reg_id: Option<u32>,
+    ) -> Result<()> {
+        let identifier = self.get_identifier(username, realm);
+        let mut locations = self.locations.lock().await;
+        if let Some(bindings) = locations.get_mut(&identifier) {
+            bindings
+                .retain(|l| l.instance_id.as_deref() != Some(instance_id) || l.reg_id != reg_id);
+            if bindings.is_empty() {
+                locations.remove(&identifier);
+            }
+        }
+        Ok(())
+    }
+
     async fn lookup(&self, username: &str, realm: Option<&str>) -> Result<Vec<Location>> {
         let identifier = self.get_identifier(username, realm);
         let locations = self.locations.lock().await;
-        if let Some(location) = locations.get(&identifier) {
-            Ok(vec![location.clone()])
+        if let Some(bindings) = locations.get(&identifier) {
+            Ok(bindings.clone())
         } else {
             info!("User not found: {}", identifier);
             Err(anyhow::anyhow!("missing user: {}", identifier))
         }
     }
+
+    async fn bindings(&self) -> Result<HashMap<String, Vec<Location>>> {
+        Ok(self.locations.lock().await.clone())
+    }
 }
 
-pub async fn create_locator(config: &LocatorConfig) -> Result<Box<dyn Locator>> {
+/// `node` owns the bindings registered through this proxy, in a cluster
+pub async fn create_locator(
+    config: &LocatorConfig,
+    node: Option<&str>,
+) -> Result<Box<dyn Locator>> {
     match config {
         LocatorConfig::Memory | LocatorConfig::Http { .. } => {
             Ok(Box::new(MemoryLocator::new()) as Box<dyn Locator>)
         }
         LocatorConfig::Database { url } => {
-            let db_locator = DbLocator::new(url.clone()).await?;
+            let mut db_locator = DbLocator::new(url.clone()).await?;
+            if let Some(node) = node {
+                db_locator = db_locator.with_node(node);
+            }
             Ok(Box::new(db_locator) as Box<dyn Locator>)
         }
     }
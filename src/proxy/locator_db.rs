@@ -1,4 +1,7 @@
-use super::locator::Locator;
+use super::{
+    cluster::{ClusterMigration, SubscriptionMigration},
+    locator::Locator,
+};
 use crate::call::Location;
 use anyhow::Result;
 use async_trait::async_trait;
@@ -6,7 +9,7 @@ use rsipstack::transport::SipAddr;
 use sea_orm::{ActiveModelTrait, Database, Set, entity::prelude::*};
 pub use sea_orm_migration::prelude::*;
 use sea_orm_migration::schema::{boolean, integer, pk_auto, string, timestamp};
-use std::time::SystemTime;
+use std::{collections::HashMap, time::SystemTime};
 use tracing::{error, info};
 
 #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
@@ -24,6 +27,8 @@ pub struct Model {
     pub created_at: DateTime,
     pub updated_at: DateTime,
     pub supports_webrtc: bool,
+    /// The cluster node the binding was registered or refreshed through
+    pub node: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -34,6 +39,7 @@ impl Entity {}
 /// Database backed Locator implementation using SeaORM
 pub struct DbLocator {
     db: DatabaseConnection,
+    node: Option<String>,
 }
 
 #[derive(DeriveMigrationName)]
@@ -96,7 +102,11 @@ pub struct Migrator;
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(Migration {})]
+        vec![
+            Box::new(Migration {}),
+            Box::new(ClusterMigration {}),
+            Box::new(SubscriptionMigration {}),
+        ]
     }
 }
 
@@ -107,7 +117,7 @@ impl DbLocator {
         let db = Database::connect(&url)
             .await
             .map_err(|e| anyhow::anyhow!("Database connection error: {}", e))?;
-        let db_locator = Self { db };
+        let db_locator = Self { db, node: None };
         info!("Creating DbLocator");
         match db_locator.migrate().await {
             Ok(_) => Ok(db_locator),
@@ -118,6 +128,12 @@ impl DbLocator {
         }
     }
 
+    /// Records `node` as the owner of the bindings registered through it
+    pub fn with_node(mut self, node: impl Into<String>) -> Self {
+        self.node = Some(node.into());
+        self
+    }
+
     pub async fn migrate(&self) -> Result<()> {
         Migrator::up(&self.db, None)
             .await
@@ -128,6 +144,12 @@ impl DbLocator {
 
 #[async_trait]
 impl Locator for DbLocator {
+    async fn check(&self) -> Result<()> {
+        self.db
+            .ping()
+            .await
+            .map_err(|e| anyhow::anyhow!("Database connection error: {}", e))
+    }
     async fn register(
         &self,
         username: &str,
@@ -150,10 +172,7 @@ impl Locator for DbLocator {
             }
         };
 
-        let now = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs() as i64;
+        let now = unix_timestamp();
 
         // Check if record exists
         let existing = Entity::find()
@@ -176,6 +195,7 @@ impl Locator for DbLocator {
                 active_model.last_modified = Set(now);
                 active_model.updated_at = Set(chrono::Utc::now().naive_utc());
                 active_model.supports_webrtc = Set(location.supports_webrtc);
+                active_model.node = Set(self.node.clone());
 
                 active_model
                     .update(&self.db)
@@ -198,6 +218,7 @@ impl Locator for DbLocator {
                 active_model.created_at = Set(now_dt);
                 active_model.updated_at = Set(now_dt);
                 active_model.supports_webrtc = Set(location.supports_webrtc);
+                active_model.node = Set(self.node.clone());
 
                 // Insert without specifying id
                 active_model
@@ -232,47 +253,81 @@ impl Locator for DbLocator {
             .filter(Column::Realm.eq(realm_value))
             .all(&self.db)
             .await
-            .map_err(|e| anyhow::anyhow!("Database error on lookup: {}", e))?;
+            .map_err(|e| anyhow::anyhow!("Database error on lookup: {}", e))?
+            .into_iter()
+            .filter(is_live)
+            .collect::<Vec<_>>();
 
         if models.is_empty() {
             return Err(anyhow::anyhow!("missing user: {}", username));
         }
 
-        let mut locations = Vec::new();
-        for model in models {
-            // Parse the aor into a Uri
-            let aor = rsip::Uri::try_from(model.aor.as_str())
-                .map_err(|e| anyhow::anyhow!("Error parsing aor: {}", e))?;
-
-            // Parse transport from string
-            let transport = match model.transport.to_uppercase().as_str() {
-                "UDP" => rsip::transport::Transport::Udp,
-                "TCP" => rsip::transport::Transport::Tcp,
-                "TLS" => rsip::transport::Transport::Tls,
-                "WS" => rsip::transport::Transport::Ws,
-                "WSS" => rsip::transport::Transport::Wss,
-                _ => rsip::transport::Transport::Udp, // Default to UDP
-            };
-
-            // Parse destination host to HostWithPort
-            let addr = model.destination.try_into()?;
-
-            // Create SipAddr
-            let destination = SipAddr {
-                r#type: Some(transport),
-                addr,
-            };
-
-            locations.push(Location {
-                aor,
-                expires: model.expires as u32,
-                destination,
-                last_modified: None,
-                supports_webrtc: model.supports_webrtc,
-                ..Default::default()
-            });
-        }
+        models.into_iter().map(model_location).collect()
+    }
 
-        Ok(locations)
+    async fn bindings(&self) -> Result<HashMap<String, Vec<Location>>> {
+        let models = Entity::find()
+            .all(&self.db)
+            .await
+            .map_err(|e| anyhow::anyhow!("Database error on bindings: {}", e))?;
+        let mut bindings: HashMap<String, Vec<Location>> = HashMap::new();
+        for model in models.into_iter().filter(is_live) {
+            let identifier = self.get_identifier(
+                &model.username,
+                Some(model.realm.as_str()).filter(|realm| !realm.is_empty()),
+            );
+            bindings
+                .entry(identifier)
+                .or_default()
+                .push(model_location(model)?);
+        }
+        Ok(bindings)
     }
 }
+
+/// Bindings expire alike on every node sharing the database, whichever
+/// removes them
+pub(crate) fn is_live(model: &Model) -> bool {
+    model.last_modified + model.expires >= unix_timestamp()
+}
+
+pub(crate) fn unix_timestamp() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+pub(crate) fn model_location(model: Model) -> Result<Location> {
+    // Parse the aor into a Uri
+    let aor = rsip::Uri::try_from(model.aor.as_str())
+        .map_err(|e| anyhow::anyhow!("Error parsing aor: {}", e))?;
+
+    // Parse transport from string
+    let transport = match model.transport.to_uppercase().as_str() {
+        "UDP" => rsip::transport::Transport::Udp,
+        "TCP" => rsip::transport::Transport::Tcp,
+        "TLS" => rsip::transport::Transport::Tls,
+        "WS" => rsip::transport::Transport::Ws,
+        "WSS" => rsip::transport::Transport::Wss,
+        _ => rsip::transport::Transport::Udp, // Default to UDP
+    };
+
+    // Parse destination host to HostWithPort
+    let addr = model.destination.try_into()?;
+
+    // Create SipAddr
+    let destination = SipAddr {
+        r#type: Some(transport),
+        addr,
+    };
+
+    Ok(Location {
+        aor,
+        expires: model.expires as u32,
+        destination,
+        last_modified: None,
+        supports_webrtc: model.supports_webrtc,
+        ..Default::default()
+    })
+}
@@ -0,0 +1,431 @@
+use super::{
+    ProxyAction, ProxyModule,
+    metering::call_tenant,
+    server::{SipServerInner, SipServerRef},
+    status::ProxyStatus,
+};
+use crate::call::{Location, TransactionCookie};
+use crate::config::ProxyConfig;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use axum::{
+    Json,
+    http::StatusCode as HttpStatusCode,
+    response::{IntoResponse, Response},
+};
+use reqwest::Client;
+use rsip::prelude::{HeadersExt, UntypedHeader};
+use rsipstack::{
+    transaction::{
+        key::{TransactionKey, TransactionRole},
+        make_tag, random_text,
+        transaction::Transaction,
+    },
+    transport::SipAddr,
+};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Instant};
+use tokio::sync::broadcast::error::RecvError;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+/// A message submitted through the REST API
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SendMessage {
+    pub from: String,
+    pub to: String,
+    pub content_type: Option<String>,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageDelivery {
+    pub id: String,
+    pub status: u16,
+    pub delivered: bool,
+}
+
+/// Relays SIP MESSAGE (RFC 3428) requests to registered users or external
+/// realms, and reports each message on the proxy status channel
+#[derive(Clone)]
+pub struct MessageModule {
+    server: SipServerRef,
+    config: Arc<ProxyConfig>,
+}
+
+impl MessageModule {
+    pub fn create(server: SipServerRef, config: Arc<ProxyConfig>) -> Result<Box<dyn ProxyModule>> {
+        let module = MessageModule::new(server, config);
+        Ok(Box::new(module))
+    }
+
+    pub fn new(server: SipServerRef, config: Arc<ProxyConfig>) -> Self {
+        Self { server, config }
+    }
+}
+
+#[async_trait]
+impl ProxyModule for MessageModule {
+    fn name(&self) -> &str {
+        "message"
+    }
+
+    fn allow_methods(&self) -> Vec<rsip::Method> {
+        vec![rsip::Method::Message]
+    }
+
+    async fn on_start(&mut self) -> Result<()> {
+        if let Some(url) = self.config.message_webhook.clone() {
+            let receiver = self.server.proxy_status.subscribe();
+            let token = self.server.cancel_token.child_token();
+            tokio::spawn(async move {
+                tokio::select! {
+                    _ = token.cancelled() => {}
+                    _ = post_message_events(url, receiver) => {}
+                }
+            });
+        }
+        debug!("Message module started");
+        Ok(())
+    }
+
+    async fn on_stop(&self) -> Result<()> {
+        debug!("Message module stopped");
+        Ok(())
+    }
+
+    async fn on_transaction_begin(
+        &self,
+        _token: CancellationToken,
+        tx: &mut Transaction,
+        _cookie: TransactionCookie,
+    ) -> Result<ProxyAction> {
+        if tx.original.method != rsip::Method::Message {
+            return Ok(ProxyAction::Continue);
+        }
+        let from = tx.original.from_header()?.uri()?;
+        let to = tx.original.to_header()?.uri()?;
+        let id = tx.original.call_id_header()?.value().to_string();
+        let content_type = rsip::header_opt!(tx.original.headers.iter(), rsip::Header::ContentType)
+            .map(|h| h.value().to_string())
+            .unwrap_or_else(|| "text/plain".to_string());
+
+        let delivery = send_message(
+            &self.server,
+            id,
+            from,
+            to,
+            content_type,
+            tx.original.body.clone(),
+        )
+        .await?;
+        tx.reply(rsip::StatusCode::from(delivery.status)).await.ok();
+        Ok(ProxyAction::Abort)
+    }
+}
+
+/// Sends a MESSAGE to `to` on behalf of `from`, trying each registered
+/// contact in turn until one accepts it
+pub async fn send_message(
+    server: &SipServerInner,
+    id: String,
+    from: rsip::Uri,
+    to: rsip::Uri,
+    content_type: String,
+    body: Vec<u8>,
+) -> Result<MessageDelivery> {
+    let timestamp = crate::get_timestamp();
+    server
+        .proxy_status
+        .send(ProxyStatus::MessageReceived {
+            id: id.clone(),
+            timestamp,
+            from: from.to_string(),
+            to: to.to_string(),
+            content_type: content_type.clone(),
+            body: String::from_utf8_lossy(&body).to_string(),
+        })
+        .ok();
+
+    let (status, destination) = match deliver(server, &from, &to, &content_type, body).await {
+        Ok(r) => r,
+        Err((e, status)) => {
+            warn!(id, %from, %to, "failed to deliver message: {}", e);
+            server
+                .proxy_status
+                .send(ProxyStatus::MessageFailed {
+                    id: id.clone(),
+                    timestamp: crate::get_timestamp(),
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    status: status.code(),
+                    reason: e.to_string(),
+                })
+                .ok();
+            return Ok(MessageDelivery {
+                id,
+                status: status.code(),
+                delivered: false,
+            });
+        }
+    };
+
+    let delivered = matches!(status.kind(), rsip::StatusCodeKind::Successful);
+    let event = if delivered {
+        info!(id, %from, %to, %destination, "message delivered");
+        ProxyStatus::MessageDelivered {
+            id: id.clone(),
+            timestamp: crate::get_timestamp(),
+            from: from.to_string(),
+            to: to.to_string(),
+            destination,
+            status: status.code(),
+        }
+    } else {
+        info!(id, %from, %to, %status, "message rejected");
+        ProxyStatus::MessageFailed {
+            id: id.clone(),
+            timestamp: crate::get_timestamp(),
+            from: from.to_string(),
+            to: to.to_string(),
+            status: status.code(),
+            reason: status.to_string(),
+        }
+    };
+    server.proxy_status.send(event).ok();
+    Ok(MessageDelivery {
+        id,
+        status: status.code(),
+        delivered,
+    })
+}
+
+pub(crate) async fn resolve_targets(
+    server: &SipServerInner,
+    to: &rsip::Uri,
+) -> Result<Vec<Location>, (anyhow::Error, rsip::StatusCode)> {
+    let realm = to.host().to_string();
+    let locations = if server.is_same_realm(&realm).await {
+        let username = to.user().unwrap_or_default().to_string();
+        server
+            .locator
+            .lookup(&username, Some(&realm))
+            .await
+            .map_err(|e| (e, rsip::StatusCode::TemporarilyUnavailable))?
+    } else {
+        vec![Location {
+            aor: to.clone(),
+            destination: SipAddr::try_from(to)
+                .map_err(|e| (anyhow!(e), rsip::StatusCode::AddressIncomplete))?,
+            ..Default::default()
+        }]
+    };
+    if locations.is_empty() {
+        return Err((anyhow!("User offline"), rsip::StatusCode::NotFound));
+    }
+    Ok(locations)
+}
+
+fn make_message(
+    server: &SipServerInner,
+    req_uri: rsip::Uri,
+    from: &rsip::Uri,
+    to: &rsip::Uri,
+    content_type: &str,
+    body: Vec<u8>,
+) -> Result<rsip::Request> {
+    let via = server.endpoint.inner.get_via(None, None)?;
+    let from = rsip::typed::From {
+        display_name: None,
+        uri: from.clone(),
+        params: vec![],
+    }
+    .with_tag(make_tag());
+    let to = rsip::typed::To {
+        display_name: None,
+        uri: to.clone(),
+        params: vec![],
+    };
+    let mut request =
+        server
+            .endpoint
+            .inner
+            .make_request(rsip::Method::Message, req_uri, via, from, to, 1);
+    request
+        .headers
+        .push(rsip::Header::ContentType(content_type.into()));
+    request
+        .headers
+        .push(rsip::headers::ContentLength::from(body.len() as u32).into());
+    request.body = body;
+    Ok(request)
+}
+
+async fn deliver(
+    server: &SipServerInner,
+    from: &rsip::Uri,
+    to: &rsip::Uri,
+    content_type: &str,
+    body: Vec<u8>,
+) -> Result<(rsip::StatusCode, String), (anyhow::Error, rsip::StatusCode)> {
+    let mut targets = resolve_targets(server, to).await?;
+    if let Some(location_inspector) = server.location_inspector.as_ref() {
+        let original = make_message(server, to.clone(), from, to, content_type, body.clone())
+            .map_err(|e| (e, rsip::StatusCode::ServerInternalError))?;
+        for target in targets.iter_mut() {
+            *target = location_inspector
+                .inspect_location(target.clone(), &original)
+                .await
+                .map_err(|(e, status)| {
+                    (e, status.unwrap_or(rsip::StatusCode::ServerInternalError))
+                })?;
+        }
+    }
+
+    let mut last_status = rsip::StatusCode::RequestTimeout;
+    for target in targets {
+        let mut request = make_message(
+            server,
+            target.aor.clone(),
+            from,
+            to,
+            content_type,
+            body.clone(),
+        )
+        .map_err(|e| (e, rsip::StatusCode::ServerInternalError))?;
+        if let Some(headers) = target.headers.as_ref() {
+            for header in headers {
+                request.headers.unique_push(header.clone());
+            }
+        }
+        let destination = target.destination.to_string();
+        let start_time = Instant::now();
+        let status = match send_request(server, request, target.destination).await {
+            Ok(status) => status,
+            Err(e) => {
+                warn!(%destination, "failed to send message: {}", e);
+                rsip::StatusCode::ServiceUnavailable
+            }
+        };
+        debug!(%destination, %status, "message response in {:?}", start_time.elapsed());
+        if matches!(status.kind(), rsip::StatusCodeKind::Successful) {
+            return Ok((status, destination));
+        }
+        last_status = status;
+    }
+    Ok((last_status, String::new()))
+}
+
+async fn send_request(
+    server: &SipServerInner,
+    request: rsip::Request,
+    destination: SipAddr,
+) -> Result<rsip::StatusCode> {
+    let key = TransactionKey::from_request(&request, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, request, server.endpoint.inner.clone(), None);
+    tx.destination = Some(destination);
+    tx.send().await?;
+    while let Some(msg) = tx.receive().await {
+        if let rsip::SipMessage::Response(resp) = msg {
+            if matches!(resp.status_code.kind(), rsip::StatusCodeKind::Provisional) {
+                continue;
+            }
+            return Ok(resp.status_code);
+        }
+    }
+    Ok(rsip::StatusCode::RequestTimeout)
+}
+
+async fn post_message_events(url: String, mut receiver: super::status::ProxyStatusReceiver) {
+    let client = Client::new();
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(n)) => {
+                warn!(url, "message webhook lagged, {} events dropped", n);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        if !matches!(
+            event,
+            ProxyStatus::MessageReceived { .. }
+                | ProxyStatus::MessageDelivered { .. }
+                | ProxyStatus::MessageFailed { .. }
+        ) {
+            continue;
+        }
+        let start_time = Instant::now();
+        match client.post(&url).json(&event).send().await {
+            Ok(response) => {
+                debug!(
+                    url,
+                    status = response.status().as_u16(),
+                    "message event posted in {:?}",
+                    start_time.elapsed()
+                );
+            }
+            Err(e) => {
+                warn!(url, "failed to post message event: {}", e);
+            }
+        }
+    }
+}
+
+/// `POST /ami/v1/message` handler
+/// Sends the message, for a principal bound to `tenant` only between parties
+/// of that tenant
+pub async fn send_message_handler(
+    server: SipServerRef,
+    message: SendMessage,
+    tenant: Option<String>,
+) -> Response {
+    let parse = |uri: &str| {
+        let uri = if uri.starts_with("sip:") || uri.starts_with("sips:") {
+            uri.to_string()
+        } else {
+            format!("sip:{}", uri)
+        };
+        rsip::Uri::try_from(uri.as_str())
+    };
+    let (from, to) = match (parse(&message.from), parse(&message.to)) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => {
+            return (
+                HttpStatusCode::BAD_REQUEST,
+                Json(serde_json::json!({"error": e.to_string()})),
+            )
+                .into_response();
+        }
+    };
+    if tenant.is_some() && call_tenant(&server, &[&from, &to]).await != tenant {
+        return (
+            HttpStatusCode::FORBIDDEN,
+            Json(serde_json::json!({"error": format!("{} is not a user of the tenant", from)})),
+        )
+            .into_response();
+    }
+    let id = random_text(16);
+    let content_type = message
+        .content_type
+        .unwrap_or_else(|| "text/plain".to_string());
+    match send_message(
+        &server,
+        id,
+        from,
+        to,
+        content_type,
+        message.body.into_bytes(),
+    )
+    .await
+    {
+        Ok(delivery) => Json(delivery).into_response(),
+        Err(e) => (
+            HttpStatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
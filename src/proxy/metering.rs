@@ -0,0 +1,352 @@
+use super::server::{SipServerInner, SipServerRef};
+use crate::config::MeteringConfig;
+use anyhow::{Result, anyhow};
+use axum::{
+    Json,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// Channel usage of a tenant or trunk over a period
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Usage {
+    /// Calls started during the period
+    pub calls: u64,
+    /// Seconds of channel occupancy during the period, ringing included
+    pub channel_seconds: u64,
+    /// Channels in use at the end of the period
+    pub active_channels: u64,
+    /// Most channels in use at once during the period
+    pub peak_channels: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageSnapshot {
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub tenants: HashMap<String, Usage>,
+    pub trunks: HashMap<String, Usage>,
+}
+
+#[derive(Debug, Default)]
+struct Counter {
+    calls: u64,
+    channel_ms: u64,
+    active: u64,
+    peak: u64,
+}
+
+impl Counter {
+    fn open(&mut self) {
+        self.calls += 1;
+        self.active += 1;
+        self.peak = self.peak.max(self.active);
+    }
+
+    fn usage(&self) -> Usage {
+        Usage {
+            calls: self.calls,
+            channel_seconds: self.channel_ms / 1000,
+            active_channels: self.active,
+            peak_channels: self.peak,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Channel {
+    tenant: Option<String>,
+    trunk: Option<String>,
+    /// Time up to which the channel is accounted for
+    since: Instant,
+}
+
+#[derive(Debug)]
+struct MeterState {
+    period_start: DateTime<Utc>,
+    tenants: HashMap<String, Counter>,
+    trunks: HashMap<String, Counter>,
+    /// Calls in progress, by Call-ID
+    channels: HashMap<String, Channel>,
+}
+
+impl MeterState {
+    /// Adds the time a channel was in use since it was last accounted for
+    fn accrue(&mut self, call_id: &str, now: Instant) {
+        let Some(channel) = self.channels.get_mut(call_id) else {
+            return;
+        };
+        let elapsed = now.saturating_duration_since(channel.since).as_millis() as u64;
+        channel.since = channel.since.max(now);
+        if let Some(tenant) = channel.tenant.as_ref() {
+            self.tenants.entry(tenant.clone()).or_default().channel_ms += elapsed;
+        }
+        if let Some(trunk) = channel.trunk.as_ref() {
+            self.trunks.entry(trunk.clone()).or_default().channel_ms += elapsed;
+        }
+    }
+}
+
+/// Accounts channel-seconds per tenant and per trunk, for operators billing
+/// their customers. A channel is a call through the proxy, from routing to
+/// hangup
+#[derive(Debug)]
+pub struct Meter {
+    state: Mutex<MeterState>,
+    snapshots: broadcast::Sender<UsageSnapshot>,
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Meter {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(MeterState {
+                period_start: Utc::now(),
+                tenants: HashMap::new(),
+                trunks: HashMap::new(),
+                channels: HashMap::new(),
+            }),
+            snapshots: broadcast::channel(16).0,
+        }
+    }
+
+    /// Receives the snapshot of every closed period
+    pub fn subscribe(&self) -> broadcast::Receiver<UsageSnapshot> {
+        self.snapshots.subscribe()
+    }
+
+    /// Starts accounting a call, until the returned guard is dropped
+    pub fn enter(
+        self: &Arc<Self>,
+        call_id: impl Into<String>,
+        tenant: Option<String>,
+    ) -> MeteredChannel {
+        let call_id = call_id.into();
+        let Ok(mut state) = self.state.lock() else {
+            return MeteredChannel {
+                meter: self.clone(),
+                call_id: None,
+            };
+        };
+        if state.channels.contains_key(&call_id) {
+            return MeteredChannel {
+                meter: self.clone(),
+                call_id: None,
+            };
+        }
+        if let Some(tenant) = tenant.as_ref() {
+            state.tenants.entry(tenant.clone()).or_default().open();
+        }
+        state.channels.insert(
+            call_id.clone(),
+            Channel {
+                tenant,
+                trunk: None,
+                since: Instant::now(),
+            },
+        );
+        MeteredChannel {
+            meter: self.clone(),
+            call_id: Some(call_id),
+        }
+    }
+
+    /// Accounts the call to the trunk it was routed to from now on
+    pub fn set_trunk(&self, call_id: &str, trunk: &str) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if state
+            .channels
+            .get(call_id)
+            .is_none_or(|c| c.trunk.as_deref() == Some(trunk))
+        {
+            return;
+        }
+        state.accrue(call_id, Instant::now());
+        let previous = state
+            .channels
+            .get_mut(call_id)
+            .and_then(|c| c.trunk.replace(trunk.to_string()));
+        if let Some(counter) = previous.and_then(|t| state.trunks.get_mut(&t)) {
+            counter.active = counter.active.saturating_sub(1);
+        }
+        state.trunks.entry(trunk.to_string()).or_default().open();
+    }
+
+    fn exit(&self, call_id: &str) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        state.accrue(call_id, Instant::now());
+        let Some(channel) = state.channels.remove(call_id) else {
+            return;
+        };
+        if let Some(counter) = channel.tenant.and_then(|t| state.tenants.get_mut(&t)) {
+            counter.active = counter.active.saturating_sub(1);
+        }
+        if let Some(counter) = channel.trunk.and_then(|t| state.trunks.get_mut(&t)) {
+            counter.active = counter.active.saturating_sub(1);
+        }
+    }
+
+    /// Usage of the current period so far
+    pub fn usage(&self) -> UsageSnapshot {
+        self.snapshot(Instant::now(), false)
+    }
+
+    /// Ends the current period and publishes its usage to subscribers. Calls
+    /// in progress carry over, each period gets its share of their time
+    pub fn close_period(&self) -> UsageSnapshot {
+        let snapshot = self.snapshot(Instant::now(), true);
+        self.snapshots.send(snapshot.clone()).ok();
+        snapshot
+    }
+
+    pub(crate) fn snapshot(&self, now: Instant, close: bool) -> UsageSnapshot {
+        let period_end = Utc::now();
+        let Ok(mut state) = self.state.lock() else {
+            return UsageSnapshot {
+                period_start: period_end,
+                period_end,
+                tenants: HashMap::new(),
+                trunks: HashMap::new(),
+            };
+        };
+        let call_ids = state.channels.keys().cloned().collect::<Vec<_>>();
+        for call_id in call_ids {
+            state.accrue(&call_id, now);
+        }
+        let usage = |counters: &HashMap<String, Counter>| {
+            counters
+                .iter()
+                .map(|(name, counter)| (name.clone(), counter.usage()))
+                .collect()
+        };
+        let snapshot = UsageSnapshot {
+            period_start: state.period_start,
+            period_end,
+            tenants: usage(&state.tenants),
+            trunks: usage(&state.trunks),
+        };
+        if close {
+            let state = &mut *state;
+            state.period_start = period_end;
+            for counters in [&mut state.tenants, &mut state.trunks] {
+                counters.retain(|_, counter| counter.active > 0);
+                for counter in counters.values_mut() {
+                    *counter = Counter {
+                        active: counter.active,
+                        peak: counter.active,
+                        ..Default::default()
+                    };
+                }
+            }
+        }
+        snapshot
+    }
+}
+
+/// A call being accounted for, ends the accounting when dropped
+pub struct MeteredChannel {
+    meter: Arc<Meter>,
+    call_id: Option<String>,
+}
+
+impl Drop for MeteredChannel {
+    fn drop(&mut self) {
+        if let Some(call_id) = self.call_id.as_ref() {
+            self.meter.exit(call_id);
+        }
+    }
+}
+
+/// The tenant of a call: the realm of the first local party, renamed by
+/// `metering.tenants`
+pub async fn call_tenant(server: &SipServerInner, parties: &[&rsip::Uri]) -> Option<String> {
+    for uri in parties {
+        let realm = uri.host().to_string();
+        if server.is_same_realm(&realm).await {
+            let tenant = server
+                .config
+                .metering
+                .as_ref()
+                .and_then(|metering| metering.tenants.get(&realm).cloned());
+            return Some(tenant.unwrap_or(realm));
+        }
+    }
+    None
+}
+
+/// Closes a usage period every `metering.interval` and exports its snapshot
+pub fn start_metering(server: &SipServerRef) {
+    let Some(config) = server.config.metering.clone() else {
+        return;
+    };
+    let interval = Duration::from_secs(config.interval.max(1));
+    info!(interval = config.interval, "exporting channel usage");
+    let server = server.clone();
+    let token = server.cancel_token.child_token();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = ticker.tick() => {
+                    let snapshot = server.meter.close_period();
+                    if let Err(e) = export_usage(&config, &snapshot).await {
+                        warn!("failed to export usage: {}", e);
+                    }
+                }
+            }
+        }
+    });
+}
+
+pub async fn export_usage(config: &MeteringConfig, snapshot: &UsageSnapshot) -> Result<()> {
+    if let Some(path) = config.path.as_ref() {
+        tokio::fs::create_dir_all(path).await?;
+        let file_name = format!(
+            "{}/usage-{}.json",
+            path,
+            snapshot.period_end.format("%Y%m%d-%H%M%S")
+        );
+        tokio::fs::write(&file_name, serde_json::to_vec_pretty(snapshot)?).await?;
+    }
+    if let Some(url) = config.url.as_ref() {
+        let response = reqwest::Client::new()
+            .post(url)
+            .json(snapshot)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(anyhow!("{} returned {}", url, response.status()));
+        }
+    }
+    Ok(())
+}
+
+/// The usage so far, for a principal bound to `tenant` only that tenant's
+pub async fn usage_handler(server: SipServerRef, tenant: Option<String>) -> Response {
+    let mut usage = server.meter.usage();
+    if let Some(tenant) = tenant {
+        usage.tenants.retain(|name, _| *name == tenant);
+        usage.trunks.clear();
+    }
+    Json(usage).into_response()
+}
@@ -8,23 +8,44 @@ use std::sync::Arc;
 use tokio_util::sync::CancellationToken;
 
 pub mod acl;
+pub mod agent;
 pub mod auth;
+pub mod auth_http;
+pub mod auth_ldap;
+pub mod auth_static;
 pub mod call;
+pub mod campaign;
+pub mod campon;
+pub mod clicktodial;
+pub mod cluster;
+pub mod fraud;
+pub mod hardening;
+pub mod limits;
+pub mod listener;
 pub mod locator;
 pub mod locator_db;
+pub mod message;
+pub mod metering;
+pub mod nat;
+pub mod parser;
 pub mod presence;
+pub mod queue;
+pub mod radius;
 pub mod registrar;
 pub mod routing;
 pub use routing::RoutingState;
 pub mod server;
 pub mod session;
+pub mod skills;
 pub mod status;
 #[cfg(test)]
 pub mod tests;
+pub mod trunk_monitor;
 pub mod user;
 pub mod user_db;
 pub mod user_http;
 pub mod user_plain;
+pub mod wallboard;
 pub mod ws;
 
 #[derive(Debug)]
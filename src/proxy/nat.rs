@@ -0,0 +1,185 @@
+use super::server::SipServerRef;
+use crate::config::{NatKeepaliveConfig, NatKeepaliveMethod};
+use anyhow::{Result, anyhow};
+use rsipstack::{
+    transaction::{
+        key::{TransactionKey, TransactionRole},
+        make_tag,
+        transaction::Transaction,
+    },
+    transport::{SipAddr, SipConnection, connection::KEEPALIVE_REQUEST, stream::StreamConnection},
+};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+#[derive(Debug, Clone)]
+pub struct NatBinding {
+    pub aor: rsip::Uri,
+    pub destination: SipAddr,
+    pub expires_at: Instant,
+}
+
+/// Pings registered UAs so the NAT bindings their registrations rely on
+/// stay open between re-REGISTERs
+pub struct NatKeepalive {
+    server: SipServerRef,
+    config: NatKeepaliveConfig,
+    bindings: Mutex<HashMap<String, NatBinding>>,
+}
+
+impl NatKeepalive {
+    pub fn new(server: SipServerRef, config: NatKeepaliveConfig) -> Self {
+        Self {
+            server,
+            config,
+            bindings: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A UA is behind NAT when the Contact it registered is not the address
+    /// its REGISTER arrived from
+    pub fn is_behind_nat(contact: Option<&rsip::Uri>, destination: &SipAddr) -> bool {
+        contact
+            .map(|uri| uri.host_with_port != destination.addr)
+            .unwrap_or(true)
+    }
+
+    pub fn should_keepalive(&self, contact: Option<&rsip::Uri>, destination: &SipAddr) -> bool {
+        !self.config.nat_only || Self::is_behind_nat(contact, destination)
+    }
+
+    pub fn add(&self, key: &str, aor: rsip::Uri, destination: SipAddr, expires: u32) {
+        let binding = NatBinding {
+            aor,
+            destination,
+            expires_at: Instant::now() + Duration::from_secs(expires as u64),
+        };
+        debug!(key, destination = %binding.destination, "keepalive binding added");
+        self.bindings
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), binding);
+    }
+
+    pub fn remove(&self, key: &str) {
+        self.bindings.lock().unwrap().remove(key);
+    }
+
+    /// Removes the binding of `aor_key` together with all its outbound flows
+    pub fn remove_aor(&self, aor_key: &str) {
+        let flow_prefix = format!("{};", aor_key);
+        self.bindings
+            .lock()
+            .unwrap()
+            .retain(|key, _| key != aor_key && !key.starts_with(&flow_prefix));
+    }
+
+    pub fn bindings(&self) -> HashMap<String, NatBinding> {
+        self.bindings.lock().unwrap().clone()
+    }
+
+    pub async fn serve(&self, token: CancellationToken) {
+        info!(
+            interval = self.config.interval,
+            method = ?self.config.method,
+            "NAT keepalive started"
+        );
+        let mut ticker = tokio::time::interval(Duration::from_secs(self.config.interval.max(1)));
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => break,
+                _ = ticker.tick() => self.ping_all().await,
+            }
+        }
+    }
+
+    /// Drops expired bindings and sends one keepalive to each of the others
+    pub async fn ping_all(&self) {
+        let now = Instant::now();
+        let bindings = {
+            let mut bindings = self.bindings.lock().unwrap();
+            bindings.retain(|_, binding| binding.expires_at > now);
+            bindings.clone()
+        };
+        for (key, binding) in bindings {
+            let result = match self.config.method {
+                NatKeepaliveMethod::Crlf => self.send_crlf(&binding).await,
+                NatKeepaliveMethod::Options => {
+                    let server = self.server.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = send_options(&server, &binding).await {
+                            debug!(destination = %binding.destination, "keepalive OPTIONS failed: {}", e);
+                        }
+                    });
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                warn!(key, "failed to send keepalive: {}", e);
+            }
+        }
+    }
+
+    async fn send_crlf(&self, binding: &NatBinding) -> Result<()> {
+        let (connection, target) = self
+            .server
+            .endpoint
+            .inner
+            .transport_layer
+            .lookup(&binding.destination, None)
+            .await?;
+        match connection {
+            SipConnection::Udp(udp) => udp.send_raw(KEEPALIVE_REQUEST, &target).await?,
+            SipConnection::Tcp(tcp) => tcp.send_raw(KEEPALIVE_REQUEST).await?,
+            SipConnection::Tls(tls) => tls.send_raw(KEEPALIVE_REQUEST).await?,
+            // websocket clients are kept alive by the websocket layer
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+async fn send_options(server: &SipServerRef, binding: &NatBinding) -> Result<rsip::StatusCode> {
+    let via = server.endpoint.inner.get_via(None, None)?;
+    let from = rsip::typed::From {
+        display_name: None,
+        uri: rsip::Uri {
+            scheme: Some(rsip::Scheme::Sip),
+            host_with_port: via.uri.host_with_port.clone(),
+            ..Default::default()
+        },
+        params: vec![],
+    }
+    .with_tag(make_tag());
+    let to = rsip::typed::To {
+        display_name: None,
+        uri: binding.aor.clone(),
+        params: vec![],
+    };
+    let request = server.endpoint.inner.make_request(
+        rsip::Method::Options,
+        binding.aor.clone(),
+        via,
+        from,
+        to,
+        1,
+    );
+    let key = TransactionKey::from_request(&request, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, request, server.endpoint.inner.clone(), None);
+    tx.destination = Some(binding.destination.clone());
+    tx.send().await?;
+    while let Some(msg) = tx.receive().await {
+        if let rsip::SipMessage::Response(resp) = msg {
+            if matches!(resp.status_code.kind(), rsip::StatusCodeKind::Provisional) {
+                continue;
+            }
+            return Ok(resp.status_code);
+        }
+    }
+    Err(anyhow!("no response"))
+}
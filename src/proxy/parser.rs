@@ -0,0 +1,196 @@
+use super::status::{ProxyStatus, ProxyStatusSender};
+use crate::config::SipParseMode;
+use rsip::SipMessage;
+use tracing::{debug, warn};
+
+#[derive(Debug)]
+pub struct ParsedMessage {
+    pub message: SipMessage,
+    /// What lenient parsing had to fix, empty for clean messages
+    pub repairs: Vec<String>,
+}
+
+/// Headers a message is useless without, never dropped to recover
+const MANDATORY_HEADERS: &[&str] = &[
+    "via",
+    "v",
+    "from",
+    "f",
+    "to",
+    "t",
+    "call-id",
+    "i",
+    "cseq",
+    "content-length",
+    "l",
+];
+
+pub fn parse_sip_message(raw: &[u8], mode: SipParseMode) -> Result<ParsedMessage, String> {
+    let text = std::str::from_utf8(raw).map_err(|e| format!("invalid utf-8: {}", e))?;
+    let strict_error = match SipMessage::try_from(text) {
+        Ok(message) => {
+            return Ok(ParsedMessage {
+                message,
+                repairs: vec![],
+            });
+        }
+        Err(e) => short_error(&e.to_string()),
+    };
+    if mode == SipParseMode::Strict {
+        return Err(strict_error);
+    }
+
+    let mut repairs = vec![];
+    let (head, body) = split_message(text);
+    let mut lines = normalize_header_lines(head, &mut repairs);
+    fix_content_length(&mut lines, body.len(), &mut repairs);
+
+    // one bad header should not cost the whole message
+    let mut kept = Vec::with_capacity(lines.len());
+    for (index, line) in lines.into_iter().enumerate() {
+        if index == 0 || is_header_line(&line) {
+            kept.push(line);
+            continue;
+        }
+        if MANDATORY_HEADERS.contains(&header_name(&line).to_lowercase().as_str()) {
+            return Err(strict_error);
+        }
+        repairs.push(format!("dropped header: {}", line));
+    }
+    match SipMessage::try_from(join_message(&kept, body).as_str()) {
+        Ok(message) => Ok(ParsedMessage { message, repairs }),
+        Err(_) => Err(strict_error),
+    }
+}
+
+/// Splits at the first empty line, accepting bare LF line endings
+fn split_message(text: &str) -> (&str, &str) {
+    for separator in ["\r\n\r\n", "\n\n", "\r\n\n", "\n\r\n"] {
+        if let Some(pos) = text.find(separator) {
+            return (&text[..pos], &text[pos + separator.len()..]);
+        }
+    }
+    (text.trim_end(), "")
+}
+
+/// Unfolds continuation lines and tidies `Name : value` spacing
+fn normalize_header_lines(head: &str, repairs: &mut Vec<String>) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    if head.replace("\r\n", "").contains('\n') {
+        repairs.push("bare LF line endings".to_string());
+    }
+    for line in head.split('\n').map(|line| line.trim_end_matches('\r')) {
+        if line.starts_with([' ', '\t']) && !lines.is_empty() {
+            let last = lines.last_mut().unwrap();
+            last.push(' ');
+            last.push_str(line.trim());
+            repairs.push("folded header line".to_string());
+            continue;
+        }
+        match line.split_once(':') {
+            Some((name, value)) if !lines.is_empty() && name != name.trim() => {
+                lines.push(format!("{}: {}", name.trim(), value.trim()));
+                repairs.push(format!("whitespace before colon: {}", name.trim()));
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+    repairs.dedup();
+    lines
+}
+
+fn fix_content_length(lines: &mut Vec<String>, body_len: usize, repairs: &mut Vec<String>) {
+    let position = lines.iter().skip(1).position(|line| {
+        let name = header_name(line).to_lowercase();
+        name == "content-length" || name == "l"
+    });
+    match position.map(|pos| pos + 1) {
+        Some(index) => {
+            let value = lines[index].split_once(':').map(|(_, v)| v.trim());
+            if value.and_then(|v| v.parse::<usize>().ok()) != Some(body_len) {
+                repairs.push(format!(
+                    "content-length {} does not match body of {} bytes",
+                    value.unwrap_or_default(),
+                    body_len
+                ));
+                lines[index] = format!("Content-Length: {}", body_len);
+            }
+        }
+        None => {
+            lines.push(format!("Content-Length: {}", body_len));
+        }
+    }
+}
+
+fn header_name(line: &str) -> &str {
+    line.split_once(':')
+        .map(|(name, _)| name.trim())
+        .unwrap_or(line)
+}
+
+/// `Name: value` with a token name, what the parser accepts as a header
+fn is_header_line(line: &str) -> bool {
+    match line.split_once(':') {
+        Some((name, _)) => {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-.!%*_+`'~".contains(c))
+        }
+        None => false,
+    }
+}
+
+fn join_message(lines: &[String], body: &str) -> String {
+    format!("{}\r\n\r\n{}", lines.join("\r\n"), body)
+}
+
+/// Parses a message off the wire and reports failures and repairs as
+/// `SipParseError` events, `None` means the message is dropped
+pub fn parse_and_report(
+    status: &ProxyStatusSender,
+    mode: SipParseMode,
+    raw: &[u8],
+    source: &str,
+    transport: &str,
+) -> Option<SipMessage> {
+    let (reason, recovered, repairs, message) = match parse_sip_message(raw, mode) {
+        Ok(parsed) if parsed.repairs.is_empty() => return Some(parsed.message),
+        Ok(parsed) => {
+            debug!(source, transport, repairs = ?parsed.repairs, "repaired SIP message");
+            (
+                "repaired malformed message".to_string(),
+                true,
+                parsed.repairs,
+                Some(parsed.message),
+            )
+        }
+        Err(reason) => {
+            warn!(
+                source,
+                transport, "dropping unparseable SIP message: {}", reason
+            );
+            (reason, false, vec![], None)
+        }
+    };
+    status
+        .send(ProxyStatus::SipParseError {
+            timestamp: crate::get_timestamp(),
+            source: source.to_string(),
+            transport: transport.to_string(),
+            reason,
+            recovered,
+            repairs,
+        })
+        .ok();
+    message
+}
+
+/// The parser echoes the whole message back in its errors, keep the gist
+fn short_error(error: &str) -> String {
+    let first_line = error.lines().next().unwrap_or_default();
+    match first_line.char_indices().nth(120) {
+        Some((pos, _)) => format!("{}...", &first_line[..pos]),
+        None => first_line.to_string(),
+    }
+}
@@ -1,21 +1,512 @@
-use super::{server::SipServerRef, ProxyModule};
+use super::{
+    ProxyAction, ProxyModule, cluster::Takeover, locator_db::unix_timestamp, server::SipServerRef,
+};
+use crate::call::{TransactionCookie, parse_route_set, user::SipUser};
 use crate::config::ProxyConfig;
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use async_trait::async_trait;
-use std::sync::Arc;
+use rsip::prelude::{HeadersExt, ToTypedHeader, UntypedHeader};
+use rsipstack::{
+    transaction::{
+        key::{TransactionKey, TransactionRole},
+        make_tag,
+        transaction::Transaction,
+    },
+    transport::SipAddr,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
 
+/// How long a SUBSCRIBE without Expires lasts, in seconds
+const DEFAULT_EXPIRES: u32 = 3600;
+/// How often expired subscriptions are ended
+const EXPIRE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A user going on or off the phone, keyed by `user@realm`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PresenceChange {
+    pub aor: String,
+    pub busy: bool,
+}
+
+/// Which users are on a call right now, fed by the calls the proxy handles
+pub struct PresenceState {
+    calls: Mutex<HashMap<String, usize>>,
+    changes: broadcast::Sender<PresenceChange>,
+}
+
+impl PresenceState {
+    pub fn new() -> Self {
+        Self {
+            calls: Mutex::new(HashMap::new()),
+            changes: broadcast::channel(64).0,
+        }
+    }
+
+    pub fn aor(user: &str, realm: &str) -> String {
+        format!("{}@{}", user, realm).to_lowercase()
+    }
+
+    pub fn is_busy(&self, aor: &str) -> bool {
+        self.calls
+            .lock()
+            .map(|calls| calls.contains_key(&aor.to_lowercase()))
+            .unwrap_or_default()
+    }
+
+    /// Whether the user is on a call in any realm
+    pub fn is_user_busy(&self, user: &str) -> bool {
+        let prefix = format!("{}@", user.to_lowercase());
+        self.calls
+            .lock()
+            .map(|calls| calls.keys().any(|aor| aor.starts_with(&prefix)))
+            .unwrap_or_default()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<PresenceChange> {
+        self.changes.subscribe()
+    }
+
+    /// Marks `aors` busy until the returned guard is dropped
+    pub fn enter_call(self: &Arc<Self>, aors: Vec<String>) -> CallPresence {
+        let aors = aors
+            .into_iter()
+            .map(|aor| aor.to_lowercase())
+            .collect::<Vec<_>>();
+        for aor in aors.iter() {
+            self.update(aor, 1);
+        }
+        CallPresence {
+            state: self.clone(),
+            aors,
+        }
+    }
+
+    fn update(&self, aor: &str, delta: isize) {
+        let Ok(mut calls) = self.calls.lock() else {
+            return;
+        };
+        let count = calls.get(aor).copied().unwrap_or_default() as isize + delta;
+        let was_busy = calls.contains_key(aor);
+        if count > 0 {
+            calls.insert(aor.to_string(), count as usize);
+        } else {
+            calls.remove(aor);
+        }
+        if was_busy != (count > 0) {
+            self.changes
+                .send(PresenceChange {
+                    aor: aor.to_string(),
+                    busy: count > 0,
+                })
+                .ok();
+        }
+    }
+}
+
+impl Default for PresenceState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct CallPresence {
+    state: Arc<PresenceState>,
+    aors: Vec<String>,
+}
+
+impl Drop for CallPresence {
+    fn drop(&mut self) {
+        for aor in self.aors.iter() {
+            self.state.update(aor, -1);
+        }
+    }
+}
+
+/// A SUBSCRIBE dialog (RFC 6665) watching a local user, shared with the
+/// other nodes of a cluster
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Subscription {
+    pub call_id: String,
+    /// The watched `user@realm`
+    pub aor: String,
+    /// `presence` or `dialog`
+    pub event: String,
+    /// Our side of the dialog, the To of the SUBSCRIBE with our tag
+    pub local: String,
+    /// The subscriber's side, the From of the SUBSCRIBE
+    pub remote: String,
+    /// The subscriber's Contact, where NOTIFYs are sent
+    pub target: String,
+    /// The Record-Route of the SUBSCRIBE
+    pub route_set: Vec<String>,
+    /// The address the SUBSCRIBE came from, e.g. the subscriber's NAT
+    pub destination: String,
+    pub transport: Option<String>,
+    /// Unix time the subscription ends at, in seconds
+    pub expires: i64,
+    /// CSeq of the last NOTIFY
+    pub cseq: u32,
+    /// Version of the next dialog-info document
+    pub version: u32,
+}
+
+impl Subscription {
+    fn destination(&self) -> Result<SipAddr> {
+        Ok(SipAddr {
+            r#type: self.transport.as_ref().and_then(|t| t.parse().ok()),
+            addr: self.destination.clone().try_into()?,
+        })
+    }
+
+    fn body(&self, busy: bool) -> (&'static str, String) {
+        let entity = format!("sip:{}", self.aor);
+        match self.event.as_str() {
+            "dialog" => {
+                let dialog = if busy {
+                    "<dialog id=\"call\"><state>confirmed</state></dialog>\n"
+                } else {
+                    ""
+                };
+                (
+                    "application/dialog-info+xml",
+                    format!(
+                        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                         <dialog-info xmlns=\"urn:ietf:params:xml:ns:dialog-info\" version=\"{}\" state=\"full\" entity=\"{}\">\n\
+                         {}</dialog-info>\n",
+                        self.version, entity, dialog
+                    ),
+                )
+            }
+            _ => {
+                let activity = if busy { "on-the-phone" } else { "unknown" };
+                (
+                    "application/pidf+xml",
+                    format!(
+                        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                         <presence xmlns=\"urn:ietf:params:xml:ns:pidf\" xmlns:dm=\"urn:ietf:params:xml:ns:pidf:data-model\" xmlns:rpid=\"urn:ietf:params:xml:ns:pidf:rpid\" entity=\"{}\">\n\
+                         <tuple id=\"pbx\"><status><basic>open</basic></status></tuple>\n\
+                         <dm:person id=\"p\"><rpid:activities><rpid:{}/></rpid:activities></dm:person>\n\
+                         </presence>\n",
+                        entity, activity
+                    ),
+                )
+            }
+        }
+    }
+}
+
+/// Answers SUBSCRIBEs to the `presence` and `dialog` (RFC 4235) events of
+/// local users, and NOTIFYs the subscribers as the users go on and off a
+/// call. In a cluster the node taking over one that went down keeps
+/// notifying its subscribers
 #[derive(Clone)]
-pub struct PresenceModule {}
+pub struct PresenceModule {
+    server: SipServerRef,
+    subscriptions: Arc<Mutex<HashMap<String, Subscription>>>,
+}
 
 impl PresenceModule {
-    pub fn create(_server: SipServerRef, config: Arc<ProxyConfig>) -> Result<Box<dyn ProxyModule>> {
-        let module = PresenceModule::new(config);
+    pub fn create(server: SipServerRef, config: Arc<ProxyConfig>) -> Result<Box<dyn ProxyModule>> {
+        let module = PresenceModule::new(server, config);
         Ok(Box::new(module))
     }
-    pub fn new(_config: Arc<ProxyConfig>) -> Self {
-        Self {}
+    pub fn new(server: SipServerRef, _config: Arc<ProxyConfig>) -> Self {
+        Self {
+            server,
+            subscriptions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn subscriptions(&self) -> Vec<Subscription> {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// This node, so the subscriber sends its refreshes here
+    fn contact(&self) -> Result<rsip::typed::Contact> {
+        let via = self.server.endpoint.inner.get_via(None, None)?;
+        let params = match via.transport {
+            rsip::Transport::Udp => vec![],
+            transport => vec![rsip::Param::Transport(transport)],
+        };
+        Ok(rsip::typed::Contact {
+            display_name: None,
+            uri: rsip::Uri {
+                scheme: Some(via.transport.sip_scheme()),
+                host_with_port: via.uri.host_with_port,
+                params,
+                ..Default::default()
+            },
+            params: vec![],
+        })
+    }
+
+    fn new_subscription(
+        &self,
+        tx: &mut Transaction,
+        aor: String,
+        event: String,
+        call_id: String,
+    ) -> Result<Subscription> {
+        let destination = SipUser::try_from(&*tx)?
+            .destination
+            .ok_or_else(|| anyhow!("no source address"))?;
+        let target = tx.original.contact_header()?.typed()?.uri.to_string();
+        let local = tx.original.to_header()?.typed()?.with_tag(make_tag());
+        let route_set = tx
+            .original
+            .headers
+            .iter()
+            .filter_map(|h| match h {
+                rsip::Header::RecordRoute(route) => Some(parse_route_set(route.value())),
+                _ => None,
+            })
+            .flatten()
+            .map(|uri| uri.to_string())
+            .collect();
+        let subscription = Subscription {
+            call_id,
+            aor,
+            event,
+            local: local.to_string(),
+            remote: tx.original.from_header()?.value().to_string(),
+            target,
+            route_set,
+            destination: destination.addr.to_string(),
+            transport: destination.r#type.map(|t| t.to_string()),
+            expires: 0,
+            cseq: 0,
+            version: 0,
+        };
+        // the 200 carries our tag
+        tx.original
+            .headers
+            .unique_push(rsip::Header::To(local.into()));
+        Ok(subscription)
+    }
+
+    /// Adds or refreshes a subscription and sends the watched user's state
+    fn subscribe(&self, subscription: Subscription) {
+        let call_id = subscription.call_id.clone();
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .insert(call_id.clone(), subscription);
+        let module = self.clone();
+        tokio::spawn(async move { module.notify(&call_id).await });
+    }
+
+    /// Sends the watched user's state to the subscriber
+    async fn notify(&self, call_id: &str) {
+        let subscription = {
+            let mut subscriptions = self.subscriptions.lock().unwrap();
+            let Some(subscription) = subscriptions.get_mut(call_id) else {
+                return;
+            };
+            subscription.cseq += 1;
+            let current = subscription.clone();
+            subscription.version += 1;
+            current
+        };
+        if let Some(cluster) = self.server.cluster.as_ref()
+            && let Err(e) = cluster.save_subscription(&subscription).await
+        {
+            warn!(call_id, "failed to share the subscription: {}", e);
+        }
+        self.send_notify(subscription).await;
+    }
+
+    /// Ends the subscription with a last NOTIFY
+    async fn terminate(&self, mut subscription: Subscription) {
+        if let Some(cluster) = self.server.cluster.as_ref()
+            && let Err(e) = cluster.remove_subscription(&subscription.call_id).await
+        {
+            warn!(
+                call_id = subscription.call_id,
+                "failed to remove the subscription: {}", e
+            );
+        }
+        subscription.cseq += 1;
+        subscription.expires = 0;
+        self.send_notify(subscription).await;
+    }
+
+    async fn send_notify(&self, subscription: Subscription) {
+        let call_id = subscription.call_id.clone();
+        let status = match self.send_request(&subscription).await {
+            Ok(status) => status,
+            Err(e) => {
+                warn!(call_id, "failed to send NOTIFY: {}", e);
+                return;
+            }
+        };
+        debug!(call_id, %status, "NOTIFY answered");
+        // the subscriber is gone or no longer knows the dialog
+        if subscription.expires <= 0
+            || !matches!(
+                status,
+                rsip::StatusCode::CallTransactionDoesNotExist | rsip::StatusCode::RequestTimeout
+            )
+        {
+            return;
+        }
+        let removed = self.subscriptions.lock().unwrap().remove(&call_id);
+        if let Some(subscription) = removed {
+            info!(
+                call_id,
+                aor = subscription.aor,
+                "subscription ended by the subscriber"
+            );
+            if let Some(cluster) = self.server.cluster.as_ref() {
+                cluster.remove_subscription(&call_id).await.ok();
+            }
+        }
+    }
+
+    async fn send_request(&self, subscription: &Subscription) -> Result<rsip::StatusCode> {
+        let remaining = subscription.expires - unix_timestamp();
+        let state = if remaining > 0 {
+            format!("active;expires={}", remaining)
+        } else {
+            "terminated;reason=timeout".to_string()
+        };
+        let (content_type, body) =
+            subscription.body(self.server.presence.is_busy(&subscription.aor));
+        let via = self.server.endpoint.inner.get_via(None, None)?;
+        let from = rsip::headers::From::from(subscription.local.clone()).typed()?;
+        let to = rsip::headers::To::from(subscription.remote.clone()).typed()?;
+        let mut request = self.server.endpoint.inner.make_request(
+            rsip::Method::Notify,
+            subscription.target.as_str().try_into()?,
+            via,
+            from,
+            to,
+            subscription.cseq,
+        );
+        request
+            .headers
+            .unique_push(rsip::Header::CallId(subscription.call_id.clone().into()));
+        request.headers.push(self.contact()?.into());
+        for route in subscription.route_set.iter() {
+            request
+                .headers
+                .push(rsip::Header::Route(format!("<{}>", route).into()));
+        }
+        request
+            .headers
+            .push(rsip::Header::Event(subscription.event.clone().into()));
+        request
+            .headers
+            .push(rsip::Header::SubscriptionState(state.into()));
+        request
+            .headers
+            .push(rsip::Header::ContentType(content_type.into()));
+        request
+            .headers
+            .push(rsip::headers::ContentLength::from(body.len() as u32).into());
+        request.body = body.into_bytes();
+
+        let key = TransactionKey::from_request(&request, TransactionRole::Client)?;
+        let mut tx =
+            Transaction::new_client(key, request, self.server.endpoint.inner.clone(), None);
+        tx.destination = Some(subscription.destination()?);
+        tx.send().await?;
+        while let Some(msg) = tx.receive().await {
+            if let rsip::SipMessage::Response(resp) = msg {
+                if matches!(resp.status_code.kind(), rsip::StatusCodeKind::Provisional) {
+                    continue;
+                }
+                return Ok(resp.status_code);
+            }
+        }
+        Ok(rsip::StatusCode::RequestTimeout)
+    }
+
+    fn on_presence_change(&self, aor: &str) {
+        let call_ids = self
+            .subscriptions
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|s| s.aor == aor)
+            .map(|s| s.call_id.clone())
+            .collect::<Vec<_>>();
+        for call_id in call_ids {
+            let module = self.clone();
+            tokio::spawn(async move { module.notify(&call_id).await });
+        }
+    }
+
+    fn expire(&self) {
+        let now = unix_timestamp();
+        let expired = {
+            let mut subscriptions = self.subscriptions.lock().unwrap();
+            let call_ids = subscriptions
+                .values()
+                .filter(|s| s.expires <= now)
+                .map(|s| s.call_id.clone())
+                .collect::<Vec<_>>();
+            call_ids
+                .iter()
+                .filter_map(|call_id| subscriptions.remove(call_id))
+                .collect::<Vec<_>>()
+        };
+        for subscription in expired {
+            let module = self.clone();
+            tokio::spawn(async move { module.terminate(subscription).await });
+        }
+    }
+
+    /// Keeps notifying the subscribers of a node that went down. The NOTIFY
+    /// carries this node's Contact, so their refreshes come here from now on
+    fn take_over(&self, takeover: Takeover) {
+        info!(
+            node = takeover.node,
+            subscriptions = takeover.subscriptions.len(),
+            "took over the subscriptions of a node that went down"
+        );
+        for subscription in takeover.subscriptions {
+            self.subscribe(subscription);
+        }
     }
 }
+
+async fn next_takeover(takeovers: &mut Option<broadcast::Receiver<Takeover>>) -> Option<Takeover> {
+    let Some(takeovers) = takeovers.as_mut() else {
+        return std::future::pending().await;
+    };
+    loop {
+        match takeovers.recv().await {
+            Ok(takeover) => return Some(takeover),
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(_) => return None,
+        }
+    }
+}
+
+/// The event package of an Event header, without its parameters
+fn event_package(value: &str) -> String {
+    value
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase()
+}
+
 #[async_trait]
 impl ProxyModule for PresenceModule {
     fn name(&self) -> &str {
@@ -29,9 +520,102 @@ impl ProxyModule for PresenceModule {
         ]
     }
     async fn on_start(&mut self) -> Result<()> {
+        let module = self.clone();
+        let token = self.server.cancel_token.child_token();
+        let mut changes = self.server.presence.subscribe();
+        let mut takeovers = self.server.cluster.as_ref().map(|c| c.subscribe());
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(EXPIRE_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = ticker.tick() => module.expire(),
+                    change = changes.recv() => match change {
+                        Ok(change) => module.on_presence_change(&change.aor),
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(_) => break,
+                    },
+                    Some(takeover) = next_takeover(&mut takeovers) => module.take_over(takeover),
+                }
+            }
+        });
         Ok(())
     }
     async fn on_stop(&self) -> Result<()> {
         Ok(())
     }
+
+    async fn on_transaction_begin(
+        &self,
+        _token: CancellationToken,
+        tx: &mut Transaction,
+        _cookie: TransactionCookie,
+    ) -> Result<ProxyAction> {
+        if !matches!(tx.original.method, rsip::Method::Subscribe) {
+            return Ok(ProxyAction::Continue);
+        }
+        let event = match rsip::header_opt!(tx.original.headers.iter(), rsip::Header::Event) {
+            Some(event) => event_package(event.value()),
+            None => return Ok(ProxyAction::Continue),
+        };
+        if !matches!(event.as_str(), "presence" | "dialog") {
+            return Ok(ProxyAction::Continue);
+        }
+        let realm = tx.original.uri.host().to_string();
+        if !self.server.is_same_realm(&realm).await {
+            return Ok(ProxyAction::Continue);
+        }
+        let aor = PresenceState::aor(&tx.original.uri.user().unwrap_or_default(), &realm);
+        let expires = tx
+            .original
+            .expires_header()
+            .and_then(|expires| expires.value().parse::<u32>().ok())
+            .unwrap_or(DEFAULT_EXPIRES);
+        let call_id = tx.original.call_id_header()?.value().to_string();
+
+        let mut subscription = if tx.original.to_header()?.tag()?.is_some() {
+            let existing = self.subscriptions.lock().unwrap().remove(&call_id);
+            match existing {
+                Some(subscription) => subscription,
+                None => {
+                    tx.reply(rsip::StatusCode::CallTransactionDoesNotExist)
+                        .await
+                        .ok();
+                    return Ok(ProxyAction::Abort);
+                }
+            }
+        } else {
+            match self.new_subscription(tx, aor, event, call_id) {
+                Ok(subscription) => subscription,
+                Err(e) => {
+                    info!("invalid SUBSCRIBE: {}", e);
+                    tx.reply(rsip::StatusCode::BadRequest).await.ok();
+                    return Ok(ProxyAction::Abort);
+                }
+            }
+        };
+        subscription.expires = unix_timestamp() + expires as i64;
+        let headers = vec![
+            rsip::Header::Expires(expires.into()),
+            self.contact()?.into(),
+        ];
+        tx.reply_with(rsip::StatusCode::OK, headers, None)
+            .await
+            .ok();
+        info!(
+            call_id = subscription.call_id,
+            aor = subscription.aor,
+            event = subscription.event,
+            expires,
+            "subscribed"
+        );
+        if expires == 0 {
+            // an unsubscribe, or a fetch of the current state
+            let module = self.clone();
+            tokio::spawn(async move { module.terminate(subscription).await });
+        } else {
+            self.subscribe(subscription);
+        }
+        Ok(ProxyAction::Abort)
+    }
 }
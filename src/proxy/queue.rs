@@ -0,0 +1,1023 @@
+use super::{
+    ProxyAction, ProxyModule,
+    agent::{AgentSessions, AgentState},
+    call::select_flows,
+    campaign::normalize_number,
+    clicktodial::{dial_routed, local_contact, parse_target, ring_locations, serve_call},
+    message::resolve_targets,
+    metering::call_tenant,
+    presence::PresenceState,
+    server::SipServerRef,
+    skills::SkillExpr,
+    wallboard::{Wallboard, run_wallboard},
+};
+use crate::call::{
+    ActiveCall, ActiveCallRef, ActiveCallType, CallOption, Command, SipUser, TransactionCookie,
+    b2bua::B2buaBuilder, gather::GatherOption, sip::Invitation,
+};
+use crate::config::{AgentConfig, ProxyConfig, QueueCallbackConfig, QueueConfig};
+use crate::event::SessionEvent;
+use crate::media::track::TrackConfig;
+use crate::synthesis::prompt::PromptSegment;
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rsip::prelude::{HeadersExt, UntypedHeader};
+use rsipstack::transaction::{make_tag, transaction::Transaction};
+use serde::Serialize;
+use serde_with::skip_serializing_none;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+const OFFER_PLAY_ID: &str = "queue-offer";
+const CONFIRM_PLAY_ID: &str = "queue-confirm";
+pub const SKILLS_HEADER: &str = "X-Queue-Skills";
+
+/// A caller waiting in a queue, or the callback booked in their place
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueEntry {
+    /// The session id of the caller's call
+    pub id: String,
+    pub caller: String,
+    pub joined_at: DateTime<Utc>,
+    /// The number to call back, the caller has hung up
+    pub callback: Option<String>,
+    /// The agent being rung for the entry
+    pub agent: Option<String>,
+    #[serde(skip)]
+    realm: String,
+    #[serde(skip)]
+    offered: bool,
+    /// Agents that did not answer, rung again once all have been tried
+    #[serde(skip)]
+    tried: Vec<String>,
+    /// Skills of the queue and the call
+    #[serde(skip)]
+    requires: Option<SkillExpr>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    entries: Vec<QueueEntry>,
+    /// Agents being rung, as `user@realm`
+    ringing: HashSet<String>,
+    stats: QueueStats,
+}
+
+/// Counts since the server started
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueStats {
+    /// Callers who joined
+    pub offered: u64,
+    /// Put through to an agent, callbacks included
+    pub answered: u64,
+    /// Answered within the service level time
+    pub answered_in_time: u64,
+    /// Hung up waiting, without booking a callback
+    pub abandoned: u64,
+    /// Callbacks booked
+    pub booked: u64,
+}
+
+/// What a wallboard shows of a queue
+#[skip_serializing_none]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueBoard {
+    pub name: String,
+    /// Callers on the line
+    pub waiting: usize,
+    /// Booked callbacks not placed yet
+    pub callbacks: usize,
+    /// Seconds the longest waiting caller has been on the line
+    pub longest_wait: u64,
+    /// The queue's agents by state
+    pub agents: HashMap<AgentState, usize>,
+    /// Percentage of the calls answered within the service level time,
+    /// of those answered or abandoned. `None` before the first
+    pub service_level: Option<f64>,
+    #[serde(flatten)]
+    pub stats: QueueStats,
+}
+
+/// The callers of a queue in the order they called. An idle agent goes
+/// to the first waiting entry whose required skills they have
+pub struct CallQueue {
+    pub name: String,
+    pub config: QueueConfig,
+    requires: Option<SkillExpr>,
+    /// By the seconds of waiting after which they apply
+    overflow: Vec<(u64, Option<SkillExpr>)>,
+    agents: Arc<HashMap<String, AgentConfig>>,
+    sessions: Arc<AgentSessions>,
+    state: Mutex<QueueState>,
+}
+
+/// Where the caller is in the callback dialogue
+enum Stage {
+    Waiting,
+    Offered,
+    Confirming(String),
+    Booked,
+}
+
+impl CallQueue {
+    pub fn new(name: String, config: QueueConfig) -> Result<Self> {
+        let requires = SkillExpr::parse(config.requires.as_deref().unwrap_or_default())?;
+        let mut overflow = config
+            .overflow
+            .iter()
+            .map(|rule| Ok((rule.after, SkillExpr::parse(&rule.requires)?)))
+            .collect::<Result<Vec<_>>>()?;
+        overflow.sort_by_key(|(after, _)| *after);
+        Ok(Self {
+            name,
+            config,
+            requires,
+            overflow,
+            agents: Arc::new(HashMap::new()),
+            sessions: Arc::new(AgentSessions::default()),
+            state: Mutex::new(QueueState::default()),
+        })
+    }
+
+    /// The skills of the agents
+    pub fn with_agents(mut self, agents: Arc<HashMap<String, AgentConfig>>) -> Self {
+        self.agents = agents;
+        self
+    }
+
+    /// Whether agents are logged in, paused or wrapping up
+    pub fn with_sessions(mut self, sessions: Arc<AgentSessions>) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
+    pub fn callback(&self) -> Option<&QueueCallbackConfig> {
+        self.config.callback.as_ref()
+    }
+
+    /// Adds a caller at the end of the queue, and returns their position.
+    /// `requires` adds to the skills of the queue
+    pub fn join(
+        &self,
+        id: &str,
+        caller: &str,
+        realm: &str,
+        requires: Option<SkillExpr>,
+        now: DateTime<Utc>,
+    ) -> usize {
+        let Ok(mut state) = self.state.lock() else {
+            return 0;
+        };
+        state.entries.push(QueueEntry {
+            id: id.to_string(),
+            caller: caller.to_string(),
+            joined_at: now,
+            callback: None,
+            agent: None,
+            realm: realm.to_string(),
+            offered: false,
+            tried: Vec::new(),
+            requires: SkillExpr::both(self.requires.clone(), requires),
+        });
+        state.stats.offered += 1;
+        state.entries.len()
+    }
+
+    /// 1 for the first in line
+    pub fn position(&self, id: &str) -> Option<usize> {
+        let state = self.state.lock().ok()?;
+        state.entries.iter().position(|e| e.id == id).map(|i| i + 1)
+    }
+
+    pub fn entries(&self) -> Vec<QueueEntry> {
+        self.state
+            .lock()
+            .map(|state| state.entries.clone())
+            .unwrap_or_default()
+    }
+
+    /// The caller hung up. A booked callback keeps its place
+    pub fn leave(&self, id: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            let count = state.entries.len();
+            state.entries.retain(|e| e.id != id || e.callback.is_some());
+            state.stats.abandoned += (count - state.entries.len()) as u64;
+        }
+    }
+
+    /// Whether the caller has waited long enough to be offered a callback,
+    /// which is offered once
+    pub fn offer_due(&self, id: &str, now: DateTime<Utc>) -> bool {
+        let Some(callback) = self.callback() else {
+            return false;
+        };
+        let Ok(mut state) = self.state.lock() else {
+            return false;
+        };
+        let Some(entry) = state.entries.iter_mut().find(|e| e.id == id) else {
+            return false;
+        };
+        if entry.offered || entry.agent.is_some() {
+            return false;
+        }
+        if (now - entry.joined_at).num_seconds() < callback.after as i64 {
+            return false;
+        }
+        entry.offered = true;
+        true
+    }
+
+    /// Turns the caller's entry into a callback to `number`, in the same
+    /// place in line
+    pub fn book_callback(&self, id: &str, number: &str) -> bool {
+        let Ok(mut state) = self.state.lock() else {
+            return false;
+        };
+        match state.entries.iter_mut().find(|e| e.id == id) {
+            Some(entry) => {
+                entry.callback = Some(number.to_string());
+                state.stats.booked += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn agent_aor(agent: &rsip::Uri) -> String {
+        PresenceState::aor(agent.user().unwrap_or_default(), &agent.host().to_string())
+    }
+
+    /// Configured by SIP URI, `user@realm` or user, in that order
+    pub fn skills(&self, agent: &rsip::Uri) -> &[String] {
+        [
+            agent.to_string(),
+            Self::agent_aor(agent),
+            agent.user().unwrap_or_default().to_string(),
+        ]
+        .iter()
+        .find_map(|key| self.agents.get(key))
+        .map(|agent| agent.skills.as_slice())
+        .unwrap_or_default()
+    }
+
+    /// The skills required of the entry's agent after its wait so far
+    fn requirement<'a>(
+        &'a self,
+        entry: &'a QueueEntry,
+        now: DateTime<Utc>,
+    ) -> Option<&'a SkillExpr> {
+        let waited = (now - entry.joined_at).num_seconds();
+        match self
+            .overflow
+            .iter()
+            .rev()
+            .find(|(after, _)| waited >= *after as i64)
+        {
+            Some((_, requires)) => requires.as_ref(),
+            None => entry.requires.as_ref(),
+        }
+    }
+
+    fn qualified(&self, entry: &QueueEntry, agent: &rsip::Uri, now: DateTime<Utc>) -> bool {
+        self.requirement(entry, now)
+            .is_none_or(|requires| requires.matches(self.skills(agent)))
+    }
+
+    /// Reserves an idle agent for the entry among the qualified ones no
+    /// waiting entry ahead of it can take. Agents are taken in the order
+    /// they are configured, skipping the ones that did not answer the
+    /// entry yet
+    fn claim_at(
+        &self,
+        state: &mut QueueState,
+        index: usize,
+        presence: &PresenceState,
+        now: DateTime<Utc>,
+    ) -> Option<rsip::Uri> {
+        let entry = &state.entries[index];
+        if entry.agent.is_some() {
+            return None;
+        }
+        let realm = rsip::Uri::try_from(format!("sip:{}", entry.realm).as_str()).ok()?;
+        let ahead = &state.entries[..index];
+        let idle: Vec<rsip::Uri> = self
+            .config
+            .agents
+            .iter()
+            .filter_map(|agent| parse_target(agent, &realm).ok())
+            .filter(|agent| {
+                let aor = Self::agent_aor(agent);
+                !presence.is_busy(&aor)
+                    && self.sessions.available(&aor, self.config.login, now)
+                    && !state.ringing.contains(&aor)
+                    && self.qualified(entry, agent, now)
+                    && !ahead
+                        .iter()
+                        .any(|e| e.agent.is_none() && self.qualified(e, agent, now))
+            })
+            .collect();
+        let entry = &mut state.entries[index];
+        let agent = match idle
+            .iter()
+            .find(|agent| !entry.tried.contains(&Self::agent_aor(agent)))
+        {
+            Some(agent) => agent.clone(),
+            None => {
+                entry.tried.clear();
+                idle.first()?.clone()
+            }
+        };
+        entry.agent = Some(agent.to_string());
+        state.ringing.insert(Self::agent_aor(&agent));
+        Some(agent)
+    }
+
+    /// An agent to ring for a waiting caller, reserved until released
+    pub fn claim(
+        &self,
+        id: &str,
+        presence: &PresenceState,
+        now: DateTime<Utc>,
+    ) -> Option<rsip::Uri> {
+        let mut state = self.state.lock().ok()?;
+        let index = state.entries.iter().position(|e| e.id == id)?;
+        self.claim_at(&mut state, index, presence, now)
+    }
+
+    /// The booked callbacks that can be placed now, with their agents
+    pub fn due_callbacks(
+        &self,
+        presence: &PresenceState,
+        now: DateTime<Utc>,
+    ) -> Vec<(QueueEntry, rsip::Uri)> {
+        let Ok(mut state) = self.state.lock() else {
+            return Vec::new();
+        };
+        let mut due = Vec::new();
+        for index in 0..state.entries.len() {
+            if state.entries[index].callback.is_none() {
+                continue;
+            }
+            if let Some(agent) = self.claim_at(&mut state, index, presence, now) {
+                due.push((state.entries[index].clone(), agent));
+            }
+        }
+        due
+    }
+
+    /// The agent did not answer, the entry waits for the next one
+    pub fn release(&self, id: &str, agent: &rsip::Uri) {
+        let aor = Self::agent_aor(agent);
+        if let Ok(mut state) = self.state.lock() {
+            state.ringing.remove(&aor);
+            if let Some(entry) = state.entries.iter_mut().find(|e| e.id == id) {
+                entry.agent = None;
+                entry.tried.push(aor);
+            }
+        }
+    }
+
+    /// The agent answered, the entry leaves the queue
+    pub fn connected(&self, id: &str, agent: &rsip::Uri, now: DateTime<Utc>) {
+        let aor = Self::agent_aor(agent);
+        if let Ok(mut state) = self.state.lock() {
+            state.ringing.remove(&aor);
+            if let Some(index) = state.entries.iter().position(|e| e.id == id) {
+                let entry = state.entries.remove(index);
+                state.stats.answered += 1;
+                if (now - entry.joined_at).num_seconds() <= self.config.sla as i64 {
+                    state.stats.answered_in_time += 1;
+                }
+            }
+        }
+        self.sessions.call_started(&aor, now);
+    }
+
+    /// The agent's call ended, wrap-up starts
+    pub fn finished(&self, agent: &rsip::Uri, now: DateTime<Utc>) {
+        self.sessions
+            .call_ended(&Self::agent_aor(agent), self.config.wrap_up, now);
+    }
+
+    /// Where the agent is, as configured in the queue. Agents without a
+    /// session are on a call or available, unless the queue needs a login
+    fn agent_state(&self, agent: &str, presence: &PresenceState, now: DateTime<Utc>) -> AgentState {
+        let agent = agent.trim_start_matches("sip:");
+        let busy = match agent.contains('@') {
+            true => presence.is_busy(agent),
+            false => presence.is_user_busy(agent),
+        };
+        match self.sessions.state(agent, now) {
+            Some(AgentState::Available) | None if busy => AgentState::OnCall,
+            Some(state) => state,
+            None if self.config.login => AgentState::LoggedOut,
+            None => AgentState::Available,
+        }
+    }
+
+    /// The queue's figures right now
+    pub fn board(&self, presence: &PresenceState, now: DateTime<Utc>) -> QueueBoard {
+        let mut agents = HashMap::new();
+        for agent in self.config.agents.iter() {
+            *agents
+                .entry(self.agent_state(agent, presence, now))
+                .or_insert(0) += 1;
+        }
+        let (entries, stats) = self
+            .state
+            .lock()
+            .map(|state| (state.entries.clone(), state.stats.clone()))
+            .unwrap_or_default();
+        let (callbacks, waiting): (Vec<_>, Vec<_>) =
+            entries.iter().partition(|e| e.callback.is_some());
+        let longest_wait = waiting
+            .iter()
+            .map(|e| (now - e.joined_at).num_seconds().max(0) as u64)
+            .max()
+            .unwrap_or_default();
+        let handled = stats.answered + stats.abandoned;
+        let service_level =
+            (handled > 0).then(|| stats.answered_in_time as f64 * 100.0 / handled as f64);
+        QueueBoard {
+            name: self.name.clone(),
+            waiting: waiting.len(),
+            callbacks: callbacks.len(),
+            longest_wait,
+            agents,
+            service_level,
+            stats,
+        }
+    }
+
+    /// Drops a callback that can't be placed
+    fn leave_callback(&self, id: &str) {
+        if let Ok(mut state) = self.state.lock() {
+            state.entries.retain(|e| e.id != id);
+        }
+    }
+
+    /// Answers the caller and keeps them waiting until an agent answers,
+    /// or a callback is booked
+    async fn wait(
+        self: Arc<Self>,
+        server: SipServerRef,
+        active_call: ActiveCallRef,
+        caller: rsip::Uri,
+        realm: String,
+        requires: Option<SkillExpr>,
+    ) -> Result<()> {
+        let id = active_call.session_id.clone();
+        active_call.variables().set("queue.name", self.name.clone());
+        let mut events = active_call.event_sender.subscribe();
+        active_call
+            .enqueue_command(Command::Accept {
+                option: CallOption::default(),
+            })
+            .await?;
+        let number = caller.user().unwrap_or_default().to_string();
+        let position = self.join(&id, &number, &realm, requires, Utc::now());
+        info!(queue = self.name, session_id = id, %caller, position, "caller queued");
+        let r = self
+            .serve_caller(&server, &active_call, &caller, &mut events)
+            .await;
+        self.leave(&id);
+        if r.is_err() {
+            active_call.cancel_token.cancel();
+        }
+        r
+    }
+
+    async fn serve_caller(
+        &self,
+        server: &SipServerRef,
+        active_call: &ActiveCallRef,
+        caller: &rsip::Uri,
+        events: &mut broadcast::Receiver<SessionEvent>,
+    ) -> Result<()> {
+        let id = active_call.session_id.clone();
+        let token = active_call.cancel_token.clone();
+        let mut changes = server.presence.subscribe();
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+        let mut stage = Stage::Waiting;
+        self.hold(active_call).await;
+        loop {
+            tokio::select! {
+                _ = token.cancelled() => return Ok(()),
+                event = events.recv() => {
+                    let event = match event {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(_)) => continue,
+                        Err(RecvError::Closed) => return Ok(()),
+                    };
+                    match event {
+                        SessionEvent::TrackEnd { play_id, .. }
+                            if matches!(stage, Stage::Waiting)
+                                && play_id.is_some()
+                                && play_id == self.config.music =>
+                        {
+                            self.hold(active_call).await;
+                        }
+                        SessionEvent::Gather { play_id, digits, .. } => {
+                            stage = self
+                                .on_gather(active_call, stage, play_id.as_deref(), digits, caller)
+                                .await?;
+                            if matches!(stage, Stage::Booked) {
+                                return Ok(());
+                            }
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+                change = changes.recv() => {
+                    if let Err(RecvError::Closed) = change {
+                        return Ok(());
+                    }
+                }
+                _ = ticker.tick() => {}
+            }
+            if matches!(stage, Stage::Waiting) && self.offer_due(&id, Utc::now()) {
+                self.offer(active_call).await?;
+                stage = Stage::Offered;
+            }
+            let Some(agent) = self.claim(&id, &server.presence, Utc::now()) else {
+                continue;
+            };
+            if self.ring(server, active_call, &agent, caller).await? {
+                self.connected(&id, &agent, Utc::now());
+                info!(queue = self.name, session_id = id, %agent, "queue call connected");
+                let _presence = server.presence.enter_call(vec![Self::agent_aor(&agent)]);
+                token.cancelled().await;
+                self.finished(&agent, Utc::now());
+                return Ok(());
+            }
+            self.release(&id, &agent);
+            stage = Stage::Waiting;
+            self.hold(active_call).await;
+        }
+    }
+
+    /// Plays the music on hold, if any
+    async fn hold(&self, active_call: &ActiveCall) {
+        let Some(music) = self.config.music.clone() else {
+            return;
+        };
+        let command = Command::Play {
+            url: music,
+            auto_hangup: None,
+            wait_input_timeout: None,
+            stream: None,
+        };
+        active_call.enqueue_command(command).await.ok();
+    }
+
+    fn prompt_variables(&self, number: Option<&str>) -> HashMap<String, String> {
+        let mut variables = HashMap::new();
+        if let Some(callback) = self.callback() {
+            variables.insert("queue.key".to_string(), callback.key.clone());
+        }
+        if let Some(number) = number {
+            variables.insert("callback.number".to_string(), number.to_string());
+        }
+        variables
+    }
+
+    async fn gather(
+        &self,
+        active_call: &ActiveCall,
+        segments: Vec<PromptSegment>,
+        play_id: &str,
+        number: Option<&str>,
+        input: GatherOption,
+    ) -> Result<()> {
+        let command = Command::Gather {
+            segments: Some(segments),
+            locale: None,
+            variables: Some(self.prompt_variables(number)),
+            play_id: Some(play_id.to_string()),
+            option: None,
+            input: Some(input),
+        };
+        active_call.enqueue_command(command).await
+    }
+
+    async fn offer(&self, active_call: &ActiveCall) -> Result<()> {
+        let Some(callback) = self.callback() else {
+            return Ok(());
+        };
+        let input = GatherOption {
+            speech: false,
+            max_digits: Some(1),
+            finish_on_key: None,
+            ..Default::default()
+        };
+        self.gather(
+            active_call,
+            callback.offer.clone(),
+            OFFER_PLAY_ID,
+            None,
+            input,
+        )
+        .await
+    }
+
+    async fn confirm(&self, active_call: &ActiveCall, number: &str) -> Result<()> {
+        let Some(callback) = self.callback() else {
+            return Ok(());
+        };
+        let input = GatherOption {
+            speech: false,
+            max_digits: Some(16),
+            ..Default::default()
+        };
+        self.gather(
+            active_call,
+            callback.confirm.clone(),
+            CONFIRM_PLAY_ID,
+            Some(number),
+            input,
+        )
+        .await
+    }
+
+    /// Moves the callback dialogue on with the caller's keys. Anything
+    /// unexpected puts the caller back on hold
+    async fn on_gather(
+        &self,
+        active_call: &ActiveCall,
+        stage: Stage,
+        play_id: Option<&str>,
+        digits: Option<String>,
+        caller: &rsip::Uri,
+    ) -> Result<Stage> {
+        let Some(callback) = self.callback() else {
+            return Ok(stage);
+        };
+        let digits = digits.unwrap_or_default();
+        let next = match (stage, play_id) {
+            (Stage::Offered, Some(OFFER_PLAY_ID)) if digits == callback.key => {
+                let number = caller.user().unwrap_or_default().to_string();
+                self.confirm(active_call, &number).await?;
+                Stage::Confirming(number)
+            }
+            (Stage::Confirming(number), Some(CONFIRM_PLAY_ID)) if digits == callback.key => {
+                let id = &active_call.session_id;
+                if !self.book_callback(id, &number) {
+                    return Err(anyhow!("caller left the queue"));
+                }
+                info!(
+                    queue = self.name,
+                    session_id = id,
+                    number,
+                    "queue callback booked"
+                );
+                let command = Command::Prompt {
+                    segments: callback.booked.clone(),
+                    locale: None,
+                    variables: Some(self.prompt_variables(Some(&number))),
+                    play_id: None,
+                    auto_hangup: Some(true),
+                    option: None,
+                    wait_input_timeout: None,
+                };
+                active_call.enqueue_command(command).await?;
+                Stage::Booked
+            }
+            (Stage::Confirming(_), Some(CONFIRM_PLAY_ID)) if !digits.is_empty() => {
+                match normalize_number(&digits) {
+                    Some(number) => {
+                        self.confirm(active_call, &number).await?;
+                        Stage::Confirming(number)
+                    }
+                    None => {
+                        self.hold(active_call).await;
+                        Stage::Waiting
+                    }
+                }
+            }
+            (Stage::Offered | Stage::Confirming(_), Some(OFFER_PLAY_ID | CONFIRM_PLAY_ID)) => {
+                debug!(
+                    queue = self.name,
+                    session_id = active_call.session_id,
+                    "callback offer declined"
+                );
+                self.hold(active_call).await;
+                Stage::Waiting
+            }
+            (stage, _) => stage,
+        };
+        Ok(next)
+    }
+
+    /// Rings the agent's devices for the caller, and tells whether one
+    /// answered
+    async fn ring(
+        &self,
+        server: &SipServerRef,
+        active_call: &ActiveCall,
+        agent: &rsip::Uri,
+        caller: &rsip::Uri,
+    ) -> Result<bool> {
+        let locations = match resolve_targets(server, agent).await {
+            Ok(locations) => locations,
+            Err((e, _)) => {
+                debug!(queue = self.name, %agent, "agent not reachable: {}", e);
+                return Ok(false);
+            }
+        };
+        let contact = local_contact(server, &self.config.extension)?;
+        let track_id = active_call.server_side_track_id.clone();
+        ring_locations(
+            active_call,
+            &track_id,
+            select_flows(locations, None),
+            caller,
+            &contact,
+        )
+        .await
+    }
+
+    /// Rings the agent, then calls the booked number once they answer
+    async fn call_back(self: Arc<Self>, server: SipServerRef, entry: QueueEntry, agent: rsip::Uri) {
+        let number = entry.callback.clone().unwrap_or_default();
+        let target = rsip::Uri::try_from(format!("sip:{}", entry.realm).as_str())
+            .map_err(|e| anyhow!(e))
+            .and_then(|realm| parse_target(&number, &realm));
+        let target = match target {
+            Ok(target) => target,
+            Err(e) => {
+                warn!(queue = self.name, number, "invalid callback number: {}", e);
+                self.release(&entry.id, &agent);
+                self.leave_callback(&entry.id);
+                return;
+            }
+        };
+        let session_id = format!("queue-{}-{}", rand::random::<u32>(), make_tag());
+        info!(queue = self.name, session_id, %agent, %target, "queue callback");
+        let active_call = Arc::new(ActiveCall::new(
+            ActiveCallType::B2bua,
+            server.cancel_token.child_token(),
+            session_id.clone(),
+            Invitation::new(server.dialog_layer.clone())
+                .with_resolver(server.resolver.clone())
+                .with_retransmitter(server.retransmitter.clone()),
+            server.app_state.clone(),
+            TrackConfig::default(),
+            None,
+            false,
+            None,
+            None,
+        ));
+        let variables = active_call.variables();
+        variables.set("queue.name", self.name.clone());
+        variables.set("callback.number", number.clone());
+
+        let answered = Mutex::new(false);
+        let connect = async {
+            let contact = local_contact(&server, &self.config.extension)?;
+            let locations = resolve_targets(&server, &agent).await.map_err(|(e, _)| e)?;
+            if !ring_locations(
+                &active_call,
+                &session_id,
+                select_flows(locations, None),
+                &target,
+                &contact,
+            )
+            .await?
+            {
+                return Err(anyhow!("{} did not answer", agent));
+            }
+            self.connected(&entry.id, &agent, Utc::now());
+            if let Ok(mut answered) = answered.lock() {
+                *answered = true;
+            }
+            let track_id = active_call.server_side_track_id.clone();
+            dial_routed(
+                &server,
+                &server.config,
+                &active_call,
+                &track_id,
+                &agent,
+                &target,
+                &contact,
+            )
+            .await
+        };
+        let r = serve_call(&server, &active_call, &[&agent, &target], connect).await;
+        match answered.into_inner().unwrap_or_default() {
+            true => self.finished(&agent, Utc::now()),
+            false => self.release(&entry.id, &agent),
+        }
+        if let Err(e) = r {
+            info!(queue = self.name, session_id, "queue callback ended: {}", e);
+        }
+    }
+}
+
+/// The queues of the config
+pub struct Queues {
+    queues: HashMap<String, Arc<CallQueue>>,
+    boards: broadcast::Sender<Arc<Wallboard>>,
+}
+
+impl Queues {
+    pub fn new(config: &ProxyConfig, sessions: Arc<AgentSessions>) -> Result<Self> {
+        let agents = Arc::new(config.agents.clone());
+        let mut queues = HashMap::new();
+        for (name, queue) in config.queues.iter() {
+            let queue = CallQueue::new(name.clone(), queue.clone())
+                .map_err(|e| anyhow!("queue {}: {}", name, e))?
+                .with_agents(agents.clone())
+                .with_sessions(sessions.clone());
+            queues.insert(name.clone(), Arc::new(queue));
+        }
+        Ok(Self {
+            queues,
+            boards: broadcast::channel(16).0,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<CallQueue>> {
+        self.queues.get(name).cloned()
+    }
+
+    /// By name
+    pub fn list(&self) -> Vec<Arc<CallQueue>> {
+        let mut queues: Vec<_> = self.queues.values().cloned().collect();
+        queues.sort_by(|a, b| a.name.cmp(&b.name));
+        queues
+    }
+
+    pub fn by_extension(&self, extension: &str) -> Option<Arc<CallQueue>> {
+        self.queues
+            .values()
+            .find(|queue| queue.config.extension == extension)
+            .cloned()
+    }
+
+    /// The wallboards published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Wallboard>> {
+        self.boards.subscribe()
+    }
+
+    pub fn watchers(&self) -> usize {
+        self.boards.receiver_count()
+    }
+
+    pub fn publish(&self, board: Arc<Wallboard>) {
+        self.boards.send(board).ok();
+    }
+}
+
+/// The skills the caller needs, set by whoever sent the call to the queue
+fn call_skills(req: &rsip::Request) -> Option<&str> {
+    req.headers.iter().find_map(|h| match h {
+        rsip::Header::Other(name, value) if name.eq_ignore_ascii_case(SKILLS_HEADER) => {
+            Some(value.as_str())
+        }
+        _ => None,
+    })
+}
+
+/// Answers the calls to queue extensions, and places booked callbacks as
+/// agents become free
+pub struct QueueModule {
+    server: SipServerRef,
+}
+
+impl QueueModule {
+    pub fn create(server: SipServerRef, config: Arc<ProxyConfig>) -> Result<Box<dyn ProxyModule>> {
+        let module = QueueModule::new(server, config);
+        Ok(Box::new(module))
+    }
+
+    pub fn new(server: SipServerRef, _config: Arc<ProxyConfig>) -> Self {
+        Self { server }
+    }
+}
+
+/// Ends wrap-ups and places the callbacks that can be, on each presence
+/// change and every second
+async fn run_callbacks(server: SipServerRef, token: CancellationToken) {
+    let mut changes = server.presence.subscribe();
+    let mut ticker = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            change = changes.recv() => {
+                if let Err(RecvError::Closed) = change {
+                    break;
+                }
+            }
+            _ = ticker.tick() => {}
+        }
+        server.agent_sessions.expire(Utc::now());
+        for queue in server.queues.list() {
+            for (entry, agent) in queue.due_callbacks(&server.presence, Utc::now()) {
+                tokio::spawn(queue.clone().call_back(server.clone(), entry, agent));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl ProxyModule for QueueModule {
+    fn name(&self) -> &str {
+        "queue"
+    }
+
+    async fn on_start(&mut self) -> Result<()> {
+        let token = self.server.cancel_token.child_token();
+        tokio::spawn(run_callbacks(self.server.clone(), token.clone()));
+        tokio::spawn(run_wallboard(self.server.clone(), token));
+        debug!("Queue module started");
+        Ok(())
+    }
+
+    async fn on_stop(&self) -> Result<()> {
+        debug!("Queue module stopped");
+        Ok(())
+    }
+
+    async fn on_transaction_begin(
+        &self,
+        _token: CancellationToken,
+        tx: &mut Transaction,
+        cookie: TransactionCookie,
+    ) -> Result<ProxyAction> {
+        if tx.original.method != rsip::Method::Invite || tx.original.to_header()?.tag()?.is_some() {
+            return Ok(ProxyAction::Continue);
+        }
+        let to = tx.original.to_header()?.uri()?;
+        let realm = to.host().to_string();
+        let Some(queue) = self
+            .server
+            .queues
+            .by_extension(to.user().unwrap_or_default())
+        else {
+            return Ok(ProxyAction::Continue);
+        };
+        if !self.server.is_same_realm(&realm).await {
+            return Ok(ProxyAction::Continue);
+        }
+        let caller = match cookie.get_user() {
+            Some(user) => user,
+            None => SipUser::try_from(&*tx)?,
+        };
+        let Some(contact) = caller.build_contact_from_invite(&*tx) else {
+            tx.reply(rsip::StatusCode::BadRequest).await.ok();
+            return Ok(ProxyAction::Abort);
+        };
+        let requires = match call_skills(&tx.original).map(SkillExpr::parse) {
+            Some(Ok(requires)) => requires,
+            Some(Err(e)) => {
+                info!(key = %tx.key, "{}", e);
+                tx.reply(rsip::StatusCode::BadRequest).await.ok();
+                return Ok(ProxyAction::Abort);
+            }
+            None => None,
+        };
+        let from = tx.original.from_header()?.uri()?;
+        let call_id = tx.original.call_id_header()?.value().to_string();
+        let tenant = call_tenant(&self.server, &[&from, &to]).await;
+        let _channel = self.server.meter.enter(call_id, tenant);
+        let _presence = self.server.presence.enter_call(vec![PresenceState::aor(
+            from.user().unwrap_or_default(),
+            &from.host().to_string(),
+        )]);
+
+        let session_id = format!("queue-{}-{}", rand::random::<u32>(), make_tag());
+        let app_state = self.server.app_state.clone();
+        let b2bua = B2buaBuilder::new(app_state.clone(), cookie, session_id)
+            .build(tx)
+            .await?;
+        let invitation = Invitation::new(self.server.dialog_layer.clone())
+            .with_resolver(self.server.resolver.clone())
+            .with_retransmitter(self.server.retransmitter.clone());
+        let server = self.server.clone();
+        if let Err(e) = b2bua
+            .serve_with(
+                tx,
+                contact.uri,
+                app_state,
+                invitation,
+                None,
+                |active_call| queue.wait(server, active_call, from, realm, requires),
+            )
+            .await
+        {
+            warn!(key = %tx.key, "queue call failed: {}", e);
+        }
+        Ok(ProxyAction::Abort)
+    }
+}
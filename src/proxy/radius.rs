@@ -0,0 +1,373 @@
+use crate::call::{ActiveCallRef, HangupCause};
+use crate::callrecord::CallRecordHangupReason;
+use crate::config::RadiusConfig;
+use crate::event::SessionEvent;
+use anyhow::{Result, anyhow};
+use chrono::Utc;
+use md5::{Digest, Md5};
+use std::time::Duration;
+use tokio::{net::UdpSocket, sync::broadcast::error::RecvError};
+use tracing::{info, warn};
+
+pub const ACCESS_REQUEST: u8 = 1;
+pub const ACCESS_ACCEPT: u8 = 2;
+pub const ACCESS_REJECT: u8 = 3;
+pub const ACCOUNTING_REQUEST: u8 = 4;
+pub const ACCOUNTING_RESPONSE: u8 = 5;
+
+pub const USER_NAME: u8 = 1;
+pub const USER_PASSWORD: u8 = 2;
+pub const CALLED_STATION_ID: u8 = 30;
+pub const CALLING_STATION_ID: u8 = 31;
+pub const NAS_IDENTIFIER: u8 = 32;
+pub const ACCT_STATUS_TYPE: u8 = 40;
+pub const ACCT_SESSION_ID: u8 = 44;
+pub const ACCT_SESSION_TIME: u8 = 46;
+pub const ACCT_TERMINATE_CAUSE: u8 = 49;
+pub const EVENT_TIMESTAMP: u8 = 55;
+
+pub const STATUS_START: u32 = 1;
+pub const STATUS_STOP: u32 = 2;
+
+pub const CAUSE_USER_REQUEST: u32 = 1;
+pub const CAUSE_NAS_REQUEST: u32 = 10;
+pub const CAUSE_SERVICE_UNAVAILABLE: u32 = 15;
+
+const HEADER_LEN: usize = 20;
+
+/// A RADIUS packet, RFC 2865 and 2866
+#[derive(Debug, Clone, PartialEq)]
+pub struct RadiusPacket {
+    pub code: u8,
+    pub identifier: u8,
+    pub authenticator: [u8; 16],
+    pub attributes: Vec<(u8, Vec<u8>)>,
+}
+
+impl RadiusPacket {
+    pub fn new(code: u8, identifier: u8) -> Self {
+        Self {
+            code,
+            identifier,
+            authenticator: [0; 16],
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Values longer than an attribute holds are cut short
+    pub fn with_attribute(mut self, kind: u8, value: impl Into<Vec<u8>>) -> Self {
+        let mut value = value.into();
+        value.truncate(255 - 2);
+        self.attributes.push((kind, value));
+        self
+    }
+
+    pub fn with_u32(self, kind: u8, value: u32) -> Self {
+        self.with_attribute(kind, value.to_be_bytes().to_vec())
+    }
+
+    pub fn attribute(&self, kind: u8) -> Option<&[u8]> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, v)| v.as_slice())
+    }
+
+    pub fn attribute_u32(&self, kind: u8) -> Option<u32> {
+        let value: [u8; 4] = self.attribute(kind)?.try_into().ok()?;
+        Some(u32::from_be_bytes(value))
+    }
+
+    pub fn attribute_str(&self, kind: u8) -> Option<&str> {
+        std::str::from_utf8(self.attribute(kind)?).ok()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![self.code, self.identifier, 0, 0];
+        data.extend_from_slice(&self.authenticator);
+        for (kind, value) in self.attributes.iter() {
+            data.push(*kind);
+            data.push(value.len() as u8 + 2);
+            data.extend_from_slice(value);
+        }
+        let len = (data.len() as u16).to_be_bytes();
+        data[2..4].copy_from_slice(&len);
+        data
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_LEN {
+            return Err(anyhow!("radius packet too short"));
+        }
+        let len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if len < HEADER_LEN || len > data.len() {
+            return Err(anyhow!("invalid radius packet length {}", len));
+        }
+        let mut packet = Self::new(data[0], data[1]);
+        packet.authenticator.copy_from_slice(&data[4..HEADER_LEN]);
+        let mut pos = HEADER_LEN;
+        while pos < len {
+            if pos + 2 > len {
+                return Err(anyhow!("truncated radius attribute"));
+            }
+            let attr_len = data[pos + 1] as usize;
+            if attr_len < 2 || pos + attr_len > len {
+                return Err(anyhow!("invalid radius attribute length {}", attr_len));
+            }
+            packet
+                .attributes
+                .push((data[pos], data[pos + 2..pos + attr_len].to_vec()));
+            pos += attr_len;
+        }
+        Ok(packet)
+    }
+
+    /// The authenticator of an Accounting-Request, or of a response to a
+    /// request with `request` as its authenticator
+    pub fn compute_authenticator(&self, request: &[u8; 16], secret: &str) -> [u8; 16] {
+        let mut data = self.encode();
+        data[4..HEADER_LEN].copy_from_slice(request);
+        let mut hasher = Md5::new();
+        hasher.update(&data);
+        hasher.update(secret.as_bytes());
+        hasher.finalize().into()
+    }
+
+    pub fn sign_accounting(&mut self, secret: &str) {
+        self.authenticator = self.compute_authenticator(&[0; 16], secret);
+    }
+
+    /// Whether a response was signed with the secret, for the request
+    /// with `request` as its authenticator
+    pub fn verify_response(&self, request: &[u8; 16], secret: &str) -> bool {
+        self.compute_authenticator(request, secret) == self.authenticator
+    }
+}
+
+/// Hides a User-Password, RFC 2865 section 5.2. Decrypting is the same
+/// with the cipher text as the chaining input
+pub fn encrypt_password(password: &[u8], secret: &str, authenticator: &[u8; 16]) -> Vec<u8> {
+    let mut padded = password.to_vec();
+    padded.truncate(128);
+    let blocks = padded.len().div_ceil(16).max(1);
+    padded.resize(blocks * 16, 0);
+    let mut last = authenticator.to_vec();
+    let mut out = Vec::with_capacity(padded.len());
+    for block in padded.chunks(16) {
+        let mut hasher = Md5::new();
+        hasher.update(secret.as_bytes());
+        hasher.update(&last);
+        let key = hasher.finalize();
+        let cipher: Vec<u8> = block.iter().zip(key.iter()).map(|(p, k)| p ^ k).collect();
+        out.extend_from_slice(&cipher);
+        last = cipher;
+    }
+    out
+}
+
+pub fn decrypt_password(cipher: &[u8], secret: &str, authenticator: &[u8; 16]) -> Vec<u8> {
+    let mut last = authenticator.to_vec();
+    let mut out = Vec::with_capacity(cipher.len());
+    for block in cipher.chunks(16) {
+        let mut hasher = Md5::new();
+        hasher.update(secret.as_bytes());
+        hasher.update(&last);
+        let key = hasher.finalize();
+        out.extend(block.iter().zip(key.iter()).map(|(c, k)| c ^ k));
+        last = block.to_vec();
+    }
+    while out.last() == Some(&0) {
+        out.pop();
+    }
+    out
+}
+
+/// The parties of a call through a trunk
+#[derive(Debug, Clone)]
+pub struct RadiusCall {
+    /// Sent as Acct-Session-Id, the SIP Call-ID
+    pub session_id: String,
+    pub caller: String,
+    pub callee: String,
+}
+
+/// Talks to the RADIUS servers of a trunk
+pub struct RadiusClient {
+    pub config: RadiusConfig,
+}
+
+impl RadiusClient {
+    pub fn new(config: RadiusConfig) -> Self {
+        Self { config }
+    }
+
+    fn nas_identifier(&self) -> &str {
+        self.config.nas_identifier.as_deref().unwrap_or("rustpbx")
+    }
+
+    /// Sends the request until a response signed with the secret arrives
+    pub async fn send(
+        &self,
+        server: &str,
+        default_port: u16,
+        request: &RadiusPacket,
+    ) -> Result<RadiusPacket> {
+        let server = match server.rsplit_once(':') {
+            Some((_, port)) if port.parse::<u16>().is_ok() => server.to_string(),
+            _ => format!("{}:{}", server, default_port),
+        };
+        let addr = tokio::net::lookup_host(&server)
+            .await?
+            .next()
+            .ok_or_else(|| anyhow!("can't resolve {}", server))?;
+        let local = match addr {
+            std::net::SocketAddr::V4(_) => "0.0.0.0:0",
+            std::net::SocketAddr::V6(_) => "[::]:0",
+        };
+        let socket = UdpSocket::bind(local).await?;
+        socket.connect(addr).await?;
+        let data = request.encode();
+        let timeout = Duration::from_secs(self.config.timeout.max(1));
+        let mut buf = vec![0u8; 4096];
+        for _ in 0..=self.config.retries {
+            socket.send(&data).await?;
+            let deadline = tokio::time::Instant::now() + timeout;
+            while let Ok(r) = tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await {
+                let response = match RadiusPacket::decode(&buf[..r?]) {
+                    Ok(response) => response,
+                    Err(e) => {
+                        warn!(%server, "invalid radius response: {}", e);
+                        continue;
+                    }
+                };
+                if response.identifier != request.identifier {
+                    continue;
+                }
+                if !response.verify_response(&request.authenticator, &self.config.secret) {
+                    warn!(%server, "radius response with a bad authenticator");
+                    continue;
+                }
+                return Ok(response);
+            }
+        }
+        Err(anyhow!("no answer from radius server {}", server))
+    }
+
+    /// Asks the auth server whether the call may go through. Without an
+    /// auth server every call may
+    pub async fn authorize(&self, call: &RadiusCall) -> Result<bool> {
+        let Some(server) = self.config.auth.as_deref() else {
+            return Ok(true);
+        };
+        let mut request = RadiusPacket::new(ACCESS_REQUEST, rand::random());
+        request.authenticator = rand::random();
+        let password = self.config.password.as_deref().unwrap_or(&call.caller);
+        let password = encrypt_password(
+            password.as_bytes(),
+            &self.config.secret,
+            &request.authenticator,
+        );
+        let request = request
+            .with_attribute(USER_NAME, call.caller.as_bytes())
+            .with_attribute(USER_PASSWORD, password)
+            .with_attribute(CALLING_STATION_ID, call.caller.as_bytes())
+            .with_attribute(CALLED_STATION_ID, call.callee.as_bytes())
+            .with_attribute(ACCT_SESSION_ID, call.session_id.as_bytes())
+            .with_attribute(NAS_IDENTIFIER, self.nas_identifier().as_bytes());
+        let response = self.send(server, 1812, &request).await?;
+        match response.code {
+            ACCESS_ACCEPT => Ok(true),
+            ACCESS_REJECT => Ok(false),
+            code => Err(anyhow!("unexpected radius code {}", code)),
+        }
+    }
+
+    /// Sends an Accounting-Request, Start without `stop`. `stop` holds the
+    /// seconds of the session and the terminate cause
+    pub async fn account(&self, call: &RadiusCall, stop: Option<(u32, u32)>) -> Result<()> {
+        let Some(server) = self.config.accounting.as_deref() else {
+            return Ok(());
+        };
+        let status = match stop {
+            Some(_) => STATUS_STOP,
+            None => STATUS_START,
+        };
+        let mut request = RadiusPacket::new(ACCOUNTING_REQUEST, rand::random())
+            .with_u32(ACCT_STATUS_TYPE, status)
+            .with_attribute(ACCT_SESSION_ID, call.session_id.as_bytes())
+            .with_attribute(USER_NAME, call.caller.as_bytes())
+            .with_attribute(CALLING_STATION_ID, call.caller.as_bytes())
+            .with_attribute(CALLED_STATION_ID, call.callee.as_bytes())
+            .with_attribute(NAS_IDENTIFIER, self.nas_identifier().as_bytes())
+            .with_u32(EVENT_TIMESTAMP, Utc::now().timestamp() as u32);
+        if let Some((session_time, cause)) = stop {
+            request = request
+                .with_u32(ACCT_SESSION_TIME, session_time)
+                .with_u32(ACCT_TERMINATE_CAUSE, cause);
+        }
+        request.sign_accounting(&self.config.secret);
+        let response = self.send(server, 1813, &request).await?;
+        match response.code {
+            ACCOUNTING_RESPONSE => Ok(()),
+            code => Err(anyhow!("unexpected radius code {}", code)),
+        }
+    }
+
+    /// Sends Accounting-Start when the call is answered, and Stop when it
+    /// ends. Calls never answered are not accounted
+    pub async fn account_call(&self, active_call: ActiveCallRef, call: RadiusCall) {
+        let mut events = active_call.event_sender.subscribe();
+        let answered = loop {
+            tokio::select! {
+                _ = active_call.cancel_token.cancelled() => break false,
+                event = events.recv() => match event {
+                    Ok(SessionEvent::Answer { .. }) => break true,
+                    Ok(_) | Err(RecvError::Lagged(_)) => {}
+                    Err(RecvError::Closed) => break false,
+                },
+            }
+        };
+        if !answered {
+            return;
+        }
+        let answer_time = Utc::now();
+        if let Err(e) = self.account(&call, None).await {
+            warn!(
+                session_id = call.session_id,
+                "radius accounting start failed: {}", e
+            );
+        }
+        active_call.cancel_token.cancelled().await;
+        let (reason, cause) = active_call
+            .call_state
+            .read()
+            .map(|state| (state.hangup_reason.clone(), state.hangup_cause))
+            .unwrap_or_default();
+        let session_time = (Utc::now() - answer_time).num_seconds().max(0) as u32;
+        let stop = (session_time, terminate_cause(reason.as_ref(), cause));
+        match self.account(&call, Some(stop)).await {
+            Ok(_) => info!(
+                session_id = call.session_id,
+                session_time, "radius accounting sent"
+            ),
+            Err(e) => warn!(
+                session_id = call.session_id,
+                "radius accounting stop failed: {}", e
+            ),
+        }
+    }
+}
+
+/// Acct-Terminate-Cause of a call that ended
+pub fn terminate_cause(reason: Option<&CallRecordHangupReason>, cause: Option<HangupCause>) -> u32 {
+    match (reason, cause) {
+        (Some(CallRecordHangupReason::ByCaller | CallRecordHangupReason::ByCallee), _) => {
+            CAUSE_USER_REQUEST
+        }
+        (_, Some(HangupCause::NormalClearing | HangupCause::NormalUnspecified)) => {
+            CAUSE_USER_REQUEST
+        }
+        (_, Some(_)) => CAUSE_SERVICE_UNAVAILABLE,
+        _ => CAUSE_NAS_REQUEST,
+    }
+}
@@ -1,12 +1,13 @@
-use super::{ProxyAction, ProxyModule, server::SipServerRef};
+use super::{ProxyAction, ProxyModule, nat::NatKeepalive, server::SipServerRef};
 use crate::call::user::SipUser;
-use crate::call::{Location, TransactionCookie};
+use crate::call::{Location, TransactionCookie, parse_route_set};
 use crate::config::ProxyConfig;
 use anyhow::Result;
 use async_trait::async_trait;
 use rsip::prelude::{HeadersExt, UntypedHeader};
 use rsipstack::transaction::transaction::Transaction;
 use std::{sync::Arc, time::Instant};
+use tokio::sync::broadcast;
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info};
 
@@ -14,6 +15,7 @@ use tracing::{debug, info};
 pub struct RegistrarModule {
     server: SipServerRef,
     config: Arc<ProxyConfig>,
+    pub nat_keepalive: Option<Arc<NatKeepalive>>,
 }
 
 impl RegistrarModule {
@@ -22,7 +24,15 @@ impl RegistrarModule {
         Ok(Box::new(module))
     }
     pub fn new(server: SipServerRef, config: Arc<ProxyConfig>) -> Self {
-        Self { server, config }
+        let nat_keepalive = config
+            .nat_keepalive
+            .clone()
+            .map(|c| Arc::new(NatKeepalive::new(server.clone(), c)));
+        Self {
+            server,
+            config,
+            nat_keepalive,
+        }
     }
 }
 
@@ -35,6 +45,36 @@ impl ProxyModule for RegistrarModule {
         vec![rsip::Method::Register]
     }
     async fn on_start(&mut self) -> Result<()> {
+        if let Some(nat_keepalive) = self.nat_keepalive.clone() {
+            let token = self.server.cancel_token.child_token();
+            if let Some(cluster) = self.server.cluster.as_ref() {
+                // the UAs of a node that went down are kept reachable here
+                let mut takeovers = cluster.subscribe();
+                let nat_keepalive = nat_keepalive.clone();
+                let token = token.clone();
+                tokio::spawn(async move {
+                    loop {
+                        let takeover = tokio::select! {
+                            _ = token.cancelled() => break,
+                            takeover = takeovers.recv() => match takeover {
+                                Ok(takeover) => takeover,
+                                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                                Err(_) => break,
+                            },
+                        };
+                        for (key, location) in takeover.bindings {
+                            nat_keepalive.add(
+                                &key,
+                                location.aor,
+                                location.destination,
+                                location.expires,
+                            );
+                        }
+                    }
+                });
+            }
+            tokio::spawn(async move { nat_keepalive.serve(token).await });
+        }
         debug!("Registrar module started");
         Ok(())
     }
@@ -79,6 +119,15 @@ impl ProxyModule for RegistrarModule {
                 return Ok(ProxyAction::Abort);
             }
         };
+        // RFC 5626 outbound flows and RFC 5627 GRUUs are keyed by the device instance
+        let instance_id = contact_param(&tx.original, "+sip.instance");
+        let reg_id = match instance_id {
+            Some(_) if is_supported(&tx.original, "outbound") => {
+                contact_param(&tx.original, "reg-id").and_then(|v| v.parse::<u32>().ok())
+            }
+            _ => None,
+        };
+
         let mut contact_params = vec![rsip::Param::Expires(expires.to_string().into())];
         match destination.r#type {
             Some(rsip::Transport::Udp) | None => {}
@@ -86,6 +135,46 @@ impl ProxyModule for RegistrarModule {
                 contact_params.push(rsip::Param::Transport(t));
             }
         }
+        if let Some(instance_id) = instance_id.as_ref() {
+            contact_params.push(rsip::Param::Other(
+                "+sip.instance".into(),
+                Some(format!("\"<{}>\"", instance_id).into()),
+            ));
+            if let Some(reg_id) = reg_id {
+                contact_params.push(rsip::Param::Other(
+                    "reg-id".into(),
+                    Some(reg_id.to_string().into()),
+                ));
+            }
+            if is_supported(&tx.original, "gruu") {
+                contact_params.push(rsip::Param::Other(
+                    "pub-gruu".into(),
+                    Some(
+                        format!(
+                            "\"sip:{}@{};gr={}\"",
+                            user.username,
+                            user.realm.as_deref().unwrap_or_default(),
+                            instance_id
+                        )
+                        .into(),
+                    ),
+                ));
+            }
+        }
+        // Path (RFC 3327) from edge proxies between us and the UA
+        let path = tx
+            .original
+            .headers
+            .iter()
+            .filter_map(|h| match h {
+                rsip::Header::Other(name, value) if name.eq_ignore_ascii_case("Path") => {
+                    Some(parse_route_set(value))
+                }
+                _ => None,
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+
         let contact = rsip::typed::Contact {
             display_name: None,
             uri: rsip::Uri {
@@ -100,20 +189,53 @@ impl ProxyModule for RegistrarModule {
             params: contact_params,
         };
 
+        let aor_key = format!(
+            "{}@{}",
+            user.username,
+            user.realm.as_deref().unwrap_or_default()
+        );
+        let binding_key = match instance_id.as_ref() {
+            Some(instance_id) => format!("{};{};{}", aor_key, instance_id, reg_id.unwrap_or(0)),
+            None => aor_key.clone(),
+        };
         if expires == 0 {
-            // delete user
+            // delete user, or only the flow of this device
             info!(
                 username = user.username,
                 contact = contact.to_string(),
                 destination = destination.to_string(),
                 realm = user.realm,
+                instance_id,
+                reg_id,
                 "unregistered user"
             );
-            self.server
-                .locator
-                .unregister(user.username.as_str(), user.realm.as_deref())
-                .await
-                .ok();
+            match instance_id.as_ref() {
+                Some(instance_id) => {
+                    self.server
+                        .locator
+                        .unregister_flow(
+                            user.username.as_str(),
+                            user.realm.as_deref(),
+                            instance_id,
+                            reg_id,
+                        )
+                        .await
+                        .ok();
+                    if let Some(nat_keepalive) = self.nat_keepalive.as_ref() {
+                        nat_keepalive.remove(&binding_key);
+                    }
+                }
+                None => {
+                    self.server
+                        .locator
+                        .unregister(user.username.as_str(), user.realm.as_deref())
+                        .await
+                        .ok();
+                    if let Some(nat_keepalive) = self.nat_keepalive.as_ref() {
+                        nat_keepalive.remove_aor(&aor_key);
+                    }
+                }
+            }
             tx.reply(rsip::StatusCode::OK).await.ok();
             return Ok(ProxyAction::Abort);
         }
@@ -124,15 +246,26 @@ impl ProxyModule for RegistrarModule {
             destination = destination.to_string(),
             realm = user.realm,
             supports_webrtc = user.is_support_webrtc,
+            instance_id,
+            reg_id,
             "registered user"
         );
 
+        // behind an edge proxy the UA is only reachable through its own
+        // Contact, the source address is the edge
+        let aor = match user.origin_contact.as_ref() {
+            Some(origin_contact) if !path.is_empty() => origin_contact.uri.clone(),
+            _ => contact.uri.clone(),
+        };
         let location = Location {
-            aor: contact.uri.clone(),
+            aor,
             expires,
             destination: destination.clone(),
             last_modified: Some(Instant::now()),
             supports_webrtc: user.is_support_webrtc,
+            instance_id: instance_id.clone(),
+            reg_id,
+            route_set: path.clone(),
             ..Default::default()
         };
 
@@ -150,7 +283,30 @@ impl ProxyModule for RegistrarModule {
             }
         }
 
+        if let Some(nat_keepalive) = self.nat_keepalive.as_ref()
+            && path.is_empty()
+        {
+            let origin_contact = user.origin_contact.as_ref().map(|c| &c.uri);
+            if nat_keepalive.should_keepalive(origin_contact, destination) {
+                nat_keepalive.add(
+                    &binding_key,
+                    contact.uri.clone(),
+                    destination.clone(),
+                    expires,
+                );
+            }
+        }
+
         let mut headers = vec![contact.into(), rsip::Header::Expires(expires.into())];
+        if reg_id.is_some() {
+            headers.push(rsip::Header::Require("outbound".into()));
+        }
+        if !path.is_empty() && is_supported(&tx.original, "path") {
+            headers.extend(
+                path.iter()
+                    .map(|uri| rsip::Header::Other("Path".to_string(), format!("<{}>", uri))),
+            );
+        }
         match tx.endpoint_inner.allows.lock().unwrap().as_ref() {
             Some(allows) => {
                 if !allows.is_empty() {
@@ -172,3 +328,36 @@ impl ProxyModule for RegistrarModule {
         Ok(ProxyAction::Abort)
     }
 }
+
+/// Contact header parameter value without the surrounding quotes and `<>`.
+/// Read from the raw header since the typed Contact rejects quoted values
+/// such as `+sip.instance="<urn:uuid:...>"`
+fn contact_param(req: &rsip::Request, name: &str) -> Option<String> {
+    let contact = req.contact_header().ok()?.value();
+    let params = match contact.find('>') {
+        Some(pos) => &contact[pos + 1..],
+        None => contact.split_once(';').map(|(_, p)| p)?,
+    };
+    params.split(';').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| {
+            value
+                .trim()
+                .trim_matches('"')
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+    })
+}
+
+/// Whether the request lists `option_tag` in its Supported headers
+fn is_supported(req: &rsip::Request, option_tag: &str) -> bool {
+    req.headers.iter().any(|h| match h {
+        rsip::Header::Supported(supported) => supported
+            .value()
+            .split(',')
+            .any(|tag| tag.trim().eq_ignore_ascii_case(option_tag)),
+        _ => false,
+    })
+}
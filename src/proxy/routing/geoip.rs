@@ -0,0 +1,130 @@
+use crate::{config::GeoIpConfig, proxy::acl::parse_network};
+use anyhow::{Result, anyhow};
+use std::net::IpAddr;
+use tracing::{info, warn};
+
+/// Address ranges `(first, last, country)` as integers
+type Ranges = Vec<(u128, u128, String)>;
+
+/// Countries of source addresses, for the `source.country` condition of
+/// routing rules
+#[derive(Debug, Default)]
+pub struct GeoIp {
+    /// Disjoint and sorted, see [`flatten`]
+    v4: Ranges,
+    v6: Ranges,
+}
+
+impl GeoIp {
+    /// Reads the networks of the config, then those of its file
+    pub async fn load(config: &GeoIpConfig) -> Result<Self> {
+        let mut entries = Vec::new();
+        for (country, networks) in &config.networks {
+            entries.extend(
+                networks
+                    .iter()
+                    .map(|network| (network.clone(), country.clone())),
+            );
+        }
+        if let Some(file) = config.file.as_ref() {
+            let text = tokio::fs::read_to_string(file)
+                .await
+                .map_err(|e| anyhow!("failed to read geoip file {}: {}", file, e))?;
+            for line in text.lines() {
+                let line = line.split('#').next().unwrap_or_default().trim();
+                if let Some((network, country)) = line.split_once(',') {
+                    entries.push((network.trim().to_string(), country.trim().to_string()));
+                }
+            }
+        }
+        let (mut v4, mut v6) = (Vec::new(), Vec::new());
+        for (network, country) in entries {
+            match parse_network(&network) {
+                Ok((addr, prefix_len)) => {
+                    let (first, last) = network_range(&addr, prefix_len);
+                    let networks = if addr.is_ipv4() { &mut v4 } else { &mut v6 };
+                    networks.push((first, last, country.to_uppercase()));
+                }
+                Err(_) => warn!(network, "invalid geoip network"),
+            }
+        }
+        info!("geoip loaded with {} networks", v4.len() + v6.len());
+        Ok(Self {
+            v4: flatten(v4),
+            v6: flatten(v6),
+        })
+    }
+
+    /// The ISO code of the country of `addr`, e.g. `US`
+    pub fn country(&self, addr: &IpAddr) -> Option<&str> {
+        let ranges = match addr {
+            IpAddr::V4(_) => &self.v4,
+            IpAddr::V6(_) => &self.v6,
+        };
+        let value = addr_value(addr);
+        let index = ranges
+            .partition_point(|(first, _, _)| *first <= value)
+            .checked_sub(1)?;
+        let (_, last, country) = &ranges[index];
+        (value <= *last).then_some(country.as_str())
+    }
+}
+
+fn addr_value(addr: &IpAddr) -> u128 {
+    match addr {
+        IpAddr::V4(addr) => u32::from(*addr) as u128,
+        IpAddr::V6(addr) => u128::from(*addr),
+    }
+}
+
+/// The first and last address of a network
+fn network_range(addr: &IpAddr, prefix_len: u8) -> (u128, u128) {
+    let bits = if addr.is_ipv4() { 32 } else { 128 };
+    let host_bits = bits - prefix_len.min(bits);
+    let host_mask = match host_bits {
+        128 => u128::MAX,
+        host_bits => (1u128 << host_bits) - 1,
+    };
+    let first = addr_value(addr) & !host_mask;
+    (first, first | host_mask)
+}
+
+/// Splits nested networks into disjoint ranges sorted by address, each
+/// with the country of the most specific network covering it
+fn flatten(mut networks: Ranges) -> Ranges {
+    // a network before the ones it contains
+    networks.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+    let mut ranges = Vec::new();
+    // the networks containing the current address, innermost last
+    let mut open = Vec::new();
+    // the first address not in a range yet, `None` past the last address
+    let mut next = Some(0);
+    for network in networks {
+        close(&mut open, &mut ranges, &mut next, Some(network.0));
+        if let (Some((_, _, country)), Some(from)) = (open.last(), next)
+            && from < network.0
+        {
+            ranges.push((from, network.0 - 1, country.clone()));
+        }
+        next = Some(network.0);
+        open.push(network);
+    }
+    close(&mut open, &mut ranges, &mut next, None);
+    ranges
+}
+
+/// Ends the open networks that end before `until`, or all of them
+fn close(open: &mut Ranges, ranges: &mut Ranges, next: &mut Option<u128>, until: Option<u128>) {
+    while let Some((_, last, country)) = open.last() {
+        if until.is_some_and(|until| *last >= until) {
+            break;
+        }
+        if let Some(from) = *next
+            && from <= *last
+        {
+            ranges.push((from, *last, country.clone()));
+            *next = last.checked_add(1);
+        }
+        open.pop();
+    }
+}
@@ -1,8 +1,10 @@
 use anyhow::{Result, anyhow};
+use chrono::{DateTime, Utc};
 use regex::Regex;
+use rsip::prelude::{HeadersExt, UntypedHeader};
 use rsipstack::{
     dialog::{authenticate::Credential, invitation::InviteOption},
-    transport::SipAddr,
+    transport::SipConnection,
 };
 use std::{
     collections::{HashMap, hash_map::DefaultHasher},
@@ -13,7 +15,11 @@ use tracing::{debug, info, warn};
 
 use crate::{
     config::RouteResult,
-    proxy::routing::{ActionType, DefaultRoute, RouteRule, RoutingState, TrunkConfig},
+    proxy::limits::{CallLimit, LimitScope},
+    proxy::routing::{
+        ActionType, DefaultRoute, DiversionConfig, HolidayCalendars, RouteAction, RouteRule,
+        RoutingState, TrunkConfig,
+    },
 };
 
 /// Main routing function
@@ -43,6 +49,12 @@ pub async fn match_invite(
     let callee_host = option.callee.host().clone();
     let request_user = origin.uri.user().unwrap_or_default().to_string();
     let request_host = origin.uri.host().clone();
+    let call_id = origin
+        .call_id_header()
+        .map(|h| h.value().to_string())
+        .unwrap_or_default();
+    let source_country = source_country(origin, &routing_state);
+    let now = Utc::now();
 
     debug!(
         "Matching INVITE: caller={}@{}, callee={}@{}, request={}@{}",
@@ -68,6 +80,9 @@ pub async fn match_invite(
             &callee_host,
             &request_user,
             &request_host,
+            &source_country,
+            now,
+            routing_state.holidays(),
         )?;
 
         if !rule_matched {
@@ -78,13 +93,24 @@ pub async fn match_invite(
 
         // Apply rewrite rules
         if let Some(rewrite) = &rule.rewrite {
+            let original_callee = option.callee.clone();
             apply_rewrite_rules(&mut option, rewrite, origin)?;
+            if option.callee.user() != original_callee.user() {
+                let diversion = rewrite.diversion.clone().unwrap_or_default();
+                apply_diversion(&mut option, &original_callee, &diversion, origin);
+            }
         }
 
+        // Business hours pick the action of the call
+        let Some(action) = branch_action(&rule.action, now, routing_state.holidays())? else {
+            debug!("No action for the time of rule: {}", rule.name);
+            continue;
+        };
+
         // Handle based on action type
-        match rule.action.get_action_type() {
+        match action.get_action_type() {
             ActionType::Reject => {
-                if let Some(reject_config) = &rule.action.reject {
+                if let Some(reject_config) = &action.reject {
                     let reason =
                         reject_config
                             .reason
@@ -107,26 +133,44 @@ pub async fn match_invite(
             ActionType::Busy => {
                 return Ok(RouteResult::Abort(486, "Busy Here".to_string()));
             }
+            ActionType::Announce => {
+                let announcement = action
+                    .announcement
+                    .clone()
+                    .ok_or_else(|| anyhow!("rule {} announces no announcement", rule.name))?;
+                info!("Announcing {} and hanging up", announcement);
+                return Ok(RouteResult::Announce(announcement));
+            }
+            ActionType::TimeCondition => unreachable!("resolved by branch_action"),
             ActionType::Forward => {
                 // Select trunk and apply configuration
-                if let Some(dest_config) = &rule.action.dest {
+                if let Some(dest_config) = &action.dest {
                     let selected_trunk = select_trunk(
                         dest_config,
-                        &rule.action.select,
-                        &rule.action.hash_key,
+                        &action.select,
+                        &action.hash_key,
                         &option,
                         routing_state.clone(),
+                        trunks,
+                        &call_id,
                     )?;
 
                     if let Some(trunk_config) = trunks
                         .as_ref()
                         .and_then(|trunks| trunks.get(&selected_trunk))
                     {
+                        let limit = trunk_limit(&selected_trunk, trunk_config);
+                        if let Err(e) = routing_state.limiter.admit(&call_id, &[limit]) {
+                            return Ok(routing_state.circuits_busy(e.to_string()));
+                        }
                         apply_trunk_config(&mut option, trunk_config)?;
                         info!(
                             "Selected trunk: {} for destination: {}",
                             selected_trunk, trunk_config.dest
                         );
+                        if let Some(profile) = rule.transrating {
+                            routing_state.set_transrating(&call_id, profile);
+                        }
                         return Ok(RouteResult::Forward(option));
                     } else {
                         warn!("Trunk '{}' not found in configuration", selected_trunk);
@@ -149,13 +193,19 @@ pub async fn match_invite(
         &default.select,
         &None,
         &option,
-        routing_state,
+        routing_state.clone(),
+        trunks,
+        &call_id,
     )?;
 
     if let Some(trunk_config) = trunks
         .as_ref()
         .and_then(|trunks| trunks.get(&selected_trunk))
     {
+        let limit = trunk_limit(&selected_trunk, trunk_config);
+        if let Err(e) = routing_state.limiter.admit(&call_id, &[limit]) {
+            return Ok(routing_state.circuits_busy(e.to_string()));
+        }
         apply_trunk_config(&mut option, trunk_config)?;
         info!(
             "Using default trunk: {} for destination: {}",
@@ -169,6 +219,30 @@ pub async fn match_invite(
     Ok(RouteResult::Forward(option))
 }
 
+/// The action a time condition branches to at `now`, through nested ones.
+/// None when the branch is unset
+fn branch_action<'a>(
+    mut action: &'a RouteAction,
+    now: DateTime<Utc>,
+    holidays: &HolidayCalendars,
+) -> Result<Option<&'a RouteAction>> {
+    while action.get_action_type() == ActionType::TimeCondition {
+        let hours = action
+            .hours
+            .as_ref()
+            .ok_or_else(|| anyhow!("time_condition action without hours"))?;
+        let branch = match hours.contains(now, holidays)? {
+            true => &action.open,
+            false => &action.closed,
+        };
+        match branch {
+            Some(branch) => action = branch,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(action))
+}
+
 /// Check if routing rule matches
 fn matches_rule(
     rule: &crate::proxy::routing::RouteRule,
@@ -179,6 +253,9 @@ fn matches_rule(
     callee_host: &rsip::Host,
     request_user: &str,
     request_host: &rsip::Host,
+    source_country: &str,
+    now: DateTime<Utc>,
+    holidays: &HolidayCalendars,
 ) -> Result<bool> {
     let conditions = &rule.match_conditions;
 
@@ -239,6 +316,18 @@ fn matches_rule(
         }
     }
 
+    if let Some(pattern) = &conditions.source_country
+        && !matches_pattern(pattern, source_country)?
+    {
+        return Ok(false);
+    }
+
+    if let Some(time) = &conditions.time
+        && !time.contains(now, holidays)?
+    {
+        return Ok(false);
+    }
+
     // Check headers
     for (header_key, pattern) in &conditions.headers {
         if header_key.starts_with("header.") {
@@ -256,6 +345,18 @@ fn matches_rule(
     Ok(true)
 }
 
+/// The country of the address the request came from, empty when unknown
+pub(crate) fn source_country(origin: &rsip::Request, routing_state: &RoutingState) -> String {
+    origin
+        .via_header()
+        .ok()
+        .and_then(|via| SipConnection::parse_target_from_via(via).ok())
+        .and_then(|(_, target)| target.host.try_into().ok())
+        .and_then(|addr| routing_state.country(&addr))
+        .unwrap_or_default()
+        .to_string()
+}
+
 /// Match pattern (supports regex)
 fn matches_pattern(pattern: &str, value: &str) -> Result<bool> {
     // If pattern doesn't contain regex special characters, use exact match
@@ -376,6 +477,63 @@ fn apply_rewrite_rules(
     Ok(())
 }
 
+/// Records the forward from `original_callee` to the rewritten callee, keeping
+/// the entries of earlier hops already in the request
+fn apply_diversion(
+    option: &mut InviteOption,
+    original_callee: &rsip::Uri,
+    diversion: &DiversionConfig,
+    origin: &rsip::Request,
+) {
+    let existing = |name: &str| {
+        origin.headers.iter().find_map(|h| match h {
+            rsip::Header::Other(key, value) if key.eq_ignore_ascii_case(name) => {
+                Some(value.clone())
+            }
+            _ => None,
+        })
+    };
+    let headers = option.headers.get_or_insert_with(Vec::new);
+
+    if diversion.history_info {
+        let (entries, index) = match existing("History-Info") {
+            Some(entries) => {
+                let index = entries
+                    .rsplit("index=")
+                    .next()
+                    .and_then(|v| v.split([';', ',', ' ']).next())
+                    .unwrap_or("1")
+                    .to_string();
+                (entries, index)
+            }
+            None => (format!("<{}>;index=1", original_callee), "1".to_string()),
+        };
+        headers.push(rsip::Header::Other(
+            "History-Info".to_string(),
+            format!(
+                "{}, <{}?Reason=SIP%3Bcause%3D{}>;index={}.1;mp={}",
+                entries,
+                option.callee,
+                diversion.cause(),
+                index,
+                index
+            ),
+        ));
+    }
+
+    if diversion.diversion {
+        let entry = format!(
+            "<{}>;reason={};counter=1",
+            original_callee, diversion.reason
+        );
+        let value = match existing("Diversion") {
+            Some(previous) => format!("{}, {}", entry, previous),
+            None => entry,
+        };
+        headers.push(rsip::Header::Other("Diversion".to_string(), value));
+    }
+}
+
 /// Apply rewrite pattern (supports capture groups)
 fn apply_rewrite_pattern_with_match(pattern: &str, original: &str) -> Result<String> {
     // Support simple replacement patterns like "0{1}" where {1} is capture group
@@ -497,6 +655,8 @@ fn select_trunk(
     hash_key: &Option<String>,
     option: &InviteOption,
     routing_state: Arc<RoutingState>,
+    trunk_configs: Option<&HashMap<String, TrunkConfig>>,
+    call_id: &str,
 ) -> Result<String> {
     let trunks = match dest_config {
         crate::proxy::routing::DestConfig::Single(trunk) => vec![trunk.clone()],
@@ -507,6 +667,42 @@ fn select_trunk(
         return Err(anyhow!("No trunks configured"));
     }
 
+    // Skip trunks the OPTIONS monitor marked down, unless all of them are
+    let available = trunks
+        .iter()
+        .filter(|trunk| routing_state.is_trunk_up(trunk))
+        .cloned()
+        .collect::<Vec<_>>();
+    let trunks = if available.is_empty() {
+        warn!("All trunks are down, trying {:?} anyway", trunks);
+        trunks
+    } else {
+        available
+    };
+
+    // Skip trunks at their call caps, the selected one refuses the call
+    // when all of them are
+    let has_capacity = |trunk: &String| {
+        trunk_configs
+            .and_then(|configs| configs.get(trunk))
+            .map(|config| {
+                routing_state
+                    .limiter
+                    .has_capacity(call_id, &trunk_limit(trunk, config))
+            })
+            .unwrap_or(true)
+    };
+    let available = trunks
+        .iter()
+        .filter(|trunk| has_capacity(trunk))
+        .cloned()
+        .collect::<Vec<_>>();
+    let trunks = if available.is_empty() {
+        trunks
+    } else {
+        available
+    };
+
     if trunks.len() == 1 {
         return Ok(trunks[0].clone());
     }
@@ -543,6 +739,15 @@ fn select_trunk(
     }
 }
 
+fn trunk_limit(name: &str, trunk: &TrunkConfig) -> CallLimit {
+    CallLimit {
+        scope: LimitScope::Trunk,
+        name: name.to_string(),
+        max_calls: trunk.max_calls,
+        max_cps: trunk.max_cps,
+    }
+}
+
 /// Apply trunk configuration
 fn apply_trunk_config(option: &mut InviteOption, trunk: &TrunkConfig) -> Result<()> {
     // Set destination
@@ -551,24 +756,7 @@ fn apply_trunk_config(option: &mut InviteOption, trunk: &TrunkConfig) -> Result<
         .as_str()
         .try_into()
         .map_err(|e| anyhow!("Invalid trunk destination '{}': {:?}", trunk.dest, e))?;
-
-    let transport = if let Some(transport_str) = &trunk.transport {
-        match transport_str.to_lowercase().as_str() {
-            "udp" => Some(rsip::transport::Transport::Udp),
-            "tcp" => Some(rsip::transport::Transport::Tcp),
-            "tls" => Some(rsip::transport::Transport::Tls),
-            "ws" => Some(rsip::transport::Transport::Ws),
-            "wss" => Some(rsip::transport::Transport::Wss),
-            _ => None,
-        }
-    } else {
-        None
-    };
-
-    option.destination = Some(SipAddr {
-        r#type: transport,
-        addr: dest_uri.host_with_port.clone(),
-    });
+    option.destination = Some(trunk.dest_addr()?);
 
     // Set authentication info
     if let (Some(username), Some(password)) = (&trunk.username, &trunk.password) {
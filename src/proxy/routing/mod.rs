@@ -1,8 +1,18 @@
+use crate::config::{RadiusConfig, RouteResult};
+use crate::media::codecs::resample::ResampleProfile;
+use crate::proxy::campaign::{CallingHours, CallingWindow, parse_offset};
+use crate::proxy::limits::CallLimiter;
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Datelike, FixedOffset, NaiveDate, Utc};
+use geoip::GeoIp;
+use rsipstack::transport::SipAddr;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+pub mod geoip;
 pub mod matcher;
 #[cfg(test)]
 mod tests;
@@ -12,15 +22,78 @@ mod tests;
 pub struct RoutingState {
     /// Round-robin counters for each destination group
     round_robin_counters: Arc<std::sync::Mutex<HashMap<String, AtomicUsize>>>,
+    /// Health of monitored trunks, trunks without an entry are assumed up
+    trunk_health: std::sync::Mutex<HashMap<String, TrunkHealth>>,
+    /// Concurrent call and call rate caps, trunks at their cap are skipped
+    pub limiter: Arc<CallLimiter>,
+    /// Transrating profile of the route each call took, until the call
+    /// takes it for its tracks
+    transrating: std::sync::Mutex<HashMap<String, ResampleProfile>>,
+    /// Countries of source addresses, no address has one when unset
+    geoip: Option<GeoIp>,
+    /// Days off of the time conditions, from `proxy.holidays`
+    holidays: HolidayCalendars,
+    /// Treatment of the calls no trunk can take, from `proxy.circuits_busy`
+    circuits_busy: Option<CircuitsBusyConfig>,
 }
 
 impl RoutingState {
     pub fn new() -> Self {
         Self {
             round_robin_counters: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            trunk_health: std::sync::Mutex::new(HashMap::new()),
+            limiter: Arc::new(CallLimiter::new()),
+            transrating: std::sync::Mutex::new(HashMap::new()),
+            geoip: None,
+            holidays: HolidayCalendars::default(),
+            circuits_busy: None,
         }
     }
 
+    pub fn with_limiter(mut self, limiter: CallLimiter) -> Self {
+        self.limiter = Arc::new(limiter);
+        self
+    }
+
+    pub fn with_geoip(mut self, geoip: GeoIp) -> Self {
+        self.geoip = Some(geoip);
+        self
+    }
+
+    pub fn with_holidays(mut self, holidays: HolidayCalendars) -> Self {
+        self.holidays = holidays;
+        self
+    }
+
+    pub fn with_circuits_busy(mut self, circuits_busy: CircuitsBusyConfig) -> Self {
+        self.circuits_busy = Some(circuits_busy);
+        self
+    }
+
+    pub fn holidays(&self) -> &HolidayCalendars {
+        &self.holidays
+    }
+
+    /// What a call hears when no trunk of its route can take it, `reason`
+    /// being why the last one refused it
+    pub fn circuits_busy(&self, reason: String) -> RouteResult {
+        match self.circuits_busy.as_ref() {
+            Some(CircuitsBusyConfig {
+                announcement: Some(announcement),
+                ..
+            }) => RouteResult::Announce(announcement.clone()),
+            Some(config) => {
+                RouteResult::Abort(config.code, config.reason.clone().unwrap_or(reason))
+            }
+            None => RouteResult::Abort(503, reason),
+        }
+    }
+
+    /// The ISO code of the country of `addr`, by the geoip config
+    pub fn country(&self, addr: &std::net::IpAddr) -> Option<&str> {
+        self.geoip.as_ref()?.country(addr)
+    }
+
     /// Get the next trunk index for round-robin selection
     pub fn next_round_robin_index(&self, destination_key: &str, trunk_count: usize) -> usize {
         if trunk_count == 0 {
@@ -35,10 +108,46 @@ impl RoutingState {
         let current = counter.fetch_add(1, Ordering::SeqCst);
         current % trunk_count
     }
+
+    pub fn is_trunk_up(&self, trunk: &str) -> bool {
+        self.trunk_health
+            .lock()
+            .unwrap()
+            .get(trunk)
+            .map(|health| health.up)
+            .unwrap_or(true)
+    }
+
+    pub fn get_trunk_health(&self, trunk: &str) -> Option<TrunkHealth> {
+        self.trunk_health.lock().unwrap().get(trunk).cloned()
+    }
+
+    pub fn set_trunk_health(&self, trunk: &str, health: TrunkHealth) {
+        self.trunk_health
+            .lock()
+            .unwrap()
+            .insert(trunk.to_string(), health);
+    }
+
+    pub fn trunk_health(&self) -> HashMap<String, TrunkHealth> {
+        self.trunk_health.lock().unwrap().clone()
+    }
+
+    pub fn set_transrating(&self, call_id: &str, profile: ResampleProfile) {
+        self.transrating
+            .lock()
+            .unwrap()
+            .insert(call_id.to_string(), profile);
+    }
+
+    /// The transrating profile of the route the call was last forwarded on
+    pub fn take_transrating(&self, call_id: &str) -> Option<ResampleProfile> {
+        self.transrating.lock().unwrap().remove(call_id)
+    }
 }
 
 /// Single trunk configuration
-#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize, Clone, Default)]
 pub struct TrunkConfig {
     pub dest: String,
     pub backup_dest: Option<String>,
@@ -56,9 +165,57 @@ pub struct TrunkConfig {
     pub weight: Option<u32>,
     #[serde(default)]
     pub transport: Option<String>,
+    /// Seconds between OPTIONS health checks, unset to disable monitoring
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub options_interval: Option<u64>,
+    /// RADIUS accounting, and optionally authorization, of the calls
+    /// through the trunk
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub radius: Option<RadiusConfig>,
+}
+
+impl TrunkConfig {
+    /// Resolve the signaling address of the trunk from `dest` and `transport`
+    pub fn dest_addr(&self) -> Result<SipAddr> {
+        let dest_uri: rsip::Uri = self
+            .dest
+            .as_str()
+            .try_into()
+            .map_err(|e| anyhow!("Invalid trunk destination '{}': {:?}", self.dest, e))?;
+
+        let transport = if let Some(transport_str) = &self.transport {
+            match transport_str.to_lowercase().as_str() {
+                "udp" => Some(rsip::transport::Transport::Udp),
+                "tcp" => Some(rsip::transport::Transport::Tcp),
+                "tls" => Some(rsip::transport::Transport::Tls),
+                "ws" => Some(rsip::transport::Transport::Ws),
+                "wss" => Some(rsip::transport::Transport::Wss),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        Ok(SipAddr {
+            r#type: transport,
+            addr: dest_uri.host_with_port,
+        })
+    }
+}
+
+/// Health of a trunk as seen by the OPTIONS monitor
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrunkHealth {
+    pub up: bool,
+    /// Round trip time of the last answered OPTIONS
+    pub latency_ms: Option<u64>,
+    /// Consecutive failed checks
+    pub failures: u32,
+    pub last_checked: DateTime<Utc>,
 }
 /// Default route strategy
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize, Clone)]
 pub struct DefaultRoute {
     pub dest: DestConfig,
     #[serde(default = "default_select")]
@@ -68,7 +225,7 @@ pub struct DefaultRoute {
 }
 
 /// Destination configuration (can be single or multiple trunks)
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize, Clone)]
 #[serde(untagged)]
 pub enum DestConfig {
     Single(String),
@@ -76,7 +233,7 @@ pub enum DestConfig {
 }
 
 /// Route rule
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize, Clone)]
 pub struct RouteRule {
     pub name: String,
     #[serde(default)]
@@ -96,12 +253,17 @@ pub struct RouteRule {
     #[serde(flatten)]
     pub action: RouteAction,
 
+    /// How the audio of the calls on this route is resampled between codecs
+    /// of different rates, `latency` or `quality`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transrating: Option<ResampleProfile>,
+
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub disabled: Option<bool>,
 }
 
 /// Match conditions
-#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize, Clone, Default)]
 pub struct MatchConditions {
     /// From user part
     #[serde(rename = "from.user")]
@@ -127,6 +289,12 @@ pub struct MatchConditions {
     /// Request URI port
     #[serde(rename = "request_uri.port")]
     pub request_uri_port: Option<String>,
+    /// ISO code of the country the request came from, by the geoip config.
+    /// Sources of no known country match `""`
+    #[serde(rename = "source.country")]
+    pub source_country: Option<String>,
+    /// Local time window the call must come in
+    pub time: Option<TimeCondition>,
     /// SIP header fields (starting with header.)
     #[serde(flatten)]
     pub headers: HashMap<String, String>,
@@ -138,8 +306,88 @@ pub struct MatchConditions {
     pub callee: Option<String>,
 }
 
+/// A window of the day, e.g. office hours for the day target of a route
+#[derive(Debug, Deserialize, JsonSchema, Serialize, Clone)]
+pub struct TimeCondition {
+    #[serde(flatten)]
+    pub hours: CallingHours,
+    /// Time zone of the window, e.g. `+08:00`, UTC when unset
+    pub utc_offset: Option<String>,
+    /// Calendars of `proxy.holidays`, the window stays closed on their days
+    #[serde(default)]
+    pub holidays: Vec<String>,
+}
+
+impl TimeCondition {
+    pub fn contains(&self, time: DateTime<Utc>, holidays: &HolidayCalendars) -> Result<bool> {
+        let window = CallingWindow::parse(&self.hours)?;
+        let offset = match self.utc_offset.as_deref() {
+            Some(offset) => parse_offset(offset)?,
+            None => FixedOffset::east_opt(0).expect("zero offset"),
+        };
+        let local = time.with_timezone(&offset);
+        if holidays.is_holiday(&self.holidays, local.date_naive())? {
+            return Ok(false);
+        }
+        Ok(window.allows(local))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Holiday {
+    Date(NaiveDate),
+    /// Month and day, every year
+    Yearly(u32, u32),
+}
+
+/// Days off by calendar name, each a `YYYY-MM-DD` date or a `MM-DD` one
+/// for every year
+#[derive(Debug, Clone, Default)]
+pub struct HolidayCalendars {
+    calendars: HashMap<String, Vec<Holiday>>,
+}
+
+impl HolidayCalendars {
+    pub fn new(config: &HashMap<String, Vec<String>>) -> Result<Self> {
+        let mut calendars = HashMap::new();
+        for (name, days) in config {
+            let mut holidays = Vec::new();
+            for day in days {
+                let day = day.trim();
+                let holiday = match NaiveDate::parse_from_str(day, "%Y-%m-%d") {
+                    Ok(date) => Holiday::Date(date),
+                    // a leap year takes any month and day
+                    Err(_) => NaiveDate::parse_from_str(&format!("2000-{}", day), "%Y-%m-%d")
+                        .map(|date| Holiday::Yearly(date.month(), date.day()))
+                        .map_err(|_| anyhow!("invalid holiday {} in {}", day, name))?,
+                };
+                holidays.push(holiday);
+            }
+            calendars.insert(name.clone(), holidays);
+        }
+        Ok(Self { calendars })
+    }
+
+    /// Whether `date` is off in one of `calendars`
+    pub fn is_holiday(&self, calendars: &[String], date: NaiveDate) -> Result<bool> {
+        for name in calendars {
+            let holidays = self
+                .calendars
+                .get(name)
+                .ok_or_else(|| anyhow!("unknown holiday calendar: {}", name))?;
+            if holidays.iter().any(|holiday| match holiday {
+                Holiday::Date(day) => *day == date,
+                Holiday::Yearly(month, day) => date.month() == *month && date.day() == *day,
+            }) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
 /// Rewrite rules
-#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize, Clone, Default)]
 pub struct RewriteRules {
     /// Rewrite From user part
     #[serde(rename = "from.user")]
@@ -174,10 +422,47 @@ pub struct RewriteRules {
     pub to: Option<String>,
     pub caller: Option<String>,
     pub callee: Option<String>,
+
+    /// History-Info/Diversion added when the callee user is rewritten
+    pub diversion: Option<DiversionConfig>,
+}
+
+/// Tells downstream voicemail and carriers who was originally called when a
+/// rule forwards the call to another number, see RFC 7044 and RFC 5806
+#[derive(Debug, Deserialize, JsonSchema, Serialize, Clone)]
+#[serde(default)]
+pub struct DiversionConfig {
+    /// `unconditional`, `user-busy`, `no-answer`, `unavailable`, `deflection`...
+    pub reason: String,
+    pub history_info: bool,
+    pub diversion: bool,
+}
+
+impl Default for DiversionConfig {
+    fn default() -> Self {
+        Self {
+            reason: "unconditional".to_string(),
+            history_info: true,
+            diversion: true,
+        }
+    }
+}
+
+impl DiversionConfig {
+    /// SIP cause of the redirect reason, as mapped by RFC 4458
+    pub fn cause(&self) -> u16 {
+        match self.reason.as_str() {
+            "user-busy" => 486,
+            "no-answer" => 408,
+            "unavailable" | "deflection" => 480,
+            "unknown" => 404,
+            _ => 302,
+        }
+    }
 }
 
 /// Route action
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize, Clone)]
 pub struct RouteAction {
     /// Explicit action type (optional, defaults to forward if dest is specified)
     #[serde(default)]
@@ -198,6 +483,21 @@ pub struct RouteAction {
     /// Reject configuration (when action is reject)
     #[serde(default)]
     pub reject: Option<RejectConfig>,
+
+    /// Audio played to the answered caller before hanging up (when action
+    /// is announce)
+    #[serde(default)]
+    pub announcement: Option<String>,
+
+    /// Business hours (when action is time_condition), `open` routes the
+    /// calls within them and `closed` the others. A branch left unset lets
+    /// the next rules route the call
+    #[serde(default)]
+    pub hours: Option<TimeCondition>,
+    #[serde(default)]
+    pub open: Option<Box<RouteAction>>,
+    #[serde(default)]
+    pub closed: Option<Box<RouteAction>>,
 }
 
 impl Default for RouteAction {
@@ -208,6 +508,10 @@ impl Default for RouteAction {
             select: default_select(),
             hash_key: None,
             reject: None,
+            announcement: None,
+            hours: None,
+            open: None,
+            closed: None,
         }
     }
 }
@@ -219,12 +523,18 @@ impl RouteAction {
             Some(action) => match action.as_str() {
                 "reject" => ActionType::Reject,
                 "busy" => ActionType::Busy,
+                "announce" => ActionType::Announce,
+                "time_condition" => ActionType::TimeCondition,
                 _ => ActionType::Forward,
             },
             None => {
                 // If no explicit action, infer from other fields
                 if self.reject.is_some() {
                     ActionType::Reject
+                } else if self.announcement.is_some() {
+                    ActionType::Announce
+                } else if self.hours.is_some() {
+                    ActionType::TimeCondition
                 } else if self.dest.is_some() {
                     ActionType::Forward
                 } else {
@@ -241,10 +551,12 @@ pub enum ActionType {
     Forward,
     Reject,
     Busy,
+    Announce,
+    TimeCondition,
 }
 
 /// Reject configuration
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize, Clone)]
 pub struct RejectConfig {
     pub code: u16,
     #[serde(default)]
@@ -253,6 +565,23 @@ pub struct RejectConfig {
     pub headers: HashMap<String, String>,
 }
 
+/// The all circuits busy treatment
+#[derive(Debug, Deserialize, JsonSchema, Serialize, Clone)]
+pub struct CircuitsBusyConfig {
+    #[serde(default = "default_circuits_busy_code")]
+    pub code: u16,
+    /// Why the trunk refused the call when unset
+    #[serde(default)]
+    pub reason: Option<String>,
+    /// Audio the caller is answered with instead, e.g. `sounds/busy.wav`
+    #[serde(default)]
+    pub announcement: Option<String>,
+}
+
+fn default_circuits_busy_code() -> u16 {
+    503
+}
+
 fn default_select() -> String {
     "rr".to_string()
 }
@@ -1,9 +1,13 @@
-use crate::config::RouteResult;
+use crate::config::{GeoIpConfig, RouteResult};
+use crate::media::codecs::resample::ResampleProfile;
 use crate::proxy::routing::matcher::match_invite;
 use crate::proxy::routing::{
-    DestConfig, MatchConditions, RejectConfig, RewriteRules, RouteAction, RouteRule, RoutingState,
-    TrunkConfig,
+    CircuitsBusyConfig, DefaultRoute, DestConfig, DiversionConfig, HolidayCalendars,
+    MatchConditions, RejectConfig, RewriteRules, RouteAction, RouteRule, RoutingState,
+    TimeCondition, TrunkConfig, TrunkHealth, geoip::GeoIp,
 };
+use chrono::{DateTime, NaiveDate, Utc};
+use rsip::prelude::{HeadersExt, UntypedHeader};
 use rsipstack::dialog::invitation::InviteOption;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -33,6 +37,7 @@ async fn test_match_invite_no_routes() {
     match result {
         RouteResult::Forward(_) => {} // Expected
         RouteResult::Abort(_, _) => panic!("Expected forward, got abort"),
+        RouteResult::Announce(_) => panic!("Expected forward, got announce"),
     }
 }
 
@@ -54,6 +59,8 @@ async fn test_match_invite_exact_match() {
             max_cps: None,
             weight: Some(100),
             transport: Some("udp".to_string()),
+            options_interval: None,
+            radius: None,
         },
     );
 
@@ -72,7 +79,9 @@ async fn test_match_invite_exact_match() {
             select: "rr".to_string(),
             hash_key: None,
             reject: None,
+            ..Default::default()
         },
+        transrating: None,
         disabled: None,
     }];
 
@@ -104,9 +113,70 @@ async fn test_match_invite_exact_match() {
             assert_eq!(cred.password, "testpass");
         }
         RouteResult::Abort(_, _) => panic!("Expected forward, got abort"),
+        RouteResult::Announce(_) => panic!("Expected forward, got announce"),
     }
 }
 
+#[tokio::test]
+async fn test_match_invite_transrating() {
+    let routing_state = Arc::new(RoutingState::new());
+    let mut trunks = HashMap::new();
+    trunks.insert(
+        "opus_trunk".to_string(),
+        TrunkConfig {
+            dest: "sip:gateway.example.com:5060".to_string(),
+            ..Default::default()
+        },
+    );
+    let routes = vec![RouteRule {
+        name: "low_latency".to_string(),
+        description: None,
+        priority: 100,
+        match_conditions: MatchConditions {
+            to_user: Some("1001".to_string()),
+            ..Default::default()
+        },
+        rewrite: None,
+        action: RouteAction {
+            dest: Some(DestConfig::Single("opus_trunk".to_string())),
+            ..Default::default()
+        },
+        transrating: Some(ResampleProfile::Latency),
+        disabled: None,
+    }];
+
+    let origin = create_test_request();
+    let call_id = origin.call_id_header().unwrap().value().to_string();
+    let result = match_invite(
+        Some(&trunks),
+        Some(&routes),
+        None,
+        create_test_invite_option(),
+        &origin,
+        routing_state.clone(),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(result, RouteResult::Forward(_)));
+    assert_eq!(
+        routing_state.take_transrating(&call_id),
+        Some(ResampleProfile::Latency)
+    );
+    assert_eq!(routing_state.take_transrating(&call_id), None);
+
+    let rule: RouteRule = toml::from_str(
+        r#"
+        name = "hd"
+        dest = "opus_trunk"
+        transrating = "quality"
+        [match]
+        "to.user" = "1002"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(rule.transrating, Some(ResampleProfile::Quality));
+}
+
 #[tokio::test]
 async fn test_match_invite_regex_match() {
     let routing_state = Arc::new(RoutingState::new());
@@ -125,6 +195,8 @@ async fn test_match_invite_regex_match() {
             max_cps: None,
             weight: Some(100),
             transport: None,
+            options_interval: None,
+            radius: None,
         },
     );
 
@@ -143,7 +215,9 @@ async fn test_match_invite_regex_match() {
             select: "rr".to_string(),
             hash_key: None,
             reject: None,
+            ..Default::default()
         },
+        transrating: None,
         disabled: None,
     }];
 
@@ -173,6 +247,7 @@ async fn test_match_invite_regex_match() {
             // Expected
         }
         RouteResult::Abort(_, _) => panic!("Expected forward, got abort"),
+        RouteResult::Announce(_) => panic!("Expected forward, got announce"),
     }
 }
 
@@ -198,7 +273,9 @@ async fn test_match_invite_reject_rule() {
                 reason: Some("Emergency calls not allowed".to_string()),
                 headers: HashMap::new(),
             }),
+            ..Default::default()
         },
+        transrating: None,
         disabled: None,
     }];
 
@@ -228,6 +305,7 @@ async fn test_match_invite_reject_rule() {
             assert_eq!(reason, "Emergency calls not allowed");
         }
         RouteResult::Forward(_) => panic!("Expected abort, got forward"),
+        RouteResult::Announce(_) => panic!("Expected abort, got announce"),
     }
 }
 
@@ -249,6 +327,8 @@ async fn test_match_invite_rewrite_rules() {
             max_cps: None,
             weight: Some(100),
             transport: None,
+            options_interval: None,
+            radius: None,
         },
     );
 
@@ -270,7 +350,9 @@ async fn test_match_invite_rewrite_rules() {
             select: "rr".to_string(),
             hash_key: None,
             reject: None,
+            ..Default::default()
         },
+        transrating: None,
         disabled: None,
     }];
 
@@ -296,9 +378,76 @@ async fn test_match_invite_rewrite_rules() {
             assert_eq!(caller_user, "013812345678");
         }
         RouteResult::Abort(_, _) => panic!("Expected forward, got abort"),
+        RouteResult::Announce(_) => panic!("Expected forward, got announce"),
     }
 }
 
+#[tokio::test]
+async fn test_match_invite_forward_adds_diversion() {
+    let routing_state = Arc::new(RoutingState::new());
+    let trunks = HashMap::new();
+
+    let routes = vec![RouteRule {
+        name: "forward_to_voicemail".to_string(),
+        description: None,
+        priority: 100,
+        match_conditions: MatchConditions {
+            to_user: Some("^1001$".to_string()),
+            ..Default::default()
+        },
+        rewrite: Some(RewriteRules {
+            to_user: Some("8001".to_string()),
+            diversion: Some(DiversionConfig {
+                reason: "no-answer".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        action: RouteAction {
+            action: None,
+            dest: None,
+            select: "rr".to_string(),
+            hash_key: None,
+            reject: None,
+            ..Default::default()
+        },
+        transrating: None,
+        disabled: None,
+    }];
+
+    let result = match_invite(
+        Some(&trunks),
+        Some(&routes),
+        None,
+        create_test_invite_option(),
+        &create_test_request(),
+        routing_state,
+    )
+    .await
+    .unwrap();
+
+    let option = match result {
+        RouteResult::Forward(option) => option,
+        RouteResult::Abort(_, _) => panic!("Expected forward, got abort"),
+        RouteResult::Announce(_) => panic!("Expected forward, got announce"),
+    };
+    assert_eq!(option.callee.user().unwrap_or_default(), "8001");
+    let headers = option
+        .headers
+        .unwrap()
+        .into_iter()
+        .map(|h| h.to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        headers,
+        vec![
+            "History-Info: <sip:1001@example.com>;index=1, \
+             <sip:8001@example.com?Reason=SIP%3Bcause%3D408>;index=1.1;mp=1",
+            "Diversion: <sip:1001@example.com>;reason=no-answer;counter=1",
+        ]
+    );
+}
+
 #[tokio::test]
 async fn test_match_invite_load_balancing() {
     let routing_state = Arc::new(RoutingState::new());
@@ -317,6 +466,8 @@ async fn test_match_invite_load_balancing() {
             max_cps: None,
             weight: Some(100),
             transport: None,
+            options_interval: None,
+            radius: None,
         },
     );
 
@@ -333,6 +484,8 @@ async fn test_match_invite_load_balancing() {
             max_cps: None,
             weight: Some(100),
             transport: None,
+            options_interval: None,
+            radius: None,
         },
     );
 
@@ -349,6 +502,8 @@ async fn test_match_invite_load_balancing() {
             max_cps: None,
             weight: Some(100),
             transport: None,
+            options_interval: None,
+            radius: None,
         },
     );
 
@@ -371,7 +526,9 @@ async fn test_match_invite_load_balancing() {
             select: "rr".to_string(),
             hash_key: None,
             reject: None,
+            ..Default::default()
         },
+        transrating: None,
         disabled: None,
     }];
 
@@ -401,6 +558,7 @@ async fn test_match_invite_load_balancing() {
                 selected_destinations.push(dest.addr.to_string());
             }
             RouteResult::Abort(_, _) => panic!("Expected forward, got abort"),
+            RouteResult::Announce(_) => panic!("Expected forward, got announce"),
         }
     }
 
@@ -408,6 +566,75 @@ async fn test_match_invite_load_balancing() {
     println!("Selected destinations: {:?}", selected_destinations);
 }
 
+#[tokio::test]
+async fn test_match_invite_skips_down_trunks() {
+    let routing_state = Arc::new(RoutingState::new());
+    let mut trunks = HashMap::new();
+    for (name, dest) in [
+        ("trunk1", "sip:gateway1.example.com:5060"),
+        ("trunk2", "sip:gateway2.example.com:5060"),
+    ] {
+        trunks.insert(
+            name.to_string(),
+            TrunkConfig {
+                dest: dest.to_string(),
+                options_interval: Some(30),
+                ..Default::default()
+            },
+        );
+    }
+    let default = DefaultRoute {
+        dest: DestConfig::Multiple(vec!["trunk1".to_string(), "trunk2".to_string()]),
+        select: "rr".to_string(),
+        action: "forward".to_string(),
+    };
+    let health = |up| TrunkHealth {
+        up,
+        latency_ms: None,
+        failures: if up { 0 } else { 3 },
+        last_checked: chrono::Utc::now(),
+    };
+    routing_state.set_trunk_health("trunk1", health(false));
+    routing_state.set_trunk_health("trunk2", health(true));
+
+    let origin = create_test_request();
+    let routes = vec![];
+    for _ in 0..3 {
+        let result = match_invite(
+            Some(&trunks),
+            Some(&routes),
+            Some(&default),
+            create_test_invite_option(),
+            &origin,
+            routing_state.clone(),
+        )
+        .await
+        .unwrap();
+        match result {
+            RouteResult::Forward(option) => assert_eq!(
+                option.destination.unwrap().addr.to_string(),
+                "gateway2.example.com:5060"
+            ),
+            RouteResult::Abort(_, _) => panic!("Expected forward, got abort"),
+            RouteResult::Announce(_) => panic!("Expected forward, got announce"),
+        }
+    }
+
+    // with every trunk down, calls still go out
+    routing_state.set_trunk_health("trunk2", health(false));
+    let result = match_invite(
+        Some(&trunks),
+        Some(&routes),
+        Some(&default),
+        create_test_invite_option(),
+        &origin,
+        routing_state.clone(),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(result, RouteResult::Forward(option) if option.destination.is_some()));
+}
+
 #[tokio::test]
 async fn test_match_invite_header_matching() {
     let routing_state = Arc::new(RoutingState::new());
@@ -426,6 +653,8 @@ async fn test_match_invite_header_matching() {
             max_cps: None,
             weight: Some(100),
             transport: None,
+            options_interval: None,
+            radius: None,
         },
     );
 
@@ -448,7 +677,9 @@ async fn test_match_invite_header_matching() {
             select: "rr".to_string(),
             hash_key: None,
             reject: None,
+            ..Default::default()
         },
+        transrating: None,
         disabled: None,
     }];
 
@@ -482,6 +713,7 @@ async fn test_match_invite_header_matching() {
             // Expected to match VIP header
         }
         RouteResult::Abort(_, _) => panic!("Expected forward, got abort"),
+        RouteResult::Announce(_) => panic!("Expected forward, got announce"),
     }
 }
 
@@ -503,6 +735,8 @@ async fn test_match_invite_default_route() {
             max_cps: None,
             weight: Some(100),
             transport: None,
+            options_interval: None,
+            radius: None,
         },
     );
 
@@ -521,7 +755,9 @@ async fn test_match_invite_default_route() {
             select: "rr".to_string(),
             hash_key: None,
             reject: None,
+            ..Default::default()
         },
+        transrating: None,
         disabled: None,
     }];
 
@@ -545,6 +781,7 @@ async fn test_match_invite_default_route() {
             println!("Default route selected: {:?}", option.destination);
         }
         RouteResult::Abort(_, _) => panic!("Expected forward, got abort"),
+        RouteResult::Announce(_) => panic!("Expected forward, got announce"),
     }
 }
 
@@ -566,6 +803,8 @@ async fn test_match_invite_advanced_rewrite_patterns() {
             max_cps: None,
             weight: Some(100),
             transport: None,
+            options_interval: None,
+            radius: None,
         },
     );
 
@@ -588,7 +827,9 @@ async fn test_match_invite_advanced_rewrite_patterns() {
             select: "rr".to_string(),
             hash_key: None,
             reject: None,
+            ..Default::default()
         },
+        transrating: None,
         disabled: None,
     }];
 
@@ -618,6 +859,7 @@ async fn test_match_invite_advanced_rewrite_patterns() {
             assert_eq!(caller_user, "0015551234567");
         }
         RouteResult::Abort(_, _) => panic!("Expected forward, got abort"),
+        RouteResult::Announce(_) => panic!("Expected forward, got announce"),
     }
 
     // Test case 2: Simple digit extraction 12345 -> prefix{1}suffix
@@ -639,7 +881,9 @@ async fn test_match_invite_advanced_rewrite_patterns() {
             select: "rr".to_string(),
             hash_key: None,
             reject: None,
+            ..Default::default()
         },
+        transrating: None,
         disabled: None,
     }];
 
@@ -669,6 +913,7 @@ async fn test_match_invite_advanced_rewrite_patterns() {
             assert_eq!(caller_user, "ext12345");
         }
         RouteResult::Abort(_, _) => panic!("Expected forward, got abort"),
+        RouteResult::Announce(_) => panic!("Expected forward, got announce"),
     }
 }
 
@@ -808,3 +1053,321 @@ fn create_test_request() -> rsip::Request {
         None,
     )
 }
+
+#[tokio::test]
+async fn test_match_invite_skips_trunks_at_capacity() {
+    let routing_state = Arc::new(RoutingState::new());
+    let mut trunks = HashMap::new();
+    for (name, host) in [("t1", "gw1.example.com"), ("t2", "gw2.example.com")] {
+        trunks.insert(
+            name.to_string(),
+            TrunkConfig {
+                dest: format!("sip:{}:5060", host),
+                backup_dest: None,
+                username: None,
+                password: None,
+                codec: vec![],
+                disabled: None,
+                max_calls: Some(1),
+                max_cps: None,
+                weight: None,
+                transport: None,
+                options_interval: None,
+                radius: None,
+            },
+        );
+    }
+    let default = DefaultRoute {
+        dest: DestConfig::Multiple(vec!["t1".to_string(), "t2".to_string()]),
+        select: "rr".to_string(),
+        action: "forward".to_string(),
+    };
+    let route = |call_id: &str| {
+        let origin = create_sip_request(
+            rsip::Method::Invite,
+            "sip:1001@example.com",
+            "Alice <sip:alice@example.com>",
+            "Bob <sip:1001@example.com>",
+            call_id,
+            1,
+            None,
+        );
+        let routing_state = routing_state.clone();
+        let trunks = &trunks;
+        let default = &default;
+        async move {
+            match_invite(
+                Some(trunks),
+                Some(&vec![]),
+                Some(default),
+                create_test_invite_option(),
+                &origin,
+                routing_state,
+            )
+            .await
+            .unwrap()
+        }
+    };
+
+    let mut hosts = vec![];
+    for call_id in ["call-1", "call-2"] {
+        match route(call_id).await {
+            RouteResult::Forward(option) => {
+                hosts.push(option.destination.unwrap().addr.host.to_string())
+            }
+            RouteResult::Abort(code, reason) => panic!("unexpected abort {} {}", code, reason),
+            RouteResult::Announce(_) => panic!("unexpected announce"),
+        }
+    }
+    hosts.sort();
+    assert_eq!(hosts, vec!["gw1.example.com", "gw2.example.com"]);
+
+    assert!(matches!(route("call-3").await, RouteResult::Abort(503, _)));
+    // a call keeps its slot when it is routed again
+    assert!(matches!(route("call-1").await, RouteResult::Forward(_)));
+
+    routing_state.limiter.release("call-2");
+    assert!(matches!(route("call-3").await, RouteResult::Forward(_)));
+}
+
+#[tokio::test]
+async fn test_match_invite_source_country_and_time() {
+    let geoip = GeoIp::load(&GeoIpConfig {
+        file: None,
+        networks: HashMap::from([
+            ("cn".to_string(), vec!["192.168.0.0/16".to_string()]),
+            (
+                "us".to_string(),
+                vec!["192.168.1.0/24".to_string(), "2001:db8::/32".to_string()],
+            ),
+        ]),
+    })
+    .await
+    .unwrap();
+    // the most specific network wins
+    assert_eq!(geoip.country(&"192.168.1.1".parse().unwrap()), Some("US"));
+    assert_eq!(geoip.country(&"192.168.1.255".parse().unwrap()), Some("US"));
+    assert_eq!(geoip.country(&"192.168.0.255".parse().unwrap()), Some("CN"));
+    assert_eq!(geoip.country(&"192.168.2.1".parse().unwrap()), Some("CN"));
+    assert_eq!(geoip.country(&"192.169.0.0".parse().unwrap()), None);
+    assert_eq!(geoip.country(&"10.0.0.1".parse().unwrap()), None);
+    assert_eq!(geoip.country(&"2001:db8::1".parse().unwrap()), Some("US"));
+    let routing_state = Arc::new(RoutingState::new().with_geoip(geoip));
+
+    let routes: Vec<RouteRule> = toml::from_str::<HashMap<String, Vec<RouteRule>>>(
+        r#"
+        [[routes]]
+        name = "never"
+        match = { time = { start = "08:00", end = "08:00" } }
+        action = "busy"
+
+        [[routes]]
+        name = "suspicious"
+        match = { "source.country" = "^(US|CN)$", "to.user" = "1001" }
+        action = "reject"
+        reject = { code = 403, reason = "Diverted" }
+        "#,
+    )
+    .unwrap()
+    .remove("routes")
+    .unwrap();
+    let result = match_invite(
+        Some(&HashMap::new()),
+        Some(&routes),
+        None,
+        create_test_invite_option(),
+        &create_test_request(),
+        routing_state,
+    )
+    .await
+    .unwrap();
+    match result {
+        RouteResult::Abort(code, reason) => {
+            assert_eq!(code, 403);
+            assert_eq!(reason, "Diverted");
+        }
+        RouteResult::Forward(_) => panic!("Expected abort, got forward"),
+        RouteResult::Announce(_) => panic!("Expected abort, got announce"),
+    }
+}
+
+#[test]
+fn test_time_condition() {
+    let night: TimeCondition = toml::from_str(
+        r#"
+        start = "18:00"
+        end = "08:00"
+        days = ["mon", "tue", "wed", "thu", "fri"]
+        utc_offset = "+08:00"
+        "#,
+    )
+    .unwrap();
+    let at = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+    let none = HolidayCalendars::default();
+    // Monday 20:00 and Tuesday 07:00 in +08:00
+    assert!(night.contains(at("2024-01-01T12:00:00Z"), &none).unwrap());
+    assert!(night.contains(at("2024-01-01T23:00:00Z"), &none).unwrap());
+    // Monday 12:00, and Saturday 02:00 of a window started on Saturday
+    assert!(!night.contains(at("2024-01-01T04:00:00Z"), &none).unwrap());
+    assert!(!night.contains(at("2024-01-06T18:00:00Z"), &none).unwrap());
+}
+
+#[test]
+fn test_time_condition_holidays() {
+    let holidays = HolidayCalendars::new(&HashMap::from([(
+        "cn".to_string(),
+        vec!["01-01".to_string(), "2024-02-12".to_string()],
+    )]))
+    .unwrap();
+    let date = |date: &str| date.parse::<NaiveDate>().unwrap();
+    let cn = vec!["cn".to_string()];
+    assert!(holidays.is_holiday(&cn, date("2025-01-01")).unwrap());
+    assert!(holidays.is_holiday(&cn, date("2024-02-12")).unwrap());
+    assert!(!holidays.is_holiday(&cn, date("2025-02-12")).unwrap());
+    assert!(
+        holidays
+            .is_holiday(&["us".to_string()], date("2025-01-01"))
+            .is_err()
+    );
+    assert!(
+        HolidayCalendars::new(&HashMap::from([(
+            "cn".to_string(),
+            vec!["13-01".to_string()]
+        )]))
+        .is_err()
+    );
+
+    let office: TimeCondition = toml::from_str(
+        r#"
+        start = "09:00"
+        end = "18:00"
+        utc_offset = "+08:00"
+        holidays = ["cn"]
+        "#,
+    )
+    .unwrap();
+    let at = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+    // 10:00 on a working day, then on New Year's Day in +08:00
+    assert!(
+        office
+            .contains(at("2024-12-31T02:00:00Z"), &holidays)
+            .unwrap()
+    );
+    assert!(
+        !office
+            .contains(at("2025-01-01T02:00:00Z"), &holidays)
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_match_invite_time_condition_and_announce() {
+    // today is a holiday, the office is closed all day
+    let today = Utc::now().format("%Y-%m-%d").to_string();
+    let holidays =
+        HolidayCalendars::new(&HashMap::from([("office".to_string(), vec![today])])).unwrap();
+    let routing_state = Arc::new(RoutingState::new().with_holidays(holidays));
+
+    let routes: Vec<RouteRule> = toml::from_str::<HashMap<String, Vec<RouteRule>>>(
+        r#"
+        [[routes]]
+        name = "never open"
+        match = { "to.user" = "1001" }
+        action = "time_condition"
+        hours = { start = "08:00", end = "08:00" }
+        open = { dest = "t1" }
+
+        [[routes]]
+        name = "office"
+        match = { "to.user" = "1001" }
+        hours = { start = "00:00", end = "23:59", holidays = ["office"] }
+        open = { dest = "t1" }
+        closed = { announcement = "sounds/closed.wav" }
+        "#,
+    )
+    .unwrap()
+    .remove("routes")
+    .unwrap();
+    let result = match_invite(
+        Some(&HashMap::new()),
+        Some(&routes),
+        None,
+        create_test_invite_option(),
+        &create_test_request(),
+        routing_state,
+    )
+    .await
+    .unwrap();
+    match result {
+        RouteResult::Announce(announcement) => assert_eq!(announcement, "sounds/closed.wav"),
+        RouteResult::Forward(_) => panic!("Expected announce, got forward"),
+        RouteResult::Abort(code, reason) => panic!("unexpected abort {} {}", code, reason),
+    }
+}
+
+#[tokio::test]
+async fn test_match_invite_circuits_busy() {
+    let trunks = HashMap::from([(
+        "t1".to_string(),
+        TrunkConfig {
+            dest: "sip:gw1.example.com:5060".to_string(),
+            max_calls: Some(1),
+            ..Default::default()
+        },
+    )]);
+    let default = DefaultRoute {
+        dest: DestConfig::Single("t1".to_string()),
+        select: "rr".to_string(),
+        action: "forward".to_string(),
+    };
+    let route = |routing_state: Arc<RoutingState>, call_id: &str| {
+        let origin = create_sip_request(
+            rsip::Method::Invite,
+            "sip:1001@example.com",
+            "Alice <sip:alice@example.com>",
+            "Bob <sip:1001@example.com>",
+            call_id,
+            1,
+            None,
+        );
+        let trunks = &trunks;
+        let default = &default;
+        async move {
+            match_invite(
+                Some(trunks),
+                Some(&vec![]),
+                Some(default),
+                create_test_invite_option(),
+                &origin,
+                routing_state,
+            )
+            .await
+            .unwrap()
+        }
+    };
+
+    let routing_state = Arc::new(RoutingState::new().with_circuits_busy(CircuitsBusyConfig {
+        code: 503,
+        reason: None,
+        announcement: Some("sounds/all-circuits-busy.wav".to_string()),
+    }));
+    assert!(matches!(
+        route(routing_state.clone(), "call-1").await,
+        RouteResult::Forward(_)
+    ));
+    assert!(matches!(
+        route(routing_state, "call-2").await,
+        RouteResult::Announce(announcement) if announcement == "sounds/all-circuits-busy.wav"
+    ));
+
+    let routing_state = Arc::new(RoutingState::new().with_circuits_busy(CircuitsBusyConfig {
+        code: 486,
+        reason: Some("All Circuits Busy".to_string()),
+        announcement: None,
+    }));
+    route(routing_state.clone(), "call-1").await;
+    assert!(matches!(
+        route(routing_state, "call-2").await,
+        RouteResult::Abort(486, reason) if reason == "All Circuits Busy"
+    ));
+}
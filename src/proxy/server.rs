@@ -5,38 +5,51 @@ use super::{
 };
 use crate::{
     app::AppState,
-    call::{LocationInspector, TransactionCookie},
-    callrecord::CallRecordSender,
-    config::ProxyConfig,
+    call::{LocationInspector, TransactionCookie, dns::SipResolver, retransmission::Retransmitter},
+    callrecord::{CallRecordEventType, CallRecordSender},
+    config::{LocatorConfig, ProxyConfig},
+    net_tool::create_udp_connection,
     proxy::{
-        FnCreateRouteInvite,
-        auth::AuthBackend,
+        FnCreateRouteInvite, RoutingState,
+        agent::AgentSessions,
+        auth::{AuthBackend, create_auth_backend},
         call::{CallRouter, DialplanInspector},
+        campaign::Campaigns,
+        cluster::{Cluster, start_cluster},
+        fraud::FraudDetector,
+        hardening::{HardeningInspector, Verdict, inspect_request},
+        limits::CallLimiter,
+        listener::{SipListener, SipReceiver, create_tls_acceptor, listen_placeholder},
+        metering::{Meter, start_metering},
+        presence::PresenceState,
+        queue::Queues,
+        routing::{HolidayCalendars, geoip::GeoIp},
+        status::ProxyStatusSender,
+        trunk_monitor::start_trunk_monitor,
     },
 };
 use anyhow::{Result, anyhow};
-use rsip::prelude::HeadersExt;
+use rsip::prelude::{HeadersExt, UntypedHeader};
 use rsipstack::{
     EndpointBuilder,
+    dialog::dialog_layer::DialogLayer,
     transaction::{
         Endpoint, TransactionReceiver,
         endpoint::{EndpointOption, MessageInspector},
         transaction::Transaction,
     },
-    transport::{
-        TcpListenerConnection, TransportLayer, WebSocketListenerConnection, udp::UdpConnection,
-    },
+    transport::{SipAddr, TransportLayer, WebSocketListenerConnection},
 };
 use std::{
     collections::HashMap,
     net::{IpAddr, SocketAddr},
     sync::{
-        Arc,
+        Arc, Mutex,
         atomic::{AtomicUsize, Ordering},
     },
     time::Instant,
 };
-use tokio::select;
+use tokio::{net::TcpListener, select, sync::broadcast};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error, info, warn};
 
@@ -53,6 +66,26 @@ pub struct SipServerInner {
     pub endpoint: Endpoint,
     pub location_inspector: Arc<Option<Box<dyn LocationInspector>>>,
     pub create_route_invite: Option<FnCreateRouteInvite>,
+    pub proxy_status: ProxyStatusSender,
+    pub routing_state: Arc<RoutingState>,
+    /// Dialogs the proxy originates or terminates as a B2BUA
+    pub dialog_layer: Arc<DialogLayer>,
+    pub presence: Arc<PresenceState>,
+    pub meter: Arc<Meter>,
+    /// This node of the registrar cluster, when the proxy is in one
+    pub cluster: Option<Arc<Cluster>>,
+    /// Scores the outbound calls, when the config has fraud rules
+    pub fraud: Option<Arc<FraudDetector>>,
+    /// Outbound campaigns started over the AMI
+    pub campaigns: Arc<Campaigns>,
+    /// Call queues of the config, served by the `queue` module
+    pub queues: Arc<Queues>,
+    /// Logins, pauses and wrap-ups of queue agents
+    pub agent_sessions: Arc<AgentSessions>,
+    /// Resolves and blacklists the destinations calls are sent to
+    pub resolver: Arc<SipResolver>,
+    /// Retransmits the 2xx responses of calls the proxy answers
+    pub retransmitter: Retransmitter,
 }
 
 pub type SipServerRef = Arc<SipServerInner>;
@@ -61,6 +94,8 @@ pub type SipServerRef = Arc<SipServerInner>;
 pub struct SipServer {
     pub inner: SipServerRef,
     modules: Arc<Vec<Box<dyn ProxyModule>>>,
+    /// Bound in `build`, received on by `serve`
+    listeners: Arc<Mutex<Vec<SipListener>>>,
 }
 
 pub struct SipServerBuilder {
@@ -147,6 +182,20 @@ impl SipServerBuilder {
         self
     }
 
+    /// Takes the app's cancel token and call record sender, unless they
+    /// were set already
+    pub fn with_app_defaults(
+        mut self,
+        cancel_token: CancellationToken,
+        callrecord_sender: Option<CallRecordSender>,
+    ) -> Self {
+        self.cancel_token.get_or_insert(cancel_token);
+        if self.callrecord_sender.is_none() {
+            self.callrecord_sender = callrecord_sender;
+        }
+        self
+    }
+
     pub fn with_message_inspector(mut self, inspector: Box<dyn MessageInspector>) -> Self {
         self.message_inspector = Some(inspector);
         self
@@ -167,11 +216,22 @@ impl SipServerBuilder {
                 }
             }
         };
-        let auth_backend = self.auth_backend;
+        let auth_backend = match (self.auth_backend, &self.config.auth_backend) {
+            (Some(backend), _) => Some(backend),
+            (None, Some(config)) => match create_auth_backend(config).await {
+                Ok(backend) => Some(backend),
+                Err(e) => {
+                    error!("failed to create auth backend: {} {:?}", e, config);
+                    return Err(e);
+                }
+            },
+            (None, None) => None,
+        };
         let locator = if let Some(locator) = self.locator {
             locator
         } else {
-            match create_locator(&self.config.locator).await {
+            let node = self.config.cluster.as_ref().map(|c| c.node_id.as_str());
+            match create_locator(&self.config.locator, node).await {
                 Ok(locator) => locator,
                 Err(e) => {
                     error!("failed to create locator: {} {:?}", e, self.config.locator);
@@ -205,26 +265,60 @@ impl SipServerBuilder {
             ));
         }
 
+        // UDP, TCP and TLS messages go through `parse_and_report` like the
+        // WebSocket ones, so the stack's own receive loops stay out
+        let mut listeners = vec![];
         if let Some(udp_port) = config.udp_port {
             let local_addr = SocketAddr::new(local_addr, udp_port);
-            let udp_conn = UdpConnection::create_connection(
-                local_addr,
-                external_ip,
-                Some(cancel_token.child_token()),
-            )
-            .await
-            .map_err(|e| anyhow!("Failed to create proxy UDP connection {} {}", local_addr, e))?;
-            transport_layer.add_transport(udp_conn.into());
+            let dscp = app_state.config.qos.as_ref().map(|qos| qos.signaling);
+            let stopped = CancellationToken::new();
+            stopped.cancel();
+            let udp_conn = create_udp_connection(local_addr, external_ip, dscp, Some(stopped))
+                .await
+                .map_err(|e| {
+                    anyhow!("Failed to create proxy UDP connection {} {}", local_addr, e)
+                })?;
+            transport_layer.add_transport(udp_conn.clone().into());
+            listeners.push(SipListener::Udp(udp_conn));
             info!("start proxy, udp port: {}", local_addr);
         }
 
-        if let Some(tcp_port) = config.tcp_port {
-            let local_addr = SocketAddr::new(local_addr, tcp_port);
-            let tcp_conn = TcpListenerConnection::new(local_addr.into(), external_ip)
+        let stream_ports = [
+            (config.tcp_port, rsip::transport::Transport::Tcp),
+            (config.tls_port, rsip::transport::Transport::Tls),
+        ];
+        for (port, transport) in stream_ports {
+            let Some(port) = port else {
+                continue;
+            };
+            let local_addr = SocketAddr::new(local_addr, port);
+            let listener = TcpListener::bind(local_addr)
                 .await
-                .map_err(|e| anyhow!("Failed to create TCP connection: {}", e))?;
-            transport_layer.add_transport(tcp_conn.into());
-            info!("start proxy, tcp port: {}", local_addr);
+                .map_err(|e| anyhow!("Failed to bind {} {}: {}", transport, local_addr, e))?;
+            let listener = match transport {
+                rsip::transport::Transport::Tls => {
+                    let (Some(certificate), Some(private_key)) =
+                        (&config.ssl_certificate, &config.ssl_private_key)
+                    else {
+                        return Err(anyhow!(
+                            "tls_port needs ssl_certificate and ssl_private_key"
+                        ));
+                    };
+                    SipListener::Tls(listener, create_tls_acceptor(certificate, private_key)?)
+                }
+                _ => SipListener::Tcp(listener),
+            };
+            let addr = SipAddr {
+                r#type: Some(transport),
+                addr: external_ip.unwrap_or(local_addr).into(),
+            };
+            transport_layer.add_transport(
+                listen_placeholder(addr)
+                    .await
+                    .map_err(|e| anyhow!("Failed to create {} listener: {}", transport, e))?,
+            );
+            listeners.push(listener);
+            info!("start proxy, {} port: {}", transport, local_addr);
         }
 
         if let Some(ws_port) = config.ws_port {
@@ -241,26 +335,61 @@ impl SipServerBuilder {
             endpoint_builder.with_user_agent(user_agent.as_str());
         }
 
+        let timers = app_state.config.sip_timers.clone().unwrap_or_default();
         let endpoint_option = EndpointOption {
             callid_suffix: config.callid_suffix.clone(),
-            ..Default::default()
+            ..timers.endpoint_option()
         };
 
-        let mut endpoint_builder = endpoint_builder
+        let endpoint_builder = endpoint_builder
             .with_cancel_token(cancel_token.clone())
             .with_option(endpoint_option)
             .with_transport_layer(transport_layer);
 
-        if let Some(inspector) = self.message_inspector {
-            endpoint_builder = endpoint_builder.with_inspector(inspector);
-        }
-
-        let endpoint = endpoint_builder.build();
+        let message_inspector = match config.hardening.clone() {
+            Some(hardening) => Some(Box::new(HardeningInspector::new(
+                hardening,
+                self.message_inspector,
+            )) as Box<dyn MessageInspector>),
+            None => self.message_inspector,
+        };
+        let retransmitter =
+            Retransmitter::new(timers, app_state.retransmissions.clone(), message_inspector);
+        let endpoint = endpoint_builder
+            .with_inspector(Box::new(retransmitter.clone()))
+            .build();
+        let dialog_layer = Arc::new(DialogLayer::new(endpoint.inner.clone()));
 
         let call_router = self.call_router;
         let location_inspector = self.location_inspector;
         let dialplan_inspector = self.dialplan_inspector;
 
+        let proxy_status = broadcast::channel(128).0;
+        let agent_sessions = Arc::new(AgentSessions::new(self.config.pause_reasons.clone()));
+        let cluster = match (self.config.cluster.clone(), &self.config.locator) {
+            (Some(config), LocatorConfig::Database { url }) => Some(Arc::new(
+                Cluster::new(config, url)
+                    .await?
+                    .with_status(proxy_status.clone()),
+            )),
+            (Some(_), _) => return Err(anyhow!("cluster needs the database locator")),
+            (None, _) => None,
+        };
+        let mut routing_state =
+            RoutingState::new().with_limiter(CallLimiter::new().with_status(proxy_status.clone()));
+        if let Some(geoip) = self.config.geoip.as_ref() {
+            routing_state = routing_state.with_geoip(GeoIp::load(geoip).await?);
+        }
+        routing_state = routing_state.with_holidays(HolidayCalendars::new(&self.config.holidays)?);
+        if let Some(circuits_busy) = self.config.circuits_busy.clone() {
+            routing_state = routing_state.with_circuits_busy(circuits_busy);
+        }
+        let fraud = match self.config.fraud.clone() {
+            Some(config) => Some(Arc::new(
+                FraudDetector::new(config)?.with_status(proxy_status.clone()),
+            )),
+            None => None,
+        };
         let inner = Arc::new(SipServerInner {
             app_state,
             config: self.config.clone(),
@@ -274,6 +403,20 @@ impl SipServerBuilder {
             location_inspector: Arc::new(location_inspector),
             dialplan_inspector: Arc::new(dialplan_inspector),
             create_route_invite: self.create_route_invite,
+            routing_state: Arc::new(routing_state),
+            proxy_status,
+            dialog_layer,
+            presence: Arc::new(PresenceState::new()),
+            meter: Arc::new(Meter::new()),
+            cluster,
+            fraud,
+            campaigns: Arc::new(Campaigns::default()),
+            queues: Arc::new(Queues::new(&self.config, agent_sessions.clone())?),
+            agent_sessions,
+            resolver: Arc::new(SipResolver::new(
+                &self.config.dns.clone().unwrap_or_default(),
+            )),
+            retransmitter,
         });
 
         let mut allow_methods = Vec::new();
@@ -344,6 +487,7 @@ impl SipServerBuilder {
         Ok(SipServer {
             inner,
             modules: Arc::new(modules),
+            listeners: Arc::new(Mutex::new(listeners)),
         })
     }
 }
@@ -352,6 +496,19 @@ impl SipServer {
     pub async fn serve(&self) -> Result<()> {
         let incoming = self.inner.endpoint.incoming_transactions()?;
         let cancel_token = self.inner.cancel_token.clone();
+        start_trunk_monitor(&self.inner);
+        start_metering(&self.inner);
+        start_cluster(&self.inner);
+        let receiver = SipReceiver {
+            endpoint: self.inner.endpoint.inner.clone(),
+            status: self.inner.proxy_status.clone(),
+            parse_mode: self.inner.config.sip_parse_mode,
+            token: cancel_token.child_token(),
+        };
+        let listeners = std::mem::take(&mut *self.listeners.lock().unwrap());
+        for listener in listeners {
+            tokio::spawn(receiver.clone().serve(listener));
+        }
         tokio::select! {
             _ = cancel_token.cancelled() => {
                 info!("cancelled");
@@ -363,6 +520,7 @@ impl SipServer {
                 info!("incoming transactions stopped");
             }
         };
+        receiver.token.cancel();
 
         for module in self.modules.iter() {
             match module.on_stop().await {
@@ -393,12 +551,18 @@ impl SipServer {
 
     async fn handle_incoming(&self, mut incoming: TransactionReceiver) -> Result<()> {
         let runnings_tx = Arc::new(AtomicUsize::new(0));
+        // out-of-dialog REFER is wanted when a module handles it, e.g. click-to-dial
+        let accepts_refer = self
+            .modules
+            .iter()
+            .any(|m| m.allow_methods().contains(&rsip::Method::Refer));
         while let Some(mut tx) = incoming.recv().await {
             debug!(key = %tx.key, "received transaction");
             let modules = self.modules.clone();
 
             let token = self.inner.cancel_token.child_token();
             let runnings_tx = runnings_tx.clone();
+            let journal = self.inner.app_state.journal.clone();
 
             if let Some(max_concurrency) = self.inner.config.max_concurrency {
                 if runnings_tx.load(Ordering::Relaxed) >= max_concurrency {
@@ -411,6 +575,20 @@ impl SipServer {
                     continue;
                 }
             }
+            if let Some(hardening) = self.inner.config.hardening.as_ref() {
+                match inspect_request(hardening, &tx.original) {
+                    Verdict::Accept => {}
+                    Verdict::Drop(reason) => {
+                        info!(key = %tx.key, reason, "dropping request");
+                        continue;
+                    }
+                    Verdict::Reject(code, reason) => {
+                        info!(key = %tx.key, %code, reason, "rejecting malformed request");
+                        tx.reply(code).await.ok();
+                        continue;
+                    }
+                }
+            }
             // Spam protection for OPTIONS requests
             // If the OPTIONS request is out-of-dialog and the tag is not present, ignore it
             if matches!(
@@ -419,7 +597,8 @@ impl SipServer {
                     | rsip::method::Method::Info
                     | rsip::method::Method::Refer
                     | rsip::method::Method::Update
-            ) {
+            ) && !(accepts_refer && tx.original.method == rsip::Method::Refer)
+            {
                 if tx.endpoint_inner.option.ignore_out_of_dialog_option {
                     let to_tag = tx
                         .original
@@ -462,6 +641,22 @@ impl SipServer {
                         tx.reply(rsip::StatusCode::NotImplemented).await.ok();
                     }
                 }
+                if let Some(journal) = journal {
+                    let call_id = tx
+                        .original
+                        .call_id_header()
+                        .map(|call_id| call_id.value().to_string())
+                        .ok();
+                    journal.record(
+                        CallRecordEventType::Sip,
+                        call_id.as_deref(),
+                        &serde_json::json!({
+                            "method": tx.original.method.to_string(),
+                            "request": tx.original.to_string(),
+                            "response": tx.last_response.as_ref().map(|r| r.to_string()),
+                        }),
+                    );
+                }
                 return Ok::<(), anyhow::Error>(());
             });
         }
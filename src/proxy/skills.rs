@@ -0,0 +1,152 @@
+use anyhow::{Result, anyhow};
+use std::fmt;
+
+/// Which agent skills a call needs, e.g. `lang:es & (billing | sales)`.
+/// `!` negates, `&` binds tighter than `|`. Skills are compared ignoring
+/// case
+#[derive(Debug, Clone, PartialEq)]
+pub enum SkillExpr {
+    Skill(String),
+    Not(Box<SkillExpr>),
+    And(Box<SkillExpr>, Box<SkillExpr>),
+    Or(Box<SkillExpr>, Box<SkillExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Skill(String),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => {}
+            '!' => tokens.push(Token::Not),
+            '&' => tokens.push(Token::And),
+            '|' => tokens.push(Token::Or),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            c if is_skill_char(c) => {
+                let mut skill = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !is_skill_char(c) {
+                        break;
+                    }
+                    skill.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Skill(skill.to_lowercase()));
+            }
+            c => return Err(anyhow!("unexpected {:?} in skills {:?}", c, text)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn is_skill_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':')
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn or(&mut self) -> Result<SkillExpr> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expr = SkillExpr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<SkillExpr> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            expr = SkillExpr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<SkillExpr> {
+        match self.next() {
+            Some(Token::Not) => Ok(SkillExpr::Not(Box::new(self.unary()?))),
+            Some(Token::Skill(skill)) => Ok(SkillExpr::Skill(skill)),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(anyhow!("missing )")),
+                }
+            }
+            Some(token) => Err(anyhow!("unexpected {:?}", token)),
+            None => Err(anyhow!("unexpected end")),
+        }
+    }
+}
+
+impl SkillExpr {
+    /// `None` for a blank expression, which any agent matches
+    pub fn parse(text: &str) -> Result<Option<Self>> {
+        let tokens = tokenize(text)?;
+        if tokens.is_empty() {
+            return Ok(None);
+        }
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser
+            .or()
+            .map_err(|e| anyhow!("invalid skills {:?}: {}", text, e))?;
+        if parser.pos < parser.tokens.len() {
+            return Err(anyhow!("invalid skills {:?}: trailing input", text));
+        }
+        Ok(Some(expr))
+    }
+
+    pub fn matches(&self, skills: &[String]) -> bool {
+        match self {
+            SkillExpr::Skill(skill) => skills.iter().any(|s| s.eq_ignore_ascii_case(skill)),
+            SkillExpr::Not(expr) => !expr.matches(skills),
+            SkillExpr::And(a, b) => a.matches(skills) && b.matches(skills),
+            SkillExpr::Or(a, b) => a.matches(skills) || b.matches(skills),
+        }
+    }
+
+    /// Both requirements, either may be absent
+    pub fn both(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        match (a, b) {
+            (Some(a), Some(b)) => Some(SkillExpr::And(Box::new(a), Box::new(b))),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+impl fmt::Display for SkillExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkillExpr::Skill(skill) => write!(f, "{}", skill),
+            SkillExpr::Not(expr) => write!(f, "!{}", expr),
+            SkillExpr::And(a, b) => write!(f, "({} & {})", a, b),
+            SkillExpr::Or(a, b) => write!(f, "({} | {})", a, b),
+        }
+    }
+}
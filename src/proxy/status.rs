@@ -1,7 +1,95 @@
+use super::{
+    fraud::FraudRule,
+    limits::{LimitKind, LimitScope},
+};
+use serde::Serialize;
 use tokio::sync::broadcast;
 
-#[derive(Debug, Clone)]
-pub enum ProxyStatus {}
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum ProxyStatus {
+    #[serde(rename_all = "camelCase")]
+    MessageReceived {
+        id: String,
+        timestamp: u64,
+        from: String,
+        to: String,
+        content_type: String,
+        body: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    MessageDelivered {
+        id: String,
+        timestamp: u64,
+        from: String,
+        to: String,
+        destination: String,
+        status: u16,
+    },
+    #[serde(rename_all = "camelCase")]
+    MessageFailed {
+        id: String,
+        timestamp: u64,
+        from: String,
+        to: String,
+        status: u16,
+        reason: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    TrunkUp {
+        trunk: String,
+        timestamp: u64,
+        latency_ms: u64,
+    },
+    #[serde(rename_all = "camelCase")]
+    TrunkDown {
+        trunk: String,
+        timestamp: u64,
+        reason: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    SipParseError {
+        timestamp: u64,
+        source: String,
+        transport: String,
+        reason: String,
+        /// Lenient parsing repaired the message and it was processed
+        recovered: bool,
+        repairs: Vec<String>,
+    },
+    /// A call was refused with 503 because a call limit was reached
+    #[serde(rename_all = "camelCase")]
+    CallLimitExceeded {
+        timestamp: u64,
+        call_id: String,
+        scope: LimitScope,
+        /// The extension or trunk, `*` for the global limit
+        name: String,
+        limit: LimitKind,
+        max: u32,
+    },
+    /// A node of the registrar cluster went down, `by` took over its
+    /// bindings
+    #[serde(rename_all = "camelCase")]
+    ClusterTakeover {
+        timestamp: u64,
+        node: String,
+        by: String,
+        bindings: usize,
+    },
+    /// An outbound call scored `alert_score` or more in the fraud rules
+    #[serde(rename_all = "camelCase")]
+    FraudAlert {
+        timestamp: u64,
+        call_id: String,
+        extension: String,
+        number: String,
+        score: u32,
+        rules: Vec<FraudRule>,
+        /// Refused with 403, it scored `block_score` or more
+        blocked: bool,
+    },
+}
 
 pub type ProxyStatusSender = broadcast::Sender<ProxyStatus>;
 pub type ProxyStatusReceiver = broadcast::Receiver<ProxyStatus>;
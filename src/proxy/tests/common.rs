@@ -1,13 +1,15 @@
 use crate::app::AppStateBuilder;
 use crate::call::user::SipUser;
 use crate::config::ProxyConfig;
+use crate::proxy::FnCreateProxyModule;
 use crate::proxy::locator::MemoryLocator;
-use crate::proxy::server::SipServerInner;
+use crate::proxy::server::{SipServer, SipServerBuilder, SipServerInner};
 use crate::proxy::user::MemoryUserBackend;
 use rsip::Header;
 use rsip::services::DigestGenerator;
 use rsip::{HostWithPort, prelude::*};
 use rsipstack::EndpointBuilder;
+use rsipstack::dialog::dialog_layer::DialogLayer;
 use rsipstack::transaction::endpoint::EndpointInner;
 use rsipstack::transaction::key::{TransactionKey, TransactionRole};
 use rsipstack::transaction::random_text;
@@ -42,6 +44,9 @@ pub async fn create_test_server_with_config(
     let locator = Box::new(MemoryLocator::new());
     let config = Arc::new(config);
     let endpoint = EndpointBuilder::new().build();
+    let dialog_layer = Arc::new(DialogLayer::new(endpoint.inner.clone()));
+
+    let agent_sessions = Arc::new(crate::proxy::agent::AgentSessions::default());
 
     // Create server inner directly
     let server_inner = Arc::new(SipServerInner {
@@ -66,6 +71,24 @@ pub async fn create_test_server_with_config(
         location_inspector: Arc::new(None),
         dialplan_inspector: Arc::new(None),
         create_route_invite: None,
+        proxy_status: tokio::sync::broadcast::channel(16).0,
+        routing_state: Arc::new(crate::proxy::RoutingState::new()),
+        dialog_layer,
+        presence: Arc::new(crate::proxy::presence::PresenceState::new()),
+        meter: Arc::new(crate::proxy::metering::Meter::new()),
+        fraud: None,
+        cluster: None,
+        campaigns: Arc::new(crate::proxy::campaign::Campaigns::default()),
+        queues: Arc::new(
+            crate::proxy::queue::Queues::new(&config, agent_sessions.clone()).unwrap(),
+        ),
+        agent_sessions,
+        resolver: Arc::new(crate::call::dns::SipResolver::default()),
+        retransmitter: crate::call::retransmission::Retransmitter::new(
+            Default::default(),
+            Arc::new(Default::default()),
+            None,
+        ),
     });
 
     // Add test users
@@ -101,6 +124,35 @@ pub async fn create_test_server_with_config(
     (server_inner, config)
 }
 
+/// Creates and serves a SIP server listening on a random local UDP port,
+/// with `modules` available to `config.modules`
+pub async fn create_udp_test_server(
+    mut config: ProxyConfig,
+    modules: &[(&str, FnCreateProxyModule)],
+) -> SipServer {
+    config.addr = "127.0.0.1".to_string();
+    config.udp_port = Some(portpicker::pick_unused_port().unwrap_or(15070));
+    let app_state = AppStateBuilder::new()
+        .with_config(crate::config::Config {
+            ua: None,
+            ..Default::default()
+        })
+        .build()
+        .await
+        .unwrap()
+        .0;
+    let mut builder = SipServerBuilder::new(Arc::new(config))
+        .with_user_backend(Box::new(MemoryUserBackend::new(None)))
+        .with_locator(Box::new(MemoryLocator::new()));
+    for (name, module_fn) in modules {
+        builder = builder.register_module(name, *module_fn);
+    }
+    let server = builder.build(app_state).await.unwrap();
+    let serving = server.clone();
+    tokio::spawn(async move { serving.serve().await });
+    server
+}
+
 /// Creates a basic SIP transaction for testing
 pub async fn create_transaction(request: rsip::Request) -> (Transaction, Arc<EndpointInner>) {
     let mock_addr = SipAddr {
@@ -1,6 +1,7 @@
 use crate::{
     call::Location,
-    proxy::{locator::Locator, locator_db::DbLocator},
+    config::ClusterConfig,
+    proxy::{cluster::Cluster, locator::Locator, locator_db::DbLocator, presence::Subscription},
 };
 use rsip::{HostWithPort, Scheme};
 use rsipstack::transport::SipAddr;
@@ -63,6 +64,11 @@ async fn test_db_locator() {
         }
     }
 
+    // Test bindings
+    let bindings = locator.bindings().await.unwrap();
+    assert_eq!(bindings.len(), 1);
+    assert_eq!(bindings["alice@example.com"][0].expires, 3600);
+
     // Test unregister
     locator
         .unregister("alice", Some("example.com"))
@@ -248,3 +254,98 @@ async fn test_db_locator_multiple_lookups() {
     assert_eq!(locations2.len(), 1);
     assert_eq!(locations2[0].aor.to_string(), aor2.to_string());
 }
+
+#[tokio::test]
+async fn test_db_locator_cluster_takeover() {
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!(
+        "sqlite://{}?mode=rwc",
+        dir.path().join("locator.db").display()
+    );
+    let node = |node_id: &str| ClusterConfig {
+        node_id: node_id.to_string(),
+        heartbeat_interval: 5,
+        node_timeout: 15,
+    };
+    let locator_a = DbLocator::new(url.clone()).await.unwrap().with_node("a");
+    let locator_b = DbLocator::new(url.clone()).await.unwrap().with_node("b");
+    let cluster_a = Cluster::new(node("a"), &url).await.unwrap();
+    let cluster_b = Cluster::new(node("b"), &url).await.unwrap();
+    let location = |user: &str| Location {
+        aor: format!("sip:{}@192.168.1.10:5060", user)
+            .try_into()
+            .unwrap(),
+        expires: 3600,
+        destination: SipAddr {
+            r#type: Some(rsip::transport::Transport::Udp),
+            addr: HostWithPort::try_from("192.168.1.10:5060").unwrap(),
+        },
+        last_modified: Some(Instant::now()),
+        ..Default::default()
+    };
+
+    // registered through one node, found through the other
+    for user in ["alice", "bob"] {
+        locator_a
+            .register(user, Some("example.com"), location(user))
+            .await
+            .unwrap();
+    }
+    let locations = locator_b.lookup("bob", Some("example.com")).await.unwrap();
+    assert_eq!(locations[0].aor.to_string(), "sip:bob@192.168.1.10:5060");
+    // a refresh through another node moves the binding to it
+    locator_b
+        .register("alice", Some("example.com"), location("alice"))
+        .await
+        .unwrap();
+
+    let subscription = |call_id: &str, expires: i64| Subscription {
+        call_id: call_id.to_string(),
+        aor: "alice@example.com".to_string(),
+        event: "dialog".to_string(),
+        local: "<sip:alice@example.com>;tag=a1".to_string(),
+        remote: "<sip:bob@example.com>;tag=b1".to_string(),
+        target: "sip:bob@192.168.1.10:5060".to_string(),
+        route_set: vec![],
+        destination: "192.168.1.10:5060".to_string(),
+        transport: Some("UDP".to_string()),
+        expires,
+        cseq: 3,
+        version: 2,
+    };
+    let watching = subscription("watch-1", i64::MAX);
+    cluster_a.save_subscription(&watching).await.unwrap();
+    cluster_a
+        .save_subscription(&subscription("expired-1", 0))
+        .await
+        .unwrap();
+    cluster_a
+        .save_subscription(&subscription("removed-1", i64::MAX))
+        .await
+        .unwrap();
+    cluster_a.remove_subscription("removed-1").await.unwrap();
+
+    cluster_a.heartbeat(1000).await.unwrap();
+    cluster_b.heartbeat(1000).await.unwrap();
+    assert_eq!(cluster_b.live_nodes(1010).await.unwrap(), vec!["a", "b"]);
+    assert!(cluster_a.check(1010).await.unwrap().is_empty());
+    assert!(cluster_b.check(1010).await.unwrap().is_empty());
+
+    // a went down, b takes over the bindings registered through it
+    cluster_b.heartbeat(1020).await.unwrap();
+    let mut takeovers = cluster_b.subscribe();
+    let takeover = cluster_b.check(1020).await.unwrap();
+    assert_eq!(takeover.len(), 1);
+    assert_eq!(takeover[0].node, "a");
+    let keys = takeover[0]
+        .bindings
+        .iter()
+        .map(|(key, _)| key.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(keys, vec!["bob@example.com"]);
+    // and keeps notifying the live subscriptions
+    assert_eq!(takeover[0].subscriptions, vec![watching]);
+    assert_eq!(takeovers.try_recv().unwrap().bindings.len(), 1);
+    assert_eq!(cluster_b.live_nodes(1020).await.unwrap(), vec!["b"]);
+    assert!(cluster_b.check(1025).await.unwrap().is_empty());
+}
@@ -1,7 +1,13 @@
 pub mod common;
+pub mod sim;
 mod locator_db_test;
 mod test_acl;
+mod test_agent;
+mod test_hardening;
+mod test_limits;
+mod test_metering;
 mod test_auth;
+mod test_auth_backend;
 mod test_proxy;
 mod test_registrar;
 mod user_db_test;
@@ -9,6 +15,21 @@ mod user_http_test;
 // mod user_http_test;
 // mod call_webrtc_sip_test;
 mod test_call;
+mod test_campaign;
+mod test_campon;
+mod test_clicktodial;
 mod test_cdr;
+mod test_forking;
+mod test_fraud;
+mod test_message;
+mod test_nat;
+mod test_outbound;
+mod test_parser;
+mod test_path;
+mod test_presence;
+mod test_queue;
+mod test_radius;
+mod test_sim;
+mod test_trunk_monitor;
 mod test_proxy_integration;
 mod test_ua;
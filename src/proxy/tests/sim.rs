@@ -0,0 +1,611 @@
+//! Scriptable SIP endpoints standing in for trunks and phones, so that tests
+//! of dialplans and IVR flows run in-process, over loopback.
+//!
+//! A [`SimEndpoint`] answers the calls it gets, or places calls, by a
+//! [`SimScript`]: ring for a while, answer or reject, then at set times
+//! after the answer play audio, send DTMF, stop sending RTP or hang up.
+//! Each call ends with a [`SimReport`] of what the endpoint heard.
+use crate::media::{
+    codecs::{Encoder, pcmu::PcmuEncoder, resample::resample_mono},
+    dtmf::{DtmfDetector, dtmf_event_code},
+    negotiate::{parse_sdp, select_peer_media},
+    track::file::read_wav_file,
+};
+use crate::{PcmBuf, Sample};
+use anyhow::{Result, anyhow};
+use bytes::Bytes;
+use rsip::prelude::HeadersExt;
+use rsip::typed::MediaType;
+use rsipstack::dialog::dialog::{DialogState, DialogStateReceiver, TerminatedReason};
+use rsipstack::dialog::dialog_layer::DialogLayer;
+use rsipstack::dialog::invitation::InviteOption;
+use rsipstack::dialog::server_dialog::ServerInviteDialog;
+use rsipstack::transaction::{EndpointBuilder, TransactionReceiver};
+use rsipstack::transport::udp::UdpConnection;
+use rsipstack::transport::{SipAddr, TransportLayer};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::select;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use webrtc::rtp::{header::Header as RtpHeader, packet::Packet as RtpPacket};
+use webrtc::util::{Marshal, Unmarshal};
+
+/// Audio goes out as PCMU, a packet every 20ms
+const SAMPLE_RATE: u32 = 8000;
+const PTIME: Duration = Duration::from_millis(20);
+const SAMPLES_PER_PACKET: usize = 160;
+const PCMU_PAYLOAD_TYPE: u8 = 0;
+const DTMF_PAYLOAD_TYPE: u8 = 101;
+/// Packets of each digit, and the silence after it
+const DTMF_DIGIT_PACKETS: u16 = 5;
+const DTMF_GAP_PACKETS: usize = 3;
+
+#[derive(Debug, Clone)]
+pub enum SimAction {
+    /// Sends the samples, 8 kHz, after what is still playing
+    Play(PcmBuf),
+    /// Sends the digits as RFC 4733 telephone-events
+    Dtmf(String),
+    /// Stops sending RTP, the peer's media goes silent
+    DropRtp,
+    Hangup,
+}
+
+/// What an endpoint does with a call, the times of the actions counted
+/// from the answer
+#[derive(Debug, Clone, Default)]
+pub struct SimScript {
+    answer_after: Duration,
+    reject: Option<u16>,
+    actions: Vec<(Duration, SimAction)>,
+}
+
+impl SimScript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rings for `delay` before answering or rejecting
+    pub fn answer_after(mut self, delay: Duration) -> Self {
+        self.answer_after = delay;
+        self
+    }
+
+    /// Rejects the calls it gets with `status`
+    pub fn reject(mut self, status: u16) -> Self {
+        self.reject = Some(status);
+        self
+    }
+
+    pub fn at(mut self, at: Duration, action: SimAction) -> Self {
+        self.actions.push((at, action));
+        self.actions.sort_by_key(|(at, _)| *at);
+        self
+    }
+
+    /// Plays a WAV fixture, such as `fixtures/sample.wav`
+    pub fn play(self, at: Duration, path: &str) -> Result<Self> {
+        let (samples, sample_rate) = read_wav_file(path)?;
+        let samples = resample_mono(&samples, sample_rate, SAMPLE_RATE);
+        Ok(self.at(at, SimAction::Play(samples)))
+    }
+
+    pub fn dtmf(self, at: Duration, digits: &str) -> Self {
+        self.at(at, SimAction::Dtmf(digits.to_string()))
+    }
+
+    pub fn drop_rtp(self, at: Duration) -> Self {
+        self.at(at, SimAction::DropRtp)
+    }
+
+    pub fn hangup(self, at: Duration) -> Self {
+        self.at(at, SimAction::Hangup)
+    }
+}
+
+/// How a call went, seen from a simulated endpoint
+#[derive(Debug, Clone, Default)]
+pub struct SimReport {
+    /// Final status of the INVITE
+    pub status: u16,
+    /// Time from the INVITE to its final response
+    pub setup: Duration,
+    /// Time from the answer to the hangup
+    pub duration: Duration,
+    pub rtp_sent: usize,
+    pub rtp_received: usize,
+    /// Time from the answer to the last RTP packet received
+    pub last_rtp: Option<Duration>,
+    /// Digits received as telephone-events
+    pub digits: String,
+    /// Whether the peer hung up, rather than the script
+    pub hangup_by_peer: bool,
+}
+
+/// A SIP endpoint on a loopback port, answering calls by its script and
+/// placing calls by theirs
+pub struct SimEndpoint {
+    addr: SocketAddr,
+    dialog_layer: Arc<DialogLayer>,
+    cancel_token: CancellationToken,
+    reports: UnboundedReceiver<SimReport>,
+}
+
+impl SimEndpoint {
+    /// Starts the endpoint, calls to it are answered by `script`
+    pub async fn start(script: SimScript) -> Result<Self> {
+        let cancel_token = CancellationToken::new();
+        let transport_layer = TransportLayer::new(cancel_token.clone());
+        let connection =
+            UdpConnection::create_connection("127.0.0.1:0".parse()?, None, None).await?;
+        let addr = connection.get_addr().get_socketaddr()?;
+        transport_layer.add_transport(connection.into());
+        let endpoint = EndpointBuilder::new()
+            .with_cancel_token(cancel_token.clone())
+            .with_transport_layer(transport_layer)
+            .build();
+        let incoming = endpoint.incoming_transactions()?;
+        let dialog_layer = Arc::new(DialogLayer::new(endpoint.inner.clone()));
+        let token = cancel_token.clone();
+        tokio::spawn(async move {
+            select! {
+                _ = endpoint.serve() => {}
+                _ = token.cancelled() => {}
+            }
+        });
+        let (report_sender, reports) = unbounded_channel();
+        tokio::spawn(serve(
+            dialog_layer.clone(),
+            incoming,
+            contact(addr)?,
+            script,
+            report_sender,
+            cancel_token.clone(),
+        ));
+        info!(%addr, "simulated endpoint started");
+        Ok(Self {
+            addr,
+            dialog_layer,
+            cancel_token,
+            reports,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// `sip:user@` the endpoint, to use as a trunk's `dest` or to call it
+    pub fn uri(&self, user: &str) -> String {
+        format!("sip:{}@{}", user, self.addr)
+    }
+
+    /// Calls `callee`, a SIP URI, and returns once the call ended
+    pub async fn call(&self, callee: &str, script: SimScript) -> Result<SimReport> {
+        self.invite(None, callee, script).await
+    }
+
+    /// Calls `callee` through `proxy`, e.g. a number at another realm that
+    /// the proxy routes to a trunk
+    pub async fn call_via(
+        &self,
+        proxy: SocketAddr,
+        callee: &str,
+        script: SimScript,
+    ) -> Result<SimReport> {
+        self.invite(Some(proxy), callee, script).await
+    }
+
+    async fn invite(
+        &self,
+        proxy: Option<SocketAddr>,
+        callee: &str,
+        script: SimScript,
+    ) -> Result<SimReport> {
+        let callee = rsip::Uri::try_from(callee).map_err(|e| anyhow!("{}: {}", callee, e))?;
+        let socket = UdpSocket::bind("127.0.0.1:0").await?;
+        let offer = sim_sdp(socket.local_addr()?.port());
+        let (state_sender, states) = unbounded_channel();
+        let option = InviteOption {
+            caller: format!("sip:sim@{}", self.addr).try_into()?,
+            callee,
+            destination: proxy.map(|addr| SipAddr {
+                r#type: Some(rsip::Transport::Udp),
+                addr: addr.into(),
+            }),
+            content_type: Some("application/sdp".to_string()),
+            offer: Some(offer.into_bytes()),
+            contact: contact(self.addr)?,
+            credential: None,
+            headers: None,
+        };
+        let started = Instant::now();
+        let (dialog, response) = match self.dialog_layer.do_invite(option, state_sender).await {
+            Ok(r) => r,
+            Err(rsipstack::Error::DialogError(_, _, code)) => {
+                return Ok(SimReport {
+                    status: code.code(),
+                    setup: started.elapsed(),
+                    ..Default::default()
+                });
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let response = response.ok_or_else(|| anyhow!("no answer to the INVITE"))?;
+        let mut report = SimReport {
+            status: response.status_code.code(),
+            setup: started.elapsed(),
+            ..Default::default()
+        };
+        if report.status != 200 {
+            return Ok(report);
+        }
+        let remote = rtp_addr(&response.body)?;
+        let hangup_dialog = dialog.clone();
+        run_media(
+            socket,
+            remote,
+            script.actions,
+            states,
+            &mut report,
+            || async move { hangup_dialog.bye().await.map_err(Into::into) },
+        )
+        .await?;
+        self.dialog_layer.remove_dialog(&dialog.id());
+        Ok(report)
+    }
+
+    /// The report of the next call the endpoint answered or rejected, once
+    /// it ended
+    pub async fn next_report(&mut self) -> Option<SimReport> {
+        self.reports.recv().await
+    }
+
+    pub fn stop(&self) {
+        self.cancel_token.cancel();
+    }
+}
+
+impl Drop for SimEndpoint {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn contact(addr: SocketAddr) -> Result<rsip::Uri> {
+    Ok(format!("sip:sim@{}", addr).try_into()?)
+}
+
+/// Offers and answers PCMU with telephone-events
+fn sim_sdp(port: u16) -> String {
+    format!(
+        "v=0\r\n\
+         o=sim 0 0 IN IP4 127.0.0.1\r\n\
+         s=sim\r\n\
+         c=IN IP4 127.0.0.1\r\n\
+         t=0 0\r\n\
+         m=audio {} RTP/AVP {} {}\r\n\
+         a=rtpmap:{} PCMU/8000\r\n\
+         a=rtpmap:{} telephone-event/8000\r\n\
+         a=fmtp:{} 0-16\r\n\
+         a=sendrecv\r\n",
+        port,
+        PCMU_PAYLOAD_TYPE,
+        DTMF_PAYLOAD_TYPE,
+        PCMU_PAYLOAD_TYPE,
+        DTMF_PAYLOAD_TYPE,
+        DTMF_PAYLOAD_TYPE
+    )
+}
+
+fn rtp_addr(sdp: &[u8]) -> Result<SocketAddr> {
+    let sdp = parse_sdp(sdp)?;
+    let media = select_peer_media(&sdp, "audio").ok_or_else(|| anyhow!("no audio in SDP"))?;
+    Ok(format!("{}:{}", media.rtp_addr, media.rtp_port).parse()?)
+}
+
+/// Hands in-dialog requests to their dialogs, and each new INVITE to a
+/// task running the script
+async fn serve(
+    dialog_layer: Arc<DialogLayer>,
+    mut incoming: TransactionReceiver,
+    contact: rsip::Uri,
+    script: SimScript,
+    reports: UnboundedSender<SimReport>,
+    cancel_token: CancellationToken,
+) {
+    loop {
+        let mut tx = select! {
+            tx = incoming.recv() => match tx {
+                Some(tx) => tx,
+                None => break,
+            },
+            _ = cancel_token.cancelled() => break,
+        };
+        let in_dialog = tx
+            .original
+            .to_header()
+            .and_then(|to| to.tag())
+            .map(|tag| tag.is_some())
+            .unwrap_or(false);
+        if in_dialog {
+            match dialog_layer.match_dialog(&tx.original) {
+                Some(mut dialog) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = dialog.handle(&mut tx).await {
+                            warn!("simulated endpoint failed to handle request: {}", e);
+                        }
+                    });
+                }
+                None => {
+                    tx.reply(rsip::StatusCode::CallTransactionDoesNotExist)
+                        .await
+                        .ok();
+                }
+            }
+            continue;
+        }
+        match tx.original.method {
+            rsip::Method::Invite => {
+                let (state_sender, states) = unbounded_channel();
+                let mut dialog = match dialog_layer.get_or_create_server_invite(
+                    &tx,
+                    state_sender,
+                    None,
+                    Some(contact.clone()),
+                ) {
+                    Ok(dialog) => dialog,
+                    Err(e) => {
+                        warn!("simulated endpoint can't take the call: {}", e);
+                        tx.reply(rsip::StatusCode::ServerInternalError).await.ok();
+                        continue;
+                    }
+                };
+                let offer = tx.original.body.clone();
+                let answering = dialog.clone();
+                tokio::spawn(async move { dialog.handle(&mut tx).await });
+                let dialog_layer = dialog_layer.clone();
+                let script = script.clone();
+                let reports = reports.clone();
+                tokio::spawn(async move {
+                    let id = answering.id();
+                    match answer(answering, offer, script, states).await {
+                        Ok(report) => {
+                            reports.send(report).ok();
+                        }
+                        Err(e) => warn!("simulated call failed: {}", e),
+                    }
+                    dialog_layer.remove_dialog(&id);
+                });
+            }
+            _ => {
+                tx.reply(rsip::StatusCode::OK).await.ok();
+            }
+        }
+    }
+}
+
+/// Rings, then answers or rejects the call, and runs the script
+async fn answer(
+    dialog: ServerInviteDialog,
+    offer: Vec<u8>,
+    script: SimScript,
+    mut states: DialogStateReceiver,
+) -> Result<SimReport> {
+    let started = Instant::now();
+    dialog.ringing(None, None)?;
+    let ringing = tokio::time::sleep(script.answer_after);
+    tokio::pin!(ringing);
+    loop {
+        select! {
+            _ = &mut ringing => break,
+            state = states.recv() => match state {
+                Some(DialogState::Terminated(_, reason)) => {
+                    let status = match reason {
+                        TerminatedReason::UacCancel => 487,
+                        _ => 0,
+                    };
+                    return Ok(SimReport {
+                        status,
+                        setup: started.elapsed(),
+                        hangup_by_peer: true,
+                        ..Default::default()
+                    });
+                }
+                Some(_) => {}
+                None => return Err(anyhow!("dialog gone while ringing")),
+            },
+        }
+    }
+    if let Some(status) = script.reject {
+        dialog.reject(Some(rsip::StatusCode::from(status)), None)?;
+        return Ok(SimReport {
+            status,
+            setup: started.elapsed(),
+            ..Default::default()
+        });
+    }
+    let socket = UdpSocket::bind("127.0.0.1:0").await?;
+    let answer = sim_sdp(socket.local_addr()?.port());
+    dialog.accept(
+        Some(vec![
+            rsip::typed::ContentType(MediaType::Sdp(vec![])).into(),
+        ]),
+        Some(answer.into_bytes()),
+    )?;
+    let mut report = SimReport {
+        status: 200,
+        setup: started.elapsed(),
+        ..Default::default()
+    };
+    let remote = rtp_addr(&offer)?;
+    let hangup_dialog = dialog.clone();
+    run_media(
+        socket,
+        remote,
+        script.actions,
+        states,
+        &mut report,
+        || async move { hangup_dialog.bye().await.map_err(Into::into) },
+    )
+    .await?;
+    Ok(report)
+}
+
+/// Sends a packet every 20ms, silence when there is nothing to play, and
+/// counts what comes back until one side hangs up
+async fn run_media<F, Fut>(
+    socket: UdpSocket,
+    remote: SocketAddr,
+    actions: Vec<(Duration, SimAction)>,
+    mut states: DialogStateReceiver,
+    report: &mut SimReport,
+    hangup: F,
+) -> Result<()>
+where
+    F: FnOnce() -> Fut,
+    Fut: std::future::Future<Output = Result<()>>,
+{
+    let started = Instant::now();
+    let mut actions = VecDeque::from(actions);
+    let mut audio = VecDeque::<Sample>::new();
+    let mut events = VecDeque::<RtpOut>::new();
+    let mut sending = true;
+    let mut encoder = PcmuEncoder::new();
+    let detector = DtmfDetector::new();
+    let ssrc = rand::random::<u32>();
+    let mut sequence_number = rand::random::<u16>();
+    let mut timestamp = rand::random::<u32>();
+    let mut ticker = tokio::time::interval(PTIME);
+    let mut buf = vec![0u8; 2048];
+    loop {
+        select! {
+            _ = ticker.tick() => {
+                let elapsed = started.elapsed();
+                while actions.front().is_some_and(|(at, _)| *at <= elapsed) {
+                    let Some((_, action)) = actions.pop_front() else {
+                        break;
+                    };
+                    match action {
+                        SimAction::Play(samples) => audio.extend(samples),
+                        SimAction::Dtmf(digits) => events.extend(dtmf_packets(&digits)?),
+                        SimAction::DropRtp => sending = false,
+                        SimAction::Hangup => {
+                            report.duration = elapsed;
+                            return hangup().await;
+                        }
+                    }
+                }
+                if !sending {
+                    continue;
+                }
+                let out = match events.pop_front() {
+                    Some(out) => out,
+                    None => {
+                        let len = audio.len().min(SAMPLES_PER_PACKET);
+                        let mut samples = audio.drain(..len).collect::<PcmBuf>();
+                        samples.resize(SAMPLES_PER_PACKET, 0);
+                        RtpOut::Audio(encoder.encode(&samples))
+                    }
+                };
+                // the packets of an event share its start
+                let (payload_type, marker, payload, advance) = match out {
+                    RtpOut::Audio(payload) => {
+                        (PCMU_PAYLOAD_TYPE, false, payload, SAMPLES_PER_PACKET)
+                    }
+                    RtpOut::Event { payload, first, last } => {
+                        let advance = match last {
+                            true => SAMPLES_PER_PACKET * DTMF_DIGIT_PACKETS as usize,
+                            false => 0,
+                        };
+                        (DTMF_PAYLOAD_TYPE, first, payload, advance)
+                    }
+                    RtpOut::Silence => {
+                        timestamp = timestamp.wrapping_add(SAMPLES_PER_PACKET as u32);
+                        continue;
+                    }
+                };
+                let packet = RtpPacket {
+                    header: RtpHeader {
+                        version: 2,
+                        marker,
+                        payload_type,
+                        sequence_number,
+                        timestamp,
+                        ssrc,
+                        ..Default::default()
+                    },
+                    payload: Bytes::from(payload),
+                };
+                sequence_number = sequence_number.wrapping_add(1);
+                timestamp = timestamp.wrapping_add(advance as u32);
+                socket.send_to(&packet.marshal()?, remote).await?;
+                report.rtp_sent += 1;
+            }
+            r = socket.recv_from(&mut buf) => {
+                let (n, _) = r?;
+                let Ok(packet) = RtpPacket::unmarshal(&mut &buf[..n]) else {
+                    continue;
+                };
+                report.rtp_received += 1;
+                report.last_rtp = Some(started.elapsed());
+                if let Some(digit) =
+                    detector.detect_rtp(packet.header.payload_type, &packet.payload)
+                {
+                    report.digits.push_str(&digit);
+                }
+            }
+            state = states.recv() => match state {
+                Some(DialogState::Terminated(..)) | None => {
+                    report.duration = started.elapsed();
+                    report.hangup_by_peer = true;
+                    return Ok(());
+                }
+                Some(_) => {}
+            },
+        }
+    }
+}
+
+enum RtpOut {
+    Audio(Vec<u8>),
+    Event {
+        payload: Vec<u8>,
+        first: bool,
+        last: bool,
+    },
+    /// A packet time without RTP, between digits
+    Silence,
+}
+
+/// The telephone-events of the digits, each 100ms long with its end sent
+/// three times, and 60ms apart
+fn dtmf_packets(digits: &str) -> Result<Vec<RtpOut>> {
+    let mut packets = Vec::new();
+    for digit in digits.chars() {
+        let event = dtmf_event_code(&digit.to_string())
+            .ok_or_else(|| anyhow!("not a DTMF digit: {}", digit))?;
+        for i in 1..=DTMF_DIGIT_PACKETS + 2 {
+            let end = i >= DTMF_DIGIT_PACKETS;
+            let duration = i.min(DTMF_DIGIT_PACKETS) * SAMPLES_PER_PACKET as u16;
+            let mut payload = vec![event, 10, 0, 0];
+            if end {
+                payload[1] |= 0x80;
+            }
+            payload[2..4].copy_from_slice(&duration.to_be_bytes());
+            packets.push(RtpOut::Event {
+                payload,
+                first: i == 1,
+                last: i == DTMF_DIGIT_PACKETS + 2,
+            });
+        }
+        packets.extend((0..DTMF_GAP_PACKETS).map(|_| RtpOut::Silence));
+    }
+    Ok(packets)
+}
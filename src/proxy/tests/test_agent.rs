@@ -0,0 +1,136 @@
+use crate::config::QueueConfig;
+use crate::proxy::agent::{AgentAction, AgentSessions, AgentState};
+use crate::proxy::presence::PresenceState;
+use crate::proxy::queue::CallQueue;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::sync::Arc;
+
+fn start() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2025, 6, 2, 9, 0, 0).unwrap()
+}
+
+#[test]
+fn test_agent_session_states() {
+    let sessions = AgentSessions::new(vec!["lunch".to_string(), "training".to_string()]);
+    let mut events = sessions.subscribe();
+
+    assert!(sessions.pause("1001@example.com", None, start()).is_err());
+    let session = sessions.login("1001@example.com", start()).unwrap();
+    assert_eq!(session.state, AgentState::Available);
+
+    assert!(
+        sessions
+            .pause("1001@example.com", Some("smoke".to_string()), start())
+            .is_err()
+    );
+    let session = sessions
+        .pause("1001@example.com", Some("lunch".to_string()), start())
+        .unwrap();
+    assert_eq!(session.state, AgentState::Paused);
+    assert_eq!(session.reason.as_deref(), Some("lunch"));
+    assert!(!sessions.available("1001@example.com", false, start()));
+
+    sessions.resume("1001@example.com", start()).unwrap();
+    assert!(sessions.resume("1001@example.com", start()).is_err());
+    sessions
+        .apply(
+            AgentAction::Logout {
+                agent: "1001@example.com".to_string(),
+            },
+            start(),
+        )
+        .unwrap();
+    assert_eq!(
+        sessions.state("1001@example.com", start()),
+        Some(AgentState::LoggedOut)
+    );
+
+    let states: Vec<_> = std::iter::from_fn(|| events.try_recv().ok())
+        .map(|e| (e.state, e.reason))
+        .collect();
+    assert_eq!(
+        states,
+        vec![
+            (AgentState::Available, None),
+            (AgentState::Paused, Some("lunch".to_string())),
+            (AgentState::Available, None),
+            (AgentState::LoggedOut, None),
+        ]
+    );
+}
+
+#[test]
+fn test_agent_wrap_up() {
+    let sessions = AgentSessions::default();
+    // logged in by user, the queue reports calls by user@realm
+    sessions.login("1001", start()).unwrap();
+    sessions.call_started("1001@example.com", start());
+    assert_eq!(
+        sessions.state("1001@example.com", start()),
+        Some(AgentState::OnCall)
+    );
+
+    let ended = start() + Duration::seconds(90);
+    sessions.call_ended("1001@example.com", 30, ended);
+    let session = sessions.get("1001").unwrap();
+    assert_eq!(session.state, AgentState::WrapUp);
+    assert_eq!(session.calls, 1);
+    assert_eq!(session.talk_time, 90);
+    assert!(!sessions.available("1001@example.com", true, ended + Duration::seconds(10)));
+    assert!(sessions.available("1001@example.com", true, ended + Duration::seconds(30)));
+
+    sessions.expire(ended + Duration::seconds(30));
+    assert_eq!(sessions.get("1001").unwrap().state, AgentState::Available);
+
+    // a pause during the call skips the wrap-up
+    sessions.call_started("1001@example.com", ended);
+    sessions.pause("1001", None, ended).unwrap();
+    sessions.call_ended("1001@example.com", 30, ended + Duration::seconds(5));
+    assert_eq!(sessions.get("1001").unwrap().state, AgentState::Paused);
+
+    // agents without a session get one with their first call
+    sessions.call_started("1002@example.com", start());
+    sessions.call_ended("1002@example.com", 0, start());
+    assert_eq!(
+        sessions.get("1002@example.com").unwrap().state,
+        AgentState::Available
+    );
+}
+
+#[test]
+fn test_queue_agent_sessions() {
+    let presence = Arc::new(PresenceState::new());
+    let sessions = Arc::new(AgentSessions::default());
+    let queue = CallQueue::new(
+        "support".to_string(),
+        QueueConfig {
+            extension: "600".to_string(),
+            agents: vec!["1001".to_string(), "1002".to_string()],
+            music: None,
+            callback: None,
+            requires: None,
+            overflow: Vec::new(),
+            login: true,
+            wrap_up: 20,
+            sla: 20,
+        },
+    )
+    .unwrap()
+    .with_sessions(sessions.clone());
+    queue.join("a", "5550001", "example.com", None, start());
+    assert!(queue.claim("a", &presence, start()).is_none());
+
+    sessions.login("1001@example.com", start()).unwrap();
+    sessions.login("1002@example.com", start()).unwrap();
+    sessions.pause("1001@example.com", None, start()).unwrap();
+    let agent = queue.claim("a", &presence, start()).unwrap();
+    assert_eq!(agent.to_string(), "sip:1002@example.com");
+    queue.connected("a", &agent, start());
+    queue.finished(&agent, start() + Duration::seconds(60));
+
+    queue.join("b", "5550002", "example.com", None, start());
+    let now = start() + Duration::seconds(70);
+    assert!(queue.claim("b", &presence, now).is_none());
+    let now = start() + Duration::seconds(80);
+    assert_eq!(queue.claim("b", &presence, now).unwrap(), agent);
+}
@@ -0,0 +1,352 @@
+use super::common::{create_auth_request, create_test_request};
+use crate::call::user::SipUser;
+use crate::config::{AuthBackendConfig, LdapAuthConfig};
+use crate::proxy::auth::{AuthBackend, create_auth_backend};
+use crate::proxy::auth_ldap::LdapAuthBackend;
+use axum::{Json, Router, http::StatusCode, routing::post};
+use serde_json::Value;
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+fn alice() -> SipUser {
+    SipUser {
+        username: "alice".to_string(),
+        password: Some("secret".to_string()),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn test_static_auth_backend() {
+    let mut bob = alice();
+    bob.username = "bob".to_string();
+    bob.realm = Some("other.com".to_string());
+    let mut carol = alice();
+    carol.username = "carol".to_string();
+    carol.enabled = false;
+    let backend = create_auth_backend(&AuthBackendConfig::Static {
+        users: vec![alice(), bob, carol],
+    })
+    .await
+    .unwrap();
+
+    let request = create_auth_request(rsip::Method::Register, "alice", "example.com", "secret");
+    let user = backend.authenticate(&request).await.unwrap().unwrap();
+    assert_eq!(user.username, "alice");
+    assert_eq!(user.realm.as_deref(), Some("example.com"));
+
+    let request = create_auth_request(rsip::Method::Register, "alice", "example.com", "wrong");
+    assert!(backend.authenticate(&request).await.unwrap().is_none());
+    // bob only exists in other.com
+    let request = create_auth_request(rsip::Method::Register, "bob", "example.com", "secret");
+    assert!(backend.authenticate(&request).await.unwrap().is_none());
+    let request = create_auth_request(rsip::Method::Register, "bob", "other.com", "secret");
+    assert!(backend.authenticate(&request).await.unwrap().is_some());
+    let request = create_auth_request(rsip::Method::Register, "carol", "example.com", "secret");
+    assert!(backend.authenticate(&request).await.unwrap().is_none());
+    let request = create_test_request(rsip::Method::Register, "alice", None, "example.com", None);
+    assert!(backend.authenticate(&request).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_http_auth_backend() {
+    let app = Router::new().route(
+        "/auth",
+        post(async |Json(body): Json<Value>| {
+            if body["username"] == "alice"
+                && body["realm"] == "example.com"
+                && body["method"] == "INVITE"
+                && body["nonce"] == "demo_nonce"
+                && body["response"].as_str().is_some_and(|r| r.len() == 32)
+            {
+                Ok(Json(serde_json::json!({
+                    "username": "alice",
+                    "display_name": "Alice",
+                })))
+            } else {
+                Err(StatusCode::FORBIDDEN)
+            }
+        }),
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let backend = create_auth_backend(&AuthBackendConfig::Http {
+        url: format!("http://{}/auth", addr),
+        headers: None,
+        timeout: Some(2),
+    })
+    .await
+    .unwrap();
+    let request = create_auth_request(rsip::Method::Invite, "alice", "example.com", "secret");
+    let user = backend.authenticate(&request).await.unwrap().unwrap();
+    assert_eq!(user.display_name.as_deref(), Some("Alice"));
+    assert_eq!(user.realm.as_deref(), Some("example.com"));
+
+    let request = create_auth_request(rsip::Method::Invite, "bob", "example.com", "secret");
+    assert!(backend.authenticate(&request).await.unwrap().is_none());
+
+    let backend = create_auth_backend(&AuthBackendConfig::Http {
+        url: format!("http://{}/missing", addr),
+        headers: None,
+        timeout: Some(2),
+    })
+    .await
+    .unwrap();
+    let request = create_auth_request(rsip::Method::Invite, "alice", "example.com", "secret");
+    assert!(backend.authenticate(&request).await.unwrap().is_none());
+}
+
+const BIND_REQUEST: u8 = 0x60;
+const BIND_RESPONSE: u8 = 0x61;
+const SEARCH_REQUEST: u8 = 0x63;
+const SEARCH_RESULT_ENTRY: u8 = 0x64;
+const SEARCH_RESULT_DONE: u8 = 0x65;
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const ENUMERATED: u8 = 0x0a;
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const RESULT_SUCCESS: i64 = 0;
+const RESULT_INVALID_CREDENTIALS: i64 = 49;
+
+/// Just enough BER for the directory the tests talk to, RFC 4511
+#[derive(Debug, Clone, PartialEq)]
+struct BerElement {
+    tag: u8,
+    value: Vec<u8>,
+}
+
+impl BerElement {
+    fn new(tag: u8, value: impl Into<Vec<u8>>) -> Self {
+        Self {
+            tag,
+            value: value.into(),
+        }
+    }
+
+    fn integer(tag: u8, n: i64) -> Self {
+        let bytes = n.to_be_bytes();
+        let start = bytes[..7]
+            .iter()
+            .zip(&bytes[1..])
+            .take_while(|(b, next)| {
+                (**b == 0 && **next & 0x80 == 0) || (**b == 0xff && **next & 0x80 != 0)
+            })
+            .count();
+        Self::new(tag, &bytes[start..])
+    }
+
+    fn string(value: &str) -> Self {
+        Self::new(OCTET_STRING, value.as_bytes())
+    }
+
+    fn constructed(tag: u8, children: &[BerElement]) -> Self {
+        Self::new(
+            tag,
+            children
+                .iter()
+                .flat_map(|child| child.encode())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = vec![self.tag];
+        let len = self.value.len();
+        if len < 0x80 {
+            data.push(len as u8);
+        } else {
+            let bytes = len.to_be_bytes();
+            let skip = bytes.iter().take_while(|b| **b == 0).count();
+            data.push(0x80 | (bytes.len() - skip) as u8);
+            data.extend_from_slice(&bytes[skip..]);
+        }
+        data.extend_from_slice(&self.value);
+        data
+    }
+
+    fn children(&self) -> Vec<BerElement> {
+        let mut children = Vec::new();
+        let mut data = self.value.as_slice();
+        while !data.is_empty() {
+            let (len, header) = match data[1] {
+                n if n < 0x80 => (n as usize, 2),
+                n => {
+                    let count = (n & 0x7f) as usize;
+                    let len = data[2..2 + count]
+                        .iter()
+                        .fold(0usize, |len, b| (len << 8) | *b as usize);
+                    (len, 2 + count)
+                }
+            };
+            children.push(Self::new(data[0], &data[header..header + len]));
+            data = &data[header + len..];
+        }
+        children
+    }
+
+    fn as_integer(&self) -> i64 {
+        let sign = if self.value[0] & 0x80 != 0 { -1i64 } else { 0 };
+        self.value.iter().fold(sign, |n, b| (n << 8) | *b as i64)
+    }
+
+    fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.value).unwrap()
+    }
+}
+
+/// Reads one whole LDAP message
+async fn read_element(stream: &mut TcpStream) -> std::io::Result<BerElement> {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await?;
+    let len = match header[1] {
+        n if n < 0x80 => n as usize,
+        n => {
+            let mut bytes = [0u8; 8];
+            let count = (n & 0x7f) as usize;
+            stream.read_exact(&mut bytes[8 - count..]).await?;
+            u64::from_be_bytes(bytes) as usize
+        }
+    };
+    let mut value = vec![0u8; len];
+    stream.read_exact(&mut value).await?;
+    Ok(BerElement::new(header[0], value))
+}
+
+fn ldap_result(tag: u8, code: i64) -> BerElement {
+    BerElement::constructed(
+        tag,
+        &[
+            BerElement::integer(ENUMERATED, code),
+            BerElement::string(""),
+            BerElement::string(""),
+        ],
+    )
+}
+
+/// Binds `cn=admin,dc=example,dc=com` with `admin`, and knows alice. Counts
+/// the connections it accepts.
+async fn ldap_server() -> (String, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let accepted = connections.clone();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            accepted.fetch_add(1, Ordering::Relaxed);
+            tokio::spawn(async move {
+                while let Ok(message) = read_element(&mut stream).await {
+                    let fields = message.children();
+                    let id = fields[0].as_integer();
+                    let op = &fields[1];
+                    let answers = match op.tag {
+                        BIND_REQUEST => {
+                            let fields = op.children();
+                            let ok = fields[1].as_str() == "cn=admin,dc=example,dc=com"
+                                && fields[2].value == b"admin";
+                            let code = if ok {
+                                RESULT_SUCCESS
+                            } else {
+                                RESULT_INVALID_CREDENTIALS
+                            };
+                            vec![ldap_result(BIND_RESPONSE, code)]
+                        }
+                        SEARCH_REQUEST => {
+                            let fields = op.children();
+                            let filter = fields[6].children();
+                            let mut answers = vec![];
+                            if filter[0].as_str() == "uid" && filter[1].as_str() == "alice" {
+                                let attribute = |name: &str, value: &str| {
+                                    BerElement::constructed(
+                                        SEQUENCE,
+                                        &[
+                                            BerElement::string(name),
+                                            BerElement::constructed(
+                                                SET,
+                                                &[BerElement::string(value)],
+                                            ),
+                                        ],
+                                    )
+                                };
+                                answers.push(BerElement::constructed(
+                                    SEARCH_RESULT_ENTRY,
+                                    &[
+                                        BerElement::string("uid=alice,ou=people,dc=example,dc=com"),
+                                        BerElement::constructed(
+                                            SEQUENCE,
+                                            &[
+                                                attribute("sipPassword", "secret"),
+                                                attribute("cn", "Alice Liddell"),
+                                            ],
+                                        ),
+                                    ],
+                                ));
+                            }
+                            answers.push(ldap_result(SEARCH_RESULT_DONE, RESULT_SUCCESS));
+                            answers
+                        }
+                        _ => break,
+                    };
+                    for answer in answers {
+                        let message = BerElement::constructed(
+                            SEQUENCE,
+                            &[BerElement::integer(INTEGER, id), answer],
+                        );
+                        stream.write_all(&message.encode()).await.unwrap();
+                    }
+                }
+            });
+        }
+    });
+    (addr, connections)
+}
+
+fn ldap_config(addr: &str, bind_password: &str) -> LdapAuthConfig {
+    LdapAuthConfig {
+        url: format!("ldap://{}", addr),
+        starttls: None,
+        bind_dn: Some("cn=admin,dc=example,dc=com".to_string()),
+        bind_password: Some(bind_password.to_string()),
+        base_dn: "ou=people,dc=example,dc=com".to_string(),
+        username_attribute: None,
+        password_attribute: None,
+        display_name_attribute: None,
+        timeout: Some(2),
+    }
+}
+
+#[tokio::test]
+async fn test_ldap_auth_backend() {
+    let (addr, connections) = ldap_server().await;
+    let backend = LdapAuthBackend::new(ldap_config(&addr, "admin")).unwrap();
+    let user = backend.lookup("alice").await.unwrap().unwrap();
+    assert_eq!(user.password.as_deref(), Some("secret"));
+    assert_eq!(user.display_name.as_deref(), Some("Alice Liddell"));
+    assert!(backend.lookup("bob").await.unwrap().is_none());
+
+    let request = create_auth_request(rsip::Method::Register, "alice", "example.com", "secret");
+    let user = backend.authenticate(&request).await.unwrap().unwrap();
+    assert_eq!(user.username, "alice");
+    assert_eq!(user.realm.as_deref(), Some("example.com"));
+    let request = create_auth_request(rsip::Method::Register, "alice", "example.com", "wrong");
+    assert!(backend.authenticate(&request).await.unwrap().is_none());
+    // the lookups share one bound connection
+    assert_eq!(connections.load(Ordering::Relaxed), 1);
+
+    let backend = LdapAuthBackend::new(ldap_config(&addr, "wrong")).unwrap();
+    let err = backend.lookup("alice").await.unwrap_err();
+    assert!(err.to_string().contains("49"), "{}", err);
+
+    let mut config = ldap_config("", "admin");
+    config.url = "ldapi://example.com".to_string();
+    assert!(LdapAuthBackend::new(config).is_err());
+}
@@ -0,0 +1,276 @@
+use crate::config::CampaignConfig;
+use crate::proxy::campaign::{
+    AnswerHistory, CallingHours, Campaign, CampaignRequest, CampaignStatus, Disposition, Pacing,
+    normalize_number, on_dnc_list, parse_csv,
+};
+use crate::proxy::presence::PresenceState;
+use chrono::{DateTime, TimeZone, Utc};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+    // 2025-06-02 is a Monday
+    Utc.with_ymd_and_hms(2025, 6, 2, hour, minute, 0).unwrap()
+}
+
+fn campaign(csv: &str, request: CampaignRequest, dnc: &[&str]) -> Campaign {
+    let dnc: HashSet<String> = dnc.iter().filter_map(|n| normalize_number(n)).collect();
+    Campaign::new(
+        "test".to_string(),
+        CampaignRequest {
+            csv: csv.to_string(),
+            agents: vec![
+                "sip:1001@example.com".to_string(),
+                "sip:1002@example.com".to_string(),
+            ],
+            ..request
+        },
+        &CampaignConfig::default(),
+        &dnc,
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_parse_csv() {
+    let rows = parse_csv(
+        "number,name\r\n+1 415 555 0100,\"Doe, \"\"JD\"\" John\"\n\n4155550101,\"two\nlines\"",
+    );
+    assert_eq!(
+        rows,
+        vec![
+            vec!["number".to_string(), "name".to_string()],
+            vec![
+                "+1 415 555 0100".to_string(),
+                "Doe, \"JD\" John".to_string()
+            ],
+            vec!["4155550101".to_string(), "two\nlines".to_string()],
+        ]
+    );
+    assert_eq!(
+        normalize_number(" +1 (415) 555-0100 "),
+        Some("+14155550100".to_string())
+    );
+    assert_eq!(normalize_number("call me"), None);
+    assert_eq!(normalize_number("12"), None);
+
+    let dnc = HashSet::from(["+14155550100".to_string()]);
+    assert!(on_dnc_list(&dnc, "4155550100"));
+    assert!(on_dnc_list(&dnc, "+14155550100"));
+    assert!(!on_dnc_list(&dnc, "4155550101"));
+    assert!(!on_dnc_list(
+        &HashSet::from(["100".to_string()]),
+        "4155550100"
+    ));
+}
+
+#[test]
+fn test_campaign_import() {
+    let campaign = campaign(
+        "Number,Name,utc_offset\n4155550100,Ann,\n4155550101,Bob,-07:00\nnot a number,Cy,\n",
+        CampaignRequest::default(),
+        &["+1 415 555 0100"],
+    );
+    let report = campaign.report(true);
+    assert_eq!(report.status, CampaignStatus::Running);
+    assert_eq!(report.dispositions.get(&Disposition::DoNotCall), Some(&1));
+    assert_eq!(report.dispositions.get(&Disposition::Invalid), Some(&1));
+    let targets = report.targets.unwrap();
+    assert_eq!(targets.len(), 3);
+    assert_eq!(targets[1].number, "4155550101");
+    assert_eq!(targets[1].variables.get("name"), Some(&"Bob".to_string()));
+    assert!(!targets[1].variables.contains_key("utc_offset"));
+
+    let missing = Campaign::new(
+        "test".to_string(),
+        CampaignRequest {
+            csv: "name\nAnn".to_string(),
+            agents: vec!["sip:1001@example.com".to_string()],
+            ..Default::default()
+        },
+        &CampaignConfig::default(),
+        &HashSet::new(),
+    );
+    assert!(missing.is_err());
+}
+
+#[test]
+fn test_campaign_calling_hours() {
+    let request = CampaignRequest {
+        calling_hours: Some(CallingHours {
+            start: "09:00".to_string(),
+            end: "21:00".to_string(),
+            days: vec!["mon".to_string()],
+        }),
+        utc_offset: Some("+08:00".to_string()),
+        ..Default::default()
+    };
+    // the second target is 15 hours behind the first
+    let campaign = campaign(
+        "number,utc_offset\n4155550100,\n4155550101,-07:00\n",
+        request,
+        &[],
+    );
+    let presence = PresenceState::new();
+    // 08:30 UTC is 16:30 on Monday at +08:00, 01:30 at -07:00
+    let dials = campaign.next_targets(&presence, at(8, 30)).unwrap();
+    assert_eq!(dials.len(), 1);
+    assert_eq!(dials[0].number, "4155550100");
+    // 00:30 UTC is 08:30 at +08:00, too early
+    campaign.finish(0, Disposition::Connected, at(8, 40));
+    assert!(
+        campaign
+            .next_targets(&presence, at(0, 30))
+            .unwrap()
+            .is_empty()
+    );
+    // 16:30 UTC on Monday is 09:30 at -07:00
+    let dials = campaign.next_targets(&presence, at(16, 30)).unwrap();
+    assert_eq!(dials.len(), 1);
+    assert_eq!(dials[0].number, "4155550101");
+}
+
+#[test]
+fn test_campaign_pacing() {
+    let csv = format!(
+        "number\n{}",
+        (0..10)
+            .map(|i| format!("415555010{}", i))
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+    let request = CampaignRequest {
+        dial_ratio: Some(1.5),
+        max_attempts: Some(2),
+        retry_interval: Some(120),
+        ..Default::default()
+    };
+    let campaign = campaign(&csv, request, &[]);
+    let presence = Arc::new(PresenceState::new());
+
+    // two idle agents, dialled ahead at 1.5 calls each
+    let dials = campaign.next_targets(&presence, at(10, 0)).unwrap();
+    assert_eq!(dials.len(), 3);
+    assert!(
+        campaign
+            .next_targets(&presence, at(10, 0))
+            .unwrap()
+            .is_empty()
+    );
+    let agent = rsip::Uri::try_from("sip:1001@example.com").unwrap();
+    campaign.connected(dials[0].index, &agent, at(10, 0));
+    let _busy = presence.enter_call(vec!["1001@example.com".to_string()]);
+    campaign.finish(dials[1].index, Disposition::Abandoned, at(10, 1));
+    campaign.finish(dials[2].index, Disposition::Busy, at(10, 1));
+
+    // half the answered calls were abandoned, one call per idle agent
+    let report = campaign.report(false);
+    assert_eq!(report.abandon_rate, 0.5);
+    assert_eq!(report.in_call, 1);
+    assert_eq!(report.dialing, 0);
+    let dials = campaign.next_targets(&presence, at(10, 2)).unwrap();
+    assert_eq!(dials.len(), 1);
+    // the busy target waits for its retry interval
+    assert_eq!(dials[0].number, "4155550103");
+    campaign.finish(dials[0].index, Disposition::NoAnswer, at(10, 2));
+    let dials = campaign.next_targets(&presence, at(10, 3)).unwrap();
+    assert_eq!(dials[0].number, "4155550102");
+
+    campaign.stop();
+    let report = campaign.report(true);
+    assert_eq!(report.status, CampaignStatus::Stopped);
+    assert!(campaign.next_targets(&presence, at(10, 5)).is_none());
+    assert_eq!(report.dispositions.get(&Disposition::Cancelled), Some(&7));
+}
+
+#[test]
+fn test_campaign_completes() {
+    let campaign = campaign("number\n4155550100\n", CampaignRequest::default(), &[]);
+    let presence = PresenceState::new();
+    let dials = campaign.next_targets(&presence, at(10, 0)).unwrap();
+    assert_eq!(dials.len(), 1);
+    // dialled once only by default
+    campaign.finish(0, Disposition::Busy, at(10, 1));
+    assert!(campaign.next_targets(&presence, at(11, 0)).is_none());
+    assert_eq!(campaign.report(false).status, CampaignStatus::Completed);
+}
+
+#[test]
+fn test_campaign_predictive() {
+    let csv = format!(
+        "number\n{}",
+        (0..40)
+            .map(|i| format!("41555501{:02}", i))
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+    let request = CampaignRequest {
+        pacing: Some(Pacing::Predictive),
+        max_concurrent: Some(100),
+        max_abandon_rate: Some(0.5),
+        ..Default::default()
+    };
+    let presence = Arc::new(PresenceState::new());
+
+    // half the calls were answered before, two calls per idle agent
+    let fresh = campaign(&csv, request.clone(), &[]);
+    let dials = fresh.next_targets(&presence, at(10, 0)).unwrap();
+    assert_eq!(dials.len(), 4);
+    assert_eq!(fresh.report(false).answer_rate, 0.5);
+
+    // a history of few answers dials ahead up to the dial ratio
+    let history = Arc::new(AnswerHistory::default());
+    for answered in [true, false, false, false, false] {
+        history.record(answered);
+    }
+    let sparse = Campaign::new(
+        "test".to_string(),
+        CampaignRequest {
+            csv: csv.clone(),
+            agents: vec!["sip:1001@example.com".to_string()],
+            ..request.clone()
+        },
+        &CampaignConfig::default(),
+        &HashSet::new(),
+    )
+    .unwrap()
+    .with_history(history);
+    assert_eq!(sparse.next_targets(&presence, at(10, 0)).unwrap().len(), 3);
+
+    // agents about to finish a call count as available
+    let timed = campaign(&csv, request, &[]);
+    let agent = rsip::Uri::try_from("sip:1001@example.com").unwrap();
+    let _busy = presence.enter_call(vec![
+        "1001@example.com".to_string(),
+        "1002@example.com".to_string(),
+    ]);
+    let mut dials = timed.next_targets(&presence, at(10, 0)).unwrap();
+    assert!(dials.is_empty());
+    // three calls of 60s, each put through 10s after dialling
+    for i in 0..3 {
+        let start = at(10, i * 2);
+        dials = timed.next_targets(&PresenceState::new(), start).unwrap();
+        let index = dials[0].index;
+        timed.connected(index, &agent, start + chrono::Duration::seconds(10));
+        timed.finish(
+            index,
+            Disposition::Connected,
+            start + chrono::Duration::seconds(70),
+        );
+        for dial in &dials[1..] {
+            timed.finish(dial.index, Disposition::NoAnswer, start);
+        }
+    }
+    let dials = timed
+        .next_targets(&PresenceState::new(), at(11, 0))
+        .unwrap();
+    let index = dials[0].index;
+    timed.connected(index, &agent, at(11, 0));
+    for dial in &dials[1..] {
+        timed.finish(dial.index, Disposition::NoAnswer, at(11, 0));
+    }
+    // both agents busy, one of them 55s into a call
+    assert!(timed.next_targets(&presence, at(11, 0)).unwrap().is_empty());
+    let dials = timed.next_targets(&presence, at(11, 0) + chrono::Duration::seconds(55));
+    assert!(!dials.unwrap().is_empty());
+}
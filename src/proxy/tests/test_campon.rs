@@ -0,0 +1,121 @@
+use super::common::{create_test_request, create_test_server, create_transaction};
+use crate::call::TransactionCookie;
+use crate::proxy::campon::{CampOn, CampOnModule, take_due};
+use crate::proxy::presence::{PresenceChange, PresenceState};
+use crate::proxy::{ProxyAction, ProxyModule};
+use rsip::Header;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+fn invite(dialled: &str) -> rsip::Request {
+    let mut request = create_test_request(rsip::Method::Invite, "alice", None, "example.com", None);
+    request.headers.retain(|h| !matches!(h, Header::To(_)));
+    request
+        .headers
+        .push(Header::To(format!("<sip:{}@example.com>", dialled).into()));
+    request
+}
+
+async fn begin(module: &CampOnModule, request: rsip::Request) -> ProxyAction {
+    let (mut tx, _) = create_transaction(request).await;
+    module
+        .on_transaction_begin(
+            CancellationToken::new(),
+            &mut tx,
+            TransactionCookie::default(),
+        )
+        .await
+        .unwrap()
+}
+
+#[test]
+fn test_presence_state() {
+    let presence = Arc::new(PresenceState::new());
+    let mut changes = presence.subscribe();
+    let first = presence.enter_call(vec!["Bob@example.com".to_string()]);
+    let second = presence.enter_call(vec!["bob@example.com".to_string()]);
+    assert!(presence.is_busy("bob@example.com"));
+
+    drop(first);
+    assert!(presence.is_busy("bob@example.com"), "still on another call");
+    drop(second);
+    assert!(!presence.is_busy("bob@example.com"));
+
+    assert_eq!(
+        changes.try_recv().unwrap(),
+        PresenceChange {
+            aor: "bob@example.com".to_string(),
+            busy: true
+        }
+    );
+    assert!(!changes.try_recv().unwrap().busy);
+    assert!(changes.try_recv().is_err());
+}
+
+#[test]
+fn test_take_due() {
+    let presence = Arc::new(PresenceState::new());
+    let now = Instant::now();
+    let camp_on = |callee: &str, expires_at| CampOn {
+        caller: "alice@example.com".to_string(),
+        callee: callee.to_string(),
+        expires_at,
+    };
+    let mut pending = vec![
+        camp_on("bob@example.com", now + Duration::from_secs(60)),
+        camp_on("carol@example.com", now + Duration::from_secs(60)),
+        camp_on("dave@example.com", now - Duration::from_secs(1)),
+    ];
+
+    let busy = presence.enter_call(vec!["bob@example.com".to_string()]);
+    let due = take_due(&mut pending, &presence, now);
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].callee, "carol@example.com");
+    assert_eq!(pending.len(), 1, "expired request dropped, busy one kept");
+
+    drop(busy);
+    assert_eq!(take_due(&mut pending, &presence, now).len(), 1);
+    assert!(pending.is_empty());
+}
+
+#[tokio::test]
+async fn test_feature_code_queues_callback() {
+    let (server_inner, config) = create_test_server().await;
+    let module = CampOnModule::new(server_inner.clone(), config);
+
+    // nothing busy was dialled yet
+    assert!(matches!(
+        begin(&module, invite("*6")).await,
+        ProxyAction::Abort
+    ));
+    assert!(module.pending().is_empty());
+
+    let _busy = server_inner.presence.enter_call(vec![
+        "bob@example.com".to_string(),
+        "carol@example.com".to_string(),
+    ]);
+    assert!(matches!(
+        begin(&module, invite("bob")).await,
+        ProxyAction::Continue
+    ));
+    assert!(matches!(
+        begin(&module, invite("*6")).await,
+        ProxyAction::Abort
+    ));
+    assert!(matches!(
+        begin(&module, invite("*6carol")).await,
+        ProxyAction::Abort
+    ));
+
+    let mut callees = module
+        .pending()
+        .into_iter()
+        .map(|c| {
+            assert_eq!(c.caller, "alice@example.com");
+            c.callee
+        })
+        .collect::<Vec<_>>();
+    callees.sort();
+    assert_eq!(callees, vec!["bob@example.com", "carol@example.com"]);
+}
@@ -0,0 +1,93 @@
+use super::common::{create_test_request, create_test_server, create_transaction};
+use crate::call::TransactionCookie;
+use crate::proxy::clicktodial::{
+    ClickToDial, ClickToDialModule, click_to_dial, parse_target, refer_to,
+};
+use crate::proxy::{ProxyAction, ProxyModule};
+use rsip::Header;
+use tokio_util::sync::CancellationToken;
+
+#[test]
+fn test_refer_to_and_target() {
+    let mut request = create_test_request(rsip::Method::Refer, "alice", None, "example.com", None);
+    request.headers.push(Header::Other(
+        "Refer-To".to_string(),
+        "\"Bob\" <sip:bob@example.com;user=phone>;method=INVITE".to_string(),
+    ));
+    assert_eq!(
+        refer_to(&request).as_deref(),
+        Some("sip:bob@example.com;user=phone")
+    );
+
+    let user = rsip::Uri::try_from("sip:alice@example.com").unwrap();
+    assert_eq!(
+        parse_target("1002", &user).unwrap().to_string(),
+        "sip:1002@example.com"
+    );
+    assert_eq!(
+        parse_target("+15551234@carrier.net", &user)
+            .unwrap()
+            .to_string(),
+        "sip:+15551234@carrier.net"
+    );
+}
+
+#[tokio::test]
+async fn test_click_to_dial_requires_registered_local_user() {
+    let (server_inner, config) = create_test_server().await;
+    let request = |user: &str| ClickToDial {
+        user: user.to_string(),
+        target: "1002".to_string(),
+        caller_id: None,
+        variables: None,
+    };
+
+    let (_, status) = click_to_dial(
+        server_inner.clone(),
+        config.clone(),
+        request("alice@example.com"),
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(status, rsip::StatusCode::TemporarilyUnavailable);
+
+    let (_, status) = click_to_dial(server_inner, config, request("sip:carol@elsewhere.net"))
+        .await
+        .unwrap_err();
+    assert_eq!(status, rsip::StatusCode::Forbidden);
+}
+
+#[tokio::test]
+async fn test_module_ignores_in_dialog_refer() {
+    let (server_inner, config) = create_test_server().await;
+    let module = ClickToDialModule::new(server_inner, config);
+
+    let mut request = create_test_request(rsip::Method::Refer, "alice", None, "example.com", None);
+    request.headers.retain(|h| !matches!(h, Header::To(_)));
+    request
+        .headers
+        .push(Header::To("<sip:alice@example.com>;tag=abc".into()));
+    let (mut tx, _) = create_transaction(request).await;
+    let result = module
+        .on_transaction_begin(
+            CancellationToken::new(),
+            &mut tx,
+            TransactionCookie::default(),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(result, ProxyAction::Continue));
+
+    // out of dialog without Refer-To is answered, not passed on
+    let request = create_test_request(rsip::Method::Refer, "alice", None, "example.com", None);
+    let (mut tx, _) = create_transaction(request).await;
+    let result = module
+        .on_transaction_begin(
+            CancellationToken::new(),
+            &mut tx,
+            TransactionCookie::default(),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(result, ProxyAction::Abort));
+}
@@ -0,0 +1,631 @@
+use super::common::create_udp_test_server;
+use crate::call::{
+    bypass::{DirectMedia, MediaLeg, is_trusted},
+    dns::SipResolver,
+    sip::{Invitation, glare_delay},
+};
+use crate::{
+    config::{DnsConfig, ProxyConfig},
+    media::negotiate::SdpCapabilities,
+    proxy::acl::IpNetwork,
+};
+use async_trait::async_trait;
+use rsip_dns::{
+    DnsClient, SrvDomain,
+    records::{AddrRecord, NaptrRecord, SrvEntry, SrvRecord},
+};
+use rsipstack::dialog::{dialog::DialogState, invitation::InviteOption};
+use rsipstack::transport::SipAddr;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{net::UdpSocket, sync::mpsc, time::timeout};
+
+/// A far end behind a forking proxy, whose forks answer as the test says
+struct ForkingPeer {
+    socket: UdpSocket,
+    addr: SocketAddr,
+}
+
+impl ForkingPeer {
+    async fn bind() -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        Self { socket, addr }
+    }
+
+    fn option(&self) -> InviteOption {
+        InviteOption {
+            caller: "sip:alice@127.0.0.1".try_into().unwrap(),
+            callee: format!("sip:bob@{}", self.addr)
+                .as_str()
+                .try_into()
+                .unwrap(),
+            destination: Some(SipAddr {
+                r#type: Some(rsip::Transport::Udp),
+                addr: self.addr.into(),
+            }),
+            content_type: Some("application/sdp".to_string()),
+            offer: Some(b"v=0\r\n".to_vec()),
+            contact: "sip:alice@127.0.0.1".try_into().unwrap(),
+            credential: None,
+            headers: None,
+        }
+    }
+
+    async fn recv(&self) -> (String, SocketAddr) {
+        let mut buf = vec![0u8; 4096];
+        let (n, from) = timeout(Duration::from_secs(5), self.socket.recv_from(&mut buf))
+            .await
+            .expect("no request from the proxy")
+            .unwrap();
+        (String::from_utf8_lossy(&buf[..n]).to_string(), from)
+    }
+
+    async fn reply(&self, request: &str, to: SocketAddr, status: &str, tag: &str) {
+        self.reply_with(request, to, status, tag, "").await
+    }
+
+    async fn reply_with(&self, request: &str, to: SocketAddr, status: &str, tag: &str, sdp: &str) {
+        let header = |name: &str| {
+            request
+                .lines()
+                .find(|l| l.starts_with(name))
+                .unwrap()
+                .to_string()
+        };
+        let to_header = match tag {
+            "" => header("To:"),
+            tag => format!("{};tag={}", header("To:"), tag),
+        };
+        let response = format!(
+            "SIP/2.0 {}\r\n{}\r\n{}\r\n{}\r\n{}\r\n{}\r\nContact: <sip:bob-{}@{}>\r\n{}Content-Length: {}\r\n\r\n{}",
+            status,
+            header("Via:"),
+            header("From:"),
+            to_header,
+            header("Call-ID:"),
+            header("CSeq:"),
+            tag,
+            self.addr,
+            match sdp {
+                "" => "",
+                _ => "Content-Type: application/sdp\r\n",
+            },
+            sdp.len(),
+            sdp,
+        );
+        self.socket.send_to(response.as_bytes(), to).await.unwrap();
+    }
+}
+
+fn to_tag(request: &str) -> String {
+    let to = request.lines().find(|l| l.starts_with("To:")).unwrap();
+    to.split(";tag=").nth(1).unwrap_or_default().to_string()
+}
+
+#[tokio::test]
+async fn test_forked_2xx_released() {
+    let server = create_udp_test_server(ProxyConfig::default(), &[]).await;
+    let inner = server.get_inner();
+    let invitation =
+        Invitation::new(inner.dialog_layer.clone()).with_retransmitter(inner.retransmitter.clone());
+    let peer = ForkingPeer::bind().await;
+
+    let (sender, _receiver) = mpsc::unbounded_channel();
+    let invite = tokio::spawn({
+        let invitation = invitation.clone();
+        let option = peer.option();
+        async move { invitation.invite(option, sender).await }
+    });
+    let (request, from) = peer.recv().await;
+    assert!(request.starts_with("INVITE "));
+    peer.reply(&request, from, "200 OK", "first").await;
+    let (ack, _) = peer.recv().await;
+    assert!(ack.starts_with("ACK sip:bob-first@"));
+    let (dialog_id, _) = invite.await.unwrap().expect("first 2xx accepted");
+    assert_eq!(dialog_id.to_tag, "first");
+
+    // the accepted 2xx is ACKed again when retransmitted
+    peer.reply(&request, from, "200 OK", "first").await;
+    let (ack, _) = peer.recv().await;
+    assert!(ack.starts_with("ACK sip:bob-first@"));
+
+    // a second fork gets an ACK, then a BYE
+    peer.reply(&request, from, "200 OK", "second").await;
+    let (ack, _) = peer.recv().await;
+    assert!(ack.starts_with("ACK sip:bob-second@"));
+    assert_eq!(to_tag(&ack), "second");
+    let (bye, from) = peer.recv().await;
+    assert!(bye.starts_with("BYE sip:bob-second@"));
+    assert_eq!(to_tag(&bye), "second");
+    assert!(bye.contains(" BYE\r\n"));
+    peer.reply(&bye, from, "200 OK", "").await;
+
+    assert!(inner.dialog_layer.get_dialog(&dialog_id).is_some());
+}
+
+#[tokio::test]
+async fn test_2xx_crossing_cancel() {
+    let server = create_udp_test_server(ProxyConfig::default(), &[]).await;
+    let inner = server.get_inner();
+    let invitation =
+        Invitation::new(inner.dialog_layer.clone()).with_retransmitter(inner.retransmitter.clone());
+    let peer = ForkingPeer::bind().await;
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let invite = tokio::spawn({
+        let invitation = invitation.clone();
+        let option = peer.option();
+        async move { invitation.invite(option, sender).await }
+    });
+    let (request, from) = peer.recv().await;
+    peer.reply(&request, from, "180 Ringing", "ring").await;
+    let dialog_id = loop {
+        match receiver.recv().await.unwrap() {
+            DialogState::Calling(id) => break id,
+            _ => continue,
+        }
+    };
+
+    // the caller hangs up, but the callee answers before the CANCEL arrives.
+    // Hanging up waits for the response to the CANCEL
+    tokio::spawn(async move { invitation.hangup(dialog_id, None, None).await });
+    let (cancel, cancel_from) = peer.recv().await;
+    assert!(cancel.starts_with("CANCEL "));
+    peer.reply(&request, from, "200 OK", "ring").await;
+    peer.reply(
+        &cancel,
+        cancel_from,
+        "481 Call/Transaction Does Not Exist",
+        "",
+    )
+    .await;
+
+    let mut bye = None;
+    for _ in 0..3 {
+        let (request, from) = peer.recv().await;
+        if request.starts_with("BYE ") {
+            peer.reply(&request, from, "200 OK", "").await;
+            bye = Some(request);
+            break;
+        }
+    }
+    assert_eq!(to_tag(&bye.expect("the answered call is hung up")), "ring");
+    match invite.await.unwrap() {
+        Err(rsipstack::Error::DialogError(_, _, code)) => {
+            assert_eq!(code, rsip::StatusCode::RequestTerminated)
+        }
+        other => panic!("unexpected invite result: {:?}", other.map(|r| r.0)),
+    }
+}
+
+/// `peers.test`, the first port tried before the second
+struct FailoverDns(u16, u16);
+
+#[async_trait]
+impl DnsClient for FailoverDns {
+    async fn naptr_lookup(&self, _domain: rsip::Domain) -> Option<NaptrRecord> {
+        None
+    }
+
+    async fn srv_lookup(&self, domain: SrvDomain) -> Option<SrvRecord> {
+        let entry = |priority, port: u16| SrvEntry {
+            priority,
+            weight: 0,
+            port: port.into(),
+            target: "peers.test".into(),
+        };
+        Some(SrvRecord {
+            entries: vec![entry(10, self.0), entry(20, self.1)],
+            domain,
+        })
+    }
+
+    async fn ip_lookup(&self, domain: rsip::Domain) -> Result<AddrRecord, rsip::Error> {
+        Ok(AddrRecord {
+            domain,
+            ip_addrs: vec![IpAddr::from([127, 0, 0, 1])],
+        })
+    }
+}
+
+#[tokio::test]
+async fn test_failover_stops_when_cancelled() {
+    let server = create_udp_test_server(ProxyConfig::default(), &[]).await;
+    let inner = server.get_inner();
+    let first = ForkingPeer::bind().await;
+    let second = ForkingPeer::bind().await;
+    let resolver = SipResolver::with_client(
+        FailoverDns(first.addr.port(), second.addr.port()),
+        &DnsConfig::default(),
+    );
+    let invitation = Invitation::new(inner.dialog_layer.clone()).with_resolver(Arc::new(resolver));
+
+    let mut option = first.option();
+    option.destination = Some(SipAddr {
+        r#type: Some(rsip::Transport::Udp),
+        addr: rsip::HostWithPort {
+            host: rsip::Host::Domain("peers.test".into()),
+            port: None,
+        },
+    });
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let invite = tokio::spawn({
+        let invitation = invitation.clone();
+        async move { invitation.invite(option, sender).await }
+    });
+    let (request, from) = first.recv().await;
+    first.reply(&request, from, "180 Ringing", "ring").await;
+    let dialog_id = loop {
+        match receiver.recv().await.unwrap() {
+            DialogState::Calling(id) => break id,
+            _ => continue,
+        }
+    };
+
+    // hung up while the first target is pending, which then answers 503
+    tokio::spawn(async move { invitation.hangup(dialog_id, None, None).await });
+    let (cancel, cancel_from) = first.recv().await;
+    assert!(cancel.starts_with("CANCEL "));
+    first.reply(&cancel, cancel_from, "200 OK", "").await;
+    first
+        .reply(&request, from, "503 Service Unavailable", "ring")
+        .await;
+
+    match invite.await.unwrap() {
+        Err(rsipstack::Error::DialogError(_, _, code)) => {
+            assert_eq!(code, rsip::StatusCode::RequestTerminated)
+        }
+        other => panic!("unexpected invite result: {:?}", other.map(|r| r.0)),
+    }
+    // the second target is never tried
+    let mut buf = vec![0u8; 4096];
+    assert!(
+        timeout(
+            Duration::from_millis(500),
+            second.socket.recv_from(&mut buf)
+        )
+        .await
+        .is_err()
+    );
+}
+
+#[test]
+fn test_glare_delay() {
+    for _ in 0..100 {
+        let owner = glare_delay(true);
+        assert!(owner >= Duration::from_millis(2100) && owner <= Duration::from_millis(4000));
+        assert!(glare_delay(false) <= Duration::from_millis(2000));
+    }
+}
+
+#[tokio::test]
+async fn test_renegotiations_queued() {
+    let server = create_udp_test_server(ProxyConfig::default(), &[]).await;
+    let inner = server.get_inner();
+    let invitation =
+        Invitation::new(inner.dialog_layer.clone()).with_retransmitter(inner.retransmitter.clone());
+    let peer = ForkingPeer::bind().await;
+
+    let (sender, _receiver) = mpsc::unbounded_channel();
+    let invite = tokio::spawn({
+        let invitation = invitation.clone();
+        let option = peer.option();
+        async move { invitation.invite(option, sender).await }
+    });
+    let (request, from) = peer.recv().await;
+    peer.reply(&request, from, "200 OK", "callee").await;
+    peer.recv().await;
+    let (dialog_id, _) = invite.await.unwrap().unwrap();
+
+    // a hold and a transfer issued back to back
+    let reinvite = tokio::spawn({
+        let (invitation, dialog_id) = (invitation.clone(), dialog_id.clone());
+        async move {
+            invitation
+                .reinvite(&dialog_id, None, Some(b"a=sendonly\r\n".to_vec()))
+                .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let update = tokio::spawn({
+        let (invitation, dialog_id) = (invitation.clone(), dialog_id.clone());
+        async move { invitation.update(&dialog_id, None, None).await }
+    });
+
+    let (request, from) = peer.recv().await;
+    assert!(request.starts_with("INVITE "));
+    assert!(invitation.is_renegotiating(&dialog_id));
+    peer.reply(&request, from, "491 Request Pending", "callee")
+        .await;
+    let (ack, _) = peer.recv().await;
+    assert!(ack.starts_with("ACK "));
+
+    // the re-INVITE is sent again before the UPDATE goes
+    let (request, from) = peer.recv().await;
+    assert!(request.starts_with("INVITE "), "{}", request);
+    peer.reply(&request, from, "200 OK", "callee").await;
+    let (ack, _) = peer.recv().await;
+    assert!(ack.starts_with("ACK "));
+    let resp = reinvite.await.unwrap().unwrap().unwrap();
+    assert_eq!(resp.status_code, rsip::StatusCode::OK);
+
+    let (request, from) = peer.recv().await;
+    assert!(request.starts_with("UPDATE "), "{}", request);
+    peer.reply(&request, from, "200 OK", "callee").await;
+    let resp = update.await.unwrap().unwrap().unwrap();
+    assert_eq!(resp.status_code, rsip::StatusCode::OK);
+    assert!(!invitation.is_renegotiating(&dialog_id));
+}
+
+#[tokio::test]
+async fn test_concurrent_reinvites_queued() {
+    let server = create_udp_test_server(ProxyConfig::default(), &[]).await;
+    let inner = server.get_inner();
+    let invitation =
+        Invitation::new(inner.dialog_layer.clone()).with_retransmitter(inner.retransmitter.clone());
+    let peer = ForkingPeer::bind().await;
+
+    let (sender, _receiver) = mpsc::unbounded_channel();
+    let invite = tokio::spawn({
+        let invitation = invitation.clone();
+        let option = peer.option();
+        async move { invitation.invite(option, sender).await }
+    });
+    let (request, from) = peer.recv().await;
+    peer.reply(&request, from, "200 OK", "callee").await;
+    peer.recv().await;
+    let (dialog_id, _) = invite.await.unwrap().unwrap();
+
+    // a hold and a codec change issued at once
+    let reinvites = ["a=sendonly\r\n", "a=rtpmap:8 PCMA/8000\r\n"].map(|body| {
+        let (invitation, dialog_id) = (invitation.clone(), dialog_id.clone());
+        tokio::spawn(async move {
+            invitation
+                .reinvite(&dialog_id, None, Some(body.as_bytes().to_vec()))
+                .await
+        })
+    });
+
+    let (first, from) = peer.recv().await;
+    assert!(first.starts_with("INVITE "), "{}", first);
+    // the other one waits for the answer to the first
+    let mut buf = vec![0u8; 4096];
+    assert!(
+        timeout(Duration::from_millis(200), peer.socket.recv_from(&mut buf))
+            .await
+            .is_err()
+    );
+    peer.reply(&first, from, "200 OK", "callee").await;
+    let (ack, _) = peer.recv().await;
+    assert!(ack.starts_with("ACK "));
+
+    let (second, from) = peer.recv().await;
+    assert!(second.starts_with("INVITE "), "{}", second);
+    assert_ne!(first.contains("sendonly"), second.contains("sendonly"));
+    peer.reply(&second, from, "200 OK", "callee").await;
+    let (ack, _) = peer.recv().await;
+    assert!(ack.starts_with("ACK "));
+
+    for reinvite in reinvites {
+        let resp = reinvite.await.unwrap().unwrap().unwrap();
+        assert_eq!(resp.status_code, rsip::StatusCode::OK);
+    }
+    assert!(!invitation.is_renegotiating(&dialog_id));
+}
+
+fn sdp(origin: &str, addr: &str, port: u16) -> String {
+    format!(
+        "v=0\r\no={} IN IP4 {}\r\ns=-\r\nc=IN IP4 {}\r\nt=0 0\r\nm=audio {} RTP/AVP 0\r\na=rtpmap:0 PCMU/8000\r\n",
+        origin, addr, addr, port
+    )
+}
+
+#[test]
+fn test_direct_media_trust() {
+    let trusted = [IpNetwork::new("10.0.0.0".parse().unwrap(), 8)];
+    assert!(is_trusted(&sdp("- 1 1", "10.1.2.3", 4000), &trusted));
+    assert!(!is_trusted(&sdp("- 1 1", "192.168.1.3", 4000), &trusted));
+    assert!(!is_trusted("v=0\r\n", &trusted));
+    // WebRTC needs rustpbx for DTLS and ICE
+    let webrtc = sdp("- 1 1", "10.1.2.3", 4000).replace("RTP/AVP", "UDP/TLS/RTP/SAVPF");
+    assert!(!is_trusted(&webrtc, &trusted));
+}
+
+/// Two confirmed dialogs stand in for the legs of a B2BUA call
+async fn media_legs(
+    invitation: &Invitation,
+    alice: &ForkingPeer,
+    bob: &ForkingPeer,
+) -> (MediaLeg, MediaLeg) {
+    let mut legs = Vec::new();
+    for (peer, peer_sdp, port) in [
+        (alice, sdp("alice 7 7", "10.0.0.1", 4000), 20000),
+        (bob, sdp("bob 9 9", "10.0.0.2", 6000), 20002),
+    ] {
+        let (sender, _receiver) = mpsc::unbounded_channel();
+        let invite = tokio::spawn({
+            let invitation = invitation.clone();
+            let option = peer.option();
+            async move { invitation.invite(option, sender).await }
+        });
+        let (request, from) = peer.recv().await;
+        peer.reply_with(&request, from, "200 OK", "leg", &peer_sdp)
+            .await;
+        peer.recv().await;
+        let (dialog_id, _) = invite.await.unwrap().unwrap();
+        let anchor = sdp("rustpbx 0 0", "127.0.0.1", port);
+        legs.push(MediaLeg::new(dialog_id, peer_sdp, anchor));
+    }
+    let callee = legs.pop().unwrap();
+    (legs.pop().unwrap(), callee)
+}
+
+#[tokio::test]
+async fn test_direct_media() {
+    let server = create_udp_test_server(ProxyConfig::default(), &[]).await;
+    let inner = server.get_inner();
+    let invitation =
+        Invitation::new(inner.dialog_layer.clone()).with_retransmitter(inner.retransmitter.clone());
+    let (alice, bob) = (ForkingPeer::bind().await, ForkingPeer::bind().await);
+    let alice_sdp = sdp("alice 7 7", "10.0.0.1", 4000);
+    let bob_sdp = sdp("bob 9 9", "10.0.0.2", 6000);
+    let (caller, callee) = media_legs(&invitation, &alice, &bob).await;
+
+    let bypass = tokio::spawn({
+        let invitation = invitation.clone();
+        async move {
+            let mut direct_media = DirectMedia::default();
+            direct_media
+                .bypass(&invitation, caller, callee)
+                .await
+                .map(|_| direct_media)
+        }
+    });
+    // the callee gets the caller's media address, under rustpbx's origin
+    let (request, from) = bob.recv().await;
+    assert!(request.starts_with("INVITE "));
+    assert!(request.contains("o=rustpbx 0 1 IN IP4 127.0.0.1"));
+    assert!(request.contains("c=IN IP4 10.0.0.1"));
+    bob.reply_with(&request, from, "200 OK", "", &bob_sdp).await;
+    assert!(bob.recv().await.0.starts_with("ACK "));
+    // and the caller the callee's answer
+    let (request, from) = alice.recv().await;
+    assert!(request.starts_with("INVITE "));
+    assert!(request.contains("o=rustpbx 0 1 IN IP4 127.0.0.1"));
+    assert!(request.contains("m=audio 6000 RTP/AVP 0"));
+    alice
+        .reply_with(&request, from, "200 OK", "", &alice_sdp)
+        .await;
+    assert!(alice.recv().await.0.starts_with("ACK "));
+    let mut direct_media = bypass.await.unwrap().unwrap();
+    assert!(direct_media.is_bypassed());
+
+    // anchored again, e.g. to record the call
+    let anchor =
+        tokio::spawn(async move { direct_media.anchor(&invitation).await.map(|_| direct_media) });
+    for (peer, peer_sdp, port) in [(&alice, &alice_sdp, 20000), (&bob, &bob_sdp, 20002)] {
+        let (request, from) = peer.recv().await;
+        assert!(request.starts_with("INVITE "));
+        assert!(request.contains("o=rustpbx 0 2 IN IP4 127.0.0.1"));
+        assert!(request.contains(&format!("m=audio {} RTP/AVP 0", port)));
+        peer.reply_with(&request, from, "200 OK", "", peer_sdp)
+            .await;
+        assert!(peer.recv().await.0.starts_with("ACK "));
+    }
+    assert!(!anchor.await.unwrap().unwrap().is_bypassed());
+}
+
+#[tokio::test]
+async fn test_late_offer() {
+    let server = create_udp_test_server(ProxyConfig::default(), &[]).await;
+    let inner = server.get_inner();
+    let invitation =
+        Invitation::new(inner.dialog_layer.clone()).with_retransmitter(inner.retransmitter.clone());
+    let peer = ForkingPeer::bind().await;
+
+    let mut option = peer.option();
+    let capabilities = SdpCapabilities::new(&sdp("- 1 1", "127.0.0.1", 12000)).unwrap();
+    assert!(invitation.late_offer(&mut option, capabilities));
+    let (sender, _receiver) = mpsc::unbounded_channel();
+    let invite = tokio::spawn({
+        let invitation = invitation.clone();
+        async move { invitation.invite(option, sender).await }
+    });
+    let (request, from) = peer.recv().await;
+    assert!(request.starts_with("INVITE "));
+    assert!(request.contains("Content-Length: 0"));
+
+    // the offer comes in the 2xx, the answer in the ACK
+    let offer = sdp("- 7 7", "10.0.0.2", 30000);
+    peer.reply_with(&request, from, "200 OK", "late", &offer)
+        .await;
+    let (ack, _) = peer.recv().await;
+    assert!(ack.starts_with("ACK sip:bob-late@"));
+    assert!(ack.contains("Content-Type: application/sdp"));
+    assert!(ack.contains("m=audio 12000 RTP/AVP 0"));
+    let (_, body) = invite.await.unwrap().expect("late offer answered");
+    assert_eq!(body, Some(offer.clone().into_bytes()));
+
+    // a retransmitted 2xx gets the same answer
+    peer.reply_with(&request, from, "200 OK", "late", &offer)
+        .await;
+    let (ack, _) = peer.recv().await;
+    assert!(ack.contains("m=audio 12000 RTP/AVP 0"));
+}
+
+#[tokio::test]
+async fn test_direct_media_failure() {
+    let server = create_udp_test_server(ProxyConfig::default(), &[]).await;
+    let inner = server.get_inner();
+    let invitation =
+        Invitation::new(inner.dialog_layer.clone()).with_retransmitter(inner.retransmitter.clone());
+    let (alice, bob) = (ForkingPeer::bind().await, ForkingPeer::bind().await);
+    let alice_sdp = sdp("alice 7 7", "10.0.0.1", 4000);
+    let bob_sdp = sdp("bob 9 9", "10.0.0.2", 6000);
+    let (caller, callee) = media_legs(&invitation, &alice, &bob).await;
+    let callee_dialog = callee.dialog_id.clone();
+
+    // a callee that doesn't answer in time keeps the media anchored
+    let mut direct_media = DirectMedia::default().with_timeout(Some(Duration::from_millis(200)));
+    let (request, _) = {
+        let bypass = direct_media.bypass(&invitation, caller.clone(), callee.clone());
+        let (result, request) = tokio::join!(bypass, bob.recv());
+        assert!(result.unwrap_err().to_string().contains("timed out"));
+        request
+    };
+    assert!(request.starts_with("INVITE "));
+    assert!(!direct_media.is_bypassed());
+    assert!(!invitation.is_renegotiating(&callee_dialog));
+
+    let bypass = tokio::spawn({
+        let invitation = invitation.clone();
+        async move {
+            direct_media
+                .bypass(&invitation, caller, callee)
+                .await
+                .map(|_| direct_media)
+        }
+    });
+    for (peer, peer_sdp) in [(&bob, &bob_sdp), (&alice, &alice_sdp)] {
+        let (request, from) = peer.recv().await;
+        assert!(request.starts_with("INVITE "));
+        peer.reply_with(&request, from, "200 OK", "", peer_sdp)
+            .await;
+        assert!(peer.recv().await.0.starts_with("ACK "));
+    }
+    let mut direct_media = bypass.await.unwrap().unwrap();
+    assert!(direct_media.is_bypassed());
+
+    // the callee refuses to be anchored, the caller gets its SDP back
+    let anchor = tokio::spawn({
+        let invitation = invitation.clone();
+        async move {
+            let result = direct_media.anchor(&invitation).await;
+            (result, direct_media)
+        }
+    });
+    let (request, from) = alice.recv().await;
+    assert!(request.contains("m=audio 20000 RTP/AVP 0"));
+    alice
+        .reply_with(&request, from, "200 OK", "", &alice_sdp)
+        .await;
+    assert!(alice.recv().await.0.starts_with("ACK "));
+    let (request, from) = bob.recv().await;
+    assert!(request.contains("m=audio 20002 RTP/AVP 0"));
+    bob.reply(&request, from, "488 Not Acceptable Here", "")
+        .await;
+    let (request, from) = alice.recv().await;
+    assert!(request.starts_with("INVITE "));
+    assert!(request.contains("o=rustpbx 0 3 IN IP4 127.0.0.1"));
+    assert!(request.contains("c=IN IP4 10.0.0.2"));
+    alice
+        .reply_with(&request, from, "200 OK", "", &alice_sdp)
+        .await;
+    let (result, direct_media) = anchor.await.unwrap();
+    assert!(result.is_err());
+    assert!(direct_media.is_bypassed());
+}
@@ -0,0 +1,124 @@
+use crate::config::FraudConfig;
+use crate::proxy::fraud::{FraudDetector, FraudRule, FraudVerdict};
+use crate::proxy::status::ProxyStatus;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+fn detector(config: &str) -> FraudDetector {
+    FraudDetector::new(toml::from_str::<FraudConfig>(config).unwrap()).unwrap()
+}
+
+fn at(time: &str) -> DateTime<Utc> {
+    time.parse().unwrap()
+}
+
+#[test]
+fn test_fraud_rules() {
+    let fraud = detector(
+        r#"
+        destinations = [{ prefix = "+88", score = 20 }, { prefix = "+882", score = 60 }]
+        velocity = { max_calls = 2, window = 10, score = 30 }
+        [after_hours]
+        business_hours = { start = "08:00", end = "18:00", days = ["mon", "tue", "wed", "thu", "fri"] }
+        utc_offset = "+08:00"
+        max_calls = 1
+        score = 40
+        "#,
+    );
+    let now = Instant::now();
+    // 10:00 in +08:00, on a Monday
+    let open = at("2024-01-01T02:00:00Z");
+    let score = fraud.score("call-1", "alice@example.com", "+88212345", now, open);
+    assert_eq!(score.score, 60);
+    assert_eq!(score.rules, vec![FraudRule::Destination]);
+    fraud.score("call-2", "alice@example.com", "1002", now, open);
+    let score = fraud.score("call-3", "alice@example.com", "1003", now, open);
+    assert_eq!(score.rules, vec![FraudRule::Velocity]);
+    // the window slid past the earlier calls
+    let later = now + Duration::from_secs(10);
+    assert_eq!(
+        fraud
+            .score("call-4", "alice@example.com", "1004", later, open)
+            .score,
+        0
+    );
+
+    // 22:00 in +08:00, the first call after hours is let through
+    let closed = at("2024-01-01T14:00:00Z");
+    let later = later + Duration::from_secs(60);
+    assert!(
+        fraud
+            .score("call-5", "alice@example.com", "1005", later, closed)
+            .rules
+            .is_empty()
+    );
+    let score = fraud.score("call-6", "alice@example.com", "1006", later, closed);
+    assert_eq!(score.rules, vec![FraudRule::AfterHours]);
+    // other extensions count on their own
+    assert!(
+        fraud
+            .score("call-7", "bob@example.com", "1007", later, closed)
+            .rules
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_fraud_international_calls() {
+    let (status, mut events) = broadcast::channel(8);
+    let fraud = Arc::new(
+        detector(
+            r#"
+            alert_score = 50
+            block_score = 100
+            destinations = [{ prefix = "0044", score = 50 }]
+            international = { max_calls = 1, score = 50 }
+            "#,
+        )
+        .with_status(status),
+    );
+
+    let first = fraud.enter("call-1");
+    assert_eq!(
+        fraud.check("call-1", "alice@example.com", "+49301234"),
+        FraudVerdict::Allow
+    );
+    assert_eq!(
+        fraud.check("call-2", "alice@example.com", "+49301235"),
+        FraudVerdict::Alert
+    );
+    assert_eq!(
+        fraud.check("call-3", "alice@example.com", "00442071234"),
+        FraudVerdict::Block
+    );
+    match events.try_recv().unwrap() {
+        ProxyStatus::FraudAlert {
+            call_id,
+            blocked,
+            rules,
+            ..
+        } => {
+            assert_eq!(call_id, "call-2");
+            assert!(!blocked);
+            assert_eq!(rules, vec![FraudRule::International]);
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+    match events.try_recv().unwrap() {
+        ProxyStatus::FraudAlert { score, blocked, .. } => {
+            assert_eq!(score, 100);
+            assert!(blocked);
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+
+    // the blocked call and the ended one are no longer in progress
+    drop(first);
+    fraud.release("call-2");
+    assert_eq!(
+        fraud.check("call-4", "alice@example.com", "+49301236"),
+        FraudVerdict::Allow
+    );
+}
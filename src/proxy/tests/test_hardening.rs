@@ -0,0 +1,87 @@
+use super::common::create_test_request;
+use crate::config::SipHardeningConfig;
+use crate::proxy::hardening::{HardeningInspector, Verdict, inspect_request};
+use rsip::{Header, SipMessage};
+use rsipstack::transaction::endpoint::MessageInspector;
+
+fn request_with(headers: Vec<Header>) -> rsip::Request {
+    let mut request = create_test_request(rsip::Method::Invite, "alice", None, "example.com", None);
+    for header in headers {
+        request.headers.unique_push(header);
+    }
+    request
+}
+
+#[test]
+fn test_inspect_request() {
+    let config = SipHardeningConfig::default();
+    assert_eq!(
+        inspect_request(&config, &request_with(vec![])),
+        Verdict::Accept
+    );
+
+    let scanner = request_with(vec![Header::UserAgent("friendly-scanner".into())]);
+    assert!(matches!(
+        inspect_request(&config, &scanner),
+        Verdict::Drop(_)
+    ));
+
+    let cseq_mismatch = request_with(vec![Header::CSeq("1 REGISTER".into())]);
+    assert!(matches!(
+        inspect_request(&config, &cseq_mismatch),
+        Verdict::Reject(rsip::StatusCode::BadRequest, _)
+    ));
+
+    let looped = request_with(vec![Header::MaxForwards("0".into())]);
+    assert!(matches!(
+        inspect_request(&config, &looped),
+        Verdict::Reject(rsip::StatusCode::TooManyHops, _)
+    ));
+
+    let lenient = SipHardeningConfig {
+        reject_malformed: false,
+        ..Default::default()
+    };
+    assert_eq!(inspect_request(&lenient, &looped), Verdict::Accept);
+}
+
+#[test]
+fn test_identity_headers() {
+    let response = rsip::Response {
+        status_code: rsip::StatusCode::OK,
+        version: rsip::Version::V2,
+        headers: vec![Header::UserAgent("rustpbx 1.0".into())].into(),
+        body: vec![],
+    };
+
+    let inspector = HardeningInspector::new(
+        SipHardeningConfig {
+            server: Some("pbx".to_string()),
+            ..Default::default()
+        },
+        None,
+    );
+    let SipMessage::Response(resp) = inspector.before_send(response.clone().into()) else {
+        panic!("expected response");
+    };
+    assert_eq!(
+        resp.headers
+            .iter()
+            .map(|h| h.to_string())
+            .collect::<Vec<_>>(),
+        vec!["Server: pbx"]
+    );
+
+    let inspector = HardeningInspector::new(
+        SipHardeningConfig {
+            server: Some("pbx".to_string()),
+            hide_identity: true,
+            ..Default::default()
+        },
+        None,
+    );
+    let SipMessage::Response(resp) = inspector.before_send(response.into()) else {
+        panic!("expected response");
+    };
+    assert!(resp.headers.iter().next().is_none());
+}
@@ -0,0 +1,87 @@
+use crate::config::{CallLimitConfig, CallLimitsConfig};
+use crate::proxy::limits::{CallLimit, CallLimiter, LimitKind, LimitScope};
+use crate::proxy::status::ProxyStatus;
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+fn limit(max_calls: Option<u32>, max_cps: Option<u32>) -> CallLimit {
+    CallLimit::new(
+        LimitScope::Extension,
+        "alice@example.com",
+        &CallLimitConfig { max_calls, max_cps },
+    )
+}
+
+#[test]
+fn test_concurrent_calls() {
+    let (status, mut events) = broadcast::channel(8);
+    let limiter = Arc::new(CallLimiter::new().with_status(status));
+    let limits = [
+        CallLimit::new(LimitScope::Global, "*", &CallLimitConfig::default()),
+        limit(Some(2), None),
+    ];
+
+    let first = limiter.enter("call-1");
+    limiter.admit("call-1", &limits).unwrap();
+    limiter.admit("call-2", &limits).unwrap();
+    assert_eq!(
+        limiter.active_calls(LimitScope::Extension, "alice@example.com"),
+        2
+    );
+    let exceeded = limiter.admit("call-3", &limits).unwrap_err();
+    assert_eq!(exceeded.kind, LimitKind::Calls);
+    assert_eq!(exceeded.max, 2);
+    // nothing is taken when a limit refuses the call
+    assert_eq!(limiter.active_calls(LimitScope::Global, "*"), 2);
+
+    match events.try_recv().unwrap() {
+        ProxyStatus::CallLimitExceeded {
+            call_id,
+            scope,
+            limit,
+            ..
+        } => {
+            assert_eq!(call_id, "call-3");
+            assert_eq!(scope, LimitScope::Extension);
+            assert_eq!(limit, LimitKind::Calls);
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+
+    drop(first);
+    limiter.admit("call-3", &limits).unwrap();
+}
+
+#[test]
+fn test_calls_per_second() {
+    let limiter = CallLimiter::new();
+    let limits = [limit(None, Some(2))];
+    limiter.admit("call-1", &limits).unwrap();
+    limiter.admit("call-2", &limits).unwrap();
+    limiter.release("call-1");
+    limiter.release("call-2");
+    // ended calls still count towards the rate
+    assert_eq!(
+        limiter.admit("call-3", &limits).unwrap_err().kind,
+        LimitKind::Cps
+    );
+    assert!(!limiter.has_capacity("call-3", &limits[0]));
+}
+
+#[test]
+fn test_call_limits_config() {
+    let config: CallLimitsConfig = toml::from_str(
+        r#"
+        max_calls = 100
+        max_cps = 10
+        extension = { max_calls = 2 }
+        extensions = { "1001" = { max_calls = 8, max_cps = 1 } }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.global.max_calls, Some(100));
+    assert_eq!(config.global.max_cps, Some(10));
+    assert_eq!(config.extension.max_calls, Some(2));
+    assert_eq!(config.extensions["1001"].max_cps, Some(1));
+    assert_eq!(config.retry_after, 5);
+}
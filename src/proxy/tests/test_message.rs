@@ -0,0 +1,181 @@
+use super::common::{create_test_request, create_test_server, create_transaction};
+use crate::app::AppStateBuilder;
+use crate::call::{Location, TransactionCookie};
+use crate::config::ProxyConfig;
+use crate::proxy::message::{MessageModule, send_message};
+use crate::proxy::server::SipServerBuilder;
+use crate::proxy::status::ProxyStatus;
+use crate::proxy::{ProxyAction, ProxyModule, locator::MemoryLocator, user::MemoryUserBackend};
+use rsipstack::transport::SipAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+
+#[tokio::test]
+async fn test_message_user_offline() {
+    let (server_inner, config) = create_test_server().await;
+    let mut status = server_inner.proxy_status.subscribe();
+    let module = MessageModule::new(server_inner.clone(), config);
+
+    let mut request =
+        create_test_request(rsip::Method::Message, "alice", None, "example.com", None);
+    request
+        .headers
+        .push(rsip::Header::ContentType("text/plain".into()));
+    request.body = b"hello".to_vec();
+    let (mut tx, _) = create_transaction(request).await;
+
+    let result = module
+        .on_transaction_begin(
+            CancellationToken::new(),
+            &mut tx,
+            TransactionCookie::default(),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(result, ProxyAction::Abort));
+
+    match status.recv().await.unwrap() {
+        ProxyStatus::MessageReceived {
+            content_type, body, ..
+        } => {
+            assert_eq!(content_type, "text/plain");
+            assert_eq!(body, "hello");
+        }
+        other => panic!("unexpected status {:?}", other),
+    }
+    assert!(matches!(
+        status.recv().await.unwrap(),
+        ProxyStatus::MessageFailed { status: 480, .. }
+    ));
+
+    // other methods are left to the rest of the modules
+    let request = create_test_request(rsip::Method::Options, "alice", None, "example.com", None);
+    let (mut tx, _) = create_transaction(request).await;
+    let result = module
+        .on_transaction_begin(
+            CancellationToken::new(),
+            &mut tx,
+            TransactionCookie::default(),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(result, ProxyAction::Continue));
+}
+
+#[tokio::test]
+async fn test_message_delivery() {
+    let port = portpicker::pick_unused_port().unwrap_or(15070);
+    let config = Arc::new(ProxyConfig {
+        addr: "127.0.0.1".to_string(),
+        udp_port: Some(port),
+        modules: Some(vec!["message".to_string()]),
+        ..Default::default()
+    });
+    let app_state = AppStateBuilder::new()
+        .with_config(crate::config::Config {
+            ua: None,
+            ..Default::default()
+        })
+        .build()
+        .await
+        .unwrap()
+        .0;
+    let server = SipServerBuilder::new(config)
+        .with_user_backend(Box::new(MemoryUserBackend::new(None)))
+        .with_locator(Box::new(MemoryLocator::new()))
+        .register_module("message", MessageModule::create)
+        .build(app_state)
+        .await
+        .unwrap();
+    let inner = server.get_inner();
+    let serving = server.clone();
+    tokio::spawn(async move { serving.serve().await });
+
+    // a registered phone that accepts every MESSAGE
+    let phone = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let phone_addr = phone.local_addr().unwrap();
+    let aor: rsip::Uri = format!("sip:bob@{}", phone_addr)
+        .as_str()
+        .try_into()
+        .unwrap();
+    inner
+        .locator
+        .register(
+            "bob",
+            Some("127.0.0.1"),
+            Location {
+                destination: SipAddr::try_from(&aor).unwrap(),
+                aor,
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+    let received = tokio::spawn(async move {
+        let mut buf = vec![0u8; 4096];
+        let (n, from) = phone.recv_from(&mut buf).await.unwrap();
+        let message = String::from_utf8_lossy(&buf[..n]).to_string();
+        let header = |name: &str| {
+            message
+                .lines()
+                .find(|l| l.starts_with(name))
+                .unwrap()
+                .to_string()
+        };
+        let ok = format!(
+            "SIP/2.0 200 OK\r\n{}\r\n{}\r\n{};tag=phone\r\n{}\r\n{}\r\nContent-Length: 0\r\n\r\n",
+            header("Via:"),
+            header("From:"),
+            header("To:"),
+            header("Call-ID:"),
+            header("CSeq:"),
+        );
+        phone.send_to(ok.as_bytes(), from).await.unwrap();
+        message
+    });
+
+    let mut status = inner.proxy_status.subscribe();
+    let delivery = tokio::time::timeout(
+        Duration::from_secs(5),
+        send_message(
+            &inner,
+            "msg-1".to_string(),
+            "sip:alice@127.0.0.1".try_into().unwrap(),
+            "sip:bob@127.0.0.1".try_into().unwrap(),
+            "text/plain".to_string(),
+            b"ping".to_vec(),
+        ),
+    )
+    .await
+    .expect("delivery timeout")
+    .unwrap();
+    assert_eq!(delivery.id, "msg-1");
+    assert_eq!(delivery.status, 200);
+    assert!(delivery.delivered);
+
+    let message = received.await.unwrap();
+    assert!(message.starts_with(&format!("MESSAGE sip:bob@{} SIP/2.0", phone_addr)));
+    assert!(message.contains("Content-Type: text/plain"));
+    assert!(message.ends_with("\r\n\r\nping"));
+
+    assert!(matches!(
+        status.recv().await.unwrap(),
+        ProxyStatus::MessageReceived { .. }
+    ));
+    match status.recv().await.unwrap() {
+        ProxyStatus::MessageDelivered {
+            id,
+            destination,
+            status,
+            ..
+        } => {
+            assert_eq!(id, "msg-1");
+            assert_eq!(status, 200);
+            assert!(destination.contains(&phone_addr.to_string()));
+        }
+        other => panic!("unexpected status {:?}", other),
+    }
+    server.stop();
+}
@@ -0,0 +1,89 @@
+use super::common::create_test_server_with_config;
+use crate::config::{MeteringConfig, ProxyConfig};
+use crate::proxy::metering::{Meter, Usage, call_tenant};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_usage_per_period() {
+    let meter = Arc::new(Meter::new());
+    let first = meter.enter("call-1", Some("acme".to_string()));
+    meter.set_trunk("call-1", "carrier");
+    let second = meter.enter("call-2", Some("acme".to_string()));
+    let now = Instant::now();
+
+    let usage = meter.snapshot(now + Duration::from_secs(30), false);
+    assert_eq!(
+        usage.tenants["acme"],
+        Usage {
+            calls: 2,
+            channel_seconds: 60,
+            active_channels: 2,
+            peak_channels: 2,
+        }
+    );
+    assert_eq!(usage.trunks["carrier"].channel_seconds, 30);
+
+    drop(second);
+    let closed = meter.snapshot(now + Duration::from_secs(90), true);
+    assert_eq!(
+        closed.tenants["acme"],
+        Usage {
+            calls: 2,
+            channel_seconds: 120,
+            active_channels: 1,
+            peak_channels: 2,
+        }
+    );
+
+    // the call in progress carries over into the next period
+    let usage = meter.snapshot(now + Duration::from_secs(100), false);
+    assert_eq!(usage.period_start, closed.period_end);
+    assert_eq!(
+        usage.tenants["acme"],
+        Usage {
+            calls: 0,
+            channel_seconds: 10,
+            active_channels: 1,
+            peak_channels: 1,
+        }
+    );
+    assert_eq!(usage.trunks["carrier"].channel_seconds, 10);
+
+    drop(first);
+    let mut snapshots = meter.subscribe();
+    meter.close_period();
+    assert_eq!(
+        snapshots.try_recv().unwrap().tenants["acme"].active_channels,
+        0
+    );
+    assert!(meter.usage().tenants.is_empty());
+}
+
+#[tokio::test]
+async fn test_call_tenant() {
+    let config = ProxyConfig {
+        metering: Some(MeteringConfig {
+            interval: 300,
+            path: None,
+            url: None,
+            tenants: HashMap::from([("example.com".to_string(), "acme".to_string())]),
+        }),
+        ..Default::default()
+    };
+    let (server, _) = create_test_server_with_config(config).await;
+    let remote: rsip::Uri = "sip:+15551234@carrier.net".try_into().unwrap();
+    let local: rsip::Uri = "sip:bob@example.com".try_into().unwrap();
+    let other: rsip::Uri = "sip:alice@localhost".try_into().unwrap();
+
+    assert_eq!(
+        call_tenant(&server, &[&remote, &local]).await.as_deref(),
+        Some("acme")
+    );
+    assert_eq!(
+        call_tenant(&server, &[&other, &local]).await.as_deref(),
+        Some("localhost")
+    );
+    assert_eq!(call_tenant(&server, &[&remote]).await, None);
+}
@@ -0,0 +1,116 @@
+use super::common::{
+    create_register_request, create_test_server_with_config, create_transaction,
+    create_udp_test_server,
+};
+use crate::call::TransactionCookie;
+use crate::config::{NatKeepaliveConfig, NatKeepaliveMethod, ProxyConfig};
+use crate::proxy::nat::NatKeepalive;
+use crate::proxy::registrar::RegistrarModule;
+use crate::proxy::{ProxyAction, ProxyModule};
+use rsip::Header;
+use rsipstack::transport::SipAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio_util::sync::CancellationToken;
+
+async fn register(module: &RegistrarModule, request: rsip::Request) {
+    let (mut tx, _) = create_transaction(request).await;
+    let result = module
+        .on_transaction_begin(
+            CancellationToken::new(),
+            &mut tx,
+            TransactionCookie::default(),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(result, ProxyAction::Abort));
+}
+
+#[tokio::test]
+async fn test_nat_keepalive_registration() {
+    let (server_inner, config) = create_test_server_with_config(ProxyConfig {
+        nat_keepalive: Some(NatKeepaliveConfig::default()),
+        ..Default::default()
+    })
+    .await;
+    let module = RegistrarModule::new(server_inner.clone(), config);
+    let nat_keepalive = module.nat_keepalive.clone().unwrap();
+
+    // the Contact matches the source address, nothing to keep open
+    register(
+        &module,
+        create_register_request("alice", "example.com", Some(60)),
+    )
+    .await;
+    assert!(nat_keepalive.bindings().is_empty());
+
+    // received/rport stamped by the transport point at the NAT's public side
+    let mut request = create_register_request("alice", "example.com", Some(60));
+    request.headers.retain(|h| !matches!(h, Header::Via(_)));
+    request.headers.push(Header::Via(
+        "SIP/2.0/UDP example.com:5060;branch=z9hG4bKnat1;received=203.0.113.5;rport=40000".into(),
+    ));
+    register(&module, request).await;
+
+    let locations = server_inner
+        .locator
+        .lookup("alice", Some("example.com"))
+        .await
+        .unwrap();
+    assert_eq!(
+        locations[0].destination.addr.to_string(),
+        "203.0.113.5:40000"
+    );
+    let bindings = nat_keepalive.bindings();
+    let binding = bindings.get("alice@example.com").unwrap();
+    assert_eq!(binding.destination.addr.to_string(), "203.0.113.5:40000");
+
+    register(
+        &module,
+        create_register_request("alice", "example.com", Some(0)),
+    )
+    .await;
+    assert!(nat_keepalive.bindings().is_empty());
+}
+
+#[tokio::test]
+async fn test_nat_keepalive_ping() {
+    let server = create_udp_test_server(ProxyConfig::default(), &[]).await;
+    let inner = server.get_inner();
+    let phone = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let aor: rsip::Uri = format!("sip:bob@{}", phone.local_addr().unwrap())
+        .as_str()
+        .try_into()
+        .unwrap();
+    let destination = SipAddr::try_from(&aor).unwrap();
+    let mut buf = vec![0u8; 4096];
+
+    let crlf = NatKeepalive::new(inner.clone(), NatKeepaliveConfig::default());
+    crlf.add("bob@127.0.0.1", aor.clone(), destination.clone(), 60);
+    crlf.add("gone@127.0.0.1", aor.clone(), destination.clone(), 0);
+    crlf.ping_all().await;
+    let (n, _) = tokio::time::timeout(Duration::from_secs(2), phone.recv_from(&mut buf))
+        .await
+        .expect("keepalive")
+        .unwrap();
+    assert_eq!(&buf[..n], b"\r\n\r\n");
+    // expired bindings are dropped instead of pinged
+    assert_eq!(crlf.bindings().len(), 1);
+
+    let options = NatKeepalive::new(
+        inner.clone(),
+        NatKeepaliveConfig {
+            method: NatKeepaliveMethod::Options,
+            ..Default::default()
+        },
+    );
+    options.add("bob@127.0.0.1", aor.clone(), destination, 60);
+    options.ping_all().await;
+    let (n, _) = tokio::time::timeout(Duration::from_secs(2), phone.recv_from(&mut buf))
+        .await
+        .expect("keepalive")
+        .unwrap();
+    let request = String::from_utf8_lossy(&buf[..n]);
+    assert!(request.starts_with(&format!("OPTIONS {} SIP/2.0", aor)));
+    server.stop();
+}
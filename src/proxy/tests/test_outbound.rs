@@ -0,0 +1,118 @@
+use super::common::{create_register_request, create_test_server, create_transaction};
+use crate::call::{Location, TransactionCookie};
+use crate::proxy::call::select_flows;
+use crate::proxy::registrar::RegistrarModule;
+use crate::proxy::{ProxyAction, ProxyModule};
+use rsip::Header;
+use tokio_util::sync::CancellationToken;
+
+const PHONE: &str = "urn:uuid:00000000-0000-1000-8000-000a95a0e128";
+const SOFTPHONE: &str = "urn:uuid:00000000-0000-1000-8000-000a95a0e129";
+
+/// REGISTER from device `instance` over flow `reg_id`, arriving from `source`
+fn outbound_register(instance: &str, reg_id: u32, source: &str, expires: u32) -> rsip::Request {
+    let mut request = create_register_request("alice", "example.com", Some(expires));
+    request
+        .headers
+        .retain(|h| !matches!(h, Header::Via(_) | Header::Contact(_)));
+    request.headers.push(Header::Via(
+        format!(
+            "SIP/2.0/UDP 192.168.1.20:5060;branch=z9hG4bK{}{};received={};rport=5060",
+            reg_id,
+            rand::random::<u32>(),
+            source
+        )
+        .into(),
+    ));
+    request.headers.push(Header::Contact(
+        format!(
+            "<sip:alice@192.168.1.20:5060>;+sip.instance=\"<{}>\";reg-id={}",
+            instance, reg_id
+        )
+        .into(),
+    ));
+    request
+        .headers
+        .push(Header::Supported("outbound, gruu".into()));
+    request
+}
+
+async fn register(module: &RegistrarModule, request: rsip::Request) {
+    let (mut tx, _) = create_transaction(request).await;
+    let result = module
+        .on_transaction_begin(
+            CancellationToken::new(),
+            &mut tx,
+            TransactionCookie::default(),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(result, ProxyAction::Abort));
+}
+
+#[tokio::test]
+async fn test_outbound_flows_per_instance() {
+    let (server_inner, config) = create_test_server().await;
+    let module = RegistrarModule::new(server_inner.clone(), config);
+
+    register(&module, outbound_register(PHONE, 1, "203.0.113.5", 60)).await;
+    register(&module, outbound_register(PHONE, 2, "203.0.113.6", 60)).await;
+    register(&module, outbound_register(SOFTPHONE, 1, "198.51.100.7", 60)).await;
+    // a re-REGISTER over a new connection replaces the flow
+    register(&module, outbound_register(PHONE, 1, "203.0.113.9", 60)).await;
+
+    let locations = server_inner
+        .locator
+        .lookup("alice", Some("example.com"))
+        .await
+        .unwrap();
+    assert_eq!(locations.len(), 3);
+    let flow = locations
+        .iter()
+        .find(|l| l.instance_id.as_deref() == Some(PHONE) && l.reg_id == Some(1))
+        .unwrap();
+    assert_eq!(flow.destination.addr.to_string(), "203.0.113.9:5060");
+
+    // one flow per device, the most recently registered one
+    let selected = select_flows(locations.clone(), None);
+    assert_eq!(selected.len(), 2);
+    assert!(
+        selected
+            .iter()
+            .any(|l| l.destination.addr.to_string() == "203.0.113.9:5060")
+    );
+    assert!(
+        selected
+            .iter()
+            .any(|l| l.instance_id.as_deref() == Some(SOFTPHONE))
+    );
+
+    // a GRUU reaches only its own device
+    let selected = select_flows(locations, Some(SOFTPHONE));
+    assert_eq!(selected.len(), 1);
+    assert_eq!(
+        selected[0].destination.addr.to_string(),
+        "198.51.100.7:5060"
+    );
+
+    // unregistering a flow leaves the other devices alone
+    register(&module, outbound_register(SOFTPHONE, 1, "198.51.100.7", 0)).await;
+    let locations = server_inner
+        .locator
+        .lookup("alice", Some("example.com"))
+        .await
+        .unwrap();
+    assert_eq!(locations.len(), 2);
+    assert!(
+        locations
+            .iter()
+            .all(|l| l.instance_id.as_deref() == Some(PHONE))
+    );
+}
+
+#[test]
+fn test_select_flows_without_instance() {
+    let legacy = Location::default();
+    assert_eq!(select_flows(vec![legacy.clone()], None).len(), 1);
+    assert!(select_flows(vec![legacy], Some(PHONE)).is_empty());
+}
@@ -0,0 +1,270 @@
+use crate::config::SipParseMode;
+use crate::proxy::listener::{SipListener, SipReceiver, StreamFrame, next_frame};
+use crate::proxy::parser::{parse_and_report, parse_sip_message};
+use crate::proxy::status::ProxyStatus;
+use rsipstack::{
+    EndpointBuilder,
+    transaction::{Endpoint, TransactionReceiver},
+    transport::{TransportLayer, udp::UdpConnection},
+};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::broadcast,
+    time::timeout,
+};
+use tokio_util::sync::CancellationToken;
+
+const HEAD: &str = "OPTIONS sip:user@example.com SIP/2.0\r\n\
+    Via: SIP/2.0/UDP host1.example.com;branch=z9hG4bK1\r\n\
+    Max-Forwards: 70\r\n\
+    From: <sip:caller@example.com>;tag=1\r\n\
+    To: <sip:user@example.com>\r\n\
+    Call-ID: abc@host\r\n\
+    CSeq: 1 OPTIONS\r\n";
+
+fn message(extra: &str) -> String {
+    format!("{}{}Content-Length: 0\r\n\r\n", HEAD, extra)
+}
+
+#[test]
+fn test_clean_message() {
+    let parsed = parse_sip_message(message("").as_bytes(), SipParseMode::Strict).unwrap();
+    assert!(parsed.repairs.is_empty());
+    let parsed = parse_sip_message(message("").as_bytes(), SipParseMode::Lenient).unwrap();
+    assert!(parsed.repairs.is_empty());
+}
+
+#[test]
+fn test_lenient_repairs_torture_messages() {
+    let cases = [
+        (message("").replace("\r\n", "\n"), "bare LF line endings"),
+        (
+            message("Subject: hello\r\n  world\r\n"),
+            "folded header line",
+        ),
+        (
+            message("Subject   :   hi\r\n"),
+            "whitespace before colon: Subject",
+        ),
+        (
+            message("ThisIsGarbage\r\n"),
+            "dropped header: ThisIsGarbage",
+        ),
+    ];
+    for (raw, repair) in cases {
+        assert!(
+            parse_sip_message(raw.as_bytes(), SipParseMode::Strict).is_err(),
+            "strict accepted {:?}",
+            raw
+        );
+        let parsed = parse_sip_message(raw.as_bytes(), SipParseMode::Lenient).unwrap();
+        assert!(
+            parsed.repairs.iter().any(|r| r == repair),
+            "{:?} not in {:?}",
+            repair,
+            parsed.repairs
+        );
+    }
+
+    let parsed = parse_sip_message(
+        message("Subject: hello\r\n\tworld\r\n").as_bytes(),
+        SipParseMode::Lenient,
+    )
+    .unwrap();
+    let rsip::SipMessage::Request(req) = parsed.message else {
+        panic!("expected request");
+    };
+    assert!(req.to_string().contains("Subject: hello world"));
+}
+
+#[test]
+fn test_lenient_drops_every_bad_header() {
+    let raw = message("ThisIsGarbage\r\nSubject: kept\r\nMore Garbage\r\n");
+    let parsed = parse_sip_message(raw.as_bytes(), SipParseMode::Lenient).unwrap();
+    assert_eq!(
+        parsed.repairs,
+        vec![
+            "dropped header: ThisIsGarbage",
+            "dropped header: More Garbage"
+        ]
+    );
+    assert!(parsed.message.to_string().contains("Subject: kept"));
+}
+
+#[test]
+fn test_lenient_gives_up_on_garbage() {
+    let broken_start = message("").replace("SIP/2.0\r\nVia", "SIP/9.9 extra\r\nVia");
+    assert!(parse_sip_message(broken_start.as_bytes(), SipParseMode::Lenient).is_err());
+    assert!(parse_sip_message(b"\x00\x01 not sip at all", SipParseMode::Lenient).is_err());
+}
+
+#[test]
+fn test_parse_error_events() {
+    let (status, mut events) = broadcast::channel(8);
+    let repaired = message("").replace("\r\n", "\n");
+    assert!(
+        parse_and_report(
+            &status,
+            SipParseMode::Lenient,
+            repaired.as_bytes(),
+            "192.0.2.1:5060",
+            "WS"
+        )
+        .is_some()
+    );
+    assert!(matches!(
+        events.try_recv().unwrap(),
+        ProxyStatus::SipParseError { recovered: true, ref repairs, .. } if !repairs.is_empty()
+    ));
+
+    assert!(
+        parse_and_report(
+            &status,
+            SipParseMode::Strict,
+            repaired.as_bytes(),
+            "192.0.2.1:5060",
+            "WS"
+        )
+        .is_none()
+    );
+    assert!(matches!(
+        events.try_recv().unwrap(),
+        ProxyStatus::SipParseError { recovered: false, ref source, .. } if source == "192.0.2.1:5060"
+    ));
+
+    // clean messages are not reported
+    parse_and_report(
+        &status,
+        SipParseMode::Strict,
+        message("").as_bytes(),
+        "192.0.2.1:5060",
+        "WS",
+    )
+    .unwrap();
+    assert!(events.try_recv().is_err());
+}
+
+#[test]
+fn test_stream_framing() {
+    let raw = message("");
+    let mut buffer = b"\r\n\r\n".to_vec();
+    buffer.extend_from_slice(&raw.as_bytes()[..20]);
+    assert_eq!(
+        next_frame(&mut buffer).unwrap(),
+        Some(StreamFrame::KeepaliveRequest)
+    );
+    assert_eq!(next_frame(&mut buffer).unwrap(), None);
+
+    let with_body = raw.replace("Content-Length: 0", "Content-Length: 4") + "abcd";
+    buffer.extend_from_slice(&raw.as_bytes()[20..]);
+    buffer.extend_from_slice(&with_body.as_bytes()[..with_body.len() - 2]);
+    assert_eq!(
+        next_frame(&mut buffer).unwrap(),
+        Some(StreamFrame::Message(raw.into_bytes()))
+    );
+    // the body is still short of its Content-Length
+    assert_eq!(next_frame(&mut buffer).unwrap(), None);
+    buffer.extend_from_slice(b"cd");
+    assert_eq!(
+        next_frame(&mut buffer).unwrap(),
+        Some(StreamFrame::Message(with_body.into_bytes()))
+    );
+    assert!(buffer.is_empty());
+}
+
+fn start_receiver(
+    listener: SipListener,
+) -> (
+    Endpoint,
+    TransactionReceiver,
+    broadcast::Receiver<ProxyStatus>,
+    CancellationToken,
+) {
+    let token = CancellationToken::new();
+    let endpoint = EndpointBuilder::new()
+        .with_cancel_token(token.clone())
+        .with_transport_layer(TransportLayer::new(token.clone()))
+        .build();
+    let incoming = endpoint.incoming_transactions().unwrap();
+    let (status, events) = broadcast::channel(8);
+    let receiver = SipReceiver {
+        endpoint: endpoint.inner.clone(),
+        status,
+        parse_mode: SipParseMode::Lenient,
+        token: token.clone(),
+    };
+    tokio::spawn(receiver.serve(listener));
+    (endpoint, incoming, events, token)
+}
+
+#[tokio::test]
+async fn test_udp_messages_are_parsed_leniently() {
+    let stopped = CancellationToken::new();
+    stopped.cancel();
+    let conn =
+        UdpConnection::create_connection("127.0.0.1:0".parse().unwrap(), None, Some(stopped))
+            .await
+            .unwrap();
+    let addr = conn.get_addr().get_socketaddr().unwrap();
+    let (endpoint, mut incoming, mut events, token) = start_receiver(SipListener::Udp(conn));
+    tokio::spawn(async move { endpoint.serve().await });
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket
+        .send_to(message("ThisIsGarbage\r\n").as_bytes(), addr)
+        .await
+        .unwrap();
+    let event = timeout(Duration::from_secs(2), events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(
+        event,
+        ProxyStatus::SipParseError { recovered: true, ref transport, .. } if transport == "UDP"
+    ));
+    let tx = timeout(Duration::from_secs(2), incoming.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(tx.original.method, rsip::Method::Options);
+    token.cancel();
+}
+
+#[tokio::test]
+async fn test_tcp_messages_are_parsed_leniently() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (endpoint, mut incoming, mut events, token) = start_receiver(SipListener::Tcp(listener));
+    tokio::spawn(async move { endpoint.serve().await });
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(b"\r\n\r\n").await.unwrap();
+    let mut pong = [0u8; 2];
+    timeout(Duration::from_secs(2), stream.read_exact(&mut pong))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&pong, b"\r\n");
+
+    let raw = message("ThisIsGarbage\r\n");
+    let (first, second) = raw.as_bytes().split_at(40);
+    stream.write_all(first).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    stream.write_all(second).await.unwrap();
+    let event = timeout(Duration::from_secs(2), events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(
+        event,
+        ProxyStatus::SipParseError { recovered: true, ref transport, .. } if transport == "TCP"
+    ));
+    let tx = timeout(Duration::from_secs(2), incoming.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(tx.original.method, rsip::Method::Options);
+    token.cancel();
+}
@@ -0,0 +1,82 @@
+use super::common::{
+    create_register_request, create_test_request, create_test_server, create_transaction,
+};
+use crate::call::{Location, TransactionCookie, parse_route_set};
+use crate::proxy::call::CallModule;
+use crate::proxy::registrar::RegistrarModule;
+use crate::proxy::{ProxyAction, ProxyModule};
+use rsip::Header;
+use tokio_util::sync::CancellationToken;
+
+#[tokio::test]
+async fn test_register_through_edge_proxy() {
+    let (server_inner, config) = create_test_server().await;
+    let module = RegistrarModule::new(server_inner.clone(), config);
+
+    let mut request = create_register_request("alice", "example.com", Some(60));
+    request.headers.push(Header::Other(
+        "Path".to_string(),
+        "<sip:edge1.example.net:5060;lr>".to_string(),
+    ));
+    request.headers.push(Header::Other(
+        "Path".to_string(),
+        "<sip:edge2.example.net;lr>".to_string(),
+    ));
+    request.headers.push(Header::Supported("path".into()));
+    let (mut tx, _) = create_transaction(request).await;
+    let result = module
+        .on_transaction_begin(
+            CancellationToken::new(),
+            &mut tx,
+            TransactionCookie::default(),
+        )
+        .await
+        .unwrap();
+    assert!(matches!(result, ProxyAction::Abort));
+
+    let locations = server_inner
+        .locator
+        .lookup("alice", Some("example.com"))
+        .await
+        .unwrap();
+    let location = &locations[0];
+    assert_eq!(location.route_set.len(), 2);
+    assert_eq!(
+        location.route_set[0].host().to_string(),
+        "edge1.example.net"
+    );
+    assert_eq!(
+        location.route_header().unwrap().to_string(),
+        "Route: <sip:edge1.example.net:5060;lr>, <sip:edge2.example.net;lr>"
+    );
+}
+
+#[tokio::test]
+async fn test_preloaded_route_set() {
+    let (server_inner, config) = create_test_server().await;
+    let module = CallModule::new(config, server_inner);
+
+    let mut request = create_test_request(rsip::Method::Invite, "alice", None, "example.com", None);
+    request.headers.push(Header::Route(
+        "<sip:example.com;lr>, <sip:core.carrier.net;lr>".into(),
+    ));
+    request
+        .headers
+        .push(Header::Route("<sip:sbc.carrier.net:5080;lr>".into()));
+    let routes = module.preloaded_routes(&request);
+    assert_eq!(
+        routes.iter().map(|uri| uri.to_string()).collect::<Vec<_>>(),
+        vec!["sip:core.carrier.net;lr", "sip:sbc.carrier.net:5080;lr"]
+    );
+}
+
+#[test]
+fn test_parse_route_set() {
+    let routes = parse_route_set("<sip:a.example.net;lr>,sip:b.example.net");
+    assert_eq!(routes.len(), 2);
+    assert_eq!(routes[1].host().to_string(), "b.example.net");
+    assert!(
+        Location::default().route_header().is_none(),
+        "no Route header without a route set"
+    );
+}
@@ -0,0 +1,123 @@
+use super::common::create_udp_test_server;
+use crate::config::ProxyConfig;
+use crate::proxy::presence::PresenceModule;
+use std::{net::SocketAddr, time::Duration};
+use tokio::{net::UdpSocket, time::timeout};
+
+async fn recv(phone: &UdpSocket) -> (String, SocketAddr) {
+    let mut buf = vec![0u8; 4096];
+    let (n, from) = timeout(Duration::from_secs(5), phone.recv_from(&mut buf))
+        .await
+        .expect("no message from the proxy")
+        .unwrap();
+    (String::from_utf8_lossy(&buf[..n]).to_string(), from)
+}
+
+fn header<'a>(message: &'a str, name: &str) -> &'a str {
+    message
+        .lines()
+        .find(|l| l.starts_with(name))
+        .unwrap_or_default()
+}
+
+/// The 200 to the SUBSCRIBE and the NOTIFY that follows it, in any order
+async fn recv_answer_and_notify(phone: &UdpSocket) -> (String, String) {
+    let (first, from) = recv(phone).await;
+    let (second, _) = recv(phone).await;
+    let (answer, notify) = match first.starts_with("SIP/2.0") {
+        true => (first, second),
+        false => (second, first),
+    };
+    accept_notify(phone, &notify, from).await;
+    (answer, notify)
+}
+
+async fn accept_notify(phone: &UdpSocket, notify: &str, to: SocketAddr) {
+    assert!(notify.starts_with("NOTIFY sip:bob@"), "{}", notify);
+    let response = format!(
+        "SIP/2.0 200 OK\r\n{}\r\n{}\r\n{}\r\n{}\r\n{}\r\nContent-Length: 0\r\n\r\n",
+        header(notify, "Via:"),
+        header(notify, "From:"),
+        header(notify, "To:"),
+        header(notify, "Call-ID:"),
+        header(notify, "CSeq:"),
+    );
+    phone.send_to(response.as_bytes(), to).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_presence_subscribe_notify() {
+    let server = create_udp_test_server(
+        ProxyConfig::default(),
+        &[("presence", PresenceModule::create)],
+    )
+    .await;
+    let inner = server.get_inner();
+    let proxy: SocketAddr = format!("127.0.0.1:{}", inner.config.udp_port.unwrap())
+        .parse()
+        .unwrap();
+    let phone = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = phone.local_addr().unwrap();
+    let subscribe = |cseq: u32, to_tag: &str, expires: u32| {
+        format!(
+            "SUBSCRIBE sip:alice@127.0.0.1 SIP/2.0\r\n\
+             Via: SIP/2.0/UDP {addr};branch=z9hG4bKblf{cseq};rport\r\n\
+             From: <sip:bob@127.0.0.1>;tag=bob1\r\n\
+             To: <sip:alice@127.0.0.1>{to_tag}\r\n\
+             Call-ID: blf-1\r\n\
+             CSeq: {cseq} SUBSCRIBE\r\n\
+             Contact: <sip:bob@{addr}>\r\n\
+             Event: dialog\r\n\
+             Expires: {expires}\r\n\
+             Max-Forwards: 70\r\n\
+             Content-Length: 0\r\n\r\n"
+        )
+    };
+
+    phone
+        .send_to(subscribe(1, "", 60).as_bytes(), proxy)
+        .await
+        .unwrap();
+    let (answer, notify) = recv_answer_and_notify(&phone).await;
+    assert!(answer.starts_with("SIP/2.0 200"), "{}", answer);
+    assert!(answer.contains("Expires: 60"));
+    let tag = header(&answer, "To:")
+        .split(";tag=")
+        .nth(1)
+        .expect("the 200 has a To tag")
+        .to_string();
+    assert!(header(&notify, "Subscription-State:").contains("active;expires="));
+    assert!(notify.contains("Event: dialog"));
+    assert!(notify.contains("application/dialog-info+xml"));
+    assert!(!notify.contains("confirmed"));
+
+    // alice goes on a call
+    let call = inner
+        .presence
+        .enter_call(vec!["alice@127.0.0.1".to_string()]);
+    let (notify, from) = recv(&phone).await;
+    accept_notify(&phone, &notify, from).await;
+    assert!(notify.contains("<state>confirmed</state>"));
+    drop(call);
+    let (notify, from) = recv(&phone).await;
+    accept_notify(&phone, &notify, from).await;
+    assert!(!notify.contains("confirmed"));
+
+    // unsubscribing ends the dialog with a last NOTIFY
+    phone
+        .send_to(subscribe(2, &format!(";tag={}", tag), 0).as_bytes(), proxy)
+        .await
+        .unwrap();
+    let (answer, notify) = recv_answer_and_notify(&phone).await;
+    assert!(answer.starts_with("SIP/2.0 200"), "{}", answer);
+    assert!(header(&notify, "Subscription-State:").contains("terminated"));
+
+    // the dialog is gone
+    phone
+        .send_to(subscribe(3, &format!(";tag={}", tag), 60).as_bytes(), proxy)
+        .await
+        .unwrap();
+    let (answer, _) = recv(&phone).await;
+    assert!(answer.starts_with("SIP/2.0 481"), "{}", answer);
+    server.stop();
+}
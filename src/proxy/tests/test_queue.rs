@@ -0,0 +1,263 @@
+use crate::config::{
+    AgentConfig, ProxyConfig, QueueCallbackConfig, QueueConfig, QueueOverflowConfig,
+};
+use crate::proxy::agent::{AgentSessions, AgentState};
+use crate::proxy::presence::PresenceState;
+use crate::proxy::queue::{CallQueue, Queues};
+use crate::proxy::skills::SkillExpr;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn start() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2025, 6, 2, 9, 0, 0).unwrap()
+}
+
+fn queue() -> CallQueue {
+    CallQueue::new(
+        "support".to_string(),
+        QueueConfig {
+            extension: "600".to_string(),
+            agents: vec!["1001".to_string(), "sip:1002@example.com".to_string()],
+            music: None,
+            callback: Some(QueueCallbackConfig {
+                after: 30,
+                ..Default::default()
+            }),
+            requires: None,
+            overflow: Vec::new(),
+            login: false,
+            wrap_up: 0,
+            sla: 20,
+        },
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_queue_callback_keeps_place() {
+    let queue = queue();
+    assert_eq!(queue.join("a", "5550001", "example.com", None, start()), 1);
+    assert_eq!(queue.join("b", "5550002", "example.com", None, start()), 2);
+
+    assert!(!queue.offer_due("a", start() + Duration::seconds(10)));
+    assert!(queue.offer_due("a", start() + Duration::seconds(31)));
+    // offered once
+    assert!(!queue.offer_due("a", start() + Duration::seconds(40)));
+
+    assert!(queue.book_callback("a", "5559999"));
+    queue.leave("a");
+    queue.leave("b");
+    let entries = queue.entries();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].callback.as_deref(), Some("5559999"));
+    assert_eq!(queue.position("a"), Some(1));
+    assert!(!queue.book_callback("b", "5550002"));
+}
+
+#[test]
+fn test_queue_claim_in_order() {
+    let presence = Arc::new(PresenceState::new());
+    let queue = queue();
+    for id in ["a", "b", "c"] {
+        queue.join(id, id, "example.com", None, start());
+    }
+
+    // nobody jumps the line
+    assert!(queue.claim("b", &presence, start()).is_none());
+    let first = queue.claim("a", &presence, start()).unwrap();
+    assert_eq!(first.to_string(), "sip:1001@example.com");
+    let second = queue.claim("b", &presence, start()).unwrap();
+    assert_eq!(second.to_string(), "sip:1002@example.com");
+    assert!(queue.claim("c", &presence, start()).is_none());
+
+    // 1001 did not answer, and is rung again as the only idle agent
+    queue.release("a", &first);
+    assert!(queue.claim("c", &presence, start()).is_none());
+    assert_eq!(queue.claim("a", &presence, start()).unwrap(), first);
+
+    queue.connected("b", &second, start());
+    assert_eq!(queue.position("c"), Some(2));
+    let _busy = presence.enter_call(vec!["1002@example.com".to_string()]);
+    queue.connected("a", &first, start());
+    assert!(queue.claim("c", &presence, start()).is_none());
+    // no wrap-up, 1001 is free again once the call ends
+    queue.finished(&first, start());
+    assert_eq!(
+        queue.claim("c", &presence, start()).unwrap().to_string(),
+        "sip:1001@example.com"
+    );
+}
+
+#[test]
+fn test_queue_due_callbacks() {
+    let presence = Arc::new(PresenceState::new());
+    let queue = queue();
+    queue.join("a", "5550001", "example.com", None, start());
+    queue.join("b", "5550002", "example.com", None, start());
+    queue.book_callback("b", "5550002");
+
+    // the caller ahead waits for an agent first
+    assert!(queue.due_callbacks(&presence, start()).is_empty());
+    let agent = queue.claim("a", &presence, start()).unwrap();
+    let due = queue.due_callbacks(&presence, start());
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].0.id, "b");
+    assert_ne!(due[0].1, agent);
+    assert!(queue.due_callbacks(&presence, start()).is_empty());
+}
+
+#[test]
+fn test_queue_config() {
+    let queues: HashMap<String, QueueConfig> = toml::from_str(
+        r#"
+        [support]
+        extension = "600"
+        agents = ["1001", "1002"]
+        music = "sounds/hold.wav"
+
+        [support.callback]
+        after = 45
+        offer = [{ type = "file", url = "sounds/offer.wav" }]
+        "#,
+    )
+    .unwrap();
+    let config = ProxyConfig {
+        queues,
+        ..Default::default()
+    };
+    let queues = Queues::new(&config, Arc::new(AgentSessions::default())).unwrap();
+    let queue = queues.by_extension("600").unwrap();
+    assert_eq!(queue.name, "support");
+    let callback = queue.callback().unwrap();
+    assert_eq!(callback.after, 45);
+    assert_eq!(callback.key, "1");
+    assert_eq!(callback.confirm.len(), 1);
+    assert!(queues.by_extension("601").is_none());
+}
+
+#[test]
+fn test_skill_expr() {
+    let expr = SkillExpr::parse("lang:es & (billing | Sales) & !trainee")
+        .unwrap()
+        .unwrap();
+    let skills = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    assert!(expr.matches(&skills(&["lang:es", "sales"])));
+    assert!(!expr.matches(&skills(&["lang:es", "sales", "trainee"])));
+    assert!(!expr.matches(&skills(&["lang:en", "billing"])));
+    assert!(!expr.matches(&skills(&["lang:es"])));
+
+    // & binds tighter than |
+    let expr = SkillExpr::parse("a | b & c").unwrap().unwrap();
+    assert_eq!(expr.to_string(), "(a | (b & c))");
+    assert!(expr.matches(&skills(&["a"])));
+
+    assert!(SkillExpr::parse("  ").unwrap().is_none());
+    assert!(SkillExpr::parse("a &").is_err());
+    assert!(SkillExpr::parse("(a | b").is_err());
+    assert!(SkillExpr::parse("a b").is_err());
+    assert!(SkillExpr::parse("a = b").is_err());
+}
+
+#[test]
+fn test_queue_skills() {
+    let presence = Arc::new(PresenceState::new());
+    let agents = HashMap::from([
+        (
+            "1001".to_string(),
+            AgentConfig {
+                skills: vec!["lang:en".to_string()],
+            },
+        ),
+        (
+            "sip:1002@example.com".to_string(),
+            AgentConfig {
+                skills: vec!["lang:es".to_string(), "billing".to_string()],
+            },
+        ),
+    ]);
+    let queue = CallQueue::new(
+        "support".to_string(),
+        QueueConfig {
+            extension: "600".to_string(),
+            agents: vec!["1001".to_string(), "1002".to_string()],
+            music: None,
+            callback: None,
+            requires: Some("billing | lang:en".to_string()),
+            overflow: vec![QueueOverflowConfig {
+                after: 60,
+                requires: String::new(),
+            }],
+            login: false,
+            wrap_up: 0,
+            sla: 20,
+        },
+    )
+    .unwrap()
+    .with_agents(Arc::new(agents));
+    let es = SkillExpr::parse("lang:es").unwrap();
+    let de = SkillExpr::parse("lang:de").unwrap();
+    queue.join("de", "5550001", "example.com", de, start());
+    queue.join("es", "5550002", "example.com", es, start());
+    queue.join("any", "5550003", "example.com", None, start());
+
+    // nobody speaks German, the others are served past the caller
+    let now = start() + Duration::seconds(5);
+    assert!(queue.claim("de", &presence, now).is_none());
+    assert_eq!(
+        queue.claim("any", &presence, now).unwrap().to_string(),
+        "sip:1001@example.com"
+    );
+    let agent = queue.claim("es", &presence, now).unwrap();
+    assert_eq!(agent.to_string(), "sip:1002@example.com");
+
+    // after the overflow time any agent takes the call, ahead of later callers
+    queue.release("es", &agent);
+    queue.join(
+        "late",
+        "5550004",
+        "example.com",
+        None,
+        start() + Duration::seconds(30),
+    );
+    let now = start() + Duration::seconds(61);
+    assert!(queue.claim("late", &presence, now).is_none());
+    assert_eq!(queue.claim("de", &presence, now).unwrap(), agent);
+}
+
+#[test]
+fn test_queue_board() {
+    let presence = Arc::new(PresenceState::new());
+    let queue = queue();
+    let board = queue.board(&presence, start());
+    assert_eq!(board.waiting, 0);
+    assert_eq!(board.service_level, None);
+    assert_eq!(board.agents.get(&AgentState::Available), Some(&2));
+
+    for id in ["a", "b", "c"] {
+        queue.join(id, id, "example.com", None, start());
+    }
+    let agent = queue.claim("a", &presence, start()).unwrap();
+    queue.connected("a", &agent, start() + Duration::seconds(10));
+    queue.leave("b");
+    let _busy = presence.enter_call(vec!["1002@example.com".to_string()]);
+
+    let board = queue.board(&presence, start() + Duration::seconds(60));
+    assert_eq!(board.waiting, 1);
+    assert_eq!(board.longest_wait, 60);
+    assert_eq!(board.stats.offered, 3);
+    assert_eq!(board.stats.answered, 1);
+    assert_eq!(board.stats.abandoned, 1);
+    assert_eq!(board.service_level, Some(50.0));
+    assert_eq!(board.agents.get(&AgentState::OnCall), Some(&2));
+
+    queue.finished(&agent, start() + Duration::seconds(60));
+    assert!(queue.book_callback("c", "5550003"));
+    queue.leave("c");
+    let board = queue.board(&presence, start() + Duration::seconds(90));
+    assert_eq!((board.waiting, board.callbacks), (0, 1));
+    assert_eq!(board.longest_wait, 0);
+    assert_eq!(board.stats.abandoned, 1);
+    assert_eq!(board.agents.get(&AgentState::Available), Some(&1));
+    assert_eq!(board.agents.get(&AgentState::OnCall), Some(&1));
+}
@@ -0,0 +1,178 @@
+use crate::config::RadiusConfig;
+use crate::proxy::radius::*;
+use tokio::net::UdpSocket;
+
+const SECRET: &str = "testing123";
+
+/// Answers each request with `code`, and returns the requests it got
+async fn radius_server(
+    code: u8,
+    count: usize,
+) -> (String, tokio::task::JoinHandle<Vec<RadiusPacket>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap().to_string();
+    let handle = tokio::spawn(async move {
+        let mut requests = Vec::new();
+        let mut buf = vec![0u8; 4096];
+        while requests.len() < count {
+            let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+            let request = RadiusPacket::decode(&buf[..n]).unwrap();
+            let mut response = RadiusPacket::new(code, request.identifier);
+            response.authenticator = response.compute_authenticator(&request.authenticator, SECRET);
+            socket.send_to(&response.encode(), from).await.unwrap();
+            requests.push(request);
+        }
+        requests
+    });
+    (addr, handle)
+}
+
+fn call() -> RadiusCall {
+    RadiusCall {
+        session_id: "call-1@example.com".to_string(),
+        caller: "5550001".to_string(),
+        callee: "18005551234".to_string(),
+    }
+}
+
+#[test]
+fn test_radius_packet() {
+    let mut packet = RadiusPacket::new(ACCOUNTING_REQUEST, 7)
+        .with_u32(ACCT_STATUS_TYPE, STATUS_START)
+        .with_attribute(USER_NAME, "alice");
+    packet.sign_accounting(SECRET);
+    let decoded = RadiusPacket::decode(&packet.encode()).unwrap();
+    assert_eq!(decoded, packet);
+    assert_eq!(decoded.attribute_u32(ACCT_STATUS_TYPE), Some(STATUS_START));
+    assert_eq!(decoded.attribute_str(USER_NAME), Some("alice"));
+    assert_eq!(
+        decoded.compute_authenticator(&[0; 16], SECRET),
+        decoded.authenticator
+    );
+
+    assert!(RadiusPacket::decode(&[1, 2, 0, 10]).is_err());
+    let mut data = packet.encode();
+    data[21] = 200;
+    assert!(RadiusPacket::decode(&data).is_err());
+
+    let authenticator = [9u8; 16];
+    for password in ["", "secret", "a password longer than sixteen bytes"] {
+        let cipher = encrypt_password(password.as_bytes(), SECRET, &authenticator);
+        assert_eq!(cipher.len() % 16, 0);
+        assert_ne!(cipher, password.as_bytes());
+        assert_eq!(
+            decrypt_password(&cipher, SECRET, &authenticator),
+            password.as_bytes()
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_radius_authorize() {
+    let (addr, server) = radius_server(ACCESS_ACCEPT, 1).await;
+    let client = RadiusClient::new(RadiusConfig {
+        auth: Some(addr),
+        secret: SECRET.to_string(),
+        password: Some("trunkpass".to_string()),
+        timeout: 1,
+        ..Default::default()
+    });
+    assert!(client.authorize(&call()).await.unwrap());
+    let request = server.await.unwrap().remove(0);
+    assert_eq!(request.code, ACCESS_REQUEST);
+    assert_eq!(request.attribute_str(USER_NAME), Some("5550001"));
+    assert_eq!(
+        request.attribute_str(CALLED_STATION_ID),
+        Some("18005551234")
+    );
+    assert_eq!(request.attribute_str(NAS_IDENTIFIER), Some("rustpbx"));
+    let password = request.attribute(USER_PASSWORD).unwrap();
+    assert_eq!(
+        decrypt_password(password, SECRET, &request.authenticator),
+        b"trunkpass"
+    );
+
+    let (addr, _server) = radius_server(ACCESS_REJECT, 1).await;
+    let client = RadiusClient::new(RadiusConfig {
+        auth: Some(addr),
+        secret: SECRET.to_string(),
+        timeout: 1,
+        ..Default::default()
+    });
+    assert!(!client.authorize(&call()).await.unwrap());
+
+    // signed with another secret, never accepted
+    let (addr, _server) = radius_server(ACCESS_ACCEPT, 2).await;
+    let client = RadiusClient::new(RadiusConfig {
+        auth: Some(addr),
+        secret: "other".to_string(),
+        timeout: 1,
+        retries: 1,
+        ..Default::default()
+    });
+    assert!(client.authorize(&call()).await.is_err());
+
+    // no auth server, nothing to ask
+    let client = RadiusClient::new(RadiusConfig::default());
+    assert!(client.authorize(&call()).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_radius_accounting() {
+    let (addr, server) = radius_server(ACCOUNTING_RESPONSE, 2).await;
+    let client = RadiusClient::new(RadiusConfig {
+        accounting: Some(addr),
+        secret: SECRET.to_string(),
+        nas_identifier: Some("pbx-1".to_string()),
+        timeout: 1,
+        ..Default::default()
+    });
+    client.account(&call(), None).await.unwrap();
+    client
+        .account(&call(), Some((125, CAUSE_USER_REQUEST)))
+        .await
+        .unwrap();
+    let requests = server.await.unwrap();
+    for request in requests.iter() {
+        assert_eq!(request.code, ACCOUNTING_REQUEST);
+        assert_eq!(
+            request.compute_authenticator(&[0; 16], SECRET),
+            request.authenticator
+        );
+        assert_eq!(
+            request.attribute_str(ACCT_SESSION_ID),
+            Some("call-1@example.com")
+        );
+        assert_eq!(request.attribute_str(CALLING_STATION_ID), Some("5550001"));
+        assert_eq!(request.attribute_str(NAS_IDENTIFIER), Some("pbx-1"));
+    }
+    assert_eq!(
+        requests[0].attribute_u32(ACCT_STATUS_TYPE),
+        Some(STATUS_START)
+    );
+    assert_eq!(requests[0].attribute(ACCT_SESSION_TIME), None);
+    assert_eq!(
+        requests[1].attribute_u32(ACCT_STATUS_TYPE),
+        Some(STATUS_STOP)
+    );
+    assert_eq!(requests[1].attribute_u32(ACCT_SESSION_TIME), Some(125));
+    assert_eq!(
+        requests[1].attribute_u32(ACCT_TERMINATE_CAUSE),
+        Some(CAUSE_USER_REQUEST)
+    );
+}
+
+#[test]
+fn test_radius_terminate_cause() {
+    use crate::call::HangupCause;
+    use crate::callrecord::CallRecordHangupReason;
+    assert_eq!(
+        terminate_cause(Some(&CallRecordHangupReason::ByCallee), None),
+        CAUSE_USER_REQUEST
+    );
+    assert_eq!(
+        terminate_cause(None, Some(HangupCause::NetworkOutOfOrder)),
+        CAUSE_SERVICE_UNAVAILABLE
+    );
+    assert_eq!(terminate_cause(None, None), CAUSE_NAS_REQUEST);
+}
@@ -0,0 +1,139 @@
+use super::sim::{SimEndpoint, SimScript};
+use crate::app::AppStateBuilder;
+use crate::config::{MediaProxyMode, ProxyConfig};
+use crate::proxy::call::CallModule;
+use crate::proxy::locator::MemoryLocator;
+use crate::proxy::routing::{RouteRule, TrunkConfig};
+use crate::proxy::server::SipServerBuilder;
+use crate::proxy::user::MemoryUserBackend;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn ms(ms: u64) -> Duration {
+    Duration::from_millis(ms)
+}
+
+#[tokio::test]
+async fn test_sim_call_plays_and_dials() {
+    let script = SimScript::new()
+        .answer_after(ms(200))
+        .play(ms(0), "fixtures/sample.wav")
+        .unwrap()
+        .dtmf(ms(300), "42#");
+    let mut callee = SimEndpoint::start(script).await.unwrap();
+    let caller = SimEndpoint::start(SimScript::new()).await.unwrap();
+
+    let report = caller
+        .call(
+            &callee.uri("bob"),
+            SimScript::new().dtmf(ms(100), "123").hangup(ms(1000)),
+        )
+        .await
+        .unwrap();
+    assert_eq!(report.status, 200);
+    assert!(report.setup >= ms(200));
+    assert_eq!(report.digits, "42#");
+    assert!(report.rtp_received > 30);
+    assert!(!report.hangup_by_peer);
+
+    let report = callee.next_report().await.unwrap();
+    assert_eq!(report.status, 200);
+    assert_eq!(report.digits, "123");
+    assert!(report.hangup_by_peer);
+}
+
+#[tokio::test]
+async fn test_sim_reject_and_drop_rtp() {
+    let busy = SimEndpoint::start(SimScript::new().reject(486))
+        .await
+        .unwrap();
+    let caller = SimEndpoint::start(SimScript::new()).await.unwrap();
+    let report = caller
+        .call(&busy.uri("bob"), SimScript::new())
+        .await
+        .unwrap();
+    assert_eq!(report.status, 486);
+
+    // the callee's media stops long before the caller hangs up
+    let silent = SimEndpoint::start(SimScript::new().drop_rtp(ms(200)))
+        .await
+        .unwrap();
+    let report = caller
+        .call(&silent.uri("bob"), SimScript::new().hangup(ms(1000)))
+        .await
+        .unwrap();
+    assert_eq!(report.status, 200);
+    assert!(report.last_rtp.unwrap() < ms(500));
+    assert!(report.rtp_sent >= 40);
+}
+
+#[tokio::test]
+async fn test_sim_dialplan_to_trunk() {
+    let mut carrier = SimEndpoint::start(SimScript::new().answer_after(ms(100)))
+        .await
+        .unwrap();
+    let mut config = ProxyConfig {
+        addr: "127.0.0.1".to_string(),
+        udp_port: Some(portpicker::pick_unused_port().unwrap()),
+        modules: Some(vec!["call".to_string()]),
+        media_proxy: MediaProxyMode::None,
+        ..Default::default()
+    };
+    config.trunks.insert(
+        "carrier".to_string(),
+        TrunkConfig {
+            dest: format!("sip:{}", carrier.addr()),
+            transport: Some("udp".to_string()),
+            ..Default::default()
+        },
+    );
+    config.routes = Some(vec![
+        toml::from_str::<RouteRule>(
+            r#"
+            name = "outbound"
+            dest = "carrier"
+            [match]
+            "to.user" = "^9"
+            "#,
+        )
+        .unwrap(),
+    ]);
+    let proxy_addr = format!("127.0.0.1:{}", config.udp_port.unwrap())
+        .parse()
+        .unwrap();
+    let app_state = AppStateBuilder::new()
+        .with_config(crate::config::Config {
+            ua: None,
+            ..Default::default()
+        })
+        .build()
+        .await
+        .unwrap()
+        .0;
+    let server = SipServerBuilder::new(Arc::new(config))
+        .with_user_backend(Box::new(MemoryUserBackend::new(None)))
+        .with_locator(Box::new(MemoryLocator::new()))
+        .register_module("call", CallModule::create)
+        .build(app_state)
+        .await
+        .unwrap();
+    let serving = server.clone();
+    tokio::spawn(async move { serving.serve().await });
+
+    let phone = SimEndpoint::start(SimScript::new()).await.unwrap();
+    let report = phone
+        .call_via(
+            proxy_addr,
+            "sip:95551234@pstn.example.com",
+            SimScript::new().dtmf(ms(100), "7").hangup(ms(600)),
+        )
+        .await
+        .unwrap();
+    assert_eq!(report.status, 200);
+    assert!(report.rtp_received > 0);
+
+    let report = carrier.next_report().await.unwrap();
+    assert_eq!(report.digits, "7");
+    assert!(report.hangup_by_peer);
+    server.stop();
+}
@@ -0,0 +1,90 @@
+use super::common::create_udp_test_server;
+use crate::config::ProxyConfig;
+use crate::proxy::routing::TrunkConfig;
+use crate::proxy::status::ProxyStatus;
+use crate::proxy::trunk_monitor::check_trunk;
+use tokio::net::UdpSocket;
+
+/// Answers every OPTIONS with the next status in `statuses`
+async fn mock_trunk(statuses: Vec<&'static str>) -> String {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 4096];
+        for status in statuses {
+            let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+            let request = String::from_utf8_lossy(&buf[..n]).to_string();
+            assert!(request.starts_with("OPTIONS sip:"));
+            let header = |name: &str| {
+                request
+                    .lines()
+                    .find(|l| l.starts_with(name))
+                    .unwrap()
+                    .to_string()
+            };
+            let response = format!(
+                "SIP/2.0 {}\r\n{}\r\n{}\r\n{};tag=trunk\r\n{}\r\n{}\r\nContent-Length: 0\r\n\r\n",
+                status,
+                header("Via:"),
+                header("From:"),
+                header("To:"),
+                header("Call-ID:"),
+                header("CSeq:"),
+            );
+            socket.send_to(response.as_bytes(), from).await.unwrap();
+        }
+    });
+    format!("sip:{}", addr)
+}
+
+#[tokio::test]
+async fn test_trunk_up_and_down() {
+    let dest = mock_trunk(vec![
+        "200 OK",
+        "503 Service Unavailable",
+        "503 Service Unavailable",
+        "503 Service Unavailable",
+        "404 Not Found",
+    ])
+    .await;
+    let trunk = TrunkConfig {
+        dest,
+        transport: Some("udp".to_string()),
+        options_interval: Some(30),
+        ..Default::default()
+    };
+    // checked by hand rather than by the server's own monitor
+    let server = create_udp_test_server(ProxyConfig::default(), &[]).await;
+    let inner = server.get_inner();
+    let mut status = inner.proxy_status.subscribe();
+
+    let health = check_trunk(&inner, "carrier", &trunk).await;
+    assert!(health.up);
+    assert!(health.latency_ms.is_some());
+    assert!(matches!(
+        status.try_recv().unwrap(),
+        ProxyStatus::TrunkUp { ref trunk, .. } if trunk == "carrier"
+    ));
+
+    // stays up until enough consecutive checks fail
+    for failures in 1..=3 {
+        let health = check_trunk(&inner, "carrier", &trunk).await;
+        assert_eq!(health.failures, failures);
+        assert_eq!(health.up, failures < 3);
+        assert_eq!(inner.routing_state.is_trunk_up("carrier"), failures < 3);
+    }
+    assert!(matches!(
+        status.try_recv().unwrap(),
+        ProxyStatus::TrunkDown { ref trunk, .. } if trunk == "carrier"
+    ));
+
+    // any other answer means the trunk is reachable again
+    let health = check_trunk(&inner, "carrier", &trunk).await;
+    assert!(health.up);
+    assert_eq!(health.failures, 0);
+    assert!(matches!(
+        status.try_recv().unwrap(),
+        ProxyStatus::TrunkUp { .. }
+    ));
+    server.stop();
+}
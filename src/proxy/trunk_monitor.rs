@@ -0,0 +1,187 @@
+use super::{
+    routing::{TrunkConfig, TrunkHealth},
+    server::{SipServerInner, SipServerRef},
+    status::ProxyStatus,
+};
+use anyhow::{Result, anyhow};
+use axum::{
+    Json,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use rsipstack::transaction::{
+    key::{TransactionKey, TransactionRole},
+    make_tag,
+    transaction::Transaction,
+};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// Consecutive failed checks before a trunk is marked down
+const MAX_FAILURES: u32 = 3;
+const OPTIONS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Starts an OPTIONS health check loop for each trunk with `options_interval`
+pub fn start_trunk_monitor(server: &SipServerRef) {
+    for (name, trunk) in server.config.trunks.iter() {
+        let interval = match trunk.options_interval {
+            Some(interval) if trunk.disabled != Some(true) => interval.max(1),
+            _ => continue,
+        };
+        info!(
+            trunk = name,
+            dest = trunk.dest,
+            interval,
+            "monitoring trunk"
+        );
+        let server = server.clone();
+        let name = name.clone();
+        let trunk = trunk.clone();
+        let token = server.cancel_token.child_token();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = ticker.tick() => {
+                        check_trunk(&server, &name, &trunk).await;
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Sends one OPTIONS to the trunk and updates its health in the routing state
+pub async fn check_trunk(server: &SipServerInner, name: &str, trunk: &TrunkConfig) -> TrunkHealth {
+    let start_time = Instant::now();
+    let result = match tokio::time::timeout(OPTIONS_TIMEOUT, send_options(server, trunk)).await {
+        Ok(Ok(status)) => match status.kind() {
+            // any answer proves the trunk is alive, except overload and timeouts
+            rsip::StatusCodeKind::ServerFailure => Err(anyhow!("{}", status)),
+            _ if status == rsip::StatusCode::RequestTimeout => Err(anyhow!("{}", status)),
+            _ => Ok(()),
+        },
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(anyhow!("timeout after {:?}", OPTIONS_TIMEOUT)),
+    };
+
+    let previous = server.routing_state.get_trunk_health(name);
+    let was_up = previous.as_ref().map(|h| h.up).unwrap_or(true);
+    let health = match result {
+        Ok(()) => {
+            let latency_ms = start_time.elapsed().as_millis() as u64;
+            debug!(trunk = name, latency_ms, "trunk answered OPTIONS");
+            if previous.is_none() || !was_up {
+                info!(trunk = name, latency_ms, "trunk is up");
+                server
+                    .proxy_status
+                    .send(ProxyStatus::TrunkUp {
+                        trunk: name.to_string(),
+                        timestamp: crate::get_timestamp(),
+                        latency_ms,
+                    })
+                    .ok();
+            }
+            TrunkHealth {
+                up: true,
+                latency_ms: Some(latency_ms),
+                failures: 0,
+                last_checked: Utc::now(),
+            }
+        }
+        Err(e) => {
+            let failures = previous.as_ref().map(|h| h.failures).unwrap_or(0) + 1;
+            let up = was_up && failures < MAX_FAILURES;
+            warn!(trunk = name, failures, "trunk OPTIONS failed: {}", e);
+            if was_up && !up {
+                warn!(trunk = name, "trunk is down");
+                server
+                    .proxy_status
+                    .send(ProxyStatus::TrunkDown {
+                        trunk: name.to_string(),
+                        timestamp: crate::get_timestamp(),
+                        reason: e.to_string(),
+                    })
+                    .ok();
+            }
+            TrunkHealth {
+                up,
+                latency_ms: None,
+                failures,
+                last_checked: Utc::now(),
+            }
+        }
+    };
+    server.routing_state.set_trunk_health(name, health.clone());
+    health
+}
+
+async fn send_options(server: &SipServerInner, trunk: &TrunkConfig) -> Result<rsip::StatusCode> {
+    let dest_uri: rsip::Uri = trunk
+        .dest
+        .as_str()
+        .try_into()
+        .map_err(|e| anyhow!("Invalid trunk destination '{}': {:?}", trunk.dest, e))?;
+    let destination = trunk.dest_addr()?;
+    let via = server.endpoint.inner.get_via(None, None)?;
+    let from = rsip::typed::From {
+        display_name: None,
+        uri: rsip::Uri {
+            scheme: Some(rsip::Scheme::Sip),
+            auth: trunk.username.as_ref().map(|user| rsip::Auth {
+                user: user.clone(),
+                password: None,
+            }),
+            host_with_port: via.uri.host_with_port.clone(),
+            ..Default::default()
+        },
+        params: vec![],
+    }
+    .with_tag(make_tag());
+    let to = rsip::typed::To {
+        display_name: None,
+        uri: dest_uri.clone(),
+        params: vec![],
+    };
+    let request =
+        server
+            .endpoint
+            .inner
+            .make_request(rsip::Method::Options, dest_uri, via, from, to, 1);
+
+    let key = TransactionKey::from_request(&request, TransactionRole::Client)?;
+    let mut tx = Transaction::new_client(key, request, server.endpoint.inner.clone(), None);
+    tx.destination = Some(destination);
+    tx.send().await?;
+    while let Some(msg) = tx.receive().await {
+        if let rsip::SipMessage::Response(resp) = msg {
+            if matches!(resp.status_code.kind(), rsip::StatusCodeKind::Provisional) {
+                continue;
+            }
+            return Ok(resp.status_code);
+        }
+    }
+    Ok(rsip::StatusCode::RequestTimeout)
+}
+
+/// `GET /ami/v1/trunks` handler
+pub async fn trunk_health_handler(server: SipServerRef) -> Response {
+    let trunks = server
+        .config
+        .trunks
+        .iter()
+        .map(|(name, trunk)| {
+            (
+                name.clone(),
+                serde_json::json!({
+                    "dest": trunk.dest,
+                    "disabled": trunk.disabled.unwrap_or(false),
+                    "monitored": trunk.options_interval.is_some(),
+                    "health": server.routing_state.get_trunk_health(name),
+                }),
+            )
+        })
+        .collect::<serde_json::Map<_, _>>();
+    Json(trunks).into_response()
+}
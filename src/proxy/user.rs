@@ -24,6 +24,10 @@ pub trait UserBackend: Send + Sync {
     async fn create_user(&self, _user: SipUser) -> Result<()> {
         Ok(())
     }
+    /// Whether the backend's store can be reached, for the readiness probe
+    async fn check(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub struct MemoryUserBackend {
@@ -53,6 +53,13 @@ impl DbBackend {
 
 #[async_trait]
 impl UserBackend for DbBackend {
+    async fn check(&self) -> Result<()> {
+        sqlx::query("SELECT 1")
+            .execute(&self.db)
+            .await
+            .map_err(|e| anyhow!("Database query error: {}", e))?;
+        Ok(())
+    }
     async fn is_same_realm(&self, realm: &str) -> bool {
         if let Some(ref realm_col) = self.config.realm_column {
             let query = format!(
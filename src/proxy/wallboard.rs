@@ -0,0 +1,117 @@
+use super::{queue::QueueBoard, server::SipServerRef};
+use axum::{
+    Json, Router,
+    extract::{
+        State, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    response::{IntoResponse, Response},
+    routing::get,
+};
+use chrono::{DateTime, Utc};
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+const DEFAULT_INTERVAL: u64 = 5;
+
+/// The figures of every queue at one time
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Wallboard {
+    pub timestamp: DateTime<Utc>,
+    pub queues: Vec<QueueBoard>,
+}
+
+impl Wallboard {
+    pub fn snapshot(server: &SipServerRef, now: DateTime<Utc>) -> Self {
+        Self {
+            timestamp: now,
+            queues: server
+                .queues
+                .list()
+                .iter()
+                .map(|queue| queue.board(&server.presence, now))
+                .collect(),
+        }
+    }
+}
+
+/// Publishes a wallboard every `wallboard_interval` seconds while anyone
+/// is watching
+pub async fn run_wallboard(server: SipServerRef, token: CancellationToken) {
+    let interval = server
+        .config
+        .wallboard_interval
+        .unwrap_or(DEFAULT_INTERVAL)
+        .max(1);
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => break,
+            _ = ticker.tick() => {}
+        }
+        if server.queues.watchers() > 0 {
+            server
+                .queues
+                .publish(Arc::new(Wallboard::snapshot(&server, Utc::now())));
+        }
+    }
+}
+
+pub fn router(server: SipServerRef) -> Router {
+    Router::new()
+        .route("/ami/v1/wallboard", get(wallboard_handler))
+        .route("/ami/v1/wallboard/ws", get(wallboard_ws_handler))
+        .with_state(server)
+}
+
+async fn wallboard_handler(State(server): State<SipServerRef>) -> Response {
+    Json(Wallboard::snapshot(&server, Utc::now())).into_response()
+}
+
+async fn wallboard_ws_handler(
+    State(server): State<SipServerRef>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    ws.on_upgrade(async move |socket| serve_wallboard_ws(server, socket).await)
+}
+
+/// Sends the current wallboard, then each one published
+async fn serve_wallboard_ws(server: SipServerRef, socket: WebSocket) {
+    let (mut sink, mut stream) = socket.split();
+    let mut boards = server.queues.subscribe();
+    let mut board = Arc::new(Wallboard::snapshot(&server, Utc::now()));
+    'serve: loop {
+        let text = serde_json::to_string(board.as_ref()).unwrap_or_default();
+        if sink.send(Message::Text(text.into())).await.is_err() {
+            break;
+        }
+        board = loop {
+            tokio::select! {
+                _ = server.cancel_token.cancelled() => break 'serve,
+                next = next_board(&mut boards) => match next {
+                    Some(board) => break board,
+                    None => break 'serve,
+                },
+                message = stream.next() => match message {
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break 'serve,
+                    Some(Ok(_)) => {}
+                },
+            }
+        };
+    }
+}
+
+async fn next_board(boards: &mut broadcast::Receiver<Arc<Wallboard>>) -> Option<Arc<Wallboard>> {
+    loop {
+        match boards.recv().await {
+            Ok(board) => return Some(board),
+            Err(RecvError::Lagged(n)) => warn!(n, "wallboard websocket lagged"),
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
@@ -1,14 +1,9 @@
+use super::{parser::parse_and_report, server::SipServerRef};
 use crate::handler::middleware::clientaddr::ClientAddr;
 use axum::extract::ws::{Message, WebSocket};
 use futures::{SinkExt, StreamExt};
-use rsip::{
-    SipMessage,
-    prelude::{HeadersExt, UntypedHeader},
-};
-use rsipstack::{
-    transaction::endpoint::EndpointInnerRef,
-    transport::{SipAddr, SipConnection, TransportEvent, channel::ChannelConnection},
-};
+use rsip::prelude::{HeadersExt, UntypedHeader};
+use rsipstack::transport::{SipAddr, SipConnection, TransportEvent, channel::ChannelConnection};
 use tokio::{select, sync::mpsc};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
@@ -17,7 +12,7 @@ pub async fn sip_ws_handler(
     token: CancellationToken,
     client_addr: ClientAddr,
     socket: WebSocket,
-    endpoint_ref: EndpointInnerRef,
+    server: SipServerRef,
 ) {
     let (mut ws_sink, mut ws_read) = socket.split();
     let (from_ws_tx, from_ws_rx) = mpsc::unbounded_channel();
@@ -54,6 +49,7 @@ pub async fn sip_ws_handler(
         "created WebSocket channel connection"
     );
 
+    let endpoint_ref = server.endpoint.inner.clone();
     endpoint_ref
         .transport_layer
         .add_connection(sip_connection.clone());
@@ -61,69 +57,53 @@ pub async fn sip_ws_handler(
     // Use select! instead of spawning multiple tasks
     let local_addr_clone = local_addr.clone();
     let sip_connection_clone = sip_connection.clone();
+    let status = server.proxy_status.clone();
+    let parse_mode = server.config.sip_parse_mode;
     let read_from_websocket_loop = async move {
-        while let Some(Ok(message)) = ws_read.next().await {
-            match message {
-                Message::Text(text) => {
-                    let text = text.to_string();
-                    match SipMessage::try_from(text.as_str()) {
-                        Ok(sip_msg) => {
-                            debug!(
-                                addr = %local_addr_clone,
-                                cseq = sip_msg.cseq_header().ok().map(|c| c.value()).unwrap_or_default(),
-                                "WebSocket received: \n{}",
-                                text
-                            );
-                            let msg = match SipConnection::update_msg_received(
-                                sip_msg,
-                                client_addr.addr,
-                                transport_type,
-                            ) {
-                                Ok(msg) => msg,
-                                Err(e) => {
-                                    warn!(addr = %local_addr_clone, "error updating SIP via: {}", e);
-                                    continue;
-                                }
-                            };
-                            if let Err(e) = from_ws_tx.send(TransportEvent::Incoming(
-                                msg,
-                                sip_connection_clone.clone(),
-                                local_addr_clone.clone(),
-                            )) {
-                                warn!(addr = %local_addr_clone, "error forwarding message to transport: {}", e);
-                                break;
-                            }
-                        }
-                        Err(e) => {
-                            warn!(addr = %local_addr_clone, "error parsing SIP message from WebSocket: {}", e);
-                        }
-                    }
-                }
-                Message::Binary(bin) => match SipMessage::try_from(bin) {
-                    Ok(sip_msg) => {
-                        debug!(
-                            addr = %local_addr_clone,
-                            "WebSocket received binary SIP message: \n{}",
-                            sip_msg.to_string()
-                        );
-                        if let Err(e) = from_ws_tx.send(TransportEvent::Incoming(
-                            sip_msg,
-                            sip_connection_clone.clone(),
-                            local_addr_clone.clone(),
-                        )) {
-                            warn!(addr = %local_addr_clone, "error forwarding binary message to transport: {:?}", e);
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        warn!(addr = %local_addr_clone, "error parsing binary SIP message from WebSocket: {}", e);
-                    }
-                },
-                Message::Close(_) => {
+        let source = client_addr.addr.to_string();
+        let transport = transport_type.to_string();
+        loop {
+            let raw = match ws_read.next().await {
+                Some(Ok(Message::Text(text))) => text.as_bytes().to_vec(),
+                Some(Ok(Message::Binary(bin))) => bin.to_vec(),
+                Some(Ok(Message::Close(_))) => {
                     debug!(addr = %local_addr_clone, "WebSocket connection closed by client");
                     break;
                 }
-                Message::Ping(_) | Message::Pong(_) => {}
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                Some(Err(e)) => {
+                    warn!(addr = %local_addr_clone, "error reading from WebSocket: {}", e);
+                    break;
+                }
+                None => break,
+            };
+            // a malformed message is reported and skipped, the connection stays up
+            let Some(sip_msg) = parse_and_report(&status, parse_mode, &raw, &source, &transport)
+            else {
+                continue;
+            };
+            debug!(
+                addr = %local_addr_clone,
+                cseq = sip_msg.cseq_header().ok().map(|c| c.value()).unwrap_or_default(),
+                "WebSocket received: \n{}",
+                sip_msg
+            );
+            let msg =
+                match SipConnection::update_msg_received(sip_msg, client_addr.addr, transport_type)
+                {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        warn!(addr = %local_addr_clone, "error updating SIP via: {}", e);
+                        continue;
+                    }
+                };
+            if let Err(e) = from_ws_tx.send(TransportEvent::Incoming(
+                msg,
+                sip_connection_clone.clone(),
+                local_addr_clone.clone(),
+            )) {
+                warn!(addr = %local_addr_clone, "error forwarding message to transport: {}", e);
+                break;
             }
         }
     };
@@ -6,10 +6,13 @@ use std::collections::HashMap;
 use tokio::sync::mpsc;
 use tokio_util::sync::CancellationToken;
 mod aliyun;
+mod mrcp;
+pub mod prompt;
 mod tencent_cloud;
 mod voiceapi;
 
 pub use aliyun::AliyunTtsClient;
+pub use mrcp::MrcpTtsClient;
 pub use tencent_cloud::TencentCloudTtsClient;
 // pub use tencent_cloud_streaming::TencentCloudStreamingTtsClient;
 pub use voiceapi::VoiceApiTtsClient;
@@ -36,6 +39,8 @@ pub enum SynthesisType {
     VoiceApi,
     #[serde(rename = "aliyun")]
     Aliyun,
+    #[serde(rename = "mrcp")]
+    Mrcp,
     #[serde(rename = "other")]
     Other(String),
 }
@@ -46,11 +51,19 @@ impl std::fmt::Display for SynthesisType {
             SynthesisType::TencentCloud => write!(f, "tencent"),
             SynthesisType::VoiceApi => write!(f, "voiceapi"),
             SynthesisType::Aliyun => write!(f, "aliyun"),
+            SynthesisType::Mrcp => write!(f, "mrcp"),
             SynthesisType::Other(provider) => write!(f, "{}", provider),
         }
     }
 }
 
+impl SynthesisType {
+    /// Whether the provider reads SSML sent as the text
+    pub fn supports_ssml(&self) -> bool {
+        matches!(self, SynthesisType::TencentCloud | SynthesisType::Mrcp)
+    }
+}
+
 impl<'de> Deserialize<'de> for SynthesisType {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -61,6 +74,7 @@ impl<'de> Deserialize<'de> for SynthesisType {
             "tencent" => Ok(SynthesisType::TencentCloud),
             "voiceapi" => Ok(SynthesisType::VoiceApi),
             "aliyun" => Ok(SynthesisType::Aliyun),
+            "mrcp" => Ok(SynthesisType::Mrcp),
             _ => Ok(SynthesisType::Other(value)),
         }
     }
@@ -276,6 +290,10 @@ pub fn create_synthesis_client(option: SynthesisOption) -> Result<Box<dyn Synthe
             let client = AliyunTtsClient::new(option);
             Ok(Box::new(client))
         }
+        SynthesisType::Mrcp => {
+            let client = MrcpTtsClient::new(option);
+            Ok(Box::new(client))
+        }
         SynthesisType::Other(provider) => {
             return Err(anyhow::anyhow!("Unsupported provider: {}", provider));
         }
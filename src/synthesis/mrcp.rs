@@ -0,0 +1,251 @@
+use super::{
+    SynthesisClient, SynthesisEvent, SynthesisEventReceiver, SynthesisEventSender, SynthesisOption,
+    SynthesisType,
+};
+use crate::PcmBuf;
+use crate::media::{
+    codecs::{self, CodecType, create_decoder, resample::resample_mono, samples_to_bytes},
+    track::rtp::{RtpPacketKind, parse_rtp_packet},
+};
+use crate::mrcp::{MrcpMessage, MrcpResource, MrcpSession, MrcpSessionOption};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+use rsipstack::dialog::authenticate::Credential;
+use std::time::Duration;
+use tokio::sync::{Mutex, mpsc};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+/// Audio still arriving after SPEAK-COMPLETE is played until the stream
+/// pauses this long
+const DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// TTS on an MRCPv2 speech server (speechsynth resource).
+///
+/// `endpoint` is the SIP uri of the server and `speaker` the Voice-Name,
+/// `extra` entries are sent as headers of each SPEAK, e.g. `Prosody-Rate`.
+/// Text starting with `<` is sent as SSML. The session is set up on the first
+/// synthesis and kept until the client is cancelled.
+pub struct MrcpTtsClient {
+    option: SynthesisOption,
+    tx: SynthesisEventSender,
+    rx: std::sync::Mutex<Option<SynthesisEventReceiver>>,
+    session: Mutex<Option<MrcpSession>>,
+    cancel_token: std::sync::Mutex<CancellationToken>,
+}
+
+impl MrcpTtsClient {
+    pub fn create(option: &SynthesisOption) -> Result<Box<dyn SynthesisClient>> {
+        let client = Self::new(option.clone());
+        Ok(Box::new(client))
+    }
+
+    pub fn new(option: SynthesisOption) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self {
+            option,
+            tx,
+            rx: std::sync::Mutex::new(Some(rx)),
+            session: Mutex::new(None),
+            cancel_token: std::sync::Mutex::new(CancellationToken::new()),
+        }
+    }
+
+    fn speak_request(text: &str, option: &SynthesisOption) -> MrcpMessage {
+        let mut request = MrcpMessage::request("SPEAK", 0);
+        if let Some(speaker) = &option.speaker {
+            request = request.with_header("Voice-Name", speaker);
+        }
+        for (name, value) in option.extra.iter().flatten() {
+            request = request.with_header(name, value);
+        }
+        let content_type = if text.trim_start().starts_with('<') {
+            "application/ssml+xml"
+        } else {
+            "text/plain"
+        };
+        request.with_body(content_type, text.as_bytes().to_vec())
+    }
+
+    async fn speak(
+        &self,
+        session: &mut MrcpSession,
+        text: &str,
+        option: &SynthesisOption,
+        cancel_token: &CancellationToken,
+    ) -> Result<()> {
+        let sample_rate = option.samplerate.unwrap_or(16000) as u32;
+        let rtp_socket = session.rtp_socket();
+        let mut decoder = Decoder::new(session.codec(), sample_rate);
+        let mut buf = vec![0u8; 2048];
+        let request_id = session.send(Self::speak_request(text, option)).await?;
+        loop {
+            tokio::select! {
+                _ = cancel_token.cancelled() => {
+                    session.request(MrcpMessage::request("STOP", 0)).await.ok();
+                    return Ok(());
+                }
+                received = rtp_socket.recv(&mut buf) => {
+                    let n = received?;
+                    if let Some(samples) = decoder.decode(&buf[..n]) {
+                        self.tx.send(Ok(SynthesisEvent::AudioChunk(samples_to_bytes(&samples))))?;
+                    }
+                }
+                message = session.recv() => {
+                    let message = message?;
+                    if message.request_id != request_id {
+                        continue;
+                    }
+                    if let Some(status) = message.status() && status >= 300 {
+                        return Err(anyhow!("SPEAK failed: {}", status));
+                    }
+                    if message.event_name() == Some("SPEAK-COMPLETE") {
+                        debug!(
+                            cause = message.header("Completion-Cause").unwrap_or_default(),
+                            "MRCP speak complete"
+                        );
+                        break;
+                    }
+                }
+            }
+        }
+        while let Ok(received) =
+            tokio::time::timeout(DRAIN_TIMEOUT, rtp_socket.recv(&mut buf)).await
+        {
+            let n = received?;
+            if let Some(samples) = decoder.decode(&buf[..n]) {
+                self.tx
+                    .send(Ok(SynthesisEvent::AudioChunk(samples_to_bytes(&samples))))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the server's RTP to PCM at the requested sample rate
+struct Decoder {
+    codec: CodecType,
+    decoder: Box<dyn codecs::Decoder>,
+    sample_rate: u32,
+}
+
+impl Decoder {
+    fn new(codec: CodecType, sample_rate: u32) -> Self {
+        Self {
+            codec,
+            decoder: create_decoder(codec),
+            sample_rate,
+        }
+    }
+
+    fn decode(&mut self, data: &[u8]) -> Option<PcmBuf> {
+        let packet = match parse_rtp_packet(data) {
+            Ok(RtpPacketKind::Rtp(packet)) => packet,
+            _ => return None,
+        };
+        if packet.header.payload_type != self.codec.payload_type() {
+            return None;
+        }
+        let samples = self.decoder.decode(&packet.payload);
+        let decoded_rate = self.decoder.sample_rate();
+        if decoded_rate == self.sample_rate {
+            Some(samples)
+        } else {
+            Some(resample_mono(&samples, decoded_rate, self.sample_rate))
+        }
+    }
+}
+
+#[async_trait]
+impl SynthesisClient for MrcpTtsClient {
+    fn provider(&self) -> SynthesisType {
+        SynthesisType::Mrcp
+    }
+
+    async fn start(
+        &self,
+        cancel_token: CancellationToken,
+    ) -> Result<BoxStream<'static, Result<SynthesisEvent>>> {
+        let rx = self.rx.lock().unwrap().take().ok_or_else(|| {
+            anyhow!("MrcpTtsClient: Receiver already taken, cannot start new stream")
+        })?;
+        *self.cancel_token.lock().unwrap() = cancel_token;
+        Ok(Box::pin(futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|event| (event, rx))
+        })))
+    }
+
+    async fn synthesize(
+        &self,
+        text: &str,
+        end_of_stream: Option<bool>,
+        option: Option<SynthesisOption>,
+    ) -> Result<()> {
+        let cache_key = option.as_ref().and_then(|opt| opt.cache_key.clone());
+        let option = self.option.merge_with(option);
+        let cancel_token = self.cancel_token.lock().unwrap().clone();
+        let mut session = self.session.lock().await;
+        if session.is_none() {
+            let endpoint = option
+                .endpoint
+                .clone()
+                .ok_or_else(|| anyhow!("MRCP server uri is required in endpoint"))?;
+            let mut session_option = MrcpSessionOption::new(endpoint);
+            if let (Some(username), Some(password)) =
+                (option.secret_id.clone(), option.secret_key.clone())
+            {
+                session_option = session_option.with_credential(Credential {
+                    username,
+                    password,
+                    realm: None,
+                });
+            }
+            let connected = MrcpSession::connect(&session_option, MrcpResource::SpeechSynth).await;
+            match connected {
+                Ok(connected) => {
+                    *session = Some(connected);
+                }
+                Err(e) => {
+                    self.tx
+                        .send(Err(anyhow!("MRCP session setup failed: {}", e)))?;
+                    return Err(e);
+                }
+            }
+        }
+        let Some(active) = session.as_mut() else {
+            return Ok(());
+        };
+        let result = self.speak(active, text, &option, &cancel_token).await;
+        if let Err(e) = result {
+            warn!("MRCP synthesis failed: {}", e);
+            if let Some(session) = session.take() {
+                session.close().await;
+            }
+            self.tx.send(Err(anyhow!("MRCP synthesis failed: {}", e)))?;
+            return Err(e);
+        }
+        if cancel_token.is_cancelled() {
+            if let Some(session) = session.take() {
+                session.close().await;
+            }
+            return Ok(());
+        }
+        self.tx.send(Ok(SynthesisEvent::Finished {
+            end_of_stream,
+            cache_key,
+        }))?;
+        Ok(())
+    }
+}
+
+impl Drop for MrcpTtsClient {
+    fn drop(&mut self) {
+        let Some(session) = self.session.get_mut().take() else {
+            return;
+        };
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(session.close());
+        }
+    }
+}
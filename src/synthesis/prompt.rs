@@ -0,0 +1,605 @@
+use crate::config::PromptPackConfig;
+use anyhow::{Result, anyhow};
+use chrono::{Datelike, NaiveDate, NaiveTime, Timelike};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Component, Path};
+use std::str::FromStr;
+
+/// One part of a prompt. Text, SSML and file urls may hold `{name}`
+/// placeholders filled from the variables, and `{name:kind}` ones spoken
+/// the way `kind` (a [`SayAs`]) is said in the prompt's locale
+#[derive(Debug, Clone, Deserialize, JsonSchema, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum PromptSegment {
+    /// A recording, played as is
+    File { url: String },
+    /// Text for the TTS
+    Text { text: String },
+    /// SSML, passed through to providers that take it and read as plain
+    /// text by the others
+    Ssml { ssml: String },
+    /// A prompt of the prompt pack, in the first locale that has it
+    Prompt { name: String },
+}
+
+/// What a rendered prompt plays, in order
+#[derive(Debug, Clone, PartialEq)]
+pub enum PromptPart {
+    File(String),
+    Speak(String),
+    Ssml(String),
+}
+
+/// How a variable is read out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SayAs {
+    /// 1234.5 as one thousand two hundred thirty-four point five
+    Number,
+    /// 1234 as one two three four
+    Digits,
+    Ordinal,
+    /// An amount in the main unit, 12.5 as twelve dollars and fifty cents
+    Currency,
+    /// YYYY-MM-DD
+    Date,
+    /// HH:MM or HH:MM:SS
+    Time,
+}
+
+impl FromStr for SayAs {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "number" | "cardinal" => Ok(Self::Number),
+            "digits" => Ok(Self::Digits),
+            "ordinal" => Ok(Self::Ordinal),
+            "currency" => Ok(Self::Currency),
+            "date" => Ok(Self::Date),
+            "time" => Ok(Self::Time),
+            _ => Err(anyhow!("unknown say-as kind: {}", s)),
+        }
+    }
+}
+
+/// Languages with number and date rules
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    English,
+    Chinese,
+}
+
+impl Language {
+    /// en, en-US, zh, zh-CN, cmn-Hans-CN...
+    pub fn from_locale(locale: &str) -> Result<Self> {
+        let language = locale.split(['-', '_']).next().unwrap_or_default();
+        match language.to_lowercase().as_str() {
+            "en" => Ok(Self::English),
+            "zh" | "cmn" => Ok(Self::Chinese),
+            _ => Err(anyhow!("unsupported prompt locale: {}", locale)),
+        }
+    }
+
+    /// Between two text segments spoken together
+    fn separator(&self) -> &'static str {
+        match self {
+            Self::English => " ",
+            Self::Chinese => "",
+        }
+    }
+}
+
+/// Fills the placeholders and turns the segments into what is played.
+/// Adjacent text is joined into one TTS request, SSML segments are kept
+/// apart when the provider `takes_ssml` and read as text otherwise. Named
+/// prompts are looked up in `pack` and filled by the rules of the locale
+/// they were found in
+pub fn render(
+    segments: &[PromptSegment],
+    variables: &HashMap<String, String>,
+    locale: &str,
+    takes_ssml: bool,
+    pack: Option<&PromptPackConfig>,
+) -> Result<Vec<PromptPart>> {
+    let mut parts = Vec::new();
+    for segment in segments {
+        let (segment, locale) = match segment {
+            PromptSegment::Prompt { name } => {
+                let pack = pack.ok_or_else(|| anyhow!("no prompt pack for prompt {}", name))?;
+                // e.g. `greeting-{experiment.greeting}` plays the variant of the call
+                let name = fill(name, variables, locale, false)?;
+                resolve(pack, &name, locale)?
+            }
+            segment => (segment.clone(), locale.to_string()),
+        };
+        let locale = locale.as_str();
+        let text = match segment {
+            PromptSegment::File { url } => {
+                parts.push(PromptPart::File(fill(&url, variables, locale, false)?));
+                continue;
+            }
+            PromptSegment::Ssml { ssml } if takes_ssml => {
+                parts.push(PromptPart::Ssml(fill(&ssml, variables, locale, true)?));
+                continue;
+            }
+            PromptSegment::Ssml { ssml } => strip_ssml(&fill(&ssml, variables, locale, true)?),
+            PromptSegment::Text { text } => fill(&text, variables, locale, false)?,
+            PromptSegment::Prompt { name } => {
+                return Err(anyhow!("prompt {} resolved to a prompt", name));
+            }
+        };
+        let separator = Language::from_locale(locale).map_or(" ", |l| l.separator());
+        match parts.last_mut() {
+            Some(PromptPart::Speak(spoken)) => {
+                spoken.push_str(separator);
+                spoken.push_str(&text);
+            }
+            _ => parts.push(PromptPart::Speak(text)),
+        }
+    }
+    Ok(parts)
+}
+
+/// The recording or text of a named prompt, and the locale it was found in
+pub fn resolve(
+    pack: &PromptPackConfig,
+    name: &str,
+    locale: &str,
+) -> Result<(PromptSegment, String)> {
+    let relative = Path::new(name);
+    if name.is_empty()
+        || !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(anyhow!("invalid prompt name: {}", name));
+    }
+    for locale in pack.locale_chain(locale) {
+        if let Some(root) = pack.path.as_ref() {
+            for extension in ["wav", "mp3"] {
+                let path = Path::new(root)
+                    .join(&locale)
+                    .join(relative)
+                    .with_extension(extension);
+                if path.is_file() {
+                    let url = path.to_string_lossy().to_string();
+                    return Ok((PromptSegment::File { url }, locale));
+                }
+            }
+        }
+        if let Some(text) = pack.texts.get(&locale).and_then(|texts| texts.get(name)) {
+            let segment = match text.trim_start().starts_with('<') {
+                true => PromptSegment::Ssml { ssml: text.clone() },
+                false => PromptSegment::Text { text: text.clone() },
+            };
+            return Ok((segment, locale));
+        }
+    }
+    Err(anyhow!("prompt {} not found for locale {}", name, locale))
+}
+
+/// Replaces `{name}` and `{name:kind}`, `{{` is a literal brace. Values are
+/// XML-escaped when `escape`, for SSML. The locale only matters to the
+/// kinds
+pub fn fill(
+    template: &str,
+    variables: &HashMap<String, String>,
+    locale: &str,
+    escape: bool,
+) -> Result<String> {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        if let Some(after) = rest.strip_prefix('{') {
+            filled.push('{');
+            rest = after;
+            continue;
+        }
+        let end = rest
+            .find('}')
+            .ok_or_else(|| anyhow!("unclosed placeholder in prompt: {}", template))?;
+        let (name, kind) = match rest[..end].split_once(':') {
+            Some((name, kind)) => (name.trim(), Some(kind.trim().parse::<SayAs>()?)),
+            None => (rest[..end].trim(), None),
+        };
+        let value = variables
+            .get(name)
+            .ok_or_else(|| anyhow!("prompt variable {} is not set", name))?;
+        let spoken = match kind {
+            Some(kind) => say(value, kind, Language::from_locale(locale)?)?,
+            None => value.clone(),
+        };
+        match escape {
+            true => filled.push_str(&escape_xml(&spoken)),
+            false => filled.push_str(&spoken),
+        }
+        rest = &rest[end + 1..];
+    }
+    filled.push_str(rest);
+    Ok(filled)
+}
+
+/// `value` in words
+pub fn say(value: &str, kind: SayAs, language: Language) -> Result<String> {
+    let value = value.trim();
+    match kind {
+        SayAs::Number => {
+            let (negative, integer, fraction) = parse_decimal(value)?;
+            let mut words = match language {
+                Language::English => en_cardinal(integer),
+                Language::Chinese => zh_cardinal(integer),
+            };
+            if !fraction.is_empty() {
+                match language {
+                    Language::English => {
+                        words.push_str(" point ");
+                        words.push_str(&say_digits(fraction, language));
+                    }
+                    Language::Chinese => {
+                        words.push('点');
+                        words.push_str(&say_digits(fraction, language).replace('幺', "一"));
+                    }
+                }
+            }
+            Ok(match (negative, language) {
+                (false, _) => words,
+                (true, Language::English) => format!("minus {}", words),
+                (true, Language::Chinese) => format!("负{}", words),
+            })
+        }
+        SayAs::Digits => {
+            if !value.chars().all(|c| c.is_ascii_digit()) {
+                return Err(anyhow!("not digits: {}", value));
+            }
+            Ok(say_digits(value, language))
+        }
+        SayAs::Ordinal => {
+            let n = value
+                .parse::<u64>()
+                .map_err(|_| anyhow!("not an ordinal: {}", value))?;
+            Ok(match language {
+                Language::English => en_ordinal(&en_cardinal(n)),
+                Language::Chinese => format!("第{}", zh_cardinal(n)),
+            })
+        }
+        SayAs::Currency => {
+            let (negative, integer, fraction) = parse_decimal(value)?;
+            let cents = format!("{:0<2}", fraction)[..2].parse::<u64>()?;
+            let words = match language {
+                Language::English => en_currency(integer, cents),
+                Language::Chinese => zh_currency(integer, cents),
+            };
+            Ok(match (negative, language) {
+                (false, _) => words,
+                (true, Language::English) => format!("minus {}", words),
+                (true, Language::Chinese) => format!("负{}", words),
+            })
+        }
+        SayAs::Date => {
+            // a datetime is read as its date
+            let date = value.get(..10).unwrap_or(value);
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| anyhow!("not a YYYY-MM-DD date: {}", value))?;
+            Ok(match language {
+                Language::English => format!(
+                    "{} {}, {}",
+                    EN_MONTHS[date.month0() as usize],
+                    en_ordinal(&en_cardinal(date.day() as u64)),
+                    en_year(date.year().unsigned_abs())
+                ),
+                Language::Chinese => format!(
+                    "{}年{}月{}日",
+                    say_digits(&date.year().to_string(), language).replace('幺', "一"),
+                    zh_cardinal(date.month() as u64),
+                    zh_cardinal(date.day() as u64)
+                ),
+            })
+        }
+        SayAs::Time => {
+            let time = NaiveTime::parse_from_str(value, "%H:%M:%S")
+                .or_else(|_| NaiveTime::parse_from_str(value, "%H:%M"))
+                .map_err(|_| anyhow!("not a HH:MM time: {}", value))?;
+            let (hour, minute) = (time.hour() as u64, time.minute() as u64);
+            Ok(match language {
+                Language::English => {
+                    let suffix = if hour < 12 { "a.m." } else { "p.m." };
+                    let hour = en_cardinal(match hour % 12 {
+                        0 => 12,
+                        hour => hour,
+                    });
+                    match minute {
+                        0 => format!("{} {}", hour, suffix),
+                        1..10 => format!("{} oh {} {}", hour, EN_ONES[minute as usize], suffix),
+                        _ => format!("{} {} {}", hour, en_cardinal(minute), suffix),
+                    }
+                }
+                Language::Chinese => match minute {
+                    0 => format!("{}点整", zh_cardinal(hour)),
+                    1..10 => format!("{}点零{}分", zh_cardinal(hour), zh_cardinal(minute)),
+                    _ => format!("{}点{}分", zh_cardinal(hour), zh_cardinal(minute)),
+                },
+            })
+        }
+    }
+}
+
+/// Sign, integer part and fraction digits of `-1,234.50`
+fn parse_decimal(value: &str) -> Result<(bool, u64, &str)> {
+    let (negative, unsigned) = match value.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, value),
+    };
+    let (integer, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    let integer = integer.replace(',', "");
+    if integer.is_empty() || !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return Err(anyhow!("not a number: {}", value));
+    }
+    let integer = integer
+        .parse::<u64>()
+        .map_err(|_| anyhow!("not a number: {}", value))?;
+    Ok((negative, integer, fraction))
+}
+
+/// Values are text, the markup of a template is left alone
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// The text of SSML, for providers that only read plain text
+pub fn strip_ssml(ssml: &str) -> String {
+    let mut text = String::with_capacity(ssml.len());
+    let mut in_tag = false;
+    for c in ssml.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    let text = text
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub(crate) const EN_ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+pub(crate) const EN_TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+const EN_SCALES: [&str; 7] = [
+    "",
+    " thousand",
+    " million",
+    " billion",
+    " trillion",
+    " quadrillion",
+    " quintillion",
+];
+pub(crate) const EN_MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+pub(crate) const ZH_DIGITS: [char; 10] =
+    ['零', '一', '二', '三', '四', '五', '六', '七', '八', '九'];
+const ZH_SCALES: [&str; 5] = ["", "万", "亿", "万亿", "亿亿"];
+
+fn say_digits(digits: &str, language: Language) -> String {
+    let digits = digits.chars().filter_map(|c| c.to_digit(10));
+    match language {
+        Language::English => digits
+            .map(|d| EN_ONES[d as usize])
+            .collect::<Vec<_>>()
+            .join(" "),
+        // 1 is read yao in numbers said digit by digit
+        Language::Chinese => digits
+            .map(|d| if d == 1 { '幺' } else { ZH_DIGITS[d as usize] })
+            .collect(),
+    }
+}
+
+fn en_below_hundred(n: u64) -> String {
+    match n {
+        0..20 => EN_ONES[n as usize].to_string(),
+        _ if n.is_multiple_of(10) => EN_TENS[n as usize / 10].to_string(),
+        _ => format!("{}-{}", EN_TENS[n as usize / 10], EN_ONES[n as usize % 10]),
+    }
+}
+
+fn en_cardinal(n: u64) -> String {
+    if n == 0 {
+        return EN_ONES[0].to_string();
+    }
+    let mut groups = Vec::new();
+    let mut rest = n;
+    for scale in EN_SCALES {
+        let group = rest % 1000;
+        if group > 0 {
+            let words = match (group / 100, group % 100) {
+                (0, tens) => en_below_hundred(tens),
+                (hundreds, 0) => format!("{} hundred", EN_ONES[hundreds as usize]),
+                (hundreds, tens) => {
+                    format!(
+                        "{} hundred {}",
+                        EN_ONES[hundreds as usize],
+                        en_below_hundred(tens)
+                    )
+                }
+            };
+            groups.push(format!("{}{}", words, scale));
+        }
+        rest /= 1000;
+        if rest == 0 {
+            break;
+        }
+    }
+    groups.reverse();
+    groups.join(" ")
+}
+
+/// twenty-one as twenty-first
+pub(crate) fn en_ordinal(cardinal: &str) -> String {
+    let split = cardinal.rfind([' ', '-']).map_or(0, |i| i + 1);
+    let (head, last) = cardinal.split_at(split);
+    let last = match last {
+        "one" => "first".to_string(),
+        "two" => "second".to_string(),
+        "three" => "third".to_string(),
+        "five" => "fifth".to_string(),
+        "eight" => "eighth".to_string(),
+        "nine" => "ninth".to_string(),
+        "twelve" => "twelfth".to_string(),
+        _ => match last.strip_suffix('y') {
+            Some(stem) => format!("{}ieth", stem),
+            None => format!("{}th", last),
+        },
+    };
+    format!("{}{}", head, last)
+}
+
+/// 1999 as nineteen ninety-nine, 2005 as two thousand five
+fn en_year(year: u32) -> String {
+    let (century, rest) = (year as u64 / 100, year as u64 % 100);
+    match year {
+        1100..10000 if !(2000..2010).contains(&year) => match rest {
+            0 => format!("{} hundred", en_below_hundred(century)),
+            1..10 => format!(
+                "{} oh {}",
+                en_below_hundred(century),
+                EN_ONES[rest as usize]
+            ),
+            _ => format!("{} {}", en_below_hundred(century), en_below_hundred(rest)),
+        },
+        _ => en_cardinal(year as u64),
+    }
+}
+
+fn en_currency(dollars: u64, cents: u64) -> String {
+    let dollars_words = match dollars {
+        1 => "one dollar".to_string(),
+        _ => format!("{} dollars", en_cardinal(dollars)),
+    };
+    match (dollars, cents) {
+        (_, 0) => dollars_words,
+        (0, 1) => "one cent".to_string(),
+        (0, _) => format!("{} cents", en_cardinal(cents)),
+        (_, 1) => format!("{} and one cent", dollars_words),
+        _ => format!("{} and {} cents", dollars_words, en_cardinal(cents)),
+    }
+}
+
+/// Four digits with 千百十, zeros in between read once
+fn zh_below_ten_thousand(n: u64) -> String {
+    let mut words = String::new();
+    let mut zero = false;
+    for (divisor, unit) in [(1000, "千"), (100, "百"), (10, "十"), (1, "")] {
+        let digit = (n / divisor % 10) as usize;
+        if digit == 0 {
+            zero = !words.is_empty();
+            continue;
+        }
+        if zero {
+            words.push('零');
+            zero = false;
+        }
+        words.push(ZH_DIGITS[digit]);
+        words.push_str(unit);
+    }
+    words
+}
+
+fn zh_cardinal(n: u64) -> String {
+    if n == 0 {
+        return ZH_DIGITS[0].to_string();
+    }
+    let mut groups = Vec::new();
+    let mut rest = n;
+    while rest > 0 {
+        groups.push(rest % 10000);
+        rest /= 10000;
+    }
+    let mut words = String::new();
+    let mut zero = false;
+    for (i, group) in groups.iter().enumerate().rev() {
+        if *group == 0 {
+            zero = !words.is_empty();
+            continue;
+        }
+        if !words.is_empty() && (zero || *group < 1000) {
+            words.push('零');
+        }
+        zero = false;
+        words.push_str(&zh_below_ten_thousand(*group));
+        words.push_str(ZH_SCALES[i]);
+    }
+    // 10 to 19 are said without the leading one
+    match words.strip_prefix("一十") {
+        Some(rest) => format!("十{}", rest),
+        None => words,
+    }
+}
+
+fn zh_currency(yuan: u64, cents: u64) -> String {
+    let (jiao, fen) = (cents / 10, cents % 10);
+    let mut words = match (yuan, cents) {
+        (0, 1..) => String::new(),
+        _ => format!("{}元", zh_cardinal(yuan)),
+    };
+    if jiao > 0 {
+        words.push(ZH_DIGITS[jiao as usize]);
+        words.push('角');
+    }
+    if fen > 0 {
+        if jiao == 0 && yuan > 0 {
+            words.push('零');
+        }
+        words.push(ZH_DIGITS[fen as usize]);
+        words.push('分');
+    }
+    words
+}
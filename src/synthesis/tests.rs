@@ -191,3 +191,194 @@ fn save_audio_to_files(audio_data: &[u8], sample_rate: u32, prefix: &str) -> Res
 
     Ok(())
 }
+
+#[test]
+fn test_prompt_say_rules() -> Result<()> {
+    use crate::synthesis::prompt::{Language, SayAs, say};
+    let cases = [
+        (
+            "1234.5",
+            SayAs::Number,
+            Language::English,
+            "one thousand two hundred thirty-four point five",
+        ),
+        (
+            "-2,000,017",
+            SayAs::Number,
+            Language::English,
+            "minus two million seventeen",
+        ),
+        ("21", SayAs::Ordinal, Language::English, "twenty-first"),
+        (
+            "12.5",
+            SayAs::Currency,
+            Language::English,
+            "twelve dollars and fifty cents",
+        ),
+        (
+            "2024-03-05",
+            SayAs::Date,
+            Language::English,
+            "March fifth, twenty twenty-four",
+        ),
+        (
+            "2005-11-30T08:00:00",
+            SayAs::Date,
+            Language::English,
+            "November thirtieth, two thousand five",
+        ),
+        ("14:05", SayAs::Time, Language::English, "two oh five p.m."),
+        (
+            "00:30:00",
+            SayAs::Time,
+            Language::English,
+            "twelve thirty a.m.",
+        ),
+        ("110", SayAs::Digits, Language::English, "one one zero"),
+        ("10500", SayAs::Number, Language::Chinese, "一万零五百"),
+        ("100000005", SayAs::Number, Language::Chinese, "一亿零五"),
+        ("15.31", SayAs::Number, Language::Chinese, "十五点三一"),
+        ("12.05", SayAs::Currency, Language::Chinese, "十二元零五分"),
+        ("3", SayAs::Ordinal, Language::Chinese, "第三"),
+        (
+            "2011-10-01",
+            SayAs::Date,
+            Language::Chinese,
+            "二零一一年十月一日",
+        ),
+        ("9:00", SayAs::Time, Language::Chinese, "九点整"),
+        ("110", SayAs::Digits, Language::Chinese, "幺幺零"),
+    ];
+    for (value, kind, language, expected) in cases {
+        assert_eq!(
+            say(value, kind, language)?,
+            expected,
+            "{} as {:?}",
+            value,
+            kind
+        );
+    }
+    assert!(say("12a", SayAs::Number, Language::English).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_prompt_render() -> Result<()> {
+    use crate::synthesis::prompt::{PromptPart, PromptSegment, render};
+    use std::collections::HashMap;
+    let segments = serde_json::from_value::<Vec<PromptSegment>>(serde_json::json!([
+        {"type": "file", "url": "prompts/{lang}/balance.wav"},
+        {"type": "text", "text": "Your balance is {amount:currency}"},
+        {"type": "ssml", "ssml": "<speak>due <say-as interpret-as=\"date\">{due}</say-as> for {name}</speak>"},
+        {"type": "file", "url": "prompts/goodbye.wav"},
+    ]))?;
+    let variables = HashMap::from([
+        ("lang".to_string(), "en".to_string()),
+        ("amount".to_string(), "42".to_string()),
+        ("due".to_string(), "2024-03-05".to_string()),
+        ("name".to_string(), "Tom & Jerry".to_string()),
+    ]);
+
+    let parts = render(&segments, &variables, "en-US", false, None)?;
+    assert_eq!(
+        parts,
+        vec![
+            PromptPart::File("prompts/en/balance.wav".to_string()),
+            PromptPart::Speak(
+                "Your balance is forty-two dollars due 2024-03-05 for Tom & Jerry".to_string()
+            ),
+            PromptPart::File("prompts/goodbye.wav".to_string()),
+        ]
+    );
+
+    let parts = render(&segments, &variables, "en", true, None)?;
+    assert_eq!(
+        parts[2],
+        PromptPart::Ssml(
+            "<speak>due <say-as interpret-as=\"date\">2024-03-05</say-as> for Tom &amp; Jerry</speak>"
+                .to_string()
+        )
+    );
+
+    let missing = HashMap::from([("lang".to_string(), "en".to_string())]);
+    assert!(render(&segments, &missing, "en", false, None).is_err());
+    assert!(render(&segments, &variables, "fr", false, None).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_prompt_pack() -> Result<()> {
+    use crate::config::PromptPackConfig;
+    use crate::synthesis::prompt::{PromptPart, PromptSegment, render};
+    use std::collections::HashMap;
+    let root = tempfile::tempdir()?;
+    std::fs::create_dir_all(root.path().join("zh/menu"))?;
+    std::fs::write(root.path().join("zh/menu/main.wav"), b"")?;
+    let pack = PromptPackConfig {
+        path: Some(root.path().to_string_lossy().to_string()),
+        texts: HashMap::from([
+            (
+                "en".to_string(),
+                HashMap::from([
+                    ("menu/main".to_string(), "Press one".to_string()),
+                    (
+                        "balance".to_string(),
+                        "You owe {amount:currency}".to_string(),
+                    ),
+                ]),
+            ),
+            (
+                "zh".to_string(),
+                HashMap::from([("balance".to_string(), "您欠{amount:currency}".to_string())]),
+            ),
+        ]),
+        ..Default::default()
+    };
+    let segments = serde_json::from_value::<Vec<PromptSegment>>(serde_json::json!([
+        {"type": "prompt", "name": "balance"},
+        {"type": "prompt", "name": "menu/main"},
+    ]))?;
+    let variables = HashMap::from([("amount".to_string(), "3".to_string())]);
+
+    let parts = render(&segments, &variables, "zh-CN", false, Some(&pack))?;
+    assert_eq!(
+        parts,
+        vec![
+            PromptPart::Speak("您欠三元".to_string()),
+            PromptPart::File(
+                root.path()
+                    .join("zh/menu/main.wav")
+                    .to_string_lossy()
+                    .to_string()
+            ),
+        ]
+    );
+    // fr falls back to the default locale, with its own number rules
+    let parts = render(&segments, &variables, "fr-FR", false, Some(&pack))?;
+    assert_eq!(
+        parts,
+        vec![PromptPart::Speak(
+            "You owe three dollars Press one".to_string()
+        )]
+    );
+
+    let missing = [PromptSegment::Prompt {
+        name: "goodbye".to_string(),
+    }];
+    assert!(render(&missing, &variables, "en", false, Some(&pack)).is_err());
+    let escape = [PromptSegment::Prompt {
+        name: "../secret".to_string(),
+    }];
+    assert!(render(&escape, &variables, "en", false, Some(&pack)).is_err());
+
+    // names take variables, e.g. the variant of an experiment
+    let variant = [PromptSegment::Prompt {
+        name: "menu/{experiment.menu}".to_string(),
+    }];
+    let variables = HashMap::from([("experiment.menu".to_string(), "main".to_string())]);
+    assert_eq!(
+        render(&variant, &variables, "en", false, Some(&pack))?,
+        vec![PromptPart::Speak("Press one".to_string())]
+    );
+    Ok(())
+}
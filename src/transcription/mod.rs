@@ -10,11 +10,14 @@ use tokio_util::sync::CancellationToken;
 use tracing::debug;
 
 mod aliyun;
+mod mrcp;
 mod tencent_cloud;
 mod voiceapi;
 
 pub use aliyun::AliyunAsrClient;
 pub use aliyun::AliyunAsrClientBuilder;
+pub use mrcp::MrcpAsrClient;
+pub use mrcp::MrcpAsrClientBuilder;
 pub use tencent_cloud::TencentCloudAsrClient;
 pub use tencent_cloud::TencentCloudAsrClientBuilder;
 pub use voiceapi::VoiceApiAsrClient;
@@ -64,6 +67,8 @@ pub enum TranscriptionType {
     VoiceApi,
     #[serde(rename = "aliyun")]
     Aliyun,
+    #[serde(rename = "mrcp")]
+    Mrcp,
     Other(String),
 }
 
@@ -90,6 +95,7 @@ impl std::fmt::Display for TranscriptionType {
             TranscriptionType::TencentCloud => write!(f, "tencent"),
             TranscriptionType::VoiceApi => write!(f, "voiceapi"),
             TranscriptionType::Aliyun => write!(f, "aliyun"),
+            TranscriptionType::Mrcp => write!(f, "mrcp"),
             TranscriptionType::Other(provider) => write!(f, "{}", provider),
         }
     }
@@ -105,6 +111,7 @@ impl<'de> Deserialize<'de> for TranscriptionType {
             "tencent" => Ok(TranscriptionType::TencentCloud),
             "voiceapi" => Ok(TranscriptionType::VoiceApi),
             "aliyun" => Ok(TranscriptionType::Aliyun),
+            "mrcp" => Ok(TranscriptionType::Mrcp),
             _ => Ok(TranscriptionType::Other(value)),
         }
     }
@@ -0,0 +1,284 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use super::handle_wait_for_answer_with_audio_drop;
+use super::{TranscriptionClient, TranscriptionOption};
+use crate::event::{EventSender, SessionEvent};
+use crate::media::codecs::{bytes_to_samples, samples_to_bytes};
+use crate::media::pipeline::MediaPipeline;
+use crate::media::track::TrackConfig;
+use crate::mrcp::{MrcpMessage, MrcpResource, MrcpSession, MrcpSessionOption, parse_nlsml};
+use crate::{AudioFrame, PcmBuf, Sample, Samples, TrackId};
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use rsipstack::dialog::authenticate::Credential;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+/// Grammar used when `extra.grammar` is not set
+const DEFAULT_GRAMMAR: &str = "builtin:speech/transcribe";
+
+/// ASR on an MRCPv2 speech server (speechrecog resource).
+///
+/// `endpoint` is the SIP uri of the server. `extra.grammar` is a grammar uri
+/// or an inline SRGS grammar, the other `extra` entries are sent as headers
+/// of each RECOGNIZE, e.g. `No-Input-Timeout`. Recognition is restarted after
+/// every result to transcribe continuously.
+pub struct MrcpAsrClient {
+    audio_tx: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+pub struct MrcpAsrClientBuilder {
+    option: TranscriptionOption,
+    track_id: Option<String>,
+    cancel_token: Option<CancellationToken>,
+    event_sender: EventSender,
+}
+
+impl MrcpAsrClientBuilder {
+    pub fn create(
+        track_id: TrackId,
+        token: CancellationToken,
+        option: TranscriptionOption,
+        event_sender: EventSender,
+    ) -> Pin<Box<dyn Future<Output = Result<Box<dyn TranscriptionClient>>> + Send>> {
+        Box::pin(async move {
+            let builder = Self::new(option, event_sender);
+            builder
+                .with_cancel_token(token)
+                .with_track_id(track_id)
+                .build()
+                .await
+                .map(|client| Box::new(client) as Box<dyn TranscriptionClient>)
+        })
+    }
+
+    pub fn new(option: TranscriptionOption, event_sender: EventSender) -> Self {
+        Self {
+            option,
+            cancel_token: None,
+            track_id: None,
+            event_sender,
+        }
+    }
+
+    pub fn with_cancel_token(mut self, token: CancellationToken) -> Self {
+        self.cancel_token = Some(token);
+        self
+    }
+
+    pub fn with_track_id(mut self, track_id: String) -> Self {
+        self.track_id = Some(track_id);
+        self
+    }
+
+    pub async fn build(self) -> Result<MrcpAsrClient> {
+        let endpoint = self
+            .option
+            .endpoint
+            .clone()
+            .ok_or_else(|| anyhow!("MRCP server uri is required in endpoint"))?;
+        let (audio_tx, mut audio_rx) = mpsc::unbounded_channel();
+        let event_sender_rx = match self.option.start_when_answer {
+            Some(true) => Some(self.event_sender.subscribe()),
+            _ => None,
+        };
+        let token = self.cancel_token.unwrap_or_default();
+        let track_id = self.track_id.unwrap_or_else(|| Uuid::new_v4().to_string());
+        let event_sender = self.event_sender;
+        let option = self.option;
+
+        info!(%track_id, endpoint, "MRCP ASR client started");
+        tokio::spawn(async move {
+            if event_sender_rx.is_some() {
+                handle_wait_for_answer_with_audio_drop(event_sender_rx, &mut audio_rx, &token)
+                    .await;
+                if token.is_cancelled() {
+                    debug!("Cancelled during wait for answer");
+                    return;
+                }
+            }
+
+            let mut session_option = MrcpSessionOption::new(endpoint);
+            if let (Some(username), Some(password)) =
+                (option.secret_id.clone(), option.secret_key.clone())
+            {
+                session_option = session_option.with_credential(Credential {
+                    username,
+                    password,
+                    realm: None,
+                });
+            }
+            let session =
+                match MrcpSession::connect(&session_option, MrcpResource::SpeechRecog).await {
+                    Ok(session) => session,
+                    Err(e) => {
+                        warn!(track_id, "Failed to set up MRCP session: {}", e);
+                        event_sender
+                            .send(SessionEvent::Error {
+                                timestamp: crate::get_timestamp(),
+                                track_id,
+                                sender: "MrcpAsrClient".to_string(),
+                                error: format!("Failed to set up MRCP session: {}", e),
+                                code: Some(500),
+                            })
+                            .ok();
+                        return;
+                    }
+                };
+            if let Err(e) =
+                recognize_loop(session, &option, &track_id, audio_rx, &event_sender, &token).await
+            {
+                warn!(track_id, "MRCP recognition error: {}", e);
+                event_sender
+                    .send(SessionEvent::Error {
+                        timestamp: crate::get_timestamp(),
+                        track_id: track_id.clone(),
+                        sender: "MrcpAsrClient".to_string(),
+                        error: format!("MRCP recognition error: {}", e),
+                        code: None,
+                    })
+                    .ok();
+            }
+            info!(track_id, "MRCP ASR client stopped");
+        });
+        Ok(MrcpAsrClient { audio_tx })
+    }
+}
+
+fn recognize_request(option: &TranscriptionOption) -> MrcpMessage {
+    let mut request = MrcpMessage::request("RECOGNIZE", 0);
+    if let Some(language) = &option.language {
+        request = request.with_header("Speech-Language", language);
+    }
+    let mut grammar = DEFAULT_GRAMMAR.to_string();
+    for (name, value) in option.extra.iter().flatten() {
+        if name == "grammar" {
+            grammar = value.clone();
+        } else {
+            request = request.with_header(name, value);
+        }
+    }
+    let content_type = if grammar.trim_start().starts_with('<') {
+        "application/srgs+xml"
+    } else {
+        "text/uri-list"
+    };
+    request.with_body(content_type, grammar.into_bytes())
+}
+
+/// Completion causes that won't get better by recognizing again, e.g.
+/// 004 grammar-load-failure or 006 recognizer-error
+fn is_recognizer_failure(cause: &str) -> bool {
+    matches!(
+        cause.split_whitespace().next().unwrap_or_default(),
+        "004" | "005" | "006" | "009" | "010" | "012" | "016"
+    )
+}
+
+async fn start_recognize(session: &mut MrcpSession, option: &TranscriptionOption) -> Result<()> {
+    let response = session.request(recognize_request(option)).await?;
+    match response.status() {
+        Some(status) if status < 300 => Ok(()),
+        status => Err(anyhow!(
+            "RECOGNIZE failed: {:?} {}",
+            status,
+            response.header("Completion-Cause").unwrap_or_default()
+        )),
+    }
+}
+
+async fn recognize_loop(
+    mut session: MrcpSession,
+    option: &TranscriptionOption,
+    track_id: &TrackId,
+    mut audio_rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    event_sender: &EventSender,
+    token: &CancellationToken,
+) -> Result<()> {
+    let sample_rate = option.samplerate.unwrap_or(16000);
+    let config = TrackConfig {
+        codec: session.codec(),
+        samplerate: sample_rate,
+        ..Default::default()
+    };
+    let mut pipeline = MediaPipeline::new(track_id.clone(), config);
+    let frame_size = sample_rate as usize / 50;
+    let mut buffer = PcmBuf::new();
+    let rtp_socket = session.rtp_socket();
+    let rtp_addr = session.rtp_addr();
+    let mut index = 0;
+    let mut speech_start = None;
+
+    start_recognize(&mut session, option).await?;
+    let result = loop {
+        tokio::select! {
+            _ = token.cancelled() => break Ok(()),
+            samples = audio_rx.recv() => {
+                let Some(samples) = samples else {
+                    break Ok(());
+                };
+                buffer.extend_from_slice(&bytes_to_samples(&samples));
+                while buffer.len() >= frame_size {
+                    let frame = AudioFrame {
+                        track_id: track_id.clone(),
+                        samples: Samples::PCM {
+                            samples: buffer.drain(..frame_size).collect(),
+                        },
+                        timestamp: crate::get_timestamp(),
+                        sample_rate,
+                    };
+                    pipeline.push_frame(&frame)?;
+                    while let Some(datagram) = pipeline.pull_packet() {
+                        rtp_socket.send_to(&datagram, rtp_addr).await?;
+                    }
+                }
+            }
+            message = session.recv() => {
+                let message = message?;
+                match message.event_name() {
+                    Some("START-OF-INPUT") => {
+                        speech_start = Some(crate::get_timestamp());
+                    }
+                    Some("RECOGNITION-COMPLETE") => {
+                        let cause = message.header("Completion-Cause").unwrap_or_default();
+                        debug!(track_id, cause, "MRCP recognition complete");
+                        let text = parse_nlsml(&String::from_utf8_lossy(&message.body));
+                        if let Some(text) = text {
+                            event_sender
+                                .send(SessionEvent::AsrFinal {
+                                    track_id: track_id.clone(),
+                                    index,
+                                    text,
+                                    timestamp: crate::get_timestamp(),
+                                    start_time: speech_start.take(),
+                                    end_time: Some(crate::get_timestamp()),
+                                })
+                                .ok();
+                            index += 1;
+                        }
+                        if is_recognizer_failure(cause) {
+                            break Err(anyhow!("recognition failed: {}", cause));
+                        }
+                        start_recognize(&mut session, option).await?;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    };
+    session.send(MrcpMessage::request("STOP", 0)).await.ok();
+    session.close().await;
+    result
+}
+
+#[async_trait]
+impl TranscriptionClient for MrcpAsrClient {
+    fn send_audio(&self, samples: &[Sample]) -> Result<()> {
+        self.audio_tx
+            .send(samples_to_bytes(samples))
+            .map_err(|_| anyhow!("MRCP ASR client stopped"))
+    }
+}
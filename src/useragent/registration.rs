@@ -6,6 +6,7 @@ use rsipstack::{
     rsip_ext::RsipResponseExt,
     transaction::endpoint::EndpointInnerRef,
 };
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::{
     sync::Arc,
@@ -19,14 +20,14 @@ use tokio::{
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, info, warn};
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
 pub struct UserCredential {
     pub username: String,
     pub password: String,
     pub realm: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Clone, Serialize)]
 pub struct RegisterOption {
     pub server: String,
     pub username: String,
@@ -90,10 +91,12 @@ impl UserAgent {
         if let Some(duration) = wait_for_clear {
             let live_users = self.alive_users.clone();
             let check_loop = async move {
-                while let Ok(users) = live_users.read() {
-                    if users.is_empty() {
-                        break;
-                    }
+                // the lock isn't held while sleeping, so the users can go
+                while live_users
+                    .read()
+                    .map(|users| !users.is_empty())
+                    .unwrap_or(false)
+                {
                     sleep(Duration::from_millis(50)).await;
                 }
             };
@@ -1,4 +1,5 @@
 use super::registration::RegistrationHandle;
+use crate::call::retransmission::{RetransmissionStats, Retransmitter};
 use crate::call::sip::Invitation;
 use crate::config::UseragentConfig;
 use crate::useragent::invitation::{
@@ -11,7 +12,7 @@ use rsipstack::EndpointBuilder;
 use rsipstack::dialog::dialog_layer::DialogLayer;
 use rsipstack::transaction::endpoint::EndpointOption;
 use rsipstack::transaction::{Endpoint, TransactionReceiver};
-use rsipstack::transport::{TransportLayer, udp::UdpConnection};
+use rsipstack::transport::TransportLayer;
 use std::collections::{HashMap, HashSet};
 use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
@@ -27,6 +28,8 @@ pub struct UserAgentBuilder {
     pub config: Option<UseragentConfig>,
     pub cancel_token: Option<CancellationToken>,
     pub create_invitation_handler: Option<FnCreateInvitationHandler>,
+    pub dscp: Option<u8>,
+    pub retransmitter: Option<Retransmitter>,
 }
 
 pub struct UserAgent {
@@ -46,6 +49,8 @@ impl UserAgentBuilder {
             config: None,
             cancel_token: None,
             create_invitation_handler: None,
+            dscp: None,
+            retransmitter: None,
         }
     }
     pub fn with_config(mut self, config: Option<UseragentConfig>) -> Self {
@@ -58,6 +63,18 @@ impl UserAgentBuilder {
         self
     }
 
+    /// Marks the SIP packets with this DSCP
+    pub fn with_dscp(mut self, dscp: Option<u8>) -> Self {
+        self.dscp = dscp;
+        self
+    }
+
+    /// Timers and retransmission counters of the endpoint
+    pub fn with_retransmitter(mut self, retransmitter: Option<Retransmitter>) -> Self {
+        self.retransmitter = retransmitter;
+        self
+    }
+
     pub fn with_create_invitation_handler(
         mut self,
         handler: Option<FnCreateInvitationHandler>,
@@ -81,17 +98,28 @@ impl UserAgentBuilder {
         let transport_layer = TransportLayer::new(cancel_token.clone());
         let local_addr: SocketAddr = format!("{}:{}", local_ip, config.udp_port).parse()?;
 
-        let udp_conn =
-            UdpConnection::create_connection(local_addr, None, Some(cancel_token.child_token()))
-                .await
-                .map_err(|e| anyhow!("Create useragent UDP connection: {} {}", local_addr, e))?;
+        let udp_conn = crate::net_tool::create_udp_connection(
+            local_addr,
+            None,
+            self.dscp,
+            Some(cancel_token.child_token()),
+        )
+        .await
+        .map_err(|e| anyhow!("Create useragent UDP connection: {} {}", local_addr, e))?;
 
         transport_layer.add_transport(udp_conn.into());
         info!("start useragent, addr: {}", local_addr);
 
+        let retransmitter = self.retransmitter.take().unwrap_or_else(|| {
+            Retransmitter::new(
+                Default::default(),
+                Arc::new(RetransmissionStats::default()),
+                None,
+            )
+        });
         let endpoint_option = EndpointOption {
             callid_suffix: config.callid_suffix.clone(),
-            ..Default::default()
+            ..retransmitter.timers().endpoint_option()
         };
         let mut endpoint_builder = EndpointBuilder::new();
         if let Some(ref user_agent) = config.useragent {
@@ -101,6 +129,7 @@ impl UserAgentBuilder {
             .with_cancel_token(cancel_token.child_token())
             .with_transport_layer(transport_layer)
             .with_option(endpoint_option)
+            .with_inspector(Box::new(retransmitter.clone()))
             .build();
         let dialog_layer = Arc::new(DialogLayer::new(endpoint.inner.clone()));
 
@@ -112,7 +141,7 @@ impl UserAgentBuilder {
             alive_users: Arc::new(RwLock::new(HashSet::new())),
             dialog_layer: dialog_layer.clone(),
             create_invitation_handler: self.create_invitation_handler,
-            invitation: Invitation::new(dialog_layer),
+            invitation: Invitation::new(dialog_layer).with_retransmitter(retransmitter),
         })
     }
 }
@@ -129,6 +158,18 @@ impl UserAgent {
             match tx.original.to_header()?.tag()?.as_ref() {
                 Some(_) => match dialog_layer.match_dialog(&tx.original) {
                     Some(mut d) => {
+                        // our own re-INVITE or UPDATE crossed this one
+                        // (RFC 3261 14.2, RFC 3311 5.2)
+                        if matches!(
+                            tx.original.method,
+                            rsip::Method::Invite | rsip::Method::Update
+                        ) && self.invitation.is_renegotiating(&d.id())
+                        {
+                            if let Err(e) = tx.reply(rsip::StatusCode::RequestPending).await {
+                                info!("error replying to request: {:?}", e);
+                            }
+                            continue;
+                        }
                         tokio::spawn(async move {
                             match d.handle(&mut tx).await {
                                 Ok(_) => (),